@@ -84,12 +84,441 @@ fn bench_metadata_operations(c: &mut Criterion) {
         });
 }
 
+/// Per-fragment allocator persist cost against bitmap size: journaled
+/// `persist` (checkpoints amortized in) versus rewriting the whole bitmap
+fn bench_allocator_persist(c: &mut Criterion) {
+    use dynamicfs::on_device_allocator::OnDeviceAllocator;
+
+    let mut group = c.benchmark_group("allocator_persist");
+    group.sample_size(20);
+    let dir = tempfile::tempdir().unwrap();
+
+    // 8 KiB, 128 KiB and 2 MiB bitmaps; 2 MiB is a 16 TB disk with 1 MiB units
+    for units in [1u64 << 16, 1 << 20, 1 << 24] {
+        let path = dir.path().join(format!("device-{}.img", units));
+        let device_size = 2 * 1024 * 1024 + units / 8 + 4096;
+        OnDeviceAllocator::format_device(&path, uuid::Uuid::new_v4(), device_size, 4096, units).unwrap();
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("journal", units / 8), &units, |b, _| {
+            b.iter(|| {
+                let start = oda.allocate_contiguous(1).unwrap();
+                oda.persist().unwrap();
+                oda.free_contiguous(start, 1).unwrap();
+                oda.persist().unwrap();
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("full_bitmap", units / 8), &units, |b, _| {
+            b.iter(|| {
+                let start = oda.allocate_contiguous(1).unwrap();
+                oda.checkpoint().unwrap();
+                oda.free_contiguous(start, 1).unwrap();
+                oda.checkpoint().unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Streaming one large file in 128 KiB reads, the way the kernel issues them,
+/// with and without read-ahead decoding the next extents in the background
+///
+/// The file is `DYNAMICFS_BENCH_STREAM_MB` MiB (default 2048). Criterion reports
+/// throughput; the spread of single-read latencies is printed per mode.
+fn bench_streaming_readahead(c: &mut Criterion) {
+    use dynamicfs::disk::Disk;
+    use dynamicfs::fuse_optimizations::{OptimizedFUSEConfig, ReadAhead, ReadAheadManager};
+    use dynamicfs::storage::StorageEngine;
+    use dynamicfs::MetadataManager;
+    use std::time::Instant;
+
+    const READ_SIZE: u64 = 128 * 1024;
+    let file_size = std::env::var("DYNAMICFS_BENCH_STREAM_MB")
+        .ok()
+        .and_then(|mb| mb.parse::<u64>().ok())
+        .unwrap_or(2048)
+        << 20;
+
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..6).map(|_| tempfile::tempdir().unwrap()).collect();
+    let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let mut storage = StorageEngine::new(metadata, disks);
+    let ino = storage.create_file(1, "stream.bin".to_string()).unwrap().ino;
+    let chunk: Vec<u8> = (0..8u64 << 20).map(|i| (i * 31 % 251) as u8).collect();
+    for offset in (0..file_size).step_by(chunk.len()) {
+        let len = chunk.len().min((file_size - offset) as usize);
+        storage.write_file(ino, &chunk[..len], offset).unwrap();
+    }
+
+    let mut group = c.benchmark_group("streaming_readahead");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(READ_SIZE));
+    for enabled in [false, true] {
+        if enabled {
+            storage.start_prefetcher();
+        }
+        let mut config = OptimizedFUSEConfig::high_performance();
+        config.enable_readahead = enabled;
+        let manager = ReadAheadManager::new(config);
+        let mut offset = 0;
+        let mut latencies = Vec::new();
+
+        let name = if enabled { "readahead" } else { "no_readahead" };
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    // Wrap around and start a new stream at the end of the file
+                    if offset >= file_size {
+                        offset = 0;
+                    }
+                    let start = Instant::now();
+                    let data = storage.read_range(ino, offset, READ_SIZE).unwrap();
+                    let elapsed = start.elapsed();
+                    match manager.record_access(1, ino, offset, data.len()) {
+                        ReadAhead::Prefetch(hint) => storage.prefetch(hint.ino, hint.offset, hint.extents),
+                        ReadAhead::Cancel => storage.cancel_prefetch(ino),
+                        ReadAhead::Idle => {}
+                    }
+                    offset += data.len() as u64;
+                    latencies.push(elapsed.as_secs_f64() * 1e6);
+                    total += elapsed;
+                }
+                total
+            });
+        });
+
+        latencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mean = latencies.iter().sum::<f64>() / latencies.len() as f64;
+        let stddev = (latencies.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / latencies.len() as f64).sqrt();
+        let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+        println!(
+            "streaming_readahead/{}: {} reads, mean {:.1}us, stddev {:.1}us, p50 {:.1}us, p99 {:.1}us, max {:.1}us",
+            name,
+            latencies.len(),
+            mean,
+            stddev,
+            percentile(0.5),
+            percentile(0.99),
+            percentile(1.0)
+        );
+    }
+    group.finish();
+}
+
+/// What `status` pays for extent counts: the saved totals against reading
+/// every extent record
+///
+/// The pool holds `DYNAMICFS_BENCH_EXTENTS` extent records (default 100000).
+fn bench_status_extent_counts(c: &mut Criterion) {
+    use dynamicfs::extent::{Extent, FragmentLocation, RedundancyPolicy};
+    use dynamicfs::MetadataManager;
+
+    let count = std::env::var("DYNAMICFS_BENCH_EXTENTS")
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(100_000);
+    let pool_dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(pool_dir.path().join("extents")).unwrap();
+    let disk_uuid = uuid::Uuid::new_v4();
+    for i in 0..count {
+        let mut extent = Extent::new(&(i as u64).to_le_bytes(), RedundancyPolicy::Replication { copies: 3 });
+        for fragment_index in 0..3 - (i % 50 == 0) as usize {
+            extent.fragment_locations.push(FragmentLocation { disk_uuid, fragment_index, on_device: None, checksum: None });
+        }
+        let path = pool_dir.path().join("extents").join(extent.uuid.to_string());
+        std::fs::write(path, serde_json::to_vec(&extent).unwrap()).unwrap();
+    }
+    // Counts the records once and saves the totals, as on first mount after an upgrade
+    drop(MetadataManager::new(pool_dir.path().to_path_buf()).unwrap());
+
+    let mut group = c.benchmark_group("status_extent_counts");
+    group.sample_size(10);
+    group.bench_with_input(BenchmarkId::new("totals", count), &count, |b, _| {
+        b.iter(|| {
+            let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+            black_box(metadata.extent_totals().complete())
+        });
+    });
+    group.bench_with_input(BenchmarkId::new("full_scan", count), &count, |b, _| {
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        b.iter(|| {
+            let complete = metadata.iter_extents().unwrap().filter_map(Result::ok).filter(|e| e.is_complete()).count();
+            black_box(complete)
+        });
+    });
+    group.finish();
+}
+
+/// Extent lookups, updates and full scans with the records in one file each
+/// against the key-value store (with `--features kv-metadata`)
+///
+/// Each pool holds `DYNAMICFS_BENCH_BACKEND_EXTENTS` extent records (default
+/// 1000000), written through the store in batches before timing starts.
+fn bench_metadata_backends(c: &mut Criterion) {
+    use dynamicfs::disk::DiskPool;
+    use dynamicfs::extent::{Extent, RedundancyPolicy};
+    use dynamicfs::metadata_store::{self, MetadataBackend, RecordKind};
+    use dynamicfs::MetadataManager;
+
+    let count = std::env::var("DYNAMICFS_BENCH_BACKEND_EXTENTS")
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(1_000_000);
+    let mut backends = vec![MetadataBackend::Files];
+    if cfg!(feature = "kv-metadata") {
+        backends.push(MetadataBackend::Kv);
+    }
+
+    let mut group = c.benchmark_group("metadata_backends");
+    group.sample_size(10);
+    for backend in backends {
+        let pool_dir = tempfile::tempdir().unwrap();
+        let mut pool = DiskPool::new();
+        pool.metadata_backend = backend;
+        pool.save(pool_dir.path()).unwrap();
+        let mut uuids = Vec::with_capacity(count);
+        {
+            let store = metadata_store::open(pool_dir.path(), backend).unwrap();
+            for start in (0..count).step_by(10_000) {
+                let batch: Vec<(String, Vec<u8>)> = (start..count.min(start + 10_000))
+                    .map(|i| {
+                        let extent = Extent::new(&(i as u64).to_le_bytes(), RedundancyPolicy::Replication { copies: 3 });
+                        uuids.push(extent.uuid);
+                        (extent.uuid.to_string(), serde_json::to_vec_pretty(&extent).unwrap())
+                    })
+                    .collect();
+                store.save_batch(RecordKind::Extent, &batch).unwrap();
+            }
+        }
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let name = backend.to_string();
+
+        let mut next = 0usize;
+        group.bench_with_input(BenchmarkId::new(format!("{}_lookup", name), count), &count, |b, _| {
+            b.iter(|| {
+                // Strided, so consecutive lookups land far apart in the store
+                next = (next + 7919) % uuids.len();
+                black_box(metadata.load_extent(&uuids[next]).unwrap())
+            });
+        });
+        group.bench_with_input(BenchmarkId::new(format!("{}_update", name), count), &count, |b, _| {
+            b.iter(|| {
+                next = (next + 7919) % uuids.len();
+                let mut extent = metadata.load_extent(&uuids[next]).unwrap();
+                extent.access_stats.read_count += 1;
+                metadata.save_extent(&extent).unwrap();
+            });
+        });
+        group.bench_with_input(BenchmarkId::new(format!("{}_scan", name), count), &count, |b, _| {
+            b.iter(|| black_box(metadata.extent_records().unwrap().filter(|(_, extent)| extent.is_ok()).count()));
+        });
+    }
+    group.finish();
+}
+
+/// Creating `DYNAMICFS_BENCH_FILES` empty files (default 10000): syncing
+/// every metadata transaction against sharing syncs within the commit window,
+/// from one thread or eight, and against one batch for all of them
+///
+/// Criterion reports the wall time; the syncs per run are printed per mode.
+fn bench_create_commits(c: &mut Criterion) {
+    use dynamicfs::disk::Disk;
+    use dynamicfs::metadata_tx::MetadataOp;
+    use dynamicfs::storage::StorageEngine;
+    use dynamicfs::{Inode, MetadataManager};
+    use std::time::Instant;
+
+    let count = std::env::var("DYNAMICFS_BENCH_FILES")
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(10_000);
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let storage = StorageEngine::new(metadata, disks);
+    let stats = || storage.metadata().read().unwrap().commit_stats();
+
+    let mut group = c.benchmark_group("create_commits");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(count as u64));
+    let mut run = 0;
+    for (name, window_ms, threads) in [("sync_each", 0, 1), ("group_commit", 2, 1), ("group_commit_8_threads", 2, 8), ("one_batch", 2, 0)] {
+        storage.set_metadata_commit_window(window_ms);
+        let before = stats();
+        let mut runs = 0;
+        group.bench_function(BenchmarkId::new(name, count), |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    run += 1;
+                    runs += 1;
+                    let dir = storage.create_dir(1, format!("run-{}", run)).unwrap().ino;
+                    let start = Instant::now();
+                    if threads == 0 {
+                        let metadata = storage.metadata();
+                        let mut metadata = metadata.write().unwrap();
+                        let ops = (0..count)
+                            .map(|i| MetadataOp::SaveInode(Inode::new_file(metadata.allocate_ino().unwrap(), dir, format!("f{}", i))))
+                            .collect();
+                        metadata.apply_batch(ops).unwrap();
+                    } else {
+                        std::thread::scope(|scope| {
+                            for t in 0..threads {
+                                let storage = &storage;
+                                scope.spawn(move || {
+                                    for i in (t..count).step_by(threads) {
+                                        storage.create_file(dir, format!("f{}", i)).unwrap();
+                                    }
+                                });
+                            }
+                        });
+                    }
+                    storage.sync_all().unwrap();
+                    total += start.elapsed();
+                }
+                total
+            });
+        });
+
+        let after = stats();
+        println!(
+            "create_commits/{}: {} files per run, {:.1} transactions and {:.1} syncs per run",
+            name,
+            count,
+            (after.transactions - before.transactions) as f64 / runs as f64,
+            (after.syncs - before.syncs) as f64 / runs as f64
+        );
+    }
+    group.finish();
+}
+
+/// stat() of `DYNAMICFS_BENCH_FILES` files (default 100000) with the inode
+/// cache emptied before every pass against a cache the previous pass filled
+///
+/// Criterion reports the time per pass; the inode records read per pass are
+/// printed per mode, and are 0 once the cache holds every file.
+fn bench_stat_inode_cache(c: &mut Criterion) {
+    use dynamicfs::disk::Disk;
+    use dynamicfs::inode_cache::InodeCacheLimits;
+    use dynamicfs::metadata_tx::MetadataOp;
+    use dynamicfs::storage::StorageEngine;
+    use dynamicfs::{Inode, MetadataManager};
+    use std::time::Instant;
+
+    let count = std::env::var("DYNAMICFS_BENCH_FILES")
+        .ok()
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(100_000);
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let storage = StorageEngine::new(metadata, disks);
+    let dir = storage.create_dir(1, "files".to_string()).unwrap().ino;
+    let inos: Vec<u64> = {
+        let metadata = storage.metadata();
+        let mut metadata = metadata.write().unwrap();
+        let inodes: Vec<Inode> = (0..count)
+            .map(|i| Inode::new_file(metadata.allocate_ino().unwrap(), dir, format!("f{}", i)))
+            .collect();
+        let inos = inodes.iter().map(|inode| inode.ino).collect();
+        metadata.apply_batch(inodes.into_iter().map(MetadataOp::SaveInode).collect()).unwrap();
+        inos
+    };
+    let limits = InodeCacheLimits { capacity: count, ttl: Duration::from_secs(3600) };
+    let misses = || storage.metrics().snapshot().inode_cache_misses;
+
+    let mut group = c.benchmark_group("stat_inode_cache");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(count as u64));
+    for (name, cold) in [("cold", true), ("warm", false)] {
+        storage.set_inode_cache_limits(limits);
+        if !cold {
+            for &ino in &inos {
+                black_box(storage.get_inode(ino).unwrap());
+            }
+        }
+        let before = misses();
+        let mut passes = 0;
+        group.bench_function(BenchmarkId::new(name, count), |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    if cold {
+                        storage.set_inode_cache_limits(limits);
+                    }
+                    passes += 1;
+                    let start = Instant::now();
+                    for &ino in &inos {
+                        black_box(storage.get_inode(ino).unwrap());
+                    }
+                    total += start.elapsed();
+                }
+                total
+            });
+        });
+        println!(
+            "stat_inode_cache/{}: {} files per pass, {:.1} inode records read per pass",
+            name,
+            count,
+            (misses() - before) as f64 / passes as f64
+        );
+    }
+    group.finish();
+}
+
+fn bench_pooled_reads(c: &mut Criterion) {
+    use dynamicfs::buffer_pool::DEFAULT_READ_BUFFER_POOL_BYTES;
+    use dynamicfs::disk::Disk;
+    use dynamicfs::storage::StorageEngine;
+    use dynamicfs::MetadataManager;
+
+    const READ_SIZE: u64 = 128 * 1024;
+    let file_size = 64u64 << 20;
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..6).map(|_| tempfile::tempdir().unwrap()).collect();
+    let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let storage = StorageEngine::new(metadata, disks);
+    let ino = storage.create_file(1, "pooled.bin".to_string()).unwrap().ino;
+    let content: Vec<u8> = (0..file_size).map(|i| (i * 31 % 251) as u8).collect();
+    storage.write_file(ino, &content, 0).unwrap();
+
+    // Uncached reads so every one decodes from fragments, with the buffer
+    // handed back the way the FUSE layer does
+    let mut group = c.benchmark_group("pooled_reads");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(READ_SIZE));
+    for (name, limit) in [("unpooled", 0), ("pooled", DEFAULT_READ_BUFFER_POOL_BYTES)] {
+        storage.set_read_buffer_pool_limit(limit);
+        let mut offset = 0;
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let data = storage.read_range_uncached(ino, offset, READ_SIZE).unwrap();
+                offset = (offset + READ_SIZE) % file_size;
+                storage.recycle_read_buffer(black_box(data));
+            })
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_sequential_write,
     bench_sequential_read,
     bench_random_read,
     bench_cache_operations,
-    bench_metadata_operations
+    bench_metadata_operations,
+    bench_allocator_persist,
+    bench_status_extent_counts,
+    bench_metadata_backends,
+    bench_create_commits,
+    bench_stat_inode_cache,
+    bench_streaming_readahead,
+    bench_pooled_reads
 );
 criterion_main!(benches);
@@ -25,7 +25,7 @@ cat << 'EOF'
   
   # 2. Add disks
   for i in {1..6}; do
-    ./target/release/dynamicfs add-disk --pool ~/pool --disk ~/disk$i
+    ./target/release/dynamicfs add-disk --pool ~/pool --disk ~/disk$i --force  # one device: testing only
   done
   
   # 3. Mount filesystem
@@ -0,0 +1,212 @@
+//! Extent reads and inode timestamps held in memory between metadata flushes
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::extent::Extent;
+use crate::metadata::Inode;
+
+/// Seconds after which relatime refreshes an atime that is newer than mtime and ctime
+const RELATIME_MAX_AGE_SECONDS: i64 = 24 * 3600;
+
+/// Reads of one extent not yet written to its metadata
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PendingAccess {
+    pub reads: u64,
+    pub last_read: i64,
+    /// Reads through uncached handles; see `Extent::apply_scan_reads`
+    pub scan_reads: u64,
+}
+
+impl PendingAccess {
+    fn merge(&mut self, other: PendingAccess) {
+        self.reads += other.reads;
+        self.last_read = self.last_read.max(other.last_read);
+        self.scan_reads += other.scan_reads;
+    }
+
+    /// Add these reads to `extent`'s access statistics
+    pub fn apply(&self, extent: &mut Extent) {
+        if self.reads > 0 {
+            extent.apply_reads(self.reads, self.last_read);
+        }
+        if self.scan_reads > 0 {
+            extent.apply_scan_reads(self.scan_reads);
+        }
+    }
+}
+
+/// Read accounting that keeps the read path free of metadata writes
+///
+/// Reads only bump an in-memory counter; the engine folds the counters into
+/// extent records in batches. Anything that looks at access statistics goes
+/// through `merged` so it sees persisted and pending reads together.
+#[derive(Debug, Default)]
+pub struct AccessTracker {
+    pending: Mutex<HashMap<Uuid, PendingAccess>>,
+}
+
+impl AccessTracker {
+    pub fn record_read(&self, extent_uuid: Uuid) {
+        let now = chrono::Utc::now().timestamp();
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(extent_uuid)
+            .or_default()
+            .merge(PendingAccess { reads: 1, last_read: now, scan_reads: 0 });
+    }
+
+    /// A read through an uncached handle, counted apart so scans do not make data hot
+    pub fn record_scan_read(&self, extent_uuid: Uuid) {
+        self.pending.lock().unwrap().entry(extent_uuid).or_default().merge(PendingAccess {
+            scan_reads: 1,
+            ..Default::default()
+        });
+    }
+
+    /// `extent` with its pending reads applied; the reads stay pending
+    pub fn merged(&self, mut extent: Extent) -> Extent {
+        if let Some(pending) = self.pending.lock().unwrap().get(&extent.uuid) {
+            pending.apply(&mut extent);
+        }
+        extent
+    }
+
+    /// Remove and return the pending reads of one extent
+    pub fn take(&self, extent_uuid: &Uuid) -> Option<PendingAccess> {
+        self.pending.lock().unwrap().remove(extent_uuid)
+    }
+
+    /// Remove and return every pending read
+    pub fn take_all(&self) -> HashMap<Uuid, PendingAccess> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Put back reads taken for a flush that did not complete
+    pub fn restore(&self, extent_uuid: Uuid, access: PendingAccess) {
+        self.pending.lock().unwrap().entry(extent_uuid).or_default().merge(access);
+    }
+}
+
+/// When reads update a file's access time, chosen per mount
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AtimeMode {
+    /// Every read sets atime
+    #[serde(rename = "strictatime")]
+    Strict,
+    /// A read sets atime only if it is not newer than mtime or ctime, or is a day old
+    #[default]
+    #[serde(rename = "relatime")]
+    Relatime,
+    /// Reads never touch atime
+    #[serde(rename = "noatime")]
+    Noatime,
+}
+
+impl AtimeMode {
+    /// The mode named by a `strictatime`, `relatime`, `atime` or `noatime` mount option
+    pub fn from_mount_option(option: &str) -> Option<AtimeMode> {
+        match option {
+            "strictatime" => Some(AtimeMode::Strict),
+            "relatime" | "atime" => Some(AtimeMode::Relatime),
+            "noatime" => Some(AtimeMode::Noatime),
+            _ => None,
+        }
+    }
+
+    /// The mount option selecting this mode
+    pub fn mount_option(self) -> &'static str {
+        match self {
+            AtimeMode::Strict => "strictatime",
+            AtimeMode::Relatime => "relatime",
+            AtimeMode::Noatime => "noatime",
+        }
+    }
+
+    /// Whether a read at `now` should move `inode`'s atime
+    pub fn wants_update(self, inode: &Inode, now: i64) -> bool {
+        match self {
+            AtimeMode::Strict => inode.atime < now,
+            AtimeMode::Relatime => {
+                inode.atime <= inode.mtime
+                    || inode.atime <= inode.ctime
+                    || now - inode.atime >= RELATIME_MAX_AGE_SECONDS
+            }
+            AtimeMode::Noatime => false,
+        }
+    }
+}
+
+/// Timestamps of one inode not yet written to its metadata
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PendingTimes {
+    /// Seconds and nanoseconds, as `Inode::set_atime` takes them
+    pub atime: Option<(i64, u32)>,
+    /// Set by data writes, which move ctime along with mtime
+    pub mtime: Option<(i64, u32)>,
+}
+
+impl PendingTimes {
+    fn merge(&mut self, other: PendingTimes) {
+        self.atime = self.atime.max(other.atime);
+        self.mtime = self.mtime.max(other.mtime);
+    }
+
+    /// Move `inode`'s timestamps forward; never backward
+    pub fn apply(&self, inode: &mut Inode) {
+        if let Some(atime) = self.atime {
+            inode.set_atime((inode.atime, inode.atime_nsec).max(atime));
+        }
+        if let Some(mtime) = self.mtime {
+            inode.set_mtime((inode.mtime, inode.mtime_nsec).max(mtime));
+            inode.set_ctime((inode.ctime, inode.ctime_nsec).max(mtime));
+        }
+    }
+}
+
+/// Inode timestamp updates that keep reads and overwrites free of inode writes
+///
+/// Like `AccessTracker`, updates are only recorded here and written to inode
+/// records in batches, so a burst of reads or writes to one file costs a single
+/// inode write. Anything returning an inode goes through `merged` so callers
+/// see the new times before they are persisted.
+#[derive(Debug, Default)]
+pub struct TimestampTracker {
+    pending: Mutex<HashMap<u64, PendingTimes>>,
+}
+
+impl TimestampTracker {
+    pub fn record_atime(&self, ino: u64, atime: (i64, u32)) {
+        self.restore(ino, PendingTimes { atime: Some(atime), mtime: None });
+    }
+
+    pub fn record_mtime(&self, ino: u64, mtime: (i64, u32)) {
+        self.restore(ino, PendingTimes { atime: None, mtime: Some(mtime) });
+    }
+
+    /// `inode` with its pending timestamps applied; they stay pending
+    pub fn merged(&self, mut inode: Inode) -> Inode {
+        if let Some(pending) = self.pending.lock().unwrap().get(&inode.ino) {
+            pending.apply(&mut inode);
+        }
+        inode
+    }
+
+    /// Remove and return the pending timestamps of one inode
+    pub fn take(&self, ino: u64) -> Option<PendingTimes> {
+        self.pending.lock().unwrap().remove(&ino)
+    }
+
+    /// Remove and return every pending timestamp
+    pub fn take_all(&self) -> HashMap<u64, PendingTimes> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Put back timestamps taken for a flush that did not complete
+    pub fn restore(&self, ino: u64, times: PendingTimes) {
+        self.pending.lock().unwrap().entry(ino).or_default().merge(times);
+    }
+}
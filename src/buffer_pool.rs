@@ -0,0 +1,156 @@
+//! Buffers reused by the fragment read and extent decode paths
+//!
+//! Reading an extent used to allocate a fresh buffer for every fragment, one
+//! for the decoded extent and one for the bytes returned, each the size of a
+//! fragment or an extent. The engine takes them from here instead and gives
+//! them back once their data has been copied out or replied with, so steady
+//! reads cycle through the same few allocations.
+//!
+//! Buffers given back are kept while their capacities add up to no more than
+//! the limit; past it they are freed. A limit of 0 keeps none, which is the
+//! old behaviour of allocating for every read.
+
+use std::sync::Mutex;
+
+pub const DEFAULT_READ_BUFFER_POOL_BYTES: u64 = 64 * 1024 * 1024;
+
+struct PoolState {
+    limit: usize,
+    /// Capacity of the buffers in `free`
+    held: usize,
+    free: Vec<Vec<u8>>,
+}
+
+/// Bounded set of idle buffers, shared by concurrent readers
+pub struct BufferPool {
+    state: Mutex<PoolState>,
+}
+
+impl BufferPool {
+    pub fn new(limit: usize) -> Self {
+        BufferPool { state: Mutex::new(PoolState { limit, held: 0, free: Vec::new() }) }
+    }
+
+    /// Change the byte limit, freeing idle buffers past the new one
+    pub fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit;
+        while state.held > limit {
+            let Some(buf) = state.free.pop() else { break };
+            state.held -= buf.capacity();
+        }
+    }
+
+    /// An empty buffer with room for at least `capacity` bytes
+    ///
+    /// The smallest idle buffer that is large enough, or a new one.
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        let best = state
+            .free
+            .iter()
+            .enumerate()
+            .filter(|(_, buf)| buf.capacity() >= capacity)
+            .min_by_key(|(_, buf)| buf.capacity())
+            .map(|(index, _)| index);
+        match best {
+            Some(index) => {
+                let buf = state.free.swap_remove(index);
+                state.held -= buf.capacity();
+                buf
+            }
+            None => {
+                drop(state);
+                Vec::with_capacity(capacity)
+            }
+        }
+    }
+
+    /// Keep `buf` for a later `take` if it fits under the limit
+    pub fn give(&self, mut buf: Vec<u8>) {
+        let capacity = buf.capacity();
+        if capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.held + capacity > state.limit {
+            return;
+        }
+        buf.clear();
+        state.held += capacity;
+        state.free.push(buf);
+    }
+
+    /// `give` every buffer of a fragment set
+    pub fn give_all(&self, bufs: impl IntoIterator<Item = Option<Vec<u8>>>) {
+        for buf in bufs.into_iter().flatten() {
+            self.give(buf);
+        }
+    }
+
+    /// Bytes of capacity held by idle buffers
+    pub fn held_bytes(&self) -> usize {
+        self.state.lock().unwrap().held
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reuses_the_smallest_buffer_that_fits() {
+        let pool = BufferPool::new(1 << 20);
+        let small = Vec::<u8>::with_capacity(1000);
+        let mut large = Vec::<u8>::with_capacity(4000);
+        large.extend_from_slice(&[7; 100]);
+        let large_ptr = large.as_ptr();
+        pool.give(small);
+        pool.give(large);
+        assert_eq!(pool.held_bytes(), 5000);
+
+        let taken = pool.take(2000);
+        assert_eq!((taken.as_ptr(), taken.len()), (large_ptr, 0), "given back cleared");
+        assert_eq!(pool.take(500).capacity(), 1000);
+        assert_eq!(pool.held_bytes(), 0);
+        assert!(pool.take(10).capacity() >= 10, "an empty pool allocates");
+    }
+
+    #[test]
+    fn test_limit_bounds_idle_buffers() {
+        let pool = BufferPool::new(3000);
+        for _ in 0..4 {
+            pool.give(Vec::with_capacity(1000));
+        }
+        assert_eq!(pool.held_bytes(), 3000, "the fourth buffer is freed");
+        pool.set_limit(1500);
+        assert_eq!(pool.held_bytes(), 1000);
+        pool.set_limit(0);
+        pool.give(Vec::with_capacity(1000));
+        assert_eq!(pool.held_bytes(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_readers_never_share_a_buffer() {
+        let pool = Arc::new(BufferPool::new(64 * 4096));
+        let threads: Vec<_> = (0..8u8)
+            .map(|id| {
+                let pool = Arc::clone(&pool);
+                std::thread::spawn(move || {
+                    for _ in 0..500 {
+                        let mut buf = pool.take(4096);
+                        buf.resize(4096, id);
+                        std::thread::yield_now();
+                        assert!(buf.iter().all(|&b| b == id));
+                        pool.give(buf);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(pool.held_bytes() <= 64 * 4096);
+    }
+}
@@ -1,5 +1,7 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use crate::io_scheduler::IoClass;
+use crate::tiering::StorageTier;
 
 #[derive(Parser)]
 #[command(name = "dynamicfs")]
@@ -13,6 +15,14 @@ pub struct Cli {
     #[arg(long, global = true, default_value = "auto")]
     pub direct_io: String,
 
+    /// Key file of an encrypted pool (32 raw bytes or 64 hex digits)
+    #[arg(long, global = true, conflicts_with = "passphrase_file")]
+    pub key_file: Option<PathBuf>,
+
+    /// File holding the passphrase of an encrypted pool
+    #[arg(long, global = true)]
+    pub passphrase_file: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -24,8 +34,122 @@ pub enum Commands {
         /// Pool directory
         #[arg(short, long)]
         pool: PathBuf,
+
+        /// Encrypt fragments at rest with the key from --key-file or --passphrase-file
+        /// (a missing key file is created with a random key)
+        #[arg(long, default_value_t = false)]
+        encrypt: bool,
+
+        /// Compress extent data before encoding (none|lz4|zstd)
+        #[arg(long, default_value = "none")]
+        compression: String,
+
+        /// Read back and checksum every fragment after writing it
+        #[arg(long, default_value_t = false)]
+        verify_writes: bool,
+
+        /// Resolve file names ignoring case, keeping the casing they were created with
+        #[arg(long, default_value_t = false)]
+        case_insensitive: bool,
+
+        /// Size in KB of the extents files are cut into: a power of two from 64 KB to 64 MB
+        #[arg(long, default_value_t = 1024)]
+        extent_size_kb: usize,
+
+        /// Keep inode, extent and extent map records as one file each or in a key-value store (files|kv);
+        /// kv needs a build with the kv-metadata feature
+        #[arg(long, default_value = "files")]
+        metadata_backend: String,
     },
     
+    /// Change the compression applied to newly written extents
+    SetCompression {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Algorithm (none|lz4|zstd)
+        #[arg(short, long)]
+        algorithm: String,
+    },
+    
+    /// Turn verify-on-write on or off for the pool
+    SetVerifyWrites {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// on|off
+        #[arg(value_parser = clap::builder::BoolishValueParser::new())]
+        enabled: bool,
+    },
+
+    /// Set the share of every disk that writes leave free for rebuilds
+    SetSpaceReserve {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Percent of each disk's capacity (0-50)
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=50))]
+        percent: u8,
+    },
+
+    /// Limit background rebuilds; applies to a mounted pool at once
+    SetRebuildLimit {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Rebuild MiB/s while the pool serves I/O (0 = unlimited)
+        #[arg(long)]
+        mbps: Option<u64>,
+
+        /// Rebuilds running at once while the pool serves I/O
+        #[arg(long)]
+        concurrent: Option<usize>,
+
+        /// Rebuild MiB/s while the pool is idle (0 = unlimited)
+        #[arg(long)]
+        idle_mbps: Option<u64>,
+
+        /// Rebuilds running at once while the pool is idle
+        #[arg(long)]
+        idle_concurrent: Option<usize>,
+
+        /// Seconds without reads or writes before the pool counts as idle
+        #[arg(long)]
+        idle_after_secs: Option<u64>,
+
+        /// Fragment requests in flight per disk; background I/O leaves one for foreground reads and writes
+        #[arg(long)]
+        disk_queue_depth: Option<usize>,
+
+        /// Background I/O class that --io-concurrent and --io-weight apply to: rebuild, scrub, defrag or gc
+        #[arg(long)]
+        io_class: Option<IoClass>,
+
+        /// Fragment requests of the class in flight at once, across all disks
+        #[arg(long, requires = "io_class")]
+        io_concurrent: Option<usize>,
+
+        /// Share of a busy disk the class gets against the other background classes
+        #[arg(long, requires = "io_class")]
+        io_weight: Option<u32>,
+    },
+    
+    /// Show or change pool settings kept in config.json and pool.json
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Define automated storage policies and run them against the pool
+    Policy {
+        #[command(subcommand)]
+        action: PolicyAction,
+    },
+
     /// Add a disk to the pool
     AddDisk {
         /// Pool directory
@@ -40,9 +164,26 @@ pub enum Commands {
         #[arg(long, default_value_t = false)]
         device: bool,
 
-        /// Force adding device even if it appears to be previously formatted (EXTREME DANGER!)
+        /// Force adding device even if it appears to be previously formatted (EXTREME DANGER!),
+        /// or a disk on the same device as another disk of the pool
         #[arg(long, default_value_t = false)]
         force: bool,
+
+        /// Add a disk that belongs to another pool, orphaning its fragments there
+        #[arg(long, default_value_t = false)]
+        force_takeover: bool,
+
+        /// Rebuild degraded extents once the disk is added, favouring it as the emptiest disk
+        #[arg(long, default_value_t = false)]
+        rebuild: bool,
+
+        /// With --rebuild, limit fragment writes to this many bytes per second
+        #[arg(long)]
+        max_bytes_per_sec: Option<u64>,
+
+        /// Storage tier of the disk: nvme, ssd or hdd (detected when omitted)
+        #[arg(long)]
+        tier: Option<StorageTier>,
     },
 
     /// Remove a disk from the pool
@@ -56,6 +197,34 @@ pub enum Commands {
         disk: PathBuf,
     },
 
+    /// Replace a failed disk: add the new one, rebuild the old one's fragments onto it,
+    /// then remove the old one from the pool (resumes an interrupted run)
+    ReplaceDisk {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// UUID of the disk being replaced
+        #[arg(long)]
+        old: uuid::Uuid,
+
+        /// Directory or block device path of the new disk
+        #[arg(long)]
+        new: PathBuf,
+
+        /// The new disk is a raw block device (REQUIRES ROOT PRIVILEGES - DANGER: will overwrite device!)
+        #[arg(long, default_value_t = false)]
+        device: bool,
+
+        /// Add the new disk even if it appears formatted or shares a device with another disk of the pool
+        #[arg(long, default_value_t = false)]
+        force: bool,
+
+        /// Limit fragment writes to this many bytes per second
+        #[arg(long)]
+        max_bytes_per_sec: Option<u64>,
+    },
+
     /// List all disks in the pool
     ListDisks {
         /// Pool directory
@@ -119,20 +288,58 @@ pub enum Commands {
         /// Pool directory
         #[arg(short, long)]
         pool: PathBuf,
+
+        /// Redraw the progress of policy changes until none is running
+        #[arg(short, long)]
+        watch: bool,
+
+        /// Seconds between updates with --watch
+        #[arg(short, long, default_value = "2", value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
     },
     
-    /// List hot extents
+    /// Stop a file's policy change after its current extent
+    CancelPolicyChange {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Path of the file inside the pool
+        #[arg(long, required_unless_present = "ino", conflicts_with = "ino")]
+        path: Option<String>,
+
+        /// Inode number of the file, instead of its path
+        #[arg(long)]
+        ino: Option<u64>,
+    },
+    
+    /// List hot extents, most frequently accessed first
     ListHot {
         /// Pool directory
         #[arg(short, long)]
         pool: PathBuf,
+        
+        /// Show at most N extents
+        #[arg(short, long)]
+        limit: Option<usize>,
     },
     
-    /// List cold extents
+    /// List cold extents, least frequently accessed first
     ListCold {
         /// Pool directory
         #[arg(short, long)]
         pool: PathBuf,
+        
+        /// Show at most N extents
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+    
+    /// Show capacity, usage and extent counts of each storage tier
+    TierStatus {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
     },
     
     /// Show extent access statistics
@@ -180,6 +387,10 @@ pub enum Commands {
         /// Pool directory
         #[arg(short, long)]
         pool: PathBuf,
+
+        /// Also recount each disk's fragments and bytes from what it holds
+        #[arg(long)]
+        recount: bool,
     },
 
     /// Scrub all extents for corruption and issues
@@ -191,6 +402,222 @@ pub enum Commands {
         /// Attempt to repair detected issues
         #[arg(short, long, default_value = "false")]
         repair: bool,
+
+        /// I/O budget (low ~20 MB/s, medium ~100 MB/s, high unlimited)
+        #[arg(short, long, default_value = "high")]
+        intensity: String,
+
+        /// Extents verified in parallel (default: one per CPU)
+        #[arg(long)]
+        workers: Option<usize>,
+
+        /// Override the intensity's read budget, in bytes per second
+        #[arg(long)]
+        max_bytes_per_sec: Option<u64>,
+    },
+
+    /// Move fragments between disks to even out utilization
+    Rebalance {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Stop when healthy disks are within this many percentage points of each other
+        #[arg(long, default_value = "10")]
+        target_spread: f64,
+
+        /// Limit fragment copies to this many bytes per second
+        #[arg(long)]
+        max_bytes_per_sec: Option<u64>,
+
+        /// Report the planned movement without moving anything
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+    },
+
+    /// Restore missing fragments of degraded extents (resumes an interrupted run)
+    Rebuild {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Limit fragment writes to this many bytes per second
+        #[arg(long)]
+        max_bytes_per_sec: Option<u64>,
+    },
+
+    /// Check the directory index against inode records
+    CheckDirindex {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Rebuild the index from inode records if they diverge
+        #[arg(short, long, default_value = "false")]
+        repair: bool,
+    },
+
+    /// Check the consistency of the pool's metadata (pool must not be mounted)
+    Check {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Fix findings that lose no data references
+        #[arg(short, long, default_value = "false")]
+        repair: bool,
+
+        /// With --repair, also drop orphaned extent maps and locations on unknown disks
+        #[arg(long, default_value = "false")]
+        force: bool,
+    },
+
+    /// Manage per-directory byte and inode quotas
+    Quota {
+        #[command(subcommand)]
+        action: QuotaAction,
+    },
+
+    /// List every extent of a file and the disks holding its fragments
+    FileLayout {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Inode number of the file
+        #[arg(long)]
+        ino: u64,
+    },
+
+    /// Verify, and optionally repair, the extents of a single file
+    VerifyFile {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Path of the file inside the pool
+        #[arg(long, required_unless_present = "ino", conflicts_with = "ino")]
+        path: Option<String>,
+
+        /// Inode number of the file, instead of its path
+        #[arg(long)]
+        ino: Option<u64>,
+
+        /// Rebuild missing and corrupt fragments where the extent can still be decoded
+        #[arg(short, long)]
+        repair: bool,
+    },
+
+    /// Logical and physical space used under a path, per directory
+    Du {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Path inside the pool (defaults to the root)
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// Report directories at most this many levels below the path, like `du -d`
+        #[arg(short, long)]
+        depth: Option<usize>,
+    },
+
+    /// Write the pool's files to a portable archive
+    Export {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Archive to write
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Only include files changed since this export snapshot
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Record this export as a snapshot named NAME, for a later --since
+        #[arg(long, value_name = "NAME")]
+        snapshot: Option<String>,
+
+        /// Compress the archive with zstd
+        #[arg(long, default_value = "false")]
+        compress: bool,
+    },
+
+    /// Create or verify integrity manifests of the pool's files
+    Manifest {
+        #[command(subcommand)]
+        action: ManifestAction,
+    },
+
+    /// Files changed since a change generation or export snapshot, with the byte ranges to copy
+    ChangedFiles {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// List changes after this generation, as printed by an earlier run
+        #[arg(long, value_name = "G", required_unless_present = "since_snapshot", conflicts_with = "since_snapshot")]
+        since_generation: Option<u64>,
+
+        /// List changes made after the export recorded as snapshot NAME
+        #[arg(long, value_name = "NAME")]
+        since_snapshot: Option<String>,
+    },
+
+    /// Move the inode, extent and extent map records to another store, verifying the copy before switching
+    MigrateMetadata {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Store to move the records to (files|kv)
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Recreate the files of an export archive in the pool
+    Import {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Archive to read
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Directory inside the pool to restore under
+        #[arg(long, default_value = "/")]
+        prefix: String,
+    },
+
+    /// Copy a directory tree from the host into the pool
+    Ingest {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Directory tree to copy in
+        #[arg(short, long)]
+        source: PathBuf,
+
+        /// Directory inside the pool to copy it under
+        #[arg(long, default_value = "/")]
+        dest: String,
+
+        /// Files written at once
+        #[arg(long, default_value = "4")]
+        threads: usize,
+
+        /// Compare checksums instead of size and mtime before skipping files an earlier run copied
+        #[arg(long, default_value = "false")]
+        verify: bool,
+
+        /// Only report what would be copied and the pool space it needs
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
     },
 
     /// Control background scrub daemon
@@ -199,6 +626,12 @@ pub enum Commands {
         action: ScrubDaemonAction,
     },
 
+    /// Take, list and delete point-in-time snapshots, shown under /.snapshots when mounted
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+
     /// Schedule periodic scrubbing
     ScrubSchedule {
         /// Pool directory
@@ -252,6 +685,10 @@ pub enum Commands {
     },
     
     /// Mount the filesystem
+    ///
+    /// `df` on the mountpoint reports usable space: raw capacity of non-failed
+    /// disks divided by the replication:3 overhead (free space counts healthy
+    /// disks only).
     Mount {
         /// Pool directory
         #[arg(short, long)]
@@ -260,6 +697,123 @@ pub enum Commands {
         /// Mount point
         #[arg(short, long)]
         mountpoint: PathBuf,
+        
+        /// Memory budget for buffered writes across all files (MiB; default: write_buffer in the pool config)
+        #[arg(long)]
+        write_buffer_mb: Option<u64>,
+        
+        /// Seconds a buffered write may sit idle before it is flushed (default: pool config)
+        #[arg(long)]
+        write_flush_secs: Option<u64>,
+
+        /// Mount read-only; writes fail with EROFS
+        #[arg(long, default_value = "false")]
+        read_only: bool,
+
+        /// Do not let other users access the mount
+        #[arg(long, default_value = "false")]
+        no_allow_other: bool,
+
+        /// Never update access times on read (same as -o noatime)
+        #[arg(long, default_value = "false", conflicts_with = "relatime")]
+        noatime: bool,
+
+        /// Update access times only when older than mtime/ctime or a day old (the default)
+        #[arg(long, default_value = "false")]
+        relatime: bool,
+
+        /// Extra FUSE mount option KEY[=VALUE] (repeatable)
+        #[arg(short = 'o', value_name = "KEY[=VALUE]")]
+        options: Vec<String>,
+
+        /// Serve Prometheus metrics and /health on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+
+        /// Address for the metrics listener
+        #[arg(long, default_value = "127.0.0.1")]
+        metrics_bind: String,
+
+        /// Seconds between recomputing pool gauges for the metrics listener
+        #[arg(long, default_value = "15")]
+        metrics_refresh_secs: u64,
+
+        /// Seconds between background orphan GC passes (0 disables; default: pool config)
+        #[arg(long)]
+        orphan_gc_interval_secs: Option<u64>,
+
+        /// Only collect orphaned fragments older than this many hours (default: pool config)
+        #[arg(long)]
+        orphan_gc_min_age_hours: Option<u64>,
+
+        /// Seconds between checks for disks that were unplugged or came back (0 disables; default: pool config)
+        #[arg(long)]
+        disk_probe_secs: Option<u64>,
+
+        /// Seconds between writing counted extent reads to metadata (0: only on unmount; default: pool config)
+        #[arg(long)]
+        access_stats_flush_secs: Option<u64>,
+
+        /// Seconds between passes moving cold extents to slower tiers (0: disabled; default: pool config)
+        #[arg(long)]
+        tiering_interval_secs: Option<u64>,
+
+        /// Also append events to events.log in the pool, rotating at this size (MiB)
+        #[arg(long)]
+        events_log_mb: Option<u64>,
+
+        /// Run in the background once mounted, logging to mount.log in the pool
+        #[arg(long, default_value = "false")]
+        daemonize: bool,
+    },
+
+    /// Unmount a mounted pool, flushing it first
+    Unmount {
+        /// Pool directory
+        #[arg(short, long, required_unless_present = "mountpoint", conflicts_with = "mountpoint")]
+        pool: Option<PathBuf>,
+
+        /// Mount point, instead of the pool
+        #[arg(short, long)]
+        mountpoint: Option<PathBuf>,
+
+        /// Seconds to wait for a clean shutdown before forcing the unmount
+        #[arg(long, default_value = "60")]
+        timeout_secs: u64,
+    },
+
+    /// Show recent storage events of a mounted pool
+    Events {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Keep running and print new events as they happen
+        #[arg(short, long, default_value = "false")]
+        follow: bool,
+
+        /// Only show events of this type (e.g. degraded_read, disk_health)
+        #[arg(long = "type", value_name = "TYPE")]
+        kind: Option<String>,
+    },
+
+    /// Show live operation, disk and inode activity of a mounted pool
+    Top {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Seconds between updates
+        #[arg(short, long, default_value = "2", value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Hottest inodes to list
+        #[arg(long, default_value = "10")]
+        top: usize,
+
+        /// Stop after this many updates
+        #[arg(short = 'n', long)]
+        count: Option<usize>,
     },
     
     /// Run performance benchmarks
@@ -275,6 +829,34 @@ pub enum Commands {
         /// Number of operations
         #[arg(short, long, default_value = "10")]
         operations: usize,
+        
+        /// Number of files the operations are spread over
+        #[arg(long, default_value = "10")]
+        files: usize,
+        
+        /// Workload: write, read or mixed
+        #[arg(long, default_value = "mixed")]
+        mode: String,
+        
+        /// Share of operations that read in mixed mode (0.0-1.0)
+        #[arg(long, default_value = "0.5")]
+        read_ratio: f64,
+        
+        /// Threads issuing operations
+        #[arg(long, default_value = "1")]
+        threads: usize,
+        
+        /// Operation sizes: sequential (whole files in turn) or random (random spans of random files)
+        #[arg(long, default_value = "sequential")]
+        pattern: String,
+        
+        /// Seed for the random choices, to repeat a run
+        #[arg(long)]
+        seed: Option<u64>,
+        
+        /// Leave the benchmark's files in the pool instead of removing them
+        #[arg(long)]
+        keep: bool,
     },
     
     /// Check system health with diagnostics
@@ -291,7 +873,7 @@ pub enum Commands {
         pool: PathBuf,
     },
     
-    /// Start defragmentation process
+    /// Enable background defragmentation in the mount process
     DefragStart {
         /// Pool directory
         #[arg(short, long)]
@@ -300,9 +882,25 @@ pub enum Commands {
         /// Defragmentation intensity (low|medium|high)
         #[arg(short, long, default_value = "medium")]
         intensity: String,
+        
+        /// Local time window for relocation, e.g. 02:00-05:00; outside it only idle periods are used
+        #[arg(long)]
+        window: Option<String>,
+        
+        /// Remove the window, relocating whenever the pool is idle
+        #[arg(long, conflicts_with = "window")]
+        clear_window: bool,
+        
+        /// Percent of extents that must be fragmented before a pass starts
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        threshold: Option<u8>,
+
+        /// Coalesce files with more than this many extents under a quarter of the pool's extent size (0 disables)
+        #[arg(long)]
+        coalesce_min_extents: Option<usize>,
     },
     
-    /// Stop defragmentation process
+    /// Disable background defragmentation
     DefragStop {
         /// Pool directory
         #[arg(short, long)]
@@ -365,6 +963,232 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum QuotaAction {
+    /// Set the limits of a directory, replacing any it already has
+    Set {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Directory inside the pool, e.g. /projects/foo
+        #[arg(long)]
+        path: String,
+
+        /// Byte limit with an optional K/M/G/T/P suffix (unlimited if omitted)
+        #[arg(long)]
+        bytes: Option<String>,
+
+        /// Inode limit with an optional K/M/G/T/P suffix (unlimited if omitted)
+        #[arg(long)]
+        inodes: Option<String>,
+    },
+
+    /// Show limits and usage of one directory, or of every quota in the pool
+    Show {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Directory inside the pool
+        #[arg(long)]
+        path: Option<String>,
+    },
+
+    /// Remove the quota of a directory
+    Remove {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Directory inside the pool
+        #[arg(long)]
+        path: String,
+    },
+
+    /// Rebuild usage counters by walking the tree, for one directory or every quota
+    Recalc {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Directory inside the pool
+        #[arg(long)]
+        path: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Capture the whole pool as snapshot NAME
+    Create {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Name of the snapshot; also its directory under /.snapshots
+        name: String,
+    },
+
+    /// List the pool's snapshots, oldest first
+    List {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+    },
+
+    /// Delete snapshot NAME and free the data only it still held
+    Delete {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Name of the snapshot
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ManifestAction {
+    /// Hash every file under a directory into a manifest
+    Create {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Directory or file of the pool to cover
+        #[arg(long, default_value = "/")]
+        path: String,
+
+        /// Manifest to write
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Files hashed at once
+        #[arg(long, default_value = "4")]
+        threads: usize,
+
+        /// Sign with the ed25519 secret key in FILE (32 raw bytes or 64 hex
+        /// digits); a missing FILE is created with a new key, and its public
+        /// key written to FILE.pub
+        #[arg(long, value_name = "FILE")]
+        signing_key: Option<PathBuf>,
+    },
+
+    /// Compare the pool's files with a manifest; exits non-zero on any difference
+    Verify {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Manifest to check against
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Read back and hash every file, even those whose extents are unchanged
+        #[arg(long, default_value = "false")]
+        full: bool,
+
+        /// Files hashed at once
+        #[arg(long, default_value = "4")]
+        threads: usize,
+
+        /// Require a signature by the ed25519 public key in FILE (32 raw bytes
+        /// or 64 hex digits)
+        #[arg(long, value_name = "FILE")]
+        public_key: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print the value of one key, or of every key
+    Get {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Key, e.g. write_buffer or rebuild.max_rate
+        key: Option<String>,
+    },
+
+    /// Validate and store a value; live keys also apply to a mounted pool
+    Set {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        key: String,
+
+        /// Sizes take K/M/G/T suffixes, booleans on/off
+        value: String,
+    },
+
+    /// List every key with its value and when a change takes effect
+    List {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PolicyAction {
+    /// Add a policy from a TOML file, replacing one of the same name
+    Add {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Policy file with a name, rules, actions and a schedule
+        #[arg(short, long)]
+        file: PathBuf,
+    },
+
+    /// List the pool's policies
+    List {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+    },
+
+    /// Remove a policy
+    Remove {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Policy name
+        name: String,
+    },
+
+    /// Evaluate policies against every extent and carry out the approved actions
+    Run {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Only this policy (default: every enabled policy)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Print the proposals without carrying them out
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+    },
+
+    /// Show actions policies have carried out, newest last
+    Audit {
+        /// Pool directory
+        #[arg(short, long)]
+        pool: PathBuf,
+
+        /// Only the last N entries
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+}
+
 #[derive(Subcommand)]
 pub enum ScrubDaemonAction {
     /// Start the background scrub daemon
@@ -0,0 +1,266 @@
+//! TCP transport for cluster `RpcMessage`s
+//!
+//! Every message travels as a frame: a 4-byte big-endian length followed by
+//! the message in bincode. Each side of a new connection first sends a
+//! `Handshake`, so nodes speaking another protocol version are refused before
+//! any request. After that the client sends requests and reads one reply to
+//! each, over as many requests as it likes.
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::distributed::RpcMessage;
+
+/// Bumped whenever `RpcMessage` or the framing changes incompatibly
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Longest a connection waits for a frame, or to send one, before giving up
+pub const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Larger frames are refused unread; an extent and its envelope fit well within
+const MAX_FRAME_BYTES: u32 = 256 << 20;
+
+/// First frame in each direction of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub node_id: u64,
+}
+
+pub fn write_frame<T: Serialize>(stream: &mut impl Write, message: &T) -> Result<()> {
+    let payload = bincode::serialize(message).context("Failed to encode RPC frame")?;
+    let len = u32::try_from(payload.len()).ok().filter(|len| *len <= MAX_FRAME_BYTES);
+    let len = len.ok_or_else(|| anyhow!("RPC frame of {} bytes is too large", payload.len()))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(&payload)?;
+    stream.flush()?;
+    Ok(())
+}
+
+pub fn read_frame<T: DeserializeOwned>(stream: &mut impl Read) -> Result<T> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_BYTES {
+        return Err(anyhow!("RPC frame of {} bytes is too large", len));
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    bincode::deserialize(&payload).context("Failed to decode RPC frame")
+}
+
+/// Answers the requests a node receives
+pub trait RpcHandler: Send + Sync + 'static {
+    /// Identity sent in this node's handshake
+    fn node_id(&self) -> u64;
+
+    /// Reply to one request; failures are reported as `RpcMessage::Error`
+    fn handle(&self, request: RpcMessage) -> RpcMessage;
+}
+
+/// Accepts RPC connections and serves each on its own thread
+///
+/// Stops, closing every open connection, when dropped.
+pub struct RpcServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    /// Open connections, to close them on shutdown
+    connections: Arc<Mutex<HashMap<u64, TcpStream>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Serve the connections `listener` accepts
+    pub fn start(listener: TcpListener, handler: Arc<dyn RpcHandler>) -> Result<Self> {
+        let local_addr = listener.local_addr()?;
+        // Polled so the thread notices `stop`
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let connections = Arc::clone(&connections);
+            thread::spawn(move || accept_connections(listener, handler, stop, connections))
+        };
+        log::info!("Serving cluster RPC on {}", local_addr);
+        Ok(RpcServer { local_addr, stop, connections, thread: Some(thread) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        for (_, stream) in self.connections.lock().unwrap().drain() {
+            stream.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
+
+fn accept_connections(
+    listener: TcpListener,
+    handler: Arc<dyn RpcHandler>,
+    stop: Arc<AtomicBool>,
+    connections: Arc<Mutex<HashMap<u64, TcpStream>>>,
+) {
+    let mut next_id = 0;
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let id = next_id;
+                next_id += 1;
+                match stream.try_clone() {
+                    Ok(clone) => {
+                        connections.lock().unwrap().insert(id, clone);
+                    }
+                    Err(e) => log::warn!("RPC connection from {} cannot be closed on shutdown: {}", peer, e),
+                }
+                let handler = Arc::clone(&handler);
+                let connections = Arc::clone(&connections);
+                thread::spawn(move || {
+                    if let Err(e) = serve_connection(handler.as_ref(), stream) {
+                        log::debug!("RPC connection from {} closed: {:#}", peer, e);
+                    }
+                    connections.lock().unwrap().remove(&id);
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(20)),
+            Err(e) => log::error!("RPC connection failed: {}", e),
+        }
+    }
+}
+
+fn serve_connection(handler: &dyn RpcHandler, mut stream: TcpStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let hello: Handshake = read_frame(&mut stream)?;
+    // Answered either way, so the client can tell why it is turned away
+    write_frame(&mut stream, &Handshake { version: PROTOCOL_VERSION, node_id: handler.node_id() })?;
+    if hello.version != PROTOCOL_VERSION {
+        return Err(anyhow!(
+            "node {} speaks protocol version {}, this node {}",
+            hello.node_id,
+            hello.version,
+            PROTOCOL_VERSION
+        ));
+    }
+    loop {
+        let request: RpcMessage = match read_frame(&mut stream) {
+            Ok(request) => request,
+            Err(e) if is_closed(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        write_frame(&mut stream, &handler.handle(request))?;
+    }
+}
+
+fn is_closed(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == ErrorKind::UnexpectedEof)
+}
+
+/// Connection to one peer's RPC server
+pub struct RpcClient {
+    stream: TcpStream,
+    peer: Handshake,
+}
+
+impl RpcClient {
+    /// Connect and exchange handshakes, failing if the peer speaks another protocol version
+    pub fn connect(addr: SocketAddr, node_id: u64) -> Result<Self> {
+        let mut stream = TcpStream::connect_timeout(&addr, IO_TIMEOUT)
+            .with_context(|| format!("Failed to connect to cluster node at {}", addr))?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        write_frame(&mut stream, &Handshake { version: PROTOCOL_VERSION, node_id })?;
+        let peer: Handshake = read_frame(&mut stream).with_context(|| format!("No handshake from {}", addr))?;
+        if peer.version != PROTOCOL_VERSION {
+            return Err(anyhow!(
+                "Node {} at {} speaks protocol version {}, this node {}",
+                peer.node_id,
+                addr,
+                peer.version,
+                PROTOCOL_VERSION
+            ));
+        }
+        Ok(RpcClient { stream, peer })
+    }
+
+    /// Node ID the peer gave in its handshake
+    pub fn peer_node_id(&self) -> u64 {
+        self.peer.node_id
+    }
+
+    /// Send one request and wait for its reply
+    ///
+    /// An `RpcMessage::Error` reply is returned as an error.
+    pub fn call(&mut self, request: &RpcMessage) -> Result<RpcMessage> {
+        write_frame(&mut self.stream, request)?;
+        match read_frame(&mut self.stream)? {
+            RpcMessage::Error { message } => Err(anyhow!("Node {} refused the request: {}", self.peer.node_id, message)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl RpcHandler for Echo {
+        fn node_id(&self) -> u64 {
+            7
+        }
+
+        fn handle(&self, request: RpcMessage) -> RpcMessage {
+            match request {
+                RpcMessage::Heartbeat { node_id, .. } => RpcMessage::HeartbeatAck { node_id: self.node_id() + node_id },
+                _ => RpcMessage::Error { message: "unsupported".to_string() },
+            }
+        }
+    }
+
+    #[test]
+    fn test_handshake_refuses_other_versions_and_requests_share_a_connection() {
+        let server = RpcServer::start(TcpListener::bind("127.0.0.1:0").unwrap(), Arc::new(Echo)).unwrap();
+        let mut client = RpcClient::connect(server.local_addr(), 1).unwrap();
+        assert_eq!(client.peer_node_id(), 7);
+        for node_id in 1..=3 {
+            let reply = client.call(&RpcMessage::Heartbeat { node_id, timestamp: 0 }).unwrap();
+            assert!(matches!(reply, RpcMessage::HeartbeatAck { node_id: ack } if ack == 7 + node_id));
+        }
+        let err = client.call(&RpcMessage::GetExtent { extent_uuid: uuid::Uuid::new_v4() }).unwrap_err();
+        assert!(err.to_string().contains("unsupported"), "{}", err);
+
+        // A node from the future is told this node's version and disconnected
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write_frame(&mut stream, &Handshake { version: PROTOCOL_VERSION + 1, node_id: 2 }).unwrap();
+        let reply: Handshake = read_frame(&mut stream).unwrap();
+        assert_eq!(reply, Handshake { version: PROTOCOL_VERSION, node_id: 7 });
+        write_frame(&mut stream, &RpcMessage::Heartbeat { node_id: 2, timestamp: 0 }).ok();
+        assert!(read_frame::<RpcMessage>(&mut stream).is_err());
+
+        // Oversized frames are refused before they are read
+        let mut oversized = (MAX_FRAME_BYTES + 1).to_be_bytes().to_vec();
+        oversized.extend_from_slice(&[0; 16]);
+        assert!(read_frame::<Handshake>(&mut oversized.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Compressed data at least this fraction of the original is stored uncompressed
+pub const MIN_SAVINGS_RATIO: f64 = 0.97;
+
+/// zstd level used for extent data; favours throughput over ratio
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression applied to extent data before it is encoded into fragments
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// Compress `data`, or `None` if this is `Compression::None` or the result
+    /// would not be meaningfully smaller than `data`
+    pub fn compress(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let compressed = match self {
+            Compression::None => return None,
+            Compression::Lz4 => lz4_flex::compress(data),
+            Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok()?,
+        };
+        if (compressed.len() as f64) < data.len() as f64 * MIN_SAVINGS_RATIO {
+            Some(compressed)
+        } else {
+            None
+        }
+    }
+
+    /// Decompress data compressed with this algorithm back to `size` bytes
+    pub fn decompress(&self, data: &[u8], size: usize) -> Result<Vec<u8>> {
+        let decompressed = match self {
+            Compression::None => data.to_vec(),
+            Compression::Lz4 => lz4_flex::decompress(data, size).map_err(|e| anyhow!("lz4: {}", e))?,
+            Compression::Zstd => zstd::bulk::decompress(data, size).map_err(|e| anyhow!("zstd: {}", e))?,
+        };
+        if decompressed.len() != size {
+            return Err(anyhow!("Decompressed {} bytes, expected {}", decompressed.len(), size));
+        }
+        Ok(decompressed)
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd),
+            other => Err(anyhow!("Invalid compression: {}. Use none, lz4 or zstd", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trip_and_incompressible_fallback() {
+        let text = b"2024-05-01T12:00:00Z INFO request served in 3ms\n".repeat(200);
+        for algorithm in [Compression::Lz4, Compression::Zstd] {
+            let compressed = algorithm.compress(&text).unwrap();
+            assert!(compressed.len() < text.len() / 4);
+            assert_eq!(algorithm.decompress(&compressed, text.len()).unwrap(), text);
+            assert!(algorithm.decompress(&compressed, text.len() + 1).is_err());
+        }
+
+        // Random bytes do not shrink enough to be worth storing compressed
+        let noise: Vec<u8> = (0..64 * 1024u32).map(|i| blake3::hash(&i.to_le_bytes()).as_bytes()[0]).collect();
+        assert_eq!(Compression::Zstd.compress(&noise), None);
+        assert_eq!(Compression::None.compress(&text), None);
+        assert_eq!("ZSTD".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("gzip".parse::<Compression>().is_err());
+    }
+}
@@ -271,3 +271,502 @@ impl ConfigBuilder {
     }
 }
 
+
+/// Version of `config.json` written by this build; newer versions are refused
+pub const POOL_CONFIG_VERSION: u32 = 1;
+
+/// Pool configuration file, relative to the pool directory
+pub const POOL_CONFIG_FILE: &str = "config.json";
+
+/// Settings of one pool that the mount process picks up at startup
+///
+/// Stored as `config.json` next to `pool.json`. Missing fields take their
+/// defaults, and fields this build does not know are kept as they are, so a
+/// config written by a newer build of the same version still loads and
+/// survives a `config set` from an older one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    pub version: u32,
+    /// Memory budget for buffered writes across all files
+    pub write_buffer_bytes: u64,
+    /// Seconds a buffered write may sit idle before it is flushed
+    pub write_flush_secs: u64,
+    /// When reads update access times, unless the mount options say otherwise
+    pub atime: crate::access_tracker::AtimeMode,
+    /// Seconds between background orphan GC passes; 0 disables them
+    pub orphan_gc_interval_secs: u64,
+    /// Only fragments orphaned for this many hours are collected
+    pub orphan_gc_min_age_hours: u64,
+    /// Seconds between checks for unplugged disks; 0 disables them
+    pub disk_probe_secs: u64,
+    /// Seconds between writing counted extent reads to metadata; 0 only at unmount
+    pub access_stats_flush_secs: u64,
+    /// Seconds between tiering passes; 0 disables them
+    pub tiering_interval_secs: u64,
+    /// Classify extents with the learned access model; false uses the thresholds alone
+    pub access_model: bool,
+    /// Poll block-device disks for SMART samples; directory-backed disks never are
+    pub smart_enabled: bool,
+    /// Seconds between SMART polls; 0 disables them
+    pub smart_poll_secs: u64,
+    /// Inodes, and names looked up, kept in memory; 0 disables the inode cache
+    pub inode_cache_capacity: u64,
+    /// Seconds cached inodes live, in the engine and the kernel; 0 disables the inode cache
+    pub inode_cache_ttl_secs: u64,
+    /// Memory kept for reusing fragment and extent read buffers; 0 disables the pool
+    pub read_buffer_pool_bytes: u64,
+    /// Seconds a placed extent settles before it is verified again; 0 disables the verifier
+    pub recent_write_settle_secs: u64,
+    /// Placed extents queued for verification at most; more are left to the full scrub
+    pub recent_write_capacity: u64,
+    /// Seconds between heartbeats to the other nodes of a cluster
+    pub cluster_heartbeat_secs: u64,
+    /// Seconds without an answer after which a cluster node counts as failed
+    pub cluster_failure_timeout_secs: u64,
+    /// Policies added with `policy add`, by name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<crate::policy_engine::Policy>,
+    /// Where pool events are sent for alerting
+    #[serde(skip_serializing_if = "crate::notify::NotificationConfig::is_default")]
+    pub notifications: crate::notify::NotificationConfig,
+    #[serde(flatten)]
+    pub unknown: std::collections::BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            version: POOL_CONFIG_VERSION,
+            write_buffer_bytes: 64 * 1024 * 1024,
+            write_flush_secs: 5,
+            atime: crate::access_tracker::AtimeMode::default(),
+            orphan_gc_interval_secs: 3600,
+            orphan_gc_min_age_hours: 24,
+            disk_probe_secs: 30,
+            access_stats_flush_secs: 60,
+            tiering_interval_secs: 3600,
+            access_model: true,
+            smart_enabled: true,
+            smart_poll_secs: 3600,
+            inode_cache_capacity: crate::inode_cache::DEFAULT_INODE_CACHE_CAPACITY as u64,
+            inode_cache_ttl_secs: crate::inode_cache::DEFAULT_INODE_CACHE_TTL.as_secs(),
+            read_buffer_pool_bytes: crate::buffer_pool::DEFAULT_READ_BUFFER_POOL_BYTES,
+            recent_write_settle_secs: crate::recent_writes::DEFAULT_RECENT_WRITE_SETTLE.as_secs(),
+            recent_write_capacity: crate::recent_writes::DEFAULT_RECENT_WRITE_CAPACITY as u64,
+            cluster_heartbeat_secs: 5,
+            cluster_failure_timeout_secs: 15,
+            policies: Vec::new(),
+            notifications: crate::notify::NotificationConfig::default(),
+            unknown: std::collections::BTreeMap::new(),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Load `config.json` from `pool_dir`, or the defaults if there is none
+    pub fn load(pool_dir: &std::path::Path) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let path = pool_dir.join(POOL_CONFIG_FILE);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(PoolConfig::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        Self::from_json(&contents).with_context(|| format!("Invalid pool config {:?}", path))
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let config: PoolConfig = serde_json::from_str(json)?;
+        if config.version > POOL_CONFIG_VERSION {
+            return Err(anyhow::anyhow!(
+                "Config version {} is newer than this build understands ({}); upgrade dynamicfs",
+                config.version,
+                POOL_CONFIG_VERSION
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn save(&self, pool_dir: &std::path::Path) -> anyhow::Result<()> {
+        let path = pool_dir.join(POOL_CONFIG_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// File a config key is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    /// `config.json`
+    Config,
+    /// `pool.json`, shared with the older `set-*` commands
+    Pool,
+}
+
+/// What values a config key accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigValueKind {
+    /// Bytes, with an optional K/M/G/T/P suffix
+    Size,
+    Count,
+    Seconds,
+    Percent,
+    /// A share of pool capacity from 1% to 100%
+    Watermark,
+    Bool,
+    Atime,
+    /// `allow` or `deny`
+    DegradedWrites,
+    /// A redundancy policy tolerating at least one failure, as `replication:N` or `erasure:K+M`
+    Policy,
+}
+
+/// A key `config get` and `config set` understand
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConfigKey {
+    pub name: &'static str,
+    pub kind: ConfigValueKind,
+    pub source: ConfigSource,
+    /// Applied to a mounted pool at once; otherwise from the next mount on
+    pub live: bool,
+    pub help: &'static str,
+}
+
+const fn key(name: &'static str, kind: ConfigValueKind, source: ConfigSource, live: bool, help: &'static str) -> ConfigKey {
+    ConfigKey { name, kind, source, live, help }
+}
+
+/// Every key of a pool's configuration
+pub const CONFIG_KEYS: &[ConfigKey] = {
+    use ConfigSource::*;
+    use ConfigValueKind::*;
+    &[
+        key("write_buffer", Size, Config, false, "Memory budget for buffered writes across all files"),
+        key("write_flush_secs", Seconds, Config, false, "Seconds a buffered write may sit idle before it is flushed"),
+        key("atime", Atime, Config, false, "Access time updates: relatime, noatime or strictatime"),
+        key("orphan_gc_interval_secs", Seconds, Config, false, "Seconds between orphan GC passes (0 disables)"),
+        key("orphan_gc_min_age_hours", Count, Config, false, "Only collect fragments orphaned this many hours"),
+        key("disk_probe_secs", Seconds, Config, false, "Seconds between checks for unplugged disks (0 disables)"),
+        key("access_stats_flush_secs", Seconds, Config, false, "Seconds between writing read counts (0: at unmount)"),
+        key("tiering_interval_secs", Seconds, Config, false, "Seconds between tiering passes (0 disables)"),
+        key("access_model", Bool, Config, false, "Classify extents with the learned access model, not thresholds alone"),
+        key("smart.enabled", Bool, Config, false, "Poll block-device disks for SMART samples to catch failing devices early"),
+        key("smart.poll_secs", Seconds, Config, false, "Seconds between SMART polls (0 disables)"),
+        key("inode_cache.capacity", Count, Config, false, "Inodes and name lookups kept in memory (0 disables)"),
+        key("inode_cache.ttl_secs", Seconds, Config, false, "Seconds cached inodes and kernel attributes live (0 disables)"),
+        key("read_buffer_pool", Size, Config, false, "Memory kept for reusing fragment and extent read buffers (0 disables)"),
+        key("recent_writes.settle_secs", Seconds, Config, false, "Seconds before a new extent is verified again (0 disables)"),
+        key("recent_writes.capacity", Count, Config, false, "New extents queued for verification; more are left to scrubs"),
+        key("cluster.heartbeat_secs", Seconds, Config, false, "Seconds between heartbeats to other cluster nodes"),
+        key("cluster.failure_timeout_secs", Seconds, Config, false, "Seconds of silence before a cluster node counts as failed"),
+        key("verify_writes", Bool, Pool, true, "Read back and checksum every fragment after writing it"),
+        key("space_reserve_percent", Percent, Pool, true, "Share of every disk that writes leave free for rebuilds (0-50)"),
+        key("space.high_watermark", Watermark, Pool, true, "Pool usage that starts reclamation and refuses low-priority writes"),
+        key("space.critical_watermark", Watermark, Pool, true, "Pool usage past which only deletes, truncates and rebuilds write"),
+        key("degraded_writes", DegradedWrites, Pool, true, "Write under a weaker policy when too few disks are writable: allow or deny"),
+        key("degraded_write_floor", Policy, Pool, true, "Weakest policy a degraded write may use (at least replication:2)"),
+        key("metadata_commit_window_ms", Count, Pool, true, "Milliseconds metadata transactions wait to share a sync (0: sync each, at most 1000)"),
+        key("rebuild.max_rate", Size, Pool, true, "Rebuild bytes per second while the pool is busy (0 = unlimited)"),
+        key("rebuild.max_concurrent", Count, Pool, true, "Rebuilds running at once while the pool is busy"),
+        key("rebuild.idle_max_rate", Size, Pool, true, "Rebuild bytes per second while the pool is idle (0 = unlimited)"),
+        key("rebuild.idle_max_concurrent", Count, Pool, true, "Rebuilds running at once while the pool is idle"),
+        key("rebuild.idle_after_secs", Seconds, Pool, true, "Seconds without I/O before the pool counts as idle"),
+    ]
+};
+
+/// Look up a key by name
+pub fn config_key(name: &str) -> anyhow::Result<&'static ConfigKey> {
+    CONFIG_KEYS
+        .iter()
+        .find(|key| key.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Unknown config key {:?}; `config list` shows them all", name))
+}
+
+/// `bytes` with the largest K/M/G/T suffix that divides it exactly
+pub fn format_size(bytes: u64) -> String {
+    for (shift, suffix) in [(40, "T"), (30, "G"), (20, "M"), (10, "K")] {
+        if bytes != 0 && bytes.is_multiple_of(1u64 << shift) {
+            return format!("{}{}", bytes >> shift, suffix);
+        }
+    }
+    bytes.to_string()
+}
+
+impl ConfigValueKind {
+    /// Check and normalise `value` as typed on the command line
+    fn parse(self, value: &str) -> anyhow::Result<serde_json::Value> {
+        let value = value.trim();
+        let invalid = |expected: &str| anyhow::anyhow!("Invalid value {:?}: expected {}", value, expected);
+        Ok(match self {
+            ConfigValueKind::Size => crate::quota::parse_limit(value)?.into(),
+            ConfigValueKind::Count | ConfigValueKind::Seconds => {
+                value.parse::<u64>().map_err(|_| invalid("a whole number"))?.into()
+            }
+            ConfigValueKind::Percent => match value.trim_end_matches('%').parse::<u8>() {
+                Ok(percent) if percent <= 50 => percent.into(),
+                _ => return Err(invalid("a percentage from 0 to 50")),
+            },
+            ConfigValueKind::Watermark => match value.trim_end_matches('%').parse::<u8>() {
+                Ok(percent) if (1..=100).contains(&percent) => percent.into(),
+                _ => return Err(invalid("a percentage from 1 to 100")),
+            },
+            ConfigValueKind::Bool => match value.to_ascii_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => true.into(),
+                "false" | "off" | "no" | "0" => false.into(),
+                _ => return Err(invalid("on or off")),
+            },
+            ConfigValueKind::Atime => match crate::access_tracker::AtimeMode::from_mount_option(value) {
+                Some(mode) => mode.mount_option().into(),
+                None => return Err(invalid("relatime, noatime or strictatime")),
+            },
+            ConfigValueKind::DegradedWrites => match value.to_ascii_lowercase().as_str() {
+                "allow" => "allow".into(),
+                "deny" => "deny".into(),
+                _ => return Err(invalid("allow or deny")),
+            },
+            ConfigValueKind::Policy => match value.parse::<crate::extent::RedundancyPolicy>() {
+                Ok(policy) if policy.failures_tolerated() >= 1 => policy.to_string().into(),
+                _ => return Err(invalid("a policy tolerating a failure, such as replication:2 or erasure:4+1")),
+            },
+        })
+    }
+
+    /// How `config get` prints a stored value
+    pub fn display(self, value: &serde_json::Value) -> String {
+        match (self, value.as_u64()) {
+            (ConfigValueKind::Size, Some(bytes)) => format_size(bytes),
+            (ConfigValueKind::Percent | ConfigValueKind::Watermark, Some(percent)) => format!("{}%", percent),
+            _ => value.as_str().map_or_else(|| value.to_string(), str::to_string),
+        }
+    }
+}
+
+/// Current value of `key` in `pool` and `config`
+pub fn get_config_value(pool: &crate::disk::DiskPool, config: &PoolConfig, key: &ConfigKey) -> serde_json::Value {
+    let limits = &pool.rebuild_limits;
+    match key.name {
+        "write_buffer" => config.write_buffer_bytes.into(),
+        "write_flush_secs" => config.write_flush_secs.into(),
+        "atime" => config.atime.mount_option().into(),
+        "orphan_gc_interval_secs" => config.orphan_gc_interval_secs.into(),
+        "orphan_gc_min_age_hours" => config.orphan_gc_min_age_hours.into(),
+        "disk_probe_secs" => config.disk_probe_secs.into(),
+        "access_stats_flush_secs" => config.access_stats_flush_secs.into(),
+        "tiering_interval_secs" => config.tiering_interval_secs.into(),
+        "access_model" => config.access_model.into(),
+        "smart.enabled" => config.smart_enabled.into(),
+        "smart.poll_secs" => config.smart_poll_secs.into(),
+        "inode_cache.capacity" => config.inode_cache_capacity.into(),
+        "inode_cache.ttl_secs" => config.inode_cache_ttl_secs.into(),
+        "read_buffer_pool" => config.read_buffer_pool_bytes.into(),
+        "recent_writes.settle_secs" => config.recent_write_settle_secs.into(),
+        "recent_writes.capacity" => config.recent_write_capacity.into(),
+        "cluster.heartbeat_secs" => config.cluster_heartbeat_secs.into(),
+        "cluster.failure_timeout_secs" => config.cluster_failure_timeout_secs.into(),
+        "verify_writes" => pool.verify_writes.into(),
+        "space_reserve_percent" => pool.space_reserve_percent.into(),
+        "space.high_watermark" => pool.space_watermarks.high_percent.into(),
+        "space.critical_watermark" => pool.space_watermarks.critical_percent.into(),
+        "degraded_writes" => pool.degraded_writes.name().into(),
+        "degraded_write_floor" => pool.degraded_write_floor.to_string().into(),
+        "metadata_commit_window_ms" => pool.metadata_commit_window_ms.into(),
+        "rebuild.max_rate" => limits.max_bytes_per_sec.into(),
+        "rebuild.max_concurrent" => limits.max_concurrent.into(),
+        "rebuild.idle_max_rate" => limits.idle_max_bytes_per_sec.into(),
+        "rebuild.idle_max_concurrent" => limits.idle_max_concurrent.into(),
+        "rebuild.idle_after_secs" => limits.idle_after_secs.into(),
+        name => unreachable!("config key {} has no field", name),
+    }
+}
+
+/// Validate `value` for `key` and store it in `pool` or `config`
+pub fn set_config_value(
+    pool: &mut crate::disk::DiskPool,
+    config: &mut PoolConfig,
+    key: &ConfigKey,
+    value: &str,
+) -> anyhow::Result<serde_json::Value> {
+    let parsed = key.kind.parse(value)?;
+    let number = parsed.as_u64().unwrap_or_default();
+    let mut limits = pool.rebuild_limits;
+    let mut watermarks = pool.space_watermarks;
+    match key.name {
+        "write_buffer" if number == 0 => return Err(anyhow::anyhow!("write_buffer must be more than 0")),
+        "write_buffer" => config.write_buffer_bytes = number,
+        "write_flush_secs" => config.write_flush_secs = number,
+        "atime" => config.atime = serde_json::from_value(parsed.clone())?,
+        "orphan_gc_interval_secs" => config.orphan_gc_interval_secs = number,
+        "orphan_gc_min_age_hours" => config.orphan_gc_min_age_hours = number,
+        "disk_probe_secs" => config.disk_probe_secs = number,
+        "access_stats_flush_secs" => config.access_stats_flush_secs = number,
+        "tiering_interval_secs" => config.tiering_interval_secs = number,
+        "access_model" => config.access_model = parsed.as_bool().unwrap_or_default(),
+        "smart.enabled" => config.smart_enabled = parsed.as_bool().unwrap_or_default(),
+        "smart.poll_secs" => config.smart_poll_secs = number,
+        "inode_cache.capacity" => config.inode_cache_capacity = number,
+        "inode_cache.ttl_secs" => config.inode_cache_ttl_secs = number,
+        "read_buffer_pool" => config.read_buffer_pool_bytes = number,
+        "recent_writes.settle_secs" => config.recent_write_settle_secs = number,
+        "recent_writes.capacity" => config.recent_write_capacity = number,
+        "cluster.heartbeat_secs" if number == 0 => return Err(anyhow::anyhow!("cluster.heartbeat_secs must be more than 0")),
+        "cluster.heartbeat_secs" => config.cluster_heartbeat_secs = number,
+        "cluster.failure_timeout_secs" => config.cluster_failure_timeout_secs = number,
+        "verify_writes" => pool.verify_writes = parsed.as_bool().unwrap_or_default(),
+        "space_reserve_percent" => pool.space_reserve_percent = number as u8,
+        "space.high_watermark" => watermarks.high_percent = number as u8,
+        "space.critical_watermark" => watermarks.critical_percent = number as u8,
+        "degraded_writes" => pool.degraded_writes = serde_json::from_value(parsed.clone())?,
+        "degraded_write_floor" => pool.degraded_write_floor = value.parse()?,
+        "metadata_commit_window_ms" if number > 1000 => {
+            return Err(anyhow::anyhow!("metadata_commit_window_ms must be at most 1000"))
+        }
+        "metadata_commit_window_ms" => pool.metadata_commit_window_ms = number,
+        "rebuild.max_rate" => limits.max_bytes_per_sec = number,
+        "rebuild.max_concurrent" => limits.max_concurrent = number as usize,
+        "rebuild.idle_max_rate" => limits.idle_max_bytes_per_sec = number,
+        "rebuild.idle_max_concurrent" => limits.idle_max_concurrent = number as usize,
+        "rebuild.idle_after_secs" => limits.idle_after_secs = number,
+        name => unreachable!("config key {} has no field", name),
+    }
+    if key.name.starts_with("cluster.") && config.cluster_failure_timeout_secs <= config.cluster_heartbeat_secs {
+        return Err(anyhow::anyhow!(
+            "cluster.failure_timeout_secs ({}) must be longer than cluster.heartbeat_secs ({})",
+            config.cluster_failure_timeout_secs,
+            config.cluster_heartbeat_secs
+        ));
+    }
+    limits.validate()?;
+    watermarks.validate()?;
+    pool.rebuild_limits = limits;
+    pool.space_watermarks = watermarks;
+    Ok(parsed)
+}
+
+/// The keys a mounted pool applies without a remount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveSettings {
+    pub verify_writes: bool,
+    pub space_reserve_percent: u8,
+    #[serde(default)]
+    pub space_watermarks: crate::placement::SpaceWatermarks,
+    pub degraded_writes: crate::placement::DegradedWrites,
+    pub degraded_write_floor: crate::extent::RedundancyPolicy,
+    pub metadata_commit_window_ms: u64,
+    pub rebuild_limits: crate::rebuild_budget::RebuildLimits,
+}
+
+impl LiveSettings {
+    pub fn from_pool(pool: &crate::disk::DiskPool) -> Self {
+        LiveSettings {
+            verify_writes: pool.verify_writes,
+            space_reserve_percent: pool.space_reserve_percent,
+            space_watermarks: pool.space_watermarks,
+            degraded_writes: pool.degraded_writes,
+            degraded_write_floor: pool.degraded_write_floor,
+            metadata_commit_window_ms: pool.metadata_commit_window_ms,
+            rebuild_limits: pool.rebuild_limits,
+        }
+    }
+
+    pub fn apply(&self, storage: &crate::storage::StorageEngine) -> anyhow::Result<()> {
+        storage.set_rebuild_limits(self.rebuild_limits)?;
+        storage.set_verify_writes(self.verify_writes);
+        storage.set_space_reserve_percent(self.space_reserve_percent);
+        storage.set_space_watermarks(self.space_watermarks);
+        storage.set_degraded_writes(self.degraded_writes, self.degraded_write_floor);
+        storage.set_metadata_commit_window(self.metadata_commit_window_ms);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::DiskPool;
+
+    #[test]
+    fn test_pool_config_round_trips_and_fills_defaults() {
+        let pool_dir = tempfile::tempdir().unwrap();
+        assert_eq!(PoolConfig::load(pool_dir.path()).unwrap(), PoolConfig::default());
+
+        let mut pool = DiskPool::new();
+        let mut config = PoolConfig::default();
+        for (name, value, shown) in [
+            ("write_buffer", "512M", "512M"),
+            ("atime", "noatime", "noatime"),
+            ("verify_writes", "on", "true"),
+            ("space_reserve_percent", "20%", "20%"),
+            ("rebuild.max_rate", "1.5G", "1536M"),
+            ("degraded_writes", "Allow", "allow"),
+            ("degraded_write_floor", "erasure:2+1", "erasure:2+1"),
+            ("metadata_commit_window_ms", "5", "5"),
+            ("space.critical_watermark", "99%", "99%"),
+            ("space.high_watermark", "95", "95%"),
+            ("smart.enabled", "off", "false"),
+            ("smart.poll_secs", "600", "600"),
+            ("inode_cache.capacity", "200000", "200000"),
+            ("inode_cache.ttl_secs", "0", "0"),
+            ("read_buffer_pool", "16M", "16M"),
+            ("recent_writes.settle_secs", "300", "300"),
+            ("recent_writes.capacity", "5000", "5000"),
+        ] {
+            let key = config_key(name).unwrap();
+            set_config_value(&mut pool, &mut config, key, value).unwrap();
+            assert_eq!(key.kind.display(&get_config_value(&pool, &config, key)), shown);
+        }
+        config.save(pool_dir.path()).unwrap();
+        assert_eq!(PoolConfig::load(pool_dir.path()).unwrap(), config);
+        assert_eq!(pool.rebuild_limits.max_bytes_per_sec, 1536 << 20);
+
+        // Written by an older build: only some fields
+        let partial = PoolConfig::from_json(r#"{"version": 1, "write_flush_secs": 9}"#).unwrap();
+        assert_eq!(partial, PoolConfig { write_flush_secs: 9, ..PoolConfig::default() });
+
+        // Type-aware validation
+        let set = |name: &str, value: &str| {
+            set_config_value(&mut DiskPool::new(), &mut PoolConfig::default(), config_key(name).unwrap(), value)
+        };
+        assert!(set("verify_writes", "maybe").is_err());
+        assert!(set("space_reserve_percent", "60").is_err());
+        assert!(set("atime", "sometimes").is_err());
+        assert!(set("degraded_writes", "maybe").is_err());
+        assert!(set("degraded_write_floor", "replication:1").is_err());
+        assert!(set("metadata_commit_window_ms", "5000").is_err());
+        assert!(set("space.high_watermark", "0").is_err());
+        assert!(set("space.high_watermark", "98").is_err());
+        assert!(set("space.critical_watermark", "101").is_err());
+        assert!(set("write_buffer", "lots").is_err());
+        assert!(set("write_buffer", "0").is_err());
+        assert!(set("rebuild.max_concurrent", "0").is_err());
+        assert!(set("cluster.heartbeat_secs", "0").is_err());
+        assert!(set("cluster.heartbeat_secs", "15").is_err());
+        assert!(set("cluster.failure_timeout_secs", "30").is_ok());
+        assert!(config_key("no_such_key").is_err());
+    }
+
+    #[test]
+    fn test_pool_config_keeps_unknown_keys_and_refuses_newer_versions() {
+        let pool_dir = tempfile::tempdir().unwrap();
+        let newer = r#"{"version": 1, "write_flush_secs": 7, "cache_dirs": ["/ssd"], "scrub": {"intensity": "low"}}"#;
+        std::fs::write(pool_dir.path().join(POOL_CONFIG_FILE), newer).unwrap();
+
+        let mut config = PoolConfig::load(pool_dir.path()).unwrap();
+        assert_eq!(config.write_flush_secs, 7);
+        config.write_flush_secs = 3;
+        config.save(pool_dir.path()).unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(pool_dir.path().join(POOL_CONFIG_FILE)).unwrap()).unwrap();
+        assert_eq!(saved["write_flush_secs"], 3);
+        assert_eq!(saved["cache_dirs"], serde_json::json!(["/ssd"]));
+        assert_eq!(saved["scrub"]["intensity"], "low");
+
+        std::fs::write(pool_dir.path().join(POOL_CONFIG_FILE), r#"{"version": 2}"#).unwrap();
+        let err = PoolConfig::load(pool_dir.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("newer than this build"));
+    }
+}
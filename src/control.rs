@@ -0,0 +1,300 @@
+//! Runtime settings of a mounted pool
+//!
+//! The mount process listens on `control.sock` in the pool directory. A
+//! client sends one JSON request line and reads one JSON reply line, so
+//! commands such as `set-rebuild-limit` and `defrag-start` take effect
+//! without a remount, and `top` and `defrag-analyze` see the live pool.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::activity::ActivitySnapshot;
+use crate::config::LiveSettings;
+use crate::defrag::{DefragConfig, DefragStatus, DefragmentationEngine, FragmentationAnalysis};
+use crate::io_scheduler::{self, IoLimits, IoSchedulerStatus};
+use crate::policy_change::PolicyChangeProgress;
+use crate::policy_engine::{Policy, PolicyRun};
+use crate::rebuild_budget::{RebuildLimits, RebuildStatus};
+use crate::snapshots::SnapshotInfo;
+use crate::storage::StorageEngine;
+
+/// Control socket of a mounted pool, relative to the pool directory
+pub const CONTROL_SOCKET: &str = "control.sock";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum ControlRequest {
+    RebuildStatus,
+    SetRebuildLimits { limits: RebuildLimits },
+    SetIoLimits { limits: IoLimits },
+    /// Counters for `top`, with the `top` busiest inodes of the last `window_secs`
+    Activity { window_secs: u64, top: usize },
+    DefragAnalyze,
+    DefragStatus,
+    SetDefragConfig { config: DefragConfig },
+    /// Apply keys changed with `config set`
+    ApplySettings { settings: LiveSettings },
+    /// Recorded policy changes, marking the ones this mount is running
+    PolicyChanges,
+    CancelPolicyChange { ino: u64 },
+    /// Evaluate `policies` with the mount's engine, so cache actions reach its data cache
+    RunPolicies { policies: Vec<Policy>, dry_run: bool },
+    /// Capture the pool as snapshot `name` with the mount's engine, so it flushes buffered writes first
+    CreateSnapshot { name: String },
+    DeleteSnapshot { name: String },
+    /// Unmount, flush and exit, as on SIGTERM
+    Shutdown,
+}
+
+impl ControlRequest {
+    /// How long a client waits for the reply; policy runs take as long as the pool is large
+    fn reply_timeout(&self) -> Option<Duration> {
+        match self {
+            ControlRequest::RunPolicies { .. } => None,
+            // Capturing or releasing a snapshot walks every file
+            ControlRequest::CreateSnapshot { .. } | ControlRequest::DeleteSnapshot { .. } => None,
+            _ => Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum ControlReply {
+    RebuildStatus { status: RebuildStatus },
+    IoStatus { status: IoSchedulerStatus },
+    Activity { snapshot: ActivitySnapshot },
+    DefragAnalysis { analysis: FragmentationAnalysis },
+    DefragStatus { status: DefragStatus, config: DefragConfig },
+    SettingsApplied { settings: LiveSettings },
+    PolicyChanges { changes: Vec<PolicyChangeProgress> },
+    /// Whether a running change was asked to stop
+    PolicyChangeCancelled { cancelled: bool },
+    PolicyRun { run: PolicyRun },
+    /// Snapshot created or deleted
+    Snapshot { snapshot: SnapshotInfo },
+    /// The mount process `pid` is unmounting
+    ShuttingDown { pid: u32 },
+    Error { message: String },
+}
+
+/// Answers control requests for a mounted pool
+///
+/// Stops, and removes the socket, when dropped.
+pub struct ControlServer {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ControlServer {
+    /// Bind `path`, replacing a socket left behind by an earlier mount
+    ///
+    /// `defrag` is the mount's defragmentation engine, if it runs one.
+    pub fn start(path: &Path, storage: StorageEngine, defrag: Option<Arc<DefragmentationEngine>>) -> Result<Self> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow!("Another process is serving control requests on {:?}", path));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind to {:?}", path))?;
+        // Polled so the thread notices `stop`
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || serve_control(listener, storage, defrag, stop))
+        };
+        Ok(ControlServer { path: path.to_path_buf(), stop, thread: Some(thread) })
+    }
+
+    /// Stop accepting connections and remove the socket
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn serve_control(
+    listener: UnixListener,
+    storage: StorageEngine,
+    defrag: Option<Arc<DefragmentationEngine>>,
+    stop: Arc<AtomicBool>,
+) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            // Requests are answered at once, so one client at a time is enough
+            Ok((stream, _)) => {
+                if let Err(e) = handle_control_client(&storage, defrag.as_deref(), stream) {
+                    log::debug!("Control client disconnected: {}", e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(e) => log::error!("Control connection failed: {}", e),
+        }
+    }
+}
+
+fn handle_control_client(storage: &StorageEngine, defrag: Option<&DefragmentationEngine>, stream: UnixStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let reply = match serde_json::from_str::<ControlRequest>(line.trim()) {
+        Ok(ControlRequest::RebuildStatus) => ControlReply::RebuildStatus { status: storage.rebuild_status() },
+        Ok(ControlRequest::SetRebuildLimits { limits }) => match storage.set_rebuild_limits(limits) {
+            Ok(()) => {
+                log::info!("Rebuild limits changed to {:?}", limits);
+                ControlReply::RebuildStatus { status: storage.rebuild_status() }
+            }
+            Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+        },
+        Ok(ControlRequest::SetIoLimits { limits }) => match io_scheduler::scheduler().set_limits(limits) {
+            Ok(()) => {
+                log::info!("I/O limits changed to {:?}", limits);
+                ControlReply::IoStatus { status: io_scheduler::scheduler().status() }
+            }
+            Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+        },
+        Ok(ControlRequest::Activity { window_secs, top }) => {
+            ControlReply::Activity { snapshot: storage.activity(window_secs, top) }
+        }
+        Ok(ControlRequest::DefragAnalyze) => {
+            let analysis = match defrag {
+                Some(engine) => engine.analyze_fragmentation(storage),
+                None => DefragmentationEngine::new(DefragConfig::default()).analyze_fragmentation(storage),
+            };
+            match analysis {
+                Ok(analysis) => ControlReply::DefragAnalysis { analysis },
+                Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+            }
+        }
+        Ok(ControlRequest::DefragStatus) => match defrag {
+            Some(engine) => ControlReply::DefragStatus { status: engine.status(), config: engine.config() },
+            None => ControlReply::Error { message: "This mount does not run defragmentation".to_string() },
+        },
+        Ok(ControlRequest::SetDefragConfig { config }) => match defrag {
+            Some(engine) => match engine.set_config(config) {
+                Ok(()) => {
+                    log::info!("Defragmentation config changed to {:?}", engine.config());
+                    ControlReply::DefragStatus { status: engine.status(), config: engine.config() }
+                }
+                Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+            },
+            None => ControlReply::Error { message: "This mount does not run defragmentation".to_string() },
+        },
+        Ok(ControlRequest::ApplySettings { settings }) => match settings.apply(storage) {
+            Ok(()) => {
+                log::info!("Pool settings changed to {:?}", settings);
+                ControlReply::SettingsApplied { settings }
+            }
+            Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+        },
+        Ok(ControlRequest::PolicyChanges) => match storage.policy_changes() {
+            Ok(changes) => ControlReply::PolicyChanges { changes },
+            Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+        },
+        Ok(ControlRequest::CancelPolicyChange { ino }) => {
+            ControlReply::PolicyChangeCancelled { cancelled: storage.cancel_policy_change(ino) }
+        }
+        Ok(ControlRequest::RunPolicies { policies, dry_run }) => {
+            match crate::policy_engine::run_policies(storage, &policies, dry_run, true) {
+                Ok(run) => ControlReply::PolicyRun { run },
+                Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+            }
+        }
+        Ok(ControlRequest::CreateSnapshot { name }) => match storage.create_snapshot(&name) {
+            Ok(snapshot) => ControlReply::Snapshot { snapshot },
+            Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+        },
+        Ok(ControlRequest::DeleteSnapshot { name }) => match storage.delete_snapshot(&name) {
+            Ok(snapshot) => ControlReply::Snapshot { snapshot },
+            Err(e) => ControlReply::Error { message: format!("{:#}", e) },
+        },
+        Ok(ControlRequest::Shutdown) => {
+            if crate::mount::request_shutdown() {
+                log::info!("Shutdown requested over the control socket");
+                ControlReply::ShuttingDown { pid: std::process::id() }
+            } else {
+                ControlReply::Error { message: "This mount cannot be shut down remotely".to_string() }
+            }
+        }
+        Err(e) => ControlReply::Error { message: format!("Unreadable request: {}", e) },
+    };
+    let mut writer = &stream;
+    writeln!(writer, "{}", serde_json::to_string(&reply)?)?;
+    Ok(())
+}
+
+/// Send one request to a mounted pool's control server
+pub fn request(socket: &Path, request: &ControlRequest) -> Result<ControlReply> {
+    let mut stream = UnixStream::connect(socket).with_context(|| format!("Failed to connect to {:?}", socket))?;
+    stream.set_read_timeout(request.reply_timeout())?;
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(line.trim())?)
+}
+
+/// Ask a mounted pool's control server for an activity snapshot
+pub fn request_activity(socket: &Path, window_secs: u64, top: usize) -> Result<ActivitySnapshot> {
+    match request(socket, &ControlRequest::Activity { window_secs, top })? {
+        ControlReply::Activity { snapshot } => Ok(snapshot),
+        ControlReply::Error { message } => Err(anyhow!("Mounted pool refused the activity request: {}", message)),
+        reply => Err(anyhow!("Unexpected reply to an activity request: {:?}", reply)),
+    }
+}
+
+/// Send one request to the mount of `pool_dir`, or return `None` if it is not mounted
+pub fn request_mounted(pool_dir: &Path, request: &ControlRequest) -> Option<ControlReply> {
+    let socket = pool_dir.join(CONTROL_SOCKET);
+    if !socket.exists() {
+        return None;
+    }
+    match self::request(&socket, request) {
+        Ok(reply) => Some(reply),
+        // A socket left behind by a mount that did not shut down cleanly
+        Err(e) => {
+            log::debug!("{:#}", e);
+            None
+        }
+    }
+}
+
+/// Live rebuild status of a mounted pool, or `None` if it is not mounted
+pub fn mounted_rebuild_status(pool_dir: &Path) -> Option<RebuildStatus> {
+    let socket = pool_dir.join(CONTROL_SOCKET);
+    if !socket.exists() {
+        return None;
+    }
+    match request(&socket, &ControlRequest::RebuildStatus) {
+        Ok(ControlReply::RebuildStatus { status }) => Some(status),
+        Ok(reply) => {
+            log::debug!("Control server did not return a rebuild status: {:?}", reply);
+            None
+        }
+        // A socket left behind by a mount that did not shut down cleanly
+        Err(e) => {
+            log::debug!("{:#}", e);
+            None
+        }
+    }
+}
@@ -1,5 +1,17 @@
+//! Simulated power loss and I/O errors for crash and chaos tests
+//!
+//! A test either arms one `CrashPoint` (`enable_at`, `enable_after_n_ops`) or
+//! installs a `FaultPlan` with `inject_faults`, under which every listed point
+//! fails at random with a seeded generator. Plans only reach work on paths
+//! under their root, so tests running in parallel on other pools are spared,
+//! and they are removed when the returned `FaultScope` drops.
+
+#[cfg(test)]
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(test)]
+use std::sync::MutexGuard;
 use anyhow::{Result, anyhow};
 
 /// Points where power loss can be simulated
@@ -29,6 +41,164 @@ pub enum CrashPoint {
     AfterFragmentDataWrite,
     /// After fdatasync, before allocator persist
     AfterFragmentFsync,
+    /// After the backup superblock is durable, before the primary is rewritten
+    BetweenSuperblockWrites,
+    /// After a transaction's journal record is durable, before it is applied
+    AfterJournalWrite,
+    /// Between two mutations while applying a journaled transaction
+    MidApply,
+    /// Before deleting a fragment of a released extent
+    BeforeFragmentDelete,
+    /// Before reading a fragment file
+    BeforeFragmentRead,
+}
+
+/// What an injected fault does to the operation that reaches its point
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The process dies here; nothing after the point happens
+    PowerLoss,
+    /// The I/O at this point fails and the caller sees the error
+    IoError,
+}
+
+/// Faults injected at random while a `FaultScope` is alive
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    /// Seed of the generator deciding which checks fail; the same seed and
+    /// sequence of checks give the same faults
+    pub seed: u64,
+    /// Chance that a check at one of the listed points fails
+    pub probability: f64,
+    /// Points where a fault simulates power loss
+    pub power_loss_at: Vec<CrashPoint>,
+    /// Points where a fault is an I/O error
+    pub io_errors_at: Vec<CrashPoint>,
+    /// Only work on paths under this directory sees the faults
+    pub root: PathBuf,
+}
+
+/// Small deterministic generator (SplitMix64), so a seed replays the same run
+#[derive(Debug, Clone)]
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        SeededRng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`; `n` must not be zero
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// True with probability `p`
+    // Drawn by fault plans and the binary's benchmark workloads, not the library
+    #[allow(dead_code)]
+    pub fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// Counts of the faults a plan has injected so far
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultCounts {
+    pub power_losses: u64,
+    pub io_errors: u64,
+}
+
+#[cfg(test)]
+struct ActivePlan {
+    plan: FaultPlan,
+    rng: SeededRng,
+    counts: FaultCounts,
+}
+
+#[cfg(test)]
+impl ActivePlan {
+    fn check(&mut self, point: CrashPoint) -> Result<()> {
+        let fault = if self.plan.power_loss_at.contains(&point) {
+            Fault::PowerLoss
+        } else if self.plan.io_errors_at.contains(&point) {
+            Fault::IoError
+        } else {
+            return Ok(());
+        };
+        if !self.rng.chance(self.plan.probability) {
+            return Ok(());
+        }
+        match fault {
+            Fault::PowerLoss => {
+                self.counts.power_losses += 1;
+                Err(anyhow!("SIMULATED POWER LOSS at {:?} (injected fault, seed {})", point, self.plan.seed))
+            }
+            Fault::IoError => {
+                self.counts.io_errors += 1;
+                Err(std::io::Error::other(format!(
+                    "SIMULATED I/O ERROR at {:?} (injected fault, seed {})",
+                    point, self.plan.seed
+                ))
+                .into())
+            }
+        }
+    }
+}
+
+/// Keeps a `FaultPlan` installed; dropping it removes the plan
+///
+/// Only one scope exists at a time: `inject_faults` waits for the previous
+/// one to drop, also when its test panicked.
+#[cfg(test)]
+pub struct FaultScope {
+    simulator: &'static CrashSimulator,
+    _exclusive: MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl FaultScope {
+    /// Faults injected since the scope was created
+    pub fn counts(&self) -> FaultCounts {
+        self.simulator.plan.lock().unwrap().as_ref().map_or_else(FaultCounts::default, |active| active.counts)
+    }
+
+    /// Remove the plan now, e.g. before checking what the faults left behind
+    pub fn disable(&self) {
+        *self.simulator.plan.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+impl Drop for FaultScope {
+    fn drop(&mut self) {
+        self.disable();
+    }
+}
+
+#[cfg(test)]
+static FAULT_SCOPE: Mutex<()> = Mutex::new(());
+
+/// Install `plan` on the shared simulator until the returned scope drops
+#[cfg(test)]
+pub fn inject_faults(plan: FaultPlan) -> FaultScope {
+    let exclusive = FAULT_SCOPE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let simulator = get_crash_simulator();
+    *simulator.plan.lock().unwrap() = Some(ActivePlan {
+        rng: SeededRng::new(plan.seed),
+        plan,
+        counts: FaultCounts::default(),
+    });
+    FaultScope { simulator, _exclusive: exclusive }
 }
 
 /// Configuration for crash simulation
@@ -39,6 +209,11 @@ pub struct CrashSimulator {
     crash_count: Arc<AtomicU64>,
     operations_count: Arc<AtomicU64>,
     crash_after_n_ops: Arc<AtomicU64>,
+    /// Only this thread crashes, when set
+    owner: Arc<Mutex<Option<std::thread::ThreadId>>>,
+    /// Random faults of the live `FaultScope`
+    #[cfg(test)]
+    plan: Arc<Mutex<Option<ActivePlan>>>,
 }
 
 impl CrashSimulator {
@@ -50,6 +225,9 @@ impl CrashSimulator {
             crash_count: Arc::new(AtomicU64::new(0)),
             operations_count: Arc::new(AtomicU64::new(0)),
             crash_after_n_ops: Arc::new(AtomicU64::new(u64::MAX)),
+            owner: Arc::new(Mutex::new(None)),
+            #[cfg(test)]
+            plan: Arc::new(Mutex::new(None)),
         }
     }
     
@@ -76,9 +254,18 @@ impl CrashSimulator {
         self.operations_count.store(0, Ordering::SeqCst);
     }
     
+    /// Like `enable_after_n_ops`, but other threads pass the point untouched
+    ///
+    /// For points that other tests running in parallel also reach.
+    pub fn enable_after_n_ops_on_current_thread(&self, point: CrashPoint, n: u64) {
+        *self.owner.lock().unwrap() = Some(std::thread::current().id());
+        self.enable_after_n_ops(point, n);
+    }
+    
     /// Disable crash simulation
     pub fn disable(&self) {
         self.enabled.store(false, Ordering::SeqCst);
+        *self.owner.lock().unwrap() = None;
         *self.crash_point.lock().unwrap() = None;
         self.crash_after_n_ops.store(u64::MAX, Ordering::SeqCst);
     }
@@ -95,6 +282,9 @@ impl CrashSimulator {
         if !self.enabled.load(Ordering::SeqCst) {
             return Ok(());
         }
+        if matches!(*self.owner.lock().unwrap(), Some(owner) if owner != std::thread::current().id()) {
+            return Ok(());
+        }
         
         let target_point = self.crash_point.lock().unwrap();
         #[cfg(test)]
@@ -119,6 +309,22 @@ impl CrashSimulator {
         Ok(())
     }
     
+    /// Check for a fault at `point` while working on `path`
+    ///
+    /// Paths under the root of an installed `FaultPlan` only see the plan's
+    /// faults; everything else sees the armed crash point, if any.
+    #[cfg(test)]
+    pub fn check_fault(&self, point: CrashPoint, path: &Path) -> Result<()> {
+        let mut plan = self.plan.lock().unwrap();
+        match plan.as_mut() {
+            Some(active) if path.starts_with(&active.plan.root) => active.check(point),
+            _ => {
+                drop(plan);
+                self.check_crash(point)
+            }
+        }
+    }
+    
     /// Get the number of times we've crashed
     pub fn crash_count(&self) -> u64 {
         self.crash_count.load(Ordering::SeqCst)
@@ -162,3 +368,72 @@ pub fn check_crash_point(point: CrashPoint) -> Result<()> {
     res
 }
 
+/// Check for a simulated fault at `point` while working on `path`
+#[cfg(test)]
+#[inline]
+pub fn check_fault_at(point: CrashPoint, path: &Path) -> Result<()> {
+    get_crash_simulator().check_fault(point, path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(seed: u64, root: &Path) -> FaultPlan {
+        FaultPlan {
+            seed,
+            probability: 0.3,
+            power_loss_at: vec![CrashPoint::AfterTempWrite],
+            io_errors_at: vec![CrashPoint::BeforeFragmentRead],
+            root: root.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn test_fault_plan_replays_by_seed_and_only_reaches_its_root() {
+        let root = Path::new("/chaos-test-root");
+        let inside = root.join("disk0/fragments/x.frag");
+        let run = |seed| {
+            let scope = inject_faults(plan(seed, root));
+            let outcomes: Vec<Option<String>> = (0..200)
+                .map(|i| {
+                    let point = if i % 2 == 0 { CrashPoint::AfterTempWrite } else { CrashPoint::BeforeFragmentRead };
+                    check_fault_at(point, &inside).err().map(|e| e.to_string())
+                })
+                .collect();
+            // Points outside the plan and paths outside the root never fail
+            assert!((0..200).all(|_| check_fault_at(CrashPoint::BeforeRename, &inside).is_ok()));
+            assert!((0..200).all(|_| check_fault_at(CrashPoint::AfterTempWrite, Path::new("/elsewhere")).is_ok()));
+            (outcomes, scope.counts())
+        };
+
+        let (first, counts) = run(7);
+        assert_eq!(run(7), (first.clone(), counts));
+        assert_ne!(run(8).0, first);
+        assert!(counts.power_losses > 0 && counts.io_errors > 0);
+        let failures: Vec<&String> = first.iter().flatten().collect();
+        assert_eq!(failures.len() as u64, counts.power_losses + counts.io_errors);
+        assert!(failures.iter().any(|e| e.starts_with("SIMULATED POWER LOSS at AfterTempWrite")));
+        assert!(failures.iter().any(|e| e.starts_with("SIMULATED I/O ERROR at BeforeFragmentRead")));
+    }
+
+    #[test]
+    fn test_fault_scope_removes_its_plan_even_after_a_panic() {
+        let root = Path::new("/chaos-scope-root");
+        let inside = root.join("pool/inodes/2");
+        let result = std::panic::catch_unwind(|| {
+            let _scope = inject_faults(FaultPlan { probability: 1.0, ..plan(1, root) });
+            let err = check_fault_at(CrashPoint::BeforeFragmentRead, &inside).unwrap_err();
+            assert!(err.downcast_ref::<std::io::Error>().is_some());
+            panic!("test body failed");
+        });
+        assert!(result.is_err());
+
+        // The next scope is not blocked by the poisoned lock, and nothing leaked
+        assert!(check_fault_at(CrashPoint::BeforeFragmentRead, &inside).is_ok());
+        let scope = inject_faults(FaultPlan { probability: 0.0, ..plan(1, root) });
+        assert!(check_fault_at(CrashPoint::AfterTempWrite, &inside).is_ok());
+        assert_eq!(scope.counts(), FaultCounts::default());
+    }
+}
@@ -0,0 +1,206 @@
+//! Running a mount in the background and finding it again
+//!
+//! `mount --daemonize` forks before the mount starts any thread. The parent
+//! waits on a pipe until the child has mounted, or failed to, and exits with
+//! that outcome; the child detaches with `setsid` and writes its output to
+//! `mount.log` in the pool. Every mount keeps its PID in `mount.pid` in the
+//! pool for service managers, and mounts name the pool directory as their
+//! source, so `unmount --mountpoint` can find the pool in the mount table.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// PID of the mount, relative to the pool directory
+pub const PID_FILE: &str = "mount.pid";
+/// Output of a daemonized mount, relative to the pool directory
+pub const LOG_FILE: &str = "mount.log";
+
+/// Tells the parent of a daemonized mount how mounting went
+pub struct Readiness {
+    pipe: Mutex<Option<File>>,
+}
+
+impl Readiness {
+    /// The filesystem is mounted; the parent exits with status 0
+    pub fn mounted(&self) {
+        self.report("ok");
+    }
+
+    /// Mounting failed; the parent prints `err` and exits with status 1
+    ///
+    /// Does nothing once `mounted` was reported.
+    pub fn failed(&self, err: &anyhow::Error) {
+        self.report(&format!("error: {:#}", err));
+    }
+
+    fn report(&self, outcome: &str) {
+        // Only the first outcome counts; closing the pipe lets the parent go
+        if let Some(mut pipe) = self.pipe.lock().unwrap().take() {
+            let _ = writeln!(pipe, "{}", outcome);
+        }
+    }
+}
+
+/// Fork into the background, returning in the child only
+///
+/// Must be called before any thread is started. The parent waits for the
+/// child's [`Readiness`] report and exits; if the child dies without one,
+/// the parent points at the log.
+pub fn daemonize(pool_dir: &Path) -> Result<Readiness> {
+    let log_path = pool_dir.join(LOG_FILE);
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open {:?}", log_path))?;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create a pipe");
+    }
+    // Kept from the commands that notification sinks run
+    for fd in fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()).context("Failed to fork"),
+        0 => {
+            drop(reader);
+            if unsafe { libc::setsid() } < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to start a session");
+            }
+            let null = File::open("/dev/null")?;
+            unsafe {
+                libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+                libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+                libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+            }
+            Ok(Readiness { pipe: Mutex::new(Some(writer)) })
+        }
+        child => {
+            drop(writer);
+            let mut outcome = String::new();
+            let mut reader = reader;
+            let _ = reader.read_to_string(&mut outcome);
+            match outcome.trim() {
+                "ok" => {
+                    println!("Mounted in the background as PID {}; output goes to {}", child, log_path.display());
+                    std::process::exit(0);
+                }
+                outcome => {
+                    match outcome.strip_prefix("error: ") {
+                        Some(message) => eprintln!("Error: {}", message),
+                        None => eprintln!("Error: mount process {} exited before mounting; see {}", child, log_path.display()),
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+/// `mount.pid` of the running mount, removed when dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(pool_dir: &Path) -> Result<Self> {
+        let path = pool_dir.join(PID_FILE);
+        fs::write(&path, format!("{}\n", std::process::id())).with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(PidFile { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Source of the FUSE mount at `mountpoint` in `/proc/self/mountinfo` text
+///
+/// Mounts name their pool directory as the source unless `-o fsname` was given.
+pub fn mount_source(mountinfo: &str, mountpoint: &Path) -> Option<PathBuf> {
+    mountinfo.lines().find_map(|line| {
+        let (mount, fs) = line.split_once(" - ")?;
+        let target = unescape_mountinfo(mount.split(' ').nth(4)?);
+        let mut fs = fs.split(' ');
+        let fstype = fs.next()?;
+        let source = unescape_mountinfo(fs.next()?);
+        (Path::new(&target) == mountpoint && fstype.starts_with("fuse")).then(|| PathBuf::from(source))
+    })
+}
+
+/// The pool mounted at `mountpoint`, per the mount table
+pub fn pool_mounted_at(mountpoint: &Path) -> Result<PathBuf> {
+    let mountpoint = fs::canonicalize(mountpoint).with_context(|| format!("No such mountpoint {:?}", mountpoint))?;
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").context("Failed to read the mount table")?;
+    let source = mount_source(&mountinfo, &mountpoint)
+        .ok_or_else(|| anyhow!("Nothing is mounted at {:?} with FUSE", mountpoint))?;
+    if !source.join(crate::pool_lock::POOL_LOCK_FILE).exists() {
+        return Err(anyhow!(
+            "{:?} is mounted from {:?}, which is not a pool directory; pass --pool",
+            mountpoint,
+            source
+        ));
+    }
+    Ok(source)
+}
+
+/// Undo the octal escapes of spaces, tabs, newlines and backslashes
+fn unescape_mountinfo(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        let code = rest.get(pos + 1..pos + 4).and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_source_finds_the_pool_of_a_fuse_mount() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+61 22 0:52 / /mnt/fs rw,nosuid,nodev,relatime shared:33 - fuse.dynamicfs /data/scfs rw,user_id=0,group_id=0
+62 22 0:53 / /mnt/my\\040fs rw,nosuid,nodev shared:34 - fuse.dynamicfs /data/my\\040pool rw,user_id=0,group_id=0
+63 22 0:54 / /mnt/other rw shared:35 - tmpfs tmpfs rw
+";
+        assert_eq!(mount_source(mountinfo, Path::new("/mnt/fs")), Some(PathBuf::from("/data/scfs")));
+        assert_eq!(mount_source(mountinfo, Path::new("/mnt/my fs")), Some(PathBuf::from("/data/my pool")));
+        assert_eq!(mount_source(mountinfo, Path::new("/mnt/other")), None);
+        assert_eq!(mount_source(mountinfo, Path::new("/mnt/none")), None);
+    }
+
+    #[test]
+    fn test_pid_file_holds_the_pid_until_dropped() {
+        let pool = tempfile::tempdir().unwrap();
+        let pid_file = PidFile::create(pool.path()).unwrap();
+        let contents = fs::read_to_string(pool.path().join(PID_FILE)).unwrap();
+        assert_eq!(contents.trim().parse::<u32>().unwrap(), std::process::id());
+        drop(pid_file);
+        assert!(!pool.path().join(PID_FILE).exists());
+    }
+}
@@ -1,13 +1,25 @@
-use anyhow::Result;
+//! Defragmentation of extents whose fragments share a disk
+//!
+//! The mount process runs one `DefragmentationEngine` against the live storage
+//! engine, with the `DefragConfig` saved in the pool. Passes start once the
+//! fragmented share of extents reaches the configured threshold, and only
+//! relocate fragments inside the schedule window or while the pool is idle.
+//! The same passes coalesce files cut into many small extents, whatever the
+//! fragmented share.
+
+use anyhow::{anyhow, Result};
+use chrono::Timelike;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 use crate::extent::Extent;
-use crate::io_scheduler::IoPriority;
+use crate::io_scheduler::IoClass;
 use crate::metrics::Metrics;
 use crate::storage::StorageEngine;
 
@@ -31,6 +43,40 @@ pub struct FragmentationAnalysis {
     pub overall_fragmentation_ratio: f64,
     pub per_disk_stats: Vec<DiskFragmentationStats>,
     pub recommendation: DefragRecommendation,
+    /// Files a pass would coalesce
+    #[serde(default)]
+    pub coalesce_candidates: u64,
+    /// Files with the most extents, most first
+    #[serde(default)]
+    pub files: Vec<FileExtents>,
+}
+
+/// Files listed by an analysis
+const ANALYSIS_FILES: usize = 20;
+
+/// Extent counts of one file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileExtents {
+    pub ino: u64,
+    /// Bytes of the file each extent covers
+    pub slot_size: usize,
+    pub extents: u64,
+    /// Extents holding less than a quarter of the pool's extent size
+    pub small_extents: u64,
+    /// Extents other files reference too, e.g. through snapshots
+    pub shared_extents: u64,
+}
+
+impl FileExtents {
+    /// Whether a pass would coalesce the file: more than `min_extents` small
+    /// extents, slots smaller than the pool's `extent_size`, and no extent
+    /// shared with another file
+    pub fn is_coalesce_candidate(&self, min_extents: usize, extent_size: usize) -> bool {
+        min_extents > 0
+            && self.small_extents > min_extents as u64
+            && self.slot_size < extent_size
+            && self.shared_extents == 0
+    }
 }
 
 /// Recommendations for defragmentation actions
@@ -66,9 +112,9 @@ impl DefragIntensity {
         }
     }
 
-    pub fn priority(&self) -> IoPriority {
-        // All defrag operations use Background priority
-        IoPriority::Background
+    pub fn priority(&self) -> IoClass {
+        // All defrag operations are scheduled as defrag I/O
+        IoClass::Defrag
     }
 
     pub fn batch_size(&self) -> usize {
@@ -89,22 +135,99 @@ pub struct DefragStatus {
     pub extents_processed: u64,
     pub extents_defragmented: u64,
     pub bytes_moved: u64,
+    #[serde(default)]
+    pub files_coalesced: u64,
     pub errors: u64,
     pub started_at: Option<i64>,
     pub last_run_at: Option<i64>,
     pub estimated_time_remaining_secs: Option<u64>,
 }
 
-/// Configuration for defragmentation operations
+/// Daily window of local time, such as `02:00-05:00`
+///
+/// A window whose end is before its start runs past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DefragWindow {
+    /// Minutes after midnight
+    pub start: u16,
+    pub end: u16,
+}
+
+impl DefragWindow {
+    /// Whether `minute` (after midnight) falls inside the window
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    pub fn contains_now(&self) -> bool {
+        let now = chrono::Local::now();
+        self.contains((now.hour() * 60 + now.minute()) as u16)
+    }
+}
+
+impl FromStr for DefragWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let minute = |time: &str| -> Option<u16> {
+            let (hours, minutes) = time.trim().split_once(':')?;
+            let (hours, minutes): (u16, u16) = (hours.parse().ok()?, minutes.parse().ok()?);
+            (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+        };
+        let window = s
+            .split_once('-')
+            .and_then(|(start, end)| Some(DefragWindow { start: minute(start)?, end: minute(end)? }))
+            .ok_or_else(|| anyhow!("Invalid window {:?}: expected HH:MM-HH:MM", s))?;
+        if window.start == window.end {
+            return Err(anyhow!("Window {:?} is empty", s));
+        }
+        Ok(window)
+    }
+}
+
+impl fmt::Display for DefragWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}-{:02}:{:02}", self.start / 60, self.start % 60, self.end / 60, self.end % 60)
+    }
+}
+
+impl TryFrom<String> for DefragWindow {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<DefragWindow> for String {
+    fn from(window: DefragWindow) -> String {
+        window.to_string()
+    }
+}
+
+/// Configuration for defragmentation operations, saved in the pool
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DefragConfig {
     pub enabled: bool,
     pub intensity: DefragIntensity,
+    /// Fragmented share of extents at which a pass starts
     pub fragmentation_threshold: f64,
     pub min_extent_fragments: usize,
     pub prioritize_hot_extents: bool,
+    /// Without a schedule, only relocate while the pool is idle
     pub pause_on_high_load: bool,
     pub max_concurrent_operations: usize,
+    /// Relocation runs inside this window, and outside it only while the pool is idle
+    pub schedule: Option<DefragWindow>,
+    /// Coalesce files with more than this many extents under a quarter of the
+    /// pool's extent size; 0 disables coalescing
+    pub coalesce_min_extents: usize,
 }
 
 impl Default for DefragConfig {
@@ -117,6 +240,28 @@ impl Default for DefragConfig {
             prioritize_hot_extents: true,
             pause_on_high_load: true,
             max_concurrent_operations: 1,
+            schedule: None,
+            coalesce_min_extents: 16,
+        }
+    }
+}
+
+impl DefragConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.fragmentation_threshold) {
+            return Err(anyhow!("Fragmentation threshold must be between 0 and 1"));
+        }
+        if self.min_extent_fragments < 2 {
+            return Err(anyhow!("An extent needs at least 2 fragments on a disk to be fragmented"));
+        }
+        Ok(())
+    }
+
+    /// Whether fragments may be relocated now
+    pub fn may_relocate(&self, in_window: bool, idle: bool) -> bool {
+        match self.schedule {
+            Some(_) => in_window || idle,
+            None => idle || !self.pause_on_high_load,
         }
     }
 }
@@ -131,6 +276,7 @@ pub struct DefragmentationEngine {
     extents_processed: Arc<AtomicU64>,
     extents_defragmented: Arc<AtomicU64>,
     bytes_moved: Arc<AtomicU64>,
+    files_coalesced: Arc<AtomicU64>,
     errors: Arc<AtomicU64>,
     started_at: Arc<Mutex<Option<i64>>>,
     last_run_at: Arc<Mutex<Option<i64>>>,
@@ -145,6 +291,7 @@ impl DefragmentationEngine {
             extents_processed: Arc::new(AtomicU64::new(0)),
             extents_defragmented: Arc::new(AtomicU64::new(0)),
             bytes_moved: Arc::new(AtomicU64::new(0)),
+            files_coalesced: Arc::new(AtomicU64::new(0)),
             errors: Arc::new(AtomicU64::new(0)),
             started_at: Arc::new(Mutex::new(None)),
             last_run_at: Arc::new(Mutex::new(None)),
@@ -157,15 +304,14 @@ impl DefragmentationEngine {
         storage: &StorageEngine,
     ) -> Result<FragmentationAnalysis> {
         let metadata_arc = storage.metadata();
-        let metadata = metadata_arc.read().unwrap();
-        let extents = metadata.list_all_extents()?;
-        drop(metadata);
+        let extents = metadata_arc.read().unwrap().iter_extents()?;
         
         let disks = storage.get_disks();
 
         let mut per_disk_stats: HashMap<Uuid, DiskFragmentationStats> = HashMap::new();
         let mut total_extents = 0u64;
         let mut fragmented_extents = 0u64;
+        let mut sizes: HashMap<Uuid, usize> = HashMap::new();
 
         // Initialize disk stats
         for disk in &disks {
@@ -183,8 +329,9 @@ impl DefragmentationEngine {
         }
 
         // Analyze each extent
-        for extent in &extents {
+        for extent in extents.filter_map(Result::ok) {
             total_extents += 1;
+            sizes.insert(extent.uuid, extent.size);
 
             // Count fragments per disk
             let mut disk_fragment_counts: HashMap<Uuid, usize> = HashMap::new();
@@ -234,6 +381,14 @@ impl DefragmentationEngine {
             DefragRecommendation::None
         };
 
+        let min_extents = self.config.lock().unwrap().coalesce_min_extents;
+        let mut files = Self::file_extents(storage, &sizes)?;
+        let coalesce_candidates = files
+            .iter()
+            .filter(|file| file.is_coalesce_candidate(min_extents, storage.extent_size()))
+            .count() as u64;
+        files.truncate(ANALYSIS_FILES);
+
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -246,10 +401,26 @@ impl DefragmentationEngine {
             overall_fragmentation_ratio,
             per_disk_stats: per_disk_stats.into_values().collect(),
             recommendation,
+            coalesce_candidates,
+            files,
         })
     }
 
+    /// Replace the configuration; a running engine picks it up before its next pass
+    pub fn set_config(&self, config: DefragConfig) -> Result<()> {
+        config.validate()?;
+        *self.config.lock().unwrap() = config;
+        Ok(())
+    }
+
+    pub fn config(&self) -> DefragConfig {
+        self.config.lock().unwrap().clone()
+    }
+
     /// Start defragmentation process
+    ///
+    /// Passes run against `storage`, which should share its state with the
+    /// engine serving I/O so relocations see buffered writes and rebuilds.
     pub fn start(&self, storage: Arc<StorageEngine>, metrics: Arc<Metrics>) -> Result<()> {
         if self.running.load(Ordering::SeqCst) {
             return Ok(()); // Already running
@@ -271,6 +442,7 @@ impl DefragmentationEngine {
         let extents_processed = Arc::clone(&self.extents_processed);
         let extents_defragmented = Arc::clone(&self.extents_defragmented);
         let bytes_moved = Arc::clone(&self.bytes_moved);
+        let files_coalesced = Arc::clone(&self.files_coalesced);
         let errors = Arc::clone(&self.errors);
         let last_run_at = Arc::clone(&self.last_run_at);
 
@@ -283,17 +455,19 @@ impl DefragmentationEngine {
                 }
 
                 let cfg = config.lock().unwrap().clone();
-                if !cfg.enabled {
-                    std::thread::sleep(std::time::Duration::from_secs(5));
+                let in_window = cfg.schedule.is_some_and(|window| window.contains_now());
+                if !cfg.enabled || !cfg.may_relocate(in_window, storage.rebuild_status().idle) {
+                    Self::sleep_while_running(&running, Duration::from_secs(5));
                     continue;
                 }
 
                 // Perform defragmentation pass
-                match Self::defrag_pass(&storage, &cfg, &metrics) {
+                match Self::defrag_pass(&storage, &cfg) {
                     Ok(stats) => {
                         extents_processed.fetch_add(stats.processed, Ordering::SeqCst);
                         extents_defragmented.fetch_add(stats.defragmented, Ordering::SeqCst);
                         bytes_moved.fetch_add(stats.bytes_moved, Ordering::SeqCst);
+                        files_coalesced.fetch_add(stats.coalesced, Ordering::SeqCst);
 
                         let timestamp = SystemTime::now()
                             .duration_since(UNIX_EPOCH)
@@ -312,18 +486,28 @@ impl DefragmentationEngine {
                     }
                     Err(e) => {
                         errors.fetch_add(1, Ordering::SeqCst);
-                        eprintln!("Defragmentation pass error: {}", e);
+                        log::error!("Defragmentation pass error: {}", e);
                     }
                 }
 
                 // Throttle between passes
-                std::thread::sleep(std::time::Duration::from_secs(60));
+                Self::sleep_while_running(&running, Duration::from_secs(60));
             }
         });
 
         Ok(())
     }
 
+    /// Sleep up to `duration`, waking early once the engine is stopped
+    fn sleep_while_running(running: &AtomicBool, duration: Duration) {
+        let step = Duration::from_millis(200);
+        let mut slept = Duration::ZERO;
+        while slept < duration && running.load(Ordering::SeqCst) {
+            std::thread::sleep(step);
+            slept += step;
+        }
+    }
+
     /// Stop defragmentation
     pub fn stop(&self) {
         self.running.store(false, Ordering::SeqCst);
@@ -350,6 +534,7 @@ impl DefragmentationEngine {
             extents_processed: self.extents_processed.load(Ordering::SeqCst),
             extents_defragmented: self.extents_defragmented.load(Ordering::SeqCst),
             bytes_moved: self.bytes_moved.load(Ordering::SeqCst),
+            files_coalesced: self.files_coalesced.load(Ordering::SeqCst),
             errors: self.errors.load(Ordering::SeqCst),
             started_at: *self.started_at.lock().unwrap(),
             last_run_at: *self.last_run_at.lock().unwrap(),
@@ -358,24 +543,41 @@ impl DefragmentationEngine {
     }
 
     /// Perform a single defragmentation pass
+    ///
+    /// Coalescing candidates are coalesced first. Then nothing is moved while
+    /// the fragmented share of extents is below `config.fragmentation_threshold`.
     fn defrag_pass(
         storage: &StorageEngine,
         config: &DefragConfig,
-        metrics: &Metrics,
     ) -> Result<DefragPassStats> {
+        let _class = IoClass::Defrag.enter();
         let metadata_arc = storage.metadata();
-        let metadata = metadata_arc.read().unwrap();
-        let extents = metadata.list_all_extents()?;
-        drop(metadata);
+        let extents = metadata_arc.read().unwrap().iter_extents()?;
         
         let mut stats = DefragPassStats {
             processed: 0,
             defragmented: 0,
             bytes_moved: 0,
+            coalesced: 0,
         };
+        // Only the fragmented extents are kept
+        let mut total = 0usize;
+        let mut fragmented = Vec::new();
+        let mut sizes: HashMap<Uuid, usize> = HashMap::new();
+        for extent in extents.filter_map(Result::ok) {
+            total += 1;
+            sizes.insert(extent.uuid, extent.size);
+            if Self::needs_defragmentation(&extent, config) {
+                fragmented.push(extent);
+            }
+        }
+        Self::coalesce_files(storage, config, &sizes, &mut stats)?;
+        if total == 0 || (fragmented.len() as f64 / total as f64) < config.fragmentation_threshold {
+            return Ok(stats);
+        }
 
-        // Filter and prioritize extents for defragmentation
-        let mut candidates = Self::select_defrag_candidates(&extents, config)?;
+        // Prioritize extents for defragmentation
+        let mut candidates = Self::select_defrag_candidates(fragmented, config)?;
         
         // Limit batch size
         let batch_size = config.intensity.batch_size();
@@ -384,13 +586,14 @@ impl DefragmentationEngine {
         for extent in candidates {
             // Check if extent needs defragmentation
             if Self::needs_defragmentation(&extent, config) {
-                match Self::defragment_extent(storage, &extent, config, metrics) {
-                    Ok(bytes) => {
+                match storage.defragment_extent(&extent.uuid) {
+                    Ok((fragments, bytes)) if fragments > 0 => {
                         stats.defragmented += 1;
                         stats.bytes_moved += bytes;
                     }
+                    Ok(_) => {}
                     Err(e) => {
-                        eprintln!("Failed to defragment extent {}: {}", extent.uuid, e);
+                        log::warn!("Failed to defragment extent {}: {}", extent.uuid, e);
                     }
                 }
 
@@ -405,16 +608,71 @@ impl DefragmentationEngine {
         Ok(stats)
     }
 
-    /// Select candidate extents for defragmentation
+    /// Coalesce the candidates with the most extents, up to a batch of them
+    fn coalesce_files(
+        storage: &StorageEngine,
+        config: &DefragConfig,
+        sizes: &HashMap<Uuid, usize>,
+        stats: &mut DefragPassStats,
+    ) -> Result<()> {
+        if config.coalesce_min_extents == 0 {
+            return Ok(());
+        }
+        let extent_size = storage.extent_size();
+        let candidates = Self::file_extents(storage, sizes)?
+            .into_iter()
+            .filter(|file| file.is_coalesce_candidate(config.coalesce_min_extents, extent_size))
+            .take(config.intensity.batch_size());
+        for file in candidates {
+            match storage.coalesce_extents(file.ino) {
+                Ok((removed, bytes)) if removed > 0 => {
+                    stats.coalesced += 1;
+                    stats.bytes_moved += bytes;
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Failed to coalesce the extents of inode {}: {}", file.ino, e),
+            }
+            std::thread::sleep(Duration::from_millis(config.intensity.io_throttle_ms()));
+        }
+        Ok(())
+    }
+
+    /// Extent counts of every file, most extents first
+    ///
+    /// `sizes` holds the size of every extent. Maps are read one at a time.
+    fn file_extents(storage: &StorageEngine, sizes: &HashMap<Uuid, usize>) -> Result<Vec<FileExtents>> {
+        let small = storage.extent_size() / 4;
+        let metadata_arc = storage.metadata();
+        let inos = metadata_arc.read().unwrap().extent_map_inos()?;
+        let references = metadata_arc.read().unwrap().extent_reference_counts()?;
+        let mut files = Vec::new();
+        for ino in inos {
+            let Ok(map) = metadata_arc.read().unwrap().load_extent_map(ino) else {
+                continue;
+            };
+            let mut file = FileExtents { ino, slot_size: map.extent_size, extents: 0, small_extents: 0, shared_extents: 0 };
+            for uuid in map.data_extents() {
+                file.extents += 1;
+                if sizes.get(uuid).is_some_and(|&size| size < small) {
+                    file.small_extents += 1;
+                }
+                if references.get(uuid).is_some_and(|&count| count > 1) {
+                    file.shared_extents += 1;
+                }
+            }
+            if file.extents > 0 {
+                files.push(file);
+            }
+        }
+        files.sort_by(|a, b| b.extents.cmp(&a.extents).then(a.ino.cmp(&b.ino)));
+        Ok(files)
+    }
+
+    /// Order fragmented extents for defragmentation
     fn select_defrag_candidates(
-        extents: &[Extent],
+        mut candidates: Vec<Extent>,
         config: &DefragConfig,
     ) -> Result<Vec<Extent>> {
-        let mut candidates: Vec<Extent> = extents
-            .iter()
-            .filter(|e| Self::needs_defragmentation(e, config))
-            .cloned()
-            .collect();
 
         // Prioritize hot extents if configured
         if config.prioritize_hot_extents {
@@ -441,30 +699,6 @@ impl DefragmentationEngine {
             .values()
             .any(|&count| count >= config.min_extent_fragments)
     }
-
-    /// Defragment a single extent by rewriting it
-    fn defragment_extent(
-        storage: &StorageEngine,
-        extent: &Extent,
-        _config: &DefragConfig,
-        _metrics: &Metrics,
-    ) -> Result<u64> {
-        // Read the extent data
-        let data = storage.read_extent(extent.uuid)?;
-
-        // Delete old fragments
-        storage.delete_extent(extent.uuid)?;
-
-        // Write extent back with new placement (will be more contiguous)
-        let new_extent = storage.write_extent(&data, extent.redundancy)?;
-
-        // Verify checksum matches
-        if new_extent.checksum != extent.checksum {
-            anyhow::bail!("Checksum mismatch after defragmentation");
-        }
-
-        Ok(data.len() as u64)
-    }
 }
 
 /// Statistics from a defragmentation pass
@@ -473,5 +707,70 @@ struct DefragPassStats {
     processed: u64,
     defragmented: u64,
     bytes_moved: u64,
+    coalesced: u64,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defrag_window_parsing_and_wraparound() {
+        let night: DefragWindow = "02:00-05:00".parse().unwrap();
+        assert_eq!((night.start, night.end), (120, 300));
+        assert!(night.contains(120) && night.contains(299));
+        assert!(!night.contains(300) && !night.contains(60));
+
+        // Runs past midnight
+        let late: DefragWindow = "22:30-01:15".parse().unwrap();
+        assert!(late.contains(23 * 60) && late.contains(0) && late.contains(74));
+        assert!(!late.contains(75) && !late.contains(12 * 60));
+        assert_eq!(late.to_string(), "22:30-01:15");
+
+        for invalid in ["", "02:00", "25:00-03:00", "02:60-03:00", "03:00-03:00", "2-5"] {
+            assert!(invalid.parse::<DefragWindow>().is_err(), "{:?} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn test_defrag_relocates_in_its_window_or_when_idle() {
+        let scheduled = DefragConfig { schedule: Some("02:00-05:00".parse().unwrap()), ..DefragConfig::default() };
+        assert!(scheduled.may_relocate(true, false));
+        assert!(scheduled.may_relocate(false, true));
+        assert!(!scheduled.may_relocate(false, false));
+
+        let unscheduled = DefragConfig::default();
+        assert!(unscheduled.pause_on_high_load);
+        assert!(unscheduled.may_relocate(false, true));
+        assert!(!unscheduled.may_relocate(false, false));
+        let eager = DefragConfig { pause_on_high_load: false, ..DefragConfig::default() };
+        assert!(eager.may_relocate(false, false));
+
+        assert!(DefragConfig { fragmentation_threshold: 1.5, ..DefragConfig::default() }.validate().is_err());
+        assert!(DefragConfig { min_extent_fragments: 1, ..DefragConfig::default() }.validate().is_err());
+        assert!(scheduled.validate().is_ok());
+    }
+
+    #[test]
+    fn test_defrag_config_is_saved_with_the_pool() {
+        let mut pool = crate::disk::DiskPool::new();
+        pool.defrag = DefragConfig {
+            enabled: true,
+            intensity: DefragIntensity::High,
+            schedule: Some("02:00-05:00".parse().unwrap()),
+            ..DefragConfig::default()
+        };
+        let mut json = serde_json::to_value(&pool).unwrap();
+        assert_eq!(json["defrag"]["schedule"], "02:00-05:00");
+        let loaded: crate::disk::DiskPool = serde_json::from_value(json.clone()).unwrap();
+        assert!(loaded.defrag.enabled);
+        assert_eq!(loaded.defrag.intensity, DefragIntensity::High);
+        assert_eq!(loaded.defrag.schedule, pool.defrag.schedule);
+
+        // Pools saved before the setting existed load with defragmentation off
+        json.as_object_mut().unwrap().remove("defrag");
+        let legacy: crate::disk::DiskPool = serde_json::from_value(json).unwrap();
+        assert!(!legacy.defrag.enabled);
+        assert_eq!(legacy.defrag.schedule, None);
+    }
+}
@@ -1,23 +1,56 @@
 use anyhow::{anyhow, Context, Result};
+use crate::error::{StorageError, StorageResult};
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Write};
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 use uuid::Uuid;
 
 #[cfg(test)]
-use crate::crash_sim::{check_crash_point, CrashPoint};
+use crate::crash_sim::{check_crash_point, check_fault_at, CrashPoint};
 
+use crate::encryption::{FragmentCipher, PoolKeySource};
+use crate::format_version::{self, FormatVersion, Upgrade};
+use crate::io_scheduler;
+use crate::logging::{EventKind, EventRing};
 use crate::tiering::StorageTier;
 
+/// Format of the `disk.json` this build writes
+pub const DISK_FORMAT_VERSION: FormatVersion = FormatVersion::new(1, 0);
+
+/// Format of the `pool.json` this build writes
+pub const POOL_FORMAT_VERSION: FormatVersion = FormatVersion::new(1, 0);
+
+/// Steps from each older major format of `disk.json` to the next
+const DISK_UPGRADES: &[Upgrade] = &[
+    // 0 to 1: unversioned disks only lack the version
+    |_| Ok(()),
+];
+
+/// Steps from each older major format of `pool.json` to the next
+const POOL_UPGRADES: &[Upgrade] = &[
+    // 0 to 1: unversioned pools only lack the version
+    |_| Ok(()),
+];
+
 /// Represents a storage disk (backed by a directory)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Disk {
+    /// Format of `disk.json`; see `format_version`
+    #[serde(default)]
+    pub format_version: FormatVersion,
     pub uuid: Uuid,
     pub path: PathBuf,
     pub capacity_bytes: u64,
+    /// Bytes of the fragment store in use; kept by `account_fragments`, corrected by `recount_usage`
     pub used_bytes: u64,
+    /// Fragments stored on the disk, kept alongside `used_bytes`
+    #[serde(default)]
+    pub fragment_count: u64,
     pub health: DiskHealth,
     /// Kind of backing (directory or block device)
     #[serde(default)]
@@ -25,6 +58,36 @@ pub struct Disk {
     /// Storage tier classification
     #[serde(default)]
     pub tier: StorageTier,
+    /// Size and block sizes read from the device when it was added (block devices only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_geometry: Option<crate::io_alignment::BlockGeometry>,
+    /// Fragments read from this disk that failed their checksum
+    #[serde(default)]
+    pub corruption_count: u64,
+    /// Recent fragment I/O errors, driving automatic health transitions
+    #[serde(default)]
+    pub io_errors: IoErrorHistory,
+    /// Latest SMART sample of a block-device disk, if it has been polled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smart: Option<crate::smart::SmartSample>,
+    /// Thresholds for those transitions, taken from the pool config
+    #[serde(skip)]
+    pub health_policy: DiskHealthPolicy,
+    /// Fragments on this disk are encrypted with the pool key
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Identity of the pool the disk was added to; absent on disks of older pools
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_uuid: Option<Uuid>,
+    /// The pool key, once the pool has been unlocked
+    #[serde(skip)]
+    pub cipher: Option<Arc<FragmentCipher>>,
+    /// Where health changes are reported while the pool is in use
+    #[serde(skip)]
+    pub events: Option<Arc<EventRing>>,
+    /// Fragment I/O since the disk was opened, shared by its clones
+    #[serde(skip)]
+    pub io_counters: Arc<DiskIoCounters>,
 
     /// In-memory allocator and index (not serialized)
     #[serde(skip)]
@@ -34,6 +97,17 @@ pub struct Disk {
     #[serde(skip)]
     /// On-device allocator (for block devices)
     pub on_device_allocator: Option<crate::on_device_allocator::OnDeviceAllocator>,
+    /// Test hook: flip a byte of every fragment after it has been written and read back
+    #[cfg(test)]
+    #[serde(skip)]
+    pub corrupt_writes: bool,
+    /// Test hook: wait this long in every fragment read, like a slow device
+    #[cfg(test)]
+    #[serde(skip)]
+    pub read_delay: Option<std::time::Duration>,
+    /// Fields of a newer minor format, kept when the disk is saved
+    #[serde(flatten)]
+    pub unknown: std::collections::BTreeMap<String, serde_json::Value>,
 }
 
 impl std::convert::AsRef<Disk> for Disk {
@@ -60,7 +134,7 @@ pub enum DiskHealth {
     Healthy,
     /// Partial failures; read-only (never selected for new writes)
     Degraded,
-    /// Intermittent errors; read-only (never selected for new writes)
+    /// Intermittent errors; only selected for new writes when too few disks are healthy
     Suspect,
     /// Being removed gracefully; read-only (never selected for new writes)
     Draining,
@@ -68,6 +142,161 @@ pub enum DiskHealth {
     Failed,
 }
 
+/// Checksum failures after which a healthy disk is marked Suspect
+pub const CORRUPTION_SUSPECT_THRESHOLD: u64 = 3;
+
+/// Percent by which the usage counter may differ from the sampled estimate before a recount
+pub const USAGE_DRIFT_PERCENT: u64 = 20;
+
+/// Files whose size is read for the sampled usage estimate
+const USAGE_SAMPLE_FILES: usize = 64;
+
+/// Fragments and bytes held in a disk's fragment store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FragmentUsage {
+    pub fragments: u64,
+    pub bytes: u64,
+}
+
+impl FragmentUsage {
+    /// A single fragment of `bytes`
+    pub fn fragment(bytes: u64) -> Self {
+        FragmentUsage { fragments: 1, bytes }
+    }
+
+    /// A file in the fragment store; only `.frag` files count as fragments
+    fn file(path: &Path, bytes: u64) -> Self {
+        let fragments = u64::from(path.extension().is_some_and(|ext| ext == "frag"));
+        FragmentUsage { fragments, bytes }
+    }
+}
+
+/// Usage counters of a disk before and after a recount changed them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageCorrection {
+    pub disk_uuid: Uuid,
+    pub before: FragmentUsage,
+    pub after: FragmentUsage,
+}
+
+/// Thresholds for changing a disk's health from its I/O error history
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct DiskHealthPolicy {
+    /// Errors within the window that turn a Healthy disk Suspect
+    pub suspect_errors: usize,
+    /// Errors within the window that mark a disk Failed
+    pub failed_errors: usize,
+    /// How long an error counts against the disk, in seconds
+    pub window_secs: i64,
+    /// Successful I/Os that cancel out one recorded error
+    pub successes_per_decay: u32,
+}
+
+impl Default for DiskHealthPolicy {
+    fn default() -> Self {
+        DiskHealthPolicy {
+            suspect_errors: 5,
+            failed_errors: 20,
+            window_secs: 600,
+            successes_per_decay: 100,
+        }
+    }
+}
+
+/// Timestamped fragment I/O errors of one disk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IoErrorHistory {
+    /// Unix timestamps of the errors still counted, oldest first
+    pub errors: Vec<i64>,
+    /// Whether the current Suspect state came from this history, so it may clear itself
+    pub marked_suspect: bool,
+    /// Successes since the last decay
+    #[serde(skip)]
+    successes: u32,
+}
+
+impl IoErrorHistory {
+    /// Drop errors older than the window; returns whether any were dropped
+    fn expire(&mut self, now: i64, policy: &DiskHealthPolicy) -> bool {
+        let before = self.errors.len();
+        self.errors.retain(|&at| now - at < policy.window_secs);
+        self.errors.len() != before
+    }
+}
+
+/// Fragment reads, writes and errors of one disk
+#[derive(Debug, Default)]
+pub struct DiskIoCounters {
+    reads: AtomicU64,
+    read_bytes: AtomicU64,
+    writes: AtomicU64,
+    write_bytes: AtomicU64,
+    errors: AtomicU64,
+    /// Moving average of successful fragment read times in microseconds; 0 before the first
+    read_latency_us: AtomicU64,
+}
+
+/// Values of `DiskIoCounters` at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskIoSnapshot {
+    pub reads: u64,
+    pub read_bytes: u64,
+    pub writes: u64,
+    pub write_bytes: u64,
+    /// Failed fragment I/O and fragments that failed their checksum
+    pub errors: u64,
+    /// Moving average of fragment read times in microseconds; 0 before the first read
+    #[serde(default)]
+    pub read_latency_us: u64,
+}
+
+impl DiskIoCounters {
+    fn record_read(&self, bytes: u64) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn record_write(&self, bytes: u64) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.write_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fold a fragment read time into the moving average, weighting it 1/8
+    fn record_read_latency(&self, latency: std::time::Duration) {
+        let sample = (latency.as_micros() as u64).max(1);
+        self.read_latency_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| match average {
+                0 => Some(sample),
+                average => Some((average * 7 + sample) / 8),
+            })
+            .ok();
+    }
+
+    /// Moving average of fragment read times, once a read has been timed
+    pub fn read_latency(&self) -> Option<std::time::Duration> {
+        match self.read_latency_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(std::time::Duration::from_micros(us)),
+        }
+    }
+
+    pub fn snapshot(&self) -> DiskIoSnapshot {
+        DiskIoSnapshot {
+            reads: self.reads.load(Ordering::Relaxed),
+            read_bytes: self.read_bytes.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            write_bytes: self.write_bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            read_latency_us: self.read_latency_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
 /// Guard to ensure temporary fragment files are cleaned up on failure
 struct TempFragmentGuard {
     path: PathBuf,
@@ -97,7 +326,7 @@ impl Drop for TempFragmentGuard {
 
 impl Disk {
     /// Create a new disk from a directory path
-    pub fn new(path: PathBuf) -> Result<Self> {
+    pub fn new(path: PathBuf) -> StorageResult<Self> {
         // Create disk metadata
         let uuid = Uuid::new_v4();
         let capacity_bytes = Self::get_available_space(&path)?;
@@ -111,16 +340,33 @@ impl Disk {
             .context("Failed to create fragments directory")?;
         
         let mut disk = Disk {
+            format_version: DISK_FORMAT_VERSION,
             uuid,
             path: path.clone(),
             capacity_bytes,
             used_bytes: 0,
+            fragment_count: 0,
             health: DiskHealth::Healthy,
             kind: DiskKind::Directory,
             tier,
+            block_geometry: None,
+            corruption_count: 0,
+            io_errors: IoErrorHistory::default(),
+            smart: None,
+            health_policy: DiskHealthPolicy::default(),
+            encrypted: false,
+            pool_uuid: None,
+            cipher: None,
+            events: None,
+            io_counters: Arc::default(),
             allocator: None,
             free_index: None,
             on_device_allocator: None,
+            #[cfg(test)]
+            corrupt_writes: false,
+            #[cfg(test)]
+            read_delay: None,
+            unknown: Default::default(),
         };
 
         // Initialize allocator and free-index for directory-backed disk
@@ -130,25 +376,43 @@ impl Disk {
     }
 
     /// Initialize a disk backed by a raw block device
-    pub fn from_block_device(path: PathBuf) -> Result<Self> {
+    pub fn from_block_device(path: PathBuf) -> StorageResult<Self> {
         // Do not create directories on raw devices
         let uuid = Uuid::new_v4();
-        let capacity_bytes = Self::get_block_device_size(&path)?;
+        let geometry = crate::io_alignment::BlockGeometry::query(&path)?;
         
-        // Detect storage tier (use /tmp as probe location for block devices)
-        let tier = Self::detect_tier(&std::path::PathBuf::from("/tmp"));
+        // Detect storage tier (use /tmp as probe location when sysfs has no answer)
+        let tier = Self::sysfs_tier(&path)
+            .unwrap_or_else(|| Self::probe_tier(&std::path::PathBuf::from("/tmp")));
 
         let mut disk = Disk {
+            format_version: DISK_FORMAT_VERSION,
             uuid,
             path: path.clone(),
-            capacity_bytes,
+            capacity_bytes: geometry.size_bytes,
             used_bytes: 0,
+            fragment_count: 0,
             health: DiskHealth::Healthy,
             kind: DiskKind::BlockDevice,
             tier,
+            block_geometry: Some(geometry),
+            corruption_count: 0,
+            io_errors: IoErrorHistory::default(),
+            smart: None,
+            health_policy: DiskHealthPolicy::default(),
+            encrypted: false,
+            pool_uuid: None,
+            cipher: None,
+            events: None,
+            io_counters: Arc::default(),
             allocator: None,
             free_index: None,
             on_device_allocator: None,
+            #[cfg(test)]
+            corrupt_writes: false,
+            #[cfg(test)]
+            read_delay: None,
+            unknown: Default::default(),
         };
 
         // Try loading on-device allocator if present (non-fatal)
@@ -166,11 +430,14 @@ impl Disk {
     }
     
     /// Load disk from its directory
-    pub fn load(path: &Path) -> Result<Self> {
+    pub fn load(path: &Path) -> StorageResult<Self> {
         let metadata_path = path.join("disk.json");
         let contents = fs::read_to_string(&metadata_path)
             .context("Failed to read disk metadata")?;
-        let mut disk: Disk = serde_json::from_str(&contents)
+        let mut value: serde_json::Value = serde_json::from_str(&contents)
+            .context("Failed to parse disk metadata")?;
+        format_version::upgrade(&mut value, DISK_FORMAT_VERSION, DISK_UPGRADES, &format!("Disk {:?}", path))?;
+        let mut disk: Disk = serde_json::from_value(value)
             .context("Failed to parse disk metadata")?;
 
         // Initialize runtime-only fields
@@ -192,11 +459,20 @@ impl Disk {
         Ok(disk)
     }
     
+    /// Whether the disk is still where it was loaded from, e.g. not unplugged
+    pub fn is_reachable(&self) -> bool {
+        match self.kind {
+            DiskKind::Directory => self.path.join("disk.json").is_file() && self.path.join("fragments").is_dir(),
+            DiskKind::BlockDevice => self.path.exists(),
+        }
+    }
+    
     /// Save disk metadata
-    pub fn save(&self) -> Result<()> {
+    pub fn save(&self) -> StorageResult<()> {
         let metadata_path = self.path.join("disk.json");
         let contents = serde_json::to_string_pretty(self)
             .context("Failed to serialize disk metadata")?;
+        format_version::backup_older_format(&metadata_path, DISK_FORMAT_VERSION)?;
         
         // Atomic write: write to temp file, then rename
         let temp_path = metadata_path.with_extension("json.tmp");
@@ -218,30 +494,47 @@ impl Disk {
         Ok(available_bytes)
     }
 
-    fn get_block_device_size(path: &Path) -> Result<u64> {
-        use std::os::unix::fs::OpenOptionsExt;
-        use std::os::unix::io::AsRawFd;
-        use std::fs::OpenOptions;
-        use libc;
+    /// Detect storage tier from the backing device, falling back to a latency probe
+    fn detect_tier(path: &Path) -> StorageTier {
+        if let Some(tier) = Self::sysfs_tier(path) {
+            return tier;
+        }
+        Self::probe_tier(path)
+    }
+
+    /// Tier of the block device holding `path`, from its sysfs queue attributes
+    ///
+    /// NVMe devices are Hot, other non-rotational devices Warm and rotational
+    /// ones Cold. `None` when the device has no sysfs entry (tmpfs, overlay,
+    /// network filesystems), leaving the choice to the latency probe.
+    #[cfg(target_os = "linux")]
+    fn sysfs_tier(path: &Path) -> Option<StorageTier> {
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
 
-        let file = OpenOptions::new()
-            .read(true)
-            .custom_flags(libc::O_RDONLY)
-            .open(path)
-            .context("Failed to open block device")?;
-        let fd = file.as_raw_fd();
+        let meta = fs::metadata(path).ok()?;
+        let dev = if meta.file_type().is_block_device() { meta.rdev() } else { meta.dev() };
+        let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+        let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+        let device = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)).ok()?;
 
-        let mut size: u64 = 0;
-        const BLKGETSIZE64: libc::c_ulong = 0x80081272;
-        let ret = unsafe { libc::ioctl(fd, BLKGETSIZE64 as _, &mut size) };
-        if ret != 0 {
-            return Err(anyhow!("BLKGETSIZE64 ioctl failed"));
-        }
-        Ok(size)
+        // Partitions carry no queue directory; their parent is the whole disk
+        let disk = if device.join("queue").exists() { device } else { device.parent()?.to_path_buf() };
+        let rotational = fs::read_to_string(disk.join("queue/rotational")).ok()?;
+        let name = disk.file_name()?.to_string_lossy().into_owned();
+        Some(match rotational.trim() {
+            "1" => StorageTier::Cold,
+            _ if name.starts_with("nvme") => StorageTier::Hot,
+            _ => StorageTier::Warm,
+        })
     }
 
-    /// Detect storage tier by measuring I/O latency
-    fn detect_tier(path: &Path) -> StorageTier {
+    #[cfg(not(target_os = "linux"))]
+    fn sysfs_tier(_path: &Path) -> Option<StorageTier> {
+        None
+    }
+
+    /// Guess the storage tier by measuring I/O latency
+    fn probe_tier(path: &Path) -> StorageTier {
         // Perform latency probe: write small file and measure time
         let test_file = path.join("latency_probe.tmp");
         let start = Instant::now();
@@ -316,23 +609,144 @@ impl Disk {
         Ok(())
     }
     
-    /// Update used space
-    pub fn update_usage(&mut self) -> Result<()> {
-        let fragments_dir = self.path.join("fragments");
-        let mut total_size = 0u64;
+    /// Usage counters as they stand
+    pub fn usage(&self) -> FragmentUsage {
+        FragmentUsage { fragments: self.fragment_count, bytes: self.used_bytes }
+    }
+    
+    /// Count fragments and bytes from what the disk actually holds
+    ///
+    /// Directory disks are walked file by file; temporary files left by an
+    /// interrupted write take space, so they count as bytes but not as
+    /// fragments. Device disks are read from the allocator bitmap and a scan
+    /// for valid fragment headers.
+    pub fn recalculate_usage(&self) -> StorageResult<FragmentUsage> {
+        if self.kind == DiskKind::BlockDevice {
+            let oda = self.on_device_allocator.as_ref().ok_or_else(|| anyhow!("Block device missing on-device allocator"))?;
+            let (fragments, used_units) = oda.usage()?;
+            return Ok(FragmentUsage { fragments, bytes: used_units * oda.unit_size });
+        }
         
+        let mut usage = FragmentUsage::default();
+        let fragments_dir = self.path.join("fragments");
         if fragments_dir.exists() {
             for entry in walkdir::WalkDir::new(&fragments_dir) {
-                let entry = entry?;
+                let entry = entry.map_err(std::io::Error::from)?;
                 if entry.file_type().is_file() {
-                    total_size += entry.metadata()?.len();
+                    let file = FragmentUsage::file(entry.path(), entry.metadata().map_err(std::io::Error::from)?.len());
+                    usage.fragments += file.fragments;
+                    usage.bytes += file.bytes;
                 }
             }
         }
+        Ok(usage)
+    }
+    
+    /// Cheap estimate of `recalculate_usage`
+    ///
+    /// Directory disks list the fragment store and read the size of at most
+    /// `USAGE_SAMPLE_FILES` files spread across it, so the fragment count is
+    /// exact and the bytes are extrapolated. Device disks take the bytes from
+    /// the in-memory bitmap; their fragment count would need a device scan, so
+    /// the counter is returned as is.
+    pub fn estimate_usage(&self) -> StorageResult<FragmentUsage> {
+        if self.kind == DiskKind::BlockDevice {
+            let oda = self.on_device_allocator.as_ref().ok_or_else(|| anyhow!("Block device missing on-device allocator"))?;
+            let used_units = oda.total_units - oda.free_count();
+            return Ok(FragmentUsage { fragments: self.fragment_count, bytes: used_units * oda.unit_size });
+        }
         
-        self.used_bytes = total_size;
+        let fragments_dir = self.path.join("fragments");
+        if !fragments_dir.exists() {
+            return Ok(FragmentUsage::default());
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&fragments_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+        }
+        let fragments = files.iter().map(|path| FragmentUsage::file(path, 0).fragments).sum();
+        let stride = files.len().div_ceil(USAGE_SAMPLE_FILES).max(1);
+        let (mut sampled, mut sampled_bytes) = (0u64, 0u64);
+        for path in files.iter().step_by(stride) {
+            // Removed since the listing
+            let Ok(metadata) = fs::metadata(path) else { continue };
+            sampled += 1;
+            sampled_bytes += metadata.len();
+        }
+        let bytes = (sampled_bytes * files.len() as u64).checked_div(sampled).unwrap_or(0);
+        Ok(FragmentUsage { fragments, bytes })
+    }
+    
+    /// Replace the usage counters with `recalculate_usage`
+    ///
+    /// A correction is logged, reported as a `usage_corrected` event and
+    /// saved; returns it, or `None` if the counters were already right.
+    pub fn recount_usage(&mut self) -> StorageResult<Option<UsageCorrection>> {
+        let before = self.usage();
+        let after = self.recalculate_usage()?;
+        if after == before {
+            return Ok(None);
+        }
+        log::warn!(
+            "Disk {} usage corrected: {} fragments / {} bytes counted, {} fragments / {} bytes found",
+            self.uuid,
+            before.fragments,
+            before.bytes,
+            after.fragments,
+            after.bytes
+        );
+        if let Some(events) = &self.events {
+            events.record(
+                EventKind::UsageCorrected,
+                None,
+                Some(self.uuid),
+                format!(
+                    "{} fragments / {} bytes -> {} fragments / {} bytes",
+                    before.fragments, before.bytes, after.fragments, after.bytes
+                ),
+            );
+        }
+        self.fragment_count = after.fragments;
+        self.used_bytes = after.bytes;
         self.save()?;
-        Ok(())
+        Ok(Some(UsageCorrection { disk_uuid: self.uuid, before, after }))
+    }
+    
+    /// Recount if the counters disagree with `estimate_usage`
+    ///
+    /// The fragment count has to match exactly; the bytes may be off by
+    /// `USAGE_DRIFT_PERCENT`, since they are extrapolated from a sample.
+    pub fn check_usage_drift(&mut self) -> StorageResult<Option<UsageCorrection>> {
+        let estimate = self.estimate_usage()?;
+        let counted = self.usage();
+        let byte_drift = estimate.bytes.abs_diff(counted.bytes);
+        let drifted = estimate.fragments != counted.fragments
+            || byte_drift * 100 > USAGE_DRIFT_PERCENT * estimate.bytes.max(counted.bytes);
+        if !drifted {
+            return Ok(None);
+        }
+        self.recount_usage()
+    }
+    
+    /// Apply one change to the fragment store to the usage counters and save them
+    ///
+    /// Every write, removal and quarantine of a fragment goes through here;
+    /// a fragment written over an existing one is `removed` and `added`.
+    fn account_fragments(&mut self, added: FragmentUsage, removed: FragmentUsage) -> Result<()> {
+        self.fragment_count = (self.fragment_count + added.fragments).saturating_sub(removed.fragments);
+        self.used_bytes = (self.used_bytes + added.bytes).saturating_sub(removed.bytes);
+        Ok(self.save()?)
+    }
+    
+    /// Fraction of the capacity in use, from 0.0 to 1.0
+    pub fn utilization(&self) -> f64 {
+        if self.capacity_bytes == 0 {
+            return 1.0;
+        }
+        self.used_bytes as f64 / self.capacity_bytes as f64
     }
     
     /// Check if disk has enough free space
@@ -358,8 +772,11 @@ impl Disk {
         extent_uuid: &Uuid,
         fragment_index: usize,
         data: &[u8],
-    ) -> Result<Option<crate::on_device_allocator::OnDevicePlacement>> {
+    ) -> StorageResult<Option<crate::on_device_allocator::OnDevicePlacement>> {
         eprintln!("[DISK DEBUG] write_fragment start: extent={}, fragment_index={}, size={}", extent_uuid, fragment_index, data.len());
+        let _slot = io_scheduler::scheduler().admit(self.uuid);
+        let payload = self.seal_fragment(extent_uuid, fragment_index, data)?;
+        let data: &[u8] = &payload;
         // Handle block device backed disks using on-device allocator when available
         if self.kind == DiskKind::BlockDevice {
             if let Some(oda) = &mut self.on_device_allocator {
@@ -390,25 +807,31 @@ impl Disk {
                 // verify readback
                 let (_rh, rd) = oda.read_fragment_at(start)?;
                 if rd != data {
-                    anyhow::bail!("Fragment verification failed on device");
+                    return Err(StorageError::Corruption {
+                        extent: *extent_uuid,
+                        detail: "Fragment verification failed on device".to_string(),
+                    });
                 }
 
                 eprintln!("[DISK DEBUG] block device: successful write; saving disk metadata");
-                self.used_bytes += data.len() as u64;
-                self.save()?;
+                let bytes = placement.unit_count * oda.unit_size;
+                self.io_counters.record_write(data.len() as u64);
+                self.account_fragments(FragmentUsage::fragment(bytes), FragmentUsage::default())?;
                 eprintln!("[DISK DEBUG] block device: save complete");
                 return Ok(Some(placement));
             } else {
-                return Err(anyhow!("Block device missing on-device allocator (not formatted)"));
+                return Err(anyhow!("Block device missing on-device allocator (not formatted)").into());
             }
         }
 
         // Regular directory-backed behavior
         let fragment_path = self.fragment_path(extent_uuid, fragment_index);
+        // A rebuild may write a fragment over an older copy
+        let replaced = fs::metadata(&fragment_path).map_or_else(|_| FragmentUsage::default(), |m| FragmentUsage::fragment(m.len()));
         
         eprintln!("[DISK DEBUG] dir-backed: before CrashPoint::BeforeFragmentWrite");
         #[cfg(test)]
-        check_crash_point(CrashPoint::BeforeFragmentWrite)?;
+        check_fault_at(CrashPoint::BeforeFragmentWrite, &fragment_path)?;
         
          let temp_path = fragment_path.with_extension("frag.tmp");
          let mut guard = TempFragmentGuard::new(temp_path.clone());
@@ -430,7 +853,7 @@ impl Disk {
          
          eprintln!("[DISK DEBUG] dir-backed: before CrashPoint::AfterFragmentWrite");
          #[cfg(test)]
-         check_crash_point(CrashPoint::AfterFragmentWrite)?;
+         check_fault_at(CrashPoint::AfterFragmentWrite, &fragment_path)?;
          
          eprintln!("[DISK DEBUG] renaming temp fragment to final location");
          fs::rename(&temp_path, &fragment_path)
@@ -442,10 +865,10 @@ impl Disk {
          let written = fs::read(&fragment_path)
              .context("Failed to verify fragment readback")?;
          if written != data {
-             return Err(anyhow!(
-                 "Fragment verification failed for {}",
-                 fragment_path.display()
-             ));
+             return Err(StorageError::Corruption {
+                 extent: *extent_uuid,
+                 detail: format!("Fragment verification failed for {}", fragment_path.display()),
+             });
          }
 
          if let Some(parent) = fragment_path.parent() {
@@ -453,46 +876,131 @@ impl Disk {
                  let _ = dir.sync_all();
              }
          }
+
+         // A device that acknowledges the write and later persists something else
+         #[cfg(test)]
+         if self.corrupt_writes {
+             let mut corrupted = written;
+             corrupted[0] ^= 0xff;
+             fs::write(&fragment_path, &corrupted)?;
+         }
         
         eprintln!("[DISK DEBUG] updating used_bytes and saving disk metadata");
-        self.used_bytes += data.len() as u64;
-        self.save()?;
+        self.io_counters.record_write(data.len() as u64);
+        self.account_fragments(FragmentUsage::fragment(data.len() as u64), replaced)?;
         eprintln!("[DISK DEBUG] disk save complete");
         
         Ok(None)
     }
     
     /// Read a fragment from disk
-    pub fn read_fragment(&self, extent_uuid: &Uuid, fragment_index: usize) -> Result<Vec<u8>> {
+    pub fn read_fragment(&self, extent_uuid: &Uuid, fragment_index: usize) -> StorageResult<Vec<u8>> {
+        let mut data = Vec::new();
+        self.read_fragment_into(extent_uuid, fragment_index, &mut data)?;
+        Ok(data)
+    }
+    
+    /// `read_fragment` into `data`, replacing what it held
+    ///
+    /// A buffer with room for the fragment is filled without reallocating.
+    pub fn read_fragment_into(&self, extent_uuid: &Uuid, fragment_index: usize, data: &mut Vec<u8>) -> StorageResult<()> {
+        let _slot = io_scheduler::scheduler().admit(self.uuid);
         // Handle block device backed disks using on-device allocator when available
         if self.kind == DiskKind::BlockDevice {
             if let Some(oda) = &self.on_device_allocator {
                 // For block devices, we need to find the placement information
                 // This requires looking up the fragment location from metadata
                 // For now, return an error - this should be handled by the caller
-                return Err(anyhow!("Block device fragment reading requires placement info"));
+                return Err(anyhow!("Block device fragment reading requires placement info").into());
             } else {
-                return Err(anyhow!("Block device missing on-device allocator"));
+                return Err(anyhow!("Block device missing on-device allocator").into());
             }
         }
 
         // Regular directory-backed behavior
         let fragment_path = self.fragment_path(extent_uuid, fragment_index);
-        fs::read(&fragment_path).context("Failed to read fragment")
+        #[cfg(test)]
+        check_fault_at(CrashPoint::BeforeFragmentRead, &fragment_path)?;
+        let started = std::time::Instant::now();
+        #[cfg(test)]
+        if let Some(delay) = self.read_delay {
+            std::thread::sleep(delay);
+        }
+        data.clear();
+        File::open(&fragment_path)
+            .and_then(|mut file| file.read_to_end(data))
+            .context("Failed to read fragment")?;
+        self.io_counters.record_read(data.len() as u64);
+        self.io_counters.record_read_latency(started.elapsed());
+        if self.encrypted {
+            *data = self.open_fragment(extent_uuid, fragment_index, std::mem::take(data))?;
+        }
+        Ok(())
     }
 
     /// Read a fragment from block device using placement information
-    pub fn read_fragment_at_placement(&self, placement: &crate::on_device_allocator::OnDevicePlacement) -> Result<Vec<u8>> {
+    pub fn read_fragment_at_placement(&self, placement: &crate::on_device_allocator::OnDevicePlacement) -> StorageResult<Vec<u8>> {
         if self.kind != DiskKind::BlockDevice {
-            return Err(anyhow!("read_fragment_at_placement only supported for block devices"));
+            return Err(StorageError::Unsupported("read_fragment_at_placement only supported for block devices".to_string()));
         }
 
         if let Some(oda) = &self.on_device_allocator {
-            let (_header, data) = oda.read_fragment_at(placement.start_unit)?;
-            Ok(data)
+            let _slot = io_scheduler::scheduler().admit(self.uuid);
+            let (header, data) = oda.read_fragment_at(placement.start_unit)?;
+            self.io_counters.record_read(data.len() as u64);
+            self.open_fragment(&header.extent_uuid, header.fragment_index as usize, data)
         } else {
-            Err(anyhow!("Block device missing on-device allocator"))
+            Err(anyhow!("Block device missing on-device allocator").into())
+        }
+    }
+
+    /// Encrypt a fragment payload if this disk belongs to an encrypted pool
+    ///
+    /// The payload checksums kept in extent metadata are over the plaintext;
+    /// only the bytes on the disk (and the on-device header checksum) see
+    /// the ciphertext.
+    fn seal_fragment<'a>(&self, extent_uuid: &Uuid, fragment_index: usize, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if !self.encrypted {
+            return Ok(Cow::Borrowed(data));
+        }
+        let cipher = self.cipher.as_ref().ok_or_else(|| self.locked_error())?;
+        Ok(Cow::Owned(cipher.seal(extent_uuid, fragment_index, data)?))
+    }
+
+    /// Decrypt a fragment read back from this disk
+    fn open_fragment(&self, extent_uuid: &Uuid, fragment_index: usize, data: Vec<u8>) -> StorageResult<Vec<u8>> {
+        if !self.encrypted {
+            return Ok(data);
         }
+        let cipher = self.cipher.as_ref().ok_or_else(|| self.locked_error())?;
+        Ok(cipher.open(extent_uuid, fragment_index, &data)?)
+    }
+
+    fn locked_error(&self) -> StorageError {
+        StorageError::DiskUnavailable {
+            disk: self.uuid,
+            detail: format!("Disk {} belongs to an encrypted pool and its key was not supplied", self.uuid),
+        }
+    }
+
+    /// Flush a fragment file and its directory entry to stable storage
+    ///
+    /// Block-device fragments are already synced by the on-device allocator when written.
+    pub fn sync_fragment(&self, extent_uuid: &Uuid, fragment_index: usize) -> StorageResult<()> {
+        if self.kind == DiskKind::BlockDevice {
+            return Ok(());
+        }
+
+        let fragment_path = self.fragment_path(extent_uuid, fragment_index);
+        File::open(&fragment_path)
+            .and_then(|f| f.sync_all())
+            .with_context(|| format!("Failed to fsync fragment {}", fragment_path.display()))?;
+        if let Some(parent) = fragment_path.parent() {
+            File::open(parent)
+                .and_then(|d| d.sync_all())
+                .with_context(|| format!("Failed to fsync directory {}", parent.display()))?;
+        }
+        Ok(())
     }
 
     /// Check if a fragment exists
@@ -501,59 +1009,552 @@ impl Disk {
     }
     
     /// Delete a fragment
-    pub fn delete_fragment(&mut self, extent_uuid: &Uuid, fragment_index: usize) -> Result<()> {
+    pub fn delete_fragment(&mut self, extent_uuid: &Uuid, fragment_index: usize) -> StorageResult<()> {
+        let _slot = io_scheduler::scheduler().admit(self.uuid);
         let fragment_path = self.fragment_path(extent_uuid, fragment_index);
         if fragment_path.exists() {
-            let size = fs::metadata(&fragment_path)?.len();
-            fs::remove_file(&fragment_path)?;
-            self.used_bytes = self.used_bytes.saturating_sub(size);
-            self.save()?;
+            self.remove_fragment_file(&fragment_path)?;
         }
         Ok(())
     }
     
+    /// Remove a file from the fragment store, e.g. a fragment or a leftover temporary file
+    pub fn remove_fragment_file(&mut self, path: &Path) -> StorageResult<()> {
+        let size = fs::metadata(path)?.len();
+        fs::remove_file(path)?;
+        Ok(self.account_fragments(FragmentUsage::default(), FragmentUsage::file(path, size))?)
+    }
+    
     /// Mark disk as draining (graceful removal)
-    pub fn mark_draining(&mut self) -> Result<()> {
+    pub fn mark_draining(&mut self) -> StorageResult<()> {
         self.health = DiskHealth::Draining;
         self.save()
     }
     
+    /// Mark a Healthy disk Suspect so placement avoids it; other states are kept
+    ///
+    /// Returns whether the health changed.
+    pub fn mark_suspect(&mut self) -> StorageResult<bool> {
+        if self.health != DiskHealth::Healthy {
+            return Ok(false);
+        }
+        self.health_event(DiskHealth::Suspect, "marked suspect");
+        self.health = DiskHealth::Suspect;
+        self.save()?;
+        Ok(true)
+    }
+    
     /// Mark disk as failed
-    pub fn mark_failed(&mut self) -> Result<()> {
+    pub fn mark_failed(&mut self) -> StorageResult<()> {
+        if self.health != DiskHealth::Failed {
+            self.health_event(DiskHealth::Failed, "marked failed");
+        }
         self.health = DiskHealth::Failed;
         self.save()
     }
+    
+    /// Count a fragment on this disk that failed its checksum
+    ///
+    /// A healthy disk becomes Suspect once `CORRUPTION_SUSPECT_THRESHOLD`
+    /// failures have been seen. Returns whether this call changed the health.
+    pub fn record_corruption(&mut self) -> StorageResult<bool> {
+        self.corruption_count += 1;
+        self.io_counters.record_error();
+        let suspect = self.health == DiskHealth::Healthy && self.corruption_count >= CORRUPTION_SUSPECT_THRESHOLD;
+        if suspect {
+            self.health_event(DiskHealth::Suspect, &format!("{} checksum failures", self.corruption_count));
+            self.health = DiskHealth::Suspect;
+        }
+        self.save()?;
+        Ok(suspect)
+    }
+    
+    /// Keep `sample` as the latest SMART sample and act on its warnings
+    ///
+    /// Each sign of a failing device since the previous sample is recorded as
+    /// a `SmartWarning` event, and makes a Healthy disk Suspect. Returns the
+    /// warnings.
+    pub fn record_smart(&mut self, sample: crate::smart::SmartSample) -> StorageResult<Vec<String>> {
+        let warnings = crate::smart::warnings(self.smart.as_ref(), &sample);
+        if let (Some(events), false) = (&self.events, warnings.is_empty()) {
+            events.record_with_fields(
+                EventKind::SmartWarning,
+                None,
+                Some(self.uuid),
+                format!("SMART on {}: {}", self.path.display(), warnings.join("; ")),
+                Some(serde_json::json!({
+                    "path": self.path,
+                    "warnings": warnings,
+                    "sample": sample,
+                })),
+            );
+        }
+        if !warnings.is_empty() && self.health == DiskHealth::Healthy {
+            self.health_event(DiskHealth::Suspect, &format!("SMART: {}", warnings.join("; ")));
+            self.health = DiskHealth::Suspect;
+        }
+        self.smart = Some(sample);
+        self.save()?;
+        Ok(warnings)
+    }
+    
+    /// Report a change from the current health to `health` to the event ring
+    pub fn health_event(&self, health: DiskHealth, reason: &str) {
+        if let Some(events) = &self.events {
+            events.record_with_fields(
+                EventKind::DiskHealth,
+                None,
+                Some(self.uuid),
+                format!("{:?} -> {:?}: {}", self.health, health, reason),
+                Some(serde_json::json!({
+                    "from": format!("{:?}", self.health),
+                    "to": format!("{:?}", health),
+                    "reason": reason,
+                    "path": self.path,
+                })),
+            );
+        }
+    }
+    
+    /// Errors counted against the disk in the current window
+    pub fn recent_io_errors(&self) -> usize {
+        let cutoff = chrono::Utc::now().timestamp() - self.health_policy.window_secs;
+        self.io_errors.errors.iter().filter(|&&at| at > cutoff).count()
+    }
+    
+    /// Record a failed fragment read or write
+    ///
+    /// A Healthy disk becomes Suspect after `suspect_errors` errors within the
+    /// window, and any disk not yet Failed becomes Failed after `failed_errors`.
+    /// Returns the new health if it changed; the change is saved.
+    pub fn record_io_error(&mut self) -> StorageResult<Option<DiskHealth>> {
+        let policy = self.health_policy;
+        let now = chrono::Utc::now().timestamp();
+        self.io_errors.expire(now, &policy);
+        self.io_errors.errors.push(now);
+        self.io_errors.successes = 0;
+        self.io_counters.record_error();
+        
+        let recent = self.io_errors.errors.len();
+        let new_health = if recent >= policy.failed_errors && self.health != DiskHealth::Failed {
+            Some(DiskHealth::Failed)
+        } else if recent >= policy.suspect_errors && self.health == DiskHealth::Healthy {
+            self.io_errors.marked_suspect = true;
+            Some(DiskHealth::Suspect)
+        } else {
+            None
+        };
+        
+        if let Some(health) = new_health {
+            log::warn!(
+                "Disk {} {:?} -> {:?} after {} I/O errors in {}s",
+                self.uuid,
+                self.health,
+                health,
+                recent,
+                policy.window_secs
+            );
+            self.health_event(health, &format!("{} I/O errors in {}s", recent, policy.window_secs));
+            self.health = health;
+        }
+        self.save()?;
+        Ok(new_health)
+    }
+    
+    /// Record a successful fragment read or write
+    ///
+    /// Every `successes_per_decay` successes forget the oldest error. A disk made
+    /// Suspect by its error history returns to Healthy once no errors remain.
+    /// Returns the new health if it changed.
+    pub fn record_io_success(&mut self) -> StorageResult<Option<DiskHealth>> {
+        if self.io_errors.errors.is_empty() {
+            return Ok(None);
+        }
+        
+        let policy = self.health_policy;
+        let mut changed = self.io_errors.expire(chrono::Utc::now().timestamp(), &policy);
+        self.io_errors.successes += 1;
+        if self.io_errors.successes >= policy.successes_per_decay && !self.io_errors.errors.is_empty() {
+            self.io_errors.errors.remove(0);
+            self.io_errors.successes = 0;
+            changed = true;
+        }
+        if !changed {
+            return Ok(None);
+        }
+        
+        let recovered = self.io_errors.errors.is_empty()
+            && self.io_errors.marked_suspect
+            && self.health == DiskHealth::Suspect;
+        if recovered {
+            log::info!("Disk {} Suspect -> Healthy: I/O errors have cleared", self.uuid);
+            self.health_event(DiskHealth::Healthy, "I/O errors have cleared");
+            self.health = DiskHealth::Healthy;
+            self.io_errors.marked_suspect = false;
+        }
+        self.save()?;
+        Ok(recovered.then_some(DiskHealth::Healthy))
+    }
+    
+    /// Feed the outcome of a fragment read or write into the error history
+    ///
+    /// A missing fragment file (deleted, quarantined) says nothing about the
+    /// device and is ignored.
+    pub fn track_io<T>(&mut self, result: &StorageResult<T>) {
+        let recorded = match result {
+            Ok(_) => self.record_io_success(),
+            Err(StorageError::Io(io)) if io.kind() == std::io::ErrorKind::NotFound => return,
+            Err(StorageError::Other(e)) if e
+                .root_cause()
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|io| io.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                return;
+            }
+            Err(_) => self.record_io_error(),
+        };
+        if let Err(e) = recorded {
+            log::error!("Failed to record I/O outcome on disk {}: {}", self.uuid, e);
+        }
+    }
+    
+    /// Forget the error history, e.g. when an operator restores the disk
+    pub fn clear_io_errors(&mut self) {
+        self.io_errors = IoErrorHistory::default();
+    }
+    
+    /// Move a corrupt fragment out of the fragment store
+    ///
+    /// The file is kept under `quarantine/` for inspection; the fragment reads
+    /// as missing until a rebuild writes a good copy.
+    pub fn quarantine_fragment(&mut self, extent_uuid: &Uuid, fragment_index: usize) -> StorageResult<PathBuf> {
+        if self.kind == DiskKind::BlockDevice {
+            return Err(StorageError::Unsupported("Quarantine is not supported for block device fragments".to_string()));
+        }
+        
+        let quarantine_dir = self.path.join("quarantine");
+        fs::create_dir_all(&quarantine_dir).context("Failed to create quarantine directory")?;
+        let target = quarantine_dir.join(format!("{}-{}.frag", extent_uuid, fragment_index));
+        let fragment_path = self.fragment_path(extent_uuid, fragment_index);
+        let size = fs::metadata(&fragment_path).context("Failed to quarantine fragment")?.len();
+        fs::rename(&fragment_path, &target)
+            .context("Failed to quarantine fragment")?;
+        self.account_fragments(FragmentUsage::default(), FragmentUsage::fragment(size))?;
+        Ok(target)
+    }
+}
+
+/// Checks of `DiskPool::check_new_disk` to waive
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NewDiskOverrides {
+    /// Accept a disk on the same device as another disk of the pool
+    pub shared_device: bool,
+    /// Take over a disk that belongs to another pool
+    pub takeover: bool,
+}
+
+/// The identity a disk path already carries, from its metadata or superblock
+#[derive(Deserialize)]
+struct DiskIdentity {
+    uuid: Uuid,
+    #[serde(default)]
+    pool_uuid: Option<Uuid>,
+}
+
+impl DiskIdentity {
+    fn read(path: &Path) -> Option<Self> {
+        if let Ok(contents) = fs::read_to_string(path.join("disk.json")) {
+            return serde_json::from_str(&contents).ok();
+        }
+        let uuid = crate::on_device_allocator::OnDeviceAllocator::device_uuid(path)?;
+        Some(DiskIdentity { uuid, pool_uuid: None })
+    }
+}
+
+/// `path` made absolute with symlinks resolved, for paths that may not exist yet
+fn canonical_path(path: &Path) -> Result<PathBuf> {
+    let absolute = std::env::current_dir()?.join(path);
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(canonical) => return Ok(missing.iter().rev().fold(canonical, |path, name| path.join(name))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+                    return Err(e.into());
+                };
+                missing.push(name);
+                existing = parent;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Device holding `path`, or the device itself for a block device
+///
+/// A path that does not exist yet is on the device of its nearest existing parent.
+#[cfg(unix)]
+fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    let canonical = canonical_path(path).ok()?;
+    let meta = canonical.ancestors().find_map(|p| fs::metadata(p).ok())?;
+    Some(if meta.file_type().is_block_device() { meta.rdev() } else { meta.dev() })
+}
+
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> Option<u64> {
+    None
 }
 
 /// Disk pool manager
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiskPool {
+    /// Format of `pool.json`; see `format_version`
+    #[serde(default)]
+    pub format_version: FormatVersion,
     pub disk_paths: Vec<PathBuf>,
+    /// Identity written into every disk added to the pool; older pools get one at their next `add-disk`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    /// Error thresholds applied to every disk in the pool
+    #[serde(default)]
+    pub health_policy: DiskHealthPolicy,
+    /// Compression applied to newly written extents
+    #[serde(default)]
+    pub compression: crate::compression::Compression,
+    /// Read back and checksum every fragment after it is written
+    #[serde(default)]
+    pub verify_writes: bool,
+    /// Percent of every disk kept free of new writes
+    #[serde(default = "default_space_reserve_percent")]
+    pub space_reserve_percent: u8,
+    /// Pool usage at which low-priority writes, then all writes of new data, are refused
+    #[serde(default)]
+    pub space_watermarks: crate::placement::SpaceWatermarks,
+    /// Concurrency and byte-rate limits for background rebuilds
+    #[serde(default)]
+    pub rebuild_limits: crate::rebuild_budget::RebuildLimits,
+    /// Whether writes fall back to a weaker policy when too few disks are writable
+    #[serde(default)]
+    pub degraded_writes: crate::placement::DegradedWrites,
+    /// Weakest policy a degraded write may fall back to
+    #[serde(default = "default_degraded_write_floor")]
+    pub degraded_write_floor: crate::extent::RedundancyPolicy,
+    /// Milliseconds metadata transactions wait to share one sync
+    #[serde(default = "default_metadata_commit_window_ms")]
+    pub metadata_commit_window_ms: u64,
+    /// Per-class limits on fragment I/O, applied by the mount
+    #[serde(default)]
+    pub io_limits: crate::io_scheduler::IoLimits,
+    /// Background defragmentation run by the mount process
+    #[serde(default)]
+    pub defrag: crate::defrag::DefragConfig,
+    /// Resolve names ignoring case while keeping the casing they were created with; fixed at `init`
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Size of the extents new files are cut into; fixed at `init`
+    #[serde(default = "crate::extent::default_extent_size")]
+    pub extent_size: usize,
+    /// Store of the inode, extent and extent map records; changed by `migrate-metadata`
+    #[serde(default)]
+    pub metadata_backend: crate::metadata_store::MetadataBackend,
+    /// Set when the pool encrypts fragments at rest; fixed at `init`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<crate::encryption::EncryptionConfig>,
+    /// The pool key, once unlocked
+    #[serde(skip)]
+    cipher: Option<Arc<FragmentCipher>>,
+    /// Fields of a newer minor format, kept when the pool is saved
+    #[serde(flatten)]
+    pub unknown: std::collections::BTreeMap<String, serde_json::Value>,
+}
+
+fn default_space_reserve_percent() -> u8 {
+    crate::placement::DEFAULT_SPACE_RESERVE_PERCENT
+}
+
+fn default_degraded_write_floor() -> crate::extent::RedundancyPolicy {
+    crate::placement::DEFAULT_DEGRADED_WRITE_FLOOR
+}
+
+fn default_metadata_commit_window_ms() -> u64 {
+    crate::metadata_tx::DEFAULT_COMMIT_WINDOW.as_millis() as u64
 }
 
 impl DiskPool {
     pub fn new() -> Self {
         DiskPool {
+            format_version: POOL_FORMAT_VERSION,
             disk_paths: Vec::new(),
+            uuid: Some(Uuid::new_v4()),
+            health_policy: DiskHealthPolicy::default(),
+            compression: crate::compression::Compression::None,
+            verify_writes: false,
+            space_reserve_percent: crate::placement::DEFAULT_SPACE_RESERVE_PERCENT,
+            space_watermarks: crate::placement::SpaceWatermarks::default(),
+            rebuild_limits: crate::rebuild_budget::RebuildLimits::default(),
+            degraded_writes: crate::placement::DegradedWrites::Deny,
+            degraded_write_floor: crate::placement::DEFAULT_DEGRADED_WRITE_FLOOR,
+            metadata_commit_window_ms: default_metadata_commit_window_ms(),
+            io_limits: crate::io_scheduler::IoLimits::default(),
+            defrag: crate::defrag::DefragConfig::default(),
+            case_insensitive: false,
+            extent_size: crate::extent::DEFAULT_EXTENT_SIZE,
+            metadata_backend: Default::default(),
+            encryption: None,
+            cipher: None,
+            unknown: Default::default(),
+        }
+    }
+
+    /// Encrypt every fragment of this pool with a key from `source`
+    ///
+    /// Only a pool without disks can be switched, so a pool never holds both
+    /// encrypted and plaintext fragments.
+    pub fn enable_encryption(&mut self, source: &PoolKeySource) -> Result<()> {
+        if self.encryption.is_some() {
+            return Err(anyhow!("Pool is already encrypted"));
+        }
+        if !self.disk_paths.is_empty() {
+            return Err(anyhow!("Encryption can only be enabled on a pool with no disks"));
         }
+        let (config, cipher) = crate::encryption::EncryptionConfig::create(source)?;
+        self.encryption = Some(config);
+        self.cipher = Some(Arc::new(cipher));
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Check `source` against the pool key and keep it for the disks loaded afterwards
+    pub fn unlock(&mut self, source: &PoolKeySource) -> Result<()> {
+        if let Some(config) = &self.encryption {
+            self.cipher = Some(Arc::new(config.unlock(source)?));
+        }
+        Ok(())
+    }
+
+    /// Fail unless the pool is plaintext or has been unlocked
+    pub fn require_key(&self) -> Result<()> {
+        if self.encryption.is_some() && self.cipher.is_none() {
+            return Err(anyhow!("Pool is encrypted: supply its key with --key-file or --passphrase-file"));
+        }
+        Ok(())
+    }
+
+    /// Mark a newly created disk with the pool's identity and encryption setting
+    pub fn adopt_disk(&mut self, disk: &mut Disk) -> Result<()> {
+        disk.pool_uuid = Some(*self.uuid.get_or_insert_with(Uuid::new_v4));
+        disk.encrypted = self.is_encrypted();
+        disk.cipher = self.cipher.clone();
+        Ok(disk.save()?)
     }
     
-    pub fn add_disk(&mut self, path: PathBuf) {
-        if !self.disk_paths.contains(&path) {
-            self.disk_paths.push(path);
+    /// Record a new disk path
+    ///
+    /// Fails if the path is already a disk of the pool, after resolving
+    /// symlinks, or lies inside or around one.
+    pub fn add_disk(&mut self, path: PathBuf) -> Result<()> {
+        self.check_disk_path(&canonical_path(&path)?)?;
+        self.disk_paths.push(path);
+        Ok(())
+    }
+    
+    fn check_disk_path(&self, canonical: &Path) -> Result<()> {
+        for existing in &self.disk_paths {
+            let Ok(other) = canonical_path(existing) else {
+                continue;
+            };
+            if other == canonical {
+                return Err(anyhow!("{} is already a disk of this pool as {}", canonical.display(), existing.display()));
+            }
+            if canonical.starts_with(&other) {
+                return Err(anyhow!("{} is inside disk {} of this pool", canonical.display(), existing.display()));
+            }
+            if other.starts_with(canonical) {
+                return Err(anyhow!("{} contains disk {} of this pool", canonical.display(), existing.display()));
+            }
         }
+        Ok(())
+    }
+    
+    /// Validate a disk about to be added to the pool at `pool_dir`
+    ///
+    /// Besides the path checks of `add_disk`, rejects paths inside or around
+    /// the pool directory, a disk of this pool reached through another path,
+    /// a disk of another pool and a disk on the same device as one already in
+    /// the pool, since placement would treat them as independent failure
+    /// domains. The last two can be waived; the warnings for waived checks are
+    /// returned.
+    pub fn check_new_disk(&self, pool_dir: &Path, path: &Path, overrides: NewDiskOverrides) -> Result<Vec<String>> {
+        let canonical = canonical_path(path)?;
+        self.check_disk_path(&canonical)?;
+        let pool_dir = canonical_path(pool_dir)?;
+        if canonical.starts_with(&pool_dir) {
+            return Err(anyhow!("{} is inside the pool directory {}", canonical.display(), pool_dir.display()));
+        }
+        if pool_dir.starts_with(&canonical) {
+            return Err(anyhow!("{} contains the pool directory {}", canonical.display(), pool_dir.display()));
+        }
+        
+        let mut warnings = Vec::new();
+        if let Some(found) = DiskIdentity::read(path) {
+            if let Some(existing) = self.disk_paths.iter().find(|p| DiskIdentity::read(p).is_some_and(|d| d.uuid == found.uuid)) {
+                return Err(anyhow!(
+                    "{} is disk {} of this pool, already added as {}",
+                    path.display(),
+                    found.uuid,
+                    existing.display()
+                ));
+            }
+            match found.pool_uuid {
+                Some(other) if Some(other) != self.uuid && overrides.takeover => {
+                    warnings.push(format!("{} belonged to pool {}; its fragments there are no longer reachable", path.display(), other));
+                }
+                Some(other) if Some(other) != self.uuid => {
+                    return Err(anyhow!(
+                        "{} is disk {} of another pool ({}); use --force-takeover to add it to this pool anyway",
+                        path.display(),
+                        found.uuid,
+                        other
+                    ));
+                }
+                _ => {}
+            }
+        }
+        
+        if let Some(device) = device_id(path) {
+            for existing in self.disk_paths.iter().filter(|p| device_id(p) == Some(device)) {
+                let message = format!(
+                    "{} is on the same device as disk {}; fragments placed on both fail together",
+                    path.display(),
+                    existing.display()
+                );
+                if !overrides.shared_device {
+                    return Err(anyhow!("{} (use --force if this is intended, e.g. for testing)", message));
+                }
+                warnings.push(message);
+            }
+        }
+        Ok(warnings)
     }
     
     pub fn remove_disk(&mut self, path: &Path) {
         self.disk_paths.retain(|p| p != path);
     }
     
-    /// Load all disks in the pool
+    /// Load every disk of the pool that is currently reachable
+    ///
+    /// A disk whose metadata cannot be read is logged and left out, so a pool
+    /// can be opened while a disk is unplugged.
     pub fn load_disks(&self) -> Result<Vec<Disk>> {
         let mut disks = Vec::new();
         for path in &self.disk_paths {
             match Disk::load(path) {
-                Ok(disk) => disks.push(disk),
+                Ok(disk) => disks.push(self.attach_loaded(disk, path)?),
                 Err(e) => {
                     log::warn!("Failed to load disk at {:?}: {}", path, e);
                 }
@@ -561,11 +1562,33 @@ impl DiskPool {
         }
         Ok(disks)
     }
+
+    /// Load one disk of the pool, e.g. when it comes back after being unplugged
+    pub fn load_disk(&self, path: &Path) -> Result<Disk> {
+        self.attach_loaded(Disk::load(path)?, path)
+    }
+
+    /// Check a loaded disk against the pool and apply its health policy and key
+    fn attach_loaded(&self, mut disk: Disk, path: &Path) -> Result<Disk> {
+        if disk.encrypted != self.is_encrypted() {
+            return Err(anyhow!(
+                "Disk {} at {:?} is {} but the pool is {}",
+                disk.uuid,
+                path,
+                if disk.encrypted { "encrypted" } else { "not encrypted" },
+                if self.is_encrypted() { "encrypted" } else { "not encrypted" }
+            ));
+        }
+        disk.health_policy = self.health_policy;
+        disk.cipher = self.cipher.clone();
+        Ok(disk)
+    }
     
     /// Save pool metadata
     pub fn save(&self, pool_dir: &Path) -> Result<()> {
         let pool_path = pool_dir.join("pool.json");
         let contents = serde_json::to_string_pretty(self)?;
+        format_version::backup_older_format(&pool_path, POOL_FORMAT_VERSION)?;
         
         let temp_path = pool_path.with_extension("json.tmp");
         fs::write(&temp_path, contents)?;
@@ -574,7 +1597,37 @@ impl DiskPool {
         Ok(())
     }
     
+    /// Whether the pool at `pool_dir` resolves names case-insensitively
+    ///
+    /// Reads only that flag, so it works on an encrypted pool without its key.
+    pub fn is_case_insensitive(pool_dir: &Path) -> bool {
+        fs::read_to_string(pool_dir.join("pool.json"))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+            .and_then(|pool| pool.get("case_insensitive")?.as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Store the pool at `pool_dir` keeps its records in
+    ///
+    /// Reads only that setting, like `is_case_insensitive`. Pools without one use files.
+    pub fn metadata_backend(pool_dir: &Path) -> Result<crate::metadata_store::MetadataBackend> {
+        let contents = match fs::read_to_string(pool_dir.join("pool.json")) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Default::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let pool: serde_json::Value = serde_json::from_str(&contents)?;
+        match pool.get("metadata_backend") {
+            Some(backend) => Ok(serde_json::from_value(backend.clone())?),
+            None => Ok(Default::default()),
+        }
+    }
+    
     /// Load pool metadata
+    ///
+    /// An encrypted pool is unlocked with the key named by `DYNAMICFS_KEY_FILE`
+    /// or `DYNAMICFS_PASSPHRASE_FILE`, if either is set.
     pub fn load(pool_dir: &Path) -> Result<Self> {
         let pool_path = pool_dir.join("pool.json");
         if !pool_path.exists() {
@@ -582,7 +1635,15 @@ impl DiskPool {
         }
         
         let contents = fs::read_to_string(&pool_path)?;
-        let pool: DiskPool = serde_json::from_str(&contents)?;
+        let mut value: serde_json::Value = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {:?}", pool_path))?;
+        format_version::upgrade(&mut value, POOL_FORMAT_VERSION, POOL_UPGRADES, &format!("Pool {:?}", pool_dir))?;
+        let mut pool: DiskPool = serde_json::from_value(value)?;
+        if pool.is_encrypted() {
+            if let Some(source) = PoolKeySource::from_env()? {
+                pool.unlock(&source)?;
+            }
+        }
         Ok(pool)
     }
 }
@@ -8,13 +8,23 @@
 // - Security via TLS and RBAC
 
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{Context, Result, anyhow};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
+use crate::cluster_rpc::{RpcClient, RpcHandler, RpcServer};
+use crate::config::PoolConfig;
+use crate::crash_sim::SeededRng;
+use crate::storage::StorageEngine;
+
 // ============================================================================
 // Phase 13.1: Network RPC & Cluster Membership
 // ============================================================================
@@ -47,6 +57,9 @@ pub enum RpcMessage {
     RaftVoteResponse { term: u64, vote_granted: bool },
     RaftAppendEntries { term: u64, leader_id: u64, prev_log_index: u64, prev_log_term: u64, entries: Vec<LogEntry>, leader_commit: u64 },
     RaftAppendEntriesResponse { term: u64, success: bool, match_index: u64 },
+    
+    /// Reply to a request the node could not serve
+    Error { message: String },
 }
 
 /// Information about a cluster node
@@ -55,6 +68,7 @@ pub struct NodeInfo {
     pub node_id: u64,
     pub addr: SocketAddr,
     pub state: NodeState,
+    /// Milliseconds since the epoch, by the clock of the node that saw it
     pub last_seen: u64,
 }
 
@@ -66,7 +80,51 @@ pub enum NodeState {
     Failed,     // Confirmed failure
 }
 
+/// A node's state changing, including recovering to `Healthy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MembershipEvent {
+    pub node_id: u64,
+    pub from: NodeState,
+    pub to: NodeState,
+}
+
+/// Time that membership decisions are made on
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the epoch
+    fn now_millis(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_millis() as u64
+    }
+}
+
+/// Clock that only moves when advanced, for tests
+#[derive(Default)]
+pub struct ManualClock {
+    millis: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn advance(&self, by: Duration) {
+        self.millis.fetch_add(by.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.millis.load(Ordering::SeqCst)
+    }
+}
+
 /// Cluster membership manager
+///
+/// A peer is suspected once two heartbeat intervals pass without hearing
+/// from it, failed after `failure_timeout`, and healthy again as soon as it
+/// answers.
 pub struct ClusterMembership {
     /// This node's ID
     local_node_id: u64,
@@ -76,15 +134,64 @@ pub struct ClusterMembership {
     heartbeat_interval: Duration,
     /// Failure detection timeout
     failure_timeout: Duration,
+    clock: Arc<dyn Clock>,
+    /// Receivers of state changes; dropped receivers are forgotten
+    listeners: Mutex<Vec<mpsc::Sender<MembershipEvent>>>,
 }
 
 impl ClusterMembership {
     pub fn new(local_node_id: u64) -> Self {
+        Self::from_config(local_node_id, &PoolConfig::default())
+    }
+    
+    /// Membership timed by the `cluster.*` keys of a pool's config
+    pub fn from_config(local_node_id: u64, config: &PoolConfig) -> Self {
+        Self::with_timeouts(
+            local_node_id,
+            Duration::from_secs(config.cluster_heartbeat_secs),
+            Duration::from_secs(config.cluster_failure_timeout_secs),
+        )
+    }
+    
+    /// Membership sending heartbeats every `heartbeat_interval` and failing
+    /// peers silent for longer than `failure_timeout`
+    pub fn with_timeouts(local_node_id: u64, heartbeat_interval: Duration, failure_timeout: Duration) -> Self {
         Self {
             local_node_id,
             nodes: Arc::new(Mutex::new(HashMap::new())),
-            heartbeat_interval: Duration::from_secs(5),
-            failure_timeout: Duration::from_secs(15),
+            heartbeat_interval,
+            failure_timeout,
+            clock: Arc::new(SystemClock),
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+    
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+    
+    /// Receive every state change from now on
+    pub fn subscribe(&self) -> mpsc::Receiver<MembershipEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.listeners.lock().unwrap().push(sender);
+        receiver
+    }
+    
+    fn emit(&self, events: &[MembershipEvent]) {
+        for event in events {
+            match event.to {
+                NodeState::Healthy => log::info!("Node {} is answering heartbeats again", event.node_id),
+                NodeState::Suspected => log::warn!("Node {} missed its heartbeats", event.node_id),
+                NodeState::Failed => log::warn!("Node {} stopped answering heartbeats", event.node_id),
+            }
+        }
+        if !events.is_empty() {
+            self.listeners.lock().unwrap().retain(|listener| events.iter().all(|event| listener.send(*event).is_ok()));
         }
     }
     
@@ -95,36 +202,52 @@ impl ClusterMembership {
             node_id,
             addr,
             state: NodeState::Healthy,
-            last_seen: current_timestamp(),
+            last_seen: self.clock.now_millis(),
         });
     }
     
     /// Update heartbeat for a node
     pub fn heartbeat_received(&self, node_id: u64) {
         let mut nodes = self.nodes.lock().unwrap();
+        let mut recovered = None;
         if let Some(node) = nodes.get_mut(&node_id) {
-            node.last_seen = current_timestamp();
+            node.last_seen = self.clock.now_millis();
+            if node.state != NodeState::Healthy {
+                recovered = Some(MembershipEvent { node_id, from: node.state, to: NodeState::Healthy });
+            }
             node.state = NodeState::Healthy;
         }
+        drop(nodes);
+        self.emit(recovered.as_slice());
     }
     
-    /// Check for failed nodes based on timeout
+    /// Check for failed nodes based on timeout, returning the newly failed
     pub fn detect_failures(&self) -> Vec<u64> {
         let mut nodes = self.nodes.lock().unwrap();
-        let now = current_timestamp();
-        let mut failed = Vec::new();
+        let now = self.clock.now_millis();
+        let suspect_after = self.heartbeat_interval.as_millis() as u64 * 2;
+        let fail_after = self.failure_timeout.as_millis() as u64;
+        let mut events = Vec::new();
         
         for (node_id, node) in nodes.iter_mut() {
-            let elapsed = now - node.last_seen;
-            if elapsed > self.failure_timeout.as_secs() && node.state != NodeState::Failed {
-                node.state = NodeState::Failed;
-                failed.push(*node_id);
-            } else if elapsed > self.heartbeat_interval.as_secs() * 2 && node.state == NodeState::Healthy {
-                node.state = NodeState::Suspected;
+            let elapsed = now.saturating_sub(node.last_seen);
+            let state = if elapsed > fail_after {
+                NodeState::Failed
+            } else if elapsed > suspect_after && node.state == NodeState::Healthy {
+                NodeState::Suspected
+            } else {
+                node.state
+            };
+            if state != node.state {
+                events.push(MembershipEvent { node_id: *node_id, from: node.state, to: state });
+                node.state = state;
             }
         }
+        drop(nodes);
         
-        failed
+        events.sort_by_key(|event| event.node_id);
+        self.emit(&events);
+        events.iter().filter(|event| event.to == NodeState::Failed).map(|event| event.node_id).collect()
     }
     
     /// Get all healthy nodes
@@ -140,6 +263,16 @@ impl ClusterMembership {
     pub fn node_count(&self) -> usize {
         self.nodes.lock().unwrap().len()
     }
+    
+    /// Every known peer, whatever its state
+    pub fn members(&self) -> Vec<NodeInfo> {
+        self.nodes.lock().unwrap().values().cloned().collect()
+    }
+    
+    /// Address of a known peer
+    pub fn addr_of(&self, node_id: u64) -> Option<SocketAddr> {
+        self.nodes.lock().unwrap().get(&node_id).map(|n| n.addr)
+    }
 }
 
 // ============================================================================
@@ -163,7 +296,37 @@ pub enum MetadataOperation {
     MigrateExtent { extent_uuid: Uuid, from_node: u64, to_node: u64 },
 }
 
+/// Applies committed metadata operations, in log order, on every node
+///
+/// Commit progress is not saved, so after a restart entries are applied
+/// again from the first.
+pub trait StateMachine: Send {
+    fn apply(&mut self, entry: &LogEntry) -> Result<()>;
+}
+
+/// File in the Raft directory holding term, vote and log
+const RAFT_STATE_FILE: &str = "raft_state.json";
+
+/// Entries sent in one AppendEntries at most
+const MAX_APPEND_ENTRIES: usize = 64;
+
+/// Base election timeout; each wait is randomized between it and twice it
+pub const DEFAULT_ELECTION_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Interval between a leader's AppendEntries to each follower
+pub const DEFAULT_RAFT_HEARTBEAT: Duration = Duration::from_millis(200);
+
+/// What a node must not forget across a restart
+#[derive(Serialize, Deserialize)]
+struct DurableRaftState {
+    current_term: u64,
+    voted_for: Option<u64>,
+    log: Vec<LogEntry>,
+}
+
 /// Raft consensus state
+///
+/// Log indices start at 1; index 0 stands for the empty log.
 pub struct RaftState {
     /// Current term
     current_term: u64,
@@ -179,6 +342,19 @@ pub struct RaftState {
     role: RaftRole,
     /// Leader node ID (if known)
     leader_id: Option<u64>,
+    /// Per follower, index of the next entry to send (leader only)
+    next_index: HashMap<u64, u64>,
+    /// Per follower, highest entry known to be on it (leader only)
+    match_index: HashMap<u64, u64>,
+    /// When a follower or candidate that has heard from no leader starts an election
+    election_deadline: Instant,
+    election_timeout: Duration,
+    rng: SeededRng,
+    /// Where term, vote and log are saved; `None` keeps them in memory only
+    persist_path: Option<PathBuf>,
+    /// Term, vote or log changed since they were last saved
+    dirty: bool,
+    state_machine: Option<Box<dyn StateMachine>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -190,7 +366,9 @@ pub enum RaftRole {
 
 impl RaftState {
     pub fn new() -> Self {
-        Self {
+        static INSTANCES: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let mut state = Self {
             current_term: 0,
             voted_for: None,
             log: Vec::new(),
@@ -198,7 +376,104 @@ impl RaftState {
             last_applied: 0,
             role: RaftRole::Follower,
             leader_id: None,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            election_deadline: Instant::now(),
+            election_timeout: DEFAULT_ELECTION_TIMEOUT,
+            // Nodes started together must not time out together
+            rng: SeededRng::new(nanos ^ INSTANCES.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+            persist_path: None,
+            dirty: false,
+            state_machine: None,
+        };
+        state.reset_election_timer();
+        state
+    }
+    
+    /// Save term, vote and log in `dir` from now on, first loading what an
+    /// earlier run saved there
+    pub fn open_storage(&mut self, dir: &Path) -> Result<()> {
+        let path = dir.join(RAFT_STATE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let durable: DurableRaftState =
+                    serde_json::from_str(&contents).with_context(|| format!("Invalid Raft state {:?}", path))?;
+                self.current_term = durable.current_term;
+                self.voted_for = durable.voted_for;
+                self.log = durable.log;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                fs::create_dir_all(dir)?;
+                self.dirty = true;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
         }
+        self.persist_path = Some(path);
+        self.persist()
+    }
+    
+    /// Save term, vote and log if they changed; must succeed before a node
+    /// answers anyone on the strength of them
+    ///
+    /// The whole log is rewritten on every save, so each append costs time
+    /// proportional to the length of the log; nothing compacts it yet.
+    pub fn persist(&mut self) -> Result<()> {
+        let Some(path) = self.persist_path.as_ref().filter(|_| self.dirty) else {
+            self.dirty = false;
+            return Ok(());
+        };
+        let durable = DurableRaftState { current_term: self.current_term, voted_for: self.voted_for, log: self.log.clone() };
+        let temp_path = path.with_extension("json.tmp");
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(&serde_json::to_vec(&durable)?)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+    
+    pub fn set_state_machine(&mut self, state_machine: Box<dyn StateMachine>) {
+        self.state_machine = Some(state_machine);
+    }
+    
+    /// Wait at least `timeout`, and up to twice it, before starting an election
+    pub fn set_election_timeout(&mut self, timeout: Duration) {
+        self.election_timeout = timeout;
+        self.reset_election_timer();
+    }
+    
+    fn reset_election_timer(&mut self) {
+        let jitter = self.rng.below(self.election_timeout.as_millis() as u64 + 1);
+        self.election_deadline = Instant::now() + self.election_timeout + Duration::from_millis(jitter);
+    }
+    
+    pub fn role(&self) -> RaftRole {
+        self.role
+    }
+    
+    pub fn current_term(&self) -> u64 {
+        self.current_term
+    }
+    
+    pub fn leader_id(&self) -> Option<u64> {
+        self.leader_id
+    }
+    
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+    
+    pub fn last_applied(&self) -> u64 {
+        self.last_applied
+    }
+    
+    /// Entry at a 1-based index
+    pub fn entry(&self, index: u64) -> Option<&LogEntry> {
+        index.checked_sub(1).and_then(|i| self.log.get(i as usize))
+    }
+    
+    fn term_at(&self, index: u64) -> u64 {
+        self.entry(index).map_or(0, |entry| entry.term)
     }
     
     /// Start an election (become candidate)
@@ -207,12 +482,16 @@ impl RaftState {
         self.role = RaftRole::Candidate;
         self.voted_for = Some(node_id);
         self.leader_id = None;
+        self.dirty = true;
+        self.reset_election_timer();
     }
     
     /// Become leader after winning election
     pub fn become_leader(&mut self, node_id: u64) {
         self.role = RaftRole::Leader;
         self.leader_id = Some(node_id);
+        self.next_index.clear();
+        self.match_index.clear();
     }
     
     /// Step down to follower (e.g., if we see higher term)
@@ -220,18 +499,141 @@ impl RaftState {
         if term > self.current_term {
             self.current_term = term;
             self.voted_for = None;
+            self.leader_id = None;
+            self.dirty = true;
+        }
+        if self.role != RaftRole::Follower {
+            self.reset_election_timer();
         }
         self.role = RaftRole::Follower;
     }
     
+    /// Answer a RaftVoteRequest: `(current term, vote granted)`
+    ///
+    /// The vote goes to the first candidate of a term whose log is at least as
+    /// up to date as this node's.
+    pub fn handle_vote_request(&mut self, term: u64, candidate_id: u64, last_log_index: u64, last_log_term: u64) -> (u64, bool) {
+        if term > self.current_term {
+            self.step_down(term);
+        }
+        let (own_index, own_term) = self.last_log_info();
+        let up_to_date = (last_log_term, last_log_index) >= (own_term, own_index);
+        let granted = term == self.current_term
+            && self.voted_for.is_none_or(|voted| voted == candidate_id)
+            && up_to_date;
+        if granted {
+            self.voted_for = Some(candidate_id);
+            self.dirty = true;
+            self.reset_election_timer();
+        }
+        (self.current_term, granted)
+    }
+    
+    /// Answer a RaftAppendEntries: `(current term, success, last index matching the leader)`
+    ///
+    /// On failure the index is this node's last, so the leader can skip back to it.
+    pub fn handle_append_entries(
+        &mut self,
+        term: u64,
+        leader_id: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+    ) -> (u64, bool, u64) {
+        if term < self.current_term {
+            return (self.current_term, false, self.last_log_info().0);
+        }
+        self.step_down(term);
+        self.leader_id = Some(leader_id);
+        self.reset_election_timer();
+        
+        if prev_log_index > self.log.len() as u64 || self.term_at(prev_log_index) != prev_log_term {
+            let last = self.last_log_info().0.min(prev_log_index.saturating_sub(1));
+            return (self.current_term, false, last);
+        }
+        let last_new = prev_log_index + entries.len() as u64;
+        for entry in entries {
+            match self.entry(entry.index) {
+                Some(existing) if existing.term == entry.term => continue,
+                // A conflicting entry and everything after it came from a deposed leader
+                Some(_) => self.log.truncate(entry.index as usize - 1),
+                None => {}
+            }
+            self.log.push(entry);
+            self.dirty = true;
+        }
+        if leader_commit > self.commit_index {
+            self.commit_index = leader_commit.min(last_new);
+        }
+        (self.current_term, true, last_new)
+    }
+    
+    /// AppendEntries for `follower` holding the entries it has not acknowledged
+    pub fn append_request(&mut self, leader_id: u64, follower: u64) -> RpcMessage {
+        let last = self.last_log_info().0;
+        let next = *self.next_index.entry(follower).or_insert(last + 1);
+        let prev_log_index = next - 1;
+        let end = self.log.len().min(prev_log_index as usize + MAX_APPEND_ENTRIES);
+        RpcMessage::RaftAppendEntries {
+            term: self.current_term,
+            leader_id,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index),
+            entries: self.log[prev_log_index as usize..end].to_vec(),
+            leader_commit: self.commit_index,
+        }
+    }
+    
+    /// Record a follower's answer to `append_request`
+    pub fn append_reply(&mut self, follower: u64, success: bool, match_index: u64) {
+        if success {
+            let matched = self.match_index.entry(follower).or_insert(0);
+            *matched = (*matched).max(match_index);
+            self.next_index.insert(follower, *matched + 1);
+        } else {
+            let next = self.next_index.entry(follower).or_insert(1);
+            *next = (*next - 1).min(match_index + 1).max(1);
+        }
+    }
+    
+    /// Commit the newest entry of this term that a majority of `cluster_size` nodes hold
+    pub fn advance_commit(&mut self, cluster_size: usize) {
+        let last = self.last_log_info().0;
+        for index in (self.commit_index + 1..=last).rev() {
+            if self.term_at(index) != self.current_term {
+                break;
+            }
+            let holders = 1 + self.match_index.values().filter(|&&matched| matched >= index).count();
+            if holders * 2 > cluster_size {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+    
+    /// Hand committed entries not yet applied to the state machine
+    pub fn apply_committed(&mut self) {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let Some(entry) = self.log.get(self.last_applied as usize - 1) else { break };
+            if let Some(state_machine) = self.state_machine.as_mut() {
+                if let Err(e) = state_machine.apply(entry) {
+                    log::error!("Failed to apply Raft entry {}: {:#}", entry.index, e);
+                }
+            }
+        }
+    }
+    
     /// Append a log entry (leader only)
     pub fn append_entry(&mut self, operation: MetadataOperation) -> u64 {
-        let index = self.log.len() as u64;
+        let index = self.log.len() as u64 + 1;
         self.log.push(LogEntry {
             term: self.current_term,
             index,
             operation,
         });
+        self.dirty = true;
         index
     }
     
@@ -310,6 +712,8 @@ pub struct ReplicationManager {
     statuses: Arc<Mutex<HashMap<Uuid, ReplicationStatus>>>,
     /// Default replication factor
     default_replication_factor: usize,
+    /// Extents that lost a replica to a failed node, oldest first
+    repair_queue: Arc<Mutex<VecDeque<Uuid>>>,
 }
 
 impl ReplicationManager {
@@ -317,6 +721,7 @@ impl ReplicationManager {
         Self {
             statuses: Arc::new(Mutex::new(HashMap::new())),
             default_replication_factor,
+            repair_queue: Arc::new(Mutex::new(VecDeque::new())),
         }
     }
     
@@ -338,7 +743,52 @@ impl ReplicationManager {
             if !status.replicas.contains(&node_id) {
                 status.replicas.push(node_id);
             }
+            if status.replicas.len() >= status.target_replicas {
+                self.repair_queue.lock().unwrap().retain(|uuid| uuid != extent_uuid);
+            }
+        }
+    }
+    
+    /// Act on a membership change; only failures matter
+    pub fn handle_membership_event(&self, event: &MembershipEvent) -> Vec<Uuid> {
+        match event.to {
+            NodeState::Failed => self.node_failed(event.node_id),
+            NodeState::Healthy | NodeState::Suspected => Vec::new(),
+        }
+    }
+    
+    /// Stop counting a failed node's replicas and queue the extents left
+    /// under-replicated for re-replication
+    ///
+    /// Returns the newly queued extents. A node that recovers does not get its
+    /// replicas back; they are rebuilt elsewhere.
+    pub fn node_failed(&self, node_id: u64) -> Vec<Uuid> {
+        let mut statuses = self.statuses.lock().unwrap();
+        let mut queue = self.repair_queue.lock().unwrap();
+        let mut queued = Vec::new();
+        for status in statuses.values_mut() {
+            let before = status.replicas.len();
+            status.replicas.retain(|&replica| replica != node_id);
+            if status.replicas.len() < before
+                && status.replicas.len() < status.target_replicas
+                && !queue.contains(&status.extent_uuid)
+            {
+                queued.push(status.extent_uuid);
+            }
         }
+        queued.sort();
+        queue.extend(&queued);
+        queued
+    }
+    
+    /// Take the extent that has waited longest for a new replica
+    pub fn next_repair(&self) -> Option<Uuid> {
+        self.repair_queue.lock().unwrap().pop_front()
+    }
+    
+    /// Extents waiting for a new replica, oldest first
+    pub fn pending_repairs(&self) -> Vec<Uuid> {
+        self.repair_queue.lock().unwrap().iter().copied().collect()
     }
     
     /// Get extents that need more replicas
@@ -487,6 +937,17 @@ pub struct DistributedCluster {
     rebalancing: Arc<RebalancingEngine>,
     /// Audit log
     audit_log: Arc<Mutex<Vec<AuditLogEntry>>>,
+    /// Engine serving GetExtent and PutExtent
+    storage: Option<Arc<StorageEngine>>,
+    /// RPC server, once bootstrapped
+    server: Option<RpcServer>,
+    /// Stops the heartbeat and Raft threads
+    stop: Arc<AtomicBool>,
+    heartbeat: Option<thread::JoinHandle<()>>,
+    raft_thread: Option<thread::JoinHandle<()>>,
+    raft_heartbeat: Duration,
+    /// Where Raft keeps term, vote and log; in memory only if unset
+    raft_dir: Option<PathBuf>,
 }
 
 impl DistributedCluster {
@@ -501,29 +962,167 @@ impl DistributedCluster {
             replication: Arc::new(ReplicationManager::new(3)),  // 3x replication
             rebalancing: Arc::new(RebalancingEngine::new()),
             audit_log: Arc::new(Mutex::new(Vec::new())),
+            storage: None,
+            server: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            heartbeat: None,
+            raft_thread: None,
+            raft_heartbeat: DEFAULT_RAFT_HEARTBEAT,
+            raft_dir: None,
         }
     }
     
-    /// Add a peer node to bootstrap the cluster
+    /// Create a node whose extent requests are served by `storage`
+    pub fn with_storage(node_id: u64, listen_addr: SocketAddr, storage: StorageEngine) -> Self {
+        let mut cluster = Self::new(node_id, listen_addr);
+        cluster.storage = Some(Arc::new(storage));
+        cluster
+    }
+    
+    /// Send heartbeats every `interval` and fail peers silent for `failure_timeout`
+    ///
+    /// Only takes effect before `bootstrap`.
+    pub fn with_heartbeat_timing(mut self, interval: Duration, failure_timeout: Duration) -> Self {
+        self.membership = Arc::new(ClusterMembership::with_timeouts(self.node_id, interval, failure_timeout));
+        self
+    }
+    
+    /// Leaders send AppendEntries every `heartbeat`; followers wait
+    /// `election_timeout` to twice it before standing for election
+    pub fn with_raft_timing(mut self, heartbeat: Duration, election_timeout: Duration) -> Self {
+        self.raft_heartbeat = heartbeat;
+        self.raft.lock().unwrap().set_election_timeout(election_timeout);
+        self
+    }
+    
+    /// Keep Raft's term, vote and log in `dir`, loading them at `bootstrap`
+    pub fn with_raft_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.raft_dir = Some(dir.into());
+        self
+    }
+    
+    /// Apply committed operations to `state_machine`
+    pub fn with_state_machine(self, state_machine: Box<dyn StateMachine>) -> Self {
+        self.raft.lock().unwrap().set_state_machine(state_machine);
+        self
+    }
+    
+    /// Heartbeat timing from the `cluster.*` keys of a pool's config
+    pub fn with_config(mut self, config: &PoolConfig) -> Self {
+        self.membership = Arc::new(ClusterMembership::from_config(self.node_id, config));
+        self
+    }
+    
+    /// Receive every change of a peer's state
+    pub fn subscribe_membership(&self) -> mpsc::Receiver<MembershipEvent> {
+        self.membership.subscribe()
+    }
+    
+    /// Extents queued for re-replication after a node failed
+    pub fn pending_repairs(&self) -> Vec<Uuid> {
+        self.replication.pending_repairs()
+    }
+    
+    /// Address peers reach this node on; the bound port once bootstrapped
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen_addr
+    }
+    
+    /// Join the cluster through the node at `peer_addr`
+    ///
+    /// Performs the JoinCluster exchange: the peer records this node and
+    /// answers with the members it knows, itself included, which are added
+    /// to this node's membership. Needs `bootstrap` first, so peers can
+    /// reach this node.
     pub fn add_peer(&mut self, peer_addr: SocketAddr) -> Result<()> {
-        // Generate a node ID from the address (in practice, would be from handshake)
-        let node_id = peer_addr.port() as u64;
-        self.membership.add_peer(node_id, peer_addr);
+        if self.server.is_none() {
+            return Err(anyhow!("Node {} must be bootstrapped before it joins a cluster", self.node_id));
+        }
+        let mut client = RpcClient::connect(peer_addr, self.node_id)?;
+        if client.peer_node_id() == self.node_id {
+            return Err(anyhow!("Node at {} has this node's ID {}", peer_addr, self.node_id));
+        }
+        let members = match client.call(&RpcMessage::JoinCluster { node_id: self.node_id, addr: self.listen_addr })? {
+            RpcMessage::JoinClusterResponse { members } => members,
+            reply => return Err(anyhow!("Unexpected reply to JoinCluster from {}: {:?}", peer_addr, reply)),
+        };
+        let peer_id = client.peer_node_id();
+        for member in members.iter().filter(|m| m.node_id != self.node_id) {
+            self.membership.add_peer(member.node_id, member.addr);
+            // The other members learn of this node too, so all agree on the cluster's size
+            if member.node_id != peer_id {
+                let join = RpcClient::connect(member.addr, self.node_id)
+                    .and_then(|mut client| client.call(&RpcMessage::JoinCluster { node_id: self.node_id, addr: self.listen_addr }));
+                if let Err(e) = join {
+                    log::warn!("Failed to introduce node {} to node {}: {:#}", self.node_id, member.node_id, e);
+                }
+            }
+        }
+        self.log_audit("join_cluster", &peer_addr.to_string(), true);
         Ok(())
     }
     
-    /// Bootstrap the cluster (start consensus, discovery, etc.)
+    /// Start serving RPC on `listen_addr`, sending heartbeats to peers and
+    /// taking part in Raft elections
+    ///
+    /// A listen port of 0 picks a free port, which `listen_addr` then returns.
     pub fn bootstrap(&mut self) -> Result<()> {
-        // In a real implementation, this would:
-        // 1. Start RPC server
-        // 2. Connect to peers
-        // 3. Start Raft election if needed
-        // 4. Begin heartbeat protocol
+        if self.server.is_some() {
+            return Ok(());
+        }
+        if let Some(dir) = &self.raft_dir {
+            self.raft.lock().unwrap().open_storage(dir)?;
+        }
+        let listener = TcpListener::bind(self.listen_addr)
+            .with_context(|| format!("Failed to bind cluster RPC to {}", self.listen_addr))?;
+        self.listen_addr = listener.local_addr()?;
+        let handler = NodeHandler {
+            node_id: self.node_id,
+            addr: self.listen_addr,
+            membership: Arc::clone(&self.membership),
+            storage: self.storage.clone(),
+            raft: Arc::clone(&self.raft),
+        };
+        self.server = Some(RpcServer::start(listener, Arc::new(handler))?);
+        
+        let events = self.membership.subscribe();
+        let (node_id, membership, stop) = (self.node_id, Arc::clone(&self.membership), Arc::clone(&self.stop));
+        let replication = Arc::clone(&self.replication);
+        self.heartbeat = Some(thread::spawn(move || {
+            send_heartbeats(node_id, &membership, &stop, || {
+                for event in events.try_iter() {
+                    replication.handle_membership_event(&event);
+                }
+            })
+        }));
         
-        // For now, just mark as ready
+        let (raft, membership, stop) = (Arc::clone(&self.raft), Arc::clone(&self.membership), Arc::clone(&self.stop));
+        let raft_heartbeat = self.raft_heartbeat;
+        self.raft_thread = Some(thread::spawn(move || run_raft(node_id, &raft, &membership, &stop, raft_heartbeat)));
         Ok(())
     }
     
+    /// Store an extent on a peer, for it to place on its own disks
+    pub fn put_remote_extent(&self, node_id: u64, extent_uuid: Uuid, data: Vec<u8>) -> Result<()> {
+        match self.call_peer(node_id, &RpcMessage::PutExtent { extent_uuid, data })? {
+            RpcMessage::PutExtentResponse { success: true } => Ok(()),
+            reply => Err(anyhow!("Node {} did not store extent {}: {:?}", node_id, extent_uuid, reply)),
+        }
+    }
+    
+    /// Decoded data of an extent stored on a peer
+    pub fn get_remote_extent(&self, node_id: u64, extent_uuid: Uuid) -> Result<Vec<u8>> {
+        match self.call_peer(node_id, &RpcMessage::GetExtent { extent_uuid })? {
+            RpcMessage::GetExtentResponse { data } => Ok(data),
+            reply => Err(anyhow!("Unexpected reply to GetExtent from node {}: {:?}", node_id, reply)),
+        }
+    }
+    
+    fn call_peer(&self, node_id: u64, request: &RpcMessage) -> Result<RpcMessage> {
+        let addr = self.membership.addr_of(node_id).ok_or_else(|| anyhow!("Node {} is not a cluster member", node_id))?;
+        RpcClient::connect(addr, self.node_id)?.call(request)
+    }
+    
     /// Replicate an extent across multiple nodes
     pub fn replicate_extent(&mut self, extent_uuid: Uuid, data: &[u8], replica_count: usize) -> Result<()> {
         // Register for replication
@@ -600,7 +1199,9 @@ impl DistributedCluster {
             return Err(anyhow!("Not the leader"));
         }
         
+        // Replicated to the followers with the next AppendEntries
         let index = raft.append_entry(operation);
+        raft.persist()?;
         Ok(index)
     }
     
@@ -610,12 +1211,20 @@ impl DistributedCluster {
         log_index <= raft.commit_index
     }
     
+    /// Raft role, term and last applied index of this node
+    pub fn raft_progress(&self) -> (RaftRole, u64, u64) {
+        let raft = self.raft.lock().unwrap();
+        (raft.role, raft.current_term, raft.last_applied)
+    }
+    
     /// Start a Raft election
+    ///
+    /// Once bootstrapped, nodes stand for election on their own when they
+    /// hear from no leader; this only turns the node into a candidate.
     pub fn start_election(&mut self) -> Result<()> {
         let mut raft = self.raft.lock().unwrap();
         raft.start_election(self.node_id);
-        
-        // In real implementation: send vote requests to all peers
+        raft.persist()?;
         self.log_audit("start_election", &format!("term {}", raft.current_term), true);
         
         Ok(())
@@ -647,6 +1256,236 @@ impl DistributedCluster {
     }
 }
 
+impl Drop for DistributedCluster {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in [self.heartbeat.take(), self.raft_thread.take()].into_iter().flatten() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Serves the RPC requests of one node
+struct NodeHandler {
+    node_id: u64,
+    addr: SocketAddr,
+    membership: Arc<ClusterMembership>,
+    storage: Option<Arc<StorageEngine>>,
+    raft: Arc<Mutex<RaftState>>,
+}
+
+impl NodeHandler {
+    fn storage(&self) -> Result<&StorageEngine> {
+        self.storage.as_deref().ok_or_else(|| anyhow!("node {} has no local storage", self.node_id))
+    }
+}
+
+impl RpcHandler for NodeHandler {
+    fn node_id(&self) -> u64 {
+        self.node_id
+    }
+    
+    fn handle(&self, request: RpcMessage) -> RpcMessage {
+        let reply = match request {
+            RpcMessage::GetExtent { extent_uuid } => self
+                .storage()
+                .and_then(|storage| Ok(storage.read_extent(extent_uuid)?))
+                .map(|data| RpcMessage::GetExtentResponse { data }),
+            RpcMessage::PutExtent { extent_uuid, data } => self
+                .storage()
+                .and_then(|storage| Ok(storage.put_extent(extent_uuid, &data)?))
+                .map(|()| RpcMessage::PutExtentResponse { success: true }),
+            RpcMessage::Heartbeat { node_id, .. } => {
+                self.membership.heartbeat_received(node_id);
+                Ok(RpcMessage::HeartbeatAck { node_id: self.node_id })
+            }
+            RpcMessage::JoinCluster { node_id, addr } => {
+                log::info!("Node {} at {} joined through node {}", node_id, addr, self.node_id);
+                self.membership.add_peer(node_id, addr);
+                let mut members: Vec<NodeInfo> = self.membership.members().into_iter().filter(|m| m.node_id != node_id).collect();
+                let last_seen = self.membership.clock.now_millis();
+                members.push(NodeInfo { node_id: self.node_id, addr: self.addr, state: NodeState::Healthy, last_seen });
+                Ok(RpcMessage::JoinClusterResponse { members })
+            }
+            RpcMessage::RaftVoteRequest { term, candidate_id, last_log_index, last_log_term } => {
+                let mut raft = self.raft.lock().unwrap();
+                let (term, vote_granted) = raft.handle_vote_request(term, candidate_id, last_log_index, last_log_term);
+                raft.persist().map(|()| RpcMessage::RaftVoteResponse { term, vote_granted })
+            }
+            RpcMessage::RaftAppendEntries { term, leader_id, prev_log_index, prev_log_term, entries, leader_commit } => {
+                let mut raft = self.raft.lock().unwrap();
+                let (term, success, match_index) =
+                    raft.handle_append_entries(term, leader_id, prev_log_index, prev_log_term, entries, leader_commit);
+                let persisted = raft.persist();
+                if persisted.is_ok() {
+                    raft.apply_committed();
+                }
+                persisted.map(|()| RpcMessage::RaftAppendEntriesResponse { term, success, match_index })
+            }
+            other => Err(anyhow!("{:?} is not served over RPC yet", std::mem::discriminant(&other))),
+        };
+        reply.unwrap_or_else(|e| RpcMessage::Error { message: format!("{:#}", e) })
+    }
+}
+
+/// Heartbeat every known peer each interval until `stop`, then check for
+/// failures and call `after_round`
+///
+/// Connections are kept between rounds and reopened after an error.
+fn send_heartbeats(node_id: u64, membership: &ClusterMembership, stop: &AtomicBool, after_round: impl Fn()) {
+    let mut clients: HashMap<u64, RpcClient> = HashMap::new();
+    while !stop.load(Ordering::SeqCst) {
+        let beat = RpcMessage::Heartbeat { node_id, timestamp: current_timestamp() };
+        let requests = membership.members().into_iter().map(|peer| (peer.node_id, peer.addr, beat.clone())).collect();
+        for (peer, reply) in call_peers(node_id, &mut clients, requests) {
+            match reply {
+                RpcMessage::HeartbeatAck { .. } => membership.heartbeat_received(peer),
+                reply => log::warn!("Node {} answered a heartbeat with {:?}", peer, reply),
+            }
+        }
+        membership.detect_failures();
+        after_round();
+        
+        let deadline = std::time::Instant::now() + membership.heartbeat_interval();
+        while !stop.load(Ordering::SeqCst) && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// Send each request to its peer at once and collect the replies that came back
+///
+/// Connections are kept in `clients` between calls and reopened after an error.
+fn call_peers(
+    node_id: u64,
+    clients: &mut HashMap<u64, RpcClient>,
+    requests: Vec<(u64, SocketAddr, RpcMessage)>,
+) -> Vec<(u64, RpcMessage)> {
+    let calls: Vec<_> = requests.into_iter().map(|(peer, addr, request)| (peer, addr, request, clients.remove(&peer))).collect();
+    let results: Vec<_> = thread::scope(|scope| {
+        let calls: Vec<_> = calls
+            .into_iter()
+            .map(|(peer, addr, request, client)| {
+                scope.spawn(move || {
+                    let client = match client {
+                        Some(client) => Ok(client),
+                        None => RpcClient::connect(addr, node_id),
+                    };
+                    (peer, client.and_then(|mut client| Ok((client.call(&request)?, client))))
+                })
+            })
+            .collect();
+        calls.into_iter().map(|call| call.join().unwrap()).collect()
+    });
+    let mut replies = Vec::new();
+    for (peer, result) in results {
+        match result {
+            Ok((reply, client)) => {
+                clients.insert(peer, client);
+                replies.push((peer, reply));
+            }
+            Err(e) => log::debug!("RPC to node {} failed: {:#}", peer, e),
+        }
+    }
+    replies
+}
+
+/// Drive this node's part in Raft until `stop`
+///
+/// Followers and candidates stand for election once their randomized timeout
+/// passes without word from a leader; leaders send AppendEntries to every
+/// peer each `heartbeat`, empty when the follower is up to date. A node that
+/// knows no peers waits to join a cluster rather than electing itself.
+fn run_raft(node_id: u64, raft: &Mutex<RaftState>, membership: &ClusterMembership, stop: &AtomicBool, heartbeat: Duration) {
+    let mut clients: HashMap<u64, RpcClient> = HashMap::new();
+    let mut next_heartbeat = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let (role, election_deadline) = {
+            let raft = raft.lock().unwrap();
+            (raft.role, raft.election_deadline)
+        };
+        let peers = membership.members();
+        let now = Instant::now();
+        if role == RaftRole::Leader && now >= next_heartbeat {
+            replicate_to_followers(node_id, raft, &peers, &mut clients);
+            next_heartbeat = now + heartbeat;
+        } else if role != RaftRole::Leader && now >= election_deadline && !peers.is_empty() {
+            run_election(node_id, raft, &peers, &mut clients);
+            next_heartbeat = Instant::now();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn run_election(node_id: u64, raft: &Mutex<RaftState>, peers: &[NodeInfo], clients: &mut HashMap<u64, RpcClient>) {
+    let request = {
+        let mut raft = raft.lock().unwrap();
+        raft.start_election(node_id);
+        if let Err(e) = raft.persist() {
+            log::error!("Node {} cannot stand for election, its vote was not saved: {:#}", node_id, e);
+            return;
+        }
+        let (last_log_index, last_log_term) = raft.last_log_info();
+        RpcMessage::RaftVoteRequest { term: raft.current_term, candidate_id: node_id, last_log_index, last_log_term }
+    };
+    let RpcMessage::RaftVoteRequest { term, .. } = request else { unreachable!() };
+    let requests = peers.iter().map(|peer| (peer.node_id, peer.addr, request.clone())).collect();
+    let replies = call_peers(node_id, clients, requests);
+    
+    let mut raft = raft.lock().unwrap();
+    if raft.role != RaftRole::Candidate || raft.current_term != term {
+        return;
+    }
+    let mut votes = 1;
+    for (_, reply) in replies {
+        match reply {
+            RpcMessage::RaftVoteResponse { term: reply_term, .. } if reply_term > term => {
+                raft.step_down(reply_term);
+                if let Err(e) = raft.persist() {
+                    log::error!("Node {} stepped down to term {} but could not save it: {:#}", node_id, reply_term, e);
+                }
+                return;
+            }
+            RpcMessage::RaftVoteResponse { vote_granted: true, .. } => votes += 1,
+            _ => {}
+        }
+    }
+    if votes * 2 > peers.len() + 1 {
+        log::info!("Node {} won the election for term {} with {} votes", node_id, term, votes);
+        raft.become_leader(node_id);
+    }
+}
+
+fn replicate_to_followers(node_id: u64, raft: &Mutex<RaftState>, peers: &[NodeInfo], clients: &mut HashMap<u64, RpcClient>) {
+    let (term, requests) = {
+        let mut raft = raft.lock().unwrap();
+        let requests = peers.iter().map(|peer| (peer.node_id, peer.addr, raft.append_request(node_id, peer.node_id))).collect();
+        (raft.current_term, requests)
+    };
+    let replies = call_peers(node_id, clients, requests);
+    
+    let mut raft = raft.lock().unwrap();
+    if raft.role != RaftRole::Leader || raft.current_term != term {
+        return;
+    }
+    for (peer, reply) in replies {
+        match reply {
+            RpcMessage::RaftAppendEntriesResponse { term: reply_term, .. } if reply_term > term => {
+                log::info!("Node {} steps down, node {} is in term {}", node_id, peer, reply_term);
+                raft.step_down(reply_term);
+                if let Err(e) = raft.persist() {
+                    log::error!("Node {} stepped down to term {} but could not save it: {:#}", node_id, reply_term, e);
+                }
+                return;
+            }
+            RpcMessage::RaftAppendEntriesResponse { success, match_index, .. } => raft.append_reply(peer, success, match_index),
+            _ => {}
+        }
+    }
+    raft.advance_commit(peers.len() + 1);
+    raft.apply_committed();
+}
+
 /// Cluster status information
 #[derive(Debug, Clone)]
 pub struct ClusterStatus {
@@ -733,12 +1572,125 @@ mod tests {
             size: 1024,
         });
         
-        assert_eq!(index, 0);
+        assert_eq!(index, 1);
         assert_eq!(raft.log.len(), 1);
+        assert_eq!(raft.last_log_info(), (1, 0));
         
         // Update commit index
-        raft.update_commit_index(0);
-        assert_eq!(raft.commit_index, 0);
+        raft.update_commit_index(1);
+        assert_eq!(raft.commit_index, 1);
+    }
+    
+    fn create_extent() -> MetadataOperation {
+        MetadataOperation::CreateExtent { extent_uuid: Uuid::new_v4(), size: 1024 }
+    }
+    
+    #[test]
+    fn test_raft_votes_once_per_term_for_candidates_with_current_logs() {
+        let mut raft = RaftState::new();
+        raft.step_down(2);
+        raft.append_entry(create_extent());
+        
+        // Stale term, or a log missing this node's entry
+        assert_eq!(raft.handle_vote_request(1, 2, 5, 1), (2, false));
+        assert_eq!(raft.handle_vote_request(3, 2, 0, 0), (3, false));
+        assert_eq!(raft.voted_for, None);
+        
+        assert_eq!(raft.handle_vote_request(3, 3, 1, 2), (3, true));
+        assert_eq!(raft.handle_vote_request(3, 3, 1, 2), (3, true));
+        assert_eq!(raft.handle_vote_request(3, 4, 9, 3), (3, false));
+        
+        // A candidate of a later term also unseats a leader
+        raft.become_leader(1);
+        assert_eq!(raft.handle_vote_request(4, 4, 1, 2), (4, true));
+        assert_eq!(raft.role, RaftRole::Follower);
+    }
+    
+    #[test]
+    fn test_raft_append_entries_repairs_a_diverged_follower_and_commits_on_majority() {
+        let mut leader = RaftState::new();
+        leader.step_down(1);
+        leader.become_leader(1);
+        for _ in 0..3 {
+            leader.append_entry(create_extent());
+        }
+        
+        // The follower kept an entry of term 1 that this leader replaced in term 2
+        let mut follower = RaftState::new();
+        follower.handle_append_entries(1, 1, 0, 0, leader.log[..1].to_vec(), 0);
+        let mut stale = leader.log[1].clone();
+        stale.operation = create_extent();
+        follower.log.push(stale);
+        leader.step_down(2);
+        leader.become_leader(1);
+        for entry in &mut leader.log[1..] {
+            entry.term = 2;
+        }
+        
+        // The leader walks back until the logs agree, then sends the rest
+        let mut rounds = 0;
+        while leader.match_index.get(&2).copied().unwrap_or(0) < 3 {
+            let RpcMessage::RaftAppendEntries { term, leader_id, prev_log_index, prev_log_term, entries, leader_commit } =
+                leader.append_request(1, 2)
+            else {
+                unreachable!()
+            };
+            let (_, success, matched) =
+                follower.handle_append_entries(term, leader_id, prev_log_index, prev_log_term, entries, leader_commit);
+            leader.append_reply(2, success, matched);
+            rounds += 1;
+            assert!(rounds < 5);
+        }
+        assert_eq!(follower.log.len(), 3);
+        assert!(follower.log.iter().zip(&leader.log).all(|(a, b)| a.term == b.term && a.index == b.index));
+        
+        // Two of three nodes hold everything; the commit reaches the follower with the next append
+        leader.advance_commit(4);
+        assert_eq!(leader.commit_index, 0);
+        leader.advance_commit(3);
+        assert_eq!(leader.commit_index, 3);
+        let RpcMessage::RaftAppendEntries { prev_log_index, prev_log_term, leader_commit, .. } = leader.append_request(1, 2) else {
+            unreachable!()
+        };
+        follower.handle_append_entries(2, 1, prev_log_index, prev_log_term, Vec::new(), leader_commit);
+        assert_eq!(follower.commit_index, 3);
+        
+        // A leader of an older term is refused
+        assert!(!follower.handle_append_entries(1, 3, 3, 2, Vec::new(), 3).1);
+    }
+    
+    #[test]
+    fn test_raft_term_vote_and_log_survive_a_restart() {
+        struct Applied(Arc<Mutex<Vec<u64>>>);
+        impl StateMachine for Applied {
+            fn apply(&mut self, entry: &LogEntry) -> Result<()> {
+                self.0.lock().unwrap().push(entry.index);
+                Ok(())
+            }
+        }
+        
+        let dir = tempfile::tempdir().unwrap();
+        let mut raft = RaftState::new();
+        raft.open_storage(dir.path()).unwrap();
+        raft.start_election(1);
+        raft.become_leader(1);
+        raft.append_entry(create_extent());
+        raft.append_entry(create_extent());
+        raft.persist().unwrap();
+        
+        let applied = Arc::new(Mutex::new(Vec::new()));
+        let mut restarted = RaftState::new();
+        restarted.set_state_machine(Box::new(Applied(applied.clone())));
+        restarted.open_storage(dir.path()).unwrap();
+        assert_eq!((restarted.current_term, restarted.voted_for), (1, Some(1)));
+        assert_eq!(restarted.last_log_info(), (2, 1));
+        assert_eq!(restarted.role, RaftRole::Follower);
+        
+        // Having voted in term 1, it does not vote for anyone else in it
+        assert_eq!(restarted.handle_vote_request(1, 2, 2, 1), (1, false));
+        restarted.update_commit_index(2);
+        restarted.apply_committed();
+        assert_eq!(*applied.lock().unwrap(), vec![1, 2]);
     }
     
     #[test]
@@ -800,6 +1752,66 @@ mod tests {
         assert!(healthy.len() >= 2); // Initially all healthy
     }
     
+    #[test]
+    fn test_silent_peer_moves_through_suspected_to_failed_and_recovers() {
+        let clock = Arc::new(ManualClock::default());
+        let membership = ClusterMembership::with_timeouts(1, Duration::from_secs(1), Duration::from_secs(5))
+            .with_clock(clock.clone());
+        membership.add_peer(2, "127.0.0.1:5001".parse().unwrap());
+        membership.add_peer(3, "127.0.0.1:5002".parse().unwrap());
+        let events = membership.subscribe();
+        let transition = |node_id, from, to| MembershipEvent { node_id, from, to };
+        
+        // Node 2 stops acking; node 3 keeps answering every interval
+        let mut seen = Vec::new();
+        for second in 1..=6 {
+            clock.advance(Duration::from_secs(1));
+            membership.heartbeat_received(3);
+            let failed = membership.detect_failures();
+            seen.extend(events.try_iter().map(|event| (second, event)));
+            assert_eq!(failed, if second == 6 { vec![2] } else { vec![] }, "at {}s", second);
+        }
+        assert_eq!(seen, vec![
+            (3, transition(2, NodeState::Healthy, NodeState::Suspected)),
+            (6, transition(2, NodeState::Suspected, NodeState::Failed)),
+        ]);
+        assert_eq!(membership.healthy_nodes().len(), 1);
+        
+        membership.heartbeat_received(2);
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![transition(2, NodeState::Failed, NodeState::Healthy)]);
+        
+        // A peer silent past the failure timeout between two checks skips suspicion
+        clock.advance(Duration::from_secs(10));
+        membership.heartbeat_received(3);
+        assert_eq!(membership.detect_failures(), vec![2]);
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![transition(2, NodeState::Healthy, NodeState::Failed)]);
+    }
+    
+    #[test]
+    fn test_failed_node_queues_its_under_replicated_extents() {
+        let replication = ReplicationManager::new(2);
+        let (on_1_and_2, on_1_and_3, only_on_2) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        replication.register_extent(on_1_and_2, 1);
+        replication.add_replica(&on_1_and_2, 2);
+        replication.register_extent(on_1_and_3, 1);
+        replication.add_replica(&on_1_and_3, 3);
+        replication.register_extent(only_on_2, 2);
+        
+        let event = |to| MembershipEvent { node_id: 2, from: NodeState::Healthy, to };
+        assert!(replication.handle_membership_event(&event(NodeState::Suspected)).is_empty());
+        let mut expected = vec![on_1_and_2, only_on_2];
+        expected.sort();
+        assert_eq!(replication.handle_membership_event(&event(NodeState::Failed)), expected);
+        assert_eq!(replication.pending_repairs(), expected);
+        assert!(replication.handle_membership_event(&event(NodeState::Failed)).is_empty());
+        
+        // A new replica takes the extent off the queue
+        replication.add_replica(&on_1_and_2, 3);
+        assert_eq!(replication.pending_repairs(), vec![only_on_2]);
+        assert_eq!(replication.next_repair(), Some(only_on_2));
+        assert_eq!(replication.next_repair(), None);
+    }
+    
     #[test]
     fn test_security_auth() {
         let admin_ctx = SecurityContext {
@@ -0,0 +1,289 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Environment variable naming the key file of an encrypted pool
+pub const KEY_FILE_ENV: &str = "DYNAMICFS_KEY_FILE";
+/// Environment variable naming a file holding the passphrase of an encrypted pool
+pub const PASSPHRASE_FILE_ENV: &str = "DYNAMICFS_PASSPHRASE_FILE";
+
+/// Leading bytes of every encrypted fragment
+const FRAGMENT_MAGIC: &[u8; 8] = b"DFSENC01";
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Context strings for the keys derived from the pool master key
+const FRAGMENT_KEY_CONTEXT: &str = "dynamicfs fragment encryption v1";
+const KEY_CHECK_CONTEXT: &[u8] = b"dynamicfs pool key check v1";
+
+/// Where the master key of an encrypted pool comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolKeySource {
+    /// 32 raw bytes, or 64 hex digits, in a file
+    KeyFile(PathBuf),
+    /// A passphrase stretched with argon2id
+    Passphrase(String),
+}
+
+impl PoolKeySource {
+    /// The key source named by `DYNAMICFS_KEY_FILE` or `DYNAMICFS_PASSPHRASE_FILE`
+    pub fn from_env() -> Result<Option<Self>> {
+        if let Some(path) = std::env::var_os(KEY_FILE_ENV) {
+            return Ok(Some(PoolKeySource::KeyFile(PathBuf::from(path))));
+        }
+        if let Some(path) = std::env::var_os(PASSPHRASE_FILE_ENV) {
+            let passphrase = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read passphrase file {:?}", path))?;
+            let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+            if passphrase.is_empty() {
+                bail!("Passphrase file {:?} is empty", path);
+            }
+            return Ok(Some(PoolKeySource::Passphrase(passphrase.to_string())));
+        }
+        Ok(None)
+    }
+}
+
+/// How the master key is derived, as recorded in pool.json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum KeyDerivation {
+    KeyFile,
+    Argon2id { salt: Vec<u8>, m_cost: u32, t_cost: u32, p_cost: u32 },
+}
+
+/// Encryption settings of a pool, fixed when the pool is initialized
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Always "xchacha20-poly1305"
+    pub cipher: String,
+    pub key_derivation: KeyDerivation,
+    /// Keyed BLAKE3 of a fixed string under the master key, to reject a wrong key
+    pub key_check: String,
+}
+
+impl EncryptionConfig {
+    /// Set up encryption for a new pool
+    ///
+    /// A key file that does not exist yet is created with a random key.
+    pub fn create(source: &PoolKeySource) -> Result<(Self, FragmentCipher)> {
+        let key_derivation = match source {
+            PoolKeySource::KeyFile(path) => {
+                if !path.exists() {
+                    generate_key_file(path)?;
+                }
+                KeyDerivation::KeyFile
+            }
+            PoolKeySource::Passphrase(_) => {
+                let mut salt = vec![0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                let params = Params::default();
+                KeyDerivation::Argon2id {
+                    salt,
+                    m_cost: params.m_cost(),
+                    t_cost: params.t_cost(),
+                    p_cost: params.p_cost(),
+                }
+            }
+        };
+
+        let master_key = derive_master_key(&key_derivation, source)?;
+        let config = EncryptionConfig {
+            cipher: "xchacha20-poly1305".to_string(),
+            key_derivation,
+            key_check: key_check(&master_key).to_hex().to_string(),
+        };
+        Ok((config, FragmentCipher::new(&master_key)))
+    }
+
+    /// Derive the pool key from `source`, failing if it is not the pool's key
+    pub fn unlock(&self, source: &PoolKeySource) -> Result<FragmentCipher> {
+        if self.cipher != "xchacha20-poly1305" {
+            bail!("Unsupported pool cipher: {}", self.cipher);
+        }
+        let master_key = derive_master_key(&self.key_derivation, source)?;
+        let expected = blake3::Hash::from_hex(&self.key_check).context("Corrupt key check in pool config")?;
+        if key_check(&master_key) != expected {
+            bail!("Wrong key for encrypted pool");
+        }
+        Ok(FragmentCipher::new(&master_key))
+    }
+}
+
+fn key_check(master_key: &[u8; KEY_LEN]) -> blake3::Hash {
+    blake3::keyed_hash(master_key, KEY_CHECK_CONTEXT)
+}
+
+fn derive_master_key(derivation: &KeyDerivation, source: &PoolKeySource) -> Result<[u8; KEY_LEN]> {
+    match (derivation, source) {
+        (KeyDerivation::KeyFile, PoolKeySource::KeyFile(path)) => read_key_file(path),
+        (KeyDerivation::Argon2id { salt, m_cost, t_cost, p_cost }, PoolKeySource::Passphrase(passphrase)) => {
+            let params = Params::new(*m_cost, *t_cost, *p_cost, Some(KEY_LEN))
+                .map_err(|e| anyhow!("Invalid argon2 parameters in pool config: {}", e))?;
+            let mut key = [0u8; KEY_LEN];
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                .map_err(|e| anyhow!("Failed to derive key from passphrase: {}", e))?;
+            Ok(key)
+        }
+        (KeyDerivation::KeyFile, PoolKeySource::Passphrase(_)) => {
+            bail!("Pool is encrypted with a key file; pass --key-file")
+        }
+        (KeyDerivation::Argon2id { .. }, PoolKeySource::KeyFile(_)) => {
+            bail!("Pool is encrypted with a passphrase; pass --passphrase-file")
+        }
+    }
+}
+
+/// A 32-byte key from a file holding it raw or as 64 hex digits
+pub fn read_key_file(path: &Path) -> Result<[u8; KEY_LEN]> {
+    let contents = fs::read(path).with_context(|| format!("Failed to read key file {:?}", path))?;
+    if let Ok(key) = <[u8; KEY_LEN]>::try_from(contents.as_slice()) {
+        return Ok(key);
+    }
+    let text = std::str::from_utf8(&contents).unwrap_or("").trim();
+    if text.len() == KEY_LEN * 2 {
+        if let Ok(hash) = blake3::Hash::from_hex(text) {
+            return Ok(*hash.as_bytes());
+        }
+    }
+    bail!("Key file {:?} must hold 32 raw bytes or 64 hex digits", path)
+}
+
+/// Write a new random key to `path`, readable by the owner only
+pub fn generate_key_file(path: &Path) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to create key file {:?}", path))?;
+    file.write_all(&key)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Seals and opens fragment payloads with XChaCha20-Poly1305
+///
+/// Each fragment gets a random 24-byte nonce, stored after a magic header:
+/// `DFSENC01 | nonce | ciphertext | tag`. The extent UUID and fragment index
+/// are bound in as associated data, so a fragment copied over another fails
+/// to open.
+pub struct FragmentCipher {
+    aead: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for FragmentCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FragmentCipher(..)")
+    }
+}
+
+impl FragmentCipher {
+    fn new(master_key: &[u8; KEY_LEN]) -> Self {
+        let key = blake3::derive_key(FRAGMENT_KEY_CONTEXT, master_key);
+        FragmentCipher { aead: XChaCha20Poly1305::new(&key.into()) }
+    }
+
+    /// Bytes an encrypted fragment takes beyond its plaintext
+    pub const OVERHEAD: usize = FRAGMENT_MAGIC.len() + NONCE_LEN + TAG_LEN;
+
+    pub fn seal(&self, extent_uuid: &Uuid, fragment_index: usize, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = Self::associated_data(extent_uuid, fragment_index);
+        let ciphertext = self
+            .aead
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| anyhow!("Failed to encrypt fragment {}-{}", extent_uuid, fragment_index))?;
+
+        let mut sealed = Vec::with_capacity(Self::OVERHEAD + plaintext.len());
+        sealed.extend_from_slice(FRAGMENT_MAGIC);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    pub fn open(&self, extent_uuid: &Uuid, fragment_index: usize, sealed: &[u8]) -> Result<Vec<u8>> {
+        if !sealed.starts_with(FRAGMENT_MAGIC) {
+            bail!(
+                "Fragment {}-{} is not encrypted; an encrypted pool cannot hold plaintext fragments",
+                extent_uuid,
+                fragment_index
+            );
+        }
+        if sealed.len() < Self::OVERHEAD {
+            bail!("Encrypted fragment {}-{} is truncated", extent_uuid, fragment_index);
+        }
+        let (nonce, ciphertext) = sealed[FRAGMENT_MAGIC.len()..].split_at(NONCE_LEN);
+        let aad = Self::associated_data(extent_uuid, fragment_index);
+        self.aead
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| anyhow!("Encrypted fragment {}-{} failed authentication", extent_uuid, fragment_index))
+    }
+
+    fn associated_data(extent_uuid: &Uuid, fragment_index: usize) -> Vec<u8> {
+        let mut aad = extent_uuid.as_bytes().to_vec();
+        aad.extend_from_slice(&(fragment_index as u64).to_le_bytes());
+        aad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragment_cipher_round_trip_and_binding() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("pool.key");
+        let (config, cipher) = EncryptionConfig::create(&PoolKeySource::KeyFile(key_file.clone())).unwrap();
+        assert_eq!(fs::read(&key_file).unwrap().len(), 32);
+
+        let extent = Uuid::new_v4();
+        let sealed = cipher.seal(&extent, 2, b"fragment payload").unwrap();
+        assert_eq!(sealed.len(), b"fragment payload".len() + FragmentCipher::OVERHEAD);
+        assert!(!sealed.windows(7).any(|w| w == b"payload"));
+        assert_eq!(cipher.open(&extent, 2, &sealed).unwrap(), b"fragment payload");
+
+        // Swapped fragments, tampered bytes and plaintext are all rejected
+        assert!(cipher.open(&extent, 1, &sealed).is_err());
+        assert!(cipher.open(&Uuid::new_v4(), 2, &sealed).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(cipher.open(&extent, 2, &tampered).is_err());
+        let err = cipher.open(&extent, 2, b"fragment payload").unwrap_err();
+        assert!(err.to_string().contains("not encrypted"), "{}", err);
+
+        // The same key file unlocks the pool; another key, or a passphrase, does not
+        let reopened = config.unlock(&PoolKeySource::KeyFile(key_file)).unwrap();
+        assert_eq!(reopened.open(&extent, 2, &sealed).unwrap(), b"fragment payload");
+        let other_key = dir.path().join("other.key");
+        fs::write(&other_key, blake3::hash(b"other").to_hex().as_bytes()).unwrap();
+        let err = config.unlock(&PoolKeySource::KeyFile(other_key)).unwrap_err();
+        assert_eq!(err.to_string(), "Wrong key for encrypted pool");
+        assert!(config.unlock(&PoolKeySource::Passphrase("secret".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_passphrase_key_derivation() {
+        let (config, cipher) = EncryptionConfig::create(&PoolKeySource::Passphrase("correct horse".to_string())).unwrap();
+        assert!(matches!(config.key_derivation, KeyDerivation::Argon2id { .. }));
+        let sealed = cipher.seal(&Uuid::nil(), 0, b"data").unwrap();
+
+        let reopened = config.unlock(&PoolKeySource::Passphrase("correct horse".to_string())).unwrap();
+        assert_eq!(reopened.open(&Uuid::nil(), 0, &sealed).unwrap(), b"data");
+        assert!(config.unlock(&PoolKeySource::Passphrase("battery staple".to_string())).is_err());
+    }
+}
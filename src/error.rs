@@ -0,0 +1,188 @@
+//! Errors of the storage layer
+//!
+//! `MetadataManager`, `Disk` and `StorageEngine` return `StorageError`, so
+//! callers can tell a full pool from corrupt data from a missing file without
+//! matching on messages. Failures without a class of their own are kept as
+//! `Io` or `Other`. Commands still work in `anyhow`: a `StorageError` turns
+//! into an `anyhow::Error` with `?`, and back with `From`, keeping its variant
+//! even under added context.
+
+use uuid::Uuid;
+
+pub type StorageResult<T> = std::result::Result<T, StorageError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    /// An inode, extent or path that does not exist
+    #[error("{0}")]
+    NotFound(String),
+    /// The disks cannot take the write under its redundancy policy, or the pool is past a space watermark
+    #[error("{0}")]
+    NoSpace(String),
+    #[error("{0}")]
+    QuotaExceeded(String),
+    /// The pool was opened or mounted read-only
+    #[error("{0}")]
+    ReadOnly(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    /// Data that fails its checksum
+    #[error("{detail}")]
+    Corruption { extent: Uuid, detail: String },
+    /// An extent with too few fragments left to decode
+    #[error("{detail}")]
+    Unrecoverable { extent: Uuid, detail: String },
+    /// A disk that is failed, missing or locked
+    #[error("{detail}")]
+    DiskUnavailable { disk: Uuid, detail: String },
+    /// An operation the disk kind, file type or pool does not support
+    #[error("{0}")]
+    Unsupported(String),
+    /// An operation stopped before it finished, such as a cancelled policy change
+    #[error("{0}")]
+    Interrupted(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl StorageError {
+    /// The same error with `message` in place of its own, keeping its variant
+    fn with_message(&self, message: String) -> Option<Self> {
+        Some(match self {
+            StorageError::NotFound(_) => StorageError::NotFound(message),
+            StorageError::NoSpace(_) => StorageError::NoSpace(message),
+            StorageError::QuotaExceeded(_) => StorageError::QuotaExceeded(message),
+            StorageError::ReadOnly(_) => StorageError::ReadOnly(message),
+            StorageError::PermissionDenied(_) => StorageError::PermissionDenied(message),
+            StorageError::Corruption { extent, .. } => StorageError::Corruption { extent: *extent, detail: message },
+            StorageError::Unrecoverable { extent, .. } => StorageError::Unrecoverable { extent: *extent, detail: message },
+            StorageError::DiskUnavailable { disk, .. } => StorageError::DiskUnavailable { disk: *disk, detail: message },
+            StorageError::Unsupported(_) => StorageError::Unsupported(message),
+            StorageError::Interrupted(_) => StorageError::Interrupted(message),
+            StorageError::Io(_) | StorageError::Other(_) => return None,
+        })
+    }
+
+    /// Class of an `std::io::Error` kind the storage layer gives a meaning to
+    fn from_io_kind(kind: std::io::ErrorKind, message: String) -> Option<Self> {
+        use std::io::ErrorKind;
+        Some(match kind {
+            ErrorKind::StorageFull => StorageError::NoSpace(message),
+            ErrorKind::QuotaExceeded => StorageError::QuotaExceeded(message),
+            ErrorKind::ReadOnlyFilesystem => StorageError::ReadOnly(message),
+            ErrorKind::PermissionDenied => StorageError::PermissionDenied(message),
+            ErrorKind::Interrupted => StorageError::Interrupted(message),
+            ErrorKind::Unsupported => StorageError::Unsupported(message),
+            _ => return None,
+        })
+    }
+
+    /// The `StorageError` an `anyhow` error carries, outermost first
+    pub fn find(err: &anyhow::Error) -> Option<&StorageError> {
+        err.chain().find_map(|cause| cause.downcast_ref::<StorageError>())
+    }
+
+    /// The errno for an error returned through `anyhow`, e.g. by a
+    /// `FilesystemInterface` backend
+    #[cfg(unix)]
+    pub fn errno_of(err: &anyhow::Error) -> i32 {
+        if let Some(class) = Self::find(err) {
+            return class.to_errno();
+        }
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .and_then(|io_err| Self::from_io_kind(io_err.kind(), String::new()))
+            .map_or(libc::EIO, |class| class.to_errno())
+    }
+
+    /// The errno a filesystem call failing with this error returns
+    #[cfg(unix)]
+    pub fn to_errno(&self) -> i32 {
+        match self {
+            StorageError::NotFound(_) => libc::ENOENT,
+            StorageError::NoSpace(_) => libc::ENOSPC,
+            StorageError::QuotaExceeded(_) => libc::EDQUOT,
+            StorageError::ReadOnly(_) => libc::EROFS,
+            StorageError::PermissionDenied(_) => libc::EACCES,
+            StorageError::Unsupported(_) => libc::EOPNOTSUPP,
+            StorageError::Interrupted(_) => libc::EINTR,
+            StorageError::Corruption { .. } | StorageError::Unrecoverable { .. } | StorageError::DiskUnavailable { .. } => {
+                libc::EIO
+            }
+            StorageError::Io(e) => match Self::from_io_kind(e.kind(), String::new()) {
+                Some(class) => class.to_errno(),
+                None => libc::EIO,
+            },
+            StorageError::Other(e) => Self::errno_of(e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for StorageError {
+    /// Recover the class of an error that went through `anyhow`
+    ///
+    /// A `StorageError` with context added keeps its variant and takes the
+    /// full message; an `std::io::Error` of a kind with a class of its own
+    /// gets that class. Anything else becomes `Other`.
+    fn from(err: anyhow::Error) -> Self {
+        if err.chain().next().is_some_and(|outermost| outermost.is::<StorageError>()) {
+            return err.downcast().expect("outermost error is a StorageError");
+        }
+        let message = format!("{:#}", err);
+        let class = match Self::find(&err) {
+            Some(inner) => inner.with_message(message),
+            None => err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+                .and_then(|io_err| Self::from_io_kind(io_err.kind(), message)),
+        };
+        class.unwrap_or(StorageError::Other(err))
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        StorageError::Other(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_class_survives_anyhow_and_context() {
+        let extent = Uuid::new_v4();
+        let err: anyhow::Error = StorageError::Corruption { extent, detail: "bad checksum".into() }.into();
+        let err = StorageError::from(Err::<(), _>(err).context("reading inode 7").unwrap_err());
+        match err {
+            StorageError::Corruption { extent: found, detail } => {
+                assert_eq!(found, extent);
+                assert_eq!(detail, "reading inode 7: bad checksum");
+            }
+            other => panic!("expected Corruption, got {:?}", other),
+        }
+
+        let full = anyhow::Error::from(std::io::Error::new(std::io::ErrorKind::StorageFull, "full"));
+        assert!(matches!(StorageError::from(full), StorageError::NoSpace(_)));
+        assert!(matches!(StorageError::from(anyhow::anyhow!("odd")), StorageError::Other(_)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_errno_mapping() {
+        assert_eq!(StorageError::NotFound("x".into()).to_errno(), libc::ENOENT);
+        assert_eq!(StorageError::NoSpace("x".into()).to_errno(), libc::ENOSPC);
+        assert_eq!(StorageError::QuotaExceeded("x".into()).to_errno(), libc::EDQUOT);
+        assert_eq!(StorageError::ReadOnly("x".into()).to_errno(), libc::EROFS);
+        assert_eq!(StorageError::Corruption { extent: Uuid::nil(), detail: "x".into() }.to_errno(), libc::EIO);
+        let io = std::io::Error::new(std::io::ErrorKind::QuotaExceeded, "x");
+        assert_eq!(StorageError::Io(io).to_errno(), libc::EDQUOT);
+        assert_eq!(StorageError::Other(anyhow::anyhow!("x")).to_errno(), libc::EIO);
+        let wrapped = anyhow::Error::from(StorageError::NoSpace("full".into())).context("writing inode 9");
+        assert_eq!(StorageError::errno_of(&wrapped), libc::ENOSPC);
+    }
+}
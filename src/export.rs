@@ -0,0 +1,669 @@
+//! Portable pool archives for `dynamicfs export` and `dynamicfs import`
+//!
+//! An archive starts with the 8-byte magic `SCFSEXP1` and one byte naming the
+//! compression of everything after it: 0 for none, 1 for zstd. The rest is a
+//! sequence of records, each a little-endian `u32` length followed by that
+//! many bytes of JSON (an [`ArchiveRecord`]). The first record is the header.
+//! A `file` record is followed by `size` bytes of content and the 32-byte
+//! BLAKE3 hash of that content. The stream ends with an `end` record; an
+//! archive without one was cut short.
+//!
+//! File contents go through the normal read path, so files on degraded but
+//! readable extents export fine. Nothing else about the pool's layout is
+//! kept: a file can be pulled out of an archive with any zstd and JSON tool.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::compression::Compression;
+use crate::disk::DiskPool;
+use crate::extent::DEFAULT_EXTENT_SIZE;
+use crate::metadata::{FileName, FileType, Inode};
+use crate::storage::StorageEngine;
+
+pub const ARCHIVE_MAGIC: &[u8; 8] = b"SCFSEXP1";
+pub const ARCHIVE_VERSION: u32 = 1;
+/// Directory in the pool holding the state recorded by `export --snapshot`
+pub const EXPORT_SNAPSHOTS_DIR: &str = "exports";
+/// Records are small; anything longer means the stream is not an archive
+const MAX_RECORD_LEN: u32 = 16 * 1024 * 1024;
+/// Suffix of the hidden name a file is imported under before it replaces the target
+const PARTIAL_IMPORT_SUFFIX: &str = ".scfs-import";
+/// Xattrs holding pool settings; the importing pool applies its own
+const POOL_XATTR_PREFIX: &str = "user.scfs.";
+
+/// Pool the archive was taken from, for the reader's information
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveHeader {
+    pub version: u32,
+    pub created_at: i64,
+    pub pool: String,
+    pub disks: usize,
+    pub compression: Compression,
+    pub verify_writes: bool,
+    pub case_insensitive: bool,
+    #[serde(default = "crate::extent::default_extent_size")]
+    pub extent_size: usize,
+    pub encrypted: bool,
+    pub small_file_policy: String,
+    pub large_file_policy: String,
+    /// Export snapshot the archive is incremental to, if any
+    pub since: Option<String>,
+}
+
+impl ArchiveHeader {
+    pub fn describe(pool_dir: &Path, pool: &DiskPool, since: Option<&str>) -> Self {
+        ArchiveHeader {
+            version: ARCHIVE_VERSION,
+            created_at: chrono::Utc::now().timestamp(),
+            pool: pool_dir.display().to_string(),
+            disks: pool.disk_paths.len(),
+            compression: pool.compression,
+            verify_writes: pool.verify_writes,
+            case_insensitive: pool.case_insensitive,
+            extent_size: pool.extent_size,
+            encrypted: pool.is_encrypted(),
+            small_file_policy: StorageEngine::default_policy_for_size(0).to_string(),
+            large_file_policy: StorageEngine::default_policy_for_size(DEFAULT_EXTENT_SIZE as u64).to_string(),
+            since: since.map(str::to_string),
+        }
+    }
+}
+
+/// Path and attributes of one directory or file, relative to the pool root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveEntry {
+    pub path: FileName,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub atime: i64,
+    pub mtime: i64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl ArchiveEntry {
+    fn of(path: FileName, inode: &Inode) -> Self {
+        ArchiveEntry {
+            path,
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            atime: inode.atime,
+            mtime: inode.mtime,
+            xattrs: inode.xattrs.as_ref().map(|x| x.attrs.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// Give `inode` these attributes; pool setting xattrs are left to the new pool
+    pub(crate) fn apply(&self, inode: &mut Inode) {
+        inode.mode = self.mode;
+        inode.uid = self.uid;
+        inode.gid = self.gid;
+        inode.atime = self.atime;
+        inode.mtime = self.mtime;
+        for (name, value) in &self.xattrs {
+            if !name.starts_with(POOL_XATTR_PREFIX) {
+                inode.set_xattr(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    Header(ArchiveHeader),
+    Directory(ArchiveEntry),
+    File {
+        #[serde(flatten)]
+        entry: ArchiveEntry,
+        size: u64,
+    },
+    End {
+        files: u64,
+        bytes: u64,
+    },
+}
+
+/// A file as of an export, to tell whether a later export must include it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    pub mtime: i64,
+    pub extents: Vec<Uuid>,
+}
+
+/// Files covered by an export, recorded under a name for `export --since`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportSnapshot {
+    pub name: String,
+    pub created_at: i64,
+    /// Change generation of the pool when the export started; 0 for
+    /// snapshots recorded before generations were kept
+    #[serde(default)]
+    pub change_generation: u64,
+    pub files: BTreeMap<u64, FileState>,
+}
+
+impl ExportSnapshot {
+    /// Reject names that are empty, hidden or not a single path component
+    pub fn check_name(name: &str) -> Result<()> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(anyhow!("Invalid export snapshot name: {:?}", name));
+        }
+        Ok(())
+    }
+
+    fn path(pool_dir: &Path, name: &str) -> Result<PathBuf> {
+        Self::check_name(name)?;
+        Ok(pool_dir.join(EXPORT_SNAPSHOTS_DIR).join(format!("{}.json", name)))
+    }
+
+    pub fn load(pool_dir: &Path, name: &str) -> Result<Self> {
+        let path = Self::path(pool_dir, name)?;
+        let contents = std::fs::read_to_string(&path).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => anyhow!("No export snapshot named {:?}", name),
+            _ => anyhow!("Failed to read export snapshot {:?}: {}", name, e),
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Write the snapshot under `self.name`, replacing one of the same name
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        let path = Self::path(pool_dir, &self.name)?;
+        std::fs::create_dir_all(pool_dir.join(EXPORT_SNAPSHOTS_DIR))?;
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Whether `ino` in `state` is new or changed since this snapshot
+    fn has_changed(&self, ino: u64, state: &FileState) -> bool {
+        self.files.get(&ino) != Some(state)
+    }
+}
+
+/// Reported after every file exported or imported
+#[derive(Debug, Clone)]
+pub struct Progress<'a> {
+    pub path: &'a FileName,
+    pub files: u64,
+    /// Files the archive will hold; unknown while importing
+    pub total_files: Option<u64>,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExportSummary {
+    pub directories: u64,
+    pub files: u64,
+    pub bytes: u64,
+    /// Files left out of an incremental export as unchanged
+    pub unchanged: u64,
+    #[serde(skip)]
+    pub snapshot: ExportSnapshot,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportSummary {
+    pub directories: u64,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Stream every directory and file of the pool into `out`
+///
+/// With `since`, files whose mtime and extents match that snapshot are left
+/// out; directories are always written so the archive recreates the tree.
+/// The returned summary carries the state of every file for a snapshot of
+/// this export, unchanged ones included.
+pub fn export_pool<W: Write>(
+    storage: &StorageEngine,
+    header: ArchiveHeader,
+    since: Option<&ExportSnapshot>,
+    compress: bool,
+    mut out: W,
+    progress: impl FnMut(&Progress),
+) -> Result<ExportSummary> {
+    out.write_all(ARCHIVE_MAGIC)?;
+    out.write_all(&[compress as u8])?;
+    if compress {
+        let mut encoder = zstd::Encoder::new(out, 0)?;
+        let summary = write_archive(storage, header, since, &mut encoder, progress)?;
+        encoder.finish()?.flush()?;
+        Ok(summary)
+    } else {
+        let summary = write_archive(storage, header, since, &mut out, progress)?;
+        out.flush()?;
+        Ok(summary)
+    }
+}
+
+fn write_archive(
+    storage: &StorageEngine,
+    header: ArchiveHeader,
+    since: Option<&ExportSnapshot>,
+    out: &mut dyn Write,
+    mut progress: impl FnMut(&Progress),
+) -> Result<ExportSummary> {
+    let mut entries = Vec::new();
+    collect_tree(storage, 1, &FileName::default(), &mut entries)?;
+
+    let mut summary = ExportSummary::default();
+    summary.snapshot.created_at = chrono::Utc::now().timestamp();
+    summary.snapshot.change_generation = storage.metadata().read().unwrap().change_generation();
+    entries.retain(|(_, inode)| {
+        if inode.file_type == FileType::Directory {
+            return true;
+        }
+        let Ok(extent_map) = storage.metadata().read().unwrap().load_extent_map(inode.ino) else { return true };
+        let state = FileState { mtime: inode.mtime, extents: extent_map.data_extents().copied().collect() };
+        let changed = since.is_none_or(|snapshot| snapshot.has_changed(inode.ino, &state));
+        summary.snapshot.files.insert(inode.ino, state);
+        summary.unchanged += u64::from(!changed);
+        changed
+    });
+    let total_files = entries.iter().filter(|(_, inode)| inode.file_type != FileType::Directory).count() as u64;
+
+    write_record(out, &ArchiveRecord::Header(header))?;
+    for (path, inode) in entries {
+        let entry = ArchiveEntry::of(path, &inode);
+        if inode.file_type == FileType::Directory {
+            write_record(out, &ArchiveRecord::Directory(entry))?;
+            summary.directories += 1;
+            continue;
+        }
+
+        write_record(out, &ArchiveRecord::File { entry: entry.clone(), size: inode.size })?;
+        let mut hasher = blake3::Hasher::new();
+        let mut offset = 0;
+        while offset < inode.size {
+            let chunk = storage
+                .read_range(inode.ino, offset, (DEFAULT_EXTENT_SIZE as u64).min(inode.size - offset))
+                .with_context(|| format!("Failed to read {}", entry.path))?;
+            if chunk.is_empty() {
+                return Err(anyhow!("{} shrank while it was being exported", entry.path));
+            }
+            hasher.update(&chunk);
+            out.write_all(&chunk)?;
+            offset += chunk.len() as u64;
+        }
+        out.write_all(hasher.finalize().as_bytes())?;
+
+        summary.files += 1;
+        summary.bytes += inode.size;
+        progress(&Progress { path: &entry.path, files: summary.files, total_files: Some(total_files), bytes: summary.bytes });
+    }
+    write_record(out, &ArchiveRecord::End { files: summary.files, bytes: summary.bytes })?;
+    Ok(summary)
+}
+
+/// Every inode under `ino` with its path, parents before children, names in order
+pub(crate) fn collect_tree(
+    storage: &StorageEngine,
+    ino: u64,
+    path: &FileName,
+    entries: &mut Vec<(FileName, Inode)>,
+) -> Result<()> {
+    let mut children = storage.list_directory(ino)?;
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    for child in children {
+        let child_path = path.join(&child.name);
+        let is_dir = child.file_type == FileType::Directory;
+        let child_ino = child.ino;
+        entries.push((child_path.clone(), child));
+        if is_dir {
+            collect_tree(storage, child_ino, &child_path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_record(out: &mut dyn Write, record: &ArchiveRecord) -> Result<()> {
+    let json = serde_json::to_vec(record)?;
+    out.write_all(&(json.len() as u32).to_le_bytes())?;
+    out.write_all(&json)?;
+    Ok(())
+}
+
+/// Next record, or `None` at a clean end of stream
+fn read_record(input: &mut dyn Read) -> Result<Option<ArchiveRecord>> {
+    let mut len = [0u8; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    if len > MAX_RECORD_LEN {
+        return Err(anyhow!("Archive record of {} bytes is too large; the archive is damaged", len));
+    }
+    let mut json = vec![0u8; len as usize];
+    read_exact_or_truncated(input, &mut json)?;
+    Ok(Some(serde_json::from_slice(&json).context("Archive record is not valid")?))
+}
+
+fn read_exact_or_truncated(input: &mut dyn Read, buf: &mut [u8]) -> Result<()> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => anyhow!("Archive is truncated"),
+        _ => e.into(),
+    })
+}
+
+/// Header of an archive, checking the magic
+pub fn read_header<R: Read>(input: R) -> Result<ArchiveHeader> {
+    let mut records = open_archive(input)?;
+    match read_record(&mut records)? {
+        Some(ArchiveRecord::Header(header)) => Ok(header),
+        _ => Err(anyhow!("Archive does not start with a header")),
+    }
+}
+
+fn open_archive<'a, R: Read + 'a>(mut input: R) -> Result<Box<dyn Read + 'a>> {
+    let mut preamble = [0u8; 9];
+    input.read_exact(&mut preamble).map_err(|_| anyhow!("Not a dynamicfs export archive"))?;
+    if &preamble[..8] != ARCHIVE_MAGIC {
+        return Err(anyhow!("Not a dynamicfs export archive"));
+    }
+    match preamble[8] {
+        0 => Ok(Box::new(std::io::BufReader::new(input))),
+        1 => Ok(Box::new(zstd::Decoder::new(input)?)),
+        other => Err(anyhow!("Unknown archive compression {}", other)),
+    }
+}
+
+/// Recreate the directories and files of an archive under `prefix`
+///
+/// Files are written through the storage engine, so they take the pool's
+/// own redundancy policy, compression and quotas. Each file is written under
+/// a hidden name, checked against the archive's hash and only then renamed
+/// over its target: an interrupted or damaged import leaves every file either
+/// as it was or fully restored. Hidden leftovers of an interrupted import are
+/// replaced when the import is run again.
+pub fn import_archive<R: Read>(
+    storage: &StorageEngine,
+    input: R,
+    prefix: &str,
+    mut progress: impl FnMut(&Progress),
+) -> Result<ImportSummary> {
+    let mut records = open_archive(input)?;
+    let header = match read_record(&mut records)? {
+        Some(ArchiveRecord::Header(header)) => header,
+        _ => return Err(anyhow!("Archive does not start with a header")),
+    };
+    if header.version > ARCHIVE_VERSION {
+        return Err(anyhow!("Archive format version {} is newer than this dynamicfs supports", header.version));
+    }
+
+    let mut summary = ImportSummary::default();
+    loop {
+        match read_record(&mut records)? {
+            None => return Err(anyhow!("Archive ends without an end record; it was cut short")),
+            Some(ArchiveRecord::Header(_)) => return Err(anyhow!("Archive has a second header")),
+            Some(ArchiveRecord::Directory(entry)) => {
+                let ino = ensure_dir(storage, join(prefix, &entry.path))?;
+                let mut inode = storage.get_inode(ino)?;
+                entry.apply(&mut inode);
+                storage.update_inode(&inode)?;
+                summary.directories += 1;
+            }
+            Some(ArchiveRecord::File { entry, size }) => {
+                let mut content = vec![0u8; size as usize];
+                read_exact_or_truncated(&mut records, &mut content)?;
+                let mut hash = [0u8; 32];
+                read_exact_or_truncated(&mut records, &mut hash)?;
+                if blake3::hash(&content) != blake3::Hash::from(hash) {
+                    return Err(anyhow!("Checksum mismatch for {}; the archive is damaged", entry.path));
+                }
+
+                install_file(storage, &join(prefix, &entry.path), &entry, &content)?;
+                summary.files += 1;
+                summary.bytes += size;
+                progress(&Progress { path: &entry.path, files: summary.files, total_files: None, bytes: summary.bytes });
+            }
+            Some(ArchiveRecord::End { files, bytes }) => {
+                if (files, bytes) != (summary.files, summary.bytes) {
+                    return Err(anyhow!(
+                        "Archive lists {} files of {} bytes but holds {} files of {} bytes",
+                        files,
+                        bytes,
+                        summary.files,
+                        summary.bytes
+                    ));
+                }
+                return Ok(summary);
+            }
+        }
+    }
+}
+
+pub(crate) fn join(prefix: &str, path: &FileName) -> FileName {
+    let path = path.as_bytes();
+    let start = path.iter().position(|&b| b != b'/').unwrap_or(path.len());
+    FileName::from(prefix.trim_end_matches('/')).join(&FileName::from(&path[start..]))
+}
+
+fn trim_trailing_slashes(path: &[u8]) -> &[u8] {
+    let end = path.iter().rposition(|&b| b != b'/').map_or(0, |last| last + 1);
+    &path[..end]
+}
+
+fn is_dot_or_dot_dot(name: &[u8]) -> bool {
+    name == b"." || name == b".."
+}
+
+/// Split an absolute pool path into its parent path and final name
+pub(crate) fn split_path(path: &FileName) -> Result<(&[u8], FileName)> {
+    let trimmed = trim_trailing_slashes(path.as_bytes());
+    match trimmed.iter().rposition(|&b| b == b'/') {
+        Some(slash) if slash + 1 < trimmed.len() && !is_dot_or_dot_dot(&trimmed[slash + 1..]) => {
+            Ok((&trimmed[..slash], FileName::from(&trimmed[slash + 1..])))
+        }
+        _ => Err(anyhow!("Invalid path in archive: {:?}", path)),
+    }
+}
+
+/// Inode of the directory at `path`, creating it and any missing parents
+pub(crate) fn ensure_dir(storage: &StorageEngine, path: impl AsRef<[u8]>) -> Result<u64> {
+    let path = path.as_ref();
+    let mut ino = 1;
+    for name in path.split(|&b| b == b'/').filter(|name| !name.is_empty()) {
+        if is_dot_or_dot_dot(name) {
+            return Err(anyhow!("Invalid path in archive: {:?}", FileName::from(path)));
+        }
+        ino = match storage.find_child(ino, name)? {
+            Some(child) if child.file_type == FileType::Directory => child.ino,
+            Some(_) => return Err(anyhow!("{} exists and is not a directory", FileName::from(path))),
+            None => storage.create_dir(ino, name)?.ino,
+        };
+    }
+    Ok(ino)
+}
+
+/// Write `content` under a hidden name next to `path`, then rename it over `path`
+fn install_file(storage: &StorageEngine, path: &FileName, entry: &ArchiveEntry, content: &[u8]) -> Result<()> {
+    let (parent_path, name) = split_path(path)?;
+    let parent = ensure_dir(storage, parent_path)?;
+
+    let partial_name = FileName::from([b".", name.as_bytes(), PARTIAL_IMPORT_SUFFIX.as_bytes()].concat());
+    if let Some(stale) = storage.find_child(parent, &partial_name)? {
+        storage.delete_file(stale.ino)?;
+    }
+    let partial = storage.create_file(parent, partial_name)?;
+    storage.write_file(partial.ino, content, 0)?;
+
+    if let Some(existing) = storage.find_child(parent, &name)? {
+        if existing.file_type == FileType::Directory {
+            storage.delete_file(partial.ino)?;
+            return Err(anyhow!("{} exists and is a directory", path));
+        }
+        storage.delete_file(existing.ino)?;
+    }
+    let mut inode = storage.get_inode(partial.ino)?;
+    inode.name = name;
+    entry.apply(&mut inode);
+    Ok(storage.update_inode(&inode)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::Disk;
+    use crate::metadata::MetadataManager;
+    use tempfile::TempDir;
+
+    fn setup_storage() -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = TempDir::new().unwrap();
+        let disk_dirs: Vec<TempDir> = (0..6).map(|_| TempDir::new().unwrap()).collect();
+        let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        storage.set_space_reserve_percent(0);
+        (pool_dir, disk_dirs, storage)
+    }
+
+    fn header() -> ArchiveHeader {
+        ArchiveHeader::describe(Path::new("/pool"), &DiskPool::new(), None)
+    }
+
+    fn file_at(storage: &StorageEngine, path: &str) -> (Inode, Vec<u8>) {
+        let inode = storage.metadata().read().unwrap().resolve_path(path).unwrap();
+        let data = storage.read_file(inode.ino).unwrap();
+        (inode, data)
+    }
+
+    /// A small tree: two files at the root, one nested, one large enough for erasure coding
+    fn populate(storage: &StorageEngine) -> Vec<u8> {
+        let docs = storage.create_dir(1, "docs".to_string()).unwrap();
+        let nested = storage.create_dir(docs.ino, "nested".to_string()).unwrap();
+        let mut large = vec![0u8; DEFAULT_EXTENT_SIZE + 12345];
+        blake3::Hasher::new().update(b"export").finalize_xof().fill(&mut large);
+
+        for (parent, name, data) in [
+            (1, "empty", &b""[..]),
+            (1, "hello.txt", b"hello world"),
+            (nested.ino, "large.bin", &large),
+        ] {
+            let file = storage.create_file(parent, name.to_string()).unwrap();
+            storage.write_file(file.ino, data, 0).unwrap();
+        }
+        let mut hello = storage.metadata().read().unwrap().resolve_path("/hello.txt").unwrap();
+        hello.mode = 0o100600;
+        hello.uid = 1000;
+        hello.mtime = 1_600_000_000;
+        hello.set_xattr("user.comment".to_string(), b"keep me".to_vec());
+        hello.set_xattr(crate::fs_interface::REDUNDANCY_XATTR.to_string(), b"replication:2".to_vec());
+        storage.update_inode(&hello).unwrap();
+        large
+    }
+
+    #[test]
+    fn test_export_and_import_round_trip() {
+        for compress in [false, true] {
+            let (_pool_dir, _disk_dirs, source) = setup_storage();
+            let large = populate(&source);
+            let mut archive = Vec::new();
+            let mut reported = Vec::new();
+            let header = header();
+            let summary = export_pool(&source, header.clone(), None, compress, &mut archive, |p| {
+                reported.push((p.path.to_string(), p.total_files))
+            })
+            .unwrap();
+            assert_eq!((summary.directories, summary.files, summary.unchanged), (2, 3, 0));
+            assert_eq!(summary.bytes, large.len() as u64 + 11);
+            assert_eq!(reported.last().unwrap(), &("/hello.txt".to_string(), Some(3)));
+            assert_eq!(read_header(&archive[..]).unwrap(), header);
+
+            let (_dest_dir, _dest_disks, dest) = setup_storage();
+            let imported = import_archive(&dest, &archive[..], "/restored", |_| {}).unwrap();
+            assert_eq!((imported.directories, imported.files, imported.bytes), (2, 3, summary.bytes));
+
+            let (hello, data) = file_at(&dest, "/restored/hello.txt");
+            assert_eq!(data, b"hello world");
+            assert_eq!((hello.mode, hello.uid, hello.mtime), (0o100600, 1000, 1_600_000_000));
+            assert_eq!(hello.get_xattr("user.comment"), Some(&b"keep me"[..]));
+            // Pool settings are not carried over; the new pool picks its own policy
+            assert_eq!(hello.get_xattr(crate::fs_interface::REDUNDANCY_XATTR), None);
+            assert_eq!(file_at(&dest, "/restored/docs/nested/large.bin").1, large);
+            assert_eq!(file_at(&dest, "/restored/empty").1, b"");
+            let names: Vec<FileName> = dest.list_directory(1).unwrap().into_iter().map(|inode| inode.name).collect();
+            assert_eq!(names, vec!["restored"]);
+        }
+    }
+
+    #[test]
+    fn test_incremental_export_includes_only_changed_files() {
+        let (pool_dir, _disk_dirs, storage) = setup_storage();
+        populate(&storage);
+        let mut archive = Vec::new();
+        let mut full = export_pool(&storage, header(), None, false, &mut archive, |_| {}).unwrap();
+        full.snapshot.name = "monday".to_string();
+        full.snapshot.save(pool_dir.path()).unwrap();
+        let snapshot = ExportSnapshot::load(pool_dir.path(), "monday").unwrap();
+        assert_eq!(snapshot.files.len(), 3);
+        assert!(ExportSnapshot::load(pool_dir.path(), "tuesday").is_err());
+
+        let (hello, _) = file_at(&storage, "/hello.txt");
+        storage.write_file(hello.ino, b"hello again", 0).unwrap();
+        let added = storage.create_file(1, "new.txt".to_string()).unwrap();
+        storage.write_file(added.ino, b"new", 0).unwrap();
+
+        let mut incremental = Vec::new();
+        let summary = export_pool(&storage, header(), Some(&snapshot), true, &mut incremental, |_| {}).unwrap();
+        assert_eq!((summary.files, summary.unchanged), (2, 2));
+        assert_eq!(summary.snapshot.files.len(), 4);
+        // The same two files are what changed since the snapshot's generation
+        let changes = crate::backup_evolution::changed_files(&storage.metadata().read().unwrap(), snapshot.change_generation).unwrap();
+        let paths: Vec<&str> = changes.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["/hello.txt", "/new.txt"]);
+
+        // Applied on top of the full export, the incremental one brings the copy up to date
+        let (_dest_dir, _dest_disks, dest) = setup_storage();
+        import_archive(&dest, &archive[..], "/", |_| {}).unwrap();
+        let imported = import_archive(&dest, &incremental[..], "/", |_| {}).unwrap();
+        assert_eq!(imported.files, 2);
+        assert_eq!(file_at(&dest, "/hello.txt").1, b"hello again");
+        assert_eq!(file_at(&dest, "/new.txt").1, b"new");
+        assert_eq!(dest.list_directory(1).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_damaged_or_truncated_archive_leaves_the_pool_intact() {
+        let (_pool_dir, _disk_dirs, source) = setup_storage();
+        populate(&source);
+        let mut archive = Vec::new();
+        export_pool(&source, header(), None, false, &mut archive, |_| {}).unwrap();
+
+        let (_dest_dir, _dest_disks, dest) = setup_storage();
+        let existing = dest.create_file(1, "hello.txt".to_string()).unwrap();
+        dest.write_file(existing.ino, b"old contents", 0).unwrap();
+        // Left behind by an import killed part way through writing a file
+        let leftover = dest.create_file(1, format!(".hello.txt{}", PARTIAL_IMPORT_SUFFIX)).unwrap();
+        dest.write_file(leftover.ino, b"hel", 0).unwrap();
+
+        // Flip a byte of hello.txt's content
+        let at = archive.windows(11).position(|w| w == b"hello world").unwrap();
+        let mut damaged = archive.clone();
+        damaged[at] ^= 0xff;
+        let err = import_archive(&dest, &damaged[..], "/", |_| {}).unwrap_err();
+        assert!(err.to_string().contains("Checksum mismatch for /hello.txt"), "{}", err);
+        assert_eq!(file_at(&dest, "/hello.txt").1, b"old contents");
+
+        let err = import_archive(&dest, &archive[..archive.len() - 40], "/", |_| {}).unwrap_err();
+        assert!(err.to_string().contains("cut short") || err.to_string().contains("truncated"), "{}", err);
+
+        // Running the import again completes it and clears the leftovers
+        import_archive(&dest, &archive[..], "/", |_| {}).unwrap();
+        assert_eq!(file_at(&dest, "/hello.txt").1, b"hello world");
+        let names: Vec<FileName> = dest.list_directory(1).unwrap().into_iter().map(|inode| inode.name).collect();
+        assert!(names.iter().all(|name| !name.as_bytes().ends_with(PARTIAL_IMPORT_SUFFIX.as_bytes())), "{:?}", names);
+
+        assert!(import_archive(&dest, &b"not an archive"[..], "/", |_| {}).is_err());
+    }
+}
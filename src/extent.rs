@@ -1,8 +1,16 @@
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use uuid::Uuid;
 
-use crate::hmm_classifier::HmmClassifier;
+use crate::compression::Compression;
+use crate::hmm_classifier::{FrequencyObservation, HmmClassifier, ModelEstimate, ACCESS_HISTORY_HOURS};
+
+/// Confidence the access model needs before its estimate overrides the thresholds
+pub const MIN_MODEL_CONFIDENCE: f64 = 0.5;
+
+/// What an uncached read counts for in the access frequency, against 1 for a normal read
+pub const SCAN_READ_WEIGHT: f64 = 0.1;
 
 /// Redundancy policy for an extent
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -32,6 +40,56 @@ impl RedundancyPolicy {
         }
     }
     
+    /// Bytes in each fragment when `len` bytes are encoded under this policy
+    ///
+    /// Shards are never empty, so erasure coding stores at least one byte per
+    /// shard even for an empty extent.
+    pub fn fragment_size(&self, len: usize) -> usize {
+        match self {
+            RedundancyPolicy::Replication { .. } => len,
+            RedundancyPolicy::ErasureCoding { data_shards, .. } => len.div_ceil(*data_shards).max(1),
+        }
+    }
+    
+    /// Raw bytes written to disks per logical byte stored under this policy
+    pub fn storage_overhead(&self) -> f64 {
+        match self {
+            RedundancyPolicy::Replication { copies } => *copies as f64,
+            RedundancyPolicy::ErasureCoding { data_shards, parity_shards } => {
+                (data_shards + parity_shards) as f64 / *data_shards as f64
+            }
+        }
+    }
+    
+    /// Fragments that can be lost without losing the data
+    pub fn failures_tolerated(&self) -> usize {
+        self.fragment_count() - self.min_fragments()
+    }
+    
+    /// Strongest policy no stronger than this one that `writable_disks` can hold
+    ///
+    /// The failures tolerated are cut to what the disks allow first, then
+    /// erasure coding gives up data shards and turns into replication when
+    /// fewer than two would be left. None when the result would tolerate fewer
+    /// failures than `floor`.
+    pub fn fallback(&self, writable_disks: usize, floor: RedundancyPolicy) -> Option<RedundancyPolicy> {
+        let tolerated = self.failures_tolerated().min(writable_disks.saturating_sub(1));
+        if writable_disks == 0 || tolerated < floor.failures_tolerated() {
+            return None;
+        }
+        Some(match *self {
+            RedundancyPolicy::Replication { copies } => {
+                RedundancyPolicy::Replication { copies: copies.min(writable_disks) }
+            }
+            RedundancyPolicy::ErasureCoding { data_shards, .. } => {
+                match data_shards.min(writable_disks - tolerated) {
+                    data if data >= 2 => RedundancyPolicy::ErasureCoding { data_shards: data, parity_shards: tolerated },
+                    _ => RedundancyPolicy::Replication { copies: tolerated + 1 },
+                }
+            }
+        })
+    }
+    
     /// Check if we can upgrade/downgrade between policies
     pub fn can_transition_from(&self, _other: RedundancyPolicy) -> bool {
         // Any policy can transition from any other policy - we're re-encoding the data
@@ -39,6 +97,52 @@ impl RedundancyPolicy {
     }
 }
 
+impl std::fmt::Display for RedundancyPolicy {
+    /// Formats as `replication:N` or `erasure:K+M`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedundancyPolicy::Replication { copies } => write!(f, "replication:{}", copies),
+            RedundancyPolicy::ErasureCoding { data_shards, parity_shards } => {
+                write!(f, "erasure:{}+{}", data_shards, parity_shards)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for RedundancyPolicy {
+    type Err = anyhow::Error;
+    
+    /// Parses `replication:N` or `erasure:K+M`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        if let Some(copies) = s.strip_prefix("replication:") {
+            let copies: usize = copies
+                .parse()
+                .map_err(|_| anyhow!("Invalid replication count: {}", copies))?;
+            if copies == 0 {
+                return Err(anyhow!("Replication requires at least 1 copy"));
+            }
+            Ok(RedundancyPolicy::Replication { copies })
+        } else if let Some(shards) = s.strip_prefix("erasure:") {
+            let (data, parity) = shards
+                .split_once('+')
+                .ok_or_else(|| anyhow!("Invalid EC policy format. Use 'erasure:K+M'"))?;
+            let data_shards: usize = data
+                .parse()
+                .map_err(|_| anyhow!("Invalid data shard count: {}", data))?;
+            let parity_shards: usize = parity
+                .parse()
+                .map_err(|_| anyhow!("Invalid parity shard count: {}", parity))?;
+            if data_shards == 0 || parity_shards == 0 {
+                return Err(anyhow!("Erasure coding requires at least 1 data and 1 parity shard"));
+            }
+            Ok(RedundancyPolicy::ErasureCoding { data_shards, parity_shards })
+        } else {
+            Err(anyhow!("Invalid policy format. Use 'replication:N' or 'erasure:K+M'"))
+        }
+    }
+}
+
 /// Track policy change history
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PolicyTransition {
@@ -73,9 +177,14 @@ pub struct AccessStats {
     pub last_write: i64,
     pub created_at: i64,
     pub classification: AccessClassification,
-    /// HMM classifier for more sophisticated state transitions
-    #[serde(skip)]
-    pub hmm_classifier: Option<HmmClassifier>,
+    /// Reads and writes per hour, as `(unix time / 3600, count)` in hour order;
+    /// hours without accesses are left out and only the last week is kept
+    #[serde(default)]
+    pub hourly_accesses: Vec<(i64, u32)>,
+    /// Reads of `read_count` made through uncached handles, typically by
+    /// backups and scans; they leave `last_read` and the hourly history alone
+    #[serde(default)]
+    pub scan_reads: u64,
 }
 
 /// Represents an immutable extent (chunk of file data)
@@ -101,6 +210,22 @@ pub struct Extent {
     
     // Phase 15: Versioning for lock-free reads
     pub generation: u64, // Monotonic generation number for versioned reads
+
+    /// Compression applied before encoding; `size` and `checksum` always describe
+    /// the uncompressed data, and the checksum is verified after decompression
+    #[serde(default)]
+    pub compression: Compression,
+    /// Length of the compressed data that was encoded, when compressed
+    #[serde(default)]
+    pub compressed_size: Option<usize>,
+    /// Slot size of the file the extent was cut from; extents saved before
+    /// pools chose their own size were cut at `DEFAULT_EXTENT_SIZE`
+    #[serde(default = "default_extent_size")]
+    pub extent_size: usize,
+    /// Policy the extent was meant to have when it was written below it because
+    /// too few disks were writable; cleared once it is upgraded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intended_policy: Option<RedundancyPolicy>,
 }
 
 /// Location of a fragment on a disk
@@ -110,6 +235,20 @@ pub struct FragmentLocation {
     pub fragment_index: usize,
     /// Optional on-device placement information (start unit + unit_count)
     pub on_device: Option<crate::on_device_allocator::OnDevicePlacement>,
+    /// BLAKE3 hash of the fragment bytes; absent for fragments written before it was recorded
+    #[serde(default)]
+    pub checksum: Option<[u8; 32]>,
+}
+
+impl FragmentLocation {
+    /// Check fragment bytes read from disk against the recorded checksum
+    ///
+    /// Fragments without a recorded checksum always pass; the extent checksum
+    /// still covers them after decoding.
+    pub fn verify_checksum(&self, data: &[u8]) -> bool {
+        self.checksum
+            .is_none_or(|expected| blake3::hash(data).as_bytes() == &expected)
+    }
 }
 
 impl Extent {
@@ -137,14 +276,64 @@ impl Extent {
                 last_write: now,
                 created_at: now,
                 classification: AccessClassification::Cold,
-                hmm_classifier: Some(HmmClassifier::new()),
+                hourly_accesses: vec![(now / 3600, 1)],
+                scan_reads: 0,
             },
             rebuild_in_progress: false,
             rebuild_progress: None,
             generation: 0, // Start at generation 0
+            compression: Compression::None,
+            compressed_size: None,
+            extent_size: DEFAULT_EXTENT_SIZE,
+            intended_policy: None,
         }
     }
     
+    /// Compress the extent's data with `algorithm`, returning the bytes to encode
+    ///
+    /// Data that does not compress well is returned as is and the extent stays
+    /// uncompressed.
+    pub fn pack<'a>(&mut self, data: &'a [u8], algorithm: Compression) -> Cow<'a, [u8]> {
+        match algorithm.compress(data) {
+            Some(compressed) => {
+                self.compression = algorithm;
+                self.compressed_size = Some(compressed.len());
+                Cow::Owned(compressed)
+            }
+            None => {
+                self.compression = Compression::None;
+                self.compressed_size = None;
+                Cow::Borrowed(data)
+            }
+        }
+    }
+    
+    /// Bytes that were encoded into fragments
+    pub fn stored_size(&self) -> usize {
+        self.compressed_size.unwrap_or(self.size)
+    }
+    
+    /// Recover the extent's data from the output of `redundancy::decode`
+    ///
+    /// Decompresses if needed. The result is `size` bytes, ready for
+    /// `verify_checksum`.
+    pub fn unpack(&self, decoded: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if decoded.len() != self.stored_size() {
+            return Err(anyhow!(
+                "Extent {} decoded to {} bytes, expected {}",
+                self.uuid,
+                decoded.len(),
+                self.stored_size()
+            ));
+        }
+        if self.compression == Compression::None {
+            return Ok(decoded);
+        }
+        self.compression
+            .decompress(&decoded, self.size)
+            .map_err(|e| anyhow!("Failed to decompress extent {}: {}", self.uuid, e))
+    }
+    
     /// Verify checksum against data
     pub fn verify_checksum(&self, data: &[u8]) -> bool {
         let computed = blake3::hash(data);
@@ -240,19 +429,67 @@ impl Extent {
     
     /// Record a read access
     pub fn record_read(&mut self) {
-        self.access_stats.read_count += 1;
-        self.access_stats.last_read = chrono::Utc::now().timestamp();
+        self.apply_reads(1, chrono::Utc::now().timestamp());
+    }
+    
+    /// Record `count` reads, the latest at `last_read`
+    ///
+    /// Batched reads all count toward the hour of `last_read`.
+    pub fn apply_reads(&mut self, count: u64, last_read: i64) {
+        self.access_stats.read_count += count;
+        self.access_stats.last_read = self.access_stats.last_read.max(last_read);
+        self.record_hourly_accesses(count, last_read);
+        self.reclassify();
+    }
+    
+    /// Record `count` uncached reads, which count `SCAN_READ_WEIGHT` each
+    /// toward the access frequency and nothing toward recency
+    pub fn apply_scan_reads(&mut self, count: u64) {
+        self.access_stats.read_count += count;
+        self.access_stats.scan_reads += count;
         self.reclassify();
     }
     
     /// Record a write access
     pub fn record_write(&mut self) {
+        let now = chrono::Utc::now().timestamp();
         self.access_stats.write_count += 1;
-        self.access_stats.last_write = chrono::Utc::now().timestamp();
+        self.access_stats.last_write = now;
         self.generation += 1; // Increment generation on writes
+        self.record_hourly_accesses(1, now);
         self.reclassify();
     }
     
+    fn record_hourly_accesses(&mut self, count: u64, at: i64) {
+        let hour = at / 3600;
+        let history = &mut self.access_stats.hourly_accesses;
+        let position = history.partition_point(|(h, _)| *h < hour);
+        match history.get_mut(position) {
+            Some((h, total)) if *h == hour => *total = total.saturating_add(count as u32),
+            _ => history.insert(position, (hour, count as u32)),
+        }
+        let newest = history.last().map_or(hour, |(h, _)| *h);
+        history.retain(|(h, _)| *h > newest - ACCESS_HISTORY_HOURS);
+    }
+    
+    /// One observation per hour from creation (at most a week back) up to `now`
+    pub fn hourly_observations(&self, now: i64) -> Vec<FrequencyObservation> {
+        let now_hour = now / 3600;
+        let first = (self.access_stats.created_at / 3600).max(now_hour - ACCESS_HISTORY_HOURS + 1);
+        let mut counts = vec![0u32; (now_hour - first + 1).max(0) as usize];
+        for (hour, count) in &self.access_stats.hourly_accesses {
+            if let Some(slot) = usize::try_from(hour - first).ok().and_then(|i| counts.get_mut(i)) {
+                *slot = *count;
+            }
+        }
+        counts.into_iter().map(FrequencyObservation::from_hourly_accesses).collect()
+    }
+    
+    /// What `model` makes of this extent's hourly history; `None` while it is too short
+    pub fn model_estimate(&self, model: &HmmClassifier, now: i64) -> Option<ModelEstimate> {
+        model.estimate(&self.hourly_observations(now))
+    }
+    
     /// Increment generation number (for atomic updates)
     pub fn increment_generation(&mut self) {
         self.generation += 1;
@@ -265,43 +502,61 @@ impl Extent {
     
     /// Get access frequency (operations per day)
     pub fn access_frequency(&self) -> f64 {
-        let now = chrono::Utc::now().timestamp();
+        self.access_frequency_at(chrono::Utc::now().timestamp())
+    }
+    
+    fn access_frequency_at(&self, now: i64) -> f64 {
         let age_seconds = (now - self.access_stats.created_at).max(1);
         let age_days = age_seconds as f64 / 86400.0;
         
-        let total_ops = (self.access_stats.read_count + self.access_stats.write_count) as f64;
+        let stats = &self.access_stats;
+        let scan_reads = stats.scan_reads.min(stats.read_count);
+        let total_ops = (stats.read_count - scan_reads + stats.write_count) as f64 + scan_reads as f64 * SCAN_READ_WEIGHT;
         total_ops / age_days.max(1.0)
     }
     
-    /// Reclassify the extent as hot/warm/cold based on access patterns
-    /// Uses HMM for more sophisticated state transitions
+    /// Reclassify the extent as hot/warm/cold from frequency and recency thresholds
     pub fn reclassify(&mut self) {
-        let now = chrono::Utc::now().timestamp();
-        
+        self.reclassify_with_model(None);
+    }
+    
+    /// Reclassify the extent, preferring `model` once it has enough history; see `classify_with_model`
+    pub fn reclassify_with_model(&mut self, model: Option<&HmmClassifier>) {
+        self.access_stats.classification = self.classify_with_model(model);
+    }
+    
+    /// Hot/warm/cold from the access thresholds, or from `model` when it is confident
+    ///
+    /// The thresholds alone see a nightly batch job as hot while it runs and
+    /// cold the next day, so its extents migrate back and forth. The model
+    /// decodes the extent's hourly history and takes the state most of the
+    /// last day decoded to; its estimate wins once the extent has been
+    /// observed for `MODEL_MIN_HOURS` and at least `MIN_MODEL_CONFIDENCE` of
+    /// that day agrees.
+    pub fn classify_with_model(&self, model: Option<&HmmClassifier>) -> AccessClassification {
+        self.classify_at(model, chrono::Utc::now().timestamp())
+    }
+    
+    pub(crate) fn classify_at(&self, model: Option<&HmmClassifier>, now: i64) -> AccessClassification {
+        match model.and_then(|model| self.model_estimate(model, now)) {
+            Some(estimate) if estimate.confidence >= MIN_MODEL_CONFIDENCE => estimate.state,
+            _ => self.threshold_classification(now),
+        }
+    }
+    
+    fn threshold_classification(&self, now: i64) -> AccessClassification {
         // Recency score: lower is better (more recent)
         let recency_hours = (now - self.access_stats.last_read.max(self.access_stats.last_write)) / 3600;
         
         // Frequency score: operations per day
-        let frequency = self.access_frequency();
+        let frequency = self.access_frequency_at(now);
         
-        // Use HMM for classification if available
-        if let Some(ref mut hmm) = self.access_stats.hmm_classifier {
-            let new_classification = hmm.classify(
-                frequency,
-                recency_hours,
-                self.access_stats.classification,
-            );
-            
-            self.access_stats.classification = new_classification;
+        if frequency > 100.0 || recency_hours < 1 {
+            AccessClassification::Hot
+        } else if frequency > 10.0 || recency_hours < 24 {
+            AccessClassification::Warm
         } else {
-            // Fallback to simple threshold-based classification
-            self.access_stats.classification = if frequency > 100.0 || recency_hours < 1 {
-                AccessClassification::Hot
-            } else if frequency > 10.0 || recency_hours < 24 {
-                AccessClassification::Warm
-            } else {
-                AccessClassification::Cold
-            };
+            AccessClassification::Cold
         }
     }
     
@@ -345,11 +600,34 @@ impl Extent {
 /// Default extent size: 1 MB
 pub const DEFAULT_EXTENT_SIZE: usize = 1024 * 1024;
 
-/// Split data into extents
-pub fn split_into_extents(data: &[u8], redundancy: RedundancyPolicy) -> Vec<Extent> {
+/// Smallest extent size a pool can be initialized with: 64 KB
+pub const MIN_EXTENT_SIZE: usize = 64 * 1024;
+
+/// Largest extent size a pool can be initialized with: 64 MB
+pub const MAX_EXTENT_SIZE: usize = 64 * 1024 * 1024;
+
+pub fn default_extent_size() -> usize {
+    DEFAULT_EXTENT_SIZE
+}
+
+/// Check that `size` is a power of two between `MIN_EXTENT_SIZE` and `MAX_EXTENT_SIZE`
+pub fn validate_extent_size(size: usize) -> anyhow::Result<()> {
+    if !size.is_power_of_two() || !(MIN_EXTENT_SIZE..=MAX_EXTENT_SIZE).contains(&size) {
+        return Err(anyhow::anyhow!(
+            "Extent size must be a power of two between {} KB and {} MB, got {} bytes",
+            MIN_EXTENT_SIZE / 1024,
+            MAX_EXTENT_SIZE / 1024 / 1024,
+            size
+        ));
+    }
+    Ok(())
+}
+
+/// Split data into extents of `extent_size` bytes
+pub fn split_into_extents(data: &[u8], redundancy: RedundancyPolicy, extent_size: usize) -> Vec<Extent> {
     let mut extents = Vec::new();
     
-    for chunk in data.chunks(DEFAULT_EXTENT_SIZE) {
+    for chunk in data.chunks(extent_size) {
         extents.push(Extent::new(chunk, redundancy));
     }
     
@@ -358,5 +636,6 @@ pub fn split_into_extents(data: &[u8], redundancy: RedundancyPolicy) -> Vec<Exte
         extents.push(Extent::new(&[], redundancy));
     }
     
+    extents.iter_mut().for_each(|extent| extent.extent_size = extent_size);
     extents
 }
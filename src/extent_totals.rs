@@ -0,0 +1,108 @@
+//! Pool-wide extent counters kept up to date as extents are saved and deleted
+//!
+//! `status`, `health` and `show-redundancy` read these instead of every
+//! extent record. Counts and sizes only change when an extent is created or
+//! deleted, since an extent's size and compression are fixed when it is first
+//! saved. Health changes with every save, so the few extents that are not
+//! complete are tracked by UUID: a save moves an extent between the sets
+//! without reading its previous record. Extents written below their policy
+//! by a degraded write are tracked the same way until they are upgraded.
+//!
+//! The totals are written after each change. A crash between an extent save
+//! and that write leaves them slightly off until `check --repair` recounts.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::extent::Extent;
+
+/// File holding the totals, relative to the pool directory
+const TOTALS_FILE: &str = "metadata/extent_totals.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtentTotals {
+    pub extents: u64,
+    /// Uncompressed bytes held by all extents
+    pub logical_bytes: u64,
+    /// Bytes encoded into fragments, after compression
+    pub stored_bytes: u64,
+    pub compressed: u64,
+    /// Extents missing fragments but still readable
+    pub degraded: BTreeSet<Uuid>,
+    /// Extents with too few fragments to read
+    pub unreadable: BTreeSet<Uuid>,
+    /// Extents stored under a weaker policy than they were written with
+    #[serde(default)]
+    pub below_policy: BTreeSet<Uuid>,
+}
+
+impl ExtentTotals {
+    /// The saved totals of the pool at `pool_dir`, if it has any
+    pub fn load(pool_dir: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(pool_dir.join(TOTALS_FILE)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents).ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        let path = pool_dir.join(TOTALS_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Count an extent saved for the first time
+    pub fn add(&mut self, extent: &Extent) {
+        self.extents += 1;
+        self.logical_bytes += extent.size as u64;
+        self.stored_bytes += extent.stored_size() as u64;
+        if extent.compressed_size.is_some() {
+            self.compressed += 1;
+        }
+        self.update(extent);
+    }
+
+    /// Track the health of an extent saved again
+    pub fn update(&mut self, extent: &Extent) {
+        self.degraded.remove(&extent.uuid);
+        self.unreadable.remove(&extent.uuid);
+        if extent.intended_policy.is_some() {
+            self.below_policy.insert(extent.uuid);
+        } else {
+            self.below_policy.remove(&extent.uuid);
+        }
+        if extent.is_complete() {
+            return;
+        }
+        if extent.is_readable() {
+            self.degraded.insert(extent.uuid);
+        } else {
+            self.unreadable.insert(extent.uuid);
+        }
+    }
+
+    /// Stop counting a deleted extent
+    pub fn remove(&mut self, extent: &Extent) {
+        self.extents = self.extents.saturating_sub(1);
+        self.logical_bytes = self.logical_bytes.saturating_sub(extent.size as u64);
+        self.stored_bytes = self.stored_bytes.saturating_sub(extent.stored_size() as u64);
+        if extent.compressed_size.is_some() {
+            self.compressed = self.compressed.saturating_sub(1);
+        }
+        self.degraded.remove(&extent.uuid);
+        self.unreadable.remove(&extent.uuid);
+        self.below_policy.remove(&extent.uuid);
+    }
+
+    /// Extents with every fragment recorded
+    pub fn complete(&self) -> u64 {
+        self.extents.saturating_sub((self.degraded.len() + self.unreadable.len()) as u64)
+    }
+}
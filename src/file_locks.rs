@@ -1,6 +1,6 @@
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
 
 /// File lock type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,122 +20,261 @@ pub struct FileLock {
     pub end: u64,       // Byte range end (inclusive, u64::MAX for EOF)
 }
 
+impl FileLock {
+    /// Whether holding both locks at once is not allowed
+    fn conflicts_with(&self, other: &FileLock) -> bool {
+        self.owner != other.owner
+            && (self.lock_type == LockType::Write || other.lock_type == LockType::Write)
+            && ranges_overlap(self.start, self.end, other.start, other.end)
+    }
+}
+
+/// A blocked lock request, queued on its inode in arrival order
+#[derive(Debug, Clone)]
+struct Waiter {
+    ticket: u64,
+    lock: FileLock,
+}
+
+#[derive(Default)]
+struct LockTable {
+    locks: HashMap<u64, Vec<FileLock>>,      // inode -> locks
+    queues: HashMap<u64, VecDeque<Waiter>>,  // inode -> blocked requests
+    next_ticket: u64,
+}
+
+impl LockTable {
+    fn held_conflict(&self, ino: u64, lock: &FileLock) -> Option<&FileLock> {
+        self.locks.get(&ino)?.iter().find(|existing| lock.conflicts_with(existing))
+    }
+
+    /// Queued requests on `ino` that the request with `ticket` has to wait for
+    ///
+    /// Requests are granted in arrival order, except that with writer priority
+    /// a write request does not wait for reads queued before it and a read
+    /// request waits for every conflicting write in the queue.
+    fn queued_ahead<'a>(&'a self, ino: u64, ticket: u64, lock: &'a FileLock, writer_priority: bool) -> impl Iterator<Item = &'a Waiter> {
+        self.queues.get(&ino).into_iter().flatten().filter(move |w| {
+            if w.ticket == ticket || !lock.conflicts_with(&w.lock) {
+                return false;
+            }
+            if !writer_priority {
+                return w.ticket < ticket;
+            }
+            match (lock.lock_type, w.lock.lock_type) {
+                (LockType::Read, LockType::Write) => true,
+                (LockType::Write, LockType::Read) => false,
+                _ => w.ticket < ticket,
+            }
+        })
+    }
+
+    fn may_grant(&self, ino: u64, ticket: u64, lock: &FileLock, writer_priority: bool) -> bool {
+        self.held_conflict(ino, lock).is_none() && self.queued_ahead(ino, ticket, lock, writer_priority).next().is_none()
+    }
+
+    /// Owners the request with `ticket` waits for: holders of conflicting locks and owners queued ahead
+    fn blockers(&self, ino: u64, ticket: u64, lock: &FileLock, writer_priority: bool) -> Vec<u64> {
+        let held = self.locks.get(&ino).into_iter().flatten().filter(|l| lock.conflicts_with(l)).map(|l| l.owner);
+        let queued = self.queued_ahead(ino, ticket, lock, writer_priority).map(|w| w.lock.owner);
+        held.chain(queued).collect()
+    }
+
+    /// Whether the waits-for graph has a path from the blockers of this request back to its owner
+    fn would_deadlock(&self, ino: u64, ticket: u64, lock: &FileLock, writer_priority: bool) -> bool {
+        let mut pending = self.blockers(ino, ticket, lock, writer_priority);
+        let mut visited = HashSet::new();
+        while let Some(owner) = pending.pop() {
+            if owner == lock.owner {
+                return true;
+            }
+            if !visited.insert(owner) {
+                continue;
+            }
+            for (&waiting_ino, queue) in &self.queues {
+                for waiter in queue.iter().filter(|w| w.lock.owner == owner && w.ticket != ticket) {
+                    pending.extend(self.blockers(waiting_ino, waiter.ticket, &waiter.lock, writer_priority));
+                }
+            }
+        }
+        false
+    }
+
+    fn is_queued(&self, ino: u64, ticket: u64) -> bool {
+        self.queues.get(&ino).is_some_and(|q| q.iter().any(|w| w.ticket == ticket))
+    }
+
+    fn dequeue(&mut self, ino: u64, ticket: u64) {
+        if let Some(queue) = self.queues.get_mut(&ino) {
+            queue.retain(|w| w.ticket != ticket);
+            if queue.is_empty() {
+                self.queues.remove(&ino);
+            }
+        }
+    }
+
+    fn grant(&mut self, ino: u64, lock: FileLock) {
+        // Remove any existing locks from this owner in this range
+        if let Some(existing_locks) = self.locks.get_mut(&ino) {
+            existing_locks.retain(|l| !(l.owner == lock.owner && ranges_overlap(lock.start, lock.end, l.start, l.end)));
+            if existing_locks.is_empty() {
+                self.locks.remove(&ino);
+            }
+        }
+
+        // Add the new lock if not unlock
+        if lock.lock_type != LockType::Unlock {
+            self.locks.entry(ino).or_default().push(lock);
+        }
+    }
+}
+
 /// Lock manager for handling file locks
-/// 
+///
 /// Current implementation uses Vec<FileLock> which results in O(m) operations
 /// for lock conflict checking and removal, where m is the number of active locks.
-/// 
+///
 /// Future optimization: For files with many locks (m >> 10), consider using:
 /// - BTreeMap keyed by start offset for O(log m) range queries
 /// - Interval tree for O(log m) overlap detection
 /// - Current implementation is sufficient for typical use cases (< 100 locks/file)
+///
+/// Blocking requests (`F_SETLKW`) that conflict wait in a queue per inode and
+/// are woken whenever locks are released. Clones share the same lock table.
+#[derive(Clone)]
 pub struct LockManager {
-    locks: Arc<RwLock<HashMap<u64, Vec<FileLock>>>>, // inode -> locks
+    table: Arc<Mutex<LockTable>>,
+    /// Signalled whenever locks or queues change
+    changed: Arc<Condvar>,
+    /// Let queued writers go before queued readers instead of strict arrival order
+    writer_priority: bool,
 }
 
 impl LockManager {
     pub fn new() -> Self {
+        Self::with_writer_priority(false)
+    }
+
+    pub fn with_writer_priority(writer_priority: bool) -> Self {
         LockManager {
-            locks: Arc::new(RwLock::new(HashMap::new())),
+            table: Arc::new(Mutex::new(LockTable::default())),
+            changed: Arc::new(Condvar::new()),
+            writer_priority,
         }
     }
-    
+
     /// Test if a lock can be acquired
     pub fn test_lock(&self, ino: u64, lock: &FileLock) -> Result<Option<FileLock>> {
-        let locks = self.locks.read().unwrap();
-        
-        if let Some(existing_locks) = locks.get(&ino) {
-            for existing in existing_locks {
-                // Check if ranges overlap
-                if self.ranges_overlap(lock.start, lock.end, existing.start, existing.end) {
-                    // Check for conflicts
-                    if lock.lock_type == LockType::Write || existing.lock_type == LockType::Write {
-                        // Different owners cause conflict
-                        if lock.owner != existing.owner {
-                            return Ok(Some(existing.clone()));
-                        }
-                    }
-                }
-            }
-        }
-        
-        Ok(None)
+        let table = self.table.lock().unwrap();
+        Ok(table.held_conflict(ino, lock).cloned())
     }
-    
+
     /// Acquire a file lock
     pub fn acquire_lock(&self, ino: u64, lock: FileLock) -> Result<()> {
-        let mut locks = self.locks.write().unwrap();
-        
+        let mut table = self.table.lock().unwrap();
+
         // First, test for conflicts
-        if let Some(existing_locks) = locks.get(&ino) {
-            for existing in existing_locks {
-                if self.ranges_overlap(lock.start, lock.end, existing.start, existing.end) {
-                    if lock.lock_type == LockType::Write || existing.lock_type == LockType::Write {
-                        if lock.owner != existing.owner {
-                            return Err(anyhow!("Lock conflict detected"));
-                        }
-                    }
-                }
-            }
+        if table.held_conflict(ino, &lock).is_some() {
+            return Err(anyhow!("Lock conflict detected"));
         }
-        
-        // Remove any existing locks from this owner in this range
-        if let Some(existing_locks) = locks.get_mut(&ino) {
-            existing_locks.retain(|l| {
-                !(l.owner == lock.owner && 
-                  self.ranges_overlap(lock.start, lock.end, l.start, l.end))
-            });
+
+        table.grant(ino, lock);
+        // A downgrade or a narrower range can unblock waiters
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Acquire a file lock, waiting while it conflicts with locks of other owners
+    ///
+    /// Waiting requests on an inode are granted in arrival order, or writers
+    /// first with writer priority. If waiting would close a cycle in the
+    /// waits-for graph between owners, this request fails with an
+    /// `io::ErrorKind::Deadlock` error (EDEADLK) and the others keep waiting.
+    /// If `release_all_locks` drops the owner's locks while it waits, the
+    /// request fails with `io::ErrorKind::Interrupted`.
+    pub fn acquire_lock_blocking(&self, ino: u64, lock: FileLock) -> Result<()> {
+        let mut table = self.table.lock().unwrap();
+        let ticket = table.next_ticket;
+        table.next_ticket += 1;
+
+        if table.may_grant(ino, ticket, &lock, self.writer_priority) {
+            table.grant(ino, lock);
+            self.changed.notify_all();
+            return Ok(());
         }
-        
-        // Add the new lock if not unlock
-        if lock.lock_type != LockType::Unlock {
-            locks.entry(ino).or_insert_with(Vec::new).push(lock);
+        if table.would_deadlock(ino, ticket, &lock, self.writer_priority) {
+            return Err(deadlock(ino, &lock));
+        }
+        table.queues.entry(ino).or_default().push_back(Waiter { ticket, lock: lock.clone() });
+
+        loop {
+            table = self.changed.wait(table).unwrap();
+            if !table.is_queued(ino, ticket) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    format!("lock request of owner {} on inode {} was cancelled", lock.owner, ino),
+                )
+                .into());
+            }
+            if table.may_grant(ino, ticket, &lock, self.writer_priority) {
+                table.dequeue(ino, ticket);
+                table.grant(ino, lock);
+                self.changed.notify_all();
+                return Ok(());
+            }
+            // Checked under the table lock, so of the requests in a cycle only one sees it
+            if table.would_deadlock(ino, ticket, &lock, self.writer_priority) {
+                table.dequeue(ino, ticket);
+                self.changed.notify_all();
+                return Err(deadlock(ino, &lock));
+            }
         }
-        
-        Ok(())
     }
-    
+
     /// Release a specific lock
     pub fn release_lock(&self, ino: u64, owner: u64, start: u64, end: u64) -> Result<()> {
-        let mut locks = self.locks.write().unwrap();
-        
-        if let Some(existing_locks) = locks.get_mut(&ino) {
+        let mut table = self.table.lock().unwrap();
+
+        if let Some(existing_locks) = table.locks.get_mut(&ino) {
             existing_locks.retain(|l| {
                 !(l.owner == owner && l.start == start && l.end == end)
             });
-            
+
             if existing_locks.is_empty() {
-                locks.remove(&ino);
+                table.locks.remove(&ino);
             }
         }
-        
+
+        self.changed.notify_all();
         Ok(())
     }
-    
-    /// Release all locks for a given owner
+
+    /// Release all locks for a given owner, and cancel its waiting requests on the inode
     pub fn release_all_locks(&self, ino: u64, owner: u64) -> Result<()> {
-        let mut locks = self.locks.write().unwrap();
-        
-        if let Some(existing_locks) = locks.get_mut(&ino) {
+        let mut table = self.table.lock().unwrap();
+
+        if let Some(existing_locks) = table.locks.get_mut(&ino) {
             existing_locks.retain(|l| l.owner != owner);
-            
+
             if existing_locks.is_empty() {
-                locks.remove(&ino);
+                table.locks.remove(&ino);
+            }
+        }
+        if let Some(queue) = table.queues.get_mut(&ino) {
+            queue.retain(|w| w.lock.owner != owner);
+            if queue.is_empty() {
+                table.queues.remove(&ino);
             }
         }
-        
+
+        self.changed.notify_all();
         Ok(())
     }
-    
+
     /// Get all locks for a file
     pub fn get_locks(&self, ino: u64) -> Vec<FileLock> {
-        let locks = self.locks.read().unwrap();
-        locks.get(&ino).cloned().unwrap_or_default()
-    }
-    
-    /// Check if two byte ranges overlap
-    fn ranges_overlap(&self, start1: u64, end1: u64, start2: u64, end2: u64) -> bool {
-        !(end1 < start2 || end2 < start1)
+        let table = self.table.lock().unwrap();
+        table.locks.get(&ino).cloned().unwrap_or_default()
     }
 }
 
@@ -145,3 +284,146 @@ impl Default for LockManager {
     }
 }
 
+/// Check if two byte ranges overlap
+fn ranges_overlap(start1: u64, end1: u64, start2: u64, end2: u64) -> bool {
+    !(end1 < start2 || end2 < start1)
+}
+
+fn deadlock(ino: u64, lock: &FileLock) -> anyhow::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Deadlock,
+        format!("waiting for bytes {}..={} of inode {} would deadlock owner {}", lock.start, lock.end, ino, lock.owner),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn write_lock(owner: u64, start: u64, end: u64) -> FileLock {
+        FileLock { owner, pid: owner as u32, lock_type: LockType::Write, start, end }
+    }
+
+    fn read_lock(owner: u64, start: u64, end: u64) -> FileLock {
+        FileLock { lock_type: LockType::Read, ..write_lock(owner, start, end) }
+    }
+
+    /// Wait until `n` requests are queued on `ino`
+    fn wait_for_queue(manager: &LockManager, ino: u64, n: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while manager.table.lock().unwrap().queues.get(&ino).map_or(0, |q| q.len()) != n {
+            assert!(Instant::now() < deadline, "{} requests never queued", n);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Request `lock` on another thread, sending its owner and outcome when it returns
+    fn request(manager: &LockManager, ino: u64, lock: FileLock, done: &mpsc::Sender<(u64, Result<()>)>) {
+        let (manager, done) = (manager.clone(), done.clone());
+        thread::spawn(move || {
+            let owner = lock.owner;
+            done.send((owner, manager.acquire_lock_blocking(ino, lock))).unwrap();
+        });
+    }
+
+    fn error_kind(result: &Result<()>) -> Option<std::io::ErrorKind> {
+        result.as_ref().err()?.downcast_ref::<std::io::Error>().map(|e| e.kind())
+    }
+
+    #[test]
+    fn test_opposite_order_write_locks_get_exactly_one_edeadlk() {
+        let manager = LockManager::new();
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let (done, results) = mpsc::channel();
+
+        for (owner, held, wanted) in [(1, (0, 9), (100, 109)), (2, (100, 109), (0, 9))] {
+            let (manager, barrier, done) = (manager.clone(), Arc::clone(&barrier), done.clone());
+            thread::spawn(move || {
+                manager.acquire_lock(1, write_lock(owner, held.0, held.1)).unwrap();
+                barrier.wait();
+                let result = manager.acquire_lock_blocking(1, write_lock(owner, wanted.0, wanted.1));
+                if result.is_err() {
+                    // The victim backs off, letting the other owner through
+                    manager.release_all_locks(1, owner).unwrap();
+                }
+                done.send((owner, result)).unwrap();
+            });
+        }
+
+        let outcomes: Vec<_> = (0..2).map(|_| results.recv_timeout(Duration::from_secs(10)).expect("lock requests hung")).collect();
+        let deadlocked: Vec<_> = outcomes.iter().filter(|(_, r)| error_kind(r) == Some(std::io::ErrorKind::Deadlock)).collect();
+        assert_eq!(deadlocked.len(), 1, "{:?}", outcomes);
+        let (winner, result) = outcomes.iter().find(|(_, r)| r.is_ok()).expect("the other request is granted");
+        assert!(result.is_ok());
+        assert_eq!(manager.get_locks(1).iter().filter(|l| l.owner == *winner).count(), 2);
+    }
+
+    #[test]
+    fn test_waiters_are_granted_in_arrival_order_when_locks_are_released() {
+        let manager = LockManager::new();
+        let (done, results) = mpsc::channel();
+        manager.acquire_lock(7, write_lock(1, 0, 99)).unwrap();
+
+        request(&manager, 7, write_lock(2, 0, 9), &done);
+        wait_for_queue(&manager, 7, 1);
+        request(&manager, 7, write_lock(3, 5, 14), &done);
+        wait_for_queue(&manager, 7, 2);
+        assert!(results.recv_timeout(Duration::from_millis(50)).is_err(), "granted while the lock is held");
+
+        manager.release_lock(7, 1, 0, 99).unwrap();
+        let (first, result) = results.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(first, 2);
+        assert!(result.is_ok());
+        assert!(results.recv_timeout(Duration::from_millis(50)).is_err(), "owner 3 overlaps owner 2");
+
+        manager.release_all_locks(7, 2).unwrap();
+        let (second, result) = results.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(second, 3);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_writer_priority_lets_queued_writers_pass_queued_readers() {
+        for writer_priority in [false, true] {
+            let manager = LockManager::with_writer_priority(writer_priority);
+            let (done, results) = mpsc::channel();
+            manager.acquire_lock(3, write_lock(1, 0, 99)).unwrap();
+
+            request(&manager, 3, read_lock(2, 0, 9), &done);
+            wait_for_queue(&manager, 3, 1);
+            request(&manager, 3, write_lock(3, 0, 9), &done);
+            wait_for_queue(&manager, 3, 2);
+
+            manager.release_all_locks(3, 1).unwrap();
+            let (first, result) = results.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert!(result.is_ok());
+            assert_eq!(first, if writer_priority { 3 } else { 2 });
+
+            manager.release_all_locks(3, first).unwrap();
+            let (second, result) = results.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert!(result.is_ok());
+            assert_eq!(second, if writer_priority { 2 } else { 3 });
+        }
+    }
+
+    #[test]
+    fn test_release_all_locks_cancels_the_owners_waiting_requests() {
+        let manager = LockManager::new();
+        let (done, results) = mpsc::channel();
+        manager.acquire_lock(5, write_lock(1, 0, 9)).unwrap();
+
+        request(&manager, 5, write_lock(2, 0, 9), &done);
+        wait_for_queue(&manager, 5, 1);
+        // The waiting process exits and its file is released
+        manager.release_all_locks(5, 2).unwrap();
+
+        let (owner, result) = results.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(owner, 2);
+        assert_eq!(error_kind(&result), Some(std::io::ErrorKind::Interrupted));
+        assert_eq!(manager.get_locks(5).len(), 1);
+    }
+}
@@ -0,0 +1,344 @@
+//! File names as the byte strings POSIX allows
+//!
+//! A name may hold any bytes but '/' and NUL, so names unpacked from foreign
+//! archives or copied from other systems need not be UTF-8. Inodes, the
+//! directory index and the inode cache keep the bytes as given, and the FUSE
+//! layer passes them through untouched. Paths inside the pool are names
+//! joined by '/', so they are held, shown and serialized the same way.
+//!
+//! Serialized, a UTF-8 name is a plain string, as names always were, so
+//! existing records and indexes read unchanged. Binary formats such as the
+//! bincode directory index store the raw bytes, which is the same encoding a
+//! `String` had. In JSON, which only holds Unicode, any other name is written
+//! as `{"percent": "..."}`: the bytes with '%', controls and everything
+//! outside printable ASCII escaped as `%XX`. That keeps both records and CLI
+//! output lossless, and the object tells it apart from a name that merely
+//! contains a '%'.
+
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Serialize};
+use std::borrow::{Borrow, Cow};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+
+/// Key of the JSON object a non-UTF-8 name is written as
+const PERCENT_KEY: &str = "percent";
+
+/// A file or directory name
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileName(Vec<u8>);
+
+impl FileName {
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        FileName(bytes.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The name as text, if it is UTF-8
+    pub fn to_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// The name as text, with invalid sequences replaced by U+FFFD
+    ///
+    /// For messages only; two names may show the same.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    #[cfg(unix)]
+    pub fn as_os_str(&self) -> &OsStr {
+        use std::os::unix::ffi::OsStrExt;
+        OsStr::from_bytes(&self.0)
+    }
+
+    #[cfg(unix)]
+    pub fn to_os_string(&self) -> OsString {
+        self.as_os_str().to_os_string()
+    }
+
+    #[cfg(not(unix))]
+    pub fn to_os_string(&self) -> OsString {
+        OsString::from(self.to_string_lossy().into_owned())
+    }
+
+    /// This path with `name` appended as its last component
+    pub fn join(&self, name: &FileName) -> FileName {
+        let mut path = self.0.clone();
+        if path.last() != Some(&b'/') {
+            path.push(b'/');
+        }
+        path.extend_from_slice(&name.0);
+        FileName(path)
+    }
+
+    /// The bytes with '%', controls and non-ASCII bytes escaped as `%XX`
+    pub fn percent_encoded(&self) -> String {
+        let mut encoded = String::with_capacity(self.0.len());
+        for &b in &self.0 {
+            if (0x20..0x7f).contains(&b) && b != b'%' {
+                encoded.push(b as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
+        }
+        encoded
+    }
+
+    /// Undo `percent_encoded`; `None` for a stray '%' or a non-hex escape
+    pub fn from_percent_encoded(encoded: &str) -> Option<Self> {
+        let bytes = encoded.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'%' {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            } else {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+        Some(FileName(decoded))
+    }
+}
+
+impl From<&str> for FileName {
+    fn from(name: &str) -> Self {
+        FileName(name.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for FileName {
+    fn from(name: String) -> Self {
+        FileName(name.into_bytes())
+    }
+}
+
+impl From<&String> for FileName {
+    fn from(name: &String) -> Self {
+        FileName::from(name.as_str())
+    }
+}
+
+impl From<Vec<u8>> for FileName {
+    fn from(bytes: Vec<u8>) -> Self {
+        FileName(bytes)
+    }
+}
+
+impl From<&[u8]> for FileName {
+    fn from(bytes: &[u8]) -> Self {
+        FileName(bytes.to_vec())
+    }
+}
+
+impl From<&OsStr> for FileName {
+    #[cfg(unix)]
+    fn from(name: &OsStr) -> Self {
+        use std::os::unix::ffi::OsStrExt;
+        FileName(name.as_bytes().to_vec())
+    }
+
+    #[cfg(not(unix))]
+    fn from(name: &OsStr) -> Self {
+        FileName::from(name.to_string_lossy().as_ref())
+    }
+}
+
+impl AsRef<[u8]> for FileName {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Names hash and compare as their bytes, so maps keyed by name can be searched by bytes
+impl Borrow<[u8]> for FileName {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(unix)]
+impl AsRef<OsStr> for FileName {
+    fn as_ref(&self) -> &OsStr {
+        self.as_os_str()
+    }
+}
+
+#[cfg(unix)]
+impl AsRef<std::path::Path> for FileName {
+    fn as_ref(&self) -> &std::path::Path {
+        std::path::Path::new(self.as_os_str())
+    }
+}
+
+impl PartialEq<str> for FileName {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for FileName {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<String> for FileName {
+    fn eq(&self, other: &String) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<FileName> for &str {
+    fn eq(&self, other: &FileName) -> bool {
+        self.as_bytes() == other.0
+    }
+}
+
+impl fmt::Display for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+impl fmt::Debug for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_str() {
+            Some(name) => fmt::Debug::fmt(name, f),
+            None => write!(f, "b\"{}\"", self.0.escape_ascii()),
+        }
+    }
+}
+
+impl Serialize for FileName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(&self.0);
+        }
+        match self.to_str() {
+            Some(name) => serializer.serialize_str(name),
+            None => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(PERCENT_KEY, &self.percent_encoded())?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl<'de> Visitor<'de> for NameVisitor {
+            type Value = FileName;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a file name as a string, bytes or a percent-encoded object")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<FileName, E> {
+                Ok(FileName::from(name))
+            }
+
+            fn visit_string<E: de::Error>(self, name: String) -> Result<FileName, E> {
+                Ok(FileName::from(name))
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<FileName, E> {
+                Ok(FileName::from(bytes))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<FileName, E> {
+                Ok(FileName(bytes))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FileName, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    bytes.push(b);
+                }
+                Ok(FileName(bytes))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FileName, A::Error> {
+                let mut name = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key != PERCENT_KEY {
+                        return Err(de::Error::unknown_field(&key, &[PERCENT_KEY]));
+                    }
+                    let encoded: String = map.next_value()?;
+                    name = Some(
+                        FileName::from_percent_encoded(&encoded)
+                            .ok_or_else(|| de::Error::custom(format!("bad percent escape in {:?}", encoded)))?,
+                    );
+                }
+                name.ok_or_else(|| de::Error::missing_field(PERCENT_KEY))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(NameVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(NameVisitor)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_names_serialize_as_before() {
+        let name = FileName::from("café.txt");
+        assert_eq!(serde_json::to_string(&name).unwrap(), "\"café.txt\"");
+        assert_eq!(serde_json::from_str::<FileName>("\"café.txt\"").unwrap(), name);
+        // bincode wrote names as `String`s, which share the byte encoding
+        let old = bincode::serialize(&(7u64, "café.txt".to_string())).unwrap();
+        assert_eq!(bincode::serialize(&(7u64, &name)).unwrap(), old);
+        assert_eq!(bincode::deserialize::<(u64, FileName)>(&old).unwrap(), (7, name));
+    }
+
+    #[test]
+    fn test_non_utf8_names_round_trip_losslessly() {
+        let name = FileName::from_bytes(b"caf\xe9 100%\n".to_vec());
+        assert_eq!(name.to_str(), None);
+        let json = serde_json::to_string(&name).unwrap();
+        assert_eq!(json, r#"{"percent":"caf%E9 100%25%0A"}"#);
+        assert_eq!(serde_json::from_str::<FileName>(&json).unwrap(), name);
+        assert_eq!(serde_json::from_str::<FileName>("[99,97,102,233,32,49,48,48,37,10]").unwrap(), name);
+        assert_eq!(bincode::deserialize::<FileName>(&bincode::serialize(&name).unwrap()).unwrap(), name);
+        assert_eq!(format!("{:?}", name), r#"b"caf\xe9 100%\n""#);
+        assert!(FileName::from_percent_encoded("bad%E").is_none());
+        assert!(serde_json::from_str::<FileName>(r#"{"percent":"%zz"}"#).is_err());
+    }
+
+    #[test]
+    fn test_order_matches_string_order() {
+        let mut names: Vec<FileName> = ["b", "a\u{e9}", "a", "B", "ab"].into_iter().map(FileName::from).collect();
+        names.push(FileName::from_bytes(b"a\xff".to_vec()));
+        names.sort();
+        let shown: Vec<String> = names.iter().map(|n| format!("{:?}", n)).collect();
+        assert_eq!(shown, [r#""B""#, r#""a""#, r#""ab""#, r#""aé""#, r#"b"a\xff""#, r#""b""#]);
+    }
+}
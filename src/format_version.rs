@@ -0,0 +1,117 @@
+//! Versions of the JSON files describing a pool and its disks
+//!
+//! `pool.json` and every `disk.json` carry a `format_version` of the form
+//! `MAJOR.MINOR`. A build opens files of its own major version, keeping the
+//! fields a newer minor version added, and refuses newer major versions.
+//! Files of an older version are upgraded in memory when loaded; the first
+//! save after that keeps the old file next to the new one as `<name>.bak`.
+//! Files written before versions were recorded count as 0.0.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FormatVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+/// Takes a file's JSON from one major version to the next
+pub type Upgrade = fn(&mut serde_json::Value) -> Result<()>;
+
+impl FormatVersion {
+    /// Files written before versions were recorded
+    pub const UNVERSIONED: FormatVersion = FormatVersion::new(0, 0);
+
+    pub const fn new(major: u32, minor: u32) -> Self {
+        FormatVersion { major, minor }
+    }
+
+    /// Version recorded in a file's JSON
+    pub fn of(value: &serde_json::Value) -> Result<Self> {
+        match value.get("format_version") {
+            None => Ok(Self::UNVERSIONED),
+            Some(version) => version
+                .as_str()
+                .ok_or_else(|| anyhow!("format_version {} is not a string", version))?
+                .parse(),
+        }
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for FormatVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (major, minor) = s.split_once('.').unwrap_or((s, "0"));
+        match (major.parse(), minor.parse()) {
+            (Ok(major), Ok(minor)) => Ok(FormatVersion { major, minor }),
+            _ => Err(anyhow!("Invalid format version {:?}: expected MAJOR.MINOR", s)),
+        }
+    }
+}
+
+impl Serialize for FormatVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FormatVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Bring a file's JSON up to `current`, returning the version it was written in
+///
+/// `upgrades[n]` takes major version `n` to `n + 1`. A newer minor version of
+/// the current major is left as it is.
+pub fn upgrade(value: &mut serde_json::Value, current: FormatVersion, upgrades: &[Upgrade], what: &str) -> Result<FormatVersion> {
+    let version = FormatVersion::of(value).with_context(|| format!("Invalid {}", what))?;
+    if version.major > current.major {
+        return Err(anyhow!(
+            "{} has format version {}, newer than this build understands ({}); upgrade dynamicfs",
+            what,
+            version,
+            current
+        ));
+    }
+    for step in &upgrades[version.major as usize..current.major as usize] {
+        step(value).with_context(|| format!("Failed to upgrade {} from format {}", what, version))?;
+    }
+    if version < current {
+        log::info!("Upgrading {} from format {} to {}", what, version, current);
+        value["format_version"] = current.to_string().into();
+    }
+    Ok(version)
+}
+
+/// Before `path` is replaced by a file of `current` format, keep it as
+/// `<path>.bak` if it is of an older one
+pub fn backup_older_format(path: &Path, current: FormatVersion) -> Result<()> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Ok(());
+    };
+    let older = serde_json::from_str(&contents).ok().and_then(|value| FormatVersion::of(&value).ok()).is_some_and(|v| v < current);
+    if older {
+        fs::copy(path, backup_path(path)).with_context(|| format!("Failed to back up {:?} before upgrading it", path))?;
+    }
+    Ok(())
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
@@ -58,9 +58,15 @@
 //!     // ... implement other methods
 //! }
 //! ```
+//!
+//! [`MemoryFs`](crate::memory_fs::MemoryFs) is a complete in-memory backend,
+//! useful for testing code that drives a `FilesystemInterface`.
 
 use anyhow::Result;
 
+use crate::permissions::{self, RequestContext};
+use crate::metadata::FileName;
+
 /// Cross-platform filesystem interface trait
 ///
 /// This trait abstracts filesystem operations to enable pluggable storage backends
@@ -130,7 +136,7 @@ pub trait FilesystemInterface {
     /// - The parent is not a directory
     /// - A file with the same name already exists in the parent
     /// - There are I/O errors creating the file
-    fn create_file(&self, parent_ino: u64, name: String) -> Result<crate::metadata::Inode>;
+    fn create_file(&self, parent_ino: u64, name: FileName) -> Result<crate::metadata::Inode>;
 
     /// Create a new directory
     ///
@@ -150,7 +156,7 @@ pub trait FilesystemInterface {
     /// - The parent is not a directory
     /// - A directory with the same name already exists in the parent
     /// - There are I/O errors creating the directory
-    fn create_dir(&self, parent_ino: u64, name: String) -> Result<crate::metadata::Inode>;
+    fn create_dir(&self, parent_ino: u64, name: FileName) -> Result<crate::metadata::Inode>;
 
     /// Delete a file
     ///
@@ -166,6 +172,24 @@ pub trait FilesystemInterface {
     /// - There are I/O errors deleting the file
     fn delete_file(&self, ino: u64) -> Result<()>;
 
+    /// Unlink a file that is still open, keeping its contents until `delete_file`
+    ///
+    /// The file leaves its directory and moves under
+    /// `metadata::ORPHAN_PARENT_INO`, named after its inode number, so reads
+    /// and writes by inode keep working. The FUSE layer calls `delete_file`
+    /// when the last handle is closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `ino` - Inode number of the file to unlink
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The inode does not exist
+    /// - There are I/O errors moving the file
+    fn orphan_file(&self, ino: u64) -> Result<()>;
+
     /// Delete a directory
     ///
     /// # Arguments
@@ -221,7 +245,7 @@ pub trait FilesystemInterface {
     /// # Arguments
     ///
     /// * `parent_ino` - Inode number of the directory to search
-    /// * `name` - Name of the child to find
+    /// * `name` - Name of the child to find, as the bytes of the entry
     ///
     /// # Returns
     ///
@@ -234,7 +258,7 @@ pub trait FilesystemInterface {
     /// - The parent inode does not exist
     /// - The parent is not a directory
     /// - There are I/O errors searching the directory
-    fn find_child(&self, parent_ino: u64, name: &str) -> Result<Option<crate::metadata::Inode>>;
+    fn find_child(&self, parent_ino: u64, name: &[u8]) -> Result<Option<crate::metadata::Inode>>;
 
     /// Update inode metadata
     ///
@@ -259,6 +283,306 @@ pub trait FilesystemInterface {
     ///
     /// Returns an error if there are I/O errors collecting the statistics
     fn stat(&self) -> Result<FilesystemStats>;
+
+    /// Check whether a caller may access an inode
+    ///
+    /// Backs `access(2)` and the permission checks of the FUSE handlers.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The caller
+    /// * `ino` - Inode number to check
+    /// * `mask` - Combination of `permissions::READ`, `WRITE` and `EXECUTE`; 0 only checks the inode exists
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The mode bits deny the access (`std::io::ErrorKind::PermissionDenied`)
+    /// - The inode does not exist
+    fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> Result<()> {
+        permissions::check_access(&self.get_inode(ino)?, ctx, mask)
+    }
+
+    /// Create a new file owned by the caller
+    ///
+    /// The caller needs write and search permission on the parent. The default
+    /// creates the file and then sets its owner and mode; backends should
+    /// override it to save both with the new inode.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The caller, who becomes the owner
+    /// * `parent_ino` - Inode number of the parent directory
+    /// * `name` - Name of the new file
+    /// * `mode` - Permission bits, with the umask already applied
+    ///
+    /// # Errors
+    ///
+    /// As `create_file`, and `std::io::ErrorKind::PermissionDenied` if the
+    /// caller may not add entries to the parent
+    fn create_file_as(&self, ctx: &RequestContext, parent_ino: u64, name: FileName, mode: u32) -> Result<crate::metadata::Inode> {
+        let parent = self.get_inode(parent_ino)?;
+        permissions::check_access(&parent, ctx, permissions::WRITE | permissions::EXECUTE)?;
+        let mut inode = self.create_file(parent_ino, name)?;
+        permissions::set_creator(&mut inode, &parent, ctx, mode);
+        self.update_inode(&inode)?;
+        Ok(inode)
+    }
+
+    /// Create a new directory owned by the caller
+    ///
+    /// As `create_file_as`, for directories.
+    fn create_dir_as(&self, ctx: &RequestContext, parent_ino: u64, name: FileName, mode: u32) -> Result<crate::metadata::Inode> {
+        let parent = self.get_inode(parent_ino)?;
+        permissions::check_access(&parent, ctx, permissions::WRITE | permissions::EXECUTE)?;
+        let mut inode = self.create_dir(parent_ino, name)?;
+        permissions::set_creator(&mut inode, &parent, ctx, mode);
+        self.update_inode(&inode)?;
+        Ok(inode)
+    }
+
+    /// Get the redundancy policy currently protecting a file
+    ///
+    /// # Arguments
+    ///
+    /// * `ino` - Inode number of the file
+    ///
+    /// # Returns
+    ///
+    /// The policy of the file's extents, or `None` if the file holds no data
+    /// and no policy has been requested for it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backend does not support redundancy control
+    /// or there are I/O errors reading the metadata
+    fn get_redundancy(&self, ino: u64) -> Result<Option<crate::extent::RedundancyPolicy>> {
+        let _ = ino;
+        Err(anyhow::anyhow!("Redundancy control is not supported by this backend"))
+    }
+
+    /// Change the redundancy policy of a file
+    ///
+    /// Existing extents are re-encoded and the policy is remembered for future writes.
+    ///
+    /// # Arguments
+    ///
+    /// * `ino` - Inode number of the file
+    /// * `policy` - The new redundancy policy
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The backend does not support redundancy control
+    /// - The policy needs more disks than are healthy (`std::io::ErrorKind::StorageFull`)
+    /// - There are I/O errors re-encoding the data
+    fn set_redundancy(&self, ino: u64, policy: crate::extent::RedundancyPolicy) -> Result<()> {
+        let _ = (ino, policy);
+        Err(anyhow::anyhow!("Redundancy control is not supported by this backend"))
+    }
+
+    /// Flush a file or directory durably to stable storage
+    ///
+    /// Backs `fsync(2)` and `fsyncdir`. Backends whose writes are already
+    /// durable when acknowledged can keep the default, which does nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `ino` - Inode number to flush
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the inode doesn't exist or the flush fails
+    fn sync_inode(&self, ino: u64) -> Result<()> {
+        let _ = ino;
+        Ok(())
+    }
+
+    /// Read part of a file
+    ///
+    /// The default reads the whole file and slices it; backends should override
+    /// this to fetch only the data overlapping the range.
+    ///
+    /// # Arguments
+    ///
+    /// * `ino` - Inode number of the file
+    /// * `offset` - Byte offset to start reading at
+    /// * `size` - Maximum number of bytes to read
+    ///
+    /// # Returns
+    ///
+    /// The bytes in the range, clipped to the end of the file. Sparse regions read as zeros.
+    fn read_range(&self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let data = self.read_file(ino)?;
+        let start = (offset as usize).min(data.len());
+        let end = (offset.saturating_add(size) as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    /// Read part of a file without caching it or reading ahead, as for `O_DIRECT`
+    ///
+    /// For single-pass readers such as backups, whose data would only push
+    /// hot data out of a cache. Backends with a cache should still count the
+    /// reads, as scans. The default is `read_range`.
+    fn read_range_uncached(&self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>> {
+        self.read_range(ino, offset, size)
+    }
+
+    /// Deallocate a byte range so it reads back as zeros (`FALLOC_FL_PUNCH_HOLE`)
+    ///
+    /// The file size is unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backend does not support sparse files or there
+    /// are I/O errors updating the data
+    fn punch_hole(&self, ino: u64, offset: u64, length: u64) -> Result<()> {
+        let _ = (ino, offset, length);
+        Err(anyhow::anyhow!("Hole punching is not supported by this backend"))
+    }
+
+    /// Zero a byte range (`FALLOC_FL_ZERO_RANGE`)
+    ///
+    /// Unless `keep_size` is set, a range past the end of the file grows it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the backend does not support sparse files or there
+    /// are I/O errors updating the data
+    fn zero_range(&self, ino: u64, offset: u64, length: u64, keep_size: bool) -> Result<()> {
+        let _ = (ino, offset, length, keep_size);
+        Err(anyhow::anyhow!("Zeroing ranges is not supported by this backend"))
+    }
+
+    /// Replace the contents of `dst_ino` with those of `src_ino` (`copy_file_range` of a whole file)
+    ///
+    /// Backends that can share data between files do so, copy-on-write. The
+    /// default copies the data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either inode is missing or not a regular file, or
+    /// there are I/O errors reading or writing the data
+    fn clone_file(&self, src_ino: u64, dst_ino: u64) -> Result<()> {
+        let data = self.read_file(src_ino)?;
+        self.write_file(dst_ino, &data, 0)
+    }
+
+    /// Bytes of storage allocated to a file, excluding sparse regions
+    ///
+    /// Used for the block count reported by `stat`. The default assumes files are not sparse.
+    fn allocated_size(&self, ino: u64) -> Result<u64> {
+        Ok(self.get_inode(ino)?.size)
+    }
+
+    /// Extents of a file and the disks holding their fragments
+    ///
+    /// Served as the read-only `user.scfs.layout` xattr. Backends without a
+    /// notion of placement keep the default, which reports no layout.
+    fn describe_layout(&self, ino: u64) -> Result<Option<crate::layout::FileLayout>> {
+        let _ = ino;
+        Ok(None)
+    }
+
+    /// Write data that may be held in memory before it reaches storage
+    ///
+    /// Backs `write(2)`. Buffered data must be visible to reads and `get_inode`
+    /// immediately, and written out by `flush_file` or `sync_inode`. The default
+    /// writes straight through.
+    ///
+    /// # Arguments
+    ///
+    /// * `ino` - Inode number of the file to write to
+    /// * `offset` - Byte offset in the file where writing should begin
+    /// * `data` - Data to write
+    fn buffered_write(&self, ino: u64, offset: u64, data: &[u8]) -> Result<()> {
+        self.write_file(ino, data, offset)
+    }
+
+    /// Write data at the current end of file, for handles opened with `O_APPEND`
+    ///
+    /// Finding the end of file and writing there must be atomic with respect to
+    /// every other writer of the file, so concurrent appenders never overlap.
+    /// Returns the offset the data was written at. The default is only correct
+    /// with a single writer.
+    fn append_write(&self, ino: u64, data: &[u8]) -> Result<u64> {
+        let offset = self.get_inode(ino)?.size;
+        self.buffered_write(ino, offset, data)?;
+        Ok(offset)
+    }
+
+    /// Write out data buffered for a file because it is being closed
+    ///
+    /// Backs the FUSE `flush` and `release` operations, so errors reach
+    /// `close(2)`. This does not make the data durable; that is `sync_inode`'s
+    /// job. Backends without a write buffer can keep the default, which does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing the buffered data fails
+    fn flush_file(&self, ino: u64) -> Result<()> {
+        let _ = ino;
+        Ok(())
+    }
+
+    /// Write out and make durable everything buffered, before unmounting
+    ///
+    /// Backs the FUSE `destroy` operation and clean shutdown. Backends whose
+    /// writes are already durable when acknowledged can keep the default,
+    /// which does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing or syncing any buffered data fails
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Metrics the FUSE layer records operation latencies into
+    ///
+    /// Backends without metrics keep the default, and latencies go unrecorded.
+    fn metrics(&self) -> Option<std::sync::Arc<crate::metrics::Metrics>> {
+        None
+    }
+
+    /// Fetch `extents` extents of a file, from the one holding `offset`, ahead of a sequential reader
+    ///
+    /// A hint; backends without a cache ignore it.
+    fn prefetch(&self, _ino: u64, _offset: u64, _extents: usize) {}
+
+    /// Drop read-ahead for a file whose reader stopped reading sequentially
+    fn cancel_prefetch(&self, _ino: u64) {}
+
+    /// Hand back a buffer a read returned once it was replied with
+    ///
+    /// A hint; backends that do not pool read buffers drop it.
+    fn recycle_read_buffer(&self, _buf: Vec<u8>) {}
+}
+
+/// Extended attribute exposing a file's redundancy policy (`replication:N` or `erasure:K+M`)
+pub const REDUNDANCY_XATTR: &str = "user.scfs.redundancy";
+
+/// Read-only extended attribute with a JSON description of a file's extent layout
+pub const LAYOUT_XATTR: &str = "user.scfs.layout";
+
+/// Extended attribute overriding the pool's verify-on-write setting for a file ("on" or "off")
+pub const VERIFY_WRITES_XATTR: &str = "user.scfs.verify_writes";
+
+/// Parse the "on" or "off" of `VERIFY_WRITES_XATTR` or `NOCACHE_XATTR`
+pub fn parse_on_off(value: &[u8]) -> Option<bool> {
+    match value {
+        b"on" => Some(true),
+        b"off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Extended attribute making every open of a file uncached, as if opened with `O_DIRECT` ("on" or "off")
+pub const NOCACHE_XATTR: &str = "user.scfs.nocache";
+
+/// Whether `inode` has `NOCACHE_XATTR` set to "on"
+pub fn is_nocache(inode: &crate::metadata::Inode) -> bool {
+    inode.get_xattr(NOCACHE_XATTR).and_then(parse_on_off).unwrap_or(false)
 }
 
 /// Filesystem statistics
@@ -277,9 +601,12 @@ pub struct FilesystemStats {
     pub total_size: u64,
     
     /// Used storage space in bytes
+    ///
+    /// Backends with redundancy report usable (logical) bytes, i.e. raw bytes
+    /// divided by their redundancy overhead, so the figure matches what `df` shows.
     pub used_space: u64,
     
-    /// Free storage space in bytes
+    /// Free storage space in bytes (usable, see `used_space`)
     pub free_space: u64,
 }
 
@@ -0,0 +1,639 @@
+//! Offline consistency check of a pool's metadata graph
+//!
+//! Walks the inodes from the root and cross-references directory entries,
+//! extent maps, extents, fragment locations and the contents of the disks.
+//! Scrub verifies extent data; this verifies that the records point at each
+//! other correctly. The pool must not be mounted while it runs.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::disk::{Disk, DiskKind};
+use crate::extent::Extent;
+use crate::extent_totals::ExtentTotals;
+use crate::inode_totals::InodeTotals;
+use crate::gc::GarbageCollector;
+use crate::metadata::{ExtentMap, FileType, Inode, MetadataManager, ORPHAN_PARENT_INO};
+
+const ROOT_INO: u64 = 1;
+const LOST_FOUND: &str = "lost+found";
+
+/// Kind of inconsistency, in report order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    /// Inode record that does not parse or fails its checksum
+    UnreadableInode,
+    /// Inode whose parent is missing, not a directory, or not reachable from the root
+    DisconnectedInode,
+    /// Directory index entry naming a missing or different inode
+    StaleDirEntry,
+    /// Inode absent from its parent's directory index
+    MissingDirEntry,
+    /// Extent map that does not parse or whose checksum cannot be regenerated
+    CorruptExtentMap,
+    /// Extent map with a wrong checksum although every extent it lists exists
+    StaleExtentMapChecksum,
+    /// Extent map whose inode no longer exists
+    OrphanedExtentMap,
+    /// Extent map listing extents that no longer exist
+    DanglingExtentRef,
+    /// Extent record that does not parse
+    UnreadableExtent,
+    /// Extent listed by no extent map and not yet released
+    UnreferencedExtent,
+    /// Fragment location on a disk that is not part of the pool
+    UnknownDisk,
+    /// Recorded fragment absent from its disk while the extent is still decodable
+    MissingFragment,
+    /// Too few fragments left to decode the extent
+    UnrecoverableExtent,
+    /// Fragment on a disk that no extent records
+    OrphanFragment,
+    /// Fragment on a block device in units its allocator bitmap marks free
+    UnmarkedDeviceFragment,
+    /// Pool extent counters that disagree with the extent records
+    StaleExtentTotals,
+    /// Pool file and directory counters that disagree with the inode records
+    StaleInodeTotals,
+    /// Directory whose subdirectory count, and so its link count, is wrong
+    StaleLinkCount,
+}
+
+/// How `check --repair` deals with a kind of finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairClass {
+    /// Fixed by `--repair`
+    Safe,
+    /// Fixed by `--repair --force`; may discard data references
+    Dangerous,
+    /// Left for another tool or for the operator
+    Manual,
+}
+
+impl FindingKind {
+    /// Warnings do not fail the check
+    pub fn is_error(self) -> bool {
+        !matches!(
+            self,
+            FindingKind::UnreferencedExtent
+                | FindingKind::MissingFragment
+                | FindingKind::OrphanFragment
+                | FindingKind::StaleExtentTotals
+                | FindingKind::StaleInodeTotals
+                | FindingKind::StaleLinkCount
+        )
+    }
+
+    pub fn repair_class(self) -> RepairClass {
+        match self {
+            FindingKind::DisconnectedInode
+            | FindingKind::StaleDirEntry
+            | FindingKind::MissingDirEntry
+            | FindingKind::StaleExtentMapChecksum
+            | FindingKind::DanglingExtentRef
+            | FindingKind::UnreferencedExtent
+            | FindingKind::UnmarkedDeviceFragment
+            | FindingKind::StaleExtentTotals
+            | FindingKind::StaleInodeTotals
+            | FindingKind::StaleLinkCount => RepairClass::Safe,
+            FindingKind::OrphanedExtentMap | FindingKind::UnknownDisk => RepairClass::Dangerous,
+            FindingKind::UnreadableInode
+            | FindingKind::CorruptExtentMap
+            | FindingKind::UnreadableExtent
+            | FindingKind::MissingFragment
+            | FindingKind::UnrecoverableExtent
+            | FindingKind::OrphanFragment => RepairClass::Manual,
+        }
+    }
+}
+
+/// What happened to a finding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "reason")]
+pub enum FindingStatus {
+    Open,
+    Repaired,
+    /// Repairable, but not in this run (missing `--force`, or unsafe in this pool's state)
+    Refused(String),
+}
+
+/// One inconsistency
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// What it is about, e.g. `ino 12` or `extent <uuid>`
+    pub subject: String,
+    pub detail: String,
+    pub status: FindingStatus,
+}
+
+/// Result of `check_pool`
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    pub inodes_checked: usize,
+    pub extent_maps_checked: usize,
+    pub extents_checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl CheckReport {
+    /// Error findings that are still present after this run
+    pub fn unresolved_errors(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.kind.is_error() && f.status != FindingStatus::Repaired)
+            .count()
+    }
+
+    /// Findings grouped by kind, in report order
+    pub fn by_kind(&self) -> BTreeMap<FindingKind, Vec<&Finding>> {
+        let mut grouped: BTreeMap<FindingKind, Vec<&Finding>> = BTreeMap::new();
+        for finding in &self.findings {
+            grouped.entry(finding.kind).or_default().push(finding);
+        }
+        grouped
+    }
+}
+
+/// Options for `check_pool`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckOptions {
+    /// Fix the safe findings
+    pub repair: bool,
+    /// With `repair`, also fix the dangerous ones
+    pub force: bool,
+}
+
+/// Check the pool at `pool_dir` against its loaded `disks`
+///
+/// `unavailable_disks` counts pool disks that could not be loaded; while any
+/// are missing, fragment locations on unknown disks are never dropped.
+pub fn check_pool(pool_dir: PathBuf, disks: &mut [Disk], unavailable_disks: usize, options: CheckOptions) -> Result<CheckReport> {
+    let mut checker = Checker {
+        metadata: MetadataManager::new(pool_dir.clone())?,
+        pool_dir,
+        options,
+        report: CheckReport::default(),
+    };
+    let inodes = checker.check_inodes()?;
+    checker.check_link_counts(&inodes)?;
+    checker.check_dir_index()?;
+    let refs = checker.check_extent_maps(&inodes)?;
+    checker.check_extents(disks, &refs, unavailable_disks)?;
+    checker.check_disk_contents(disks)?;
+    Ok(checker.report)
+}
+
+/// Extents the extent maps point at
+struct ExtentRefs {
+    referenced: HashSet<Uuid>,
+    /// Some map could not be read, so `referenced` may be incomplete
+    incomplete: bool,
+}
+
+struct Checker {
+    metadata: MetadataManager,
+    pool_dir: PathBuf,
+    options: CheckOptions,
+    report: CheckReport,
+}
+
+impl Checker {
+    fn record(&mut self, kind: FindingKind, subject: String, detail: String) -> usize {
+        self.report.findings.push(Finding { kind, subject, detail, status: FindingStatus::Open });
+        self.report.findings.len() - 1
+    }
+
+    /// Whether to attempt the repair of finding `index`, marking it refused if not
+    fn should_repair(&mut self, index: usize, blocked: Option<&str>) -> bool {
+        if !self.options.repair {
+            return false;
+        }
+        let finding = &mut self.report.findings[index];
+        if let Some(reason) = blocked {
+            finding.status = FindingStatus::Refused(reason.to_string());
+            return false;
+        }
+        if finding.kind.repair_class() == RepairClass::Dangerous && !self.options.force {
+            finding.status = FindingStatus::Refused("needs --force".to_string());
+            return false;
+        }
+        true
+    }
+
+    fn repaired(&mut self, index: usize) {
+        self.report.findings[index].status = FindingStatus::Repaired;
+    }
+
+    /// Load every inode and reattach the ones not reachable from the root
+    ///
+    /// Returns the readable inodes; unreadable ones are recorded as `None`.
+    fn check_inodes(&mut self) -> Result<BTreeMap<u64, Option<Inode>>> {
+        let mut inodes = BTreeMap::new();
+        for ino in self.metadata.inode_numbers()? {
+            match self.metadata.load_inode(ino) {
+                Ok(inode) => {
+                    inodes.insert(ino, Some(inode));
+                }
+                Err(e) => {
+                    self.record(FindingKind::UnreadableInode, format!("ino {}", ino), format!("{:#}", e));
+                    inodes.insert(ino, None);
+                }
+            }
+        }
+        self.report.inodes_checked = inodes.len();
+        self.check_inode_totals(&inodes)?;
+
+        let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+        for inode in inodes.values().flatten() {
+            if inode.ino != ROOT_INO {
+                children.entry(inode.parent_ino).or_default().push(inode.ino);
+            }
+        }
+        // Files unlinked while open wait under the orphan parent to be deleted at mount
+        let mut reachable = HashSet::from([ROOT_INO]);
+        reachable.extend(children.get(&ORPHAN_PARENT_INO).into_iter().flatten());
+        let mut pending = vec![ROOT_INO];
+        while let Some(ino) = pending.pop() {
+            let is_dir = matches!(inodes.get(&ino), Some(Some(inode)) if inode.file_type == FileType::Directory);
+            if !is_dir {
+                continue;
+            }
+            for child in children.get(&ino).into_iter().flatten() {
+                if reachable.insert(*child) {
+                    pending.push(*child);
+                }
+            }
+        }
+
+        // Report only the top of each disconnected subtree; reattaching it reconnects the rest
+        let mut tops: BTreeMap<u64, String> = BTreeMap::new();
+        for inode in inodes.values().flatten().filter(|inode| !reachable.contains(&inode.ino)) {
+            let mut seen = Vec::new();
+            let mut current = inode;
+            let (top, reason) = loop {
+                seen.push(current.ino);
+                match inodes.get(&current.parent_ino) {
+                    None => break (current.ino, format!("parent {} does not exist", current.parent_ino)),
+                    // An unreadable parent is reported on its own; leave its children alone
+                    Some(None) => break (0, String::new()),
+                    Some(Some(parent)) if parent.file_type != FileType::Directory => {
+                        break (current.ino, format!("parent {} is not a directory", parent.ino))
+                    }
+                    Some(Some(parent)) if seen.contains(&parent.ino) => {
+                        let cycle_min = *seen[seen.iter().position(|ino| *ino == parent.ino).unwrap()..].iter().min().unwrap();
+                        break (cycle_min, "in a directory cycle unreachable from the root".to_string());
+                    }
+                    Some(Some(parent)) => current = parent,
+                }
+            };
+            if top != 0 {
+                tops.entry(top).or_insert(reason);
+            }
+        }
+
+        let mut lost_found = None;
+        for (ino, reason) in tops {
+            let index = self.record(FindingKind::DisconnectedInode, format!("ino {}", ino), reason);
+            if !self.should_repair(index, None) {
+                continue;
+            }
+            let dir = match lost_found {
+                Some(dir) => dir,
+                None => {
+                    let dir = self.lost_found()?;
+                    inodes.insert(dir, Some(self.metadata.load_inode(dir)?));
+                    *lost_found.insert(dir)
+                }
+            };
+            let Some(Some(inode)) = inodes.get_mut(&ino) else { continue };
+            inode.parent_ino = dir;
+            inode.name = format!("#{}", ino).into();
+            self.metadata.save_inode(inode)?;
+            self.repaired(index);
+        }
+        Ok(inodes)
+    }
+
+    /// Compare the pool's file and directory counters with the inodes just read
+    ///
+    /// Runs before any repair, since repairs that save inodes keep the counters
+    /// up to date themselves.
+    fn check_inode_totals(&mut self, inodes: &BTreeMap<u64, Option<Inode>>) -> Result<()> {
+        let mut counted = InodeTotals::default();
+        for inode in inodes.values().flatten() {
+            counted.add(inode);
+        }
+        let saved = self.metadata.inode_totals();
+        if saved != counted {
+            let index = self.record(
+                FindingKind::StaleInodeTotals,
+                "pool".to_string(),
+                format!(
+                    "counters record {} files, {} directories and {} bytes; {}, {} and {} found",
+                    saved.files, saved.dirs, saved.logical_bytes, counted.files, counted.dirs, counted.logical_bytes
+                ),
+            );
+            if self.should_repair(index, None) {
+                self.metadata.recount_inode_totals()?;
+                self.repaired(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare each directory's subdirectory count with the directories under it
+    ///
+    /// Counts are kept by the storage engine from when they were introduced,
+    /// so directories of older pools and ones reattached above start out wrong.
+    fn check_link_counts(&mut self, inodes: &BTreeMap<u64, Option<Inode>>) -> Result<()> {
+        let mut counted: HashMap<u64, u64> = HashMap::new();
+        for inode in inodes.values().flatten() {
+            if inode.file_type == FileType::Directory && inode.ino != inode.parent_ino {
+                *counted.entry(inode.parent_ino).or_default() += 1;
+            }
+        }
+        for dir in inodes.values().flatten().filter(|inode| inode.file_type == FileType::Directory) {
+            let subdirs = counted.get(&dir.ino).copied().unwrap_or(0);
+            if dir.subdirs == subdirs {
+                continue;
+            }
+            let index = self.record(
+                FindingKind::StaleLinkCount,
+                format!("ino {}", dir.ino),
+                format!("records {} subdirectories; {} found", dir.subdirs, subdirs),
+            );
+            if self.should_repair(index, None) {
+                let mut inode = self.metadata.load_inode(dir.ino)?;
+                inode.subdirs = subdirs;
+                self.metadata.save_inode(&inode)?;
+                self.repaired(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// The root's lost+found directory, created if needed
+    fn lost_found(&mut self) -> Result<u64> {
+        if let Some(existing) = self.metadata.find_child(ROOT_INO, LOST_FOUND)? {
+            if existing.file_type == FileType::Directory {
+                return Ok(existing.ino);
+            }
+        }
+        let mut dir = Inode::new_dir(self.metadata.allocate_ino()?, ROOT_INO, LOST_FOUND.to_string());
+        dir.generation = self.metadata.generation();
+        dir.mode = 0o700;
+        self.metadata.save_inode(&dir)?;
+        Ok(dir.ino)
+    }
+
+    fn check_dir_index(&mut self) -> Result<()> {
+        let dir_index = self.metadata.check_dir_index(false)?;
+        let mut indexes = Vec::new();
+        for entry in &dir_index.stale {
+            indexes.push(self.record(
+                FindingKind::StaleDirEntry,
+                format!("ino {}", entry.parent_ino),
+                format!("entry {:?} names inode {} which does not match", entry.name, entry.ino),
+            ));
+        }
+        for entry in &dir_index.missing {
+            indexes.push(self.record(
+                FindingKind::MissingDirEntry,
+                format!("ino {}", entry.parent_ino),
+                format!("inode {} ({:?}) is not listed", entry.ino, entry.name),
+            ));
+        }
+        let mut repair = false;
+        for index in &indexes {
+            repair |= self.should_repair(*index, None);
+        }
+        if repair {
+            self.metadata.check_dir_index(true)?;
+            for index in indexes {
+                self.repaired(index);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_extent_maps(&mut self, inodes: &BTreeMap<u64, Option<Inode>>) -> Result<ExtentRefs> {
+        let mut refs = ExtentRefs { referenced: HashSet::new(), incomplete: false };
+        // Extents only a snapshot still lists are pinned, not unreferenced
+        match crate::snapshots::captured_extents(&self.pool_dir) {
+            Ok(captured) => refs.referenced.extend(captured),
+            Err(e) => {
+                self.record(FindingKind::CorruptExtentMap, "snapshots".to_string(), format!("{:#}", e));
+                refs.incomplete = true;
+            }
+        }
+        for ino in self.metadata.extent_map_inos()? {
+            self.report.extent_maps_checked += 1;
+            let subject = format!("extent map {}", ino);
+            let mut map = match self.metadata.read_extent_map_record(ino) {
+                Ok(map) => map,
+                Err(e) => {
+                    self.record(FindingKind::CorruptExtentMap, subject, format!("{:#}", e));
+                    refs.incomplete = true;
+                    continue;
+                }
+            };
+            if map.ino != ino {
+                self.record(FindingKind::CorruptExtentMap, subject, format!("records ino {}", map.ino));
+                refs.incomplete = true;
+                continue;
+            }
+
+            let missing: Vec<Uuid> = map.data_extents().filter(|uuid| !self.metadata.extent_exists(uuid)).copied().collect();
+            if let Err(mismatch) = map.verify_checksum() {
+                refs.referenced.extend(map.data_extents().copied());
+                if !missing.is_empty() {
+                    self.record(
+                        FindingKind::CorruptExtentMap,
+                        subject,
+                        format!("{}; {} listed extents no longer exist", mismatch, missing.len()),
+                    );
+                    continue;
+                }
+                let index = self.record(FindingKind::StaleExtentMapChecksum, subject.clone(), mismatch.to_string());
+                if self.should_repair(index, None) {
+                    self.metadata.save_extent_map(&map)?;
+                    self.repaired(index);
+                }
+            }
+
+            if !inodes.contains_key(&ino) {
+                let index = self.record(
+                    FindingKind::OrphanedExtentMap,
+                    subject,
+                    format!("inode {} does not exist; {} extents listed", ino, map.data_extents().count()),
+                );
+                if self.should_repair(index, None) {
+                    // Its extents become unreferenced and are released below
+                    self.metadata.delete_extent_map(ino)?;
+                    self.repaired(index);
+                    continue;
+                }
+            }
+            refs.referenced.extend(map.data_extents().copied());
+
+            if !missing.is_empty() && map.verify_checksum().is_ok() {
+                let index = self.record(
+                    FindingKind::DanglingExtentRef,
+                    format!("ino {}", ino),
+                    format!("{} listed extents do not exist, e.g. {}", missing.len(), missing[0]),
+                );
+                if self.should_repair(index, None) {
+                    for slot in map.extents.iter_mut().filter(|uuid| missing.contains(uuid)) {
+                        *slot = ExtentMap::HOLE;
+                    }
+                    self.metadata.save_extent_map(&map)?;
+                    self.repaired(index);
+                }
+            }
+        }
+        Ok(refs)
+    }
+
+    fn check_extents(&mut self, disks: &[Disk], refs: &ExtentRefs, unavailable_disks: usize) -> Result<()> {
+        let released: HashSet<Uuid> = self.metadata.released_extents()?.into_iter().collect();
+        let mut counted = ExtentTotals::default();
+
+        for (uuid, extent) in self.metadata.extent_records()? {
+            self.report.extents_checked += 1;
+            let Ok(mut extent) = extent else {
+                self.record(FindingKind::UnreadableExtent, format!("extent {}", uuid), "record does not parse".to_string());
+                continue;
+            };
+            let subject = format!("extent {}", extent.uuid);
+            if !refs.referenced.contains(&extent.uuid) && !released.contains(&extent.uuid) {
+                let index = self.record(FindingKind::UnreferencedExtent, subject.clone(), "listed by no extent map".to_string());
+                let blocked = refs.incomplete.then_some("some extent maps could not be read");
+                if self.should_repair(index, blocked) {
+                    self.metadata.release_extent(&extent.uuid)?;
+                    self.repaired(index);
+                }
+            }
+
+            let unknown: Vec<Uuid> = extent
+                .fragment_locations
+                .iter()
+                .map(|location| location.disk_uuid)
+                .filter(|disk_uuid| !disks.iter().any(|disk| disk.uuid == *disk_uuid))
+                .collect();
+            if !unknown.is_empty() {
+                let index = self.record(
+                    FindingKind::UnknownDisk,
+                    subject.clone(),
+                    format!("{} fragments on disks not in the pool, e.g. {}", unknown.len(), unknown[0]),
+                );
+                let blocked = (unavailable_disks > 0).then_some("some pool disks could not be loaded");
+                if self.should_repair(index, blocked) {
+                    extent.fragment_locations.retain(|location| !unknown.contains(&location.disk_uuid));
+                    self.metadata.save_extent(&extent)?;
+                    self.repaired(index);
+                }
+            }
+            counted.add(&extent);
+
+            let present = extent
+                .fragment_locations
+                .iter()
+                .filter(|location| {
+                    disks
+                        .iter()
+                        .find(|disk| disk.uuid == location.disk_uuid)
+                        .is_some_and(|disk| fragment_present(disk, &extent, location))
+                })
+                .map(|location| location.fragment_index)
+                .collect::<HashSet<usize>>()
+                .len();
+            let needed = extent.redundancy.min_fragments();
+            let total = extent.redundancy.fragment_count();
+            if present < needed {
+                self.record(
+                    FindingKind::UnrecoverableExtent,
+                    subject,
+                    format!("{} of {} fragments present, {} needed", present, total, needed),
+                );
+            } else if present < total {
+                self.record(
+                    FindingKind::MissingFragment,
+                    subject,
+                    format!("{} of {} fragments present; `scrub --repair` rebuilds them", present, total),
+                );
+            }
+        }
+
+        let saved = self.metadata.extent_totals();
+        if saved != counted {
+            let index = self.record(
+                FindingKind::StaleExtentTotals,
+                "pool".to_string(),
+                format!("counters record {} extents, {} found", saved.extents, counted.extents),
+            );
+            if self.should_repair(index, None) {
+                self.metadata.recount_extent_totals()?;
+                self.repaired(index);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_disk_contents(&mut self, disks: &mut [Disk]) -> Result<()> {
+        let gc = GarbageCollector::new(self.pool_dir.clone(), disks.to_vec());
+        let mut per_disk: BTreeMap<Uuid, (usize, u64)> = BTreeMap::new();
+        for orphan in gc.detect_orphans()? {
+            // Fragments of unreadable extents are not orphans; the extent is reported instead
+            if self.metadata.extent_exists(&orphan.extent_uuid) {
+                continue;
+            }
+            let entry = per_disk.entry(orphan.disk_uuid).or_default();
+            entry.0 += 1;
+            entry.1 += orphan.size_bytes;
+        }
+        for (disk_uuid, (count, bytes)) in per_disk {
+            self.record(
+                FindingKind::OrphanFragment,
+                format!("disk {}", disk_uuid),
+                format!("{} fragments ({} bytes) belong to no extent; `cleanup-orphans` removes them", count, bytes),
+            );
+        }
+
+        for disk in disks.iter_mut() {
+            let Some(oda) = disk.on_device_allocator.as_mut() else { continue };
+            let unmarked = oda.unmarked_fragments()?;
+            if unmarked.is_empty() {
+                continue;
+            }
+            let index = self.record(
+                FindingKind::UnmarkedDeviceFragment,
+                format!("disk {}", disk.uuid),
+                format!("{} fragments lie in units the bitmap marks free, e.g. unit {}", unmarked.len(), unmarked[0]),
+            );
+            if self.should_repair(index, None) {
+                oda.reconcile_and_persist()?;
+                self.repaired(index);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the fragment a location records is on its disk
+fn fragment_present(disk: &Disk, extent: &Extent, location: &crate::extent::FragmentLocation) -> bool {
+    match disk.kind {
+        DiskKind::Directory => disk.fragment_path(&extent.uuid, location.fragment_index).exists(),
+        DiskKind::BlockDevice => match (&disk.on_device_allocator, &location.on_device) {
+            (Some(oda), Some(placement)) => oda.read_fragment_at(placement.start_unit).is_ok_and(|(header, _)| {
+                header.extent_uuid == extent.uuid && header.fragment_index as usize == location.fragment_index
+            }),
+            // Without placement details the fragment cannot be located; assume it is there
+            _ => true,
+        },
+    }
+}
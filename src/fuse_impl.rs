@@ -9,21 +9,36 @@
 #[cfg(not(target_os = "windows"))]
 use fuser::{
     FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEntry,
-    ReplyWrite, Request, TimeOrNow, ReplyXattr, ReplyLock, ReplyOpen,
+    ReplyWrite, Request, TimeOrNow, ReplyXattr, ReplyLock, ReplyOpen, ReplyStatfs,
 };
 #[cfg(not(target_os = "windows"))]
 use libc::{EEXIST, ENOENT, ENOTDIR, ENODATA, ERANGE, ENOSYS};
 #[cfg(not(target_os = "windows"))]
 use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 #[cfg(not(target_os = "windows"))]
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_os = "windows"))]
+use std::sync::Arc;
+#[cfg(not(target_os = "windows"))]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[cfg(not(target_os = "windows"))]
-use crate::metadata::FileType as InodeFileType;
+use crate::error::StorageError;
+#[cfg(not(target_os = "windows"))]
+use crate::metadata::{now_timespec, FileName, FileType as InodeFileType};
+#[cfg(not(target_os = "windows"))]
+use crate::fs_interface::{
+    is_nocache, parse_on_off, FilesystemInterface, LAYOUT_XATTR, NOCACHE_XATTR, REDUNDANCY_XATTR, VERIFY_WRITES_XATTR,
+};
 #[cfg(not(target_os = "windows"))]
-use crate::fs_interface::FilesystemInterface;
+use crate::permissions::{self, RequestContext};
 #[cfg(not(target_os = "windows"))]
 use crate::file_locks::{LockManager, FileLock, LockType};
+#[cfg(not(target_os = "windows"))]
+use crate::metrics::{FuseOp, Metrics};
+#[cfg(not(target_os = "windows"))]
+use crate::fuse_optimizations::ReadAhead;
 #[cfg(target_os = "macos")]
 use crate::macos::MacOSHandler;
 
@@ -31,47 +46,151 @@ use crate::macos::MacOSHandler;
 const MAX_XATTR_SIZE: usize = 64 * 1024; // 64KB max xattr size
 #[cfg(not(target_os = "windows"))]
 const MAX_XATTR_NAME: usize = 255;
+#[cfg(not(target_os = "windows"))]
+const STATFS_BLOCK_SIZE: u64 = 4096;
+/// Most bytes one `copy_file_range` copies through; callers loop on short copies
+#[cfg(not(target_os = "windows"))]
+const COPY_CHUNK: u64 = 16 * 1024 * 1024;
+
+/// State of an open file behind a FUSE file handle
+#[cfg(not(target_os = "windows"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OpenFile {
+    pub ino: u64,
+    /// Flags passed to open(2)
+    pub flags: i32,
+    /// The file had `NOCACHE_XATTR` set when it was opened
+    pub nocache: bool,
+}
+
+/// Records the latency of a FUSE operation when dropped, after the handler has replied
+#[cfg(not(target_os = "windows"))]
+struct OpTimer {
+    metrics: Option<Arc<Metrics>>,
+    op: FuseOp,
+    started: Instant,
+}
+
+#[cfg(not(target_os = "windows"))]
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_fuse_op(self.op, self.started.elapsed());
+        }
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+impl OpenFile {
+    fn is_append(&self) -> bool {
+        self.flags & libc::O_APPEND != 0
+    }
+
+    /// Reads skip the data cache and read-ahead; see `FilesystemInterface::read_range_uncached`
+    fn is_uncached(&self) -> bool {
+        // macOS has no O_DIRECT open flag
+        #[cfg(target_os = "linux")]
+        let direct = self.flags & libc::O_DIRECT != 0;
+        #[cfg(not(target_os = "linux"))]
+        let direct = false;
+        direct || self.nocache
+    }
+}
+
+/// Supplementary groups of process `pid`, or none if they cannot be read
+#[cfg(target_os = "linux")]
+fn process_groups(pid: u32) -> Vec<u32> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .map(|groups| groups.split_whitespace().filter_map(|gid| gid.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// `SystemTime` of a time stored as seconds and nanoseconds since the epoch
+#[cfg(not(target_os = "windows"))]
+fn system_time((secs, nsec): (i64, u32)) -> SystemTime {
+    let whole = Duration::from_secs(secs.unsigned_abs());
+    let base = if secs >= 0 { UNIX_EPOCH + whole } else { UNIX_EPOCH - whole };
+    base + Duration::from_nanos(nsec as u64)
+}
+
+/// Seconds and nanoseconds since the epoch of `time`, which may predate it
+#[cfg(not(target_os = "windows"))]
+fn timespec(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        // 1.25s before the epoch is -2s plus 0.75s
+        Err(e) => match e.duration() {
+            before if before.subsec_nanos() == 0 => (-(before.as_secs() as i64), 0),
+            before => (-(before.as_secs() as i64) - 1, 1_000_000_000 - before.subsec_nanos()),
+        },
+    }
+}
 
 #[cfg(not(target_os = "windows"))]
 pub struct DynamicFS {
-    pub(crate) storage: Box<dyn FilesystemInterface + Send + Sync>,
+    /// Shared so a failed mount can be retried with the same storage
+    pub(crate) storage: Arc<dyn FilesystemInterface + Send + Sync>,
     pub(crate) lock_manager: LockManager,
     #[cfg(target_os = "macos")]
     pub(crate) macos_handler: MacOSHandler,
     pub(crate) xattr_cache: Option<crate::fuse_optimizations::XAttrCache>,
     pub(crate) readahead_manager: Option<crate::fuse_optimizations::ReadAheadManager>,
     pub(crate) config: Option<crate::fuse_optimizations::OptimizedFUSEConfig>,
+    /// Open files by file handle
+    pub(crate) handles: HashMap<u64, OpenFile>,
+    next_fh: u64,
+    /// Number of handles open on each inode
+    open_counts: HashMap<u64, usize>,
+    /// Unlinked while open; deleted when their last handle is released
+    unlinked_open: HashSet<u64>,
+    /// Where operation latencies go, if the storage keeps metrics
+    metrics: Option<Arc<Metrics>>,
 }
 
 #[cfg(not(target_os = "windows"))]
 impl DynamicFS {
     pub fn new(storage: Box<dyn FilesystemInterface + Send + Sync>) -> Self {
+        let metrics = storage.metrics();
         DynamicFS { 
-            storage,
+            storage: Arc::from(storage),
             lock_manager: LockManager::new(),
             #[cfg(target_os = "macos")]
             macos_handler: MacOSHandler::new(),
             xattr_cache: None,
             readahead_manager: None,
             config: None,
+            handles: HashMap::new(),
+            next_fh: 1,
+            open_counts: HashMap::new(),
+            unlinked_open: HashSet::new(),
+            metrics,
         }
     }
     
     pub fn new_with_config(
-        storage: Box<dyn FilesystemInterface + Send + Sync>,
+        storage: Arc<dyn FilesystemInterface + Send + Sync>,
         config: crate::fuse_optimizations::OptimizedFUSEConfig,
     ) -> Self {
         let xattr_cache = Some(crate::fuse_optimizations::XAttrCache::new(config.clone()));
         let readahead_manager = Some(crate::fuse_optimizations::ReadAheadManager::new(config.clone()));
+        let metrics = storage.metrics();
         
         DynamicFS { 
             storage,
-            lock_manager: LockManager::new(),
+            lock_manager: LockManager::with_writer_priority(config.lock_writer_priority),
             #[cfg(target_os = "macos")]
             macos_handler: MacOSHandler::new(),
             xattr_cache,
             readahead_manager,
             config: Some(config),
+            handles: HashMap::new(),
+            next_fh: 1,
+            open_counts: HashMap::new(),
+            unlinked_open: HashSet::new(),
+            metrics,
         }
     }
     
@@ -79,23 +198,75 @@ impl DynamicFS {
         &self.lock_manager
     }
     
+    /// How long the kernel may cache attributes; the storage's inode cache TTL when mounted
+    fn attr_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.as_ref().map_or(1, |config| config.attr_timeout_secs))
+    }
+    
+    /// How long the kernel may cache a name lookup
+    fn entry_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.as_ref().map_or(1, |config| config.entry_timeout_secs))
+    }
+    
+    /// Start timing an operation; the latency is recorded when the timer is dropped
+    fn time_op(&self, op: FuseOp) -> OpTimer {
+        OpTimer { metrics: self.metrics.clone(), op, started: Instant::now() }
+    }
+    
+    /// Allocate a file handle for an open of `ino`
+    ///
+    /// Returns the handle and the FOPEN flags to reply with. Appending handles
+    /// bypass the page cache: the kernel picks write offsets from its cached
+    /// size, but the data lands wherever the end of file really is. So do
+    /// uncached ones, whose data would otherwise fill it.
+    fn open_handle(&mut self, ino: u64, flags: i32) -> (u64, u32) {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        let nocache = self.storage.get_inode(ino).is_ok_and(|inode| is_nocache(&inode));
+        let file = OpenFile { ino, flags, nocache };
+        self.handles.insert(fh, file);
+        *self.open_counts.entry(ino).or_insert(0) += 1;
+        let open_flags = if file.is_append() || file.is_uncached() { fuser::consts::FOPEN_DIRECT_IO } else { 0 };
+        (fh, open_flags)
+    }
+    
+    /// Free a file handle; returns how many handles remain open on its inode
+    fn close_handle(&mut self, fh: u64) -> usize {
+        let Some(file) = self.handles.remove(&fh) else {
+            return 0;
+        };
+        match self.open_counts.get_mut(&file.ino) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                *count
+            }
+            _ => {
+                self.open_counts.remove(&file.ino);
+                0
+            }
+        }
+    }
+    
     fn inode_to_file_attr(&self, inode: &crate::metadata::Inode) -> FileAttr {
         let kind = match inode.file_type {
             InodeFileType::RegularFile => FileType::RegularFile,
             InodeFileType::Directory => FileType::Directory,
         };
         
+        // Holes punched into the file take no space
+        let allocated = self.storage.allocated_size(inode.ino).unwrap_or(inode.size);
+        
         FileAttr {
             ino: inode.ino,
             size: inode.size,
-            blocks: (inode.size + 511) / 512,
-            atime: UNIX_EPOCH + Duration::from_secs(inode.atime as u64),
-            mtime: UNIX_EPOCH + Duration::from_secs(inode.mtime as u64),
-            ctime: UNIX_EPOCH + Duration::from_secs(inode.ctime as u64),
-            crtime: UNIX_EPOCH + Duration::from_secs(inode.ctime as u64),
+            blocks: allocated.div_ceil(512),
+            atime: system_time((inode.atime, inode.atime_nsec)),
+            mtime: system_time((inode.mtime, inode.mtime_nsec)),
+            ctime: system_time((inode.ctime, inode.ctime_nsec)),
+            crtime: system_time(inode.crtime()),
             kind,
             perm: inode.mode as u16,
-            nlink: 1,
+            nlink: inode.nlink(),
             uid: inode.uid,
             gid: inode.gid,
             rdev: 0,
@@ -103,48 +274,253 @@ impl DynamicFS {
             flags: 0,
         }
     }
+    
+    /// Map a failed blocking lock request to EDEADLK, EINTR or EIO
+    fn lock_errno(err: &anyhow::Error) -> i32 {
+        match err.downcast_ref::<std::io::Error>().map(|io_err| io_err.kind()) {
+            Some(std::io::ErrorKind::Deadlock) => libc::EDEADLK,
+            Some(std::io::ErrorKind::Interrupted) => libc::EINTR,
+            _ => libc::EIO,
+        }
+    }
+
+    /// The caller of a request, with its supplementary groups read from
+    /// `/proc/<pid>/status` the first time a check needs them
+    fn request_context(req: &Request) -> RequestContext {
+        let ctx = RequestContext::new(req.uid(), req.gid());
+        #[cfg(target_os = "linux")]
+        let ctx = {
+            let pid = req.pid();
+            let groups = std::sync::OnceLock::new();
+            ctx.with_groups(Arc::new(move |gid| groups.get_or_init(|| process_groups(pid)).contains(&gid)))
+        };
+        ctx
+    }
+
+    /// Check that the caller may access `ino` as `mask`; returns the errno to reply with
+    fn check_access(&self, req: &Request, ino: u64, mask: u32) -> Result<(), i32> {
+        self.storage
+            .access(&Self::request_context(req), ino, mask)
+            .map_err(|e| StorageError::errno_of(&e))
+    }
+
+    /// Check that `inode` may move into directory `newparent`, replacing `replaced` if any
+    ///
+    /// Follows rename(2): a directory may only replace an empty directory and
+    /// only a directory may replace one, and no directory may move beneath itself.
+    fn check_rename_target(
+        &self,
+        inode: &crate::metadata::Inode,
+        newparent: u64,
+        replaced: Option<&crate::metadata::Inode>,
+        flags: u32,
+    ) -> Result<(), i32> {
+        let is_dir = inode.file_type == InodeFileType::Directory;
+        if let Some(existing) = replaced {
+            if flags & libc::RENAME_NOREPLACE != 0 {
+                return Err(EEXIST);
+            }
+            match (is_dir, existing.file_type == InodeFileType::Directory) {
+                (true, false) => return Err(ENOTDIR),
+                (false, true) => return Err(libc::EISDIR),
+                (true, true) => {
+                    let children = self.storage.list_directory(existing.ino).map_err(|e| StorageError::errno_of(&e))?;
+                    if !children.is_empty() {
+                        return Err(libc::ENOTEMPTY);
+                    }
+                }
+                (false, false) => {}
+            }
+        }
+        if is_dir {
+            let mut ancestor = newparent;
+            while ancestor != 1 {
+                if ancestor == inode.ino {
+                    return Err(libc::EINVAL);
+                }
+                ancestor = self.storage.get_inode(ancestor).map_err(|e| StorageError::errno_of(&e))?.parent_ino;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the caller may remove `child` from directory `parent`
+    fn check_remove(&self, req: &Request, parent: u64, child: &crate::metadata::Inode) -> Result<(), i32> {
+        // Nothing under `/.snapshots` can be unlinked or renamed, whoever asks
+        if crate::snapshots::is_snapshot_ino(parent) || crate::snapshots::is_snapshot_ino(child.ino) {
+            return Err(libc::EROFS);
+        }
+        let dir = self.storage.get_inode(parent).map_err(|e| StorageError::errno_of(&e))?;
+        permissions::check_remove(&dir, child, &Self::request_context(req)).map_err(|_| libc::EACCES)
+    }
+
+    /// Access an open(2) with `flags` needs
+    fn open_mask(flags: i32) -> u32 {
+        let mask = match flags & libc::O_ACCMODE {
+            libc::O_WRONLY => permissions::WRITE,
+            libc::O_RDWR => permissions::READ | permissions::WRITE,
+            _ => permissions::READ,
+        };
+        if flags & libc::O_TRUNC != 0 {
+            mask | permissions::WRITE
+        } else {
+            mask
+        }
+    }
+
+    /// Check a read or write of `ino` through handle `fh`
+    ///
+    /// A handle was checked when it was opened and keeps the access it was
+    /// opened with, as after chmod on an open file. Requests without one of
+    /// our handles are checked against the inode's mode.
+    fn check_io(&self, req: &Request, ino: u64, fh: Option<u64>, mask: u32) -> Result<(), i32> {
+        match fh.and_then(|fh| self.handles.get(&fh)) {
+            Some(file) if file.ino == ino => {
+                if Self::open_mask(file.flags & libc::O_ACCMODE) & mask == mask {
+                    Ok(())
+                } else {
+                    Err(libc::EBADF)
+                }
+            }
+            _ => self.check_access(req, ino, mask),
+        }
+    }
+
+    /// Check a chmod/chown request against the inode's ownership
+    ///
+    /// Follows chmod(2)/chown(2): only root or the owner may change the mode,
+    /// only root may hand the inode to another user, and the owner may only
+    /// change the group to their own primary group. Returns the errno to reply with.
+    fn check_attr_change(
+        inode: &crate::metadata::Inode,
+        caller_uid: u32,
+        caller_gid: u32,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), i32> {
+        if caller_uid == 0 {
+            return Ok(());
+        }
+        let is_owner = caller_uid == inode.uid;
+        if mode.is_some() && !is_owner {
+            return Err(libc::EPERM);
+        }
+        if uid.is_some_and(|uid| uid != inode.uid) {
+            return Err(libc::EPERM);
+        }
+        if gid.is_some_and(|gid| gid != inode.gid && (!is_owner || gid != caller_gid)) {
+            return Err(libc::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Check a utimensat request against the inode's ownership and mode
+    ///
+    /// Setting explicit times needs ownership; setting both to the current
+    /// time, as `touch` does, also works with write access. Returns the errno to reply with.
+    fn check_times_change(
+        inode: &crate::metadata::Inode,
+        ctx: &RequestContext,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+    ) -> Result<(), i32> {
+        if (atime.is_none() && mtime.is_none()) || ctx.is_root() || ctx.uid == inode.uid {
+            return Ok(());
+        }
+        if [atime, mtime].iter().any(|time| matches!(time, Some(TimeOrNow::SpecificTime(_)))) {
+            return Err(libc::EPERM);
+        }
+        permissions::check_access(inode, ctx, permissions::WRITE).map_err(|_| libc::EACCES)
+    }
+
+    /// Flush an inode durably and answer an fsync/fsyncdir request
+    fn sync_reply(&self, ino: u64, reply: fuser::ReplyEmpty) {
+        if let Err(e) = self.storage.get_inode(ino) {
+            reply.error(StorageError::errno_of(&e));
+            return;
+        }
+
+        match self.storage.sync_inode(ino) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                log::error!("sync of inode {} failed: {}", ino, e);
+                reply.error(StorageError::errno_of(&e));
+            }
+        }
+    }
+
+    /// Entries a readdir resuming after cookie `offset` should return, in cookie order
+    ///
+    /// `.` and `..` have cookies 1 and 2. A child's cookie is its inode number
+    /// plus one: inode numbers are never reused and new files get larger ones, so
+    /// creating or deleting entries between calls cannot shift the cookies of the
+    /// others and a resumed listing neither repeats nor skips them.
+    fn readdir_entries(
+        ino: u64,
+        parent_ino: u64,
+        mut children: Vec<crate::metadata::Inode>,
+        offset: i64,
+    ) -> Vec<(u64, i64, FileType, FileName)> {
+        children.sort_by_key(|child| child.ino);
+
+        let mut entries = vec![
+            (ino, 1, FileType::Directory, FileName::from(".")),
+            (parent_ino, 2, FileType::Directory, FileName::from("..")),
+        ];
+        entries.extend(children.into_iter().map(|child| {
+            let kind = match child.file_type {
+                InodeFileType::RegularFile => FileType::RegularFile,
+                InodeFileType::Directory => FileType::Directory,
+            };
+            (child.ino, child.ino as i64 + 1, kind, child.name)
+        }));
+        entries.retain(|(_, cookie, _, _)| *cookie > offset);
+        entries
+    }
 }
 
 impl Filesystem for DynamicFS {
+    fn destroy(&mut self) {
+        log::info!("destroy: flushing buffered data");
+        if let Err(e) = self.storage.sync_all() {
+            log::error!("flush on destroy failed: {}", e);
+        }
+    }
+    
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         log::debug!("lookup(parent={}, name={:?})", parent, name);
+        let _timer = self.time_op(FuseOp::Lookup);
         
-        let name_str = match name.to_str() {
-            Some(s) => s,
-            None => {
-                reply.error(ENOENT);
-                return;
-            }
-        };
-        
-        match self.storage.find_child(parent, name_str) {
+        match self.storage.find_child(parent, name.as_bytes()) {
             Ok(Some(inode)) => {
                 let attr = self.inode_to_file_attr(&inode);
-                let ttl = Duration::from_secs(1);
-                reply.entry(&ttl, &attr, 0);
+                let ttl = self.entry_ttl();
+                reply.entry(&ttl, &attr, inode.generation);
             }
             Ok(None) => {
                 reply.error(ENOENT);
             }
             Err(e) => {
                 log::error!("lookup failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
     
     fn getattr(&mut self, _req: &Request, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         log::debug!("getattr(ino={})", ino);
+        let _timer = self.time_op(FuseOp::Getattr);
         
         match self.storage.get_inode(ino) {
             Ok(inode) => {
                 let attr = self.inode_to_file_attr(&inode);
-                let ttl = Duration::from_secs(1);
+                let ttl = self.attr_ttl();
                 reply.attr(&ttl, &attr);
             }
             Err(e) => {
                 log::error!("getattr failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
@@ -158,45 +534,22 @@ impl Filesystem for DynamicFS {
         mut reply: ReplyDirectory,
     ) {
         log::debug!("readdir(ino={}, offset={})", ino, offset);
+        let _timer = self.time_op(FuseOp::Readdir);
         
-        let entries = match self.storage.list_directory(ino) {
+        let children = match self.storage.list_directory(ino) {
             Ok(e) => e,
             Err(e) => {
                 log::error!("readdir failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
+        let parent_ino = self.storage.get_inode(ino).map(|inode| inode.parent_ino).unwrap_or(ino);
         
-        let mut idx = offset as usize;
-        
-        // Add . and ..
-        if idx == 0 {
-            if reply.add(ino, 1, FileType::Directory, ".") {
-                reply.ok();
-                return;
-            }
-            idx += 1;
-        }
-        
-        if idx == 1 {
-            if let Ok(inode) = self.storage.get_inode(ino) {
-                if reply.add(inode.parent_ino, 2, FileType::Directory, "..") {
-                    reply.ok();
-                    return;
-                }
-            }
-            idx += 1;
-        }
-        
-        // Add actual entries
-        for (i, entry) in entries.iter().enumerate().skip(idx.saturating_sub(2)) {
-            let kind = match entry.file_type {
-                InodeFileType::RegularFile => FileType::RegularFile,
-                InodeFileType::Directory => FileType::Directory,
-            };
-            
-            if reply.add(entry.ino, (i + 3) as i64, kind, &entry.name) {
+        // Each entry is added with its own cookie; the kernel passes back the
+        // cookie of the last entry it consumed and we resume strictly after it
+        for (entry_ino, cookie, kind, name) in Self::readdir_entries(ino, parent_ino, children, offset) {
+            if reply.add(entry_ino, cookie, kind, &name) {
                 break;
             }
         }
@@ -206,9 +559,9 @@ impl Filesystem for DynamicFS {
     
     fn read(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         size: u32,
         _flags: i32,
@@ -216,30 +569,49 @@ impl Filesystem for DynamicFS {
         reply: ReplyData,
     ) {
         log::debug!("read(ino={}, offset={}, size={})", ino, offset, size);
+        let _timer = self.time_op(FuseOp::Read);
         
-        match self.storage.read_file(ino) {
+        if let Err(errno) = self.check_io(req, ino, Some(fh), permissions::READ) {
+            reply.error(errno);
+            return;
+        }
+        
+        let offset = offset.max(0) as u64;
+        let uncached = self.handles.get(&fh).is_some_and(|file| file.is_uncached());
+        let data = if uncached {
+            self.storage.read_range_uncached(ino, offset, size as u64)
+        } else {
+            self.storage.read_range(ino, offset, size as u64)
+        };
+        let read = match data {
             Ok(data) => {
-                let start = offset as usize;
-                let end = (start + size as usize).min(data.len());
-                
-                if start < data.len() {
-                    reply.data(&data[start..end]);
-                } else {
-                    reply.data(&[]);
-                }
+                reply.data(&data);
+                let read = data.len();
+                self.storage.recycle_read_buffer(data);
+                read
             }
             Err(e) => {
                 log::error!("read failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
+                return;
+            }
+        };
+        
+        // Keep the extents after a sequential reader decoded ahead of it
+        if let Some(manager) = self.readahead_manager.as_ref().filter(|_| !uncached) {
+            match manager.record_access(fh, ino, offset, read) {
+                ReadAhead::Prefetch(hint) => self.storage.prefetch(hint.ino, hint.offset, hint.extents),
+                ReadAhead::Cancel => self.storage.cancel_prefetch(ino),
+                ReadAhead::Idle => {}
             }
         }
     }
     
     fn write(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         data: &[u8],
         _write_flags: u32,
@@ -248,16 +620,28 @@ impl Filesystem for DynamicFS {
         reply: ReplyWrite,
     ) {
         log::debug!("write(ino={}, offset={}, size={})", ino, offset, data.len());
+        let _timer = self.time_op(FuseOp::Write);
+        
+        if let Err(errno) = self.check_io(req, ino, Some(fh), permissions::WRITE) {
+            reply.error(errno);
+            return;
+        }
         
-        // For this prototype, we only support full rewrites at offset 0
-        // A production system would handle partial writes
-        match self.storage.write_file(ino, data, offset as u64) {
+        // Small sequential writes are coalesced in the write buffer; flush on
+        // close() writes them out and fsync makes them durable. O_APPEND writes
+        // ignore the kernel's offset and go to the end of file as it is now.
+        let written = if self.handles.get(&fh).is_some_and(|file| file.ino == ino && file.is_append()) {
+            self.storage.append_write(ino, data).map(|_| ())
+        } else {
+            self.storage.buffered_write(ino, offset as u64, data)
+        };
+        match written {
             Ok(()) => {
                 reply.written(data.len() as u32);
             }
             Err(e) => {
                 log::error!("write failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
@@ -267,23 +651,16 @@ impl Filesystem for DynamicFS {
         req: &Request,
         parent: u64,
         name: &OsStr,
-        _mode: u32,
-        _umask: u32,
-        _flags: i32,
+        mode: u32,
+        umask: u32,
+        flags: i32,
         reply: ReplyCreate,
     ) {
         log::debug!("create(parent={}, name={:?})", parent, name);
-        
-        let name_str = match name.to_str() {
-            Some(s) => s.to_string(),
-            None => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
+        let _timer = self.time_op(FuseOp::Create);
         
         // Check if already exists
-        match self.storage.find_child(parent, &name_str) {
+        match self.storage.find_child(parent, name.as_bytes()) {
             Ok(Some(_)) => {
                 reply.error(EEXIST);
                 return;
@@ -291,20 +668,23 @@ impl Filesystem for DynamicFS {
             Ok(None) => {}
             Err(e) => {
                 log::error!("create check failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         }
         
-        match self.storage.create_file(parent, name_str) {
+        let created = self.storage.create_file_as(&Self::request_context(req), parent, FileName::from(name), mode & !umask);
+        
+        match created {
             Ok(inode) => {
                 let attr = self.inode_to_file_attr(&inode);
-                let ttl = Duration::from_secs(1);
-                reply.created(&ttl, &attr, 0, 0, 0);
+                let ttl = self.entry_ttl();
+                let (fh, open_flags) = self.open_handle(inode.ino, flags);
+                reply.created(&ttl, &attr, inode.generation, fh, open_flags);
             }
             Err(e) => {
                 log::error!("create failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
@@ -314,22 +694,14 @@ impl Filesystem for DynamicFS {
         req: &Request,
         parent: u64,
         name: &OsStr,
-        _mode: u32,
-        _umask: u32,
+        mode: u32,
+        umask: u32,
         reply: ReplyEntry,
     ) {
         log::debug!("mkdir(parent={}, name={:?})", parent, name);
         
-        let name_str = match name.to_str() {
-            Some(s) => s.to_string(),
-            None => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
-        
         // Check if already exists
-        match self.storage.find_child(parent, &name_str) {
+        match self.storage.find_child(parent, name.as_bytes()) {
             Ok(Some(_)) => {
                 reply.error(EEXIST);
                 return;
@@ -337,37 +709,32 @@ impl Filesystem for DynamicFS {
             Ok(None) => {}
             Err(e) => {
                 log::error!("mkdir check failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         }
         
-        match self.storage.create_dir(parent, name_str) {
+        let created = self.storage.create_dir_as(&Self::request_context(req), parent, FileName::from(name), mode & !umask);
+        
+        match created {
             Ok(inode) => {
                 let attr = self.inode_to_file_attr(&inode);
-                let ttl = Duration::from_secs(1);
-                reply.entry(&ttl, &attr, 0);
+                let ttl = self.entry_ttl();
+                reply.entry(&ttl, &attr, inode.generation);
             }
             Err(e) => {
                 log::error!("mkdir failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
     
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
         log::debug!("unlink(parent={}, name={:?})", parent, name);
-        
-        let name_str = match name.to_str() {
-            Some(s) => s,
-            None => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
+        let _timer = self.time_op(FuseOp::Unlink);
         
         // Find the file
-        let inode = match self.storage.find_child(parent, name_str) {
+        let inode = match self.storage.find_child(parent, name.as_bytes()) {
             Ok(Some(i)) => i,
             Ok(None) => {
                 reply.error(ENOENT);
@@ -375,34 +742,38 @@ impl Filesystem for DynamicFS {
             }
             Err(e) => {
                 log::error!("unlink lookup failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
         
-        // Delete the file
-        match self.storage.delete_file(inode.ino) {
+        if let Err(errno) = self.check_remove(req, parent, &inode) {
+            reply.error(errno);
+            return;
+        }
+        
+        // An open file keeps its data until the last handle goes away
+        let removed = if self.open_counts.contains_key(&inode.ino) {
+            self.storage.orphan_file(inode.ino).map(|()| {
+                self.unlinked_open.insert(inode.ino);
+            })
+        } else {
+            self.storage.delete_file(inode.ino)
+        };
+        match removed {
             Ok(()) => reply.ok(),
             Err(e) => {
                 log::error!("unlink failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
     
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
         log::debug!("rmdir(parent={}, name={:?})", parent, name);
         
-        let name_str = match name.to_str() {
-            Some(s) => s,
-            None => {
-                reply.error(libc::EINVAL);
-                return;
-            }
-        };
-        
         // Find the directory
-        let inode = match self.storage.find_child(parent, name_str) {
+        let inode = match self.storage.find_child(parent, name.as_bytes()) {
             Ok(Some(i)) => i,
             Ok(None) => {
                 reply.error(ENOENT);
@@ -410,7 +781,7 @@ impl Filesystem for DynamicFS {
             }
             Err(e) => {
                 log::error!("rmdir lookup failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
@@ -421,6 +792,11 @@ impl Filesystem for DynamicFS {
             return;
         }
         
+        if let Err(errno) = self.check_remove(req, parent, &inode) {
+            reply.error(errno);
+            return;
+        }
+        
         // Check if it's empty
         match self.storage.list_directory(inode.ino) {
             Ok(children) if !children.is_empty() => {
@@ -430,7 +806,7 @@ impl Filesystem for DynamicFS {
             Ok(_) => {}
             Err(e) => {
                 log::error!("rmdir check failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         }
@@ -440,23 +816,106 @@ impl Filesystem for DynamicFS {
             Ok(()) => reply.ok(),
             Err(e) => {
                 log::error!("rmdir failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
+            }
+        }
+    }
+    
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        log::debug!("rename(parent={}, name={:?}, newparent={}, newname={:?})", parent, name, newparent, newname);
+        
+        // Swapping two entries is not supported
+        if flags & !libc::RENAME_NOREPLACE != 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        
+        let lookup = |fs: &Self, parent: u64, name: &OsStr| fs.storage.find_child(parent, name.as_bytes()).map_err(|e| {
+            log::error!("rename lookup failed: {}", e);
+            StorageError::errno_of(&e)
+        });
+        let mut inode = match lookup(self, parent, name) {
+            Ok(Some(inode)) => inode,
+            Ok(None) => {
+                reply.error(ENOENT);
+                return;
+            }
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let replaced = match lookup(self, newparent, newname) {
+            // A case-only rename on a case-insensitive pool finds the entry itself
+            Ok(existing) => existing.filter(|existing| existing.ino != inode.ino),
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        
+        let checked = self
+            .check_remove(req, parent, &inode)
+            .and_then(|()| self.check_access(req, newparent, permissions::WRITE | permissions::EXECUTE))
+            .and_then(|()| match &replaced {
+                Some(existing) => self.check_remove(req, newparent, existing),
+                None => Ok(()),
+            })
+            .and_then(|()| self.check_rename_target(&inode, newparent, replaced.as_ref(), flags));
+        if let Err(errno) = checked {
+            reply.error(errno);
+            return;
+        }
+        
+        // The entry it replaces goes as an unlink or rmdir would
+        if let Some(existing) = replaced {
+            let removed = if self.open_counts.contains_key(&existing.ino) {
+                self.storage.orphan_file(existing.ino).map(|()| {
+                    self.unlinked_open.insert(existing.ino);
+                })
+            } else {
+                self.storage.delete_file(existing.ino)
+            };
+            if let Err(e) = removed {
+                log::error!("rename failed to replace {:?}: {}", newname, e);
+                reply.error(StorageError::errno_of(&e));
+                return;
+            }
+        }
+        
+        inode.parent_ino = newparent;
+        inode.name = FileName::from(newname);
+        inode.set_ctime(now_timespec());
+        match self.storage.update_inode(&inode) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                log::error!("rename failed: {}", e);
+                reply.error(StorageError::errno_of(&e));
             }
         }
     }
     
     fn setattr(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
-        _mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
         size: Option<u64>,
         atime: Option<TimeOrNow>,
         mtime: Option<TimeOrNow>,
         _ctime: Option<SystemTime>,
-        _fh: Option<u64>,
+        fh: Option<u64>,
         _crtime: Option<SystemTime>,
         _chgtime: Option<SystemTime>,
         _bkuptime: Option<SystemTime>,
@@ -469,51 +928,119 @@ impl Filesystem for DynamicFS {
             Ok(i) => i,
             Err(e) => {
                 log::error!("setattr failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
         
-        // Handle truncate
+        // Permission check happens before anything is modified
+        if let Err(errno) = Self::check_attr_change(&inode, req.uid(), req.gid(), mode, uid, gid) {
+            reply.error(errno);
+            return;
+        }
+        if size.is_some() {
+            if let Err(errno) = self.check_io(req, ino, fh, permissions::WRITE) {
+                reply.error(errno);
+                return;
+            }
+        }
+        if let Err(errno) = Self::check_times_change(&inode, &Self::request_context(req), atime, mtime) {
+            reply.error(errno);
+            return;
+        }
+        
+        let now = now_timespec();
+        
+        // Handle truncate
         if let Some(new_size) = size {
             if new_size == 0 {
                 // Truncate to zero: delete all extents
                 if let Err(e) = self.storage.write_file(ino, &[], 0) {
                     log::error!("truncate failed: {}", e);
-                    reply.error(libc::EIO);
+                    reply.error(StorageError::errno_of(&e));
                     return;
                 }
                 inode.size = 0;
+                inode.touch_contents(now);
             } else if new_size != inode.size {
                 log::warn!("Truncate to non-zero size not fully supported");
             }
         }
         
-        // Update times
-        let now = chrono::Utc::now().timestamp();
-        if let Some(_) = atime {
-            inode.atime = now;
+        // Update times: exact values from utimensat/touch -d, the clock for UTIME_NOW
+        let resolve = |time: TimeOrNow| match time {
+            TimeOrNow::SpecificTime(time) => timespec(time),
+            TimeOrNow::Now => now,
+        };
+        if let Some(atime) = atime {
+            inode.set_atime(resolve(atime));
         }
-        if let Some(_) = mtime {
-            inode.mtime = now;
+        if let Some(mtime) = mtime {
+            inode.set_mtime(resolve(mtime));
+        }
+        
+        // chmod/chown
+        if let Some(mode) = mode {
+            inode.mode = mode & 0o7777;
+        }
+        if let Some(uid) = uid {
+            inode.uid = uid;
+        }
+        if let Some(gid) = gid {
+            inode.gid = gid;
+        }
+        if mode.is_some() || uid.is_some() || gid.is_some() || atime.is_some() || mtime.is_some() {
+            inode.set_ctime(now);
         }
         
         if let Err(e) = self.storage.update_inode(&inode) {
             log::error!("setattr update failed: {}", e);
-            reply.error(libc::EIO);
+            reply.error(StorageError::errno_of(&e));
             return;
         }
         
         let attr = self.inode_to_file_attr(&inode);
-        let ttl = Duration::from_secs(1);
+        let ttl = self.attr_ttl();
         reply.attr(&ttl, &attr);
     }
     
+    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        log::debug!("statfs()");
+        
+        // Space figures are usable bytes (already divided by redundancy overhead)
+        let stats = match self.storage.stat() {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("statfs failed: {}", e);
+                reply.error(StorageError::errno_of(&e));
+                return;
+            }
+        };
+        
+        let blocks = stats.total_capacity() / STATFS_BLOCK_SIZE;
+        let bfree = stats.free_space / STATFS_BLOCK_SIZE;
+        
+        // No fixed inode table: advertise one free inode per free block
+        let used_inodes = stats.total_files + stats.total_dirs;
+        let ffree = bfree;
+        
+        reply.statfs(
+            blocks,
+            bfree,
+            bfree,
+            used_inodes + ffree,
+            ffree,
+            STATFS_BLOCK_SIZE as u32,
+            255, // max filename length
+            STATFS_BLOCK_SIZE as u32,
+        );
+    }
+    
     // ===== Extended Attributes =====
     
     fn setxattr(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         name: &OsStr,
         value: &[u8],
@@ -542,6 +1069,42 @@ impl Filesystem for DynamicFS {
             return;
         }
         
+        // Setting any xattr, the control xattrs included, needs write access
+        if let Err(errno) = self.check_access(req, ino, permissions::WRITE) {
+            reply.error(errno);
+            return;
+        }
+        
+        // Layout xattr is generated from the extents on every read
+        if name_str == LAYOUT_XATTR {
+            reply.error(libc::EPERM);
+            return;
+        }
+        
+        if (name_str == VERIFY_WRITES_XATTR || name_str == NOCACHE_XATTR) && parse_on_off(value).is_none() {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        
+        // Redundancy xattr re-encodes the file instead of being stored verbatim
+        if name_str == REDUNDANCY_XATTR {
+            let policy = match std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                Some(p) => p,
+                None => {
+                    reply.error(libc::EINVAL);
+                    return;
+                }
+            };
+            match self.storage.set_redundancy(ino, policy) {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    log::error!("setxattr redundancy change failed: {}", e);
+                    reply.error(StorageError::errno_of(&e));
+                }
+            }
+            return;
+        }
+        
         // macOS-specific xattr handling
         #[cfg(target_os = "macos")]
         if let Err(e) = self.macos_handler.handle_xattr(name_str, Some(value)) {
@@ -555,18 +1118,19 @@ impl Filesystem for DynamicFS {
             Ok(i) => i,
             Err(e) => {
                 log::error!("setxattr failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
         
         // Set the xattr
         inode.set_xattr(name_str.to_string(), value.to_vec());
+        inode.set_ctime(now_timespec());
         
         // Update inode
         if let Err(e) = self.storage.update_inode(&inode) {
             log::error!("setxattr update failed: {}", e);
-            reply.error(libc::EIO);
+            reply.error(StorageError::errno_of(&e));
             return;
         }
         
@@ -575,7 +1139,7 @@ impl Filesystem for DynamicFS {
     
     fn getxattr(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         name: &OsStr,
         size: u32,
@@ -599,18 +1163,47 @@ impl Filesystem for DynamicFS {
             return;
         }
         
+        if let Err(errno) = self.check_access(req, ino, permissions::READ) {
+            reply.error(errno);
+            return;
+        }
+        
         // Get inode
         let inode = match self.storage.get_inode(ino) {
             Ok(i) => i,
             Err(e) => {
                 log::error!("getxattr failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
         
+        // Redundancy xattr reports the policy of the file's extents
+        let synthetic_value = if name_str == REDUNDANCY_XATTR {
+            match self.storage.get_redundancy(ino) {
+                Ok(policy) => policy.map(|p| p.to_string().into_bytes()),
+                Err(e) => {
+                    log::error!("getxattr redundancy lookup failed: {}", e);
+                    reply.error(StorageError::errno_of(&e));
+                    return;
+                }
+            }
+        } else if name_str == LAYOUT_XATTR && inode.file_type == InodeFileType::RegularFile {
+            // Summarized when the full listing would not fit in an xattr
+            match self.storage.describe_layout(ino) {
+                Ok(layout) => layout.map(|layout| layout.to_xattr_value(MAX_XATTR_SIZE)),
+                Err(e) => {
+                    log::error!("getxattr layout lookup failed: {}", e);
+                    reply.error(StorageError::errno_of(&e));
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        
         // Get the xattr
-        match inode.get_xattr(name_str) {
+        match synthetic_value.as_deref().or_else(|| inode.get_xattr(name_str)) {
             Some(value) => {
                 if size == 0 {
                     // Query size
@@ -635,13 +1228,20 @@ impl Filesystem for DynamicFS {
             Ok(i) => i,
             Err(e) => {
                 log::error!("listxattr failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
         
-        // Get all xattr names
-        let names = inode.list_xattrs();
+        // Get all xattr names, advertising the synthetic xattrs on regular files
+        let mut names = inode.list_xattrs();
+        if inode.file_type == InodeFileType::RegularFile {
+            for synthetic in [REDUNDANCY_XATTR, LAYOUT_XATTR] {
+                if !names.iter().any(|n| n == synthetic) {
+                    names.push(synthetic.to_string());
+                }
+            }
+        }
         
         // Build null-terminated list
         let mut list = Vec::new();
@@ -660,7 +1260,7 @@ impl Filesystem for DynamicFS {
         }
     }
     
-    fn removexattr(&mut self, _req: &Request, ino: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+    fn removexattr(&mut self, req: &Request, ino: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
         log::debug!("removexattr(ino={}, name={:?})", ino, name);
         
         let name_str = match name.to_str() {
@@ -671,12 +1271,22 @@ impl Filesystem for DynamicFS {
             }
         };
         
+        if name_str == LAYOUT_XATTR {
+            reply.error(libc::EPERM);
+            return;
+        }
+        
+        if let Err(errno) = self.check_access(req, ino, permissions::WRITE) {
+            reply.error(errno);
+            return;
+        }
+        
         // Get inode
         let mut inode = match self.storage.get_inode(ino) {
             Ok(i) => i,
             Err(e) => {
                 log::error!("removexattr failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
@@ -686,11 +1296,12 @@ impl Filesystem for DynamicFS {
             reply.error(ENODATA);
             return;
         }
+        inode.set_ctime(now_timespec());
         
         // Update inode
         if let Err(e) = self.storage.update_inode(&inode) {
             log::error!("removexattr update failed: {}", e);
-            reply.error(libc::EIO);
+            reply.error(StorageError::errno_of(&e));
             return;
         }
         
@@ -762,7 +1373,7 @@ impl Filesystem for DynamicFS {
         end: u64,
         typ: i32,
         pid: u32,
-        _sleep: bool,
+        sleep: bool,
         reply: fuser::ReplyEmpty,
     ) {
         log::debug!("setlk(ino={}, start={}, end={}, type={})", ino, start, end, typ);
@@ -794,9 +1405,22 @@ impl Filesystem for DynamicFS {
                 end,
             };
             
-            if let Err(e) = self.lock_manager.acquire_lock(ino, lock) {
-                log::error!("lock failed: {}", e);
-                reply.error(libc::EAGAIN);
+            if let Err(e) = self.lock_manager.acquire_lock(ino, lock.clone()) {
+                if !sleep {
+                    log::error!("lock failed: {}", e);
+                    reply.error(libc::EAGAIN);
+                    return;
+                }
+                // F_SETLKW: wait off the session thread, which has to keep
+                // serving the requests that will release the conflicting lock
+                let lock_manager = self.lock_manager.clone();
+                std::thread::spawn(move || match lock_manager.acquire_lock_blocking(ino, lock) {
+                    Ok(()) => reply.ok(),
+                    Err(e) => {
+                        log::debug!("blocking lock on inode {} failed: {}", ino, e);
+                        reply.error(Self::lock_errno(&e));
+                    }
+                });
                 return;
             }
         }
@@ -823,28 +1447,43 @@ impl Filesystem for DynamicFS {
             Ok(i) => i,
             Err(e) => {
                 log::error!("fallocate failed: {}", e);
-                reply.error(ENOENT);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         };
         
-        // Handle punch hole
-        // NOTE: Current implementation returns success but does not actually
-        // create sparse regions. Data remains allocated. This is a known
-        // limitation documented in PHASE_16_COMPLETE.md under "Limitations".
-        // Future enhancement: Implement true sparse file support with extent splitting.
+        if offset < 0 || length <= 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        
+        // Handle punch hole: whole extents in the range are deallocated, partial ones re-encoded
         if mode & libc::FALLOC_FL_PUNCH_HOLE != 0 {
-            log::info!("Punch hole: offset={}, length={} (data remains allocated)", offset, length);
-            reply.ok();
+            // The kernel requires KEEP_SIZE alongside PUNCH_HOLE
+            if mode & libc::FALLOC_FL_KEEP_SIZE == 0 {
+                reply.error(libc::EOPNOTSUPP);
+                return;
+            }
+            match self.storage.punch_hole(ino, offset as u64, length as u64) {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    log::error!("punch hole failed: {}", e);
+                    reply.error(StorageError::errno_of(&e));
+                }
+            }
             return;
         }
         
-        // Handle zero range
-        // NOTE: Similar to punch hole, this claims success but doesn't zero data.
-        // Future enhancement: Actually zero the specified range.
+        // Handle zero range: punched, then the size extended unless KEEP_SIZE is set
         if mode & libc::FALLOC_FL_ZERO_RANGE != 0 {
-            log::info!("Zero range: offset={}, length={} (data remains allocated)", offset, length);
-            reply.ok();
+            let keep_size = mode & libc::FALLOC_FL_KEEP_SIZE != 0;
+            match self.storage.zero_range(ino, offset as u64, length as u64, keep_size) {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    log::error!("zero range failed: {}", e);
+                    reply.error(StorageError::errno_of(&e));
+                }
+            }
             return;
         }
         
@@ -854,7 +1493,7 @@ impl Filesystem for DynamicFS {
             inode.size = new_size;
             if let Err(e) = self.storage.update_inode(&inode) {
                 log::error!("fallocate update failed: {}", e);
-                reply.error(libc::EIO);
+                reply.error(StorageError::errno_of(&e));
                 return;
             }
         }
@@ -862,32 +1501,138 @@ impl Filesystem for DynamicFS {
         reply.ok();
     }
     
+    // ===== Copy File Range =====
+    
+    fn copy_file_range(
+        &mut self,
+        req: &Request,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        log::debug!(
+            "copy_file_range(ino_in={}, offset_in={}, ino_out={}, offset_out={}, len={})",
+            ino_in,
+            offset_in,
+            ino_out,
+            offset_out,
+            len
+        );
+        
+        let checked = self
+            .check_io(req, ino_in, Some(fh_in), permissions::READ)
+            .and_then(|()| self.check_io(req, ino_out, Some(fh_out), permissions::WRITE));
+        if let Err(errno) = checked {
+            reply.error(errno);
+            return;
+        }
+        if flags != 0 || offset_in < 0 || offset_out < 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let (offset_in, offset_out) = (offset_in as u64, offset_out as u64);
+        let (source, target) = match (self.storage.get_inode(ino_in), self.storage.get_inode(ino_out)) {
+            (Ok(source), Ok(target)) => (source, target),
+            (Err(e), _) | (_, Err(e)) => {
+                reply.error(StorageError::errno_of(&e));
+                return;
+            }
+        };
+        
+        // A whole file copied over one no longer than it shares its extents;
+        // a snapshot's are only copied out, so it keeps holding them alone
+        let whole_file = offset_in == 0 && offset_out == 0 && len >= source.size && target.size <= source.size;
+        if whole_file && ino_in != ino_out && source.size > 0 && !crate::snapshots::is_snapshot_ino(ino_in) {
+            match self.storage.clone_file(ino_in, ino_out) {
+                // Past 4 GB the caller comes back for the rest, which already matches
+                Ok(()) => reply.written(source.size.min(u32::MAX as u64) as u32),
+                Err(e) => {
+                    log::error!("clone of inode {} into {} failed: {}", ino_in, ino_out, e);
+                    reply.error(StorageError::errno_of(&e));
+                }
+            }
+            return;
+        }
+        
+        // Partial ranges are copied through
+        let copied = self.storage.read_range(ino_in, offset_in, len.min(COPY_CHUNK)).and_then(|data| {
+            if data.is_empty() {
+                return Ok(0);
+            }
+            let unchanged = offset_in == offset_out
+                && ino_in != ino_out
+                && self.storage.read_range(ino_out, offset_out, data.len() as u64)? == data;
+            if !unchanged {
+                self.storage.buffered_write(ino_out, offset_out, &data)?;
+            }
+            Ok(data.len())
+        });
+        match copied {
+            Ok(copied) => reply.written(copied as u32),
+            Err(e) => {
+                log::error!("copy_file_range failed: {}", e);
+                reply.error(StorageError::errno_of(&e));
+            }
+        }
+    }
+    
     // ===== Open/Release =====
     
-    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
         log::debug!("open(ino={}, flags={})", ino, flags);
         
-        // Verify file exists
-        if self.storage.get_inode(ino).is_err() {
-            reply.error(ENOENT);
+        // Handles keep the access checked here for as long as they are open
+        if let Err(errno) = self.check_access(req, ino, Self::open_mask(flags)) {
+            reply.error(errno);
             return;
         }
         
-        // Return file handle (we use inode number as handle for simplicity)
-        reply.opened(ino, flags as u32);
+        let (fh, open_flags) = self.open_handle(ino, flags);
+        reply.opened(fh, open_flags);
     }
     
-    fn release(
+    /// Runs on every close(); buffered data is written out here so write
+    /// errors reach the caller. Durability still requires fsync.
+    fn flush(
         &mut self,
         _req: &Request,
         ino: u64,
         _fh: u64,
+        _lock_owner: u64,
+        reply: fuser::ReplyEmpty,
+    ) {
+        log::debug!("flush(ino={})", ino);
+        
+        match self.storage.flush_file(ino) {
+            Ok(()) => reply.ok(),
+            Err(e) => {
+                log::error!("flush of inode {} failed: {}", ino, e);
+                reply.error(StorageError::errno_of(&e));
+            }
+        }
+    }
+    
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
         _flags: i32,
         lock_owner: Option<u64>,
         _flush: bool,
         reply: fuser::ReplyEmpty,
     ) {
-        log::debug!("release(ino={})", ino);
+        log::debug!("release(ino={}, fh={})", ino, fh);
+        let still_open = self.close_handle(fh) > 0;
+        if let Some(manager) = &self.readahead_manager {
+            manager.forget_handle(fh);
+        }
         
         // Release all locks for this owner
         if let Some(owner) = lock_owner {
@@ -896,6 +1641,43 @@ impl Filesystem for DynamicFS {
             }
         }
         
+        if !still_open && self.unlinked_open.remove(&ino) {
+            if let Err(e) = self.storage.delete_file(ino) {
+                log::error!("delete of unlinked inode {} failed: {}", ino, e);
+            }
+        } else if let Err(e) = self.storage.flush_file(ino) {
+            // Normally already done by flush; catches writes through other handles
+            log::error!("flush on release of inode {} failed: {}", ino, e);
+        }
+        
+        reply.ok();
+    }
+    
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        log::debug!("access(ino={}, mask={:o})", ino, mask);
+        
+        match self.check_access(req, ino, mask as u32) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+    
+    fn opendir(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        log::debug!("opendir(ino={}, flags={})", ino, flags);
+        
+        match self.storage.get_inode(ino) {
+            Ok(inode) if inode.file_type == InodeFileType::Directory => {
+                let (fh, _) = self.open_handle(ino, flags);
+                reply.opened(fh, 0);
+            }
+            Ok(_) => reply.error(libc::ENOTDIR),
+            Err(e) => reply.error(StorageError::errno_of(&e)),
+        }
+    }
+    
+    fn releasedir(&mut self, _req: &Request, ino: u64, fh: u64, _flags: i32, reply: fuser::ReplyEmpty) {
+        log::debug!("releasedir(ino={}, fh={})", ino, fh);
+        self.close_handle(fh);
         reply.ok();
     }
     
@@ -910,15 +1692,20 @@ impl Filesystem for DynamicFS {
         reply: fuser::ReplyEmpty,
     ) {
         log::debug!("fsync(ino={})", ino);
-        
-        // In a production system, this would flush all pending writes
-        // For now, all writes are synchronous, so just verify inode exists
-        if self.storage.get_inode(ino).is_err() {
-            reply.error(ENOENT);
-            return;
-        }
-        
-        reply.ok();
+        let _timer = self.time_op(FuseOp::Fsync);
+        self.sync_reply(ino, reply);
+    }
+
+    fn fsyncdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        log::debug!("fsyncdir(ino={})", ino);
+        self.sync_reply(ino, reply);
     }
     
     // ===== IOCTL (minimal support) =====
@@ -936,8 +1723,1326 @@ impl Filesystem for DynamicFS {
     ) {
         log::debug!("ioctl(ino={}, cmd={})", ino, cmd);
         
-        // Most ioctls are not supported
+        // FICLONE and FICLONERANGE never arrive here: the kernel handles them
+        // in the VFS and refuses them for FUSE, so whole-file clones go
+        // through copy_file_range instead. Other ioctls are not supported
         // Return ENOSYS to indicate not implemented
         reply.error(ENOSYS);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Inode;
+    use crate::storage::StorageEngine;
+    use crate::test_utils::setup_test_env;
+    use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+
+    fn owned_file(uid: u32, gid: u32) -> Inode {
+        let mut inode = Inode::new_file(2, 1, "f".to_string());
+        inode.uid = uid;
+        inode.gid = gid;
+        inode
+    }
+
+    /// Mounts `storage` on a fresh temporary directory, or returns `None`
+    /// when the sandbox has no FUSE.
+    fn mount_for_test(storage: StorageEngine) -> Option<(fuser::BackgroundSession, tempfile::TempDir)> {
+        let mountpoint = tempfile::tempdir().unwrap();
+        let fs = DynamicFS::new(Box::new(storage));
+        let options = [fuser::MountOption::FSName("dynamicfs-test".to_string())];
+        match fuser::spawn_mount2(fs, mountpoint.path(), &options) {
+            Ok(session) => Some((session, mountpoint)),
+            Err(e) => {
+                eprintln!("skipping mount test: FUSE unavailable ({})", e);
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn test_chmod_requires_owner_or_root() {
+        let inode = owned_file(1000, 1000);
+        assert_eq!(DynamicFS::check_attr_change(&inode, 1000, 1000, Some(0o600), None, None), Ok(()));
+        assert_eq!(DynamicFS::check_attr_change(&inode, 0, 0, Some(0o600), None, None), Ok(()));
+        assert_eq!(
+            DynamicFS::check_attr_change(&inode, 1001, 1000, Some(0o600), None, None),
+            Err(libc::EPERM)
+        );
+    }
+
+    #[test]
+    fn test_chown_rules() {
+        let inode = owned_file(1000, 1000);
+        // Only root may give the file away
+        assert_eq!(DynamicFS::check_attr_change(&inode, 1000, 1000, None, Some(1001), None), Err(libc::EPERM));
+        assert_eq!(DynamicFS::check_attr_change(&inode, 0, 0, None, Some(1001), Some(1001)), Ok(()));
+        // Chown to the current owner is a no-op anyone may request
+        assert_eq!(DynamicFS::check_attr_change(&inode, 1000, 1000, None, Some(1000), None), Ok(()));
+        // The owner may switch to their own group, but not to an arbitrary one
+        assert_eq!(DynamicFS::check_attr_change(&inode, 1000, 2000, None, None, Some(2000)), Ok(()));
+        assert_eq!(DynamicFS::check_attr_change(&inode, 1000, 1000, None, None, Some(3000)), Err(libc::EPERM));
+        assert_eq!(DynamicFS::check_attr_change(&inode, 1001, 2000, None, None, Some(2000)), Err(libc::EPERM));
+    }
+
+    /// Page through a directory the way the kernel does, `page` entries per call
+    fn paged_readdir(children: &[Inode], page: usize, mut between_calls: impl FnMut(&mut Vec<Inode>)) -> Vec<FileName> {
+        let mut children = children.to_vec();
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let entries = DynamicFS::readdir_entries(1, 1, children.clone(), offset);
+            if entries.is_empty() {
+                return names;
+            }
+            for (_, cookie, _, name) in entries.into_iter().take(page) {
+                names.push(name);
+                offset = cookie;
+            }
+            between_calls(&mut children);
+        }
+    }
+
+    #[test]
+    fn test_readdir_pages_return_each_entry_once() {
+        let children: Vec<Inode> = (0..10_000u64)
+            .map(|i| Inode::new_file(i + 2, 1, format!("file_{:05}", i)))
+            .collect();
+
+        let mut names = paged_readdir(&children, 97, |_| {});
+        assert_eq!(&names[..2], &[".", ".."]);
+        names.drain(..2);
+        names.sort();
+        let expected: Vec<FileName> = children.iter().map(|c| c.name.clone()).collect();
+        assert_eq!(names, expected);
+
+        // Entries created or deleted between calls do not disturb the others
+        let mut next_ino = 20_000;
+        let names = paged_readdir(&children, 97, |children| {
+            if next_ino < 20_050 {
+                children.remove(children.len() / 2);
+                children.push(Inode::new_file(next_ino, 1, format!("new_{}", next_ino)));
+                next_ino += 1;
+            }
+        });
+        let mut seen = std::collections::HashSet::new();
+        for name in &names {
+            assert!(seen.insert(name.clone()), "{} listed twice", name);
+        }
+        assert!(seen.contains(&b"file_00000"[..]) && seen.contains(&b"file_09999"[..]));
+    }
+
+    #[test]
+    fn test_mounted_readdir_spans_multiple_calls() {
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+
+        // Long names so the listing needs several kernel readdir calls
+        let expected: Vec<String> = (0..500).map(|i| format!("{:032}_{:04}", 0, i)).collect();
+        for name in &expected {
+            storage.create_file(1, name.clone()).unwrap();
+        }
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let mut names: Vec<String> = std::fs::read_dir(mountpoint.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, expected);
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_non_utf8_names_pass_through() {
+        use std::ffi::OsStr;
+
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let existing = storage.create_file(1, b"caf\xe9.txt".to_vec()).unwrap();
+        storage.write_file(existing.ino, b"latin-1", 0).unwrap();
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let dir = mountpoint.path().join(OsStr::from_bytes(b"d\xfcr"));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join(OsStr::from_bytes(b"\xff\xfe")), b"bytes").unwrap();
+        assert_eq!(std::fs::read(mountpoint.path().join(OsStr::from_bytes(b"caf\xe9.txt"))).unwrap(), b"latin-1");
+        assert_eq!(std::fs::read(dir.join(OsStr::from_bytes(b"\xff\xfe"))).unwrap(), b"bytes");
+
+        let mut names: Vec<Vec<u8>> = std::fs::read_dir(mountpoint.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().as_bytes().to_vec())
+            .collect();
+        names.sort();
+        assert_eq!(names, [b"caf\xe9.txt".to_vec(), b"d\xfcr".to_vec()]);
+
+        // Renaming into the other directory moves both parents' times
+        let mtime = |path: &std::path::Path| std::fs::metadata(path).unwrap().modified().unwrap();
+        let (root_before, dir_before) = (mtime(mountpoint.path()), mtime(&dir));
+        std::thread::sleep(Duration::from_millis(10));
+        let renamed = dir.join(OsStr::from_bytes(b"na\xefve.txt"));
+        std::fs::rename(mountpoint.path().join(OsStr::from_bytes(b"caf\xe9.txt")), &renamed).unwrap();
+        assert!(!mountpoint.path().join(OsStr::from_bytes(b"caf\xe9.txt")).exists());
+        assert_eq!(std::fs::read(&renamed).unwrap(), b"latin-1");
+        assert!(mtime(mountpoint.path()) > root_before);
+        assert!(mtime(&dir) > dir_before);
+
+        // Moving a subdirectory moves a link from one parent to the other
+        let sub = dir.join(OsStr::from_bytes(b"s\xe9"));
+        std::fs::create_dir(&sub).unwrap();
+        assert_eq!(std::fs::metadata(&dir).unwrap().nlink(), 3);
+        std::fs::rename(&sub, mountpoint.path().join(OsStr::from_bytes(b"s\xe9"))).unwrap();
+        assert_eq!(std::fs::metadata(&dir).unwrap().nlink(), 2);
+        assert_eq!(std::fs::metadata(mountpoint.path()).unwrap().nlink(), 4);
+        std::fs::remove_dir(mountpoint.path().join(OsStr::from_bytes(b"s\xe9"))).unwrap();
+
+        std::fs::remove_file(dir.join(OsStr::from_bytes(b"\xff\xfe"))).unwrap();
+        std::fs::remove_file(&renamed).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+        assert!(!dir.exists());
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_read_only_storage_returns_erofs() {
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let file = storage.create_file(1, "existing.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"kept", 0).unwrap();
+        storage.set_read_only(true);
+
+        // No RO mount option: the storage layer alone must refuse writes
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let existing = mountpoint.path().join("existing.txt");
+        let erofs = |r: std::io::Result<()>| r.unwrap_err().raw_os_error() == Some(libc::EROFS);
+        assert!(erofs(std::fs::write(&existing, b"changed")));
+        assert!(erofs(std::fs::write(mountpoint.path().join("new.txt"), b"new")));
+        assert!(erofs(std::fs::create_dir(mountpoint.path().join("dir"))));
+        assert!(erofs(std::fs::remove_file(&existing)));
+        assert_eq!(std::fs::read(&existing).unwrap(), b"kept");
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_o_append_writes_at_the_real_end_of_file() {
+        use std::io::Write;
+        use std::os::unix::fs::FileExt;
+
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let file = storage.create_file(1, "log.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"kept", 0).unwrap();
+        let other_writer = storage.background_handle();
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let path = mountpoint.path().join("log.txt");
+        let mut appender = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        let positioned = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        // Grows the file behind the kernel's back, so its cached size is stale
+        other_writer.append_write(file.ino, b"-other").unwrap();
+        appender.write_all(b"-appended").unwrap();
+        positioned.write_at(b"K", 0).unwrap();
+        drop((appender, positioned));
+
+        other_writer.flush_file(file.ino).unwrap();
+        assert_eq!(other_writer.read_file(file.ino).unwrap(), b"Kept-other-appended");
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_layout_xattr_supports_size_query() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let file = storage.create_file(1, "data.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[9u8; 8192], 0).unwrap();
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let path = CString::new(mountpoint.path().join("data.bin").as_os_str().as_bytes()).unwrap();
+        let name = CString::new(LAYOUT_XATTR).unwrap();
+        // Size query first, as getfattr does, then fetch into a buffer of that size
+        let size = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        assert!(size > 0, "size query failed: {}", std::io::Error::last_os_error());
+        let mut value = vec![0u8; size as usize];
+        let read = unsafe { libc::getxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        assert_eq!(read, size);
+
+        let layout: serde_json::Value = serde_json::from_slice(&value).unwrap();
+        assert_eq!(layout["summarized"], false);
+        assert_eq!(layout["size"], 8192);
+        assert_eq!(layout["extents"].as_array().unwrap().len(), 1);
+        assert_eq!(layout["extents"][0]["fragments"][0]["disk_health"], "Healthy");
+
+        // Generated on read, so it cannot be set or removed
+        let set = unsafe { libc::setxattr(path.as_ptr(), name.as_ptr(), b"{}".as_ptr().cast(), 2, 0) };
+        assert_eq!(set, -1);
+        assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+        assert_eq!(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) }, -1);
+        assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_unplugged_disks_return_eio_until_they_come_back() {
+        let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+        let mut pool = crate::disk::DiskPool::new();
+        for dir in &disk_dirs {
+            pool.add_disk(dir.path().to_path_buf()).unwrap();
+        }
+        let mut storage = StorageEngine::new(metadata, disks);
+        let file = storage.create_file(1, "data.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[6u8; 4096], 0).unwrap();
+        storage.start_disk_probe(pool, Duration::from_millis(20));
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let aside: Vec<std::path::PathBuf> = (0..disk_dirs.len()).map(|i| pool_dir.path().join(format!("unplugged{}", i))).collect();
+        for (dir, aside) in disk_dirs.iter().zip(&aside) {
+            std::fs::rename(dir.path(), aside).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        let path = mountpoint.path().join("data.bin");
+        let err = std::fs::read(&path).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EIO));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4096);
+        assert_eq!(std::fs::read_dir(mountpoint.path()).unwrap().count(), 1);
+
+        for (dir, aside) in disk_dirs.iter().zip(&aside) {
+            std::fs::rename(aside, dir.path()).unwrap();
+        }
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let data = loop {
+            match std::fs::read(&path) {
+                Ok(data) => break data,
+                Err(_) if std::time::Instant::now() < deadline => std::thread::sleep(Duration::from_millis(20)),
+                Err(e) => panic!("disks did not come back: {}", e),
+            }
+        };
+        assert_eq!(data, vec![6u8; 4096]);
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_sequential_writes_flushed_on_close() {
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let metrics = std::sync::Arc::new(crate::metrics::Metrics::new());
+        let config = crate::write_optimizer::WriteBufferConfig {
+            flush_interval: std::time::Duration::from_secs(600),
+            ..Default::default()
+        };
+        let storage = StorageEngine::with_write_buffer(metadata, disks, metrics.clone(), config);
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let path = mountpoint.path().join("log.txt");
+        let data: Vec<u8> = (0..64 * 4096).map(|i| (i % 253) as u8).collect();
+        {
+            use std::io::Write;
+            let mut file = std::fs::File::create(&path).unwrap();
+            for chunk in data.chunks(4096) {
+                file.write_all(chunk).unwrap();
+            }
+            // Buffered data is visible before close
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), data.len() as u64);
+        }
+
+        let snapshot = metrics.snapshot();
+        assert!(snapshot.write_buffer_coalesced > 0);
+        assert!(snapshot.write_buffer_flushes_release >= 1);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_unlinked_open_file_is_usable_until_closed() {
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let (_pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let fragments = || -> usize {
+            disk_dirs.iter().map(|dir| std::fs::read_dir(dir.path().join("fragments")).unwrap().count()).sum()
+        };
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        // The tmpfile pattern: create, unlink, keep using the descriptor
+        let path = mountpoint.path().join("scratch.tmp");
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        file.write_all(&vec![5u8; 256 * 1024]).unwrap();
+        file.sync_all().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(mountpoint.path()).unwrap().count(), 0);
+
+        file.write_all(b"after unlink").unwrap();
+        file.sync_all().unwrap();
+        assert!(fragments() > 0);
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(256 * 1024)).unwrap();
+        file.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, b"after unlink");
+        let meta = file.metadata().unwrap();
+        assert_eq!((meta.len(), std::os::unix::fs::MetadataExt::nlink(&meta)), (256 * 1024 + 12, 0));
+
+        // The kernel sends release asynchronously after the last close
+        drop(file);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while fragments() > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(fragments(), 0);
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_copy_file_range_clones_and_ficlone_is_refused() {
+        use std::os::unix::io::AsRawFd;
+
+        let (_pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let fragments = || -> usize {
+            disk_dirs.iter().map(|dir| std::fs::read_dir(dir.path().join("fragments")).unwrap().count()).sum()
+        };
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let data: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+        let source_path = mountpoint.path().join("take1.mov");
+        std::fs::write(&source_path, &data).unwrap();
+        let source = std::fs::File::open(&source_path).unwrap();
+        let target = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(mountpoint.path().join("take1-edit.mov"))
+            .unwrap();
+        let stored = fragments();
+
+        // The kernel answers FICLONE and FICLONERANGE itself, as FUSE has no remap_file_range
+        let clone = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+        assert_eq!(clone, -1);
+        assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EOPNOTSUPP));
+        let range = libc::file_clone_range {
+            src_fd: source.as_raw_fd() as i64,
+            src_offset: 0,
+            src_length: 0,
+            dest_offset: 0,
+        };
+        let clone_range = unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONERANGE, &range) };
+        assert_eq!(clone_range, -1);
+        assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(libc::EOPNOTSUPP));
+
+        // A whole-file copy_file_range shares the extents instead
+        let copied = unsafe {
+            libc::copy_file_range(
+                source.as_raw_fd(),
+                std::ptr::null_mut(),
+                target.as_raw_fd(),
+                std::ptr::null_mut(),
+                data.len(),
+                0,
+            )
+        };
+        assert_eq!(copied, data.len() as isize);
+        drop(target);
+        assert_eq!(std::fs::read(mountpoint.path().join("take1-edit.mov")).unwrap(), data);
+        assert_eq!(fragments(), stored);
+
+        drop(source);
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_flocks_exclude_other_handles_apart_from_record_locks() {
+        use std::os::unix::io::AsRawFd;
+
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        // FUSE_FLOCK_LOCKS is not negotiated, so the kernel keeps flocks itself
+        let path = mountpoint.path().join("cron.lock");
+        std::fs::write(&path, b"").unwrap();
+        let first = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let second = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let flock = |file: &std::fs::File, op: i32| {
+            if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error().raw_os_error().unwrap())
+            }
+        };
+
+        assert_eq!(flock(&first, libc::LOCK_EX | libc::LOCK_NB), Ok(()));
+        assert_eq!(flock(&second, libc::LOCK_SH | libc::LOCK_NB), Err(libc::EWOULDBLOCK));
+
+        // A record lock through the other handle goes to setlk and does not conflict
+        let mut record = unsafe { std::mem::zeroed::<libc::flock>() };
+        record.l_type = libc::F_WRLCK as i16;
+        record.l_whence = libc::SEEK_SET as i16;
+        assert_eq!(unsafe { libc::fcntl(second.as_raw_fd(), libc::F_SETLK, &record) }, 0);
+
+        // Downgrading lets shared flocks in; closing the handle drops its flock
+        assert_eq!(flock(&first, libc::LOCK_SH | libc::LOCK_NB), Ok(()));
+        assert_eq!(flock(&second, libc::LOCK_SH | libc::LOCK_NB), Ok(()));
+        assert_eq!(flock(&second, libc::LOCK_EX | libc::LOCK_NB), Err(libc::EWOULDBLOCK));
+        drop(first);
+        assert_eq!(flock(&second, libc::LOCK_EX | libc::LOCK_NB), Ok(()));
+
+        drop(second);
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_operations_record_latency_histograms() {
+        use crate::metrics::{FuseOp, LatencySnapshot};
+
+        // 10 ops under 1ms, 10 between 5 and 20ms: p50 sits at the top of the first bucket
+        let latency = LatencySnapshot { buckets: [10, 0, 10, 0, 0, 0], sum_us: 0 };
+        assert_eq!(latency.quantile(0.5), Some(Duration::from_millis(1)));
+        assert_eq!(latency.quantile(0.75), Some(Duration::from_micros(12_500)));
+        assert_eq!(LatencySnapshot::default().quantile(0.5), None);
+
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let metrics = std::sync::Arc::new(crate::metrics::Metrics::new());
+        let storage = StorageEngine::with_metrics(metadata, disks, metrics.clone());
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let path = mountpoint.path().join("timed.txt");
+        {
+            use std::io::Write;
+            let mut file = std::fs::File::create(&path).unwrap();
+            file.write_all(b"timed").unwrap();
+            file.sync_all().unwrap();
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"timed");
+        std::fs::read_dir(mountpoint.path()).unwrap().for_each(drop);
+        std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Latencies are recorded just after the reply, so the last one may still be landing
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        let snapshot = loop {
+            let snapshot = metrics.snapshot();
+            let missing = snapshot.fuse_latency.iter().find(|(_, latency)| latency.count() == 0);
+            match missing {
+                None => break snapshot,
+                Some(_) if std::time::Instant::now() < deadline => std::thread::sleep(Duration::from_millis(20)),
+                Some((op, _)) => panic!("no {:?} samples", op),
+            }
+        };
+        let count = |op: FuseOp| snapshot.fuse_latency.iter().find(|(o, _)| *o == op).unwrap().1.count();
+        assert_eq!(count(FuseOp::Unlink), 1);
+
+        let body = crate::monitoring::PrometheusExporter::new(metrics.clone()).export();
+        assert!(body.contains("# TYPE dynamicfs_fuse_op_duration_seconds histogram"), "{}", body);
+        assert!(body.contains("dynamicfs_fuse_op_duration_seconds_bucket{op=\"unlink\",le=\"+Inf\"} 1"), "{}", body);
+        assert!(body.contains("dynamicfs_fuse_op_duration_seconds_count{op=\"unlink\"} 1"), "{}", body);
+        assert!(snapshot.to_string().contains("unlink   1 ops, p50"));
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_chmod_chown_round_trip() {
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let path = mountpoint.path().join("secret.txt");
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o640)
+            .open(&path)
+            .unwrap();
+        let umask = unsafe {
+            let mask = libc::umask(0);
+            libc::umask(mask);
+            mask as u32
+        };
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!(meta.mode() & 0o7777, 0o640 & !umask);
+        assert_eq!(meta.uid(), unsafe { libc::geteuid() });
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o7777, 0o600);
+
+        let (uid, gid) = if unsafe { libc::geteuid() } == 0 {
+            (4242, 4242)
+        } else {
+            (meta.uid(), meta.gid())
+        };
+        std::os::unix::fs::chown(&path, Some(uid), Some(gid)).unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (uid, gid));
+
+        drop(session);
+    }
+
+    #[test]
+    fn test_mounted_snapshots_are_read_only_and_keep_what_the_live_tree_lost() {
+        let (_pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        let engine = storage.background_handle();
+
+        let Some((session, mountpoint)) = mount_for_test(storage) else { return };
+
+        let live = mountpoint.path().join("draft.txt");
+        std::fs::write(&live, b"version one").unwrap();
+        engine.create_snapshot("v1").unwrap();
+        std::fs::write(&live, b"version two, longer").unwrap();
+        std::fs::remove_file(&live).unwrap();
+
+        let mut names: Vec<_> = std::fs::read_dir(mountpoint.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, [".snapshots"]);
+        let snapshot = mountpoint.path().join(".snapshots/v1");
+        let captured = snapshot.join("draft.txt");
+        assert_eq!(std::fs::read(&captured).unwrap(), b"version one");
+        assert_eq!(std::fs::metadata(&captured).unwrap().ino() >> crate::snapshots::SNAPSHOT_ID_SHIFT, 1);
+
+        // Copying out goes through copy_file_range, which copies rather than shares
+        std::fs::copy(&captured, &live).unwrap();
+        assert_eq!(std::fs::read(&live).unwrap(), b"version one");
+
+        let erofs = |result: std::io::Result<()>| result.unwrap_err().raw_os_error() == Some(libc::EROFS);
+        assert!(erofs(std::fs::OpenOptions::new().write(true).open(&captured).map(drop)));
+        assert!(erofs(std::fs::write(snapshot.join("new.txt"), b"x")));
+        assert!(erofs(std::fs::remove_file(&captured)));
+        assert!(erofs(std::fs::set_permissions(&captured, std::fs::Permissions::from_mode(0o600))));
+        assert!(erofs(std::fs::rename(&captured, mountpoint.path().join("restored.txt"))));
+        assert!(erofs(std::fs::create_dir(mountpoint.path().join(".snapshots/v2"))));
+        assert!(erofs(std::fs::remove_dir(mountpoint.path().join(".snapshots"))));
+        assert_eq!(std::fs::read(&captured).unwrap(), b"version one");
+
+        drop(session);
+    }
+
+    /// Drives `DynamicFS` over `MemoryFs` through a real fuser session
+    ///
+    /// The test plays the kernel: requests are encoded and replies decoded
+    /// per the Linux FUSE ABI and exchanged over a socket pair, so each
+    /// handler sees a genuine `Request` and every errno is checked exactly.
+    #[cfg(target_os = "linux")]
+    mod golden {
+        use super::*;
+        use crate::memory_fs::{FsMethod, MemoryFs};
+        use std::io::ErrorKind;
+        use std::os::unix::net::UnixDatagram;
+
+        const UID: u32 = 1000;
+        const GID: u32 = 1000;
+
+        const LOOKUP: u32 = 1;
+        const GETATTR: u32 = 3;
+        const SETATTR: u32 = 4;
+        const MKDIR: u32 = 9;
+        const UNLINK: u32 = 10;
+        const RMDIR: u32 = 11;
+        const RENAME: u32 = 12;
+        const OPEN: u32 = 14;
+        const READ: u32 = 15;
+        const WRITE: u32 = 16;
+        const RELEASE: u32 = 18;
+        const OPENDIR: u32 = 27;
+        const FSYNC: u32 = 20;
+        const SETXATTR: u32 = 21;
+        const GETXATTR: u32 = 22;
+        const LISTXATTR: u32 = 23;
+        const REMOVEXATTR: u32 = 24;
+        const INIT: u32 = 26;
+        const READDIR: u32 = 28;
+        const RELEASEDIR: u32 = 29;
+        const GETLK: u32 = 31;
+        const SETLK: u32 = 32;
+        const ACCESS: u32 = 34;
+        const CREATE: u32 = 35;
+        const RENAME2: u32 = 45;
+        const COPY_FILE_RANGE: u32 = 47;
+
+        const FATTR_MODE: u32 = 1 << 0;
+        const FATTR_SIZE: u32 = 1 << 3;
+        const FUSE_RELEASE_FLOCK_UNLOCK: u32 = 1 << 1;
+
+        /// Offset of `fuse_attr` in `fuse_entry_out`, and the size of `fuse_entry_out`
+        const ENTRY_ATTR: usize = 40;
+        const ENTRY_SIZE: usize = ENTRY_ATTR + 88;
+
+        fn u32_at(bytes: &[u8], at: usize) -> u32 {
+            u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap())
+        }
+
+        fn u64_at(bytes: &[u8], at: usize) -> u64 {
+            u64::from_ne_bytes(bytes[at..at + 8].try_into().unwrap())
+        }
+
+        /// Little builder for request bodies, in native byte order
+        #[derive(Default)]
+        struct Body(Vec<u8>);
+
+        impl Body {
+            fn u32(mut self, value: u32) -> Self {
+                self.0.extend_from_slice(&value.to_ne_bytes());
+                self
+            }
+
+            fn u64(mut self, value: u64) -> Self {
+                self.0.extend_from_slice(&value.to_ne_bytes());
+                self
+            }
+
+            /// A NUL-terminated name
+            fn name(mut self, name: &[u8]) -> Self {
+                self.0.extend_from_slice(name);
+                self.0.push(0);
+                self
+            }
+
+            fn bytes(mut self, data: &[u8]) -> Self {
+                self.0.extend_from_slice(data);
+                self
+            }
+
+            /// `fuse_file_lock` inside `fuse_lk_in`
+            fn lk_in(self, owner: u64, start: u64, end: u64, typ: i32) -> Self {
+                self.u64(0).u64(owner).u64(start).u64(end).u32(typ as u32).u32(owner as u32).u32(0).u32(0)
+            }
+        }
+
+        /// The `fuse_attr` fields the tests look at
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        struct Attr {
+            ino: u64,
+            size: u64,
+            mode: u32,
+            uid: u32,
+            gid: u32,
+        }
+
+        fn attr_at(bytes: &[u8], at: usize) -> Attr {
+            Attr {
+                ino: u64_at(bytes, at),
+                size: u64_at(bytes, at + 8),
+                mode: u32_at(bytes, at + 60),
+                uid: u32_at(bytes, at + 68),
+                gid: u32_at(bytes, at + 72),
+            }
+        }
+
+        struct Harness {
+            kernel: UnixDatagram,
+            memory: MemoryFs,
+            unique: u64,
+            session: Option<std::thread::JoinHandle<()>>,
+            /// Caller of requests sent with `call`
+            uid: u32,
+            gid: u32,
+        }
+
+        impl Drop for Harness {
+            fn drop(&mut self) {
+                // An empty message ends the session loop
+                let _ = self.kernel.send(&[]);
+                if let Some(session) = self.session.take() {
+                    let _ = session.join();
+                }
+            }
+        }
+
+        impl Harness {
+            fn new() -> Self {
+                // The root belongs to the test user, as a mount's root does to whoever made the pool
+                let memory = MemoryFs::new();
+                let mut root = memory.get_inode(1).unwrap();
+                (root.uid, root.gid) = (UID, GID);
+                memory.update_inode(&root).unwrap();
+                let (kernel, device) = UnixDatagram::pair().unwrap();
+                kernel.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+                let fs = DynamicFS::new(Box::new(memory.clone()));
+                let mut session = fuser::Session::from_fd(fs, device.into(), fuser::SessionACL::All);
+                let session = std::thread::spawn(move || session.run().unwrap());
+                let mut harness = Harness { kernel, memory, unique: 0, session: Some(session), uid: UID, gid: GID };
+                let init = Body::default().u32(7).u32(31).u32(0).u32(0);
+                harness.call_as(INIT, 0, UID, GID, init).unwrap();
+                harness
+            }
+
+            /// Send a request and wait for its reply: the payload after the
+            /// `fuse_out_header`, or the errno the reply carried
+            fn call_as(&mut self, opcode: u32, nodeid: u64, uid: u32, gid: u32, body: Body) -> Result<Vec<u8>, i32> {
+                self.unique += 1;
+                let header = Body::default()
+                    .u32(40 + body.0.len() as u32)
+                    .u32(opcode)
+                    .u64(self.unique)
+                    .u64(nodeid)
+                    .u32(uid)
+                    .u32(gid)
+                    .u32(uid)
+                    .u32(0);
+                self.kernel.send(&header.bytes(&body.0).0).unwrap();
+
+                let mut reply = vec![0; 1 << 20];
+                let len = self.kernel.recv(&mut reply).expect("handler did not reply");
+                assert_eq!(u32_at(&reply, 0) as usize, len);
+                assert_eq!(u64_at(&reply, 8), self.unique);
+                match u32_at(&reply, 4) as i32 {
+                    0 => Ok(reply[16..len].to_vec()),
+                    error => Err(-error),
+                }
+            }
+
+            fn call(&mut self, opcode: u32, nodeid: u64, body: Body) -> Result<Vec<u8>, i32> {
+                self.call_as(opcode, nodeid, self.uid, self.gid, body)
+            }
+
+            fn empty(&mut self, opcode: u32, nodeid: u64, body: Body) -> Result<(), i32> {
+                self.call(opcode, nodeid, body).map(|_| ())
+            }
+
+            fn lookup(&mut self, parent: u64, name: &str) -> Result<Attr, i32> {
+                self.lookup_bytes(parent, name.as_bytes())
+            }
+
+            fn lookup_bytes(&mut self, parent: u64, name: &[u8]) -> Result<Attr, i32> {
+                let entry = self.call(LOOKUP, parent, Body::default().name(name))?;
+                assert_eq!(u64_at(&entry, 0), attr_at(&entry, ENTRY_ATTR).ino);
+                Ok(attr_at(&entry, ENTRY_ATTR))
+            }
+
+            /// Generation number the kernel hands out in file handles for `name`
+            fn generation(&mut self, parent: u64, name: &str) -> Result<u64, i32> {
+                let entry = self.call(LOOKUP, parent, Body::default().name(name.as_bytes()))?;
+                Ok(u64_at(&entry, 8))
+            }
+
+            fn getattr(&mut self, ino: u64) -> Result<Attr, i32> {
+                let out = self.call(GETATTR, ino, Body::default().u32(0).u32(0).u64(0))?;
+                Ok(attr_at(&out, 16))
+            }
+
+            /// `(ino, cookie, name)` of each entry after `offset`
+            fn readdir(&mut self, ino: u64, offset: i64) -> Result<Vec<(u64, i64, String)>, i32> {
+                let read_in = Body::default().u64(0).u64(offset as u64).u32(4096).u32(0).u64(0).u32(0).u32(0);
+                let buffer = self.call(READDIR, ino, read_in)?;
+                let mut entries = Vec::new();
+                let mut at = 0;
+                while at < buffer.len() {
+                    let len = u32_at(&buffer, at + 16) as usize;
+                    let name = String::from_utf8(buffer[at + 24..at + 24 + len].to_vec()).unwrap();
+                    entries.push((u64_at(&buffer, at), u64_at(&buffer, at + 8) as i64, name));
+                    at += (24 + len).div_ceil(8) * 8;
+                }
+                Ok(entries)
+            }
+
+            /// Attributes of the new file and its file handle
+            fn create(&mut self, parent: u64, name: &str, mode: u32) -> Result<(Attr, u64), i32> {
+                let create_in = Body::default().u32(libc::O_RDWR as u32).u32(libc::S_IFREG | mode).u32(0o022).u32(0);
+                let out = self.call(CREATE, parent, create_in.name(name.as_bytes()))?;
+                Ok((attr_at(&out, ENTRY_ATTR), u64_at(&out, ENTRY_SIZE)))
+            }
+
+            fn mkdir(&mut self, parent: u64, name: &str) -> Result<Attr, i32> {
+                let mkdir_in = Body::default().u32(0o755).u32(0o022);
+                let entry = self.call(MKDIR, parent, mkdir_in.name(name.as_bytes()))?;
+                Ok(attr_at(&entry, ENTRY_ATTR))
+            }
+
+            fn write(&mut self, ino: u64, fh: u64, offset: i64, data: &[u8]) -> Result<u32, i32> {
+                let write_in = Body::default().u64(fh).u64(offset as u64).u32(data.len() as u32).u32(0).u64(0).u32(0).u32(0);
+                let out = self.call(WRITE, ino, write_in.bytes(data))?;
+                Ok(u32_at(&out, 0))
+            }
+
+            fn read(&mut self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+                let read_in = Body::default().u64(0).u64(offset as u64).u32(size).u32(0).u64(0).u32(0).u32(0);
+                self.call(READ, ino, read_in)
+            }
+
+            fn unlink(&mut self, parent: u64, name: &str) -> Result<(), i32> {
+                self.empty(UNLINK, parent, Body::default().name(name.as_bytes()))
+            }
+
+            fn rmdir(&mut self, parent: u64, name: &str) -> Result<(), i32> {
+                self.empty(RMDIR, parent, Body::default().name(name.as_bytes()))
+            }
+
+            fn rename(&mut self, parent: u64, name: &[u8], newparent: u64, newname: &[u8]) -> Result<(), i32> {
+                self.empty(RENAME, parent, Body::default().u64(newparent).name(name).name(newname))
+            }
+
+            fn rename2(&mut self, parent: u64, name: &[u8], newparent: u64, newname: &[u8], flags: u32) -> Result<(), i32> {
+                let rename_in = Body::default().u64(newparent).u32(flags).u32(0);
+                self.empty(RENAME2, parent, rename_in.name(name).name(newname))
+            }
+
+            /// chmod and/or truncate as `uid`
+            fn setattr(&mut self, uid: u32, ino: u64, mode: Option<u32>, size: Option<u64>) -> Result<Attr, i32> {
+                let valid = mode.map_or(0, |_| FATTR_MODE) | size.map_or(0, |_| FATTR_SIZE);
+                let mut setattr_in = Body::default().u32(valid).u32(0).u64(0).u64(size.unwrap_or(0));
+                setattr_in = setattr_in.u64(0).u64(0).u64(0).u64(0).u32(0).u32(0).u32(0);
+                setattr_in = setattr_in.u32(mode.unwrap_or(0)).u32(0).u32(0).u32(0).u32(0);
+                let out = self.call_as(SETATTR, ino, uid, GID, setattr_in)?;
+                Ok(attr_at(&out, 16))
+            }
+
+            fn setxattr(&mut self, ino: u64, name: &str, value: &[u8]) -> Result<(), i32> {
+                let setxattr_in = Body::default().u32(value.len() as u32).u32(0);
+                self.empty(SETXATTR, ino, setxattr_in.name(name.as_bytes()).bytes(value))
+            }
+
+            /// The value, or its length when `size` is 0
+            fn getxattr(&mut self, ino: u64, name: &str, size: u32) -> Result<Vec<u8>, i32> {
+                self.call(GETXATTR, ino, Body::default().u32(size).u32(0).name(name.as_bytes()))
+            }
+
+            fn listxattr(&mut self, ino: u64, size: u32) -> Result<Vec<u8>, i32> {
+                self.call(LISTXATTR, ino, Body::default().u32(size).u32(0))
+            }
+
+            fn removexattr(&mut self, ino: u64, name: &str) -> Result<(), i32> {
+                self.empty(REMOVEXATTR, ino, Body::default().name(name.as_bytes()))
+            }
+
+            /// `(start, end, type, pid)` of the conflicting lock, or of the
+            /// probed range as F_UNLCK when there is none
+            fn getlk(&mut self, ino: u64, owner: u64, start: u64, end: u64, typ: i32) -> Result<(u64, u64, i32, u32), i32> {
+                let out = self.call(GETLK, ino, Body::default().lk_in(owner, start, end, typ))?;
+                Ok((u64_at(&out, 0), u64_at(&out, 8), u32_at(&out, 16) as i32, u32_at(&out, 20)))
+            }
+
+            fn setlk(&mut self, ino: u64, owner: u64, start: u64, end: u64, typ: i32) -> Result<(), i32> {
+                self.empty(SETLK, ino, Body::default().lk_in(owner, start, end, typ))
+            }
+
+            fn open(&mut self, ino: u64, flags: i32) -> Result<u64, i32> {
+                let out = self.call(OPEN, ino, Body::default().u32(flags as u32).u32(0))?;
+                Ok(u64_at(&out, 0))
+            }
+
+            /// The FOPEN flags a new handle is opened with
+            fn open_flags(&mut self, ino: u64, flags: i32) -> Result<u32, i32> {
+                let out = self.call(OPEN, ino, Body::default().u32(flags as u32).u32(0))?;
+                Ok(u32_at(&out, 8))
+            }
+
+            fn release(&mut self, ino: u64, fh: u64, lock_owner: Option<u64>) -> Result<(), i32> {
+                let release_flags = lock_owner.map_or(0, |_| FUSE_RELEASE_FLOCK_UNLOCK);
+                self.empty(RELEASE, ino, Body::default().u64(fh).u32(0).u32(release_flags).u64(lock_owner.unwrap_or(0)))
+            }
+
+            /// Bytes copied from `(ino_in, offset_in)` to `(ino_out, offset_out)`
+            fn copy_file_range(
+                &mut self,
+                (ino_in, fh_in, offset_in): (u64, u64, u64),
+                (ino_out, fh_out, offset_out): (u64, u64, u64),
+                len: u64,
+                flags: u64,
+            ) -> Result<u32, i32> {
+                let copy_in = Body::default().u64(fh_in).u64(offset_in).u64(ino_out).u64(fh_out).u64(offset_out);
+                let out = self.call(COPY_FILE_RANGE, ino_in, copy_in.u64(len).u64(flags))?;
+                Ok(u32_at(&out, 0))
+            }
+
+            fn opendir(&mut self, ino: u64) -> Result<u64, i32> {
+                let out = self.call(OPENDIR, ino, Body::default().u32(0).u32(0))?;
+                Ok(u64_at(&out, 0))
+            }
+
+            fn releasedir(&mut self, ino: u64, fh: u64) -> Result<(), i32> {
+                self.empty(RELEASEDIR, ino, Body::default().u64(fh).u32(0).u32(0).u64(0))
+            }
+
+            fn access(&mut self, ino: u64, mask: i32) -> Result<(), i32> {
+                self.empty(ACCESS, ino, Body::default().u32(mask as u32).u32(0))
+            }
+
+            fn fsync(&mut self, ino: u64) -> Result<(), i32> {
+                self.empty(FSYNC, ino, Body::default().u64(0).u32(0).u32(0))
+            }
+        }
+
+        #[test]
+        fn test_golden_lookup_getattr_readdir() {
+            let mut h = Harness::new();
+            let (file, _) = h.create(1, "a.txt", 0o666).unwrap();
+            let dir = h.mkdir(1, "dir").unwrap();
+            assert_eq!((file.ino, dir.ino), (2, 3));
+            assert_eq!(file.mode, libc::S_IFREG | 0o644);
+            assert_eq!(dir.mode, libc::S_IFDIR | 0o755);
+            assert_eq!((file.uid, file.gid), (1000, 1000));
+
+            assert_eq!(h.lookup(1, "a.txt"), Ok(file));
+            assert_eq!(h.getattr(dir.ino), Ok(dir));
+            assert_eq!(h.lookup(1, "missing"), Err(libc::ENOENT));
+            assert_eq!(h.lookup(dir.ino, "a.txt"), Err(libc::ENOENT));
+            assert_eq!(h.lookup_bytes(1, b"\xff"), Err(libc::ENOENT));
+            assert_eq!(h.getattr(99), Err(libc::ENOENT));
+            assert_eq!(h.mkdir(1, "a.txt"), Err(libc::EEXIST));
+            assert_eq!(h.create(1, "dir", 0o644).map(|(attr, _)| attr), Err(libc::EEXIST));
+
+            // Cookies: 1 and 2 for the dot entries, inode + 1 for children
+            let listing = h.readdir(1, 0).unwrap();
+            let expected = vec![
+                (1, 1, ".".to_string()),
+                (1, 2, "..".to_string()),
+                (2, 3, "a.txt".to_string()),
+                (3, 4, "dir".to_string()),
+            ];
+            assert_eq!(listing, expected);
+            assert_eq!(h.readdir(1, 3).unwrap(), expected[3..]);
+            assert_eq!(h.readdir(1, 4).unwrap(), vec![]);
+            assert_eq!(h.readdir(dir.ino, 0).unwrap()[1], (1, 2, "..".to_string()));
+            assert_eq!(h.readdir(file.ino, 0), Err(libc::ENOENT));
+
+            // A failing backend lookup is an I/O error, not a missing entry
+            h.memory.fail_next(FsMethod::FindChild, 1, ErrorKind::Other);
+            assert_eq!(h.lookup(1, "a.txt"), Err(libc::EIO));
+            h.memory.fail_next(FsMethod::ListDirectory, 1, ErrorKind::Other);
+            assert_eq!(h.readdir(1, 0), Err(libc::EIO));
+        }
+
+        #[test]
+        fn test_golden_create_read_write() {
+            let mut h = Harness::new();
+            let (file, fh) = h.create(1, "data.bin", 0o600).unwrap();
+            assert_eq!(h.write(file.ino, fh, 0, b"hello world"), Ok(11));
+            assert_eq!(h.write(file.ino, fh, 6, b"there"), Ok(5));
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"hello there");
+            assert_eq!(h.read(file.ino, 6, 3).unwrap(), b"the");
+            assert_eq!(h.read(file.ino, 100, 10).unwrap(), b"");
+            assert_eq!(h.getattr(file.ino).unwrap().size, 11);
+
+            // O_APPEND handles write at the end whatever offset the kernel passes
+            let appender = h.open(file.ino, libc::O_WRONLY | libc::O_APPEND).unwrap();
+            assert_ne!(appender, fh);
+            assert_eq!(h.write(file.ino, appender, 0, b"!"), Ok(1));
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"hello there!");
+            assert_eq!(h.release(file.ino, appender, None), Ok(()));
+            assert_eq!(h.open(99, libc::O_RDONLY), Err(libc::ENOENT));
+
+            // Storage errors map to the errno the caller can act on
+            for (kind, errno) in [
+                (ErrorKind::StorageFull, libc::ENOSPC),
+                (ErrorKind::QuotaExceeded, libc::EDQUOT),
+                (ErrorKind::ReadOnlyFilesystem, libc::EROFS),
+                (ErrorKind::Other, libc::EIO),
+            ] {
+                h.memory.fail_next(FsMethod::WriteFile, 1, kind);
+                assert_eq!(h.write(file.ino, fh, 0, b"lost"), Err(errno));
+                h.memory.fail_next(FsMethod::CreateFile, 1, kind);
+                assert_eq!(h.create(1, "new.bin", 0o644).map(|(attr, _)| attr), Err(errno));
+            }
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"hello there!");
+            h.memory.fail_next(FsMethod::ReadFile, 1, ErrorKind::Other);
+            assert_eq!(h.read(file.ino, 0, 4096), Err(libc::EIO));
+            h.memory.fail_next(FsMethod::FindChild, 1, ErrorKind::Other);
+            assert_eq!(h.create(1, "new.bin", 0o644).map(|(attr, _)| attr), Err(libc::EIO));
+            assert_eq!(h.lookup(1, "new.bin"), Err(libc::ENOENT));
+        }
+
+        #[test]
+        fn test_golden_uncached_opens() {
+            let mut h = Harness::new();
+            let (file, fh) = h.create(1, "scan.bin", 0o644).unwrap();
+            assert_eq!(h.write(file.ino, fh, 0, b"streamed"), Ok(8));
+            let direct = fuser::consts::FOPEN_DIRECT_IO;
+            assert_eq!(h.open_flags(file.ino, libc::O_RDONLY), Ok(0));
+            assert_eq!(h.open_flags(file.ino, libc::O_RDONLY | libc::O_DIRECT), Ok(direct));
+
+            assert_eq!(h.setxattr(file.ino, NOCACHE_XATTR, b"sometimes"), Err(libc::EINVAL));
+            assert_eq!(h.setxattr(file.ino, NOCACHE_XATTR, b"on"), Ok(()));
+            assert_eq!(h.open_flags(file.ino, libc::O_RDONLY), Ok(direct));
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"streamed");
+            assert_eq!(h.setxattr(file.ino, NOCACHE_XATTR, b"off"), Ok(()));
+            assert_eq!(h.open_flags(file.ino, libc::O_RDONLY), Ok(0));
+        }
+
+        #[test]
+        fn test_golden_copy_file_range() {
+            let mut h = Harness::new();
+            let (source, source_fh) = h.create(1, "source.bin", 0o600).unwrap();
+            assert_eq!(h.write(source.ino, source_fh, 0, b"hello world"), Ok(11));
+            let (copy, copy_fh) = h.create(1, "copy.bin", 0o600).unwrap();
+
+            // The whole file, then ranges of it over and past the end of the copy
+            let (from, to) = ((source.ino, source_fh, 0), (copy.ino, copy_fh, 0));
+            assert_eq!(h.copy_file_range(from, to, u64::MAX, 0), Ok(11));
+            assert_eq!(h.read(copy.ino, 0, 4096).unwrap(), b"hello world");
+            assert_eq!(h.copy_file_range(from, (copy.ino, copy_fh, 6), 5, 0), Ok(5));
+            assert_eq!(h.read(copy.ino, 0, 4096).unwrap(), b"hello hello");
+            assert_eq!(h.copy_file_range((source.ino, source_fh, 8), (copy.ino, copy_fh, 11), 100, 0), Ok(3));
+            assert_eq!(h.read(copy.ino, 0, 4096).unwrap(), b"hello hellorld");
+            assert_eq!(h.copy_file_range((source.ino, source_fh, 11), to, 100, 0), Ok(0));
+            assert_eq!(h.read(source.ino, 0, 4096).unwrap(), b"hello world");
+
+            assert_eq!(h.copy_file_range(from, to, 11, 1), Err(libc::EINVAL));
+            let reader = h.open(copy.ino, libc::O_RDONLY).unwrap();
+            assert_eq!(h.copy_file_range(from, (copy.ino, reader, 0), 11, 0), Err(libc::EBADF));
+        }
+
+        #[test]
+        fn test_golden_unlink_rmdir() {
+            let mut h = Harness::new();
+            let dir = h.mkdir(1, "dir").unwrap();
+            let (inner, fh) = h.create(dir.ino, "inner", 0o644).unwrap();
+            h.release(inner.ino, fh, None).unwrap();
+            let (file, fh) = h.create(1, "file", 0o644).unwrap();
+            h.release(file.ino, fh, None).unwrap();
+
+            assert_eq!(h.unlink(1, "missing"), Err(libc::ENOENT));
+            assert_eq!(h.rmdir(1, "missing"), Err(libc::ENOENT));
+            assert_eq!(h.rmdir(1, "file"), Err(libc::ENOTDIR));
+            assert_eq!(h.rmdir(1, "dir"), Err(libc::ENOTEMPTY));
+
+            h.memory.fail_next(FsMethod::DeleteFile, 1, ErrorKind::ReadOnlyFilesystem);
+            assert_eq!(h.unlink(dir.ino, "inner"), Err(libc::EROFS));
+            h.memory.fail_next(FsMethod::FindChild, 1, ErrorKind::Other);
+            assert_eq!(h.unlink(dir.ino, "inner"), Err(libc::EIO));
+            assert_eq!(h.unlink(dir.ino, "inner"), Ok(()));
+            assert_eq!(h.lookup(dir.ino, "inner"), Err(libc::ENOENT));
+
+            h.memory.fail_next(FsMethod::ListDirectory, 1, ErrorKind::Other);
+            assert_eq!(h.rmdir(1, "dir"), Err(libc::EIO));
+            assert_eq!(h.rmdir(1, "dir"), Ok(()));
+            assert_eq!(h.unlink(1, "file"), Ok(()));
+            assert_eq!(h.readdir(1, 2).unwrap(), vec![]);
+        }
+
+        #[test]
+        fn test_golden_rename() {
+            let mut h = Harness::new();
+            let dir = h.mkdir(1, "dir").unwrap();
+            let sub = h.mkdir(dir.ino, "sub").unwrap();
+            let (file, fh) = h.create(1, "file", 0o644).unwrap();
+            h.write(file.ino, fh, 0, b"moved").unwrap();
+            h.release(file.ino, fh, None).unwrap();
+            let (other, fh) = h.create(dir.ino, "other", 0o644).unwrap();
+            h.release(other.ino, fh, None).unwrap();
+
+            // Names are bytes all the way, Latin-1 included
+            assert_eq!(h.rename(1, b"file", dir.ino, b"caf\xe9"), Ok(()));
+            assert_eq!(h.lookup(1, "file"), Err(libc::ENOENT));
+            assert_eq!(h.lookup_bytes(dir.ino, b"caf\xe9").map(|attr| attr.ino), Ok(file.ino));
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"moved");
+
+            assert_eq!(h.rename(1, b"missing", 1, b"x"), Err(libc::ENOENT));
+            assert_eq!(h.rename2(dir.ino, b"caf\xe9", dir.ino, b"other", libc::RENAME_NOREPLACE), Err(libc::EEXIST));
+            assert_eq!(h.rename2(dir.ino, b"caf\xe9", dir.ino, b"other", libc::RENAME_EXCHANGE), Err(libc::EINVAL));
+            assert_eq!(h.rename(dir.ino, b"caf\xe9", dir.ino, b"sub"), Err(libc::EISDIR));
+            assert_eq!(h.rename(dir.ino, b"sub", dir.ino, b"other"), Err(libc::ENOTDIR));
+            assert_eq!(h.rename(1, b"dir", sub.ino, b"loop"), Err(libc::EINVAL));
+            assert_eq!(h.rename(1, b"dir", dir.ino, b"self"), Err(libc::EINVAL));
+
+            // Replacing an entry removes it
+            assert_eq!(h.rename(dir.ino, b"caf\xe9", dir.ino, b"other"), Ok(()));
+            assert_eq!(h.lookup(dir.ino, "other").map(|attr| attr.ino), Ok(file.ino));
+            assert_eq!(h.getattr(other.ino), Err(libc::ENOENT));
+
+            // A directory only replaces an empty one
+            let (inner, fh) = h.create(sub.ino, "inner", 0o644).unwrap();
+            h.release(inner.ino, fh, None).unwrap();
+            let empty = h.mkdir(1, "empty").unwrap();
+            assert_eq!(h.rename(1, b"empty", dir.ino, b"sub"), Err(libc::ENOTEMPTY));
+            assert_eq!(h.rename(dir.ino, b"sub", 1, b"empty"), Ok(()));
+            assert_eq!(h.lookup(1, "empty").map(|attr| attr.ino), Ok(sub.ino));
+            assert_eq!(h.getattr(empty.ino), Err(libc::ENOENT));
+            assert_eq!(h.lookup(sub.ino, "inner").map(|attr| attr.ino), Ok(inner.ino));
+
+            h.memory.fail_next(FsMethod::UpdateInode, 1, ErrorKind::ReadOnlyFilesystem);
+            assert_eq!(h.rename(1, b"empty", 1, b"renamed"), Err(libc::EROFS));
+            assert_eq!(h.lookup(1, "empty").map(|attr| attr.ino), Ok(sub.ino));
+        }
+
+        #[test]
+        fn test_golden_unlinked_file_lives_until_last_release() {
+            let mut h = Harness::new();
+            let (file, fh) = h.create(1, "scratch", 0o644).unwrap();
+            let reader = h.open(file.ino, libc::O_RDONLY).unwrap();
+            h.write(file.ino, fh, 0, b"still here").unwrap();
+            assert_eq!(h.generation(1, "scratch"), Ok(0));
+
+            // Gone from the namespace, but open handles keep working
+            assert_eq!(h.unlink(1, "scratch"), Ok(()));
+            assert_eq!(h.lookup(1, "scratch"), Err(libc::ENOENT));
+            assert_eq!(h.readdir(1, 2).unwrap(), vec![]);
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"still here");
+            assert_eq!(h.getattr(file.ino).map(|attr| attr.size), Ok(10));
+
+            h.release(file.ino, fh, None).unwrap();
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"still here");
+            h.release(file.ino, reader, None).unwrap();
+            assert_eq!(h.getattr(file.ino), Err(libc::ENOENT));
+
+            // Directory handles are distinct from every file handle
+            let dir = h.mkdir(1, "dir").unwrap();
+            let first = h.opendir(dir.ino).unwrap();
+            let second = h.opendir(dir.ino).unwrap();
+            assert!(first != second && first > reader && second > reader);
+            assert_eq!(h.opendir(99), Err(libc::ENOENT));
+            assert_eq!(h.releasedir(dir.ino, first), Ok(()));
+            assert_eq!(h.releasedir(dir.ino, second), Ok(()));
+        }
+
+        #[test]
+        fn test_golden_xattrs() {
+            let mut h = Harness::new();
+            let (file, _) = h.create(1, "f", 0o644).unwrap();
+
+            assert_eq!(h.setxattr(file.ino, "user.tag", b"blue"), Ok(()));
+            assert_eq!(h.getxattr(file.ino, "user.tag", 0), Ok(4u32.to_ne_bytes().iter().chain(&[0; 4]).copied().collect()));
+            assert_eq!(h.getxattr(file.ino, "user.tag", 64).unwrap(), b"blue");
+            assert_eq!(h.getxattr(file.ino, "user.tag", 3), Err(libc::ERANGE));
+            assert_eq!(h.getxattr(file.ino, "user.other", 64), Err(libc::ENODATA));
+            assert_eq!(h.getxattr(99, "user.tag", 64), Err(libc::ENOENT));
+
+            // Limits on names and values
+            assert_eq!(h.setxattr(file.ino, &format!("user.{}", "n".repeat(251)), b"v"), Err(libc::ERANGE));
+            assert_eq!(h.setxattr(file.ino, "user.big", &vec![0; MAX_XATTR_SIZE + 1]), Err(libc::ERANGE));
+            assert_eq!(h.setxattr(99, "user.tag", b"v"), Err(libc::ENOENT));
+
+            // Synthetic xattrs: read-only layout, validated settings, policy held by the backend
+            assert_eq!(h.setxattr(file.ino, LAYOUT_XATTR, b"{}"), Err(libc::EPERM));
+            assert_eq!(h.removexattr(file.ino, LAYOUT_XATTR), Err(libc::EPERM));
+            assert_eq!(h.getxattr(file.ino, LAYOUT_XATTR, 64), Err(libc::ENODATA));
+            assert_eq!(h.setxattr(file.ino, VERIFY_WRITES_XATTR, b"maybe"), Err(libc::EINVAL));
+            assert_eq!(h.setxattr(file.ino, VERIFY_WRITES_XATTR, b"on"), Ok(()));
+            assert_eq!(h.setxattr(file.ino, REDUNDANCY_XATTR, b"mirrored"), Err(libc::EINVAL));
+            assert_eq!(h.setxattr(file.ino, REDUNDANCY_XATTR, b"replication:2"), Err(libc::EIO));
+            assert_eq!(h.getxattr(file.ino, REDUNDANCY_XATTR, 64), Err(libc::EIO));
+
+            let expected = b"user.scfs.verify_writes\0user.tag\0user.scfs.redundancy\0user.scfs.layout\0";
+            assert_eq!(h.listxattr(file.ino, 4096).unwrap(), expected);
+            assert_eq!(u32_at(&h.listxattr(file.ino, 0).unwrap(), 0), expected.len() as u32);
+            assert_eq!(h.listxattr(file.ino, 8), Err(libc::ERANGE));
+            let dir = h.mkdir(1, "d").unwrap();
+            assert_eq!(h.listxattr(dir.ino, 4096).unwrap(), b"");
+
+            assert_eq!(h.removexattr(file.ino, "user.tag"), Ok(()));
+            assert_eq!(h.removexattr(file.ino, "user.tag"), Err(libc::ENODATA));
+            h.memory.fail_next(FsMethod::UpdateInode, 1, ErrorKind::StorageFull);
+            assert_eq!(h.setxattr(file.ino, "user.tag", b"v"), Err(libc::ENOSPC));
+            assert_eq!(h.getxattr(file.ino, "user.tag", 64), Err(libc::ENODATA));
+        }
+
+        #[test]
+        fn test_golden_posix_locks() {
+            let mut h = Harness::new();
+            let (file, fh) = h.create(1, "locked", 0o644).unwrap();
+
+            assert_eq!(h.setlk(file.ino, 1, 0, 99, libc::F_WRLCK), Ok(()));
+            assert_eq!(h.getlk(file.ino, 2, 50, 60, libc::F_RDLCK), Ok((0, 99, libc::F_WRLCK, 1)));
+            assert_eq!(h.getlk(file.ino, 2, 100, 200, libc::F_RDLCK), Ok((100, 200, libc::F_UNLCK, 2)));
+            assert_eq!(h.getlk(file.ino, 1, 0, 10, libc::F_WRLCK), Ok((0, 10, libc::F_UNLCK, 1)));
+            assert_eq!(h.setlk(file.ino, 2, 50, 60, libc::F_RDLCK), Err(libc::EAGAIN));
+            assert_eq!(h.setlk(file.ino, 2, 0, 10, 42), Err(libc::EINVAL));
+            assert_eq!(h.getlk(file.ino, 2, 0, 10, 42), Err(libc::EINVAL));
+
+            // Closing releases the owner's locks
+            assert_eq!(h.release(file.ino, fh, Some(1)), Ok(()));
+            assert_eq!(h.setlk(file.ino, 2, 50, 60, libc::F_RDLCK), Ok(()));
+            assert_eq!(h.setlk(file.ino, 2, 50, 60, libc::F_UNLCK), Ok(()));
+            assert_eq!(h.getlk(file.ino, 3, 0, 99, libc::F_WRLCK), Ok((0, 99, libc::F_UNLCK, 3)));
+        }
+
+        #[test]
+        fn test_golden_setattr() {
+            let mut h = Harness::new();
+            let (file, fh) = h.create(1, "f", 0o644).unwrap();
+            h.write(file.ino, fh, 0, b"contents").unwrap();
+
+            assert_eq!(h.setattr(1001, file.ino, Some(0o600), None), Err(libc::EPERM));
+            assert_eq!(h.setattr(UID, file.ino, Some(0o600), None).unwrap().mode, libc::S_IFREG | 0o600);
+            assert_eq!(h.setattr(0, file.ino, Some(0o640), None).unwrap().mode, libc::S_IFREG | 0o640);
+            assert_eq!(h.setattr(UID, 99, Some(0o600), None), Err(libc::ENOENT));
+
+            h.memory.fail_next(FsMethod::UpdateInode, 1, ErrorKind::ReadOnlyFilesystem);
+            assert_eq!(h.setattr(UID, file.ino, Some(0o600), None), Err(libc::EROFS));
+            h.memory.fail_next(FsMethod::WriteFile, 1, ErrorKind::StorageFull);
+            assert_eq!(h.setattr(UID, file.ino, None, Some(0)), Err(libc::ENOSPC));
+            assert_eq!(h.setattr(UID, file.ino, None, Some(0)).unwrap().size, 0);
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"");
+
+            assert_eq!(h.fsync(99), Err(libc::ENOENT));
+            h.memory.fail_next(FsMethod::SyncInode, 1, ErrorKind::Other);
+            assert_eq!(h.fsync(file.ino), Err(libc::EIO));
+            assert_eq!(h.fsync(file.ino), Ok(()));
+        }
+
+        #[test]
+        fn test_golden_permissions() {
+            let mut h = Harness::new();
+            let (file, fh) = h.create(1, "private", 0o600).unwrap();
+            h.write(file.ino, fh, 0, b"secret").unwrap();
+            let dir = h.mkdir(1, "shared").unwrap();
+            let other = 1001;
+
+            // access(2): the owner class for the owner, the other class for everyone else
+            assert_eq!(h.access(file.ino, libc::R_OK | libc::W_OK), Ok(()));
+            assert_eq!(h.access(file.ino, libc::X_OK), Err(libc::EACCES));
+            assert_eq!(h.access(99, libc::F_OK), Err(libc::ENOENT));
+            h.uid = other;
+            assert_eq!(h.access(file.ino, libc::F_OK), Ok(()));
+            assert_eq!(h.access(file.ino, libc::R_OK), Err(libc::EACCES));
+            h.uid = 0;
+            assert_eq!(h.access(file.ino, libc::R_OK | libc::W_OK), Ok(()));
+            assert_eq!(h.access(file.ino, libc::X_OK), Err(libc::EACCES));
+
+            // Another user can neither use nor remove the owner's 0600 file
+            h.uid = other;
+            assert_eq!(h.open(file.ino, libc::O_RDONLY), Err(libc::EACCES));
+            assert_eq!(h.read(file.ino, 0, 4096), Err(libc::EACCES));
+            assert_eq!(h.write(file.ino, 0, 0, b"mine"), Err(libc::EACCES));
+            assert_eq!(h.getxattr(file.ino, REDUNDANCY_XATTR, 64), Err(libc::EACCES));
+            assert_eq!(h.setxattr(file.ino, REDUNDANCY_XATTR, b"replication:2"), Err(libc::EACCES));
+            assert_eq!(h.setxattr(file.ino, "user.tag", b"v"), Err(libc::EACCES));
+            assert_eq!(h.removexattr(file.ino, "user.tag"), Err(libc::EACCES));
+            assert_eq!(h.setattr(other, file.ino, None, Some(0)), Err(libc::EACCES));
+            assert_eq!(h.unlink(1, "private"), Err(libc::EACCES));
+            assert_eq!(h.create(dir.ino, "intruder", 0o644).map(|(attr, _)| attr), Err(libc::EACCES));
+            assert_eq!(h.mkdir(dir.ino, "intruder"), Err(libc::EACCES));
+
+            // 0644 lets others read but not write; a read-only handle cannot write
+            h.uid = UID;
+            h.setattr(UID, file.ino, Some(0o644), None).unwrap();
+            h.uid = other;
+            assert_eq!(h.read(file.ino, 0, 4096).unwrap(), b"secret");
+            assert_eq!(h.open(file.ino, libc::O_WRONLY), Err(libc::EACCES));
+            assert_eq!(h.open(file.ino, libc::O_RDONLY | libc::O_TRUNC), Err(libc::EACCES));
+            let reader = h.open(file.ino, libc::O_RDONLY).unwrap();
+            assert_eq!(h.write(file.ino, reader, 0, b"mine"), Err(libc::EBADF));
+
+            // New inodes belong to their creator
+            h.uid = UID;
+            h.setattr(UID, dir.ino, Some(0o777), None).unwrap();
+            h.uid = other;
+            let (theirs, fh) = h.create(dir.ino, "theirs", 0o600).unwrap();
+            assert_eq!((theirs.uid, theirs.gid, theirs.mode), (other, GID, libc::S_IFREG | 0o600));
+            assert_eq!(h.write(theirs.ino, fh, 0, b"hers"), Ok(4));
+            h.uid = UID;
+            assert_eq!(h.read(theirs.ino, 0, 4096), Err(libc::EACCES));
+            assert_eq!(h.unlink(dir.ino, "theirs"), Ok(()));
+        }
+    }
+}
@@ -61,9 +61,15 @@ pub struct OptimizedFUSEConfig {
     pub enable_readahead: bool,
     
     /// Read-ahead size in bytes (default: 128KB)
-    /// Larger values improve sequential read performance
+    /// Forward gaps shorter than this still count as sequential reading
     pub readahead_size: usize,
     
+    /// Extents decoded ahead of a sequential reader (K)
+    pub readahead_extents: usize,
+    
+    /// Sequential reads on a handle before read-ahead starts
+    pub readahead_trigger: u32,
+    
     /// Number of FUSE worker threads for parallel operations
     /// Recommended: Number of CPU cores
     pub worker_threads: usize,
@@ -91,6 +97,9 @@ pub struct OptimizedFUSEConfig {
     
     /// Maximum write size for single operation
     pub max_write_size: usize,
+    
+    /// Grant waiting write locks before waiting read locks instead of in arrival order
+    pub lock_writer_priority: bool,
 }
 
 impl OptimizedFUSEConfig {
@@ -107,6 +116,8 @@ impl OptimizedFUSEConfig {
             entry_timeout_secs: 5,
             enable_readahead: true,
             readahead_size: 128 * 1024, // 128KB
+            readahead_extents: 2,
+            readahead_trigger: 2,
             worker_threads,
             enable_writeback: false, // Conservative default
             writeback_buffer_size: 4 * 1024 * 1024, // 4MB
@@ -116,6 +127,7 @@ impl OptimizedFUSEConfig {
             enable_splice: true,
             max_read_size: 1024 * 1024, // 1MB
             max_write_size: 1024 * 1024, // 1MB
+            lock_writer_priority: false,
         }
     }
     
@@ -133,6 +145,8 @@ impl OptimizedFUSEConfig {
             entry_timeout_secs: 10,
             enable_readahead: true,
             readahead_size: 256 * 1024, // 256KB
+            readahead_extents: 4,
+            readahead_trigger: 2,
             worker_threads,
             enable_writeback: true, // Aggressive
             writeback_buffer_size: 16 * 1024 * 1024, // 16MB
@@ -142,6 +156,7 @@ impl OptimizedFUSEConfig {
             enable_splice: true,
             max_read_size: 2 * 1024 * 1024, // 2MB
             max_write_size: 2 * 1024 * 1024, // 2MB
+            lock_writer_priority: false,
         }
     }
     
@@ -159,6 +174,8 @@ impl OptimizedFUSEConfig {
             entry_timeout_secs: 1,
             enable_readahead: true,
             readahead_size: 64 * 1024, // 64KB
+            readahead_extents: 1,
+            readahead_trigger: 3,
             worker_threads,
             enable_writeback: false,
             writeback_buffer_size: 1024 * 1024, // 1MB
@@ -168,6 +185,7 @@ impl OptimizedFUSEConfig {
             enable_splice: false,
             max_read_size: 512 * 1024, // 512KB
             max_write_size: 512 * 1024, // 512KB
+            lock_writer_priority: false,
         }
     }
     
@@ -317,6 +335,9 @@ pub struct XAttrCacheStats {
 }
 
 /// Read-ahead manager for sequential access detection
+///
+/// Tracks each open file handle on its own, so two readers streaming the same
+/// file, or one streaming while another seeks, do not break each other's run.
 pub struct ReadAheadManager {
     patterns: Arc<Mutex<HashMap<u64, AccessPattern>>>,
     config: OptimizedFUSEConfig,
@@ -324,10 +345,21 @@ pub struct ReadAheadManager {
 
 #[derive(Debug, Clone)]
 struct AccessPattern {
-    last_offset: u64,
+    /// Where the last read on the handle ended
+    next_offset: u64,
     last_access: Instant,
     sequential_count: u32,
-    prefetch_offset: u64,
+}
+
+/// What to do about read-ahead after a read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadAhead {
+    /// Nothing to change
+    Idle,
+    /// The handle reads sequentially: fetch ahead of it
+    Prefetch(PrefetchHint),
+    /// A sequential run ended in a seek; drop read-ahead for the file
+    Cancel,
 }
 
 impl ReadAheadManager {
@@ -338,82 +370,65 @@ impl ReadAheadManager {
         }
     }
     
-    /// Record a read access and detect sequential patterns
-    pub fn record_access(&self, ino: u64, offset: u64, size: usize) -> Option<PrefetchHint> {
-        if !self.config.enable_readahead {
-            return None;
+    /// Record a read of `size` bytes at `offset` through handle `fh` of `ino`
+    ///
+    /// A read starting where the previous one ended, or a little past it, extends
+    /// the run. Once the run reaches `readahead_trigger` reads, every read asks
+    /// for the `readahead_extents` extents after it.
+    pub fn record_access(&self, fh: u64, ino: u64, offset: u64, size: usize) -> ReadAhead {
+        if !self.config.enable_readahead || self.config.readahead_extents == 0 {
+            return ReadAhead::Idle;
         }
         
         let mut patterns = self.patterns.lock().unwrap();
-        
-        let pattern = patterns.entry(ino).or_insert(AccessPattern {
-            last_offset: 0,
+        let pattern = patterns.entry(fh).or_insert(AccessPattern {
+            // The first read on a handle starts a run rather than extending one
+            next_offset: u64::MAX,
             last_access: Instant::now(),
             sequential_count: 0,
-            prefetch_offset: 0,
         });
         
-        // Detect sequential access
-        let is_sequential = offset == pattern.last_offset + size as u64 ||
-                           (offset > pattern.last_offset && 
-                            offset - pattern.last_offset < self.config.readahead_size as u64);
-        
+        let streaming = pattern.sequential_count >= self.config.readahead_trigger;
+        let is_sequential = offset >= pattern.next_offset
+            && offset - pattern.next_offset < self.config.readahead_size as u64
+            && pattern.last_access.elapsed() < Duration::from_secs(READAHEAD_IDLE_SECS);
         if is_sequential {
             pattern.sequential_count += 1;
         } else {
             pattern.sequential_count = 0;
         }
-        
-        pattern.last_offset = offset;
+        pattern.next_offset = offset + size as u64;
         pattern.last_access = Instant::now();
         
-        // Trigger prefetch if sequential pattern detected
-        if pattern.sequential_count >= 2 {
-            let prefetch_offset = offset + size as u64;
-            let prefetch_size = self.config.readahead_size;
-            
-            // Update prefetch offset
-            pattern.prefetch_offset = prefetch_offset + prefetch_size as u64;
-            
-            return Some(PrefetchHint {
+        if pattern.sequential_count >= self.config.readahead_trigger {
+            ReadAhead::Prefetch(PrefetchHint {
                 ino,
-                offset: prefetch_offset,
-                size: prefetch_size,
-            });
-        }
-        
-        None
-    }
-    
-    /// Check if data should be prefetched
-    pub fn should_prefetch(&self, ino: u64, offset: u64) -> bool {
-        if !self.config.enable_readahead {
-            return false;
-        }
-        
-        let patterns = self.patterns.lock().unwrap();
-        if let Some(pattern) = patterns.get(&ino) {
-            // Prefetch if we're approaching the prefetch offset
-            if offset >= pattern.prefetch_offset.saturating_sub(64 * 1024) {
-                return true;
-            }
+                offset: pattern.next_offset,
+                extents: self.config.readahead_extents,
+            })
+        } else if streaming {
+            ReadAhead::Cancel
+        } else {
+            ReadAhead::Idle
         }
-        
-        false
     }
     
-    /// Clear pattern tracking for an inode
-    pub fn invalidate(&self, ino: u64) {
-        let mut patterns = self.patterns.lock().unwrap();
-        patterns.remove(&ino);
+    /// Stop tracking a closed file handle
+    pub fn forget_handle(&self, fh: u64) {
+        self.patterns.lock().unwrap().remove(&fh);
     }
 }
 
-#[derive(Debug, Clone)]
+/// A reader pausing this long starts a new run
+const READAHEAD_IDLE_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PrefetchHint {
     pub ino: u64,
+    /// Next byte the reader is expected to ask for
     pub offset: u64,
-    pub size: usize,
+    /// Extents to fetch from there
+    pub extents: usize,
 }
 
 #[cfg(test)]
@@ -458,21 +473,17 @@ mod tests {
         let manager = ReadAheadManager::new(config.clone());
         
         // First access
-        let hint = manager.record_access(1, 0, 4096);
-        assert!(hint.is_none()); // Not sequential yet
+        assert_eq!(manager.record_access(7, 1, 0, 4096), ReadAhead::Idle); // Not sequential yet
         
         // Second sequential access
-        let hint = manager.record_access(1, 4096, 4096);
-        assert!(hint.is_none()); // Still building pattern
+        assert_eq!(manager.record_access(7, 1, 4096, 4096), ReadAhead::Idle); // Still building pattern
         
         // Third sequential access - should trigger prefetch
-        let hint = manager.record_access(1, 8192, 4096);
-        assert!(hint.is_some());
-        
-        let hint = hint.unwrap();
-        assert_eq!(hint.ino, 1);
-        assert_eq!(hint.offset, 8192 + 4096);
-        assert_eq!(hint.size, config.readahead_size);
+        let hint = manager.record_access(7, 1, 8192, 4096);
+        assert_eq!(
+            hint,
+            ReadAhead::Prefetch(PrefetchHint { ino: 1, offset: 8192 + 4096, extents: config.readahead_extents })
+        );
     }
     
     #[test]
@@ -481,12 +492,30 @@ mod tests {
         let manager = ReadAheadManager::new(config);
         
         // Random access pattern
-        manager.record_access(1, 0, 4096);
-        manager.record_access(1, 100000, 4096);
-        let hint = manager.record_access(1, 50000, 4096);
+        manager.record_access(7, 1, 0, 4096);
+        manager.record_access(7, 1, 100000, 4096);
+        let hint = manager.record_access(7, 1, 50000, 4096);
         
         // Should not trigger prefetch for random access
-        assert!(hint.is_none());
+        assert_eq!(hint, ReadAhead::Idle);
+    }
+    
+    #[test]
+    fn test_readahead_tracks_handles_apart_and_cancels_on_seek() {
+        let manager = ReadAheadManager::new(OptimizedFUSEConfig::balanced());
+        for i in 0..3 {
+            manager.record_access(7, 1, i * 131072, 131072);
+            // A second handle seeking around the same file does not disturb the first
+            assert_eq!(manager.record_access(8, 1, (5 - i) * 131072, 4096), ReadAhead::Idle);
+        }
+        assert!(matches!(manager.record_access(7, 1, 3 * 131072, 131072), ReadAhead::Prefetch(_)));
+        
+        assert_eq!(manager.record_access(7, 1, 0, 131072), ReadAhead::Cancel);
+        assert_eq!(manager.record_access(7, 1, 10 << 20, 131072), ReadAhead::Idle);
+        
+        // A reopened handle starts over
+        manager.forget_handle(8);
+        assert_eq!(manager.record_access(8, 1, 0, 4096), ReadAhead::Idle);
     }
     
     #[test]
@@ -1,16 +1,24 @@
 use anyhow::{Context, Result};
-use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::SystemTime;
 use uuid::Uuid;
 
 use crate::disk::Disk;
+use crate::io_scheduler::IoClass;
 use crate::metadata::MetadataManager;
+use crate::error::StorageResult;
+
+/// File in the pool directory recording what the background collector last did
+const GC_STATUS_FILE: &str = "gc_status.json";
 
 /// Orphan fragment information
 #[derive(Debug, Clone)]
 pub struct OrphanFragment {
+    pub disk_uuid: Uuid,
     pub disk_path: PathBuf,
     pub fragment_path: PathBuf,
     pub extent_uuid: Uuid,
@@ -19,23 +27,82 @@ pub struct OrphanFragment {
     pub size_bytes: u64,
 }
 
+/// Fragments referenced by metadata, keyed by disk, extent and fragment index
+type ReferencedFragments = HashSet<(Uuid, Uuid, usize)>;
+
+/// Outcome of one collection pass
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    /// Orphans removed (or, in a dry run, that would have been)
+    pub removed: Vec<OrphanFragment>,
+    /// Disks left alone because they hold fragments of extents whose metadata failed to load
+    pub skipped_disks: Vec<Uuid>,
+    /// Orphan candidates kept because a write or rebuild still owned their extent
+    pub protected: usize,
+}
+
+impl GcReport {
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.removed.iter().map(|o| o.size_bytes).sum()
+    }
+}
+
+/// Where the collector reads extent metadata from
+enum MetadataSource {
+    /// A pool that is not mounted; metadata is loaded fresh for every scan
+    Pool(PathBuf),
+    /// The metadata of a running `StorageEngine`
+    Shared(Arc<RwLock<MetadataManager>>),
+}
+
 /// Garbage collection manager for orphaned fragments
+///
+/// A fragment is an orphan when no loaded extent lists it at its disk and
+/// index. Fragments are listed before metadata is read, so a write that
+/// commits during the scan is seen as referenced rather than orphaned.
 pub struct GarbageCollector {
-    pool_dir: PathBuf,
-    disks: Vec<Disk>,
+    metadata: MetadataSource,
+    disks: Vec<Arc<Mutex<Disk>>>,
 }
 
 impl GarbageCollector {
     pub fn new(pool_dir: PathBuf, disks: Vec<Disk>) -> Self {
-        GarbageCollector { pool_dir, disks }
+        GarbageCollector {
+            metadata: MetadataSource::Pool(pool_dir),
+            disks: disks.into_iter().map(|d| Arc::new(Mutex::new(d))).collect(),
+        }
     }
 
-    /// Scan all disks and build a set of all fragment locations on disk
-    fn scan_all_fragments(&self) -> Result<HashMap<Uuid, HashSet<usize>>> {
-        let mut all_fragments: HashMap<Uuid, HashSet<usize>> = HashMap::new();
+    /// Collector working on the live metadata and disks of a mounted pool
+    pub fn for_engine(metadata: Arc<RwLock<MetadataManager>>, disks: Vec<Arc<Mutex<Disk>>>) -> Self {
+        GarbageCollector {
+            metadata: MetadataSource::Shared(metadata),
+            disks,
+        }
+    }
+
+    /// Fragment `(extent, index)` named by a file in a disk's fragment store
+    ///
+    /// Fragments are stored as `<extent>-<index>.frag`; the older
+    /// `<extent>_<index>` form is still recognised.
+    fn parse_fragment_name(name: &str) -> Option<(Uuid, usize)> {
+        let (uuid, index) = match name.strip_suffix(".frag") {
+            Some(stem) => stem.rsplit_once('-')?,
+            None => name.split_once('_')?,
+        };
+        Some((Uuid::parse_str(uuid).ok()?, index.parse().ok()?))
+    }
+
+    /// Every fragment file on every disk
+    fn scan_all_fragments(&self) -> Result<Vec<OrphanFragment>> {
+        let mut fragments = Vec::new();
 
         for disk in &self.disks {
-            let fragments_dir = disk.path.join("fragments");
+            let (disk_uuid, disk_path) = {
+                let disk = disk.lock().unwrap();
+                (disk.uuid, disk.path.clone())
+            };
+            let fragments_dir = disk_path.join("fragments");
             if !fragments_dir.exists() {
                 continue;
             }
@@ -45,90 +112,101 @@ impl GarbageCollector {
             {
                 let entry = entry?;
                 let filename = entry.file_name();
-                let filename_str = filename.to_string_lossy();
-
-                // Fragment naming: <uuid>_<index>
-                if let Some((uuid_str, index_str)) = filename_str.split_once('_') {
-                    if let (Ok(uuid), Ok(index)) = (
-                        Uuid::parse_str(uuid_str),
-                        index_str.parse::<usize>(),
-                    ) {
-                        all_fragments
-                            .entry(uuid)
-                            .or_insert_with(HashSet::new)
-                            .insert(index);
-                    }
-                }
+                let Some((extent_uuid, fragment_index)) = Self::parse_fragment_name(&filename.to_string_lossy()) else {
+                    continue;
+                };
+                // Removed since it was listed, e.g. by a rollback
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                let age_seconds = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                    .unwrap_or_default()
+                    .as_secs();
+
+                fragments.push(OrphanFragment {
+                    disk_uuid,
+                    disk_path: disk_path.clone(),
+                    fragment_path: entry.path(),
+                    extent_uuid,
+                    fragment_index,
+                    age_seconds,
+                    size_bytes: metadata.len(),
+                });
             }
         }
 
-        Ok(all_fragments)
+        Ok(fragments)
     }
 
-    /// Load all extent metadata and build a set of referenced fragment locations
-    fn scan_referenced_fragments(&self) -> Result<HashMap<Uuid, HashSet<usize>>> {
-        let metadata = MetadataManager::new(self.pool_dir.clone())?;
-        let mut referenced: HashMap<Uuid, HashSet<usize>> = HashMap::new();
-
-        // Load all extents from metadata
-        let extents = metadata.list_all_extents()?;
+    /// Metadata to check fragments against
+    fn metadata(&self) -> Result<Arc<RwLock<MetadataManager>>> {
+        match &self.metadata {
+            MetadataSource::Pool(pool_dir) => Ok(Arc::new(RwLock::new(MetadataManager::new(pool_dir.clone())?))),
+            MetadataSource::Shared(metadata) => Ok(Arc::clone(metadata)),
+        }
+    }
 
-        for extent in extents {
-            let uuid = extent.uuid;
-            for location in &extent.fragment_locations {
-                referenced
-                    .entry(uuid)
-                    .or_insert_with(HashSet::new)
-                    .insert(location.fragment_index);
+    /// Fragments referenced by loaded extents, and the extents that failed to load
+    fn scan_referenced_fragments(metadata: &MetadataManager) -> Result<(ReferencedFragments, HashSet<Uuid>)> {
+        // Only fragment references are kept, not the extents
+        let mut referenced = ReferencedFragments::default();
+        let mut unreadable = HashSet::new();
+        for (uuid, extent) in metadata.extent_records()? {
+            match extent {
+                Ok(extent) => referenced.extend(
+                    extent
+                        .fragment_locations
+                        .iter()
+                        .map(|location| (location.disk_uuid, extent.uuid, location.fragment_index)),
+                ),
+                Err(_) => {
+                    unreadable.insert(uuid);
+                }
             }
         }
-
-        Ok(referenced)
+        Ok((referenced, unreadable))
     }
 
-    /// Detect orphaned fragments (on disk but not referenced in metadata)
-    pub fn detect_orphans(&self) -> Result<Vec<OrphanFragment>> {
-        let all_fragments = self.scan_all_fragments()?;
-        let referenced = self.scan_referenced_fragments()?;
+    /// Orphans on disk, the extents among them with unreadable metadata, and
+    /// how many candidates `protected` held back
+    fn find_orphans(
+        &self,
+        metadata: &RwLock<MetadataManager>,
+        protected: &dyn Fn(&Uuid) -> bool,
+    ) -> Result<(Vec<OrphanFragment>, HashSet<Uuid>, usize)> {
+        let on_disk = self.scan_all_fragments()?;
+        // Decided before metadata is read: an extent no longer in flight by
+        // now has either committed, and is in the metadata read below, or
+        // been rolled back
+        let protected_extents: HashSet<Uuid> = on_disk
+            .iter()
+            .map(|fragment| fragment.extent_uuid)
+            .filter(|uuid| protected(uuid))
+            .collect();
+        let (referenced, unreadable) = Self::scan_referenced_fragments(&metadata.read().unwrap())?;
 
+        let mut held_back = 0;
         let mut orphans = Vec::new();
-
-        for (extent_uuid, fragment_indices) in all_fragments {
-            let referenced_indices = referenced.get(&extent_uuid).cloned().unwrap_or_default();
-
-            for fragment_index in fragment_indices {
-                if !referenced_indices.contains(&fragment_index) {
-                    // This fragment is orphaned - find it on disk
-                    for disk in &self.disks {
-                        let fragment_path = disk
-                            .path
-                            .join("fragments")
-                            .join(format!("{}_{}", extent_uuid, fragment_index));
-
-                        if fragment_path.exists() {
-                            let metadata = fs::metadata(&fragment_path)?;
-                            let modified = metadata.modified()?;
-                            let age_seconds = SystemTime::now()
-                                .duration_since(modified)
-                                .unwrap_or_default()
-                                .as_secs();
-
-                            orphans.push(OrphanFragment {
-                                disk_path: disk.path.clone(),
-                                fragment_path: fragment_path.clone(),
-                                extent_uuid,
-                                fragment_index,
-                                age_seconds,
-                                size_bytes: metadata.len(),
-                            });
-                            break;
-                        }
-                    }
-                }
+        for fragment in on_disk {
+            if referenced.contains(&(fragment.disk_uuid, fragment.extent_uuid, fragment.fragment_index)) {
+                continue;
             }
+            if protected_extents.contains(&fragment.extent_uuid) {
+                held_back += 1;
+                continue;
+            }
+            orphans.push(fragment);
         }
+        Ok((orphans, unreadable, held_back))
+    }
 
-        Ok(orphans)
+    /// Detect orphaned fragments (on disk but not referenced in metadata)
+    pub fn detect_orphans(&self) -> Result<Vec<OrphanFragment>> {
+        let metadata = self.metadata()?;
+        Ok(self.find_orphans(&metadata, &|_| false)?.0)
     }
 
     /// Clean up orphaned fragments older than the specified age
@@ -140,20 +218,76 @@ impl GarbageCollector {
     /// # Returns
     /// Vector of orphans that were (or would be) cleaned up
     pub fn cleanup_orphans(&self, min_age_seconds: u64, dry_run: bool) -> Result<Vec<OrphanFragment>> {
-        let orphans = self.detect_orphans()?;
-        let mut cleaned = Vec::new();
+        Ok(self.collect(min_age_seconds, dry_run, &|_| false)?.removed)
+    }
+
+    /// Remove orphans at least `min_age_seconds` old, with safety interlocks
+    ///
+    /// Fragments of extents for which `protected` returns true (writes and
+    /// rebuilds in progress) are kept. A disk holding any fragment of an
+    /// extent whose metadata exists but cannot be loaded is skipped entirely,
+    /// since its "orphans" may be data whose metadata is only temporarily
+    /// unreadable. Just before each deletion, under the disk lock, the
+    /// extent is checked again so a fragment referenced since the scan is kept.
+    pub fn collect(&self, min_age_seconds: u64, dry_run: bool, protected: &dyn Fn(&Uuid) -> bool) -> Result<GcReport> {
+        let _class = IoClass::Gc.enter();
+        let metadata = self.metadata()?;
+        let (orphans, unreadable, mut held_back) = self.find_orphans(&metadata, protected)?;
 
+        let mut skipped_disks: Vec<Uuid> = orphans
+            .iter()
+            .filter(|orphan| unreadable.contains(&orphan.extent_uuid))
+            .map(|orphan| orphan.disk_uuid)
+            .collect();
+        skipped_disks.sort();
+        skipped_disks.dedup();
+        for disk in &skipped_disks {
+            log::warn!("Orphan GC skipping disk {}: it holds fragments of extents with unreadable metadata", disk);
+        }
+
+        let mut removed = Vec::new();
         for orphan in orphans {
-            if orphan.age_seconds >= min_age_seconds {
-                if !dry_run {
-                    fs::remove_file(&orphan.fragment_path)
-                        .context(format!("Failed to remove orphan: {:?}", orphan.fragment_path))?;
-                }
-                cleaned.push(orphan);
+            if orphan.age_seconds < min_age_seconds || skipped_disks.contains(&orphan.disk_uuid) {
+                continue;
+            }
+            if dry_run {
+                removed.push(orphan);
+                continue;
             }
+
+            let Some(disk) = self.disks.iter().find(|d| d.lock().unwrap().uuid == orphan.disk_uuid) else {
+                continue;
+            };
+            // Metadata before disk, the engine's lock order
+            let metadata = metadata.read().unwrap();
+            let mut disk = disk.lock().unwrap();
+            if protected(&orphan.extent_uuid) || Self::referenced_now(&metadata, &orphan) {
+                held_back += 1;
+                continue;
+            }
+            disk.remove_fragment_file(&orphan.fragment_path)
+                .context(format!("Failed to remove orphan: {:?}", orphan.fragment_path))?;
+            removed.push(orphan);
         }
 
-        Ok(cleaned)
+        Ok(GcReport {
+            removed,
+            skipped_disks,
+            protected: held_back,
+        })
+    }
+
+    /// Whether the orphan's extent, as stored right now, lists it or cannot be read
+    fn referenced_now(metadata: &MetadataManager, orphan: &OrphanFragment) -> bool {
+        if !metadata.extent_exists(&orphan.extent_uuid) {
+            return false;
+        }
+        metadata.load_extent(&orphan.extent_uuid).map_or(true, |extent| {
+            extent
+                .fragment_locations
+                .iter()
+                .any(|l| l.disk_uuid == orphan.disk_uuid && l.fragment_index == orphan.fragment_index)
+        })
     }
 
     /// Get statistics about orphaned fragments
@@ -186,3 +320,117 @@ pub struct OrphanStats {
     pub old_count: usize,       // Older than 24 hours
     pub old_bytes: u64,
 }
+
+/// Settings for the background collector of a mounted pool
+#[derive(Debug, Clone, Copy)]
+pub struct OrphanGcConfig {
+    /// Time between passes
+    pub interval: std::time::Duration,
+    /// Orphans younger than this are left alone
+    pub min_age_seconds: u64,
+}
+
+impl Default for OrphanGcConfig {
+    fn default() -> Self {
+        OrphanGcConfig {
+            interval: std::time::Duration::from_secs(3600),
+            min_age_seconds: 86400,
+        }
+    }
+}
+
+/// What the background collector of a mounted pool has done, kept in the pool directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GcStatus {
+    /// Unix time of the last pass, successful or not
+    pub last_run: Option<i64>,
+    pub last_removed: usize,
+    pub last_bytes_reclaimed: u64,
+    pub last_skipped_disks: Vec<Uuid>,
+    pub last_error: Option<String>,
+    pub total_removed: u64,
+    pub total_bytes_reclaimed: u64,
+}
+
+impl GcStatus {
+    pub fn load(pool_dir: &Path) -> Result<Self> {
+        let path = pool_dir.join(GC_STATUS_FILE);
+        if !path.exists() {
+            return Ok(GcStatus::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        let path = pool_dir.join(GC_STATUS_FILE);
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(temp_path, path)?;
+        Ok(())
+    }
+
+    /// Fold the outcome of a pass into the status
+    pub fn record(&mut self, outcome: &StorageResult<GcReport>) {
+        self.last_run = Some(chrono::Utc::now().timestamp());
+        match outcome {
+            Ok(report) => {
+                self.last_removed = report.removed.len();
+                self.last_bytes_reclaimed = report.bytes_reclaimed();
+                self.last_skipped_disks = report.skipped_disks.clone();
+                self.last_error = None;
+                self.total_removed += report.removed.len() as u64;
+                self.total_bytes_reclaimed += report.bytes_reclaimed();
+            }
+            Err(e) => {
+                self.last_removed = 0;
+                self.last_bytes_reclaimed = 0;
+                self.last_skipped_disks.clear();
+                self.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Extents whose fragments are being written but not yet committed to metadata
+///
+/// The orphan collector leaves their fragments alone.
+#[derive(Debug, Default)]
+pub struct InFlightExtents {
+    extents: Mutex<HashSet<Uuid>>,
+}
+
+impl InFlightExtents {
+    /// Start tracking the extents of one write; they are released when it is dropped
+    pub fn begin(self: &Arc<Self>) -> InFlightWrite {
+        InFlightWrite {
+            owner: Arc::clone(self),
+            extents: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, extent_uuid: &Uuid) -> bool {
+        self.extents.lock().unwrap().contains(extent_uuid)
+    }
+}
+
+/// Extents registered by one write, held until the write has committed or rolled back
+pub struct InFlightWrite {
+    owner: Arc<InFlightExtents>,
+    extents: Vec<Uuid>,
+}
+
+impl InFlightWrite {
+    pub fn add(&mut self, extent_uuid: Uuid) {
+        self.owner.extents.lock().unwrap().insert(extent_uuid);
+        self.extents.push(extent_uuid);
+    }
+}
+
+impl Drop for InFlightWrite {
+    fn drop(&mut self) {
+        let mut extents = self.owner.extents.lock().unwrap();
+        for uuid in &self.extents {
+            extents.remove(uuid);
+        }
+    }
+}
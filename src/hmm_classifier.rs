@@ -1,14 +1,37 @@
-/// Hidden Markov Model for hot/cold classification
-/// 
-/// Uses a 3-state HMM (Hot, Warm, Cold) with:
-/// - Emission probabilities based on observed access frequency
-/// - Transition probabilities that smooth state changes
-/// - Viterbi decoding for most likely state sequence
+//! Hidden Markov Model for hot/cold classification
+//! 
+//! Uses a 3-state HMM (Hot, Warm, Cold) with:
+//! - Emission probabilities based on observed access frequency
+//! - Transition probabilities that smooth state changes
+//! - Viterbi decoding for most likely state sequence
+//!
+//! The pool-wide model used by the engine decodes hourly access counts of each
+//! extent; see `HmmClassifier::hourly` and `Extent::classify_with_model`.
 
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 use crate::extent::AccessClassification;
 
+/// File in the pool directory holding the trained access model
+pub const ACCESS_MODEL_FILE: &str = "access_model.json";
+
+/// Hours of access counts an extent keeps, and the longest sequence the model decodes
+pub const ACCESS_HISTORY_HOURS: i64 = 7 * 24;
+
+/// Hours an extent must have been observed before the model's estimate is used
+pub const MODEL_MIN_HOURS: usize = 24;
+
+/// Trailing hours of the decoded state sequence the estimate is taken over
+pub const MODEL_WINDOW_HOURS: usize = 24;
+
+/// Share of trained parameters replaced by each training pass
+const TRAINING_RATE: f64 = 0.2;
+
+/// Decoded hours a state needs in a pass before its parameters are retrained
+const MIN_STATE_HOURS: f64 = 24.0;
+
 /// HMM emission states based on access frequency observations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrequencyObservation {
@@ -18,6 +41,35 @@ pub enum FrequencyObservation {
     Low,       // <1 op/day
 }
 
+impl FrequencyObservation {
+    /// Observation for one hour of an extent's access history
+    pub fn from_hourly_accesses(count: u32) -> Self {
+        match count {
+            0 => FrequencyObservation::Low,
+            1..=2 => FrequencyObservation::Medium,
+            3..=20 => FrequencyObservation::High,
+            _ => FrequencyObservation::VeryHigh,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            FrequencyObservation::VeryHigh => 0,
+            FrequencyObservation::High => 1,
+            FrequencyObservation::Medium => 2,
+            FrequencyObservation::Low => 3,
+        }
+    }
+}
+
+/// What the model makes of one extent's recent history
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ModelEstimate {
+    pub state: AccessClassification,
+    /// Share of the last `MODEL_WINDOW_HOURS` decoded to `state`
+    pub confidence: f64,
+}
+
 /// HMM parameters and state tracking
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HmmClassifier {
@@ -36,6 +88,10 @@ pub struct HmmClassifier {
     
     /// State history for smoothing transitions
     pub state_history: Vec<(i64, AccessClassification)>,
+
+    /// Hours of access history the model has been trained on
+    #[serde(default)]
+    pub trained_hours: u64,
 }
 
 impl Default for HmmClassifier {
@@ -78,8 +134,99 @@ impl HmmClassifier {
             emission_log_probs,
             recent_access_boost: 2.0, // 2x boost for recent accesses
             state_history: Vec::new(),
+            trained_hours: 0,
+        }
+    }
+
+    /// Untrained model for sequences of hourly observations
+    ///
+    /// States persist for hours at a time, so a short burst in an otherwise
+    /// idle history decodes as cold rather than as a brief hot spell.
+    pub fn hourly() -> Self {
+        let ln = |row: [f64; 3]| row.map(f64::ln);
+        let ln4 = |row: [f64; 4]| row.map(f64::ln);
+        Self {
+            transition_log_probs: [ln([0.95, 0.04, 0.01]), ln([0.03, 0.94, 0.03]), ln([0.01, 0.04, 0.95])],
+            emission_log_probs: [
+                ln4([0.6, 0.3, 0.08, 0.02]),
+                ln4([0.1, 0.4, 0.4, 0.1]),
+                ln4([0.01, 0.03, 0.16, 0.8]),
+            ],
+            ..Self::new()
+        }
+    }
+
+    /// Load the model saved in `pool_dir`, or an untrained `hourly` one
+    pub fn load(pool_dir: &Path) -> Result<Self> {
+        let path = pool_dir.join(ACCESS_MODEL_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).with_context(|| format!("Invalid access model {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::hourly()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
         }
     }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        let path = pool_dir.join(ACCESS_MODEL_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Most likely state over the last hours of `observations`, one per hour
+    ///
+    /// `None` until there are `MODEL_MIN_HOURS` observations.
+    pub fn estimate(&self, observations: &[FrequencyObservation]) -> Option<ModelEstimate> {
+        if observations.len() < MODEL_MIN_HOURS {
+            return None;
+        }
+        let path = self.viterbi_sequence(observations);
+        let window = &path[path.len().saturating_sub(MODEL_WINDOW_HOURS)..];
+        let count = |state| window.iter().filter(|s| **s == state).count();
+        let (hot, warm, cold) = (count(AccessClassification::Hot), count(AccessClassification::Warm), count(AccessClassification::Cold));
+        let (state, hours) = if hot >= warm && hot >= cold {
+            (AccessClassification::Hot, hot)
+        } else if warm >= cold {
+            (AccessClassification::Warm, warm)
+        } else {
+            (AccessClassification::Cold, cold)
+        };
+        Some(ModelEstimate { state, confidence: hours as f64 / window.len() as f64 })
+    }
+
+    /// Update the model from the hourly histories of many extents
+    ///
+    /// Each history is decoded with the current model and the transitions and
+    /// emissions along the decoded paths are counted (Viterbi training). Every
+    /// state seen for long enough moves `TRAINING_RATE` of the way towards
+    /// those counts, so one pass never overturns what earlier passes learned.
+    /// Returns the hours of history used.
+    pub fn train(&mut self, histories: &[Vec<FrequencyObservation>]) -> usize {
+        let mut transitions = [[1.0_f64; 3]; 3];
+        let mut emissions = [[1.0_f64; 4]; 3];
+        let mut hours = 0;
+        for history in histories.iter().filter(|h| h.len() >= 2) {
+            let path: Vec<usize> = self.viterbi_sequence(history).into_iter().map(state_index).collect();
+            for (t, observation) in history.iter().enumerate() {
+                emissions[path[t]][observation.index()] += 1.0;
+                if t > 0 {
+                    transitions[path[t - 1]][path[t]] += 1.0;
+                }
+            }
+            hours += history.len();
+        }
+        for state in 0..3 {
+            let seen: f64 = emissions[state].iter().sum::<f64>() - 4.0;
+            if seen < MIN_STATE_HOURS {
+                continue;
+            }
+            blend(&mut self.transition_log_probs[state], &transitions[state]);
+            blend(&mut self.emission_log_probs[state], &emissions[state]);
+        }
+        self.trained_hours += hours as u64;
+        hours
+    }
     
     /// Observe frequency and update classification using HMM
     pub fn classify(
@@ -310,3 +457,99 @@ impl HmmClassifier {
     }
 }
 
+fn state_index(state: AccessClassification) -> usize {
+    match state {
+        AccessClassification::Hot => 0,
+        AccessClassification::Warm => 1,
+        AccessClassification::Cold => 2,
+    }
+}
+
+/// Move a row of log probabilities `TRAINING_RATE` of the way towards `counts`
+fn blend<const N: usize>(row: &mut [f64; N], counts: &[f64; N]) {
+    let total: f64 = counts.iter().sum();
+    for (log_prob, count) in row.iter_mut().zip(counts) {
+        *log_prob = ((1.0 - TRAINING_RATE) * log_prob.exp() + TRAINING_RATE * count / total).ln();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crash_sim::SeededRng;
+    use crate::extent::{Extent, RedundancyPolicy};
+
+    const START: i64 = 1_700_000_000 / 3600 * 3600;
+
+    /// Replay `reads_in_hour` over `days`, counting how often the
+    /// classification changes after the first day under thresholds and model
+    fn replay(days: i64, model: &HmmClassifier, reads_in_hour: impl Fn(i64) -> u64) -> (usize, usize, AccessClassification) {
+        let mut extent = Extent::new(b"trace", RedundancyPolicy::Replication { copies: 3 });
+        extent.access_stats.created_at = START;
+        extent.access_stats.last_write = START;
+        extent.access_stats.write_count = 0;
+        extent.access_stats.hourly_accesses.clear();
+        let (mut threshold_changes, mut model_changes) = (0, 0);
+        let mut previous = None;
+        for hour in 0..days * 24 {
+            let now = START + hour * 3600 + 1800;
+            let reads = reads_in_hour(hour);
+            if reads > 0 {
+                extent.apply_reads(reads, now);
+            }
+            let current = (extent.classify_at(None, now), extent.classify_at(Some(model), now));
+            if let (Some((threshold, modelled)), true) = (previous, hour >= 24) {
+                threshold_changes += usize::from(threshold != current.0);
+                model_changes += usize::from(modelled != current.1);
+            }
+            previous = Some(current);
+        }
+        (threshold_changes, model_changes, previous.unwrap().1)
+    }
+
+    #[test]
+    fn test_model_keeps_periodic_and_bursty_extents_from_thrashing() {
+        let model = HmmClassifier::hourly();
+
+        // A batch job reading the extent for one hour every other night
+        let (thresholds, modelled, state) = replay(14, &model, |hour| if hour % 48 == 2 { 15 } else { 0 });
+        assert!(thresholds >= 12, "thresholds changed {} times", thresholds);
+        assert!(modelled <= 1, "model changed {} times", modelled);
+        assert_eq!(state, AccessClassification::Cold);
+
+        // Short bursts at random hours, a day or more apart
+        let mut rng = SeededRng::new(7);
+        let bursts: Vec<i64> = (0..10).map(|day| day * 24 + 24 + rng.below(20) as i64).collect();
+        let (thresholds, modelled, state) =
+            replay(12, &model, |hour| if bursts.iter().any(|b| (*b..b + 2).contains(&hour)) { 40 } else { 0 });
+        assert!(thresholds >= 10, "thresholds changed {} times", thresholds);
+        assert!(modelled <= 1, "model changed {} times", modelled);
+        assert_eq!(state, AccessClassification::Cold);
+
+        // Sustained reads still make the extent hot
+        let (_, _, state) = replay(3, &model, |_| 30);
+        assert_eq!(state, AccessClassification::Hot);
+    }
+
+    #[test]
+    fn test_training_keeps_rows_normalised_and_persists() {
+        let mut model = HmmClassifier::hourly();
+        let periodic: Vec<_> = (0..168).map(|h| FrequencyObservation::from_hourly_accesses(if h % 48 == 2 { 15 } else { 0 })).collect();
+        let busy: Vec<_> = (0..168).map(|h| FrequencyObservation::from_hourly_accesses(25 + h % 5)).collect();
+        assert_eq!(model.train(&[periodic.clone(), busy.clone(), vec![]]), 336);
+        for state in 0..3 {
+            let transitions: f64 = model.transition_log_probs[state].iter().map(|p| p.exp()).sum();
+            let emissions: f64 = model.emission_log_probs[state].iter().map(|p| p.exp()).sum();
+            assert!((transitions - 1.0).abs() < 1e-9 && (emissions - 1.0).abs() < 1e-9);
+        }
+        assert_eq!(model.estimate(&periodic).unwrap().state, AccessClassification::Cold);
+        assert_eq!(model.estimate(&busy).unwrap().state, AccessClassification::Hot);
+        assert!(model.estimate(&periodic[..MODEL_MIN_HOURS - 1]).is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(HmmClassifier::load(dir.path()).unwrap().trained_hours, 0);
+        model.save(dir.path()).unwrap();
+        assert_eq!(HmmClassifier::load(dir.path()).unwrap().trained_hours, 336);
+    }
+}
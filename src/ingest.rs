@@ -0,0 +1,604 @@
+//! Bulk import of a host directory tree for `dynamicfs ingest`
+//!
+//! The source tree is walked directly rather than through a mount: directories
+//! and inodes are created through the storage API, and file contents are
+//! written by several worker threads at once through `write_file`, so their
+//! metadata transactions share the syncs of each commit window.
+//!
+//! Every finished file is appended to a manifest in the pool once its metadata
+//! is durable. Running the same ingest again skips files the manifest lists
+//! whose size and mtime are unchanged, or whose source and pool contents both
+//! still hash to the recorded checksum with `verify`.
+//!
+//! The metadata layer has no symlinks, hard links or device nodes: symlinks
+//! and special files are skipped and reported, and each name of a hard-linked
+//! file is ingested as a separate copy.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::export::{ensure_dir, join, split_path, ArchiveEntry};
+use crate::metadata::{FileName, FileType};
+use crate::storage::StorageEngine;
+
+/// Directory in the pool holding the manifests of `ingest` runs
+pub const INGEST_MANIFESTS_DIR: &str = "ingest";
+/// Shortest commit window an ingest runs with, so its workers share syncs
+pub const INGEST_COMMIT_WINDOW_MS: u64 = 20;
+/// Suffix of the hidden name a file is written under before it replaces the target
+const PARTIAL_INGEST_SUFFIX: &str = ".scfs-ingest";
+/// Finished files recorded in the manifest per sync of the metadata
+const MANIFEST_BATCH: usize = 256;
+/// Extents read from the source and written to the pool per `write_file` call
+const CHUNK_EXTENTS: usize = 16;
+
+/// A directory or regular file of the source tree
+#[derive(Debug, Clone)]
+struct SourceEntry {
+    source: PathBuf,
+    entry: ArchiveEntry,
+    size: u64,
+    atime_nsec: u32,
+    mtime_nsec: u32,
+}
+
+impl SourceEntry {
+    fn read(source: PathBuf, path: FileName, meta: &fs::Metadata) -> Result<Self> {
+        let xattrs = read_xattrs(&source).with_context(|| format!("Failed to read xattrs of {}", source.display()))?;
+        Ok(SourceEntry {
+            entry: ArchiveEntry {
+                path,
+                mode: meta.mode() & 0o7777,
+                uid: meta.uid(),
+                gid: meta.gid(),
+                atime: meta.atime(),
+                mtime: meta.mtime(),
+                xattrs,
+            },
+            source,
+            size: meta.len(),
+            atime_nsec: meta.atime_nsec() as u32,
+            mtime_nsec: meta.mtime_nsec() as u32,
+        })
+    }
+}
+
+/// Source entry left out of the ingest, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedEntry {
+    pub path: FileName,
+    pub reason: &'static str,
+}
+
+/// What a source tree holds and the pool space it would take
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IngestPlan {
+    #[serde(skip)]
+    directories: Vec<SourceEntry>,
+    #[serde(skip)]
+    files: Vec<SourceEntry>,
+    pub directory_count: u64,
+    pub file_count: u64,
+    pub bytes: u64,
+    /// Fragment bytes the files take under the pool's default redundancy, before compression
+    pub raw_bytes: u64,
+    /// Files with more than one name in the source, each ingested as its own copy
+    pub hardlinked_files: u64,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+impl IngestPlan {
+    /// Walk `source` and list what an ingest of it would create
+    pub fn scan(source: &Path, extent_size: usize) -> Result<Self> {
+        let mut plan = IngestPlan::default();
+        let mut links = HashSet::new();
+        for item in walkdir::WalkDir::new(source).follow_links(false).sort_by_file_name() {
+            let item = item?;
+            let path = relative_path(source, item.path())?;
+            let meta = item.path().symlink_metadata()?;
+            let file_type = meta.file_type();
+            if file_type.is_dir() {
+                plan.directories.push(SourceEntry::read(item.path().to_path_buf(), path, &meta)?);
+            } else if file_type.is_file() {
+                if meta.nlink() > 1 && !links.insert((meta.dev(), meta.ino())) {
+                    plan.hardlinked_files += 1;
+                }
+                plan.bytes += meta.len();
+                plan.raw_bytes += raw_bytes(meta.len(), extent_size);
+                plan.files.push(SourceEntry::read(item.path().to_path_buf(), path, &meta)?);
+            } else if file_type.is_symlink() {
+                plan.skipped.push(SkippedEntry { path, reason: "symlinks are not supported" });
+            } else {
+                plan.skipped.push(SkippedEntry { path, reason: "not a regular file or directory" });
+            }
+        }
+        plan.directory_count = plan.directories.len() as u64;
+        plan.file_count = plan.files.len() as u64;
+        Ok(plan)
+    }
+}
+
+/// Fragment bytes a file of `size` bytes takes in extents of `extent_size`
+fn raw_bytes(size: u64, extent_size: usize) -> u64 {
+    let policy = StorageEngine::default_policy_for_size(size);
+    let full = size / extent_size as u64;
+    let tail = (size % extent_size as u64) as usize;
+    let per_extent = |len: usize| (policy.fragment_size(len) * policy.fragment_count()) as u64;
+    full * per_extent(extent_size) + if tail > 0 || size == 0 { per_extent(tail) } else { 0 }
+}
+
+/// Pool path of `path` under `source`, starting with `/`
+///
+/// Names keep their bytes, whether or not they are UTF-8.
+fn relative_path(source: &Path, path: &Path) -> Result<FileName> {
+    let relative = path.strip_prefix(source)?;
+    Ok(relative
+        .components()
+        .fold(FileName::from("/"), |out, part| out.join(&FileName::from(part.as_os_str()))))
+}
+
+/// Extended attributes of `path` itself, not of a symlink target
+#[cfg(target_os = "linux")]
+fn read_xattrs(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut names = vec![0u8; 4096];
+    let len = loop {
+        let len = unsafe { libc::llistxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+        if len >= 0 {
+            break len as usize;
+        }
+        match std::io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::ERANGE) => names.resize(names.len() * 2, 0),
+            e if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(BTreeMap::new()),
+            e => return Err(e.into()),
+        }
+    };
+
+    let mut xattrs = BTreeMap::new();
+    for name in names[..len].split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let c_name = CString::new(name)?;
+        let size = unsafe { libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            // Removed since it was listed
+            continue;
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe { libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if size < 0 {
+            continue;
+        }
+        value.truncate(size as usize);
+        xattrs.insert(String::from_utf8_lossy(name).into_owned(), value);
+    }
+    Ok(xattrs)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_xattrs(_path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    Ok(BTreeMap::new())
+}
+
+/// A file the manifest records as ingested
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub path: FileName,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    /// BLAKE3 of the contents, hex
+    pub hash: String,
+}
+
+/// Files finished by earlier runs of one ingest, one JSON line each
+///
+/// Lines are only appended once the metadata of their files has been synced,
+/// so a crash loses at most the record of files the next run copies again.
+pub struct IngestManifest {
+    path: PathBuf,
+    done: HashMap<FileName, ManifestEntry>,
+    pending: Mutex<Vec<ManifestEntry>>,
+    file: Mutex<fs::File>,
+}
+
+impl IngestManifest {
+    /// Open the manifest of ingesting `source` under `dest` in the pool at `pool_dir`
+    pub fn open(pool_dir: &Path, source: &Path, dest: &str) -> Result<Self> {
+        let dir = pool_dir.join(INGEST_MANIFESTS_DIR);
+        fs::create_dir_all(&dir)?;
+        let key = blake3::hash(format!("{}\0{}", source.display(), dest).as_bytes()).to_hex();
+        let path = dir.join(format!("{}.jsonl", &key[..16]));
+
+        let mut done = HashMap::new();
+        if let Ok(existing) = fs::File::open(&path) {
+            for line in BufReader::new(existing).lines() {
+                // A torn last line is the record of a file the next run copies again
+                if let Ok(entry) = serde_json::from_str::<ManifestEntry>(&line?) {
+                    done.insert(entry.path.clone(), entry);
+                }
+            }
+        }
+        let file = fs::OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(IngestManifest { path, done, pending: Mutex::new(Vec::new()), file: Mutex::new(file) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Files recorded by earlier runs
+    pub fn completed(&self) -> usize {
+        self.done.len()
+    }
+
+    /// Record of an earlier run that ingested `file` as it is now
+    fn finished(&self, file: &SourceEntry) -> Option<&ManifestEntry> {
+        self.done
+            .get(&file.entry.path)
+            .filter(|done| (done.size, done.mtime, done.mtime_nsec) == (file.size, file.entry.mtime, file.mtime_nsec))
+    }
+
+    fn record(&self, entry: ManifestEntry) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        pending.push(entry);
+        pending.len()
+    }
+
+    /// Sync the pool's metadata and append every file recorded since the last flush
+    fn flush(&self, storage: &StorageEngine) -> Result<()> {
+        let entries = std::mem::take(&mut *self.pending.lock().unwrap());
+        if entries.is_empty() {
+            return Ok(());
+        }
+        storage.metadata().read().unwrap().sync_commits()?;
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        let mut file = self.file.lock().unwrap();
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IngestOptions {
+    /// Directory of the pool the tree is ingested under
+    pub dest: String,
+    pub threads: usize,
+    /// Hash the source and the pool copy of files the manifest lists before skipping them
+    pub verify: bool,
+}
+
+/// Reported after every file ingested or skipped
+#[derive(Debug, Clone)]
+pub struct IngestProgress {
+    pub files: u64,
+    pub total_files: u64,
+    pub bytes: u64,
+    pub total_bytes: u64,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IngestSummary {
+    pub directories: u64,
+    pub files: u64,
+    pub bytes: u64,
+    /// Files an earlier run already ingested
+    pub skipped_files: u64,
+    pub skipped_bytes: u64,
+    pub elapsed_secs: f64,
+}
+
+/// Create the directories and files of `plan` under `options.dest`
+///
+/// Directories are created first and given their source attributes last, so
+/// writing their children does not change them. Files are written under a
+/// hidden name and renamed over their target once complete, so an
+/// interrupted ingest never leaves a partly written file under its real name.
+pub fn ingest(
+    storage: &StorageEngine,
+    plan: &IngestPlan,
+    manifest: &IngestManifest,
+    options: &IngestOptions,
+    progress: impl Fn(&IngestProgress) + Sync,
+) -> Result<IngestSummary> {
+    let started = Instant::now();
+    let mut directories = Vec::with_capacity(plan.directories.len());
+    for dir in &plan.directories {
+        directories.push((ensure_dir(storage, join(&options.dest, &dir.entry.path))?, dir));
+    }
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let error = Mutex::new(None);
+    let (files, bytes) = (AtomicU64::new(0), AtomicU64::new(0));
+    let (skipped_files, skipped_bytes) = (AtomicU64::new(0), AtomicU64::new(0));
+    let chunk = storage.extent_size() * CHUNK_EXTENTS;
+
+    std::thread::scope(|scope| {
+        for _ in 0..options.threads.max(1) {
+            scope.spawn(|| {
+                while !failed.load(Ordering::SeqCst) {
+                    let Some(file) = plan.files.get(next.fetch_add(1, Ordering::SeqCst)) else { break };
+                    let result = ingest_file(storage, manifest, options, file, chunk).and_then(|copied| {
+                        if copied {
+                            files.fetch_add(1, Ordering::SeqCst);
+                        } else {
+                            skipped_files.fetch_add(1, Ordering::SeqCst);
+                            skipped_bytes.fetch_add(file.size, Ordering::SeqCst);
+                        }
+                        bytes.fetch_add(file.size, Ordering::SeqCst);
+                        if manifest.pending.lock().unwrap().len() >= MANIFEST_BATCH {
+                            manifest.flush(storage)?;
+                        }
+                        Ok(())
+                    });
+                    if let Err(e) = result {
+                        failed.store(true, Ordering::SeqCst);
+                        error.lock().unwrap().get_or_insert(e.context(format!("Failed to ingest {}", file.source.display())));
+                        break;
+                    }
+                    progress(&IngestProgress {
+                        files: files.load(Ordering::SeqCst) + skipped_files.load(Ordering::SeqCst),
+                        total_files: plan.file_count,
+                        bytes: bytes.load(Ordering::SeqCst),
+                        total_bytes: plan.bytes,
+                        elapsed: started.elapsed(),
+                    });
+                }
+            });
+        }
+    });
+    // Whatever finished is kept for the next run, even when this one failed
+    manifest.flush(storage)?;
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    // Deepest first, and the pool root keeps its own attributes
+    for (ino, dir) in directories.iter().rev() {
+        if *ino == 1 {
+            continue;
+        }
+        let mut inode = storage.get_inode(*ino)?;
+        dir.entry.apply(&mut inode);
+        inode.atime_nsec = dir.atime_nsec;
+        inode.mtime_nsec = dir.mtime_nsec;
+        storage.update_inode(&inode)?;
+    }
+
+    let skipped_bytes = skipped_bytes.into_inner();
+    Ok(IngestSummary {
+        directories: plan.directory_count,
+        files: files.into_inner(),
+        bytes: bytes.into_inner() - skipped_bytes,
+        skipped_files: skipped_files.into_inner(),
+        skipped_bytes,
+        elapsed_secs: started.elapsed().as_secs_f64(),
+    })
+}
+
+/// Copy one file into the pool unless an earlier run did; true if it was copied
+fn ingest_file(
+    storage: &StorageEngine,
+    manifest: &IngestManifest,
+    options: &IngestOptions,
+    file: &SourceEntry,
+    chunk: usize,
+) -> Result<bool> {
+    let path = join(&options.dest, &file.entry.path);
+    let (parent_path, name) = split_path(&path)?;
+    let parent = ensure_dir(storage, parent_path)?;
+
+    if let Some(done) = manifest.finished(file) {
+        let existing = storage.find_child(parent, &name)?;
+        if let Some(existing) = existing.filter(|e| e.file_type == FileType::RegularFile && e.size == file.size) {
+            if !options.verify
+                || (hash_source(&file.source, chunk)? == done.hash && hash_pool(storage, existing.ino, chunk)? == done.hash)
+            {
+                return Ok(false);
+            }
+        }
+    }
+
+    let partial_name = FileName::from([b".", name.as_bytes(), PARTIAL_INGEST_SUFFIX.as_bytes()].concat());
+    if let Some(stale) = storage.find_child(parent, &partial_name)? {
+        storage.delete_file(stale.ino)?;
+    }
+    let partial = storage.create_file(parent, partial_name)?;
+    let hash = match write_contents(storage, partial.ino, file, chunk) {
+        Ok(hash) => hash,
+        Err(e) => {
+            storage.delete_file(partial.ino).ok();
+            return Err(e);
+        }
+    };
+
+    if let Some(existing) = storage.find_child(parent, &name)? {
+        if existing.file_type == FileType::Directory {
+            storage.delete_file(partial.ino)?;
+            return Err(anyhow!("{} exists and is a directory", path));
+        }
+        storage.delete_file(existing.ino)?;
+    }
+    let mut inode = storage.get_inode(partial.ino)?;
+    inode.name = name;
+    file.entry.apply(&mut inode);
+    inode.atime_nsec = file.atime_nsec;
+    inode.mtime_nsec = file.mtime_nsec;
+    storage.update_inode(&inode)?;
+
+    manifest.record(ManifestEntry {
+        path: file.entry.path.clone(),
+        size: file.size,
+        mtime: file.entry.mtime,
+        mtime_nsec: file.mtime_nsec,
+        hash,
+    });
+    Ok(true)
+}
+
+/// Write the source file to `ino` in chunks, returning the hash of what was written
+fn write_contents(storage: &StorageEngine, ino: u64, file: &SourceEntry, chunk: usize) -> Result<String> {
+    let mut source = fs::File::open(&file.source)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; chunk.min(file.size as usize).max(1)];
+    let mut offset = 0u64;
+    loop {
+        let len = read_full(&mut source, &mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        storage.write_file(ino, &buf[..len], offset)?;
+        offset += len as u64;
+    }
+    if offset != file.size {
+        return Err(anyhow!("{} changed size while it was ingested", file.source.display()));
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Fill `buf` unless the reader ends first; the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn hash_source(path: &Path, chunk: usize) -> Result<String> {
+    let mut source = fs::File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; chunk];
+    loop {
+        match read_full(&mut source, &mut buf)? {
+            0 => return Ok(hasher.finalize().to_hex().to_string()),
+            len => hasher.update(&buf[..len]),
+        };
+    }
+}
+
+fn hash_pool(storage: &StorageEngine, ino: u64, chunk: usize) -> Result<String> {
+    let size = storage.get_inode(ino)?.size;
+    let mut hasher = blake3::Hasher::new();
+    let mut offset = 0;
+    while offset < size {
+        let data = storage.read_range(ino, offset, (chunk as u64).min(size - offset))?;
+        if data.is_empty() {
+            break;
+        }
+        hasher.update(&data);
+        offset += data.len() as u64;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::Disk;
+    use crate::metadata::MetadataManager;
+    use tempfile::TempDir;
+
+    fn setup_storage() -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = TempDir::new().unwrap();
+        let disk_dirs: Vec<TempDir> = (0..6).map(|_| TempDir::new().unwrap()).collect();
+        let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        (pool_dir, disk_dirs, StorageEngine::new(metadata, disks))
+    }
+
+    fn source_tree() -> TempDir {
+        let source = TempDir::new().unwrap();
+        fs::create_dir_all(source.path().join("docs/deep")).unwrap();
+        fs::write(source.path().join("top.txt"), b"top level").unwrap();
+        fs::write(source.path().join("docs/deep/big.bin"), vec![7u8; 3 * 1024 * 1024 + 5]).unwrap();
+        fs::write(source.path().join("docs/empty"), b"").unwrap();
+        fs::hard_link(source.path().join("top.txt"), source.path().join("docs/top-link.txt")).unwrap();
+        std::os::unix::fs::symlink("top.txt", source.path().join("link")).unwrap();
+        source
+    }
+
+    fn options(dest: &str, verify: bool) -> IngestOptions {
+        IngestOptions { dest: dest.to_string(), threads: 3, verify }
+    }
+
+    fn pool_file(storage: &StorageEngine, path: &str) -> Vec<u8> {
+        let path = FileName::from(path);
+        let (parent, name) = split_path(&path).unwrap();
+        let parent = ensure_dir(storage, parent).unwrap();
+        let ino = storage.find_child(parent, &name).unwrap().unwrap().ino;
+        storage.read_file(ino).unwrap()
+    }
+
+    #[test]
+    fn test_ingest_copies_tree_with_attributes() {
+        let (pool_dir, _disks, storage) = setup_storage();
+        let source = source_tree();
+        let top = source.path().join("top.txt");
+        fs::set_permissions(&top, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+
+        let plan = IngestPlan::scan(source.path(), storage.extent_size()).unwrap();
+        assert_eq!(plan.file_count, 4);
+        assert_eq!(plan.hardlinked_files, 1);
+        assert_eq!(plan.skipped.len(), 1);
+        assert_eq!(plan.skipped[0].path, "/link");
+        assert!(plan.raw_bytes > plan.bytes);
+
+        let manifest = IngestManifest::open(pool_dir.path(), source.path(), "/imported").unwrap();
+        let summary = ingest(&storage, &plan, &manifest, &options("/imported", false), |_| {}).unwrap();
+        assert_eq!((summary.files, summary.skipped_files), (4, 0));
+
+        assert_eq!(pool_file(&storage, "/imported/top.txt"), b"top level");
+        assert_eq!(pool_file(&storage, "/imported/docs/top-link.txt"), b"top level");
+        assert_eq!(pool_file(&storage, "/imported/docs/deep/big.bin"), vec![7u8; 3 * 1024 * 1024 + 5]);
+        assert!(pool_file(&storage, "/imported/docs/empty").is_empty());
+
+        let imported = ensure_dir(&storage, "/imported").unwrap();
+        let inode = storage.find_child(imported, "top.txt").unwrap().unwrap();
+        let meta = fs::metadata(&top).unwrap();
+        assert_eq!(inode.mode, 0o600);
+        assert_eq!((inode.mtime, inode.mtime_nsec), (meta.mtime(), meta.mtime_nsec() as u32));
+        assert!(storage.find_child(imported, "link").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_ingest_resumes_from_manifest() {
+        let (pool_dir, _disks, storage) = setup_storage();
+        let source = source_tree();
+        let plan = IngestPlan::scan(source.path(), storage.extent_size()).unwrap();
+        let manifest = IngestManifest::open(pool_dir.path(), source.path(), "/").unwrap();
+        ingest(&storage, &plan, &manifest, &options("/", false), |_| {}).unwrap();
+
+        // A changed file is copied again, unchanged ones are skipped
+        fs::remove_file(source.path().join("top.txt")).unwrap();
+        fs::write(source.path().join("top.txt"), b"rewritten, longer").unwrap();
+        let plan = IngestPlan::scan(source.path(), storage.extent_size()).unwrap();
+        let manifest = IngestManifest::open(pool_dir.path(), source.path(), "/").unwrap();
+        assert_eq!(manifest.completed(), 4);
+        let summary = ingest(&storage, &plan, &manifest, &options("/", true), |_| {}).unwrap();
+        assert_eq!((summary.files, summary.skipped_files), (1, 3));
+        assert_eq!(pool_file(&storage, "/top.txt"), b"rewritten, longer");
+        // The hard link is a separate copy and keeps what it was ingested with
+        assert_eq!(pool_file(&storage, "/docs/top-link.txt"), b"top level");
+    }
+}
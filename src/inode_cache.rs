@@ -0,0 +1,322 @@
+//! Inode records kept in memory between metadata operations
+//!
+//! Without it every getattr, lookup and readdir entry reads and parses an
+//! inode's JSON record. `MetadataManager` asks the cache first and drops an
+//! inode's entries whenever it saves or deletes the inode, so a cached inode
+//! is never older than its record. Names looked up under a directory are
+//! cached too, as the inode number they resolved to.
+//!
+//! Entries expire after the TTL, which the kernel is also given for the
+//! attributes and entries it caches, and the least recently used go once
+//! either map holds `capacity` entries. A capacity or TTL of 0 disables the
+//! cache.
+
+use crate::metadata::{FileName, Inode};
+use crate::metrics::Metrics;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_INODE_CACHE_CAPACITY: usize = 65536;
+pub const DEFAULT_INODE_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// Size and lifetime of cached entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeCacheLimits {
+    /// Entries of each of the inode and name maps
+    pub capacity: usize,
+    pub ttl: Duration,
+}
+
+impl Default for InodeCacheLimits {
+    fn default() -> Self {
+        InodeCacheLimits { capacity: DEFAULT_INODE_CACHE_CAPACITY, ttl: DEFAULT_INODE_CACHE_TTL }
+    }
+}
+
+impl InodeCacheLimits {
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0 && !self.ttl.is_zero()
+    }
+}
+
+struct Entry<T> {
+    value: T,
+    cached_at: Instant,
+    /// Tick of the last use; the entry's key in `Lru::order`
+    used: u64,
+}
+
+/// Map evicting its least recently used entries
+struct Lru<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, T: Clone> Lru<K, T> {
+    fn new() -> Self {
+        Lru { entries: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    /// The entry under `key`, marked used at `tick`; call `expire` first
+    fn get(&mut self, key: &K, tick: u64) -> Option<T> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.used);
+        self.order.insert(tick, key.clone());
+        entry.used = tick;
+        Some(entry.value.clone())
+    }
+
+    /// Drop the entry under `key` if it is older than `ttl`, returning it
+    fn expire(&mut self, key: &K, ttl: Duration) -> Option<T> {
+        if self.entries.get(key)?.cached_at.elapsed() < ttl {
+            return None;
+        }
+        self.remove(key)
+    }
+
+    /// Insert, returning the entries evicted to make room
+    fn insert(&mut self, key: K, value: T, tick: u64, capacity: usize) -> Vec<(K, T)> {
+        self.remove(&key);
+        let mut evicted = Vec::new();
+        while self.entries.len() >= capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                evicted.push((oldest, entry.value));
+            }
+        }
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, Entry { value, cached_at: Instant::now(), used: tick });
+        evicted
+    }
+
+    fn remove(&mut self, key: &K) -> Option<T> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.used);
+        Some(entry.value)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+struct CacheState {
+    limits: InodeCacheLimits,
+    inodes: Lru<u64, Inode>,
+    /// Directory index keys, folded on case-insensitive pools, to inode numbers
+    names: Lru<(u64, FileName), u64>,
+    /// The name entry resolving to each inode, so dropping the inode drops it too
+    name_of: HashMap<u64, (u64, FileName)>,
+    tick: u64,
+    /// Bumped by every invalidation; see `InodeCache::stamp`
+    epoch: u64,
+}
+
+impl CacheState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Drop the back link of a name entry that was removed
+    fn unlink_name(&mut self, key: &(u64, FileName), ino: u64) {
+        if self.name_of.get(&ino) == Some(key) {
+            self.name_of.remove(&ino);
+        }
+    }
+
+    fn forget_name(&mut self, key: &(u64, FileName)) {
+        if let Some(ino) = self.names.remove(key) {
+            self.unlink_name(key, ino);
+        }
+    }
+}
+
+/// Bounded, expiring cache of inodes and name lookups
+pub struct InodeCache {
+    state: Mutex<CacheState>,
+    metrics: Arc<Metrics>,
+}
+
+impl InodeCache {
+    pub fn new(limits: InodeCacheLimits, metrics: Arc<Metrics>) -> Self {
+        InodeCache {
+            state: Mutex::new(CacheState {
+                limits,
+                inodes: Lru::new(),
+                names: Lru::new(),
+                name_of: HashMap::new(),
+                tick: 0,
+                epoch: 0,
+            }),
+            metrics,
+        }
+    }
+
+    /// Replace the limits, dropping everything cached under the old ones
+    pub fn set_limits(&self, limits: InodeCacheLimits) {
+        let mut state = self.state.lock().unwrap();
+        state.limits = limits;
+        state.inodes.clear();
+        state.names.clear();
+        state.name_of.clear();
+        state.epoch += 1;
+    }
+
+    /// Taken before reading a record that is then passed to `insert`
+    ///
+    /// A save can land between reading a record and caching it; the stamp
+    /// has moved on by then, and the record read before it is not cached.
+    pub fn stamp(&self) -> u64 {
+        self.state.lock().unwrap().epoch
+    }
+
+    pub fn get(&self, ino: u64) -> Option<Inode> {
+        let mut state = self.state.lock().unwrap();
+        let tick = state.next_tick();
+        let ttl = state.limits.ttl;
+        state.inodes.expire(&ino, ttl);
+        let inode = state.inodes.get(&ino, tick);
+        self.count(inode.is_some());
+        inode
+    }
+
+    /// Cache a record read after `stamp` was taken
+    pub fn insert(&self, inode: &Inode, stamp: u64) {
+        let mut state = self.state.lock().unwrap();
+        if !state.limits.enabled() || state.epoch != stamp {
+            return;
+        }
+        let tick = state.next_tick();
+        let capacity = state.limits.capacity;
+        state.inodes.insert(inode.ino, inode.clone(), tick, capacity);
+    }
+
+    /// Inode number a directory index key resolved to
+    pub fn lookup(&self, key: &(u64, FileName)) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        let tick = state.next_tick();
+        let ttl = state.limits.ttl;
+        if let Some(expired) = state.names.expire(key, ttl) {
+            state.unlink_name(key, expired);
+        }
+        let ino = state.names.get(key, tick);
+        self.count(ino.is_some());
+        ino
+    }
+
+    /// Cache a directory index key resolved after `stamp` was taken
+    pub fn insert_name(&self, key: (u64, FileName), ino: u64, stamp: u64) {
+        let mut state = self.state.lock().unwrap();
+        if !state.limits.enabled() || state.epoch != stamp {
+            return;
+        }
+        // An inode has one name; one it was renamed from is gone
+        if let Some(previous) = state.name_of.remove(&ino) {
+            state.names.remove(&previous);
+        }
+        state.forget_name(&key);
+        let tick = state.next_tick();
+        let capacity = state.limits.capacity;
+        for (evicted, evicted_ino) in state.names.insert(key.clone(), ino, tick, capacity) {
+            state.unlink_name(&evicted, evicted_ino);
+        }
+        state.name_of.insert(ino, key);
+    }
+
+    /// Drop a name entry that no longer resolves to the inode it names
+    pub fn forget_name(&self, key: &(u64, FileName)) {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        state.forget_name(key);
+    }
+
+    /// Drop an inode and the name resolving to it, before it changes or goes away
+    pub fn invalidate(&self, ino: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        state.inodes.remove(&ino);
+        if let Some(key) = state.name_of.remove(&ino) {
+            state.names.remove(&key);
+        }
+    }
+
+    fn count(&self, hit: bool) {
+        if hit {
+            self.metrics.record_inode_cache_hit();
+        } else {
+            self.metrics.record_inode_cache_miss();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(capacity: usize, ttl: Duration) -> InodeCache {
+        InodeCache::new(InodeCacheLimits { capacity, ttl }, Arc::new(Metrics::new()))
+    }
+
+    fn counts(cache: &InodeCache) -> (u64, u64) {
+        let snapshot = cache.metrics.snapshot();
+        (snapshot.inode_cache_hits, snapshot.inode_cache_misses)
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = cache(2, Duration::from_secs(60));
+        for ino in [2, 3] {
+            cache.insert(&Inode::new_file(ino, 1, format!("f{}", ino)), cache.stamp());
+        }
+        assert!(cache.get(2).is_some());
+        cache.insert(&Inode::new_file(4, 1, "f4".to_string()), cache.stamp());
+        assert!(cache.get(3).is_none(), "3 was used least recently");
+        assert!(cache.get(2).is_some() && cache.get(4).is_some());
+        assert_eq!(counts(&cache), (3, 1));
+    }
+
+    #[test]
+    fn test_invalidation_drops_inode_name_and_racing_inserts() {
+        let cache = cache(16, Duration::from_secs(60));
+        let inode = Inode::new_file(7, 1, "a".to_string());
+        let key = (1, FileName::from("a"));
+        let stamp = cache.stamp();
+        cache.insert(&inode, stamp);
+        cache.insert_name(key.clone(), 7, stamp);
+        assert_eq!(cache.lookup(&key), Some(7));
+
+        // A record read before a save is not cached after it
+        let stale = cache.stamp();
+        cache.invalidate(7);
+        assert!(cache.get(7).is_none());
+        assert_eq!(cache.lookup(&key), None);
+        cache.insert(&inode, stale);
+        assert!(cache.get(7).is_none());
+
+        // A renamed inode's old name goes when the new one is cached
+        let stamp = cache.stamp();
+        cache.insert_name(key.clone(), 7, stamp);
+        cache.insert_name((1, FileName::from("b")), 7, stamp);
+        assert_eq!(cache.lookup(&key), None);
+        assert_eq!(cache.lookup(&(1, FileName::from("b"))), Some(7));
+    }
+
+    #[test]
+    fn test_entries_expire_and_zero_disables() {
+        let cache = cache(16, Duration::from_millis(20));
+        cache.insert(&Inode::new_file(2, 1, "f".to_string()), cache.stamp());
+        assert!(cache.get(2).is_some());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get(2).is_none());
+
+        cache.set_limits(InodeCacheLimits { capacity: 0, ttl: Duration::from_secs(60) });
+        cache.insert(&Inode::new_file(2, 1, "f".to_string()), cache.stamp());
+        assert!(cache.get(2).is_none());
+    }
+}
@@ -0,0 +1,73 @@
+//! Pool-wide inode counters kept up to date as inodes are saved and deleted
+//!
+//! `statfs` reads these instead of every inode record. A save compares the
+//! inode with the copy it replaces, so creating, growing, truncating and
+//! deleting files all move the totals by the difference. Saving an inode
+//! again with the same contents leaves them as they are, which keeps a replayed
+//! metadata transaction from counting its inodes twice.
+//!
+//! The totals are written after each change. A crash between an inode save
+//! and that write leaves them slightly off until `check --repair` recounts.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::metadata::{FileType, Inode};
+
+/// File holding the totals, relative to the pool directory
+const TOTALS_FILE: &str = "metadata/inode_totals.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InodeTotals {
+    pub files: u64,
+    pub dirs: u64,
+    /// Sum of the sizes of all inodes
+    pub logical_bytes: u64,
+}
+
+impl InodeTotals {
+    /// The saved totals of the pool at `pool_dir`, if it has any
+    pub fn load(pool_dir: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(pool_dir.join(TOTALS_FILE)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents).ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        let path = pool_dir.join(TOTALS_FILE);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Count an inode saved for the first time
+    pub fn add(&mut self, inode: &Inode) {
+        match inode.file_type {
+            FileType::RegularFile => self.files += 1,
+            FileType::Directory => self.dirs += 1,
+        }
+        self.logical_bytes += inode.size;
+    }
+
+    /// Stop counting a deleted inode
+    pub fn remove(&mut self, inode: &Inode) {
+        match inode.file_type {
+            FileType::RegularFile => self.files = self.files.saturating_sub(1),
+            FileType::Directory => self.dirs = self.dirs.saturating_sub(1),
+        }
+        self.logical_bytes = self.logical_bytes.saturating_sub(inode.size);
+    }
+
+    /// Count an inode saved over `previous`, its last saved copy if it had one
+    pub fn replace(&mut self, previous: Option<&Inode>, inode: &Inode) {
+        if let Some(previous) = previous {
+            self.remove(previous);
+        }
+        self.add(inode);
+    }
+}
@@ -22,6 +22,7 @@ mod integration_tests {
         // Perform mount-time rebuild which should migrate fragments off draining disk
         let res = storage.perform_mount_rebuild();
         assert!(res.is_ok(), "perform_mount_rebuild should succeed");
+        storage.wait_for_rebuilds();
 
         // After rebuild, ensure no fragment references point to the drained disk
         let metadata_mgr = storage.metadata();
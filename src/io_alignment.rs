@@ -9,6 +9,64 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
 use std::ptr;
 use nix::sys::statvfs;
+use serde::{Deserialize, Serialize};
+
+const BLKSSZGET: libc::c_ulong = 0x1268;
+const BLKPBSZGET: libc::c_ulong = 0x127b;
+const BLKGETSIZE64: libc::c_ulong = 0x80081272;
+
+/// Size and block sizes of a block device, as reported by the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockGeometry {
+    pub size_bytes: u64,
+    /// Smallest unit the device can address (512 on 512e drives, 4096 on 4Kn)
+    pub logical_block_size: u32,
+    /// Unit the device writes internally; smaller or unaligned writes cost a read-modify-write
+    pub physical_block_size: u32,
+}
+
+impl BlockGeometry {
+    /// Query a block device with BLKGETSIZE64, BLKSSZGET and BLKPBSZGET
+    ///
+    /// A device that does not report its physical block size is taken to
+    /// write in logical blocks.
+    pub fn query(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open block device {:?}", path))?;
+        let fd = file.as_raw_fd();
+
+        let mut size_bytes: u64 = 0;
+        if unsafe { libc::ioctl(fd, BLKGETSIZE64 as _, &mut size_bytes) } != 0 {
+            return Err(io::Error::last_os_error()).context("BLKGETSIZE64 ioctl failed");
+        }
+        let mut logical: libc::c_int = 0;
+        if unsafe { libc::ioctl(fd, BLKSSZGET as _, &mut logical) } != 0 || logical <= 0 {
+            return Err(io::Error::last_os_error()).context("BLKSSZGET ioctl failed");
+        }
+        let mut physical: libc::c_uint = 0;
+        if unsafe { libc::ioctl(fd, BLKPBSZGET as _, &mut physical) } != 0 || physical == 0 {
+            physical = logical as libc::c_uint;
+        }
+
+        Ok(BlockGeometry {
+            size_bytes,
+            logical_block_size: logical as u32,
+            physical_block_size: physical.max(logical as u32),
+        })
+    }
+}
+
+/// Round `value` up to a multiple of `align`
+pub fn align_up(value: u64, align: u64) -> u64 {
+    value.div_ceil(align) * align
+}
+
+/// Allocation unit for a device with `block_size` byte physical blocks
+///
+/// `unit_size` rounded up to a whole number of blocks, so every unit starts
+/// and ends on a block boundary.
+pub fn aligned_unit_size(unit_size: u64, block_size: u64) -> u64 {
+    align_up(unit_size.max(1), block_size.max(1))
+}
 
 /// Simple RAII wrapper for aligned memory allocated via posix_memalign
 pub struct AlignedBuf {
@@ -198,26 +256,19 @@ pub fn pread_direct(fd: RawFd, buf: &mut AlignedBuf, offset: u64) -> Result<usiz
     Ok(read)
 }
 
+/// Whether `path` is a block device
+pub fn is_block_device(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).map(|meta| meta.file_type().is_block_device()).unwrap_or(false)
+}
+
 /// Detect the required alignment (in bytes) for I/O on `path`.
-/// For block devices, use BLKSSZGET (sector size). For regular files, use filesystem block size.
+/// For block devices, use the physical block size, which is also a multiple of
+/// the logical size O_DIRECT needs. For regular files, use filesystem block size.
 pub fn detect_alignment_from_path(path: &Path) -> Result<usize> {
-    // If path exists and is block device, query block device sector size
-    if path.exists() {
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::FileTypeExt;
-            if std::fs::metadata(path)?.file_type().is_block_device() {
-                use std::os::unix::io::AsRawFd;
-                let f = File::open(path)?;
-                let fd = f.as_raw_fd();
-                // BLKSSZGET ioctl
-                let mut sector_size: libc::c_uint = 0;
-                const BLKSSZGET: libc::c_ulong = 0x1268;
-                let ret = unsafe { libc::ioctl(fd, BLKSSZGET as _, &mut sector_size) };
-                if ret == 0 && sector_size > 0 {
-                    return Ok(sector_size as usize);
-                }
-            }
+    if is_block_device(path) {
+        if let Ok(geometry) = BlockGeometry::query(path) {
+            return Ok(geometry.physical_block_size as usize);
         }
     }
 
@@ -235,8 +286,13 @@ pub fn detect_alignment_from_path(path: &Path) -> Result<usize> {
 
 /// Write `data` to `path` using aligned buffer and O_DIRECT if possible. If `prefer_direct` is true
 /// try to open with O_DIRECT and fall back as needed. Pads short writes up to alignment.
+///
+/// A regular file is left `data.len()` bytes long. On a block device the
+/// zero padding up to the next block boundary is written too, buffered or not,
+/// so the device never sees a partial block.
 pub fn write_aligned_file(path: &Path, data: &[u8], prefer_direct: bool) -> Result<()> {
     let align = detect_alignment_from_path(path)?;
+    let block_device = is_block_device(path);
     // Determine write size: round up to multiple of alignment
     let mut write_size = data.len();
     if write_size % align != 0 {
@@ -261,13 +317,21 @@ pub fn write_aligned_file(path: &Path, data: &[u8], prefer_direct: bool) -> Resu
             anyhow::bail!("short write for direct I/O: {} != {}", wrote, write_size);
         }
         // Trim the file to the actual data length to match behavior of buffered writes
-        f.set_len(data.len() as u64)
-            .context("Failed to truncate direct-written file to data length")?;
+        if !block_device {
+            f.set_len(data.len() as u64)
+                .context("Failed to truncate direct-written file to data length")?;
+        }
         // Ensure metadata and truncation are persisted
         f.sync_all()?;
         Ok(())
     } else {
         // Fallback to normal write
+        if block_device {
+            let mut f = OpenOptions::new().write(true).open(path)?;
+            f.write_all(buf.as_slice())?;
+            f.sync_all()?;
+            return Ok(());
+        }
         let mut f = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
         f.write_all(&buf.as_slice()[..data.len()])?;
         f.sync_all()?;
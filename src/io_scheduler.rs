@@ -1,302 +1,446 @@
-/// Phase 15: I/O Scheduler with per-disk worker pools
-/// 
-/// Provides parallel I/O execution with prioritized scheduling and per-disk
-/// work queues to maximize throughput while maintaining fairness.
-
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Arc, Mutex, Condvar};
-use std::thread::{self, JoinHandle};
+//! Admission of fragment I/O by class
+//!
+//! Every fragment read, write and delete takes a slot on its disk first. A
+//! request carries the class of the thread making it: threads serve
+//! foreground (FUSE) I/O unless a background pass entered its own class with
+//! `IoClass::enter`. Foreground requests go ahead of every queued background
+//! request, and background requests leave one slot of each disk free, so a
+//! saturating scrub or rebuild delays a read by at most the requests already
+//! in flight. Each background class has a limit on its requests in flight
+//! across all disks, and a disk's free slots go to the waiting class that has
+//! used the least of its weighted share.
+//!
+//! There is one scheduler per process. The mount applies the pool's limits
+//! from pool.json; `set-rebuild-limit` changes them, live on a mounted pool.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock};
 use std::time::Duration;
 use uuid::Uuid;
 
-/// I/O request priority levels
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum IoPriority {
-    /// Critical metadata operations
-    Critical = 0,
-    /// Read requests for hot data
-    HighRead = 1,
-    /// Normal read requests
-    NormalRead = 2,
-    /// Write requests
-    Write = 3,
-    /// Background operations (scrub, GC, etc.)
-    Background = 4,
+/// Waiters re-check their turn this often, in case a wakeup was missed
+const RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+/// Share used by one request of a class of weight 1
+const SHARE: u64 = 1 << 20;
+
+/// What a fragment request is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoClass {
+    /// Reads and writes of files, and anything not tagged otherwise
+    Foreground,
+    /// Rebuilds, `rebuild` and `replace-disk`
+    Rebuild,
+    Scrub,
+    /// Defragmentation and rebalancing moves
+    Defrag,
+    /// Orphan fragment collection
+    Gc,
 }
 
-/// An I/O request to be executed
-#[derive(Debug, Clone)]
-pub struct IoRequest {
-    pub id: Uuid,
-    pub disk_uuid: Uuid,
-    pub priority: IoPriority,
-    pub operation: IoOperation,
+thread_local! {
+    static CURRENT_CLASS: Cell<IoClass> = const { Cell::new(IoClass::Foreground) };
 }
 
-/// Type of I/O operation
-#[derive(Debug, Clone)]
-pub enum IoOperation {
-    Read {
-        extent_uuid: Uuid,
-        fragment_index: usize,
-    },
-    Write {
-        extent_uuid: Uuid,
-        fragment_index: usize,
-        data: Vec<u8>,
-    },
-    Delete {
-        extent_uuid: Uuid,
-        fragment_index: usize,
-    },
+impl IoClass {
+    pub const ALL: [IoClass; 5] = [IoClass::Foreground, IoClass::Rebuild, IoClass::Scrub, IoClass::Defrag, IoClass::Gc];
+    pub const BACKGROUND: [IoClass; 4] = [IoClass::Rebuild, IoClass::Scrub, IoClass::Defrag, IoClass::Gc];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IoClass::Foreground => "foreground",
+            IoClass::Rebuild => "rebuild",
+            IoClass::Scrub => "scrub",
+            IoClass::Defrag => "defrag",
+            IoClass::Gc => "gc",
+        }
+    }
+
+    /// Class of the fragment I/O the calling thread makes
+    pub fn current() -> IoClass {
+        CURRENT_CLASS.with(Cell::get)
+    }
+
+    /// Tag the calling thread's fragment I/O with this class until the guard is dropped
+    pub fn enter(self) -> IoClassGuard {
+        IoClassGuard { previous: CURRENT_CLASS.with(|class| class.replace(self)) }
+    }
 }
 
-/// Result of an I/O operation
-#[derive(Debug, Clone)]
-pub struct IoResult {
-    pub request_id: Uuid,
-    pub success: bool,
-    pub data: Option<Vec<u8>>,
-    pub error: Option<String>,
+impl std::fmt::Display for IoClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
-/// Per-disk work queue
-pub struct DiskQueue {
-    /// Disk UUID
-    pub disk_uuid: Uuid,
-    /// Pending requests sorted by priority
-    pub requests: VecDeque<IoRequest>,
-    /// Worker thread handle
-    pub worker: Option<JoinHandle<()>>,
-    /// Shutdown signal
-    pub shutdown: bool,
-    /// Queue statistics
-    pub total_processed: u64,
-    pub total_bytes: u64,
+impl std::str::FromStr for IoClass {
+    type Err = anyhow::Error;
+
+    /// Parses the background class names; foreground I/O has no limits to set
+    fn from_str(s: &str) -> Result<Self> {
+        IoClass::BACKGROUND
+            .into_iter()
+            .find(|class| class.name() == s.to_ascii_lowercase())
+            .ok_or_else(|| anyhow!("Unknown I/O class '{}' (expected rebuild, scrub, defrag or gc)", s))
+    }
 }
 
-impl DiskQueue {
-    fn new(disk_uuid: Uuid) -> Self {
-        DiskQueue {
-            disk_uuid,
-            requests: VecDeque::new(),
-            worker: None,
-            shutdown: false,
-            total_processed: 0,
-            total_bytes: 0,
+/// Restores the thread's previous class when dropped
+pub struct IoClassGuard {
+    previous: IoClass,
+}
+
+impl Drop for IoClassGuard {
+    fn drop(&mut self) {
+        CURRENT_CLASS.with(|class| class.set(self.previous));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassLimit {
+    /// Requests of the class in flight at once, across all disks
+    pub max_concurrent: usize,
+    /// Share of a busy disk against the other background classes
+    pub weight: u32,
+}
+
+/// Limits of the I/O scheduler, stored with the pool configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IoLimits {
+    /// Requests in flight on one disk; background classes leave one of them free
+    pub disk_queue_depth: usize,
+    pub rebuild: ClassLimit,
+    pub scrub: ClassLimit,
+    pub defrag: ClassLimit,
+    pub gc: ClassLimit,
+}
+
+impl Default for IoLimits {
+    fn default() -> Self {
+        IoLimits {
+            disk_queue_depth: 8,
+            rebuild: ClassLimit { max_concurrent: 4, weight: 4 },
+            scrub: ClassLimit { max_concurrent: 2, weight: 2 },
+            defrag: ClassLimit { max_concurrent: 2, weight: 1 },
+            gc: ClassLimit { max_concurrent: 1, weight: 1 },
         }
     }
-    
-    /// Add request to queue (maintains priority order)
-    fn enqueue(&mut self, request: IoRequest) {
-        // Find insertion point to maintain priority order
-        let pos = self.requests
-            .iter()
-            .position(|r| r.priority > request.priority)
-            .unwrap_or(self.requests.len());
-        
-        self.requests.insert(pos, request);
+}
+
+impl IoLimits {
+    pub fn validate(&self) -> Result<()> {
+        if self.disk_queue_depth < 2 {
+            return Err(anyhow!("The disk queue depth must be at least 2; one slot is kept for foreground I/O"));
+        }
+        for class in IoClass::BACKGROUND {
+            let limit = self.class(class).unwrap();
+            if limit.max_concurrent == 0 || limit.weight == 0 {
+                return Err(anyhow!("{} I/O needs a concurrency and weight of at least 1", class));
+            }
+        }
+        Ok(())
     }
-    
-    /// Remove and return highest priority request
-    fn dequeue(&mut self) -> Option<IoRequest> {
-        self.requests.pop_front()
+
+    /// Limit of a background class; foreground I/O has none
+    pub fn class(&self, class: IoClass) -> Option<ClassLimit> {
+        let mut limits = *self;
+        limits.class_mut(class).copied()
     }
-    
-    /// Get queue length
-    fn len(&self) -> usize {
-        self.requests.len()
+
+    pub fn class_mut(&mut self, class: IoClass) -> Option<&mut ClassLimit> {
+        match class {
+            IoClass::Foreground => None,
+            IoClass::Rebuild => Some(&mut self.rebuild),
+            IoClass::Scrub => Some(&mut self.scrub),
+            IoClass::Defrag => Some(&mut self.defrag),
+            IoClass::Gc => Some(&mut self.gc),
+        }
+    }
+}
+
+/// Requests of one class on one disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClassQueue {
+    /// Waiting for a slot
+    pub queued: u64,
+    pub in_flight: u64,
+    pub completed: u64,
+}
+
+/// Queues of each disk that has seen fragment I/O, by class
+pub type DiskQueues = BTreeMap<Uuid, BTreeMap<IoClass, ClassQueue>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoSchedulerStatus {
+    pub limits: IoLimits,
+    /// Requests in flight across all disks, by class
+    pub in_flight: BTreeMap<IoClass, u64>,
+    pub disks: DiskQueues,
+}
+
+#[derive(Default)]
+struct DiskSlots {
+    classes: [ClassQueue; IoClass::ALL.len()],
+    /// Weighted share each background class has used; the lowest goes next
+    used: [u64; IoClass::ALL.len()],
+}
+
+impl DiskSlots {
+    fn in_flight(&self) -> u64 {
+        self.classes.iter().map(|class| class.in_flight).sum()
     }
-    
-    /// Check if queue is empty
-    fn is_empty(&self) -> bool {
-        self.requests.is_empty()
+
+    fn busy(&self, class: IoClass) -> bool {
+        let queue = &self.classes[class as usize];
+        queue.queued > 0 || queue.in_flight > 0
     }
 }
 
-/// I/O scheduler with per-disk worker pools
+struct SchedulerState {
+    limits: IoLimits,
+    in_flight: [u64; IoClass::ALL.len()],
+    disks: HashMap<Uuid, DiskSlots>,
+}
+
+/// Per-disk slots for fragment I/O; see the module docs
 pub struct IoScheduler {
-    /// Per-disk queues
-    pub queues: Arc<Mutex<HashMap<Uuid, DiskQueue>>>,
-    /// Condition variable for work availability
-    work_available: Arc<Condvar>,
-    /// Result callback
-    result_handler: Arc<Mutex<Option<Box<dyn Fn(IoResult) + Send>>>>,
-    /// Maximum queue size per disk (backpressure)
-    max_queue_size: usize,
+    state: Mutex<SchedulerState>,
+    changed: Condvar,
+}
+
+/// The process's scheduler, which `Disk` fragment I/O goes through
+pub fn scheduler() -> &'static IoScheduler {
+    static SCHEDULER: OnceLock<IoScheduler> = OnceLock::new();
+    SCHEDULER.get_or_init(|| IoScheduler::new(IoLimits::default()))
 }
 
 impl IoScheduler {
-    /// Create new I/O scheduler
-    pub fn new(max_queue_size: usize) -> Self {
+    pub fn new(limits: IoLimits) -> Self {
         IoScheduler {
-            queues: Arc::new(Mutex::new(HashMap::new())),
-            work_available: Arc::new(Condvar::new()),
-            result_handler: Arc::new(Mutex::new(None)),
-            max_queue_size,
-        }
-    }
-    
-    /// Register a disk with the scheduler
-    pub fn register_disk(&self, disk_uuid: Uuid, workers: usize) {
-        let mut queues = self.queues.lock().unwrap();
-        
-        if queues.contains_key(&disk_uuid) {
-            return; // Already registered
-        }
-        
-        let queue = DiskQueue::new(disk_uuid);
-        queues.insert(disk_uuid, queue);
-        drop(queues);
-        
-        // Start worker threads for this disk
-        for worker_id in 0..workers {
-            self.start_worker(disk_uuid, worker_id);
+            state: Mutex::new(SchedulerState {
+                limits,
+                in_flight: [0; IoClass::ALL.len()],
+                disks: HashMap::new(),
+            }),
+            changed: Condvar::new(),
         }
     }
-    
-    /// Start a worker thread for a disk
-    fn start_worker(&self, disk_uuid: Uuid, worker_id: usize) {
-        let queues = self.queues.clone();
-        let work_available = self.work_available.clone();
-        let result_handler = self.result_handler.clone();
-        
-        thread::Builder::new()
-            .name(format!("io-worker-{}-{}", disk_uuid, worker_id))
-            .spawn(move || {
-                log::debug!("I/O worker {}-{} started", disk_uuid, worker_id);
-                
-                loop {
-                    // Wait for work
-                    let request = {
-                        let mut qs = queues.lock().unwrap();
-                        
-                        // Wait for work or shutdown
-                        while !qs.get(&disk_uuid).map_or(false, |q| q.shutdown || !q.is_empty()) {
-                            qs = work_available.wait(qs).unwrap();
-                        }
-                        
-                        let queue = qs.get_mut(&disk_uuid).unwrap();
-                        
-                        // Check for shutdown
-                        if queue.shutdown && queue.is_empty() {
-                            log::debug!("I/O worker {}-{} shutting down", disk_uuid, worker_id);
-                            return;
-                        }
-                        
-                        queue.dequeue()
-                    };
-                    
-                    // Execute request
-                    if let Some(req) = request {
-                        let result = Self::execute_request(&req);
-                        
-                        // Update stats
-                        {
-                            let mut qs = queues.lock().unwrap();
-                            if let Some(queue) = qs.get_mut(&disk_uuid) {
-                                queue.total_processed += 1;
-                                if let Some(ref data) = result.data {
-                                    queue.total_bytes += data.len() as u64;
-                                }
-                            }
-                        }
-                        
-                        // Invoke callback
-                        if let Some(ref handler) = *result_handler.lock().unwrap() {
-                            handler(result);
-                        }
-                    }
-                }
-            })
-            .expect("Failed to spawn I/O worker thread");
+
+    /// Replace the limits; queued requests pick them up at once
+    pub fn set_limits(&self, limits: IoLimits) -> Result<()> {
+        limits.validate()?;
+        self.state.lock().unwrap().limits = limits;
+        self.changed.notify_all();
+        Ok(())
     }
-    
-    /// Execute an I/O request (stub - actual implementation would interact with disk)
-    fn execute_request(request: &IoRequest) -> IoResult {
-        // This is a stub - actual implementation would interact with the disk
-        log::trace!(
-            "Executing I/O request {} on disk {} with priority {:?}",
-            request.id,
-            request.disk_uuid,
-            request.priority
-        );
-        
-        // TODO: Remove this simulation delay in production builds
-        // This is only for testing to simulate I/O latency
-        #[cfg(test)]
-        thread::sleep(Duration::from_micros(100));
-        
-        IoResult {
-            request_id: request.id,
-            success: true,
-            data: None,
-            error: None,
-        }
+
+    pub fn limits(&self) -> IoLimits {
+        self.state.lock().unwrap().limits
     }
-    
-    /// Submit an I/O request
-    /// 
-    /// Returns Ok(()) if enqueued, Err if queue is full (backpressure)
-    pub fn submit(&self, request: IoRequest) -> Result<(), String> {
-        let mut queues = self.queues.lock().unwrap();
-        
-        let queue = queues.get_mut(&request.disk_uuid)
-            .ok_or_else(|| format!("Disk {} not registered", request.disk_uuid))?;
-        
-        // Check backpressure
-        if queue.len() >= self.max_queue_size {
-            return Err(format!("Queue full for disk {}", request.disk_uuid));
+
+    pub fn status(&self) -> IoSchedulerStatus {
+        let state = self.state.lock().unwrap();
+        IoSchedulerStatus {
+            limits: state.limits,
+            in_flight: IoClass::ALL.into_iter().map(|class| (class, state.in_flight[class as usize])).collect(),
+            disks: Self::queues_of(&state),
         }
-        
-        queue.enqueue(request);
-        
-        // Signal workers
-        drop(queues);
-        self.work_available.notify_all();
-        
-        Ok(())
     }
-    
-    /// Set result handler callback
-    pub fn set_result_handler<F>(&self, handler: F)
-    where
-        F: Fn(IoResult) + Send + 'static,
-    {
-        *self.result_handler.lock().unwrap() = Some(Box::new(handler));
+
+    pub fn queues(&self) -> DiskQueues {
+        Self::queues_of(&self.state.lock().unwrap())
     }
-    
-    /// Get queue statistics for a disk
-    pub fn queue_stats(&self, disk_uuid: &Uuid) -> Option<(usize, u64, u64)> {
-        let queues = self.queues.lock().unwrap();
-        queues.get(disk_uuid).map(|q| (q.len(), q.total_processed, q.total_bytes))
+
+    /// Wait for a slot on `disk` for the calling thread's class
+    pub fn admit(&self, disk: Uuid) -> IoSlot<'_> {
+        self.admit_as(disk, IoClass::current())
     }
-    
-    /// Get total queue lengths across all disks
-    pub fn total_queue_length(&self) -> usize {
-        let queues = self.queues.lock().unwrap();
-        queues.values().map(|q| q.len()).sum()
+
+    pub fn admit_as(&self, disk: Uuid, class: IoClass) -> IoSlot<'_> {
+        let mut state = self.state.lock().unwrap();
+        let slots = state.disks.entry(disk).or_default();
+        if class != IoClass::Foreground && !slots.busy(class) {
+            // A class that was idle starts level with the busy ones instead of ahead of them
+            let level = IoClass::BACKGROUND.into_iter().filter(|c| slots.busy(*c)).map(|c| slots.used[c as usize]).min();
+            if let Some(level) = level {
+                slots.used[class as usize] = slots.used[class as usize].max(level);
+            }
+        }
+        slots.classes[class as usize].queued += 1;
+
+        while !Self::may_start(&state, disk, class) {
+            state = self.changed.wait_timeout(state, RECHECK_INTERVAL).unwrap().0;
+        }
+        let limits = state.limits;
+        state.in_flight[class as usize] += 1;
+        let slots = state.disks.get_mut(&disk).unwrap();
+        slots.classes[class as usize].queued -= 1;
+        slots.classes[class as usize].in_flight += 1;
+        if let Some(limit) = limits.class(class) {
+            slots.used[class as usize] += SHARE / limit.weight as u64;
+        }
+        IoSlot { scheduler: self, disk, class }
     }
-    
-    /// Shutdown the scheduler and all workers
-    pub fn shutdown(&self) {
-        let mut queues = self.queues.lock().unwrap();
-        
-        for queue in queues.values_mut() {
-            queue.shutdown = true;
+
+    /// Whether a queued request of `class` may take a slot on `disk` now
+    fn may_start(state: &MutexGuard<SchedulerState>, disk: Uuid, class: IoClass) -> bool {
+        let slots = &state.disks[&disk];
+        let depth = state.limits.disk_queue_depth as u64;
+        if class == IoClass::Foreground {
+            return slots.in_flight() < depth;
         }
-        
-        drop(queues);
-        self.work_available.notify_all();
-        
-        // Wait a bit for workers to finish
-        thread::sleep(Duration::from_millis(100));
+        if slots.classes[IoClass::Foreground as usize].queued > 0 || slots.in_flight() + 1 >= depth {
+            return false;
+        }
+        let next = IoClass::BACKGROUND
+            .into_iter()
+            .filter(|c| slots.classes[*c as usize].queued > 0)
+            .filter(|c| state.in_flight[*c as usize] < state.limits.class(*c).unwrap().max_concurrent as u64)
+            .min_by_key(|c| slots.used[*c as usize]);
+        next == Some(class)
+    }
+
+    fn release(&self, disk: Uuid, class: IoClass) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight[class as usize] -= 1;
+        let queue = &mut state.disks.get_mut(&disk).unwrap().classes[class as usize];
+        queue.in_flight -= 1;
+        queue.completed += 1;
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    fn queues_of(state: &SchedulerState) -> DiskQueues {
+        state
+            .disks
+            .iter()
+            .map(|(disk, slots)| {
+                (*disk, IoClass::ALL.into_iter().map(|class| (class, slots.classes[class as usize])).collect())
+            })
+            .collect()
     }
 }
 
-impl Drop for IoScheduler {
+/// A slot on one disk, given back when dropped
+pub struct IoSlot<'a> {
+    scheduler: &'a IoScheduler,
+    disk: Uuid,
+    class: IoClass,
+}
+
+impl Drop for IoSlot<'_> {
     fn drop(&mut self) {
-        self.shutdown();
+        self.scheduler.release(self.disk, self.class);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Wait until `disk` has `queued` requests of `class` waiting
+    fn wait_queued(scheduler: &IoScheduler, disk: Uuid, class: IoClass, queued: u64) {
+        while scheduler.queues().get(&disk).map_or(0, |classes| classes[&class].queued) < queued {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_foreground_goes_ahead_of_queued_background_requests() {
+        let limits = IoLimits { disk_queue_depth: 2, ..Default::default() };
+        let scheduler = IoScheduler::new(limits);
+        let disk = Uuid::new_v4();
+
+        // The one background slot is taken; a scrub request queues behind it
+        let held = scheduler.admit_as(disk, IoClass::Rebuild);
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            let scheduler = &scheduler;
+            let tx_scrub = tx.clone();
+            scope.spawn(move || {
+                let _slot = scheduler.admit_as(disk, IoClass::Scrub);
+                tx_scrub.send(IoClass::Scrub).unwrap();
+            });
+            wait_queued(scheduler, disk, IoClass::Scrub, 1);
+
+            // Foreground takes the reserved slot, and the scrub waits while foreground is queued
+            let foreground = scheduler.admit_as(disk, IoClass::Foreground);
+            let tx_fg = tx.clone();
+            scope.spawn(move || {
+                let _slot = scheduler.admit_as(disk, IoClass::Foreground);
+                tx_fg.send(IoClass::Foreground).unwrap();
+            });
+            wait_queued(scheduler, disk, IoClass::Foreground, 1);
+            drop(held);
+            assert_eq!(rx.recv().unwrap(), IoClass::Foreground);
+            drop(foreground);
+            assert_eq!(rx.recv().unwrap(), IoClass::Scrub);
+        });
+        let queues = &scheduler.queues()[&disk];
+        assert_eq!(queues[&IoClass::Foreground].completed, 2);
+        assert_eq!(queues[&IoClass::Scrub].completed, 1);
+        assert!(queues.values().all(|queue| queue.queued == 0 && queue.in_flight == 0));
+    }
+
+    #[test]
+    fn test_background_classes_share_a_disk_by_weight_within_their_limits() {
+        let mut limits = IoLimits { disk_queue_depth: 2, ..Default::default() };
+        limits.rebuild = ClassLimit { max_concurrent: 8, weight: 3 };
+        limits.scrub = ClassLimit { max_concurrent: 8, weight: 1 };
+        let scheduler = IoScheduler::new(limits);
+        let disk = Uuid::new_v4();
+
+        // Hold the slot while both classes queue up, then record the order they get it in
+        let order = Mutex::new(Vec::new());
+        let held = scheduler.admit_as(disk, IoClass::Gc);
+        thread::scope(|scope| {
+            for class in [IoClass::Rebuild, IoClass::Scrub] {
+                for _ in 0..8 {
+                    let (scheduler, order) = (&scheduler, &order);
+                    scope.spawn(move || {
+                        let _slot = scheduler.admit_as(disk, class);
+                        order.lock().unwrap().push(class);
+                    });
+                }
+            }
+            wait_queued(&scheduler, disk, IoClass::Rebuild, 8);
+            wait_queued(&scheduler, disk, IoClass::Scrub, 8);
+            drop(held);
+        });
+        let order = order.into_inner().unwrap();
+        let rebuilds = order[..8].iter().filter(|c| **c == IoClass::Rebuild).count();
+        assert_eq!(rebuilds, 6, "{:?}", order);
+
+        // A class at its limit across disks waits even with slots free
+        let mut limits = scheduler.limits();
+        limits.disk_queue_depth = 8;
+        limits.scrub.max_concurrent = 1;
+        scheduler.set_limits(limits).unwrap();
+        let first = scheduler.admit_as(disk, IoClass::Scrub);
+        let other_disk = Uuid::new_v4();
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| drop(scheduler.admit_as(other_disk, IoClass::Scrub)));
+            wait_queued(&scheduler, other_disk, IoClass::Scrub, 1);
+            assert!(!waiter.is_finished());
+            drop(first);
+        });
+        assert_eq!(scheduler.status().in_flight[&IoClass::Scrub], 0);
+    }
+
+    #[test]
+    fn test_limits_reject_a_depth_without_a_foreground_slot() {
+        assert!(IoLimits::default().validate().is_ok());
+        assert!(IoLimits { disk_queue_depth: 1, ..Default::default() }.validate().is_err());
+        let mut limits = IoLimits::default();
+        limits.gc.weight = 0;
+        assert!(limits.validate().is_err());
+        assert_eq!("Scrub".parse::<IoClass>().unwrap(), IoClass::Scrub);
+        assert!("foreground".parse::<IoClass>().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::disk::DiskHealth;
+
+/// Where a file's data lives: every extent and the disks holding its fragments
+///
+/// Produced by `StorageEngine::describe_layout`, served as the `user.scfs.layout`
+/// xattr and printed by `dynamicfs file-layout`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileLayout {
+    pub ino: u64,
+    pub size: u64,
+    /// Bytes of the file covered by each slot
+    pub extent_size: usize,
+    /// Data extents, skipping holes
+    pub extents: Vec<ExtentLayout>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtentLayout {
+    /// Slot of the extent in the file; slot N starts at N × extent size
+    pub slot: usize,
+    pub uuid: Uuid,
+    pub size: usize,
+    pub redundancy: String,
+    pub compression: String,
+    /// Bytes encoded into fragments, after compression
+    pub stored_size: usize,
+    pub fragments: Vec<FragmentLayout>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FragmentLayout {
+    pub index: usize,
+    pub disk: Uuid,
+    /// Health of the disk holding the fragment; `None` if it is no longer in the pool
+    pub disk_health: Option<DiskHealth>,
+}
+
+/// Fragments a file keeps on one disk, for the summarized layout
+#[derive(Debug, Clone, Serialize)]
+struct DiskUsage {
+    disk: Uuid,
+    disk_health: Option<DiskHealth>,
+    fragments: usize,
+}
+
+/// Layout too large to list extent by extent
+#[derive(Debug, Clone, Serialize)]
+struct LayoutSummary {
+    ino: u64,
+    size: u64,
+    summarized: bool,
+    extent_count: usize,
+    /// Extent count per redundancy policy
+    policies: BTreeMap<String, usize>,
+    disks: Vec<DiskUsage>,
+    full_listing: String,
+}
+
+impl FileLayout {
+    /// JSON for the layout xattr, at most `limit` bytes
+    ///
+    /// The full listing when it fits; otherwise a per-disk and per-policy
+    /// summary that points at `dynamicfs file-layout` for the rest.
+    pub fn to_xattr_value(&self, limit: usize) -> Vec<u8> {
+        let full = serde_json::json!({
+            "ino": self.ino,
+            "size": self.size,
+            "extent_size": self.extent_size,
+            "summarized": false,
+            "extents": self.extents,
+        });
+        let full = serde_json::to_vec(&full).unwrap_or_default();
+        if full.len() <= limit {
+            return full;
+        }
+
+        let mut policies = BTreeMap::new();
+        let mut disks: BTreeMap<Uuid, DiskUsage> = BTreeMap::new();
+        for extent in &self.extents {
+            *policies.entry(extent.redundancy.clone()).or_insert(0) += 1;
+            for fragment in &extent.fragments {
+                disks
+                    .entry(fragment.disk)
+                    .or_insert(DiskUsage { disk: fragment.disk, disk_health: fragment.disk_health, fragments: 0 })
+                    .fragments += 1;
+            }
+        }
+        let summary = LayoutSummary {
+            ino: self.ino,
+            size: self.size,
+            summarized: true,
+            extent_count: self.extents.len(),
+            policies,
+            disks: disks.into_values().collect(),
+            full_listing: format!("dynamicfs file-layout --pool <pool> --ino {}", self.ino),
+        };
+        serde_json::to_vec(&summary).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_xattr_summarizes_when_over_limit() {
+        let disks: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let layout = FileLayout {
+            ino: 42,
+            size: 2000 * 1024,
+            extent_size: 1024,
+            extents: (0..2000)
+                .map(|slot| ExtentLayout {
+                    slot,
+                    uuid: Uuid::new_v4(),
+                    size: 1024,
+                    redundancy: "replication:3".to_string(),
+                    compression: "none".to_string(),
+                    stored_size: 1024,
+                    fragments: disks
+                        .iter()
+                        .enumerate()
+                        .map(|(index, disk)| FragmentLayout { index, disk: *disk, disk_health: Some(DiskHealth::Healthy) })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        let full: serde_json::Value = serde_json::from_slice(&layout.to_xattr_value(usize::MAX)).unwrap();
+        assert_eq!(full["summarized"], false);
+        assert_eq!(full["extents"].as_array().unwrap().len(), 2000);
+        assert_eq!(full["extents"][7]["fragments"][2]["disk_health"], "Healthy");
+
+        let value = layout.to_xattr_value(64 * 1024);
+        assert!(value.len() <= 64 * 1024);
+        let summary: serde_json::Value = serde_json::from_slice(&value).unwrap();
+        assert_eq!(summary["summarized"], true);
+        assert_eq!(summary["extent_count"], 2000);
+        assert_eq!(summary["policies"]["replication:3"], 2000);
+        assert_eq!(summary["disks"].as_array().unwrap().len(), 3);
+        assert!(summary["disks"].as_array().unwrap().iter().all(|d| d["fragments"] == 2000));
+    }
+}
@@ -3,60 +3,96 @@ pub use crate::storage::*;
 pub use crate::disk::*;
 // Re-export modules used by integration tests
 
+pub mod activity;
 mod cli;
-mod config;
+pub mod config;
+pub mod control;
 mod crash_sim;
 mod diagnostics;
+pub mod error;
+pub mod export;
+pub mod file_name;
+pub mod manifest;
+pub mod ingest;
+pub mod smart;
+pub mod compression;
 pub mod disk;
+pub mod encryption;
+pub mod format_version;
 // test_utils moved into tests/unit; expose helper shim to compile test-only APIs
 #[cfg(test)]
 pub mod test_utils {
     include!("../tests/unit/test_utils.rs");
 }
 
+pub mod access_tracker;
 mod allocator;
-mod on_device_allocator;
+pub mod on_device_allocator;
 mod free_extent;
 mod metadata_btree;
 mod file_locks;
-mod io_scheduler;
-mod defrag;
+pub mod io_scheduler;
+pub mod defrag;
 mod trim;
 mod reclamation;
 mod io_alignment;
-mod extent;
+pub mod extent;
+pub mod extent_totals;
+pub mod inode_cache;
+pub mod inode_totals;
 #[cfg(not(target_os = "windows"))]
 mod fuse_impl;
+pub mod fsck;
 mod gc;
+mod periodic;
 mod hmm_classifier;
 mod json_output;
-mod logging;
+pub mod logging;
 mod metadata;
-mod metadata_tx;
+pub mod metadata_tx;
+pub mod metadata_store;
 mod metrics;
-mod monitoring;
+pub mod monitoring;
+pub mod notify;
+pub mod daemon;
+pub mod pool_lock;
 mod storage_engine;
 mod placement;
+pub mod rebalance;
+pub mod rebuild;
+pub mod replace_disk;
+pub mod rebuild_budget;
+mod rebuild_queue;
+pub mod recent_writes;
+mod prefetch_queue;
+pub mod policy_change;
 mod redundancy;
 mod scheduler;
-mod scrubber;
+pub mod scrubber;
 mod scrub_daemon;
 pub mod storage;
+pub mod quota;
+pub mod layout;
+pub mod usage;
 mod write_optimizer;
 mod adaptive;
-mod snapshots;
+pub mod snapshots;
 mod tiering;
-mod backup_evolution;
+pub mod backup_evolution;
 mod security;
+pub mod permissions;
 
 // Phase 9: Multi-OS Support
 pub mod fs_interface;
+pub mod memory_fs;
 pub mod path_utils;
 pub mod mount;
 
 // Phase 9.2: Windows Support
 #[cfg(target_os = "windows")]
 pub mod windows_fs;
+#[cfg(target_os = "windows")]
+mod winfsp_sys;
 
 // Phase 9.3: macOS Support
 #[cfg(target_os = "macos")]
@@ -64,12 +100,14 @@ pub mod macos;
 
 // Phase 10: Mixed Storage Speed Optimization
 pub mod data_cache;
+pub mod buffer_pool;
 
 // Phase 14: Multi-Level Caching Optimization
 pub mod multi_level_cache;
 
 // Phase 13: Multi-Node Network Distribution
 pub mod distributed;
+pub mod cluster_rpc;
 
 // Phase 17: Automated Intelligent Policies
 pub mod policy_engine;
@@ -1,6 +1,18 @@
 use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Write;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write as IoWrite};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
 /// Structured log event with JSON serialization
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -216,6 +228,12 @@ pub struct RequestContext {
     pub user_id: Option<String>,
 }
 
+impl Default for RequestContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RequestContext {
     pub fn new() -> Self {
         RequestContext {
@@ -283,3 +301,483 @@ impl TimingEvent {
     }
 }
 
+
+/// Storage events kept in memory by a mounted pool
+pub const EVENT_RING_CAPACITY: usize = 10_000;
+/// Events of one kind accepted per second; the rest are counted and dropped
+pub const EVENT_RATE_PER_SEC: u32 = 100;
+/// Optional event log in the pool directory, rotated to `events.log.1`
+pub const EVENTS_FILE: &str = "events.log";
+/// Socket in the pool directory that `events` connects to
+pub const EVENTS_SOCKET: &str = "events.sock";
+
+/// What happened to the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A read succeeded with fragments missing
+    DegradedRead,
+    RebuildStarted,
+    RebuildFinished,
+    RebuildFailed,
+    /// A fragment failed its checksum and was quarantined
+    ChecksumFailure,
+    /// A disk changed health state
+    DiskHealth,
+    /// A write or policy change was rejected for lack of space
+    NoSpace,
+    /// A disk's fragment and byte counters were recounted and corrected
+    UsageCorrected,
+    /// A scrub pass finished; `fields` holds its summary
+    ScrubFinished,
+    /// An extent has too few fragments left to decode
+    Unrecoverable,
+    /// Pool usage crossed the configured warning threshold, up or down
+    SpaceThreshold,
+    /// Pool usage crossed its high or critical space watermark, up or down
+    SpaceWatermark,
+    /// A disk's SMART attributes show signs of a failing device
+    SmartWarning,
+    /// A rebuild pass over the whole pool finished
+    RebuildPassFinished,
+    Mounted,
+    Unmounted,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 16] = [
+        EventKind::DegradedRead,
+        EventKind::RebuildStarted,
+        EventKind::RebuildFinished,
+        EventKind::RebuildFailed,
+        EventKind::ChecksumFailure,
+        EventKind::DiskHealth,
+        EventKind::NoSpace,
+        EventKind::UsageCorrected,
+        EventKind::ScrubFinished,
+        EventKind::Unrecoverable,
+        EventKind::SpaceThreshold,
+        EventKind::SpaceWatermark,
+        EventKind::SmartWarning,
+        EventKind::RebuildPassFinished,
+        EventKind::Mounted,
+        EventKind::Unmounted,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::DegradedRead => "degraded_read",
+            EventKind::RebuildStarted => "rebuild_started",
+            EventKind::RebuildFinished => "rebuild_finished",
+            EventKind::RebuildFailed => "rebuild_failed",
+            EventKind::ChecksumFailure => "checksum_failure",
+            EventKind::DiskHealth => "disk_health",
+            EventKind::NoSpace => "no_space",
+            EventKind::UsageCorrected => "usage_corrected",
+            EventKind::ScrubFinished => "scrub_finished",
+            EventKind::Unrecoverable => "unrecoverable",
+            EventKind::SpaceThreshold => "space_threshold",
+            EventKind::SpaceWatermark => "space_watermark",
+            EventKind::SmartWarning => "smart_warning",
+            EventKind::RebuildPassFinished => "rebuild_pass_finished",
+            EventKind::Mounted => "mounted",
+            EventKind::Unmounted => "unmounted",
+        }
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        EventKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = EventKind::ALL.iter().map(|k| k.name()).collect();
+                anyhow!("Unknown event type '{}' (expected one of: {})", s, names.join(", "))
+            })
+    }
+}
+
+/// One entry of the event ring
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageEvent {
+    /// Increases by one per recorded event, starting at 1
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub kind: EventKind,
+    pub extent_uuid: Option<Uuid>,
+    pub disk_uuid: Option<Uuid>,
+    pub message: String,
+    /// Details specific to the kind, such as a scrub summary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<serde_json::Value>,
+}
+
+impl StorageEvent {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("storage events serialize")
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut output = format!("[{}] {}", self.timestamp.format("%Y-%m-%d %H:%M:%S%.3f"), self.kind);
+        if let Some(extent) = self.extent_uuid {
+            write!(output, " extent={}", extent).unwrap();
+        }
+        if let Some(disk) = self.disk_uuid {
+            write!(output, " disk={}", disk).unwrap();
+        }
+        write!(output, ": {}", self.message).unwrap();
+        output
+    }
+}
+
+#[derive(Debug)]
+struct RateWindow {
+    started: Instant,
+    accepted: u32,
+    suppressed: u64,
+}
+
+/// Size-capped append-only copy of the ring
+#[derive(Debug)]
+struct EventFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: u64,
+}
+
+impl EventFile {
+    fn append(&mut self, line: &str) -> std::io::Result<()> {
+        let bytes = line.len() as u64 + 1;
+        if self.len > 0 && self.len + bytes > self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            fs::rename(&self.path, rotated)?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.len = 0;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.len += bytes;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct RingState {
+    events: VecDeque<StorageEvent>,
+    next_seq: u64,
+    rates: HashMap<EventKind, RateWindow>,
+    file: Option<EventFile>,
+}
+
+/// Called with every event a ring accepts, outside its lock
+pub type EventListener = Box<dyn Fn(&StorageEvent) + Send + Sync>;
+
+/// Bounded, rate-limited record of recent storage events
+///
+/// Shared by the engine and its disks; the oldest events are dropped once
+/// `capacity` is reached. Each kind is limited to `rate_per_sec` events per
+/// second so a failing disk cannot flush everything else out; the number
+/// suppressed is noted on the next event of that kind that gets through.
+pub struct EventRing {
+    state: Mutex<RingState>,
+    appended: Condvar,
+    capacity: usize,
+    rate_per_sec: u32,
+    listeners: RwLock<Vec<EventListener>>,
+}
+
+impl std::fmt::Debug for EventRing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventRing")
+            .field("capacity", &self.capacity)
+            .field("rate_per_sec", &self.rate_per_sec)
+            .field("listeners", &self.listeners.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for EventRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventRing {
+    pub fn new() -> Self {
+        Self::with_limits(EVENT_RING_CAPACITY, EVENT_RATE_PER_SEC)
+    }
+
+    pub fn with_limits(capacity: usize, rate_per_sec: u32) -> Self {
+        EventRing {
+            state: Mutex::new(RingState {
+                events: VecDeque::new(),
+                next_seq: 1,
+                rates: HashMap::new(),
+                file: None,
+            }),
+            appended: Condvar::new(),
+            capacity,
+            rate_per_sec,
+            listeners: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Call `listener` with every event accepted from now on
+    ///
+    /// Listeners run on the recording thread, so they must not block.
+    pub fn subscribe(&self, listener: EventListener) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Also append every recorded event to `path` as a JSON line
+    ///
+    /// Once the file would grow past `max_bytes` it is renamed to `<path>.1`,
+    /// replacing the previous one, and a new file is started.
+    pub fn attach_file(&self, path: &Path, max_bytes: u64) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open event log {:?}", path))?;
+        let len = file.metadata()?.len();
+        self.state.lock().unwrap().file = Some(EventFile { path: path.to_path_buf(), file, len, max_bytes });
+        Ok(())
+    }
+
+    /// Record an event; returns false if it was dropped by the rate limit
+    pub fn record(
+        &self,
+        kind: EventKind,
+        extent_uuid: Option<Uuid>,
+        disk_uuid: Option<Uuid>,
+        message: impl Into<String>,
+    ) -> bool {
+        self.record_with_fields(kind, extent_uuid, disk_uuid, message, None)
+    }
+
+    /// Like `record`, with details specific to the kind
+    pub fn record_with_fields(
+        &self,
+        kind: EventKind,
+        extent_uuid: Option<Uuid>,
+        disk_uuid: Option<Uuid>,
+        message: impl Into<String>,
+        fields: Option<serde_json::Value>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let window = state.rates.entry(kind).or_insert(RateWindow { started: now, accepted: 0, suppressed: 0 });
+        if now.duration_since(window.started) >= Duration::from_secs(1) {
+            window.started = now;
+            window.accepted = 0;
+        }
+        if window.accepted >= self.rate_per_sec {
+            window.suppressed += 1;
+            return false;
+        }
+        window.accepted += 1;
+        let mut message = message.into();
+        if window.suppressed > 0 {
+            write!(message, " ({} similar events suppressed)", window.suppressed).unwrap();
+            window.suppressed = 0;
+        }
+
+        let event = StorageEvent {
+            seq: state.next_seq,
+            timestamp: Utc::now(),
+            kind,
+            extent_uuid,
+            disk_uuid,
+            message,
+            fields,
+        };
+        state.next_seq += 1;
+        if let Some(file) = &mut state.file {
+            if let Err(e) = file.append(&event.to_json()) {
+                log::warn!("Failed to write event log {:?}: {}", file.path, e);
+            }
+        }
+        if state.events.len() >= self.capacity {
+            state.events.pop_front();
+        }
+        let listeners = self.listeners.read().unwrap();
+        let notified = (!listeners.is_empty()).then(|| event.clone());
+        state.events.push_back(event);
+        drop(state);
+        self.appended.notify_all();
+        if let Some(event) = notified {
+            for listener in listeners.iter() {
+                listener(&event);
+            }
+        }
+        true
+    }
+
+    /// Events still held with a sequence number after `after` (0 for all)
+    pub fn since(&self, after: u64) -> Vec<StorageEvent> {
+        let state = self.state.lock().unwrap();
+        state.events.iter().filter(|e| e.seq > after).cloned().collect()
+    }
+
+    /// Like `since`, but waits up to `timeout` for a new event if there is none yet
+    pub fn wait_since(&self, after: u64, timeout: Duration) -> Vec<StorageEvent> {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self
+            .appended
+            .wait_timeout_while(state, timeout, |s| s.next_seq <= after + 1)
+            .unwrap();
+        state.events.iter().filter(|e| e.seq > after).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What an `events` client asks the mounted process for, sent as one JSON line
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventQuery {
+    /// Keep the connection open and stream new events as they are recorded
+    pub follow: bool,
+    /// Only events of this kind
+    pub kind: Option<EventKind>,
+}
+
+impl EventQuery {
+    pub fn matches(&self, event: &StorageEvent) -> bool {
+        self.kind.is_none_or(|kind| kind == event.kind)
+    }
+}
+
+/// Serves the event ring on a unix socket for the `events` command
+///
+/// Replies are JSON lines, one event each. Stops, and removes the socket,
+/// when dropped.
+pub struct EventServer {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl EventServer {
+    /// Bind `path`, replacing a socket left behind by an earlier mount
+    pub fn start(path: &Path, ring: Arc<EventRing>) -> Result<Self> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow!("Another process is serving events on {:?}", path));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind to {:?}", path))?;
+        // Polled so the thread notices `stop`
+        listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || serve_events(listener, ring, stop))
+        };
+        Ok(EventServer { path: path.to_path_buf(), stop, thread: Some(thread) })
+    }
+
+    /// Stop accepting connections and remove the socket
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for EventServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+fn serve_events(listener: UnixListener, ring: Arc<EventRing>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let ring = Arc::clone(&ring);
+                let stop = Arc::clone(&stop);
+                // Followers hold their connection open, so each gets a thread
+                thread::spawn(move || {
+                    if let Err(e) = handle_event_client(&ring, stream, &stop) {
+                        log::debug!("Event client disconnected: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(e) => log::error!("Event connection failed: {}", e),
+        }
+    }
+}
+
+fn handle_event_client(ring: &EventRing, stream: UnixStream, stop: &AtomicBool) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let query: EventQuery = serde_json::from_str(request.trim())?;
+
+    let mut writer = &stream;
+    let mut last = 0;
+    let mut events = ring.since(0);
+    loop {
+        for event in &events {
+            last = event.seq;
+            if query.matches(event) {
+                writeln!(writer, "{}", event.to_json())?;
+            }
+        }
+        if !query.follow || stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        events = ring.wait_since(last, Duration::from_millis(500));
+    }
+}
+
+/// Ask a mounted pool's event server for events; yields one event per line
+pub fn request_events(socket: &Path, query: &EventQuery) -> Result<impl Iterator<Item = Result<StorageEvent>>> {
+    let mut stream = UnixStream::connect(socket).with_context(|| format!("Failed to connect to {:?}", socket))?;
+    writeln!(stream, "{}", serde_json::to_string(query)?)?;
+    Ok(BufReader::new(stream).lines().map(|line| Ok(serde_json::from_str(&line?)?)))
+}
+
+/// Events in the pool's rotated event logs, oldest first
+pub fn read_event_log(pool_dir: &Path) -> Result<Vec<StorageEvent>> {
+    let current = pool_dir.join(EVENTS_FILE);
+    let mut rotated = current.clone().into_os_string();
+    rotated.push(".1");
+    let mut events = Vec::new();
+    for path in [PathBuf::from(rotated), current] {
+        if !path.exists() {
+            continue;
+        }
+        let file = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(event) => events.push(event),
+                // A line cut short by a crash
+                Err(e) => log::warn!("Skipping unreadable line in {:?}: {}", path, e),
+            }
+        }
+    }
+    Ok(events)
+}
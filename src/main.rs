@@ -1,8 +1,20 @@
+mod activity;
 mod cli;
 mod config;
+mod control;
 mod crash_sim;
 mod diagnostics;
+mod error;
+mod export;
+mod file_name;
+mod manifest;
+mod ingest;
+mod smart;
+mod compression;
 mod disk;
+mod encryption;
+mod format_version;
+mod access_tracker;
 mod allocator;
 mod on_device_allocator;
 mod free_extent;
@@ -14,29 +26,54 @@ mod trim;
 mod reclamation;
 mod io_alignment;
 mod extent;
+mod extent_totals;
+mod inode_cache;
+mod inode_totals;
 #[cfg(not(target_os = "windows"))]
 mod fuse_impl;
 #[cfg(target_os = "windows")]
 mod windows_fs;
+#[cfg(target_os = "windows")]
+mod winfsp_sys;
 #[cfg(target_os = "macos")]
 mod macos;
+mod fsck;
 mod gc;
+mod periodic;
 mod hmm_classifier;
 mod json_output;
 mod logging;
 mod metadata;
 mod metadata_tx;
+mod metadata_store;
 mod metrics;
+mod quota;
+mod layout;
+mod usage;
 mod monitoring;
+mod notify;
+mod daemon;
+mod pool_lock;
 mod storage_engine;
 #[cfg(test)]
+#[path = "../tests/unit/phase_1_3_tests.rs"]
 mod phase_1_3_tests;
 
 // Test helpers (timeouts, small utilities) used only by tests
 #[cfg(test)]
+#[path = "../tests/unit/test_utils.rs"]
 mod test_utils;
 mod perf;
 mod placement;
+mod rebalance;
+mod rebuild;
+mod replace_disk;
+mod rebuild_budget;
+mod rebuild_queue;
+mod recent_writes;
+mod prefetch_queue;
+mod policy_change;
+mod policy_engine;
 mod redundancy;
 pub mod scheduler;
 mod scrubber;
@@ -48,18 +85,26 @@ mod snapshots;
 mod tiering;
 mod backup_evolution;
 mod security;
+mod permissions;
 
 // Phase 9.1: Cross-Platform Storage Abstraction modules
 mod fs_interface;
+#[cfg(test)]
+mod memory_fs;
 mod path_utils;
 mod mount;
 
 // Phase 10: Mixed Storage Speed Optimization
 mod data_cache;
+mod buffer_pool;
 
 // Phase 14: Multi-Level Caching Optimization
 mod multi_level_cache;
 
+// Phase 11 Alternative: FUSE Performance Optimization
+#[cfg(not(target_os = "windows"))]
+mod fuse_optimizations;
+
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use std::fs;
@@ -67,13 +112,15 @@ use std::io::{Read, Seek, Write};
 use std::path::Path;
 use std::sync::Arc;
 
-use cli::{Cli, Commands, ScrubDaemonAction};
-use disk::{Disk, DiskPool};
+use cli::{Cli, Commands, ConfigAction, ManifestAction, PolicyAction, QuotaAction, ScrubDaemonAction, SnapshotAction};
+use disk::{Disk, DiskPool, NewDiskOverrides};
 use extent::RedundancyPolicy;
 use metadata::MetadataManager;
+use placement::DegradedWrites;
 use metrics::Metrics;
 use storage::StorageEngine;
 use scrub_daemon::{ScrubDaemon, ScrubSchedule, ScrubIntensity};
+use write_optimizer::WriteBufferConfig;
 
 fn main() -> Result<()> {
     env_logger::Builder::from_default_env()
@@ -84,39 +131,265 @@ fn main() -> Result<()> {
     let json_output = cli.json;
     // Set a global override for direct I/O preference; commands and modules can read this env var
     std::env::set_var("DYNAMICFS_DIRECT_IO", &cli.direct_io);
+    // Encrypted pools are unlocked from these when the pool config is loaded
+    if let Some(key_file) = &cli.key_file {
+        std::env::set_var(encryption::KEY_FILE_ENV, key_file);
+    }
+    if let Some(passphrase_file) = &cli.passphrase_file {
+        std::env::set_var(encryption::PASSPHRASE_FILE_ENV, passphrase_file);
+    }
     
+    // Held until the command returns
+    let _pool_lock = offline_mutation(&cli.command)
+        .map(|(pool, command, mounted)| lock_pool(pool, command, None, mounted))
+        .transpose()?;
+
     match cli.command {
-        Commands::Init { pool } => cmd_init(&pool, json_output),
-        Commands::AddDisk { pool, disk, device, force } => cmd_add_disk(&pool, &disk, device, force, json_output),
+        Commands::Init { pool, encrypt, compression, verify_writes, case_insensitive, extent_size_kb, metadata_backend } => {
+            let options = InitOptions {
+                encrypt,
+                compression,
+                verify_writes,
+                case_insensitive,
+                extent_size_kb,
+                metadata_backend,
+            };
+            cmd_init(&pool, &options, json_output)
+        }
+        Commands::SetCompression { pool, algorithm } => cmd_set_compression(&pool, &algorithm, json_output),
+        Commands::SetVerifyWrites { pool, enabled } => cmd_set_verify_writes(&pool, enabled, json_output),
+        Commands::SetSpaceReserve { pool, percent } => cmd_set_space_reserve(&pool, percent, json_output),
+        Commands::SetRebuildLimit {
+            pool,
+            mbps,
+            concurrent,
+            idle_mbps,
+            idle_concurrent,
+            idle_after_secs,
+            disk_queue_depth,
+            io_class,
+            io_concurrent,
+            io_weight,
+        } => {
+            let change = RebuildLimitChange {
+                mbps,
+                concurrent,
+                idle_mbps,
+                idle_concurrent,
+                idle_after_secs,
+                disk_queue_depth,
+                io_class,
+                io_concurrent,
+                io_weight,
+            };
+            cmd_set_rebuild_limit(&pool, change, json_output)
+        }
+        Commands::AddDisk { pool, disk, device, force, force_takeover, rebuild, max_bytes_per_sec, tier } => {
+            cmd_add_disk(&pool, &disk, device, force, force_takeover, tier, json_output)?;
+            if rebuild {
+                println!();
+                cmd_rebuild(&pool, max_bytes_per_sec, json_output)?;
+            }
+            Ok(())
+        }
         Commands::RemoveDisk { pool, disk } => cmd_remove_disk(&pool, &disk, json_output),
+        Commands::ReplaceDisk { pool, old, new, device, force, max_bytes_per_sec } => {
+            cmd_replace_disk(&pool, old, &new, device, force, max_bytes_per_sec, json_output)
+        }
         Commands::ListDisks { pool } => cmd_list_disks(&pool, json_output),
         Commands::ListExtents { pool } => cmd_list_extents(&pool, json_output),
         Commands::ShowRedundancy { pool } => cmd_show_redundancy(&pool, json_output),
         Commands::FailDisk { pool, disk } => cmd_fail_disk(&pool, &disk, json_output),
         Commands::SetDiskHealth { pool, disk, health } => cmd_set_disk_health(&pool, &disk, &health, json_output),
         Commands::ChangePolicy { pool, policy } => cmd_change_policy(&pool, &policy, json_output),
-        Commands::PolicyStatus { pool } => cmd_policy_status(&pool, json_output),
-        Commands::ListHot { pool } => cmd_list_hot(&pool, json_output),
-        Commands::ListCold { pool } => cmd_list_cold(&pool, json_output),
+        Commands::PolicyStatus { pool, watch, interval } => cmd_policy_status(&pool, watch, interval, json_output),
+        Commands::CancelPolicyChange { pool, path, ino } => {
+            cmd_cancel_policy_change(&pool, path.as_deref(), ino, json_output)
+        }
+        Commands::ListHot { pool, limit } => cmd_list_hot(&pool, limit, json_output),
+        Commands::ListCold { pool, limit } => cmd_list_cold(&pool, limit, json_output),
+        Commands::TierStatus { pool } => cmd_tier_status(&pool, json_output),
         Commands::ExtentStats { pool, extent } => cmd_extent_stats(&pool, &extent, json_output),
         Commands::DetectOrphans { pool } => cmd_detect_orphans(&pool, json_output),
         Commands::CleanupOrphans { pool, min_age_hours, dry_run } => {
             cmd_cleanup_orphans(&pool, min_age_hours, dry_run, json_output)
         }
         Commands::OrphanStats { pool } => cmd_orphan_stats(&pool, json_output),
-        Commands::ProbeDisks { pool } => cmd_probe_disks(&pool, json_output),
-        Commands::Scrub { pool, repair } => cmd_scrub(&pool, repair, json_output),
+        Commands::ProbeDisks { pool, recount } => cmd_probe_disks(&pool, recount, json_output),
+        Commands::Scrub { pool, repair, intensity, workers, max_bytes_per_sec } => {
+            let mut config = scrubber::ScrubConfig::for_intensity(parse_intensity(&intensity)?);
+            config.repair = repair;
+            if let Some(workers) = workers {
+                config.workers = workers.max(1);
+            }
+            if max_bytes_per_sec.is_some() {
+                config.max_bytes_per_sec = max_bytes_per_sec;
+            }
+            cmd_scrub(&pool, &config, json_output)
+        }
+        Commands::Rebalance { pool, target_spread, max_bytes_per_sec, dry_run } => {
+            cmd_rebalance(&pool, target_spread, max_bytes_per_sec, dry_run, json_output)
+        }
+        Commands::Rebuild { pool, max_bytes_per_sec } => cmd_rebuild(&pool, max_bytes_per_sec, json_output),
+        Commands::CheckDirindex { pool, repair } => cmd_check_dirindex(&pool, repair, json_output),
+        Commands::Check { pool, repair, force } => cmd_check(&pool, repair, force, json_output),
+        Commands::Config { action } => cmd_config(action, json_output),
+        Commands::Policy { action } => cmd_policy(action, json_output),
+        Commands::Quota { action } => cmd_quota(action, json_output),
+        Commands::FileLayout { pool, ino } => cmd_file_layout(&pool, ino, json_output),
+        Commands::VerifyFile { pool, path, ino, repair } => {
+            cmd_verify_file(&pool, path.as_deref(), ino, repair, json_output)
+        }
+        Commands::Du { pool, path, depth } => cmd_du(&pool, &path, depth, json_output),
+        Commands::Export { pool, output, since, snapshot, compress } => {
+            cmd_export(&pool, &output, since.as_deref(), snapshot, compress, json_output)
+        }
+        Commands::Manifest { action } => cmd_manifest(action, json_output),
+        Commands::ChangedFiles { pool, since_generation, since_snapshot } => {
+            cmd_changed_files(&pool, since_generation, since_snapshot.as_deref(), json_output)
+        }
+        Commands::MigrateMetadata { pool, to } => cmd_migrate_metadata(&pool, &to, json_output),
+        Commands::Import { pool, input, prefix } => cmd_import(&pool, &input, &prefix, json_output),
+        Commands::Ingest { pool, source, dest, threads, verify, dry_run } => {
+            cmd_ingest(&pool, &source, &dest, threads, verify, dry_run, json_output)
+        }
         Commands::ScrubDaemon { action } => cmd_scrub_daemon(action, json_output),
+        Commands::Snapshot { action } => cmd_snapshot(action, json_output),
         Commands::ScrubSchedule { pool, frequency, intensity, dry_run, auto_repair } => {
             cmd_scrub_schedule(&pool, &frequency, &intensity, dry_run, auto_repair, json_output)
         }
         Commands::MetricsServer { pool, port, bind } => cmd_metrics_server(&pool, port, &bind, json_output),
         Commands::Status { pool } => cmd_status(&pool, json_output),
         Commands::Metrics { pool } => cmd_metrics(&pool, json_output),
-        Commands::Mount { pool, mountpoint } => cmd_mount(&pool, &mountpoint, json_output),
-        Commands::Benchmark { pool, file_size, operations } => cmd_benchmark(&pool, file_size, operations, json_output),
+        Commands::Mount {
+            pool,
+            mountpoint,
+            write_buffer_mb,
+            write_flush_secs,
+            read_only,
+            no_allow_other,
+            noatime,
+            relatime,
+            options,
+            metrics_port,
+            metrics_bind,
+            metrics_refresh_secs,
+            orphan_gc_interval_secs,
+            orphan_gc_min_age_hours,
+            disk_probe_secs,
+            access_stats_flush_secs,
+            tiering_interval_secs,
+            events_log_mb,
+            daemonize,
+        } => {
+            // Flags override the pool config
+            let config = crate::config::PoolConfig::load(&pool)?;
+            let write_buffer_bytes = write_buffer_mb.map_or(config.write_buffer_bytes, |mb| mb * 1024 * 1024);
+            let write_flush_secs = write_flush_secs.unwrap_or(config.write_flush_secs);
+            let orphan_gc_interval_secs = orphan_gc_interval_secs.unwrap_or(config.orphan_gc_interval_secs);
+            let orphan_gc_min_age_hours = orphan_gc_min_age_hours.unwrap_or(config.orphan_gc_min_age_hours);
+            let disk_probe_secs = disk_probe_secs.unwrap_or(config.disk_probe_secs);
+            let access_stats_flush_secs = access_stats_flush_secs.unwrap_or(config.access_stats_flush_secs);
+            let tiering_interval_secs = tiering_interval_secs.unwrap_or(config.tiering_interval_secs);
+            let smart_poll_secs = if config.smart_enabled { config.smart_poll_secs } else { 0 };
+            let background = MountBackground {
+                write_buffer: WriteBufferConfig {
+                    memory_budget: write_buffer_bytes as usize,
+                    flush_interval: std::time::Duration::from_secs(write_flush_secs),
+                    ..Default::default()
+                },
+                orphan_gc: (orphan_gc_interval_secs > 0).then(|| gc::OrphanGcConfig {
+                    interval: std::time::Duration::from_secs(orphan_gc_interval_secs),
+                    min_age_seconds: orphan_gc_min_age_hours * 3600,
+                }),
+                disk_probe: (disk_probe_secs > 0).then(|| std::time::Duration::from_secs(disk_probe_secs)),
+                smart_poll: (smart_poll_secs > 0).then(|| std::time::Duration::from_secs(smart_poll_secs)),
+                access_stats_flush: (access_stats_flush_secs > 0)
+                    .then(|| std::time::Duration::from_secs(access_stats_flush_secs)),
+                tiering: (tiering_interval_secs > 0).then(|| tiering::TierPassConfig {
+                    interval: std::time::Duration::from_secs(tiering_interval_secs),
+                    ..Default::default()
+                }),
+                events_log_bytes: events_log_mb.map(|mb| mb * 1024 * 1024),
+                access_model: config.access_model,
+                space_warn_percent: config.notifications.space_warn_percent,
+                inode_cache: inode_cache::InodeCacheLimits {
+                    capacity: config.inode_cache_capacity as usize,
+                    ttl: std::time::Duration::from_secs(config.inode_cache_ttl_secs),
+                },
+                read_buffer_pool_bytes: config.read_buffer_pool_bytes,
+                recent_writes: (config.recent_write_settle_secs > 0).then(|| recent_writes::RecentWriteConfig {
+                    settle: std::time::Duration::from_secs(config.recent_write_settle_secs),
+                    ..Default::default()
+                }),
+                recent_write_capacity: config.recent_write_capacity as usize,
+            };
+            // The config, then the flags, so a later -o can still override them
+            let configured = config.atime != crate::access_tracker::AtimeMode::default();
+            let atime_flags = [(configured, config.atime.mount_option()), (noatime, "noatime"), (relatime, "relatime")];
+            // Named after the pool, so `unmount --mountpoint` finds it in the mount table
+            let source = fs::canonicalize(&pool)
+                .map(|path| path.to_string_lossy().into_owned())
+                .ok()
+                .filter(|path| !path.contains(','))
+                .map(|path| format!("fsname={},subtype=dynamicfs", path));
+            let options = source
+                .into_iter()
+                .chain(atime_flags.into_iter().filter(|(set, _)| *set).map(|(_, option)| option.to_string()))
+                .chain(options)
+                .collect();
+            let settings = crate::mount::MountSettings {
+                read_only,
+                allow_other: !no_allow_other,
+                options,
+                case_insensitive: crate::disk::DiskPool::is_case_insensitive(&pool),
+                attr_ttl: std::time::Duration::from_secs(config.inode_cache_ttl_secs),
+            };
+            let metrics_addr = metrics_port.map(|port| format!("{}:{}", metrics_bind, port));
+            let metrics_refresh = std::time::Duration::from_secs(metrics_refresh_secs);
+            // Before the mount starts any thread; only the child returns
+            let readiness = if daemonize { Some(daemon::daemonize(&pool)?) } else { None };
+            let result = cmd_mount(
+                &pool,
+                &mountpoint,
+                background,
+                &settings,
+                metrics_addr.as_deref(),
+                metrics_refresh,
+                readiness.as_ref(),
+            );
+            if let (Err(e), Some(readiness)) = (&result, &readiness) {
+                readiness.failed(e);
+            }
+            result
+        }
+        Commands::Unmount { pool, mountpoint, timeout_secs } => {
+            let pool = match (pool, mountpoint) {
+                (Some(pool), _) => pool,
+                (None, Some(mountpoint)) => daemon::pool_mounted_at(&mountpoint)?,
+                (None, None) => unreachable!("clap requires --pool or --mountpoint"),
+            };
+            cmd_unmount(&pool, std::time::Duration::from_secs(timeout_secs), json_output)
+        }
+        Commands::Events { pool, follow, kind } => cmd_events(&pool, follow, kind.as_deref(), json_output),
+        Commands::Top { pool, interval, top, count } => cmd_top(&pool, interval, top, count, json_output),
+        Commands::Benchmark { pool, file_size, operations, files, mode, read_ratio, threads, pattern, seed, keep } => {
+            let workload = perf::Workload {
+                mode: mode.parse()?,
+                pattern: pattern.parse()?,
+                read_ratio,
+                file_size,
+                operations,
+                threads: threads.max(1),
+                seed: seed.unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)),
+            };
+            cmd_benchmark(&pool, &workload, files, keep, json_output)
+        }
         Commands::DefragAnalyze { pool } => cmd_defrag_analyze(&pool, json_output),
-        Commands::DefragStart { pool, intensity } => cmd_defrag_start(&pool, &intensity, json_output),
+        Commands::DefragStart { pool, intensity, window, clear_window, threshold, coalesce_min_extents } => {
+            let change = DefragChange { intensity, window, clear_window, threshold, coalesce_min_extents };
+            cmd_defrag_start(&pool, change, json_output)
+        }
         Commands::DefragStop { pool } => cmd_defrag_stop(&pool, json_output),
         Commands::DefragStatus { pool } => cmd_defrag_status(&pool, json_output),
         Commands::TrimNow { pool, disk } => cmd_trim_now(&pool, disk, json_output),
@@ -128,11 +401,60 @@ fn main() -> Result<()> {
     }
 }
 
-fn cmd_probe_disks(pool_dir: &Path, _json_output: bool) -> Result<()> {
+/// What a mounted pool offers instead of an offline mutation
+const UNMOUNT_FIRST: &str = "unmount it first";
+
+/// Pool, name and mounted-pool alternative of commands that rewrite metadata
+/// or fragments offline, which must not run beside a mount or each other
+fn offline_mutation(command: &Commands) -> Option<(&Path, &'static str, &'static str)> {
+    match command {
+        Commands::AddDisk { pool, rebuild: true, .. } | Commands::Rebuild { pool, .. } => {
+            Some((pool, "rebuild", "the mount rebuilds degraded extents itself; `health` shows its progress"))
+        }
+        Commands::RemoveDisk { pool, .. } => Some((pool, "remove-disk", UNMOUNT_FIRST)),
+        Commands::ReplaceDisk { pool, .. } => Some((pool, "replace-disk", UNMOUNT_FIRST)),
+        Commands::ChangePolicy { pool, .. } => {
+            Some((pool, "change-policy", "`policy run` applies policies through the mount"))
+        }
+        Commands::CleanupOrphans { pool, dry_run: false, .. } => Some((
+            pool,
+            "cleanup-orphans",
+            "the mount deletes orphans itself every orphan_gc_interval_secs, or unmount it to clean up now",
+        )),
+        Commands::Scrub { pool, repair: true, .. } => Some((pool, "scrub --repair", UNMOUNT_FIRST)),
+        Commands::Rebalance { pool, dry_run: false, .. } => Some((pool, "rebalance", UNMOUNT_FIRST)),
+        Commands::CheckDirindex { pool, repair: true } => Some((pool, "check-dirindex --repair", UNMOUNT_FIRST)),
+        Commands::Check { pool, repair: true, .. } => Some((pool, "check --repair", UNMOUNT_FIRST)),
+        Commands::VerifyFile { pool, repair: true, .. } => Some((pool, "verify-file --repair", UNMOUNT_FIRST)),
+        Commands::MigrateMetadata { pool, .. } => Some((pool, "migrate-metadata", UNMOUNT_FIRST)),
+        Commands::Import { pool, .. } => {
+            Some((pool, "import", "copy the files in through the mountpoint, or unmount it first"))
+        }
+        Commands::Ingest { pool, dry_run: false, .. } => {
+            Some((pool, "ingest", "copy the files in through the mountpoint, or unmount it first"))
+        }
+        Commands::Benchmark { pool, .. } => Some((pool, "benchmark", UNMOUNT_FIRST)),
+        Commands::Recover { pool, .. } => Some((pool, "recover", UNMOUNT_FIRST)),
+        _ => None,
+    }
+}
+
+/// Take the pool lock for `command`; if a mount holds it, the error says
+/// what to do instead
+fn lock_pool(pool_dir: &Path, command: &str, mountpoint: Option<&Path>, mounted: &str) -> Result<pool_lock::PoolLock> {
+    pool_lock::PoolLock::exclusive(pool_dir, command, mountpoint).map_err(|e| match e.downcast::<pool_lock::PoolBusy>() {
+        Ok(busy) if busy.is_mount() => anyhow!("{}; {}", busy, mounted),
+        Ok(busy) => anyhow!("{}; try again once it has finished", busy),
+        Err(e) => e,
+    })
+}
+
+fn cmd_probe_disks(pool_dir: &Path, recount: bool, _json_output: bool) -> Result<()> {
     println!("Probing disks in pool {:?}", pool_dir);
 
     let pool = DiskPool::load(pool_dir)?;
     let disk_paths = pool.disk_paths.clone();
+    let smart = config::PoolConfig::load(pool_dir)?.smart_enabled;
 
     for path in disk_paths {
         match Disk::load(&path) {
@@ -140,11 +462,35 @@ fn cmd_probe_disks(pool_dir: &Path, _json_output: bool) -> Result<()> {
                 if path.exists() {
                     if disk.health == disk::DiskHealth::Failed {
                         disk.health = disk::DiskHealth::Healthy;
+                        disk.clear_io_errors();
                         disk.save()?;
                         println!("  Disk {} is reachable again: Failed -> Healthy", disk.uuid);
                     } else {
                         println!("  Disk {} is reachable: {:?}", disk.uuid, disk.health);
                     }
+                    if smart && disk.kind == disk::DiskKind::BlockDevice {
+                        match smart::read_sample(&disk.path) {
+                            Ok(sample) => {
+                                println!("    SMART: {}", sample.summary());
+                                for warning in disk.record_smart(sample)? {
+                                    println!("    ⚠ {}", warning);
+                                }
+                            }
+                            Err(e) => println!("    SMART: no sample ({:#})", e),
+                        }
+                    }
+                    if recount {
+                        match disk.recount_usage()? {
+                            Some(correction) => println!(
+                                "    Usage corrected: {} fragments / {} bytes -> {} fragments / {} bytes",
+                                correction.before.fragments,
+                                correction.before.bytes,
+                                correction.after.fragments,
+                                correction.after.bytes
+                            ),
+                            None => println!("    Usage verified: {} fragments / {} bytes", disk.fragment_count, disk.used_bytes),
+                        }
+                    }
                 } else {
                     if disk.health != disk::DiskHealth::Failed {
                         disk.mark_failed()?;
@@ -163,55 +509,377 @@ fn cmd_probe_disks(pool_dir: &Path, _json_output: bool) -> Result<()> {
     Ok(())
 }
 
-fn cmd_scrub(pool_dir: &Path, repair: bool, _json_output: bool) -> Result<()> {
-    println!("Scrubbing all extents in pool {:?}", pool_dir);
-    if repair {
-        println!("Repair mode: ENABLED - will attempt to fix detected issues");
+fn cmd_check_dirindex(pool_dir: &Path, repair: bool, json_output: bool) -> Result<()> {
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let report = metadata.check_dir_index(repair)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("Directory index check for pool {:?}", pool_dir);
+    println!();
+    println!("  Entries expected: {}", report.expected_entries);
+    println!("  Missing:          {}", report.missing.len());
+    println!("  Stale:            {}", report.stale.len());
+    println!();
+
+    for entry in &report.missing {
+        println!("  missing: {}/{:?} -> {}", entry.parent_ino, entry.name, entry.ino);
+    }
+    for entry in &report.stale {
+        println!("  stale:   {}/{:?} -> {}", entry.parent_ino, entry.name, entry.ino);
+    }
+
+    if report.is_consistent() {
+        println!("✓ Directory index matches inode records");
+    } else if report.repaired {
+        println!("✓ Directory index rebuilt from inode records");
+    } else {
+        println!("Use --repair to rebuild the index from inode records");
+    }
+
+    Ok(())
+}
+
+fn cmd_check(pool_dir: &Path, repair: bool, force: bool, json_output: bool) -> Result<()> {
+    let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
+    let mut disks = pool.load_disks()?;
+    let unavailable = pool.disk_paths.len() - disks.len();
+    let options = fsck::CheckOptions { repair, force };
+    let report = fsck::check_pool(pool_dir.to_path_buf(), &mut disks, unavailable, options)?;
+    let unresolved = report.unresolved_errors();
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("Consistency check for pool {:?}", pool_dir);
+        if unavailable > 0 {
+            println!("⚠ {} disks could not be loaded; their fragments count as missing", unavailable);
+        }
+        println!();
+        println!("  Inodes:      {}", report.inodes_checked);
+        println!("  Extent maps: {}", report.extent_maps_checked);
+        println!("  Extents:     {}", report.extents_checked);
+        println!();
+
+        for (kind, findings) in report.by_kind() {
+            let severity = if kind.is_error() { "error" } else { "warning" };
+            println!("{:?} ({}, {}):", kind, severity, findings.len());
+            for finding in findings {
+                let status = match &finding.status {
+                    fsck::FindingStatus::Open => String::new(),
+                    fsck::FindingStatus::Repaired => " [repaired]".to_string(),
+                    fsck::FindingStatus::Refused(reason) => format!(" [not repaired: {}]", reason),
+                };
+                println!("  - {}: {}{}", finding.subject, finding.detail, status);
+            }
+        }
+
+        if report.findings.is_empty() {
+            println!("✓ No inconsistencies found");
+        } else if unresolved == 0 {
+            println!();
+            println!("✓ No unresolved errors");
+        } else if !repair {
+            println!();
+            println!("Use `check --pool {} --repair` to fix the safe findings", pool_dir.display());
+        }
+    }
+
+    if unresolved > 0 {
+        return Err(anyhow!("{} consistency errors remain", unresolved));
+    }
+    Ok(())
+}
+
+/// Set by SIGINT so a rebalance or rebuild stops after the fragment or extent it is working on
+static STOP_REQUESTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP_REQUESTED.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+fn cmd_rebalance(
+    pool_dir: &Path,
+    target_spread: f64,
+    max_bytes_per_sec: Option<u64>,
+    dry_run: bool,
+    json_output: bool,
+) -> Result<()> {
+    use crate::rebalance::{RebalanceConfig, Rebalancer};
+
+    let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
+    let mut disks = pool.load_disks()?;
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let config = RebalanceConfig { target_spread, max_bytes_per_sec, dry_run };
+
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    let rebalancer = Rebalancer::new(pool_dir.to_path_buf());
+    let report = rebalancer.run(&metadata, &mut disks, &config, &STOP_REQUESTED)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if report.dry_run {
+        println!("Rebalance plan for pool {:?} (dry run)", pool_dir);
+    } else {
+        println!("Rebalance of pool {:?}", pool_dir);
+    }
+    if report.resumed {
+        println!("  Resumed an interrupted rebalance");
+    }
+    println!();
+    println!("  Spread before: {:.1}%", report.spread_before);
+    println!("  Spread after:  {:.1}%", report.spread_after);
+    println!("  Fragments:     {}", report.moves.len());
+    println!();
+
+    for disk in report.per_disk.iter().filter(|d| d.bytes_in > 0 || d.bytes_out > 0) {
+        println!(
+            "  {} ({:?}): -{} KB / +{} KB, {:.1}% -> {:.1}%",
+            disk.disk_uuid,
+            disk.path,
+            disk.bytes_out / 1024,
+            disk.bytes_in / 1024,
+            disk.utilization_before,
+            disk.utilization_after
+        );
+    }
+
+    if report.skipped > 0 {
+        println!();
+        println!("⚠ {} planned moves skipped; see the log for details", report.skipped);
     }
     println!();
+    if report.dry_run {
+        println!("Run without --dry-run to move the fragments");
+    } else if report.interrupted {
+        println!("Rebalance interrupted; run it again to resume");
+    } else if report.spread_after < target_spread {
+        println!("✓ Disk utilization is within {:.1} percentage points", target_spread);
+    } else {
+        println!("No further moves can narrow the spread");
+    }
+
+    Ok(())
+}
+
+fn cmd_rebuild(pool_dir: &Path, max_bytes_per_sec: Option<u64>, json_output: bool) -> Result<()> {
+    use crate::rebuild::{RebuildPassProgress, Rebuilder};
 
     let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
     let mut disks = pool.load_disks()?;
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let (events, notifier) = offline_notifications(pool_dir, &mut disks);
 
-    let scrubber = scrubber::Scrubber::new(pool_dir.to_path_buf());
-    let placement = placement::PlacementEngine;
-
-    let mut results = Vec::new();
-    let extents = metadata.list_all_extents()?;
-
-    for mut extent in extents {
-        let fragments_result = {
-            let mut fragments = vec![None; extent.redundancy.fragment_count()];
-            for location in &extent.fragment_locations {
-                if let Some(disk) = disks.iter().find(|d| d.uuid == location.disk_uuid) {
-                    if let Ok(data) = disk.read_fragment(&extent.uuid, location.fragment_index) {
-                        fragments[location.fragment_index] = Some(data);
-                    }
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    if !json_output {
+        println!("Rebuilding degraded extents in pool {:?}", pool_dir);
+        match max_bytes_per_sec {
+            Some(limit) => println!("I/O budget: {} MB/s", limit / 1024 / 1024),
+            None => println!("I/O budget: unlimited"),
+        }
+        println!();
+    }
+
+    let rebuilder = Rebuilder::new(pool_dir.to_path_buf());
+    let progress = RebuildPassProgress::default();
+    let done = std::sync::atomic::AtomicBool::new(false);
+    let report = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut last_report = std::time::Instant::now();
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                if !json_output && last_report.elapsed() >= std::time::Duration::from_secs(10) {
+                    println!("  Progress: {}", progress);
+                    last_report = std::time::Instant::now();
                 }
             }
-            fragments
-        };
+        });
+        let report = rebuilder.run(&metadata, &mut disks, max_bytes_per_sec, &progress, &STOP_REQUESTED);
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        report
+    })?;
+    if let Some(notifier) = notifier {
+        for extent_uuid in &report.unrecoverable {
+            events.record(logging::EventKind::Unrecoverable, Some(*extent_uuid), None, "found by rebuild");
+        }
+        let summary = serde_json::json!({
+            "scanned": report.scanned,
+            "rebuilt": report.rebuilt.len(),
+            "failed": report.failed.len(),
+            "unrecoverable": report.unrecoverable.len(),
+            "bytes_written": report.bytes_written,
+            "interrupted": report.interrupted,
+        });
+        let message = format!(
+            "Rebuild {}: {} rebuilt, {} failed, {} unrecoverable",
+            if report.interrupted { "interrupted" } else { "finished" },
+            report.rebuilt.len(),
+            report.failed.len(),
+            report.unrecoverable.len()
+        );
+        events.record_with_fields(logging::EventKind::RebuildPassFinished, None, None, message, Some(summary));
+        finish_notifications(notifier);
+    }
 
-        let result = if repair {
-            match scrubber.repair_extent(&mut extent, &metadata, &mut disks, &placement, &fragments_result) {
-                Ok(r) => r,
-                Err(e) => {
-                    log::error!("Error repairing extent {}: {}", extent.uuid, e);
-                    scrubber.verify_extent(&extent, &metadata, &disks)?
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if report.resumed {
+        println!("  Resumed an interrupted rebuild");
+    }
+    println!("  Scanned:       {}", report.scanned);
+    println!("  Rebuilt:       {}", report.rebuilt.len());
+    println!("  Written:       {:.1} MB", report.bytes_written as f64 / 1024.0 / 1024.0);
+    println!("  Failed:        {}", report.failed.len());
+    println!("  Unrecoverable: {}", report.unrecoverable.len());
+    for failure in &report.failed {
+        println!("    - {}: {}", failure.extent_uuid, failure.error);
+    }
+    for extent_uuid in &report.unrecoverable {
+        println!("    - {}: too few fragments to decode", extent_uuid);
+    }
+    println!();
+
+    if report.interrupted {
+        println!("Rebuild interrupted; run it again to resume");
+    } else if report.failed.is_empty() && report.unrecoverable.is_empty() {
+        println!("✓ No degraded extents left");
+    } else {
+        println!("⚠ Some extents remain degraded; see above");
+    }
+
+    Ok(())
+}
+
+fn cmd_scrub(pool_dir: &Path, config: &scrubber::ScrubConfig, json_output: bool) -> Result<()> {
+    let repair = config.repair;
+    if !json_output {
+        println!("Scrubbing all extents in pool {:?}", pool_dir);
+        if repair {
+            println!("Repair mode: ENABLED - will attempt to fix detected issues");
+        }
+        match config.max_bytes_per_sec {
+            Some(limit) => println!("Workers: {}, I/O budget: {} MB/s", config.workers, limit / 1024 / 1024),
+            None => println!("Workers: {}, I/O budget: unlimited", config.workers),
+        }
+        println!();
+    }
+
+    let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
+    let mut disks = pool.load_disks()?;
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let (events, notifier) = offline_notifications(pool_dir, &mut disks);
+
+    // A mount verifies its recent writes itself; without one, this pass
+    // stands in for that, and with the lock held it may dequeue them
+    let recent_writes = recent_writes::RecentWriteQueue::open(pool_dir, usize::MAX)?;
+    let mounted = pool_lock::PoolLock::holder(pool_dir)?.is_some_and(|holder| holder.mountpoint.is_some());
+    let mut config = config.clone();
+    if mounted {
+        config.skip = recent_writes.extents().into_iter().collect();
+        if !json_output && !config.skip.is_empty() {
+            println!("Leaving {} recently written extents to the mount's verifier", config.skip.len());
+            println!();
+        }
+    }
+    let config = &config;
+
+    let was_suspect: Vec<uuid::Uuid> =
+        disks.iter().filter(|d| d.health == disk::DiskHealth::Suspect).map(|d| d.uuid).collect();
+    let scrubber = scrubber::Scrubber::new(pool_dir.to_path_buf());
+    let progress = scrubber::ScrubPassProgress::default();
+    let done = std::sync::atomic::AtomicBool::new(false);
+
+    let results = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut last_report = std::time::Instant::now();
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                if !json_output && last_report.elapsed() >= std::time::Duration::from_secs(10) {
+                    println!("  Progress: {}", progress);
+                    last_report = std::time::Instant::now();
                 }
             }
-        } else {
-            scrubber.verify_extent(&extent, &metadata, &disks)?
-        };
+        });
+        let results = scrubber.scrub_pool(&metadata, &mut disks, config, &progress);
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        results
+    })?;
+
+    let newly_suspect: Vec<uuid::Uuid> = disks
+        .iter()
+        .filter(|d| d.health == disk::DiskHealth::Suspect && !was_suspect.contains(&d.uuid))
+        .map(|d| d.uuid)
+        .collect();
+    let stats = scrubber::Scrubber::stats(&results);
+    if repair {
+        let verified: Vec<uuid::Uuid> = results
+            .iter()
+            .filter(|r| matches!(r.status, scrubber::ScrubStatus::Healthy | scrubber::ScrubStatus::Repaired))
+            .map(|r| r.extent_uuid)
+            .collect();
+        recent_writes.complete(&verified);
+    }
+    let maps = metadata.check_extent_maps(repair)?;
+    if let Some(notifier) = notifier {
+        for result in results.iter().filter(|r| r.status == scrubber::ScrubStatus::Unrecoverable) {
+            let missing = serde_json::json!({"missing_fragments": result.missing_fragments});
+            events.record_with_fields(logging::EventKind::Unrecoverable, Some(result.extent_uuid), None, "found by scrub", Some(missing));
+        }
+        let summary = serde_json::json!({
+            "stats": stats,
+            "repair": repair,
+            "io_bytes": progress.io_bytes(),
+            "elapsed_ms": progress.elapsed().as_millis() as u64,
+            "suspect_disks": newly_suspect,
+        });
+        events.record_with_fields(logging::EventKind::ScrubFinished, None, None, stats.to_string(), Some(summary));
+        finish_notifications(notifier);
+    }
 
-        results.push(result);
+    if json_output {
+        let scrub_json = serde_json::json!({
+            "pool": pool_dir.display().to_string(),
+            "repair": repair,
+            "io_bytes": progress.io_bytes(),
+            "elapsed_ms": progress.elapsed().as_millis() as u64,
+            "stats": stats,
+            "suspect_disks": newly_suspect,
+            "extents": results.iter().filter(|r| !r.issues.is_empty()).collect::<Vec<_>>(),
+            "extent_maps": maps,
+        });
+        println!("{}", serde_json::to_string_pretty(&scrub_json)?);
+        return Ok(());
     }
 
-    let stats = scrubber::Scrubber::stats(&results);
+    for disk_uuid in &newly_suspect {
+        println!("⚠ Disk {} marked Suspect: repeated fragment checksum failures", disk_uuid);
+    }
 
     println!("Scrub Results:");
     println!();
+    println!("  Read {:.1} MB in {:.1}s", progress.io_bytes() as f64 / 1024.0 / 1024.0, progress.elapsed().as_secs_f64());
     println!("  Healthy:       {}", stats.healthy);
     println!("  Degraded:      {}", stats.degraded);
     println!("  Repaired:      {}", stats.repaired);
@@ -252,6 +920,25 @@ fn cmd_scrub(pool_dir: &Path, repair: bool, _json_output: bool) -> Result<()> {
         println!("✓ All extents are healthy and verified");
     }
 
+    println!();
+    println!(
+        "Extent maps: {} checked, {} stale, {} corrupt, {} without checksum",
+        maps.checked,
+        maps.stale.len(),
+        maps.corrupt.len(),
+        maps.unchecksummed
+    );
+    for ino in &maps.stale {
+        let action = if maps.repaired.contains(ino) { "checksum regenerated" } else { "stale checksum" };
+        println!("  - ino {}: {}", ino, action);
+    }
+    for map in &maps.corrupt {
+        println!("  - ino {}: {}", map.ino, map.reason);
+    }
+    if !maps.stale.is_empty() && !repair {
+        println!("  Use `scrub --pool {} --repair` to regenerate stale checksums", pool_dir.display());
+    }
+
     Ok(())
 }
 
@@ -273,20 +960,18 @@ fn cmd_status(pool_dir: &Path, json_output: bool) -> Result<()> {
 
     // Load metadata
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let extents = metadata.list_all_extents()?;
-
-    let mut complete = 0;
-    let mut readable = 0;
-    let mut unreadable = 0;
-    for extent in &extents {
-        if extent.is_complete() {
-            complete += 1;
-        } else if extent.is_readable() {
-            readable += 1;
-        } else {
-            unreadable += 1;
-        }
-    }
+    // Counters kept by the metadata; no extent record is read
+    let extents = metadata.extent_totals();
+    let complete = extents.complete();
+    let readable = extents.degraded.len();
+    let unreadable = extents.unreadable.len();
+    let below_policy = extents.below_policy.len();
+    let compressed = extents.compressed;
+    let logical_bytes = extents.logical_bytes;
+    let stored_bytes = extents.stored_bytes;
+
+    // Logical bytes per byte encoded; 1.0 when nothing is compressed
+    let compression_ratio = if stored_bytes > 0 { logical_bytes as f64 / stored_bytes as f64 } else { 1.0 };
 
     if json_output {
         let status_json = serde_json::json!({
@@ -296,15 +981,37 @@ fn cmd_status(pool_dir: &Path, json_output: bool) -> Result<()> {
                 "total": disks.len(),
                 "healthy": healthy,
                 "degraded": degraded,
-                "failed": failed
+                "failed": failed,
+                "devices": disk_error_summary(&disks)
             },
             "extents": {
-                "total": extents.len(),
+                "total": extents.extents,
                 "complete": complete,
                 "readable": readable,
-                "unreadable": unreadable
+                "unreadable": unreadable,
+                "below_policy": below_policy
+            },
+            "verify_writes": pool.verify_writes,
+            "space_reserve_percent": pool.space_reserve_percent,
+            "degraded_writes": pool.degraded_writes.name(),
+            "degraded_write_floor": pool.degraded_write_floor.to_string(),
+            "case_insensitive": pool.case_insensitive,
+            "extent_size": pool.extent_size,
+            "metadata_backend": metadata.metadata_backend().to_string(),
+            "compression": {
+                "algorithm": pool.compression.to_string(),
+                "compressed_extents": compressed,
+                "logical_bytes": logical_bytes,
+                "stored_bytes": stored_bytes,
+                "ratio": compression_ratio
             },
-            "health": if unreadable > 0 { "critical" } else if readable > 0 { "degraded" } else { "healthy" }
+            "health": if unreadable > 0 {
+                "critical"
+            } else if readable > 0 || below_policy > 0 {
+                "degraded"
+            } else {
+                "healthy"
+            }
         });
         println!("{}", serde_json::to_string_pretty(&status_json)?);
     } else {
@@ -313,26 +1020,45 @@ fn cmd_status(pool_dir: &Path, json_output: bool) -> Result<()> {
         println!("Disks: {}", disks.len());
         for disk in &disks {
             println!(
-                "  {} ({:?}) - {} MB used / {} MB total",
+                "  {} ({:?}) - {} MB used / {} MB total, {} I/O errors in last {}s",
                 disk.uuid,
                 disk.health,
                 disk.used_bytes / 1024 / 1024,
-                disk.capacity_bytes / 1024 / 1024
+                disk.capacity_bytes / 1024 / 1024,
+                disk.recent_io_errors(),
+                disk.health_policy.window_secs
             );
         }
         println!();
         println!("Disk Summary: {} healthy, {} degraded/suspect, {} failed", healthy, degraded, failed);
         println!();
-        println!("Extents: {} total", extents.len());
+        println!("Extents: {} total", extents.extents);
         println!("  {} complete", complete);
         println!("  {} degraded (readable)", readable);
         println!("  {} unreadable", unreadable);
+        println!("  {} below their intended policy", below_policy);
+        println!(
+            "Compression: {} ({} extents compressed, ratio {:.2}x)",
+            pool.compression, compressed, compression_ratio
+        );
+        println!("Verify on write: {}", if pool.verify_writes { "on" } else { "off" });
+        println!("Space reserve: {}% of each disk", pool.space_reserve_percent);
+        match pool.degraded_writes {
+            DegradedWrites::Allow => println!("Degraded writes: allowed, down to {}", pool.degraded_write_floor),
+            DegradedWrites::Deny => println!("Degraded writes: denied"),
+        }
+        println!("Extent size: {} KB", pool.extent_size / 1024);
+        println!("Name lookup: {}", if pool.case_insensitive { "case-insensitive" } else { "case-sensitive" });
+        println!("Metadata records: {}", metadata.metadata_backend());
         if unreadable > 0 {
             println!();
             println!("⚠ WARNING: {} unreadable extents - data loss risk!", unreadable);
         } else if readable > 0 {
             println!();
             println!("⚠ NOTICE: {} degraded extents - rebuild recommended", readable);
+        } else if below_policy > 0 {
+            println!();
+            println!("⚠ NOTICE: {} extents below their intended policy - upgraded once enough disks are writable", below_policy);
         } else {
             println!();
             println!("✓ All extents healthy");
@@ -342,12 +1068,90 @@ fn cmd_status(pool_dir: &Path, json_output: bool) -> Result<()> {
     Ok(())
 }
 
-fn cmd_init(pool_dir: &Path, _json_output: bool) -> Result<()> {
+/// Per-disk health and error counts for JSON output
+fn disk_error_summary(disks: &[Disk]) -> Vec<serde_json::Value> {
+    disks
+        .iter()
+        .map(|disk| {
+            serde_json::json!({
+                "uuid": disk.uuid.to_string(),
+                "health": format!("{:?}", disk.health),
+                "recent_io_errors": disk.recent_io_errors(),
+                "error_window_secs": disk.health_policy.window_secs,
+                "corruption_count": disk.corruption_count,
+                "fragments": disk.fragment_count,
+                "used_bytes": disk.used_bytes,
+                "smart": smart_json(disk)
+            })
+        })
+        .collect()
+}
+
+/// Latest SMART sample of `disk` with its age, or null if it has none
+fn smart_json(disk: &Disk) -> serde_json::Value {
+    match &disk.smart {
+        Some(sample) => {
+            let mut value = serde_json::to_value(sample).unwrap_or_default();
+            value["age_secs"] = sample.age_secs().into();
+            value["summary"] = sample.summary().into();
+            value
+        }
+        None => serde_json::Value::Null,
+    }
+}
+
+/// Settings of `init`, fixed for the life of the pool or until changed by their own command
+struct InitOptions {
+    encrypt: bool,
+    compression: String,
+    verify_writes: bool,
+    case_insensitive: bool,
+    extent_size_kb: usize,
+    metadata_backend: String,
+}
+
+fn cmd_init(pool_dir: &Path, options: &InitOptions, _json_output: bool) -> Result<()> {
     println!("Initializing storage pool at {:?}", pool_dir);
     
+    let mut pool = DiskPool::new();
+    let extent_size = options.extent_size_kb.checked_mul(1024).ok_or_else(|| anyhow!("Extent size is too large"))?;
+    extent::validate_extent_size(extent_size)?;
+    pool.extent_size = extent_size;
+    if extent_size != extent::DEFAULT_EXTENT_SIZE {
+        println!("  Extent size: {} KB", options.extent_size_kb);
+    }
+    pool.compression = options.compression.parse()?;
+    if pool.compression != compression::Compression::None {
+        println!("  Compression: {}", pool.compression);
+    }
+    pool.verify_writes = options.verify_writes;
+    if options.verify_writes {
+        println!("  Verify on write: on");
+    }
+    pool.case_insensitive = options.case_insensitive;
+    if options.case_insensitive {
+        println!("  Name lookup: case-insensitive");
+    }
+    pool.metadata_backend = options.metadata_backend.parse()?;
+    if pool.metadata_backend != metadata_store::MetadataBackend::Files {
+        if !cfg!(feature = "kv-metadata") {
+            return Err(anyhow!("--metadata-backend {} needs a build with the kv-metadata feature", pool.metadata_backend));
+        }
+        println!("  Metadata records: {}", pool.metadata_backend);
+    }
+    if options.encrypt {
+        let source = encryption::PoolKeySource::from_env()?
+            .ok_or_else(|| anyhow!("--encrypt needs --key-file or --passphrase-file"))?;
+        if let encryption::PoolKeySource::KeyFile(path) = &source {
+            if !path.exists() {
+                println!("  Generating new key file {:?}", path);
+            }
+        }
+        pool.enable_encryption(&source)?;
+        println!("  Encryption: XChaCha20-Poly1305");
+    }
+
     fs::create_dir_all(pool_dir).context("Failed to create pool directory")?;
-    
-    let pool = DiskPool::new();
     pool.save(pool_dir)?;
     
     // Initialize metadata
@@ -357,8 +1161,53 @@ fn cmd_init(pool_dir: &Path, _json_output: bool) -> Result<()> {
     Ok(())
 }
 
-fn cmd_add_disk(pool_dir: &Path, disk_path: &Path, device: bool, force: bool, _json_output: bool) -> Result<()> {
-    println!("Adding disk {:?} to pool {:?}", disk_path, pool_dir);
+fn cmd_add_disk(
+    pool_dir: &Path,
+    disk_path: &Path,
+    device: bool,
+    force: bool,
+    force_takeover: bool,
+    tier: Option<tiering::StorageTier>,
+    json_output: bool,
+) -> Result<()> {
+    let disk = add_disk(pool_dir, disk_path, device, force, force_takeover, tier, !json_output)?;
+    if json_output {
+        let summary = serde_json::json!({
+            "uuid": disk.uuid,
+            "path": disk.path,
+            "capacity_bytes": disk.capacity_bytes,
+            "tier": disk.tier,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    }
+    Ok(())
+}
+
+/// Format a new disk and record it in the pool, narrating each step when `verbose`
+fn add_disk(
+    pool_dir: &Path,
+    disk_path: &Path,
+    device: bool,
+    force: bool,
+    force_takeover: bool,
+    tier: Option<tiering::StorageTier>,
+    verbose: bool,
+) -> Result<Disk> {
+    if verbose {
+        println!("Adding disk {:?} to pool {:?}", disk_path, pool_dir);
+    }
+
+    // Before anything is created or formatted at the path
+    let mut pool = DiskPool::load(pool_dir)?;
+    let overrides = NewDiskOverrides { shared_device: force, takeover: force_takeover };
+    for warning in pool.check_new_disk(pool_dir, disk_path, overrides)? {
+        log::warn!("Adding disk anyway: {}", warning);
+        if verbose {
+            println!("⚠ WARNING: {}", warning);
+        } else {
+            eprintln!("⚠ WARNING: {}", warning);
+        }
+    }
 
     // Auto-detect block device and require explicit --device flag for safety
     if disk_path.exists() {
@@ -377,7 +1226,9 @@ fn cmd_add_disk(pool_dir: &Path, disk_path: &Path, device: bool, force: bool, _j
         if !disk_path.exists() {
             return Err(anyhow!("Raw device path does not exist: {:?}", disk_path));
         }
-        println!("  Treating {:?} as a raw block device (explicit confirmation)", disk_path);
+        if verbose {
+            println!("  Treating {:?} as a raw block device (explicit confirmation)", disk_path);
+        }
 
         // If device looks like it already contains a superblock, require --force to proceed
         if crate::on_device_allocator::OnDeviceAllocator::has_superblock(disk_path) && !force {
@@ -394,20 +1245,34 @@ fn cmd_add_disk(pool_dir: &Path, disk_path: &Path, device: bool, force: bool, _j
     }
 
     // Initialize disk
-    let disk = if device {
+    let mut disk = if device {
         Disk::from_block_device(disk_path.to_path_buf())?
     } else {
         Disk::new(disk_path.to_path_buf())?
     };
-    println!("  Capacity: {} MB", disk.capacity_bytes / 1024 / 1024);
+    if let Some(tier) = tier {
+        disk.tier = tier;
+    }
+    pool.adopt_disk(&mut disk)?;
+    if verbose {
+        println!("  Capacity: {} MB", disk.capacity_bytes / 1024 / 1024);
+        if let Some(geometry) = &disk.block_geometry {
+            println!(
+                "  Block size: {} bytes logical, {} bytes physical",
+                geometry.logical_block_size, geometry.physical_block_size
+            );
+        }
+        println!("  Tier: {} ({}){}", disk.tier.device_kind(), disk.tier, if tier.is_some() { "" } else { ", detected" });
+    }
 
     // Add to pool
-    let mut pool = DiskPool::load(pool_dir)?;
-    pool.add_disk(disk_path.to_path_buf());
+    pool.add_disk(disk_path.to_path_buf())?;
     pool.save(pool_dir)?;
 
-    println!("✓ Disk added");
-    Ok(())
+    if verbose {
+        println!("✓ Disk added");
+    }
+    Ok(disk)
 }
 
 fn cmd_remove_disk(pool_dir: &Path, disk_path: &Path, _json_output: bool) -> Result<()> {
@@ -429,6 +1294,7 @@ fn cmd_remove_disk(pool_dir: &Path, disk_path: &Path, _json_output: bool) -> Res
         log::warn!("Rebuild attempt encountered errors: {}", e);
         println!("  Warning: automatic rebuild reported errors; manual intervention may be required");
     }
+    storage.wait_for_rebuilds();
 
     // Remove from pool
     pool.remove_disk(disk_path);
@@ -438,19 +1304,163 @@ fn cmd_remove_disk(pool_dir: &Path, disk_path: &Path, _json_output: bool) -> Res
     Ok(())
 }
 
-fn cmd_list_disks(pool_dir: &Path, _json_output: bool) -> Result<()> {
-    let pool = DiskPool::load(pool_dir)?;
-    let disks = pool.load_disks()?;
-    
-    println!("Disks in pool ({} total):", disks.len());
-    println!();
+fn cmd_replace_disk(
+    pool_dir: &Path,
+    old_disk: uuid::Uuid,
+    new_path: &Path,
+    device: bool,
+    force: bool,
+    max_bytes_per_sec: Option<u64>,
+    json_output: bool,
+) -> Result<()> {
+    use crate::replace_disk::{DiskReplacer, ReplacePassProgress, ReplaceProgress};
+
+    DiskPool::load(pool_dir)?.require_key()?;
+    let replacer = match DiskReplacer::resume(pool_dir.to_path_buf())? {
+        Some(replacer) => {
+            let progress = replacer.progress();
+            if progress.old_disk != old_disk || progress.new_path != new_path {
+                return Err(anyhow!(
+                    "Replacement of disk {} by {:?} is unfinished; run replace-disk --old {} --new {:?} to complete it first",
+                    progress.old_disk,
+                    progress.new_path,
+                    progress.old_disk,
+                    progress.new_path
+                ));
+            }
+            replacer
+        }
+        None => {
+            let new_disk = add_disk(pool_dir, new_path, device, force, false, None, !json_output)?;
+            if !json_output {
+                println!();
+            }
+            DiskReplacer::start(pool_dir.to_path_buf(), ReplaceProgress::new(old_disk, new_disk.uuid, new_path.to_path_buf()))?
+        }
+    };
+
+    let mut pool = DiskPool::load(pool_dir)?;
+    let mut disks = pool.load_disks()?;
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    if !json_output {
+        println!("Replacing disk {} with {} ({:?})", old_disk, replacer.progress().new_disk, new_path);
+        match max_bytes_per_sec {
+            Some(limit) => println!("I/O budget: {} MB/s", limit / 1024 / 1024),
+            None => println!("I/O budget: unlimited"),
+        }
+        println!();
+    }
+
+    let live = ReplacePassProgress::default();
+    let done = std::sync::atomic::AtomicBool::new(false);
+    let mut report = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut last_report = std::time::Instant::now();
+            while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                if !json_output && last_report.elapsed() >= std::time::Duration::from_secs(10) {
+                    println!("  Progress: {}", live);
+                    last_report = std::time::Instant::now();
+                }
+            }
+        });
+        let report = replacer.run(&metadata, &mut disks, max_bytes_per_sec, &live, &STOP_REQUESTED);
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        report
+    })?;
+
+    // Only a disk no extent refers to any more leaves the pool
+    let mut unreachable_paths = Vec::new();
+    if report.complete() {
+        let mut old_path = None;
+        for path in &pool.disk_paths {
+            match Disk::load(path) {
+                Ok(disk) if disk.uuid == old_disk => old_path = Some(path.clone()),
+                Ok(_) => {}
+                Err(_) => unreachable_paths.push(path.clone()),
+            }
+        }
+        // A dead disk cannot say which path was its own; a lone unreachable path was,
+        // as long as the disk held fragments of this pool
+        if old_path.is_none() && unreachable_paths.len() == 1 && report.total_extents_repaired > 0 {
+            old_path = unreachable_paths.pop();
+        }
+        match old_path {
+            Some(path) => {
+                log::info!("Removing replaced disk {} at {:?} from the pool", old_disk, path);
+                pool.remove_disk(&path);
+                pool.save(pool_dir)?;
+                report.old_disk_removed = true;
+            }
+            // Stale locations of a disk that already left the pool
+            None => report.old_disk_removed = unreachable_paths.is_empty(),
+        }
+    }
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if report.resumed {
+        println!("  Resumed an interrupted replacement");
+    }
+    println!("  Scanned:       {}", report.scanned);
+    println!("  Repaired:      {}", report.repaired.len());
+    println!("  Written:       {:.1} MB", report.bytes_written as f64 / 1024.0 / 1024.0);
+    println!("  Failed:        {}", report.failed.len());
+    println!("  Unrecoverable: {}", report.unrecoverable.len());
+    for failure in &report.failed {
+        println!("    - {}: {}", failure.extent_uuid, failure.error);
+    }
+    for extent_uuid in &report.unrecoverable {
+        println!("    - {}: too few fragments survive without disk {}", extent_uuid, old_disk);
+    }
+    println!();
+
+    if report.interrupted {
+        println!("Replacement interrupted; run it again to resume");
+    } else if !report.complete() {
+        println!("⚠ Some extents still refer to disk {}, which stays in the pool; run replace-disk again to retry them", old_disk);
+    } else if report.old_disk_removed {
+        println!("✓ Disk {} replaced and removed from the pool", old_disk);
+    } else {
+        println!("⚠ Disk {} is no longer referenced, but several pool disks are unreachable:", old_disk);
+        for path in &unreachable_paths {
+            println!("    - {:?}", path);
+        }
+        println!("  Remove its path from pool.json once it is known");
+    }
+
+    Ok(())
+}
+
+fn cmd_list_disks(pool_dir: &Path, _json_output: bool) -> Result<()> {
+    let pool = DiskPool::load(pool_dir)?;
+    let disks = pool.load_disks()?;
+    
+    println!("Disks in pool ({} total):", disks.len());
+    println!();
     
     for disk in disks {
         println!("  UUID: {}", disk.uuid);
         println!("  Path: {:?}", disk.path);
         println!("  Health: {:?}", disk.health);
+        println!("  Tier: {}", tier_label(Some(disk.tier)));
+        println!("  Corrupt fragments: {}", disk.corruption_count);
+        match (&disk.smart, disk.kind) {
+            (Some(sample), _) => println!("  SMART: {} ({}s ago)", sample.summary(), sample.age_secs()),
+            (None, disk::DiskKind::BlockDevice) => println!("  SMART: not polled yet"),
+            (None, disk::DiskKind::Directory) => {}
+        }
         println!("  Capacity: {} MB", disk.capacity_bytes / 1024 / 1024);
-        println!("  Used: {} MB", disk.used_bytes / 1024 / 1024);
+        println!("  Used: {} MB in {} fragments", disk.used_bytes / 1024 / 1024, disk.fragment_count);
         println!("  Free: {} MB", 
                  (disk.capacity_bytes - disk.used_bytes) / 1024 / 1024);
         println!();
@@ -461,12 +1471,19 @@ fn cmd_list_disks(pool_dir: &Path, _json_output: bool) -> Result<()> {
 
 fn cmd_list_extents(pool_dir: &Path, _json_output: bool) -> Result<()> {
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let extents = metadata.list_all_extents()?;
     
-    println!("Extents in pool ({} total):", extents.len());
+    println!("Extents in pool ({} total):", metadata.extent_totals().extents);
     println!();
     
-    for extent in extents {
+    for extent in metadata.extent_records_sorted(None)? {
+        let extent = match extent {
+            (_, Ok(extent)) => extent,
+            (uuid, Err(e)) => {
+                println!("  UUID: {} (unreadable: {})", uuid, e);
+                println!();
+                continue;
+            }
+        };
         println!("  UUID: {}", extent.uuid);
         println!("  Size: {} bytes", extent.size);
         println!("  Redundancy: {:?}", extent.redundancy);
@@ -481,23 +1498,12 @@ fn cmd_list_extents(pool_dir: &Path, _json_output: bool) -> Result<()> {
 
 fn cmd_show_redundancy(pool_dir: &Path, _json_output: bool) -> Result<()> {
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let extents = metadata.list_all_extents()?;
-    
-    let mut total_extents = 0;
-    let mut complete_extents = 0;
-    let mut degraded_extents = 0;
-    let mut unreadable_extents = 0;
-    
-    for extent in &extents {
-        total_extents += 1;
-        if extent.is_complete() {
-            complete_extents += 1;
-        } else if extent.is_readable() {
-            degraded_extents += 1;
-        } else {
-            unreadable_extents += 1;
-        }
-    }
+    let totals = metadata.extent_totals();
+    
+    let total_extents = totals.extents;
+    let complete_extents = totals.complete();
+    let degraded_extents = totals.degraded.len();
+    let unreadable_extents = totals.unreadable.len();
     
     println!("Redundancy Status:");
     println!("  Total extents: {}", total_extents);
@@ -530,20 +1536,29 @@ fn cmd_show_redundancy(pool_dir: &Path, _json_output: bool) -> Result<()> {
 // -----------------------------
 
 fn cmd_defrag_analyze(pool_dir: &Path, json_output: bool) -> Result<()> {
-    use crate::defrag::{DefragConfig, DefragmentationEngine};
-
-    let pool = DiskPool::load(pool_dir)?;
-    let disks = pool.load_disks()?;
-    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let storage = StorageEngine::new(metadata, disks);
+    use crate::control::{ControlReply, ControlRequest};
+    use crate::defrag::DefragmentationEngine;
 
-    let defrag_engine = DefragmentationEngine::new(DefragConfig::default());
-    let analysis = defrag_engine.analyze_fragmentation(&storage)?;
+    // A mounted pool answers with its in-memory state
+    let (analysis, live) = match control::request_mounted(pool_dir, &ControlRequest::DefragAnalyze) {
+        Some(ControlReply::DefragAnalysis { analysis }) => (analysis, true),
+        Some(ControlReply::Error { message }) => return Err(anyhow!("Mounted pool could not analyze fragmentation: {}", message)),
+        Some(reply) => return Err(anyhow!("Unexpected reply to a fragmentation analysis: {:?}", reply)),
+        None => {
+            let pool = DiskPool::load(pool_dir)?;
+            pool.require_key()?;
+            let disks = pool.load_disks()?;
+            let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+            let storage = StorageEngine::new(metadata, disks);
+            storage.set_extent_size(pool.extent_size)?;
+            (DefragmentationEngine::new(pool.defrag.clone()).analyze_fragmentation(&storage)?, false)
+        }
+    };
 
     if json_output {
         println!("{}", serde_json::to_string_pretty(&analysis)?);
     } else {
-        println!("Fragmentation Analysis:");
+        println!("Fragmentation Analysis{}:", if live { " (mounted pool)" } else { "" });
         println!("  Total extents: {}", analysis.total_extents);
         println!("  Fragmented extents: {}", analysis.fragmented_extents);
         println!("  Fragmentation ratio: {:.2}%", analysis.overall_fragmentation_ratio * 100.0);
@@ -555,46 +1570,159 @@ fn cmd_defrag_analyze(pool_dir: &Path, json_output: bool) -> Result<()> {
             println!("    Fragmented: {}", disk_stats.fragmented_extents);
             println!("    Ratio: {:.2}%", disk_stats.fragmentation_ratio * 100.0);
         }
+        println!("\nFiles with the most extents ({} to coalesce):", analysis.coalesce_candidates);
+        println!("  {:>10} {:>8} {:>8} {:>8} {:>10}", "Inode", "Extents", "Small", "Shared", "Slot KB");
+        for file in &analysis.files {
+            println!(
+                "  {:>10} {:>8} {:>8} {:>8} {:>10}",
+                file.ino,
+                file.extents,
+                file.small_extents,
+                file.shared_extents,
+                file.slot_size / 1024
+            );
+        }
     }
 
     Ok(())
 }
 
-fn cmd_defrag_start(pool_dir: &Path, intensity: &str, json_output: bool) -> Result<()> {
-    use crate::defrag::{DefragConfig, DefragIntensity, DefragmentationEngine};
-    use std::sync::Arc;
+/// Flags of `defrag-start`
+struct DefragChange {
+    intensity: String,
+    window: Option<String>,
+    clear_window: bool,
+    threshold: Option<u8>,
+    coalesce_min_extents: Option<usize>,
+}
 
-    let pool = DiskPool::load(pool_dir)?;
-    let disks = pool.load_disks()?;
-    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let storage = Arc::new(StorageEngine::new(metadata, disks));
-    let metrics = Arc::new(Metrics::new());
+fn cmd_defrag_start(pool_dir: &Path, change: DefragChange, json_output: bool) -> Result<()> {
+    use crate::defrag::{DefragIntensity, DefragWindow};
 
-    let intensity_enum = match intensity {
+    let mut pool = DiskPool::load(pool_dir)?;
+    let mut config = pool.defrag.clone();
+    config.enabled = true;
+    config.intensity = match change.intensity.as_str() {
         "low" => DefragIntensity::Low,
         "medium" => DefragIntensity::Medium,
         "high" => DefragIntensity::High,
         _ => DefragIntensity::Medium,
     };
+    if let Some(window) = &change.window {
+        config.schedule = Some(window.parse::<DefragWindow>()?);
+    } else if change.clear_window {
+        config.schedule = None;
+    }
+    if let Some(percent) = change.threshold {
+        config.fragmentation_threshold = percent as f64 / 100.0;
+    }
+    if let Some(min_extents) = change.coalesce_min_extents {
+        config.coalesce_min_extents = min_extents;
+    }
+    config.validate()?;
 
-    let mut cfg = DefragConfig::default();
-    cfg.enabled = true;
-    cfg.intensity = intensity_enum;
+    let live = apply_defrag_config(pool_dir, &mut pool, config)?;
+    if live.is_none() {
+        // Raw devices are compacted here; the mount process works on files
+        pool.require_key()?;
+        let disks = pool.load_disks()?;
+        let devices: Vec<uuid::Uuid> = disks.iter().filter(|d| d.on_device_allocator.is_some()).map(|d| d.uuid).collect();
+        if !devices.is_empty() {
+            let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+            let storage = StorageEngine::new(metadata, disks);
+            for uuid in devices {
+                let moved = storage.defragment_device(uuid)?;
+                if !json_output {
+                    println!("✓ Compacted device {}: {} fragments moved", uuid, moved);
+                }
+            }
+        }
+    }
+    print_defrag_config(&pool.defrag, live, json_output)
+}
 
-    let engine = DefragmentationEngine::new(cfg);
-    engine.start(storage, metrics)?;
+fn cmd_defrag_stop(pool_dir: &Path, json_output: bool) -> Result<()> {
+    let mut pool = DiskPool::load(pool_dir)?;
+    let config = crate::defrag::DefragConfig { enabled: false, ..pool.defrag.clone() };
+    let live = apply_defrag_config(pool_dir, &mut pool, config)?;
+    print_defrag_config(&pool.defrag, live, json_output)
+}
 
-    println!("✓ Defragmentation started (intensity={})", intensity);
-    Ok(())
+fn cmd_defrag_status(pool_dir: &Path, json_output: bool) -> Result<()> {
+    use crate::control::{ControlReply, ControlRequest};
+
+    let pool = DiskPool::load(pool_dir)?;
+    let live = match control::request_mounted(pool_dir, &ControlRequest::DefragStatus) {
+        Some(ControlReply::DefragStatus { status, .. }) => Some(status),
+        Some(ControlReply::Error { message }) => {
+            log::debug!("Mounted pool has no defragmentation status: {}", message);
+            None
+        }
+        Some(reply) => return Err(anyhow!("Unexpected reply to a defragmentation status request: {:?}", reply)),
+        None => None,
+    };
+    print_defrag_config(&pool.defrag, live, json_output)
 }
 
-fn cmd_defrag_stop(_pool_dir: &Path, _json_output: bool) -> Result<()> {
-    println!("Defragmentation stop requested (not fully implemented)");
-    Ok(())
+/// Save `config` in the pool and hand it to the mount, returning the mount's status if it took it
+fn apply_defrag_config(
+    pool_dir: &Path,
+    pool: &mut DiskPool,
+    config: crate::defrag::DefragConfig,
+) -> Result<Option<crate::defrag::DefragStatus>> {
+    use crate::control::{ControlReply, ControlRequest};
+
+    pool.defrag = config.clone();
+    pool.save(pool_dir)?;
+    match control::request_mounted(pool_dir, &ControlRequest::SetDefragConfig { config }) {
+        Some(ControlReply::DefragStatus { status, .. }) => Ok(Some(status)),
+        Some(ControlReply::Error { message }) => Err(anyhow!("Mounted pool refused the defragmentation config: {}", message)),
+        Some(reply) => Err(anyhow!("Unexpected reply to a defragmentation config: {:?}", reply)),
+        None => Ok(None),
+    }
 }
 
-fn cmd_defrag_status(_pool_dir: &Path, _json_output: bool) -> Result<()> {
-    println!("Defragmentation status: (not implemented yet)");
+fn print_defrag_config(
+    config: &crate::defrag::DefragConfig,
+    live: Option<crate::defrag::DefragStatus>,
+    json_output: bool,
+) -> Result<()> {
+    if json_output {
+        let output = serde_json::json!({
+            "config": config,
+            "mounted": live.is_some(),
+            "status": live,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    let window = config.schedule.map_or("idle periods only".to_string(), |w| format!("{} and idle periods", w));
+    let coalesce = match config.coalesce_min_extents {
+        0 => "no coalescing".to_string(),
+        min => format!("coalesces files with over {} small extents", min),
+    };
+    println!(
+        "Defragmentation: {} (intensity={:?}, threshold={:.0}%, {}, relocates during {})",
+        if config.enabled { "enabled" } else { "disabled" },
+        config.intensity,
+        config.fragmentation_threshold * 100.0,
+        coalesce,
+        window
+    );
+    match live {
+        Some(status) => {
+            println!("  Mounted: {}", if status.paused { "paused" } else { "running" });
+            println!("  Extents processed: {}", status.extents_processed);
+            println!("  Extents defragmented: {}", status.extents_defragmented);
+            println!("  Bytes moved: {}", status.bytes_moved);
+            println!("  Files coalesced: {}", status.files_coalesced);
+            println!("  Errors: {}", status.errors);
+            if let Some(last_run_at) = status.last_run_at {
+                println!("  Last pass: {}", chrono::DateTime::from_timestamp(last_run_at, 0).map_or(last_run_at.to_string(), |t| t.to_rfc3339()));
+            }
+        }
+        None => println!("  Pool is not mounted; the mount process runs defragmentation"),
+    }
     Ok(())
 }
 
@@ -608,6 +1736,400 @@ fn cmd_trim_status(_pool_dir: &Path, _json_output: bool) -> Result<()> {
     Ok(())
 }
 
+fn cmd_set_compression(pool_dir: &Path, algorithm: &str, _json_output: bool) -> Result<()> {
+    let mut pool = DiskPool::load(pool_dir)?;
+    let compression: compression::Compression = algorithm.parse()?;
+    let previous = pool.compression;
+    pool.compression = compression;
+    pool.save(pool_dir)?;
+
+    println!("✓ Compression changed from {} to {}", previous, compression);
+    println!("  Applies to extents written from the next mount on; existing extents are unchanged");
+    Ok(())
+}
+
+fn cmd_set_verify_writes(pool_dir: &Path, enabled: bool, _json_output: bool) -> Result<()> {
+    let mut pool = DiskPool::load(pool_dir)?;
+    pool.verify_writes = enabled;
+    pool.save(pool_dir)?;
+
+    println!("✓ Verify on write {}", if enabled { "enabled" } else { "disabled" });
+    println!("  Applies from the next mount on; files with user.scfs.verify_writes set keep their own setting");
+    Ok(())
+}
+
+fn cmd_set_space_reserve(pool_dir: &Path, percent: u8, _json_output: bool) -> Result<()> {
+    let mut pool = DiskPool::load(pool_dir)?;
+    let previous = pool.space_reserve_percent;
+    pool.space_reserve_percent = percent;
+    pool.save(pool_dir)?;
+
+    println!("✓ Space reserve changed from {}% to {}%", previous, percent);
+    println!("  Applies from the next mount on");
+    Ok(())
+}
+
+/// Options of `set-rebuild-limit`; rates are in MiB/s and unset ones keep their value
+struct RebuildLimitChange {
+    mbps: Option<u64>,
+    concurrent: Option<usize>,
+    idle_mbps: Option<u64>,
+    idle_concurrent: Option<usize>,
+    idle_after_secs: Option<u64>,
+    disk_queue_depth: Option<usize>,
+    io_class: Option<io_scheduler::IoClass>,
+    io_concurrent: Option<usize>,
+    io_weight: Option<u32>,
+}
+
+fn cmd_set_rebuild_limit(pool_dir: &Path, change: RebuildLimitChange, json_output: bool) -> Result<()> {
+    use crate::control::{ControlReply, ControlRequest};
+
+    let mut pool = DiskPool::load(pool_dir)?;
+    let mut limits = pool.rebuild_limits;
+    if let Some(mbps) = change.mbps {
+        limits.max_bytes_per_sec = mbps * 1024 * 1024;
+    }
+    if let Some(concurrent) = change.concurrent {
+        limits.max_concurrent = concurrent;
+    }
+    if let Some(mbps) = change.idle_mbps {
+        limits.idle_max_bytes_per_sec = mbps * 1024 * 1024;
+    }
+    if let Some(concurrent) = change.idle_concurrent {
+        limits.idle_max_concurrent = concurrent;
+    }
+    if let Some(secs) = change.idle_after_secs {
+        limits.idle_after_secs = secs;
+    }
+    limits.validate()?;
+    let mut io_limits = pool.io_limits;
+    if let Some(depth) = change.disk_queue_depth {
+        io_limits.disk_queue_depth = depth;
+    }
+    if let Some(class) = change.io_class.and_then(|class| io_limits.class_mut(class)) {
+        if let Some(concurrent) = change.io_concurrent {
+            class.max_concurrent = concurrent;
+        }
+        if let Some(weight) = change.io_weight {
+            class.weight = weight;
+        }
+    }
+    io_limits.validate()?;
+    pool.rebuild_limits = limits;
+    pool.io_limits = io_limits;
+    pool.save(pool_dir)?;
+
+    let socket = pool_dir.join(control::CONTROL_SOCKET);
+    let live = if socket.exists() {
+        match control::request(&socket, &ControlRequest::SetRebuildLimits { limits }) {
+            Ok(ControlReply::RebuildStatus { status }) => Some(status),
+            Ok(ControlReply::Error { message }) => return Err(anyhow!("Mounted pool refused the limits: {}", message)),
+            Ok(reply) => return Err(anyhow!("Unexpected reply to new rebuild limits: {:?}", reply)),
+            // A socket left behind by a mount that did not shut down cleanly
+            Err(e) => {
+                log::debug!("{:#}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let io_status = match live {
+        Some(_) => match control::request(&socket, &ControlRequest::SetIoLimits { limits: io_limits })? {
+            ControlReply::IoStatus { status } => Some(status),
+            ControlReply::Error { message } => return Err(anyhow!("Mounted pool refused the I/O limits: {}", message)),
+            reply => return Err(anyhow!("Unexpected reply to new I/O limits: {:?}", reply)),
+        },
+        None => None,
+    };
+
+    if json_output {
+        let output = serde_json::json!({
+            "limits": limits,
+            "io_limits": io_limits,
+            "applied_to_mount": live.is_some(),
+            "status": live,
+            "io_status": io_status,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    println!(
+        "✓ Rebuild limits: {} concurrent at {} while busy, {} concurrent at {} after {}s idle",
+        limits.max_concurrent,
+        format_rebuild_rate(limits.max_bytes_per_sec),
+        limits.idle_max_concurrent,
+        format_rebuild_rate(limits.idle_max_bytes_per_sec),
+        limits.idle_after_secs
+    );
+    let classes: Vec<String> = io_scheduler::IoClass::BACKGROUND
+        .into_iter()
+        .map(|class| {
+            let limit = io_limits.class(class).unwrap();
+            format!("{} {} at weight {}", class, limit.max_concurrent, limit.weight)
+        })
+        .collect();
+    println!("✓ Fragment I/O: {} per disk; {}", io_limits.disk_queue_depth, classes.join(", "));
+    if live.is_some() {
+        println!("  Applied to the mounted pool");
+    } else {
+        println!("  Pool is not mounted; applies from the next mount on");
+    }
+    Ok(())
+}
+
+fn cmd_config(action: ConfigAction, json_output: bool) -> Result<()> {
+    use crate::config::{config_key, get_config_value, ConfigSource, LiveSettings, PoolConfig, CONFIG_KEYS};
+    use crate::control::{ControlReply, ControlRequest};
+
+    let applies = |live: bool| if live { "live" } else { "next mount" };
+    match action {
+        ConfigAction::Get { pool: pool_dir, key } => {
+            let pool = DiskPool::load(&pool_dir)?;
+            let config = PoolConfig::load(&pool_dir)?;
+            let keys = match key {
+                Some(name) => vec![config_key(&name)?],
+                None => CONFIG_KEYS.iter().collect(),
+            };
+            if json_output {
+                let values: serde_json::Map<_, _> =
+                    keys.iter().map(|key| (key.name.to_string(), get_config_value(&pool, &config, key))).collect();
+                println!("{}", serde_json::to_string_pretty(&values)?);
+            } else if let [key] = keys.as_slice() {
+                println!("{}", key.kind.display(&get_config_value(&pool, &config, key)));
+            } else {
+                for key in keys {
+                    println!("{} = {}", key.name, key.kind.display(&get_config_value(&pool, &config, key)));
+                }
+            }
+            Ok(())
+        }
+
+        ConfigAction::List { pool: pool_dir } => {
+            let pool = DiskPool::load(&pool_dir)?;
+            let config = PoolConfig::load(&pool_dir)?;
+            if json_output {
+                let keys: Vec<_> = CONFIG_KEYS
+                    .iter()
+                    .map(|key| serde_json::json!({ "key": key, "value": get_config_value(&pool, &config, key) }))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "version": config.version, "keys": keys }))?);
+                return Ok(());
+            }
+            println!("{:<28} {:>10}  {:<10}  DESCRIPTION", "KEY", "VALUE", "APPLIES");
+            for key in CONFIG_KEYS {
+                let value = key.kind.display(&get_config_value(&pool, &config, key));
+                println!("{:<28} {:>10}  {:<10}  {}", key.name, value, applies(key.live), key.help);
+            }
+            if !config.unknown.is_empty() {
+                let names: Vec<_> = config.unknown.keys().map(String::as_str).collect();
+                println!("\nKept but not understood by this build: {}", names.join(", "));
+            }
+            Ok(())
+        }
+
+        ConfigAction::Set { pool: pool_dir, key, value } => {
+            let key = config_key(&key)?;
+            let mut pool = DiskPool::load(&pool_dir)?;
+            let mut config = PoolConfig::load(&pool_dir)?;
+            let value = crate::config::set_config_value(&mut pool, &mut config, key, &value)?;
+            match key.source {
+                ConfigSource::Config => config.save(&pool_dir)?,
+                ConfigSource::Pool => pool.save(&pool_dir)?,
+            }
+
+            let applied = if key.live {
+                let settings = LiveSettings::from_pool(&pool);
+                match control::request_mounted(&pool_dir, &ControlRequest::ApplySettings { settings }) {
+                    Some(ControlReply::SettingsApplied { .. }) => true,
+                    Some(ControlReply::Error { message }) => {
+                        return Err(anyhow!("Saved, but the mounted pool refused the change: {}", message))
+                    }
+                    Some(reply) => return Err(anyhow!("Unexpected reply to new settings: {:?}", reply)),
+                    None => false,
+                }
+            } else {
+                false
+            };
+
+            if json_output {
+                let output = serde_json::json!({
+                    "key": key.name,
+                    "value": value,
+                    "live": key.live,
+                    "applied_to_mount": applied,
+                });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else if applied {
+                println!("✓ {} = {} (applied to the mounted pool)", key.name, key.kind.display(&value));
+            } else if key.live {
+                println!("✓ {} = {}", key.name, key.kind.display(&value));
+            } else {
+                println!("✓ {} = {} (takes effect at the next mount)", key.name, key.kind.display(&value));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn cmd_policy(action: PolicyAction, json_output: bool) -> Result<()> {
+    use crate::config::PoolConfig;
+    use crate::control::{ControlReply, ControlRequest};
+    use crate::policy_engine::{Policy, POLICY_AUDIT_FILE};
+
+    match action {
+        PolicyAction::Add { pool: pool_dir, file } => {
+            let text = std::fs::read_to_string(&file).with_context(|| format!("Failed to read {:?}", file))?;
+            let policy = Policy::from_toml(&text).with_context(|| format!("Invalid policy file {:?}", file))?;
+            let mut config = PoolConfig::load(&pool_dir)?;
+            let replaced = config.policies.iter().any(|p| p.name == policy.name);
+            config.policies.retain(|p| p.name != policy.name);
+            config.policies.push(policy.clone());
+            config.save(&pool_dir)?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "policy": policy, "replaced": replaced }))?);
+            } else {
+                println!(
+                    "✓ {} policy '{}': {} rules, {} actions, {:?}",
+                    if replaced { "Replaced" } else { "Added" },
+                    policy.name,
+                    policy.rules.len(),
+                    policy.actions.len(),
+                    policy.schedule
+                );
+            }
+            Ok(())
+        }
+
+        PolicyAction::List { pool: pool_dir } => {
+            let config = PoolConfig::load(&pool_dir)?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "policies": config.policies }))?);
+                return Ok(());
+            }
+            if config.policies.is_empty() {
+                println!("No policies; add one with `policy add`");
+            }
+            for policy in &config.policies {
+                println!("{} ({}, {:?})", policy.name, if policy.enabled { "enabled" } else { "disabled" }, policy.schedule);
+                for rule in &policy.rules {
+                    println!("  when {:?}", rule);
+                }
+                for action in &policy.actions {
+                    println!("  then {}", action);
+                }
+            }
+            Ok(())
+        }
+
+        PolicyAction::Remove { pool: pool_dir, name } => {
+            let mut config = PoolConfig::load(&pool_dir)?;
+            if !config.policies.iter().any(|p| p.name == name) {
+                return Err(anyhow!("No policy named '{}'", name));
+            }
+            config.policies.retain(|p| p.name != name);
+            config.save(&pool_dir)?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "removed": name }))?);
+            } else {
+                println!("✓ Removed policy '{}'", name);
+            }
+            Ok(())
+        }
+
+        PolicyAction::Run { pool: pool_dir, name, dry_run } => {
+            let config = PoolConfig::load(&pool_dir)?;
+            let policies: Vec<Policy> = match &name {
+                Some(name) => vec![config
+                    .policies
+                    .into_iter()
+                    .find(|p| &p.name == name)
+                    .ok_or_else(|| anyhow!("No policy named '{}'", name))?],
+                None => config.policies.into_iter().filter(|p| p.enabled).collect(),
+            };
+            if policies.is_empty() {
+                return Err(anyhow!("Pool {:?} has no enabled policies; add one with `policy add`", pool_dir));
+            }
+            let request = ControlRequest::RunPolicies { policies: policies.clone(), dry_run };
+            let (run, mounted) = match control::request_mounted(&pool_dir, &request) {
+                Some(ControlReply::PolicyRun { run }) => (run, true),
+                Some(ControlReply::Error { message }) => return Err(anyhow!("Mounted pool could not run policies: {}", message)),
+                Some(reply) => return Err(anyhow!("Unexpected reply to a policy run: {:?}", reply)),
+                None => {
+                    let _lock = lock_pool(&pool_dir, "policy run", None, "run it again to go through the mount")?;
+                    (policy_engine::run_policies(&open_storage(&pool_dir)?, &policies, dry_run, false)?, false)
+                }
+            };
+
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "mounted": mounted, "run": run }))?);
+                return Ok(());
+            }
+            println!(
+                "Policy run on pool {:?}{}: {} extents, {} proposals",
+                pool_dir,
+                if dry_run { " (dry run)" } else { "" },
+                run.extents,
+                run.outcomes.len()
+            );
+            for outcome in &run.outcomes {
+                let proposal = &outcome.proposal;
+                let result = match (&outcome.refused, &outcome.impact, &outcome.error) {
+                    (Some(reason), _, _) => format!("refused: {}", reason),
+                    (_, Some(impact), _) => format!("done: {} ({} bytes)", impact.detail, impact.bytes),
+                    (_, _, Some(error)) => format!("failed: {}", error),
+                    _ => "approved".to_string(),
+                };
+                println!(
+                    "  {} {} [{}] benefit {:.1}, cost {:.1}: {}",
+                    proposal.target_extent, proposal.action, proposal.policy_name, proposal.estimated_benefit, proposal.estimated_cost, result
+                );
+            }
+            let (executed, refused, failed) = run.counts();
+            println!();
+            if dry_run {
+                println!("Dry run: {} would run, {} refused", run.outcomes.len() - refused, refused);
+            } else {
+                println!("Executed {}, refused {}, failed {}", executed, refused, failed);
+            }
+            Ok(())
+        }
+
+        PolicyAction::Audit { pool: pool_dir, limit } => {
+            let entries = policy_engine::read_audit_log(&pool_dir.join(POLICY_AUDIT_FILE))?;
+            let entries = &entries[entries.len().saturating_sub(limit.unwrap_or(entries.len()))..];
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "entries": entries }))?);
+                return Ok(());
+            }
+            if entries.is_empty() {
+                println!("No policy actions recorded");
+            }
+            for entry in entries {
+                println!(
+                    "{} {} {} [{}] {}: {}",
+                    format_timestamp(entry.timestamp as i64),
+                    entry.target,
+                    entry.action,
+                    entry.policy_name,
+                    if entry.success { "ok" } else { "FAILED" },
+                    entry.impact.detail
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn format_rebuild_rate(bytes_per_sec: u64) -> String {
+    if bytes_per_sec == 0 {
+        "unlimited".to_string()
+    } else {
+        format!("{} MiB/s", bytes_per_sec as f64 / (1024.0 * 1024.0))
+    }
+}
+
 fn cmd_set_reclamation_policy(pool_dir: &Path, policy_str: &str, _json_output: bool) -> Result<()> {
     println!("Setting reclamation policy to '{}' for pool {:?}", policy_str, pool_dir);
     // TODO: Validate and persist policy; for now just acknowledge
@@ -652,6 +2174,10 @@ fn cmd_set_disk_health(_pool_dir: &Path, disk_path: &Path, health: &str, _json_o
     };
 
     disk.health = new_health;
+    // Restoring a disk by hand gives it a clean slate
+    if new_health == disk::DiskHealth::Healthy {
+        disk.clear_io_errors();
+    }
     disk.save()?;
 
     println!(
@@ -665,33 +2191,7 @@ fn cmd_change_policy(pool_dir: &Path, policy_str: &str, _json_output: bool) -> R
     println!("Preparing to change redundancy policy...");
     
     // Parse policy string
-    let new_policy = if policy_str.starts_with("replication:") {
-        let copies: usize = policy_str
-            .strip_prefix("replication:")
-            .unwrap()
-            .parse()
-            .context("Invalid replication count")?;
-        RedundancyPolicy::Replication { copies }
-    } else if policy_str.starts_with("erasure:") {
-        let parts: Vec<&str> = policy_str
-            .strip_prefix("erasure:")
-            .unwrap()
-            .split('+')
-            .collect();
-        if parts.len() != 2 {
-            return Err(anyhow!("Invalid EC policy format. Use 'erasure:K+M'"));
-        }
-        let data_shards: usize = parts[0].parse()?;
-        let parity_shards: usize = parts[1].parse()?;
-        RedundancyPolicy::ErasureCoding {
-            data_shards,
-            parity_shards,
-        }
-    } else {
-        return Err(anyhow!(
-            "Invalid policy format. Use 'replication:N' or 'erasure:K+M'"
-        ));
-    };
+    let new_policy: RedundancyPolicy = policy_str.parse()?;
     
     println!("Target policy: {:?}", new_policy);
     println!();
@@ -703,20 +2203,80 @@ fn cmd_change_policy(pool_dir: &Path, policy_str: &str, _json_output: bool) -> R
     Ok(())
 }
 
-fn cmd_policy_status(pool_dir: &Path, _json_output: bool) -> Result<()> {
-    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let all_extents = metadata.list_all_extents()?;
-    
-    let transitioning: Vec<_> = all_extents
-        .iter()
-        .filter(|e| e.is_transitioning())
-        .collect();
-    
-    let with_history: Vec<_> = all_extents
-        .iter()
-        .filter(|e| !e.policy_transitions.is_empty())
-        .collect();
+/// Policy changes recorded in the pool, from the mount when there is one
+fn recorded_policy_changes(pool_dir: &Path) -> Result<Vec<policy_change::PolicyChangeProgress>> {
+    use crate::control::{ControlReply, ControlRequest};
+
+    match control::request_mounted(pool_dir, &ControlRequest::PolicyChanges) {
+        Some(ControlReply::PolicyChanges { changes }) => Ok(changes),
+        Some(ControlReply::Error { message }) => Err(anyhow!("Mounted pool could not list policy changes: {}", message)),
+        Some(reply) => Err(anyhow!("Unexpected reply to a policy change listing: {:?}", reply)),
+        None => policy_change::PolicyChangeProgress::list(pool_dir),
+    }
+}
+
+fn print_policy_changes(metadata: &MetadataManager, changes: &[policy_change::PolicyChangeProgress]) {
+    println!("Policy Changes: {}", changes.len());
+    for change in changes {
+        let path = metadata.path_of(change.ino).unwrap_or_else(|_| format!("inode {}", change.ino).into());
+        println!("  {} (inode {}): {}", path, change.ino, change);
+        for failed in &change.failed {
+            println!("    extent {} at slot {}: {}", failed.uuid, failed.slot, failed.error);
+        }
+        if change.state_label() == "interrupted" || change.state_label() == "cancelled" {
+            println!("    setting the same policy again resumes at slot {}", change.next_slot);
+        }
+    }
+    println!();
+}
+
+fn cmd_policy_status(pool_dir: &Path, watch: bool, interval: u64, json_output: bool) -> Result<()> {
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    if watch {
+        loop {
+            let changes = recorded_policy_changes(pool_dir)?;
+            if json_output {
+                println!("{}", serde_json::to_string(&changes)?);
+            } else {
+                // Redraw in place, like top
+                print!("\x1b[2J\x1b[H");
+                println!("{} - {}", pool_dir.display(), chrono::Local::now().format("%H:%M:%S"));
+                println!();
+                print_policy_changes(&metadata, &changes);
+            }
+            std::io::Write::flush(&mut std::io::stdout())?;
+            if !changes.iter().any(|change| change.active) {
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_secs(interval));
+        }
+    }
+    let changes = recorded_policy_changes(pool_dir)?;
+    
+    // Only extents with transitions are kept
+    let mut transitioning = Vec::new();
+    let mut with_history = Vec::new();
+    for extent in metadata.iter_extents()?.filter_map(Result::ok) {
+        if extent.policy_transitions.is_empty() {
+            continue;
+        }
+        if extent.is_transitioning() {
+            transitioning.push(extent.clone());
+        }
+        with_history.push(extent);
+    }
+    
+    if json_output {
+        let status_json = serde_json::json!({
+            "changes": changes,
+            "extents_in_transition": transitioning.iter().map(|e| e.uuid).collect::<Vec<_>>(),
+            "extents_with_history": with_history.len(),
+        });
+        println!("{}", serde_json::to_string_pretty(&status_json)?);
+        return Ok(());
+    }
     
+    print_policy_changes(&metadata, &changes);
     println!("Policy Transition Status:");
     println!();
     println!("  Extents in transition: {}", transitioning.len());
@@ -734,126 +2294,1213 @@ fn cmd_policy_status(pool_dir: &Path, _json_output: bool) -> Result<()> {
             );
         }
     }
-    
-    if transitioning.is_empty() && with_history.is_empty() {
-        println!("  No policy transitions detected");
+    
+    if transitioning.is_empty() && with_history.is_empty() {
+        println!("  No policy transitions detected");
+    }
+    
+    Ok(())
+}
+
+fn cmd_cancel_policy_change(pool_dir: &Path, path: Option<&str>, ino: Option<u64>, json_output: bool) -> Result<()> {
+    use crate::control::{ControlReply, ControlRequest};
+    use crate::policy_change::{PolicyChangeProgress, PolicyChangeState};
+
+    let ino = match (path, ino) {
+        (Some(path), _) => MetadataManager::new(pool_dir.to_path_buf())?.resolve_path(path)?.ino,
+        (None, Some(ino)) => ino,
+        (None, None) => return Err(anyhow!("Either --path or --ino is required")),
+    };
+    let cancelled = match control::request_mounted(pool_dir, &ControlRequest::CancelPolicyChange { ino }) {
+        Some(ControlReply::PolicyChangeCancelled { cancelled }) => cancelled,
+        Some(ControlReply::Error { message }) => return Err(anyhow!("Mounted pool could not cancel: {}", message)),
+        Some(reply) => return Err(anyhow!("Unexpected reply to a policy change cancel: {:?}", reply)),
+        None => false,
+    };
+    let message = if cancelled {
+        format!("Policy change of inode {} stops after its current extent", ino)
+    } else {
+        // Nothing is running it; an interrupted change is marked so it is not taken for a live one
+        match PolicyChangeProgress::load(pool_dir, ino)? {
+            Some(mut progress) if progress.state == PolicyChangeState::Running => {
+                progress.state = PolicyChangeState::Cancelled;
+                progress.in_progress = None;
+                progress.save(pool_dir)?;
+                format!("Interrupted policy change of inode {} marked cancelled", ino)
+            }
+            _ => return Err(anyhow!("No policy change of inode {} is running", ino)),
+        }
+    };
+    if json_output {
+        println!("{}", serde_json::json!({ "ino": ino, "cancelled": true, "message": message }));
+    } else {
+        println!("✓ {}", message);
+    }
+    Ok(())
+}
+
+/// Background work the mounted engine runs
+struct MountBackground {
+    write_buffer: WriteBufferConfig,
+    /// `None` disables the background orphan collector
+    orphan_gc: Option<gc::OrphanGcConfig>,
+    /// How often to look for unplugged or returning disks; `None` disables it
+    disk_probe: Option<std::time::Duration>,
+    /// How often block-device disks are polled for SMART samples; `None` disables it
+    smart_poll: Option<std::time::Duration>,
+    /// How often extent reads counted in memory are written out; `None` waits for unmount
+    access_stats_flush: Option<std::time::Duration>,
+    /// Settings of the pass moving extents between tiers; `None` disables it
+    tiering: Option<tiering::TierPassConfig>,
+    /// Size at which events.log is rotated; `None` keeps events in memory only
+    events_log_bytes: Option<u64>,
+    /// Classify extents with the learned access model, retrained hourly
+    access_model: bool,
+    /// Pool usage that raises a space_threshold event; 0 disables the check
+    space_warn_percent: u8,
+    /// Size and lifetime of the inode cache
+    inode_cache: inode_cache::InodeCacheLimits,
+    /// Memory kept for reusing read buffers
+    read_buffer_pool_bytes: u64,
+    /// Settings of the pass verifying settled new extents; `None` disables it
+    recent_writes: Option<recent_writes::RecentWriteConfig>,
+    /// New extents queued for that pass at most
+    recent_write_capacity: usize,
+}
+
+fn cmd_mount(
+    pool_dir: &Path,
+    mountpoint: &Path,
+    background: MountBackground,
+    settings: &crate::mount::MountSettings,
+    metrics_addr: Option<&str>,
+    metrics_refresh: std::time::Duration,
+    readiness: Option<&daemon::Readiness>,
+) -> Result<()> {
+    println!("Mounting filesystem at {:?}", mountpoint);
+    println!("Pool: {:?}", pool_dir);
+
+    // Held until the filesystem is unmounted
+    let mountpoint_path = fs::canonicalize(mountpoint).unwrap_or_else(|_| mountpoint.to_path_buf());
+    let _pool_lock = lock_pool(pool_dir, "mount", Some(&mountpoint_path), "a pool is mounted once only")?;
+    let _pid_file = daemon::PidFile::create(pool_dir)?;
+    
+    // Load pool and disks
+    let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
+    let disks = pool.load_disks()?;
+    
+    println!("Loaded {} disks:", disks.len());
+    for disk in &disks {
+        println!("  - {} ({:?})", disk.uuid, disk.health);
+    }
+    for path in pool.disk_paths.iter().filter(|p| !disks.iter().any(|d| &d.path == *p)) {
+        println!("  - {:?} unavailable; files that need it may return EIO until it returns", path);
+    }
+    
+    // Initialize metadata and storage
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let metrics = Arc::new(Metrics::new());
+    // Buffered runs are handed off as they fill one of the pool's extents
+    let write_buffer = WriteBufferConfig { extent_size: pool.extent_size, ..background.write_buffer };
+    let mut storage = StorageEngine::with_write_buffer(metadata, disks, Arc::clone(&metrics), write_buffer);
+    storage.set_compression(pool.compression);
+    storage.set_verify_writes(pool.verify_writes);
+    storage.set_extent_size(pool.extent_size)?;
+    storage.set_space_reserve_percent(pool.space_reserve_percent);
+    storage.set_space_watermarks(pool.space_watermarks);
+    storage.set_degraded_writes(pool.degraded_writes, pool.degraded_write_floor);
+    storage.set_metadata_commit_window(pool.metadata_commit_window_ms);
+    storage.set_atime_mode(settings.atime_mode());
+    storage.set_inode_cache_limits(background.inode_cache);
+    storage.set_read_buffer_pool_limit(background.read_buffer_pool_bytes);
+    storage.set_recent_write_capacity(background.recent_write_capacity);
+    storage.set_rebuild_limits(pool.rebuild_limits)?;
+    io_scheduler::scheduler().set_limits(pool.io_limits)?;
+    if let Err(e) = storage.set_access_model_enabled(background.access_model) {
+        log::warn!("Classifying extents by thresholds alone: {:#}", e);
+    }
+    // Subscribed first, so events of the mount-time rebuild reach the sinks
+    let events = storage.events();
+    let notifier = notify::start_for_pool(pool_dir, &events);
+
+    if settings.read_only {
+        // Enforced here too, in case the kernel ignores the ro option
+        storage.set_read_only(true);
+        println!("Read-only: skipping mount-time rebuild and orphan GC");
+    } else {
+        // Files unlinked while open when the last mount ended
+        match storage.delete_orphans() {
+            Ok(0) => {}
+            Ok(count) => println!("Deleted {} unlinked file(s) left open by the last mount", count),
+            Err(e) => log::error!("Deleting unlinked files failed: {}", e),
+        }
+        // Perform mount-time rebuilds before mounting
+        if let Err(e) = storage.perform_mount_rebuild() {
+            log::error!("Mount-time rebuild failed: {}", e);
+        }
+        if let Some(config) = background.orphan_gc {
+            println!(
+                "Orphan GC: every {}s, fragments older than {}h",
+                config.interval.as_secs(),
+                config.min_age_seconds / 3600
+            );
+            storage.start_orphan_gc(config);
+        }
+        if let Some(interval) = background.access_stats_flush {
+            storage.start_access_stats_flush(interval);
+        }
+        if let Some(config) = background.tiering {
+            println!("Tiering: every {}s", config.interval.as_secs());
+            storage.start_tiering(config);
+        }
+        if let Some(config) = background.recent_writes {
+            println!("Recent-write verification: extents settled for {}s", config.settle.as_secs());
+            storage.start_recent_write_verifier(config);
+        }
+        if background.access_model {
+            storage.start_access_model_training(std::time::Duration::from_secs(3600));
+        }
+    }
+    if let Some(interval) = background.disk_probe {
+        storage.start_disk_probe(pool.clone(), interval);
+    }
+    if let Some(interval) = background.smart_poll {
+        storage.start_smart_poll(interval);
+    }
+    if background.space_warn_percent > 0 {
+        storage.start_space_watch(background.space_warn_percent, notify::SPACE_CHECK_INTERVAL);
+    }
+    storage.start_prefetcher();
+
+    if let Some(max_bytes) = background.events_log_bytes {
+        storage.events().attach_file(&pool_dir.join(logging::EVENTS_FILE), max_bytes)?;
+    }
+    // Losing the event socket should not keep the pool from mounting
+    let event_server = match logging::EventServer::start(&pool_dir.join(logging::EVENTS_SOCKET), storage.events()) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::warn!("Not serving events: {:#}", e);
+            None
+        }
+    };
+    // Shares the live engine, so relocation sees buffered writes and waits for rebuilds
+    let defrag = if settings.read_only {
+        None
+    } else {
+        let engine = Arc::new(crate::defrag::DefragmentationEngine::new(pool.defrag.clone()));
+        engine.start(Arc::new(storage.background_handle()), Arc::clone(&metrics))?;
+        if pool.defrag.enabled {
+            match pool.defrag.schedule {
+                Some(window) => println!("Defragmentation: {} and idle periods", window),
+                None => println!("Defragmentation: idle periods"),
+            }
+        }
+        Some(engine)
+    };
+    // Policies are re-read every hour, so added ones run without a remount
+    let policy_runs = (!settings.read_only).then(|| {
+        let handle = storage.background_handle();
+        periodic::PeriodicTask::spawn(std::time::Duration::from_secs(3600), move || {
+            match policy_engine::run_scheduled_policies(&handle, chrono::Timelike::hour(&chrono::Utc::now())) {
+                Ok(Some(run)) => {
+                    let (executed, refused, failed) = run.counts();
+                    log::info!("Scheduled policies executed {} actions; {} refused, {} failed", executed, refused, failed);
+                }
+                Ok(None) => {}
+                Err(e) => log::error!("Scheduled policy run failed: {:#}", e),
+            }
+        })
+    });
+    let control_server = match control::ControlServer::start(
+        &pool_dir.join(control::CONTROL_SOCKET),
+        storage.background_handle(),
+        defrag.clone(),
+    ) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::warn!("Not serving control requests; set-rebuild-limit needs a remount: {:#}", e);
+            None
+        }
+    };
+
+    println!();
+    println!("Mounting...");
+    println!("Press Ctrl+C to unmount");
+    println!();
+    
+    // Serve the engine's own counters; pool gauges are recomputed from a shared handle
+    let metrics_server = match metrics_addr {
+        Some(addr) => {
+            let handle = storage.background_handle();
+            let refresh = monitoring::PoolHealthRefresh {
+                interval: metrics_refresh,
+                compute: Box::new(move || {
+                    // Also ages the rebuild throughput gauge when no rebuild finishes
+                    handle.rebuild_status();
+                    Ok(handle.pool_health()?)
+                }),
+            };
+            let exporter = monitoring::PrometheusExporter::new(metrics);
+            let server = monitoring::MetricsServer::start(addr, exporter, Some(refresh))?;
+            println!("Metrics: http://{}/metrics", server.local_addr());
+            Some(server)
+        }
+        None => None,
+    };
+    
+    // Use cross-platform mounting
+    let mounted = serde_json::json!({"mountpoint": mountpoint, "read_only": settings.read_only});
+    events.record_with_fields(logging::EventKind::Mounted, None, None, format!("Mounted at {:?}", mountpoint), Some(mounted));
+    let result = crate::mount::mount_filesystem_then(Box::new(storage), mountpoint, settings, || {
+        if let Some(readiness) = readiness {
+            readiness.mounted();
+        }
+    });
+    let unmounted = serde_json::json!({"mountpoint": mountpoint, "error": result.as_ref().err().map(|e| format!("{:#}", e))});
+    events.record_with_fields(logging::EventKind::Unmounted, None, None, format!("Unmounted {:?}", mountpoint), Some(unmounted));
+    
+    if let Some(server) = metrics_server {
+        server.stop();
+    }
+    if let Some(server) = event_server {
+        server.stop();
+    }
+    if let Some(server) = control_server {
+        server.stop();
+    }
+    if let Some(task) = policy_runs {
+        task.stop();
+    }
+    if let Some(engine) = defrag {
+        engine.stop();
+    }
+    if let Some(notifier) = notifier {
+        finish_notifications(notifier);
+    }
+    result
+}
+
+/// Events of a command run against an unmounted pool, sent to its notification sinks
+///
+/// The disks report their health changes to the returned ring.
+fn offline_notifications(pool_dir: &Path, disks: &mut [disk::Disk]) -> (Arc<logging::EventRing>, Option<notify::Notifier>) {
+    let events = Arc::new(logging::EventRing::new());
+    let notifier = notify::start_for_pool(pool_dir, &events);
+    if notifier.is_some() {
+        for disk in disks.iter_mut() {
+            disk.events = Some(Arc::clone(&events));
+        }
+    }
+    (events, notifier)
+}
+
+/// Give queued notifications a few seconds to be delivered before exiting
+fn finish_notifications(notifier: notify::Notifier) {
+    let stats = notifier.shutdown(std::time::Duration::from_secs(5));
+    if stats.failed > 0 || stats.dropped > 0 {
+        log::warn!("Notifications: {} sent, {} failed, {} dropped", stats.sent, stats.failed, stats.dropped);
+    }
+}
+
+/// Ask the mount of `pool_dir` to unmount over its control socket, then force
+/// it with `fusermount -u` and SIGTERM if it has not exited within `timeout`
+fn cmd_unmount(pool_dir: &Path, timeout: std::time::Duration, json_output: bool) -> Result<()> {
+    use crate::control::{ControlReply, ControlRequest};
+
+    let holder = pool_lock::PoolLock::holder(pool_dir)?.ok_or_else(|| anyhow!("Pool {:?} is not mounted", pool_dir))?;
+    let mountpoint = holder
+        .mountpoint
+        .clone()
+        .ok_or_else(|| anyhow!("Pool {:?} is not mounted; PID {} runs `{}` on it", pool_dir, holder.pid, holder.command))?;
+    let pid = holder.pid;
+
+    let asked = match control::request(&pool_dir.join(control::CONTROL_SOCKET), &ControlRequest::Shutdown) {
+        Ok(ControlReply::ShuttingDown { .. }) => true,
+        Ok(reply) => {
+            log::warn!("Mount did not accept the shutdown request: {:?}", reply);
+            false
+        }
+        Err(e) => {
+            log::warn!("Mount is not answering on its control socket: {:#}", e);
+            false
+        }
+    };
+    if asked && !json_output {
+        println!("Asked PID {} to unmount {:?}; waiting up to {}s", pid, mountpoint, timeout.as_secs());
+    }
+    let clean = asked && wait_for_release(pool_dir, pid, timeout)?;
+    if !clean {
+        log::warn!("Unmounting {:?} with fusermount and sending SIGTERM to PID {}", mountpoint, pid);
+        if let Err(e) = crate::mount::unmount_filesystem(&mountpoint) {
+            log::warn!("{:#}", e);
+        }
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+        if !wait_for_release(pool_dir, pid, timeout)? {
+            return Err(anyhow!("PID {} still holds pool {:?} after SIGTERM; it may need SIGKILL", pid, pool_dir));
+        }
+    }
+
+    if json_output {
+        println!("{}", serde_json::json!({ "mountpoint": mountpoint, "pid": pid, "forced": !clean }));
+    } else {
+        println!("✓ Unmounted {:?}{}", mountpoint, if clean { "" } else { " (forced)" });
+    }
+    Ok(())
+}
+
+/// Wait for process `pid` to release the pool lock, the last thing a mount does
+fn wait_for_release(pool_dir: &Path, pid: u32, timeout: std::time::Duration) -> Result<bool> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if pool_lock::PoolLock::holder(pool_dir)?.is_none_or(|holder| holder.pid != pid) {
+            return Ok(true);
+        }
+        if std::time::Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+fn cmd_events(pool_dir: &Path, follow: bool, kind: Option<&str>, json_output: bool) -> Result<()> {
+    use crate::logging::{EventKind, EventQuery, StorageEvent};
+
+    let query = EventQuery {
+        follow,
+        kind: kind.map(str::parse::<EventKind>).transpose()?,
+    };
+    let print = |event: &StorageEvent| {
+        if json_output {
+            println!("{}", event.to_json());
+        } else {
+            println!("{}", event.to_text());
+        }
+    };
+
+    let socket = pool_dir.join(logging::EVENTS_SOCKET);
+    if socket.exists() {
+        match logging::request_events(&socket, &query) {
+            Ok(events) => {
+                for event in events {
+                    print(&event?);
+                }
+                return Ok(());
+            }
+            // A socket left behind by a mount that did not shut down cleanly
+            Err(e) => log::debug!("{:#}", e),
+        }
+    }
+
+    if follow {
+        return Err(anyhow!("Pool {:?} is not mounted; --follow needs a running mount", pool_dir));
+    }
+    if !json_output {
+        eprintln!("Pool is not mounted; showing events from {}", logging::EVENTS_FILE);
+    }
+    for event in logging::read_event_log(pool_dir)?.iter().filter(|e| query.matches(e)) {
+        print(event);
+    }
+    Ok(())
+}
+
+fn cmd_top(pool_dir: &Path, interval: u64, top: usize, count: Option<usize>, json_output: bool) -> Result<()> {
+    use crate::activity::ActivityReport;
+
+    let socket = pool_dir.join(control::CONTROL_SOCKET);
+    let not_mounted = || anyhow!("Pool {:?} is not mounted; top needs a running mount", pool_dir);
+    if !socket.exists() {
+        return Err(not_mounted());
+    }
+    // A socket left behind by a mount that did not shut down cleanly
+    let mut previous = control::request_activity(&socket, interval, top).map_err(|e| {
+        log::debug!("{:#}", e);
+        not_mounted()
+    })?;
+
+    let mut shown = 0;
+    while count.is_none_or(|count| shown < count) {
+        std::thread::sleep(std::time::Duration::from_secs(interval));
+        let current = match control::request_activity(&socket, interval, top) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                log::debug!("{:#}", e);
+                if !json_output {
+                    eprintln!("Pool {:?} was unmounted", pool_dir);
+                }
+                return Ok(());
+            }
+        };
+        let report = ActivityReport::between(&previous, &current);
+        if json_output {
+            println!("{}", serde_json::to_string(&report)?);
+        } else {
+            // Redraw in place, like top
+            print!("\x1b[2J\x1b[H");
+            println!("{} - {}", pool_dir.display(), chrono::Local::now().format("%H:%M:%S"));
+            println!();
+            print!("{}", report);
+        }
+        std::io::Write::flush(&mut std::io::stdout())?;
+        previous = current;
+        shown += 1;
+    }
+    Ok(())
+}
+
+fn cmd_list_hot(pool_dir: &Path, limit: Option<usize>, json_output: bool) -> Result<()> {
+    let storage = open_storage(pool_dir)?;
+    let extents = storage.get_hot_extents()?;
+    print_extent_access_list(
+        &storage,
+        "Hot extents",
+        "accessed more than 100 times/day or within last hour",
+        &extents,
+        limit,
+        json_output,
+    )
+}
+
+/// Open the pool's storage engine for read-only inspection commands
+fn open_storage(pool_dir: &Path) -> Result<StorageEngine> {
+    let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
+    let disks = pool.load_disks()?;
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    Ok(StorageEngine::new(metadata, disks))
+}
+
+/// Format a unix timestamp for display, treating 0 as never
+fn format_timestamp(ts: i64) -> String {
+    if ts == 0 {
+        return "never".to_string();
+    }
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|dt| dt.to_rfc3339())
+        .unwrap_or_else(|| ts.to_string())
+}
+
+fn extent_access_json(extent: &extent::Extent) -> serde_json::Value {
+    let stats = extent.access_stats();
+    serde_json::json!({
+        "uuid": extent.uuid.to_string(),
+        "size": extent.size,
+        "policy": extent.redundancy.to_string(),
+        "classification": format!("{:?}", stats.classification),
+        "read_count": stats.read_count,
+        "write_count": stats.write_count,
+        "last_read": stats.last_read,
+        "last_write": stats.last_write,
+        "created_at": stats.created_at,
+        "access_frequency": extent.access_frequency()
+    })
+}
+
+/// Tier an extent's fragments are on, for listings; `-` when none is on an attached disk
+fn tier_label(tier: Option<tiering::StorageTier>) -> String {
+    tier.map_or("-".to_string(), |tier| format!("{} ({})", tier.device_kind(), tier))
+}
+
+fn print_extent_access_list(
+    storage: &StorageEngine,
+    title: &str,
+    description: &str,
+    extents: &[extent::Extent],
+    limit: Option<usize>,
+    json_output: bool,
+) -> Result<()> {
+    let shown = &extents[..limit.unwrap_or(extents.len()).min(extents.len())];
+    
+    if json_output {
+        let list: Vec<_> = shown
+            .iter()
+            .map(|extent| {
+                let mut entry = extent_access_json(extent);
+                entry["tier"] = serde_json::json!(storage.extent_tier(extent).map(|tier| tier.device_kind()));
+                entry
+            })
+            .collect();
+        let output = serde_json::json!({
+            "total": extents.len(),
+            "extents": list
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    
+    println!("{} ({} total, {}):", title, extents.len(), description);
+    println!();
+    
+    if shown.is_empty() {
+        println!("  None");
+        return Ok(());
+    }
+    
+    for extent in shown {
+        let stats = extent.access_stats();
+        println!("  UUID: {}", extent.uuid);
+        println!("  Size: {} bytes", extent.size);
+        println!("  Policy: {}", extent.redundancy);
+        println!("  Tier: {}", tier_label(storage.extent_tier(extent)));
+        println!("  Reads: {}  Writes: {}  ({:.1} ops/day)", stats.read_count, stats.write_count, extent.access_frequency());
+        println!("  Last access: {}", format_timestamp(stats.last_read.max(stats.last_write)));
+        println!();
+    }
+    
+    if shown.len() < extents.len() {
+        println!("  ... {} more not shown", extents.len() - shown.len());
+    }
+    
+    Ok(())
+}
+
+fn cmd_metrics(_pool_dir: &Path, json_output: bool) -> Result<()> {
+    // Create default metrics snapshot for demo
+    let snapshot = metrics::Metrics::new().snapshot();
+    
+    if json_output {
+        let metrics_json = serde_json::json!({
+            "disk": {
+                "reads": snapshot.disk_reads,
+                "read_bytes": snapshot.disk_read_bytes,
+                "writes": snapshot.disk_writes,
+                "write_bytes": snapshot.disk_write_bytes,
+                "errors": snapshot.disk_errors
+            },
+            "extents": {
+                "healthy": snapshot.extents_healthy,
+                "degraded": snapshot.extents_degraded,
+                "unrecoverable": snapshot.extents_unrecoverable,
+                "degraded_writes": snapshot.degraded_writes,
+                "degraded_upgrades": snapshot.degraded_upgrades
+            },
+            "rebuild": {
+                "attempted": snapshot.rebuilds_attempted,
+                "successful": snapshot.rebuilds_successful,
+                "failed": snapshot.rebuilds_failed,
+                "bytes_written": snapshot.rebuild_bytes_written,
+                "queue_depth": snapshot.rebuild_queue_depth,
+                "queue_rejected": snapshot.rebuild_queue_rejected
+            },
+            "scrub": {
+                "completed": snapshot.scrubs_completed,
+                "issues_found": snapshot.scrub_issues_found,
+                "repairs_attempted": snapshot.scrub_repairs_attempted,
+                "repairs_successful": snapshot.scrub_repairs_successful
+            },
+            "cache": {
+                "hits": snapshot.cache_hits,
+                "misses": snapshot.cache_misses,
+                "hit_rate": snapshot.cache_hits as f64 / ((snapshot.cache_hits + snapshot.cache_misses) as f64 + 0.001)
+            },
+            "inode_cache": {
+                "hits": snapshot.inode_cache_hits,
+                "misses": snapshot.inode_cache_misses
+            },
+            "note": "Metrics are collected during filesystem operation. These are default/zero values; actual metrics require an active mounted instance."
+        });
+        println!("{}", serde_json::to_string_pretty(&metrics_json)?);
+    } else {
+        println!("{}", snapshot);
+        println!();
+        println!("Note: Metrics are collected during filesystem operation.");
+        println!("These are default/zero values; actual metrics require an active mounted instance.");
+    }
+    
+    Ok(())
+}
+
+fn cmd_list_cold(pool_dir: &Path, limit: Option<usize>, json_output: bool) -> Result<()> {
+    let storage = open_storage(pool_dir)?;
+    let extents = storage.get_cold_extents()?;
+    print_extent_access_list(
+        &storage,
+        "Cold extents",
+        "accessed less than 10 times/day and not accessed in 24+ hours",
+        &extents,
+        limit,
+        json_output,
+    )
+}
+
+fn cmd_tier_status(pool_dir: &Path, json_output: bool) -> Result<()> {
+    let storage = open_storage(pool_dir)?;
+    let tiers = storage.tier_status()?;
+    
+    if json_output {
+        let list: Vec<_> = tiers
+            .iter()
+            .map(|status| {
+                serde_json::json!({
+                    "tier": status.tier.device_kind(),
+                    "disks": status.disks,
+                    "capacity_bytes": status.capacity_bytes,
+                    "used_bytes": status.used_bytes,
+                    "utilization_percent": status.utilization_percent(),
+                    "extents": status.extents,
+                    "extent_bytes": status.extent_bytes
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "tiers": list }))?);
+        return Ok(());
+    }
+    
+    println!("Storage tiers of pool {:?}:", pool_dir);
+    println!();
+    println!("  {:<12} {:>5} {:>12} {:>12} {:>7} {:>9} {:>12}", "TIER", "DISKS", "CAPACITY", "USED", "USE%", "EXTENTS", "DATA");
+    for status in &tiers {
+        println!(
+            "  {:<12} {:>5} {:>9} MB {:>9} MB {:>6.1}% {:>9} {:>9} MB",
+            format!("{} ({})", status.tier.device_kind(), status.tier),
+            status.disks,
+            status.capacity_bytes / 1024 / 1024,
+            status.used_bytes / 1024 / 1024,
+            status.utilization_percent(),
+            status.extents,
+            status.extent_bytes / 1024 / 1024
+        );
+    }
+    Ok(())
+}
+
+fn cmd_extent_stats(pool_dir: &Path, extent_str: &str, json_output: bool) -> Result<()> {
+    let extent_uuid = uuid::Uuid::parse_str(extent_str)
+        .map_err(|_| anyhow!("Invalid extent UUID: {}", extent_str))?;
+    
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let mut extent = metadata
+        .load_extent(&extent_uuid)
+        .map_err(|_| anyhow!("Extent {} not found in pool {:?}", extent_uuid, pool_dir))?;
+    let model = if crate::config::PoolConfig::load(pool_dir)?.access_model {
+        Some(hmm_classifier::HmmClassifier::load(pool_dir)?)
+    } else {
+        None
+    };
+    extent.reclassify_with_model(model.as_ref());
+    let now = chrono::Utc::now().timestamp();
+    let estimate = model.as_ref().and_then(|model| extent.model_estimate(model, now));
+    let hours = extent.hourly_observations(now).len();
+    let stats = extent.access_stats();
+    
+    if json_output {
+        let mut output = extent_access_json(&extent);
+        output["recommended_policy"] = serde_json::json!(extent.recommended_policy().to_string());
+        output["model"] = serde_json::json!({
+            "enabled": model.is_some(),
+            "observed_hours": hours,
+            "state": estimate.map(|e| format!("{:?}", e.state)),
+            "confidence": estimate.map(|e| e.confidence),
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    let model_label = match (&model, estimate) {
+        (None, _) => "disabled (thresholds only)".to_string(),
+        (Some(_), Some(estimate)) => format!("{:?}, {:.0}% confidence", estimate.state, estimate.confidence * 100.0),
+        (Some(_), None) => format!("learning ({} of {} hours observed)", hours, hmm_classifier::MODEL_MIN_HOURS),
+    };
+    
+    println!("Extent statistics for: {}", extent.uuid);
+    println!();
+    println!("  Size:           {} bytes", extent.size);
+    println!("  Policy:         {}", extent.redundancy);
+    println!("  Classification: {:?}", stats.classification);
+    println!("  Access model:   {}", model_label);
+    println!("  Recommended:    {}", extent.recommended_policy());
+    println!("  Reads:          {}", stats.read_count);
+    println!("  Writes:         {}", stats.write_count);
+    println!("  Frequency:      {:.1} ops/day", extent.access_frequency());
+    println!("  Last read:      {}", format_timestamp(stats.last_read));
+    println!("  Last write:     {}", format_timestamp(stats.last_write));
+    println!("  Created:        {}", format_timestamp(stats.created_at));
+    
+    Ok(())
+}
+
+fn cmd_file_layout(pool_dir: &Path, ino: u64, json_output: bool) -> Result<()> {
+    let storage = open_storage(pool_dir)?;
+    let layout = storage.describe_layout(ino)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&layout)?);
+        return Ok(());
+    }
+
+    println!(
+        "Layout of inode {} ({} bytes, {} extents of {} KB)",
+        layout.ino,
+        layout.size,
+        layout.extents.len(),
+        layout.extent_size / 1024
+    );
+    for extent in &layout.extents {
+        println!();
+        println!(
+            "  Slot {}: extent {} ({} bytes, {} stored, {}, compression {})",
+            extent.slot, extent.uuid, extent.size, extent.stored_size, extent.redundancy, extent.compression
+        );
+        for fragment in &extent.fragments {
+            let health = fragment.disk_health.map_or("not in pool".to_string(), |health| format!("{:?}", health));
+            println!("    Fragment {} on disk {} ({})", fragment.index, fragment.disk, health);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_verify_file(pool_dir: &Path, path: Option<&str>, ino: Option<u64>, repair: bool, json_output: bool) -> Result<()> {
+    let storage = open_storage(pool_dir)?;
+    let ino = match (path, ino) {
+        (Some(path), _) => storage.metadata().read().unwrap().resolve_path(path)?.ino,
+        (None, Some(ino)) => ino,
+        (None, None) => return Err(anyhow!("Either --path or --ino is required")),
+    };
+    let path = storage.metadata().read().unwrap().path_of(ino).unwrap_or_else(|_| format!("inode {}", ino).into());
+    let report = if repair { storage.repair_file(ino)? } else { storage.verify_file(ino)? };
+    let summary = report.summary();
+    let unrecoverable = report.unrecoverable();
+
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "path": path,
+                "ino": report.ino,
+                "size": report.size,
+                "repair": repair,
+                "extents": report.extents,
+                "summary": summary,
+            }))?
+        );
+    } else {
+        println!("Verifying {} (inode {}, {} bytes, {} extents)", path, report.ino, report.size, report.extents.len());
+        println!();
+        for check in &report.extents {
+            let state = match &check.health {
+                scrubber::ExtentHealth::Healthy => "healthy".to_string(),
+                scrubber::ExtentHealth::Degraded { missing_fragments } => {
+                    format!("degraded, fragments {:?} missing", missing_fragments)
+                }
+                scrubber::ExtentHealth::Repaired { rebuilt_fragments } => {
+                    format!("repaired, fragments {:?} rebuilt", rebuilt_fragments)
+                }
+                scrubber::ExtentHealth::ChecksumFailed => "checksum failed".to_string(),
+                scrubber::ExtentHealth::Unreadable { readable, needed } => {
+                    format!("unreadable, {} of {} needed fragments", readable, needed)
+                }
+            };
+            println!("  Slot {} (offset {}): extent {} {}", check.slot, check.offset, check.uuid, state);
+            if check.health != scrubber::ExtentHealth::Healthy {
+                for issue in &check.issues {
+                    println!("    - {}", issue);
+                }
+            }
+        }
+        println!();
+        println!("  Healthy:         {}", summary.healthy);
+        println!("  Degraded:        {}", summary.degraded);
+        println!("  Repaired:        {}", summary.repaired);
+        println!("  Checksum failed: {}", summary.checksum_failed);
+        println!("  Unreadable:      {}", summary.unreadable);
+        println!();
+        if unrecoverable > 0 {
+            println!("⚠ WARNING: {} unrecoverable extents; restore the file from a backup", unrecoverable);
+        } else if summary.degraded > 0 && !repair {
+            println!("✓ {} extents can be repaired", summary.degraded);
+            println!("  Use `verify-file --pool {} --ino {} --repair` to rebuild them", pool_dir.display(), report.ino);
+        } else if summary.degraded > 0 {
+            println!("⚠ {} extents could not be repaired; see above", summary.degraded);
+        } else {
+            println!("✓ All extents of the file are readable");
+        }
+    }
+
+    if unrecoverable > 0 {
+        return Err(anyhow!("{} unrecoverable extents in {}", unrecoverable, path));
+    }
+    Ok(())
+}
+
+fn cmd_du(pool_dir: &Path, path: &str, depth: Option<usize>, json_output: bool) -> Result<()> {
+    let overhead = if DiskPool::load(pool_dir)?.is_encrypted() { encryption::FragmentCipher::OVERHEAD } else { 0 };
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let rows = usage::UsageScanner::new(&metadata, depth, overhead)?.scan(path)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "usage": rows }))?);
+        return Ok(());
+    }
+
+    let kib = |bytes: u64| bytes.div_ceil(1024);
+    println!(
+        "{:>12} {:>12} {:>12} {:>8} {:>9} {:>10}  PATH",
+        "LOGICAL KiB", "PHYSICAL KiB", "SHARED KiB", "FILES", "EXTENTS", "FRAGMENTS"
+    );
+    for row in &rows {
+        println!(
+            "{:>12} {:>12} {:>12} {:>8} {:>9} {:>10}  {}",
+            kib(row.logical_bytes),
+            kib(row.physical_bytes),
+            kib(row.shared_bytes),
+            row.files,
+            row.extents,
+            row.fragments,
+            row.path
+        );
+    }
+    Ok(())
+}
+
+/// Print export or import progress at most once a second
+fn archive_progress(verb: &'static str, json_output: bool) -> impl FnMut(&export::Progress) {
+    let mut last_printed = std::time::Instant::now();
+    move |progress| {
+        let finished = progress.total_files == Some(progress.files);
+        if json_output || (!finished && last_printed.elapsed() < std::time::Duration::from_secs(1)) {
+            return;
+        }
+        last_printed = std::time::Instant::now();
+        let mib = progress.bytes as f64 / (1024.0 * 1024.0);
+        match progress.total_files {
+            Some(total) => println!("  {} {}/{} files ({:.1} MiB)", verb, progress.files, total, mib),
+            None => println!("  {} {} files ({:.1} MiB), last {}", verb, progress.files, mib, progress.path),
+        }
+    }
+}
+
+fn cmd_export(
+    pool_dir: &Path,
+    output: &Path,
+    since: Option<&str>,
+    snapshot: Option<String>,
+    compress: bool,
+    json_output: bool,
+) -> Result<()> {
+    if let Some(name) = &snapshot {
+        export::ExportSnapshot::check_name(name)?;
+    }
+    let since_snapshot = since.map(|name| export::ExportSnapshot::load(pool_dir, name)).transpose()?;
+    let header = export::ArchiveHeader::describe(pool_dir, &DiskPool::load(pool_dir)?, since);
+    let storage = open_storage(pool_dir)?;
+    storage.set_read_only(true);
+
+    // Written under a temporary name so an interrupted export never looks complete
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = std::path::PathBuf::from(partial);
+    let mut writer = std::io::BufWriter::new(fs::File::create(&partial)?);
+    if !json_output {
+        println!("Exporting {} to {}", pool_dir.display(), output.display());
+    }
+    let exported = export::export_pool(&storage, header, since_snapshot.as_ref(), compress, &mut writer, archive_progress("exported", json_output))
+        .and_then(|summary| {
+            writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok(summary)
+        });
+    let mut summary = match exported {
+        Ok(summary) => summary,
+        Err(e) => {
+            fs::remove_file(&partial).ok();
+            return Err(e);
+        }
+    };
+    fs::rename(&partial, output)?;
+    if let Some(name) = snapshot {
+        summary.snapshot.name = name;
+        summary.snapshot.save(pool_dir)?;
+    }
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "export": summary, "snapshot": summary.snapshot.name }))?);
+        return Ok(());
+    }
+    println!(
+        "✓ Exported {} directories and {} files ({} bytes)",
+        summary.directories, summary.files, summary.bytes
+    );
+    if let Some(since) = since {
+        println!("  {} files unchanged since {} left out", summary.unchanged, since);
+    }
+    if !summary.snapshot.name.is_empty() {
+        println!("  Recorded as export snapshot {}", summary.snapshot.name);
+    }
+    Ok(())
+}
+
+fn cmd_manifest(action: ManifestAction, json_output: bool) -> Result<()> {
+    match action {
+        ManifestAction::Create { pool: pool_dir, path, output, threads, signing_key } => {
+            let storage = open_storage(&pool_dir)?;
+            storage.set_read_only(true);
+            if !json_output {
+                println!("Hashing {} in {}", path, pool_dir.display());
+            }
+            let mut manifest = manifest::create(&storage, &pool_dir, &path, threads, archive_progress("hashed", json_output))?;
+            if let Some(key_path) = &signing_key {
+                if !key_path.exists() {
+                    encryption::generate_key_file(key_path)?;
+                }
+                manifest.sign(&encryption::read_key_file(key_path)?)?;
+                let mut public_path = key_path.as_os_str().to_owned();
+                public_path.push(".pub");
+                let public_key = &manifest.signature.as_ref().unwrap().public_key;
+                fs::write(&public_path, format!("{}\n", public_key))?;
+            }
+            manifest.save(&output)?;
+
+            if json_output {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&serde_json::json!({
+                        "manifest": output,
+                        "root": manifest.root,
+                        "files": manifest.files,
+                        "bytes": manifest.bytes,
+                        "root_hash": manifest.root_hash,
+                        "public_key": manifest.signature.as_ref().map(|s| &s.public_key),
+                    }))?
+                );
+                return Ok(());
+            }
+            println!("✓ Wrote manifest of {} files ({} bytes) to {}", manifest.files, manifest.bytes, output.display());
+            println!("  Root hash: {}", manifest.root_hash);
+            if let Some(signature) = &manifest.signature {
+                println!("  Signed by: {}", signature.public_key);
+            }
+            Ok(())
+        }
+        ManifestAction::Verify { pool: pool_dir, input, full, threads, public_key } => {
+            let manifest = manifest::Manifest::load(&input)?;
+            let trusted_key = public_key.as_deref().map(encryption::read_key_file).transpose()?;
+            let signature = manifest.check(trusted_key.as_ref())?;
+            let storage = open_storage(&pool_dir)?;
+            storage.set_read_only(true);
+            if !json_output {
+                println!("Verifying {} in {} against {}", manifest.root, pool_dir.display(), input.display());
+            }
+            let report = manifest::verify(&storage, &manifest, signature, full, threads, archive_progress("checked", json_output))?;
+
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                match report.signature {
+                    manifest::SignatureStatus::Unsigned => println!("  Manifest is not signed"),
+                    manifest::SignatureStatus::Untrusted => {
+                        println!("  Signature matches the key in the manifest, which was not checked; pass --public-key")
+                    }
+                    manifest::SignatureStatus::Trusted => println!("  Signature matches the trusted public key"),
+                }
+                for path in &report.added {
+                    println!("  added:    {}", path);
+                }
+                for path in &report.removed {
+                    println!("  removed:  {}", path);
+                }
+                for modified in &report.modified {
+                    println!("  modified: {} ({})", modified.path, modified.reason);
+                }
+                println!("  {} files checked, {} read back and hashed", report.checked, report.rehashed);
+            }
+            if !report.is_clean() {
+                return Err(anyhow!(
+                    "{} files added, {} removed and {} modified since the manifest",
+                    report.added.len(),
+                    report.removed.len(),
+                    report.modified.len()
+                ));
+            }
+            if !json_output {
+                println!("✓ Files match the manifest");
+            }
+            Ok(())
+        }
+    }
+}
+
+fn cmd_changed_files(
+    pool_dir: &Path,
+    since_generation: Option<u64>,
+    since_snapshot: Option<&str>,
+    json_output: bool,
+) -> Result<()> {
+    let since = match since_snapshot {
+        Some(name) => export::ExportSnapshot::load(pool_dir, name)?.change_generation,
+        None => since_generation.unwrap_or(0),
+    };
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let changes = backup_evolution::changed_files(&metadata, since)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&changes)?);
+        return Ok(());
+    }
+    println!(
+        "{} entries changed after generation {} (now {})",
+        changes.files.len(),
+        changes.since_generation,
+        changes.generation
+    );
+    for file in &changes.files {
+        let ranges: Vec<String> =
+            file.ranges.iter().map(|range| format!("{}+{}", range.offset, range.length)).collect();
+        println!("{:>8} {:>12}  {}  {}", file.change_generation, file.size, file.path, ranges.join(","));
+    }
+    Ok(())
+}
+
+fn cmd_migrate_metadata(pool_dir: &Path, to: &str, json_output: bool) -> Result<()> {
+    let to: metadata_store::MetadataBackend = to.parse()?;
+    if !json_output {
+        println!("Moving metadata records of {:?} to {}", pool_dir, to);
     }
-    
+    let report = metadata_store::migrate(pool_dir, to)?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "migration": report }))?);
+        return Ok(());
+    }
+    println!(
+        "✓ Copied and verified {} inodes, {} extents and {} extent maps from {} to {}",
+        report.inodes, report.extents, report.extent_maps, report.from, report.to
+    );
     Ok(())
 }
 
-fn cmd_mount(pool_dir: &Path, mountpoint: &Path, _json_output: bool) -> Result<()> {
-    println!("Mounting filesystem at {:?}", mountpoint);
-    println!("Pool: {:?}", pool_dir);
-    
-    // Load pool and disks
+fn cmd_import(pool_dir: &Path, input: &Path, prefix: &str, json_output: bool) -> Result<()> {
+    let header = export::read_header(fs::File::open(input)?)?;
     let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
     let disks = pool.load_disks()?;
-    
-    println!("Loaded {} disks:", disks.len());
-    for disk in &disks {
-        println!("  - {} ({:?})", disk.uuid, disk.health);
-    }
-    
-    // Initialize metadata and storage
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
     let storage = StorageEngine::new(metadata, disks);
+    storage.set_compression(pool.compression);
+    storage.set_verify_writes(pool.verify_writes);
+    storage.set_extent_size(pool.extent_size)?;
+    storage.set_space_reserve_percent(pool.space_reserve_percent);
+    storage.set_space_watermarks(pool.space_watermarks);
+    storage.set_degraded_writes(pool.degraded_writes, pool.degraded_write_floor);
+    storage.set_metadata_commit_window(pool.metadata_commit_window_ms);
 
-    // Perform mount-time rebuilds before mounting
-    if let Err(e) = storage.perform_mount_rebuild() {
-        log::error!("Mount-time rebuild failed: {}", e);
+    if !json_output {
+        println!(
+            "Importing {} (exported from {} at {}{}) under {}",
+            input.display(),
+            header.pool,
+            format_timestamp(header.created_at),
+            header.since.as_ref().map(|since| format!(", changes since {}", since)).unwrap_or_default(),
+            prefix
+        );
     }
+    let input = std::io::BufReader::new(fs::File::open(input)?);
+    let summary = export::import_archive(&storage, input, prefix, archive_progress("imported", json_output))?;
+    storage.sync_all()?;
 
-    println!();
-    println!("Mounting...");
-    println!("Press Ctrl+C to unmount");
-    println!();
-    
-    // Use cross-platform mounting
-    crate::mount::mount_filesystem(Box::new(storage), mountpoint)?;
-    
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "import": summary }))?);
+        return Ok(());
+    }
+    println!(
+        "✓ Imported {} directories and {} files ({} bytes)",
+        summary.directories, summary.files, summary.bytes
+    );
     Ok(())
 }
 
-fn cmd_list_hot(_pool_dir: &Path, _json_output: bool) -> Result<()> {
-    println!("Hot extents:");
-    println!("(Extents accessed more than 100 times/day or within last hour)");
-    println!();
-    
-    // In a real implementation, this would query the storage engine
-    println!("Note: Access statistics are tracked during operation");
-    
-    Ok(())
-}
+fn cmd_ingest(
+    pool_dir: &Path,
+    source: &Path,
+    dest: &str,
+    threads: usize,
+    verify: bool,
+    dry_run: bool,
+    json_output: bool,
+) -> Result<()> {
+    if !source.is_dir() {
+        return Err(anyhow!("{} is not a directory", source.display()));
+    }
+    let source = source.canonicalize()?;
+    let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
+    let disks = pool.load_disks()?;
+    let writable: u64 = disks
+        .iter()
+        .map(|disk| placement::writable_bytes(disk, pool.space_reserve_percent))
+        .sum();
 
-fn cmd_metrics(_pool_dir: &Path, json_output: bool) -> Result<()> {
-    // Create default metrics snapshot for demo
-    let snapshot = metrics::Metrics::new().snapshot();
-    
-    if json_output {
-        let metrics_json = serde_json::json!({
-            "disk": {
-                "reads": snapshot.disk_reads,
-                "read_bytes": snapshot.disk_read_bytes,
-                "writes": snapshot.disk_writes,
-                "write_bytes": snapshot.disk_write_bytes,
-                "errors": snapshot.disk_errors
-            },
-            "extents": {
-                "healthy": snapshot.extents_healthy,
-                "degraded": snapshot.extents_degraded,
-                "unrecoverable": snapshot.extents_unrecoverable
-            },
-            "rebuild": {
-                "attempted": snapshot.rebuilds_attempted,
-                "successful": snapshot.rebuilds_successful,
-                "failed": snapshot.rebuilds_failed,
-                "bytes_written": snapshot.rebuild_bytes_written
-            },
-            "scrub": {
-                "completed": snapshot.scrubs_completed,
-                "issues_found": snapshot.scrub_issues_found,
-                "repairs_attempted": snapshot.scrub_repairs_attempted,
-                "repairs_successful": snapshot.scrub_repairs_successful
-            },
-            "cache": {
-                "hits": snapshot.cache_hits,
-                "misses": snapshot.cache_misses,
-                "hit_rate": snapshot.cache_hits as f64 / ((snapshot.cache_hits + snapshot.cache_misses) as f64 + 0.001)
-            },
-            "note": "Metrics are collected during filesystem operation. These are default/zero values; actual metrics require an active mounted instance."
-        });
-        println!("{}", serde_json::to_string_pretty(&metrics_json)?);
-    } else {
-        println!("{}", snapshot);
-        println!();
-        println!("Note: Metrics are collected during filesystem operation.");
-        println!("These are default/zero values; actual metrics require an active mounted instance.");
+    let plan = ingest::IngestPlan::scan(&source, pool.extent_size)?;
+    if !json_output {
+        println!(
+            "{}: {} directories, {} files ({:.1} MiB), about {:.1} MiB of pool space with {:.1} MiB writable",
+            source.display(),
+            plan.directory_count,
+            plan.file_count,
+            plan.bytes as f64 / (1024.0 * 1024.0),
+            plan.raw_bytes as f64 / (1024.0 * 1024.0),
+            writable as f64 / (1024.0 * 1024.0)
+        );
+        if plan.hardlinked_files > 0 {
+            println!("  {} hard-linked names will be copied as separate files", plan.hardlinked_files);
+        }
+        for skipped in &plan.skipped {
+            println!("  ⚠ skipping {}: {}", skipped.path, skipped.reason);
+        }
+    }
+    if dry_run {
+        if json_output {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&serde_json::json!({ "plan": plan, "writable_bytes": writable }))?
+            );
+        } else if plan.raw_bytes > writable {
+            println!("✗ The pool does not have room for this tree");
+        }
+        return Ok(());
     }
-    
-    Ok(())
-}
 
-fn cmd_list_cold(_pool_dir: &Path, _json_output: bool) -> Result<()> {
-    println!("Cold extents:");
-    println!("(Extents accessed less than 10 times/day and not accessed in 24+ hours)");
-    println!();
-    
-    // In a real implementation, this would query the storage engine
-    println!("Note: Access statistics are tracked during operation");
-    
-    Ok(())
-}
+    let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
+    let storage = StorageEngine::new(metadata, disks);
+    storage.set_compression(pool.compression);
+    storage.set_verify_writes(pool.verify_writes);
+    storage.set_extent_size(pool.extent_size)?;
+    storage.set_space_reserve_percent(pool.space_reserve_percent);
+    storage.set_space_watermarks(pool.space_watermarks);
+    storage.set_degraded_writes(pool.degraded_writes, pool.degraded_write_floor);
+    storage.set_metadata_commit_window(pool.metadata_commit_window_ms.max(ingest::INGEST_COMMIT_WINDOW_MS));
 
-fn cmd_extent_stats(_pool_dir: &Path, extent_str: &str, _json_output: bool) -> Result<()> {
-    println!("Extent statistics for: {}", extent_str);
-    println!();
-    println!("Classification system:");
-    println!("  Hot:  frequency > 100 ops/day OR last access < 1 hour");
-    println!("  Warm: frequency > 10 ops/day OR last access < 24 hours");
-    println!("  Cold: frequency ≤ 10 ops/day AND last access ≥ 24 hours");
-    println!();
-    
+    let manifest = ingest::IngestManifest::open(pool_dir, &source, dest)?;
+    if !json_output && manifest.completed() > 0 {
+        println!("  resuming: {} files recorded in {}", manifest.completed(), manifest.path().display());
+    }
+    let options = ingest::IngestOptions { dest: dest.to_string(), threads, verify };
+    let last_printed = std::sync::Mutex::new(std::time::Instant::now());
+    let summary = ingest::ingest(&storage, &plan, &manifest, &options, |progress| {
+        let mut last_printed = last_printed.lock().unwrap();
+        if json_output || last_printed.elapsed() < std::time::Duration::from_secs(1) {
+            return;
+        }
+        *last_printed = std::time::Instant::now();
+        let secs = progress.elapsed.as_secs_f64().max(0.001);
+        let rate = progress.bytes as f64 / secs;
+        let eta = if rate > 0.0 { (progress.total_bytes - progress.bytes) as f64 / rate } else { 0.0 };
+        println!(
+            "  {}/{} files, {:.1} files/s, {:.1} MB/s, ETA {}s",
+            progress.files,
+            progress.total_files,
+            progress.files as f64 / secs,
+            rate / 1_000_000.0,
+            eta.round()
+        );
+    })?;
+    storage.sync_all()?;
+
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "ingest": summary }))?);
+        return Ok(());
+    }
+    println!(
+        "✓ Ingested {} files ({} bytes) in {:.1}s; {} files ({} bytes) were already in the pool",
+        summary.files, summary.bytes, summary.elapsed_secs, summary.skipped_files, summary.skipped_bytes
+    );
     Ok(())
 }
 
@@ -981,6 +3628,21 @@ fn cmd_orphan_stats(pool_dir: &Path, _json_output: bool) -> Result<()> {
         stats.old_bytes / 1024 / 1024
     );
     
+    let status = gc::GcStatus::load(pool_dir)?;
+    println!();
+    println!("Background GC:");
+    println!("  Last run:         {}", format_timestamp(status.last_run.unwrap_or(0)));
+    if status.last_run.is_some() {
+        println!("  Last removed:     {} fragments ({} bytes)", status.last_removed, status.last_bytes_reclaimed);
+        for disk in &status.last_skipped_disks {
+            println!("  Skipped disk:     {} (unreadable extent metadata)", disk);
+        }
+        if let Some(err) = &status.last_error {
+            println!("  Last error:       {}", err);
+        }
+    }
+    println!("  Total removed:    {} fragments ({} bytes)", status.total_removed, status.total_bytes_reclaimed);
+    
     if stats.old_count > 0 {
         println!();
         println!("Recommendation: Run 'cleanup-orphans' to reclaim space");
@@ -1097,91 +3759,119 @@ fn cmd_recover(pool_dir: &Path, cleanup: bool, _json_output: bool) -> Result<()>
     Ok(())
 }
 
-fn cmd_benchmark(pool_dir: &Path, file_size: usize, operations: usize, json_output: bool) -> Result<()> {
-    use crate::perf::{Benchmark, PerfStats};
-    
-    if !json_output {
-        println!("Running DynamicFS Performance Benchmark");
-        println!("======================================");
-        println!("File size:   {} bytes", file_size);
-        println!("Operations:  {}", operations);
-        println!();
+fn cmd_benchmark(pool_dir: &Path, workload: &perf::Workload, files: usize, keep: bool, json_output: bool) -> Result<()> {
+    if files == 0 || workload.file_size == 0 {
+        return Err(anyhow!("--files and --file-size must be at least 1"));
+    }
+    if !(0.0..=1.0).contains(&workload.read_ratio) {
+        return Err(anyhow!("--read-ratio must be between 0 and 1"));
     }
     
     let pool = DiskPool::load(pool_dir)?;
+    pool.require_key()?;
     let disks = pool.load_disks()?;
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
     let storage = StorageEngine::new(metadata, disks);
+    storage.set_compression(pool.compression);
+    storage.set_verify_writes(pool.verify_writes);
+    storage.set_extent_size(pool.extent_size)?;
+    storage.set_space_reserve_percent(pool.space_reserve_percent);
+    storage.set_space_watermarks(pool.space_watermarks);
+    storage.set_degraded_writes(pool.degraded_writes, pool.degraded_write_floor);
+    storage.set_metadata_commit_window(pool.metadata_commit_window_ms);
+    // Benchmarks are the first writes a filling pool turns away
+    storage.set_low_priority_writes(true);
     
-    // Create test data
-    let test_data = vec![42u8; file_size];
-    
-    // Benchmark write operations
-    let write_bench = Benchmark::start("write");
-    for i in 0..operations {
-        let ino = 1000 + i as u64;
-        match storage.write_file(ino, &test_data, 0) {
-            Ok(_) => {},
-            Err(e) => {
-                log::warn!("Write operation {} failed: {}", i, e);
-            }
+    let dir_name = format!("benchmark-{}", chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+    if !json_output {
+        println!("Running DynamicFS Performance Benchmark");
+        println!("======================================");
+        println!("Mode:        {:?} ({:?} sizes)", workload.mode, workload.pattern);
+        if workload.mode == perf::WorkloadMode::Mixed {
+            println!("Read ratio:  {:.2}", workload.read_ratio);
         }
+        println!("File size:   {} bytes", workload.file_size);
+        println!("Files:       {} in /{}", files, dir_name);
+        println!("Operations:  {} on {} thread(s)", workload.operations, workload.threads);
+        println!();
     }
-    let write_elapsed = write_bench.elapsed_ms();
-    let mut write_stats = PerfStats::new("write");
-    write_stats.count = operations as u64;
-    write_stats.total_bytes = (file_size as u64) * (operations as u64);
-    write_stats.total_ms = write_elapsed;
     
-    // Benchmark read operations
-    let read_bench = Benchmark::start("read");
-    for i in 0..operations {
-        let ino = 1000 + i as u64;
-        match storage.read_file(ino) {
-            Ok(_) => {},
-            Err(e) => {
-                log::warn!("Read operation {} failed: {}", i, e);
+    let dir = storage.create_dir(1, dir_name.clone())?.ino;
+    let mut inodes = Vec::with_capacity(files);
+    let result = run_benchmark(&storage, workload, dir, files, &mut inodes);
+    let commits = storage.metadata().read().unwrap().commit_stats();
+    
+    // Clean up even when the run failed part way through
+    if !keep {
+        for &ino in inodes.iter().chain(std::iter::once(&dir)) {
+            if let Err(e) = storage.delete_file(ino) {
+                log::warn!("Failed to remove benchmark file {}: {}", ino, e);
             }
         }
     }
-    let read_elapsed = read_bench.elapsed_ms();
-    let mut read_stats = PerfStats::new("read");
-    read_stats.count = operations as u64;
-    read_stats.total_bytes = (file_size as u64) * (operations as u64);
-    read_stats.total_ms = read_elapsed;
+    let result = result?;
     
     if json_output {
         let bench_json = serde_json::json!({
             "benchmark": "performance",
-            "file_size": file_size,
-            "operations": operations,
-            "write": {
-                "elapsed_ms": write_elapsed,
-                "throughput_mbps": write_stats.throughput_mbps(),
-                "ops_per_sec": write_stats.ops_per_sec()
-            },
-            "read": {
-                "elapsed_ms": read_elapsed,
-                "throughput_mbps": read_stats.throughput_mbps(),
-                "ops_per_sec": read_stats.ops_per_sec()
-            }
+            "mode": workload.mode,
+            "pattern": workload.pattern,
+            "read_ratio": workload.read_ratio,
+            "file_size": workload.file_size,
+            "files": files,
+            "operations": workload.operations,
+            "threads": workload.threads,
+            "elapsed_ms": result.elapsed_ms,
+            "directory": format!("/{}", dir_name),
+            "kept": keep,
+            "write": result.write,
+            "read": result.read,
+            "metadata_transactions": commits.transactions,
+            "metadata_syncs": commits.syncs,
         });
         println!("{}", serde_json::to_string_pretty(&bench_json)?);
     } else {
-        println!("Write Performance:");
-        println!("  Elapsed time:  {} ms", write_elapsed);
-        println!("  Throughput:    {:.2} MB/s", write_stats.throughput_mbps());
-        println!("  Operations:    {:.0} ops/sec", write_stats.ops_per_sec());
+        println!("Elapsed time:  {} ms", result.elapsed_ms);
+        println!("Metadata:      {} transactions in {} syncs", commits.transactions, commits.syncs);
+        for (name, summary) in [("Write", &result.write), ("Read", &result.read)] {
+            if summary.count == 0 && summary.errors == 0 {
+                continue;
+            }
+            let latency = &summary.latency_ms;
+            println!();
+            println!("{} Performance:", name);
+            println!("  Operations:    {} ({} failed)", summary.count, summary.errors);
+            println!("  Throughput:    {:.2} MB/s", summary.throughput_mbps);
+            println!("  Rate:          {:.0} ops/sec", summary.ops_per_sec);
+            println!("  Latency:       p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms", latency.p50, latency.p95, latency.p99, latency.max);
+        }
         println!();
-        println!("Read Performance:");
-        println!("  Elapsed time:  {} ms", read_elapsed);
-        println!("  Throughput:    {:.2} MB/s", read_stats.throughput_mbps());
-        println!("  Operations:    {:.0} ops/sec", read_stats.ops_per_sec());
+        if keep {
+            println!("Benchmark files kept in /{}", dir_name);
+        } else {
+            println!("Benchmark files removed");
+        }
     }
     
     Ok(())
 }
 
+/// Create the benchmark's files in `dir`, recording them in `inodes` for cleanup, and run it
+fn run_benchmark(storage: &StorageEngine, workload: &perf::Workload, dir: u64, files: usize, inodes: &mut Vec<u64>) -> Result<perf::WorkloadResult> {
+    for i in 0..files {
+        inodes.push(storage.create_file(dir, format!("file-{}", i))?.ino);
+    }
+    // Reads need something to read; filling the files is not timed
+    if workload.mode != perf::WorkloadMode::Write {
+        let data: Vec<u8> = (0..workload.file_size).map(|i| (i % 251) as u8).collect();
+        for &ino in inodes.iter() {
+            storage.write_file(ino, &data, 0)?;
+            storage.flush_file(ino)?;
+        }
+    }
+    Ok(workload.run(storage, inodes))
+}
+
 
 
 fn cmd_health(pool_dir: &Path, json_output: bool) -> Result<()> {
@@ -1189,47 +3879,18 @@ fn cmd_health(pool_dir: &Path, json_output: bool) -> Result<()> {
     let pool = DiskPool::load(pool_dir)?;
     let disks = pool.load_disks()?;
     let metadata = MetadataManager::new(pool_dir.to_path_buf())?;
-    let extents = metadata.list_all_extents()?;
     
     // Calculate health metrics
-    let mut healthy_disks = 0;
-    let mut degraded_disks = 0;
-    let mut failed_disks = 0;
-    let mut total_disk_capacity = 0u64;
-    let mut total_disk_used = 0u64;
-    
-    for disk in &disks {
-        match disk.health {
-            disk::DiskHealth::Healthy => healthy_disks += 1,
-            disk::DiskHealth::Failed => failed_disks += 1,
-            _ => degraded_disks += 1,
-        }
-        total_disk_capacity += disk.capacity_bytes;
-        total_disk_used += disk.used_bytes;
-    }
-    
-    let mut healthy_extents = 0;
-    let mut degraded_extents = 0;
-    let mut unreadable_extents = 0;
-    
-    for extent in &extents {
-        if extent.is_complete() {
-            healthy_extents += 1;
-        } else if extent.is_readable() {
-            degraded_extents += 1;
-        } else {
-            unreadable_extents += 1;
-        }
-    }
-    
-    // Determine overall health status
-    let health_status = if unreadable_extents > 0 {
-        "critical"
-    } else if failed_disks > 0 || degraded_extents > 0 {
-        "degraded"
-    } else {
-        "healthy"
-    };
+    let health = monitoring::PoolHealth::from_pool(&disks, &metadata.extent_totals());
+    let (healthy_disks, degraded_disks, failed_disks) = (health.healthy_disks, health.degraded_disks, health.failed_disks);
+    let total_disk_capacity = health.capacity_bytes();
+    let total_disk_used = health.used_bytes();
+    let (healthy_extents, degraded_extents, unreadable_extents) =
+        (health.healthy_extents, health.degraded_extents, health.unreadable_extents);
+    let health_status = health.status().as_str();
+    let space_level = pool.space_watermarks.level(total_disk_used, total_disk_capacity);
+    // Live figures only exist while the pool is mounted
+    let rebuild_status = control::mounted_rebuild_status(pool_dir);
     
     if json_output {
         let health_json = serde_json::json!({
@@ -1246,13 +3907,30 @@ fn cmd_health(pool_dir: &Path, json_output: bool) -> Result<()> {
                     (total_disk_used as f64 / total_disk_capacity as f64) * 100.0
                 } else {
                     0.0
-                }
+                },
+                "devices": disk_error_summary(&disks)
+            },
+            "space": {
+                "level": space_level,
+                "high_watermark_percent": pool.space_watermarks.high_percent,
+                "critical_watermark_percent": pool.space_watermarks.critical_percent,
+                "reserve_percent": pool.space_reserve_percent
             },
             "extents": {
-                "total": extents.len(),
+                "total": health.total_extents(),
                 "healthy": healthy_extents,
                 "degraded": degraded_extents,
-                "unreadable": unreadable_extents
+                "unreadable": unreadable_extents,
+                "below_policy": health.below_policy_extents
+            },
+            "rebuild": {
+                "limits": pool.rebuild_limits,
+                "mounted": rebuild_status.is_some(),
+                "idle": rebuild_status.as_ref().map(|s| s.idle),
+                "running": rebuild_status.as_ref().map(|s| s.running),
+                "max_concurrent": rebuild_status.as_ref().map(|s| s.max_concurrent),
+                "max_bytes_per_sec": rebuild_status.as_ref().map(|s| s.max_bytes_per_sec),
+                "throughput_bytes_per_sec": rebuild_status.as_ref().map(|s| s.throughput_bytes_per_sec)
             },
             "metrics": {
                 "iops": 0,
@@ -1281,19 +3959,65 @@ fn cmd_health(pool_dir: &Path, json_output: bool) -> Result<()> {
                 0.0
             }
         );
+        println!(
+            "  Watermarks: {}% high, {}% critical (now {})",
+            pool.space_watermarks.high_percent,
+            pool.space_watermarks.critical_percent,
+            space_level.name()
+        );
+        for disk in disks.iter().filter(|d| d.recent_io_errors() > 0 || d.health != disk::DiskHealth::Healthy) {
+            println!(
+                "  {} {:?}: {} I/O errors in last {}s, {} corrupt fragments",
+                disk.uuid,
+                disk.health,
+                disk.recent_io_errors(),
+                disk.health_policy.window_secs,
+                disk.corruption_count
+            );
+        }
         println!();
         println!("Data Integrity:");
         println!("  Healthy extents:   {}", healthy_extents);
         println!("  Degraded extents:  {}", degraded_extents);
         println!("  Unreadable extents: {}", unreadable_extents);
+        println!("  Below policy:      {}", health.below_policy_extents);
+        println!();
+        println!("Rebuild:");
+        let limits = pool.rebuild_limits;
+        println!("  Busy limit: {} concurrent, {}", limits.max_concurrent, format_rebuild_rate(limits.max_bytes_per_sec));
+        println!(
+            "  Idle limit: {} concurrent, {} after {}s without I/O",
+            limits.idle_max_concurrent,
+            format_rebuild_rate(limits.idle_max_bytes_per_sec),
+            limits.idle_after_secs
+        );
+        match &rebuild_status {
+            Some(status) => println!(
+                "  Running:    {} of {} ({}), {:.1} MiB/s",
+                status.running,
+                status.max_concurrent,
+                if status.idle { "idle" } else { "busy" },
+                status.throughput_bytes_per_sec as f64 / (1024.0 * 1024.0)
+            ),
+            None => println!("  Running:    pool not mounted"),
+        }
         println!();
         
         if unreadable_extents > 0 {
             println!("⚠ CRITICAL: {} unreadable extents - immediate action required!", unreadable_extents);
+        } else if space_level == placement::SpaceLevel::Critical {
+            println!("⚠ CRITICAL: pool past its critical space watermark - new data is refused until files are deleted");
         } else if failed_disks > 0 {
             println!("⚠ WARNING: {} failed disks - rebuild in progress", failed_disks);
         } else if degraded_extents > 0 {
             println!("⚠ NOTICE: {} degraded extents - rebuild recommended", degraded_extents);
+        } else if space_level == placement::SpaceLevel::High {
+            println!("⚠ WARNING: pool past its high space watermark - reclaiming space, low-priority writes refused");
+        } else if health.below_policy_extents > 0 {
+            println!(
+                "⚠ NOTICE: {} extents below their intended policy - upgraded once enough disks are writable",
+                health.below_policy_extents
+            );
         } else {
             println!("✓ All systems nominal");
         }
@@ -1303,6 +4027,173 @@ fn cmd_health(pool_dir: &Path, json_output: bool) -> Result<()> {
 }
 
 
+fn cmd_quota(action: QuotaAction, json_output: bool) -> Result<()> {
+    match action {
+        QuotaAction::Set { pool, path, bytes, inodes } => {
+            if bytes.is_none() && inodes.is_none() {
+                return Err(anyhow::anyhow!("Specify --bytes, --inodes or both"));
+            }
+            let metadata = MetadataManager::new(pool)?;
+            let dir = quota_dir(&metadata, &path)?;
+            let mut quota = quota::Quota::new(
+                dir.ino,
+                bytes.as_deref().map(quota::parse_limit).transpose()?,
+                inodes.as_deref().map(quota::parse_limit).transpose()?,
+            );
+            (quota.bytes_used, quota.inodes_used) = metadata.tree_usage(dir.ino)?;
+            metadata.save_quota(&quota)?;
+            print_quotas(&metadata, &[quota], json_output)
+        }
+
+        QuotaAction::Show { pool, path } => {
+            let metadata = MetadataManager::new(pool)?;
+            let quotas = match path {
+                Some(path) => {
+                    let dir = quota_dir(&metadata, &path)?;
+                    vec![metadata
+                        .load_quota(dir.ino)?
+                        .ok_or_else(|| anyhow::anyhow!("No quota on {}", path))?]
+                }
+                None => metadata.list_quotas()?,
+            };
+            print_quotas(&metadata, &quotas, json_output)
+        }
+
+        QuotaAction::Remove { pool, path } => {
+            let metadata = MetadataManager::new(pool)?;
+            let dir = quota_dir(&metadata, &path)?;
+            if metadata.load_quota(dir.ino)?.is_none() {
+                return Err(anyhow::anyhow!("No quota on {}", path));
+            }
+            metadata.delete_quota(dir.ino)?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "removed": path }))?);
+            } else {
+                println!("✓ Removed quota on {}", path);
+            }
+            Ok(())
+        }
+
+        QuotaAction::Recalc { pool, path } => {
+            let metadata = MetadataManager::new(pool)?;
+            let mut quotas = match path {
+                Some(path) => {
+                    let dir = quota_dir(&metadata, &path)?;
+                    vec![metadata
+                        .load_quota(dir.ino)?
+                        .ok_or_else(|| anyhow::anyhow!("No quota on {}", path))?]
+                }
+                None => metadata.list_quotas()?,
+            };
+            for quota in &mut quotas {
+                let (bytes, inodes) = metadata.tree_usage(quota.dir_ino)?;
+                if (bytes, inodes) != (quota.bytes_used, quota.inodes_used) {
+                    log::warn!(
+                        "Quota usage of directory {} drifted: {} bytes/{} inodes recorded, {} bytes/{} inodes found",
+                        quota.dir_ino, quota.bytes_used, quota.inodes_used, bytes, inodes
+                    );
+                }
+                (quota.bytes_used, quota.inodes_used) = (bytes, inodes);
+                metadata.save_quota(quota)?;
+            }
+            print_quotas(&metadata, &quotas, json_output)
+        }
+    }
+}
+
+/// Directory a quota command applies to
+fn quota_dir(metadata: &MetadataManager, path: &str) -> Result<metadata::Inode> {
+    let dir = metadata.resolve_path(path)?;
+    if dir.file_type != metadata::FileType::Directory {
+        return Err(anyhow::anyhow!("Not a directory: {}", path));
+    }
+    Ok(dir)
+}
+
+fn print_quotas(metadata: &MetadataManager, quotas: &[quota::Quota], json_output: bool) -> Result<()> {
+    let limit = |limit: Option<u64>| limit.map_or("unlimited".to_string(), |limit| limit.to_string());
+    if json_output {
+        let entries: Vec<serde_json::Value> = quotas
+            .iter()
+            .map(|quota| {
+                serde_json::json!({
+                    "path": metadata.path_of(quota.dir_ino).ok(),
+                    "dir_ino": quota.dir_ino,
+                    "bytes_limit": quota.bytes_limit,
+                    "bytes_used": quota.bytes_used,
+                    "inodes_limit": quota.inodes_limit,
+                    "inodes_used": quota.inodes_used,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if quotas.is_empty() {
+        println!("No quotas set");
+    }
+    for quota in quotas {
+        let path = metadata.path_of(quota.dir_ino).unwrap_or_else(|_| format!("<inode {}>", quota.dir_ino).into());
+        println!("{}", path);
+        println!("  Bytes:  {} / {}", quota.bytes_used, limit(quota.bytes_limit));
+        println!("  Inodes: {} / {}", quota.inodes_used, limit(quota.inodes_limit));
+    }
+    Ok(())
+}
+
+fn cmd_snapshot(action: SnapshotAction, json_output: bool) -> Result<()> {
+    use crate::control::{ControlReply, ControlRequest};
+    use crate::snapshots::SnapshotInfo;
+
+    // A mounted pool changes through its own engine, which has the buffered writes
+    let change = |pool_dir: &Path, request: ControlRequest, offline: &dyn Fn(&StorageEngine) -> Result<SnapshotInfo>| {
+        match control::request_mounted(pool_dir, &request) {
+            Some(ControlReply::Snapshot { snapshot }) => Ok(snapshot),
+            Some(ControlReply::Error { message }) => Err(anyhow!("Mounted pool refused: {}", message)),
+            Some(reply) => Err(anyhow!("Unexpected reply to {:?}: {:?}", request, reply)),
+            None => {
+                let _lock = lock_pool(pool_dir, "snapshot", None, "run it again to go through the mount")?;
+                offline(&open_storage(pool_dir)?)
+            }
+        }
+    };
+    let (verb, snapshot) = match action {
+        SnapshotAction::Create { pool, name } => {
+            let request = ControlRequest::CreateSnapshot { name: name.clone() };
+            ("Created", change(&pool, request, &|storage| Ok(storage.create_snapshot(&name)?))?)
+        }
+        SnapshotAction::Delete { pool, name } => {
+            let request = ControlRequest::DeleteSnapshot { name: name.clone() };
+            ("Deleted", change(&pool, request, &|storage| Ok(storage.delete_snapshot(&name)?))?)
+        }
+        SnapshotAction::List { pool } => return print_snapshots(&SnapshotInfo::list(&pool)?, json_output),
+    };
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&snapshot)?);
+    } else {
+        println!("✓ {} snapshot {} ({} files, {} bytes)", verb, snapshot.name, snapshot.files, snapshot.bytes);
+    }
+    Ok(())
+}
+
+fn print_snapshots(snapshots: &[crate::snapshots::SnapshotInfo], json_output: bool) -> Result<()> {
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(snapshots)?);
+        return Ok(());
+    }
+
+    if snapshots.is_empty() {
+        println!("No snapshots");
+    }
+    for snapshot in snapshots {
+        println!("{}", snapshot.name);
+        println!("  Created: {}", format_timestamp(snapshot.created_at));
+        println!("  Files:   {} ({} bytes)", snapshot.files, snapshot.bytes);
+    }
+    Ok(())
+}
+
 fn cmd_scrub_daemon(action: ScrubDaemonAction, json_output: bool) -> Result<()> {
     
     
@@ -1357,6 +4248,10 @@ fn cmd_scrub_daemon(action: ScrubDaemonAction, json_output: bool) -> Result<()>
             let daemon = ScrubDaemon::new();
             let progress = daemon.get_progress();
             let metrics = daemon.get_metrics();
+            // Journaled by the mount, so readable whether or not one is running
+            let settle = std::time::Duration::from_secs(crate::config::PoolConfig::load(&pool)?.recent_write_settle_secs);
+            let recent = recent_writes::RecentWriteQueue::open(&pool, usize::MAX)?
+                .status(chrono::Utc::now().timestamp(), settle);
             
             if json_output {
                 let result = serde_json::json!({
@@ -1369,7 +4264,8 @@ fn cmd_scrub_daemon(action: ScrubDaemonAction, json_output: bool) -> Result<()>
                         "issues_found": metrics.issues_found,
                         "repairs_triggered": metrics.repairs_triggered,
                         "io_bytes": metrics.scrub_io_bytes,
-                    }
+                    },
+                    "recent_writes": recent,
                 });
                 println!("{}", serde_json::to_string_pretty(&result)?);
             } else {
@@ -1385,6 +4281,14 @@ fn cmd_scrub_daemon(action: ScrubDaemonAction, json_output: bool) -> Result<()>
                 println!("  Issues found:       {}", metrics.issues_found);
                 println!("  Repairs triggered:  {}", metrics.repairs_triggered);
                 println!("  I/O bytes:          {}", metrics.scrub_io_bytes);
+                println!();
+                println!("Recent writes:");
+                println!("  Queued:             {}", recent.queued);
+                println!("  Due:                {}", recent.due);
+                if let Some(oldest) = recent.oldest_placed_at.and_then(|at| chrono::DateTime::from_timestamp(at, 0)) {
+                    println!("  Oldest placed:      {}", oldest.to_rfc3339());
+                }
+                println!("  Verification lag:   {}s", recent.lag_secs);
             }
             Ok(())
         }
@@ -1503,18 +4407,14 @@ fn cmd_metrics_server(
     bind: &str,
     json_output: bool
 ) -> Result<()> {
-    use std::io::{Read, Write};
-    use std::net::TcpListener;
-    use std::sync::Arc;
-    use monitoring::PrometheusExporter;
+    use monitoring::{MetricsServer, PrometheusExporter};
     
-    // Load metrics (in a real implementation, this would load from the pool)
+    // Counters live in the mount process; `mount --metrics-port` exports the live ones
     let metrics = Arc::new(Metrics::new());
     let exporter = PrometheusExporter::new(metrics);
     
     let addr = format!("{}:{}", bind, port);
-    let listener = TcpListener::bind(&addr)
-        .context(format!("Failed to bind to {}", addr))?;
+    let server = MetricsServer::start(&addr, exporter, None)?;
     
     if json_output {
         let result = serde_json::json!({
@@ -1534,56 +4434,6 @@ fn cmd_metrics_server(
         println!("Press Ctrl+C to stop...");
     }
     
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let mut buffer = [0; 1024];
-                match stream.read(&mut buffer) {
-                    Ok(bytes_read) => {
-                        let request = String::from_utf8_lossy(&buffer[..bytes_read]);
-                        
-                        if request.contains("GET /metrics") {
-                            let metrics_text = exporter.export();
-                            let response = format!(
-                                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
-                                metrics_text.len(),
-                                metrics_text
-                            );
-                            if let Err(e) = stream.write_all(response.as_bytes()) {
-                                log::warn!("Failed to write metrics response: {}", e);
-                            }
-                        } else if request.contains("GET /health") {
-                            let health = r#"{"status":"ok"}"#;
-                            let response = format!(
-                                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
-                                health.len(),
-                                health
-                            );
-                            if let Err(e) = stream.write_all(response.as_bytes()) {
-                                log::warn!("Failed to write health response: {}", e);
-                            }
-                        } else {
-                            let not_found = "404 Not Found\nAvailable endpoints:\n  /metrics - Prometheus metrics\n  /health - Health check\n";
-                            let response = format!(
-                                "HTTP/1.1 404 NOT FOUND\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
-                                not_found.len(),
-                                not_found
-                            );
-                            if let Err(e) = stream.write_all(response.as_bytes()) {
-                                log::debug!("Failed to write 404 response: {}", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Failed to read request: {}", e);
-                    }
-                }
-            }
-            Err(e) => {
-                log::error!("Connection failed: {}", e);
-            }
-        }
-    }
-    
+    server.join();
     Ok(())
 }
@@ -0,0 +1,526 @@
+//! Integrity manifests for `dynamicfs manifest create` and `dynamicfs manifest verify`
+//!
+//! A manifest is a JSON file listing every regular file under a directory of
+//! the pool with its size, mtime and the BLAKE3 hash of its contents, so the
+//! files can be checked from outside the pool: a file's `hash` is what
+//! `b3sum` prints for it on the mounted filesystem.
+//!
+//! `root_hash` covers the whole list. It is the BLAKE3 hash of, for each
+//! entry in byte order of `path`: the path's bytes, a zero byte, `size` as a
+//! little-endian `u64`, `mtime` as a little-endian `i64`, `mtime_nsec` as a
+//! little-endian `u32` and the 32 bytes of `hash`. A signed manifest carries
+//! an ed25519 signature of `scfs-manifest-v1` followed by the 32 bytes of
+//! `root_hash`, and the public key to check it with.
+//!
+//! Each entry also records `extent_digest`, a hash of the checksums of the
+//! extents holding the file. Verifying compares it first and only reads a
+//! file back when it differs, unless every file is to be rehashed.
+
+use anyhow::{anyhow, bail, Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::export::{collect_tree, Progress};
+use crate::metadata::{ExtentMap, FileName, FileType, Inode};
+use crate::storage::StorageEngine;
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Prefix of the signed message, ahead of the root hash
+const SIGNATURE_CONTEXT: &[u8] = b"scfs-manifest-v1";
+
+/// Extents read back per call while hashing a file
+const CHUNK_EXTENTS: usize = 4;
+
+/// One regular file of a manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path from the pool root, starting with `/`
+    pub path: FileName,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    /// BLAKE3 of the file's contents, hex
+    pub hash: String,
+    /// BLAKE3 over the extent size, file size and extent checksums, hex
+    pub extent_digest: String,
+}
+
+/// Ed25519 signature of a manifest's root hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSignature {
+    /// Hex of the 32-byte public key
+    pub public_key: String,
+    /// Hex of the 64-byte signature
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub created_at: i64,
+    /// Pool directory the manifest was taken from
+    pub pool: String,
+    /// Directory or file of the pool the manifest covers
+    pub root: String,
+    pub files: u64,
+    pub bytes: u64,
+    /// See the module documentation
+    pub root_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ManifestSignature>,
+    /// Sorted by path
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path).with_context(|| format!("Failed to read manifest {:?}", path))?;
+        let manifest: Manifest =
+            serde_json::from_slice(&contents).with_context(|| format!("Manifest {:?} is not valid JSON", path))?;
+        if manifest.version != MANIFEST_VERSION {
+            bail!("Manifest {:?} has version {}; this build reads version {}", path, manifest.version, MANIFEST_VERSION);
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write manifest {:?}", path))
+    }
+
+    /// Sign the root hash with the 32-byte ed25519 secret key `secret`
+    pub fn sign(&mut self, secret: &[u8; 32]) -> Result<()> {
+        let key = SigningKey::from_bytes(secret);
+        let signature = key.sign(&signed_message(&self.root_hash)?);
+        self.signature = Some(ManifestSignature {
+            public_key: to_hex(key.verifying_key().as_bytes()),
+            signature: to_hex(&signature.to_bytes()),
+        });
+        Ok(())
+    }
+
+    /// Check the entries against the root hash and the root hash against the signature
+    ///
+    /// With `trusted_key` the signature must have been made with it; without,
+    /// it is checked against the key the manifest carries, which only shows
+    /// the manifest was not damaged since it was signed.
+    pub fn check(&self, trusted_key: Option<&[u8; 32]>) -> Result<SignatureStatus> {
+        let root_hash = to_hex(&root_hash(&self.entries));
+        if root_hash != self.root_hash {
+            bail!("Manifest entries do not match its root hash {}; the manifest was changed", self.root_hash);
+        }
+        let Some(signature) = &self.signature else {
+            if trusted_key.is_some() {
+                bail!("Manifest is not signed");
+            }
+            return Ok(SignatureStatus::Unsigned);
+        };
+        let public_key: [u8; 32] = from_hex(&signature.public_key)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow!("Manifest public key is not 64 hex digits"))?;
+        if trusted_key.is_some_and(|trusted| *trusted != public_key) {
+            bail!("Manifest was signed with key {}, not the trusted key", signature.public_key);
+        }
+        let bytes: [u8; 64] = from_hex(&signature.signature)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| anyhow!("Manifest signature is not 128 hex digits"))?;
+        VerifyingKey::from_bytes(&public_key)
+            .map_err(|e| anyhow!("Manifest public key is invalid: {}", e))?
+            .verify(&signed_message(&self.root_hash)?, &Signature::from_bytes(&bytes))
+            .map_err(|_| anyhow!("Manifest signature does not match its root hash"))?;
+        Ok(if trusted_key.is_some() { SignatureStatus::Trusted } else { SignatureStatus::Untrusted })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureStatus {
+    Unsigned,
+    /// Valid for the key the manifest carries, which was not checked against a trusted one
+    Untrusted,
+    /// Valid for the trusted public key given
+    Trusted,
+}
+
+/// A file whose size, contents or mtime differ from its manifest entry
+#[derive(Debug, Clone, Serialize)]
+pub struct ModifiedFile {
+    pub path: FileName,
+    /// `size`, `content` or `mtime`, the first that differs
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub root: String,
+    pub signature: SignatureStatus,
+    pub checked: u64,
+    /// Files read back and hashed, because their extents changed or all were to be
+    pub rehashed: u64,
+    pub added: Vec<FileName>,
+    pub removed: Vec<FileName>,
+    pub modified: Vec<ModifiedFile>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// Hash every regular file under `root` into a manifest
+///
+/// `root` may also name a single file. Files are hashed on `threads` threads.
+pub fn create(
+    storage: &StorageEngine,
+    pool_dir: &Path,
+    root: &str,
+    threads: usize,
+    progress: impl FnMut(&Progress) + Send,
+) -> Result<Manifest> {
+    let root = normalize_root(root);
+    let files = collect_files(storage, &root)?;
+    let total_files = files.len() as u64;
+    let progress = Mutex::new(progress);
+    let done = (AtomicU64::new(0), AtomicU64::new(0));
+
+    let entries = parallel_map(&files, threads, |(path, inode)| {
+        let entry = ManifestEntry {
+            path: path.clone(),
+            size: inode.size,
+            mtime: inode.mtime,
+            mtime_nsec: inode.mtime_nsec,
+            hash: to_hex(&content_hash(storage, inode)?),
+            extent_digest: to_hex(&extent_digest(storage, inode)?),
+        };
+        let files = done.0.fetch_add(1, Ordering::SeqCst) + 1;
+        let bytes = done.1.fetch_add(inode.size, Ordering::SeqCst) + inode.size;
+        (progress.lock().unwrap())(&Progress { path, files, total_files: Some(total_files), bytes });
+        Ok(entry)
+    })?;
+
+    Ok(Manifest {
+        version: MANIFEST_VERSION,
+        created_at: chrono::Utc::now().timestamp(),
+        pool: pool_dir.display().to_string(),
+        root,
+        files: total_files,
+        bytes: entries.iter().map(|entry| entry.size).sum(),
+        root_hash: to_hex(&root_hash(&entries)),
+        signature: None,
+        entries,
+    })
+}
+
+/// Compare the files under the manifest's root with its entries
+///
+/// Files whose extent digest still matches are taken as unchanged without
+/// reading them, unless `full` is set. `check` the manifest itself first.
+pub fn verify(
+    storage: &StorageEngine,
+    manifest: &Manifest,
+    signature: SignatureStatus,
+    full: bool,
+    threads: usize,
+    progress: impl FnMut(&Progress) + Send,
+) -> Result<VerifyReport> {
+    let mut current: BTreeMap<FileName, Inode> = collect_files(storage, &manifest.root)?.into_iter().collect();
+    let mut report = VerifyReport {
+        root: manifest.root.clone(),
+        signature,
+        checked: 0,
+        rehashed: 0,
+        added: Vec::new(),
+        removed: Vec::new(),
+        modified: Vec::new(),
+    };
+    let mut common = Vec::new();
+    for entry in &manifest.entries {
+        match current.remove(&entry.path) {
+            Some(inode) => common.push((entry, inode)),
+            None => report.removed.push(entry.path.clone()),
+        }
+    }
+    report.added = current.into_keys().collect();
+
+    let total_files = common.len() as u64;
+    let progress = Mutex::new(progress);
+    let done = (AtomicU64::new(0), AtomicU64::new(0));
+    let rehashed = AtomicU64::new(0);
+    let results = parallel_map(&common, threads, |(entry, inode)| {
+        let reason = if inode.size != entry.size {
+            Some("size")
+        } else {
+            let unchanged_extents = !full && to_hex(&extent_digest(storage, inode)?) == entry.extent_digest;
+            if !unchanged_extents {
+                rehashed.fetch_add(1, Ordering::SeqCst);
+            }
+            if !unchanged_extents && to_hex(&content_hash(storage, inode)?) != entry.hash {
+                Some("content")
+            } else if (inode.mtime, inode.mtime_nsec) != (entry.mtime, entry.mtime_nsec) {
+                Some("mtime")
+            } else {
+                None
+            }
+        };
+        let files = done.0.fetch_add(1, Ordering::SeqCst) + 1;
+        let bytes = done.1.fetch_add(inode.size, Ordering::SeqCst) + inode.size;
+        (progress.lock().unwrap())(&Progress { path: &entry.path, files, total_files: Some(total_files), bytes });
+        Ok(reason.map(|reason| ModifiedFile { path: entry.path.clone(), reason }))
+    })?;
+
+    report.checked = total_files;
+    report.rehashed = rehashed.into_inner();
+    report.modified = results.into_iter().flatten().collect();
+    Ok(report)
+}
+
+/// `/` for the pool root, otherwise the path without a trailing slash
+fn normalize_root(root: &str) -> String {
+    let trimmed = root.trim_end_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else if trimmed.starts_with('/') {
+        trimmed.to_string()
+    } else {
+        format!("/{}", trimmed)
+    }
+}
+
+/// Regular files under `root` with their paths, in path order
+fn collect_files(storage: &StorageEngine, root: &str) -> Result<Vec<(FileName, Inode)>> {
+    let inode = storage
+        .metadata()
+        .read()
+        .unwrap()
+        .resolve_path(root)
+        .with_context(|| format!("Failed to find {} in the pool", root))?;
+    let mut entries = Vec::new();
+    if inode.file_type == FileType::Directory {
+        collect_tree(storage, inode.ino, &FileName::from(root.trim_end_matches('/')), &mut entries)?;
+    } else {
+        entries.push((FileName::from(root), inode));
+    }
+    entries.retain(|(_, inode)| inode.file_type == FileType::RegularFile);
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/// `f` applied to every item on `threads` threads, results in item order
+///
+/// The first error stops the remaining work and is returned.
+fn parallel_map<T: Sync, R: Send>(items: &[T], threads: usize, f: impl Fn(&T) -> Result<R> + Sync) -> Result<Vec<R>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let error = Mutex::new(None);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                while !failed.load(Ordering::SeqCst) {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(index) else { break };
+                    match f(item) {
+                        Ok(result) => results.lock().unwrap()[index] = Some(result),
+                        Err(e) => {
+                            failed.store(true, Ordering::SeqCst);
+                            error.lock().unwrap().get_or_insert(e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+    Ok(results.into_inner().unwrap().into_iter().flatten().collect())
+}
+
+/// BLAKE3 of a file's contents, read through the storage read path
+fn content_hash(storage: &StorageEngine, inode: &Inode) -> Result<[u8; 32]> {
+    let chunk = (storage.extent_size() * CHUNK_EXTENTS) as u64;
+    let mut hasher = blake3::Hasher::new();
+    let mut offset = 0;
+    while offset < inode.size {
+        let data = storage
+            .read_range_uncached(inode.ino, offset, chunk.min(inode.size - offset))
+            .with_context(|| format!("Failed to read inode {} at offset {}", inode.ino, offset))?;
+        if data.is_empty() {
+            bail!("Inode {} ended at {} bytes of {}", inode.ino, offset, inode.size);
+        }
+        hasher.update(&data);
+        offset += data.len() as u64;
+    }
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// Hash of what the extent map says the file holds, without reading it
+///
+/// Holes hash as 32 zero bytes in place of an extent checksum.
+fn extent_digest(storage: &StorageEngine, inode: &Inode) -> Result<[u8; 32]> {
+    let metadata = storage.metadata();
+    let metadata = metadata.read().unwrap();
+    let map = metadata.load_extent_map(inode.ino)?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&(map.extent_size as u64).to_le_bytes());
+    hasher.update(&inode.size.to_le_bytes());
+    for uuid in &map.extents {
+        if ExtentMap::is_hole(uuid) {
+            hasher.update(&[0u8; 32]);
+        } else {
+            hasher.update(&metadata.load_extent(uuid)?.checksum);
+        }
+    }
+    Ok(*hasher.finalize().as_bytes())
+}
+
+fn root_hash(entries: &[ManifestEntry]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    for entry in entries {
+        hasher.update(entry.path.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(&entry.size.to_le_bytes());
+        hasher.update(&entry.mtime.to_le_bytes());
+        hasher.update(&entry.mtime_nsec.to_le_bytes());
+        // A hash that is not hex cannot match any file; hash it as zeros
+        let hash = from_hex(&entry.hash).and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()).unwrap_or([0; 32]);
+        hasher.update(&hash);
+    }
+    *hasher.finalize().as_bytes()
+}
+
+fn signed_message(root_hash: &str) -> Result<Vec<u8>> {
+    let hash = from_hex(root_hash)
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or_else(|| anyhow!("Manifest root hash is not 64 hex digits"))?;
+    Ok([SIGNATURE_CONTEXT, &hash].concat())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::Disk;
+    use crate::extent::DEFAULT_EXTENT_SIZE;
+    use crate::metadata::MetadataManager;
+    use tempfile::TempDir;
+
+    fn setup_storage() -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = TempDir::new().unwrap();
+        let disk_dirs: Vec<TempDir> = (0..6).map(|_| TempDir::new().unwrap()).collect();
+        let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        storage.set_space_reserve_percent(0);
+        (pool_dir, disk_dirs, storage)
+    }
+
+    fn write(storage: &StorageEngine, parent: u64, name: &str, data: &[u8]) -> Inode {
+        let inode = storage.create_file(parent, name.to_string()).unwrap();
+        storage.write_file(inode.ino, data, 0).unwrap();
+        storage.get_inode(inode.ino).unwrap()
+    }
+
+    /// Two files under /docs, one large enough for erasure coding, and one at the root
+    fn populate(storage: &StorageEngine) -> Vec<u8> {
+        let docs = storage.create_dir(1, "docs".to_string()).unwrap();
+        let mut large = vec![0u8; DEFAULT_EXTENT_SIZE + 4321];
+        blake3::Hasher::new().update(b"manifest").finalize_xof().fill(&mut large);
+        write(storage, docs.ino, "large.bin", &large);
+        write(storage, docs.ino, "note.txt", b"first note");
+        write(storage, 1, "top.txt", b"top level");
+        large
+    }
+
+    #[test]
+    fn test_create_hashes_contents_and_covers_subtrees() {
+        let (pool_dir, _disks, storage) = setup_storage();
+        let large = populate(&storage);
+
+        let manifest = create(&storage, pool_dir.path(), "/", 3, |_| {}).unwrap();
+        let paths: Vec<&FileName> = manifest.entries.iter().map(|entry| &entry.path).collect();
+        assert_eq!(paths, ["/docs/large.bin", "/docs/note.txt", "/top.txt"]);
+        assert_eq!(manifest.entries[0].hash, blake3::hash(&large).to_hex().to_string());
+        assert_eq!(manifest.bytes, large.len() as u64 + 19);
+        assert_eq!(manifest.check(None).unwrap(), SignatureStatus::Unsigned);
+
+        let docs = create(&storage, pool_dir.path(), "docs/", 1, |_| {}).unwrap();
+        assert_eq!(docs.root, "/docs");
+        assert_eq!(docs.entries, manifest.entries[..2]);
+        let single = create(&storage, pool_dir.path(), "/top.txt", 1, |_| {}).unwrap();
+        assert_eq!(single.entries, manifest.entries[2..]);
+    }
+
+    #[test]
+    fn test_verify_reports_added_removed_and_modified() {
+        let (pool_dir, _disks, storage) = setup_storage();
+        populate(&storage);
+        let manifest = create(&storage, pool_dir.path(), "/", 2, |_| {}).unwrap();
+
+        let clean = verify(&storage, &manifest, SignatureStatus::Unsigned, false, 2, |_| {}).unwrap();
+        assert!(clean.is_clean());
+        assert_eq!((clean.checked, clean.rehashed), (3, 0), "unchanged extents need no reads");
+        let full = verify(&storage, &manifest, SignatureStatus::Unsigned, true, 2, |_| {}).unwrap();
+        assert!(full.is_clean());
+        assert_eq!(full.rehashed, 3);
+
+        // Same size, new contents; a new file; a removed file
+        let note = storage.metadata().read().unwrap().resolve_path("/docs/note.txt").unwrap();
+        storage.write_file(note.ino, b"other note", 0).unwrap();
+        write(&storage, 1, "new.txt", b"new");
+        let top = storage.metadata().read().unwrap().resolve_path("/top.txt").unwrap();
+        storage.delete_file(top.ino).unwrap();
+
+        let report = verify(&storage, &manifest, SignatureStatus::Unsigned, false, 2, |_| {}).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.added, ["/new.txt"]);
+        assert_eq!(report.removed, ["/top.txt"]);
+        assert_eq!(report.modified.len(), 1);
+        assert_eq!(report.modified[0].path, "/docs/note.txt");
+        assert_eq!(report.modified[0].reason, "content");
+    }
+
+    #[test]
+    fn test_signatures_and_tampering() {
+        let (pool_dir, _disks, storage) = setup_storage();
+        populate(&storage);
+        let mut manifest = create(&storage, pool_dir.path(), "/", 1, |_| {}).unwrap();
+        let secret = [7u8; 32];
+        let public = *SigningKey::from_bytes(&secret).verifying_key().as_bytes();
+        assert!(manifest.check(Some(&public)).is_err(), "an unsigned manifest is not trusted");
+
+        manifest.sign(&secret).unwrap();
+        let path = pool_dir.path().join("manifest.json");
+        manifest.save(&path).unwrap();
+        let manifest = Manifest::load(&path).unwrap();
+        assert_eq!(manifest.check(None).unwrap(), SignatureStatus::Untrusted);
+        assert_eq!(manifest.check(Some(&public)).unwrap(), SignatureStatus::Trusted);
+        assert!(manifest.check(Some(&[9u8; 32])).is_err());
+
+        // An edited entry no longer matches the root hash
+        let mut edited = manifest.clone();
+        edited.entries[1].size += 1;
+        assert!(edited.check(None).is_err());
+        // A recomputed root hash no longer matches the signature
+        edited.root_hash = to_hex(&root_hash(&edited.entries));
+        assert!(edited.check(None).is_err());
+    }
+}
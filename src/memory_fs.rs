@@ -0,0 +1,389 @@
+//! In-memory `FilesystemInterface` for tests and embedders
+//!
+//! `MemoryFs` keeps inodes, directory entries, file contents and extended
+//! attributes in hash maps, with none of the storage engine's disks, extents
+//! or journal. Inode numbers are handed out in order starting after the root
+//! (inode 1), so tests can predict them. `fail_next` makes the next calls of
+//! a chosen method fail with a chosen I/O error, to drive error paths such as
+//! ENOSPC or EROFS in the FUSE handlers.
+
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::error::StorageError;
+use crate::fs_interface::{FilesystemInterface, FilesystemStats};
+use crate::metadata::{now_timespec, FileName, FileType, Inode, ORPHAN_PARENT_INO};
+
+/// Capacity `stat` reports, so free space is finite
+pub const MEMORY_FS_CAPACITY: u64 = 1024 * 1024 * 1024;
+
+/// A `FilesystemInterface` method that can be made to fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FsMethod {
+    ReadFile,
+    WriteFile,
+    CreateFile,
+    CreateDir,
+    DeleteFile,
+    OrphanFile,
+    DeleteDir,
+    GetInode,
+    ListDirectory,
+    FindChild,
+    UpdateInode,
+    Stat,
+    PunchHole,
+    ZeroRange,
+    FlushFile,
+    SyncInode,
+}
+
+/// Failures still to be returned by one method
+#[derive(Debug, Clone, Copy)]
+struct Fault {
+    remaining: usize,
+    kind: std::io::ErrorKind,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Inodes without their xattrs, which live in `xattrs`
+    inodes: HashMap<u64, Inode>,
+    /// Directory entries by parent inode, name to inode
+    entries: HashMap<u64, BTreeMap<FileName, u64>>,
+    contents: HashMap<u64, Vec<u8>>,
+    xattrs: HashMap<u64, BTreeMap<String, Vec<u8>>>,
+    next_ino: u64,
+    faults: HashMap<FsMethod, Fault>,
+}
+
+/// A filesystem held entirely in memory
+///
+/// Clones share one filesystem, so a test can keep a handle to the one it
+/// mounted or passed to `DynamicFS`.
+#[derive(Debug, Clone)]
+pub struct MemoryFs {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    /// An empty filesystem holding only the root directory
+    pub fn new() -> Self {
+        let mut state = State { next_ino: 2, ..Default::default() };
+        state.inodes.insert(1, Inode::new_dir(1, 1, FileName::default()));
+        state.entries.insert(1, BTreeMap::new());
+        MemoryFs { state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Fail the next `count` calls of `method` with an I/O error of `kind`
+    ///
+    /// Replaces any failures still pending for `method`; a count of 0 clears them.
+    pub fn fail_next(&self, method: FsMethod, count: usize, kind: std::io::ErrorKind) {
+        let mut state = self.state.lock().unwrap();
+        if count == 0 {
+            state.faults.remove(&method);
+        } else {
+            state.faults.insert(method, Fault { remaining: count, kind });
+        }
+    }
+
+    /// Lock the state, or return the failure injected for `method`
+    fn enter(&self, method: FsMethod) -> Result<std::sync::MutexGuard<'_, State>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(fault) = state.faults.get_mut(&method) {
+            let kind = fault.kind;
+            fault.remaining -= 1;
+            if fault.remaining == 0 {
+                state.faults.remove(&method);
+            }
+            return Err(std::io::Error::new(kind, format!("injected {:?} failure", method)).into());
+        }
+        Ok(state)
+    }
+
+    fn create(&self, method: FsMethod, parent_ino: u64, name: FileName, file_type: FileType) -> Result<Inode> {
+        let mut state = self.enter(method)?;
+        let state = &mut *state;
+        let entries = state
+            .entries
+            .get_mut(&parent_ino)
+            .ok_or_else(|| anyhow!("Parent {} is not a directory", parent_ino))?;
+        if entries.contains_key(&name) {
+            return Err(anyhow!("{:?} already exists in directory {}", name, parent_ino));
+        }
+        let ino = state.next_ino;
+        state.next_ino += 1;
+        entries.insert(name.clone(), ino);
+        let inode = match file_type {
+            FileType::RegularFile => {
+                state.contents.insert(ino, Vec::new());
+                Inode::new_file(ino, parent_ino, name)
+            }
+            FileType::Directory => {
+                state.entries.insert(ino, BTreeMap::new());
+                Inode::new_dir(ino, parent_ino, name)
+            }
+        };
+        state.inodes.insert(ino, inode.clone());
+        Ok(inode)
+    }
+
+    fn remove(state: &mut State, ino: u64) -> Result<()> {
+        let inode = state.inodes.remove(&ino).ok_or_else(|| not_found(format!("Inode {} not found", ino)))?;
+        if let Some(entries) = state.entries.get_mut(&inode.parent_ino) {
+            entries.retain(|_, child| *child != ino);
+        }
+        state.entries.remove(&ino);
+        state.contents.remove(&ino);
+        state.xattrs.remove(&ino);
+        Ok(())
+    }
+
+    fn contents_mut(state: &mut State, ino: u64) -> Result<&mut Vec<u8>> {
+        state.contents.get_mut(&ino).ok_or_else(|| anyhow!("Inode {} is not a regular file", ino))
+    }
+
+    /// Set the size and mtime of a file after its contents changed
+    fn touch(state: &mut State, ino: u64) {
+        let size = state.contents.get(&ino).map_or(0, |data| data.len() as u64);
+        if let Some(inode) = state.inodes.get_mut(&ino) {
+            inode.size = size;
+            inode.touch_contents(now_timespec());
+        }
+    }
+
+    fn with_xattrs(state: &State, inode: &Inode) -> Inode {
+        let mut inode = inode.clone();
+        if let Some(attrs) = state.xattrs.get(&inode.ino).filter(|attrs| !attrs.is_empty()) {
+            for (name, value) in attrs {
+                inode.set_xattr(name.clone(), value.clone());
+            }
+        }
+        inode
+    }
+}
+
+/// A missing inode, or a lookup in something that is not a directory
+fn not_found(message: String) -> anyhow::Error {
+    StorageError::NotFound(message).into()
+}
+
+impl FilesystemInterface for MemoryFs {
+    fn read_file(&self, ino: u64) -> Result<Vec<u8>> {
+        let state = self.enter(FsMethod::ReadFile)?;
+        state.contents.get(&ino).cloned().ok_or_else(|| anyhow!("Inode {} is not a regular file", ino))
+    }
+
+    /// At offset 0 the data replaces the whole file, as with the storage engine
+    fn write_file(&self, ino: u64, data: &[u8], offset: u64) -> Result<()> {
+        let mut state = self.enter(FsMethod::WriteFile)?;
+        let contents = Self::contents_mut(&mut state, ino)?;
+        if offset == 0 {
+            *contents = data.to_vec();
+        } else {
+            let end = offset as usize + data.len();
+            if contents.len() < end {
+                contents.resize(end, 0);
+            }
+            contents[offset as usize..end].copy_from_slice(data);
+        }
+        Self::touch(&mut state, ino);
+        Ok(())
+    }
+
+    fn create_file(&self, parent_ino: u64, name: FileName) -> Result<Inode> {
+        self.create(FsMethod::CreateFile, parent_ino, name, FileType::RegularFile)
+    }
+
+    fn create_dir(&self, parent_ino: u64, name: FileName) -> Result<Inode> {
+        self.create(FsMethod::CreateDir, parent_ino, name, FileType::Directory)
+    }
+
+    fn delete_file(&self, ino: u64) -> Result<()> {
+        let mut state = self.enter(FsMethod::DeleteFile)?;
+        Self::remove(&mut state, ino)
+    }
+
+    fn orphan_file(&self, ino: u64) -> Result<()> {
+        let mut state = self.enter(FsMethod::OrphanFile)?;
+        let state = &mut *state;
+        let inode = state.inodes.get_mut(&ino).ok_or_else(|| not_found(format!("Inode {} not found", ino)))?;
+        if let Some(entries) = state.entries.get_mut(&inode.parent_ino) {
+            entries.retain(|_, child| *child != ino);
+        }
+        inode.parent_ino = ORPHAN_PARENT_INO;
+        inode.name = ino.to_string().into();
+        inode.set_ctime(now_timespec());
+        Ok(())
+    }
+
+    fn delete_dir(&self, ino: u64) -> Result<()> {
+        let mut state = self.enter(FsMethod::DeleteDir)?;
+        match state.entries.get(&ino) {
+            None => return Err(anyhow!("Inode {} is not a directory", ino)),
+            Some(entries) if !entries.is_empty() => return Err(anyhow!("Directory {} is not empty", ino)),
+            Some(_) => {}
+        }
+        Self::remove(&mut state, ino)
+    }
+
+    fn get_inode(&self, ino: u64) -> Result<Inode> {
+        let state = self.enter(FsMethod::GetInode)?;
+        let inode = state.inodes.get(&ino).ok_or_else(|| not_found(format!("Inode {} not found", ino)))?;
+        Ok(Self::with_xattrs(&state, inode))
+    }
+
+    fn list_directory(&self, parent_ino: u64) -> Result<Vec<Inode>> {
+        let state = self.enter(FsMethod::ListDirectory)?;
+        let entries = state.entries.get(&parent_ino).ok_or_else(|| not_found(format!("Inode {} is not a directory", parent_ino)))?;
+        Ok(entries.values().map(|ino| Self::with_xattrs(&state, &state.inodes[ino])).collect())
+    }
+
+    fn find_child(&self, parent_ino: u64, name: &[u8]) -> Result<Option<Inode>> {
+        let state = self.enter(FsMethod::FindChild)?;
+        let entries = state.entries.get(&parent_ino).ok_or_else(|| not_found(format!("Inode {} is not a directory", parent_ino)))?;
+        Ok(entries.get(name).map(|ino| Self::with_xattrs(&state, &state.inodes[ino])))
+    }
+
+    /// A new parent or name moves the entry, as a rename
+    fn update_inode(&self, inode: &Inode) -> Result<()> {
+        let mut state = self.enter(FsMethod::UpdateInode)?;
+        let state = &mut *state;
+        let Some(old) = state.inodes.get(&inode.ino) else {
+            return Err(not_found(format!("Inode {} not found", inode.ino)));
+        };
+        if (old.parent_ino, &old.name) != (inode.parent_ino, &inode.name) {
+            let entries = state
+                .entries
+                .get(&inode.parent_ino)
+                .ok_or_else(|| anyhow!("Parent {} is not a directory", inode.parent_ino))?;
+            if entries.get(&inode.name).is_some_and(|&ino| ino != inode.ino) {
+                return Err(anyhow!("{:?} already exists in directory {}", inode.name, inode.parent_ino));
+            }
+            if let Some(entries) = state.entries.get_mut(&old.parent_ino) {
+                entries.remove(&old.name);
+            }
+            state.entries.get_mut(&inode.parent_ino).unwrap().insert(inode.name.clone(), inode.ino);
+        }
+        let mut stored = inode.clone();
+        let attrs = stored.xattrs.take().map(|xattrs| xattrs.attrs).unwrap_or_default();
+        state.xattrs.insert(inode.ino, attrs);
+        state.inodes.insert(inode.ino, stored);
+        Ok(())
+    }
+
+    fn stat(&self) -> Result<FilesystemStats> {
+        let state = self.enter(FsMethod::Stat)?;
+        let used_space: u64 = state.contents.values().map(|data| data.len() as u64).sum();
+        Ok(FilesystemStats {
+            total_files: state.contents.len() as u64,
+            total_dirs: state.entries.len() as u64,
+            total_size: used_space,
+            used_space,
+            free_space: MEMORY_FS_CAPACITY.saturating_sub(used_space),
+        })
+    }
+
+    fn punch_hole(&self, ino: u64, offset: u64, length: u64) -> Result<()> {
+        let mut state = self.enter(FsMethod::PunchHole)?;
+        let contents = Self::contents_mut(&mut state, ino)?;
+        let end = offset.saturating_add(length).min(contents.len() as u64) as usize;
+        if (offset as usize) < end {
+            contents[offset as usize..end].fill(0);
+        }
+        Ok(())
+    }
+
+    fn zero_range(&self, ino: u64, offset: u64, length: u64, keep_size: bool) -> Result<()> {
+        let mut state = self.enter(FsMethod::ZeroRange)?;
+        let contents = Self::contents_mut(&mut state, ino)?;
+        let end = offset.saturating_add(length) as usize;
+        if !keep_size && contents.len() < end {
+            contents.resize(end, 0);
+        }
+        let end = end.min(contents.len());
+        if (offset as usize) < end {
+            contents[offset as usize..end].fill(0);
+        }
+        Self::touch(&mut state, ino);
+        Ok(())
+    }
+
+    fn flush_file(&self, _ino: u64) -> Result<()> {
+        drop(self.enter(FsMethod::FlushFile)?);
+        Ok(())
+    }
+
+    fn sync_inode(&self, _ino: u64) -> Result<()> {
+        drop(self.enter(FsMethod::SyncInode)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inode_numbers_are_allocated_in_order() {
+        let fs = MemoryFs::new();
+        let dir = fs.create_dir(1, "dir".into()).unwrap();
+        let file = fs.create_file(dir.ino, "a.txt".into()).unwrap();
+        assert_eq!((dir.ino, file.ino), (2, 3));
+        assert!(fs.create_file(dir.ino, "a.txt".into()).is_err());
+        assert!(fs.create_file(file.ino, "b.txt".into()).is_err());
+
+        fs.write_file(file.ino, b"hello world", 0).unwrap();
+        fs.write_file(file.ino, b"there", 6).unwrap();
+        assert_eq!(fs.read_file(file.ino).unwrap(), b"hello there");
+        assert_eq!(fs.get_inode(file.ino).unwrap().size, 11);
+        assert_eq!(fs.find_child(dir.ino, b"a.txt").unwrap().map(|inode| inode.ino), Some(file.ino));
+
+        // Deleted numbers are not reused
+        assert!(fs.delete_dir(dir.ino).is_err());
+        fs.delete_file(file.ino).unwrap();
+        fs.delete_dir(dir.ino).unwrap();
+        assert_eq!(fs.create_file(1, "c.txt".into()).unwrap().ino, 4);
+        assert_eq!(fs.list_directory(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_injected_failures_run_out() {
+        let fs = MemoryFs::new();
+        let file = fs.create_file(1, "f".into()).unwrap();
+        fs.fail_next(FsMethod::WriteFile, 2, std::io::ErrorKind::StorageFull);
+
+        for _ in 0..2 {
+            let err = fs.write_file(file.ino, b"x", 0).unwrap_err();
+            assert_eq!(err.downcast_ref::<std::io::Error>().unwrap().kind(), std::io::ErrorKind::StorageFull);
+        }
+        fs.write_file(file.ino, b"x", 0).unwrap();
+        // Other methods were never affected
+        assert_eq!(fs.read_file(file.ino).unwrap(), b"x");
+
+        fs.fail_next(FsMethod::GetInode, 1, std::io::ErrorKind::Other);
+        fs.fail_next(FsMethod::GetInode, 0, std::io::ErrorKind::Other);
+        assert!(fs.get_inode(file.ino).is_ok());
+    }
+
+    #[test]
+    fn test_xattrs_round_trip_through_update_inode() {
+        let fs = MemoryFs::new();
+        let mut inode = fs.create_file(1, "f".into()).unwrap();
+        inode.set_xattr("user.tag".to_string(), b"blue".to_vec());
+        fs.update_inode(&inode).unwrap();
+        assert_eq!(fs.get_inode(inode.ino).unwrap().get_xattr("user.tag"), Some(&b"blue"[..]));
+
+        let mut inode = fs.get_inode(inode.ino).unwrap();
+        inode.remove_xattr("user.tag");
+        fs.update_inode(&inode).unwrap();
+        assert!(fs.get_inode(inode.ino).unwrap().list_xattrs().is_empty());
+    }
+}
@@ -1,13 +1,30 @@
 use anyhow::{anyhow, Context, Result};
+use crate::error::{StorageError, StorageResult};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use uuid::Uuid;
 
+pub use crate::file_name::FileName;
+
 use crate::extent::Extent;
+use crate::extent_totals::ExtentTotals;
+use crate::inode_cache::InodeCache;
+use crate::inode_totals::InodeTotals;
+use crate::metadata_store::{MetadataBackend, MetadataStore, RecordKind};
+use crate::quota::Quota;
 
 #[cfg(test)]
-use crate::crash_sim::{check_crash_point, CrashPoint};
+use crate::crash_sim::{check_fault_at, CrashPoint};
+use crate::metadata_tx::{MetadataOp, MetadataRootManager, MetadataTransaction};
+
+/// Committed metadata roots kept around after each transaction
+const KEEP_METADATA_ROOTS: usize = 4;
+
+/// Change generations persisted ahead of use; see `MetadataManager::next_change_generation`
+const CHANGE_GENERATION_RESERVE: u64 = 1024;
 
 /// POSIX file type
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -40,17 +57,57 @@ pub enum AclTag {
     Other,       // ACL_OTHER
 }
 
+/// The current time as seconds and nanoseconds since the Unix epoch
+pub fn now_timespec() -> (i64, u32) {
+    let now = chrono::Utc::now();
+    (now.timestamp(), now.timestamp_subsec_nanos())
+}
+
+fn is_zero<T: Default + PartialEq>(n: &T) -> bool {
+    *n == T::default()
+}
+
+/// Parent of files unlinked while still open
+///
+/// No inode has number 0, so entries under it never show up in the tree. Each
+/// such file is named after its inode number and deleted on its last close,
+/// or when the pool is next mounted if that never happened.
+pub const ORPHAN_PARENT_INO: u64 = 0;
+
 /// Inode represents a file or directory
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Inode {
     pub ino: u64,
     pub parent_ino: u64,
     pub file_type: FileType,
-    pub name: String,
+    pub name: FileName,
     pub size: u64,
     pub atime: i64,
     pub mtime: i64,
     pub ctime: i64,
+    /// Nanoseconds past `atime`; inodes saved before sub-second times read as zero
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub atime_nsec: u32,
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub mtime_nsec: u32,
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub ctime_nsec: u32,
+    /// Birth time, set once at creation; see `Inode::crtime`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub crtime: Option<(i64, u32)>,
+    /// Told apart from earlier inodes with the same number, e.g. by NFS file handles
+    ///
+    /// Inode numbers are not reused, so this only moves when the allocator had
+    /// to be recovered; see `MetadataManager::generation`.
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub generation: u64,
+    /// Change generation of the last save that created, resized, renamed or
+    /// moved the inode; see `MetadataManager::change_generation`
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub change_generation: u64,
+    /// Directories directly inside this one; kept by `StorageEngine`, see `Inode::nlink`
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub subdirs: u64,
     pub uid: u32,
     pub gid: u32,
     pub mode: u32,
@@ -63,17 +120,24 @@ pub struct Inode {
 }
 
 impl Inode {
-    pub fn new_file(ino: u64, parent_ino: u64, name: String) -> Self {
-        let now = chrono::Utc::now().timestamp();
+    pub fn new_file(ino: u64, parent_ino: u64, name: impl Into<FileName>) -> Self {
+        let (now, now_nsec) = now_timespec();
         let inode = Inode {
             ino,
             parent_ino,
             file_type: FileType::RegularFile,
-            name,
+            name: name.into(),
             size: 0,
             atime: now,
             mtime: now,
             ctime: now,
+            atime_nsec: now_nsec,
+            mtime_nsec: now_nsec,
+            ctime_nsec: now_nsec,
+            crtime: Some((now, now_nsec)),
+            generation: 0,
+            change_generation: 0,
+            subdirs: 0,
             uid: unsafe { libc::getuid() },
             gid: unsafe { libc::getgid() },
             mode: 0o644,
@@ -94,17 +158,24 @@ impl Inode {
         inode
     }
     
-    pub fn new_dir(ino: u64, parent_ino: u64, name: String) -> Self {
-        let now = chrono::Utc::now().timestamp();
+    pub fn new_dir(ino: u64, parent_ino: u64, name: impl Into<FileName>) -> Self {
+        let (now, now_nsec) = now_timespec();
         let inode = Inode {
             ino,
             parent_ino,
             file_type: FileType::Directory,
-            name,
+            name: name.into(),
             size: 0,
             atime: now,
             mtime: now,
             ctime: now,
+            atime_nsec: now_nsec,
+            mtime_nsec: now_nsec,
+            ctime_nsec: now_nsec,
+            crtime: Some((now, now_nsec)),
+            generation: 0,
+            change_generation: 0,
+            subdirs: 0,
             uid: unsafe { libc::getuid() },
             gid: unsafe { libc::getgid() },
             mode: 0o755,
@@ -125,6 +196,44 @@ impl Inode {
         inode
     }
     
+    pub fn set_atime(&mut self, (secs, nsec): (i64, u32)) {
+        self.atime = secs;
+        self.atime_nsec = nsec;
+    }
+    
+    pub fn set_mtime(&mut self, (secs, nsec): (i64, u32)) {
+        self.mtime = secs;
+        self.mtime_nsec = nsec;
+    }
+    
+    pub fn set_ctime(&mut self, (secs, nsec): (i64, u32)) {
+        self.ctime = secs;
+        self.ctime_nsec = nsec;
+    }
+    
+    /// Record a change to the contents at `now`, which moves mtime and ctime
+    pub fn touch_contents(&mut self, now: (i64, u32)) {
+        self.set_mtime(now);
+        self.set_ctime(now);
+    }
+    
+    /// Hard links to the inode as `stat` reports them
+    ///
+    /// A directory has its entry in the parent, its own `.` and the `..` of
+    /// each subdirectory. Files unlinked while open have none.
+    pub fn nlink(&self) -> u32 {
+        match self.file_type {
+            _ if self.parent_ino == ORPHAN_PARENT_INO => 0,
+            FileType::Directory => 2u32.saturating_add(self.subdirs.try_into().unwrap_or(u32::MAX)),
+            FileType::RegularFile => 1,
+        }
+    }
+    
+    /// Birth time; inodes created before it was recorded report their ctime
+    pub fn crtime(&self) -> (i64, u32) {
+        self.crtime.unwrap_or((self.ctime, self.ctime_nsec))
+    }
+    
     /// Get extended attribute
     pub fn get_xattr(&self, name: &str) -> Option<&[u8]> {
         self.xattrs.as_ref()?.attrs.get(name).map(|v| v.as_slice())
@@ -156,16 +265,274 @@ pub struct ExtentMap {
     pub ino: u64,
     pub extents: Vec<Uuid>, // Ordered list of extent UUIDs
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub checksum: Option<String>,  // BLAKE3 checksum of serialized map (excluding this field)
+    pub checksum: Option<String>,  // BLAKE3 of ino and extent UUIDs; see `ExtentMap::compute_checksum`
+    /// Bytes of the file covered by each slot; maps saved before pools chose
+    /// their own extent size use `DEFAULT_EXTENT_SIZE`
+    #[serde(default = "crate::extent::default_extent_size")]
+    pub extent_size: usize,
+    /// Change generation of the last save that changed `extents`; set by
+    /// `MetadataManager::save_extent_map`, whatever the caller passes
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub change_generation: u64,
+    /// Change generation at which each slot last changed; empty on maps saved
+    /// before changes were tracked
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub slot_generations: Vec<u64>,
+}
+
+impl ExtentMap {
+    /// Empty map of a file laid out in `extent_size` slots
+    pub fn new(ino: u64, extent_size: usize) -> Self {
+        ExtentMap {
+            ino,
+            extents: Vec::new(),
+            checksum: None,
+            extent_size,
+            change_generation: 0,
+            slot_generations: Vec::new(),
+        }
+    }
+
+    /// BLAKE3 over the inode number, the ordered extent UUIDs and a non-default
+    /// extent size, hex encoded
+    pub fn compute_checksum(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.ino.to_le_bytes());
+        for uuid in &self.extents {
+            hasher.update(uuid.as_bytes());
+        }
+        if self.extent_size != crate::extent::DEFAULT_EXTENT_SIZE {
+            hasher.update(&(self.extent_size as u64).to_le_bytes());
+        }
+        hasher.finalize().to_hex().to_string()
+    }
+
+    /// Checksum written before `compute_checksum`: BLAKE3 of the map serialized without it
+    fn legacy_checksum(&self) -> String {
+        // The map as serialized back then, before it had an extent size
+        #[derive(Serialize)]
+        struct LegacyExtentMap<'a> {
+            ino: u64,
+            extents: &'a [Uuid],
+        }
+        let json = serde_json::to_string(&LegacyExtentMap { ino: self.ino, extents: &self.extents }).unwrap();
+        blake3::hash(json.as_bytes()).to_hex().to_string()
+    }
+
+    /// Check the recorded checksum; maps saved before checksums existed pass
+    pub fn verify_checksum(&self) -> std::result::Result<(), ExtentMapChecksumMismatch> {
+        match &self.checksum {
+            Some(stored) if *stored != self.compute_checksum() && *stored != self.legacy_checksum() => {
+                Err(ExtentMapChecksumMismatch {
+                    ino: self.ino,
+                    expected: stored.clone(),
+                    actual: self.compute_checksum(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sparse marker: an extent slot with no storage behind it, read as zeros
+    ///
+    /// Slot `i` covers file bytes `i * extent_size ..`; slots past the
+    /// end of `extents` (e.g. after growing the file with fallocate) are holes too.
+    pub const HOLE: Uuid = Uuid::nil();
+
+    pub fn is_hole(uuid: &Uuid) -> bool {
+        *uuid == Self::HOLE
+    }
+
+    /// Extents that hold data, skipping holes
+    pub fn data_extents(&self) -> impl Iterator<Item = &Uuid> {
+        self.extents.iter().filter(|uuid| !Self::is_hole(uuid))
+    }
+
+    /// Byte offset at which slot `index` starts
+    pub fn slot_offset(&self, index: usize) -> u64 {
+        index as u64 * self.extent_size as u64
+    }
+
+    /// Slot holding the byte at `offset`
+    pub fn slot_index(&self, offset: u64) -> usize {
+        (offset / self.extent_size as u64) as usize
+    }
+
+    /// Length of slot `index` in a file of `file_size` bytes
+    pub fn slot_len(&self, index: usize, file_size: u64) -> u64 {
+        file_size
+            .saturating_sub(self.slot_offset(index))
+            .min(self.extent_size as u64)
+    }
+
+    /// Bytes of a `file_size`-byte file backed by extents rather than holes
+    pub fn allocated_bytes(&self, file_size: u64) -> u64 {
+        self.extents
+            .iter()
+            .enumerate()
+            .filter(|(_, uuid)| !Self::is_hole(uuid))
+            .map(|(index, _)| self.slot_len(index, file_size))
+            .sum()
+    }
+}
+
+/// An extent map whose contents do not match its recorded checksum
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Extent map {ino} checksum mismatch: expected {expected}, got {actual}")]
+pub struct ExtentMapChecksumMismatch {
+    pub ino: u64,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// An extent map that cannot be trusted or regenerated
+#[derive(Debug, Clone, Serialize)]
+pub struct CorruptExtentMap {
+    pub ino: u64,
+    pub reason: String,
+}
+
+/// Outcome of verifying every extent map against its checksum
+#[derive(Debug, Default, Serialize)]
+pub struct ExtentMapReport {
+    pub checked: usize,
+    /// Maps saved before checksums existed; they get one on their next write
+    pub unchecksummed: usize,
+    /// Maps whose checksum is wrong although every extent they list still exists
+    pub stale: Vec<u64>,
+    /// Maps that do not parse, or that list extents which no longer exist
+    pub corrupt: Vec<CorruptExtentMap>,
+    /// Stale maps whose checksum was regenerated
+    pub repaired: Vec<u64>,
+}
+
+/// A `(parent_ino, name) -> ino` directory index entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DirIndexEntry {
+    pub parent_ino: u64,
+    pub name: FileName,
+    pub ino: u64,
+}
+
+/// Outcome of comparing the directory index against the inode records
+#[derive(Debug, Default, Serialize)]
+pub struct DirIndexReport {
+    /// Entries the inode records imply
+    pub expected_entries: usize,
+    /// Entries present in the inode records but absent from the index
+    pub missing: Vec<DirIndexEntry>,
+    /// Index entries with no matching inode record (or pointing at the wrong inode)
+    pub stale: Vec<DirIndexEntry>,
+    /// Whether the index was rebuilt from the inode records
+    pub repaired: bool,
+}
+
+impl DirIndexReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.stale.is_empty()
+    }
 }
 
 /// Metadata manager
 pub struct MetadataManager {
     pool_dir: PathBuf,
+    // inode, extent and extent map records
+    store: Arc<dyn MetadataStore>,
     next_ino: u64,
+    // given to new inodes; see `generation`
+    generation: u64,
     // persisted btrees for fast metadata lookup
     pub inode_table: crate::metadata_btree::PersistedBTree<u64, Inode>,
     pub extent_map_table: crate::metadata_btree::PersistedBTree<u64, ExtentMap>,
+    // directory entries keyed by (parent_ino, name) for point lookups and range listings;
+    // the name is case-folded when `fold_names` is set
+    pub dir_index: crate::metadata_btree::PersistedBTree<(u64, FileName), u64>,
+    // set for case-insensitive pools
+    fold_names: bool,
+    // versioned roots committed by journaled transactions
+    roots: MetadataRootManager,
+    // counters for status and health; also serialises saves and deletes of extent records
+    extent_totals: std::sync::Mutex<ExtentTotals>,
+    // counters for statfs; also serialises saves and deletes of inode records
+    inode_totals: std::sync::Mutex<InodeTotals>,
+    // inodes and name lookups kept between calls; set by the engine, see `set_inode_cache`
+    inode_cache: Option<Arc<InodeCache>>,
+    // last change generation handed out and the highest one persisted
+    change_generations: std::sync::Mutex<ChangeGenerations>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChangeGenerations {
+    current: u64,
+    reserved: u64,
+}
+
+/// Cache entries of an inode whose record is changing, dropped with the guard
+///
+/// Dropped once the record has changed, so a reader that loaded the old
+/// record meanwhile cannot cache it; see `InodeCache::stamp`.
+struct Uncache<'a> {
+    cache: Option<&'a InodeCache>,
+    ino: u64,
+    /// A name the inode is saved under, which may have resolved to another inode
+    name: Option<(u64, FileName)>,
+}
+
+impl Drop for Uncache<'_> {
+    fn drop(&mut self) {
+        if let Some(cache) = self.cache {
+            cache.invalidate(self.ino);
+            if let Some(name) = &self.name {
+                cache.forget_name(name);
+            }
+        }
+    }
+}
+
+/// Extent records read one at a time, for passes over the whole pool
+///
+/// Holds the store's keys as it lists them, or in UUID order the UUIDs still
+/// to come, but never the extents themselves. Records deleted since the pass
+/// started are skipped; ones that cannot be read or parsed come back as errors.
+pub struct ExtentRecords {
+    store: Arc<dyn MetadataStore>,
+    source: ExtentSource,
+}
+
+enum ExtentSource {
+    Listed(crate::metadata_store::RecordKeys),
+    Sorted(std::vec::IntoIter<Uuid>),
+}
+
+impl ExtentRecords {
+    /// Records still to come, counting any deleted since; unknown in listing order
+    pub fn remaining(&self) -> Option<usize> {
+        match &self.source {
+            ExtentSource::Listed(_) => None,
+            ExtentSource::Sorted(uuids) => Some(uuids.len()),
+        }
+    }
+}
+
+impl Iterator for ExtentRecords {
+    type Item = (Uuid, Result<Extent>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let uuid = match &mut self.source {
+                ExtentSource::Listed(keys) => match keys.next()?.map(|key| Uuid::parse_str(&key)) {
+                    Ok(Ok(uuid)) => uuid,
+                    _ => continue,
+                },
+                ExtentSource::Sorted(uuids) => uuids.next()?,
+            };
+            match self.store.load(RecordKind::Extent, &uuid.to_string()) {
+                Ok(Some(contents)) => return Some((uuid, serde_json::from_slice(&contents).map_err(Into::into))),
+                Ok(None) => continue,
+                Err(e) => return Some((uuid, Err(e))),
+            }
+        }
+    }
 }
 
 impl MetadataManager {
@@ -177,14 +544,6 @@ impl MetadataManager {
         blake3::hash(json.as_bytes()).to_hex().to_string()
     }
     
-    /// Compute BLAKE3 checksum for an extent map (excluding the checksum field)
-    fn compute_extent_map_checksum(map: &ExtentMap) -> String {
-        let mut map_copy = map.clone();
-        map_copy.checksum = None;
-        let json = serde_json::to_string(&map_copy).unwrap();
-        blake3::hash(json.as_bytes()).to_hex().to_string()
-    }
-    
     /// Verify inode checksum
     fn verify_inode_checksum(inode: &Inode) -> Result<()> {
         if let Some(stored_checksum) = &inode.checksum {
@@ -201,52 +560,190 @@ impl MetadataManager {
         Ok(())
     }
     
-    /// Verify extent map checksum
-    fn verify_extent_map_checksum(map: &ExtentMap) -> Result<()> {
-        if let Some(stored_checksum) = &map.checksum {
-            let computed = Self::compute_extent_map_checksum(map);
-            if &computed != stored_checksum {
-                return Err(anyhow!(
-                    "ExtentMap {} checksum mismatch: expected {}, got {}",
-                    map.ino,
-                    stored_checksum,
-                    computed
-                ));
-            }
-        }
-        Ok(())
-    }
 
-    pub fn new(pool_dir: PathBuf) -> Result<Self> {
+    pub fn new(pool_dir: PathBuf) -> StorageResult<Self> {
         // Create metadata directories
         fs::create_dir_all(pool_dir.join("metadata"))?;
-        fs::create_dir_all(pool_dir.join("inodes"))?;
-        fs::create_dir_all(pool_dir.join("extent_maps"))?;
-        fs::create_dir_all(pool_dir.join("extents"))?;
-        
-        // Load or initialize next_ino
-        let next_ino = Self::load_next_ino(&pool_dir).unwrap_or(2); // 1 is reserved for root
+        let store = crate::metadata_store::open(&pool_dir, crate::disk::DiskPool::metadata_backend(&pool_dir)?)?;
+        fs::create_dir_all(pool_dir.join("released"))?;
+        fs::create_dir_all(pool_dir.join("extent_refs"))?;
+        fs::create_dir_all(pool_dir.join("quotas"))?;
         
         // Initialize persisted B-trees
         let inode_btree_path = pool_dir.join("metadata").join("inodes.btree");
         let extent_map_btree_path = pool_dir.join("metadata").join("extent_maps.btree");
+        let dir_index_path = pool_dir.join("metadata").join("dir_index.btree");
+        let dir_index_missing = !dir_index_path.exists();
+        let fold_names = crate::disk::DiskPool::is_case_insensitive(&pool_dir);
+        let casefold_marker = pool_dir.join("metadata").join("dir_index.casefold");
+        let index_folded = casefold_marker.exists();
 
         let inode_table = crate::metadata_btree::PersistedBTree::new(Some(inode_btree_path))?;
         let extent_map_table = crate::metadata_btree::PersistedBTree::new(Some(extent_map_btree_path))?;
+        let dir_index = crate::metadata_btree::PersistedBTree::new(Some(dir_index_path))?;
+        let roots = MetadataRootManager::new(pool_dir.clone())?;
+        let (next_ino, generation) = Self::recover_allocator(&pool_dir, store.as_ref(), &roots)?;
+        // Generations reserved but not handed out before a restart are skipped
+        let reserved = fs::read_to_string(pool_dir.join("metadata").join("change_generation"))
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok())
+            .unwrap_or(0);
 
         let mut manager = MetadataManager {
             pool_dir,
+            store,
             next_ino,
+            generation,
             inode_table,
             extent_map_table,
+            dir_index,
+            fold_names,
+            roots,
+            extent_totals: std::sync::Mutex::new(ExtentTotals::default()),
+            inode_totals: std::sync::Mutex::new(InodeTotals::default()),
+            inode_cache: None,
+            change_generations: std::sync::Mutex::new(ChangeGenerations { current: reserved, reserved }),
         };
         
+        // Pools created before the directory index existed get it built from their inode records,
+        // as does an index whose keys were folded for the other case mode
+        if dir_index_missing || index_folded != fold_names {
+            let entries = manager.rebuild_dir_index()?;
+            log::info!("Built directory index with {} entries", entries);
+            if fold_names {
+                fs::write(&casefold_marker, b"")?;
+            } else if index_folded {
+                fs::remove_file(&casefold_marker)?;
+            }
+        }
+        
+        // Pools from before the counters were kept are counted once, before
+        // creating the root can save counters that miss everything else
+        let inode_totals = match InodeTotals::load(&manager.pool_dir)? {
+            Some(totals) => totals,
+            None => manager.recount_inode_totals()?,
+        };
+        *manager.inode_totals.lock().unwrap() = inode_totals;
+        
         // Ensure root directory exists
         manager.ensure_root()?;
         
+        // Likewise the extent counters
+        let totals = match ExtentTotals::load(&manager.pool_dir)? {
+            Some(totals) => totals,
+            None => manager.recount_extent_totals()?,
+        };
+        *manager.extent_totals.lock().unwrap() = totals;
+        
+        // Finish transactions interrupted by a crash
+        manager.recover_transactions()?;
+        
         Ok(manager)
     }
     
+    /// Replay journaled transactions that may not be on stable storage, discarding torn records
+    ///
+    /// The records only go once the replayed transactions are synced, so
+    /// power loss during recovery leaves them to be replayed again. Records of
+    /// transactions applied in full earlier in this boot go with the same sync.
+    fn recover_transactions(&mut self) -> Result<()> {
+        self.roots.commits().adopt(crate::metadata_tx::applied_journal_records(&self.pool_dir)?);
+        let records = crate::metadata_tx::pending_journal_records(&self.pool_dir)?;
+        for (path, record) in &records {
+            match record {
+                Some(record) if record.is_intact() => {
+                    log::info!("Replaying metadata transaction {} ({} ops)", record.version, record.ops.len());
+                    let mut tx = self.roots.begin_transaction();
+                    for op in &record.ops {
+                        tx.record(op.clone());
+                    }
+                    self.apply_transaction(tx)?;
+                }
+                _ => log::warn!("Discarding incomplete metadata transaction {}", path.display()),
+            }
+        }
+        self.sync_commits()?;
+        for (path, _) in records {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        if let Err(e) = crate::metadata_tx::record_boot(&self.pool_dir) {
+            log::warn!("Could not record the boot the pool was recovered in: {}", e);
+        }
+        Ok(())
+    }
+    
+    /// Journal a set of mutations as one transaction
+    ///
+    /// Once this returns the transaction is committed: if applying it fails or
+    /// the process dies, the next `MetadataManager::new` replays it.
+    pub fn journal_transaction(&mut self, ops: Vec<MetadataOp>) -> StorageResult<MetadataTransaction> {
+        let mut tx = self.roots.begin_transaction();
+        for op in ops {
+            tx.record(op);
+        }
+        tx.write_journal()?;
+        Ok(tx)
+    }
+    
+    /// Apply a journaled transaction and retire its journal record
+    pub fn apply_transaction(&mut self, mut tx: MetadataTransaction) -> StorageResult<()> {
+        #[cfg(test)]
+        check_fault_at(CrashPoint::AfterJournalWrite, &self.pool_dir)?;
+        
+        for (i, op) in tx.ops().iter().enumerate() {
+            if i > 0 {
+                #[cfg(test)]
+                check_fault_at(CrashPoint::MidApply, &self.pool_dir)?;
+            }
+            
+            match op {
+                MetadataOp::SaveExtent(extent) => self.save_extent(extent)?,
+                MetadataOp::SaveExtentMap(map) => self.save_extent_map(map)?,
+                MetadataOp::SaveInode(inode) => self.save_inode(inode)?,
+                MetadataOp::DeleteExtent(uuid) => self.delete_extent(uuid)?,
+                MetadataOp::ReleaseExtent(uuid) => self.release_extent(uuid)?,
+                MetadataOp::ShareExtent(uuid, holders) => self.share_extent(uuid, holders)?,
+                MetadataOp::SaveQuota(quota) => self.save_quota(quota)?,
+                MetadataOp::DeleteExtentMap(ino) => self.delete_extent_map(*ino)?,
+                MetadataOp::DeleteInode(ino) => self.delete_inode(*ino)?,
+                MetadataOp::DeleteQuota(ino) => self.delete_quota(*ino)?,
+            }
+        }
+        
+        let checksum = crate::metadata_tx::JournalRecord::new(tx.version(), tx.ops().to_vec()).checksum;
+        tx.pending_root_mut()?.next_ino = self.next_ino;
+        self.roots.commit_transaction(tx, checksum)?;
+        self.roots.gc_old_roots(KEEP_METADATA_ROOTS)?;
+        Ok(())
+    }
+    
+    /// Journal and apply `ops` as one transaction
+    ///
+    /// However many records the mutations touch, they cost one journal record
+    /// and one root, and share the sync of their commit window with every
+    /// other transaction committed in it. Call `sync_commits` to wait for it.
+    pub fn apply_batch(&mut self, ops: Vec<MetadataOp>) -> StorageResult<()> {
+        let tx = self.journal_transaction(ops)?;
+        self.apply_transaction(tx)
+    }
+    
+    /// Make every transaction committed so far durable
+    pub fn sync_commits(&self) -> StorageResult<()> {
+        Ok(self.roots.commits().sync()?)
+    }
+    
+    /// How long committed transactions wait to share a sync
+    pub fn set_commit_window(&self, window: std::time::Duration) {
+        self.roots.commits().set_window(window);
+    }
+    
+    /// Transactions committed and syncs that made them durable since the pool was opened
+    pub fn commit_stats(&self) -> crate::metadata_tx::CommitStats {
+        self.roots.commits().stats()
+    }
+    
     fn ensure_root(&mut self) -> Result<()> {
         if !self.inode_exists(1) {
             let root = Inode::new_dir(1, 1, String::from(""));
@@ -257,11 +754,84 @@ impl MetadataManager {
         Ok(())
     }
     
-    pub fn allocate_ino(&mut self) -> u64 {
+    /// Hand out a fresh inode number
+    ///
+    /// Numbers only go up, and the next one is persisted before this one is
+    /// used, so a crash cannot hand the same number out twice.
+    pub fn allocate_ino(&mut self) -> StorageResult<u64> {
         let ino = self.next_ino;
         self.next_ino += 1;
-        self.save_next_ino().ok();
-        ino
+        self.save_next_ino()?;
+        Ok(ino)
+    }
+    
+    /// Generation given to new inodes
+    ///
+    /// Bumped whenever the persisted next inode number is lost or behind the
+    /// inodes on disk, the one case where a number freed by a delete might be
+    /// handed out again.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+    
+    /// Change generation of the pool: the latest one any inode or extent map carries
+    ///
+    /// Bumped by every save that changes a file's extents, size, name or
+    /// directory, so a backup tool can ask for everything newer than the
+    /// generation it last saw. Generations only go up, across restarts too.
+    pub fn change_generation(&self) -> u64 {
+        self.change_generations.lock().unwrap().current
+    }
+    
+    /// Hand out a new change generation
+    ///
+    /// The persisted counter runs `CHANGE_GENERATION_RESERVE` ahead, so it is
+    /// written once per that many changes rather than with every save.
+    fn next_change_generation(&self) -> Result<u64> {
+        let mut generations = self.change_generations.lock().unwrap();
+        let next = generations.current + 1;
+        if next > generations.reserved {
+            let reserved = next + CHANGE_GENERATION_RESERVE;
+            let path = self.pool_dir.join("metadata").join("change_generation");
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, reserved.to_string())?;
+            fs::rename(&temp_path, &path)?;
+            generations.reserved = reserved;
+        }
+        generations.current = next;
+        Ok(next)
+    }
+    
+    /// Next inode number and generation, checked against what the pool holds
+    fn recover_allocator(pool_dir: &Path, store: &dyn MetadataStore, roots: &MetadataRootManager) -> Result<(u64, u64)> {
+        let generation_path = pool_dir.join("metadata").join("generation");
+        let mut generation: u64 = fs::read_to_string(&generation_path)
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok())
+            .unwrap_or(0);
+        
+        // 1 is reserved for root; never below a number in use or one a committed transaction saw
+        let highest = store.keys(RecordKind::Inode)?.filter_map(|key| key.ok()?.parse::<u64>().ok()).max();
+        let floor = highest.map_or(2, |ino| ino + 1).max(roots.current_root().next_ino).max(2);
+        let next_ino = match Self::load_next_ino(pool_dir) {
+            Ok(next_ino) if next_ino >= floor => next_ino,
+            // A new pool has nothing to collide with
+            Err(_) if highest.is_none() => floor,
+            recorded => {
+                generation += 1;
+                log::warn!(
+                    "Next inode number {:?} is missing or behind the pool; continuing at {} with generation {}",
+                    recorded.ok(),
+                    floor,
+                    generation
+                );
+                let temp_path = generation_path.with_extension("tmp");
+                fs::write(&temp_path, generation.to_string())?;
+                fs::rename(&temp_path, &generation_path)?;
+                floor
+            }
+        };
+        Ok((next_ino, generation))
     }
     
     fn load_next_ino(pool_dir: &Path) -> Result<u64> {
@@ -279,61 +849,91 @@ impl MetadataManager {
     }
     
     // Inode operations
-    pub fn save_inode(&self, inode: &Inode) -> Result<()> {
+    pub fn save_inode(&self, inode: &Inode) -> StorageResult<()> {
+        let mut totals = self.inode_totals.lock().unwrap();
+        // The record rather than the btree copy, which may not survive a reopen
+        let saved = self.load_inode(inode.ino).ok();
+        // Whichever way the save ends, readers then load the record again
+        let _uncache = self.uncache_on_drop(inode.ino, Some(self.dir_key(inode.parent_ino, inode.name.as_bytes())));
+        
         // Compute checksum before saving
         let mut inode_with_checksum = inode.clone();
-        inode_with_checksum.checksum = Some(Self::compute_inode_checksum(inode));
+        inode_with_checksum.change_generation = match &saved {
+            Some(saved)
+                if saved.size == inode.size && saved.parent_ino == inode.parent_ino && saved.name == inode.name =>
+            {
+                saved.change_generation
+            }
+            _ => self.next_change_generation()?,
+        };
+        inode_with_checksum.checksum = Some(Self::compute_inode_checksum(&inode_with_checksum));
         
-        let path = self.pool_dir.join("inodes").join(inode.ino.to_string());
         let contents = serde_json::to_string_pretty(&inode_with_checksum)?;
-        let temp_path = path.with_extension("tmp");
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_inode {:?} BeforeTempWrite", inode.ino);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::BeforeTempWrite)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_inode {:?} writing temp", inode.ino);
-        fs::write(&temp_path, &contents)?;
         
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_inode {:?} AfterTempWrite", inode.ino);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::AfterTempWrite)?;
-        
-        // In production, we'd fsync here
-        // For testing, we simulate the crash point
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_inode {:?} BeforeRename", inode.ino);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::BeforeRename)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_inode {:?} renaming", inode.ino);
-        fs::rename(&temp_path, &path)?;
+        // Index the new name before the record lands: an entry whose record never
+        // made it is skipped as stale, whereas a record missing from the index
+        // would be invisible
+        let key = self.dir_key(inode.parent_ino, inode.name.as_bytes());
+        let replaced = self.dir_index.get(&key);
+        let indexed = self.index_dir_entry(inode)?;
+        if let Err(e) = self.store.save(RecordKind::Inode, &inode.ino.to_string(), contents.as_bytes()) {
+            // A failed save that did not take the process down can put the entry back
+            if indexed {
+                let restored = match replaced {
+                    Some(ino) => self.dir_index.insert(key, ino),
+                    None => self.dir_index.remove(&key).map(drop),
+                };
+                if let Err(undo) = restored {
+                    log::warn!("Failed to undo directory index entry for inode {}: {}", inode.ino, undo);
+                }
+            }
+            return Err(e.into());
+        }
         
         #[cfg(test)]
         eprintln!("[METADATA DEBUG] save_inode {:?} AfterRename", inode.ino);
         #[cfg(test)]
-        check_crash_point(CrashPoint::AfterRename)?;
+        check_fault_at(CrashPoint::AfterRename, &self.pool_dir.join("inodes"))?;
 
         // Also update persisted btree index
         #[cfg(test)]
         eprintln!("[METADATA DEBUG] save_inode {:?} inserting to btree", inode.ino);
+        let previous = self.inode_table.get(&inode.ino);
         let _ = self.inode_table.insert(inode.ino, inode_with_checksum);
+        
+        // The old name is dropped only once the renamed record is in place
+        if let Some(previous) = previous {
+            // A case-only rename keeps its folded key and must not lose the entry
+            if self.dir_key(previous.parent_ino, previous.name.as_bytes()) != self.dir_key(inode.parent_ino, inode.name.as_bytes()) {
+                self.unindex_dir_entry(&previous)?;
+            }
+        }
+        totals.replace(saved.as_ref(), inode);
+        totals.save(&self.pool_dir)?;
         #[cfg(test)]
         eprintln!("[METADATA DEBUG] save_inode {:?} done", inode.ino);
         Ok(())
     }
     
-    pub fn load_inode(&self, ino: u64) -> Result<Inode> {
-        // Prefer file-based storage if present (so on-disk corruption is detectable);
-        // fallback to btree index if file is missing.
-        let path = self.pool_dir.join("inodes").join(ino.to_string());
-        if path.exists() {
-            let contents = fs::read_to_string(path)?;
-            let inode: Inode = serde_json::from_str(&contents)?;
+    pub fn load_inode(&self, ino: u64) -> StorageResult<Inode> {
+        let Some(cache) = &self.inode_cache else {
+            return self.read_inode(ino);
+        };
+        if let Some(inode) = cache.get(ino) {
+            return Ok(inode);
+        }
+        let stamp = cache.stamp();
+        let inode = self.read_inode(ino)?;
+        cache.insert(&inode, stamp);
+        Ok(inode)
+    }
+    
+    /// Load an inode from its record, bypassing the inode cache
+    fn read_inode(&self, ino: u64) -> StorageResult<Inode> {
+        // Prefer the record if present (so corruption in the store is detectable);
+        // fallback to btree index if it is missing.
+        if let Some(contents) = self.store.load(RecordKind::Inode, &ino.to_string())? {
+            let inode: Inode = serde_json::from_slice(&contents)?;
             // Verify checksum if present
             Self::verify_inode_checksum(&inode)
                 .context(format!("Corrupted inode metadata for ino {}", ino))?;
@@ -346,141 +946,507 @@ impl MetadataManager {
             return Ok(inode);
         }
 
-        Err(anyhow!("Inode {} not found", ino))
+        Err(StorageError::NotFound(format!("Inode {} not found", ino)))
     }
     
     pub fn inode_exists(&self, ino: u64) -> bool {
-        self.pool_dir.join("inodes").join(ino.to_string()).exists()
+        self.store.exists(RecordKind::Inode, &ino.to_string()).unwrap_or(false)
     }
     
-    pub fn delete_inode(&self, ino: u64) -> Result<()> {
-        let path = self.pool_dir.join("inodes").join(ino.to_string());
-        if path.exists() {
-            fs::remove_file(path)?;
+    /// Inodes with a record, in ascending order
+    pub fn inode_numbers(&self) -> StorageResult<Vec<u64>> {
+        self.record_numbers(RecordKind::Inode)
+    }
+    
+    /// Numeric keys of every record of `kind`, in ascending order
+    fn record_numbers(&self, kind: RecordKind) -> StorageResult<Vec<u64>> {
+        let mut numbers = Vec::new();
+        for key in self.store.keys(kind)? {
+            if let Ok(number) = key?.parse() {
+                numbers.push(number);
+            }
+        }
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+    
+    /// Store the records are kept in
+    pub fn metadata_backend(&self) -> MetadataBackend {
+        self.store.backend()
+    }
+    
+    pub fn delete_inode(&self, ino: u64) -> StorageResult<()> {
+        let mut totals = self.inode_totals.lock().unwrap();
+        let inode = self.load_inode(ino).ok();
+        let _uncache = self.uncache_on_drop(ino, None);
+        self.store.delete(RecordKind::Inode, &ino.to_string())?;
+        // Or `load_inode` would fall back to the btree copy
+        self.inode_table.remove(&ino)?;
+        // Unindex after the record is gone so a crash in between only leaves a stale entry
+        if let Some(inode) = inode {
+            self.unindex_dir_entry(&inode)?;
+            totals.remove(&inode);
+            totals.save(&self.pool_dir)?;
         }
         Ok(())
     }
     
-    pub fn list_directory(&self, parent_ino: u64) -> Result<Vec<Inode>> {
+    pub fn list_directory(&self, parent_ino: u64) -> StorageResult<Vec<Inode>> {
+        use std::ops::Bound;
+        
+        let range = (
+            Bound::Included((parent_ino, FileName::default())),
+            Bound::Excluded((parent_ino + 1, FileName::default())),
+        );
+        // Listings are often followed by a lookup of every name, as by `ls -l`
+        let stamp = self.inode_cache.as_ref().map(|cache| cache.stamp());
         let mut children = Vec::new();
-        let inodes_dir = self.pool_dir.join("inodes");
+        for ((_, name), ino) in self.dir_index.range(range) {
+            if let Some(child) = self.load_indexed_child(parent_ino, &name, ino) {
+                if let (Some(cache), Some(stamp)) = (&self.inode_cache, stamp) {
+                    cache.insert_name((parent_ino, name), ino, stamp);
+                }
+                children.push(child);
+            }
+        }
         
-        for entry in fs::read_dir(inodes_dir)? {
-            let entry = entry?;
-            if let Ok(contents) = fs::read_to_string(entry.path()) {
-                if let Ok(inode) = serde_json::from_str::<Inode>(&contents) {
-                    if inode.parent_ino == parent_ino {
-                        children.push(inode);
+        Ok(children)
+    }
+    
+    pub fn find_child(&self, parent_ino: u64, name: impl AsRef<[u8]>) -> StorageResult<Option<Inode>> {
+        let key = self.dir_key(parent_ino, name.as_ref());
+        let Some(cache) = &self.inode_cache else {
+            return Ok(self
+                .dir_index
+                .get(&key)
+                .and_then(|ino| self.load_indexed_child(parent_ino, &key.1, ino)));
+        };
+        if let Some(ino) = cache.lookup(&key) {
+            match self.load_inode(ino) {
+                Ok(inode) if self.dir_key(inode.parent_ino, inode.name.as_bytes()) == key => return Ok(Some(inode)),
+                // Renamed or repaired since; the index has the answer
+                _ => cache.forget_name(&key),
+            }
+        }
+        let stamp = cache.stamp();
+        let child = self
+            .dir_index
+            .get(&key)
+            .and_then(|ino| self.load_indexed_child(parent_ino, &key.1, ino));
+        if let Some(child) = &child {
+            cache.insert_name(key, child.ino, stamp);
+        }
+        Ok(child)
+    }
+    
+    /// Keep inodes and name lookups in `cache`, which saves and deletes keep current
+    pub fn set_inode_cache(&mut self, cache: Arc<InodeCache>) {
+        self.inode_cache = Some(cache);
+    }
+    
+    /// Drop `ino`, and the entry of the name it is saved under, from the cache when dropped
+    fn uncache_on_drop(&self, ino: u64, name: Option<(u64, FileName)>) -> Uncache<'_> {
+        Uncache { cache: self.inode_cache.as_deref(), ino, name }
+    }
+    
+    /// Case-fold a name for a case-insensitive pool
+    ///
+    /// Uses the Unicode lowercase mapping, which folds ASCII exactly. Names are
+    /// not normalized, so a precomposed "é" (NFC) and "e" plus a combining accent
+    /// (NFD) stay distinct names, as they do on a case-sensitive pool. Names
+    /// that are not UTF-8 only have their ASCII letters folded.
+    pub fn fold_name(name: &[u8]) -> FileName {
+        match std::str::from_utf8(name) {
+            Ok(name) => FileName::from(name.to_lowercase()),
+            Err(_) => FileName::from(name.to_ascii_lowercase()),
+        }
+    }
+    
+    /// Directory index key for `name` under `parent_ino`
+    fn dir_key(&self, parent_ino: u64, name: &[u8]) -> (u64, FileName) {
+        if self.fold_names {
+            (parent_ino, Self::fold_name(name))
+        } else {
+            (parent_ino, FileName::from(name))
+        }
+    }
+    
+    /// Whether two names in one directory refer to the same entry on this pool
+    pub fn same_name(&self, a: &[u8], b: &[u8]) -> bool {
+        self.dir_key(0, a) == self.dir_key(0, b)
+    }
+    
+    /// Load the inode an index entry points at, skipping entries that no longer match it
+    fn load_indexed_child(&self, parent_ino: u64, key_name: &FileName, ino: u64) -> Option<Inode> {
+        match self.load_inode(ino) {
+            Ok(inode) if self.dir_key(inode.parent_ino, inode.name.as_bytes()) == (parent_ino, key_name.clone()) => {
+                Some(inode)
+            }
+            _ => {
+                log::warn!(
+                    "Directory index entry ({}, {:?}) -> {} is stale; run check-dirindex --repair",
+                    parent_ino, key_name, ino
+                );
+                None
+            }
+        }
+    }
+    
+    /// Index an inode under its name, returning whether the entry is new
+    fn index_dir_entry(&self, inode: &Inode) -> Result<bool> {
+        // The root is its own parent but not its own child
+        if inode.ino == inode.parent_ino {
+            return Ok(false);
+        }
+        let key = self.dir_key(inode.parent_ino, inode.name.as_bytes());
+        if self.dir_index.get(&key) == Some(inode.ino) {
+            return Ok(false);
+        }
+        self.dir_index
+            .insert(key, inode.ino)
+            .context("Failed to update directory index")?;
+        Ok(true)
+    }
+    
+    fn unindex_dir_entry(&self, inode: &Inode) -> Result<()> {
+        let key = self.dir_key(inode.parent_ino, inode.name.as_bytes());
+        // Only drop the entry if it still names this inode (it may have been replaced)
+        if self.dir_index.get(&key) == Some(inode.ino) {
+            self.dir_index
+                .remove(&key)
+                .context("Failed to update directory index")?;
+        }
+        Ok(())
+    }
+    
+    /// Directory entries implied by the inode records on disk
+    fn dir_entries_from_inodes(&self) -> Result<BTreeMap<(u64, FileName), u64>> {
+        let mut entries = BTreeMap::new();
+        for key in self.store.keys(RecordKind::Inode)? {
+            if let Ok(Some(contents)) = self.store.load(RecordKind::Inode, &key?) {
+                if let Ok(inode) = serde_json::from_slice::<Inode>(&contents) {
+                    if inode.ino != inode.parent_ino {
+                        let key = self.dir_key(inode.parent_ino, inode.name.as_bytes());
+                        if let Some(other) = entries.insert(key, inode.ino) {
+                            log::warn!(
+                                "Inodes {} and {} have names in directory {} that differ only in case; one is hidden",
+                                other, inode.ino, inode.parent_ino
+                            );
+                        }
                     }
                 }
             }
         }
+        Ok(entries)
+    }
+    
+    /// Rebuild the directory index from the inode records, returning the entry count
+    pub fn rebuild_dir_index(&self) -> StorageResult<usize> {
+        let entries = self.dir_entries_from_inodes()?;
+        let count = entries.len();
+        self.dir_index.replace_all(entries)?;
+        Ok(count)
+    }
+    
+    /// Compare the directory index against the inode records, optionally rebuilding it
+    pub fn check_dir_index(&self, repair: bool) -> StorageResult<DirIndexReport> {
+        let expected = self.dir_entries_from_inodes()?;
+        let indexed: BTreeMap<(u64, FileName), u64> = self.dir_index.range(..).into_iter().collect();
         
-        Ok(children)
+        let to_entry = |((parent_ino, name), ino): (&(u64, FileName), &u64)| DirIndexEntry {
+            parent_ino: *parent_ino,
+            name: name.clone(),
+            ino: *ino,
+        };
+        let mut report = DirIndexReport {
+            expected_entries: expected.len(),
+            missing: expected
+                .iter()
+                .filter(|(key, ino)| indexed.get(*key) != Some(*ino))
+                .map(to_entry)
+                .collect(),
+            stale: indexed
+                .iter()
+                .filter(|(key, ino)| expected.get(*key) != Some(*ino))
+                .map(to_entry)
+                .collect(),
+            repaired: false,
+        };
+        
+        if repair && !report.is_consistent() {
+            self.dir_index.replace_all(expected)?;
+            report.repaired = true;
+        }
+        Ok(report)
     }
     
-    pub fn find_child(&self, parent_ino: u64, name: &str) -> Result<Option<Inode>> {
-        for child in self.list_directory(parent_ino)? {
-            if child.name == name {
-                return Ok(Some(child));
+    /// Verify every extent map file against its checksum
+    ///
+    /// With `repair`, stale maps (wrong checksum, but every extent they list
+    /// still exists) are saved again with a fresh checksum. Corrupt maps are
+    /// only reported.
+    pub fn check_extent_maps(&self, repair: bool) -> StorageResult<ExtentMapReport> {
+        let mut report = ExtentMapReport::default();
+        for ino in self.extent_map_inos()? {
+            let parsed = self.read_extent_map_record(ino);
+            report.checked += 1;
+            let map = match parsed {
+                Ok(map) if map.ino == ino => map,
+                Ok(map) => {
+                    report.corrupt.push(CorruptExtentMap { ino, reason: format!("records ino {}", map.ino) });
+                    continue;
+                }
+                Err(e) => {
+                    report.corrupt.push(CorruptExtentMap { ino, reason: e.to_string() });
+                    continue;
+                }
+            };
+            if map.checksum.is_none() {
+                report.unchecksummed += 1;
+                continue;
+            }
+            if let Err(mismatch) = map.verify_checksum() {
+                let missing: Vec<&Uuid> = map.data_extents().filter(|uuid| !self.extent_exists(uuid)).collect();
+                if !missing.is_empty() {
+                    report.corrupt.push(CorruptExtentMap {
+                        ino,
+                        reason: format!("{}; {} listed extents no longer exist", mismatch, missing.len()),
+                    });
+                    continue;
+                }
+                report.stale.push(ino);
+                if repair {
+                    self.save_extent_map(&map)?;
+                    report.repaired.push(ino);
+                }
             }
         }
-        Ok(None)
+        Ok(report)
     }
     
     // Extent operations
-    pub fn save_extent(&self, extent: &Extent) -> Result<()> {
-        let path = self.pool_dir.join("extents").join(extent.uuid.to_string());
+    pub fn save_extent(&self, extent: &Extent) -> StorageResult<()> {
+        let mut totals = self.extent_totals.lock().unwrap();
+        let key = extent.uuid.to_string();
+        let new = !self.store.exists(RecordKind::Extent, &key)?;
         let contents = serde_json::to_string_pretty(extent)?;
+        self.store.save(RecordKind::Extent, &key, contents.as_bytes())?;
+        if new {
+            totals.add(extent);
+        } else {
+            totals.update(extent);
+        }
+        Ok(totals.save(&self.pool_dir)?)
+    }
+    
+    pub fn load_extent(&self, uuid: &Uuid) -> StorageResult<Extent> {
+        match self.store.load(RecordKind::Extent, &uuid.to_string())? {
+            Some(contents) => Ok(serde_json::from_slice(&contents)?),
+            None => Err(StorageError::NotFound(format!("Extent {} not found", uuid))),
+        }
+    }
+    
+    pub fn delete_extent(&self, uuid: &Uuid) -> StorageResult<()> {
+        let mut totals = self.extent_totals.lock().unwrap();
+        let key = uuid.to_string();
+        if !self.store.exists(RecordKind::Extent, &key)? {
+            return Ok(());
+        }
+        let extent = self.load_extent(uuid);
+        self.store.delete(RecordKind::Extent, &key)?;
+        match extent {
+            Ok(extent) => totals.remove(&extent),
+            // An unparsable record was never counted by a save
+            Err(e) => log::warn!("Deleted unreadable extent record {}: {}", uuid, e),
+        }
+        Ok(totals.save(&self.pool_dir)?)
+    }
+    
+    /// Record that an extent is no longer referenced and can be reclaimed
+    ///
+    /// The extent record stays until `forget_released_extent`, so a crash
+    /// before its fragments are deleted leaves enough to finish the job. A
+    /// shared extent is only released once none of its holders' extent maps
+    /// still list it, so transactions save the releasing file's new map first.
+    /// A snapshot holds its extents for as long as it is committed.
+    pub fn release_extent(&self, uuid: &Uuid) -> StorageResult<()> {
+        if crate::snapshots::holds(&self.pool_dir, uuid) {
+            return Ok(());
+        }
+        let holders = self.extent_holders(uuid)?;
+        if !holders.is_empty() {
+            let remaining: Vec<u64> = holders
+                .into_iter()
+                .filter(|ino| self.load_extent_map(*ino).is_ok_and(|map| map.extents.contains(uuid)))
+                .collect();
+            // The last holder is kept, so replaying this release cannot free it
+            if !remaining.is_empty() {
+                return Ok(self.save_extent_holders(uuid, &remaining)?);
+            }
+            fs::remove_file(self.pool_dir.join("extent_refs").join(uuid.to_string()))?;
+        }
+        let path = self.pool_dir.join("released").join(uuid.to_string());
+        fs::write(&path, b"")?;
+        fs::File::open(&path)?.sync_all()?;
+        Ok(())
+    }
+    
+    /// Files sharing an extent, recorded when it was first shared
+    ///
+    /// Empty for extents only ever held by one file. The list may name files
+    /// that have since stopped referencing the extent; releases prune it.
+    pub fn extent_holders(&self, uuid: &Uuid) -> StorageResult<Vec<u64>> {
+        let path = self.pool_dir.join("extent_refs").join(uuid.to_string());
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    
+    /// Add `holders` to the files sharing an extent
+    ///
+    /// A union, so replaying it after a crash changes nothing.
+    pub fn share_extent(&self, uuid: &Uuid, holders: &[u64]) -> StorageResult<()> {
+        let mut all = self.extent_holders(uuid)?;
+        all.extend_from_slice(holders);
+        all.sort_unstable();
+        all.dedup();
+        Ok(self.save_extent_holders(uuid, &all)?)
+    }
+    
+    fn save_extent_holders(&self, uuid: &Uuid, holders: &[u64]) -> Result<()> {
+        let path = self.pool_dir.join("extent_refs").join(uuid.to_string());
         let temp_path = path.with_extension("tmp");
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent {:?} DuringExtentMetadata", extent.uuid);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::DuringExtentMetadata)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent {:?} writing temp", extent.uuid);
-        fs::write(&temp_path, &contents)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent {:?} AfterTempWrite", extent.uuid);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::AfterTempWrite)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent {:?} BeforeRename", extent.uuid);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::BeforeRename)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent {:?} renaming", extent.uuid);
+        fs::write(&temp_path, serde_json::to_string(holders)?)?;
         fs::rename(&temp_path, &path)?;
         Ok(())
     }
     
-    pub fn load_extent(&self, uuid: &Uuid) -> Result<Extent> {
-        let path = self.pool_dir.join("extents").join(uuid.to_string());
-        let contents = fs::read_to_string(path)?;
-        Ok(serde_json::from_str(&contents)?)
+    /// Extents released by committed transactions but not yet reclaimed
+    pub fn released_extents(&self) -> StorageResult<Vec<Uuid>> {
+        let dir = self.pool_dir.join("released");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut released = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            if let Ok(uuid) = Uuid::parse_str(&entry?.file_name().to_string_lossy()) {
+                released.push(uuid);
+            }
+        }
+        Ok(released)
     }
     
-    pub fn delete_extent(&self, uuid: &Uuid) -> Result<()> {
-        let path = self.pool_dir.join("extents").join(uuid.to_string());
+    /// Drop the release marker of a reclaimed extent
+    pub fn forget_released_extent(&self, uuid: &Uuid) -> StorageResult<()> {
+        let path = self.pool_dir.join("released").join(uuid.to_string());
         if path.exists() {
             fs::remove_file(path)?;
         }
         Ok(())
     }
     
-    pub fn list_all_extents(&self) -> Result<Vec<Extent>> {
-        let mut extents = Vec::new();
-        let extents_dir = self.pool_dir.join("extents");
-        
-        for entry in fs::read_dir(extents_dir)? {
-            let entry = entry?;
-            if let Ok(contents) = fs::read_to_string(entry.path()) {
-                if let Ok(extent) = serde_json::from_str::<Extent>(&contents) {
-                    extents.push(extent);
+    /// Every readable extent, loaded at once
+    ///
+    /// Memory grows with the pool; passes over a whole pool use `iter_extents`.
+    pub fn list_all_extents(&self) -> StorageResult<Vec<Extent>> {
+        Ok(self.iter_extents()?.filter_map(Result::ok).collect())
+    }
+    
+    /// Every extent, read lazily in the order the store lists them
+    pub fn iter_extents(&self) -> StorageResult<impl Iterator<Item = Result<Extent>>> {
+        Ok(self
+            .extent_records()?
+            .map(|(uuid, extent)| extent.with_context(|| format!("Extent record {} is unreadable", uuid))))
+    }
+    
+    /// Every extent record, read lazily in the order the store lists them
+    pub fn extent_records(&self) -> StorageResult<ExtentRecords> {
+        let keys = self.store.keys(RecordKind::Extent)?;
+        Ok(ExtentRecords { store: self.store.clone(), source: ExtentSource::Listed(keys) })
+    }
+    
+    /// Extent records in UUID order, starting after `after`
+    ///
+    /// The order does not change as extents come and go, so a pass can record
+    /// the last UUID it finished and resume from there. Only the UUIDs are
+    /// held in memory.
+    pub fn extent_records_sorted(&self, after: Option<Uuid>) -> StorageResult<ExtentRecords> {
+        let mut uuids = Vec::new();
+        for key in self.store.keys(RecordKind::Extent)? {
+            if let Ok(uuid) = Uuid::parse_str(&key?) {
+                if after.is_none_or(|after| uuid > after) {
+                    uuids.push(uuid);
                 }
             }
         }
-        
-        Ok(extents)
+        uuids.sort_unstable();
+        Ok(ExtentRecords { store: self.store.clone(), source: ExtentSource::Sorted(uuids.into_iter()) })
+    }
+    
+    /// Extent counts and sizes for the whole pool, without reading any extent
+    pub fn extent_totals(&self) -> ExtentTotals {
+        self.extent_totals.lock().unwrap().clone()
+    }
+    
+    /// Count every extent record again and save the result
+    ///
+    /// Returns the recounted totals; `check --repair` uses this after a crash
+    /// left the saved ones behind.
+    pub fn recount_extent_totals(&self) -> StorageResult<ExtentTotals> {
+        let mut totals = self.extent_totals.lock().unwrap();
+        let mut recounted = ExtentTotals::default();
+        for (_, extent) in self.extent_records()? {
+            if let Ok(extent) = extent {
+                recounted.add(&extent);
+            }
+        }
+        recounted.save(&self.pool_dir)?;
+        *totals = recounted.clone();
+        Ok(recounted)
+    }
+    
+    /// File and directory counts and sizes for the whole pool, without reading any inode
+    pub fn inode_totals(&self) -> InodeTotals {
+        *self.inode_totals.lock().unwrap()
+    }
+    
+    /// Count every inode record again and save the result
+    ///
+    /// Returns the recounted totals; `check --repair` uses this after a crash
+    /// left the saved ones behind.
+    pub fn recount_inode_totals(&self) -> StorageResult<InodeTotals> {
+        let mut totals = self.inode_totals.lock().unwrap();
+        let mut recounted = InodeTotals::default();
+        for key in self.store.keys(RecordKind::Inode)? {
+            let Ok(ino) = key?.parse() else { continue };
+            if let Ok(inode) = self.load_inode(ino) {
+                recounted.add(&inode);
+            }
+        }
+        recounted.save(&self.pool_dir)?;
+        *totals = recounted;
+        Ok(recounted)
+    }
+    
+    pub fn extent_exists(&self, uuid: &Uuid) -> bool {
+        self.store.exists(RecordKind::Extent, &uuid.to_string()).unwrap_or(false)
+    }
+    
+    pub fn pool_dir(&self) -> &Path {
+        &self.pool_dir
     }
     
     // Extent map operations
-    pub fn save_extent_map(&self, map: &ExtentMap) -> Result<()> {
+    pub fn save_extent_map(&self, map: &ExtentMap) -> StorageResult<()> {
         // Compute checksum before saving
         let mut map_with_checksum = map.clone();
-        map_with_checksum.checksum = Some(Self::compute_extent_map_checksum(map));
+        map_with_checksum.checksum = Some(map.compute_checksum());
+        self.stamp_changed_slots(&mut map_with_checksum)?;
         
-        let path = self.pool_dir.join("extent_maps").join(map.ino.to_string());
         let contents = serde_json::to_string_pretty(&map_with_checksum)?;
-        let temp_path = path.with_extension("tmp");
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent_map ino={} DuringExtentMap", map.ino);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::DuringExtentMap)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent_map ino={} writing temp", map.ino);
-        fs::write(&temp_path, &contents)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent_map ino={} AfterTempWrite", map.ino);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::AfterTempWrite)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent_map ino={} BeforeRename", map.ino);
-        #[cfg(test)]
-        check_crash_point(CrashPoint::BeforeRename)?;
-        
-        #[cfg(test)]
-        eprintln!("[METADATA DEBUG] save_extent_map ino={} renaming", map.ino);
-        fs::rename(&temp_path, &path)?;
+        self.store.save(RecordKind::ExtentMap, &map.ino.to_string(), contents.as_bytes())?;
         
         // update persisted extent map table
         #[cfg(test)]
@@ -491,41 +1457,222 @@ impl MetadataManager {
         Ok(())
     }
     
-    pub fn load_extent_map(&self, ino: u64) -> Result<ExtentMap> {
-        // Prefer file-based storage if present (so on-disk corruption is detectable);
-        // fallback to btree index if file is missing.
-        let path = self.pool_dir.join("extent_maps").join(ino.to_string());
-        if path.exists() {
-            let contents = fs::read_to_string(&path).unwrap_or_else(|_| {
-                serde_json::to_string(&ExtentMap {
-                    ino,
-                    extents: Vec::new(),
-                    checksum: None,
-                })
-                .unwrap()
-            });
-            let map: ExtentMap = serde_json::from_str(&contents)?;
-            // Verify checksum if present
-            Self::verify_extent_map_checksum(&map)
+    /// Carry over the change generations of the slots `map` keeps from the saved
+    /// map, giving changed slots a new one
+    fn stamp_changed_slots(&self, map: &mut ExtentMap) -> Result<()> {
+        let saved = self.extent_map_table.get(&map.ino);
+        let saved = saved.as_ref().filter(|saved| saved.extent_size == map.extent_size);
+        if let Some(saved) = saved.filter(|saved| saved.extents == map.extents) {
+            map.change_generation = saved.change_generation;
+            map.slot_generations = saved.slot_generations.clone();
+            return Ok(());
+        }
+        let generation = self.next_change_generation()?;
+        map.change_generation = generation;
+        map.slot_generations = (0..map.extents.len())
+            .map(|index| match saved {
+                Some(saved) if saved.extents.get(index) == map.extents.get(index) => {
+                    saved.slot_generations.get(index).copied().unwrap_or(generation)
+                }
+                _ => generation,
+            })
+            .collect();
+        Ok(())
+    }
+    
+    /// Flush the inode, extent map and extent records of `ino` to stable storage
+    ///
+    /// Committed transactions are synced first. Records that do not exist
+    /// (e.g. a directory without an extent map) are skipped.
+    pub fn sync_inode_metadata(&self, ino: u64, extent_uuids: &[Uuid]) -> StorageResult<()> {
+        // Their last transaction may still wait for its group's sync
+        self.sync_commits()?;
+        self.store.sync(RecordKind::Inode, &[ino.to_string()])?;
+        self.store.sync(RecordKind::ExtentMap, &[ino.to_string()])?;
+        let extents: Vec<String> = extent_uuids.iter().map(Uuid::to_string).collect();
+        self.store.sync(RecordKind::Extent, &extents)?;
+        Ok(())
+    }
+
+    pub fn load_extent_map(&self, ino: u64) -> StorageResult<ExtentMap> {
+        // Prefer the record if present (so corruption in the store is detectable);
+        // fallback to btree index if it is missing.
+        let record = self
+            .store
+            .load(RecordKind::ExtentMap, &ino.to_string())
+            .with_context(|| format!("Failed to read extent map for ino {}", ino))?;
+        if let Some(contents) = record {
+            let map: ExtentMap = serde_json::from_slice(&contents)
+                .with_context(|| format!("Corrupted extent map metadata for ino {}", ino))?;
+            // A mismatch surfaces as `ExtentMapChecksumMismatch` for callers that downcast
+            map.verify_checksum()
                 .context(format!("Corrupted extent map metadata for ino {}", ino))?;
             return Ok(map);
         }
 
         // Fallback to btree index
         if let Some(map) = self.extent_map_table.get(&ino) {
-            Self::verify_extent_map_checksum(&map).context(format!("Corrupted extent map metadata for ino {}", ino))?;
+            map.verify_checksum().context(format!("Corrupted extent map metadata for ino {}", ino))?;
             return Ok(map);
         }
 
         // If neither exists, return an empty map
-        Ok(ExtentMap { ino, extents: Vec::new(), checksum: None })
+        Ok(ExtentMap::new(ino, crate::extent::DEFAULT_EXTENT_SIZE))
+    }
+    
+    /// The extent map record of `ino` as stored, without checking its checksum
+    ///
+    /// For checks that report a bad map rather than fail on it.
+    pub fn read_extent_map_record(&self, ino: u64) -> Result<ExtentMap> {
+        let contents = self
+            .store
+            .load(RecordKind::ExtentMap, &ino.to_string())?
+            .ok_or_else(|| anyhow!("Extent map {} not found", ino))?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+    
+    pub fn delete_extent_map(&self, ino: u64) -> StorageResult<()> {
+        self.store.delete(RecordKind::ExtentMap, &ino.to_string())?;
+        self.extent_map_table.remove(&ino)?;
+        Ok(())
+    }
+    
+    // Quota operations
+    pub fn save_quota(&self, quota: &Quota) -> StorageResult<()> {
+        let path = self.pool_dir.join("quotas").join(quota.dir_ino.to_string());
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(quota)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+    
+    pub fn load_quota(&self, dir_ino: u64) -> StorageResult<Option<Quota>> {
+        let path = self.pool_dir.join("quotas").join(dir_ino.to_string());
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
     
-    pub fn delete_extent_map(&self, ino: u64) -> Result<()> {
-        let path = self.pool_dir.join("extent_maps").join(ino.to_string());
+    pub fn delete_quota(&self, dir_ino: u64) -> StorageResult<()> {
+        let path = self.pool_dir.join("quotas").join(dir_ino.to_string());
         if path.exists() {
             fs::remove_file(path)?;
         }
         Ok(())
     }
+    
+    /// Whether any directory in the pool has a quota
+    pub fn has_quotas(&self) -> bool {
+        fs::read_dir(self.pool_dir.join("quotas"))
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+    }
+    
+    /// Every quota in the pool, ordered by directory inode
+    pub fn list_quotas(&self) -> StorageResult<Vec<Quota>> {
+        let dir = self.pool_dir.join("quotas");
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut quotas = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let Ok(dir_ino) = entry?.file_name().to_string_lossy().parse::<u64>() else {
+                continue;
+            };
+            quotas.extend(self.load_quota(dir_ino)?);
+        }
+        quotas.sort_by_key(|quota| quota.dir_ino);
+        Ok(quotas)
+    }
+    
+    /// Quota records after charging a usage change under `parent_ino`
+    ///
+    /// Every quota on the directories from `parent_ino` up to the root is
+    /// charged. Nothing is saved: callers persist the returned records along
+    /// with the change, normally as `MetadataOp::SaveQuota` in its transaction.
+    /// Fails with `QuotaExceeded` if any of them would go over its limit.
+    pub fn charge_quotas(&self, parent_ino: u64, bytes: i64, inodes: i64) -> StorageResult<Vec<Quota>> {
+        // Orphans gave their usage back when they were unlinked
+        if (bytes == 0 && inodes == 0) || parent_ino == ORPHAN_PARENT_INO || !self.has_quotas() {
+            return Ok(Vec::new());
+        }
+        let mut charged = Vec::new();
+        let mut ino = parent_ino;
+        loop {
+            if let Some(mut quota) = self.load_quota(ino)? {
+                quota.charge(bytes, inodes)?;
+                charged.push(quota);
+            }
+            let parent = self.load_inode(ino)?.parent_ino;
+            if parent == ino {
+                return Ok(charged);
+            }
+            ino = parent;
+        }
+    }
+    
+    /// Logical bytes and inode count of the tree under a directory, excluding the directory
+    pub fn tree_usage(&self, dir_ino: u64) -> StorageResult<(u64, u64)> {
+        let (mut bytes, mut inodes) = (0u64, 0u64);
+        let mut pending = vec![dir_ino];
+        while let Some(dir) = pending.pop() {
+            for child in self.list_directory(dir)? {
+                inodes += 1;
+                match child.file_type {
+                    FileType::Directory => pending.push(child.ino),
+                    _ => bytes += child.size,
+                }
+            }
+        }
+        Ok((bytes, inodes))
+    }
+    
+    /// Inodes with an extent map, in ascending order
+    pub fn extent_map_inos(&self) -> StorageResult<Vec<u64>> {
+        self.record_numbers(RecordKind::ExtentMap)
+    }
+    
+    /// How many extent maps reference each data extent
+    ///
+    /// Reads the maps one at a time, so only the counts are held in memory.
+    pub fn extent_reference_counts(&self) -> StorageResult<std::collections::HashMap<Uuid, u32>> {
+        let mut counts = std::collections::HashMap::new();
+        for key in self.store.keys(RecordKind::ExtentMap)? {
+            let Ok(ino) = key?.parse::<u64>() else {
+                continue;
+            };
+            let Ok(map) = self.load_extent_map(ino) else {
+                continue;
+            };
+            for uuid in map.data_extents() {
+                *counts.entry(*uuid).or_insert(0) += 1;
+            }
+        }
+        Ok(counts)
+    }
+    
+    /// Inode at an absolute path inside the pool, e.g. `/projects/foo`
+    pub fn resolve_path(&self, path: impl AsRef<[u8]>) -> StorageResult<Inode> {
+        let path = path.as_ref();
+        let mut inode = self.load_inode(1)?;
+        for name in path.split(|&b| b == b'/').filter(|name| !name.is_empty()) {
+            inode = self.find_child(inode.ino, name)?.ok_or_else(|| {
+                StorageError::NotFound(format!("No such file or directory: {}", String::from_utf8_lossy(path)))
+            })?;
+        }
+        Ok(inode)
+    }
+    
+    /// Absolute path of an inode inside the pool
+    pub fn path_of(&self, ino: u64) -> StorageResult<FileName> {
+        let mut names = Vec::new();
+        let mut inode = self.load_inode(ino)?;
+        while inode.parent_ino != inode.ino {
+            names.push(inode.name.clone());
+            inode = self.load_inode(inode.parent_ino)?;
+        }
+        Ok(names.iter().rev().fold(FileName::from("/"), |path, name| path.join(name)))
+    }
 }
@@ -2,18 +2,112 @@ use anyhow::{Context, Result};
 use serde::{de::DeserializeOwned, Serialize, Deserialize};
 use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+/// Snapshots are rewritten once the log holds this many records (and at least one per entry)
+const MIN_COMPACT_RECORDS: usize = 1024;
+
+/// A mutation appended to the log between snapshots
+#[derive(Serialize, Deserialize)]
+enum LogOp<K, V> {
+    Insert(K, V),
+    Remove(K),
+}
+
 /// Generic persisted B-tree map using bincode serialization with interior mutability.
+///
+/// The map is stored as a bincode snapshot plus an append-only log of mutations
+/// (`<path>.btlog`), so an update costs one small append instead of rewriting the
+/// whole map. The log is folded into the snapshot once it grows as large as the map.
 pub struct PersistedBTree<K, V> {
     inner: Mutex<PersistedBTreeInner<K, V>>,
 }
 
-#[derive(Clone)]
 struct PersistedBTreeInner<K, V> {
     map: BTreeMap<K, V>,
     persist_path: Option<PathBuf>,
+    log_records: usize,
+}
+
+impl<K, V> PersistedBTreeInner<K, V>
+where
+    K: Ord + Clone + Serialize + DeserializeOwned + Send + 'static,
+    V: Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    fn log_path(path: &Path) -> PathBuf {
+        path.with_extension("btlog")
+    }
+
+    /// Replay logged mutations on top of the snapshot, stopping at a torn tail
+    ///
+    /// Records are framed as a little-endian u32 length followed by the bincode payload.
+    fn replay_log(&mut self, path: &Path) {
+        let contents = match fs::read(Self::log_path(path)) {
+            Ok(contents) => contents,
+            Err(_) => return,
+        };
+        let mut rest = contents.as_slice();
+        let mut undecodable = 0usize;
+        while rest.len() >= 4 {
+            let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if rest.len() - 4 < len {
+                eprintln!("Warning: ignoring torn tail of btree log {:?}", Self::log_path(path));
+                break;
+            }
+            match bincode::deserialize::<LogOp<K, V>>(&rest[4..4 + len]) {
+                Ok(LogOp::Insert(k, v)) => {
+                    self.map.insert(k, v);
+                }
+                Ok(LogOp::Remove(k)) => {
+                    self.map.remove(&k);
+                }
+                Err(_) => undecodable += 1,
+            }
+            self.log_records += 1;
+            rest = &rest[4 + len..];
+        }
+        if undecodable > 0 {
+            eprintln!("Warning: skipped {} undecodable records in btree log {:?}", undecodable, Self::log_path(path));
+        }
+    }
+
+    fn append(&mut self, op: &LogOp<&K, &V>) -> Result<()> {
+        let path = match &self.persist_path {
+            Some(path) => path.clone(),
+            None => return Ok(()),
+        };
+        let payload = bincode::serialize(op).context("Failed to serialize btree log record")?;
+        let mut record = (payload.len() as u32).to_le_bytes().to_vec();
+        record.extend_from_slice(&payload);
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::log_path(&path))
+            .and_then(|mut log| log.write_all(&record))
+            .context("Failed to append btree log record")?;
+        self.log_records += 1;
+        if self.log_records >= MIN_COMPACT_RECORDS.max(self.map.len()) {
+            self.write_snapshot()?;
+        }
+        Ok(())
+    }
+
+    /// Write the full map as the new snapshot and truncate the log
+    fn write_snapshot(&mut self) -> Result<()> {
+        if let Some(path) = &self.persist_path {
+            let tmp = path.with_extension("bt.tmp");
+            let encoded = bincode::serialize(&self.map).context("Failed to serialize btree map")?;
+            fs::write(&tmp, encoded).context("Failed to write tmp btree file")?;
+            fs::rename(&tmp, path).context("Failed to atomically persist btree file")?;
+            // Replaying a stale log onto the new snapshot is harmless, so a crash here loses nothing
+            fs::File::create(Self::log_path(path)).context("Failed to truncate btree log")?;
+        }
+        self.log_records = 0;
+        Ok(())
+    }
 }
 
 impl<K, V> PersistedBTree<K, V>
@@ -25,6 +119,7 @@ where
         let mut inner = PersistedBTreeInner {
             map: BTreeMap::new(),
             persist_path: persist_path.clone(),
+            log_records: 0,
         };
         if let Some(path) = &persist_path {
             if path.exists() {
@@ -43,6 +138,7 @@ where
                     }
                 }
             }
+            inner.replay_log(path);
         }
         Ok(PersistedBTree {
             inner: Mutex::new(inner),
@@ -51,34 +147,38 @@ where
 
     pub fn insert(&self, k: K, v: V) -> Result<()> {
         let mut guard = self.inner.lock().unwrap();
+        guard.append(&LogOp::Insert(&k, &v))?;
         guard.map.insert(k, v);
-        // persist
-        if let Some(path) = &guard.persist_path {
-            let tmp = path.with_extension("bt.tmp");
-            let encoded = bincode::serialize(&guard.map).context("Failed to serialize btree map")?;
-            fs::write(&tmp, encoded).context("Failed to write tmp btree file")?;
-            fs::rename(&tmp, path).context("Failed to atomically persist btree file")?;
-        }
         Ok(())
     }
 
     pub fn remove(&self, k: &K) -> Result<Option<V>> {
         let mut guard = self.inner.lock().unwrap();
         let res = guard.map.remove(k);
-        if let Some(path) = &guard.persist_path {
-            let tmp = path.with_extension("bt.tmp");
-            let encoded = bincode::serialize(&guard.map).context("Failed to serialize btree map")?;
-            fs::write(&tmp, encoded).context("Failed to write tmp btree file")?;
-            fs::rename(&tmp, path).context("Failed to atomically persist btree file")?;
+        if res.is_some() {
+            guard.append(&LogOp::Remove(k))?;
         }
         Ok(res)
     }
 
+    /// Replace the whole map and persist it as a fresh snapshot
+    pub fn replace_all(&self, map: BTreeMap<K, V>) -> Result<()> {
+        let mut guard = self.inner.lock().unwrap();
+        guard.map = map;
+        guard.write_snapshot()
+    }
+
     pub fn get(&self, k: &K) -> Option<V> {
         let guard = self.inner.lock().unwrap();
         guard.map.get(k).cloned()
     }
 
+    /// Entries with keys inside `range`, in key order
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Vec<(K, V)> {
+        let guard = self.inner.lock().unwrap();
+        guard.map.range(range).map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
     pub fn contains_key(&self, k: &K) -> bool {
         let guard = self.inner.lock().unwrap();
         guard.map.contains_key(k)
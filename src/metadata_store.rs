@@ -0,0 +1,667 @@
+//! Where `MetadataManager` keeps inode, extent and extent map records
+//!
+//! Each record is a JSON document under a key: the inode number of an inode
+//! or extent map, or the UUID of an extent. The default store keeps one file
+//! per record in `inodes/`, `extents/` and `extent_maps/`, replaced by
+//! renaming a temp file over it. A pool with millions of extents spends more
+//! on directory lookups and host inodes than on the records themselves; built
+//! with the `kv-metadata` feature, it can keep them in one redb database under
+//! `metadata/` instead. The store is chosen at `init`, changed by
+//! `migrate-metadata`, and recorded in pool.json.
+//!
+//! The directory index, quotas, counters and transaction journal stay in
+//! files whichever store holds the records.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[cfg(test)]
+use crate::crash_sim::{check_fault_at, CrashPoint};
+
+/// File the key-value store keeps every record in, under `metadata/`
+pub const KV_STORE_FILE: &str = "records.redb";
+
+/// Records `migrate` copies per transaction
+const MIGRATE_BATCH: usize = 4096;
+
+/// Store of a pool's records; see the module docs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataBackend {
+    /// One file per record
+    #[default]
+    Files,
+    /// A redb database, in builds with the `kv-metadata` feature
+    Kv,
+}
+
+impl std::fmt::Display for MetadataBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataBackend::Files => write!(f, "files"),
+            MetadataBackend::Kv => write!(f, "kv"),
+        }
+    }
+}
+
+impl std::str::FromStr for MetadataBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "files" => Ok(MetadataBackend::Files),
+            "kv" => Ok(MetadataBackend::Kv),
+            other => Err(anyhow!("Invalid metadata backend: {}. Use files or kv", other)),
+        }
+    }
+}
+
+/// Kind of record, each with keys of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Inode,
+    Extent,
+    ExtentMap,
+}
+
+impl RecordKind {
+    pub const ALL: [RecordKind; 3] = [RecordKind::Inode, RecordKind::Extent, RecordKind::ExtentMap];
+
+    /// Directory of the file store and table of the key-value store
+    pub fn name(self) -> &'static str {
+        match self {
+            RecordKind::Inode => "inodes",
+            RecordKind::Extent => "extents",
+            RecordKind::ExtentMap => "extent_maps",
+        }
+    }
+
+    /// Where a crash test can stop a save before anything is written
+    #[cfg(test)]
+    fn save_crash_point(self) -> CrashPoint {
+        match self {
+            RecordKind::Inode => CrashPoint::BeforeTempWrite,
+            RecordKind::Extent => CrashPoint::DuringExtentMetadata,
+            RecordKind::ExtentMap => CrashPoint::DuringExtentMap,
+        }
+    }
+}
+
+/// Keys of one kind of record, read lazily
+pub type RecordKeys = Box<dyn Iterator<Item = Result<String>> + Send>;
+
+/// Records of one pool, by kind and key
+pub trait MetadataStore: Send + Sync {
+    fn backend(&self) -> MetadataBackend;
+
+    /// The record under `key`, or `None` if there is none
+    fn load(&self, kind: RecordKind, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Replace the record under `key`; a crash leaves either the old record or the new one
+    fn save(&self, kind: RecordKind, key: &str, contents: &[u8]) -> Result<()>;
+
+    /// Save several records, in one transaction where the store has them
+    fn save_batch(&self, kind: RecordKind, records: &[(String, Vec<u8>)]) -> Result<()> {
+        for (key, contents) in records {
+            self.save(kind, key, contents)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the record under `key`, returning whether there was one
+    fn delete(&self, kind: RecordKind, key: &str) -> Result<bool>;
+
+    fn exists(&self, kind: RecordKind, key: &str) -> Result<bool> {
+        Ok(self.load(kind, key)?.is_some())
+    }
+
+    /// Keys of every record of `kind`, in no particular order
+    ///
+    /// Records saved or deleted while the keys are read may or may not be among them.
+    fn keys(&self, kind: RecordKind) -> Result<RecordKeys>;
+
+    /// Make the records under `keys`, saved or deleted, survive a crash
+    fn sync(&self, kind: RecordKind, keys: &[String]) -> Result<()>;
+}
+
+/// Open the `backend` store of the pool at `pool_dir`, creating it if missing
+pub fn open(pool_dir: &Path, backend: MetadataBackend) -> Result<Arc<dyn MetadataStore>> {
+    match backend {
+        MetadataBackend::Files => Ok(Arc::new(FileStore::open(pool_dir)?)),
+        #[cfg(feature = "kv-metadata")]
+        MetadataBackend::Kv => Ok(Arc::new(kv::KvStore::open(pool_dir)?)),
+        #[cfg(not(feature = "kv-metadata"))]
+        MetadataBackend::Kv => Err(crate::error::StorageError::Unsupported(format!(
+            "Pool {:?} keeps its metadata in a key-value store; this build lacks the kv-metadata feature",
+            pool_dir
+        ))
+        .into()),
+    }
+}
+
+/// Remove every record of the `backend` store of the pool at `pool_dir`
+fn destroy(pool_dir: &Path, backend: MetadataBackend) -> Result<()> {
+    let paths = match backend {
+        MetadataBackend::Files => RecordKind::ALL.iter().map(|kind| pool_dir.join(kind.name())).collect(),
+        MetadataBackend::Kv => vec![pool_dir.join("metadata").join(KV_STORE_FILE)],
+    };
+    for path in paths {
+        let removed = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        match removed {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to remove {:?}", path)),
+        }
+    }
+    Ok(())
+}
+
+/// One file per record, replaced by writing a temp file and renaming it over
+pub struct FileStore {
+    pool_dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn open(pool_dir: &Path) -> Result<Self> {
+        for kind in RecordKind::ALL {
+            fs::create_dir_all(pool_dir.join(kind.name()))?;
+        }
+        Ok(FileStore { pool_dir: pool_dir.to_path_buf() })
+    }
+
+    fn path(&self, kind: RecordKind, key: &str) -> PathBuf {
+        self.pool_dir.join(kind.name()).join(key)
+    }
+}
+
+impl MetadataStore for FileStore {
+    fn backend(&self) -> MetadataBackend {
+        MetadataBackend::Files
+    }
+
+    fn load(&self, kind: RecordKind, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.path(kind, key)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, kind: RecordKind, key: &str, contents: &[u8]) -> Result<()> {
+        let path = self.path(kind, key);
+        let temp_path = path.with_extension("tmp");
+        #[cfg(test)]
+        check_fault_at(kind.save_crash_point(), &path)?;
+        fs::write(&temp_path, contents)?;
+        #[cfg(test)]
+        check_fault_at(CrashPoint::AfterTempWrite, &path)?;
+        #[cfg(test)]
+        check_fault_at(CrashPoint::BeforeRename, &path)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    fn delete(&self, kind: RecordKind, key: &str) -> Result<bool> {
+        match fs::remove_file(self.path(kind, key)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn exists(&self, kind: RecordKind, key: &str) -> Result<bool> {
+        Ok(self.path(kind, key).exists())
+    }
+
+    fn keys(&self, kind: RecordKind) -> Result<RecordKeys> {
+        let entries = fs::read_dir(self.pool_dir.join(kind.name()))?;
+        // Temp files of saves in progress are not records
+        Ok(Box::new(entries.filter_map(|entry| match entry {
+            Ok(entry) => entry.file_name().into_string().ok().filter(|name| !name.contains('.')).map(Ok),
+            Err(e) => Some(Err(e.into())),
+        })))
+    }
+
+    fn sync(&self, kind: RecordKind, keys: &[String]) -> Result<()> {
+        for key in keys {
+            let path = self.path(kind, key);
+            match fs::File::open(&path) {
+                Ok(file) => file.sync_all().with_context(|| format!("Failed to fsync {}", path.display()))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => Err(e).with_context(|| format!("Failed to open {}", path.display()))?,
+            }
+        }
+        // The renames that committed the files, and the removals
+        let dir = self.pool_dir.join(kind.name());
+        fs::File::open(&dir)
+            .and_then(|d| d.sync_all())
+            .with_context(|| format!("Failed to fsync directory {}", dir.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kv-metadata")]
+mod kv {
+    use super::{MetadataBackend, MetadataStore, RecordKeys, RecordKind, KV_STORE_FILE};
+    #[cfg(test)]
+    use crate::crash_sim::{check_fault_at, CrashPoint};
+    use anyhow::{anyhow, Result};
+    use redb::{Database, Durability, TableDefinition};
+    use std::collections::HashMap;
+    use std::ops::Bound;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+    /// Keys read per read transaction while listing a table
+    const KEY_PAGE: usize = 1024;
+
+    /// Commits between ones made durable, which let redb reuse the pages the others freed
+    const DURABLE_EVERY: u64 = 1024;
+
+    fn table(kind: RecordKind) -> TableDefinition<'static, &'static str, &'static [u8]> {
+        TableDefinition::new(kind.name())
+    }
+
+    /// redb allows one `Database` per file in a process; managers of the same pool share it
+    fn shared_database(path: &Path) -> Result<Arc<Database>> {
+        static OPEN: OnceLock<Mutex<HashMap<PathBuf, Weak<Database>>>> = OnceLock::new();
+        let mut open = OPEN.get_or_init(Default::default).lock().unwrap();
+        open.retain(|_, db| db.strong_count() > 0);
+        let key = path.parent().and_then(|dir| dir.canonicalize().ok()).map_or(path.to_path_buf(), |dir| {
+            dir.join(KV_STORE_FILE)
+        });
+        if let Some(db) = open.get(&key).and_then(Weak::upgrade) {
+            return Ok(db);
+        }
+        let db = Database::create(path).map_err(|e| match e {
+            redb::DatabaseError::DatabaseAlreadyOpen => {
+                anyhow!("Metadata store {:?} is open in another process; is the pool mounted?", path)
+            }
+            other => anyhow::Error::from(other).context(format!("Failed to open metadata store {:?}", path)),
+        })?;
+        let db = Arc::new(db);
+        open.insert(key, Arc::downgrade(&db));
+        Ok(db)
+    }
+
+    /// Every record in one redb database, a table per kind
+    ///
+    /// Commits are not synced one by one, as renames in the file store are
+    /// not; `sync` makes everything committed so far durable.
+    pub struct KvStore {
+        db: Arc<Database>,
+        commits: AtomicU64,
+        #[cfg(test)]
+        pool_dir: PathBuf,
+    }
+
+    impl KvStore {
+        pub fn open(pool_dir: &Path) -> Result<Self> {
+            let metadata_dir = pool_dir.join("metadata");
+            std::fs::create_dir_all(&metadata_dir)?;
+            let db = shared_database(&metadata_dir.join(KV_STORE_FILE))?;
+            // Reads fail on a table that was never created
+            let tx = db.begin_write()?;
+            for kind in RecordKind::ALL {
+                tx.open_table(table(kind))?;
+            }
+            tx.commit()?;
+            Ok(KvStore {
+                db,
+                commits: AtomicU64::new(0),
+                #[cfg(test)]
+                pool_dir: pool_dir.to_path_buf(),
+            })
+        }
+
+        /// Crash points of a save where the file store has them; inside the
+        /// write transaction, a crash at any of them aborts it
+        #[cfg(test)]
+        fn check_save_faults(&self, kind: RecordKind, key: &str, inserted: bool) -> Result<()> {
+            let path = self.pool_dir.join(kind.name()).join(key);
+            if !inserted {
+                return check_fault_at(kind.save_crash_point(), &path);
+            }
+            check_fault_at(CrashPoint::AfterTempWrite, &path)?;
+            check_fault_at(CrashPoint::BeforeRename, &path)
+        }
+
+        fn write<T>(&self, change: impl FnOnce(&redb::WriteTransaction) -> Result<T>) -> Result<T> {
+            let mut tx = self.db.begin_write()?;
+            if !(self.commits.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(DURABLE_EVERY) {
+                tx.set_durability(Durability::Eventual);
+            }
+            let result = change(&tx)?;
+            tx.commit()?;
+            Ok(result)
+        }
+
+    }
+
+    /// Up to `KEY_PAGE` keys of `kind` after `after`, in key order
+    fn key_page(db: &Database, kind: RecordKind, after: Option<&str>) -> Result<Vec<String>> {
+        let tx = db.begin_read()?;
+        let records = tx.open_table(table(kind))?;
+        let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+        records
+            .range::<&str>((start, Bound::Unbounded))?
+            .take(KEY_PAGE)
+            .map(|entry| Ok(entry?.0.value().to_string()))
+            .collect()
+    }
+
+    impl MetadataStore for KvStore {
+        fn backend(&self) -> MetadataBackend {
+            MetadataBackend::Kv
+        }
+
+        fn load(&self, kind: RecordKind, key: &str) -> Result<Option<Vec<u8>>> {
+            let tx = self.db.begin_read()?;
+            let records = tx.open_table(table(kind))?;
+            let contents = records.get(key)?.map(|value| value.value().to_vec());
+            Ok(contents)
+        }
+
+        fn save(&self, kind: RecordKind, key: &str, contents: &[u8]) -> Result<()> {
+            self.write(|tx| {
+                #[cfg(test)]
+                self.check_save_faults(kind, key, false)?;
+                tx.open_table(table(kind))?.insert(key, contents)?;
+                #[cfg(test)]
+                self.check_save_faults(kind, key, true)?;
+                Ok(())
+            })
+        }
+
+        fn save_batch(&self, kind: RecordKind, records: &[(String, Vec<u8>)]) -> Result<()> {
+            self.write(|tx| {
+                let mut table = tx.open_table(table(kind))?;
+                for (key, contents) in records {
+                    #[cfg(test)]
+                    self.check_save_faults(kind, key, false)?;
+                    table.insert(key.as_str(), contents.as_slice())?;
+                    #[cfg(test)]
+                    self.check_save_faults(kind, key, true)?;
+                }
+                Ok(())
+            })
+        }
+
+        fn delete(&self, kind: RecordKind, key: &str) -> Result<bool> {
+            self.write(|tx| {
+                let removed = tx.open_table(table(kind))?.remove(key)?.is_some();
+                Ok(removed)
+            })
+        }
+
+        fn keys(&self, kind: RecordKind) -> Result<RecordKeys> {
+            let db = self.db.clone();
+            let mut after: Option<String> = None;
+            let mut page = Vec::new().into_iter();
+            let mut done = false;
+            Ok(Box::new(std::iter::from_fn(move || loop {
+                if let Some(key) = page.next() {
+                    return Some(Ok(key));
+                }
+                if done {
+                    return None;
+                }
+                match key_page(&db, kind, after.as_deref()) {
+                    Ok(keys) => {
+                        done = keys.len() < KEY_PAGE;
+                        after = keys.last().cloned();
+                        page = keys.into_iter();
+                    }
+                    Err(e) => {
+                        done = true;
+                        return Some(Err(e));
+                    }
+                }
+            })))
+        }
+
+        fn sync(&self, _kind: RecordKind, _keys: &[String]) -> Result<()> {
+            // A durable commit makes the eventual ones before it durable too
+            let mut tx = self.db.begin_write()?;
+            tx.set_durability(Durability::Immediate);
+            tx.commit()?;
+            Ok(())
+        }
+    }
+}
+
+/// Records of each kind copied by `migrate`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationReport {
+    pub from: MetadataBackend,
+    pub to: MetadataBackend,
+    pub inodes: u64,
+    pub extents: u64,
+    pub extent_maps: u64,
+}
+
+impl MigrationReport {
+    fn count_mut(&mut self, kind: RecordKind) -> &mut u64 {
+        match kind {
+            RecordKind::Inode => &mut self.inodes,
+            RecordKind::Extent => &mut self.extents,
+            RecordKind::ExtentMap => &mut self.extent_maps,
+        }
+    }
+}
+
+/// Copy every record of the pool at `pool_dir` into a new `to` store and switch the pool to it
+///
+/// The pool must not be open. The new store is filled from scratch, then the
+/// records of each kind are counted and compared, by BLAKE3 hash, with the
+/// ones they were copied from. Only then is `to` recorded in pool.json; until
+/// that point the pool keeps its old store, and an interrupted migration
+/// leaves a partial new store that the next one starts over. The old store
+/// is removed last.
+pub fn migrate(pool_dir: &Path, to: MetadataBackend) -> Result<MigrationReport> {
+    let from = crate::disk::DiskPool::metadata_backend(pool_dir)?;
+    if from == to {
+        return Err(anyhow!("Pool {:?} already keeps its metadata in {}", pool_dir, to));
+    }
+    destroy(pool_dir, to)?;
+    let report = {
+        let source = open(pool_dir, from)?;
+        let target = open(pool_dir, to)?;
+        let mut report = MigrationReport { from, to, ..Default::default() };
+        for kind in RecordKind::ALL {
+            *report.count_mut(kind) = copy_records(source.as_ref(), target.as_ref(), kind)
+                .with_context(|| format!("Failed to copy {} records", kind.name()))?;
+            target.sync(kind, &[])?;
+        }
+        for kind in RecordKind::ALL {
+            verify_copy(source.as_ref(), target.as_ref(), kind, *report.count_mut(kind))
+                .with_context(|| format!("Copied {} records do not match; the pool still uses {}", kind.name(), from))?;
+        }
+        report
+    };
+
+    let mut pool = crate::disk::DiskPool::load(pool_dir)?;
+    pool.metadata_backend = to;
+    pool.save(pool_dir)?;
+    destroy(pool_dir, from)?;
+    Ok(report)
+}
+
+/// Copy the records of `kind` in batches, returning how many there were
+fn copy_records(source: &dyn MetadataStore, target: &dyn MetadataStore, kind: RecordKind) -> Result<u64> {
+    let mut copied = 0;
+    let mut batch = Vec::with_capacity(MIGRATE_BATCH);
+    for key in source.keys(kind)? {
+        let key = key?;
+        // Listed but gone; the pool is locked, so never expected
+        if let Some(contents) = source.load(kind, &key)? {
+            batch.push((key, contents));
+        }
+        if batch.len() == MIGRATE_BATCH {
+            target.save_batch(kind, &batch)?;
+            copied += batch.len() as u64;
+            batch.clear();
+        }
+    }
+    target.save_batch(kind, &batch)?;
+    Ok(copied + batch.len() as u64)
+}
+
+/// Check that `target` holds exactly `count` records of `kind`, each the same as in `source`
+fn verify_copy(source: &dyn MetadataStore, target: &dyn MetadataStore, kind: RecordKind, count: u64) -> Result<()> {
+    let mut found = 0u64;
+    for key in target.keys(kind)? {
+        let key = key?;
+        found += 1;
+        let copied = target.load(kind, &key)?.map(|contents| blake3::hash(&contents));
+        let original = source.load(kind, &key)?.map(|contents| blake3::hash(&contents));
+        if copied != original {
+            return Err(anyhow!("Record {} differs from the one it was copied from", key));
+        }
+    }
+    if found != count {
+        return Err(anyhow!("Found {} records, copied {}", found, count));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::DiskPool;
+    use crate::extent::{Extent, RedundancyPolicy};
+    use crate::metadata::{ExtentMap, Inode, MetadataManager};
+
+    /// Backends of this build; the kv tests run with `--features kv-metadata`
+    fn backends() -> Vec<MetadataBackend> {
+        let mut backends = vec![MetadataBackend::Files];
+        if cfg!(feature = "kv-metadata") {
+            backends.push(MetadataBackend::Kv);
+        }
+        backends
+    }
+
+    fn pool_with(backend: MetadataBackend) -> tempfile::TempDir {
+        let pool_dir = tempfile::tempdir().unwrap();
+        let mut pool = DiskPool::new();
+        pool.metadata_backend = backend;
+        pool.save(pool_dir.path()).unwrap();
+        pool_dir
+    }
+
+    /// Run `test` on a new pool of each backend
+    fn for_each_backend(test: impl Fn(&Path)) {
+        for backend in backends() {
+            let pool_dir = pool_with(backend);
+            eprintln!("metadata backend {}", backend);
+            test(pool_dir.path());
+        }
+    }
+
+    #[test]
+    fn test_records_round_trip_on_every_backend() {
+        for_each_backend(|pool_dir| {
+            let metadata = MetadataManager::new(pool_dir.to_path_buf()).unwrap();
+            let ino = metadata.inode_numbers().unwrap().last().unwrap() + 1;
+            let inode = Inode::new_file(ino, 1, "a".to_string());
+            metadata.save_inode(&inode).unwrap();
+            let extents: Vec<Extent> = (0..3)
+                .map(|i| Extent::new(&[i; 16], RedundancyPolicy::Replication { copies: 2 }))
+                .collect();
+            let mut map = ExtentMap::new(ino, crate::extent::DEFAULT_EXTENT_SIZE);
+            for extent in &extents {
+                metadata.save_extent(extent).unwrap();
+                map.extents.push(extent.uuid);
+            }
+            metadata.save_extent_map(&map).unwrap();
+            metadata.sync_inode_metadata(ino, &map.extents).unwrap();
+
+            assert_eq!(metadata.load_inode(ino).unwrap().name, "a");
+            assert!(metadata.inode_numbers().unwrap().contains(&ino));
+            assert_eq!(metadata.extent_map_inos().unwrap(), vec![ino]);
+            assert_eq!(metadata.load_extent_map(ino).unwrap().extents, map.extents);
+            assert_eq!(metadata.extent_records().unwrap().count(), 3);
+            let mut sorted = map.extents.clone();
+            sorted.sort();
+            let after_first: Vec<_> = metadata.extent_records_sorted(Some(sorted[0])).unwrap().map(|(uuid, _)| uuid).collect();
+            assert_eq!(after_first, sorted[1..]);
+
+            // Records outlive the manager
+            drop(metadata);
+            let metadata = MetadataManager::new(pool_dir.to_path_buf()).unwrap();
+            assert_eq!(metadata.load_extent(&extents[1].uuid).unwrap().uuid, extents[1].uuid);
+            metadata.delete_extent(&extents[1].uuid).unwrap();
+            metadata.delete_extent_map(ino).unwrap();
+            metadata.delete_inode(ino).unwrap();
+            assert!(!metadata.extent_exists(&extents[1].uuid) && metadata.extent_exists(&extents[0].uuid));
+            assert!(matches!(metadata.load_extent(&extents[1].uuid), Err(crate::error::StorageError::NotFound(_))));
+            assert!(metadata.extent_map_inos().unwrap().is_empty());
+            assert!(!metadata.inode_exists(ino));
+            assert_eq!(metadata.recount_extent_totals().unwrap().extents, 2);
+        });
+    }
+
+    #[test]
+    fn test_keys_list_every_record_once() {
+        for backend in backends() {
+            let pool_dir = tempfile::tempdir().unwrap();
+            let store = open(pool_dir.path(), backend).unwrap();
+            // More than one page of the kv store's listing
+            let records: Vec<(String, Vec<u8>)> = (0..2500).map(|i| (i.to_string(), vec![1])).collect();
+            store.save_batch(RecordKind::Inode, &records).unwrap();
+            std::fs::write(pool_dir.path().join("inodes").join("7.tmp"), b"x").ok();
+            let mut keys: Vec<u64> = store.keys(RecordKind::Inode).unwrap().map(|key| key.unwrap().parse().unwrap()).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, (0..2500).collect::<Vec<u64>>(), "{}", backend);
+            assert!(store.delete(RecordKind::Inode, "7").unwrap());
+            assert!(!store.delete(RecordKind::Inode, "7").unwrap());
+            assert_eq!(store.load(RecordKind::Extent, "7").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_migrate_verifies_before_switching() {
+        let pool_dir = pool_with(MetadataBackend::Files);
+        let pool = pool_dir.path();
+        let metadata = MetadataManager::new(pool.to_path_buf()).unwrap();
+        let extent = Extent::new(b"record", RedundancyPolicy::Replication { copies: 2 });
+        metadata.save_extent(&extent).unwrap();
+        metadata.save_inode(&Inode::new_file(2, 1, "a".to_string())).unwrap();
+        drop(metadata);
+        assert!(migrate(pool, MetadataBackend::Files).is_err());
+
+        if !cfg!(feature = "kv-metadata") {
+            assert!(migrate(pool, MetadataBackend::Kv).is_err());
+            assert_eq!(DiskPool::metadata_backend(pool).unwrap(), MetadataBackend::Files);
+            assert!(MetadataManager::new(pool.to_path_buf()).unwrap().extent_exists(&extent.uuid));
+            return;
+        }
+
+        let report = migrate(pool, MetadataBackend::Kv).unwrap();
+        assert_eq!((report.inodes, report.extents, report.extent_maps), (2, 1, 0));
+        assert_eq!(DiskPool::metadata_backend(pool).unwrap(), MetadataBackend::Kv);
+        assert!(!pool.join("extents").exists());
+        let metadata = MetadataManager::new(pool.to_path_buf()).unwrap();
+        assert_eq!(metadata.metadata_backend(), MetadataBackend::Kv);
+        assert_eq!(metadata.load_inode(2).unwrap().name, "a");
+        assert!(metadata.extent_exists(&extent.uuid));
+        drop(metadata);
+
+        // A copy that does not match leaves the pool where it was
+        let source = open(pool, MetadataBackend::Kv).unwrap();
+        let target = open(pool, MetadataBackend::Files).unwrap();
+        copy_records(source.as_ref(), target.as_ref(), RecordKind::Extent).unwrap();
+        target.save(RecordKind::Extent, &extent.uuid.to_string(), b"{}").unwrap();
+        assert!(verify_copy(source.as_ref(), target.as_ref(), RecordKind::Extent, 1).is_err());
+        drop((source, target));
+
+        migrate(pool, MetadataBackend::Files).unwrap();
+        assert!(!pool.join("metadata").join(KV_STORE_FILE).exists());
+        let metadata = MetadataManager::new(pool.to_path_buf()).unwrap();
+        assert_eq!(metadata.load_extent(&extent.uuid).unwrap().uuid, extent.uuid);
+    }
+}
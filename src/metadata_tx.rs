@@ -1,8 +1,15 @@
 use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::extent::Extent;
+use crate::metadata::{ExtentMap, Inode};
+use crate::quota::Quota;
 
 /// Metadata root with versioning for atomic commits
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,12 +86,232 @@ impl MetadataRoot {
     }
 }
 
+/// A metadata mutation carried by a transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MetadataOp {
+    SaveExtent(Box<Extent>),
+    SaveExtentMap(ExtentMap),
+    SaveInode(Inode),
+    DeleteExtent(Uuid),
+    /// Mark an extent superseded by the transaction; its fragments and record
+    /// are reclaimed after the transaction has been applied
+    ReleaseExtent(Uuid),
+    /// Add files to those sharing an extent, which is only released once
+    /// none of them references it
+    ShareExtent(Uuid, Vec<u64>),
+    /// Quota record with the usage after the transaction's changes
+    SaveQuota(Quota),
+    DeleteExtentMap(u64),
+    DeleteInode(u64),
+    /// Drop the quota set on a directory
+    DeleteQuota(u64),
+}
+
+/// Journal record of a transaction, written before any of its mutations are applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    /// Root version the transaction commits
+    pub version: u64,
+    pub ops: Vec<MetadataOp>,
+    /// BLAKE3 of the serialized ops; a mismatch means the record was torn
+    pub checksum: String,
+}
+
+impl JournalRecord {
+    pub fn new(version: u64, ops: Vec<MetadataOp>) -> Self {
+        let checksum = Self::compute_checksum(&ops);
+        JournalRecord { version, ops, checksum }
+    }
+    
+    fn compute_checksum(ops: &[MetadataOp]) -> String {
+        let json = serde_json::to_string(ops).unwrap();
+        blake3::hash(json.as_bytes()).to_hex().to_string()
+    }
+    
+    /// Whether the record was written completely
+    pub fn is_intact(&self) -> bool {
+        Self::compute_checksum(&self.ops) == self.checksum
+    }
+}
+
+/// Directory holding journal records of transactions not yet fully applied
+pub fn journal_dir(pool_dir: &Path) -> PathBuf {
+    pool_dir.join("metadata").join("journal")
+}
+
+/// Versions and paths of the journal records on disk, oldest first
+///
+/// Temporary files are not records: they were never renamed into place.
+fn journal_records(pool_dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let dir = journal_dir(pool_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    
+    let mut records = Vec::new();
+    for entry in fs::read_dir(&dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(version) = name.strip_prefix("tx.").and_then(|v| v.parse::<u64>().ok()) {
+            records.push((version, entry.path()));
+        }
+    }
+    records.sort_by_key(|(version, _)| *version);
+    Ok(records)
+}
+
+/// Newest version whose journal record no longer needs replaying
+///
+/// A group commit records the newest version it synced. Since this boot
+/// began, the current root also counts: the kernel holds everything written
+/// before it even if it is not on disk yet. After a reboot it proves nothing,
+/// as power loss can keep a root while losing the mutations it covers.
+fn applied_version(pool_dir: &Path) -> u64 {
+    let durable = durable_version(pool_dir);
+    if !same_boot(pool_dir) {
+        return durable;
+    }
+    MetadataRootManager::load_latest_root(&pool_dir.join("metadata").join("roots"))
+        .map_or(durable, |root| root.version.max(durable))
+}
+
+/// Journal records left behind by an interrupted run, oldest first
+///
+/// Records that cannot be parsed are returned as `None` so the caller can discard them.
+/// Records of transactions a group commit synced are removed, and those of
+/// transactions applied in full this boot are left to `applied_journal_records`.
+pub fn pending_journal_records(pool_dir: &Path) -> Result<Vec<(PathBuf, Option<JournalRecord>)>> {
+    let durable = durable_version(pool_dir);
+    let applied = applied_version(pool_dir);
+    let mut records = Vec::new();
+    for (version, path) in journal_records(pool_dir)? {
+        if version <= durable {
+            let _ = fs::remove_file(&path);
+            continue;
+        }
+        if version <= applied {
+            continue;
+        }
+        let record = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<JournalRecord>(&contents).ok());
+        records.push((path, record));
+    }
+    Ok(records)
+}
+
+/// Records of transactions applied in full this boot that only wait for a sync, oldest first
+pub fn applied_journal_records(pool_dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let durable = durable_version(pool_dir);
+    let applied = applied_version(pool_dir);
+    Ok(journal_records(pool_dir)?
+        .into_iter()
+        .filter(|(version, _)| *version > durable && *version <= applied)
+        .collect())
+}
+
+/// Path of the marker holding the newest version a group commit synced
+fn durable_path(pool_dir: &Path) -> PathBuf {
+    journal_dir(pool_dir).join("durable")
+}
+
+/// Newest transaction version known to be on stable storage
+fn durable_version(pool_dir: &Path) -> u64 {
+    fs::read_to_string(durable_path(pool_dir))
+        .ok()
+        .and_then(|contents| contents.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Record that every transaction up to `version` is on stable storage
+///
+/// Another process syncing the same pool may have recorded a newer version.
+fn record_durable_version(pool_dir: &Path, version: u64) -> Result<()> {
+    if version <= durable_version(pool_dir) {
+        return Ok(());
+    }
+    fs::create_dir_all(journal_dir(pool_dir))?;
+    write_synced(&durable_path(pool_dir), version.to_string().as_bytes())
+}
+
+/// Newest version of any journal record, synced or not
+fn newest_journal_version(pool_dir: &Path) -> u64 {
+    journal_records(pool_dir)
+        .ok()
+        .and_then(|records| records.last().map(|(version, _)| *version))
+        .unwrap_or(0)
+}
+
+/// Path of the marker naming the boot the pool was last recovered in
+fn boot_path(pool_dir: &Path) -> PathBuf {
+    journal_dir(pool_dir).join("boot")
+}
+
+/// Identity of the running boot of the machine, where the platform has one
+fn current_boot_id() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        fs::read_to_string("/proc/sys/kernel/random/boot_id").ok().map(|id| id.trim().to_string())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Whether the pool was recovered earlier in this boot, so nothing written since can have been lost
+fn same_boot(pool_dir: &Path) -> bool {
+    let Some(boot) = current_boot_id() else { return false };
+    fs::read_to_string(boot_path(pool_dir)).is_ok_and(|recorded| recorded.trim() == boot)
+}
+
+/// Record that the pool has been recovered in this boot
+///
+/// Called once recovery has synced, so a root written from here on proves its
+/// transaction applied until the machine restarts. On the first recovery of
+/// a boot no writer from before can still be running, so temporary files
+/// left in the journal are removed.
+pub fn record_boot(pool_dir: &Path) -> Result<()> {
+    if same_boot(pool_dir) {
+        return Ok(());
+    }
+    let dir = journal_dir(pool_dir);
+    fs::create_dir_all(&dir)?;
+    for entry in fs::read_dir(&dir)?.flatten() {
+        if entry.file_name().to_string_lossy().ends_with(".tmp") {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    let Some(boot) = current_boot_id() else { return Ok(()) };
+    write_synced(&boot_path(pool_dir), boot.as_bytes())
+}
+
+/// Durably replace `path` with `contents` by writing and syncing a temporary file, renaming it and syncing the directory
+///
+/// Each call has a temporary file of its own, since another process or pool
+/// handle may be writing the same file.
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    static NEXT_TEMP: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let temp = NEXT_TEMP.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let name = path.file_name().ok_or_else(|| anyhow!("No file name in {}", path.display()))?;
+    let temp_path = path.with_file_name(format!("{}.{}-{}.tmp", name.to_string_lossy(), std::process::id(), temp));
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
 /// Transaction coordinator for atomic metadata updates
 pub struct MetadataTransaction {
     pool_dir: PathBuf,
     current_root: MetadataRoot,
     pending_root: Option<MetadataRoot>,
     committed: bool,
+    /// Mutations to journal and apply
+    ops: Vec<MetadataOp>,
 }
 
 impl MetadataTransaction {
@@ -97,9 +324,44 @@ impl MetadataTransaction {
             current_root,
             pending_root: Some(pending_root),
             committed: false,
+            ops: Vec::new(),
         }
     }
     
+    /// Version this transaction will commit
+    pub fn version(&self) -> u64 {
+        self.pending_root
+            .as_ref()
+            .map(|root| root.version)
+            .unwrap_or(self.current_root.version)
+    }
+    
+    /// Add a mutation to the transaction
+    pub fn record(&mut self, op: MetadataOp) {
+        self.ops.push(op);
+    }
+    
+    /// Mutations recorded so far
+    pub fn ops(&self) -> &[MetadataOp] {
+        &self.ops
+    }
+    
+    /// Path of this transaction's journal record
+    pub fn journal_path(&self) -> PathBuf {
+        journal_dir(&self.pool_dir).join(format!("tx.{}", self.version()))
+    }
+    
+    /// Durably write the journal record; after this the transaction survives a crash
+    ///
+    /// Only the record is synced here. The mutations and root it leads to wait
+    /// for the group commit, which syncs them with every other transaction
+    /// committed in the same window.
+    pub fn write_journal(&self) -> Result<()> {
+        fs::create_dir_all(journal_dir(&self.pool_dir))?;
+        let record = JournalRecord::new(self.version(), self.ops.clone());
+        write_synced(&self.journal_path(), serde_json::to_string(&record)?.as_bytes())
+    }
+    
     /// Get the pending root (mutable)
     pub fn pending_root_mut(&mut self) -> Result<&mut MetadataRoot> {
         self.pending_root.as_mut()
@@ -107,6 +369,8 @@ impl MetadataTransaction {
     }
     
     /// Commit the transaction
+    ///
+    /// The journal record stays until the group commit has synced the root.
     pub fn commit(mut self, state_checksum: String) -> Result<MetadataRoot> {
         let mut pending = self.pending_root
             .take()
@@ -118,9 +382,6 @@ impl MetadataTransaction {
         // Write to disk atomically
         self.write_root(&pending)?;
         
-        // Fsync to ensure durability
-        self.fsync_root_dir()?;
-        
         self.committed = true;
         Ok(pending)
     }
@@ -164,23 +425,6 @@ impl MetadataTransaction {
         
         Ok(())
     }
-    
-    /// Fsync the root directory for durability
-    fn fsync_root_dir(&self) -> Result<()> {
-        let root_dir = self.pool_dir.join("metadata").join("roots");
-        
-        // Open directory and fsync (Unix only)
-        #[cfg(unix)]
-        {
-            use std::os::unix::io::AsRawFd;
-            let dir = fs::File::open(&root_dir)?;
-            unsafe {
-                libc::fsync(dir.as_raw_fd());
-            }
-        }
-        
-        Ok(())
-    }
 }
 
 impl Drop for MetadataTransaction {
@@ -196,6 +440,8 @@ impl Drop for MetadataTransaction {
 pub struct MetadataRootManager {
     pool_dir: PathBuf,
     current_root: Arc<Mutex<MetadataRoot>>,
+    /// Makes committed transactions durable
+    commits: Arc<GroupCommit>,
 }
 
 impl MetadataRootManager {
@@ -205,11 +451,14 @@ impl MetadataRootManager {
         fs::create_dir_all(&root_dir)?;
         
         // Load or create initial root
-        let current_root = Self::load_latest_root(&root_dir)
+        let mut current_root = Self::load_latest_root(&root_dir)
             .unwrap_or_else(|| {
                 log::info!("Creating initial metadata root");
                 MetadataRoot::new(2) // Start at ino 2 (1 is root dir)
             });
+        // A root lost to power loss can be older than journal records still
+        // waiting to be replayed; new transactions must not reuse their names
+        current_root.version = current_root.version.max(newest_journal_version(&pool_dir));
         
         // Verify root is valid
         if !current_root.is_valid() {
@@ -219,6 +468,7 @@ impl MetadataRootManager {
         log::info!("Loaded metadata root version {}", current_root.version);
         
         Ok(MetadataRootManager {
+            commits: GroupCommit::new(pool_dir.clone(), DEFAULT_COMMIT_WINDOW),
             pool_dir,
             current_root: Arc::new(Mutex::new(current_root)),
         })
@@ -277,16 +527,24 @@ impl MetadataRootManager {
     }
     
     /// Commit a transaction and update current root
+    ///
+    /// The transaction is durable once the group commit has synced it.
     pub fn commit_transaction(&self, tx: MetadataTransaction, state_checksum: String) -> Result<()> {
+        let record = tx.journal_path();
         let new_root = tx.commit(state_checksum)?;
+        let version = new_root.version;
         
         // Update current root
-        let mut current = self.current_root.lock().unwrap();
-        *current = new_root;
+        *self.current_root.lock().unwrap() = new_root;
         
-        log::info!("Committed metadata root version {}", current.version);
+        log::info!("Committed metadata root version {}", version);
         
-        Ok(())
+        self.commits.committed(version, record)
+    }
+    
+    /// Group commit of this pool's transactions
+    pub fn commits(&self) -> &Arc<GroupCommit> {
+        &self.commits
     }
     
     /// Clean up old root versions (keep last N)
@@ -294,15 +552,11 @@ impl MetadataRootManager {
         let root_dir = self.pool_dir.join("metadata").join("roots");
         let mut roots = Vec::new();
         
-        // Scan for all roots
+        // Scan for all roots; the version is in the name, so none has to be read
         if let Ok(entries) = fs::read_dir(&root_dir) {
             for entry in entries.flatten() {
-                if let Some(name) = entry.file_name().to_str() {
-                    if name.starts_with("root.") && !name.ends_with(".tmp") {
-                        if let Ok(root) = Self::read_root_file(&entry.path()) {
-                            roots.push((root.version, entry.path()));
-                        }
-                    }
+                if let Some(version) = entry.file_name().to_str().and_then(|name| name.strip_prefix("root.")?.parse::<u64>().ok()) {
+                    roots.push((version, entry.path()));
                 }
             }
         }
@@ -325,3 +579,181 @@ impl MetadataRootManager {
     }
 }
 
+/// How long a group commit waits for more transactions before syncing
+pub const DEFAULT_COMMIT_WINDOW: Duration = Duration::from_millis(2);
+
+/// Transactions and syncs of a group commit since it was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommitStats {
+    pub transactions: u64,
+    pub syncs: u64,
+}
+
+#[derive(Default)]
+struct GroupState {
+    window: Duration,
+    /// Version of the latest committed transaction
+    committed: u64,
+    /// Transactions up to this version are durable
+    durable: u64,
+    /// Journal records of committed transactions, removed after the next sync
+    records: Vec<PathBuf>,
+    syncing: bool,
+    /// A timer will start the next sync when the window closes
+    scheduled: bool,
+    stats: CommitStats,
+}
+
+/// Makes committed transactions durable in groups
+///
+/// Each transaction syncs its own journal record before it is applied, but
+/// its mutations and root are written without a sync. The first commit after
+/// a sync opens a window; when it closes, one sync of the pool's file system
+/// makes every transaction committed so far durable, the newest synced
+/// version is recorded, and only then are their journal records removed.
+/// With a zero window every commit syncs before it returns. Callers that need
+/// durability now, such as fsync, call `sync` rather than wait for the window.
+pub struct GroupCommit {
+    pool_dir: PathBuf,
+    state: Mutex<GroupState>,
+    done: Condvar,
+}
+
+impl GroupCommit {
+    pub fn new(pool_dir: PathBuf, window: Duration) -> Arc<Self> {
+        Arc::new(GroupCommit {
+            pool_dir,
+            state: Mutex::new(GroupState { window, ..GroupState::default() }),
+            done: Condvar::new(),
+        })
+    }
+    
+    /// Change how long transactions wait to share a sync
+    pub fn set_window(&self, window: Duration) {
+        self.state.lock().unwrap().window = window;
+    }
+    
+    pub fn stats(&self) -> CommitStats {
+        self.state.lock().unwrap().stats
+    }
+    
+    /// Take journal records of transactions applied before this pool was opened into the next sync
+    pub fn adopt(&self, records: Vec<(u64, PathBuf)>) {
+        let mut state = self.state.lock().unwrap();
+        for (version, record) in records {
+            state.committed = state.committed.max(version);
+            state.records.push(record);
+        }
+    }
+    
+    /// Take the transaction committing `version`, journaled at `record`, into the next sync
+    fn committed(self: &Arc<Self>, version: u64, record: PathBuf) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.committed = state.committed.max(version);
+        state.records.push(record);
+        state.stats.transactions += 1;
+        if state.window.is_zero() {
+            drop(state);
+            return self.sync();
+        }
+        self.schedule(&mut state);
+        Ok(())
+    }
+    
+    /// Start a timer for the next sync unless one is pending or running
+    fn schedule(self: &Arc<Self>, state: &mut GroupState) {
+        if state.scheduled || state.syncing || state.records.is_empty() {
+            return;
+        }
+        state.scheduled = true;
+        let window = state.window;
+        // A pool closed in the meantime is not synced; its records are discarded when it is opened again
+        let commits = Arc::downgrade(self);
+        let timer = std::thread::Builder::new().name("group-commit".to_string()).spawn(move || {
+            std::thread::sleep(window);
+            let Some(commits) = commits.upgrade() else { return };
+            let mut state = commits.state.lock().unwrap();
+            state.scheduled = false;
+            // A sync still running reschedules when it finishes
+            if !state.syncing && !state.records.is_empty() {
+                let target = state.committed;
+                if let Err(e) = commits.run_sync(state, target) {
+                    log::error!("Syncing committed metadata transactions failed: {:#}", e);
+                }
+            }
+        });
+        if let Err(e) = timer {
+            log::warn!("Could not start group commit timer, syncing on the next commit: {}", e);
+            state.scheduled = false;
+        }
+    }
+    
+    /// Make every transaction committed so far durable
+    pub fn sync(self: &Arc<Self>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let target = state.committed;
+        loop {
+            if state.durable >= target {
+                return Ok(());
+            }
+            if state.syncing {
+                state = self.done.wait(state).unwrap();
+                continue;
+            }
+            return self.run_sync(state, target);
+        }
+    }
+    
+    /// Sync the file system holding the pool, then retire the records that covered
+    ///
+    /// The synced version is recorded before any record goes, so a record
+    /// whose removal is lost to power loss is still known to be applied.
+    fn run_sync(self: &Arc<Self>, mut state: MutexGuard<'_, GroupState>, target: u64) -> Result<()> {
+        state.syncing = true;
+        let records = std::mem::take(&mut state.records);
+        let upto = state.committed.max(target);
+        drop(state);
+        
+        let result = sync_filesystem(&self.pool_dir)
+            .and_then(|()| record_durable_version(&self.pool_dir, upto));
+        if result.is_ok() {
+            for record in &records {
+                let _ = fs::remove_file(record);
+            }
+        }
+        
+        let mut state = self.state.lock().unwrap();
+        state.syncing = false;
+        match &result {
+            Ok(()) => {
+                state.durable = state.durable.max(upto);
+                state.stats.syncs += 1;
+            }
+            // Left for the next sync to retry
+            Err(_) => state.records.extend(records),
+        }
+        self.done.notify_all();
+        if !state.window.is_zero() {
+            self.schedule(&mut state);
+        }
+        result
+    }
+}
+
+/// Flush everything written to the file system holding `dir`
+fn sync_filesystem(dir: &Path) -> Result<()> {
+    let dir = fs::File::open(dir)?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        if unsafe { libc::syncfs(dir.as_raw_fd()) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        drop(dir);
+        unsafe { libc::sync() };
+    }
+    Ok(())
+}
@@ -1,5 +1,117 @@
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::write_optimizer::FlushCause;
+
+/// FUSE operations whose latency is tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuseOp {
+    Lookup,
+    Getattr,
+    Readdir,
+    Read,
+    Write,
+    Create,
+    Unlink,
+    Fsync,
+}
+
+impl FuseOp {
+    pub const ALL: [FuseOp; 8] = [
+        FuseOp::Lookup,
+        FuseOp::Getattr,
+        FuseOp::Readdir,
+        FuseOp::Read,
+        FuseOp::Write,
+        FuseOp::Create,
+        FuseOp::Unlink,
+        FuseOp::Fsync,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FuseOp::Lookup => "lookup",
+            FuseOp::Getattr => "getattr",
+            FuseOp::Readdir => "readdir",
+            FuseOp::Read => "read",
+            FuseOp::Write => "write",
+            FuseOp::Create => "create",
+            FuseOp::Unlink => "unlink",
+            FuseOp::Fsync => "fsync",
+        }
+    }
+}
+
+/// Upper bounds of the latency buckets in microseconds; a last bucket takes everything slower
+pub const LATENCY_BUCKET_BOUNDS_US: [u64; 5] = [1_000, 5_000, 20_000, 100_000, 500_000];
+
+const LATENCY_BUCKETS: usize = LATENCY_BUCKET_BOUNDS_US.len() + 1;
+
+/// Fixed-bucket latency histogram
+///
+/// Recording is two relaxed atomic adds, so it is safe on the FUSE hot path.
+#[derive(Debug, Default)]
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+    sum_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, elapsed: Duration) {
+        let us = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKET_BOUNDS_US.iter().position(|bound| us < *bound).unwrap_or(LATENCY_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            sum_us: self.sum_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bucket counts of a `LatencyHistogram`; `buckets[i]` is not cumulative
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    pub buckets: [u64; LATENCY_BUCKETS],
+    pub sum_us: u64,
+}
+
+impl LatencySnapshot {
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Estimate the `q` quantile (0.0..=1.0) by interpolating within its bucket
+    ///
+    /// Samples in the unbounded last bucket are reported at its lower bound.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = q.clamp(0.0, 1.0) * count as f64;
+        let mut below = 0u64;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            if n > 0 && (below + n) as f64 >= rank {
+                let lower = if i == 0 { 0 } else { LATENCY_BUCKET_BOUNDS_US[i - 1] };
+                let Some(&upper) = LATENCY_BUCKET_BOUNDS_US.get(i) else {
+                    return Some(Duration::from_micros(lower));
+                };
+                let fraction = ((rank - below as f64) / n as f64).clamp(0.0, 1.0);
+                return Some(Duration::from_micros(lower + ((upper - lower) as f64 * fraction) as u64));
+            }
+            below += n;
+        }
+        None
+    }
+}
 
 /// System-wide metrics collection
 #[derive(Debug, Clone)]
@@ -10,17 +122,41 @@ pub struct Metrics {
     pub disk_read_bytes: Arc<AtomicU64>,
     pub disk_write_bytes: Arc<AtomicU64>,
     pub disk_errors: Arc<AtomicU64>,
+    pub fragment_checksum_failures: Arc<AtomicU64>,
+    /// Fragments that failed verify-on-write read-back, per disk
+    pub write_verify_failures: Arc<Mutex<BTreeMap<Uuid, u64>>>,
+
+    // Orphan GC metrics
+    pub orphan_gc_runs: Arc<AtomicU64>,
+    pub orphan_gc_fragments_removed: Arc<AtomicU64>,
+    pub orphan_gc_bytes_reclaimed: Arc<AtomicU64>,
+    /// Unix time of the last completed pass
+    pub orphan_gc_last_run: Arc<AtomicU64>,
+    /// Disks the last pass skipped because of unreadable extent metadata
+    pub orphan_gc_skipped_disks: Arc<AtomicU64>,
 
     // Extent metrics
     pub extents_healthy: Arc<AtomicU64>,
     pub extents_degraded: Arc<AtomicU64>,
     pub extents_unrecoverable: Arc<AtomicU64>,
+    /// Extents written below their policy because too few disks were writable
+    pub degraded_writes: Arc<AtomicU64>,
+    /// Degraded extents re-encoded to their intended policy
+    pub degraded_upgrades: Arc<AtomicU64>,
 
     // Rebuild metrics
     pub rebuilds_attempted: Arc<AtomicU64>,
     pub rebuilds_successful: Arc<AtomicU64>,
     pub rebuilds_failed: Arc<AtomicU64>,
     pub rebuild_bytes_written: Arc<AtomicU64>,
+    pub rebuild_queue_depth: Arc<AtomicU64>,
+    pub rebuild_queue_rejected: Arc<AtomicU64>,
+    /// Repair budget gauges, refreshed by `RebuildBudget`
+    pub rebuilds_running: Arc<AtomicU64>,
+    pub rebuild_throughput_bytes_per_sec: Arc<AtomicU64>,
+    pub rebuild_limit_concurrent: Arc<AtomicU64>,
+    pub rebuild_limit_bytes_per_sec: Arc<AtomicU64>,
+    pub rebuild_pool_idle: Arc<AtomicU64>,
 
     // Scrub metrics
     pub scrubs_completed: Arc<AtomicU64>,
@@ -28,9 +164,23 @@ pub struct Metrics {
     pub scrub_repairs_attempted: Arc<AtomicU64>,
     pub scrub_repairs_successful: Arc<AtomicU64>,
 
+    // Recent-write verification metrics
+    /// Placed extents waiting to be verified
+    pub recent_write_queue_depth: Arc<AtomicU64>,
+    /// Placements not queued because the queue was full; left to the full scrub
+    pub recent_write_overflow: Arc<AtomicU64>,
+    pub recent_writes_verified: Arc<AtomicU64>,
+    /// Verified extents found degraded or unrecoverable
+    pub recent_write_failures: Arc<AtomicU64>,
+    /// Seconds the oldest queued extent has waited beyond the settle delay
+    pub recent_write_lag_secs: Arc<AtomicU64>,
+
     // Cache metrics
     pub cache_hits: Arc<AtomicU64>,
     pub cache_misses: Arc<AtomicU64>,
+    /// Inode and name lookups served by, or missing, the inode cache
+    pub inode_cache_hits: Arc<AtomicU64>,
+    pub inode_cache_misses: Arc<AtomicU64>,
     
     // Phase 15: Concurrency metrics
     pub lock_acquisitions: Arc<AtomicU64>,
@@ -57,6 +207,19 @@ pub struct Metrics {
     pub device_trim_bytes: Arc<AtomicU64>,
     pub scrub_progress_extents: Arc<AtomicU64>,
     pub scrub_progress_bytes: Arc<AtomicU64>,
+
+    // Write buffer metrics
+    pub write_buffer_coalesced: Arc<AtomicU64>,
+    pub write_buffer_flushes_extent_full: Arc<AtomicU64>,
+    pub write_buffer_flushes_non_sequential: Arc<AtomicU64>,
+    pub write_buffer_flushes_fsync: Arc<AtomicU64>,
+    pub write_buffer_flushes_release: Arc<AtomicU64>,
+    pub write_buffer_flushes_timer: Arc<AtomicU64>,
+    pub write_buffer_flushes_memory_pressure: Arc<AtomicU64>,
+    pub write_buffer_flushes_explicit: Arc<AtomicU64>,
+
+    /// FUSE operation latency, indexed by `FuseOp`
+    pub fuse_latency: Arc<[LatencyHistogram; FuseOp::ALL.len()]>,
 }
 
 impl Metrics {
@@ -67,23 +230,48 @@ impl Metrics {
             disk_read_bytes: Arc::new(AtomicU64::new(0)),
             disk_write_bytes: Arc::new(AtomicU64::new(0)),
             disk_errors: Arc::new(AtomicU64::new(0)),
+            fragment_checksum_failures: Arc::new(AtomicU64::new(0)),
+            write_verify_failures: Arc::new(Mutex::new(BTreeMap::new())),
+
+            orphan_gc_runs: Arc::new(AtomicU64::new(0)),
+            orphan_gc_fragments_removed: Arc::new(AtomicU64::new(0)),
+            orphan_gc_bytes_reclaimed: Arc::new(AtomicU64::new(0)),
+            orphan_gc_last_run: Arc::new(AtomicU64::new(0)),
+            orphan_gc_skipped_disks: Arc::new(AtomicU64::new(0)),
 
             extents_healthy: Arc::new(AtomicU64::new(0)),
             extents_degraded: Arc::new(AtomicU64::new(0)),
             extents_unrecoverable: Arc::new(AtomicU64::new(0)),
+            degraded_writes: Arc::new(AtomicU64::new(0)),
+            degraded_upgrades: Arc::new(AtomicU64::new(0)),
 
             rebuilds_attempted: Arc::new(AtomicU64::new(0)),
             rebuilds_successful: Arc::new(AtomicU64::new(0)),
             rebuilds_failed: Arc::new(AtomicU64::new(0)),
             rebuild_bytes_written: Arc::new(AtomicU64::new(0)),
+            rebuild_queue_depth: Arc::new(AtomicU64::new(0)),
+            rebuild_queue_rejected: Arc::new(AtomicU64::new(0)),
+            rebuilds_running: Arc::new(AtomicU64::new(0)),
+            rebuild_throughput_bytes_per_sec: Arc::new(AtomicU64::new(0)),
+            rebuild_limit_concurrent: Arc::new(AtomicU64::new(0)),
+            rebuild_limit_bytes_per_sec: Arc::new(AtomicU64::new(0)),
+            rebuild_pool_idle: Arc::new(AtomicU64::new(0)),
 
             scrubs_completed: Arc::new(AtomicU64::new(0)),
             scrub_issues_found: Arc::new(AtomicU64::new(0)),
             scrub_repairs_attempted: Arc::new(AtomicU64::new(0)),
             scrub_repairs_successful: Arc::new(AtomicU64::new(0)),
 
+            recent_write_queue_depth: Arc::new(AtomicU64::new(0)),
+            recent_write_overflow: Arc::new(AtomicU64::new(0)),
+            recent_writes_verified: Arc::new(AtomicU64::new(0)),
+            recent_write_failures: Arc::new(AtomicU64::new(0)),
+            recent_write_lag_secs: Arc::new(AtomicU64::new(0)),
+
             cache_hits: Arc::new(AtomicU64::new(0)),
             cache_misses: Arc::new(AtomicU64::new(0)),
+            inode_cache_hits: Arc::new(AtomicU64::new(0)),
+            inode_cache_misses: Arc::new(AtomicU64::new(0)),
             
             // Phase 15: Concurrency metrics
             lock_acquisitions: Arc::new(AtomicU64::new(0)),
@@ -110,6 +298,17 @@ impl Metrics {
             device_trim_bytes: Arc::new(AtomicU64::new(0)),
             scrub_progress_extents: Arc::new(AtomicU64::new(0)),
             scrub_progress_bytes: Arc::new(AtomicU64::new(0)),
+
+            write_buffer_coalesced: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_extent_full: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_non_sequential: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_fsync: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_release: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_timer: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_memory_pressure: Arc::new(AtomicU64::new(0)),
+            write_buffer_flushes_explicit: Arc::new(AtomicU64::new(0)),
+
+            fuse_latency: Arc::new(Default::default()),
         }
     }
 
@@ -127,6 +326,22 @@ impl Metrics {
         self.disk_errors.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn record_fragment_checksum_failure(&self) {
+        self.fragment_checksum_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write_verify_failure(&self, disk: Uuid) {
+        *self.write_verify_failures.lock().unwrap().entry(disk).or_insert(0) += 1;
+    }
+
+    pub fn record_orphan_gc(&self, fragments: u64, bytes: u64, skipped_disks: u64) {
+        self.orphan_gc_runs.fetch_add(1, Ordering::Relaxed);
+        self.orphan_gc_fragments_removed.fetch_add(fragments, Ordering::Relaxed);
+        self.orphan_gc_bytes_reclaimed.fetch_add(bytes, Ordering::Relaxed);
+        self.orphan_gc_last_run.store(chrono::Utc::now().timestamp() as u64, Ordering::Relaxed);
+        self.orphan_gc_skipped_disks.store(skipped_disks, Ordering::Relaxed);
+    }
+
     pub fn record_rebuild_start(&self) {
         self.rebuilds_attempted.fetch_add(1, Ordering::Relaxed);
     }
@@ -140,6 +355,33 @@ impl Metrics {
         self.rebuilds_failed.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn update_rebuild_queue_depth(&self, depth: u64) {
+        self.rebuild_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub fn update_rebuild_budget(&self, status: &crate::rebuild_budget::RebuildStatus) {
+        self.rebuilds_running.store(status.running as u64, Ordering::Relaxed);
+        self.rebuild_throughput_bytes_per_sec.store(status.throughput_bytes_per_sec, Ordering::Relaxed);
+        self.rebuild_limit_concurrent.store(status.max_concurrent as u64, Ordering::Relaxed);
+        self.rebuild_limit_bytes_per_sec.store(status.max_bytes_per_sec, Ordering::Relaxed);
+        self.rebuild_pool_idle.store(status.idle as u64, Ordering::Relaxed);
+    }
+
+    /// Extent written below its policy; see `StorageEngine::set_degraded_writes`
+    pub fn record_degraded_write(&self) {
+        self.degraded_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Degraded extent brought up to its intended policy
+    pub fn record_degraded_upgrade(&self) {
+        self.degraded_upgrades.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Rebuild dropped because the background queue was full
+    pub fn record_rebuild_queue_rejected(&self) {
+        self.rebuild_queue_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn record_scrub_completed(&self, issues: u64, repairs: u64, successful: u64) {
         self.scrubs_completed.fetch_add(1, Ordering::Relaxed);
         self.scrub_issues_found.fetch_add(issues, Ordering::Relaxed);
@@ -147,6 +389,26 @@ impl Metrics {
         self.scrub_repairs_successful.fetch_add(successful, Ordering::Relaxed);
     }
 
+    /// Placement left to the full scrub because the recent-write queue was full
+    pub fn record_recent_write_overflow(&self) {
+        self.recent_write_overflow.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn update_recent_write_queue_depth(&self, depth: u64) {
+        self.recent_write_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Backlog after a verification pass
+    pub fn update_recent_write_backlog(&self, status: &crate::recent_writes::RecentWriteStatus) {
+        self.recent_write_queue_depth.store(status.queued as u64, Ordering::Relaxed);
+        self.recent_write_lag_secs.store(status.lag_secs, Ordering::Relaxed);
+    }
+
+    pub fn record_recent_write_pass(&self, pass: &crate::recent_writes::RecentWritePass) {
+        self.recent_writes_verified.fetch_add(pass.verified(), Ordering::Relaxed);
+        self.recent_write_failures.fetch_add(pass.repairs_queued + pass.unrecoverable, Ordering::Relaxed);
+    }
+
     pub fn record_cache_hit(&self) {
         self.cache_hits.fetch_add(1, Ordering::Relaxed);
     }
@@ -154,6 +416,14 @@ impl Metrics {
     pub fn record_cache_miss(&self) {
         self.cache_misses.fetch_add(1, Ordering::Relaxed);
     }
+
+    pub fn record_inode_cache_hit(&self) {
+        self.inode_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_inode_cache_miss(&self) {
+        self.inode_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
     
     // Phase 15: Concurrency metric recording
     
@@ -224,6 +494,31 @@ impl Metrics {
         self.scrub_progress_bytes.store(bytes, Ordering::Relaxed);
     }
 
+    // Write buffer metric recording
+
+    /// A buffered write merged into an existing dirty run
+    pub fn record_write_buffer_coalesced(&self) {
+        self.write_buffer_coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write_buffer_flush(&self, cause: FlushCause) {
+        let counter = match cause {
+            FlushCause::ExtentFull => &self.write_buffer_flushes_extent_full,
+            FlushCause::NonSequential => &self.write_buffer_flushes_non_sequential,
+            FlushCause::Fsync => &self.write_buffer_flushes_fsync,
+            FlushCause::Release => &self.write_buffer_flushes_release,
+            FlushCause::Timer => &self.write_buffer_flushes_timer,
+            FlushCause::MemoryPressure => &self.write_buffer_flushes_memory_pressure,
+            FlushCause::Explicit => &self.write_buffer_flushes_explicit,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Time taken by one FUSE operation, from the request to its reply
+    pub fn record_fuse_op(&self, op: FuseOp, elapsed: Duration) {
+        self.fuse_latency[op as usize].record(elapsed);
+    }
+
     /// Get snapshot of all metrics
     pub fn snapshot(&self) -> MetricsSnapshot {
         MetricsSnapshot {
@@ -232,19 +527,42 @@ impl Metrics {
             disk_read_bytes: self.disk_read_bytes.load(Ordering::Relaxed),
             disk_write_bytes: self.disk_write_bytes.load(Ordering::Relaxed),
             disk_errors: self.disk_errors.load(Ordering::Relaxed),
+            fragment_checksum_failures: self.fragment_checksum_failures.load(Ordering::Relaxed),
+            write_verify_failures: self.write_verify_failures.lock().unwrap().clone(),
+            orphan_gc_runs: self.orphan_gc_runs.load(Ordering::Relaxed),
+            orphan_gc_fragments_removed: self.orphan_gc_fragments_removed.load(Ordering::Relaxed),
+            orphan_gc_bytes_reclaimed: self.orphan_gc_bytes_reclaimed.load(Ordering::Relaxed),
+            orphan_gc_last_run: self.orphan_gc_last_run.load(Ordering::Relaxed),
+            orphan_gc_skipped_disks: self.orphan_gc_skipped_disks.load(Ordering::Relaxed),
             extents_healthy: self.extents_healthy.load(Ordering::Relaxed),
             extents_degraded: self.extents_degraded.load(Ordering::Relaxed),
             extents_unrecoverable: self.extents_unrecoverable.load(Ordering::Relaxed),
+            degraded_writes: self.degraded_writes.load(Ordering::Relaxed),
+            degraded_upgrades: self.degraded_upgrades.load(Ordering::Relaxed),
             rebuilds_attempted: self.rebuilds_attempted.load(Ordering::Relaxed),
             rebuilds_successful: self.rebuilds_successful.load(Ordering::Relaxed),
             rebuilds_failed: self.rebuilds_failed.load(Ordering::Relaxed),
             rebuild_bytes_written: self.rebuild_bytes_written.load(Ordering::Relaxed),
+            rebuild_queue_depth: self.rebuild_queue_depth.load(Ordering::Relaxed),
+            rebuild_queue_rejected: self.rebuild_queue_rejected.load(Ordering::Relaxed),
+            rebuilds_running: self.rebuilds_running.load(Ordering::Relaxed),
+            rebuild_throughput_bytes_per_sec: self.rebuild_throughput_bytes_per_sec.load(Ordering::Relaxed),
+            rebuild_limit_concurrent: self.rebuild_limit_concurrent.load(Ordering::Relaxed),
+            rebuild_limit_bytes_per_sec: self.rebuild_limit_bytes_per_sec.load(Ordering::Relaxed),
+            rebuild_pool_idle: self.rebuild_pool_idle.load(Ordering::Relaxed) != 0,
             scrubs_completed: self.scrubs_completed.load(Ordering::Relaxed),
             scrub_issues_found: self.scrub_issues_found.load(Ordering::Relaxed),
             scrub_repairs_attempted: self.scrub_repairs_attempted.load(Ordering::Relaxed),
             scrub_repairs_successful: self.scrub_repairs_successful.load(Ordering::Relaxed),
+            recent_write_queue_depth: self.recent_write_queue_depth.load(Ordering::Relaxed),
+            recent_write_overflow: self.recent_write_overflow.load(Ordering::Relaxed),
+            recent_writes_verified: self.recent_writes_verified.load(Ordering::Relaxed),
+            recent_write_failures: self.recent_write_failures.load(Ordering::Relaxed),
+            recent_write_lag_secs: self.recent_write_lag_secs.load(Ordering::Relaxed),
             cache_hits: self.cache_hits.load(Ordering::Relaxed),
             cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            inode_cache_hits: self.inode_cache_hits.load(Ordering::Relaxed),
+            inode_cache_misses: self.inode_cache_misses.load(Ordering::Relaxed),
             lock_acquisitions: self.lock_acquisitions.load(Ordering::Relaxed),
             lock_contentions: self.lock_contentions.load(Ordering::Relaxed),
             group_commits: self.group_commits.load(Ordering::Relaxed),
@@ -265,6 +583,16 @@ impl Metrics {
             device_trim_bytes: self.device_trim_bytes.load(Ordering::Relaxed),
             scrub_progress_extents: self.scrub_progress_extents.load(Ordering::Relaxed),
             scrub_progress_bytes: self.scrub_progress_bytes.load(Ordering::Relaxed),
+            write_buffer_coalesced: self.write_buffer_coalesced.load(Ordering::Relaxed),
+            write_buffer_flushes_extent_full: self.write_buffer_flushes_extent_full.load(Ordering::Relaxed),
+            write_buffer_flushes_non_sequential: self.write_buffer_flushes_non_sequential.load(Ordering::Relaxed),
+            write_buffer_flushes_fsync: self.write_buffer_flushes_fsync.load(Ordering::Relaxed),
+            write_buffer_flushes_release: self.write_buffer_flushes_release.load(Ordering::Relaxed),
+            write_buffer_flushes_timer: self.write_buffer_flushes_timer.load(Ordering::Relaxed),
+            write_buffer_flushes_memory_pressure: self.write_buffer_flushes_memory_pressure.load(Ordering::Relaxed),
+            write_buffer_flushes_explicit: self.write_buffer_flushes_explicit.load(Ordering::Relaxed),
+            fuse_latency: FuseOp::ALL.map(|op| (op, self.fuse_latency[op as usize].snapshot())).to_vec(),
+            disk_io_queues: crate::io_scheduler::scheduler().queues(),
         }
     }
 }
@@ -283,19 +611,43 @@ pub struct MetricsSnapshot {
     pub disk_read_bytes: u64,
     pub disk_write_bytes: u64,
     pub disk_errors: u64,
+    pub fragment_checksum_failures: u64,
+    pub write_verify_failures: BTreeMap<Uuid, u64>,
+    pub orphan_gc_runs: u64,
+    pub orphan_gc_fragments_removed: u64,
+    pub orphan_gc_bytes_reclaimed: u64,
+    pub orphan_gc_last_run: u64,
+    pub orphan_gc_skipped_disks: u64,
     pub extents_healthy: u64,
     pub extents_degraded: u64,
     pub extents_unrecoverable: u64,
+    pub degraded_writes: u64,
+    pub degraded_upgrades: u64,
     pub rebuilds_attempted: u64,
     pub rebuilds_successful: u64,
     pub rebuilds_failed: u64,
     pub rebuild_bytes_written: u64,
+    pub rebuild_queue_depth: u64,
+    pub rebuild_queue_rejected: u64,
+    pub rebuilds_running: u64,
+    pub rebuild_throughput_bytes_per_sec: u64,
+    pub rebuild_limit_concurrent: u64,
+    /// 0 is unlimited
+    pub rebuild_limit_bytes_per_sec: u64,
+    pub rebuild_pool_idle: bool,
     pub scrubs_completed: u64,
     pub scrub_issues_found: u64,
     pub scrub_repairs_attempted: u64,
     pub scrub_repairs_successful: u64,
+    pub recent_write_queue_depth: u64,
+    pub recent_write_overflow: u64,
+    pub recent_writes_verified: u64,
+    pub recent_write_failures: u64,
+    pub recent_write_lag_secs: u64,
     pub cache_hits: u64,
     pub cache_misses: u64,
+    pub inode_cache_hits: u64,
+    pub inode_cache_misses: u64,
     // Phase 15: Concurrency metrics
     pub lock_acquisitions: u64,
     pub lock_contentions: u64,
@@ -319,6 +671,18 @@ pub struct MetricsSnapshot {
     pub device_trim_bytes: u64,
     pub scrub_progress_extents: u64,
     pub scrub_progress_bytes: u64,
+    // Write buffer metrics
+    pub write_buffer_coalesced: u64,
+    pub write_buffer_flushes_extent_full: u64,
+    pub write_buffer_flushes_non_sequential: u64,
+    pub write_buffer_flushes_fsync: u64,
+    pub write_buffer_flushes_release: u64,
+    pub write_buffer_flushes_timer: u64,
+    pub write_buffer_flushes_memory_pressure: u64,
+    pub write_buffer_flushes_explicit: u64,
+    pub fuse_latency: Vec<(FuseOp, LatencySnapshot)>,
+    /// Fragment requests queued and in flight on each disk, by class
+    pub disk_io_queues: crate::io_scheduler::DiskQueues,
 }
 
 impl MetricsSnapshot {
@@ -357,6 +721,7 @@ impl std::fmt::Display for MetricsSnapshot {
     Reads:  {} ({} bytes)
     Writes: {} ({} bytes)
     Errors: {}
+    Checksum failures: {}
   Extents:
     Healthy:      {}
     Degraded:     {}
@@ -366,6 +731,7 @@ impl std::fmt::Display for MetricsSnapshot {
     Successful:   {}
     Failed:       {}
     Bytes written: {}
+    Queue depth:  {} ({} rejected)
   Scrubs:
     Completed:    {}
     Issues found: {}
@@ -373,12 +739,16 @@ impl std::fmt::Display for MetricsSnapshot {
   Cache:
     Hits:   {} (hit rate: {:.1}%)
     Misses: {}
+  Inode cache:
+    Hits:   {}
+    Misses: {}
 "#,
             self.disk_reads,
             self.disk_read_bytes,
             self.disk_writes,
             self.disk_write_bytes,
             self.disk_errors,
+            self.fragment_checksum_failures,
             self.extents_healthy,
             self.extents_degraded,
             self.extents_unrecoverable,
@@ -387,6 +757,8 @@ impl std::fmt::Display for MetricsSnapshot {
             self.rebuilds_successful,
             self.rebuilds_failed,
             self.rebuild_bytes_written,
+            self.rebuild_queue_depth,
+            self.rebuild_queue_rejected,
             self.scrubs_completed,
             self.scrub_issues_found,
             self.scrub_repairs_attempted,
@@ -394,6 +766,24 @@ impl std::fmt::Display for MetricsSnapshot {
             self.cache_hits,
             self.cache_hit_rate(),
             self.cache_misses,
-        )
+            self.inode_cache_hits,
+            self.inode_cache_misses,
+        )?;
+        writeln!(f, "  FUSE latency:")?;
+        let millis = |d: Option<Duration>| d.map_or(0.0, |d| d.as_secs_f64() * 1000.0);
+        for (op, latency) in &self.fuse_latency {
+            match latency.count() {
+                0 => writeln!(f, "    {:<8} no operations", op.name())?,
+                count => writeln!(
+                    f,
+                    "    {:<8} {} ops, p50 {:.2} ms, p95 {:.2} ms",
+                    op.name(),
+                    count,
+                    millis(latency.quantile(0.5)),
+                    millis(latency.quantile(0.95))
+                )?,
+            }
+        }
+        Ok(())
     }
 }
@@ -1,15 +1,41 @@
 use std::fmt::Write;
-use std::sync::Arc;
-use crate::metrics::Metrics;
+use std::io::{Read, Write as IoWrite};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use crate::disk::{Disk, DiskHealth};
+use crate::extent_totals::ExtentTotals;
+use crate::metrics::{Metrics, LATENCY_BUCKET_BOUNDS_US};
 
 /// Prometheus-compatible metrics exporter
 pub struct PrometheusExporter {
     metrics: Arc<Metrics>,
+    /// Gauges computed from the pool, once a refresh has run
+    pool_health: RwLock<Option<PoolHealth>>,
 }
 
 impl PrometheusExporter {
     pub fn new(metrics: Arc<Metrics>) -> Self {
-        PrometheusExporter { metrics }
+        PrometheusExporter {
+            metrics,
+            pool_health: RwLock::new(None),
+        }
+    }
+
+    /// Replace the pool gauges exported alongside the counters
+    pub fn set_pool_health(&self, health: PoolHealth) {
+        *self.pool_health.write().unwrap() = Some(health);
+    }
+
+    /// Health for `/health`: from the pool gauges when available, else from the counters
+    pub fn health_check(&self) -> HealthCheckStatus {
+        match &*self.pool_health.read().unwrap() {
+            Some(health) => health.check(),
+            None => HealthChecker::new(Arc::clone(&self.metrics)).check(),
+        }
     }
 
     /// Generate Prometheus metrics in text format
@@ -38,6 +64,36 @@ impl PrometheusExporter {
         writeln!(output, "# TYPE dynamicfs_disk_errors_total counter").unwrap();
         writeln!(output, "dynamicfs_disk_errors_total {}", snapshot.disk_errors).unwrap();
 
+        writeln!(output, "# HELP dynamicfs_fragment_checksum_failures_total Fragments that failed their checksum on read").unwrap();
+        writeln!(output, "# TYPE dynamicfs_fragment_checksum_failures_total counter").unwrap();
+        writeln!(output, "dynamicfs_fragment_checksum_failures_total {}", snapshot.fragment_checksum_failures).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_write_verify_failures_total Fragments that failed read-back after a verified write, by disk").unwrap();
+        writeln!(output, "# TYPE dynamicfs_write_verify_failures_total counter").unwrap();
+        for (disk, count) in &snapshot.write_verify_failures {
+            writeln!(output, "dynamicfs_write_verify_failures_total{{disk=\"{}\"}} {}", disk, count).unwrap();
+        }
+
+        writeln!(output, "# HELP dynamicfs_orphan_gc_runs_total Completed background orphan GC passes").unwrap();
+        writeln!(output, "# TYPE dynamicfs_orphan_gc_runs_total counter").unwrap();
+        writeln!(output, "dynamicfs_orphan_gc_runs_total {}", snapshot.orphan_gc_runs).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_orphan_gc_fragments_removed_total Orphaned fragments removed by background GC").unwrap();
+        writeln!(output, "# TYPE dynamicfs_orphan_gc_fragments_removed_total counter").unwrap();
+        writeln!(output, "dynamicfs_orphan_gc_fragments_removed_total {}", snapshot.orphan_gc_fragments_removed).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_orphan_gc_bytes_reclaimed_total Bytes freed by background orphan GC").unwrap();
+        writeln!(output, "# TYPE dynamicfs_orphan_gc_bytes_reclaimed_total counter").unwrap();
+        writeln!(output, "dynamicfs_orphan_gc_bytes_reclaimed_total {}", snapshot.orphan_gc_bytes_reclaimed).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_orphan_gc_last_run_timestamp_seconds Unix time of the last orphan GC pass").unwrap();
+        writeln!(output, "# TYPE dynamicfs_orphan_gc_last_run_timestamp_seconds gauge").unwrap();
+        writeln!(output, "dynamicfs_orphan_gc_last_run_timestamp_seconds {}", snapshot.orphan_gc_last_run).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_orphan_gc_skipped_disks Disks the last orphan GC pass skipped for unreadable extent metadata").unwrap();
+        writeln!(output, "# TYPE dynamicfs_orphan_gc_skipped_disks gauge").unwrap();
+        writeln!(output, "dynamicfs_orphan_gc_skipped_disks {}", snapshot.orphan_gc_skipped_disks).unwrap();
+
         writeln!(output, "# HELP dynamicfs_extents_healthy Number of healthy extents").unwrap();
         writeln!(output, "# TYPE dynamicfs_extents_healthy gauge").unwrap();
         writeln!(output, "dynamicfs_extents_healthy {}", snapshot.extents_healthy).unwrap();
@@ -66,6 +122,84 @@ impl PrometheusExporter {
         writeln!(output, "# TYPE dynamicfs_rebuild_bytes_written counter").unwrap();
         writeln!(output, "dynamicfs_rebuild_bytes_written {}", snapshot.rebuild_bytes_written).unwrap();
 
+        writeln!(output, "# HELP dynamicfs_rebuild_queue_depth Extents waiting for background rebuild").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuild_queue_depth gauge").unwrap();
+        writeln!(output, "dynamicfs_rebuild_queue_depth {}", snapshot.rebuild_queue_depth).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_rebuild_queue_rejected Rebuilds dropped because the queue was full").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuild_queue_rejected counter").unwrap();
+        writeln!(output, "dynamicfs_rebuild_queue_rejected {}", snapshot.rebuild_queue_rejected).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_degraded_writes_total Extents written below their policy because too few disks were writable").unwrap();
+        writeln!(output, "# TYPE dynamicfs_degraded_writes_total counter").unwrap();
+        writeln!(output, "dynamicfs_degraded_writes_total {}", snapshot.degraded_writes).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_degraded_upgrades_total Degraded extents re-encoded to their intended policy").unwrap();
+        writeln!(output, "# TYPE dynamicfs_degraded_upgrades_total counter").unwrap();
+        writeln!(output, "dynamicfs_degraded_upgrades_total {}", snapshot.degraded_upgrades).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_rebuilds_running Rebuilds in progress").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuilds_running gauge").unwrap();
+        writeln!(output, "dynamicfs_rebuilds_running {}", snapshot.rebuilds_running).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_rebuild_throughput_bytes_per_second Rebuilt bytes per second over the last 10 seconds").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuild_throughput_bytes_per_second gauge").unwrap();
+        writeln!(output, "dynamicfs_rebuild_throughput_bytes_per_second {}", snapshot.rebuild_throughput_bytes_per_sec).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_rebuild_limit_concurrent Concurrent rebuilds the repair budget allows now").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuild_limit_concurrent gauge").unwrap();
+        writeln!(output, "dynamicfs_rebuild_limit_concurrent {}", snapshot.rebuild_limit_concurrent).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_rebuild_limit_bytes_per_second Rebuild bytes per second the repair budget allows now (0: unlimited)").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuild_limit_bytes_per_second gauge").unwrap();
+        writeln!(output, "dynamicfs_rebuild_limit_bytes_per_second {}", snapshot.rebuild_limit_bytes_per_sec).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_rebuild_pool_idle Whether the idle repair budget applies (no recent foreground I/O)").unwrap();
+        writeln!(output, "# TYPE dynamicfs_rebuild_pool_idle gauge").unwrap();
+        writeln!(output, "dynamicfs_rebuild_pool_idle {}", snapshot.rebuild_pool_idle as u8).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_disk_io_queued Fragment requests waiting for a slot on a disk, by class").unwrap();
+        writeln!(output, "# TYPE dynamicfs_disk_io_queued gauge").unwrap();
+        for (disk, classes) in &snapshot.disk_io_queues {
+            for (class, queue) in classes {
+                writeln!(output, "dynamicfs_disk_io_queued{{disk=\"{}\",class=\"{}\"}} {}", disk, class, queue.queued).unwrap();
+            }
+        }
+
+        writeln!(output, "# HELP dynamicfs_disk_io_in_flight Fragment requests running on a disk, by class").unwrap();
+        writeln!(output, "# TYPE dynamicfs_disk_io_in_flight gauge").unwrap();
+        for (disk, classes) in &snapshot.disk_io_queues {
+            for (class, queue) in classes {
+                writeln!(output, "dynamicfs_disk_io_in_flight{{disk=\"{}\",class=\"{}\"}} {}", disk, class, queue.in_flight).unwrap();
+            }
+        }
+
+        writeln!(output, "# HELP dynamicfs_disk_io_completed_total Fragment requests completed on a disk, by class").unwrap();
+        writeln!(output, "# TYPE dynamicfs_disk_io_completed_total counter").unwrap();
+        for (disk, classes) in &snapshot.disk_io_queues {
+            for (class, queue) in classes {
+                writeln!(output, "dynamicfs_disk_io_completed_total{{disk=\"{}\",class=\"{}\"}} {}", disk, class, queue.completed).unwrap();
+            }
+        }
+
+        writeln!(output, "# HELP dynamicfs_write_buffer_coalesced Writes merged into a buffered dirty run").unwrap();
+        writeln!(output, "# TYPE dynamicfs_write_buffer_coalesced counter").unwrap();
+        writeln!(output, "dynamicfs_write_buffer_coalesced {}", snapshot.write_buffer_coalesced).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_write_buffer_flushes Buffered runs written out, by cause").unwrap();
+        writeln!(output, "# TYPE dynamicfs_write_buffer_flushes counter").unwrap();
+        for (cause, count) in [
+            ("extent_full", snapshot.write_buffer_flushes_extent_full),
+            ("non_sequential", snapshot.write_buffer_flushes_non_sequential),
+            ("fsync", snapshot.write_buffer_flushes_fsync),
+            ("release", snapshot.write_buffer_flushes_release),
+            ("timer", snapshot.write_buffer_flushes_timer),
+            ("memory_pressure", snapshot.write_buffer_flushes_memory_pressure),
+            ("explicit", snapshot.write_buffer_flushes_explicit),
+        ] {
+            writeln!(output, "dynamicfs_write_buffer_flushes{{cause=\"{}\"}} {}", cause, count).unwrap();
+        }
+
         writeln!(output, "# HELP dynamicfs_scrubs_completed Total completed scrubs").unwrap();
         writeln!(output, "# TYPE dynamicfs_scrubs_completed counter").unwrap();
         writeln!(output, "dynamicfs_scrubs_completed {}", snapshot.scrubs_completed).unwrap();
@@ -82,6 +216,26 @@ impl PrometheusExporter {
         writeln!(output, "# TYPE dynamicfs_scrub_repairs_successful counter").unwrap();
         writeln!(output, "dynamicfs_scrub_repairs_successful {}", snapshot.scrub_repairs_successful).unwrap();
 
+        writeln!(output, "# HELP dynamicfs_recent_write_queue_depth Placed extents waiting for recent-write verification").unwrap();
+        writeln!(output, "# TYPE dynamicfs_recent_write_queue_depth gauge").unwrap();
+        writeln!(output, "dynamicfs_recent_write_queue_depth {}", snapshot.recent_write_queue_depth).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_recent_write_overflow_total Placements left to the full scrub because the recent-write queue was full").unwrap();
+        writeln!(output, "# TYPE dynamicfs_recent_write_overflow_total counter").unwrap();
+        writeln!(output, "dynamicfs_recent_write_overflow_total {}", snapshot.recent_write_overflow).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_recent_writes_verified_total Recently written extents verified after settling").unwrap();
+        writeln!(output, "# TYPE dynamicfs_recent_writes_verified_total counter").unwrap();
+        writeln!(output, "dynamicfs_recent_writes_verified_total {}", snapshot.recent_writes_verified).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_recent_write_failures_total Recently written extents found degraded or unrecoverable").unwrap();
+        writeln!(output, "# TYPE dynamicfs_recent_write_failures_total counter").unwrap();
+        writeln!(output, "dynamicfs_recent_write_failures_total {}", snapshot.recent_write_failures).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_recent_write_lag_seconds Time the oldest queued extent has waited beyond the settle delay").unwrap();
+        writeln!(output, "# TYPE dynamicfs_recent_write_lag_seconds gauge").unwrap();
+        writeln!(output, "dynamicfs_recent_write_lag_seconds {}", snapshot.recent_write_lag_secs).unwrap();
+
         writeln!(output, "# HELP dynamicfs_cache_hits Total cache hits").unwrap();
         writeln!(output, "# TYPE dynamicfs_cache_hits counter").unwrap();
         writeln!(output, "dynamicfs_cache_hits {}", snapshot.cache_hits).unwrap();
@@ -90,6 +244,34 @@ impl PrometheusExporter {
         writeln!(output, "# TYPE dynamicfs_cache_misses counter").unwrap();
         writeln!(output, "dynamicfs_cache_misses {}", snapshot.cache_misses).unwrap();
 
+        writeln!(output, "# HELP dynamicfs_inode_cache_hits Inode and name lookups served from the inode cache").unwrap();
+        writeln!(output, "# TYPE dynamicfs_inode_cache_hits counter").unwrap();
+        writeln!(output, "dynamicfs_inode_cache_hits {}", snapshot.inode_cache_hits).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_inode_cache_misses Inode and name lookups that read metadata").unwrap();
+        writeln!(output, "# TYPE dynamicfs_inode_cache_misses counter").unwrap();
+        writeln!(output, "dynamicfs_inode_cache_misses {}", snapshot.inode_cache_misses).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_fuse_op_duration_seconds Time from FUSE request to reply, by operation").unwrap();
+        writeln!(output, "# TYPE dynamicfs_fuse_op_duration_seconds histogram").unwrap();
+        for (op, latency) in &snapshot.fuse_latency {
+            let mut cumulative = 0;
+            for (bound_us, count) in LATENCY_BUCKET_BOUNDS_US.iter().zip(&latency.buckets) {
+                cumulative += count;
+                writeln!(
+                    output,
+                    "dynamicfs_fuse_op_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    op.name(),
+                    *bound_us as f64 / 1_000_000.0,
+                    cumulative
+                )
+                .unwrap();
+            }
+            writeln!(output, "dynamicfs_fuse_op_duration_seconds_bucket{{op=\"{}\",le=\"+Inf\"}} {}", op.name(), latency.count()).unwrap();
+            writeln!(output, "dynamicfs_fuse_op_duration_seconds_sum{{op=\"{}\"}} {}", op.name(), latency.sum_us as f64 / 1_000_000.0).unwrap();
+            writeln!(output, "dynamicfs_fuse_op_duration_seconds_count{{op=\"{}\"}} {}", op.name(), latency.count()).unwrap();
+        }
+
         // Derived metrics
         writeln!(output, "# HELP dynamicfs_disk_iops_total Total I/O operations per second").unwrap();
         writeln!(output, "# TYPE dynamicfs_disk_iops_total gauge").unwrap();
@@ -103,6 +285,10 @@ impl PrometheusExporter {
         writeln!(output, "# TYPE dynamicfs_rebuild_success_rate gauge").unwrap();
         writeln!(output, "dynamicfs_rebuild_success_rate {:.2}", snapshot.rebuild_success_rate()).unwrap();
 
+        if let Some(health) = &*self.pool_health.read().unwrap() {
+            health.export(&mut output);
+        }
+
         output
     }
 
@@ -188,6 +374,16 @@ pub enum HealthStatus {
     Critical,
 }
 
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Critical => "critical",
+        }
+    }
+}
+
 impl HealthCheckStatus {
     pub fn to_json(&self) -> String {
         format!(
@@ -196,11 +392,7 @@ impl HealthCheckStatus {
   "message": "{}",
   "timestamp": "{}"
 }}"#,
-            match self.status {
-                HealthStatus::Healthy => "healthy",
-                HealthStatus::Degraded => "degraded",
-                HealthStatus::Critical => "critical",
-            },
+            self.status.as_str(),
             self.message,
             self.timestamp,
         )
@@ -285,3 +477,278 @@ impl HealthChecker {
     }
 }
 
+
+/// Used and total bytes of one disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub uuid: uuid::Uuid,
+    pub used_bytes: u64,
+    pub capacity_bytes: u64,
+    /// Moving average of fragment read times, once the disk has been read
+    pub read_latency: Option<Duration>,
+}
+
+/// Disk and extent health of a pool, as reported by `dynamicfs health`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolHealth {
+    pub healthy_disks: usize,
+    /// Disks neither Healthy nor Failed
+    pub degraded_disks: usize,
+    pub failed_disks: usize,
+    pub disk_usage: Vec<DiskUsage>,
+    /// Extents with every fragment
+    pub healthy_extents: usize,
+    /// Extents missing fragments but still readable
+    pub degraded_extents: usize,
+    pub unreadable_extents: usize,
+    /// Extents written below their policy, waiting for disks to upgrade them
+    pub below_policy_extents: usize,
+}
+
+impl PoolHealth {
+    pub fn from_pool(disks: &[Disk], extents: &ExtentTotals) -> Self {
+        let mut health = PoolHealth::default();
+        for disk in disks {
+            health.add_disk(disk);
+        }
+        health.add_extents(extents);
+        health
+    }
+
+    pub fn add_disk(&mut self, disk: &Disk) {
+        match disk.health {
+            DiskHealth::Healthy => self.healthy_disks += 1,
+            DiskHealth::Failed => self.failed_disks += 1,
+            _ => self.degraded_disks += 1,
+        }
+        self.disk_usage.push(DiskUsage {
+            uuid: disk.uuid,
+            used_bytes: disk.used_bytes,
+            capacity_bytes: disk.capacity_bytes,
+            read_latency: disk.io_counters.read_latency(),
+        });
+    }
+
+    pub fn add_extents(&mut self, extents: &ExtentTotals) {
+        self.healthy_extents += extents.complete() as usize;
+        self.degraded_extents += extents.degraded.len();
+        self.unreadable_extents += extents.unreadable.len();
+        self.below_policy_extents += extents.below_policy.len();
+    }
+
+    pub fn total_disks(&self) -> usize {
+        self.healthy_disks + self.degraded_disks + self.failed_disks
+    }
+
+    pub fn total_extents(&self) -> usize {
+        self.healthy_extents + self.degraded_extents + self.unreadable_extents
+    }
+
+    pub fn capacity_bytes(&self) -> u64 {
+        self.disk_usage.iter().map(|d| d.capacity_bytes).sum()
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.disk_usage.iter().map(|d| d.used_bytes).sum()
+    }
+
+    /// Critical with unreadable extents, degraded with failed disks or extents degraded or below policy
+    pub fn status(&self) -> HealthStatus {
+        if self.unreadable_extents > 0 {
+            HealthStatus::Critical
+        } else if self.failed_disks > 0 || self.degraded_extents > 0 || self.below_policy_extents > 0 {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        }
+    }
+
+    pub fn check(&self) -> HealthCheckStatus {
+        let status = self.status();
+        let message = match status {
+            HealthStatus::Critical => format!("CRITICAL: {} unreadable extents", self.unreadable_extents),
+            HealthStatus::Degraded => format!(
+                "DEGRADED: {} failed disks, {} degraded extents, {} extents below policy",
+                self.failed_disks, self.degraded_extents, self.below_policy_extents
+            ),
+            HealthStatus::Healthy => format!(
+                "HEALTHY: {} disks, {} extents",
+                self.total_disks(),
+                self.total_extents()
+            ),
+        };
+        HealthCheckStatus {
+            status,
+            message,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Append the pool gauges in Prometheus text format
+    fn export(&self, output: &mut String) {
+        writeln!(output, "# HELP dynamicfs_pool_disks Disks in the pool by health").unwrap();
+        writeln!(output, "# TYPE dynamicfs_pool_disks gauge").unwrap();
+        for (state, count) in [
+            ("healthy", self.healthy_disks),
+            ("degraded", self.degraded_disks),
+            ("failed", self.failed_disks),
+        ] {
+            writeln!(output, "dynamicfs_pool_disks{{state=\"{}\"}} {}", state, count).unwrap();
+        }
+
+        writeln!(output, "# HELP dynamicfs_pool_extents Extents in the pool by health").unwrap();
+        writeln!(output, "# TYPE dynamicfs_pool_extents gauge").unwrap();
+        for (state, count) in [
+            ("healthy", self.healthy_extents),
+            ("degraded", self.degraded_extents),
+            ("unreadable", self.unreadable_extents),
+        ] {
+            writeln!(output, "dynamicfs_pool_extents{{state=\"{}\"}} {}", state, count).unwrap();
+        }
+
+        writeln!(output, "# HELP dynamicfs_pool_extents_below_policy Extents written below their policy by degraded writes").unwrap();
+        writeln!(output, "# TYPE dynamicfs_pool_extents_below_policy gauge").unwrap();
+        writeln!(output, "dynamicfs_pool_extents_below_policy {}", self.below_policy_extents).unwrap();
+
+        writeln!(output, "# HELP dynamicfs_pool_disk_used_bytes Bytes used on each disk").unwrap();
+        writeln!(output, "# TYPE dynamicfs_pool_disk_used_bytes gauge").unwrap();
+        for disk in &self.disk_usage {
+            writeln!(output, "dynamicfs_pool_disk_used_bytes{{disk=\"{}\"}} {}", disk.uuid, disk.used_bytes).unwrap();
+        }
+
+        writeln!(output, "# HELP dynamicfs_pool_disk_capacity_bytes Capacity of each disk").unwrap();
+        writeln!(output, "# TYPE dynamicfs_pool_disk_capacity_bytes gauge").unwrap();
+        for disk in &self.disk_usage {
+            writeln!(output, "dynamicfs_pool_disk_capacity_bytes{{disk=\"{}\"}} {}", disk.uuid, disk.capacity_bytes).unwrap();
+        }
+
+        writeln!(output, "# HELP dynamicfs_disk_read_latency_seconds Moving average of fragment read times on each disk").unwrap();
+        writeln!(output, "# TYPE dynamicfs_disk_read_latency_seconds gauge").unwrap();
+        for disk in &self.disk_usage {
+            if let Some(latency) = disk.read_latency {
+                writeln!(output, "dynamicfs_disk_read_latency_seconds{{disk=\"{}\"}} {}", disk.uuid, latency.as_secs_f64()).unwrap();
+            }
+        }
+
+        writeln!(output, "# HELP dynamicfs_pool_health Overall pool health (0 healthy, 1 degraded, 2 critical)").unwrap();
+        writeln!(output, "# TYPE dynamicfs_pool_health gauge").unwrap();
+        let level = match self.status() {
+            HealthStatus::Healthy => 0,
+            HealthStatus::Degraded => 1,
+            HealthStatus::Critical => 2,
+        };
+        writeln!(output, "dynamicfs_pool_health {}", level).unwrap();
+    }
+}
+
+/// Recomputes the pool gauges of a running `MetricsServer`
+pub struct PoolHealthRefresh {
+    pub interval: Duration,
+    pub compute: Box<dyn Fn() -> Result<PoolHealth> + Send>,
+}
+
+/// HTTP listener serving `/metrics` and `/health` from a background thread
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Bind `addr` and start serving; pool gauges are refreshed if `refresh` is given
+    pub fn start(addr: &str, exporter: PrometheusExporter, refresh: Option<PoolHealthRefresh>) -> Result<Self> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("Failed to bind to {}", addr))?;
+        // Polled so the thread notices `stop`
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || serve(listener, exporter, refresh, &stop))
+        };
+        Ok(MetricsServer { addr, stop, thread: Some(thread) })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Block until the server thread exits
+    pub fn join(mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+
+    /// Stop accepting connections and wait for the server thread
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+fn serve(listener: TcpListener, exporter: PrometheusExporter, refresh: Option<PoolHealthRefresh>, stop: &AtomicBool) {
+    let mut next_refresh = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        if let Some(refresh) = &refresh {
+            if Instant::now() >= next_refresh {
+                match (refresh.compute)() {
+                    Ok(health) => exporter.set_pool_health(health),
+                    Err(e) => log::warn!("Failed to refresh pool health gauges: {}", e),
+                }
+                next_refresh = Instant::now() + refresh.interval;
+            }
+        }
+
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = handle_request(&exporter, stream) {
+                    log::warn!("Failed to serve metrics request: {}", e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(e) => log::error!("Connection failed: {}", e),
+        }
+    }
+}
+
+fn handle_request(exporter: &PrometheusExporter, mut stream: TcpStream) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut buffer = [0; 1024];
+    let bytes_read = stream.read(&mut buffer)?;
+    let request = String::from_utf8_lossy(&buffer[..bytes_read]);
+
+    let (status, content_type, body) = if request.starts_with("GET /metrics") {
+        ("200 OK", "text/plain; version=0.0.4", exporter.export())
+    } else if request.starts_with("GET /health") {
+        let check = exporter.health_check();
+        let status = match check.http_status_code() {
+            200 => "200 OK",
+            202 => "202 Accepted",
+            _ => "503 Service Unavailable",
+        };
+        (status, "application/json", check.to_json())
+    } else {
+        let body = "404 Not Found\nAvailable endpoints:\n  /metrics - Prometheus metrics\n  /health - Health check\n";
+        ("404 Not Found", "text/plain", body.to_string())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
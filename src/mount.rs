@@ -9,7 +9,7 @@
 //!
 //! - **Linux**: FUSE (Filesystem in Userspace) via `fuser` crate
 //! - **macOS**: macFUSE or FUSE-T via `fuser` crate
-//! - **Windows**: WinFsp (Windows Filesystem Proxy) integration (planned)
+//! - **Windows**: WinFsp (Windows Filesystem Proxy), loaded at mount time
 //!
 //! ## Architecture
 //!
@@ -76,6 +76,12 @@ use crate::fs_interface::FilesystemInterface;
 /// - AllowOther: Allows other users to access
 /// - DefaultPermissions: Enable kernel permission checking
 ///
+/// With DefaultPermissions the kernel checks every access against the mode,
+/// uid and gid stored on each inode. Those come from the creating process
+/// (requested mode masked by its umask) and change through chmod/chown.
+/// `setattr` still applies the chmod(2)/chown(2) ownership rules itself, so
+/// mounts without the option cannot be used to take over files either.
+///
 /// ## macOS
 /// Uses macFUSE/FUSE-T with macOS-optimized options:
 /// - All Linux options, plus:
@@ -106,75 +112,338 @@ pub fn mount_filesystem(
     fs: Box<dyn FilesystemInterface + Send + Sync>,
     mountpoint: &Path,
 ) -> Result<()> {
-    #[cfg(target_os = "linux")]
-    {
-        mount_linux(fs, mountpoint)
+    mount_filesystem_with(fs, mountpoint, &MountSettings::default())
+}
+
+/// Options chosen on the `mount` command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountSettings {
+    /// Ask the kernel to mount read-only
+    ///
+    /// The kernel may ignore this, so callers should also put the storage
+    /// itself into read-only mode.
+    pub read_only: bool,
+    /// Let users other than the one mounting access the filesystem
+    pub allow_other: bool,
+    /// Extra `KEY[=VALUE]` options, each possibly comma-separated
+    pub options: Vec<String>,
+    /// The pool folds name case on lookup; reported to WinFsp on Windows
+    pub case_insensitive: bool,
+    /// How long the kernel caches attributes and directory entries
+    pub attr_ttl: std::time::Duration,
+}
+
+impl MountSettings {
+    /// When reads update atime: the last of `strictatime`, `relatime`, `atime`
+    /// or `noatime` in the options, else relatime
+    pub fn atime_mode(&self) -> crate::access_tracker::AtimeMode {
+        self.options
+            .iter()
+            .flat_map(|o| o.split(','))
+            .filter_map(|o| crate::access_tracker::AtimeMode::from_mount_option(o.trim()))
+            .next_back()
+            .unwrap_or_default()
     }
+}
 
-    #[cfg(target_os = "macos")]
+impl Default for MountSettings {
+    fn default() -> Self {
+        MountSettings {
+            read_only: false,
+            allow_other: true,
+            options: Vec::new(),
+            case_insensitive: false,
+            attr_ttl: crate::inode_cache::DEFAULT_INODE_CACHE_TTL,
+        }
+    }
+}
+
+/// Mount the filesystem with explicit mount settings
+///
+/// Behaves like [`mount_filesystem`], with `settings` applied on top of the
+/// platform defaults. If the mount fails with EPERM while AllowOther is set
+/// (typically `user_allow_other` missing from `/etc/fuse.conf`), it is retried
+/// without AllowOther and a warning is logged.
+pub fn mount_filesystem_with(
+    fs: Box<dyn FilesystemInterface + Send + Sync>,
+    mountpoint: &Path,
+    settings: &MountSettings,
+) -> Result<()> {
+    mount_filesystem_then(fs, mountpoint, settings, || {})
+}
+
+/// Mount like [`mount_filesystem_with`], calling `mounted` once the
+/// filesystem is mounted and before blocking until it is unmounted
+pub fn mount_filesystem_then(
+    fs: Box<dyn FilesystemInterface + Send + Sync>,
+    mountpoint: &Path,
+    settings: &MountSettings,
+    mounted: impl FnOnce(),
+) -> Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
     {
-        mount_macos(fs, mountpoint)
+        mount_fuse(fs, mountpoint, settings, mounted)
     }
 
     #[cfg(target_os = "windows")]
     {
-        mount_windows(fs, mountpoint)
+        mount_windows(fs, mountpoint, settings, mounted)
     }
 
     #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
     {
+        let _ = (fs, mountpoint, settings, mounted);
         Err(anyhow::anyhow!("Unsupported operating system for filesystem mounting"))
     }
 }
 
-/// Mount filesystem on Linux using FUSE with optimized settings
-#[cfg(target_os = "linux")]
-fn mount_linux(
-    fs: Box<dyn FilesystemInterface + Send + Sync>,
-    mountpoint: &Path,
-) -> Result<()> {
-    use crate::fuse_impl::DynamicFS;
-    use crate::fuse_optimizations::OptimizedFUSEConfig;
+/// Unmount the filesystem mounted by this process and shut down as on
+/// SIGTERM; false where mounts do not handle shutdown requests
+///
+/// A signal after this counts as the second one and exits at once.
+pub fn request_shutdown() -> bool {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        shutdown::request();
+        true
+    }
 
-    // Use high-performance configuration
-    let config = OptimizedFUSEConfig::high_performance();
-    let options = config.to_mount_options();
+    #[cfg(target_os = "windows")]
+    {
+        crate::windows_fs::request_shutdown();
+        true
+    }
 
-    let dynamic_fs = DynamicFS::new_with_config(fs, config);
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        false
+    }
+}
 
-    fuser::mount2(dynamic_fs, mountpoint, &options)
-        .map_err(|e| anyhow::anyhow!("Failed to mount filesystem: {}", e))
+/// Apply `settings` to the platform default mount options
+#[cfg(not(target_os = "windows"))]
+pub fn apply_mount_settings(
+    defaults: Vec<fuser::MountOption>,
+    settings: &MountSettings,
+) -> Vec<fuser::MountOption> {
+    use fuser::MountOption;
+
+    let mut options = defaults;
+    if !settings.allow_other {
+        options.retain(|o| *o != MountOption::AllowOther);
+    }
+    if settings.read_only {
+        options.retain(|o| *o != MountOption::RW);
+        options.push(MountOption::RO);
+    }
+    for option in settings.options.iter().flat_map(|o| o.split(',')) {
+        let option = option.trim();
+        if option.is_empty() {
+            continue;
+        }
+        let option = parse_mount_option(option);
+        // A later option replaces an earlier one of the same kind
+        options.retain(|o| !conflicts(o, &option));
+        options.push(option);
+    }
+    options
 }
 
-/// Mount filesystem on macOS using macFUSE/FUSE-T with optimized settings
-#[cfg(target_os = "macos")]
-fn mount_macos(
+/// Translate one `KEY[=VALUE]` option; unknown keys are passed through as-is
+#[cfg(not(target_os = "windows"))]
+pub fn parse_mount_option(option: &str) -> fuser::MountOption {
+    use fuser::MountOption;
+
+    let (key, value) = match option.split_once('=') {
+        Some((key, value)) => (key, Some(value)),
+        None => (option, None),
+    };
+    match (key, value) {
+        ("fsname", Some(name)) => MountOption::FSName(name.to_string()),
+        ("subtype", Some(name)) => MountOption::Subtype(name.to_string()),
+        ("ro", None) => MountOption::RO,
+        ("rw", None) => MountOption::RW,
+        ("allow_other", None) => MountOption::AllowOther,
+        ("allow_root", None) => MountOption::AllowRoot,
+        ("auto_unmount", None) => MountOption::AutoUnmount,
+        ("default_permissions", None) => MountOption::DefaultPermissions,
+        ("dev", None) => MountOption::Dev,
+        ("nodev", None) => MountOption::NoDev,
+        ("suid", None) => MountOption::Suid,
+        ("nosuid", None) => MountOption::NoSuid,
+        ("exec", None) => MountOption::Exec,
+        ("noexec", None) => MountOption::NoExec,
+        ("atime", None) => MountOption::Atime,
+        ("noatime", None) => MountOption::NoAtime,
+        ("sync", None) => MountOption::Sync,
+        ("async", None) => MountOption::Async,
+        ("dirsync", None) => MountOption::DirSync,
+        _ => MountOption::CUSTOM(option.to_string()),
+    }
+}
+
+/// Whether `new` should replace `existing`: the same option, its opposite, or
+/// another value for a valued option
+#[cfg(not(target_os = "windows"))]
+fn conflicts(existing: &fuser::MountOption, new: &fuser::MountOption) -> bool {
+    use fuser::MountOption::*;
+
+    let opposites = [(RO, RW), (AllowOther, AllowRoot), (Dev, NoDev), (Suid, NoSuid), (Exec, NoExec), (Atime, NoAtime), (Sync, Async)];
+    existing == new
+        || matches!((existing, new), (FSName(_), FSName(_)) | (Subtype(_), Subtype(_)))
+        || opposites
+            .iter()
+            .any(|(a, b)| (existing == a && new == b) || (existing == b && new == a))
+}
+
+/// Mount with FUSE on Linux or macFUSE/FUSE-T on macOS with optimized settings
+///
+/// Blocks until the filesystem is unmounted externally, SIGINT/SIGTERM
+/// arrives or [`request_shutdown`] is called. Then the filesystem is
+/// unmounted, which stops new operations, and buffered writes are flushed and
+/// background workers joined before returning `Ok`. A second signal during
+/// shutdown exits the process immediately.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn mount_fuse(
     fs: Box<dyn FilesystemInterface + Send + Sync>,
     mountpoint: &Path,
+    settings: &MountSettings,
+    mounted: impl FnOnce(),
 ) -> Result<()> {
     use crate::fuse_impl::DynamicFS;
     use crate::fuse_optimizations::OptimizedFUSEConfig;
+    use fuser::MountOption;
+    use std::sync::Arc;
 
-    // Use high-performance configuration
-    let config = OptimizedFUSEConfig::high_performance();
-    let options = config.to_mount_options();
+    // Use high-performance configuration, with the kernel caching no longer than the engine
+    let mut config = OptimizedFUSEConfig::high_performance();
+    config.attr_timeout_secs = settings.attr_ttl.as_secs();
+    config.entry_timeout_secs = settings.attr_ttl.as_secs();
+    let options = apply_mount_settings(config.to_mount_options(), settings);
 
-    let dynamic_fs = DynamicFS::new_with_config(fs, config);
+    let fs: Arc<dyn FilesystemInterface + Send + Sync> = Arc::from(fs);
+    let session = fuser::spawn_mount2(DynamicFS::new_with_config(Arc::clone(&fs), config.clone()), mountpoint, &options);
 
-    fuser::mount2(dynamic_fs, mountpoint, &options)
-        .map_err(|e| anyhow::anyhow!("Failed to mount filesystem: {}", e))
+    let session = match session {
+        Err(e) if is_permission_denied(&e) && options.contains(&MountOption::AllowOther) => {
+            log::warn!(
+                "Mounting with allow_other was not permitted ({}); retrying without it. \
+                 Other users will not be able to access {:?}",
+                e,
+                mountpoint
+            );
+            let options: Vec<MountOption> = options
+                .into_iter()
+                .filter(|o| *o != MountOption::AllowOther)
+                .collect();
+            fuser::spawn_mount2(DynamicFS::new_with_config(Arc::clone(&fs), config), mountpoint, &options)
+        }
+        other => other,
+    };
+    let mut session = session.map_err(|e| anyhow::anyhow!("Failed to mount filesystem: {}", e))?;
+
+    let signals = shutdown::install();
+    mounted();
+    while !shutdown::requested() && !session.guard.is_finished() {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    if shutdown::requested() {
+        log::info!("Shutdown requested, unmounting {:?}", mountpoint);
+    }
+
+    // Unmounting ends the session loop, which calls `destroy` and drops its
+    // handle. The thread is joined here so its error is logged, not unwrapped.
+    let guard = std::mem::replace(&mut session.guard, std::thread::spawn(|| Ok(())));
+    drop(session);
+    match guard.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::error!("FUSE session ended with an error: {}", e),
+        Err(_) => log::error!("FUSE session thread panicked"),
+    }
+    let result = fs.sync_all();
+    // The last handle: the storage engine joins its background workers
+    drop(fs);
+    shutdown::restore(signals);
+
+    result.map_err(|e| anyhow::anyhow!("Failed to flush filesystem on unmount: {}", e))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn is_permission_denied(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EPERM) || err.kind() == std::io::ErrorKind::PermissionDenied
+}
+
+/// SIGINT/SIGTERM handling while a filesystem is mounted
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod shutdown {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+    /// Signals received since `install`
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Shutdown asked for by `request`, which may come before `install`
+    static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn handle(_signal: libc::c_int) {
+        // A second signal means the clean shutdown is stuck
+        if RECEIVED.fetch_add(1, Ordering::SeqCst) > 0 || REQUESTED.load(Ordering::SeqCst) {
+            unsafe { libc::_exit(1) };
+        }
+    }
+
+    pub(super) fn request() {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    /// Install the handlers, returning the ones they replace
+    pub(super) fn install() -> Vec<(libc::c_int, libc::sighandler_t)> {
+        RECEIVED.store(0, Ordering::SeqCst);
+        SIGNALS
+            .iter()
+            .map(|&signal| {
+                let previous = unsafe { libc::signal(signal, handle as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+                (signal, previous)
+            })
+            .collect()
+    }
+
+    pub(super) fn requested() -> bool {
+        RECEIVED.load(Ordering::SeqCst) > 0 || REQUESTED.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn restore(previous: Vec<(libc::c_int, libc::sighandler_t)>) {
+        REQUESTED.store(false, Ordering::SeqCst);
+        for (signal, handler) in previous {
+            unsafe { libc::signal(signal, handler) };
+        }
+    }
 }
 
 /// Mount filesystem on Windows using WinFsp
+///
+/// Blocks like the FUSE mount until Ctrl+C or [`request_shutdown`]. Only the
+/// read-only and case settings apply; FUSE mount options have no WinFsp
+/// equivalent.
 #[cfg(target_os = "windows")]
 fn mount_windows(
     fs: Box<dyn FilesystemInterface + Send + Sync>,
     mountpoint: &Path,
+    settings: &MountSettings,
+    mounted: impl FnOnce(),
 ) -> Result<()> {
+    use crate::windows_fs::windows_utils::{FILE_CASE_SENSITIVE_SEARCH, FILE_READ_ONLY_VOLUME};
     use crate::windows_fs::WindowsFS;
 
-    let windows_fs = WindowsFS::new(fs);
-    windows_fs.mount(mountpoint)
+    let mut windows_fs = WindowsFS::new(fs);
+    if settings.read_only {
+        windows_fs.fs_flags |= FILE_READ_ONLY_VOLUME;
+    }
+    if settings.case_insensitive {
+        windows_fs.fs_flags &= !FILE_CASE_SENSITIVE_SEARCH;
+    }
+    windows_fs.mount_then(mountpoint, mounted)
 }
 
 /// Unmount the filesystem from the specified mountpoint
@@ -298,4 +567,58 @@ mod tests {
         // Verify the unmount API signature
         let _f: fn(&Path) -> Result<()> = unmount_filesystem;
     }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_mount_settings_translate_to_options() {
+        use fuser::MountOption;
+
+        let defaults = vec![
+            MountOption::FSName("dynamicfs".to_string()),
+            MountOption::AllowOther,
+            MountOption::DefaultPermissions,
+        ];
+        assert_eq!(apply_mount_settings(defaults.clone(), &MountSettings::default()), defaults);
+
+        let settings = MountSettings {
+            read_only: true,
+            allow_other: false,
+            options: vec!["noatime,fsname=pool0".to_string(), "max_read=65536".to_string()],
+            ..Default::default()
+        };
+        let options = apply_mount_settings(defaults.clone(), &settings);
+        assert_eq!(
+            options,
+            vec![
+                MountOption::DefaultPermissions,
+                MountOption::RO,
+                MountOption::NoAtime,
+                MountOption::FSName("pool0".to_string()),
+                MountOption::CUSTOM("max_read=65536".to_string()),
+            ]
+        );
+
+        // A later option overrides its opposite
+        let settings = MountSettings {
+            options: vec!["nodev".to_string(), "dev".to_string(), "allow_root".to_string()],
+            ..Default::default()
+        };
+        let options = apply_mount_settings(defaults, &settings);
+        assert!(options.contains(&MountOption::Dev) && !options.contains(&MountOption::NoDev));
+        assert!(options.contains(&MountOption::AllowRoot) && !options.contains(&MountOption::AllowOther));
+    }
+
+    #[test]
+    fn test_atime_mode_follows_the_last_atime_option() {
+        use crate::access_tracker::AtimeMode;
+
+        assert_eq!(MountSettings::default().atime_mode(), AtimeMode::Relatime);
+        let settings = |options: &[&str]| MountSettings {
+            options: options.iter().map(|o| o.to_string()).collect(),
+            ..Default::default()
+        };
+        assert_eq!(settings(&["noatime"]).atime_mode(), AtimeMode::Noatime);
+        assert_eq!(settings(&["noatime", "fsname=pool0,strictatime"]).atime_mode(), AtimeMode::Strict);
+        assert_eq!(settings(&["strictatime, relatime"]).atime_mode(), AtimeMode::Relatime);
+    }
 }
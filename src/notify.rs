@@ -0,0 +1,737 @@
+//! Pool events delivered to external alerting
+//!
+//! Sinks listed under `notifications` in `config.json` receive events from
+//! the event ring as JSON documents: an exec sink runs a command with the
+//! document on stdin, a webhook sink POSTs it, and a logfile sink appends it
+//! as a line. Recording an event never waits for a sink: events pass through
+//! bounded queues, and a sink that falls behind loses events, which are
+//! counted, rather than slowing the pool down.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::logging::{EventKind, EventRing, StorageEvent};
+
+/// File in the pool directory holding the last notification sequence number
+pub const NOTIFY_SEQ_FILE: &str = "notify.seq";
+/// How often a mounted pool compares its usage with `space_warn_percent`
+pub const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Longest wait between two delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Where pool events are sent, from the `notifications` key of `config.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationConfig {
+    pub sinks: Vec<SinkConfig>,
+    /// Percent of pool capacity in use that raises a `space_threshold` event; 0 disables it
+    pub space_warn_percent: u8,
+    /// Events a sink may have waiting; further ones are dropped until it catches up
+    pub queue_len: usize,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        NotificationConfig { sinks: Vec::new(), space_warn_percent: 90, queue_len: 1000 }
+    }
+}
+
+impl NotificationConfig {
+    pub fn is_default(&self) -> bool {
+        *self == NotificationConfig::default()
+    }
+}
+
+/// One destination for events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SinkConfig {
+    #[serde(flatten)]
+    pub target: SinkTarget,
+    /// Event types sent to this sink; empty for all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<EventKind>,
+    /// Seconds one delivery attempt may take
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Further attempts after a failed delivery
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_backoff_ms() -> u64 {
+    500
+}
+
+impl SinkConfig {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkTarget {
+    /// Run `command` with `sh -c`, the event on its stdin; a non-zero exit is a failure
+    Exec { command: String },
+    /// POST the event to an `http://` URL; any 2xx status is success
+    Webhook { url: String },
+    /// Append the event to a file as a JSON line
+    Logfile { path: PathBuf },
+}
+
+impl std::fmt::Display for SinkTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkTarget::Exec { command } => write!(f, "exec '{}'", command),
+            SinkTarget::Webhook { url } => write!(f, "webhook {}", url),
+            SinkTarget::Logfile { path } => write!(f, "logfile {:?}", path),
+        }
+    }
+}
+
+/// The document a sink receives for an event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Increases by one per notification from the pool, across processes
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    pub pool: PathBuf,
+    pub extent_uuid: Option<Uuid>,
+    pub disk_uuid: Option<Uuid>,
+    pub message: String,
+    /// Details specific to the type; an empty object for types without any
+    pub fields: serde_json::Value,
+}
+
+impl Notification {
+    pub fn new(seq: u64, pool: &Path, event: &StorageEvent) -> Self {
+        Notification {
+            seq,
+            timestamp: event.timestamp,
+            kind: event.kind,
+            pool: pool.to_path_buf(),
+            extent_uuid: event.extent_uuid,
+            disk_uuid: event.disk_uuid,
+            message: event.message.clone(),
+            fields: event.fields.clone().unwrap_or_else(|| serde_json::json!({})),
+        }
+    }
+}
+
+/// Delivery counters of a notifier
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NotifyStats {
+    /// Deliveries a sink accepted
+    pub sent: u64,
+    /// Deliveries that failed every attempt
+    pub failed: u64,
+    /// Deliveries lost to a full queue
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    sent: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Sends events to the configured sinks from background threads
+///
+/// A dispatcher thread numbers each event and hands it to one thread per
+/// sink. Dropping the notifier stops accepting events without waiting for
+/// the sinks; `shutdown` gives them time to finish.
+pub struct Notifier {
+    inbox: Arc<Mutex<Option<SyncSender<StorageEvent>>>>,
+    counters: Arc<Counters>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Notifier {
+    /// Start delivering to the sinks of `config`; fails if one of them is invalid
+    pub fn start(pool_dir: &Path, config: &NotificationConfig) -> Result<Self> {
+        let counters = Arc::new(Counters::default());
+        let queue_len = config.queue_len.max(1);
+        let mut threads = Vec::new();
+        let mut sinks = Vec::new();
+        for sink in &config.sinks {
+            let deliver = Delivery::new(sink)?;
+            let (sender, queue) = mpsc::sync_channel::<Arc<String>>(queue_len);
+            let counters = Arc::clone(&counters);
+            threads.push(thread::spawn(move || deliver.run(queue, &counters)));
+            sinks.push((sink.clone(), sender));
+        }
+
+        let (inbox, events) = mpsc::sync_channel(queue_len);
+        let mut seq = SeqCounter { path: pool_dir.join(NOTIFY_SEQ_FILE), last: 0 };
+        let pool = pool_dir.to_path_buf();
+        let dispatch_counters = Arc::clone(&counters);
+        threads.push(thread::spawn(move || {
+            dispatch(events, &sinks, &mut seq, &pool, &dispatch_counters);
+        }));
+        Ok(Notifier { inbox: Arc::new(Mutex::new(Some(inbox))), counters, threads })
+    }
+
+    /// Send every event `ring` accepts from now on
+    pub fn subscribe(&self, ring: &EventRing) {
+        let inbox = Arc::clone(&self.inbox);
+        let counters = Arc::clone(&self.counters);
+        ring.subscribe(Box::new(move |event| enqueue(&inbox, &counters, event)));
+    }
+
+    pub fn stats(&self) -> NotifyStats {
+        NotifyStats {
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Stop accepting events and wait up to `grace` for queued ones to be delivered
+    pub fn shutdown(mut self, grace: Duration) -> NotifyStats {
+        self.inbox.lock().unwrap().take();
+        let deadline = Instant::now() + grace;
+        for thread in self.threads.drain(..) {
+            while !thread.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            if thread.is_finished() {
+                thread.join().ok();
+            }
+        }
+        self.stats()
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.inbox.lock().unwrap().take();
+    }
+}
+
+fn enqueue(inbox: &Mutex<Option<SyncSender<StorageEvent>>>, counters: &Counters, event: &StorageEvent) {
+    let sent = inbox.lock().unwrap().as_ref().is_some_and(|sender| sender.try_send(event.clone()).is_ok());
+    if !sent {
+        counters.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn dispatch(
+    events: Receiver<StorageEvent>,
+    sinks: &[(SinkConfig, SyncSender<Arc<String>>)],
+    seq: &mut SeqCounter,
+    pool: &Path,
+    counters: &Counters,
+) {
+    for event in events {
+        if !sinks.iter().any(|(sink, _)| sink.wants(event.kind)) {
+            continue;
+        }
+        let notification = Notification::new(seq.next(), pool, &event);
+        let body = Arc::new(serde_json::to_string(&notification).expect("notifications serialize"));
+        for (sink, queue) in sinks.iter().filter(|(sink, _)| sink.wants(event.kind)) {
+            if queue.try_send(Arc::clone(&body)).is_err() {
+                log::debug!("Notification queue of {} is full; dropping event {}", sink.target, notification.seq);
+                counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Sequence numbers shared by every process notifying for a pool
+///
+/// The last number is kept in `notify.seq` under an exclusive `flock`, so a
+/// mount and a concurrent `scrub` never hand out the same one. If the file
+/// cannot be used, numbering continues in memory.
+struct SeqCounter {
+    path: PathBuf,
+    last: u64,
+}
+
+impl SeqCounter {
+    fn next(&mut self) -> u64 {
+        match bump_seq_file(&self.path) {
+            Ok(seq) => self.last = seq.max(self.last + 1),
+            Err(e) => {
+                log::warn!("Failed to update {:?}: {:#}", self.path, e);
+                self.last += 1;
+            }
+        }
+        self.last
+    }
+}
+
+fn bump_seq_file(path: &Path) -> Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    // Released when the file is closed
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let last: u64 = match contents.trim() {
+        "" => 0,
+        text => text.parse().with_context(|| format!("Invalid sequence number '{}'", text))?,
+    };
+    file.seek(SeekFrom::Start(0))?;
+    file.set_len(0)?;
+    write!(file, "{}", last + 1)?;
+    Ok(last + 1)
+}
+
+/// How one sink is reached, validated up front
+enum Target {
+    Exec(String),
+    Webhook(HttpUrl),
+    Logfile(PathBuf),
+}
+
+struct Delivery {
+    target: Target,
+    name: String,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Delivery {
+    fn new(sink: &SinkConfig) -> Result<Self> {
+        let target = match &sink.target {
+            SinkTarget::Exec { command } => Target::Exec(command.clone()),
+            SinkTarget::Webhook { url } => Target::Webhook(HttpUrl::parse(url)?),
+            SinkTarget::Logfile { path } => Target::Logfile(path.clone()),
+        };
+        Ok(Delivery {
+            target,
+            name: sink.target.to_string(),
+            timeout: Duration::from_secs(sink.timeout_secs.max(1)),
+            retries: sink.retries,
+            backoff: Duration::from_millis(sink.backoff_ms),
+        })
+    }
+
+    fn run(&self, queue: Receiver<Arc<String>>, counters: &Counters) {
+        for body in queue {
+            let mut backoff = self.backoff;
+            let mut attempt = 0;
+            loop {
+                match self.deliver(&body) {
+                    Ok(()) => {
+                        counters.sent.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                    Err(e) if attempt < self.retries => {
+                        log::debug!("Notification to {} failed, retrying in {:?}: {:#}", self.name, backoff, e);
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        log::warn!("Notification to {} failed after {} attempts: {:#}", self.name, attempt + 1, e);
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn deliver(&self, body: &str) -> Result<()> {
+        match &self.target {
+            Target::Exec(command) => run_command(command, body, self.timeout),
+            Target::Webhook(url) => url.post_json(body, self.timeout),
+            Target::Logfile(path) => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open {:?}", path))?;
+                writeln!(file, "{}", body)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn run_command(command: &str, body: &str, timeout: Duration) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command that does not read its input is not a failure
+        if let Err(e) = stdin.write_all(body.as_bytes()).and_then(|_| stdin.write_all(b"\n")) {
+            log::debug!("'{}' did not read the event: {}", command, e);
+        }
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return match status.success() {
+                true => Ok(()),
+                false => Err(anyhow!("'{}' exited with {}", command, status)),
+            };
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(anyhow!("'{}' did not finish within {:?}", command, timeout));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// An `http://host[:port]/path` URL; https is not supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HttpUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl HttpUrl {
+    fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("Webhook URL '{}' must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().with_context(|| format!("Invalid port in '{}'", url))?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(anyhow!("Webhook URL '{}' has no host", url));
+        }
+        Ok(HttpUrl { host: host.to_string(), port, path: path.to_string() })
+    }
+
+    fn post_json(&self, body: &str, timeout: Duration) -> Result<()> {
+        let addrs = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", self.host))?;
+        let mut last_error = anyhow!("{} has no addresses", self.host);
+        let mut stream = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(e) => last_error = anyhow!("Failed to connect to {}: {}", addr, e),
+            }
+        }
+        let mut stream = stream.ok_or(last_error)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: dynamicfs\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+
+        let mut status_line = String::new();
+        BufReader::new(&stream).read_line(&mut status_line)?;
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| anyhow!("Invalid HTTP response '{}'", status_line.trim()))?;
+        if !(200..300).contains(&status) {
+            return Err(anyhow!("Webhook answered {}", status_line.trim()));
+        }
+        Ok(())
+    }
+}
+
+/// Start notifying for the pool at `pool_dir` if its config has sinks
+///
+/// Problems are logged rather than returned, so notifications never keep a
+/// command from running.
+pub fn start_for_pool(pool_dir: &Path, ring: &EventRing) -> Option<Notifier> {
+    let config = match crate::config::PoolConfig::load(pool_dir) {
+        Ok(config) => config.notifications,
+        Err(e) => {
+            log::warn!("Not sending notifications: {:#}", e);
+            return None;
+        }
+    };
+    if config.sinks.is_empty() {
+        return None;
+    }
+    match Notifier::start(pool_dir, &config) {
+        Ok(notifier) => {
+            notifier.subscribe(ring);
+            Some(notifier)
+        }
+        Err(e) => {
+            log::warn!("Not sending notifications: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Which side of the space warning threshold a pool was on when last checked
+#[derive(Debug, Clone)]
+pub struct SpaceWatch {
+    threshold_percent: u8,
+    above: bool,
+}
+
+impl SpaceWatch {
+    pub fn new(threshold_percent: u8) -> Self {
+        SpaceWatch { threshold_percent, above: false }
+    }
+
+    /// The message and fields of a `space_threshold` event if usage crossed the threshold
+    pub fn check(&mut self, used_bytes: u64, capacity_bytes: u64) -> Option<(String, serde_json::Value)> {
+        if self.threshold_percent == 0 || capacity_bytes == 0 {
+            return None;
+        }
+        let used_percent = used_bytes as f64 * 100.0 / capacity_bytes as f64;
+        let above = used_percent >= self.threshold_percent as f64;
+        if above == self.above {
+            return None;
+        }
+        self.above = above;
+        let message = match above {
+            true => format!("Pool is {:.1}% full, at or above {}%", used_percent, self.threshold_percent),
+            false => format!("Pool is {:.1}% full, back below {}%", used_percent, self.threshold_percent),
+        };
+        let fields = serde_json::json!({
+            "above": above,
+            "used_percent": (used_percent * 10.0).round() / 10.0,
+            "threshold_percent": self.threshold_percent,
+            "used_bytes": used_bytes,
+            "capacity_bytes": capacity_bytes,
+        });
+        Some((message, fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Answers 500 to the first `failures` requests and 200 after, keeping the bodies
+    fn http_server(failures: usize, delay: Duration) -> (String, Arc<Mutex<Vec<String>>>, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let (received, count) = (Arc::clone(&bodies), Arc::clone(&requests));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                thread::sleep(delay);
+                let n = count.fetch_add(1, Ordering::SeqCst);
+                let status = if n < failures { "500 Internal Server Error" } else { "200 OK" };
+                if n >= failures {
+                    received.lock().unwrap().push(String::from_utf8(body).unwrap());
+                }
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).ok();
+            }
+        });
+        (url, bodies, requests)
+    }
+
+    fn sink(target: SinkTarget) -> SinkConfig {
+        SinkConfig {
+            target,
+            events: Vec::new(),
+            timeout_secs: default_timeout_secs(),
+            retries: default_retries(),
+            backoff_ms: default_backoff_ms(),
+        }
+    }
+
+    fn webhook(url: &str, retries: u32) -> SinkConfig {
+        SinkConfig { retries, backoff_ms: 10, ..sink(SinkTarget::Webhook { url: url.to_string() }) }
+    }
+
+    #[test]
+    fn test_webhook_retries_until_accepted() {
+        let pool = tempfile::tempdir().unwrap();
+        let (url, bodies, requests) = http_server(2, Duration::ZERO);
+        let config = NotificationConfig { sinks: vec![webhook(&url, 3)], ..Default::default() };
+        let ring = EventRing::new();
+        let notifier = Notifier::start(pool.path(), &config).unwrap();
+        notifier.subscribe(&ring);
+        let disk = Uuid::new_v4();
+        let fields = serde_json::json!({"from": "Healthy", "to": "Failed"});
+        ring.record_with_fields(EventKind::DiskHealth, None, Some(disk), "Healthy -> Failed", Some(fields.clone()));
+
+        let stats = notifier.shutdown(Duration::from_secs(10));
+        assert_eq!(stats, NotifyStats { sent: 1, failed: 0, dropped: 0 });
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        let bodies = bodies.lock().unwrap();
+        let notification: Notification = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(notification.seq, 1);
+        assert_eq!(notification.kind, EventKind::DiskHealth);
+        assert_eq!(notification.pool, pool.path());
+        assert_eq!(notification.disk_uuid, Some(disk));
+        assert_eq!(notification.fields, fields);
+    }
+
+    #[test]
+    fn test_webhook_gives_up_after_its_retries() {
+        let pool = tempfile::tempdir().unwrap();
+        let (url, bodies, requests) = http_server(usize::MAX, Duration::ZERO);
+        let config = NotificationConfig { sinks: vec![webhook(&url, 2)], ..Default::default() };
+        let notifier = Notifier::start(pool.path(), &config).unwrap();
+        let ring = EventRing::new();
+        notifier.subscribe(&ring);
+        ring.record(EventKind::Unrecoverable, Some(Uuid::new_v4()), None, "0/2 fragments");
+
+        let stats = notifier.shutdown(Duration::from_secs(10));
+        assert_eq!(stats, NotifyStats { sent: 0, failed: 1, dropped: 0 });
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(bodies.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_slow_sink_drops_instead_of_blocking() {
+        let pool = tempfile::tempdir().unwrap();
+        let (url, _, _) = http_server(0, Duration::from_millis(500));
+        let config = NotificationConfig { sinks: vec![webhook(&url, 0)], queue_len: 2, ..Default::default() };
+        let notifier = Notifier::start(pool.path(), &config).unwrap();
+        let ring = EventRing::new();
+        notifier.subscribe(&ring);
+
+        let started = Instant::now();
+        for i in 0..50 {
+            ring.record(EventKind::DegradedRead, None, None, format!("read {}", i));
+        }
+        assert!(started.elapsed() < Duration::from_millis(250), "recording waited for the sink");
+
+        let stats = notifier.shutdown(Duration::ZERO);
+        assert!(stats.dropped >= 40, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_exec_and_logfile_sinks_filter_events() {
+        let pool = tempfile::tempdir().unwrap();
+        let exec_out = pool.path().join("exec.json");
+        let log = pool.path().join("events.jsonl");
+        let mut exec = sink(SinkTarget::Exec { command: format!("cat >> {}", exec_out.display()) });
+        exec.events = vec![EventKind::ScrubFinished];
+        let config = NotificationConfig {
+            sinks: vec![exec, sink(SinkTarget::Logfile { path: log.clone() })],
+            ..Default::default()
+        };
+        let notifier = Notifier::start(pool.path(), &config).unwrap();
+        let ring = EventRing::new();
+        notifier.subscribe(&ring);
+        ring.record(EventKind::Mounted, None, None, "mounted");
+        ring.record(EventKind::ScrubFinished, None, None, "10/10 healthy");
+        notifier.shutdown(Duration::from_secs(10));
+
+        let exec_lines: Vec<Notification> =
+            std::fs::read_to_string(&exec_out).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(exec_lines.len(), 1);
+        assert_eq!((exec_lines[0].kind, exec_lines[0].seq), (EventKind::ScrubFinished, 2));
+        let logged: Vec<Notification> =
+            std::fs::read_to_string(&log).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(logged.iter().map(|n| n.seq).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(logged[0].fields, serde_json::json!({}));
+
+        // A later notifier for the same pool continues the numbering
+        let notifier = Notifier::start(pool.path(), &config).unwrap();
+        let ring = EventRing::new();
+        notifier.subscribe(&ring);
+        ring.record(EventKind::Unmounted, None, None, "unmounted");
+        notifier.shutdown(Duration::from_secs(10));
+        let last: Notification = serde_json::from_str(std::fs::read_to_string(&log).unwrap().lines().last().unwrap()).unwrap();
+        assert_eq!(last.seq, 3);
+    }
+
+    #[test]
+    fn test_sink_config_format() {
+        let config: NotificationConfig = serde_json::from_str(
+            r#"{"sinks": [
+                {"type": "webhook", "url": "http://alerts.example:8080/dynamicfs", "retries": 5},
+                {"type": "exec", "command": "logger -t dynamicfs", "events": ["disk_health", "unrecoverable"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.space_warn_percent, 90);
+        assert_eq!(config.sinks[0].retries, 5);
+        assert_eq!(config.sinks[1].events, vec![EventKind::DiskHealth, EventKind::Unrecoverable]);
+        assert_eq!(
+            HttpUrl::parse("http://alerts.example:8080/dynamicfs").unwrap(),
+            HttpUrl { host: "alerts.example".into(), port: 8080, path: "/dynamicfs".into() }
+        );
+        assert!(HttpUrl::parse("https://alerts.example/").is_err());
+        let pool = tempfile::tempdir().unwrap();
+        let https = NotificationConfig {
+            sinks: vec![webhook("https://alerts.example/", 0)],
+            ..Default::default()
+        };
+        assert!(Notifier::start(pool.path(), &https).is_err());
+    }
+
+    #[test]
+    fn test_space_watch_reports_crossings_once() {
+        let mut watch = SpaceWatch::new(90);
+        assert!(watch.check(50, 100).is_none());
+        let (_, fields) = watch.check(91, 100).unwrap();
+        assert_eq!(fields["above"], true);
+        assert!(watch.check(95, 100).is_none());
+        let (message, _) = watch.check(80, 100).unwrap();
+        assert!(message.contains("back below 90%"), "{}", message);
+        assert!(SpaceWatch::new(0).check(100, 100).is_none());
+    }
+}
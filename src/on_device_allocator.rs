@@ -19,6 +19,16 @@ const BLKDISCARD: u64 = 0x12 << 8 | 119;
 const SUPERBLOCK_MAGIC: &[u8; 8] = b"DFSBLOCK";
 const SUPERBLOCK_VERSION: u32 = 1;
 pub const SUPERBLOCK_SIZE: usize = 4096;
+/// Offsets of the two superblock copies; the allocator bitmap follows at 64 KiB
+pub const PRIMARY_SUPERBLOCK_OFFSET: u64 = 0;
+pub const BACKUP_SUPERBLOCK_OFFSET: u64 = SUPERBLOCK_SIZE as u64;
+/// Unit size assumed for devices formatted before the superblock recorded it
+pub const LEGACY_UNIT_SIZE: u64 = 1024 * 1024;
+/// The allocation journal fills the space between the backup superblock and the bitmap
+pub const JOURNAL_OFFSET: u64 = 2 * SUPERBLOCK_SIZE as u64;
+pub const JOURNAL_ENTRY_SIZE: usize = 32;
+/// Journal entries written before the bitmap and superblock are checkpointed
+pub const JOURNAL_CHECKPOINT_INTERVAL: u64 = 256;
 
 /// Simple on-device superblock
 #[derive(Debug, Clone)]
@@ -30,10 +40,12 @@ pub struct Superblock {
     pub allocator_offset: u64,
     pub allocator_len: u64,
     pub checksum: u64,
+    /// Allocation unit the device was formatted with; 0 in superblocks written before it was recorded
+    pub unit_size: u64,
 }
 
 impl Superblock {
-    pub fn new(device_uuid: Uuid, seq: u64, allocator_offset: u64, allocator_len: u64) -> Self {
+    pub fn new(device_uuid: Uuid, seq: u64, allocator_offset: u64, allocator_len: u64, unit_size: u64) -> Self {
         Superblock {
             magic: *SUPERBLOCK_MAGIC,
             version: SUPERBLOCK_VERSION,
@@ -42,6 +54,7 @@ impl Superblock {
             allocator_offset,
             allocator_len,
             checksum: 0,
+            unit_size,
         }
     }
 
@@ -55,6 +68,7 @@ impl Superblock {
         buf[36..44].copy_from_slice(&self.allocator_offset.to_le_bytes());
         buf[44..52].copy_from_slice(&self.allocator_len.to_le_bytes());
         // checksum placeholder at 52..60 (8 bytes)
+        buf[60..68].copy_from_slice(&self.unit_size.to_le_bytes());
 
         // compute blake3 over everything except checksum field
         let mut hasher = Hasher::new();
@@ -83,6 +97,7 @@ impl Superblock {
         let allocator_offset = u64::from_le_bytes(buf[36..44].try_into().unwrap());
         let allocator_len = u64::from_le_bytes(buf[44..52].try_into().unwrap());
         let cs = u64::from_le_bytes(buf[52..60].try_into().unwrap());
+        let unit_size = u64::from_le_bytes(buf[60..68].try_into().unwrap());
         // verify checksum
         let mut hasher = Hasher::new();
         hasher.update(&buf[0..52]);
@@ -101,15 +116,74 @@ impl Superblock {
             allocator_offset,
             allocator_len,
             checksum: cs,
+            unit_size,
         })
     }
 }
 
+/// Change to a run of units recorded in the allocation journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JournalOp {
+    Alloc = 1,
+    Free = 2,
+}
+
+/// One allocation journal record
+///
+/// `generation` is the superblock seq of the checkpoint the entry applies to,
+/// so entries left over from before the last checkpoint are not replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct JournalEntry {
+    generation: u64,
+    start_unit: u64,
+    count: u64,
+    op: JournalOp,
+}
+
+impl JournalEntry {
+    fn to_bytes(self) -> [u8; JOURNAL_ENTRY_SIZE] {
+        let mut buf = [0u8; JOURNAL_ENTRY_SIZE];
+        buf[0..8].copy_from_slice(&self.generation.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.start_unit.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.count.to_le_bytes());
+        buf[24] = self.op as u8;
+        let crc = crc32fast::hash(&buf[0..28]);
+        buf[28..32].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// Decode an entry, or None for a torn, stale or never written slot
+    fn from_bytes(buf: &[u8], generation: u64) -> Option<Self> {
+        let crc = u32::from_le_bytes(buf[28..32].try_into().unwrap());
+        if crc != crc32fast::hash(&buf[0..28]) {
+            return None;
+        }
+        let op = match buf[24] {
+            1 => JournalOp::Alloc,
+            2 => JournalOp::Free,
+            _ => return None,
+        };
+        let entry = JournalEntry {
+            generation: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            start_unit: u64::from_le_bytes(buf[8..16].try_into().unwrap()),
+            count: u64::from_le_bytes(buf[16..24].try_into().unwrap()),
+            op,
+        };
+        (entry.generation == generation).then_some(entry)
+    }
+}
+
 /// Minimal on-device allocator scaffold.
+///
+/// The in-memory bitmap is authoritative. `persist` appends the allocations and
+/// frees made since the last call to a small journal region; the whole bitmap
+/// and the superblock are only rewritten by `checkpoint`, every
+/// `checkpoint_interval` entries, when the journal is full and on clean shutdown.
 #[derive(Debug, Clone)]
 pub struct OnDeviceAllocator {
     device_path: PathBuf,
-    superblock_offset: u64,
+    /// Last superblock written to both slots
+    superblock: Superblock,
     pub unit_size: u64,
     pub total_units: u64,
     /// In-memory bitmap (LSB first per byte)
@@ -117,6 +191,13 @@ pub struct OnDeviceAllocator {
     /// Free extent index for efficient contiguous allocation
     free_extents: FreeExtentIndex,
     allocator_offset: u64,
+    /// Physical block size of the device; fragment writes start and end on its boundaries
+    pub block_size: u64,
+    /// Bitmap changes not yet in the journal
+    pending: Vec<JournalEntry>,
+    /// Entries in the journal since the last checkpoint
+    journal_len: u64,
+    pub checkpoint_interval: u64,
 }
 
 /// Guard that holds an exclusive lock on a device. Releases the lock when dropped.
@@ -145,6 +226,15 @@ pub struct OnDevicePlacement {
     pub start_unit: u64,
     pub unit_count: u64,
 }
+
+/// A fragment `OnDeviceAllocator::defragment` moved, for updating its extent's placement
+#[derive(Debug, Clone)]
+pub struct FragmentMove {
+    pub extent_uuid: Uuid,
+    pub fragment_index: u32,
+    pub from: OnDevicePlacement,
+    pub to: OnDevicePlacement,
+}
 impl FragmentHeader {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(16 + 4 + 8 + 32 + 4);
@@ -188,6 +278,15 @@ impl OnDeviceAllocator {
         false
     }
 
+    /// UUID recorded in the superblock of a formatted device, if it has a readable one
+    pub fn device_uuid(path: &Path) -> Option<Uuid> {
+        let mut f = OpenOptions::new().read(true).open(path).ok()?;
+        Self::read_superblock(&mut f, PRIMARY_SUPERBLOCK_OFFSET)
+            .or_else(|_| Self::read_superblock(&mut f, BACKUP_SUPERBLOCK_OFFSET))
+            .ok()
+            .map(|sb| sb.device_uuid)
+    }
+
     /// Acquire exclusive flock on device path. Returns a guard that releases the lock on Drop.
     pub fn acquire_device_lock(path: &Path) -> Result<DeviceLock> {
         use std::os::unix::io::AsRawFd;
@@ -201,9 +300,22 @@ impl OnDeviceAllocator {
 
     /// Format the device with a superblock and empty bitmap allocator.
     /// `device_size` is the total size to ensure the file/device is large enough when testing with files.
-    pub fn format_device(path: &Path, device_uuid: Uuid, device_size: u64, unit_size: u64, total_units: u64) -> Result<()> {
+    ///
+    /// `unit_size` is rounded up to a multiple of the device's physical block
+    /// size and recorded in the superblock; the unit size used is returned.
+    pub fn format_device(path: &Path, device_uuid: Uuid, device_size: u64, unit_size: u64, total_units: u64) -> Result<u64> {
+        let block_size = crate::io_alignment::detect_alignment_from_path(path).unwrap_or(SUPERBLOCK_SIZE) as u64;
+        let aligned = crate::io_alignment::aligned_unit_size(unit_size, block_size);
+        if aligned != unit_size {
+            log::warn!(
+                "Unit size {} of {:?} is not a multiple of its {} byte blocks; using {}",
+                unit_size, path, block_size, aligned
+            );
+        }
+        let unit_size = aligned;
         let allocator_bytes = ((total_units + 7) / 8) as usize;
-        let allocator_offset = 64 * 1024u64; // place allocator after 64KB
+        // place allocator after 64KB, on a block boundary
+        let allocator_offset = crate::io_alignment::align_up(64 * 1024, block_size);
         let allocator_len = allocator_bytes as u64;
 
         let mut f = OpenOptions::new()
@@ -221,30 +333,87 @@ impl OnDeviceAllocator {
         f.write_all(&zeros)?;
         f.sync_all()?;
 
-        // write superblock
-        let mut sb = Superblock::new(device_uuid, 1, allocator_offset, allocator_len);
+        // write both superblock copies
+        let mut sb = Superblock::new(device_uuid, 1, allocator_offset, allocator_len, unit_size);
         let buf = sb.to_bytes();
-        f.seek(SeekFrom::Start(0))?;
-        f.write_all(&buf)?;
-        f.sync_all()?;
-        Ok(())
+        for offset in [BACKUP_SUPERBLOCK_OFFSET, PRIMARY_SUPERBLOCK_OFFSET] {
+            f.seek(SeekFrom::Start(offset))?;
+            f.write_all(&buf)?;
+            f.sync_all()?;
+        }
+        Ok(unit_size)
     }
 
-    /// Load allocator from a device path using superblock at offset 0
-    pub fn load_from_device(path: &Path) -> Result<Self> {
-        let mut f = OpenOptions::new().read(true).write(true).open(path).context("Failed to open device file")?;
+    /// Read the superblock copy at `offset`
+    fn read_superblock(f: &mut File, offset: u64) -> Result<Superblock> {
         let mut buf = vec![0u8; SUPERBLOCK_SIZE];
-        f.seek(SeekFrom::Start(0))?;
+        f.seek(SeekFrom::Start(offset))?;
         f.read_exact(&mut buf)?;
-        let sb = Superblock::from_bytes(&buf)?;
-        // read bitmap
+        Superblock::from_bytes(&buf)
+    }
+
+    /// The newest valid superblock copy, rewriting the other copy from it if it is damaged or older
+    ///
+    /// Fails only when neither copy is valid.
+    fn load_superblock(f: &mut File, path: &Path) -> Result<Superblock> {
+        let primary = Self::read_superblock(f, PRIMARY_SUPERBLOCK_OFFSET);
+        let backup = Self::read_superblock(f, BACKUP_SUPERBLOCK_OFFSET);
+        let (mut sb, stale_offset) = match (primary, backup) {
+            (Ok(primary), Ok(backup)) if backup.seq > primary.seq => {
+                log::warn!(
+                    "Primary superblock of {} is older than the backup (seq {} < {}); using the backup",
+                    path.display(), primary.seq, backup.seq
+                );
+                (backup, Some(PRIMARY_SUPERBLOCK_OFFSET))
+            }
+            (Ok(primary), Ok(backup)) => {
+                let stale = (backup.seq < primary.seq).then_some(BACKUP_SUPERBLOCK_OFFSET);
+                (primary, stale)
+            }
+            (Ok(primary), Err(e)) => {
+                log::warn!("Backup superblock of {} is damaged ({}); using the primary", path.display(), e);
+                (primary, Some(BACKUP_SUPERBLOCK_OFFSET))
+            }
+            (Err(e), Ok(backup)) => {
+                log::warn!("Primary superblock of {} is damaged ({}); using the backup", path.display(), e);
+                (backup, Some(PRIMARY_SUPERBLOCK_OFFSET))
+            }
+            (Err(primary), Err(backup)) => {
+                anyhow::bail!(
+                    "No valid superblock on {}: primary: {}; backup: {}",
+                    path.display(), primary, backup
+                );
+            }
+        };
+
+        if let Some(offset) = stale_offset {
+            f.seek(SeekFrom::Start(offset))?;
+            f.write_all(&sb.to_bytes())?;
+            f.sync_all()?;
+            log::info!("Rewrote superblock copy at offset {} of {} (seq {})", offset, path.display(), sb.seq);
+        }
+        Ok(sb)
+    }
+
+    /// Load allocator from a device path, using the newest valid superblock copy
+    pub fn load_from_device(path: &Path) -> Result<Self> {
+        let mut f = OpenOptions::new().read(true).write(true).open(path).context("Failed to open device file")?;
+        let sb = Self::load_superblock(&mut f, path)?;
+        // read the last checkpointed bitmap and replay the journal over it
         let mut bitmap = vec![0u8; sb.allocator_len as usize];
         f.seek(SeekFrom::Start(sb.allocator_offset))?;
         f.read_exact(&mut bitmap)?;
-
-        // derive unit_size from caller expectations; for now keep a convention
-        let unit_size = 1024 * 1024; // placeholder default 1MiB
-        let total_units = (sb.allocator_len as u64) * 8;
+        let replayed = Self::replay_journal(&mut f, &sb, &mut bitmap)?;
+
+        let unit_size = if sb.unit_size == 0 { LEGACY_UNIT_SIZE } else { sb.unit_size };
+        let total_units = sb.allocator_len * 8;
+        let block_size = crate::io_alignment::detect_alignment_from_path(path).unwrap_or(SUPERBLOCK_SIZE) as u64;
+        if unit_size % block_size != 0 {
+            log::warn!(
+                "{} was formatted with {} byte units, not a multiple of its {} byte blocks; writes will be slower",
+                path.display(), unit_size, block_size
+            );
+        }
 
         // Initialize free extent index
         let mut free_extents = FreeExtentIndex::new(None)?;
@@ -259,14 +428,25 @@ impl OnDeviceAllocator {
 
         let mut oda = OnDeviceAllocator {
             device_path: path.to_path_buf(),
-            superblock_offset: 0,
+            superblock: sb.clone(),
             unit_size,
             total_units,
             bitmap,
             free_extents,
             allocator_offset: sb.allocator_offset,
+            block_size,
+            pending: Vec::new(),
+            journal_len: 0,
+            checkpoint_interval: JOURNAL_CHECKPOINT_INTERVAL,
         };
 
+        // Fold replayed entries into a checkpoint, so entries appended from now
+        // on cannot be followed by leftovers past a torn tail
+        if replayed > 0 {
+            log::info!("Replayed {} allocation journal entries on {}", replayed, path.display());
+            oda.checkpoint()?;
+        }
+
         // Run quick reconciliation to ensure bitmap reflects present fragments
         let changed = oda.reconcile_and_persist()?;
         if changed {
@@ -276,36 +456,106 @@ impl OnDeviceAllocator {
         Ok(oda)
     }
 
-    /// Persist bitmap to device and bump superblock seq atomically
+    /// Apply the journal entries of the checkpoint `sb` describes to `bitmap`
+    ///
+    /// Replay stops at the first entry that is torn, unwritten or from an older
+    /// checkpoint. Returns the number of entries applied.
+    fn replay_journal(f: &mut File, sb: &Superblock, bitmap: &mut [u8]) -> Result<u64> {
+        let total_units = sb.allocator_len * 8;
+        let mut journal = vec![0u8; Self::journal_capacity_for(sb.allocator_offset) as usize * JOURNAL_ENTRY_SIZE];
+        f.seek(SeekFrom::Start(JOURNAL_OFFSET))?;
+        f.read_exact(&mut journal)?;
+
+        let mut replayed = 0;
+        for slot in journal.chunks_exact(JOURNAL_ENTRY_SIZE) {
+            let Some(entry) = JournalEntry::from_bytes(slot, sb.seq) else { break };
+            if entry.start_unit.saturating_add(entry.count) > total_units {
+                log::warn!("Allocation journal entry {} is out of range; stopping replay", replayed);
+                break;
+            }
+            for u in entry.start_unit..entry.start_unit + entry.count {
+                let bit = 1u8 << (u % 8);
+                match entry.op {
+                    JournalOp::Alloc => bitmap[(u / 8) as usize] |= bit,
+                    JournalOp::Free => bitmap[(u / 8) as usize] &= !bit,
+                }
+            }
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    fn journal_capacity_for(allocator_offset: u64) -> u64 {
+        allocator_offset.saturating_sub(JOURNAL_OFFSET) / JOURNAL_ENTRY_SIZE as u64
+    }
+
+    /// Make the allocations and frees since the last call durable
+    ///
+    /// Appends one journal entry per change, so the cost does not depend on the
+    /// size of the bitmap, and checkpoints once the journal holds
+    /// `checkpoint_interval` entries or has no room left.
     pub fn persist(&mut self) -> Result<()> {
-        let mut f = OpenOptions::new().read(true).write(true).open(&self.device_path).context("Failed to open device for persist")?;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let capacity = Self::journal_capacity_for(self.allocator_offset);
+        if self.journal_len + self.pending.len() as u64 > capacity {
+            return self.checkpoint();
+        }
+
+        let mut buf = Vec::with_capacity(self.pending.len() * JOURNAL_ENTRY_SIZE);
+        for entry in &mut self.pending {
+            entry.generation = self.superblock.seq;
+            buf.extend_from_slice(&entry.to_bytes());
+        }
+        let mut f = OpenOptions::new().write(true).open(&self.device_path).context("Failed to open device for persist")?;
+        f.seek(SeekFrom::Start(JOURNAL_OFFSET + self.journal_len * JOURNAL_ENTRY_SIZE as u64))?;
+        f.write_all(&buf)?;
+        f.sync_data()?;
+        self.journal_len += self.pending.len() as u64;
+        self.pending.clear();
+
+        if self.journal_len >= self.checkpoint_interval {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Write the whole bitmap and bump the superblock seq, emptying the journal
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let mut f = OpenOptions::new().read(true).write(true).open(&self.device_path).context("Failed to open device for checkpoint")?;
         f.seek(SeekFrom::Start(self.allocator_offset))?;
         f.write_all(&self.bitmap)?;
         f.sync_all()?;
 
-        // Atomic superblock update: write to temporary location first
-        let temp_sb_offset = SUPERBLOCK_SIZE as u64; // Use second superblock slot as temp
-        let mut buf = vec![0u8; SUPERBLOCK_SIZE];
-        f.seek(SeekFrom::Start(0))?;
-        f.read_exact(&mut buf)?;
-        let mut sb = Superblock::from_bytes(&buf)?;
-        sb.seq += 1;
-        let sbbuf = sb.to_bytes();
+        // Bump seq and write the backup copy, then the primary. While the backup
+        // is written the primary still holds the previous superblock, and while
+        // the primary is written the backup holds the new one, so a torn write
+        // always leaves a valid copy for `load_from_device`
+        self.superblock.seq += 1;
+        let sbbuf = self.superblock.to_bytes();
 
-        // Write to temp location
-        f.seek(SeekFrom::Start(temp_sb_offset))?;
+        f.seek(SeekFrom::Start(BACKUP_SUPERBLOCK_OFFSET))?;
         f.write_all(&sbbuf)?;
         f.sync_all()?;
 
-        // Atomically copy to primary location (in practice, this would use filesystem atomic rename,
-        // but for block devices we ensure the write is durable)
-        f.seek(SeekFrom::Start(0))?;
+        #[cfg(test)]
+        check_crash_point(CrashPoint::BetweenSuperblockWrites)?;
+
+        f.seek(SeekFrom::Start(PRIMARY_SUPERBLOCK_OFFSET))?;
         f.write_all(&sbbuf)?;
         f.sync_all()?;
 
+        // Entries carry the old seq now, so the journal starts over
+        self.pending.clear();
+        self.journal_len = 0;
         Ok(())
     }
 
+    fn record(&mut self, op: JournalOp, start_unit: u64, count: u64) {
+        self.pending.push(JournalEntry { generation: 0, start_unit, count, op });
+    }
+
     /// Attempt to allocate n contiguous units using free extent index
     pub fn allocate_contiguous(&mut self, n: u64) -> Option<u64> {
         if n == 0 || n > self.total_units {
@@ -320,6 +570,7 @@ impl OnDeviceAllocator {
                 let b = 1u8 << (u % 8);
                 self.bitmap[idx] |= b;
             }
+            self.record(JournalOp::Alloc, start, n);
             Some(start)
         } else {
             None
@@ -332,18 +583,16 @@ impl OnDeviceAllocator {
     }
 
     /// Compute where fragment data region starts (right after allocator region, aligned to unit_size)
+    ///
+    /// Also aligned to the block size, for devices formatted with units that are not whole blocks.
     pub fn data_region_base(&self) -> u64 {
         let end = self.allocator_offset + (self.bitmap.len() as u64);
-        let mask = self.unit_size - 1;
-        if end & mask == 0 {
-            end
-        } else {
-            (end + self.unit_size) & !mask
-        }
+        let base = crate::io_alignment::align_up(end, self.unit_size);
+        crate::io_alignment::align_up(base, self.block_size)
     }
 
     /// Write a fragment (header + data) into the allocated units starting at `start_unit`.
-    /// Steps: write header+data (padded to units), fdatasync, persist bitmap & bump superblock.
+    /// Steps: write header+data (padded to units), fdatasync, journal the allocation.
     pub fn write_fragment_at(&mut self, start_unit: u64, data: &[u8], hdr: &FragmentHeader) -> Result<OnDevicePlacement> {
         let n_units = ((hdr.total_length + (16 + 4 + 8 + 32 + 4) as u64) + self.unit_size - 1) / self.unit_size;
         if start_unit + n_units > self.total_units {
@@ -374,7 +623,7 @@ impl OnDeviceAllocator {
         #[cfg(test)]
         check_crash_point(CrashPoint::AfterFragmentFsync)?;
 
-        // Persist allocator bitmap and superblock
+        // Journal the allocation
         self.persist()?;
 
         Ok(OnDevicePlacement { start_unit, unit_count: n_units })
@@ -407,6 +656,7 @@ impl OnDeviceAllocator {
             let b = 1u8 << (u % 8);
             self.bitmap[idx] &= !b;
         }
+        self.record(JournalOp::Free, start, n);
         // Update free extent index
         self.free_extents.insert_run(start, n)?;
         Ok(())
@@ -452,6 +702,25 @@ impl OnDeviceAllocator {
         Ok(())
     }
 
+    /// Discard every run of free units, returning the bytes discarded
+    pub fn trim_free_units(&self) -> Result<u64> {
+        let mut trimmed = 0u64;
+        let mut run_start = None;
+        for unit in 0..=self.total_units {
+            let free = unit < self.total_units && self.bitmap[(unit / 8) as usize] & (1u8 << (unit % 8)) == 0;
+            match (free, run_start) {
+                (true, None) => run_start = Some(unit),
+                (false, Some(start)) => {
+                    self.trim_freed_units(start, unit - start)?;
+                    trimmed += (unit - start) * self.unit_size;
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        Ok(trimmed)
+    }
+
     /// Enhanced free_contiguous that also performs TRIM operation
     pub fn free_and_trim(&mut self, start: u64, n: u64) -> Result<()> {
         self.free_contiguous(start, n)?;
@@ -459,72 +728,145 @@ impl OnDeviceAllocator {
         Ok(())
     }
 
-    /// Defragmentation: consolidate fragmented free space by moving allocated units
-    /// Returns the number of units moved
-    pub fn defragment(&mut self) -> Result<u64> {
-        let mut units_moved = 0u64;
-        let mut write_pos = 0u64;
-
-        // Simple defrag algorithm: move allocated units to the beginning
-        for read_pos in 0..self.total_units {
-            let read_idx = (read_pos / 8) as usize;
-            let read_bit = 1u8 << (read_pos % 8);
-
-            if (self.bitmap[read_idx] & read_bit) != 0 {
-                // Unit is allocated
-                if read_pos != write_pos {
-                    // Move the fragment from read_pos to write_pos
-                    self.move_fragment(read_pos, write_pos)?;
-                    units_moved += 1;
-                }
-                write_pos += 1;
+    /// Compact the data region: move whole fragments towards unit 0 until the free space is one run
+    ///
+    /// Fragments are moved lowest first, each to the lowest free run below it
+    /// that is large enough. A fragment that can only slide partway over
+    /// itself is first copied to free space elsewhere and moved down from
+    /// there, so its only copy is never overwritten. Allocated units without a
+    /// valid fragment, e.g. reserved for a write in progress, stay where they are.
+    ///
+    /// For every move the destination is written and made durable, then
+    /// `on_move` records the new placement (in the extent metadata), and only
+    /// then is the source cleared and freed. If `on_move` fails the move is
+    /// undone and defragmentation stops with its error. Returns the number of
+    /// fragments moved.
+    pub fn defragment<F>(&mut self, mut on_move: F) -> Result<u64>
+    where
+        F: FnMut(&FragmentMove) -> Result<()>,
+    {
+        let mut moved = 0u64;
+        let mut lowest_free = 0u64;
+
+        for (start, count) in self.live_fragments()? {
+            while lowest_free < start && self.unit_is_used(lowest_free) {
+                lowest_free += 1;
+            }
+            let Some(dest) = self.first_free_below(lowest_free, count, start) else {
+                continue;
+            };
+            if dest + count <= start {
+                self.reserve(dest, count)?;
+                self.relocate(start, dest, &mut on_move)?;
+                moved += 1;
+            } else {
+                // Sliding down would overwrite the fragment's own units
+                let Some(scratch) = self.allocate_contiguous(count) else {
+                    log::warn!(
+                        "No free run of {} units to stage the fragment at unit {} of {}; leaving it in place",
+                        count, start, self.device_path.display()
+                    );
+                    continue;
+                };
+                self.relocate(start, scratch, &mut on_move)?;
+                self.reserve(dest, count)?;
+                self.relocate(scratch, dest, &mut on_move)?;
+                moved += 1;
             }
         }
 
-        // Clear the bitmap for the now-free units at the end and rebuild free extents
-        self.free_extents = FreeExtentIndex::new(None)?;
-        for unit in write_pos..self.total_units {
-            let idx = (unit / 8) as usize;
-            let bit = 1u8 << (unit % 8);
-            self.bitmap[idx] &= !bit;
-            self.free_extents.insert_run(unit, 1)?;
+        if moved > 0 {
+            self.checkpoint()?;
         }
+        Ok(moved)
+    }
 
-        if units_moved > 0 {
-            self.persist()?;
+    /// Start and unit count of every valid fragment the bitmap marks allocated, in unit order
+    ///
+    /// Headers left inside another fragment's units are stale and skipped.
+    fn live_fragments(&self) -> Result<Vec<(u64, u64)>> {
+        let mut live: Vec<(u64, u64)> = Vec::new();
+        for (start, count) in self.scan_valid_fragments()? {
+            let inside_previous = live.last().is_some_and(|(s, c)| start < s + c);
+            let allocated = start + count <= self.total_units && (start..start + count).all(|u| self.unit_is_used(u));
+            if allocated && !inside_previous {
+                live.push((start, count));
+            }
         }
-
-        Ok(units_moved)
+        Ok(live)
     }
 
-    /// Move a fragment from one unit position to another
-    fn move_fragment(&mut self, from_unit: u64, to_unit: u64) -> Result<()> {
-        // Read the fragment at from_unit
-        let (header, data) = self.read_fragment_at(from_unit)?;
-
-        // Calculate how many units this fragment occupies
-        let fragment_size = ((header.total_length + (16 + 4 + 8 + 32 + 4) as u64) + self.unit_size - 1) / self.unit_size;
+    /// First unit at or after `from` and below `source` where `count` units are free,
+    /// apart from units of the fragment at `source` itself
+    fn first_free_below(&self, from: u64, count: u64, source: u64) -> Option<u64> {
+        let mut pos = from;
+        while pos < source {
+            match (pos..pos + count).find(|u| self.unit_is_used(*u) && !(source..source + count).contains(u)) {
+                Some(used) => pos = used + 1,
+                None => return Some(pos),
+            }
+        }
+        None
+    }
 
-        // Free the old location
-        self.free_contiguous(from_unit, fragment_size)?;
+    /// Mark the free units `start..start + count` allocated
+    fn reserve(&mut self, start: u64, count: u64) -> Result<()> {
+        self.free_extents.consume_range(start, count)?;
+        for u in start..start + count {
+            self.bitmap[(u / 8) as usize] |= 1u8 << (u % 8);
+        }
+        self.record(JournalOp::Alloc, start, count);
+        Ok(())
+    }
 
-        // Write to new location
-        let placement = self.write_fragment_at(to_unit, &data, &header)?;
+    /// Copy the fragment at `from` to the reserved units at `to`, then free `from`
+    fn relocate<F>(&mut self, from: u64, to: u64, on_move: &mut F) -> Result<()>
+    where
+        F: FnMut(&FragmentMove) -> Result<()>,
+    {
+        let (header, data) = self.read_fragment_at(from)?;
+        let destination = self.write_fragment_at(to, &data, &header)?;
+        let count = destination.unit_count;
+        let undo = |oda: &mut Self| -> Result<()> {
+            oda.clear_fragment_header(to)?;
+            oda.free_contiguous(to, count)?;
+            oda.persist()
+        };
 
-        // Update bitmap for the new location
-        for unit in to_unit..(to_unit + fragment_size) {
-            let idx = (unit / 8) as usize;
-            let bit = 1u8 << (unit % 8);
-            self.bitmap[idx] |= bit;
+        if self.read_fragment_at(to).map(|(_, copy)| copy != data).unwrap_or(true) {
+            undo(self)?;
+            anyhow::bail!("copy of the fragment at unit {} to unit {} failed verification", from, to);
+        }
+        let change = FragmentMove {
+            extent_uuid: header.extent_uuid,
+            fragment_index: header.fragment_index,
+            from: OnDevicePlacement { start_unit: from, unit_count: count },
+            to: destination,
+        };
+        if let Err(e) = on_move(&change) {
+            undo(self)?;
+            return Err(e.context(format!("recording the move of the fragment at unit {} to unit {}", from, to)));
         }
 
+        // The old copy is unreferenced now; clear its header so a reconcile does not revive it
+        self.clear_fragment_header(from)?;
+        self.free_contiguous(from, count)?;
+        self.persist()
+    }
+
+    /// Zero the fragment header at `start_unit` and make it durable
+    fn clear_fragment_header(&self, start_unit: u64) -> Result<()> {
+        let offset = self.data_region_base() + start_unit * self.unit_size;
+        let mut f = OpenOptions::new().write(true).open(&self.device_path).context("Failed to open device to clear a fragment header")?;
+        f.seek(SeekFrom::Start(offset))?;
+        f.write_all(&[0u8; 16 + 4 + 8 + 32 + 4])?;
+        f.sync_data()?;
         Ok(())
     }
 
-    /// Scan data region for valid fragment headers and ensure bitmap marks used units.
-    /// Returns true if bitmap was changed and persisted.
-    pub fn reconcile_and_persist(&mut self) -> Result<bool> {
-        let mut changed = false;
+    /// Units spanned by every fragment in the data region with a valid header and data checksum
+    fn scan_valid_fragments(&self) -> Result<Vec<(u64, u64)>> {
+        let mut found = Vec::new();
         let base = self.data_region_base();
         let mut f = OpenOptions::new().read(true).open(&self.device_path).context("Failed to open device during reconcile")?;
         for unit in 0..self.total_units {
@@ -542,29 +884,306 @@ impl OnDeviceAllocator {
                     if f.read_exact(&mut data).is_ok() {
                         let ch = blake3::hash(&data);
                         if ch.as_bytes() == &hdr.data_checksum {
-                            // header + data valid - mark the units that correspond to this fragment
                             let fragment_total = ((data_len + (16+4+8+32+4) + self.unit_size as usize -1) / self.unit_size as usize) as u64;
-                            for u in unit..(unit + fragment_total) {
-                                let idx = (u / 8) as usize;
-                                let b = 1u8 << (u % 8);
-                                if (self.bitmap[idx] & b) == 0 {
-                                    self.bitmap[idx] |= b;
-                                    changed = true;
-                                    // Remove from free extents if it was marked free
-                                    let _ = self.free_extents.consume_range(u, 1);
-                                }
-                            }
+                            found.push((unit, fragment_total));
                         }
                     }
                 }
             }
         }
+        Ok(found)
+    }
+
+    fn unit_is_used(&self, unit: u64) -> bool {
+        self.bitmap[(unit / 8) as usize] & (1u8 << (unit % 8)) != 0
+    }
+
+    /// Valid fragments on the device and units the bitmap marks used
+    pub fn usage(&self) -> Result<(u64, u64)> {
+        let fragments = self.scan_valid_fragments()?.len() as u64;
+        Ok((fragments, self.total_units - self.free_count()))
+    }
+
+    /// Start units of valid fragments that the bitmap marks (partly) free, without changing anything
+    pub fn unmarked_fragments(&self) -> Result<Vec<u64>> {
+        Ok(self
+            .scan_valid_fragments()?
+            .into_iter()
+            .filter(|(start, count)| (*start..start + count).any(|u| !self.unit_is_used(u)))
+            .map(|(start, _)| start)
+            .collect())
+    }
+
+    /// Scan data region for valid fragment headers and ensure bitmap marks used units.
+    /// Returns true if bitmap was changed and persisted.
+    pub fn reconcile_and_persist(&mut self) -> Result<bool> {
+        let mut changed = false;
+        for (start, fragment_total) in self.scan_valid_fragments()? {
+            // header + data valid - mark the units that correspond to this fragment
+            for u in start..(start + fragment_total) {
+                if !self.unit_is_used(u) {
+                    self.bitmap[(u / 8) as usize] |= 1u8 << (u % 8);
+                    changed = true;
+                    // Remove from free extents if it was marked free
+                    let _ = self.free_extents.consume_range(u, 1);
+                }
+            }
+        }
 
         if changed {
-            self.persist()?;
+            self.checkpoint()?;
             return Ok(true);
         }
         Ok(false)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(data: &[u8]) -> FragmentHeader {
+        FragmentHeader {
+            extent_uuid: Uuid::new_v4(),
+            fragment_index: 0,
+            total_length: data.len() as u64,
+            data_checksum: *blake3::hash(data).as_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_unit_size_is_block_aligned_and_read_back_from_the_superblock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("device.img");
+        std::fs::File::create(&path).unwrap();
+        let block = crate::io_alignment::detect_alignment_from_path(&path).unwrap() as u64;
+
+        // A unit that is not a whole number of blocks is rounded up
+        let unit_size = OnDeviceAllocator::format_device(&path, Uuid::new_v4(), 8 << 20, block + 100, 64).unwrap();
+        assert_eq!(unit_size, 2 * block);
+
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.unit_size, unit_size);
+        assert_eq!(oda.data_region_base() % block, 0);
+
+        let data = vec![0x5au8; unit_size as usize];
+        let start = oda.allocate_contiguous(2).unwrap();
+        let placement = oda.write_fragment_at(start, &data, &fragment(&data)).unwrap();
+        assert_eq!(placement.unit_count, 2);
+        assert_eq!(oda.read_fragment_at(start).unwrap().1, data);
+
+        // Reloading keeps the recorded unit size, so the fragment is found where it was written
+        let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.unit_size, unit_size);
+        assert_eq!(oda.read_fragment_at(start).unwrap().1, data);
+    }
+
+    #[test]
+    fn test_superblock_without_unit_size_loads_with_legacy_units() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("device.img");
+        OnDeviceAllocator::format_device(&path, Uuid::new_v4(), 4 << 20, 4096, 16).unwrap();
+
+        // Superblocks written before the unit size was recorded have zeros there
+        let mut f = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut buf = vec![0u8; SUPERBLOCK_SIZE];
+        f.read_exact(&mut buf).unwrap();
+        let mut sb = Superblock::from_bytes(&buf).unwrap();
+        sb.unit_size = 0;
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.write_all(&sb.to_bytes()).unwrap();
+        drop(f);
+
+        assert_eq!(OnDeviceAllocator::load_from_device(&path).unwrap().unit_size, LEGACY_UNIT_SIZE);
+    }
+
+    fn journal_device(dir: &tempfile::TempDir) -> PathBuf {
+        let path = dir.path().join("device.img");
+        OnDeviceAllocator::format_device(&path, Uuid::new_v4(), 4 << 20, 4096, 256).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_journal_is_replayed_over_the_last_checkpointed_bitmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = journal_device(&dir);
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        let seq = oda.superblock.seq;
+
+        let data = b"journaled";
+        let kept = oda.allocate_contiguous(1).unwrap();
+        oda.write_fragment_at(kept, data, &fragment(data)).unwrap();
+        let freed = oda.allocate_contiguous(4).unwrap();
+        oda.persist().unwrap();
+        oda.free_contiguous(freed + 1, 2).unwrap();
+        oda.persist().unwrap();
+
+        // Only journal entries were written: the superblock and bitmap on disk are unchanged
+        assert_eq!(oda.journal_len, 3);
+        assert_eq!(oda.superblock.seq, seq);
+        let bitmap = oda.bitmap().to_vec();
+        assert_eq!(bitmap[0], 0b1_0011);
+        drop(oda);
+
+        // Reloading without a clean shutdown replays the journal and checkpoints it
+        let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.bitmap(), &bitmap[..]);
+        assert_eq!(oda.journal_len, 0);
+        assert_eq!(oda.superblock.seq, seq + 1);
+        assert_eq!(oda.read_fragment_at(kept).unwrap().1, data);
+        assert_eq!(oda.free_count(), 256 - 3);
+    }
+
+    #[test]
+    fn test_replay_stops_at_a_torn_journal_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = journal_device(&dir);
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        for n in [1, 2, 3] {
+            oda.allocate_contiguous(n).unwrap();
+            oda.persist().unwrap();
+        }
+        assert_eq!(oda.bitmap()[0], 0b11_1111);
+        drop(oda);
+
+        // Tear the second entry: the third is intact but comes after it, so it is dropped too
+        let mut f = OpenOptions::new().write(true).open(&path).unwrap();
+        f.seek(SeekFrom::Start(JOURNAL_OFFSET + JOURNAL_ENTRY_SIZE as u64 + 10)).unwrap();
+        f.write_all(&[0xff; 4]).unwrap();
+        drop(f);
+
+        let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.bitmap()[0], 0b1);
+        assert_eq!(oda.free_count(), 255);
+    }
+
+    #[test]
+    fn test_journal_is_checkpointed_every_interval_and_stale_entries_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = journal_device(&dir);
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        oda.checkpoint_interval = 4;
+        let seq = oda.superblock.seq;
+
+        let first = oda.allocate_contiguous(8).unwrap();
+        oda.persist().unwrap();
+        for u in 0..3 {
+            oda.free_contiguous(first + u, 1).unwrap();
+            oda.persist().unwrap();
+        }
+        // The fourth entry triggered a checkpoint
+        assert_eq!(oda.superblock.seq, seq + 1);
+        assert_eq!(oda.journal_len, 0);
+
+        // One entry into the new journal; the three after it belong to the old checkpoint
+        oda.free_contiguous(first + 3, 1).unwrap();
+        oda.persist().unwrap();
+        let bitmap = oda.bitmap().to_vec();
+        drop(oda);
+
+        let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.bitmap(), &bitmap[..]);
+        assert_eq!(oda.bitmap()[0], 0b1111_0000);
+    }
+
+    /// Data filling `units` units exactly, header included
+    fn fragment_data(units: u64, seed: u8) -> Vec<u8> {
+        let len = units as usize * 4096 - (16 + 4 + 8 + 32 + 4);
+        let mut data = vec![0u8; len];
+        blake3::Hasher::new().update(&[seed]).finalize_xof().fill(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_defragment_moves_whole_fragments_and_leaves_free_space_contiguous() {
+        use std::collections::HashMap;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = journal_device(&dir);
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+
+        // Mixed sizes laid out from unit 0; every other one is deleted below
+        let mut placements = HashMap::new();
+        let mut deleted = Vec::new();
+        for (i, units) in [1u64, 3, 2, 5, 1, 4, 2, 3].into_iter().enumerate() {
+            let data = fragment_data(units, i as u8);
+            let start = oda.allocate_contiguous(units).unwrap();
+            let header = fragment(&data);
+            oda.write_fragment_at(start, &data, &header).unwrap();
+            if i % 2 == 0 {
+                deleted.push((start, units));
+            } else {
+                placements.insert(header.extent_uuid, (start, data));
+            }
+        }
+        for (start, units) in deleted {
+            oda.clear_fragment_header(start).unwrap();
+            oda.free_contiguous(start, units).unwrap();
+        }
+        oda.persist().unwrap();
+
+        // The callback stands in for the extent metadata
+        let mut moves = Vec::new();
+        let moved = oda
+            .defragment(|change| {
+                let (start, _) = placements.get_mut(&change.extent_uuid).unwrap();
+                assert_eq!(*start, change.from.start_unit, "moved from where the metadata says");
+                *start = change.to.start_unit;
+                moves.push(change.clone());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(moved, 4);
+        // Fragments sliding down over themselves were staged elsewhere first
+        assert!(moves.len() > 4);
+
+        // Every fragment is at its recorded placement and the free space is one run at the end
+        let used: u64 = 3 + 5 + 4 + 3;
+        for (start, data) in placements.values() {
+            assert!(*start + data.len() as u64 / 4096 < used);
+            assert_eq!(&oda.read_fragment_at(*start).unwrap().1, data);
+        }
+        assert_eq!(oda.free_count(), 256 - used);
+        assert_eq!(oda.free_extents.list_runs(), vec![(used, 256 - used)]);
+
+        // The same holds after a reload: no header was left behind at a vacated source
+        let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.free_count(), 256 - used);
+        for (start, data) in placements.values() {
+            assert_eq!(&oda.read_fragment_at(*start).unwrap().1, data);
+        }
+        let mut f = File::open(&path).unwrap();
+        for change in moves.iter().filter(|m| m.from.start_unit >= used) {
+            let mut header = [0u8; 16 + 4 + 8 + 32 + 4];
+            f.seek(SeekFrom::Start(oda.data_region_base() + change.from.start_unit * 4096)).unwrap();
+            f.read_exact(&mut header).unwrap();
+            assert!(FragmentHeader::from_bytes(&header).is_err());
+        }
+    }
+
+    #[test]
+    fn test_defragment_keeps_the_source_when_the_placement_cannot_be_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = journal_device(&dir);
+        let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+
+        let gap = oda.allocate_contiguous(4).unwrap();
+        let data = fragment_data(2, 7);
+        let start = oda.allocate_contiguous(2).unwrap();
+        oda.write_fragment_at(start, &data, &fragment(&data)).unwrap();
+        oda.free_contiguous(gap, 4).unwrap();
+        oda.persist().unwrap();
+        let bitmap = oda.bitmap().to_vec();
+
+        let err = oda.defragment(|_| anyhow::bail!("metadata unavailable")).unwrap_err();
+        assert!(format!("{:#}", err).contains("metadata unavailable"));
+
+        // The fragment is still where the metadata points and the destination was released
+        assert_eq!(oda.read_fragment_at(start).unwrap().1, data);
+        assert_eq!(oda.bitmap(), &bitmap[..]);
+        drop(oda);
+        let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+        assert_eq!(oda.bitmap(), &bitmap[..]);
+        assert_eq!(oda.read_fragment_at(start).unwrap().1, data);
+    }
+}
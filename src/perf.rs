@@ -1,78 +1,222 @@
 /// Simple performance benchmarking utilities
-use std::time::Instant;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-pub struct Benchmark {
-    pub name: String,
-    pub start: Instant,
+use crate::crash_sim::SeededRng;
+use crate::storage::StorageEngine;
+
+/// Operations a benchmark run performs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkloadMode {
+    Write,
+    /// Reads files written before the clock starts
+    Read,
+    /// Reads and writes, reading with the workload's `read_ratio`
+    Mixed,
 }
 
-impl Benchmark {
-    pub fn start(name: &str) -> Self {
-        Benchmark {
-            name: name.to_string(),
-            start: Instant::now(),
+impl FromStr for WorkloadMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "write" => Ok(WorkloadMode::Write),
+            "read" => Ok(WorkloadMode::Read),
+            "mixed" => Ok(WorkloadMode::Mixed),
+            _ => Err(anyhow!("Unknown benchmark mode {:?}: expected write, read or mixed", s)),
         }
     }
+}
 
-    pub fn elapsed_ms(&self) -> u64 {
-        self.start.elapsed().as_millis() as u64
-    }
+/// Which part of a file each operation covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizePattern {
+    /// The whole file, visiting files in turn
+    Sequential,
+    /// A random span of a random file
+    Random,
+}
+
+impl FromStr for SizePattern {
+    type Err = anyhow::Error;
 
-    pub fn report(&self) {
-        let elapsed = self.elapsed_ms();
-        println!("{}: {} ms", self.name, elapsed);
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sequential" => Ok(SizePattern::Sequential),
+            "random" => Ok(SizePattern::Random),
+            _ => Err(anyhow!("Unknown size pattern {:?}: expected sequential or random", s)),
+        }
     }
 }
 
-impl Drop for Benchmark {
-    fn drop(&mut self) {
-        self.report();
+/// A benchmark run over a set of files
+#[derive(Debug, Clone)]
+pub struct Workload {
+    pub mode: WorkloadMode,
+    pub pattern: SizePattern,
+    pub read_ratio: f64,
+    pub file_size: usize,
+    pub operations: usize,
+    pub threads: usize,
+    pub seed: u64,
+}
+
+/// Latency percentiles of one kind of operation, in milliseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Latency {
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Latency {
+    pub fn from_samples(samples: &mut [Duration]) -> Self {
+        if samples.is_empty() {
+            return Latency::default();
+        }
+        samples.sort_unstable();
+        // Nearest rank: the smallest sample at or above p percent of them
+        let rank = |p: f64| samples[((p / 100.0 * samples.len() as f64).ceil() as usize).clamp(1, samples.len()) - 1];
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        Latency {
+            p50: ms(rank(50.0)),
+            p95: ms(rank(95.0)),
+            p99: ms(rank(99.0)),
+            max: ms(samples[samples.len() - 1]),
+            mean: ms(samples.iter().sum::<Duration>()) / samples.len() as f64,
+        }
     }
 }
 
-/// Track performance metrics
-pub struct PerfStats {
-    pub operation: String,
+/// Results of one kind of operation
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OpSummary {
     pub count: u64,
-    pub total_bytes: u64,
-    pub total_ms: u64,
+    pub errors: u64,
+    pub bytes: u64,
+    pub throughput_mbps: f64,
+    pub ops_per_sec: f64,
+    pub latency_ms: Latency,
 }
 
-impl PerfStats {
-    pub fn new(operation: &str) -> Self {
-        PerfStats {
-            operation: operation.to_string(),
-            count: 0,
-            total_bytes: 0,
-            total_ms: 0,
-        }
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkloadResult {
+    pub elapsed_ms: u64,
+    pub write: OpSummary,
+    pub read: OpSummary,
+}
+
+#[derive(Default)]
+struct OpSamples {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    errors: u64,
+}
+
+impl OpSamples {
+    fn merge(&mut self, other: OpSamples) {
+        self.latencies.extend(other.latencies);
+        self.bytes += other.bytes;
+        self.errors += other.errors;
     }
 
-    pub fn throughput_mbps(&self) -> f64 {
-        if self.total_ms == 0 {
-            0.0
-        } else {
-            (self.total_bytes as f64 / 1_000_000.0) / (self.total_ms as f64 / 1000.0)
+    fn summary(mut self, elapsed: Duration) -> OpSummary {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        OpSummary {
+            count: self.latencies.len() as u64,
+            errors: self.errors,
+            bytes: self.bytes,
+            throughput_mbps: self.bytes as f64 / 1_000_000.0 / secs,
+            ops_per_sec: self.latencies.len() as f64 / secs,
+            latency_ms: Latency::from_samples(&mut self.latencies),
         }
     }
+}
+
+impl Workload {
+    /// Run against `files`; unless the mode only writes, each must already hold `file_size` bytes
+    ///
+    /// Operations are shared out evenly between the threads. Failed
+    /// operations are counted as errors and left out of the latencies.
+    pub fn run(&self, storage: &StorageEngine, files: &[u64]) -> WorkloadResult {
+        let data: Vec<u8> = (0..self.file_size).map(|i| (i % 251) as u8).collect();
+        let threads = self.threads.max(1);
+        let start = Instant::now();
+        let (mut writes, mut reads) = (OpSamples::default(), OpSamples::default());
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|thread| {
+                    let data = &data;
+                    scope.spawn(move || self.run_thread(storage, files, data, thread, threads))
+                })
+                .collect();
+            for worker in workers {
+                let (thread_writes, thread_reads) = worker.join().unwrap();
+                writes.merge(thread_writes);
+                reads.merge(thread_reads);
+            }
+        });
+        let elapsed = start.elapsed();
+        WorkloadResult { elapsed_ms: elapsed.as_millis() as u64, write: writes.summary(elapsed), read: reads.summary(elapsed) }
+    }
 
-    pub fn ops_per_sec(&self) -> f64 {
-        if self.total_ms == 0 {
-            0.0
-        } else {
-            (self.count as f64 * 1000.0) / self.total_ms as f64
+    fn run_thread(&self, storage: &StorageEngine, files: &[u64], data: &[u8], thread: usize, threads: usize) -> (OpSamples, OpSamples) {
+        let mut rng = SeededRng::new(self.seed ^ (thread as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let (mut writes, mut reads) = (OpSamples::default(), OpSamples::default());
+        for op in (thread..self.operations).step_by(threads) {
+            let read = match self.mode {
+                WorkloadMode::Write => false,
+                WorkloadMode::Read => true,
+                WorkloadMode::Mixed => rng.chance(self.read_ratio),
+            };
+            let (ino, offset, len) = match self.pattern {
+                SizePattern::Sequential => (files[op % files.len()], 0, self.file_size),
+                SizePattern::Random => {
+                    let len = 1 + rng.below(self.file_size as u64) as usize;
+                    let offset = rng.below((self.file_size - len) as u64 + 1);
+                    (files[rng.below(files.len() as u64) as usize], offset, len)
+                }
+            };
+            let started = Instant::now();
+            let (result, samples) = if read {
+                (storage.read_range(ino, offset, len as u64).map(drop), &mut reads)
+            } else {
+                (storage.write_file(ino, &data[..len], offset), &mut writes)
+            };
+            match result {
+                Ok(()) => {
+                    samples.latencies.push(started.elapsed());
+                    samples.bytes += len as u64;
+                }
+                Err(e) => {
+                    log::warn!("Benchmark {} of inode {} failed: {}", if read { "read" } else { "write" }, ino, e);
+                    samples.errors += 1;
+                }
+            }
         }
+        (writes, reads)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles_use_the_nearest_rank() {
+        let mut samples: Vec<Duration> = (1..=200).rev().map(Duration::from_millis).collect();
+        let latency = Latency::from_samples(&mut samples);
+        assert_eq!((latency.p50, latency.p95, latency.p99, latency.max), (100.0, 190.0, 198.0, 200.0));
+        assert_eq!(latency.mean, 100.5);
 
-    pub fn report(&self) {
-        println!(
-            "{}: {} ops, {} bytes, {} ms ({:.2} MB/s, {:.0} ops/s)",
-            self.operation,
-            self.count,
-            self.total_bytes,
-            self.total_ms,
-            self.throughput_mbps(),
-            self.ops_per_sec()
-        );
+        let single = Latency::from_samples(&mut [Duration::from_millis(7)]);
+        assert_eq!((single.p50, single.p99), (7.0, 7.0));
+        assert_eq!(Latency::from_samples(&mut []), Latency::default());
     }
 }
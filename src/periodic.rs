@@ -0,0 +1,38 @@
+//! Background threads that run a task at a fixed interval
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Thread calling a task every interval until stopped
+pub struct PeriodicTask {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: JoinHandle<()>,
+}
+
+impl PeriodicTask {
+    /// Call `task` every `interval`, the first time one interval from now
+    pub fn spawn(interval: Duration, mut task: impl FnMut() + Send + 'static) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = Arc::clone(&stop);
+        let thread = thread::spawn(move || loop {
+            let (stopped, wake) = &*signal;
+            let (stopped, _) = wake
+                .wait_timeout_while(stopped.lock().unwrap(), interval, |stopped| !*stopped)
+                .unwrap();
+            if *stopped {
+                return;
+            }
+            drop(stopped);
+            task();
+        });
+        PeriodicTask { stop, thread }
+    }
+
+    /// Wake the thread and wait for a running task to finish
+    pub fn stop(self) {
+        *self.stop.0.lock().unwrap() = true;
+        self.stop.1.notify_all();
+        self.thread.join().ok();
+    }
+}
@@ -0,0 +1,209 @@
+//! Caller identity and POSIX permission checks
+//!
+//! The FUSE layer builds a `RequestContext` from each request and passes it
+//! to the `FilesystemInterface` methods that act on behalf of a caller, so
+//! new inodes belong to whoever created them and `check_access` can decide
+//! whether the caller may read, write or search an inode. Root passes every
+//! check except execute, which needs at least one execute bit, as on Linux.
+//! Denials are `StorageError::PermissionDenied`, surfaced as EACCES.
+
+use anyhow::Result;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::StorageError;
+use crate::metadata::{FileType, Inode};
+
+/// Read permission, as in access(2)'s `R_OK`
+pub const READ: u32 = 4;
+/// Write permission (`W_OK`)
+pub const WRITE: u32 = 2;
+/// Execute permission, or search for a directory (`X_OK`)
+pub const EXECUTE: u32 = 1;
+
+/// Restricted deletion flag: only owners may remove entries from the directory
+const STICKY: u32 = 0o1000;
+/// New entries of a directory with this flag take its group
+const SETGID: u32 = 0o2000;
+
+/// Answers whether the caller belongs to a supplementary group
+pub type GroupCheck = Arc<dyn Fn(u32) -> bool + Send + Sync>;
+
+/// Who an operation is performed for
+#[derive(Clone)]
+pub struct RequestContext {
+    pub uid: u32,
+    pub gid: u32,
+    /// Consulted for groups other than `gid`; without it only `gid` counts
+    groups: Option<GroupCheck>,
+}
+
+impl fmt::Debug for RequestContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestContext").field("uid", &self.uid).field("gid", &self.gid).finish()
+    }
+}
+
+impl RequestContext {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        RequestContext { uid, gid, groups: None }
+    }
+
+    /// Also count the groups `groups` accepts; it is only called when needed
+    pub fn with_groups(mut self, groups: GroupCheck) -> Self {
+        self.groups = Some(groups);
+        self
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Whether the caller is in group `gid`, as primary or supplementary group
+    pub fn in_group(&self, gid: u32) -> bool {
+        gid == self.gid || self.groups.as_ref().is_some_and(|groups| groups(gid))
+    }
+}
+
+fn denied(what: String) -> anyhow::Error {
+    StorageError::PermissionDenied(what).into()
+}
+
+/// Check that `ctx` may access `inode` as `mask` (a combination of `READ`, `WRITE` and `EXECUTE`)
+///
+/// Uses the owner bits if the caller owns the inode, else the group bits if
+/// it is in the inode's group, else the other bits. A mask of 0 only asks
+/// whether the inode exists and always passes.
+pub fn check_access(inode: &Inode, ctx: &RequestContext, mask: u32) -> Result<()> {
+    let mask = mask & (READ | WRITE | EXECUTE);
+    if ctx.is_root() {
+        let executable = inode.file_type == FileType::Directory || inode.mode & 0o111 != 0;
+        if mask & EXECUTE == 0 || executable {
+            return Ok(());
+        }
+    } else {
+        let granted = if ctx.uid == inode.uid {
+            inode.mode >> 6
+        } else if ctx.in_group(inode.gid) {
+            inode.mode >> 3
+        } else {
+            inode.mode
+        } & 0o7;
+        if granted & mask == mask {
+            return Ok(());
+        }
+    }
+    Err(denied(format!("uid {} may not access inode {} with mask {:o}", ctx.uid, inode.ino, mask)))
+}
+
+/// Check that `ctx` may remove `child` from `dir`
+///
+/// Needs write and search permission on the directory. In a sticky directory
+/// only the owner of the entry or of the directory, or root, may remove it.
+pub fn check_remove(dir: &Inode, child: &Inode, ctx: &RequestContext) -> Result<()> {
+    check_access(dir, ctx, WRITE | EXECUTE)?;
+    if dir.mode & STICKY != 0 && !ctx.is_root() && ctx.uid != dir.uid && ctx.uid != child.uid {
+        return Err(denied(format!("uid {} may not remove inode {} from sticky directory {}", ctx.uid, child.ino, dir.ino)));
+    }
+    Ok(())
+}
+
+/// Give a new inode in `parent` to its creator, with permission bits `mode`
+///
+/// In a set-group-ID directory the inode takes the directory's group instead
+/// of the creator's, and new subdirectories keep the flag.
+pub fn set_creator(inode: &mut Inode, parent: &Inode, ctx: &RequestContext, mode: u32) {
+    inode.uid = ctx.uid;
+    inode.gid = ctx.gid;
+    inode.mode = mode & 0o7777;
+    if parent.mode & SETGID != 0 {
+        inode.gid = parent.gid;
+        if inode.file_type == FileType::Directory {
+            inode.mode |= SETGID;
+        }
+    }
+}
+
+/// Whether `err` is a permission denial from this module
+pub fn is_denied(err: &anyhow::Error) -> bool {
+    matches!(StorageError::find(err), Some(StorageError::PermissionDenied(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(mode: u32, uid: u32, gid: u32) -> Inode {
+        let mut inode = Inode::new_file(2, 1, "f".to_string());
+        inode.mode = mode;
+        inode.uid = uid;
+        inode.gid = gid;
+        inode
+    }
+
+    #[test]
+    fn test_owner_group_and_other_bits() {
+        let inode = owned(0o640, 1000, 100);
+        let owner = RequestContext::new(1000, 1000);
+        let member = RequestContext::new(1001, 100);
+        let other = RequestContext::new(1002, 1002);
+        assert!(check_access(&inode, &owner, READ | WRITE).is_ok());
+        assert!(check_access(&inode, &member, READ).is_ok());
+        assert!(check_access(&inode, &member, WRITE).is_err());
+        assert!(check_access(&inode, &other, READ).is_err());
+        assert!(check_access(&inode, &other, 0).is_ok());
+
+        // The owner class applies even when it grants less than the others
+        let inode = owned(0o077, 1000, 100);
+        assert!(is_denied(&check_access(&inode, &owner, READ).unwrap_err()));
+        assert!(check_access(&inode, &other, READ | WRITE | EXECUTE).is_ok());
+    }
+
+    #[test]
+    fn test_supplementary_groups() {
+        let inode = owned(0o060, 1000, 200);
+        let ctx = RequestContext::new(1001, 100);
+        assert!(check_access(&inode, &ctx, READ).is_err());
+        let ctx = ctx.with_groups(Arc::new(|gid| gid == 200));
+        assert!(check_access(&inode, &ctx, READ | WRITE).is_ok());
+    }
+
+    #[test]
+    fn test_root_needs_an_execute_bit() {
+        let root = RequestContext::new(0, 0);
+        assert!(check_access(&owned(0o000, 1000, 1000), &root, READ | WRITE).is_ok());
+        assert!(check_access(&owned(0o644, 1000, 1000), &root, EXECUTE).is_err());
+        assert!(check_access(&owned(0o744, 1000, 1000), &root, EXECUTE).is_ok());
+    }
+
+    #[test]
+    fn test_sticky_directory_removal() {
+        let mut dir = Inode::new_dir(1, 1, String::new());
+        dir.mode = 0o1777;
+        dir.uid = 0;
+        let child = owned(0o644, 1000, 1000);
+        assert!(check_remove(&dir, &child, &RequestContext::new(1000, 1000)).is_ok());
+        assert!(check_remove(&dir, &child, &RequestContext::new(1001, 1001)).is_err());
+        assert!(check_remove(&dir, &child, &RequestContext::new(0, 0)).is_ok());
+        dir.mode = 0o777;
+        assert!(check_remove(&dir, &child, &RequestContext::new(1001, 1001)).is_ok());
+    }
+
+    #[test]
+    fn test_creator_owns_new_inodes() {
+        let mut parent = Inode::new_dir(1, 1, String::new());
+        parent.gid = 500;
+        let ctx = RequestContext::new(1000, 1000);
+        let mut file = Inode::new_file(2, 1, "f".to_string());
+        set_creator(&mut file, &parent, &ctx, 0o100640);
+        assert_eq!((file.uid, file.gid, file.mode), (1000, 1000, 0o640));
+
+        // Set-group-ID directories hand down their group, and the flag to subdirectories
+        parent.mode = 0o2775;
+        set_creator(&mut file, &parent, &ctx, 0o640);
+        assert_eq!((file.gid, file.mode), (500, 0o640));
+        let mut dir = Inode::new_dir(3, 1, "d".to_string());
+        set_creator(&mut dir, &parent, &ctx, 0o755);
+        assert_eq!((dir.gid, dir.mode), (500, 0o2755));
+    }
+}
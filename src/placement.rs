@@ -1,22 +1,312 @@
 use anyhow::{anyhow, Result};
 use uuid::Uuid;
 use std::thread;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 
 use crate::disk::{Disk, DiskHealth};
-use crate::extent::{Extent, FragmentLocation};
+use crate::encryption::FragmentCipher;
+use crate::extent::{Extent, FragmentLocation, RedundancyPolicy};
 use crate::tiering::StorageTier;
 
+/// Share of every disk's capacity that writes leave free, for rebuilds
+pub const DEFAULT_SPACE_RESERVE_PERCENT: u8 = 2;
+
+/// Policy a degraded write may fall back to at the weakest, unless the pool sets its own floor
+pub const DEFAULT_DEGRADED_WRITE_FLOOR: RedundancyPolicy = RedundancyPolicy::Replication { copies: 2 };
+
+/// What a write does when too few disks are writable for its policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DegradedWrites {
+    /// Write under the strongest weaker policy the disks can hold, down to the pool's floor
+    Allow,
+    /// Fail the write
+    #[default]
+    Deny,
+}
+
+impl DegradedWrites {
+    pub fn name(self) -> &'static str {
+        match self {
+            DegradedWrites::Allow => "allow",
+            DegradedWrites::Deny => "deny",
+        }
+    }
+}
+
+/// Disks among `disks` that take new fragments
+pub fn writable_disk_count(disks: &[Arc<Mutex<Disk>>]) -> usize {
+    disks
+        .iter()
+        .filter(|d| matches!(d.lock().unwrap().health, DiskHealth::Healthy | DiskHealth::Suspect))
+        .count()
+}
+
+/// Free bytes of `disk` that new writes may use
+///
+/// Zero for disks that do not take new fragments.
+pub fn writable_bytes(disk: &Disk, reserve_percent: u8) -> u64 {
+    if !matches!(disk.health, DiskHealth::Healthy | DiskHealth::Suspect) {
+        return 0;
+    }
+    let reserve = disk.capacity_bytes / 100 * reserve_percent as u64;
+    disk.capacity_bytes.saturating_sub(disk.used_bytes).saturating_sub(reserve)
+}
+
+/// Fragment bytes promised to writes that passed the space check but have not finished
+///
+/// Checking and reserving happen under one lock, so concurrent writers cannot
+/// all see the same free space and then overflow the disks together.
+#[derive(Debug, Default)]
+pub struct SpaceReservations {
+    reserved: Mutex<u64>,
+}
+
+impl SpaceReservations {
+    /// Reserve room for extents of `(policy, stored bytes)`, or fail with `NoSpace`
+    ///
+    /// The pool must have `reserve_percent` of every disk left afterwards, and
+    /// every extent needs as many disks with room for one of its fragments as
+    /// it has fragments. The room is held until the reservation is dropped;
+    /// fragments already placed by then are counted twice meanwhile, which
+    /// errs on the side of failing early.
+    pub fn reserve(
+        self: &Arc<Self>,
+        disks: &[Arc<Mutex<Disk>>],
+        reserve_percent: u8,
+        extents: &[(RedundancyPolicy, usize)],
+    ) -> Result<SpaceReservation> {
+        let mut reserved = self.reserved.lock().unwrap();
+        let room: Vec<u64> = disks.iter().map(|d| writable_bytes(&d.lock().unwrap(), reserve_percent)).collect();
+        
+        let mut needed = 0u64;
+        for (policy, len) in extents {
+            let fragment_size = (policy.fragment_size(*len) + FragmentCipher::OVERHEAD) as u64;
+            let fitting = room.iter().filter(|&&r| r >= fragment_size).count();
+            if fitting < policy.fragment_count() {
+                return Err(no_space(format!(
+                    "{} needs {} disks with {} bytes free, {} have room",
+                    policy,
+                    policy.fragment_count(),
+                    fragment_size,
+                    fitting
+                )));
+            }
+            needed += fragment_size * policy.fragment_count() as u64;
+        }
+        
+        let available = room.iter().sum::<u64>().saturating_sub(*reserved);
+        if needed > available {
+            return Err(no_space(format!(
+                "write needs {} bytes of fragment space, {} available",
+                needed, available
+            )));
+        }
+        *reserved += needed;
+        Ok(SpaceReservation { owner: Arc::clone(self), bytes: needed })
+    }
+    
+    /// Bytes held by unfinished writes
+    pub fn reserved(&self) -> u64 {
+        *self.reserved.lock().unwrap()
+    }
+}
+
+fn no_space(message: String) -> anyhow::Error {
+    crate::error::StorageError::NoSpace(message).into()
+}
+
+/// Room held for one write, returned when it is dropped
+pub struct SpaceReservation {
+    owner: Arc<SpaceReservations>,
+    bytes: u64,
+}
+
+impl Drop for SpaceReservation {
+    fn drop(&mut self) {
+        *self.owner.reserved.lock().unwrap() -= self.bytes;
+    }
+}
+
+/// Pool usage at which writes start being turned away
+///
+/// Past `high_percent` the mount reclaims space and low-priority writes
+/// (defrag relocations, `benchmark`) fail with ENOSPC. Past
+/// `critical_percent` every write of new data fails too; deletes, truncates
+/// and rebuilds still go through, rebuilds placing into the space reserve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SpaceWatermarks {
+    pub high_percent: u8,
+    pub critical_percent: u8,
+}
+
+impl Default for SpaceWatermarks {
+    fn default() -> Self {
+        SpaceWatermarks { high_percent: 90, critical_percent: 97 }
+    }
+}
+
+impl SpaceWatermarks {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=100).contains(&self.high_percent) || !(1..=100).contains(&self.critical_percent) {
+            return Err(anyhow!("Space watermarks must be between 1% and 100%"));
+        }
+        if self.high_percent > self.critical_percent {
+            return Err(anyhow!(
+                "The high space watermark ({}%) must not be above the critical one ({}%)",
+                self.high_percent,
+                self.critical_percent
+            ));
+        }
+        Ok(())
+    }
+
+    /// Level of a pool with `used_bytes` of `capacity_bytes` in use
+    pub fn level(&self, used_bytes: u64, capacity_bytes: u64) -> SpaceLevel {
+        let at_or_above = |percent: u8| capacity_bytes > 0 && used_bytes as u128 * 100 >= capacity_bytes as u128 * percent as u128;
+        if at_or_above(self.critical_percent) {
+            SpaceLevel::Critical
+        } else if at_or_above(self.high_percent) {
+            SpaceLevel::High
+        } else {
+            SpaceLevel::Normal
+        }
+    }
+}
+
+/// Which watermark pool usage is at or above
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpaceLevel {
+    #[default]
+    Normal,
+    High,
+    Critical,
+}
+
+impl SpaceLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            SpaceLevel::Normal => "normal",
+            SpaceLevel::High => "high",
+            SpaceLevel::Critical => "critical",
+        }
+    }
+}
+
+/// Which writes still go through as the pool fills up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePriority {
+    /// Defrag relocations and benchmarks; stopped at the high watermark
+    Low,
+    /// File writes; stopped at the critical watermark
+    User,
+    /// Rebuilds, repairs and reclamation; never stopped by the watermarks
+    Essential,
+}
+
+impl WritePriority {
+    pub fn allowed_at(self, level: SpaceLevel) -> bool {
+        match self {
+            WritePriority::Low => level == SpaceLevel::Normal,
+            WritePriority::User => level != SpaceLevel::Critical,
+            WritePriority::Essential => true,
+        }
+    }
+}
+
+/// A pool's watermarks and the level its usage was last seen at
+#[derive(Debug, Default)]
+pub struct SpacePressure {
+    watermarks: RwLock<SpaceWatermarks>,
+    level: Mutex<SpaceLevel>,
+    /// Set while a reclamation pass runs, so crossings do not start a second one
+    reclaiming: AtomicBool,
+}
+
+impl SpacePressure {
+    pub fn set_watermarks(&self, watermarks: SpaceWatermarks) {
+        *self.watermarks.write().unwrap() = watermarks;
+    }
+
+    pub fn watermarks(&self) -> SpaceWatermarks {
+        *self.watermarks.read().unwrap()
+    }
+
+    /// Level of the given usage, and the previous level if it changed
+    pub fn update(&self, used_bytes: u64, capacity_bytes: u64) -> (SpaceLevel, Option<SpaceLevel>) {
+        let level = self.watermarks().level(used_bytes, capacity_bytes);
+        let previous = std::mem::replace(&mut *self.level.lock().unwrap(), level);
+        (level, (previous != level).then_some(previous))
+    }
+
+    /// Claim the reclamation pass; false if one is already running
+    pub fn begin_reclaim(&self) -> bool {
+        !self.reclaiming.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn end_reclaim(&self) {
+        self.reclaiming.store(false, Ordering::SeqCst);
+    }
+}
+
+/// What a reclamation pass started by the high watermark freed
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct SpaceReclaimReport {
+    /// Released extents whose fragments were still on disk
+    pub released_extents: usize,
+    /// Bytes of orphaned fragments removed
+    pub orphan_bytes: u64,
+    /// Bytes discarded on raw block devices
+    pub trimmed_bytes: u64,
+    /// Bytes moved off the fast tiers
+    pub demoted_bytes: u64,
+}
+
 /// Placement engine: decides where to place fragments
 pub struct PlacementEngine;
 
 impl PlacementEngine {
+    /// Disks eligible for a new fragment, best first
+    ///
+    /// Failed, Draining and Degraded disks, disks in `exclude` and disks without
+    /// room for the fragment are never returned. Healthy disks come before
+    /// Suspect ones, then disks are ordered by distance from the target tier,
+    /// the faster tier winning a tie, so hot data falls back to the next fastest
+    /// disks when its own tier is full. Within each group the least utilized
+    /// disk comes first, so writes drift toward emptier disks.
+    fn rank_candidates<'a>(
+        disks: impl IntoIterator<Item = &'a Disk>,
+        fragment_size: usize,
+        target_tier: StorageTier,
+        exclude: &[Uuid],
+    ) -> Vec<&'a Disk> {
+        let mut candidates: Vec<&Disk> = disks
+            .into_iter()
+            .filter(|d| matches!(d.health, DiskHealth::Healthy | DiskHealth::Suspect))
+            .filter(|d| !exclude.contains(&d.uuid))
+            .filter(|d| d.capacity_bytes.saturating_sub(d.used_bytes) >= fragment_size as u64)
+            .collect();
+        
+        candidates.sort_by(|a, b| {
+            let rank = |d: &Disk| {
+                let distance = (d.tier.rank() as i8 - target_tier.rank() as i8).unsigned_abs();
+                (d.health == DiskHealth::Suspect, distance, d.tier.rank())
+            };
+            rank(a).cmp(&rank(b)).then(a.utilization().total_cmp(&b.utilization()))
+        });
+        candidates
+    }
+    
     /// Select disks for placing fragments
     /// Ensures:
     /// - Different disks for each fragment of same extent
-    /// - Prefer healthy disks
-    /// - Prefer disks with more free space
-    /// - Filter by target storage tier
+    /// - Never Failed, Draining or Degraded disks; Suspect disks only when
+    ///   there are not enough healthy ones
+    /// - Prefer the target storage tier
+    /// - Prefer the least utilized disks
     pub fn select_disks(
         &self,
         disks: &[MutexGuard<Disk>],
@@ -24,25 +314,7 @@ impl PlacementEngine {
         fragment_size: usize,
         target_tier: StorageTier,
     ) -> Result<Vec<Uuid>> {
-        // Filter healthy disks with enough space and matching tier
-        let mut candidates = disks
-            .iter()
-            .filter(|d| {
-                d.health == DiskHealth::Healthy 
-                && d.has_space(fragment_size as u64)
-                && d.tier == target_tier
-            })
-            .collect::<Vec<_>>();
-        
-        // If no disks in target tier, fall back to any healthy disk
-        if candidates.is_empty() {
-            candidates = disks
-                .iter()
-                .filter(|d| {
-                    d.health == DiskHealth::Healthy && d.has_space(fragment_size as u64)
-                })
-                .collect();
-        }
+        let candidates = Self::rank_candidates(disks.iter().map(|d| &**d), fragment_size, target_tier, &[]);
         
         if candidates.len() < fragment_count {
             return Err(anyhow!(
@@ -53,15 +325,11 @@ impl PlacementEngine {
             ));
         }
         
-        // Sort by free space (descending)
-        candidates.sort_by_key(|d| std::cmp::Reverse(d.as_ref().capacity_bytes - d.as_ref().used_bytes));
-        
-        // Select top N disks
-        Ok(candidates
-            .iter()
-            .take(fragment_count)
-            .map(|d| d.as_ref().uuid)
-            .collect())
+        let selected: Vec<&Disk> = candidates.into_iter().take(fragment_count).collect();
+        if let Some(suspect) = selected.iter().find(|d| d.health == DiskHealth::Suspect) {
+            log::warn!("Placing a fragment on Suspect disk {}: not enough healthy disks", suspect.uuid);
+        }
+        Ok(selected.iter().map(|d| d.uuid).collect())
     }
     
     /// Place fragments of an extent onto disks
@@ -78,11 +346,7 @@ impl PlacementEngine {
         };
         
         // Determine target tier based on extent classification
-        let target_tier = match extent.access_stats.classification {
-            crate::extent::AccessClassification::Hot => StorageTier::Hot,
-            crate::extent::AccessClassification::Warm => StorageTier::Warm,
-            crate::extent::AccessClassification::Cold => StorageTier::Cold,
-        };
+        let target_tier = StorageTier::for_classification(extent.access_stats.classification);
         
         // Select disks (acquire guards briefly to inspect state)
         let disk_guards: Vec<std::sync::MutexGuard<Disk>> = disks.iter().map(|d| d.lock().unwrap()).collect();
@@ -90,55 +354,7 @@ impl PlacementEngine {
         // Drop guards before performing writes so worker threads can lock disks
         drop(disk_guards);
 
-        // Prepare write operations
-        let mut write_ops = Vec::new();
-        for (fragment_index, (fragment_data, disk_uuid)) in
-            fragments.iter().zip(disk_uuids.iter()).enumerate()
-        {
-            // Find the disk index
-            let disk_index = disks
-                .iter()
-                .position(|d| d.lock().unwrap().uuid == *disk_uuid)
-                .ok_or_else(|| anyhow!("Disk not found: {}", disk_uuid))?;
-            
-            write_ops.push((fragment_index, disk_index, fragment_data.clone(), *disk_uuid));
-        }
-        
-        // Execute writes in parallel
-        let mut write_tasks = Vec::new();
-        for (fragment_index, disk_index, fragment_data, disk_uuid) in write_ops {
-            let extent_uuid = extent.uuid;
-            let disk_arc = disks[disk_index].clone();
-            let task = thread::spawn(move || {
-                let mut disk = disk_arc.lock().unwrap();
-                match disk.write_fragment(&extent_uuid, fragment_index, &fragment_data) {
-                    Ok(placement) => Ok((fragment_index, disk_uuid, placement)),
-                    Err(e) => Err((fragment_index, disk_uuid, e)),
-                }
-            });
-            write_tasks.push(task);
-        }
-        
-        // Wait for tasks and collect results
-        let mut written_locations = Vec::new();
-        let mut errors = Vec::new();
-        for task in write_tasks {
-            match task.join() {
-                Ok(Ok((fragment_index, disk_uuid, placement))) => {
-                    written_locations.push(FragmentLocation {
-                        disk_uuid,
-                        fragment_index,
-                        on_device: placement,
-                    });
-                }
-                Ok(Err((fragment_index, disk_uuid, e))) => {
-                    errors.push((fragment_index, disk_uuid, e));
-                }
-                Err(e) => {
-                    log::error!("Task join error: {:?}", e);
-                }
-            }
-        }
+        let (written_locations, errors) = self.write_fragments(&extent.uuid, disks, fragments, &disk_uuids);
         
         // If any errors, rollback successful writes
         if !errors.is_empty() {
@@ -156,6 +372,60 @@ impl PlacementEngine {
         Ok(())
     }
     
+    /// Write fragment `i` to `disk_uuids[i]`, one thread per disk
+    ///
+    /// Returns the locations written and the fragments that failed.
+    fn write_fragments(
+        &self,
+        extent_uuid: &Uuid,
+        disks: &[Arc<Mutex<Disk>>],
+        fragments: &[Vec<u8>],
+        disk_uuids: &[Uuid],
+    ) -> (Vec<FragmentLocation>, Vec<(usize, Uuid, anyhow::Error)>) {
+        let mut written_locations = Vec::new();
+        let mut errors = Vec::new();
+        
+        thread::scope(|scope| {
+            let mut write_tasks = Vec::new();
+            for (fragment_index, (fragment_data, disk_uuid)) in
+                fragments.iter().zip(disk_uuids.iter()).enumerate()
+            {
+                let disk_arc = match disks.iter().find(|d| d.lock().unwrap().uuid == *disk_uuid) {
+                    Some(d) => d,
+                    None => {
+                        errors.push((fragment_index, *disk_uuid, anyhow!("Disk not found: {}", disk_uuid)));
+                        continue;
+                    }
+                };
+                let task = scope.spawn(move || {
+                    let mut disk = disk_arc.lock().unwrap();
+                    let result = disk.write_fragment(extent_uuid, fragment_index, fragment_data);
+                    disk.track_io(&result);
+                    result
+                });
+                write_tasks.push((fragment_index, *disk_uuid, task));
+            }
+            
+            for (fragment_index, disk_uuid, task) in write_tasks {
+                match task.join() {
+                    Ok(Ok(placement)) => written_locations.push(FragmentLocation {
+                        disk_uuid,
+                        fragment_index,
+                        on_device: placement,
+                        checksum: Some(*blake3::hash(&fragments[fragment_index]).as_bytes()),
+                    }),
+                    Ok(Err(e)) => errors.push((fragment_index, disk_uuid, e.into())),
+                    Err(e) => {
+                        log::error!("Task join error: {:?}", e);
+                        errors.push((fragment_index, disk_uuid, anyhow!("Fragment writer panicked")));
+                    }
+                }
+            }
+        });
+        
+        (written_locations, errors)
+    }
+    
     /// Rebuild missing fragments of an extent
     pub fn rebuild_extent(
         &self,
@@ -181,94 +451,69 @@ impl PlacementEngine {
         );
         
         // First, decode the original data
-        let original_data = crate::redundancy::decode(existing_fragments, extent.redundancy)?;
+        let original_data = crate::redundancy::decode(existing_fragments, extent.redundancy, extent.stored_size())?;
         
         // Re-encode to get all fragments
         let all_fragments = crate::redundancy::encode(&original_data, extent.redundancy)?;
         
         // Determine target tier based on extent classification
-        let target_tier = match extent.access_stats.classification {
-            crate::extent::AccessClassification::Hot => StorageTier::Hot,
-            crate::extent::AccessClassification::Warm => StorageTier::Warm,
-            crate::extent::AccessClassification::Cold => StorageTier::Cold,
-        };
+        let target_tier = StorageTier::for_classification(extent.access_stats.classification);
         
         // Place missing fragments on new disks
+        let mut rebuilt_indices: Vec<usize> = Vec::new();
         for missing_index in missing_indices {
             let fragment_data = &all_fragments[missing_index];
             
-            // Find a disk that doesn't already have this extent and matches target tier
-            let mut used_disk_uuids: Vec<Uuid> = extent
+            // Find a disk that doesn't already hold a live fragment of this extent; locations
+            // of lost fragments don't count, so a disk may host its replacement
+            let used_disk_uuids: Vec<Uuid> = extent
                 .fragment_locations
                 .iter()
-                .map(|loc| loc.disk_uuid)
-                .collect();
-            
-            // Also treat draining disks as unusable targets; we'll migrate away from them
-            let draining_uuids: Vec<Uuid> = disks
-                .iter()
-                .filter_map(|d| {
-                    let locked = d.lock().unwrap();
-                    if locked.health == DiskHealth::Draining { Some(locked.uuid) } else { None }
-                })
-                .collect();
-
-            // Remove draining disks from used list so we can place new fragments elsewhere
-            used_disk_uuids.extend(draining_uuids.iter());
-            
-            let available_disks: Vec<&Arc<Mutex<Disk>>> = disks
-                .iter()
-                .filter(|d| {
-                    let d_locked = d.lock().unwrap();
-                    d_locked.health == DiskHealth::Healthy
-                        && !used_disk_uuids.contains(&d_locked.uuid)
-                        && d_locked.has_space(fragment_data.len() as u64)
-                        && d_locked.tier == target_tier
+                .filter(|loc| {
+                    existing_fragments.get(loc.fragment_index).is_none_or(|f| f.is_some())
+                        || rebuilt_indices.contains(&loc.fragment_index)
                 })
+                .map(|loc| loc.disk_uuid)
                 .collect();
             
-            // If no disks in target tier, fall back to any healthy disk
-            let available_disks = if available_disks.is_empty() {
-                disks
-                    .iter()
-                    .filter(|d| {
-                        let d_locked = d.lock().unwrap();
-                        d_locked.health == DiskHealth::Healthy
-                            && !used_disk_uuids.contains(&d_locked.uuid)
-                            && d_locked.has_space(fragment_data.len() as u64)
-                    })
-                    .collect()
-            } else {
-                available_disks
+            // Draining disks are never candidates; we'll migrate away from them
+            let target_disk_uuid = {
+                let disk_guards: Vec<MutexGuard<Disk>> = disks.iter().map(|d| d.lock().unwrap()).collect();
+                let candidates = Self::rank_candidates(
+                    disk_guards.iter().map(|d| &**d),
+                    fragment_data.len(),
+                    target_tier,
+                    &used_disk_uuids,
+                );
+                match candidates.first() {
+                    Some(disk) => disk.uuid,
+                    None => {
+                        return Err(anyhow!(
+                            "No available disk for rebuilding fragment {} (target tier: {:?})",
+                            missing_index, target_tier
+                        ));
+                    }
+                }
             };
-            
-            if available_disks.is_empty() {
-                return Err(anyhow!(
-                    "No available disk for rebuilding fragment {} (target tier: {:?})",
-                    missing_index, target_tier
-                ));
-            }
-            
-            // Use disk with most free space
-            let target_disk_arc = available_disks
-                .iter()
-                .max_by_key(|d| {
-                    let d_locked = d.lock().unwrap();
-                    d_locked.capacity_bytes - d_locked.used_bytes
-                })
-                .unwrap();
-            
-            let target_disk_uuid = target_disk_arc.lock().unwrap().uuid;
+            let target_disk_arc = disks.iter().find(|d| d.lock().unwrap().uuid == target_disk_uuid).unwrap();
             
             // Write fragment
-            let placement = target_disk_arc.lock().unwrap().write_fragment(&extent.uuid, missing_index, fragment_data)?;
+            let placement = {
+                let mut target_disk = target_disk_arc.lock().unwrap();
+                let result = target_disk.write_fragment(&extent.uuid, missing_index, fragment_data);
+                target_disk.track_io(&result);
+                result?
+            };
             
-            // Record location
+            // Record location, replacing the one that was lost
+            extent.fragment_locations.retain(|loc| loc.fragment_index != missing_index);
             extent.fragment_locations.push(FragmentLocation {
                 disk_uuid: target_disk_uuid,
                 fragment_index: missing_index,
                 on_device: placement,
+                checksum: Some(*blake3::hash(fragment_data).as_bytes()),
             });
+            rebuilt_indices.push(missing_index);
             
             log::info!(
                 "Rebuilt fragment {} of extent {} on disk {}",
@@ -319,14 +564,12 @@ impl PlacementEngine {
         // Step 0: Initiate policy change
         extent.initiate_policy_change(new_policy)?;
         
-        // Step 1: Decode with old policy
-        let original_data = crate::redundancy::decode(existing_fragments, old_policy)?;
-        
-        // Step 2: Re-encode with new policy
+        // Steps 1 and 2: Decode with old policy, re-encode with new policy
         let new_fragments = crate::redundancy::reencode(
             existing_fragments,
             old_policy,
             new_policy,
+            extent.stored_size(),
         )?;
         
         log::debug!(
@@ -360,38 +603,26 @@ impl PlacementEngine {
         };
         
         // Determine target tier based on extent classification
-        let target_tier = match extent.access_stats.classification {
-            crate::extent::AccessClassification::Hot => StorageTier::Hot,
-            crate::extent::AccessClassification::Warm => StorageTier::Warm,
-            crate::extent::AccessClassification::Cold => StorageTier::Cold,
-        };
+        let target_tier = StorageTier::for_classification(extent.access_stats.classification);
         
         let disk_guards: Vec<std::sync::MutexGuard<Disk>> = disks.iter().map(|d| d.lock().unwrap()).collect();
         let disk_uuids = self.select_disks(&disk_guards, new_fragments.len(), fragment_size, target_tier)?;
+        // Release guards before re-locking individual disks below
+        drop(disk_guards);
         
-        for (fragment_index, (fragment_data, disk_uuid)) in
-            new_fragments.iter().zip(disk_uuids.iter()).enumerate()
-        {
-            let disk = disks
-                .iter()
-                .find(|d| d.lock().unwrap().uuid == *disk_uuid)
-                .ok_or_else(|| anyhow!("Disk not found: {}", disk_uuid))?;
-            
-            let placement = disk.lock().unwrap().write_fragment(&extent.uuid, fragment_index, fragment_data)?;
-            
-            extent.fragment_locations.push(FragmentLocation {
-                disk_uuid: *disk_uuid,
-                fragment_index,
-                on_device: placement,
-            });
-            
+        let (written_locations, errors) = self.write_fragments(&extent.uuid, disks, &new_fragments, &disk_uuids);
+        if !errors.is_empty() {
+            return Err(anyhow!("Failed to write some fragments: {:?}", errors));
+        }
+        for location in &written_locations {
             log::debug!(
                 "Placed new fragment {} of extent {} on disk {}",
-                fragment_index,
+                location.fragment_index,
                 extent.uuid,
-                disk_uuid
+                location.disk_uuid
             );
         }
+        extent.fragment_locations.extend(written_locations);
         
         // Step 5: Commit policy change
         extent.commit_policy_change(new_policy)?;
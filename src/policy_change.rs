@@ -0,0 +1,236 @@
+//! Progress of redundancy policy changes
+//!
+//! `StorageEngine::change_file_redundancy` converts a file one extent at a
+//! time, in slot order, and commits each extent on its own. Until the change
+//! finishes the file is a mixture in which every extent is wholly on either
+//! the old or the new policy, so reads work at any point.
+//!
+//! The progress file of a change records how far it got. A change cancelled
+//! or cut short by a crash resumes at the next extent when it is started
+//! again, and `policy-status` reads the file from another process.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::extent::RedundancyPolicy;
+
+/// Directory of the progress files, one per inode, in the pool directory
+pub const PROGRESS_DIR: &str = "policy_changes";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyChangeState {
+    /// Being converted, or interrupted by a crash if no engine is running it
+    Running,
+    /// Stopped by `cancel_policy_change` after a whole extent
+    Cancelled,
+    /// Every extent visited; some may have failed
+    Finished,
+}
+
+/// An extent left on the old policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedExtent {
+    pub slot: usize,
+    pub uuid: Uuid,
+    pub error: String,
+}
+
+/// Persisted state of one file's policy change
+///
+/// Slots are visited in order. Extents before `next_slot` are done unless
+/// listed in `failed`, the one at `next_slot` is in progress while
+/// `in_progress` is set, and the rest are pending. Totals count the extents
+/// that needed converting when the change started, each shared extent once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyChangeProgress {
+    pub ino: u64,
+    pub policy: RedundancyPolicy,
+    pub state: PolicyChangeState,
+    pub total_extents: u64,
+    pub total_bytes: u64,
+    pub next_slot: usize,
+    /// Extent at `next_slot` while it is being converted
+    pub in_progress: Option<Uuid>,
+    pub extents_done: u64,
+    pub bytes_converted: u64,
+    pub failed: Vec<FailedExtent>,
+    pub started_at: i64,
+    /// Start of the current run and the bytes converted before it, for the ETA
+    pub resumed_at: i64,
+    pub bytes_before_resume: u64,
+    /// Set in replies from the engine running the change; never saved
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub active: bool,
+}
+
+impl PolicyChangeProgress {
+    pub fn new(ino: u64, policy: RedundancyPolicy, total_extents: u64, total_bytes: u64) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        PolicyChangeProgress {
+            ino,
+            policy,
+            state: PolicyChangeState::Running,
+            total_extents,
+            total_bytes,
+            next_slot: 0,
+            in_progress: None,
+            extents_done: 0,
+            bytes_converted: 0,
+            failed: Vec::new(),
+            started_at: now,
+            resumed_at: now,
+            bytes_before_resume: 0,
+            active: false,
+        }
+    }
+
+    fn path(pool_dir: &Path, ino: u64) -> PathBuf {
+        pool_dir.join(PROGRESS_DIR).join(format!("{}.json", ino))
+    }
+
+    /// The last change started on `ino`, if its progress file is still there
+    pub fn load(pool_dir: &Path, ino: u64) -> Result<Option<Self>> {
+        match fs::read_to_string(Self::path(pool_dir, ino)) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Every saved change, by inode number
+    pub fn list(pool_dir: &Path) -> Result<Vec<Self>> {
+        let dir = pool_dir.join(PROGRESS_DIR);
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut inos: Vec<u64> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_suffix(".json")?.parse().ok())
+            .collect();
+        inos.sort_unstable();
+        let mut changes = Vec::new();
+        for ino in inos {
+            changes.extend(Self::load(pool_dir, ino)?);
+        }
+        Ok(changes)
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        let path = Self::path(pool_dir, self.ino);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    pub fn clear(pool_dir: &Path, ino: u64) -> Result<()> {
+        match fs::remove_file(Self::path(pool_dir, ino)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pick up a saved change to the same policy where it stopped
+    pub fn resume(&mut self) {
+        self.state = PolicyChangeState::Running;
+        self.in_progress = None;
+        self.resumed_at = chrono::Utc::now().timestamp();
+        self.bytes_before_resume = self.bytes_converted;
+    }
+
+    /// `running`, `interrupted`, `cancelled` or `finished`
+    ///
+    /// A change saved as running that no engine reports as active was cut short.
+    pub fn state_label(&self) -> &'static str {
+        match self.state {
+            PolicyChangeState::Running if self.active => "running",
+            PolicyChangeState::Running => "interrupted",
+            PolicyChangeState::Cancelled => "cancelled",
+            PolicyChangeState::Finished => "finished",
+        }
+    }
+
+    /// Seconds left at the rate of the current run; `None` until it has converted something
+    pub fn eta_secs(&self, now: i64) -> Option<u64> {
+        if self.state != PolicyChangeState::Running {
+            return None;
+        }
+        let converted = self.bytes_converted.saturating_sub(self.bytes_before_resume);
+        let elapsed = now.saturating_sub(self.resumed_at).max(1) as u64;
+        if converted == 0 {
+            return None;
+        }
+        let remaining = self.total_bytes.saturating_sub(self.bytes_converted);
+        Some(remaining.saturating_mul(elapsed) / converted)
+    }
+}
+
+impl fmt::Display for PolicyChangeProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} to {}: {}/{} extents, {:.1}/{:.1} MB converted",
+            self.state_label(),
+            self.policy,
+            self.extents_done,
+            self.total_extents,
+            self.bytes_converted as f64 / 1024.0 / 1024.0,
+            self.total_bytes as f64 / 1024.0 / 1024.0
+        )?;
+        if !self.failed.is_empty() {
+            write!(f, ", {} failed", self.failed.len())?;
+        }
+        if let Some(eta) = self.active.then(|| self.eta_secs(chrono::Utc::now().timestamp())).flatten() {
+            write!(f, ", ETA {}:{:02}:{:02}", eta / 3600, eta / 60 % 60, eta % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// Changes running in this engine, with their cancel requests
+#[derive(Default)]
+pub struct RunningPolicyChanges {
+    cancelled: Mutex<HashMap<u64, bool>>,
+}
+
+impl RunningPolicyChanges {
+    /// Register a change of `ino`; false if one is already running
+    pub fn begin(&self, ino: u64) -> bool {
+        let mut cancelled = self.cancelled.lock().unwrap();
+        if cancelled.contains_key(&ino) {
+            return false;
+        }
+        cancelled.insert(ino, false);
+        true
+    }
+
+    pub fn end(&self, ino: u64) {
+        self.cancelled.lock().unwrap().remove(&ino);
+    }
+
+    pub fn is_running(&self, ino: u64) -> bool {
+        self.cancelled.lock().unwrap().contains_key(&ino)
+    }
+
+    /// Ask the change of `ino` to stop after its current extent; false if none is running
+    pub fn cancel(&self, ino: u64) -> bool {
+        match self.cancelled.lock().unwrap().get_mut(&ino) {
+            Some(cancelled) => {
+                *cancelled = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_cancelled(&self, ino: u64) -> bool {
+        self.cancelled.lock().unwrap().get(&ino).copied().unwrap_or(false)
+    }
+}
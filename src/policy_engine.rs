@@ -9,18 +9,33 @@
 // - Safety constraints and operator override
 // - Simulation harness and explainability
 // - Audit trail and observability
+//
+// Policies are kept in the pool's config.json and written as TOML files by
+// operators; `run_policies` evaluates them against every extent of a pool and
+// carries out the approved actions through `PoolExecutor`.
 
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
+use crate::extent::Extent;
+use crate::storage::StorageEngine;
+
+/// File in the pool directory the audit trail is appended to, one JSON entry per line
+pub const POLICY_AUDIT_FILE: &str = "policy_audit.jsonl";
+
 // Time constants for better maintainability
 const SECONDS_PER_HOUR: u64 = 3600;
 const SECONDS_PER_DAY: u64 = 86400;
 const SECONDS_PER_HOUR_F64: f64 = 3600.0;
 
 /// Storage tier for tiering decisions
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum StorageTier {
     NVMe,
     SSD,
@@ -28,8 +43,20 @@ pub enum StorageTier {
     Archive,
 }
 
+impl StorageTier {
+    /// Tier of the pool's disks this stands for; archive data lives on the slowest
+    pub(crate) fn pool_tier(self) -> crate::tiering::StorageTier {
+        match self {
+            StorageTier::NVMe => crate::tiering::StorageTier::Hot,
+            StorageTier::SSD => crate::tiering::StorageTier::Warm,
+            StorageTier::HDD | StorageTier::Archive => crate::tiering::StorageTier::Cold,
+        }
+    }
+}
+
 /// Policy rule for automated decision making
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum PolicyRule {
     /// Trigger when hotness exceeds threshold (0.0-1.0)
     HotnessThreshold { threshold: f64 },
@@ -51,7 +78,8 @@ pub enum PolicyRule {
 }
 
 /// Automated action to execute
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum PolicyAction {
     /// Promote data to cache
     PromoteToCache,
@@ -75,8 +103,23 @@ pub enum PolicyAction {
     NoOp,
 }
 
+impl std::fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyAction::PromoteToCache => write!(f, "promote_to_cache"),
+            PolicyAction::DemoteFromCache => write!(f, "demote_from_cache"),
+            PolicyAction::MigrateTier { target } => write!(f, "migrate_tier to {:?}", target),
+            PolicyAction::Defragment => write!(f, "defragment"),
+            PolicyAction::Trim => write!(f, "trim"),
+            PolicyAction::Rebalance => write!(f, "rebalance"),
+            PolicyAction::NoOp => write!(f, "no_op"),
+        }
+    }
+}
+
 /// Policy execution schedule
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum PolicySchedule {
     /// Evaluate continuously
     Continuous,
@@ -88,20 +131,44 @@ pub enum PolicySchedule {
     Daily { hour: u32 },
     
     /// Evaluate on-demand only
+    #[default]
     Manual,
 }
 
+impl PolicySchedule {
+    /// Whether a mount's hourly policy pass at `hour` (UTC) runs the policy
+    pub fn is_due(&self, hour: u32) -> bool {
+        match self {
+            PolicySchedule::Continuous | PolicySchedule::Hourly => true,
+            PolicySchedule::Daily { hour: daily } => *daily == hour,
+            PolicySchedule::Manual => false,
+        }
+    }
+}
+
 /// Complete policy definition
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Policy {
     pub name: String,
+    #[serde(default)]
     pub rules: Vec<PolicyRule>,
     pub actions: Vec<PolicyAction>,
+    #[serde(default)]
     pub schedule: PolicySchedule,
+    #[serde(default = "default_enabled")]
     pub enabled: bool,
+    #[serde(default = "default_version")]
     pub version: u32,
 }
 
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_version() -> u32 {
+    1
+}
+
 impl Policy {
     /// Create a new policy
     pub fn new(
@@ -120,6 +187,57 @@ impl Policy {
         }
     }
     
+    /// Parse a policy file such as
+    ///
+    /// ```toml
+    /// name = "promote-hot"
+    /// schedule = { type = "hourly" }
+    ///
+    /// [[rules]]
+    /// type = "hotness_threshold"
+    /// threshold = 0.7
+    ///
+    /// [[actions]]
+    /// type = "migrate_tier"
+    /// target = "nvme"
+    /// ```
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        let policy: Policy = toml::from_str(text).context("Invalid policy file")?;
+        policy.validate()?;
+        Ok(policy)
+    }
+    
+    /// Refuse policies that could never match or act
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Policy has no name"));
+        }
+        if self.actions.is_empty() {
+            return Err(anyhow!("Policy '{}' has no actions", self.name));
+        }
+        for rule in &self.rules {
+            let valid = match rule {
+                PolicyRule::HotnessThreshold { threshold } => (0.0..=1.0).contains(threshold),
+                PolicyRule::CacheUtilization { max } | PolicyRule::TierUtilization { max, .. } => (0.0..=1.0).contains(max),
+                PolicyRule::TimeWindow { start_hour, end_hour } => *start_hour < 24 && *end_hour < 24,
+                PolicyRule::AccessFrequency { .. } | PolicyRule::DataAge { .. } => true,
+            };
+            if !valid {
+                return Err(anyhow!(
+                    "Policy '{}': rule {:?} is out of range (fractions 0.0-1.0, hours 0-23)",
+                    self.name,
+                    rule
+                ));
+            }
+        }
+        if let PolicySchedule::Daily { hour } = self.schedule {
+            if hour >= 24 {
+                return Err(anyhow!("Policy '{}': daily schedule hour {} is not 0-23", self.name, hour));
+            }
+        }
+        Ok(())
+    }
+    
     /// Check if all rules match for given state
     pub fn matches(&self, state: &SystemState) -> bool {
         if !self.enabled {
@@ -190,7 +308,7 @@ impl Default for SystemState {
 }
 
 /// Action proposal with metadata
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActionProposal {
     pub policy_name: String,
     pub action: PolicyAction,
@@ -253,6 +371,28 @@ impl WorkloadFeatures {
     }
 }
 
+impl WorkloadFeatures {
+    /// Features of an extent from its access statistics as of `now`
+    pub fn from_extent(extent: &Extent, now: i64) -> Self {
+        let stats = extent.access_stats();
+        let total = stats.read_count + stats.write_count;
+        let age_hours = ((now - stats.created_at) as f64 / SECONDS_PER_HOUR_F64).max(1.0);
+        let mut temporal_pattern = vec![0.0; 24];
+        for (hour, count) in &stats.hourly_accesses {
+            if let Some(bucket) = usize::try_from(now / SECONDS_PER_HOUR as i64 - hour).ok().and_then(|h| temporal_pattern.get_mut(h)) {
+                *bucket += *count as f64;
+            }
+        }
+        Self {
+            access_frequency: total as f64 / age_hours,
+            read_ratio: if total == 0 { 0.5 } else { stats.read_count as f64 / total as f64 },
+            avg_size: extent.size as f64,
+            temporal_pattern,
+            last_access_recency: (now - stats.last_read.max(stats.last_write)).max(0) as f64,
+        }
+    }
+}
+
 impl Default for WorkloadFeatures {
     fn default() -> Self {
         Self {
@@ -343,7 +483,7 @@ impl Default for HotnessPredictor {
 }
 
 /// Policy execution audit entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuditEntry {
     pub timestamp: u64,
     pub policy_name: String,
@@ -355,18 +495,38 @@ pub struct AuditEntry {
 }
 
 /// Impact of policy execution
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ExecutionImpact {
     pub benefit: f64,
     pub cost: f64,
     pub latency_improvement: f64,
     pub resource_usage: f64,
+    /// Bytes the action moved, cached or discarded; 0 when simulated
+    #[serde(default)]
+    pub bytes: u64,
+    /// What the action did, or why it failed
+    #[serde(default)]
+    pub detail: String,
+}
+
+/// What carrying out an action did
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionOutcome {
+    pub bytes: u64,
+    pub detail: String,
+}
+
+/// Carries out approved actions on the subsystems they name
+pub trait ActionExecutor {
+    fn perform(&self, action: &PolicyAction, extent: Uuid) -> anyhow::Result<ActionOutcome>;
 }
 
 /// Policy engine
 pub struct PolicyEngine {
     policies: HashMap<String, Policy>,
     audit_trail: Vec<AuditEntry>,
+    /// Where executed actions are appended, if anywhere
+    audit_log: Option<PathBuf>,
     metrics: PolicyMetrics,
     predictor: HotnessPredictor,
 }
@@ -388,11 +548,18 @@ impl PolicyEngine {
         Self {
             policies: HashMap::new(),
             audit_trail: Vec::new(),
+            audit_log: None,
             metrics: PolicyMetrics::default(),
             predictor: HotnessPredictor::new(),
         }
     }
     
+    /// Also append every audit entry to `path`; see `read_audit_log`
+    pub fn with_audit_log(mut self, path: PathBuf) -> Self {
+        self.audit_log = Some(path);
+        self
+    }
+    
     /// Add a policy
     pub fn add_policy(&mut self, policy: Policy) {
         self.policies.insert(policy.name.clone(), policy);
@@ -435,7 +602,7 @@ impl PolicyEngine {
                         estimated_benefit: benefit,
                         estimated_cost: cost,
                         confidence: 0.75,
-                        reason: format!("Policy '{}' rules matched", policy.name),
+                        reason: format!("Policy '{}' rules matched (hotness {:.2})", policy.name, state.hotness),
                     });
                     
                     self.metrics.actions_proposed += 1;
@@ -454,18 +621,28 @@ impl PolicyEngine {
             cost: proposal.estimated_cost,
             latency_improvement: proposal.estimated_benefit * 0.8,
             resource_usage: proposal.estimated_cost * 0.5,
+            bytes: 0,
+            detail: String::new(),
         }
     }
     
-    /// Execute an action with safety checks
-    pub fn execute(&mut self, proposal: &ActionProposal) -> Result<ExecutionImpact, String> {
-        // Safety checks
+    /// Safety checks a proposal must pass before it is executed
+    pub fn approve(&self, proposal: &ActionProposal) -> Result<(), String> {
         if proposal.estimated_cost > proposal.estimated_benefit * 2.0 {
             return Err("Cost too high relative to benefit".to_string());
         }
+        Ok(())
+    }
+    
+    /// Execute an approved action through `executor`, recording it in the audit trail
+    pub fn execute(&mut self, proposal: &ActionProposal, executor: &dyn ActionExecutor) -> Result<ExecutionImpact, String> {
+        self.approve(proposal)?;
         
-        // Simulate execution
-        let impact = self.simulate(proposal);
+        let result = executor.perform(&proposal.action, proposal.target_extent);
+        let impact = match &result {
+            Ok(outcome) => ExecutionImpact { bytes: outcome.bytes, detail: outcome.detail.clone(), ..self.simulate(proposal) },
+            Err(e) => ExecutionImpact { detail: format!("{:#}", e), ..Default::default() },
+        };
         
         // Record audit entry
         let entry = AuditEntry {
@@ -476,12 +653,21 @@ impl PolicyEngine {
             policy_name: proposal.policy_name.clone(),
             action: proposal.action.clone(),
             target: proposal.target_extent,
-            success: true,
+            success: result.is_ok(),
             reason: proposal.reason.clone(),
             impact: impact.clone(),
         };
-        
+        if let Some(path) = &self.audit_log {
+            if let Err(e) = append_audit_entry(path, &entry) {
+                log::warn!("Failed to record policy action in {:?}: {:#}", path, e);
+            }
+        }
         self.audit_trail.push(entry);
+        
+        if result.is_err() {
+            self.metrics.actions_failed += 1;
+            return Err(impact.detail);
+        }
         self.metrics.actions_executed += 1;
         self.metrics.total_benefit += impact.benefit;
         self.metrics.total_cost += impact.cost;
@@ -518,9 +704,13 @@ impl PolicyEngine {
                 let cost = 5.0;
                 (benefit, cost)
             }
-            PolicyAction::MigrateTier { .. } => {
-                // Benefit based on hotness mismatch, high cost
-                let benefit = state.hotness * 80.0;
+            PolicyAction::MigrateTier { target } => {
+                // Benefit based on hotness mismatch, high cost: hot data gains
+                // from fast tiers, cold data frees them by moving down
+                let benefit = match target {
+                    StorageTier::NVMe | StorageTier::SSD => state.hotness * 80.0,
+                    StorageTier::HDD | StorageTier::Archive => (1.0 - state.hotness) * 80.0,
+                };
                 let cost = 30.0;
                 (benefit, cost)
             }
@@ -547,10 +737,192 @@ impl Default for PolicyEngine {
     }
 }
 
+fn append_audit_entry(path: &Path, entry: &AuditEntry) -> anyhow::Result<()> {
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Audit entries appended to `path`, oldest first; none if it does not exist
+///
+/// A line cut short by a crash is skipped.
+pub fn read_audit_log(path: &Path) -> anyhow::Result<Vec<AuditEntry>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", path)),
+    };
+    let mut entries = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => log::warn!("Skipping unreadable entry in {:?}: {}", path, e),
+        }
+    }
+    Ok(entries)
+}
+
+/// What a policy run proposed for one extent and what became of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProposalOutcome {
+    pub proposal: ActionProposal,
+    /// Why the safety checks turned the proposal down
+    pub refused: Option<String>,
+    /// Set once the proposal was carried out
+    pub impact: Option<ExecutionImpact>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PolicyRun {
+    pub dry_run: bool,
+    pub extents: u64,
+    pub outcomes: Vec<ProposalOutcome>,
+}
+
+/// Carries out actions on a pool through its storage engine
+pub struct PoolExecutor<'a> {
+    pub storage: &'a StorageEngine,
+    /// Only a mounted pool has a data cache worth filling
+    pub mounted: bool,
+}
+
+impl ActionExecutor for PoolExecutor<'_> {
+    fn perform(&self, action: &PolicyAction, extent: Uuid) -> anyhow::Result<ActionOutcome> {
+        let moved = |(fragments, bytes): (u64, u64), what: &str| ActionOutcome { bytes, detail: format!("{} fragments {}", fragments, what) };
+        match action {
+            PolicyAction::PromoteToCache | PolicyAction::DemoteFromCache if !self.mounted => {
+                Err(anyhow!("the data cache only exists while the pool is mounted"))
+            }
+            PolicyAction::PromoteToCache => {
+                let bytes = self.storage.cache_extent(&extent)?;
+                Ok(ActionOutcome { bytes, detail: "cached".to_string() })
+            }
+            PolicyAction::DemoteFromCache => {
+                let cached = self.storage.evict_cached_extent(&extent);
+                Ok(ActionOutcome { bytes: 0, detail: if cached { "evicted" } else { "was not cached" }.to_string() })
+            }
+            PolicyAction::MigrateTier { target } => {
+                let tier = target.pool_tier();
+                Ok(moved(self.storage.place_extent_on_tier(&extent, tier)?, &format!("moved to the {} tier", tier)))
+            }
+            PolicyAction::Defragment => Ok(moved(self.storage.defragment_extent(&extent)?, "spread onto disks of their own")),
+            PolicyAction::Rebalance => Ok(moved(self.storage.rebalance_extent(&extent)?, "moved to emptier disks")),
+            PolicyAction::Trim => {
+                let extent = self.storage.metadata().read().unwrap().load_extent(&extent)?;
+                let mut disks: Vec<Uuid> = extent.fragment_locations.iter().map(|l| l.disk_uuid).collect();
+                disks.sort();
+                disks.dedup();
+                let mut bytes = 0;
+                for disk in &disks {
+                    bytes += self.storage.trim_free_space(*disk)?;
+                }
+                Ok(ActionOutcome { bytes, detail: format!("discarded free space on {} disks", disks.len()) })
+            }
+            PolicyAction::NoOp => Ok(ActionOutcome { bytes: 0, detail: "nothing to do".to_string() }),
+        }
+    }
+}
+
+/// Evaluate `policies` against every extent of the pool and, unless `dry_run`,
+/// execute the proposals that pass the safety checks
+///
+/// Hotness comes from each extent's access statistics; cache and tier
+/// utilization are taken once at the start. Executed actions are appended
+/// to `POLICY_AUDIT_FILE` in the pool directory.
+pub fn run_policies(storage: &StorageEngine, policies: &[Policy], dry_run: bool, mounted: bool) -> anyhow::Result<PolicyRun> {
+    if !storage.is_read_only() {
+        if let Err(e) = storage.flush_access_stats() {
+            log::warn!("Evaluating policies without unflushed reads: {}", e);
+        }
+    }
+    let pool_dir = storage.metadata().read().unwrap().pool_dir().to_path_buf();
+    let mut engine = PolicyEngine::new().with_audit_log(pool_dir.join(POLICY_AUDIT_FILE));
+    for policy in policies {
+        engine.add_policy(policy.clone());
+    }
+    
+    let mut tier_utilization = HashMap::new();
+    for status in storage.tier_status()?.iter().filter(|s| s.capacity_bytes > 0) {
+        for tier in [StorageTier::NVMe, StorageTier::SSD, StorageTier::HDD, StorageTier::Archive] {
+            if tier.pool_tier() == status.tier {
+                tier_utilization.insert(tier, status.utilization_percent() / 100.0);
+            }
+        }
+    }
+    let cache_utilization = storage.cache_utilization();
+    // Collected first: executing an action takes the metadata lock
+    let extents: Vec<Extent> = storage.metadata().read().unwrap().iter_extents()?.filter_map(Result::ok).collect();
+    let executor = PoolExecutor { storage, mounted };
+    let now = chrono::Utc::now().timestamp();
+    
+    let mut run = PolicyRun { dry_run, extents: extents.len() as u64, outcomes: Vec::new() };
+    for extent in &extents {
+        let stats = extent.access_stats();
+        let (hotness, _) = engine.predictor().predict(&WorkloadFeatures::from_extent(extent, now));
+        let state = SystemState {
+            hotness,
+            cache_utilization,
+            tier_utilization: tier_utilization.clone(),
+            access_count: stats.read_count + stats.write_count,
+            age_seconds: (now - stats.created_at).max(0) as u64,
+        };
+        for proposal in engine.evaluate_policies(&state, extent.uuid) {
+            let refused = engine.approve(&proposal).err();
+            let (impact, error) = if dry_run || refused.is_some() {
+                (None, None)
+            } else {
+                match engine.execute(&proposal, &executor) {
+                    Ok(impact) => (Some(impact), None),
+                    Err(e) => (None, Some(e)),
+                }
+            };
+            run.outcomes.push(ProposalOutcome { proposal, refused, impact, error });
+        }
+    }
+    Ok(run)
+}
+
+/// Run the pool's policies whose schedule is due at `hour` (UTC), for a mount's hourly pass
+///
+/// Policies are read from config.json each time, so `policy add` takes
+/// effect without a remount. Returns `None` when no policy was due.
+pub fn run_scheduled_policies(storage: &StorageEngine, hour: u32) -> anyhow::Result<Option<PolicyRun>> {
+    let pool_dir = storage.metadata().read().unwrap().pool_dir().to_path_buf();
+    let config = crate::config::PoolConfig::load(&pool_dir)?;
+    let due: Vec<Policy> = config.policies.into_iter().filter(|p| p.enabled && p.schedule.is_due(hour)).collect();
+    if due.is_empty() {
+        return Ok(None);
+    }
+    run_policies(storage, &due, false, true).map(Some)
+}
+
+impl PolicyRun {
+    /// Proposals carried out, refused by the safety checks, and failed
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let executed = self.outcomes.iter().filter(|o| o.impact.is_some()).count();
+        let refused = self.outcomes.iter().filter(|o| o.refused.is_some()).count();
+        let failed = self.outcomes.iter().filter(|o| o.error.is_some()).count();
+        (executed, refused, failed)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    /// Carries out every action, failing defragmentation
+    struct Applied;
+    
+    impl ActionExecutor for Applied {
+        fn perform(&self, action: &PolicyAction, _extent: Uuid) -> anyhow::Result<ActionOutcome> {
+            match action {
+                PolicyAction::Defragment => Err(anyhow!("disk unavailable")),
+                _ => Ok(ActionOutcome { bytes: 4096, detail: action.to_string() }),
+            }
+        }
+    }
+    
     #[test]
     fn test_policy_creation() {
         let policy = Policy::new(
@@ -650,7 +1022,7 @@ mod tests {
             reason: "Test execution".to_string(),
         };
         
-        let impact = engine.execute(&proposal).unwrap();
+        let impact = engine.execute(&proposal, &Applied).unwrap();
         assert!(impact.benefit > 0.0);
         assert_eq!(engine.audit_trail().len(), 1);
         assert_eq!(engine.metrics().actions_executed, 1);
@@ -670,7 +1042,7 @@ mod tests {
             reason: "High cost operation".to_string(),
         };
         
-        let result = engine.execute(&proposal);
+        let result = engine.execute(&proposal, &Applied);
         assert!(result.is_err());
     }
     
@@ -721,11 +1093,84 @@ mod tests {
             reason: "Audit trail test".to_string(),
         };
         
-        engine.execute(&proposal).unwrap();
+        engine.execute(&proposal, &Applied).unwrap();
         
         let audit = engine.audit_trail();
         assert_eq!(audit.len(), 1);
         assert_eq!(audit[0].policy_name, "audit_test");
         assert!(audit[0].success);
     }
+    
+    #[test]
+    fn test_policy_file_parses_and_is_validated() {
+        let policy = Policy::from_toml(
+            r#"
+            name = "demote-cold"
+            schedule = { type = "daily", hour = 2 }
+
+            [[rules]]
+            type = "data_age"
+            min_days = 7
+
+            [[rules]]
+            type = "tier_utilization"
+            tier = "nvme"
+            max = 0.85
+
+            [[actions]]
+            type = "migrate_tier"
+            target = "hdd"
+
+            [[actions]]
+            type = "trim"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.rules[1], PolicyRule::TierUtilization { tier: StorageTier::NVMe, max: 0.85 });
+        assert_eq!(policy.actions, vec![PolicyAction::MigrateTier { target: StorageTier::HDD }, PolicyAction::Trim]);
+        assert_eq!(policy.schedule, PolicySchedule::Daily { hour: 2 });
+        assert!(policy.enabled && policy.schedule.is_due(2) && !policy.schedule.is_due(3));
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(serde_json::from_str::<Policy>(&json).unwrap(), policy);
+        
+        assert!(Policy::from_toml("name = \"none\"\nactions = []").is_err());
+        assert!(Policy::from_toml("name = \"x\"\n[[rules]]\ntype = \"hotness_threshold\"\nthreshold = 7.0\n[[actions]]\ntype = \"trim\"").is_err());
+        assert!(Policy::from_toml("name = \"x\"\n[[actions]]\ntype = \"teleport\"").is_err());
+    }
+    
+    #[test]
+    fn test_executed_actions_are_persisted_to_the_audit_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(POLICY_AUDIT_FILE);
+        let mut engine = PolicyEngine::new().with_audit_log(path.clone());
+        let proposal = |action| ActionProposal {
+            policy_name: "persisted".to_string(),
+            action,
+            target_extent: Uuid::new_v4(),
+            estimated_benefit: 50.0,
+            estimated_cost: 10.0,
+            confidence: 0.8,
+            reason: "test".to_string(),
+        };
+        
+        assert_eq!(engine.execute(&proposal(PolicyAction::Trim), &Applied).unwrap().bytes, 4096);
+        assert!(engine.execute(&proposal(PolicyAction::Defragment), &Applied).is_err());
+        assert_eq!(engine.metrics().actions_failed, 1);
+        
+        let entries = read_audit_log(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].success && entries[0].action == PolicyAction::Trim);
+        assert!(!entries[1].success && entries[1].impact.detail.contains("disk unavailable"));
+        assert!(read_audit_log(&dir.path().join("missing")).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_demotion_benefits_cold_data() {
+        let engine = PolicyEngine::new();
+        let cold = SystemState { hotness: 0.1, ..SystemState::default() };
+        let (benefit, cost) = engine.estimate_impact(&PolicyAction::MigrateTier { target: StorageTier::HDD }, &cold);
+        assert!(benefit * 2.0 >= cost);
+        let (benefit, cost) = engine.estimate_impact(&PolicyAction::MigrateTier { target: StorageTier::NVMe }, &cold);
+        assert!(benefit * 2.0 < cost);
+    }
 }
@@ -0,0 +1,226 @@
+//! Pool-wide lock against concurrent mounts and offline mutations
+//!
+//! `pool.lock` in the pool directory is held with an exclusive `flock` by a
+//! mount for its whole lifetime and by commands that rewrite metadata or
+//! fragments offline, so two of them never work on the same pool at once.
+//! Read-only commands do not take it. The kernel releases the lock when its
+//! holder exits however it exits; the PID and start time written into the
+//! file only describe the holder in the error of whoever is turned away.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Lock file, relative to the pool directory
+pub const POOL_LOCK_FILE: &str = "pool.lock";
+
+/// Process holding the pool lock, as recorded in the lock file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    /// Start time of the process in clock ticks after boot, which tells a
+    /// live holder from a later process that reused its PID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_ticks: Option<u64>,
+    pub started_at: DateTime<Utc>,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mountpoint: Option<PathBuf>,
+}
+
+impl LockHolder {
+    fn current(command: &str, mountpoint: Option<&Path>) -> Self {
+        let pid = std::process::id();
+        LockHolder {
+            pid,
+            start_ticks: process_start_ticks(pid),
+            started_at: Utc::now(),
+            command: command.to_string(),
+            mountpoint: mountpoint.map(Path::to_path_buf),
+        }
+    }
+
+    /// Whether the recorded process is still running
+    fn is_alive(&self) -> bool {
+        match (self.start_ticks, process_start_ticks(self.pid)) {
+            (Some(recorded), Some(current)) => recorded == current,
+            (Some(_), None) => false,
+            (None, _) => (unsafe { libc::kill(self.pid as libc::pid_t, 0) }) == 0,
+        }
+    }
+}
+
+/// The pool lock is held by another process
+#[derive(Debug)]
+pub struct PoolBusy {
+    /// Recorded holder, if it is still running
+    pub holder: Option<LockHolder>,
+}
+
+impl PoolBusy {
+    /// Whether the holder is a mount, which takes requests on its control socket
+    pub fn is_mount(&self) -> bool {
+        self.holder.as_ref().is_some_and(|holder| holder.mountpoint.is_some())
+    }
+}
+
+impl fmt::Display for PoolBusy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.holder {
+            Some(LockHolder { pid, mountpoint: Some(mountpoint), started_at, .. }) => write!(
+                f,
+                "pool is in use by PID {} (mounted at {} since {})",
+                pid,
+                mountpoint.display(),
+                started_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            Some(LockHolder { pid, command, started_at, .. }) => write!(
+                f,
+                "pool is in use by PID {} (`{}` since {})",
+                pid,
+                command,
+                started_at.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            None => write!(f, "pool is in use by another process"),
+        }
+    }
+}
+
+impl std::error::Error for PoolBusy {}
+
+/// Exclusive hold on a pool, released when dropped
+#[derive(Debug)]
+pub struct PoolLock {
+    file: File,
+}
+
+impl PoolLock {
+    /// Lock `pool_dir` for `command`, or fail at once with a [`PoolBusy`]
+    /// error if another process holds it
+    pub fn exclusive(pool_dir: &Path, command: &str, mountpoint: Option<&Path>) -> Result<Self> {
+        let path = pool_dir.join(POOL_LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open pool lock {:?}", path))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(err).with_context(|| format!("Failed to lock pool {:?}", pool_dir));
+            }
+            // A holder that exited without clearing its record left it stale
+            let holder = read_holder(&mut file).filter(LockHolder::is_alive);
+            return Err(PoolBusy { holder }.into());
+        }
+
+        let holder = LockHolder::current(command, mountpoint);
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        file.sync_data()?;
+        Ok(PoolLock { file })
+    }
+
+    /// The live process holding the lock on `pool_dir`, if any
+    ///
+    /// None also when the holder left no record of itself.
+    pub fn holder(pool_dir: &Path) -> Result<Option<LockHolder>> {
+        let path = pool_dir.join(POOL_LOCK_FILE);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open pool lock {:?}", path)),
+        };
+        // Granted, and released with the file, only if nobody holds it
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+            return Ok(None);
+        }
+        Ok(read_holder(&mut file).filter(LockHolder::is_alive))
+    }
+}
+
+impl Drop for PoolLock {
+    fn drop(&mut self) {
+        // Cleared while still locked; the lock goes with the descriptor
+        let _ = self.file.set_len(0);
+    }
+}
+
+fn read_holder(file: &mut File) -> Option<LockHolder> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    serde_json::from_str(contents.trim()).ok()
+}
+
+/// Field 22 of /proc/<pid>/stat; None where there is no procfs
+fn process_start_ticks(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in field 2 may contain spaces and parentheses
+    let rest = &stat[stat.rfind(')')? + 1..];
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn busy(err: anyhow::Error) -> PoolBusy {
+        err.downcast::<PoolBusy>().expect("PoolBusy error")
+    }
+
+    #[test]
+    fn test_second_holder_is_turned_away_with_the_first_ones_pid_and_mountpoint() {
+        let pool = TempDir::new().unwrap();
+        let mountpoint = Path::new("/mnt/pool");
+        let lock = PoolLock::exclusive(pool.path(), "mount", Some(mountpoint)).unwrap();
+
+        let err = busy(PoolLock::exclusive(pool.path(), "rebuild", None).unwrap_err());
+        assert!(err.is_mount());
+        let holder = err.holder.as_ref().unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        assert_eq!(holder.command, "mount");
+        let message = err.to_string();
+        assert!(message.starts_with(&format!("pool is in use by PID {} (mounted at /mnt/pool", std::process::id())));
+
+        assert_eq!(PoolLock::holder(pool.path()).unwrap().unwrap().mountpoint.as_deref(), Some(mountpoint));
+        drop(lock);
+        assert!(PoolLock::holder(pool.path()).unwrap().is_none());
+        let lock = PoolLock::exclusive(pool.path(), "rebuild", None).unwrap();
+        let err = busy(PoolLock::exclusive(pool.path(), "import", None).unwrap_err());
+        assert!(!err.is_mount());
+        assert!(err.to_string().contains("`rebuild`"));
+        drop(lock);
+    }
+
+    #[test]
+    fn test_record_of_an_exited_holder_is_not_reported() {
+        let pool = TempDir::new().unwrap();
+        let stale = LockHolder {
+            pid: std::process::id(),
+            start_ticks: process_start_ticks(std::process::id()).map(|ticks| ticks + 1),
+            started_at: Utc::now(),
+            command: "mount".to_string(),
+            mountpoint: Some(PathBuf::from("/mnt/gone")),
+        };
+        std::fs::write(pool.path().join(POOL_LOCK_FILE), serde_json::to_string(&stale).unwrap()).unwrap();
+
+        // Held by someone who never wrote a record of their own
+        let file = File::open(pool.path().join(POOL_LOCK_FILE)).unwrap();
+        assert_eq!(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) }, 0);
+        let err = busy(PoolLock::exclusive(pool.path(), "rebuild", None).unwrap_err());
+        assert!(err.holder.is_none());
+        assert_eq!(err.to_string(), "pool is in use by another process");
+        drop(file);
+
+        let _lock = PoolLock::exclusive(pool.path(), "rebuild", None).unwrap();
+    }
+}
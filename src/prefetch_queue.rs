@@ -0,0 +1,142 @@
+//! Read-ahead requests for sequential readers
+//!
+//! `DynamicFS::read` asks for the extents following a sequential reader's
+//! position; a worker owned by `StorageEngine` decodes them into the data
+//! cache before the reader gets there.
+//!
+//! Each inode has at most one live request, the newest: a later request for
+//! the same file supersedes the one queued or being worked on, and a random
+//! seek cancels it, so the worker never spends disk time on a stream that has
+//! moved elsewhere.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+
+/// Extents of one file to decode ahead of its reader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefetchRequest {
+    pub ino: u64,
+    /// File offset whose extent is fetched first
+    pub offset: u64,
+    /// Number of extents to fetch from there
+    pub extents: usize,
+    id: u64,
+}
+
+#[derive(Default)]
+struct QueueState {
+    queue: VecDeque<PrefetchRequest>,
+    /// The live request of each inode, queued or in progress
+    live: HashMap<u64, u64>,
+    next_id: u64,
+    /// Set while a worker serves the queue; requests are ignored otherwise
+    open: bool,
+}
+
+/// Latest read-ahead request per inode, in arrival order
+///
+/// Starts closed: requests are dropped until `open`, so engines without a
+/// read-ahead worker never accumulate them.
+#[derive(Default)]
+pub struct PrefetchQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+impl PrefetchQueue {
+    /// Ask for `extents` extents of `ino` starting at the one holding `offset`
+    pub fn request(&self, ino: u64, offset: u64, extents: usize) {
+        let mut state = self.state.lock().unwrap();
+        if !state.open || extents == 0 {
+            return;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queue.retain(|queued| queued.ino != ino);
+        state.queue.push_back(PrefetchRequest { ino, offset, extents, id });
+        state.live.insert(ino, id);
+        self.changed.notify_all();
+    }
+
+    /// Drop the live request of `ino`; a worker on it stops at the next extent
+    pub fn cancel(&self, ino: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.retain(|queued| queued.ino != ino);
+        state.live.remove(&ino);
+    }
+
+    /// Whether `request` is still wanted
+    pub fn is_live(&self, request: &PrefetchRequest) -> bool {
+        self.state.lock().unwrap().live.get(&request.ino) == Some(&request.id)
+    }
+
+    /// Accept requests, for a worker about to call `next`
+    pub fn open(&self) {
+        self.state.lock().unwrap().open = true;
+    }
+
+    /// Block until a request is available; returns `None` once shut down
+    ///
+    /// Every request returned must be acknowledged with `complete`.
+    pub fn next(&self) -> Option<PrefetchRequest> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if !state.open {
+                return None;
+            }
+            if let Some(request) = state.queue.pop_front() {
+                return Some(request);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Mark a request returned by `next` as done
+    pub fn complete(&self, request: &PrefetchRequest) {
+        let mut state = self.state.lock().unwrap();
+        if state.live.get(&request.ino) == Some(&request.id) {
+            state.live.remove(&request.ino);
+        }
+    }
+
+    /// Wake the worker and refuse new requests
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.open = false;
+        state.queue.clear();
+        state.live.clear();
+        self.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newer_request_supersedes_and_cancel_drops() {
+        let queue = PrefetchQueue::default();
+        queue.request(3, 0, 2);
+        queue.open();
+        queue.request(1, 0, 2);
+        queue.request(2, 0, 2);
+        let first = queue.next().unwrap();
+        assert_eq!((first.ino, first.offset), (1, 0));
+
+        // The worker notices a newer request for the same file and stops
+        queue.request(1, 4 << 20, 2);
+        assert!(!queue.is_live(&first));
+        queue.complete(&first);
+
+        // A seek on file 2 drops its queued request
+        queue.cancel(2);
+        let second = queue.next().unwrap();
+        assert_eq!((second.ino, second.offset), (1, 4 << 20));
+        assert!(queue.is_live(&second));
+        queue.cancel(1);
+        assert!(!queue.is_live(&second));
+
+        queue.shutdown();
+        assert!(queue.next().is_none());
+    }
+}
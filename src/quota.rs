@@ -0,0 +1,110 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Byte and inode limits on the tree under a directory, with its current usage
+///
+/// Usage covers every file and directory below `dir_ino`, not the directory
+/// itself. Bytes are logical file sizes, so sparse regions count. Quotas nest:
+/// a change is charged to every quota on the path up to the root and each
+/// limit is enforced on its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub dir_ino: u64,
+    pub bytes_limit: Option<u64>,
+    pub inodes_limit: Option<u64>,
+    pub bytes_used: u64,
+    pub inodes_used: u64,
+}
+
+impl Quota {
+    pub fn new(dir_ino: u64, bytes_limit: Option<u64>, inodes_limit: Option<u64>) -> Self {
+        Quota {
+            dir_ino,
+            bytes_limit,
+            inodes_limit,
+            bytes_used: 0,
+            inodes_used: 0,
+        }
+    }
+
+    /// Apply a usage change, failing with `QuotaExceeded` if growth passes a limit
+    ///
+    /// Shrinking always succeeds, so a tree left over a lowered limit can
+    /// still be cleaned up.
+    pub fn charge(&mut self, bytes: i64, inodes: i64) -> Result<()> {
+        let bytes_used = self.bytes_used.saturating_add_signed(bytes);
+        let inodes_used = self.inodes_used.saturating_add_signed(inodes);
+        if let Some(limit) = self.bytes_limit.filter(|&limit| bytes > 0 && bytes_used > limit) {
+            return Err(exceeded(format!(
+                "Byte quota of directory {} exceeded: {} of {} bytes",
+                self.dir_ino, bytes_used, limit
+            )));
+        }
+        if let Some(limit) = self.inodes_limit.filter(|&limit| inodes > 0 && inodes_used > limit) {
+            return Err(exceeded(format!(
+                "Inode quota of directory {} exceeded: {} of {} inodes",
+                self.dir_ino, inodes_used, limit
+            )));
+        }
+        self.bytes_used = bytes_used;
+        self.inodes_used = inodes_used;
+        Ok(())
+    }
+}
+
+fn exceeded(message: String) -> anyhow::Error {
+    crate::error::StorageError::QuotaExceeded(message).into()
+}
+
+/// Parse a quota limit such as `500G`, `1M` or `4096`
+///
+/// Suffixes K, M, G, T and P are powers of 1024, for inode counts as well as bytes.
+pub fn parse_limit(limit: &str) -> Result<u64> {
+    let limit = limit.trim();
+    let split = limit.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(limit.len());
+    let (number, suffix) = limit.split_at(split);
+    let shift = match suffix.trim().to_ascii_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        "P" => 50,
+        _ => return Err(anyhow!("Invalid quota limit: {}. Use a number with an optional K, M, G, T or P suffix", limit)),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid quota limit: {}. Use a number with an optional K, M, G, T or P suffix", limit))?;
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_charge_and_limit_parsing() {
+        let mut quota = Quota::new(7, Some(100), Some(2));
+        quota.charge(60, 1).unwrap();
+        quota.charge(40, 1).unwrap();
+        let err = quota.charge(1, 0).unwrap_err();
+        assert!(matches!(err.downcast_ref::<crate::error::StorageError>(), Some(crate::error::StorageError::QuotaExceeded(_))));
+        assert!(quota.charge(0, 1).is_err());
+        assert_eq!((quota.bytes_used, quota.inodes_used), (100, 2));
+
+        // Lowering the limit below usage still lets the tree shrink
+        quota.bytes_limit = Some(10);
+        quota.charge(-50, -1).unwrap();
+        assert_eq!((quota.bytes_used, quota.inodes_used), (50, 1));
+        quota.charge(-500, -5).unwrap();
+        assert_eq!((quota.bytes_used, quota.inodes_used), (0, 0));
+
+        assert_eq!(parse_limit("4096").unwrap(), 4096);
+        assert_eq!(parse_limit("500G").unwrap(), 500 << 30);
+        assert_eq!(parse_limit("1M").unwrap(), 1 << 20);
+        assert_eq!(parse_limit("1.5k").unwrap(), 1536);
+        assert_eq!(parse_limit("2GiB").unwrap(), 2 << 30);
+        assert!(parse_limit("10X").is_err());
+        assert!(parse_limit("G").is_err());
+    }
+}
@@ -0,0 +1,518 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::disk::{Disk, DiskHealth};
+use crate::extent::FragmentLocation;
+use crate::io_scheduler::IoClass;
+use crate::metadata::MetadataManager;
+
+/// Progress file kept in the pool directory while a rebalance is unfinished
+pub const PROGRESS_FILE: &str = "rebalance.json";
+
+/// Options for a rebalance run
+#[derive(Debug, Clone)]
+pub struct RebalanceConfig {
+    /// Stop once the utilization spread between healthy disks is below this many percentage points
+    pub target_spread: f64,
+    /// Throttle fragment copies to this many bytes per second
+    pub max_bytes_per_sec: Option<u64>,
+    /// Plan the moves without touching any disk
+    pub dry_run: bool,
+}
+
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        RebalanceConfig {
+            target_spread: 10.0,
+            max_bytes_per_sec: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// One fragment moving from one disk to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FragmentMove {
+    pub extent_uuid: Uuid,
+    pub fragment_index: usize,
+    pub from_disk: Uuid,
+    pub to_disk: Uuid,
+    pub bytes: u64,
+}
+
+/// Persisted state of an unfinished rebalance
+///
+/// `in_flight` is written before a move starts and cleared once the old copy is
+/// gone, so a resumed run knows which fragment may exist on two disks.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebalanceProgress {
+    pub in_flight: Option<FragmentMove>,
+    pub fragments_moved: u64,
+    pub bytes_moved: u64,
+}
+
+impl RebalanceProgress {
+    /// Load the progress of an earlier, unfinished run
+    pub fn load(pool_dir: &Path) -> Result<Option<Self>> {
+        load_progress(pool_dir, PROGRESS_FILE)
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        save_progress(pool_dir, PROGRESS_FILE, self)
+    }
+
+    pub fn clear(pool_dir: &Path) -> Result<()> {
+        clear_progress(pool_dir, PROGRESS_FILE)
+    }
+}
+
+/// Load progress file `name` of an earlier, unfinished run from the pool directory
+pub fn load_progress<T: DeserializeOwned>(pool_dir: &Path, name: &str) -> Result<Option<T>> {
+    let contents = match fs::read_to_string(pool_dir.join(name)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(Some(serde_json::from_str(&contents)?))
+}
+
+/// Replace progress file `name` in the pool directory
+///
+/// The temporary file is synced before it is renamed over the old one and
+/// the directory after, so a crash leaves either the old or the new progress.
+pub fn save_progress<T: Serialize>(pool_dir: &Path, name: &str, progress: &T) -> Result<()> {
+    let path = pool_dir.join(name);
+    let temp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(serde_json::to_string_pretty(progress)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, &path)?;
+    fs::File::open(pool_dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Remove progress file `name` once its run has finished
+pub fn clear_progress(pool_dir: &Path, name: &str) -> Result<()> {
+    match fs::remove_file(pool_dir.join(name)) {
+        Ok(()) => fs::File::open(pool_dir)?.sync_all()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// Bytes leaving and arriving on a single disk
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskMovement {
+    pub disk_uuid: Uuid,
+    pub path: PathBuf,
+    pub bytes_out: u64,
+    pub bytes_in: u64,
+    /// Utilization in percent before the run
+    pub utilization_before: f64,
+    /// Utilization in percent after the moves
+    pub utilization_after: f64,
+}
+
+/// Outcome of a rebalance run, or the plan of a dry run
+#[derive(Debug, Clone, Serialize)]
+pub struct RebalanceReport {
+    pub dry_run: bool,
+    /// True when the run picked up an earlier progress file
+    pub resumed: bool,
+    /// True when the run was stopped before finishing its plan
+    pub interrupted: bool,
+    pub spread_before: f64,
+    pub spread_after: f64,
+    pub moves: Vec<FragmentMove>,
+    /// Planned moves that were abandoned, e.g. because the extent changed
+    pub skipped: usize,
+    pub per_disk: Vec<DiskMovement>,
+    /// Totals across this run and any earlier interrupted runs
+    pub total_fragments_moved: u64,
+    pub total_bytes_moved: u64,
+}
+
+/// Moves existing fragments from the fullest disks to the emptiest ones
+///
+/// Runs against an unmounted pool, like the scrubber. A fragment is copied,
+/// verified and recorded at its new location before the old copy is deleted,
+/// so an extent never has fewer live fragments than its policy needs.
+pub struct Rebalancer {
+    pool_dir: PathBuf,
+}
+
+impl Rebalancer {
+    pub fn new(pool_dir: PathBuf) -> Self {
+        Rebalancer { pool_dir }
+    }
+
+    /// Utilization gap between the most and least utilized healthy disks, in percentage points
+    pub fn spread(disks: &[Disk]) -> f64 {
+        spread_of(
+            disks
+                .iter()
+                .filter(|d| d.health == DiskHealth::Healthy)
+                .map(|d| percent_used(d.used_bytes, d.capacity_bytes)),
+        )
+    }
+
+    /// Plan fragment moves until the spread drops below `target_spread`
+    ///
+    /// Only Healthy disks take part. The fullest disk gives up a fragment to
+    /// the emptiest disk of the same tier that holds no other fragment of the
+    /// extent, as long as the move doesn't leave the receiver fuller than the
+    /// giver. Planning stops early when no such move is left.
+    pub fn plan(
+        &self,
+        metadata: &MetadataManager,
+        disks: &[Disk],
+        target_spread: f64,
+    ) -> Result<Vec<FragmentMove>> {
+        let healthy: Vec<&Disk> = disks.iter().filter(|d| d.health == DiskHealth::Healthy).collect();
+        let mut usage: HashMap<Uuid, (u64, u64)> =
+            healthy.iter().map(|d| (d.uuid, (d.used_bytes, d.capacity_bytes))).collect();
+
+        let mut holders: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        let mut candidates: HashMap<Uuid, Vec<(Uuid, usize, u64)>> = HashMap::new();
+        for extent in metadata.iter_extents()?.filter_map(Result::ok) {
+            holders.insert(extent.uuid, extent.fragment_locations.iter().map(|l| l.disk_uuid).collect());
+            if extent.is_transitioning() || extent.rebuild_in_progress {
+                continue;
+            }
+            for location in &extent.fragment_locations {
+                // Block-device fragments are addressed by placement and stay put
+                if location.on_device.is_some() {
+                    continue;
+                }
+                let Some(disk) = healthy.iter().find(|d| d.uuid == location.disk_uuid) else {
+                    continue;
+                };
+                let path = disk.fragment_path(&extent.uuid, location.fragment_index);
+                if let Ok(meta) = fs::metadata(&path) {
+                    candidates
+                        .entry(disk.uuid)
+                        .or_default()
+                        .push((extent.uuid, location.fragment_index, meta.len()));
+                }
+            }
+        }
+        // Largest fragments first so fewer moves close the gap
+        for list in candidates.values_mut() {
+            list.sort_by_key(|c| std::cmp::Reverse(c.2));
+        }
+
+        let utilization = |(used, capacity): (u64, u64)| percent_used(used, capacity);
+
+        let mut moves = Vec::new();
+        while spread_of(usage.values().map(|&u| utilization(u))) >= target_spread {
+            let Some(source) = healthy
+                .iter()
+                .max_by(|a, b| utilization(usage[&a.uuid]).total_cmp(&utilization(usage[&b.uuid])))
+            else {
+                break;
+            };
+            let mut targets: Vec<&&Disk> = healthy
+                .iter()
+                .filter(|d| d.uuid != source.uuid && d.tier == source.tier)
+                .collect();
+            targets.sort_by(|a, b| utilization(usage[&a.uuid]).total_cmp(&utilization(usage[&b.uuid])));
+
+            let source_list = candidates.entry(source.uuid).or_default();
+            let mut chosen = None;
+            'fragments: for (pos, &(extent_uuid, fragment_index, bytes)) in source_list.iter().enumerate() {
+                let (source_used, source_capacity) = usage[&source.uuid];
+                let source_after = utilization((source_used.saturating_sub(bytes), source_capacity));
+                for target in &targets {
+                    let (used, capacity) = usage[&target.uuid];
+                    if holders[&extent_uuid].contains(&target.uuid) || capacity.saturating_sub(used) < bytes {
+                        continue;
+                    }
+                    if utilization((used + bytes, capacity)) <= source_after {
+                        chosen = Some((pos, FragmentMove {
+                            extent_uuid,
+                            fragment_index,
+                            from_disk: source.uuid,
+                            to_disk: target.uuid,
+                            bytes,
+                        }));
+                        break 'fragments;
+                    }
+                }
+            }
+
+            let Some((pos, mv)) = chosen else {
+                break;
+            };
+            source_list.remove(pos);
+            if let Some(entry) = usage.get_mut(&mv.from_disk) {
+                entry.0 = entry.0.saturating_sub(mv.bytes);
+            }
+            if let Some(entry) = usage.get_mut(&mv.to_disk) {
+                entry.0 += mv.bytes;
+            }
+            if let Some(disk_uuids) = holders.get_mut(&mv.extent_uuid) {
+                if let Some(slot) = disk_uuids.iter_mut().find(|u| **u == mv.from_disk) {
+                    *slot = mv.to_disk;
+                }
+            }
+            moves.push(mv);
+        }
+
+        Ok(moves)
+    }
+
+    /// Plan and, unless `config.dry_run` is set, carry out the moves
+    ///
+    /// Progress is persisted before every move. Setting `stop` ends the run
+    /// after the current move; the next run reconciles any half-finished move
+    /// and continues from the pool's current state.
+    pub fn run(
+        &self,
+        metadata: &MetadataManager,
+        disks: &mut [Disk],
+        config: &RebalanceConfig,
+        stop: &AtomicBool,
+    ) -> Result<RebalanceReport> {
+        let _class = IoClass::Defrag.enter();
+        let earlier = if config.dry_run { None } else { RebalanceProgress::load(&self.pool_dir)? };
+        let resumed = earlier.is_some();
+        let mut progress = earlier.unwrap_or_default();
+        if let Some(mv) = progress.in_flight.take() {
+            self.reconcile(metadata, disks, &mv)?;
+            progress.save(&self.pool_dir)?;
+        }
+
+        let before: Vec<Disk> = disks.to_vec();
+        let spread_before = Self::spread(disks);
+        let planned = self.plan(metadata, disks, config.target_spread)?;
+
+        if config.dry_run {
+            let per_disk = movements(&before, &planned);
+            let spread_after = spread_of(
+                per_disk
+                    .iter()
+                    .zip(&before)
+                    .filter(|(_, d)| d.health == DiskHealth::Healthy)
+                    .map(|(m, _)| m.utilization_after),
+            );
+            return Ok(RebalanceReport {
+                dry_run: true,
+                resumed: false,
+                interrupted: false,
+                spread_before,
+                spread_after,
+                total_fragments_moved: 0,
+                total_bytes_moved: 0,
+                skipped: 0,
+                per_disk,
+                moves: planned,
+            });
+        }
+
+        let started = Instant::now();
+        let mut bytes_this_run = 0u64;
+        let mut moves = Vec::new();
+        let mut skipped = 0;
+        let mut interrupted = false;
+        for mv in planned {
+            if stop.load(Ordering::SeqCst) {
+                interrupted = true;
+                break;
+            }
+
+            progress.in_flight = Some(mv.clone());
+            progress.save(&self.pool_dir)?;
+            match self.move_fragment(metadata, disks, &mv) {
+                Ok(true) => {
+                    progress.fragments_moved += 1;
+                    progress.bytes_moved += mv.bytes;
+                    bytes_this_run += mv.bytes;
+                    moves.push(mv);
+                }
+                Ok(false) => skipped += 1,
+                Err(e) => {
+                    log::warn!(
+                        "Failed to move fragment {} of extent {} to disk {}: {}",
+                        mv.fragment_index, mv.extent_uuid, mv.to_disk, e
+                    );
+                    skipped += 1;
+                }
+            }
+            progress.in_flight = None;
+            progress.save(&self.pool_dir)?;
+
+            if let Some(limit) = config.max_bytes_per_sec.filter(|l| *l > 0) {
+                let due = Duration::from_secs_f64(bytes_this_run as f64 / limit as f64);
+                let elapsed = started.elapsed();
+                if due > elapsed {
+                    std::thread::sleep(due - elapsed);
+                }
+            }
+        }
+
+        if !interrupted {
+            RebalanceProgress::clear(&self.pool_dir)?;
+        }
+
+        Ok(RebalanceReport {
+            dry_run: false,
+            resumed,
+            interrupted,
+            spread_before,
+            spread_after: Self::spread(disks),
+            skipped,
+            per_disk: movements(&before, &moves),
+            total_fragments_moved: progress.fragments_moved,
+            total_bytes_moved: progress.bytes_moved,
+            moves,
+        })
+    }
+
+    /// Copy one fragment to its new disk, verify it, record it, then delete the old copy
+    ///
+    /// Returns `Ok(false)` when the move no longer applies: the extent is gone,
+    /// the fragment has moved, or the source copy fails its checksum (that is
+    /// left for the scrubber to repair rather than spread).
+    fn move_fragment(&self, metadata: &MetadataManager, disks: &mut [Disk], mv: &FragmentMove) -> Result<bool> {
+        let Ok(mut extent) = metadata.load_extent(&mv.extent_uuid) else {
+            return Ok(false);
+        };
+        let Some(pos) = extent
+            .fragment_locations
+            .iter()
+            .position(|l| l.fragment_index == mv.fragment_index && l.disk_uuid == mv.from_disk)
+        else {
+            return Ok(false);
+        };
+        if extent.fragment_locations.iter().any(|l| l.disk_uuid == mv.to_disk) {
+            return Ok(false);
+        }
+
+        let data = {
+            let source = find_disk(disks, mv.from_disk)?;
+            let result = source.read_fragment(&mv.extent_uuid, mv.fragment_index);
+            source.track_io(&result);
+            result?
+        };
+        if !extent.fragment_locations[pos].verify_checksum(&data) {
+            log::warn!(
+                "Not moving fragment {} of extent {}: checksum mismatch on disk {}",
+                mv.fragment_index, mv.extent_uuid, mv.from_disk
+            );
+            return Ok(false);
+        }
+        let checksum = *blake3::hash(&data).as_bytes();
+
+        let target = find_disk(disks, mv.to_disk)?;
+        let placement = {
+            let result = target.write_fragment(&mv.extent_uuid, mv.fragment_index, &data);
+            target.track_io(&result);
+            result?
+        };
+        let copy_ok = target
+            .read_fragment(&mv.extent_uuid, mv.fragment_index)
+            .is_ok_and(|copy| *blake3::hash(&copy).as_bytes() == checksum);
+        if !copy_ok {
+            target.delete_fragment(&mv.extent_uuid, mv.fragment_index).ok();
+            return Err(anyhow!("copy on disk {} failed verification", mv.to_disk));
+        }
+
+        extent.fragment_locations[pos] = FragmentLocation {
+            disk_uuid: mv.to_disk,
+            fragment_index: mv.fragment_index,
+            on_device: placement,
+            checksum: Some(checksum),
+        };
+        if let Err(e) = metadata.save_extent(&extent) {
+            target.delete_fragment(&mv.extent_uuid, mv.fragment_index).ok();
+            return Err(e.into());
+        }
+
+        find_disk(disks, mv.from_disk)?.delete_fragment(&mv.extent_uuid, mv.fragment_index)?;
+        log::info!(
+            "Moved fragment {} of extent {} from disk {} to disk {}",
+            mv.fragment_index, mv.extent_uuid, mv.from_disk, mv.to_disk
+        );
+        Ok(true)
+    }
+
+    /// Finish or roll back a move that was interrupted part way
+    ///
+    /// The extent metadata decides: if it already points at the new disk the
+    /// old copy is deleted, otherwise the possibly partial new copy is.
+    fn reconcile(&self, metadata: &MetadataManager, disks: &mut [Disk], mv: &FragmentMove) -> Result<()> {
+        let recorded_on_target = metadata.load_extent(&mv.extent_uuid).is_ok_and(|extent| {
+            extent
+                .fragment_locations
+                .iter()
+                .any(|l| l.fragment_index == mv.fragment_index && l.disk_uuid == mv.to_disk)
+        });
+        let (stale_disk, action) = if recorded_on_target {
+            (mv.from_disk, "completing")
+        } else {
+            (mv.to_disk, "rolling back")
+        };
+        log::info!(
+            "Resuming rebalance: {} interrupted move of fragment {} of extent {}",
+            action, mv.fragment_index, mv.extent_uuid
+        );
+        if let Ok(disk) = find_disk(disks, stale_disk) {
+            disk.delete_fragment(&mv.extent_uuid, mv.fragment_index)?;
+        }
+        Ok(())
+    }
+}
+
+fn find_disk(disks: &mut [Disk], uuid: Uuid) -> Result<&mut Disk> {
+    disks
+        .iter_mut()
+        .find(|d| d.uuid == uuid)
+        .ok_or_else(|| anyhow!("Disk not found: {}", uuid))
+}
+
+fn percent_used(used: u64, capacity: u64) -> f64 {
+    if capacity == 0 {
+        return 100.0;
+    }
+    used as f64 * 100.0 / capacity as f64
+}
+
+/// Gap between the largest and smallest utilization, in percentage points
+fn spread_of(utilizations: impl Iterator<Item = f64>) -> f64 {
+    let mut min = f64::MAX;
+    let mut max = f64::MIN;
+    for percent in utilizations {
+        min = min.min(percent);
+        max = max.max(percent);
+    }
+    if max < min { 0.0 } else { max - min }
+}
+
+/// Per-disk byte movement for `moves`, in the order of `disks`
+fn movements(disks: &[Disk], moves: &[FragmentMove]) -> Vec<DiskMovement> {
+    disks
+        .iter()
+        .map(|d| {
+            let bytes_out: u64 = moves.iter().filter(|m| m.from_disk == d.uuid).map(|m| m.bytes).sum();
+            let bytes_in: u64 = moves.iter().filter(|m| m.to_disk == d.uuid).map(|m| m.bytes).sum();
+            let percent = |used: u64| percent_used(used, d.capacity_bytes);
+            DiskMovement {
+                disk_uuid: d.uuid,
+                path: d.path.clone(),
+                bytes_out,
+                bytes_in,
+                utilization_before: percent(d.used_bytes),
+                utilization_after: percent((d.used_bytes + bytes_in).saturating_sub(bytes_out)),
+            }
+        })
+        .collect()
+}
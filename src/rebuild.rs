@@ -0,0 +1,260 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::disk::{Disk, DiskHealth};
+use crate::extent::Extent;
+use crate::io_scheduler::IoClass;
+use crate::metadata::MetadataManager;
+use crate::placement::PlacementEngine;
+use crate::rebalance::{clear_progress, load_progress, save_progress};
+
+/// Progress file kept in the pool directory while a rebuild is unfinished
+pub const PROGRESS_FILE: &str = "rebuild.json";
+
+/// Persisted state of an unfinished rebuild
+///
+/// Extents are visited in UUID order; `cursor` is the last one finished, so a
+/// resumed run starts after it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebuildProgress {
+    pub cursor: Option<Uuid>,
+    pub extents_rebuilt: u64,
+    pub bytes_written: u64,
+}
+
+impl RebuildProgress {
+    /// Load the progress of an earlier, unfinished run
+    pub fn load(pool_dir: &Path) -> Result<Option<Self>> {
+        load_progress(pool_dir, PROGRESS_FILE)
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        save_progress(pool_dir, PROGRESS_FILE, self)
+    }
+
+    pub fn clear(pool_dir: &Path) -> Result<()> {
+        clear_progress(pool_dir, PROGRESS_FILE)
+    }
+}
+
+/// Live counters of a rebuild pass, for progress reporting from another thread
+#[derive(Debug, Default)]
+pub struct RebuildPassProgress {
+    total: AtomicU64,
+    scanned: AtomicU64,
+    rebuilt: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl RebuildPassProgress {
+    pub fn scanned(&self) -> u64 {
+        self.scanned.load(Ordering::Relaxed)
+    }
+
+    pub fn rebuilt(&self) -> u64 {
+        self.rebuilt.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for RebuildPassProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} extents scanned, {} rebuilt, {:.1} MB written",
+            self.scanned(),
+            self.total.load(Ordering::Relaxed),
+            self.rebuilt(),
+            self.bytes_written.load(Ordering::Relaxed) as f64 / 1024.0 / 1024.0
+        )
+    }
+}
+
+/// An extent the run could not rebuild
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildFailure {
+    pub extent_uuid: Uuid,
+    pub error: String,
+}
+
+/// Outcome of a rebuild run
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildReport {
+    /// True when the run picked up an earlier progress file
+    pub resumed: bool,
+    /// True when the run was stopped before visiting every extent
+    pub interrupted: bool,
+    pub scanned: u64,
+    pub rebuilt: Vec<Uuid>,
+    /// Extents with too few fragments left to decode
+    pub unrecoverable: Vec<Uuid>,
+    pub failed: Vec<RebuildFailure>,
+    pub bytes_written: u64,
+    /// Totals across this run and any earlier interrupted runs
+    pub total_extents_rebuilt: u64,
+    pub total_bytes_written: u64,
+}
+
+/// Restores extents that have lost fragments, e.g. after a failed disk is replaced
+///
+/// Runs against an unmounted pool, like the rebalancer. Missing fragments are
+/// re-encoded from the surviving ones and placed on the least utilized
+/// eligible disks, so a freshly added disk takes most of them. Each rebuilt
+/// fragment replaces the extent's location for that index, including
+/// locations on disks that are no longer in the pool.
+pub struct Rebuilder {
+    pool_dir: PathBuf,
+}
+
+impl Rebuilder {
+    pub fn new(pool_dir: PathBuf) -> Self {
+        Rebuilder { pool_dir }
+    }
+
+    /// Read every recorded fragment, leaving lost ones as `None`
+    ///
+    /// Fragments on Failed or unknown disks, unreadable ones and ones failing
+    /// their checksum all count as lost.
+    pub fn available_fragments(extent: &Extent, disks: &[Disk]) -> Vec<Option<Vec<u8>>> {
+        let mut fragments = vec![None; extent.redundancy.fragment_count()];
+        for location in &extent.fragment_locations {
+            if location.fragment_index >= fragments.len() || fragments[location.fragment_index].is_some() {
+                continue;
+            }
+            let Some(disk) = disks.iter().find(|d| d.uuid == location.disk_uuid && d.health != DiskHealth::Failed)
+            else {
+                continue;
+            };
+            match disk.read_fragment(&extent.uuid, location.fragment_index) {
+                Ok(data) if location.verify_checksum(&data) => fragments[location.fragment_index] = Some(data),
+                Ok(_) => log::warn!(
+                    "Fragment {} of extent {} on disk {} fails its checksum",
+                    location.fragment_index, extent.uuid, disk.uuid
+                ),
+                Err(_) => {}
+            }
+        }
+        fragments
+    }
+
+    /// Rebuild every extent with fewer than `fragment_count()` available fragments
+    ///
+    /// Progress is persisted after every extent. Setting `stop` ends the run
+    /// after the current extent; the next run continues after the last one
+    /// finished. Writes are throttled to `max_bytes_per_sec` when given.
+    pub fn run(
+        &self,
+        metadata: &MetadataManager,
+        disks: &mut [Disk],
+        max_bytes_per_sec: Option<u64>,
+        live: &RebuildPassProgress,
+        stop: &AtomicBool,
+    ) -> Result<RebuildReport> {
+        let _class = IoClass::Rebuild.enter();
+        let earlier = RebuildProgress::load(&self.pool_dir)?;
+        let resumed = earlier.is_some();
+        let mut progress = earlier.unwrap_or_default();
+
+        let extents = metadata.extent_records_sorted(progress.cursor)?;
+        live.total.store(extents.remaining().unwrap_or(0) as u64, Ordering::Relaxed);
+
+        let placement = PlacementEngine;
+        let started = Instant::now();
+        let mut report = RebuildReport {
+            resumed,
+            interrupted: false,
+            scanned: 0,
+            rebuilt: Vec::new(),
+            unrecoverable: Vec::new(),
+            failed: Vec::new(),
+            bytes_written: 0,
+            total_extents_rebuilt: 0,
+            total_bytes_written: 0,
+        };
+        for (uuid, extent) in extents {
+            if stop.load(Ordering::SeqCst) {
+                report.interrupted = true;
+                break;
+            }
+            let mut extent = match extent {
+                Ok(extent) => extent,
+                Err(e) => {
+                    log::error!("Skipping extent {} in rebuild: {:#}", uuid, e);
+                    continue;
+                }
+            };
+            report.scanned += 1;
+            live.scanned.fetch_add(1, Ordering::Relaxed);
+
+            let fragments = Self::available_fragments(&extent, disks);
+            let available = fragments.iter().filter(|f| f.is_some()).count();
+            if available < extent.redundancy.min_fragments() {
+                log::error!(
+                    "Extent {} is unrecoverable: {}/{} fragments",
+                    extent.uuid, available, extent.redundancy.min_fragments()
+                );
+                report.unrecoverable.push(extent.uuid);
+            } else if available < extent.redundancy.fragment_count() && !extent.is_transitioning() {
+                let fragment_len = fragments.iter().flatten().next().map_or(0, |f| f.len() as u64);
+                let bytes = fragment_len * (extent.redundancy.fragment_count() - available) as u64;
+                match Self::rebuild_extent(&placement, metadata, disks, &mut extent, &fragments) {
+                    Ok(()) => {
+                        progress.extents_rebuilt += 1;
+                        progress.bytes_written += bytes;
+                        report.bytes_written += bytes;
+                        report.rebuilt.push(extent.uuid);
+                        live.rebuilt.fetch_add(1, Ordering::Relaxed);
+                        live.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to rebuild extent {}: {:#}", extent.uuid, e);
+                        report.failed.push(RebuildFailure { extent_uuid: extent.uuid, error: format!("{:#}", e) });
+                    }
+                }
+            }
+
+            progress.cursor = Some(extent.uuid);
+            progress.save(&self.pool_dir)?;
+
+            if let Some(limit) = max_bytes_per_sec.filter(|l| *l > 0) {
+                let due = Duration::from_secs_f64(report.bytes_written as f64 / limit as f64);
+                let elapsed = started.elapsed();
+                if due > elapsed {
+                    std::thread::sleep(due - elapsed);
+                }
+            }
+        }
+
+        if !report.interrupted {
+            RebuildProgress::clear(&self.pool_dir)?;
+        }
+        report.total_extents_rebuilt = progress.extents_rebuilt;
+        report.total_bytes_written = progress.bytes_written;
+        Ok(report)
+    }
+
+    /// Place the missing fragments of one extent and record their locations
+    fn rebuild_extent(
+        placement: &PlacementEngine,
+        metadata: &MetadataManager,
+        disks: &mut [Disk],
+        extent: &mut Extent,
+        fragments: &[Option<Vec<u8>>],
+    ) -> Result<()> {
+        let disk_arcs: Vec<Arc<Mutex<Disk>>> = disks.iter().map(|d| Arc::new(Mutex::new(d.clone()))).collect();
+        let result = placement.rebuild_extent(extent, &disk_arcs, fragments);
+        // Carry usage and I/O counters back so later extents see the new utilization
+        for (disk, arc) in disks.iter_mut().zip(&disk_arcs) {
+            *disk = arc.lock().unwrap().clone();
+        }
+        result?;
+        metadata.save_extent(extent)?;
+        log::info!("Rebuilt extent {}", extent.uuid);
+        Ok(())
+    }
+}
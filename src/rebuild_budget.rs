@@ -0,0 +1,318 @@
+//! Limits on background rebuild I/O
+//!
+//! The rebuild worker takes a permit from the budget before it starts a
+//! rebuild and charges the extent's bytes to a token bucket, so a degraded
+//! pool repairs itself at the pace the operator chose instead of saturating
+//! its disks. The pool counts as idle once the foreground read and write
+//! counters have not moved for `idle_after_secs`; the idle limits apply then.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::metrics::Metrics;
+
+/// Throughput is averaged over this much recent history
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+/// Waiters re-check idleness, limit changes and shutdown this often
+const RECHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Repair budget of a pool, stored with the pool configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RebuildLimits {
+    /// Rebuilds running at once while the pool serves foreground I/O
+    pub max_concurrent: usize,
+    /// Rebuild bytes per second while the pool is busy; 0 is unlimited
+    pub max_bytes_per_sec: u64,
+    /// Rebuilds running at once while the pool is idle
+    pub idle_max_concurrent: usize,
+    /// Rebuild bytes per second while the pool is idle; 0 is unlimited
+    pub idle_max_bytes_per_sec: u64,
+    /// Seconds without foreground reads or writes before the pool is idle
+    pub idle_after_secs: u64,
+}
+
+impl Default for RebuildLimits {
+    fn default() -> Self {
+        RebuildLimits {
+            max_concurrent: 1,
+            max_bytes_per_sec: 64 * 1024 * 1024,
+            idle_max_concurrent: 4,
+            idle_max_bytes_per_sec: 0,
+            idle_after_secs: 10,
+        }
+    }
+}
+
+impl RebuildLimits {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_concurrent == 0 || self.idle_max_concurrent == 0 {
+            return Err(anyhow!("At least one concurrent rebuild must be allowed"));
+        }
+        Ok(())
+    }
+
+    /// Concurrent rebuilds and bytes per second in effect
+    pub fn effective(&self, idle: bool) -> (usize, u64) {
+        if idle {
+            (self.idle_max_concurrent, self.idle_max_bytes_per_sec)
+        } else {
+            (self.max_concurrent, self.max_bytes_per_sec)
+        }
+    }
+}
+
+/// What the budget allows right now and how much of it rebuilds use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RebuildStatus {
+    pub limits: RebuildLimits,
+    pub idle: bool,
+    pub running: usize,
+    pub max_concurrent: usize,
+    /// 0 is unlimited
+    pub max_bytes_per_sec: u64,
+    /// Rebuilt bytes per second over the last ten seconds
+    pub throughput_bytes_per_sec: u64,
+}
+
+struct BudgetState {
+    limits: RebuildLimits,
+    running: usize,
+    /// Bytes that may be charged without waiting; negative after a large extent
+    tokens: f64,
+    refilled: Instant,
+    /// Foreground reads plus writes when last sampled, and when they last moved
+    foreground_ops: u64,
+    last_foreground: Instant,
+    completed: VecDeque<(Instant, u64)>,
+    shutdown: bool,
+}
+
+/// Shared limiter for the rebuild worker; see the module docs
+pub struct RebuildBudget {
+    state: Mutex<BudgetState>,
+    changed: Condvar,
+    metrics: Arc<Metrics>,
+}
+
+impl RebuildBudget {
+    pub fn new(limits: RebuildLimits, metrics: Arc<Metrics>) -> Self {
+        let now = Instant::now();
+        let budget = RebuildBudget {
+            state: Mutex::new(BudgetState {
+                limits,
+                running: 0,
+                tokens: 0.0,
+                refilled: now,
+                foreground_ops: 0,
+                last_foreground: now,
+                completed: VecDeque::new(),
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+            metrics,
+        };
+        budget.status();
+        budget
+    }
+
+    /// Replace the limits; waiting rebuilds pick them up at once
+    pub fn set_limits(&self, limits: RebuildLimits) -> Result<()> {
+        limits.validate()?;
+        self.state.lock().unwrap().limits = limits;
+        self.changed.notify_all();
+        self.status();
+        Ok(())
+    }
+
+    /// Current status; also refreshes the rebuild gauges in the metrics
+    pub fn status(&self) -> RebuildStatus {
+        let mut state = self.state.lock().unwrap();
+        self.publish(&mut state)
+    }
+
+    /// Block until another rebuild may start; `None` once shut down
+    pub fn acquire(self: &Arc<Self>) -> Option<RebuildPermit> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return None;
+            }
+            let idle = self.is_idle(&mut state);
+            let (max_concurrent, _) = state.limits.effective(idle);
+            if state.running < max_concurrent {
+                state.running += 1;
+                self.publish(&mut state);
+                return Some(RebuildPermit { budget: Arc::clone(self), bytes: 0 });
+            }
+            state = self.changed.wait_timeout(state, RECHECK_INTERVAL).unwrap().0;
+        }
+    }
+
+    /// Charge `bytes` of rebuild I/O, waiting while the byte rate is used up
+    ///
+    /// An extent larger than a second's worth still goes through; the debt
+    /// holds back the rebuilds after it. Returns `false` if the budget was
+    /// shut down while waiting.
+    pub fn charge(&self, bytes: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return false;
+            }
+            let idle = self.is_idle(&mut state);
+            let (_, rate) = state.limits.effective(idle);
+            let now = Instant::now();
+            if rate == 0 {
+                state.tokens = 0.0;
+                state.refilled = now;
+                return true;
+            }
+            let rate = rate as f64;
+            // At most one second of unused rate carries over
+            state.tokens = (state.tokens + now.duration_since(state.refilled).as_secs_f64() * rate).min(rate);
+            state.refilled = now;
+            if state.tokens >= 0.0 {
+                state.tokens -= bytes as f64;
+                return true;
+            }
+            let wait = Duration::from_secs_f64(-state.tokens / rate).min(RECHECK_INTERVAL);
+            state = self.changed.wait_timeout(state, wait).unwrap().0;
+        }
+    }
+
+    /// Wake everything waiting on the budget and refuse further rebuilds
+    pub fn shutdown(&self) {
+        self.state.lock().unwrap().shutdown = true;
+        self.changed.notify_all();
+    }
+
+    fn release(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.running = state.running.saturating_sub(1);
+        if bytes > 0 {
+            state.completed.push_back((Instant::now(), bytes));
+        }
+        self.publish(&mut state);
+        self.changed.notify_all();
+    }
+
+    /// Whether foreground I/O has been quiet for `idle_after_secs`
+    fn is_idle(&self, state: &mut MutexGuard<BudgetState>) -> bool {
+        let ops = self.metrics.disk_reads.load(Ordering::Relaxed) + self.metrics.disk_writes.load(Ordering::Relaxed);
+        if ops != state.foreground_ops {
+            state.foreground_ops = ops;
+            state.last_foreground = Instant::now();
+        }
+        state.last_foreground.elapsed() >= Duration::from_secs(state.limits.idle_after_secs)
+    }
+
+    fn publish(&self, state: &mut MutexGuard<BudgetState>) -> RebuildStatus {
+        let idle = self.is_idle(state);
+        while state.completed.front().is_some_and(|(at, _)| at.elapsed() > THROUGHPUT_WINDOW) {
+            state.completed.pop_front();
+        }
+        let recent: u64 = state.completed.iter().map(|(_, bytes)| bytes).sum();
+        let (max_concurrent, max_bytes_per_sec) = state.limits.effective(idle);
+        let status = RebuildStatus {
+            limits: state.limits,
+            idle,
+            running: state.running,
+            max_concurrent,
+            max_bytes_per_sec,
+            throughput_bytes_per_sec: recent / THROUGHPUT_WINDOW.as_secs(),
+        };
+        self.metrics.update_rebuild_budget(&status);
+        status
+    }
+}
+
+/// Held for the duration of one rebuild
+pub struct RebuildPermit {
+    budget: Arc<RebuildBudget>,
+    bytes: u64,
+}
+
+impl RebuildPermit {
+    /// Count `bytes` towards the rebuild throughput when the permit is dropped
+    pub fn rebuilt(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+}
+
+impl Drop for RebuildPermit {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(limits: RebuildLimits) -> Arc<RebuildBudget> {
+        Arc::new(RebuildBudget::new(limits, Arc::new(Metrics::new())))
+    }
+
+    #[test]
+    fn test_concurrency_limit_switches_when_the_pool_goes_idle() {
+        let metrics = Arc::new(Metrics::new());
+        let limits = RebuildLimits { max_concurrent: 1, idle_max_concurrent: 2, idle_after_secs: 1, ..Default::default() };
+        let budget = Arc::new(RebuildBudget::new(limits, Arc::clone(&metrics)));
+        metrics.record_disk_read(4096);
+
+        let first = budget.acquire().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            std::thread::spawn(move || {
+                let permit = budget.acquire();
+                tx.send(Instant::now()).unwrap();
+                permit.is_some()
+            })
+        };
+        // Busy: the second rebuild waits until foreground I/O has been quiet for a second
+        let started = Instant::now();
+        metrics.record_disk_read(4096);
+        let admitted = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(admitted.duration_since(started) >= Duration::from_millis(800));
+        assert!(waiter.join().unwrap());
+        let status = budget.status();
+        assert!(status.idle);
+        assert_eq!((status.running, status.max_concurrent), (1, 2));
+        drop(first);
+        assert_eq!(budget.status().running, 0);
+    }
+
+    #[test]
+    fn test_byte_rate_paces_rebuilds_and_reports_throughput() {
+        let limits = RebuildLimits { max_bytes_per_sec: 1_000_000, idle_after_secs: 3600, ..Default::default() };
+        let budget = budget(limits);
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            let mut permit = budget.acquire().unwrap();
+            assert!(budget.charge(500_000));
+            permit.rebuilt(500_000);
+        }
+        // The first charge is free; the other two wait for the rate to catch up
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+        assert_eq!(budget.status().throughput_bytes_per_sec, 150_000);
+
+        // Raising the limit at runtime lets waiting rebuilds through at once
+        assert!(budget.set_limits(RebuildLimits { max_concurrent: 0, ..limits }).is_err());
+        budget.set_limits(RebuildLimits { max_bytes_per_sec: 0, ..limits }).unwrap();
+        let started = Instant::now();
+        assert!(budget.charge(100_000_000));
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        budget.shutdown();
+        assert!(budget.acquire().is_none());
+        assert!(!budget.charge(1));
+    }
+}
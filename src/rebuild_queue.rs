@@ -0,0 +1,264 @@
+//! Background rebuild queue
+//!
+//! Degraded extents discovered on the read path (or by the mount-time scan) are
+//! queued here and repaired by a worker thread owned by `StorageEngine`, so a
+//! read never waits for a rebuild.
+//!
+//! The queue is bounded, deduplicated by extent UUID, and ordered so that the
+//! extents closest to becoming unreadable (smallest fragment margin above the
+//! policy minimum) are rebuilt first.
+
+use std::collections::{BinaryHeap, HashSet};
+use std::cmp::Ordering;
+use std::sync::{Condvar, Mutex};
+use uuid::Uuid;
+
+/// Default maximum number of queued rebuilds
+pub const DEFAULT_REBUILD_QUEUE_CAPACITY: usize = 1024;
+
+/// A pending rebuild
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildTask {
+    pub extent_uuid: Uuid,
+    /// Surviving fragments beyond the minimum needed to decode (0 = one more loss is fatal)
+    pub margin: usize,
+    seq: u64,
+}
+
+impl Ord for RebuildTask {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap: smaller margin wins, then older submission
+        other
+            .margin
+            .cmp(&self.margin)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for RebuildTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Result of submitting an extent to the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueResult {
+    Queued,
+    /// Already queued or being rebuilt
+    Duplicate,
+    /// Queue at capacity (only returned by `try_enqueue`)
+    Full,
+    /// Queue has been shut down
+    Closed,
+}
+
+struct QueueState {
+    heap: BinaryHeap<RebuildTask>,
+    /// Extents queued or in flight
+    tracked: HashSet<Uuid>,
+    /// Tasks handed to the worker plus active producers (e.g. the mount scan)
+    active: usize,
+    next_seq: u64,
+    shutdown: bool,
+}
+
+/// Bounded, deduplicated priority queue of extents awaiting rebuild
+pub struct RebuildQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    capacity: usize,
+}
+
+impl RebuildQueue {
+    pub fn new(capacity: usize) -> Self {
+        RebuildQueue {
+            state: Mutex::new(QueueState {
+                heap: BinaryHeap::new(),
+                tracked: HashSet::new(),
+                active: 0,
+                next_seq: 0,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Queue an extent without blocking; used by the read path
+    pub fn try_enqueue(&self, extent_uuid: Uuid, margin: usize) -> EnqueueResult {
+        let mut state = self.state.lock().unwrap();
+        if state.shutdown {
+            return EnqueueResult::Closed;
+        }
+        if state.tracked.contains(&extent_uuid) {
+            return EnqueueResult::Duplicate;
+        }
+        if state.heap.len() >= self.capacity {
+            return EnqueueResult::Full;
+        }
+        Self::push(&mut state, extent_uuid, margin);
+        self.changed.notify_all();
+        EnqueueResult::Queued
+    }
+
+    /// Queue an extent, waiting for space if the queue is full; used by background scans
+    pub fn enqueue(&self, extent_uuid: Uuid, margin: usize) -> EnqueueResult {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return EnqueueResult::Closed;
+            }
+            if state.tracked.contains(&extent_uuid) {
+                return EnqueueResult::Duplicate;
+            }
+            if state.heap.len() < self.capacity {
+                break;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+        Self::push(&mut state, extent_uuid, margin);
+        self.changed.notify_all();
+        EnqueueResult::Queued
+    }
+
+    fn push(state: &mut QueueState, extent_uuid: Uuid, margin: usize) {
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.tracked.insert(extent_uuid);
+        state.heap.push(RebuildTask { extent_uuid, margin, seq });
+    }
+
+    /// Block until a task is available; returns `None` once shut down
+    ///
+    /// Every task returned must be acknowledged with `complete`.
+    pub fn next(&self) -> Option<RebuildTask> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.shutdown {
+                return None;
+            }
+            if let Some(task) = state.heap.pop() {
+                state.active += 1;
+                self.changed.notify_all();
+                return Some(task);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Mark a task returned by `next` as finished
+    pub fn complete(&self, task: &RebuildTask) {
+        let mut state = self.state.lock().unwrap();
+        state.tracked.remove(&task.extent_uuid);
+        state.active = state.active.saturating_sub(1);
+        self.changed.notify_all();
+    }
+
+    /// Register a producer whose pending submissions should hold off `wait_idle`
+    pub fn begin_producer(&self) {
+        self.state.lock().unwrap().active += 1;
+    }
+
+    /// Unregister a producer started with `begin_producer`
+    pub fn end_producer(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.active = state.active.saturating_sub(1);
+        self.changed.notify_all();
+    }
+
+    /// Block until the queue is empty and no task or producer is in flight
+    ///
+    /// Returns false if the queue was shut down instead.
+    pub fn wait_idle(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.shutdown && (!state.heap.is_empty() || state.active > 0) {
+            state = self.changed.wait(state).unwrap();
+        }
+        !state.shutdown
+    }
+
+    /// Stop the queue, discarding pending tasks and waking all waiters
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.shutdown = true;
+        state.heap.clear();
+        self.changed.notify_all();
+    }
+
+    /// Whether an extent is queued or being rebuilt
+    pub fn is_tracked(&self, extent_uuid: &Uuid) -> bool {
+        self.state.lock().unwrap().tracked.contains(extent_uuid)
+    }
+
+    /// Number of queued (not yet started) rebuilds
+    pub fn depth(&self) -> usize {
+        self.state.lock().unwrap().heap.len()
+    }
+}
+
+impl Default for RebuildQueue {
+    fn default() -> Self {
+        Self::new(DEFAULT_REBUILD_QUEUE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lowest_margin_first() {
+        let queue = RebuildQueue::new(8);
+        let safe = Uuid::new_v4();
+        let critical = Uuid::new_v4();
+        let middle = Uuid::new_v4();
+
+        assert_eq!(queue.try_enqueue(safe, 2), EnqueueResult::Queued);
+        assert_eq!(queue.try_enqueue(critical, 0), EnqueueResult::Queued);
+        assert_eq!(queue.try_enqueue(middle, 1), EnqueueResult::Queued);
+
+        let order: Vec<Uuid> = (0..3)
+            .map(|_| {
+                let task = queue.next().unwrap();
+                queue.complete(&task);
+                task.extent_uuid
+            })
+            .collect();
+        assert_eq!(order, vec![critical, middle, safe]);
+    }
+
+    #[test]
+    fn test_deduplicates_queued_and_in_flight() {
+        let queue = RebuildQueue::new(8);
+        let extent = Uuid::new_v4();
+
+        assert_eq!(queue.try_enqueue(extent, 1), EnqueueResult::Queued);
+        assert_eq!(queue.try_enqueue(extent, 0), EnqueueResult::Duplicate);
+
+        let task = queue.next().unwrap();
+        assert_eq!(queue.try_enqueue(extent, 1), EnqueueResult::Duplicate);
+        queue.complete(&task);
+        assert_eq!(queue.try_enqueue(extent, 1), EnqueueResult::Queued);
+    }
+
+    #[test]
+    fn test_bounded_capacity() {
+        let queue = RebuildQueue::new(2);
+        assert_eq!(queue.try_enqueue(Uuid::new_v4(), 1), EnqueueResult::Queued);
+        assert_eq!(queue.try_enqueue(Uuid::new_v4(), 1), EnqueueResult::Queued);
+        assert_eq!(queue.try_enqueue(Uuid::new_v4(), 0), EnqueueResult::Full);
+        assert_eq!(queue.depth(), 2);
+    }
+
+    #[test]
+    fn test_shutdown_wakes_worker() {
+        let queue = std::sync::Arc::new(RebuildQueue::new(2));
+        let worker_queue = queue.clone();
+        let worker = std::thread::spawn(move || worker_queue.next());
+
+        queue.shutdown();
+        assert!(worker.join().unwrap().is_none());
+        assert_eq!(queue.try_enqueue(Uuid::new_v4(), 0), EnqueueResult::Closed);
+    }
+}
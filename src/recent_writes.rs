@@ -0,0 +1,353 @@
+//! Recent-write verification queue
+//!
+//! A full scrub reaches an extent days after it was written, yet data just
+//! written to a marginal disk is the most likely to be bad and the most likely
+//! to be read soon. Every extent placement is therefore recorded here with the
+//! time it was placed, and a background pass of the mounted engine verifies
+//! each one once it has settled, that is once it is older than the settle
+//! delay and so most likely read from the media rather than a cache.
+//!
+//! The queue lives in memory and is journaled to `RECENT_WRITES_FILE` in the
+//! pool directory, one JSON record per line: an extent queued, or an extent
+//! taken off. Opening the pool replays the journal, so the backlog survives
+//! restarts; once taken-off records outnumber the extents still queued the
+//! journal is rewritten from memory. The queue is capped: placements beyond
+//! the cap are not queued and are left to the full scrub.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Journal of the queue, relative to the pool directory
+pub const RECENT_WRITES_FILE: &str = "recent_writes.jsonl";
+
+/// Default age at which a placed extent is verified
+pub const DEFAULT_RECENT_WRITE_SETTLE: Duration = Duration::from_secs(600);
+
+/// Default number of extents the queue holds
+pub const DEFAULT_RECENT_WRITE_CAPACITY: usize = 100_000;
+
+/// Taken-off records the journal may hold before it is rewritten, however short the queue
+const MIN_COMPACT_RECORDS: usize = 1024;
+
+/// Settings of the background pass verifying recent writes
+#[derive(Debug, Clone, Copy)]
+pub struct RecentWriteConfig {
+    /// Time between passes
+    pub interval: Duration,
+    /// Extents younger than this are left for a later pass
+    pub settle: Duration,
+    /// Extents verified per pass at most, oldest first
+    pub batch: usize,
+}
+
+impl Default for RecentWriteConfig {
+    fn default() -> Self {
+        RecentWriteConfig {
+            interval: Duration::from_secs(60),
+            settle: DEFAULT_RECENT_WRITE_SETTLE,
+            batch: 256,
+        }
+    }
+}
+
+/// What one verification pass found
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RecentWritePass {
+    pub healthy: u64,
+    /// Degraded extents handed to the background rebuild
+    pub repairs_queued: u64,
+    pub unrecoverable: u64,
+    /// Extents deleted since they were written
+    pub gone: u64,
+    /// Extents that could not be checked; they stay queued for the next pass
+    pub errors: u64,
+}
+
+impl RecentWritePass {
+    pub fn verified(&self) -> u64 {
+        self.healthy + self.repairs_queued + self.unrecoverable
+    }
+}
+
+/// Backlog of the queue, for `scrub-daemon status`
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentWriteStatus {
+    /// Extents waiting to be verified
+    pub queued: usize,
+    /// Of those, extents past the settle delay
+    pub due: usize,
+    /// Unix time the oldest queued extent was placed
+    pub oldest_placed_at: Option<i64>,
+    /// Seconds the oldest extent has waited beyond the settle delay
+    pub lag_secs: u64,
+}
+
+/// One line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JournalRecord {
+    Queued { extent: Uuid, placed_at: i64 },
+    Done { extent: Uuid },
+}
+
+struct QueueState {
+    /// Queued extents, oldest first
+    by_age: BTreeSet<(i64, Uuid)>,
+    placed_at: HashMap<Uuid, i64>,
+    capacity: usize,
+    /// Opened on the first record, so readers of the queue never create it
+    journal: Option<File>,
+    /// Taken-off records in the journal since it was last rewritten
+    stale: usize,
+}
+
+/// Extents placed recently and not yet verified, oldest first
+pub struct RecentWriteQueue {
+    path: Option<PathBuf>,
+    state: Mutex<QueueState>,
+}
+
+impl RecentWriteQueue {
+    /// A queue kept in memory only
+    pub fn new(capacity: usize) -> Self {
+        RecentWriteQueue {
+            path: None,
+            state: Mutex::new(QueueState {
+                by_age: BTreeSet::new(),
+                placed_at: HashMap::new(),
+                capacity,
+                journal: None,
+                stale: 0,
+            }),
+        }
+    }
+
+    /// The queue journaled in `pool_dir`, replaying what an earlier run left
+    ///
+    /// A line that does not parse, such as one torn by a crash, is skipped.
+    pub fn open(pool_dir: &Path, capacity: usize) -> anyhow::Result<Self> {
+        let mut queue = Self::new(capacity);
+        let path = pool_dir.join(RECENT_WRITES_FILE);
+        let file = match File::open(&path) {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(anyhow::anyhow!("Failed to open {:?}: {}", path, e)),
+        };
+        if let Some(file) = file {
+            let state = queue.state.get_mut().unwrap();
+            let mut records = 0;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                match serde_json::from_str::<JournalRecord>(&line) {
+                    Ok(JournalRecord::Queued { extent, placed_at }) => {
+                        Self::remove(state, &extent);
+                        state.by_age.insert((placed_at, extent));
+                        state.placed_at.insert(extent, placed_at);
+                    }
+                    Ok(JournalRecord::Done { extent }) => {
+                        Self::remove(state, &extent);
+                    }
+                    Err(e) => {
+                        log::warn!("Skipping unreadable line of {:?}: {}", path, e);
+                        continue;
+                    }
+                }
+                records += 1;
+            }
+            state.stale = records - state.by_age.len();
+        }
+        queue.path = Some(path);
+        Ok(queue)
+    }
+
+    /// Queue `extent` as placed at `placed_at`; false if the queue is full
+    ///
+    /// An extent queued already, as when a rebuild rewrites its fragments,
+    /// is moved to the new time.
+    pub fn record(&self, extent: Uuid, placed_at: i64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let requeued = Self::remove(&mut state, &extent);
+        if !requeued && state.placed_at.len() >= state.capacity {
+            return false;
+        }
+        state.by_age.insert((placed_at, extent));
+        state.placed_at.insert(extent, placed_at);
+        self.append(&mut state, &[JournalRecord::Queued { extent, placed_at }]);
+        true
+    }
+
+    /// Up to `limit` queued extents placed at or before `settled_at`, oldest first
+    pub fn due(&self, settled_at: i64, limit: usize) -> Vec<(Uuid, i64)> {
+        let state = self.state.lock().unwrap();
+        state
+            .by_age
+            .iter()
+            .take_while(|(placed_at, _)| *placed_at <= settled_at)
+            .take(limit)
+            .map(|(placed_at, extent)| (*extent, *placed_at))
+            .collect()
+    }
+
+    /// Take verified extents off the queue
+    pub fn complete(&self, extents: &[Uuid]) {
+        let mut state = self.state.lock().unwrap();
+        let mut records = Vec::new();
+        for extent in extents {
+            if Self::remove(&mut state, extent) {
+                records.push(JournalRecord::Done { extent: *extent });
+            }
+        }
+        if records.is_empty() {
+            return;
+        }
+        state.stale += records.len();
+        if state.stale > state.by_age.len().max(MIN_COMPACT_RECORDS) {
+            if let Err(e) = self.compact(&mut state) {
+                log::warn!("Failed to rewrite the recent-write journal: {}", e);
+                self.append(&mut state, &records);
+            }
+        } else {
+            self.append(&mut state, &records);
+        }
+    }
+
+    pub fn contains(&self, extent: &Uuid) -> bool {
+        self.state.lock().unwrap().placed_at.contains_key(extent)
+    }
+
+    /// Every queued extent
+    pub fn extents(&self) -> Vec<Uuid> {
+        self.state.lock().unwrap().by_age.iter().map(|(_, extent)| *extent).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().placed_at.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hold at most `capacity` extents; ones queued already stay
+    pub fn set_capacity(&self, capacity: usize) {
+        self.state.lock().unwrap().capacity = capacity;
+    }
+
+    /// Backlog at unix time `now` with extents due after `settle`
+    pub fn status(&self, now: i64, settle: Duration) -> RecentWriteStatus {
+        let state = self.state.lock().unwrap();
+        let settled_at = now - settle.as_secs() as i64;
+        let oldest_placed_at = state.by_age.first().map(|(placed_at, _)| *placed_at);
+        RecentWriteStatus {
+            queued: state.by_age.len(),
+            due: state.by_age.iter().take_while(|(placed_at, _)| *placed_at <= settled_at).count(),
+            oldest_placed_at,
+            lag_secs: oldest_placed_at.map_or(0, |placed_at| (settled_at - placed_at).max(0) as u64),
+        }
+    }
+
+    fn remove(state: &mut QueueState, extent: &Uuid) -> bool {
+        match state.placed_at.remove(extent) {
+            Some(placed_at) => state.by_age.remove(&(placed_at, *extent)),
+            None => false,
+        }
+    }
+
+    /// Append `records` to the journal; on failure the queue is kept in memory only
+    fn append(&self, state: &mut QueueState, records: &[JournalRecord]) {
+        let Some(path) = &self.path else { return };
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record).expect("journal records serialize"));
+            lines.push('\n');
+        }
+        let written = match &mut state.journal {
+            Some(journal) => journal.write_all(lines.as_bytes()),
+            None => OpenOptions::new().create(true).append(true).open(path).and_then(|mut journal| {
+                journal.write_all(lines.as_bytes())?;
+                state.journal = Some(journal);
+                Ok(())
+            }),
+        };
+        if let Err(e) = written {
+            log::warn!("Failed to append to {:?}: {}", path, e);
+        }
+    }
+
+    /// Rewrite the journal with just the queued extents
+    fn compact(&self, state: &mut QueueState) -> std::io::Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let temp_path = path.with_extension("jsonl.tmp");
+        let mut lines = String::new();
+        for (placed_at, extent) in &state.by_age {
+            let record = JournalRecord::Queued { extent: *extent, placed_at: *placed_at };
+            lines.push_str(&serde_json::to_string(&record).expect("journal records serialize"));
+            lines.push('\n');
+        }
+        std::fs::write(&temp_path, lines)?;
+        std::fs::rename(&temp_path, path)?;
+        state.journal = None;
+        state.stale = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_is_replayed_from_its_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let queue = RecentWriteQueue::open(dir.path(), 10).unwrap();
+        assert!(queue.record(a, 100));
+        assert!(queue.record(b, 50));
+        assert!(queue.record(c, 300));
+        queue.complete(&[b]);
+        // Rewritten fragments are verified again from their new placement
+        assert!(queue.record(a, 200));
+        drop(queue);
+
+        let queue = RecentWriteQueue::open(dir.path(), 10).unwrap();
+        assert_eq!(queue.extents(), vec![a, c]);
+        assert_eq!(queue.due(250, 10), vec![(a, 200)]);
+        assert_eq!(queue.due(1000, 1), vec![(a, 200)]);
+
+        // A torn last line is skipped
+        let mut journal = OpenOptions::new().append(true).open(dir.path().join(RECENT_WRITES_FILE)).unwrap();
+        journal.write_all(b"{\"queued\":{\"ext").unwrap();
+        assert_eq!(RecentWriteQueue::open(dir.path(), 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_full_queue_refuses_new_extents_and_the_journal_is_compacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = RecentWriteQueue::open(dir.path(), 2).unwrap();
+        let extents: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        assert!(queue.record(extents[0], 1));
+        assert!(queue.record(extents[1], 2));
+        assert!(!queue.record(extents[2], 3));
+        assert!(queue.record(extents[1], 4), "a queued extent can always be requeued");
+        assert_eq!(queue.extents(), extents[..2].to_vec());
+
+        let status = queue.status(700, Duration::from_secs(600));
+        assert_eq!((status.queued, status.due, status.oldest_placed_at, status.lag_secs), (2, 2, Some(1), 99));
+
+        queue.set_capacity(MIN_COMPACT_RECORDS * 2);
+        let many: Vec<Uuid> = (0..MIN_COMPACT_RECORDS + 1).map(|_| Uuid::new_v4()).collect();
+        for (i, extent) in many.iter().enumerate() {
+            queue.record(*extent, 10 + i as i64);
+        }
+        queue.complete(&many);
+        let journal = std::fs::read_to_string(dir.path().join(RECENT_WRITES_FILE)).unwrap();
+        assert_eq!(journal.lines().count(), 2);
+        assert_eq!(RecentWriteQueue::open(dir.path(), 2).unwrap().extents(), extents[..2].to_vec());
+    }
+}
@@ -56,6 +56,8 @@ impl ReclamationPolicy {
                 prioritize_hot_extents: false, // Defrag everything
                 pause_on_high_load: false,
                 max_concurrent_operations: 4,
+                schedule: None,
+                coalesce_min_extents: 8,
             },
             ReclamationPolicy::Balanced => DefragConfig {
                 enabled: true,
@@ -65,6 +67,8 @@ impl ReclamationPolicy {
                 prioritize_hot_extents: true,
                 pause_on_high_load: true,
                 max_concurrent_operations: 2,
+                schedule: None,
+                coalesce_min_extents: 16,
             },
             ReclamationPolicy::Conservative => DefragConfig {
                 enabled: true,
@@ -74,6 +78,8 @@ impl ReclamationPolicy {
                 prioritize_hot_extents: false,
                 pause_on_high_load: true,
                 max_concurrent_operations: 1,
+                schedule: None,
+                coalesce_min_extents: 64,
             },
             ReclamationPolicy::Performance => DefragConfig {
                 enabled: false,
@@ -83,6 +89,8 @@ impl ReclamationPolicy {
                 prioritize_hot_extents: true,
                 pause_on_high_load: true,
                 max_concurrent_operations: 1,
+                schedule: None,
+                coalesce_min_extents: 0,
             },
             ReclamationPolicy::Custom => DefragConfig::default(),
         }
@@ -4,6 +4,11 @@ use reed_solomon_erasure::galois_8::ReedSolomon;
 use crate::extent::RedundancyPolicy;
 
 /// Encode data according to redundancy policy
+///
+/// Replication stores `data` as is in every copy. Erasure coding zero-pads it
+/// to `data_shards × shard_size` bytes, with `shard_size` from
+/// `RedundancyPolicy::fragment_size`, so every shard has the same length.
+/// The padding is not recorded: `decode` is given the original length.
 pub fn encode(data: &[u8], policy: RedundancyPolicy) -> Result<Vec<Vec<u8>>> {
     match policy {
         RedundancyPolicy::Replication { copies } => encode_replication(data, copies),
@@ -13,12 +18,26 @@ pub fn encode(data: &[u8], policy: RedundancyPolicy) -> Result<Vec<Vec<u8>>> {
     }
 }
 
-/// Decode data from fragments
-pub fn decode(fragments: &[Option<Vec<u8>>], policy: RedundancyPolicy) -> Result<Vec<u8>> {
+/// Decode the `len` bytes that were encoded into `fragments`
+///
+/// `len` is the length passed to `encode`, i.e. `Extent::stored_size`; the
+/// padding added by `encode` is dropped, so exactly `len` bytes come back.
+pub fn decode(fragments: &[Option<Vec<u8>>], policy: RedundancyPolicy, len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    decode_into(fragments, policy, len, &mut out)?;
+    Ok(out)
+}
+
+/// `decode` into `out`, replacing what it held
+///
+/// Lets a reader reuse one buffer across extents. When every data shard is
+/// present they are copied out as they are, without reconstruction.
+pub fn decode_into(fragments: &[Option<Vec<u8>>], policy: RedundancyPolicy, len: usize, out: &mut Vec<u8>) -> Result<()> {
+    out.clear();
     match policy {
-        RedundancyPolicy::Replication { .. } => decode_replication(fragments),
+        RedundancyPolicy::Replication { .. } => decode_replication(fragments, len, out),
         RedundancyPolicy::ErasureCoding { data_shards, parity_shards } => {
-            decode_erasure_coding(fragments, data_shards, parity_shards)
+            decode_erasure_coding(fragments, data_shards, parity_shards, len, out)
         }
     }
 }
@@ -29,9 +48,10 @@ pub fn reencode(
     fragments: &[Option<Vec<u8>>],
     old_policy: RedundancyPolicy,
     new_policy: RedundancyPolicy,
+    len: usize,
 ) -> Result<Vec<Vec<u8>>> {
     // First, decode with old policy
-    let original_data = decode(fragments, old_policy)?;
+    let original_data = decode(fragments, old_policy, len)?;
     
     // Then, encode with new policy
     encode(&original_data, new_policy)
@@ -57,10 +77,14 @@ fn encode_replication(data: &[u8], copies: usize) -> Result<Vec<Vec<u8>>> {
 }
 
 /// Replication decoding: return first available copy
-fn decode_replication(fragments: &[Option<Vec<u8>>]) -> Result<Vec<u8>> {
-    for fragment in fragments {
-        if let Some(data) = fragment {
-            return Ok(data.clone());
+///
+/// Copies re-encoded from an erasure-coded extent before `decode` trimmed its
+/// padding can be longer than `len`; shorter copies are skipped as truncated.
+fn decode_replication(fragments: &[Option<Vec<u8>>], len: usize, out: &mut Vec<u8>) -> Result<()> {
+    for data in fragments.iter().flatten() {
+        if data.len() >= len {
+            out.extend_from_slice(&data[..len]);
+            return Ok(());
         }
     }
     Err(anyhow!("No fragments available for replication decode"))
@@ -76,7 +100,7 @@ fn encode_erasure_coding(
         .context("Failed to create Reed-Solomon encoder")?;
     
     // Calculate shard size
-    let shard_size = (data.len() + data_shards - 1) / data_shards;
+    let shard_size = RedundancyPolicy::ErasureCoding { data_shards, parity_shards }.fragment_size(data.len());
     
     // Create shards
     let mut shards: Vec<Vec<u8>> = Vec::new();
@@ -114,9 +138,9 @@ fn decode_erasure_coding(
     fragments: &[Option<Vec<u8>>],
     data_shards: usize,
     parity_shards: usize,
-) -> Result<Vec<u8>> {
-    let rs = ReedSolomon::new(data_shards, parity_shards)
-        .context("Failed to create Reed-Solomon decoder")?;
+    len: usize,
+    out: &mut Vec<u8>,
+) -> Result<()> {
     
     // Check we have enough fragments
     let available = fragments.iter().filter(|f| f.is_some()).count();
@@ -128,6 +152,35 @@ fn decode_erasure_coding(
         ));
     }
     
+    // Every shard is padded to the same length, and together they cover `len`.
+    // Shards can be longer than `fragment_size(len)` when an extent was
+    // re-encoded from data that still carried its earlier padding.
+    let mut present = fragments.iter().flatten();
+    let shard_size = present.next().map_or(0, |shard| shard.len());
+    if present.any(|shard| shard.len() != shard_size) {
+        return Err(anyhow!("Shards of one extent differ in length"));
+    }
+    if shard_size * data_shards < len {
+        return Err(anyhow!(
+            "{} data shards of {} bytes cannot hold {} bytes",
+            data_shards,
+            shard_size,
+            len
+        ));
+    }
+    
+    // The usual read has every data shard; parity is only needed to stand in for one
+    out.reserve(len);
+    if fragments.len() >= data_shards && fragments[..data_shards].iter().all(Option::is_some) {
+        for shard in fragments[..data_shards].iter().flatten() {
+            out.extend_from_slice(&shard[..shard.len().min(len - out.len())]);
+        }
+        return Ok(());
+    }
+    
+    let rs = ReedSolomon::new(data_shards, parity_shards)
+        .context("Failed to create Reed-Solomon decoder")?;
+    
     // Convert to format expected by reed-solomon-erasure
     let mut shards: Vec<Option<Vec<u8>>> = fragments.to_vec();
     
@@ -137,19 +190,144 @@ fn decode_erasure_coding(
     }
     
     // Reconstruct
-    rs.reconstruct(&mut shards)
+    rs.reconstruct_data(&mut shards)
         .context("Failed to reconstruct with Reed-Solomon")?;
     
     // Concatenate data shards
-    let mut result = Vec::new();
     for i in 0..data_shards {
         if let Some(shard) = &shards[i] {
-            result.extend_from_slice(shard);
+            out.extend_from_slice(shard);
         } else {
             return Err(anyhow!("Failed to reconstruct data shard {}", i));
         }
     }
     
-    Ok(result)
+    // Drop the padding
+    out.truncate(len);
+    Ok(())
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extent::DEFAULT_EXTENT_SIZE;
+
+    /// Deterministic pseudo-random bytes
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len + 32);
+        let mut counter = 0u64;
+        while out.len() < len {
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&seed.to_le_bytes());
+            hasher.update(&counter.to_le_bytes());
+            out.extend_from_slice(hasher.finalize().as_bytes());
+            counter += 1;
+        }
+        out.truncate(len);
+        out
+    }
+
+    fn policies() -> Vec<RedundancyPolicy> {
+        let mut policies: Vec<RedundancyPolicy> = (2..=5).map(|copies| RedundancyPolicy::Replication { copies }).collect();
+        for data_shards in 2..=8 {
+            for parity_shards in 1..=3 {
+                policies.push(RedundancyPolicy::ErasureCoding { data_shards, parity_shards });
+            }
+        }
+        policies
+    }
+
+    /// Every way to keep exactly `keep` of `total` fragments
+    fn subsets(total: usize, keep: usize) -> Vec<Vec<bool>> {
+        (0u32..1 << total)
+            .filter(|mask| mask.count_ones() as usize == keep)
+            .map(|mask| (0..total).map(|i| mask & (1 << i) != 0).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_random_sizes_under_every_policy() {
+        // Edge sizes around shard and extent boundaries, then random sizes up to several extents
+        let mut sizes = vec![1, 2, 3, 7, 8, 9, 4095, 4096, 4097, DEFAULT_EXTENT_SIZE - 1, DEFAULT_EXTENT_SIZE + 1];
+        for seed in 0..12u64 {
+            let word = u64::from_le_bytes(noise(seed, 8).try_into().unwrap());
+            sizes.push(1 + (word % (3 * DEFAULT_EXTENT_SIZE as u64)) as usize);
+        }
+
+        let inputs: Vec<Vec<u8>> = sizes.iter().enumerate().map(|(seed, &len)| noise(seed as u64, len)).collect();
+        for policy in policies() {
+            for data in &inputs {
+                let len = data.len();
+                let fragments = encode(data, policy).unwrap();
+                assert_eq!(fragments.len(), policy.fragment_count());
+                assert!(fragments.iter().all(|f| f.len() == policy.fragment_size(len)), "{} len {}", policy, len);
+
+                let all: Vec<Option<Vec<u8>>> = fragments.iter().cloned().map(Some).collect();
+                assert_eq!(&decode(&all, policy, len).unwrap(), data, "{} len {}", policy, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_from_every_minimal_subset_of_fragments() {
+        for policy in policies() {
+            let total = policy.fragment_count();
+            for len in [1, 4097, 61_441] {
+                let data = noise(len as u64, len);
+                let fragments = encode(&data, policy).unwrap();
+                for keep in subsets(total, policy.min_fragments()) {
+                    let available: Vec<Option<Vec<u8>>> = fragments
+                        .iter()
+                        .zip(&keep)
+                        .map(|(fragment, &kept)| kept.then(|| fragment.clone()))
+                        .collect();
+                    assert_eq!(decode(&available, policy, len).unwrap(), data, "{} len {} {:?}", policy, len, keep);
+                }
+                let too_few: Vec<Option<Vec<u8>>> = fragments
+                    .iter()
+                    .enumerate()
+                    .map(|(i, fragment)| (i + 1 < policy.min_fragments()).then(|| fragment.clone()))
+                    .collect();
+                assert!(decode(&too_few, policy, len).is_err());
+            }
+        }
+    }
+
+    /// Sizes that failed or relied on callers trimming the padding
+    #[test]
+    fn test_padding_regressions() {
+        let ec = RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+
+        // An empty extent used to fail to encode: Reed-Solomon rejects empty shards
+        let fragments = encode(&[], ec).unwrap();
+        assert!(fragments.iter().all(|f| f.len() == 1));
+        let available: Vec<Option<Vec<u8>>> = fragments.into_iter().map(Some).collect();
+        assert!(decode(&available, ec, 0).unwrap().is_empty());
+
+        // 1 MiB - 1 under 4+2 decodes to exactly its length, not the padded 1 MiB
+        let data = noise(1, DEFAULT_EXTENT_SIZE - 1);
+        let mut available: Vec<Option<Vec<u8>>> = encode(&data, ec).unwrap().into_iter().map(Some).collect();
+        available[0] = None;
+        available[3] = None;
+        assert_eq!(decode(&available, ec, data.len()).unwrap(), data);
+
+        // Fragments re-encoded from still-padded data are longer than needed and still decode
+        let padded = [&b"hello"[..], &[0u8; 3]].concat();
+        let wide = RedundancyPolicy::ErasureCoding { data_shards: 3, parity_shards: 1 };
+        let available: Vec<Option<Vec<u8>>> = encode(&padded, wide).unwrap().into_iter().map(Some).collect();
+        assert_eq!(available[0].as_ref().unwrap().len(), 3);
+        assert_eq!(decode(&available, wide, 5).unwrap(), b"hello");
+        let replicas = vec![None, Some(padded.clone())];
+        assert_eq!(decode(&replicas, RedundancyPolicy::Replication { copies: 2 }, 5).unwrap(), b"hello");
+
+        // Shards that cannot hold the data, or disagree in length, are refused
+        let mut short: Vec<Option<Vec<u8>>> = encode(&data, ec).unwrap().into_iter().map(Some).collect();
+        assert!(decode(&short, ec, data.len() + 4).is_err());
+        short[2].as_mut().unwrap().pop();
+        assert!(decode(&short, ec, data.len()).is_err());
+        let truncated = vec![Some(b"hel".to_vec())];
+        assert!(decode(&truncated, RedundancyPolicy::Replication { copies: 1 }, 5).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Replacing a failed disk with a new one
+//!
+//! `replace-disk` adds the new disk and then visits every extent in UUID
+//! order. Fragments recorded on the old disk are rebuilt from the surviving
+//! ones straight onto the new disk, and their locations are rewritten to point
+//! at it. Once no extent refers to the old disk any more, it leaves the pool.
+//!
+//! Progress, including which disk replaces which, is persisted after every
+//! extent, so an interrupted run continues where it stopped without adding
+//! the new disk twice.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::disk::Disk;
+use crate::extent::{Extent, FragmentLocation};
+use crate::io_scheduler::IoClass;
+use crate::metadata::MetadataManager;
+use crate::rebalance::{clear_progress, load_progress, save_progress};
+use crate::rebuild::{RebuildFailure, Rebuilder};
+
+/// Progress file kept in the pool directory while a replacement is unfinished
+pub const PROGRESS_FILE: &str = "replace_disk.json";
+
+/// Persisted state of an unfinished replacement
+///
+/// `cursor` is the last extent finished, so a resumed run starts after it. A
+/// run that leaves references to the old disk behind resets it, so the next
+/// run looks at every extent again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplaceProgress {
+    pub old_disk: Uuid,
+    pub new_disk: Uuid,
+    pub new_path: PathBuf,
+    pub cursor: Option<Uuid>,
+    pub extents_repaired: u64,
+    pub bytes_written: u64,
+}
+
+impl ReplaceProgress {
+    pub fn new(old_disk: Uuid, new_disk: Uuid, new_path: PathBuf) -> Self {
+        ReplaceProgress { old_disk, new_disk, new_path, cursor: None, extents_repaired: 0, bytes_written: 0 }
+    }
+
+    /// Load the progress of an earlier, unfinished run
+    pub fn load(pool_dir: &Path) -> Result<Option<Self>> {
+        load_progress(pool_dir, PROGRESS_FILE)
+    }
+
+    pub fn save(&self, pool_dir: &Path) -> Result<()> {
+        save_progress(pool_dir, PROGRESS_FILE, self)
+    }
+
+    pub fn clear(pool_dir: &Path) -> Result<()> {
+        clear_progress(pool_dir, PROGRESS_FILE)
+    }
+}
+
+/// Live counters of a replacement, for progress reporting from another thread
+#[derive(Debug, Default)]
+pub struct ReplacePassProgress {
+    total: AtomicU64,
+    scanned: AtomicU64,
+    repaired: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl ReplacePassProgress {
+    pub fn scanned(&self) -> u64 {
+        self.scanned.load(Ordering::Relaxed)
+    }
+
+    pub fn repaired(&self) -> u64 {
+        self.repaired.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for ReplacePassProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} extents scanned, {} repaired, {:.1} MB written",
+            self.scanned(),
+            self.total.load(Ordering::Relaxed),
+            self.repaired(),
+            self.bytes_written.load(Ordering::Relaxed) as f64 / 1024.0 / 1024.0
+        )
+    }
+}
+
+/// Outcome of a replacement run
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplaceReport {
+    pub old_disk: Uuid,
+    pub new_disk: Uuid,
+    /// True when the run picked up an earlier progress file
+    pub resumed: bool,
+    /// True when the run was stopped before visiting every extent
+    pub interrupted: bool,
+    pub scanned: u64,
+    /// Extents whose fragments on the old disk now live on the new one
+    pub repaired: Vec<Uuid>,
+    /// Extents with too few surviving fragments to rebuild; they keep their old locations
+    pub unrecoverable: Vec<Uuid>,
+    pub failed: Vec<RebuildFailure>,
+    pub bytes_written: u64,
+    /// Totals across this run and any earlier interrupted runs
+    pub total_extents_repaired: u64,
+    pub total_bytes_written: u64,
+    /// Set by the caller once the old disk has left the pool
+    pub old_disk_removed: bool,
+}
+
+impl ReplaceReport {
+    /// Whether every extent was visited and none still refers to the old disk
+    pub fn complete(&self) -> bool {
+        !self.interrupted && self.unrecoverable.is_empty() && self.failed.is_empty()
+    }
+}
+
+/// Moves every fragment location of one disk onto another, rebuilding the fragments
+///
+/// Runs against an unmounted pool, like the rebuilder. The old disk's own
+/// copies are never read, even when it is still reachable.
+pub struct DiskReplacer {
+    pool_dir: PathBuf,
+    progress: ReplaceProgress,
+    /// Picked up from an earlier run's progress file
+    resumed: bool,
+}
+
+impl DiskReplacer {
+    /// Begin a replacement, recording it in the pool directory
+    pub fn start(pool_dir: PathBuf, progress: ReplaceProgress) -> Result<Self> {
+        progress.save(&pool_dir)?;
+        Ok(DiskReplacer { pool_dir, progress, resumed: false })
+    }
+
+    /// The unfinished replacement of the pool at `pool_dir`, if any
+    pub fn resume(pool_dir: PathBuf) -> Result<Option<Self>> {
+        let progress = ReplaceProgress::load(&pool_dir)?;
+        Ok(progress.map(|progress| DiskReplacer { pool_dir, progress, resumed: true }))
+    }
+
+    pub fn progress(&self) -> &ReplaceProgress {
+        &self.progress
+    }
+
+    /// Rebuild the old disk's fragments of every extent onto the new disk
+    ///
+    /// Progress is persisted after every extent. Setting `stop` ends the run
+    /// after the current extent; the next run continues after the last one
+    /// finished. Writes are throttled to `max_bytes_per_sec` when given.
+    pub fn run(
+        &self,
+        metadata: &MetadataManager,
+        disks: &mut [Disk],
+        max_bytes_per_sec: Option<u64>,
+        live: &ReplacePassProgress,
+        stop: &AtomicBool,
+    ) -> Result<ReplaceReport> {
+        let _class = IoClass::Rebuild.enter();
+        let mut progress = self.progress.clone();
+        if !disks.iter().any(|disk| disk.uuid == progress.new_disk) {
+            return Err(anyhow!("New disk {} is not reachable", progress.new_disk));
+        }
+        let extents = metadata.extent_records_sorted(progress.cursor)?;
+        live.total.store(extents.remaining().unwrap_or(0) as u64, Ordering::Relaxed);
+
+        let started = Instant::now();
+        let mut report = ReplaceReport {
+            old_disk: progress.old_disk,
+            new_disk: progress.new_disk,
+            resumed: self.resumed,
+            interrupted: false,
+            scanned: 0,
+            repaired: Vec::new(),
+            unrecoverable: Vec::new(),
+            failed: Vec::new(),
+            bytes_written: 0,
+            total_extents_repaired: 0,
+            total_bytes_written: 0,
+            old_disk_removed: false,
+        };
+        for (uuid, extent) in extents {
+            if stop.load(Ordering::SeqCst) {
+                report.interrupted = true;
+                break;
+            }
+            let mut extent = match extent {
+                Ok(extent) => extent,
+                Err(e) => {
+                    log::error!("Skipping extent {} in disk replacement: {:#}", uuid, e);
+                    report.failed.push(RebuildFailure { extent_uuid: uuid, error: format!("{:#}", e) });
+                    continue;
+                }
+            };
+            report.scanned += 1;
+            live.scanned.fetch_add(1, Ordering::Relaxed);
+
+            if extent.fragment_locations.iter().any(|location| location.disk_uuid == progress.old_disk) {
+                match Self::replace_fragments(metadata, disks, &mut extent, progress.old_disk, progress.new_disk) {
+                    Ok(Some(bytes)) => {
+                        progress.extents_repaired += 1;
+                        progress.bytes_written += bytes;
+                        report.bytes_written += bytes;
+                        report.repaired.push(extent.uuid);
+                        live.repaired.fetch_add(1, Ordering::Relaxed);
+                        live.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    Ok(None) => {
+                        log::error!("Extent {} is unrecoverable without disk {}", extent.uuid, progress.old_disk);
+                        report.unrecoverable.push(extent.uuid);
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to move extent {} to disk {}: {:#}", extent.uuid, progress.new_disk, e);
+                        report.failed.push(RebuildFailure { extent_uuid: extent.uuid, error: format!("{:#}", e) });
+                    }
+                }
+            }
+
+            progress.cursor = Some(extent.uuid);
+            progress.save(&self.pool_dir)?;
+
+            if let Some(limit) = max_bytes_per_sec.filter(|l| *l > 0) {
+                let due = Duration::from_secs_f64(report.bytes_written as f64 / limit as f64);
+                let elapsed = started.elapsed();
+                if due > elapsed {
+                    std::thread::sleep(due - elapsed);
+                }
+            }
+        }
+
+        report.total_extents_repaired = progress.extents_repaired;
+        report.total_bytes_written = progress.bytes_written;
+        if report.complete() {
+            ReplaceProgress::clear(&self.pool_dir)?;
+        } else if !report.interrupted {
+            // Extents left behind are looked at again by the next run
+            progress.cursor = None;
+            progress.save(&self.pool_dir)?;
+        }
+        Ok(report)
+    }
+
+    /// Rebuild the fragments `extent` has on `old_disk` onto `new_disk` and save it
+    ///
+    /// Returns the bytes written, or `None` when too few other fragments survive.
+    fn replace_fragments(
+        metadata: &MetadataManager,
+        disks: &mut [Disk],
+        extent: &mut Extent,
+        old_disk: Uuid,
+        new_disk: Uuid,
+    ) -> Result<Option<u64>> {
+        if extent.is_transitioning() {
+            return Err(anyhow!("a redundancy policy change is in progress; finish or cancel it first"));
+        }
+        let mut surviving = extent.clone();
+        surviving.fragment_locations.retain(|location| location.disk_uuid != old_disk);
+        let fragments = Rebuilder::available_fragments(&surviving, disks);
+        if fragments.iter().flatten().count() < extent.redundancy.min_fragments() {
+            return Ok(None);
+        }
+        let data = crate::redundancy::decode(&fragments, extent.redundancy, extent.stored_size())?;
+        let encoded = crate::redundancy::encode(&data, extent.redundancy)?;
+
+        // A fragment with another surviving copy only loses its old location
+        let lost: Vec<usize> = extent
+            .fragment_locations
+            .iter()
+            .filter(|location| location.disk_uuid == old_disk)
+            .map(|location| location.fragment_index)
+            .filter(|index| fragments.get(*index).is_some_and(|f| f.is_none()))
+            .collect();
+        let target = disks.iter_mut().find(|disk| disk.uuid == new_disk).unwrap();
+        let mut bytes = 0;
+        let mut rebuilt = Vec::new();
+        for index in lost {
+            let fragment = &encoded[index];
+            let result = target.write_fragment(&extent.uuid, index, fragment);
+            target.track_io(&result);
+            rebuilt.push(FragmentLocation {
+                disk_uuid: new_disk,
+                fragment_index: index,
+                on_device: result?,
+                checksum: Some(*blake3::hash(fragment).as_bytes()),
+            });
+            bytes += fragment.len() as u64;
+        }
+
+        extent.fragment_locations.retain(|location| location.disk_uuid != old_disk);
+        extent.fragment_locations.extend(rebuilt);
+        metadata.save_extent(extent)?;
+        log::info!("Moved extent {} from disk {} to disk {}", extent.uuid, old_disk, new_disk);
+        Ok(Some(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageEngine;
+    use crate::test_utils::setup_test_env;
+
+    #[test]
+    fn test_replacement_moves_every_location_resumes_and_lists_unrecoverable_extents() {
+        let (pool_dir, _disk_dirs, metadata, mut disks) = setup_test_env();
+        let new_dir = tempfile::tempdir().unwrap();
+        let new_disk = Disk::new(new_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks.clone());
+        let mut inos = Vec::new();
+        for i in 0..6u8 {
+            let inode = storage.create_file(1, format!("f{}", i)).unwrap();
+            storage.write_file(inode.ino, &vec![i; 4096], 0).unwrap();
+            inos.push(inode.ino);
+        }
+        drop(storage);
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let old_disk = disks[0].uuid;
+        let on_old = |metadata: &MetadataManager| -> Vec<Uuid> {
+            let extents = metadata.list_all_extents().unwrap();
+            let on_old = extents.iter().filter(|e| e.fragment_locations.iter().any(|l| l.disk_uuid == old_disk));
+            on_old.map(|e| e.uuid).collect()
+        };
+        let mut moved = on_old(&metadata);
+        moved.sort();
+        assert!(moved.len() >= 2, "too few extents on the old disk");
+
+        // One extent loses every other copy as well
+        let mut lost = metadata.load_extent(&moved[0]).unwrap();
+        lost.fragment_locations.retain(|l| l.disk_uuid == old_disk);
+        metadata.save_extent(&lost).unwrap();
+
+        disks[0].mark_failed().unwrap();
+        disks.push(new_disk.clone());
+        let progress = ReplaceProgress::new(old_disk, new_disk.uuid, new_dir.path().to_path_buf());
+        let replacer = DiskReplacer::start(pool_dir.path().to_path_buf(), progress).unwrap();
+
+        // Stopped before the first extent, the run leaves its progress for the next one
+        let report = replacer.run(&metadata, &mut disks, None, &ReplacePassProgress::default(), &AtomicBool::new(true)).unwrap();
+        assert!(report.interrupted && !report.resumed && report.repaired.is_empty());
+        let replacer = DiskReplacer::resume(pool_dir.path().to_path_buf()).unwrap().unwrap();
+        let progress = replacer.progress();
+        assert_eq!((progress.old_disk, progress.new_disk, progress.cursor), (old_disk, new_disk.uuid, None));
+
+        let live = ReplacePassProgress::default();
+        let report = replacer.run(&metadata, &mut disks, None, &live, &AtomicBool::new(false)).unwrap();
+        assert!(report.resumed && !report.complete());
+        assert_eq!(report.unrecoverable, vec![moved[0]]);
+        assert_eq!(report.repaired, moved[1..].to_vec());
+        assert_eq!(live.repaired(), moved.len() as u64 - 1);
+        assert!(report.bytes_written > 0);
+
+        // Only the unrecoverable extent still refers to the old disk
+        assert_eq!(on_old(&metadata), vec![moved[0]]);
+        for uuid in &moved[1..] {
+            let extent = metadata.load_extent(uuid).unwrap();
+            assert!(extent.fragment_locations.iter().any(|l| l.disk_uuid == new_disk.uuid));
+            assert!(Rebuilder::available_fragments(&extent, &disks).iter().all(|f| f.is_some()));
+        }
+        // The next run looks at every extent again
+        let replacer = DiskReplacer::resume(pool_dir.path().to_path_buf()).unwrap().unwrap();
+        assert_eq!(replacer.progress().cursor, None);
+
+        metadata.delete_extent(&moved[0]).unwrap();
+        let report = replacer.run(&metadata, &mut disks, None, &ReplacePassProgress::default(), &AtomicBool::new(false)).unwrap();
+        assert!(report.complete() && report.repaired.is_empty());
+        assert_eq!(report.total_extents_repaired, moved.len() as u64 - 1);
+        assert!(DiskReplacer::resume(pool_dir.path().to_path_buf()).unwrap().is_none());
+    }
+}
@@ -1,170 +1,42 @@
 use anyhow::Result;
-use uuid::Uuid;
+use std::time::Duration;
 
 use crate::disk::{Disk, DiskHealth};
-use crate::extent::Extent;
-
-/// Smart replica selection strategy for optimized reads
-pub struct ReplicaSelector;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ReplicaSelectionStrategy {
-    /// Pick first available replica (fast, simple)
-    First,
-    /// Pick replica on least-loaded disk
-    LeastLoaded,
-    /// Pick replica based on health and load
-    Smart,
-    /// Round-robin across replicas (balance load)
-    RoundRobin,
-}
-
-impl ReplicaSelector {
-    /// Select best replica fragment for reading based on strategy
-    pub fn select_replica(
-        extent: &Extent,
-        disks: &[&Disk],
-        strategy: ReplicaSelectionStrategy,
-    ) -> Option<(Uuid, usize)> {
-        // Find all readable replicas
-        let mut candidates: Vec<(Uuid, usize)> = Vec::new();
-
-        for location in &extent.fragment_locations {
-            if let Some(disk) = disks.iter().find(|d| (**d).uuid == location.disk_uuid) {
-                // Only select from healthy/degraded disks (never failed)
-                if (**disk).health != DiskHealth::Failed {
-                    candidates.push((location.disk_uuid, location.fragment_index));
-                }
-            }
-        }
-
-        if candidates.is_empty() {
-            return None;
-        }
-
-        match strategy {
-            ReplicaSelectionStrategy::First => candidates.first().copied(),
-            ReplicaSelectionStrategy::LeastLoaded => {
-                Self::select_least_loaded(disks, &candidates)
-            }
-            ReplicaSelectionStrategy::Smart => Self::select_smart(disks, &candidates),
-            ReplicaSelectionStrategy::RoundRobin => {
-                // For now, use first available (stateless)
-                candidates.first().copied()
-            }
-        }
-    }
-
-    /// Select replica on disk with least usage
-    fn select_least_loaded(
-        disks: &[&Disk],
-        candidates: &[(Uuid, usize)],
-    ) -> Option<(Uuid, usize)> {
-        candidates
-            .iter()
-            .copied()
-            .min_by_key(|(disk_uuid, _)| {
-                disks
-                    .iter()
-                    .find(|d| (**d).uuid == *disk_uuid)
-                    .map(|d| (**d).used_bytes)
-                    .unwrap_or(u64::MAX)
-            })
-    }
-
-    /// Select replica considering both health and load
-    fn select_smart(
-        disks: &[&Disk],
-        candidates: &[(Uuid, usize)],
-    ) -> Option<(Uuid, usize)> {
-        let mut best: Option<(Uuid, usize, i32)> = None;
-
-        for &(disk_uuid, fragment_index) in candidates {
-            if let Some(disk) = disks.iter().find(|d| (**d).uuid == disk_uuid) {
-                // Score based on: health (primary) + load (secondary)
-                let health_score = match (**disk).health {
-                    DiskHealth::Healthy => 100,
-                    DiskHealth::Degraded => 50,
-                    DiskHealth::Suspect => 25,
-                    DiskHealth::Draining => 10,
-                    DiskHealth::Failed => 0,
-                };
-
-                // Load score: lower is better (normalize to 0-100)
-                let capacity = (**disk).capacity_bytes.max(1);
-                let load_score =
-                    100 - (((**disk).used_bytes as f64 / capacity as f64 * 100.0) as i32).min(100);
-
-                // Combined score: health weighted 3x, load weighted 1x
-                let score = health_score * 3 + load_score;
-
-                if best.is_none() || score > best.as_ref().unwrap().2 {
-                    best = Some((disk_uuid, fragment_index, score));
-                }
-            }
-        }
-
-        best.map(|(uuid, idx, _)| (uuid, idx))
-    }
+use crate::extent::{Extent, RedundancyPolicy};
+use crate::tiering::StorageTier;
+
+/// Expected time to read one fragment from `disk`
+///
+/// The moving average of its timed reads once it has one, else a guess from its tier.
+pub fn expected_read_latency(disk: &Disk) -> Duration {
+    disk.io_counters.read_latency().unwrap_or(match disk.tier {
+        StorageTier::Hot => Duration::from_micros(100),
+        StorageTier::Warm => Duration::from_micros(500),
+        StorageTier::Cold => Duration::from_millis(8),
+    })
 }
 
-/// Fragment read scheduler for parallel operations
-pub struct FragmentReadScheduler {
-    /// Maximum concurrent reads
-    pub max_concurrent: usize,
-}
-
-impl FragmentReadScheduler {
-    pub fn new(max_concurrent: usize) -> Self {
-        FragmentReadScheduler {
-            max_concurrent: max_concurrent.max(1),
-        }
-    }
-
-    /// Plan parallel reads for multiple fragments
-    /// Returns batches of (disk_uuid, fragment_index) tuples to read in parallel
-    pub fn plan_parallel_reads(
-        &self,
-        extent: &Extent,
-        disks: &[Disk],
-        strategy: ReplicaSelectionStrategy,
-    ) -> Vec<Vec<(Uuid, usize)>> {
-        let mut batches = Vec::new();
-        let mut current_batch = Vec::new();
-        let mut disk_load: std::collections::HashMap<Uuid, usize> = std::collections::HashMap::new();
-
-        // Create references for the selector
-        let disk_refs: Vec<&Disk> = disks.iter().collect();
-
-        // For each fragment, select best replica
-        for i in 0..extent.redundancy.fragment_count() {
-            if let Some((replica_disk, replica_idx)) =
-                ReplicaSelector::select_replica(extent, &disk_refs, strategy)
-            {
-                // Check if we can add to current batch (different disks preferred)
-                let disk_count = disk_load.entry(replica_disk).or_insert(0);
-                if current_batch.len() < self.max_concurrent && *disk_count == 0 {
-                    current_batch.push((replica_disk, replica_idx));
-                    *disk_count += 1;
-                } else {
-                    // Start new batch
-                    if !current_batch.is_empty() {
-                        batches.push(current_batch.clone());
-                        current_batch.clear();
-                        disk_load.clear();
-                    }
-                    current_batch.push((replica_disk, replica_idx));
-                    disk_load.insert(replica_disk, 1);
-                }
-            }
-        }
-
-        if !current_batch.is_empty() {
-            batches.push(current_batch);
-        }
-
-        batches
-    }
+/// Order in which to try reading the fragments of `extent`
+///
+/// `holders[i]` is the disk holding fragment `i`, if it can be read at all.
+/// Fragments on Suspect disks come last, then the parity shards of an
+/// erasure-coded extent, so they are only read when a data shard fails;
+/// within each group the disk expected to be fastest goes first.
+pub fn fragment_read_order(extent: &Extent, holders: &[Option<&Disk>]) -> Vec<usize> {
+    let data_shards = match extent.redundancy {
+        RedundancyPolicy::ErasureCoding { data_shards, .. } => data_shards,
+        RedundancyPolicy::Replication { .. } => holders.len(),
+    };
+    let mut order: Vec<(bool, bool, Duration, usize)> = holders
+        .iter()
+        .enumerate()
+        .filter_map(|(index, disk)| {
+            let disk = (*disk)?;
+            Some((disk.health == DiskHealth::Suspect, index >= data_shards, expected_read_latency(disk), index))
+        })
+        .collect();
+    order.sort();
+    order.into_iter().map(|(_, _, _, index)| index).collect()
 }
 
 /// Write scheduler for optimized fragment placement
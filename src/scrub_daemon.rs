@@ -2,6 +2,7 @@ use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use uuid::Uuid;
+use crate::scrubber::ScrubPassProgress;
 
 /// Background scrubber daemon for continuous verification
 pub struct ScrubDaemon {
@@ -14,6 +15,8 @@ pub struct ScrubDaemon {
     issues_found: Arc<AtomicU64>,
     repairs_triggered: Arc<AtomicU64>,
     scrub_io_bytes: Arc<AtomicU64>,
+    /// Counters of the current or last scrub pass
+    pass: Arc<ScrubPassProgress>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -40,6 +43,15 @@ impl ScrubIntensity {
         }
     }
 
+    /// Default read budget of a scrub pass; `None` is unlimited
+    pub fn io_budget_bytes_per_sec(&self) -> Option<u64> {
+        match self {
+            ScrubIntensity::Low => Some(20 * 1024 * 1024),
+            ScrubIntensity::Medium => Some(100 * 1024 * 1024),
+            ScrubIntensity::High => None,
+        }
+    }
+
     pub fn priority(&self) -> u32 {
         match self {
             ScrubIntensity::Low => 10,
@@ -121,9 +133,15 @@ impl ScrubDaemon {
             issues_found: Arc::new(AtomicU64::new(0)),
             repairs_triggered: Arc::new(AtomicU64::new(0)),
             scrub_io_bytes: Arc::new(AtomicU64::new(0)),
+            pass: Arc::new(ScrubPassProgress::default()),
         }
     }
 
+    /// Counters for `Scrubber::scrub_pool` to update; `get_progress` reports from them
+    pub fn pass_progress(&self) -> Arc<ScrubPassProgress> {
+        Arc::clone(&self.pass)
+    }
+
     /// Start the scrub daemon
     pub fn start(&self, schedule: ScrubSchedule) -> anyhow::Result<()> {
         if self.running.load(Ordering::Relaxed) {
@@ -189,21 +207,36 @@ impl ScrubDaemon {
     }
 
     /// Get current progress
+    ///
+    /// Once a pass has started, totals, counts and the ETA come from its counters.
     pub fn get_progress(&self) -> ScrubProgress {
+        let status = if self.is_paused() {
+            ScrubStatus::Paused
+        } else if self.is_running() {
+            ScrubStatus::Running
+        } else {
+            ScrubStatus::Idle
+        };
+        let now = std::time::SystemTime::now();
+        if self.pass.extents_total() > 0 {
+            return ScrubProgress {
+                status,
+                extents_total: self.pass.extents_total(),
+                extents_scanned: self.pass.extents_done(),
+                issues_found: self.pass.issues_found(),
+                repairs_triggered: self.pass.repairs(),
+                start_time: now - self.pass.elapsed(),
+                estimated_completion: now + self.pass.eta().unwrap_or(Duration::from_secs(3600)),
+            };
+        }
         ScrubProgress {
-            status: if self.is_paused() {
-                ScrubStatus::Paused
-            } else if self.is_running() {
-                ScrubStatus::Running
-            } else {
-                ScrubStatus::Idle
-            },
-            extents_total: 0, // Would be populated from metadata
+            status,
+            extents_total: 0,
             extents_scanned: self.extents_scanned.load(Ordering::Relaxed),
             issues_found: self.issues_found.load(Ordering::Relaxed),
             repairs_triggered: self.repairs_triggered.load(Ordering::Relaxed),
-            start_time: std::time::SystemTime::now(),
-            estimated_completion: std::time::SystemTime::now() + Duration::from_secs(3600),
+            start_time: now,
+            estimated_completion: now + Duration::from_secs(3600),
         }
     }
 
@@ -1,11 +1,18 @@
 use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use crate::disk::Disk;
-use crate::extent::Extent;
+use crate::disk::{Disk, DiskHealth};
+use crate::extent::{Extent, RedundancyPolicy};
+use crate::io_scheduler::IoClass;
 use crate::metadata::MetadataManager;
 use crate::placement::PlacementEngine;
 use crate::redundancy;
+use crate::scrub_daemon::ScrubIntensity;
 
 /// Scrubber performs online verification and repair
 pub struct Scrubber {
@@ -13,16 +20,21 @@ pub struct Scrubber {
 }
 
 /// Result of a scrub operation on a single extent
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ScrubResult {
     pub extent_uuid: Uuid,
     pub status: ScrubStatus,
-    pub issues: Vec<String>,
+    pub issues: Vec<ScrubIssue>,
     pub repairs_attempted: usize,
     pub repairs_successful: usize,
+    /// Fragments whose bytes failed their checksum, with the disk that returned them
+    pub corrupt_fragments: Vec<(usize, Uuid)>,
+    /// Fragment indices with no intact copy: missing, unreadable or corrupt
+    pub missing_fragments: Vec<usize>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ScrubStatus {
     Healthy,       // All fragments OK, checksums verified
     Degraded,      // Some fragments missing but readable
@@ -30,6 +42,61 @@ pub enum ScrubStatus {
     Unrecoverable, // Cannot read or repair
 }
 
+/// What is wrong with an extent, or with one of its fragments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Fewer fragments recorded than the policy calls for
+    MissingFragments,
+    /// Recorded on a disk the pool no longer has
+    MissingDisk,
+    /// Recorded on a disk marked Failed
+    FailedDisk,
+    /// Recorded in metadata but absent from its disk
+    FragmentNotOnDisk,
+    ReadFailed,
+    /// Bytes do not match the fragment's recorded checksum
+    ChecksumMismatch,
+    /// Replica bytes differ from the authoritative copy
+    ReplicaMismatch,
+    /// Shard differs from re-encoding the decoded data
+    ShardMismatch,
+    /// Too few intact fragments to decode
+    Unreadable,
+    DecodeFailed,
+    /// Decoded data does not match the extent checksum
+    ExtentChecksumMismatch,
+    Repaired,
+    RepairFailed,
+}
+
+/// One finding of a scrub; fragment findings name the fragment and its disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScrubIssue {
+    pub kind: IssueKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fragment_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_uuid: Option<Uuid>,
+    pub message: String,
+}
+
+impl ScrubIssue {
+    fn extent(kind: IssueKind, message: String) -> Self {
+        ScrubIssue { kind, fragment_index: None, disk_uuid: None, message }
+    }
+
+    fn fragment(kind: IssueKind, fragment_index: usize, disk_uuid: Uuid, message: String) -> Self {
+        ScrubIssue { kind, fragment_index: Some(fragment_index), disk_uuid: Some(disk_uuid), message }
+    }
+}
+
+impl std::fmt::Display for ScrubIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
 impl Scrubber {
     pub fn new(metadata_dir: std::path::PathBuf) -> Self {
         Scrubber { metadata_dir }
@@ -48,6 +115,8 @@ impl Scrubber {
             issues: Vec::new(),
             repairs_attempted: 0,
             repairs_successful: 0,
+            corrupt_fragments: Vec::new(),
+            missing_fragments: Vec::new(),
         };
 
         // Check 1: Fragment count vs policy
@@ -55,78 +124,218 @@ impl Scrubber {
         let available_fragments = extent.fragment_locations.len();
         
         if available_fragments < expected_fragments {
-            result.issues.push(format!(
-                "Missing fragments: have {}, expected {}",
-                available_fragments, expected_fragments
+            result.issues.push(ScrubIssue::extent(
+                IssueKind::MissingFragments,
+                format!("Missing fragments: have {}, expected {}", available_fragments, expected_fragments),
             ));
             if available_fragments < extent.redundancy.min_fragments() {
+                result.missing_fragments = (0..expected_fragments)
+                    .filter(|index| !extent.fragment_locations.iter().any(|l| l.fragment_index == *index))
+                    .collect();
                 result.status = ScrubStatus::Unrecoverable;
                 return Ok(result);
             }
             result.status = ScrubStatus::Degraded;
         }
 
-        // Check 2: Verify checksum on readable data
+        // Check 2: Read every fragment from its disk and verify it on its own
         let mut fragments = vec![None; expected_fragments];
-        let mut readable_count = 0;
+        // Disk each intact fragment came from, and whether it had a checksum to pass
+        let mut sources: Vec<Option<(Uuid, bool)>> = vec![None; expected_fragments];
 
         for location in &extent.fragment_locations {
-            if let Some(disk) = disks.iter().find(|d| d.uuid == location.disk_uuid) {
-                let data_result = if let Some(ref placement) = location.on_device {
-                    // Block device: use placement information
-                    disk.read_fragment_at_placement(placement)
-                } else {
-                    // Regular disk: use file-based reading
-                    disk.read_fragment(&extent.uuid, location.fragment_index)
-                };
+            let index = location.fragment_index;
+            let Some(disk) = disks.iter().find(|d| d.uuid == location.disk_uuid) else {
+                result.issues.push(ScrubIssue::fragment(
+                    IssueKind::MissingDisk,
+                    index,
+                    location.disk_uuid,
+                    format!("Fragment {} on missing disk {}", index, location.disk_uuid),
+                ));
+                continue;
+            };
+            // Reads skip failed disks, so their fragments are as good as gone
+            if disk.health == DiskHealth::Failed {
+                result.issues.push(ScrubIssue::fragment(
+                    IssueKind::FailedDisk,
+                    index,
+                    disk.uuid,
+                    format!("Fragment {} on failed disk {}", index, disk.uuid),
+                ));
+                continue;
+            }
+            let data_result = if let Some(ref placement) = location.on_device {
+                // Block device: use placement information
+                disk.read_fragment_at_placement(placement)
+            } else {
+                // Regular disk: use file-based reading
+                disk.read_fragment(&extent.uuid, index)
+            };
 
-                match data_result {
-                    Ok(data) => {
-                        fragments[location.fragment_index] = Some(data);
-                        readable_count += 1;
-                    }
-                    Err(e) => {
-                        result.issues.push(format!(
-                            "Failed to read fragment {}: {}",
-                            location.fragment_index, e
-                        ));
-                    }
+            match data_result {
+                Ok(data) if !location.verify_checksum(&data) => {
+                    result.issues.push(ScrubIssue::fragment(
+                        IssueKind::ChecksumMismatch,
+                        index,
+                        disk.uuid,
+                        format!("Fragment {} on disk {} failed checksum", index, disk.uuid),
+                    ));
+                    result.corrupt_fragments.push((index, disk.uuid));
+                }
+                Ok(data) => {
+                    fragments[index] = Some(data);
+                    sources[index] = Some((disk.uuid, location.checksum.is_some()));
+                }
+                Err(_) if location.on_device.is_none() && !disk.has_fragment(&extent.uuid, index) => {
+                    result.issues.push(ScrubIssue::fragment(
+                        IssueKind::FragmentNotOnDisk,
+                        index,
+                        disk.uuid,
+                        format!("Fragment {} is recorded on disk {} but not there", index, disk.uuid),
+                    ));
+                }
+                Err(e) => {
+                    result.issues.push(ScrubIssue::fragment(
+                        IssueKind::ReadFailed,
+                        index,
+                        disk.uuid,
+                        format!("Failed to read fragment {} from disk {}: {}", index, disk.uuid, e),
+                    ));
                 }
-            } else {
-                result.issues.push(format!(
-                    "Fragment {} on missing disk {}",
-                    location.fragment_index, location.disk_uuid
-                ));
             }
         }
 
+        if let RedundancyPolicy::Replication { .. } = extent.redundancy {
+            Self::check_replicas(extent, &mut fragments, &sources, &mut result);
+        }
+
+        result.missing_fragments = (0..expected_fragments).filter(|index| fragments[*index].is_none()).collect();
+        if !result.missing_fragments.is_empty() && result.status == ScrubStatus::Healthy {
+            result.status = ScrubStatus::Degraded;
+        }
+
+        let readable_count = fragments.iter().flatten().count();
         if readable_count < extent.redundancy.min_fragments() {
             result.status = ScrubStatus::Unrecoverable;
-            result.issues.push(format!(
-                "Not enough readable fragments to decode: {}/{}",
-                readable_count,
-                extent.redundancy.min_fragments()
+            result.issues.push(ScrubIssue::extent(
+                IssueKind::Unreadable,
+                format!(
+                    "Not enough readable fragments to decode: {}/{}",
+                    readable_count,
+                    extent.redundancy.min_fragments()
+                ),
             ));
             return Ok(result);
         }
 
+        if !result.corrupt_fragments.is_empty() {
+            result.status = ScrubStatus::Degraded;
+        }
+
         // Check 3: Verify data checksum (if we can decode)
-        match redundancy::decode(&fragments, extent.redundancy) {
-            Ok(data) => {
-                if !extent.verify_checksum(&data[..extent.size]) {
-                    result.issues.push("Checksum verification failed".to_string());
-                    result.status = ScrubStatus::Unrecoverable;
-                }
+        let stored = match redundancy::decode(&fragments, extent.redundancy, extent.stored_size()) {
+            Ok(stored) => stored,
+            Err(e) => {
+                result.issues.push(ScrubIssue::extent(IssueKind::DecodeFailed, format!("Failed to decode extent: {}", e)));
+                result.status = ScrubStatus::Unrecoverable;
+                return Ok(result);
+            }
+        };
+        match extent.unpack(stored.clone()) {
+            Ok(data) if extent.verify_checksum(&data) => {}
+            Ok(_) => {
+                result.issues.push(ScrubIssue::extent(IssueKind::ExtentChecksumMismatch, "Checksum verification failed".to_string()));
+                result.status = ScrubStatus::Unrecoverable;
+                return Ok(result);
             }
             Err(e) => {
-                result.issues.push(format!("Failed to decode extent: {}", e));
+                result.issues.push(ScrubIssue::extent(IssueKind::DecodeFailed, format!("Failed to decode extent: {}", e)));
                 result.status = ScrubStatus::Unrecoverable;
+                return Ok(result);
+            }
+        }
+
+        // Check 4: Every erasure-coded shard must be what encoding the verified data gives
+        if let RedundancyPolicy::ErasureCoding { .. } = extent.redundancy {
+            let expected = redundancy::encode(&stored, extent.redundancy)?;
+            for (index, (fragment, source)) in fragments.iter().zip(&sources).enumerate() {
+                let (Some(fragment), Some((disk_uuid, _))) = (fragment, source) else { continue };
+                if expected.get(index).is_some_and(|shard| blake3::hash(shard) != blake3::hash(fragment)) {
+                    result.issues.push(ScrubIssue::fragment(
+                        IssueKind::ShardMismatch,
+                        index,
+                        *disk_uuid,
+                        format!("Shard {} on disk {} does not match the re-encoded data", index, disk_uuid),
+                    ));
+                    result.corrupt_fragments.push((index, *disk_uuid));
+                    result.missing_fragments.push(index);
+                    result.status = ScrubStatus::Degraded;
+                }
             }
+            result.missing_fragments.sort_unstable();
         }
 
         Ok(result)
     }
 
+    /// Drop replicas that differ from the authoritative copy, reporting them as corrupt
+    ///
+    /// A copy that passed its recorded checksum is authoritative. Without one,
+    /// the bytes most copies agree on are, with ties going to the copy that
+    /// matches the extent checksum.
+    fn check_replicas(
+        extent: &Extent,
+        fragments: &mut [Option<Vec<u8>>],
+        sources: &[Option<(Uuid, bool)>],
+        result: &mut ScrubResult,
+    ) {
+        let hashes: Vec<Option<blake3::Hash>> = fragments.iter().map(|f| f.as_deref().map(blake3::hash)).collect();
+        let mut votes: Vec<(blake3::Hash, usize, usize)> = Vec::new();
+        for (index, hash) in hashes.iter().enumerate() {
+            let Some(hash) = hash else { continue };
+            match votes.iter_mut().find(|(h, _, _)| h == hash) {
+                Some((_, count, _)) => *count += 1,
+                None => votes.push((*hash, 1, index)),
+            }
+        }
+        if votes.len() < 2 {
+            return;
+        }
+        let checksummed = (0..fragments.len()).find(|i| matches!(sources[*i], Some((_, true))));
+        let authoritative = match checksummed {
+            Some(index) => hashes[index].unwrap(),
+            None => {
+                let matches_extent = |index: usize| {
+                    let data = fragments[index].clone().unwrap_or_default();
+                    extent.unpack(data).is_ok_and(|data| extent.verify_checksum(&data))
+                };
+                let most = votes.iter().map(|(_, count, _)| *count).max().unwrap_or(0);
+                let leaders: Vec<_> = votes.iter().filter(|(_, count, _)| *count == most).collect();
+                match leaders.as_slice() {
+                    [(hash, _, _)] => *hash,
+                    _ => match leaders.iter().find(|(_, _, index)| matches_extent(*index)) {
+                        Some((hash, _, _)) => *hash,
+                        // No copy can be trusted over another; decoding will tell
+                        None => return,
+                    },
+                }
+            }
+        };
+        for index in 0..fragments.len() {
+            let (Some(hash), Some((disk_uuid, _))) = (hashes[index], sources[index]) else { continue };
+            if hash != authoritative {
+                result.issues.push(ScrubIssue::fragment(
+                    IssueKind::ReplicaMismatch,
+                    index,
+                    disk_uuid,
+                    format!("Replica {} on disk {} differs from the other copies", index, disk_uuid),
+                ));
+                result.corrupt_fragments.push((index, disk_uuid));
+                fragments[index] = None;
+            }
+        }
+    }
+
     /// Attempt automatic repair of a degraded extent
     /// Conservative: only repairs when safe (min_fragments available)
     /// Idempotent: safe to call multiple times
@@ -134,7 +343,7 @@ impl Scrubber {
         &self,
         extent: &mut Extent,
         metadata: &MetadataManager,
-        disks: &mut [Disk],
+        disks: &[Disk],
         placement: &PlacementEngine,
         fragments: &[Option<Vec<u8>>],
     ) -> Result<ScrubResult> {
@@ -145,10 +354,26 @@ impl Scrubber {
             return Ok(result);
         }
 
+        // The placement engine works on Arc<Mutex<Disk>>; quarantines and
+        // repairs are counted on these copies, and `scrub_pool` recounts usage afterwards
+        let disk_arcs: Vec<std::sync::Arc<std::sync::Mutex<Disk>>> = 
+            disks.iter().map(|d| std::sync::Arc::new(std::sync::Mutex::new(d.clone()))).collect();
+
+        // Corrupt fragments are quarantined and rebuilt like missing ones
+        let mut fragments = fragments.to_vec();
+        for &(fragment_index, disk_uuid) in &result.corrupt_fragments {
+            fragments[fragment_index] = None;
+            if let Some(disk) = disk_arcs.iter().find(|d| d.lock().unwrap().uuid == disk_uuid) {
+                if let Err(e) = disk.lock().unwrap().quarantine_fragment(&extent.uuid, fragment_index) {
+                    log::warn!("Failed to quarantine fragment {} of extent {}: {}", fragment_index, extent.uuid, e);
+                }
+            }
+        }
+
         // Check: Do we have minimum fragments to decode?
         let readable_count = fragments.iter().filter(|f| f.is_some()).count();
         if readable_count < extent.redundancy.min_fragments() {
-            result.issues.push("Insufficient fragments to repair (cannot decode)".to_string());
+            result.issues.push(ScrubIssue::extent(IssueKind::Unreadable, "Insufficient fragments to repair (cannot decode)".to_string()));
             result.status = ScrubStatus::Unrecoverable;
             return Ok(result);
         }
@@ -156,19 +381,16 @@ impl Scrubber {
         result.repairs_attempted += 1;
 
         // Use placement engine's rebuild_extent method for repair
-        // Convert disks to Arc<Mutex<Disk>> format expected by placement engine
-        let disk_arcs: Vec<std::sync::Arc<std::sync::Mutex<Disk>>> = 
-            disks.iter_mut().map(|d| std::sync::Arc::new(std::sync::Mutex::new(d.clone()))).collect();
-        
-        match placement.rebuild_extent(extent, &disk_arcs, fragments) {
+        match placement.rebuild_extent(extent, &disk_arcs, &fragments) {
             Ok(_) => {
+                metadata.save_extent(extent)?;
                 result.repairs_successful += 1;
                 result.status = ScrubStatus::Repaired;
-                result.issues.push("Successfully repaired extent".to_string());
+                result.issues.push(ScrubIssue::extent(IssueKind::Repaired, "Successfully repaired extent".to_string()));
                 log::info!("Successfully repaired extent {}", extent.uuid);
             }
             Err(e) => {
-                result.issues.push(format!("Repair failed: {}", e));
+                result.issues.push(ScrubIssue::extent(IssueKind::RepairFailed, format!("Repair failed: {}", e)));
                 result.status = ScrubStatus::Degraded;
                 log::warn!("Repair attempted but failed for extent {}: {}", extent.uuid, e);
             }
@@ -183,11 +405,18 @@ impl Scrubber {
         disks: &[Disk],
     ) -> Result<Vec<ScrubResult>> {
         log::info!("Starting full scrub of all extents");
+        let _class = IoClass::Scrub.enter();
 
-        let extents = metadata.list_all_extents()?;
         let mut results = Vec::new();
 
-        for extent in extents {
+        for extent in metadata.iter_extents()? {
+            let extent = match extent {
+                Ok(extent) => extent,
+                Err(e) => {
+                    log::error!("Failed to load extent: {:#}", e);
+                    continue;
+                }
+            };
             match self.verify_extent(&extent, metadata, disks) {
                 Ok(result) => {
                     if result.status != ScrubStatus::Healthy {
@@ -210,6 +439,170 @@ impl Scrubber {
         Ok(results)
     }
 
+    /// Read every recorded fragment of an extent, leaving unreadable ones as `None`
+    ///
+    /// Checksums are not verified here; `repair_extent` drops the fragments
+    /// its own verification finds corrupt.
+    pub fn read_fragments(extent: &Extent, disks: &[Disk]) -> Vec<Option<Vec<u8>>> {
+        let mut fragments = vec![None; extent.redundancy.fragment_count()];
+        for location in &extent.fragment_locations {
+            if let Some(disk) = disks.iter().find(|d| d.uuid == location.disk_uuid) {
+                if let Ok(data) = disk.read_fragment(&extent.uuid, location.fragment_index) {
+                    fragments[location.fragment_index] = Some(data);
+                }
+            }
+        }
+        fragments
+    }
+
+    /// Verify, and with `config.repair` repair, every extent in the pool
+    ///
+    /// Extents are read from metadata one at a time, in UUID order, and handed
+    /// out to `config.workers` threads. Fragment reads are scheduled as
+    /// scrub I/O and charged to a shared token bucket of `config.max_bytes_per_sec`. Repairs
+    /// run one at a time so they never race each other in the placement engine.
+    /// Results come back in extent order, as from a single-threaded pass, and
+    /// corruption is charged to `disks` once all workers are done.
+    pub fn scrub_pool(
+        &self,
+        metadata: &MetadataManager,
+        disks: &mut [Disk],
+        config: &ScrubConfig,
+        progress: &ScrubPassProgress,
+    ) -> Result<Vec<ScrubResult>> {
+        let skipped = config.skip.iter().filter(|uuid| metadata.extent_exists(uuid)).count();
+        let total = (metadata.extent_totals().extents as usize).saturating_sub(skipped);
+        progress.begin(total);
+        log::info!("Scrubbing {} extents with {} workers", total, config.workers.max(1));
+
+        let placement = PlacementEngine;
+        let budget = config.max_bytes_per_sec.filter(|rate| *rate > 0).map(TokenBucket::new);
+        let repair_lock = Mutex::new(());
+        // Records in pass order, numbered as they are handed out
+        let records = Mutex::new((0usize, metadata.extent_records_sorted(None)?));
+        let disks_ref: &[Disk] = disks;
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            for _ in 0..config.workers.max(1) {
+                let sender = sender.clone();
+                let (records, budget, repair_lock, placement) = (&records, &budget, &repair_lock, &placement);
+                scope.spawn(move || {
+                    let _class = IoClass::Scrub.enter();
+                    loop {
+                        let (index, uuid, extent) = {
+                            let mut records = records.lock().unwrap();
+                            let Some((uuid, extent)) = records.1.find(|(uuid, _)| !config.skip.contains(uuid)) else {
+                                break;
+                            };
+                            records.0 += 1;
+                            (records.0 - 1, uuid, extent)
+                        };
+                        let extent = match extent {
+                            Ok(extent) => extent,
+                            Err(e) => {
+                                let _ = sender.send((index, uuid, Err(e)));
+                                continue;
+                            }
+                        };
+                        let io_bytes = Self::scrub_io_bytes(&extent);
+                        if let Some(budget) = budget {
+                            budget.acquire(io_bytes);
+                        }
+                        let result = self.scrub_one(&extent, metadata, disks_ref, placement, config.repair, repair_lock);
+                        if let Ok(result) = &result {
+                            progress.record(result, io_bytes);
+                        }
+                        if sender.send((index, uuid, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        drop(sender);
+
+        let mut results: Vec<(usize, ScrubResult)> = Vec::with_capacity(total);
+        for (index, uuid, result) in receiver {
+            match result {
+                Ok(result) => {
+                    if result.status != ScrubStatus::Healthy {
+                        log::warn!("Extent {}: {:?} - {:?}", result.extent_uuid, result.status, result.issues);
+                    }
+                    results.push((index, result));
+                }
+                Err(e) => log::error!("Failed to scrub extent {}: {:#}", uuid, e),
+            }
+        }
+        results.sort_by_key(|(index, _)| *index);
+        let results: Vec<ScrubResult> = results.into_iter().map(|(_, result)| result).collect();
+
+        for result in &results {
+            for disk_uuid in Self::record_corruption(result, disks)? {
+                log::warn!("Disk {} marked Suspect: repeated fragment checksum failures", disk_uuid);
+            }
+        }
+        if config.repair && results.iter().any(|r| r.status != ScrubStatus::Healthy) {
+            for disk in disks.iter_mut() {
+                if let Err(e) = disk.recount_usage() {
+                    log::error!("Failed to recount usage of disk {} after repairs: {}", disk.uuid, e);
+                }
+            }
+        }
+        log::info!("Scrub complete: {} extents verified", results.len());
+        Ok(results)
+    }
+
+    /// Verify one extent and, if it is degraded and `repair` is set, repair it
+    fn scrub_one(
+        &self,
+        extent: &Extent,
+        metadata: &MetadataManager,
+        disks: &[Disk],
+        placement: &PlacementEngine,
+        repair: bool,
+        repair_lock: &Mutex<()>,
+    ) -> Result<ScrubResult> {
+        let result = self.verify_extent(extent, metadata, disks)?;
+        if !repair || result.status != ScrubStatus::Degraded {
+            return Ok(result);
+        }
+
+        let _repairing = repair_lock.lock().unwrap();
+        let mut extent = extent.clone();
+        let fragments = Self::read_fragments(&extent, disks);
+        match self.repair_extent(&mut extent, metadata, disks, placement, &fragments) {
+            Ok(result) => Ok(result),
+            Err(e) => {
+                log::error!("Error repairing extent {}: {}", extent.uuid, e);
+                Ok(result)
+            }
+        }
+    }
+
+    /// Bytes a scrub reads for an extent: one fragment per recorded location
+    fn scrub_io_bytes(extent: &Extent) -> u64 {
+        let shard_bytes = extent.stored_size().div_ceil(extent.redundancy.min_fragments().max(1));
+        (shard_bytes * extent.fragment_locations.len()) as u64
+    }
+
+    /// Charge each corrupt fragment found by a scrub to the disk that returned it
+    ///
+    /// Bumps the disks' corruption counters, which marks a disk Suspect once it
+    /// reaches the threshold. Returns the disks that became Suspect.
+    pub fn record_corruption(result: &ScrubResult, disks: &mut [Disk]) -> Result<Vec<Uuid>> {
+        let mut suspect = Vec::new();
+        for (_, disk_uuid) in &result.corrupt_fragments {
+            if let Some(disk) = disks.iter_mut().find(|d| d.uuid == *disk_uuid) {
+                if disk.record_corruption()? {
+                    log::warn!("Disk {} marked Suspect after {} corrupt fragments", disk.uuid, disk.corruption_count);
+                    suspect.push(disk.uuid);
+                }
+            }
+        }
+        Ok(suspect)
+    }
+
     /// Get scrub statistics
     pub fn stats(results: &[ScrubResult]) -> ScrubStats {
         let mut stats = ScrubStats {
@@ -237,7 +630,7 @@ impl Scrubber {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ScrubStats {
     pub total_extents: usize,
     pub healthy: usize,
@@ -257,3 +650,241 @@ impl std::fmt::Display for ScrubStats {
         )
     }
 }
+
+/// State of one extent of a file, as reported by `verify-file`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ExtentHealth {
+    /// Every fragment present and intact
+    Healthy,
+    /// Readable, but these fragments are missing or corrupt
+    Degraded { missing_fragments: Vec<usize> },
+    /// Was degraded; these fragments have been rebuilt
+    Repaired { rebuilt_fragments: Vec<usize> },
+    /// Enough fragments to decode, but the data does not match its checksum
+    ChecksumFailed,
+    /// Too few fragments left to decode
+    Unreadable { readable: usize, needed: usize },
+}
+
+impl ExtentHealth {
+    /// Classify the result of verifying or repairing `extent`
+    pub fn from_scrub(result: &ScrubResult, extent: &Extent) -> Self {
+        match result.status {
+            ScrubStatus::Healthy => ExtentHealth::Healthy,
+            ScrubStatus::Degraded => ExtentHealth::Degraded { missing_fragments: result.missing_fragments.clone() },
+            ScrubStatus::Repaired => ExtentHealth::Repaired { rebuilt_fragments: result.missing_fragments.clone() },
+            ScrubStatus::Unrecoverable => {
+                let needed = extent.redundancy.min_fragments();
+                let readable = extent.redundancy.fragment_count().saturating_sub(result.missing_fragments.len());
+                if readable < needed {
+                    ExtentHealth::Unreadable { readable, needed }
+                } else {
+                    ExtentHealth::ChecksumFailed
+                }
+            }
+        }
+    }
+
+    /// Whether the extent's data is lost short of a backup
+    pub fn is_unrecoverable(&self) -> bool {
+        matches!(self, ExtentHealth::ChecksumFailed | ExtentHealth::Unreadable { .. })
+    }
+}
+
+/// One extent of a file checked by `StorageEngine::verify_file` or `repair_file`
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtentCheck {
+    /// Slot of the extent in the file; slot N starts at N × extent size
+    pub slot: usize,
+    /// File offset of the slot's first byte
+    pub offset: u64,
+    pub uuid: Uuid,
+    #[serde(flatten)]
+    pub health: ExtentHealth,
+    pub issues: Vec<ScrubIssue>,
+}
+
+/// Every data extent of one file and what verifying it found
+#[derive(Debug, Clone, Serialize)]
+pub struct FileCheckReport {
+    pub ino: u64,
+    pub size: u64,
+    pub extents: Vec<ExtentCheck>,
+}
+
+/// Extents of a `FileCheckReport` in each state
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FileCheckSummary {
+    pub healthy: usize,
+    pub degraded: usize,
+    pub repaired: usize,
+    pub checksum_failed: usize,
+    pub unreadable: usize,
+}
+
+impl FileCheckReport {
+    pub fn summary(&self) -> FileCheckSummary {
+        let mut summary = FileCheckSummary::default();
+        for check in &self.extents {
+            match check.health {
+                ExtentHealth::Healthy => summary.healthy += 1,
+                ExtentHealth::Degraded { .. } => summary.degraded += 1,
+                ExtentHealth::Repaired { .. } => summary.repaired += 1,
+                ExtentHealth::ChecksumFailed => summary.checksum_failed += 1,
+                ExtentHealth::Unreadable { .. } => summary.unreadable += 1,
+            }
+        }
+        summary
+    }
+
+    /// Extents whose data cannot be recovered from the pool
+    pub fn unrecoverable(&self) -> usize {
+        self.extents.iter().filter(|check| check.health.is_unrecoverable()).count()
+    }
+}
+
+/// How a scrub pass is spread over threads and throttled
+#[derive(Debug, Clone)]
+pub struct ScrubConfig {
+    /// Extents verified concurrently
+    pub workers: usize,
+    /// Fragment bytes read per second across all workers; `None` is unlimited
+    pub max_bytes_per_sec: Option<u64>,
+    /// Repair degraded extents
+    pub repair: bool,
+    /// Extents left out of the pass, such as those a mount's recent-write
+    /// verifier is about to read
+    pub skip: HashSet<Uuid>,
+}
+
+impl ScrubConfig {
+    /// One worker per CPU, throttled to the intensity's I/O budget
+    pub fn for_intensity(intensity: ScrubIntensity) -> Self {
+        ScrubConfig {
+            workers: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            max_bytes_per_sec: intensity.io_budget_bytes_per_sec(),
+            repair: false,
+            skip: HashSet::new(),
+        }
+    }
+}
+
+/// Live counters of a scrub pass, shared with progress reporters and the scrub daemon
+#[derive(Debug)]
+pub struct ScrubPassProgress {
+    extents_total: AtomicU64,
+    extents_done: AtomicU64,
+    issues_found: AtomicU64,
+    repairs: AtomicU64,
+    io_bytes: AtomicU64,
+    started: Mutex<Instant>,
+}
+
+impl Default for ScrubPassProgress {
+    fn default() -> Self {
+        ScrubPassProgress {
+            extents_total: AtomicU64::new(0),
+            extents_done: AtomicU64::new(0),
+            issues_found: AtomicU64::new(0),
+            repairs: AtomicU64::new(0),
+            io_bytes: AtomicU64::new(0),
+            started: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl ScrubPassProgress {
+    fn begin(&self, extents_total: usize) {
+        self.extents_total.store(extents_total as u64, Ordering::SeqCst);
+        self.extents_done.store(0, Ordering::SeqCst);
+        self.issues_found.store(0, Ordering::SeqCst);
+        self.repairs.store(0, Ordering::SeqCst);
+        self.io_bytes.store(0, Ordering::SeqCst);
+        *self.started.lock().unwrap() = Instant::now();
+    }
+
+    fn record(&self, result: &ScrubResult, io_bytes: u64) {
+        self.issues_found.fetch_add(result.issues.len() as u64, Ordering::SeqCst);
+        self.repairs.fetch_add(result.repairs_successful as u64, Ordering::SeqCst);
+        self.io_bytes.fetch_add(io_bytes, Ordering::SeqCst);
+        self.extents_done.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn extents_total(&self) -> u64 {
+        self.extents_total.load(Ordering::SeqCst)
+    }
+
+    pub fn extents_done(&self) -> u64 {
+        self.extents_done.load(Ordering::SeqCst)
+    }
+
+    pub fn issues_found(&self) -> u64 {
+        self.issues_found.load(Ordering::SeqCst)
+    }
+
+    pub fn repairs(&self) -> u64 {
+        self.repairs.load(Ordering::SeqCst)
+    }
+
+    pub fn io_bytes(&self) -> u64 {
+        self.io_bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.lock().unwrap().elapsed()
+    }
+
+    /// Remaining time at the rate so far; `None` until an extent is done
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.extents_done();
+        if done == 0 {
+            return None;
+        }
+        let remaining = self.extents_total().saturating_sub(done);
+        Some(self.elapsed().mul_f64(remaining as f64 / done as f64))
+    }
+}
+
+impl std::fmt::Display for ScrubPassProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (done, total) = (self.extents_done(), self.extents_total());
+        let percent = if total > 0 { done as f64 * 100.0 / total as f64 } else { 100.0 };
+        write!(f, "{}/{} extents ({:.1}%), {} issues", done, total, percent, self.issues_found())?;
+        if let Some(eta) = self.eta() {
+            write!(f, ", ETA {}s", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared byte budget refilled at a fixed rate
+///
+/// Callers take what they need and sleep off any debt, so a read larger than
+/// one second's budget still goes through.
+struct TokenBucket {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec as f64;
+        TokenBucket { rate, state: Mutex::new((rate, Instant::now())) }
+    }
+
+    fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, refilled) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * self.rate).min(self.rate);
+            *refilled = now;
+            *tokens -= bytes as f64;
+            if *tokens < 0.0 { -*tokens / self.rate } else { 0.0 }
+        };
+        if wait > 0.0 {
+            std::thread::sleep(Duration::from_secs_f64(wait));
+        }
+    }
+}
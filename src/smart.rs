@@ -0,0 +1,350 @@
+//! SMART samples of block-device disks for predictive health
+//!
+//! A sample is read with `smartctl --json` when it is installed. With the
+//! `smart-ioctl` feature, NVMe devices are also read directly through the
+//! admin command ioctl when it is not. Directory-backed disks are never
+//! sampled: their device is shared with whatever else lives on it.
+//!
+//! Each disk keeps its latest sample in `disk.json`. A new sample is compared
+//! with the previous one by `warnings`; any warning makes a Healthy disk
+//! Suspect, so placement moves off it before it fails outright.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Wear, as a percent of rated endurance used, past which a disk is warned about
+pub const WEAR_WARN_PERCENT: u8 = 90;
+
+/// How a sample was read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmartSource {
+    Smartctl,
+    /// The NVMe admin command ioctl
+    Ioctl,
+}
+
+/// Key SMART attributes of one device; those it does not report are `None`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartSample {
+    /// Unix time the sample was read
+    pub taken_at: i64,
+    pub source: SmartSource,
+    /// The device's own overall health assessment
+    pub passed: Option<bool>,
+    /// ATA attribute 5
+    pub reallocated_sectors: Option<u64>,
+    /// ATA attribute 197
+    pub pending_sectors: Option<u64>,
+    /// NVMe media and data integrity errors
+    pub media_errors: Option<u64>,
+    /// NVMe critical warning bits
+    pub critical_warning: Option<u8>,
+    /// Percent of rated endurance used; may pass 100
+    pub wear_percent_used: Option<u8>,
+    pub temperature_celsius: Option<i64>,
+}
+
+impl SmartSample {
+    pub fn age_secs(&self) -> i64 {
+        (chrono::Utc::now().timestamp() - self.taken_at).max(0)
+    }
+
+    /// One line of the attributes the device reported
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        match self.passed {
+            Some(true) => parts.push("passed".to_string()),
+            Some(false) => parts.push("FAILED".to_string()),
+            None => {}
+        }
+        if let Some(count) = self.reallocated_sectors {
+            parts.push(format!("{} reallocated", count));
+        }
+        if let Some(count) = self.pending_sectors {
+            parts.push(format!("{} pending", count));
+        }
+        if let Some(count) = self.media_errors {
+            parts.push(format!("{} media errors", count));
+        }
+        if let Some(bits) = self.critical_warning.filter(|&bits| bits != 0) {
+            parts.push(format!("critical warning {:#04x}", bits));
+        }
+        if let Some(percent) = self.wear_percent_used {
+            parts.push(format!("{}% worn", percent));
+        }
+        if let Some(celsius) = self.temperature_celsius {
+            parts.push(format!("{}°C", celsius));
+        }
+        if parts.is_empty() {
+            return "no attributes reported".to_string();
+        }
+        parts.join(", ")
+    }
+}
+
+/// Signs of a failing device in `current` that `previous` did not show
+///
+/// Counters only warn when they grow, so a disk that came with a few
+/// reallocated sectors is not flagged again at every poll. Without a previous
+/// sample, any pending sector or critical warning counts as growth.
+pub fn warnings(previous: Option<&SmartSample>, current: &SmartSample) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let before = |field: fn(&SmartSample) -> Option<u64>| previous.and_then(field);
+
+    if current.passed == Some(false) && previous.and_then(|p| p.passed) != Some(false) {
+        warnings.push("SMART overall health assessment failed".to_string());
+    }
+    let grown = |now: Option<u64>, then: Option<u64>| match (now, then) {
+        (Some(now), Some(then)) if now > then => Some((then, now)),
+        _ => None,
+    };
+    if let Some((then, now)) = grown(current.reallocated_sectors, before(|s| s.reallocated_sectors)) {
+        warnings.push(format!("reallocated sectors grew from {} to {}", then, now));
+    }
+    if let Some((then, now)) = grown(current.pending_sectors, Some(before(|s| s.pending_sectors).unwrap_or(0))) {
+        warnings.push(format!("sectors pending reallocation grew from {} to {}", then, now));
+    }
+    if let Some((then, now)) = grown(current.media_errors, before(|s| s.media_errors)) {
+        warnings.push(format!("media errors grew from {} to {}", then, now));
+    }
+    if let Some(bits) = current.critical_warning.filter(|&bits| bits != 0) {
+        if previous.and_then(|p| p.critical_warning) != Some(bits) {
+            warnings.push(format!("critical warning {:#04x}", bits));
+        }
+    }
+    if let Some(percent) = current.wear_percent_used.filter(|&p| p >= WEAR_WARN_PERCENT) {
+        if previous.and_then(|p| p.wear_percent_used).is_none_or(|p| p < WEAR_WARN_PERCENT) {
+            warnings.push(format!("{}% of rated endurance used", percent));
+        }
+    }
+    warnings
+}
+
+/// Read a sample of the block device at `device`
+pub fn read_sample(device: &Path) -> Result<SmartSample> {
+    match read_smartctl(device) {
+        Err(e) if is_not_found(&e) => read_ioctl(device).context("smartctl is not installed"),
+        result => result,
+    }
+}
+
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+fn read_smartctl(device: &Path) -> Result<SmartSample> {
+    let output = std::process::Command::new("smartctl").arg("--json").arg("-a").arg(device).output()?;
+    // Bits 0 and 1 of the status mean the command line or the device open failed;
+    // the others describe the device and still come with its attributes
+    if output.status.code().is_none_or(|code| code & 0b11 != 0) {
+        return Err(anyhow!(
+            "smartctl failed on {}: {}",
+            device.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_smartctl_json(&String::from_utf8_lossy(&output.stdout), chrono::Utc::now().timestamp())
+}
+
+/// Sample from the output of `smartctl --json -a`
+pub fn parse_smartctl_json(json: &str, taken_at: i64) -> Result<SmartSample> {
+    let doc: serde_json::Value = serde_json::from_str(json).context("smartctl printed invalid JSON")?;
+    let ata_attribute = |id: u64| {
+        doc["ata_smart_attributes"]["table"]
+            .as_array()?
+            .iter()
+            .find(|attr| attr["id"].as_u64() == Some(id))
+            .cloned()
+    };
+    let raw = |id: u64| ata_attribute(id).and_then(|attr| attr["raw"]["value"].as_u64());
+    // Wear leveling count (177) or media wearout indicator (233) count down from 100
+    let ata_wear = || {
+        [177, 233]
+            .into_iter()
+            .find_map(|id| ata_attribute(id).and_then(|attr| attr["value"].as_u64()))
+            .map(|remaining| 100u64.saturating_sub(remaining))
+    };
+    let nvme = &doc["nvme_smart_health_information_log"];
+
+    Ok(SmartSample {
+        taken_at,
+        source: SmartSource::Smartctl,
+        passed: doc["smart_status"]["passed"].as_bool(),
+        reallocated_sectors: raw(5),
+        pending_sectors: raw(197),
+        media_errors: nvme["media_errors"].as_u64(),
+        critical_warning: nvme["critical_warning"].as_u64().map(|bits| bits as u8),
+        wear_percent_used: nvme["percentage_used"]
+            .as_u64()
+            .or_else(|| doc["endurance_used"]["current_percent"].as_u64())
+            .or_else(ata_wear)
+            .map(|percent| percent.min(u8::MAX as u64) as u8),
+        temperature_celsius: doc["temperature"]["current"].as_i64(),
+    })
+}
+
+#[cfg(not(all(feature = "smart-ioctl", target_os = "linux")))]
+fn read_ioctl(device: &Path) -> Result<SmartSample> {
+    Err(anyhow!("No SMART source for {}; install smartmontools", device.display()))
+}
+
+/// Read the SMART / health information log page of an NVMe device
+#[cfg(all(feature = "smart-ioctl", target_os = "linux"))]
+fn read_ioctl(device: &Path) -> Result<SmartSample> {
+    use std::os::unix::io::AsRawFd;
+
+    /// `struct nvme_admin_cmd` of linux/nvme_ioctl.h
+    #[repr(C)]
+    #[derive(Default)]
+    struct NvmeAdminCmd {
+        opcode: u8,
+        flags: u8,
+        rsvd1: u16,
+        nsid: u32,
+        cdw2: u32,
+        cdw3: u32,
+        metadata: u64,
+        addr: u64,
+        metadata_len: u32,
+        data_len: u32,
+        cdw10: u32,
+        cdw11: u32,
+        cdw12: u32,
+        cdw13: u32,
+        cdw14: u32,
+        cdw15: u32,
+        timeout_ms: u32,
+        result: u32,
+    }
+    /// `_IOWR('N', 0x41, struct nvme_admin_cmd)`
+    const NVME_IOCTL_ADMIN_CMD: libc::c_ulong = 0xC048_4E41;
+    const GET_LOG_PAGE: u8 = 0x02;
+    const SMART_LOG: u32 = 0x02;
+    const LOG_LEN: usize = 512;
+
+    let is_nvme = device.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("nvme"));
+    if !is_nvme {
+        return Err(anyhow!("No SMART source for {}; install smartmontools", device.display()));
+    }
+    let file = std::fs::File::open(device).with_context(|| format!("Failed to open {}", device.display()))?;
+    let mut log = [0u8; LOG_LEN];
+    let mut cmd = NvmeAdminCmd {
+        opcode: GET_LOG_PAGE,
+        nsid: u32::MAX,
+        addr: log.as_mut_ptr() as u64,
+        data_len: LOG_LEN as u32,
+        // Number of dwords minus one in the upper half
+        cdw10: SMART_LOG | (((LOG_LEN / 4 - 1) as u32) << 16),
+        ..Default::default()
+    };
+    if unsafe { libc::ioctl(file.as_raw_fd(), NVME_IOCTL_ADMIN_CMD, &mut cmd) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("NVMe get log page failed");
+    }
+
+    let kelvin = u16::from_le_bytes([log[1], log[2]]) as i64;
+    let mut media_errors = [0u8; 8];
+    media_errors.copy_from_slice(&log[160..168]);
+    Ok(SmartSample {
+        taken_at: chrono::Utc::now().timestamp(),
+        source: SmartSource::Ioctl,
+        passed: Some(log[0] == 0),
+        reallocated_sectors: None,
+        pending_sectors: None,
+        media_errors: Some(u64::from_le_bytes(media_errors)),
+        critical_warning: Some(log[0]),
+        wear_percent_used: Some(log[5]),
+        temperature_celsius: (kelvin > 0).then_some(kelvin - 273),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ATA: &str = r#"{
+        "smart_status": {"passed": true},
+        "temperature": {"current": 36},
+        "ata_smart_attributes": {"table": [
+            {"id": 5, "name": "Reallocated_Sector_Ct", "value": 100, "raw": {"value": 8}},
+            {"id": 177, "name": "Wear_Leveling_Count", "value": 93, "raw": {"value": 70}},
+            {"id": 197, "name": "Current_Pending_Sector", "value": 100, "raw": {"value": 0}}
+        ]}
+    }"#;
+
+    const NVME: &str = r#"{
+        "smart_status": {"passed": true},
+        "temperature": {"current": 41},
+        "nvme_smart_health_information_log": {
+            "critical_warning": 0, "temperature": 41, "percentage_used": 12, "media_errors": 3
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_smartctl_ata_and_nvme() {
+        let ata = parse_smartctl_json(ATA, 100).unwrap();
+        assert_eq!(ata.passed, Some(true));
+        assert_eq!((ata.reallocated_sectors, ata.pending_sectors), (Some(8), Some(0)));
+        assert_eq!(ata.wear_percent_used, Some(7));
+        assert_eq!(ata.temperature_celsius, Some(36));
+        assert_eq!(ata.media_errors, None);
+
+        let nvme = parse_smartctl_json(NVME, 100).unwrap();
+        assert_eq!((nvme.media_errors, nvme.critical_warning), (Some(3), Some(0)));
+        assert_eq!(nvme.wear_percent_used, Some(12));
+        assert_eq!(nvme.reallocated_sectors, None);
+        assert_eq!(nvme.summary(), "passed, 3 media errors, 12% worn, 41°C");
+    }
+
+    #[test]
+    fn test_warnings_only_on_growth() {
+        let first = parse_smartctl_json(ATA, 100).unwrap();
+        // Sectors reallocated before the first poll are not a warning by themselves
+        assert!(warnings(None, &first).is_empty());
+        assert!(warnings(Some(&first), &first).is_empty());
+
+        let mut worse = first.clone();
+        worse.reallocated_sectors = Some(12);
+        worse.pending_sectors = Some(2);
+        let found = warnings(Some(&first), &worse);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].contains("from 8 to 12"));
+
+        let mut nvme = parse_smartctl_json(NVME, 100).unwrap();
+        let before = nvme.clone();
+        nvme.media_errors = Some(4);
+        nvme.wear_percent_used = Some(95);
+        nvme.passed = Some(false);
+        assert_eq!(warnings(Some(&before), &nvme).len(), 3);
+        // Already reported, so the next identical sample is quiet
+        assert!(warnings(Some(&nvme), &nvme).is_empty());
+    }
+
+    #[test]
+    fn test_growing_reallocations_make_disk_suspect() {
+        use crate::disk::{Disk, DiskHealth};
+        use crate::logging::{EventKind, EventRing};
+        use std::sync::Arc;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut disk = Disk::new(dir.path().to_path_buf()).unwrap();
+        let events = Arc::new(EventRing::new());
+        disk.events = Some(Arc::clone(&events));
+
+        let first = parse_smartctl_json(ATA, 100).unwrap();
+        assert!(disk.record_smart(first.clone()).unwrap().is_empty());
+        assert_eq!(disk.health, DiskHealth::Healthy);
+
+        let mut worse = first;
+        worse.taken_at = 200;
+        worse.reallocated_sectors = Some(40);
+        assert_eq!(disk.record_smart(worse.clone()).unwrap().len(), 1);
+        assert_eq!(disk.health, DiskHealth::Suspect);
+        let kinds: Vec<EventKind> = events.since(0).into_iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![EventKind::SmartWarning, EventKind::DiskHealth]);
+
+        // The sample is kept in disk.json
+        assert_eq!(Disk::load(dir.path()).unwrap().smart, Some(worse));
+    }
+}
@@ -1,8 +1,422 @@
-use std::collections::HashMap;
+//! Point-in-time snapshots of the pool's namespace
+//!
+//! `StorageEngine::create_snapshot` captures every inode reachable from the
+//! root, with the extent maps of the regular files, into
+//! `snapshots/<id>/tree.json`. Each extent it lists is then recorded as held
+//! by the snapshot under `snapshots/held/<uuid>`, and adding the snapshot to
+//! `snapshots/index.json` commits it. An extent a committed snapshot holds is
+//! never freed; see `holds`. A deleted snapshot leaves its empty directory
+//! behind so its id is never handed out again.
+//!
+//! Mounted pools show each snapshot read-only under `/.snapshots/<name>`. The
+//! inodes there carry the snapshot id above `SNAPSHOT_ID_SHIFT`, so they never
+//! collide with live inode numbers.
+//!
+//! The index is read and rewritten whole, so listing, creating and deleting
+//! snapshots take time in proportion to how many a pool has, at most
+//! `MAX_SNAPSHOT_ID`. Creating or deleting one also writes a record for each
+//! extent it captured.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use anyhow::{anyhow, Context, Result};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use crate::extent::Extent;
+use crate::metadata::{ExtentMap, Inode};
+
+/// Directory in the pool holding captured snapshots
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+/// Name of the virtual directory in the root that lists the snapshots
+pub const SNAPSHOTS_DIR_NAME: &str = ".snapshots";
+/// Bits of an inode number below the snapshot id
+pub const SNAPSHOT_ID_SHIFT: u32 = 48;
+/// Highest id a snapshot can get; the one above is the `.snapshots` directory's
+///
+/// Inode numbers stay below 2^63, as readdir cookies are derived from them and are signed.
+pub const MAX_SNAPSHOT_ID: u16 = 0x7FFE;
+/// Inode number of the `.snapshots` directory
+pub const SNAPSHOTS_DIR_INO: u64 = snapshot_ino(MAX_SNAPSHOT_ID + 1, 1);
+
+/// Inode `ino` of the tree captured by snapshot `id`
+///
+/// Id 0 is the live tree, so `snapshot_ino(0, ino) == ino`.
+pub const fn snapshot_ino(id: u16, ino: u64) -> u64 {
+    ((id as u64) << SNAPSHOT_ID_SHIFT) | ino
+}
+
+/// The snapshot id and captured inode number of `ino`; id 0 for live inodes
+pub const fn split_snapshot_ino(ino: u64) -> (u16, u64) {
+    ((ino >> SNAPSHOT_ID_SHIFT) as u16, ino & ((1 << SNAPSHOT_ID_SHIFT) - 1))
+}
+
+/// Whether `ino` is under `/.snapshots`, including the directory itself
+pub const fn is_snapshot_ino(ino: u64) -> bool {
+    ino >> SNAPSHOT_ID_SHIFT != 0
+}
+
+/// A committed snapshot, as listed by `dynamicfs snapshot list`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub id: u16,
+    pub name: String,
+    pub created_at: i64,
+    /// Regular files captured
+    pub files: u64,
+    /// Bytes those files held
+    pub bytes: u64,
+}
+
+impl SnapshotInfo {
+    /// Reject names that are empty, hidden or not a single path component
+    pub fn check_name(name: &str) -> Result<()> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+            return Err(anyhow!("Invalid snapshot name: {:?}", name));
+        }
+        Ok(())
+    }
+
+    /// Every committed snapshot, oldest first
+    pub fn list(pool_dir: &Path) -> Result<Vec<Self>> {
+        match std::fs::read(index_path(pool_dir)) {
+            Ok(contents) => serde_json::from_slice(&contents).context("Corrupted snapshot index"),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e).context("Failed to read the snapshot index"),
+        }
+    }
+
+    /// The committed snapshot called `name`
+    pub fn find(pool_dir: &Path, name: &str) -> Result<Self> {
+        Self::list(pool_dir)?
+            .into_iter()
+            .find(|info| info.name == name)
+            .ok_or_else(|| anyhow!("No snapshot named {:?}", name))
+    }
+
+    /// Add the snapshot to the index, which commits it
+    pub(crate) fn commit(&self, pool_dir: &Path) -> Result<()> {
+        let mut snapshots = Self::list(pool_dir)?;
+        snapshots.push(self.clone());
+        write_synced(&index_path(pool_dir), &serde_json::to_vec_pretty(&snapshots)?)
+    }
+
+    /// Drop the snapshot from the index, after which it holds nothing
+    pub(crate) fn uncommit(&self, pool_dir: &Path) -> Result<()> {
+        let mut snapshots = Self::list(pool_dir)?;
+        snapshots.retain(|info| info.id != self.id);
+        write_synced(&index_path(pool_dir), &serde_json::to_vec_pretty(&snapshots)?)
+    }
+}
+
+/// Id for the next snapshot: above every one taken, including deleted ones
+pub(crate) fn next_snapshot_id(pool_dir: &Path) -> Result<u16> {
+    let next = snapshot_ids(pool_dir)?.last().map_or(1, |id| id + 1);
+    if next > MAX_SNAPSHOT_ID {
+        return Err(anyhow!("The pool has used all {} snapshot ids", MAX_SNAPSHOT_ID));
+    }
+    Ok(next)
+}
+
+fn snapshot_ids(pool_dir: &Path) -> Result<Vec<u16>> {
+    let entries = match std::fs::read_dir(pool_dir.join(SNAPSHOTS_DIR)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut ids = Vec::new();
+    for entry in entries {
+        if let Ok(id) = entry?.file_name().to_string_lossy().parse::<u16>() {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+fn snapshot_dir(pool_dir: &Path, id: u16) -> PathBuf {
+    pool_dir.join(SNAPSHOTS_DIR).join(id.to_string())
+}
+
+fn index_path(pool_dir: &Path) -> PathBuf {
+    pool_dir.join(SNAPSHOTS_DIR).join("index.json")
+}
+
+fn tree_path(pool_dir: &Path, id: u16) -> PathBuf {
+    snapshot_dir(pool_dir, id).join("tree.json")
+}
+
+fn held_dir(pool_dir: &Path) -> PathBuf {
+    pool_dir.join(SNAPSHOTS_DIR).join("held")
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<()> {
+    write_unsynced_dir(path, contents)?;
+    if let Some(parent) = path.parent() {
+        std::fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Replace `path` through a synced temporary file, leaving the directory to the caller
+fn write_unsynced_dir(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Snapshots recorded as holding extent `uuid`, committed or not
+fn holders(pool_dir: &Path, uuid: &Uuid) -> Result<Vec<u16>> {
+    match std::fs::read(held_dir(pool_dir).join(uuid.to_string())) {
+        Ok(contents) => serde_json::from_slice(&contents).with_context(|| format!("Corrupted holders of extent {}", uuid)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Record snapshot `id` as a holder of `extents`, before it is committed
+pub(crate) fn hold(pool_dir: &Path, id: u16, extents: &HashSet<Uuid>) -> Result<()> {
+    let dir = held_dir(pool_dir);
+    std::fs::create_dir_all(&dir)?;
+    for uuid in extents {
+        let mut ids = holders(pool_dir, uuid)?;
+        if !ids.contains(&id) {
+            ids.push(id);
+            write_unsynced_dir(&dir.join(uuid.to_string()), &serde_json::to_vec(&ids)?)?;
+        }
+    }
+    std::fs::File::open(&dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Drop snapshot `id` from the holders of `extents`, once it is uncommitted
+pub(crate) fn unhold(pool_dir: &Path, id: u16, extents: &HashSet<Uuid>) -> Result<()> {
+    let dir = held_dir(pool_dir);
+    for uuid in extents {
+        let mut ids = holders(pool_dir, uuid)?;
+        ids.retain(|holder| *holder != id);
+        let path = dir.join(uuid.to_string());
+        if ids.is_empty() {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        } else {
+            write_unsynced_dir(&path, &serde_json::to_vec(&ids)?)?;
+        }
+    }
+    if dir.exists() {
+        std::fs::File::open(&dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Whether a committed snapshot holds extent `uuid`, so it must not be freed
+///
+/// A record that cannot be read counts as held: keeping an extent only costs space.
+pub fn holds(pool_dir: &Path, uuid: &Uuid) -> bool {
+    let held = || -> Result<bool> {
+        let ids = holders(pool_dir, uuid)?;
+        if ids.is_empty() {
+            return Ok(false);
+        }
+        Ok(SnapshotInfo::list(pool_dir)?.iter().any(|info| ids.contains(&info.id)))
+    };
+    held().unwrap_or_else(|e| {
+        log::error!("Keeping extent {}, as its snapshot holders cannot be read: {:#}", uuid, e);
+        true
+    })
+}
+
+/// Extents held by any committed snapshot
+///
+/// Reads the record of every held extent, so it costs a file read per extent
+/// the snapshots captured; only the offline check calls it.
+pub fn captured_extents(pool_dir: &Path) -> Result<HashSet<Uuid>> {
+    let committed: HashSet<u16> = SnapshotInfo::list(pool_dir)?.iter().map(|info| info.id).collect();
+    let entries = match std::fs::read_dir(held_dir(pool_dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut extents = HashSet::new();
+    for entry in entries {
+        // Leftover temporary files do not parse
+        let Ok(uuid) = Uuid::parse_str(&entry?.file_name().to_string_lossy()) else {
+            continue;
+        };
+        if holders(pool_dir, &uuid)?.iter().any(|id| committed.contains(id)) {
+            extents.insert(uuid);
+        }
+    }
+    Ok(extents)
+}
+
+
+/// Inodes and extent maps captured by a snapshot, by captured inode number
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SnapshotTree {
+    pub inodes: BTreeMap<u64, Inode>,
+    pub extent_maps: BTreeMap<u64, ExtentMap>,
+    #[serde(skip)]
+    children: HashMap<u64, Vec<u64>>,
+}
+
+impl SnapshotTree {
+    pub fn new(inodes: BTreeMap<u64, Inode>, extent_maps: BTreeMap<u64, ExtentMap>) -> Self {
+        let mut tree = SnapshotTree { inodes, extent_maps, children: HashMap::new() };
+        tree.index_children();
+        tree
+    }
+
+    pub fn load(pool_dir: &Path, id: u16) -> Result<Self> {
+        let contents = std::fs::read(tree_path(pool_dir, id)).with_context(|| format!("Failed to read snapshot {}", id))?;
+        let mut tree: SnapshotTree =
+            serde_json::from_slice(&contents).with_context(|| format!("Corrupted tree of snapshot {}", id))?;
+        tree.index_children();
+        Ok(tree)
+    }
+
+    /// Write the tree of snapshot `id`; it only counts once `SnapshotInfo::commit` runs
+    pub(crate) fn save(&self, pool_dir: &Path, id: u16) -> Result<()> {
+        write_synced(&tree_path(pool_dir, id), &serde_json::to_vec(self)?)
+    }
+
+    /// Drop the tree of a deleted snapshot, once it is uncommitted
+    pub(crate) fn remove(pool_dir: &Path, id: u16) -> Result<()> {
+        match std::fs::remove_file(tree_path(pool_dir, id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn index_children(&mut self) {
+        self.children.clear();
+        for inode in self.inodes.values().filter(|inode| inode.ino != inode.parent_ino) {
+            self.children.entry(inode.parent_ino).or_default().push(inode.ino);
+        }
+    }
+
+    pub fn inode(&self, ino: u64) -> Option<&Inode> {
+        self.inodes.get(&ino)
+    }
+
+    /// The captured map of `ino`; files without one read as holes
+    pub fn extent_map(&self, ino: u64) -> Option<&ExtentMap> {
+        self.extent_maps.get(&ino)
+    }
+
+    pub fn children(&self, ino: u64) -> impl Iterator<Item = &Inode> {
+        self.children.get(&ino).into_iter().flatten().filter_map(|child| self.inodes.get(child))
+    }
+
+    /// Extents the captured maps list, each once
+    pub fn extents(&self) -> HashSet<Uuid> {
+        self.extent_maps.values().flat_map(|map| map.data_extents().copied()).collect()
+    }
+}
+
+/// A committed snapshot with its tree, as `/.snapshots/<name>` shows it
+#[derive(Debug)]
+pub struct LoadedSnapshot {
+    pub info: SnapshotInfo,
+    pub tree: SnapshotTree,
+}
+
+impl LoadedSnapshot {
+    /// Captured inode `ino` with its inode numbers moved into the snapshot's range
+    ///
+    /// The captured root becomes the directory named after the snapshot.
+    pub fn view_inode(&self, ino: u64) -> Option<Inode> {
+        let mut inode = self.tree.inode(ino)?.clone();
+        inode.ino = snapshot_ino(self.info.id, ino);
+        if ino == 1 {
+            inode.parent_ino = SNAPSHOTS_DIR_INO;
+            inode.name = self.info.name.as_str().into();
+        } else {
+            inode.parent_ino = snapshot_ino(self.info.id, inode.parent_ino);
+        }
+        Some(inode)
+    }
+}
+
+/// The `.snapshots` directory, with the pool root's owner and times and no write bits
+pub fn snapshots_dir_inode(root: &Inode, snapshots: usize) -> Inode {
+    let mut inode = Inode::new_dir(SNAPSHOTS_DIR_INO, root.ino, SNAPSHOTS_DIR_NAME);
+    inode.uid = root.uid;
+    inode.gid = root.gid;
+    inode.mode = 0o555;
+    (inode.atime, inode.mtime, inode.ctime) = (root.atime, root.mtime, root.ctime);
+    (inode.atime_nsec, inode.mtime_nsec, inode.ctime_nsec) = (root.atime_nsec, root.mtime_nsec, root.ctime_nsec);
+    inode.crtime = Some(root.crtime());
+    inode.subdirs = snapshots as u64;
+    inode
+}
+
+/// Snapshots read by the engine, loaded on first use
+///
+/// The index is read again whenever it has changed on disk, so snapshots
+/// taken by another process show up too.
+pub struct LoadedSnapshots {
+    pool_dir: PathBuf,
+    cache: Mutex<SnapshotCache>,
+}
+
+#[derive(Default)]
+struct SnapshotCache {
+    /// Modification time and length of the index the infos were read from
+    stamp: Option<(Option<SystemTime>, u64)>,
+    infos: Option<Vec<SnapshotInfo>>,
+    trees: HashMap<u16, Arc<LoadedSnapshot>>,
+}
+
+impl LoadedSnapshots {
+    pub fn new(pool_dir: &Path) -> Self {
+        LoadedSnapshots { pool_dir: pool_dir.to_path_buf(), cache: Mutex::new(SnapshotCache::default()) }
+    }
+
+    /// Every committed snapshot, oldest first
+    pub fn infos(&self) -> Result<Vec<SnapshotInfo>> {
+        let stamp = std::fs::metadata(index_path(&self.pool_dir)).ok().map(|meta| (meta.modified().ok(), meta.len()));
+        let mut cache = self.cache.lock().unwrap();
+        if cache.infos.is_none() || cache.stamp != stamp {
+            let infos = SnapshotInfo::list(&self.pool_dir)?;
+            cache.trees.retain(|id, _| infos.iter().any(|info| info.id == *id));
+            cache.infos = Some(infos);
+            cache.stamp = stamp;
+        }
+        Ok(cache.infos.clone().unwrap_or_default())
+    }
+
+    /// Snapshot `id` with its tree, or `None` if it is not committed
+    pub fn get(&self, id: u16) -> Result<Option<Arc<LoadedSnapshot>>> {
+        let Some(info) = self.infos()?.into_iter().find(|info| info.id == id) else {
+            return Ok(None);
+        };
+        if let Some(snapshot) = self.cache.lock().unwrap().trees.get(&id) {
+            return Ok(Some(Arc::clone(snapshot)));
+        }
+        let snapshot = Arc::new(LoadedSnapshot { tree: SnapshotTree::load(&self.pool_dir, id)?, info });
+        self.cache.lock().unwrap().trees.insert(id, Arc::clone(&snapshot));
+        Ok(Some(snapshot))
+    }
+
+    /// Forget the index, after this engine changed it
+    pub fn invalidate(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.infos = None;
+        cache.trees.clear();
+    }
+}
 
 /// Represents a point-in-time snapshot of filesystem state
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +443,7 @@ pub struct SnapshotDelta {
 }
 
 /// Copy-on-write snapshot manager
+#[derive(Default)]
 pub struct SnapshotManager {
     snapshots: HashMap<Uuid, Snapshot>,
     extent_refcounts: HashMap<Uuid, u64>, // Track extent references
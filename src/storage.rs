@@ -1,15 +1,63 @@
 use anyhow::{anyhow, Result};
+use crate::error::{StorageError, StorageResult};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::{Arc, RwLock, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::thread;
 
-use crate::disk::Disk;
-use crate::extent::{split_into_extents, Extent, RedundancyPolicy, AccessStats, AccessClassification, DEFAULT_EXTENT_SIZE};
+use crate::access_tracker::{AccessTracker, AtimeMode, TimestampTracker};
+use crate::compression::Compression;
+use crate::disk::{Disk, DiskHealth, DiskKind, DiskPool};
 use crate::hmm_classifier::HmmClassifier;
-use crate::metadata::{ExtentMap, Inode, MetadataManager};
-use crate::placement::PlacementEngine;
+use crate::gc::{GarbageCollector, GcReport, GcStatus, InFlightExtents, InFlightWrite, OrphanGcConfig};
+use crate::periodic::PeriodicTask;
+use crate::extent::{split_into_extents, Extent, FragmentLocation, RedundancyPolicy, AccessClassification, DEFAULT_EXTENT_SIZE};
+use crate::logging::{EventKind, EventRing};
+use crate::layout::{ExtentLayout, FileLayout, FragmentLayout};
+use crate::metadata::{now_timespec, ExtentMap, FileName, FileType, Inode, MetadataManager, ORPHAN_PARENT_INO};
+use crate::metadata_tx::MetadataOp;
+use crate::placement::{
+    DegradedWrites, PlacementEngine, SpaceLevel, SpacePressure, SpaceReclaimReport, SpaceReservation, SpaceReservations,
+    SpaceWatermarks, WritePriority, DEFAULT_SPACE_RESERVE_PERCENT,
+};
 use crate::redundancy;
+use crate::io_scheduler::IoClass;
 use crate::metrics::Metrics;
-use crate::scheduler::{ReplicaSelector, ReplicaSelectionStrategy};
+use crate::permissions::{self, RequestContext};
+use crate::activity::{ActivityCounters, ActivitySnapshot, DiskActivity, HotInode, RecentInodeAccess};
+use crate::rebuild_budget::{RebuildBudget, RebuildLimits, RebuildStatus};
+use crate::rebuild_queue::{EnqueueResult, RebuildQueue};
+use crate::recent_writes::{RecentWriteConfig, RecentWritePass, RecentWriteQueue, RecentWriteStatus, DEFAULT_RECENT_WRITE_CAPACITY};
+use crate::scheduler::fragment_read_order;
+use crate::scrubber::{ExtentCheck, ExtentHealth, FileCheckReport, ScrubIssue, ScrubStatus, Scrubber};
+use crate::snapshots::{self, LoadedSnapshot, LoadedSnapshots, SnapshotInfo, SnapshotTree, SNAPSHOTS_DIR_INO, SNAPSHOTS_DIR_NAME};
+use crate::tiering::{self, StorageTier, TierPassConfig, TierPassReport, TierStatus};
+use crate::write_optimizer::{DirtyRun, FlushCause, WriteBuffer, WriteBufferConfig};
+use crate::buffer_pool::{BufferPool, DEFAULT_READ_BUFFER_POOL_BYTES};
+use crate::data_cache::DataCache;
+use crate::fs_interface::is_nocache;
+use crate::inode_cache::{InodeCache, InodeCacheLimits};
+use crate::prefetch_queue::{PrefetchQueue, PrefetchRequest};
+use crate::policy_change::{FailedExtent, PolicyChangeProgress, PolicyChangeState, RunningPolicyChanges};
+
+/// Policy whose overhead is used to convert raw disk space into usable space in `stat`
+pub const STATFS_REDUNDANCY_POLICY: RedundancyPolicy = RedundancyPolicy::Replication { copies: 3 };
+
+/// Orphaned fragments this old are collected early once the pool passes its high watermark
+pub const PRESSURE_ORPHAN_MIN_AGE: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Inode write locks; writers of inodes sharing a stripe also serialise
+const INODE_WRITE_LOCK_STRIPES: usize = 64;
+
+/// Memory for extents decoded ahead of sequential readers
+pub const READAHEAD_CACHE_BYTES: usize = 256 * 1024 * 1024;
+
+/// Fragments collected for an extent
+struct FragmentReads {
+    fragments: Vec<Option<Vec<u8>>>,
+    /// Fragments known to be lost: read errors, checksum mismatches or no readable location
+    failed: usize,
+}
 
 /// Storage engine handling read/write operations
 pub struct StorageEngine {
@@ -17,33 +65,612 @@ pub struct StorageEngine {
     disks: Arc<RwLock<Vec<Arc<Mutex<Disk>>>>>,
     placement: PlacementEngine,
     metrics: Arc<Metrics>,
+    rebuild_queue: Arc<RebuildQueue>,
+    /// Concurrency and byte-rate limits for background rebuilds
+    rebuild_budget: Arc<RebuildBudget>,
+    /// Recent reads and writes per inode, for `top`
+    inode_activity: Arc<RecentInodeAccess>,
+    /// Background rebuild worker; only set on the engine that owns it
+    rebuild_worker: Option<thread::JoinHandle<()>>,
+    /// Dirty file data not yet written to extents
+    write_buffer: Arc<WriteBuffer>,
+    /// Timer thread flushing idle buffered runs; only set on the engine that owns it
+    buffer_flusher: Option<thread::JoinHandle<()>>,
+    /// Snapshots shown under `/.snapshots`
+    snapshots: Arc<LoadedSnapshots>,
+    /// Reject every mutation with `ReadOnlyFilesystem`
+    read_only: Arc<AtomicBool>,
+    /// Compression applied to newly written extents
+    compression: Arc<RwLock<Compression>>,
+    /// Read back new fragments before a write succeeds; see `place_extent`
+    verify_writes: Arc<AtomicBool>,
+    /// Slot size of files written from scratch; existing files keep the size in their extent map
+    extent_size: Arc<AtomicUsize>,
+    /// Percent of every disk that writes leave free; see `reserve_space`
+    space_reserve_percent: Arc<AtomicU8>,
+    /// Weakest policy writes may fall back to when too few disks are writable; `None` fails them
+    degraded_write_floor: Arc<RwLock<Option<RedundancyPolicy>>>,
+    /// Fragment space held by writes in progress
+    space_reservations: Arc<SpaceReservations>,
+    /// Watermarks of pool usage and the level last seen; see `check_space_watermarks`
+    space_pressure: Arc<SpacePressure>,
+    /// Treat every write as low priority; set by `benchmark`
+    low_priority_writes: Arc<AtomicBool>,
+    /// Serialises writers of an inode, striped by inode number; see `lock_inode_writes`
+    inode_write_locks: Arc<Vec<Mutex<()>>>,
+    /// Extents placed by writes that have not committed yet; the orphan collector skips them
+    in_flight: Arc<InFlightExtents>,
+    /// Background orphan collector; only set on the engine that owns it
+    orphan_gc: Option<PeriodicTask>,
+    /// Disks whose path vanished while in use, held as Failed until they come back
+    detached_disks: Arc<Mutex<HashSet<uuid::Uuid>>>,
+    /// Periodic `reprobe_disks`; only set on the engine that owns it
+    disk_probe: Option<PeriodicTask>,
+    /// Periodic `poll_smart`; only set on the engine that owns it
+    smart_poll: Option<PeriodicTask>,
+    /// Periodic `check_space_threshold`; only set on the engine that owns it
+    space_watch: Option<PeriodicTask>,
+    /// Extent reads not yet written to extent metadata; see `flush_access_stats`
+    access: Arc<AccessTracker>,
+    /// Periodic `flush_access_stats` and `flush_inode_times`; only set on the engine that owns it
+    access_flush: Option<PeriodicTask>,
+    /// Whether reads move atime; see `touch_atime`
+    atime_mode: Arc<RwLock<AtimeMode>>,
+    /// Inode atime and mtime updates not yet written to inode metadata; see `flush_inode_times`
+    timestamps: Arc<TimestampTracker>,
+    /// Periodic `run_tier_pass`; only set on the engine that owns it
+    tiering: Option<PeriodicTask>,
+    /// Model classifying extents from their hourly accesses; `None` uses the thresholds alone
+    access_model: Arc<RwLock<Option<HmmClassifier>>>,
+    /// Periodic `train_access_model`; only set on the engine that owns it
+    model_training: Option<PeriodicTask>,
+    /// Recent degraded reads, rebuilds, checksum failures and health changes
+    events: Arc<EventRing>,
+    /// Extents decoded ahead of sequential readers; see `prefetch`
+    data_cache: Arc<DataCache>,
+    /// Read-ahead requests waiting for the prefetcher
+    prefetch_queue: Arc<PrefetchQueue>,
+    /// Read-ahead worker; only set on the engine that owns it
+    prefetcher: Option<thread::JoinHandle<()>>,
+    /// Policy changes in progress, with their cancel requests
+    policy_changes: Arc<RunningPolicyChanges>,
+    /// Inodes and name lookups the metadata manager keeps between calls
+    inode_cache: Arc<InodeCache>,
+    /// Fragment and extent buffers reused across reads
+    buffers: Arc<BufferPool>,
+    /// Extents placed and not yet verified; see `verify_recent_writes`
+    recent_writes: Arc<RecentWriteQueue>,
+    /// Periodic `verify_recent_writes`; only set on the engine that owns it
+    recent_write_verifier: Option<PeriodicTask>,
 }
 
 impl StorageEngine {
     pub fn new(metadata: MetadataManager, disks: Vec<Disk>) -> Self {
-        let disks = disks.into_iter().map(|d| Arc::new(Mutex::new(d))).collect();
-        StorageEngine {
+        Self::with_metrics(metadata, disks, Arc::new(Metrics::new()))
+    }
+    
+    pub fn with_metrics(metadata: MetadataManager, disks: Vec<Disk>, metrics: Arc<Metrics>) -> Self {
+        Self::with_write_buffer(metadata, disks, metrics, WriteBufferConfig::default())
+    }
+    
+    pub fn with_write_buffer(
+        mut metadata: MetadataManager,
+        disks: Vec<Disk>,
+        metrics: Arc<Metrics>,
+        buffer_config: WriteBufferConfig,
+    ) -> Self {
+        let events = Arc::new(EventRing::new());
+        let disks = disks
+            .into_iter()
+            .map(|mut d| {
+                d.events = Some(Arc::clone(&events));
+                Arc::new(Mutex::new(d))
+            })
+            .collect();
+        let rebuild_budget = Arc::new(RebuildBudget::new(RebuildLimits::default(), Arc::clone(&metrics)));
+        let snapshots = Arc::new(LoadedSnapshots::new(metadata.pool_dir()));
+        let inode_cache = Arc::new(InodeCache::new(InodeCacheLimits::default(), Arc::clone(&metrics)));
+        metadata.set_inode_cache(Arc::clone(&inode_cache));
+        let recent_writes = RecentWriteQueue::open(metadata.pool_dir(), DEFAULT_RECENT_WRITE_CAPACITY).unwrap_or_else(|e| {
+            log::error!("Failed to load the recent-write queue, starting it empty: {:#}", e);
+            RecentWriteQueue::new(DEFAULT_RECENT_WRITE_CAPACITY)
+        });
+        let mut engine = StorageEngine {
             metadata: Arc::new(RwLock::new(metadata)),
             disks: Arc::new(RwLock::new(disks)),
             placement: PlacementEngine,
-            metrics: Arc::new(Metrics::new()),
+            metrics,
+            rebuild_queue: Arc::new(RebuildQueue::default()),
+            rebuild_budget,
+            inode_activity: Arc::new(RecentInodeAccess::default()),
+            rebuild_worker: None,
+            write_buffer: Arc::new(WriteBuffer::new(buffer_config)),
+            buffer_flusher: None,
+            snapshots,
+            read_only: Arc::new(AtomicBool::new(false)),
+            compression: Arc::new(RwLock::new(Compression::None)),
+            verify_writes: Arc::new(AtomicBool::new(false)),
+            extent_size: Arc::new(AtomicUsize::new(DEFAULT_EXTENT_SIZE)),
+            space_reserve_percent: Arc::new(AtomicU8::new(DEFAULT_SPACE_RESERVE_PERCENT)),
+            degraded_write_floor: Arc::new(RwLock::new(None)),
+            space_reservations: Arc::new(SpaceReservations::default()),
+            space_pressure: Arc::new(SpacePressure::default()),
+            low_priority_writes: Arc::new(AtomicBool::new(false)),
+            inode_write_locks: Arc::new((0..INODE_WRITE_LOCK_STRIPES).map(|_| Mutex::new(())).collect()),
+            in_flight: Arc::new(InFlightExtents::default()),
+            orphan_gc: None,
+            detached_disks: Arc::new(Mutex::new(HashSet::new())),
+            disk_probe: None,
+            smart_poll: None,
+            space_watch: None,
+            access: Arc::new(AccessTracker::default()),
+            access_flush: None,
+            atime_mode: Arc::new(RwLock::new(AtimeMode::default())),
+            timestamps: Arc::new(TimestampTracker::default()),
+            tiering: None,
+            access_model: Arc::new(RwLock::new(None)),
+            model_training: None,
+            events,
+            data_cache: Arc::new(DataCache::new(READAHEAD_CACHE_BYTES)),
+            prefetch_queue: Arc::new(PrefetchQueue::default()),
+            prefetcher: None,
+            policy_changes: Arc::new(RunningPolicyChanges::default()),
+            inode_cache,
+            buffers: Arc::new(BufferPool::new(DEFAULT_READ_BUFFER_POOL_BYTES as usize)),
+            recent_writes: Arc::new(recent_writes),
+            recent_write_verifier: None,
+        };
+        
+        // Finish reclaiming extents released before a crash
+        if let Err(e) = engine.reclaim_released_extents() {
+            log::error!("Failed to reclaim released extents: {}", e);
         }
+        
+        let worker = engine.background_handle();
+        engine.rebuild_worker = Some(thread::spawn(move || worker.run_rebuild_worker()));
+        let flusher = engine.background_handle();
+        engine.buffer_flusher = Some(thread::spawn(move || flusher.run_buffer_flusher()));
+        engine
     }
     
-    pub fn with_metrics(metadata: MetadataManager, disks: Vec<Disk>, metrics: Arc<Metrics>) -> Self {
-        let disks = disks.into_iter().map(|d| Arc::new(Mutex::new(d))).collect();
+    /// Engine sharing all state with `self`, for use on background threads
+    ///
+    /// Dropping a handle does not stop the owner's workers or flush its buffer.
+    pub(crate) fn background_handle(&self) -> StorageEngine {
         StorageEngine {
-            metadata: Arc::new(RwLock::new(metadata)),
-            disks: Arc::new(RwLock::new(disks)),
+            metadata: Arc::clone(&self.metadata),
+            disks: Arc::clone(&self.disks),
             placement: PlacementEngine,
-            metrics,
+            metrics: Arc::clone(&self.metrics),
+            rebuild_queue: Arc::clone(&self.rebuild_queue),
+            rebuild_budget: Arc::clone(&self.rebuild_budget),
+            inode_activity: Arc::clone(&self.inode_activity),
+            rebuild_worker: None,
+            write_buffer: Arc::clone(&self.write_buffer),
+            buffer_flusher: None,
+            snapshots: Arc::clone(&self.snapshots),
+            read_only: Arc::clone(&self.read_only),
+            compression: Arc::clone(&self.compression),
+            verify_writes: Arc::clone(&self.verify_writes),
+            extent_size: Arc::clone(&self.extent_size),
+            space_reserve_percent: Arc::clone(&self.space_reserve_percent),
+            degraded_write_floor: Arc::clone(&self.degraded_write_floor),
+            space_reservations: Arc::clone(&self.space_reservations),
+            space_pressure: Arc::clone(&self.space_pressure),
+            low_priority_writes: Arc::clone(&self.low_priority_writes),
+            inode_write_locks: Arc::clone(&self.inode_write_locks),
+            in_flight: Arc::clone(&self.in_flight),
+            orphan_gc: None,
+            detached_disks: Arc::clone(&self.detached_disks),
+            disk_probe: None,
+            smart_poll: None,
+            space_watch: None,
+            access: Arc::clone(&self.access),
+            access_flush: None,
+            atime_mode: Arc::clone(&self.atime_mode),
+            timestamps: Arc::clone(&self.timestamps),
+            tiering: None,
+            access_model: Arc::clone(&self.access_model),
+            model_training: None,
+            events: Arc::clone(&self.events),
+            data_cache: Arc::clone(&self.data_cache),
+            prefetch_queue: Arc::clone(&self.prefetch_queue),
+            prefetcher: None,
+            policy_changes: Arc::clone(&self.policy_changes),
+            inode_cache: Arc::clone(&self.inode_cache),
+            buffers: Arc::clone(&self.buffers),
+            recent_writes: Arc::clone(&self.recent_writes),
+            recent_write_verifier: None,
+        }
+    }
+    
+    /// Reject or allow writes; reads stop updating access stats and migrating extents
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::SeqCst);
+    }
+    
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+    
+    /// Compress extents written from now on; existing extents keep their own setting
+    pub fn set_compression(&self, compression: Compression) {
+        *self.compression.write().unwrap() = compression;
+    }
+    
+    pub fn compression(&self) -> Compression {
+        *self.compression.read().unwrap()
+    }
+    
+    /// Read back every fragment after writing it, unless a file overrides this
+    pub fn set_verify_writes(&self, verify: bool) {
+        self.verify_writes.store(verify, Ordering::SeqCst);
+    }
+    
+    pub fn verify_writes(&self) -> bool {
+        self.verify_writes.load(Ordering::SeqCst)
+    }
+    
+    /// Cut files written from scratch into extents of `size` bytes
+    pub fn set_extent_size(&self, size: usize) -> StorageResult<()> {
+        crate::extent::validate_extent_size(size)?;
+        self.extent_size.store(size, Ordering::SeqCst);
+        Ok(())
+    }
+    
+    pub fn extent_size(&self) -> usize {
+        self.extent_size.load(Ordering::SeqCst)
+    }
+    
+    /// Choose when reads update atime, as set by the `noatime`/`relatime` mount options
+    pub fn set_atime_mode(&self, mode: AtimeMode) {
+        *self.atime_mode.write().unwrap() = mode;
+    }
+    
+    pub fn atime_mode(&self) -> AtimeMode {
+        *self.atime_mode.read().unwrap()
+    }
+    
+    /// Keep `percent` of every disk free of new writes, leaving room for rebuilds
+    /// How long metadata transactions wait to share a sync; zero syncs each on commit
+    pub fn set_metadata_commit_window(&self, window_ms: u64) {
+        self.metadata.read().unwrap().set_commit_window(std::time::Duration::from_millis(window_ms));
+    }
+    
+    pub fn set_space_reserve_percent(&self, percent: u8) {
+        self.space_reserve_percent.store(percent, Ordering::SeqCst);
+    }
+    
+    pub fn space_reserve_percent(&self) -> u8 {
+        self.space_reserve_percent.load(Ordering::SeqCst)
+    }
+    
+    /// Pool usage at which low-priority writes, then all writes of new data, fail
+    pub fn set_space_watermarks(&self, watermarks: SpaceWatermarks) {
+        self.space_pressure.set_watermarks(watermarks);
+    }
+    
+    pub fn space_watermarks(&self) -> SpaceWatermarks {
+        self.space_pressure.watermarks()
+    }
+    
+    /// Give up writes first once the pool passes its high watermark, as benchmarks should
+    pub fn set_low_priority_writes(&self, low: bool) {
+        self.low_priority_writes.store(low, Ordering::SeqCst);
+    }
+    
+    /// How far into a filling pool the calling thread's writes may go
+    ///
+    /// Follows the thread's I/O class: defrag passes are low priority, rebuild,
+    /// scrub and GC passes essential, and everything else a user write.
+    fn write_priority(&self) -> WritePriority {
+        if self.low_priority_writes.load(Ordering::SeqCst) {
+            return WritePriority::Low;
+        }
+        match IoClass::current() {
+            IoClass::Foreground => WritePriority::User,
+            IoClass::Defrag => WritePriority::Low,
+            IoClass::Rebuild | IoClass::Scrub | IoClass::Gc => WritePriority::Essential,
+        }
+    }
+    
+    /// Let writes fall back to a policy no weaker than `floor` when too few disks are writable
+    pub fn set_degraded_writes(&self, mode: DegradedWrites, floor: RedundancyPolicy) {
+        *self.degraded_write_floor.write().unwrap() = (mode == DegradedWrites::Allow).then_some(floor);
+    }
+    
+    /// Policy to write new data under when `wanted` is asked for, and `wanted` if that is weaker
+    ///
+    /// With too few writable disks for `wanted` and degraded writes allowed,
+    /// the strongest policy the disks can hold down to the floor is returned
+    /// with `wanted` as the intended policy to record on the extent. Otherwise
+    /// `wanted` itself, and placement fails as it would without the fallback.
+    fn policy_to_write(&self, disks: &[Arc<Mutex<Disk>>], wanted: RedundancyPolicy) -> (RedundancyPolicy, Option<RedundancyPolicy>) {
+        let writable = crate::placement::writable_disk_count(disks);
+        if writable >= wanted.fragment_count() {
+            return (wanted, None);
+        }
+        match (*self.degraded_write_floor.read().unwrap()).and_then(|floor| wanted.fallback(writable, floor)) {
+            Some(fallback) => (fallback, Some(wanted)),
+            None => (wanted, None),
+        }
+    }
+    
+    /// Note a new extent written under `policy` in place of `intended`
+    fn record_degraded_write(&self, extent: &mut Extent, intended: Option<RedundancyPolicy>) {
+        let Some(intended) = intended else { return };
+        log::warn!(
+            "Too few writable disks for {}; extent {} written as {} until they return",
+            intended,
+            extent.uuid,
+            extent.redundancy
+        );
+        extent.intended_policy = Some(intended);
+        self.metrics.record_degraded_write();
+    }
+    
+    /// Hold room for new extents of `(policy, bytes)` before placing any fragment
+    ///
+    /// Fails with `NoSpace` when the disks cannot take them without
+    /// dipping into the reserve, or when the pool is past a watermark the
+    /// calling thread's writes stop at, so a write fails cleanly instead of
+    /// part way through placement.
+    fn reserve_space(&self, disks: &[Arc<Mutex<Disk>>], extents: &[(RedundancyPolicy, usize)]) -> Result<SpaceReservation> {
+        // Truncating to zero writes no data and must keep working on a full pool
+        let checked = if extents.iter().all(|(_, len)| *len == 0) { Ok(()) } else { self.check_write_watermark() };
+        checked
+            .and_then(|()| self.space_reservations.reserve(disks, self.space_reserve_percent(), extents))
+            .inspect_err(|e| {
+                self.events.record(EventKind::NoSpace, None, None, e.to_string());
+            })
+    }
+    
+    /// Fail with `NoSpace` if the pool is past the watermark the calling thread's writes stop at
+    fn check_write_watermark(&self) -> Result<()> {
+        let level = self.check_space_watermarks();
+        let priority = self.write_priority();
+        if priority.allowed_at(level) {
+            return Ok(());
+        }
+        let watermarks = self.space_watermarks();
+        let (which, percent) = match level {
+            SpaceLevel::Critical => ("critical", watermarks.critical_percent),
+            _ => ("high", watermarks.high_percent),
+        };
+        let writes = if priority == WritePriority::Low { "low-priority writes are" } else { "new data is" };
+        Err(StorageError::NoSpace(format!(
+            "pool is at or above its {} space watermark ({}%); {} refused until space is freed",
+            which, percent, writes
+        ))
+        .into())
+    }
+    
+    /// Whether writes to `ino` are verified: its verify xattr if set, else the pool setting
+    fn verify_writes_for(&self, metadata: &MetadataManager, ino: u64) -> bool {
+        metadata
+            .load_inode(ino)
+            .ok()
+            .and_then(|inode| crate::fs_interface::parse_on_off(inode.get_xattr(crate::fs_interface::VERIFY_WRITES_XATTR)?))
+            .unwrap_or_else(|| self.verify_writes())
+    }
+    
+    /// Write an extent's fragments, then with `verify` read each one back
+    ///
+    /// The extent is registered with `in_flight` first, so the orphan
+    /// collector leaves its fragments alone until the write commits. A
+    /// fragment that cannot be read back or fails its checksum fails the
+    /// whole placement: every fragment of the extent is deleted, the disk that
+    /// returned bad data is marked Suspect and the error names it. A placed
+    /// extent is queued to be verified again once it has settled.
+    fn place_extent(
+        &self,
+        extent: &mut Extent,
+        disks: &[Arc<Mutex<Disk>>],
+        fragments: &[Vec<u8>],
+        verify: bool,
+        in_flight: &mut InFlightWrite,
+    ) -> Result<()> {
+        in_flight.add(extent.uuid);
+        self.placement.place_extent(extent, disks, fragments)?;
+        if !verify {
+            self.record_recent_write(extent.uuid);
+            return Ok(());
+        }
+        
+        let mut failures = Vec::new();
+        for location in &extent.fragment_locations {
+            let Some(disk) = disks.iter().find(|d| d.lock().unwrap().uuid == location.disk_uuid) else {
+                continue;
+            };
+            let disk = disk.lock().unwrap();
+            let read_back = match &location.on_device {
+                Some(placement) => disk.read_fragment_at_placement(placement),
+                None => disk.read_fragment(&extent.uuid, location.fragment_index),
+            };
+            match read_back {
+                Ok(data) if location.verify_checksum(&data) => {}
+                Ok(_) => failures.push((location.fragment_index, location.disk_uuid, "checksum mismatch".to_string())),
+                Err(e) => failures.push((location.fragment_index, location.disk_uuid, e.to_string())),
+            }
+        }
+        if failures.is_empty() {
+            self.record_recent_write(extent.uuid);
+            return Ok(());
+        }
+        
+        Self::delete_fragments(disks, extent);
+        extent.fragment_locations.clear();
+        for (fragment_index, disk_uuid, reason) in &failures {
+            self.metrics.record_write_verify_failure(*disk_uuid);
+            log::error!(
+                "Fragment {} of extent {} failed read-back on disk {}: {}",
+                fragment_index, extent.uuid, disk_uuid, reason
+            );
+            if let Some(disk) = disks.iter().find(|d| d.lock().unwrap().uuid == *disk_uuid) {
+                match disk.lock().unwrap().mark_suspect() {
+                    Ok(true) => log::warn!("Disk {} marked Suspect after a failed write verification", disk_uuid),
+                    Ok(false) => {}
+                    Err(e) => log::error!("Failed to mark disk {} Suspect: {}", disk_uuid, e),
+                }
+            }
+        }
+        let (fragment_index, disk_uuid, reason) = &failures[0];
+        Err(anyhow!(
+            "Write verification failed on disk {}: fragment {} of extent {} ({})",
+            disk_uuid, fragment_index, extent.uuid, reason
+        ))
+    }
+    
+    /// Queue a placed extent for `verify_recent_writes`, counting it if the queue is full
+    fn record_recent_write(&self, extent_uuid: uuid::Uuid) {
+        if !self.recent_writes.record(extent_uuid, chrono::Utc::now().timestamp()) {
+            self.metrics.record_recent_write_overflow();
+        }
+        self.metrics.update_recent_write_queue_depth(self.recent_writes.len() as u64);
+    }
+    
+    /// Compress `data` for `extent` with the pool's setting and encode it into fragments
+    fn encode_extent(&self, extent: &mut Extent, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let payload = extent.pack(data, self.compression());
+        redundancy::encode(&payload, extent.redundancy)
+    }
+    
+    /// Hold off other writers of `ino` until the guard is dropped
+    ///
+    /// Taken by the public write entry points, outside every other lock, so an
+    /// append can find the end of file and write there atomically. Flushes of
+    /// the write buffer run under the buffer lock instead and never take it.
+    fn lock_inode_writes(&self, ino: u64) -> std::sync::MutexGuard<'_, ()> {
+        self.inode_write_locks[(ino % INODE_WRITE_LOCK_STRIPES as u64) as usize].lock().unwrap()
+    }
+    
+    fn check_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            return Err(StorageError::ReadOnly("filesystem is mounted read-only".to_string()).into());
+        }
+        Ok(())
+    }
+    
+    /// Drain the rebuild queue until it is shut down
+    ///
+    /// Each rebuild runs on its own thread once the repair budget grants a
+    /// permit, and waits for the budget's byte rate before it starts.
+    fn run_rebuild_worker(&self) {
+        let mut rebuilds: Vec<thread::JoinHandle<()>> = Vec::new();
+        while let Some(task) = self.rebuild_queue.next() {
+            self.metrics.update_rebuild_queue_depth(self.rebuild_queue.depth() as u64);
+            let Some(mut permit) = self.rebuild_budget.acquire() else {
+                self.rebuild_queue.complete(&task);
+                break;
+            };
+            rebuilds.retain(|rebuild| !rebuild.is_finished());
+            let rebuilder = self.background_handle();
+            rebuilds.push(thread::spawn(move || {
+                let _class = IoClass::Rebuild.enter();
+                let size = rebuilder.metadata.read().unwrap().load_extent(&task.extent_uuid).map_or(0, |e| e.size as u64);
+                if rebuilder.rebuild_budget.charge(size) {
+                    match rebuilder.rebuild_extent(task.extent_uuid) {
+                        Ok(rebuilt) => permit.rebuilt(rebuilt),
+                        Err(e) => log::error!("Background rebuild of extent {} failed: {}", task.extent_uuid, e),
+                    }
+                }
+                drop(permit);
+                rebuilder.rebuild_queue.complete(&task);
+            }));
+        }
+        for rebuild in rebuilds {
+            rebuild.join().ok();
+        }
+    }
+    
+    /// Replace the repair budget's limits, e.g. from `set-rebuild-limit` on a mounted pool
+    pub fn set_rebuild_limits(&self, limits: RebuildLimits) -> StorageResult<()> {
+        Ok(self.rebuild_budget.set_limits(limits)?)
+    }
+    
+    /// Limits in effect, running rebuilds and recent rebuild throughput
+    pub fn rebuild_status(&self) -> RebuildStatus {
+        self.rebuild_budget.status()
+    }
+    
+    /// Counters, per-disk fragment I/O and the `top` busiest inodes of the last `window_secs`
+    pub fn activity(&self, window_secs: u64, top: usize) -> ActivitySnapshot {
+        let metrics = &self.metrics;
+        let counters = ActivityCounters {
+            reads: metrics.disk_reads.load(Ordering::Relaxed),
+            read_bytes: metrics.disk_read_bytes.load(Ordering::Relaxed),
+            writes: metrics.disk_writes.load(Ordering::Relaxed),
+            write_bytes: metrics.disk_write_bytes.load(Ordering::Relaxed),
+            cache_hits: metrics.cache_hits.load(Ordering::Relaxed),
+            cache_misses: metrics.cache_misses.load(Ordering::Relaxed),
+        };
+        let disks = self
+            .disks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|disk| {
+                let disk = disk.lock().unwrap();
+                DiskActivity {
+                    uuid: disk.uuid,
+                    path: disk.path.display().to_string(),
+                    health: disk.health,
+                    io: disk.io_counters.snapshot(),
+                }
+            })
+            .collect();
+        let hot = self.inode_activity.top(window_secs, top);
+        let metadata = self.metadata.read().unwrap();
+        let hot_inodes = hot
+            .into_iter()
+            .map(|(ino, access)| HotInode {
+                ino,
+                path: metadata.path_of(ino).unwrap_or_default(),
+                ops: access.ops,
+                bytes: access.bytes,
+            })
+            .collect();
+        ActivitySnapshot {
+            taken_at_ms: chrono::Utc::now().timestamp_millis() as u64,
+            counters,
+            rebuild_queue_depth: self.rebuild_queue.depth() as u64,
+            disks,
+            hot_inodes,
+        }
+    }
+    
+    /// Flush buffered runs that have been idle for the flush interval until shut down
+    fn run_buffer_flusher(&self) {
+        while self.write_buffer.wait_tick() {
+            if let Err(e) = self.write_buffer.flush_expired(|ino, run, cause| self.flush_run(ino, run, cause)) {
+                log::error!("Timed write-buffer flush failed: {}", e);
+            }
+        }
+    }
+    
+    /// Queue a degraded extent for background rebuild without blocking
+    fn queue_rebuild(&self, extent_uuid: uuid::Uuid, margin: usize) {
+        if self.rebuild_queue.try_enqueue(extent_uuid, margin) == EnqueueResult::Full {
+            self.metrics.record_rebuild_queue_rejected();
+            log::warn!("Rebuild queue full, deferring rebuild of extent {}", extent_uuid);
         }
+        self.metrics.update_rebuild_queue_depth(self.rebuild_queue.depth() as u64);
+    }
+    
+    /// Block until all queued rebuilds (including a running mount scan) have finished
+    pub fn wait_for_rebuilds(&self) {
+        self.rebuild_queue.wait_idle();
     }
     
     pub fn metrics(&self) -> Arc<Metrics> {
         self.metrics.clone()
     }
     
+    /// The event ring shared by this engine and its disks
+    pub fn events(&self) -> Arc<EventRing> {
+        Arc::clone(&self.events)
+    }
+    
     /// Get a reference to the metadata manager
     pub fn metadata(&self) -> Arc<RwLock<MetadataManager>> {
         Arc::clone(&self.metadata)
@@ -55,59 +682,32 @@ impl StorageEngine {
     }
     
     /// Read extent data by UUID
-    pub fn read_extent(&self, extent_uuid: uuid::Uuid) -> Result<Vec<u8>> {
+    pub fn read_extent(&self, extent_uuid: uuid::Uuid) -> StorageResult<Vec<u8>> {
         let metadata = self.metadata.read().unwrap();
         let extent = metadata.load_extent(&extent_uuid)?;
         drop(metadata);
         
         let disks = self.disks.read().unwrap();
-        let fragments = self.read_fragments(&extent, &*disks)?;
+        let fragments = self.read_fragments_for_decode(&extent, &disks).fragments;
         drop(disks);
         
         // Reconstruct data from fragments
-        redundancy::decode(&fragments, extent.redundancy)
+        let payload = redundancy::decode(&fragments, extent.redundancy, extent.stored_size())
+            .map_err(|e| self.unreadable_extent(&extent, e))?;
+        self.buffers.give_all(fragments);
+        Ok(extent.unpack(payload)?)
     }
     
     /// Write extent data and return the extent
-    pub fn write_extent(&self, data: &[u8], policy: RedundancyPolicy) -> Result<Extent> {
-        use uuid::Uuid;
-        use blake3;
-        
-        let extent_uuid = Uuid::new_v4();
-        let checksum_hash = blake3::hash(data);
-        let checksum: [u8; 32] = checksum_hash.into();
-        
-        // Create extent object first
-        let now = chrono::Utc::now().timestamp();
-        let mut extent = Extent {
-            uuid: extent_uuid,
-            size: data.len(),
-            checksum,
-            redundancy: policy,
-            fragment_locations: Vec::new(),
-            previous_policy: None,
-            policy_transitions: Vec::new(),
-            last_policy_change: None,
-            access_stats: AccessStats {
-                read_count: 0,
-                write_count: 1,
-                last_read: 0,
-                last_write: now,
-                created_at: now,
-                classification: AccessClassification::Cold,
-                hmm_classifier: Some(HmmClassifier::new()),
-            },
-            rebuild_in_progress: false,
-            rebuild_progress: None,
-            generation: 0,
-        };
+    pub fn write_extent(&self, data: &[u8], policy: RedundancyPolicy) -> StorageResult<Extent> {
+        let mut extent = Extent::new(data, policy);
         
         // Encode fragments based on redundancy policy
-        let fragments = redundancy::encode(data, policy)?;
+        let fragments = self.encode_extent(&mut extent, data)?;
         
         // Place extent on disks
         let disks = self.disks.write().unwrap();
-        self.placement.place_extent(&mut extent, &*disks, &fragments)?;
+        self.place_extent(&mut extent, &disks, &fragments, self.verify_writes(), &mut self.in_flight.begin())?;
         
         // Record metrics
         for fragment in &fragments {
@@ -117,8 +717,37 @@ impl StorageEngine {
         Ok(extent)
     }
     
+    /// Store `data` as an extent with the given UUID, placed on this engine's disks
+    ///
+    /// For extents sent by cluster peers. No file lists them, so `check`
+    /// reports them as unreferenced. An extent that already exists is left
+    /// as it is, so a retried request does no harm.
+    pub fn put_extent(&self, extent_uuid: uuid::Uuid, data: &[u8]) -> StorageResult<()> {
+        self.check_writable()?;
+        if data.len() > self.extent_size() {
+            return Err(anyhow!("Extent of {} bytes is larger than the pool's {} byte extents", data.len(), self.extent_size()).into());
+        }
+        if self.metadata.read().unwrap().extent_exists(&extent_uuid) {
+            return Ok(());
+        }
+        let mut extent = Extent::new(data, Self::default_policy_for_size(data.len() as u64));
+        extent.uuid = extent_uuid;
+        let fragments = self.encode_extent(&mut extent, data)?;
+        let mut in_flight = self.in_flight.begin();
+        let disks = self.disks.read().unwrap();
+        self.place_extent(&mut extent, &disks, &fragments, self.verify_writes(), &mut in_flight)?;
+        if let Err(err) = self.metadata.read().unwrap().save_extent(&extent) {
+            Self::delete_fragments(&disks, &extent);
+            return Err(err);
+        }
+        for fragment in &fragments {
+            self.metrics.record_disk_write(fragment.len() as u64);
+        }
+        Ok(())
+    }
+
     /// Delete an extent and its fragments
-    pub fn delete_extent(&self, extent_uuid: uuid::Uuid) -> Result<()> {
+    pub fn delete_extent(&self, extent_uuid: uuid::Uuid) -> StorageResult<()> {
         let metadata = self.metadata.read().unwrap();
         let extent = metadata.load_extent(&extent_uuid)?;
         drop(metadata);
@@ -133,134 +762,764 @@ impl StorageEngine {
         Ok(())
     }
 
-    /// Perform mount-time rebuild: scan extents and rebuild missing fragments where possible
-    pub fn perform_mount_rebuild(&self) -> Result<()> {
-        log::info!("Starting mount-time rebuild scan");
-        let metadata_w = self.metadata.write().unwrap();
-        let extents = metadata_w.list_all_extents()?;
-
-        for mut extent in extents {
-            let extent_uuid = extent.uuid;
-
-            // Read fragments
-            let disks = self.disks.read().unwrap();
-            let fragments = match self.read_fragments(&extent, &disks) {
-                Ok(f) => f,
-                Err(e) => {
-                    log::warn!("Failed to read fragments for extent {:?}: {:?}", extent_uuid, e);
+    /// Remove orphaned fragments at least `min_age_seconds` old
+    ///
+    /// Fragments of extents still being written or queued for rebuild are
+    /// kept, and so is everything on a disk holding fragments of extents
+    /// whose metadata cannot be loaded; see `GarbageCollector::collect`.
+    pub fn collect_orphans(&self, min_age_seconds: u64) -> StorageResult<GcReport> {
+        let disks: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().iter().cloned().collect();
+        let gc = GarbageCollector::for_engine(Arc::clone(&self.metadata), disks);
+        let report = gc.collect(min_age_seconds, false, &|extent_uuid| {
+            self.in_flight.contains(extent_uuid) || self.rebuild_queue.is_tracked(extent_uuid)
+        })?;
+        self.metrics.record_orphan_gc(report.removed.len() as u64, report.bytes_reclaimed(), report.skipped_disks.len() as u64);
+        if !report.removed.is_empty() || report.protected > 0 {
+            log::info!(
+                "Orphan GC removed {} fragments ({} bytes), kept {} still being written or rebuilt",
+                report.removed.len(),
+                report.bytes_reclaimed(),
+                report.protected
+            );
+        }
+        Ok(report)
+    }
+    
+    /// Run `collect_orphans` every `config.interval` until the engine is dropped
+    ///
+    /// Passes are skipped while the engine is read-only. Each pass is recorded
+    /// in the pool's GC status file for `orphan-stats`.
+    pub fn start_orphan_gc(&mut self, config: OrphanGcConfig) {
+        let collector = self.background_handle();
+        let pool_dir = self.metadata.read().unwrap().pool_dir().to_path_buf();
+        self.orphan_gc = Some(PeriodicTask::spawn(config.interval, move || {
+            if collector.is_read_only() {
+                return;
+            }
+            let outcome = collector.collect_orphans(config.min_age_seconds);
+            if let Err(e) = &outcome {
+                log::error!("Orphan GC pass failed: {}", e);
+            }
+            let mut status = GcStatus::load(&pool_dir).unwrap_or_default();
+            status.record(&outcome);
+            if let Err(e) = status.save(&pool_dir) {
+                log::error!("Failed to save orphan GC status: {}", e);
+            }
+        }));
+    }
+    
+    /// Pick up disks of `pool` that went away or came back since the last probe
+    ///
+    /// A disk whose path is no longer reachable is treated as Failed in memory,
+    /// so reads fall back to redundancy and writes avoid it. A disk that was
+    /// detached this way, or that was missing when the pool was opened, is
+    /// reloaded once reachable and used again; a rebuild scan then reconciles
+    /// extents written or degraded while it was gone. Returns the disks that
+    /// came back.
+    pub fn reprobe_disks(&self, pool: &DiskPool) -> Vec<uuid::Uuid> {
+        let mut returned = Vec::new();
+        let mut detached = self.detached_disks.lock().unwrap();
+        let mut disks = self.disks.write().unwrap();
+        
+        for disk_arc in disks.iter() {
+            let mut disk = disk_arc.lock().unwrap();
+            if detached.contains(&disk.uuid) {
+                if !disk.is_reachable() {
                     continue;
                 }
-            };
-            drop(disks);
-
-            let available_count = fragments.iter().filter(|f| f.is_some()).count();
-            let required = extent.redundancy.fragment_count();
-            let min_needed = extent.redundancy.min_fragments();
-
-            // Determine if rebuild is needed due to missing fragments
-            let needs_rebuild = available_count < required && available_count >= min_needed;
-
-            // Also consider draining disks: if any fragment resides on a draining disk, attempt to migrate
-            let disks_snapshot = self.disks.read().unwrap();
-            let draining_disk_uuids: Vec<uuid::Uuid> = disks_snapshot
-                .iter()
-                .filter_map(|d| {
-                    let d = d.lock().unwrap();
-                    if d.health == crate::disk::DiskHealth::Draining { Some(d.uuid) } else { None }
-                })
-                .collect();
-            drop(disks_snapshot);
-
-            let has_draining_fragment = extent
-                .fragment_locations
-                .iter()
-                .any(|loc| draining_disk_uuids.contains(&loc.disk_uuid));
-
-            if needs_rebuild || has_draining_fragment {
-                if has_draining_fragment {
-                    log::info!("Migrating fragments for extent {:?} away from draining disks", extent_uuid);
-                } else {
-                    log::info!("Rebuilding extent {:?}: {}/{} available", extent_uuid, available_count, required);
+                match pool.load_disk(&disk.path) {
+                    Ok(reloaded) if reloaded.uuid == disk.uuid => {
+                        log::info!("Disk {} at {:?} is reachable again", disk.uuid, disk.path);
+                        detached.remove(&disk.uuid);
+                        returned.push(disk.uuid);
+                        let io_counters = Arc::clone(&disk.io_counters);
+                        *disk = reloaded;
+                        disk.io_counters = io_counters;
+                        disk.events = Some(Arc::clone(&self.events));
+                        disk.health_event(disk.health, "reachable again");
+                    }
+                    Ok(other) => log::warn!("Disk {:?} now holds disk {}, expected {}", disk.path, other.uuid, disk.uuid),
+                    Err(e) => log::warn!("Disk {} at {:?} is back but failed to load: {}", disk.uuid, disk.path, e),
                 }
-
-                extent.rebuild_in_progress = true;
-                extent.rebuild_progress = Some(available_count);
-                metadata_w.save_extent(&extent)?;
-
-                // perform rebuild/migration
-                self.metrics.record_rebuild_start();
-                let disks_mut = self.disks.write().unwrap();
-
-                // When migrating from draining disks, prefer to preserve existing fragments and only replace those on draining disks
-                let rebuild_result = if has_draining_fragment && !needs_rebuild {
-                    // Read existing fragments and rewrite missing/draining ones
-                    self.placement.rebuild_extent(&mut extent, &*disks_mut, &fragments)
-                } else {
-                    self.placement.rebuild_extent(&mut extent, &*disks_mut, &fragments)
-                };
-
-                if let Err(e) = rebuild_result {
-                    self.metrics.record_rebuild_failure();
-                    log::error!("Failed to rebuild/migrate extent {:?}: {:?}", extent_uuid, e);
-                    extent.rebuild_in_progress = false;
-                    metadata_w.save_extent(&extent)?;
-                    continue;
+            } else if !disk.is_reachable() {
+                // Also taken when I/O errors already failed it, so it is reloaded on return
+                log::warn!("Disk {} at {:?} is no longer reachable; treating it as failed until it returns", disk.uuid, disk.path);
+                if disk.health != DiskHealth::Failed {
+                    disk.health_event(DiskHealth::Failed, "no longer reachable");
                 }
-
-                self.metrics.record_rebuild_success(extent.size as u64);
-                extent.rebuild_in_progress = false;
-                extent.rebuild_progress = Some(extent.fragment_locations.len());
-                metadata_w.save_extent(&extent)?;
-                log::info!("Rebuild/migration complete for extent {:?}", extent_uuid);
+                disk.health = DiskHealth::Failed;
+                detached.insert(disk.uuid);
             }
         }
-
-        log::info!("Mount-time rebuild scan complete");
-        Ok(())
+        
+        // Disks that were already missing when the pool was opened
+        let known_paths: Vec<std::path::PathBuf> = disks.iter().map(|d| d.lock().unwrap().path.clone()).collect();
+        for path in pool.disk_paths.iter().filter(|p| !known_paths.contains(p)) {
+            let Ok(mut disk) = pool.load_disk(path) else { continue };
+            if disks.iter().any(|d| d.lock().unwrap().uuid == disk.uuid) {
+                continue;
+            }
+            log::info!("Disk {} at {:?} is reachable; adding it to the pool", disk.uuid, path);
+            disk.events = Some(Arc::clone(&self.events));
+            disk.health_event(disk.health, "reachable; added to the pool");
+            returned.push(disk.uuid);
+            disks.push(Arc::new(Mutex::new(disk)));
+        }
+        drop(disks);
+        drop(detached);
+        
+        if !returned.is_empty() && !self.is_read_only() {
+            if let Err(e) = self.perform_mount_rebuild() {
+                log::error!("Rebuild scan after disks returned failed: {}", e);
+            }
+        }
+        returned
     }
     
-    /// Write data to a file
-    pub fn write_file(&self, ino: u64, data: &[u8], offset: u64) -> Result<()> {
-        log::debug!("Writing {} bytes to inode {} at offset {}", data.len(), ino, offset);
+    /// Run `reprobe_disks` against `pool` every `interval` until the engine is dropped
+    pub fn start_disk_probe(&mut self, pool: DiskPool, interval: std::time::Duration) {
+        let prober = self.background_handle();
+        self.disk_probe = Some(PeriodicTask::spawn(interval, move || {
+            prober.reprobe_disks(&pool);
+            prober.check_disk_usage();
+            prober.check_space_watermarks();
+        }));
+    }
+    
+    /// Run `poll_smart` now and every `interval` until the engine is dropped
+    pub fn start_smart_poll(&mut self, interval: std::time::Duration) {
+        let poller = self.background_handle();
+        poller.poll_smart();
+        self.smart_poll = Some(PeriodicTask::spawn(interval, move || {
+            poller.poll_smart();
+        }));
+    }
+    
+    /// Read a SMART sample of every reachable block-device disk and record it
+    ///
+    /// Directory-backed disks are skipped. The device is read without holding
+    /// the disk's lock, since `smartctl` can take seconds. Returns the disks
+    /// sampled.
+    pub fn poll_smart(&self) -> usize {
+        let devices: Vec<(Arc<Mutex<Disk>>, std::path::PathBuf)> = self
+            .disks
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|disk_arc| {
+                let disk = disk_arc.lock().unwrap();
+                (disk.kind == DiskKind::BlockDevice && disk.health != DiskHealth::Failed)
+                    .then(|| (Arc::clone(disk_arc), disk.path.clone()))
+            })
+            .collect();
         
-        // For simplicity, this prototype only supports full overwrites at offset 0
-        if offset != 0 {
-            return Err(anyhow!("Only full file writes at offset 0 are supported"));
+        let mut sampled = 0;
+        for (disk_arc, device) in devices {
+            let sample = match crate::smart::read_sample(&device) {
+                Ok(sample) => sample,
+                Err(e) => {
+                    log::warn!("No SMART sample of {:?}: {:#}", device, e);
+                    continue;
+                }
+            };
+            let mut disk = disk_arc.lock().unwrap();
+            match disk.record_smart(sample) {
+                Ok(warnings) if !warnings.is_empty() => {
+                    log::warn!("SMART on disk {} at {:?}: {}", disk.uuid, device, warnings.join("; "))
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Failed to save the SMART sample of disk {}: {:#}", disk.uuid, e),
+            }
+            sampled += 1;
         }
+        sampled
+    }
+    
+    /// Run `check_space_threshold` every `interval` until the engine is dropped
+    pub fn start_space_watch(&mut self, threshold_percent: u8, interval: std::time::Duration) {
+        let checker = self.background_handle();
+        let mut watch = crate::notify::SpaceWatch::new(threshold_percent);
+        checker.check_space_threshold(&mut watch);
+        self.space_watch = Some(PeriodicTask::spawn(interval, move || checker.check_space_threshold(&mut watch)));
+    }
+    
+    /// Record a `SpaceThreshold` event if pool usage crossed `watch`'s threshold since the last check
+    pub fn check_space_threshold(&self, watch: &mut crate::notify::SpaceWatch) {
+        let (used, capacity) = self.pool_usage();
+        if let Some((message, fields)) = watch.check(used, capacity) {
+            self.events.record_with_fields(EventKind::SpaceThreshold, None, None, message, Some(fields));
+        }
+    }
+    
+    /// Bytes used and capacity summed over every disk
+    fn pool_usage(&self) -> (u64, u64) {
+        self.disks.read().unwrap().iter().fold((0, 0), |(used, capacity), disk| {
+            let disk = disk.lock().unwrap();
+            (used + disk.used_bytes, capacity + disk.capacity_bytes)
+        })
+    }
+    
+    /// Compare pool usage with the space watermarks and return its level
+    ///
+    /// Runs before every write of new data and with each disk probe. Crossing
+    /// a watermark in either direction records a `SpaceWatermark` event;
+    /// rising to the high watermark or past it starts `reclaim_space` on a
+    /// background thread unless a pass is already running.
+    pub fn check_space_watermarks(&self) -> SpaceLevel {
+        let (used, capacity) = self.pool_usage();
+        let (level, previous) = self.space_pressure.update(used, capacity);
+        let Some(previous) = previous else { return level };
         
-        // Determine redundancy policy based on file size
-        let redundancy = if data.len() < DEFAULT_EXTENT_SIZE {
-            // Small files: use replication
-            RedundancyPolicy::Replication { copies: 3 }
-        } else {
-            // Large files: use erasure coding
-            RedundancyPolicy::ErasureCoding {
-                data_shards: 4,
-                parity_shards: 2,
-            }
+        let watermarks = self.space_watermarks();
+        let used_percent = used as f64 * 100.0 / capacity as f64;
+        let message = match level {
+            SpaceLevel::Critical => format!(
+                "Pool is {:.1}% full, at or above its critical watermark ({}%); writes of new data fail until space is freed",
+                used_percent, watermarks.critical_percent
+            ),
+            SpaceLevel::High if previous == SpaceLevel::Normal => format!(
+                "Pool is {:.1}% full, at or above its high watermark ({}%); reclaiming space and refusing low-priority writes",
+                used_percent, watermarks.high_percent
+            ),
+            SpaceLevel::High => format!(
+                "Pool is {:.1}% full, back below its critical watermark ({}%)",
+                used_percent, watermarks.critical_percent
+            ),
+            SpaceLevel::Normal => format!(
+                "Pool is {:.1}% full, back below its high watermark ({}%)",
+                used_percent, watermarks.high_percent
+            ),
         };
+        log::warn!("{}", message);
+        let fields = serde_json::json!({
+            "level": level,
+            "previous": previous,
+            "used_percent": (used_percent * 10.0).round() / 10.0,
+            "high_percent": watermarks.high_percent,
+            "critical_percent": watermarks.critical_percent,
+            "used_bytes": used,
+            "capacity_bytes": capacity,
+        });
+        self.events.record_with_fields(EventKind::SpaceWatermark, None, None, message, Some(fields));
         
-        // Split into extents using correct chunk boundaries
-        let extents = split_into_extents(data, redundancy);
-        let mut written_extents: Vec<Extent> = Vec::new();
-        
-        // Acquire disks write lock, collect references, then release before spawning
-        let disks_arc = self.disks.clone();
-        let disk_refs: Vec<Arc<Mutex<Disk>>> = {
-            let disks = disks_arc.write().unwrap();
-            disks.iter().map(|d| d.clone()).collect()
-        }; // RwLock is released here
+        if level > previous && !self.is_read_only() && self.space_pressure.begin_reclaim() {
+            let reclaimer = self.background_handle();
+            thread::spawn(move || {
+                let _class = IoClass::Gc.enter();
+                let report = reclaimer.reclaim_space();
+                log::info!(
+                    "Space reclamation: {} released extents, {} orphan bytes, {} bytes trimmed, {} bytes demoted",
+                    report.released_extents,
+                    report.orphan_bytes,
+                    report.trimmed_bytes,
+                    report.demoted_bytes
+                );
+                reclaimer.space_pressure.end_reclaim();
+                reclaimer.check_space_watermarks();
+            });
+        }
+        level
+    }
+    
+    /// Free what space can be freed without touching live data
+    ///
+    /// Finishes reclaiming released extents, collects orphaned fragments at
+    /// least `PRESSURE_ORPHAN_MIN_AGE` old, discards free space on raw block
+    /// devices and runs a tiering pass to demote cold extents off the fast
+    /// tiers. Each step's failure is logged and the rest still run.
+    pub fn reclaim_space(&self) -> SpaceReclaimReport {
+        let mut report = SpaceReclaimReport::default();
+        if self.is_read_only() {
+            return report;
+        }
+        match self.reclaim_released_extents() {
+            Ok(count) => report.released_extents = count,
+            Err(e) => log::warn!("Space reclamation could not reclaim released extents: {}", e),
+        }
+        match self.collect_orphans(PRESSURE_ORPHAN_MIN_AGE.as_secs()) {
+            Ok(gc) => report.orphan_bytes = gc.bytes_reclaimed(),
+            Err(e) => log::warn!("Space reclamation could not collect orphans: {}", e),
+        }
+        let disk_uuids: Vec<uuid::Uuid> = self.disks.read().unwrap().iter().map(|d| d.lock().unwrap().uuid).collect();
+        for disk_uuid in disk_uuids {
+            match self.trim_free_space(disk_uuid) {
+                Ok(bytes) => report.trimmed_bytes += bytes,
+                Err(e) => log::warn!("Space reclamation could not trim disk {}: {}", disk_uuid, e),
+            }
+        }
+        match self.run_tier_pass(&TierPassConfig::default()) {
+            Ok(tiers) => report.demoted_bytes = tiers.bytes_moved,
+            Err(e) => log::warn!("Space reclamation could not demote extents: {}", e),
+        }
+        report
+    }
+    
+    /// Recount the usage of every reachable disk whose counters drifted from a sampled estimate
+    ///
+    /// Runs with each disk probe. Returns the corrections made.
+    pub fn check_disk_usage(&self) -> Vec<crate::disk::UsageCorrection> {
+        if self.is_read_only() {
+            return Vec::new();
+        }
+        let detached = self.detached_disks.lock().unwrap().clone();
+        let mut corrections = Vec::new();
+        for disk_arc in self.disks.read().unwrap().iter() {
+            let mut disk = disk_arc.lock().unwrap();
+            if detached.contains(&disk.uuid) || !disk.is_reachable() {
+                continue;
+            }
+            match disk.check_usage_drift() {
+                Ok(Some(correction)) => corrections.push(correction),
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to check usage of disk {}: {}", disk.uuid, e),
+            }
+        }
+        corrections
+    }
+    
+    /// Classify extents with the access model saved in the pool, or with the thresholds alone
+    pub fn set_access_model_enabled(&self, enabled: bool) -> StorageResult<()> {
+        let model = if enabled {
+            let pool_dir = self.metadata.read().unwrap().pool_dir().to_path_buf();
+            Some(HmmClassifier::load(&pool_dir)?)
+        } else {
+            None
+        };
+        *self.access_model.write().unwrap() = model;
+        Ok(())
+    }
+    
+    /// The access model in use, if enabled
+    pub fn access_model(&self) -> Option<HmmClassifier> {
+        self.access_model.read().unwrap().clone()
+    }
+    
+    /// `extent` with pending reads applied and classified as the engine would
+    fn classified(&self, extent: Extent) -> Extent {
+        let mut extent = self.access.merged(extent);
+        extent.reclassify_with_model(self.access_model.read().unwrap().as_ref());
+        extent
+    }
+    
+    /// Train the access model on every extent's hourly history and save it to the pool
+    ///
+    /// Pending reads are flushed first so the newest hour counts. Does
+    /// nothing while the model is disabled. Returns the hours of history used.
+    pub fn train_access_model(&self) -> StorageResult<usize> {
+        let Some(mut model) = self.access_model() else {
+            return Ok(0);
+        };
+        if let Err(e) = self.flush_access_stats() {
+            log::warn!("Training the access model without unflushed reads: {}", e);
+        }
+        let now = chrono::Utc::now().timestamp();
+        let metadata = self.metadata.read().unwrap();
+        let histories: Vec<_> = metadata.iter_extents()?.filter_map(Result::ok).map(|e| e.hourly_observations(now)).collect();
+        let hours = model.train(&histories);
+        if !self.is_read_only() {
+            model.save(metadata.pool_dir())?;
+        }
+        drop(metadata);
+        let mut current = self.access_model.write().unwrap();
+        // Stays disabled if it was turned off while training
+        if current.is_some() {
+            *current = Some(model);
+        }
+        Ok(hours)
+    }
+    
+    /// Run `train_access_model` every `interval` until the engine is dropped
+    pub fn start_access_model_training(&mut self, interval: std::time::Duration) {
+        let trainer = self.background_handle();
+        self.model_training = Some(PeriodicTask::spawn(interval, move || match trainer.train_access_model() {
+            Ok(hours) => log::debug!("Trained the access model on {} hours of extent history", hours),
+            Err(e) => log::error!("Training the access model failed: {}", e),
+        }));
+    }
+    
+    /// Write pending extent reads and inode timestamps to metadata every `interval`
+    pub fn start_access_stats_flush(&mut self, interval: std::time::Duration) {
+        let flusher = self.background_handle();
+        self.access_flush = Some(PeriodicTask::spawn(interval, move || {
+            if let Err(e) = flusher.flush_access_stats() {
+                log::error!("Failed to flush extent access statistics: {}", e);
+            }
+            if let Err(e) = flusher.flush_inode_times() {
+                log::error!("Failed to flush inode timestamps: {}", e);
+            }
+        }));
+    }
+    
+    /// Fold reads counted in memory into the extents' metadata
+    ///
+    /// Reads are not persisted one by one; this runs periodically, on unmount
+    /// and whenever `read_slot` is about to act on an extent's classification.
+    /// Extents that no longer exist are skipped. Returns the number of extent
+    /// records written.
+    pub fn flush_access_stats(&self) -> StorageResult<usize> {
+        if self.is_read_only() {
+            return Ok(0);
+        }
+        let pending = self.access.take_all();
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        
+        let metadata = self.metadata.write().unwrap();
+        let mut written = 0;
+        let mut entries = pending.into_iter();
+        while let Some((extent_uuid, access)) = entries.next() {
+            let Ok(mut extent) = metadata.load_extent(&extent_uuid) else { continue };
+            access.apply(&mut extent);
+            if let Err(e) = metadata.save_extent(&extent) {
+                // Keep what was not written for the next flush
+                self.access.restore(extent_uuid, access);
+                for (uuid, access) in entries {
+                    self.access.restore(uuid, access);
+                }
+                return Err(e);
+            }
+            written += 1;
+        }
+        log::debug!("Flushed access statistics of {} extents", written);
+        Ok(written)
+    }
+    
+    /// Write atime and mtime updates held in memory to the inodes
+    ///
+    /// Runs alongside `flush_access_stats`, on fsync of the inode and on
+    /// unmount. Inodes deleted in the meantime are skipped. Returns the number
+    /// of inode records written.
+    pub fn flush_inode_times(&self) -> StorageResult<usize> {
+        if self.is_read_only() {
+            return Ok(0);
+        }
+        let pending = self.timestamps.take_all();
+        if pending.is_empty() {
+            return Ok(0);
+        }
         
-        #[cfg(test)]
-        eprintln!("[WRITE_FILE DEBUG] starting placement for {} extents", disk_refs.len());
+        let metadata = self.metadata.write().unwrap();
+        let mut written = 0;
+        let mut entries = pending.into_iter();
+        while let Some((ino, times)) = entries.next() {
+            let Ok(mut inode) = metadata.load_inode(ino) else { continue };
+            times.apply(&mut inode);
+            if let Err(e) = metadata.save_inode(&inode) {
+                // Keep what was not written for the next flush
+                self.timestamps.restore(ino, times);
+                for (ino, times) in entries {
+                    self.timestamps.restore(ino, times);
+                }
+                return Err(e);
+            }
+            written += 1;
+        }
+        log::debug!("Flushed timestamps of {} inodes", written);
+        Ok(written)
+    }
+    
+    /// Note a read of `inode` at the current time, as the atime mode allows
+    ///
+    /// Only recorded in memory; `flush_inode_times` persists it.
+    fn touch_atime(&self, inode: Inode) {
+        if self.is_read_only() {
+            return;
+        }
+        let now = now_timespec();
+        if self.atime_mode().wants_update(&self.timestamps.merged(inode.clone()), now.0) {
+            self.timestamps.record_atime(inode.ino, now);
+        }
+    }
+    
+    /// Explain a failed decode of `extent` by naming the disks it could not use
+    fn unreadable_extent(&self, extent: &Extent, err: anyhow::Error) -> anyhow::Error {
+        let disks = self.disks.read().unwrap();
+        let mut unavailable: Vec<String> = Vec::new();
+        for location in &extent.fragment_locations {
+            let described = match disks.iter().find(|d| d.lock().unwrap().uuid == location.disk_uuid) {
+                None => format!("{} (not attached)", location.disk_uuid),
+                Some(disk_arc) => {
+                    let disk = disk_arc.lock().unwrap();
+                    if disk.health != DiskHealth::Failed && disk.is_reachable() {
+                        continue;
+                    }
+                    format!("{} at {:?}", disk.uuid, disk.path)
+                }
+            };
+            if !unavailable.contains(&described) {
+                unavailable.push(described);
+            }
+        }
+        if unavailable.is_empty() {
+            return StorageError::Unrecoverable { extent: extent.uuid, detail: format!("{:#}", err) }.into();
+        }
+        let message = format!(
+            "Extent {} is unreadable: fragments on unavailable disks {}",
+            extent.uuid,
+            unavailable.join(", ")
+        );
+        log::error!("{}", message);
+        StorageError::Unrecoverable { extent: extent.uuid, detail: format!("{}: {:#}", message, err) }.into()
+    }
+    
+    /// Perform mount-time rebuild: scan extents in the background and queue
+    /// those with missing fragments or fragments on draining disks
+    ///
+    /// Returns immediately; use `wait_for_rebuilds` to wait for completion.
+    pub fn perform_mount_rebuild(&self) -> StorageResult<()> {
+        log::info!("Starting mount-time rebuild scan");
+        let scanner = self.background_handle();
+        self.rebuild_queue.begin_producer();
+        thread::spawn(move || {
+            let counts = scanner.scan_for_rebuilds();
+            if let Err(e) = &counts {
+                log::error!("Mount-time rebuild scan failed: {}", e);
+            }
+            scanner.rebuild_queue.end_producer();
+            if let Ok((queued, unrecoverable)) = counts {
+                if scanner.rebuild_queue.wait_idle() {
+                    scanner.events.record_with_fields(
+                        EventKind::RebuildPassFinished,
+                        None,
+                        None,
+                        format!("Mount-time rebuild finished: {} queued, {} unrecoverable", queued, unrecoverable),
+                        Some(serde_json::json!({"queued": queued, "unrecoverable": unrecoverable})),
+                    );
+                }
+            }
+        });
+        Ok(())
+    }
+    
+    /// Queue every extent that needs a rebuild or migration
+    ///
+    /// Returns the number of extents queued and the number found unrecoverable.
+    fn scan_for_rebuilds(&self) -> Result<(usize, usize)> {
+        let extents = self.metadata.read().unwrap().iter_extents()?;
+        let draining_disk_uuids = self.draining_disk_uuids();
+        let (mut queued, mut unrecoverable) = (0, 0);
+
+        for extent in extents {
+            let extent = match extent {
+                Ok(extent) => extent,
+                Err(e) => {
+                    log::warn!("Skipping extent in rebuild scan: {:#}", e);
+                    continue;
+                }
+            };
+            let disks = self.disks.read().unwrap();
+            let fragments = match self.read_fragments(&extent, &disks) {
+                Ok(f) => f,
+                Err(e) => {
+                    log::warn!("Failed to read fragments for extent {:?}: {:?}", extent.uuid, e);
+                    continue;
+                }
+            };
+            drop(disks);
+
+            let available_count = fragments.iter().filter(|f| f.is_some()).count();
+            let min_needed = extent.redundancy.min_fragments();
+            if available_count < min_needed {
+                log::error!("Extent {:?} is unrecoverable: {}/{} fragments", extent.uuid, available_count, min_needed);
+                self.unrecoverable_event(extent.uuid, available_count, min_needed);
+                unrecoverable += 1;
+                continue;
+            }
+
+            let needs_rebuild = available_count < extent.redundancy.fragment_count();
+            let has_draining_fragment = extent
+                .fragment_locations
+                .iter()
+                .any(|loc| draining_disk_uuids.contains(&loc.disk_uuid));
+            let upgradable = self.pending_upgrade(&extent).is_some();
+
+            if needs_rebuild || has_draining_fragment || upgradable {
+                if self.rebuild_queue.enqueue(extent.uuid, available_count - min_needed) == EnqueueResult::Closed {
+                    break;
+                }
+                queued += 1;
+                self.metrics.update_rebuild_queue_depth(self.rebuild_queue.depth() as u64);
+            }
+        }
+
+        log::info!("Mount-time rebuild scan complete");
+        Ok((queued, unrecoverable))
+    }
+
+    fn unrecoverable_event(&self, extent_uuid: uuid::Uuid, available: usize, needed: usize) {
+        self.events.record_with_fields(
+            EventKind::Unrecoverable,
+            Some(extent_uuid),
+            None,
+            format!("{}/{} fragments available", available, needed),
+            Some(serde_json::json!({"available_fragments": available, "needed_fragments": needed})),
+        );
+    }
+    
+    fn draining_disk_uuids(&self) -> Vec<uuid::Uuid> {
+        self.disks
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|d| {
+                let d = d.lock().unwrap();
+                if d.health == crate::disk::DiskHealth::Draining { Some(d.uuid) } else { None }
+            })
+            .collect()
+    }
+    
+    /// The policy a degraded extent was written in place of, once enough disks are writable for it
+    fn pending_upgrade(&self, extent: &Extent) -> Option<RedundancyPolicy> {
+        let intended = extent.intended_policy?;
+        let writable = crate::placement::writable_disk_count(&self.disks.read().unwrap());
+        (writable >= intended.fragment_count()).then_some(intended)
+    }
+    
+    /// Rebuild missing fragments of an extent and migrate fragments off draining disks
+    ///
+    /// An extent written below its policy is re-encoded to the intended policy
+    /// instead once the disks allow it. Re-checks the extent first, so stale
+    /// queue entries are cheap no-ops.
+    fn rebuild_extent(&self, extent_uuid: uuid::Uuid) -> Result<u64> {
+        // Hold the metadata write lock so concurrent reads cannot persist stale fragment locations
+        let metadata_w = self.metadata.write().unwrap();
+        let mut extent = metadata_w.load_extent(&extent_uuid)?;
+
+        let disks = self.disks.read().unwrap();
+        let fragments = self.read_fragments(&extent, &disks)?;
+        drop(disks);
+
+        let available_count = fragments.iter().filter(|f| f.is_some()).count();
+        let required = extent.redundancy.fragment_count();
+        let min_needed = extent.redundancy.min_fragments();
+        if available_count < min_needed {
+            self.unrecoverable_event(extent_uuid, available_count, min_needed);
+            return Err(StorageError::Unrecoverable {
+                extent: extent_uuid,
+                detail: format!("Extent {} is unrecoverable: {}/{} fragments", extent_uuid, available_count, min_needed),
+            }
+            .into());
+        }
 
+        let needs_rebuild = available_count < required;
+        let draining_disk_uuids = self.draining_disk_uuids();
+        let has_draining_fragment = extent
+            .fragment_locations
+            .iter()
+            .any(|loc| draining_disk_uuids.contains(&loc.disk_uuid));
+        let upgrade = self.pending_upgrade(&extent);
+        if !needs_rebuild && !has_draining_fragment && upgrade.is_none() {
+            return Ok(0);
+        }
+
+        if let Some(intended) = upgrade {
+            log::info!("Upgrading degraded extent {:?} from {} to {}", extent_uuid, extent.redundancy, intended);
+        } else if has_draining_fragment && !needs_rebuild {
+            log::info!("Migrating fragments for extent {:?} away from draining disks", extent_uuid);
+        } else {
+            log::info!("Rebuilding extent {:?}: {}/{} available", extent_uuid, available_count, required);
+        }
+
+        extent.rebuild_in_progress = true;
+        extent.rebuild_progress = Some(available_count);
+        metadata_w.save_extent(&extent)?;
+
+        self.metrics.record_rebuild_start();
+        self.events.record(
+            EventKind::RebuildStarted,
+            Some(extent_uuid),
+            None,
+            format!("{}/{} fragments available", available_count, required),
+        );
+        let disks_mut = self.disks.write().unwrap();
+        let before = extent.clone();
+        let result = match upgrade {
+            Some(intended) => self.placement.rebundle_extent(&mut extent, &disks_mut, &fragments, intended),
+            None => self.placement.rebuild_extent(&mut extent, &disks_mut, &fragments),
+        };
+        if let Err(e) = result {
+            self.metrics.record_rebuild_failure();
+            self.events.record(EventKind::RebuildFailed, Some(extent_uuid), None, format!("{:#}", e));
+            // A failed upgrade may have begun a policy transition; keep the extent as it was
+            if upgrade.is_some() {
+                extent = before;
+            }
+            extent.rebuild_in_progress = false;
+            metadata_w.save_extent(&extent)?;
+            return Err(e);
+        }
+        if upgrade.is_some() {
+            extent.intended_policy = None;
+            self.metrics.record_degraded_upgrade();
+        }
+
+        self.metrics.record_rebuild_success(extent.size as u64);
+        self.events.record(
+            EventKind::RebuildFinished,
+            Some(extent_uuid),
+            None,
+            format!("{} fragments placed", extent.fragment_locations.len()),
+        );
+        extent.rebuild_in_progress = false;
+        extent.rebuild_progress = Some(extent.fragment_locations.len());
+        metadata_w.save_extent(&extent)?;
+        // The new fragments are written as any placement is, so they are checked the same way
+        self.record_recent_write(extent_uuid);
+        log::info!("Rebuild/migration complete for extent {:?}", extent_uuid);
+        Ok(extent.size as u64)
+    }
+    
+    /// Write data to a file
+    ///
+    /// At offset 0 the data replaces the whole file, truncating it to
+    /// `data.len()`. At any other offset only the covered bytes change, see
+    /// `write_range`. Either way the write bypasses the write buffer; buffered
+    /// data for the file is discarded or flushed first.
+    pub fn write_file(&self, ino: u64, data: &[u8], offset: u64) -> StorageResult<()> {
+        log::debug!("Writing {} bytes to inode {} at offset {}", data.len(), ino, offset);
+        self.check_writable()?;
+        let _writer = self.lock_inode_writes(ino);
+        
+        if offset != 0 {
+            self.flush_buffered(ino, FlushCause::Explicit)?;
+            return self.write_range(ino, offset, data);
+        }
+        // The new contents supersede anything still buffered
+        self.write_buffer.discard(ino);
+        
+        // Honour a policy requested through the redundancy xattr, otherwise pick by file size
+        let (wanted, verify) = {
+            let metadata = self.metadata.read().unwrap();
+            // Fail before writing any fragments; the charge itself is journaled below
+            let inode = metadata.load_inode(ino)?;
+            metadata.charge_quotas(inode.parent_ino, data.len() as i64 - inode.size as i64, 0)?;
+            (Self::policy_for_size(&metadata, ino, data.len() as u64), self.verify_writes_for(&metadata, ino))
+        };
+        
+        // Acquire disks write lock, collect references, then release before spawning
+        let disks_arc = self.disks.clone();
+        let disk_refs: Vec<Arc<Mutex<Disk>>> = {
+            let disks = disks_arc.write().unwrap();
+            disks.iter().map(|d| d.clone()).collect()
+        }; // RwLock is released here
+        let (redundancy, intended) = self.policy_to_write(&disk_refs, wanted);
+        
+        // Split into extents using correct chunk boundaries; a rewrite takes the pool's current size
+        let extent_size = self.extent_size();
+        let extents = split_into_extents(data, redundancy, extent_size);
+        let mut written_extents: Vec<Extent> = Vec::new();
+        let mut in_flight = self.in_flight.begin();
+        let demand: Vec<(RedundancyPolicy, usize)> = extents.iter().map(|e| (redundancy, e.size)).collect();
+        let _reservation = self.reserve_space(&disk_refs, &demand)?;
+        
         for (idx, mut extent) in extents.into_iter().enumerate() {
-            let chunk_start = idx * DEFAULT_EXTENT_SIZE;
+            let chunk_start = idx * extent_size;
             let chunk_end = chunk_start + extent.size;
             let chunk = &data[chunk_start..chunk_end];
 
-            let fragments = redundancy::encode(chunk, extent.redundancy)?;
-            if let Err(err) = self.placement.place_extent(&mut extent, &disk_refs, &fragments) {
+            let fragments = self.encode_extent(&mut extent, chunk)?;
+            if let Err(err) = self.place_extent(&mut extent, &disk_refs, &fragments, verify, &mut in_flight) {
                 // Cleanup fragments from previously written extents before exiting
                 for previous in &written_extents {
                     for location in &previous.fragment_locations {
@@ -269,426 +1528,2092 @@ impl StorageEngine {
                         }
                     }
                 }
-                return Err(err);
+                return Err(err.into());
             }
 
             extent.record_write();
+            self.record_degraded_write(&mut extent, intended);
             written_extents.push(extent);
         }
 
-        #[cfg(test)]
-        eprintln!("[WRITE_FILE DEBUG] placement complete; {} extents", written_extents.len());
-
         let extent_ids: Vec<_> = written_extents.iter().map(|e| e.uuid).collect();
 
-        // Persist metadata after all fragments are durable; roll back fragments if persistence fails
-        if let Err(err) = (|| -> Result<()> {
-            #[cfg(test)]
-            eprintln!("[WRITE_FILE DEBUG] persisting metadata: {} extents", written_extents.len());
-            let metadata = self.metadata.write().unwrap();
-            for extent in &written_extents {
-                #[cfg(test)]
-                eprintln!("[WRITE_FILE DEBUG] save_extent {}", extent.uuid);
-                metadata.save_extent(extent)?;
+        // Journal all metadata mutations as one transaction once every fragment is durable
+        let mut metadata = self.metadata.write().unwrap();
+        let journaled = (|| -> StorageResult<crate::metadata_tx::MetadataTransaction> {
+            let mut ops: Vec<MetadataOp> = written_extents
+                .iter()
+                .map(|extent| MetadataOp::SaveExtent(Box::new(extent.clone())))
+                .collect();
+            let superseded = metadata.load_extent_map(ino)?;
+            ops.push(MetadataOp::SaveExtentMap(ExtentMap {
+                extents: extent_ids.clone(),
+                ..ExtentMap::new(ino, extent_size)
+            }));
+
+            let mut inode = metadata.load_inode(ino)?;
+            let size_change = data.len() as i64 - inode.size as i64;
+            inode.size = data.len() as u64;
+            inode.touch_contents(now_timespec());
+            ops.extend(Self::quota_ops(&metadata, inode.parent_ino, size_change, 0)?);
+            ops.push(MetadataOp::SaveInode(inode));
+            ops.extend(superseded.data_extents().map(|uuid| MetadataOp::ReleaseExtent(*uuid)));
+
+            metadata.journal_transaction(ops)
+        })();
+
+        let tx = match journaled {
+            Ok(tx) => tx,
+            Err(err) => {
+                // Nothing was committed; the new fragments are unreferenced
+                let disks = self.disks.write().unwrap();
+                for extent in &written_extents {
+                    Self::delete_fragments(&disks, extent);
+                }
+                return Err(err);
             }
+        };
 
-            let extent_map = ExtentMap {
-                ino,
-                extents: extent_ids.clone(),
-                checksum: None,
-            };
-            #[cfg(test)]
-            eprintln!("[WRITE_FILE DEBUG] save_extent_map ino={}", ino);
-            metadata.save_extent_map(&extent_map)?;
+        // The write is committed once journaled; a failed apply is replayed on the next mount
+        if let Err(err) = metadata.apply_transaction(tx) {
+            log::error!("Applying journaled write to inode {} failed, will replay on recovery: {}", ino, err);
+            return Err(err);
+        }
+        drop(metadata);
+        self.reclaim_after_commit();
+        
+        // Record metrics for write operation
+        self.metrics.record_disk_write(data.len() as u64);
+        self.inode_activity.record(ino, data.len() as u64);
+        
+        log::info!("Wrote {} bytes to inode {} across {} extents", 
+                   data.len(), ino, written_extents.len());
+        
+        Ok(())
+    }
+    
+    /// Redundancy for new data in a file of `size` bytes
+    ///
+    /// A policy requested through the redundancy xattr wins; otherwise small
+    /// files are replicated and large ones erasure coded.
+    fn policy_for_size(metadata: &MetadataManager, ino: u64, size: u64) -> RedundancyPolicy {
+        Self::requested_redundancy_in(metadata, ino).unwrap_or_else(|| Self::default_policy_for_size(size))
+    }
+    
+    /// Policy given to a file of `size` bytes that does not request one
+    pub fn default_policy_for_size(size: u64) -> RedundancyPolicy {
+        if size < DEFAULT_EXTENT_SIZE as u64 {
+            RedundancyPolicy::Replication { copies: 3 }
+        } else {
+            RedundancyPolicy::ErasureCoding {
+                data_shards: 4,
+                parity_shards: 2,
+            }
+        }
+    }
+    
+    /// Overwrite part of a file, growing it if the range ends past its size
+    ///
+    /// Every extent the range touches is replaced copy-on-write: partially
+    /// covered extents are read, patched and re-encoded into new extents under
+    /// their existing policy. Slots skipped over when writing past the end
+    /// become holes. The new map commits as one transaction and the replaced
+    /// extents are only reclaimed after that, so a crash at any point leaves
+    /// either the old or the new contents.
+    pub fn write_range(&self, ino: u64, offset: u64, data: &[u8]) -> StorageResult<()> {
+        log::debug!("Writing {} bytes to inode {} at offset {}", data.len(), ino, offset);
+        self.check_writable()?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        
+        let mut metadata = self.metadata.write().unwrap();
+        let mut inode = metadata.load_inode(ino)?;
+        let end = offset + data.len() as u64;
+        let new_size = inode.size.max(end);
+        let grows = new_size > inode.size;
+        let now = now_timespec();
+        let quota_ops = Self::quota_ops(&metadata, inode.parent_ino, (new_size - inode.size) as i64, 0)?;
+        
+        let mut extent_map = metadata.load_extent_map(ino)?;
+        if extent_map.data_extents().next().is_none() {
+            // Nothing but holes yet: lay the file out in the pool's current extent size
+            extent_map = ExtentMap::new(ino, self.extent_size());
+        }
+        let extent_size = extent_map.extent_size;
+        let disk_refs: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().iter().cloned().collect();
+        let default_policy = Self::policy_for_size(&metadata, ino, new_size);
+        let verify = self.verify_writes_for(&metadata, ino);
+        let mut in_flight = self.in_flight.begin();
+        
+        let first = extent_map.slot_index(offset);
+        let last = extent_map.slot_index(end - 1);
+        // Replacements keep an existing extent's policy, or the one it was meant to have, and
+        // its size; new slots take the default
+        let planned = (first..=last)
+            .map(|index| {
+                let to = (end - extent_map.slot_offset(index)).min(extent_size as u64) as usize;
+                let (wanted, size) = match extent_map.extents.get(index).filter(|uuid| !ExtentMap::is_hole(uuid)) {
+                    Some(uuid) => metadata
+                        .load_extent(uuid)
+                        .map(|old| (old.intended_policy.unwrap_or(old.redundancy), old.size.max(to)))?,
+                    None => (default_policy, to),
+                };
+                Ok((self.policy_to_write(&disk_refs, wanted), size))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let demand: Vec<(RedundancyPolicy, usize)> = planned.iter().map(|((policy, _), size)| (*policy, *size)).collect();
+        let _reservation = self.reserve_space(&disk_refs, &demand)?;
+        let mut released: Vec<Extent> = Vec::new();
+        let mut replacements: Vec<Extent> = Vec::new();
+        
+        let journaled = (|| -> StorageResult<crate::metadata_tx::MetadataTransaction> {
+            if extent_map.extents.len() <= last {
+                extent_map.extents.resize(last + 1, ExtentMap::HOLE);
+            }
+            
+            for index in first..=last {
+                let slot_start = extent_map.slot_offset(index);
+                let from = (offset.max(slot_start) - slot_start) as usize;
+                let to = (end - slot_start).min(extent_size as u64) as usize;
+                let patch = &data[(slot_start + from as u64 - offset) as usize..(slot_start + to as u64 - offset) as usize];
+                
+                let old_uuid = extent_map.extents[index];
+                let old = if ExtentMap::is_hole(&old_uuid) {
+                    None
+                } else {
+                    Some(metadata.load_extent(&old_uuid)?)
+                };
+                
+                // Keep the bytes of the old extent the write does not cover
+                let mut slot = match &old {
+                    Some(extent) if from > 0 || to < extent.size => {
+                        let fragments = self.read_fragments_for_decode(extent, &disk_refs).fragments;
+                        let old_data = extent.unpack(redundancy::decode(&fragments, extent.redundancy, extent.stored_size())?)?;
+                        if !extent.verify_checksum(&old_data) {
+                            return Err(checksum_failed(extent.uuid));
+                        }
+                        old_data
+                    }
+                    _ => Vec::new(),
+                };
+                if slot.len() < to {
+                    slot.resize(to, 0);
+                }
+                slot[from..to].copy_from_slice(patch);
+                
+                let (policy, intended) = planned[index - first].0;
+                let mut replacement = Extent::new(&slot, policy);
+                replacement.extent_size = extent_size;
+                let fragments = self.encode_extent(&mut replacement, &slot)?;
+                self.place_extent(&mut replacement, &disk_refs, &fragments, verify, &mut in_flight)?;
+                self.record_degraded_write(&mut replacement, intended);
+                extent_map.extents[index] = replacement.uuid;
+                replacements.push(replacement);
+                released.extend(old);
+            }
+            
+            let mut ops: Vec<MetadataOp> = replacements
+                .iter()
+                .map(|extent| MetadataOp::SaveExtent(Box::new(extent.clone())))
+                .collect();
+            ops.push(MetadataOp::SaveExtentMap(extent_map.clone()));
+            // Overwrites within the file leave the inode alone; their mtime is batched
+            if grows {
+                inode.size = new_size;
+                inode.touch_contents(now);
+                ops.extend(quota_ops);
+                ops.push(MetadataOp::SaveInode(inode.clone()));
+            }
+            ops.extend(released.iter().map(|extent| MetadataOp::ReleaseExtent(extent.uuid)));
+            metadata.journal_transaction(ops)
+        })();
+        
+        let tx = match journaled {
+            Ok(tx) => tx,
+            Err(err) => {
+                for replacement in &replacements {
+                    Self::delete_fragments(&disk_refs, replacement);
+                }
+                return Err(err);
+            }
+        };
+        metadata.apply_transaction(tx)?;
+        drop(metadata);
+        if !grows {
+            self.timestamps.record_mtime(ino, now);
+        }
+        self.reclaim_after_commit();
+        
+        self.metrics.record_disk_write(data.len() as u64);
+        self.inode_activity.record(ino, data.len() as u64);
+        log::debug!("Wrote {} bytes to inode {} across {} extents", data.len(), ino, replacements.len());
+        Ok(())
+    }
+    
+    /// Write through the per-inode write buffer
+    ///
+    /// Sequential writes are coalesced and reach the extents a whole extent at a
+    /// time; the rest stays buffered until fsync, release, the flush timer or
+    /// memory pressure writes it out. Reads see buffered bytes immediately.
+    pub fn buffered_write(&self, ino: u64, offset: u64, data: &[u8]) -> StorageResult<()> {
+        self.check_writable()?;
+        let _writer = self.lock_inode_writes(ino);
+        Ok(self.buffer_write(ino, offset, data)?)
+    }
+    
+    /// Append to a file through the write buffer, returning the offset written at
+    ///
+    /// The end of file, including buffered data, is found and written under the
+    /// inode's write lock, so concurrent appenders never overlap and writers at
+    /// explicit offsets cannot slip in between.
+    pub fn append_write(&self, ino: u64, data: &[u8]) -> StorageResult<u64> {
+        self.check_writable()?;
+        let _writer = self.lock_inode_writes(ino);
+        // The buffer first: a run flushed in between has already grown the inode
+        let buffered_end = self.write_buffer.buffered_end(ino).unwrap_or(0);
+        let offset = self.metadata.read().unwrap().load_inode(ino)?.size.max(buffered_end);
+        self.buffer_write(ino, offset, data)?;
+        Ok(offset)
+    }
+    
+    fn buffer_write(&self, ino: u64, offset: u64, data: &[u8]) -> Result<()> {
+        self.check_quota_for_growth(ino, offset + data.len() as u64)?;
+        let coalesced = self
+            .write_buffer
+            .write(ino, offset, data, |ino, run, cause| self.flush_run(ino, run, cause))?;
+        if coalesced {
+            self.metrics.record_write_buffer_coalesced();
+        }
+        Ok(())
+    }
+    
+    /// Fail with `QuotaExceeded` if growing `ino` to `end` bytes would pass a quota
+    ///
+    /// Buffered data is only charged when it is flushed, so writes check up
+    /// front against the size including what is still buffered.
+    fn check_quota_for_growth(&self, ino: u64, end: u64) -> Result<()> {
+        let buffered_end = self.write_buffer.buffered_end(ino).unwrap_or(0);
+        let metadata = self.metadata.read().unwrap();
+        if !metadata.has_quotas() {
+            return Ok(());
+        }
+        let inode = metadata.load_inode(ino)?;
+        let size = inode.size.max(buffered_end);
+        if end > size {
+            metadata.charge_quotas(inode.parent_ino, (end - size) as i64, 0)?;
+        }
+        Ok(())
+    }
+    
+    /// Quota records charged with a usage change under `parent_ino`, as transaction ops
+    fn quota_ops(metadata: &MetadataManager, parent_ino: u64, bytes: i64, inodes: i64) -> Result<Vec<MetadataOp>> {
+        Ok(metadata
+            .charge_quotas(parent_ino, bytes, inodes)?
+            .into_iter()
+            .map(MetadataOp::SaveQuota)
+            .collect())
+    }
+    
+    /// Save an inode, in one transaction with any quota records it changes
+    fn save_inode_with_quotas(metadata: &mut MetadataManager, inode: &Inode, quota_ops: Vec<MetadataOp>) -> Result<()> {
+        let mut ops = quota_ops;
+        ops.push(MetadataOp::SaveInode(inode.clone()));
+        Ok(metadata.apply_batch(ops)?)
+    }
+    
+    /// Write out an inode's buffered data because its file was closed
+    ///
+    /// After this returns the data is in extents but, like any write, only
+    /// durable once `sync_inode` has run.
+    pub fn flush_file(&self, ino: u64) -> StorageResult<()> {
+        Ok(self.flush_buffered(ino, FlushCause::Release)?)
+    }
+    
+    fn flush_buffered(&self, ino: u64, cause: FlushCause) -> Result<()> {
+        self.write_buffer
+            .flush_inode(ino, cause, |ino, run, cause| self.flush_run(ino, run, cause))
+    }
+    
+    /// Write-buffer callback: store one dirty run in the file's extents
+    fn flush_run(&self, ino: u64, run: &DirtyRun, cause: FlushCause) -> Result<()> {
+        log::debug!("Flushing {} buffered bytes of inode {} ({:?})", run.data.len(), ino, cause);
+        self.metrics.record_write_buffer_flush(cause);
+        Ok(self.write_range(ino, run.offset, &run.data)?)
+    }
+    
+    /// Copy buffered bytes of `ino` over `data`, which holds the file from `offset`
+    ///
+    /// `data` is extended up to `limit` bytes when the run reaches past it.
+    fn overlay_buffered(run: &DirtyRun, offset: u64, limit: u64, data: &mut Vec<u8>) {
+        let start = run.offset.max(offset);
+        let end = run.end().min(offset + limit);
+        if start >= end {
+            return;
+        }
+        let (from, to) = ((start - offset) as usize, (end - offset) as usize);
+        if data.len() < to {
+            data.resize(to, 0);
+        }
+        let src = (start - run.offset) as usize;
+        data[from..to].copy_from_slice(&run.data[src..src + (to - from)]);
+    }
+    
+    /// Read data from a file
+    ///
+    /// Holes in the extent map, and any tail past the last extent up to the
+    /// inode size, read as zeros without touching the disks. Bytes still in the
+    /// write buffer take precedence over the extents.
+    pub fn read_file(&self, ino: u64) -> StorageResult<Vec<u8>> {
+        log::debug!("Reading inode {}", ino);
+        
+        // Snapshot before the metadata lock; flushes take the locks in that order
+        let buffered = self.write_buffer.snapshot(ino);
+        let metadata = self.metadata.read().unwrap();
+        
+        // Load extent map
+        let extent_map = metadata.load_extent_map(ino)?;
+        let inode = metadata.load_inode(ino).ok();
+        let stored_size = inode.as_ref().map_or(0, |inode| inode.size);
+        let uncached = inode.as_ref().is_some_and(is_nocache);
+        let file_size = buffered.as_ref().map_or(stored_size, |run| stored_size.max(run.end()));
+        if let Some(inode) = inode {
+            self.touch_atime(inode);
+        }
+        
+        if extent_map.extents.is_empty() && file_size == 0 {
+            return Ok(Vec::new());
+        }
+        
+        let mut result = Vec::new();
+        
+        // A policy requested through the redundancy xattr overrides access-based migration
+        let pinned_policy = Self::requested_redundancy_in(&metadata, ino);
+        
+        // Read each extent
+        for (index, extent_uuid) in extent_map.extents.iter().enumerate() {
+            if ExtentMap::is_hole(extent_uuid) {
+                result.resize(result.len() + extent_map.slot_len(index, file_size) as usize, 0);
+                continue;
+            }
+            
+            // Every slot but the last is a full extent; pad a short one grown past by fallocate
+            result.resize(extent_map.slot_offset(index) as usize, 0);
+            let extent_data = self.read_slot(&metadata, extent_uuid, pinned_policy, uncached)?;
+            result.extend_from_slice(&extent_data);
+        }
+        
+        // Sparse tail beyond the last extent
+        if (result.len() as u64) < file_size {
+            result.resize(file_size as usize, 0);
+        }
+        if let Some(run) = &buffered {
+            Self::overlay_buffered(run, 0, file_size, &mut result);
+        }
+        
+        // Record metrics for read operation
+        self.metrics.record_disk_read(result.len() as u64);
+        self.inode_activity.record(ino, result.len() as u64);
+        
+        log::debug!("Read {} bytes from inode {}", result.len(), ino);
+        Ok(result)
+    }
+    
+    /// Read up to `size` bytes of a file starting at `offset`
+    ///
+    /// Only the extents overlapping the range are fetched; holes read as zeros.
+    /// The range is clipped to the inode size, including buffered writes past it.
+    pub fn read_range(&self, ino: u64, offset: u64, size: u64) -> StorageResult<Vec<u8>> {
+        self.read_range_with(ino, offset, size, false)
+    }
+    
+    /// `read_range` for O_DIRECT handles and scans
+    ///
+    /// Extents are decoded from the disks even when cached, the decoded data
+    /// is not cached and reads count as scan reads. Files with the nocache
+    /// xattr are always read this way.
+    pub fn read_range_uncached(&self, ino: u64, offset: u64, size: u64) -> StorageResult<Vec<u8>> {
+        self.read_range_with(ino, offset, size, true)
+    }
+    
+    fn read_range_with(&self, ino: u64, offset: u64, size: u64, uncached: bool) -> StorageResult<Vec<u8>> {
+        log::debug!("Reading {} bytes from inode {} at offset {}", size, ino, offset);
+        
+        let buffered = self.write_buffer.snapshot(ino);
+        let metadata = self.metadata.read().unwrap();
+        let inode = metadata.load_inode(ino)?;
+        let stored_size = inode.size;
+        let uncached = uncached || is_nocache(&inode);
+        self.touch_atime(inode);
+        let file_size = buffered.as_ref().map_or(stored_size, |run| stored_size.max(run.end()));
+        let end = offset.saturating_add(size).min(file_size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        
+        let extent_map = metadata.load_extent_map(ino)?;
+        let pinned_policy = Self::requested_redundancy_in(&metadata, ino);
+        let mut result = self.read_mapped(&metadata, &extent_map, offset, end, pinned_policy, uncached)?;
+        if let Some(run) = &buffered {
+            Self::overlay_buffered(run, offset, end - offset, &mut result);
+        }
+        
+        self.metrics.record_disk_read(result.len() as u64);
+        self.inode_activity.record(ino, result.len() as u64);
+        Ok(result)
+    }
+    
+    /// Bytes `offset..end` of the file laid out by `extent_map`, holes as zeros
+    fn read_mapped(
+        &self,
+        metadata: &MetadataManager,
+        extent_map: &ExtentMap,
+        offset: u64,
+        end: u64,
+        pinned_policy: Option<RedundancyPolicy>,
+        uncached: bool,
+    ) -> Result<Vec<u8>> {
+        let first = extent_map.slot_index(offset);
+        let last = extent_map.slot_index(end - 1);
+        let mut result = if first == last { Vec::new() } else { self.buffers.take((end - offset) as usize) };
+        for index in first..=last {
+            let slot_start = extent_map.slot_offset(index);
+            let from = (offset.max(slot_start) - slot_start) as usize;
+            let to = (end - slot_start).min(extent_map.extent_size as u64) as usize;
+            
+            match extent_map.extents.get(index) {
+                Some(extent_uuid) if !ExtentMap::is_hole(extent_uuid) => {
+                    let mut extent_data = self.read_slot(metadata, extent_uuid, pinned_policy, uncached)?;
+                    extent_data.resize(extent_data.len().max(to), 0);
+                    if first == last {
+                        // Within one extent its buffer is the result, without another copy
+                        extent_data.truncate(to);
+                        extent_data.drain(..from);
+                        result = extent_data;
+                    } else {
+                        result.extend_from_slice(&extent_data[from..to]);
+                        self.buffers.give(extent_data);
+                    }
+                }
+                _ => result.resize(result.len() + (to - from), 0),
+            }
+        }
+        Ok(result)
+    }
+    
+    /// Read and verify one extent of a file, recording the access
+    ///
+    /// Triggers lazy migration (unless the file pins its policy) and queues a
+    /// background rebuild if fragments were lost. Returns the extent's bytes
+    /// without padding. An `uncached` read neither uses the data cache nor
+    /// migrates, and counts as a scan read.
+    fn read_slot(
+        &self,
+        metadata: &MetadataManager,
+        extent_uuid: &uuid::Uuid,
+        pinned_policy: Option<RedundancyPolicy>,
+        uncached: bool,
+    ) -> Result<Vec<u8>> {
+        let read_only = self.is_read_only();
+        // Counted in memory; persisted by `flush_access_stats`
+        let record_access = || match (read_only, uncached) {
+            (true, _) => {}
+            (false, false) => self.access.record_read(*extent_uuid),
+            (false, true) => self.access.record_scan_read(*extent_uuid),
+        };
+        // Decoded and verified by the prefetcher; extents never change under a UUID
+        if !uncached {
+            if let Some(extent_data) = self.data_cache.get(extent_uuid) {
+                self.metrics.record_cache_hit();
+                record_access();
+                return Ok(extent_data);
+            }
+            self.metrics.record_cache_miss();
+        }
+        let mut extent = metadata.load_extent(extent_uuid)?;
+        record_access();
+        
+        // Read just enough fragments to decode with current policy
+        let disks = self.disks.read().unwrap();
+        let FragmentReads { fragments, failed } = self.read_fragments_for_decode(&extent, &disks);
+        drop(disks);
+        
+        // Decode data with current policy
+        let mut payload = self.buffers.take(extent.stored_size());
+        redundancy::decode_into(&fragments, extent.redundancy, extent.stored_size(), &mut payload)
+            .map_err(|e| self.unreadable_extent(&extent, e))?;
+        let extent_data = extent.unpack(payload)?;
+        
+        // Verify checksum
+        if !extent.verify_checksum(&extent_data) {
+            return Err(checksum_failed(*extent_uuid).into());
+        }
+        
+        // Check if lazy migration is needed (after successful read), counting unflushed reads
+        // A degraded extent is upgraded by the rebuild worker rather than migrated here
+        let degraded = extent.intended_policy.is_some();
+        if degraded && failed == 0 && !read_only && self.pending_upgrade(&extent).is_some() {
+            self.queue_rebuild(*extent_uuid, extent.redundancy.failures_tolerated());
+        }
+        let should_migrate = !read_only
+            && !uncached
+            && !degraded
+            && pinned_policy.is_none()
+            && self.classified(extent.clone()).should_migrate();
+        if should_migrate {
+            // Pending reads are persisted with the migrated extent
+            let access = self.access.take(extent_uuid);
+            if let Some(access) = access {
+                access.apply(&mut extent);
+            }
+            extent.reclassify_with_model(self.access_model.read().unwrap().as_ref());
+            let recommended_policy = extent.recommended_policy();
+            log::info!(
+                "Lazy migration triggered for extent {}: {:?} → {:?}",
+                extent_uuid,
+                extent.redundancy,
+                recommended_policy
+            );
+            
+            // Perform migration in background (non-blocking)
+            let disks_mut = self.disks.write().unwrap();
+            if let Err(e) = self.placement.rebundle_extent(&mut extent, &disks_mut, &fragments, recommended_policy) {
+                log::error!("Failed to perform lazy migration for extent {}: {}", extent_uuid, e);
+                if let Some(access) = access {
+                    self.access.restore(*extent_uuid, access);
+                }
+            } else {
+                metadata.save_extent(&extent)?;
+            }
+        }
+        
+        // Degraded extents are repaired by the background worker; the data is already decoded
+        if failed > 0 && !read_only {
+            let surviving = extent.redundancy.fragment_count().saturating_sub(failed);
+            log::warn!(
+                "Extent {} has lost {} of {} fragments, queueing rebuild",
+                extent_uuid,
+                failed,
+                extent.redundancy.fragment_count()
+            );
+            self.events.record(
+                EventKind::DegradedRead,
+                Some(*extent_uuid),
+                None,
+                format!("{} of {} fragments missing", failed, extent.redundancy.fragment_count()),
+            );
+            self.queue_rebuild(*extent_uuid, surviving.saturating_sub(extent.redundancy.min_fragments()));
+        }
+        
+        self.buffers.give_all(fragments);
+        Ok(extent_data)
+    }
+    
+    /// Start the worker decoding extents ahead of sequential readers
+    ///
+    /// Without it `prefetch` requests are dropped.
+    pub fn start_prefetcher(&mut self) {
+        if self.prefetcher.is_some() {
+            return;
+        }
+        self.prefetch_queue.open();
+        let worker = self.background_handle();
+        self.prefetcher = Some(thread::spawn(move || {
+            while let Some(request) = worker.prefetch_queue.next() {
+                if let Err(e) = worker.prefetch_extents(&request) {
+                    log::debug!("Read-ahead of inode {} stopped: {:#}", request.ino, e);
+                }
+                worker.prefetch_queue.complete(&request);
+            }
+        }));
+    }
+    
+    /// Decode `extents` extents of a file from the one holding `offset` into the cache
+    ///
+    /// Returns at once; a later request for the same file replaces this one.
+    pub fn prefetch(&self, ino: u64, offset: u64, extents: usize) {
+        self.prefetch_queue.request(ino, offset, extents);
+    }
+    
+    /// Drop read-ahead for a file whose reader stopped reading sequentially
+    pub fn cancel_prefetch(&self, ino: u64) {
+        self.prefetch_queue.cancel(ino);
+    }
+    
+    /// Fetch the extents of a read-ahead request until it is superseded or cancelled
+    ///
+    /// Degraded extents are left to the read path, which queues their rebuild.
+    /// Stops when the cache has no room short of evicting hotter data.
+    fn prefetch_extents(&self, request: &PrefetchRequest) -> Result<()> {
+        let extent_map = self.metadata.read().unwrap().load_extent_map(request.ino)?;
+        let first = extent_map.slot_index(request.offset);
+        let last = (first + request.extents).min(extent_map.extents.len());
+        for extent_uuid in extent_map.extents.get(first..last).unwrap_or_default() {
+            if !self.prefetch_queue.is_live(request) {
+                break;
+            }
+            if ExtentMap::is_hole(extent_uuid) || self.data_cache.contains(extent_uuid) {
+                continue;
+            }
+            let extent = self.metadata.read().unwrap().load_extent(extent_uuid)?;
+            let disks: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().clone();
+            let FragmentReads { fragments, failed } = self.read_fragments_for_decode(&extent, &disks);
+            if failed > 0 {
+                continue;
+            }
+            let payload = redundancy::decode(&fragments, extent.redundancy, extent.stored_size())?;
+            self.buffers.give_all(fragments);
+            let extent_data = extent.unpack(payload)?;
+            if !extent.verify_checksum(&extent_data) {
+                continue;
+            }
+            if !self.data_cache.admit(*extent_uuid, extent_data) {
+                break;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Read every fragment of an extent, one thread per fragment
+    ///
+    /// Fragments that cannot be read or fail their checksum are left as `None`.
+    fn read_fragments(&self, extent: &Extent, disks: &[Arc<Mutex<Disk>>]) -> Result<Vec<Option<Vec<u8>>>> {
+        let fragment_count = extent.redundancy.fragment_count();
+        Ok(self.gather_fragments(extent, disks, fragment_count, fragment_count).fragments)
+    }
+    
+    /// Read just enough fragments to decode an extent
+    ///
+    /// A replicated extent is read from the one replica expected to be fastest,
+    /// and from another only if that fails. For erasure coding the data shards
+    /// are read from the fastest disks first and a parity shard only when a
+    /// shard fails. See `fragment_read_order`.
+    fn read_fragments_for_decode(&self, extent: &Extent, disks: &[Arc<Mutex<Disk>>]) -> FragmentReads {
+        let needed = extent.redundancy.min_fragments();
+        self.gather_fragments(extent, disks, needed, needed)
+    }
+    
+    /// Issue fragment reads concurrently until `needed` have succeeded
+    ///
+    /// `initial` reads start at once; each failure starts the next candidate.
+    fn gather_fragments(
+        &self,
+        extent: &Extent,
+        disks: &[Arc<Mutex<Disk>>],
+        needed: usize,
+        initial: usize,
+    ) -> FragmentReads {
+        let fragment_count = extent.redundancy.fragment_count();
+        let mut fragments = vec![None; fragment_count];
+        
+        // Snapshot disk state without holding locks during reads
+        let disk_snapshots: Vec<Disk> = disks
+            .iter()
+            .map(|d| d.lock().unwrap().clone())
+            .collect();
+        
+        // One readable location per fragment index, skipping failed or missing disks
+        type Readable<'a> = (Arc<Mutex<Disk>>, &'a FragmentLocation, &'a Disk);
+        let mut locations: Vec<Option<Readable>> = vec![None; fragment_count];
+        for location in &extent.fragment_locations {
+            if location.fragment_index >= fragment_count || locations[location.fragment_index].is_some() {
+                continue;
+            }
+            let readable = disk_snapshots
+                .iter()
+                .position(|d| d.uuid == location.disk_uuid && d.health != crate::disk::DiskHealth::Failed);
+            if let Some(pos) = readable {
+                locations[location.fragment_index] = Some((disks[pos].clone(), location, &disk_snapshots[pos]));
+            }
+        }
+        let mut failed = locations.iter().filter(|l| l.is_none()).count();
+        
+        let holders: Vec<Option<&Disk>> = locations.iter().map(|l| l.as_ref().map(|(_, _, disk)| *disk)).collect();
+        let mut pending = fragment_read_order(extent, &holders).into_iter();
+        
+        let (tx, rx) = std::sync::mpsc::channel();
+        let fragment_size = extent.redundancy.fragment_size(extent.stored_size());
+        let launch = |fragment_index: usize| {
+            let disk = locations[fragment_index].as_ref().unwrap().0.clone();
+            let extent_uuid = extent.uuid;
+            let tx = tx.clone();
+            let mut buf = self.buffers.take(fragment_size);
+            // Reads for a background pass keep its class
+            let class = IoClass::current();
+            thread::spawn(move || {
+                let _class = class.enter();
+                let mut disk = disk.lock().unwrap();
+                let result = disk.read_fragment_into(&extent_uuid, fragment_index, &mut buf).map(|()| buf);
+                disk.track_io(&result);
+                drop(disk);
+                // The receiver is gone once enough fragments arrived; late results are dropped
+                tx.send((fragment_index, result)).ok();
+            });
+        };
+        
+        let mut in_flight = 0;
+        for fragment_index in pending.by_ref().take(initial.max(1)) {
+            launch(fragment_index);
+            in_flight += 1;
+        }
+        
+        let mut received = 0;
+        while received < needed && in_flight > 0 {
+            let (fragment_index, result) = match rx.recv() {
+                Ok(r) => r,
+                Err(_) => break,
+            };
+            in_flight -= 1;
+            let (disk, location, _) = locations[fragment_index].as_ref().unwrap();
+            match result {
+                // A corrupt fragment counts as lost: decode from the others and let the rebuild replace it
+                Ok(data) if !location.verify_checksum(&data) => {
+                    self.buffers.give(data);
+                    failed += 1;
+                    self.quarantine_corrupt_fragment(&extent.uuid, fragment_index, disk);
+                    if let Some(next) = pending.next() {
+                        launch(next);
+                        in_flight += 1;
+                    }
+                }
+                Ok(data) => {
+                    fragments[fragment_index] = Some(data);
+                    received += 1;
+                }
+                Err(e) => {
+                    failed += 1;
+                    self.metrics.record_disk_error();
+                    log::warn!(
+                        "Failed to read fragment {} of extent {}: {}",
+                        fragment_index,
+                        extent.uuid,
+                        e
+                    );
+                    if let Some(next) = pending.next() {
+                        launch(next);
+                        in_flight += 1;
+                    }
+                }
+            }
+        }
+        
+        FragmentReads { fragments, failed }
+    }
+    
+    /// Take a fragment that failed its checksum out of service
+    ///
+    /// The fragment is moved to the disk's quarantine directory so it reads as
+    /// missing, and the disk's corruption count is bumped, which may mark it Suspect.
+    fn quarantine_corrupt_fragment(&self, extent_uuid: &uuid::Uuid, fragment_index: usize, disk: &Arc<Mutex<Disk>>) {
+        self.metrics.record_fragment_checksum_failure();
+        let mut disk = disk.lock().unwrap();
+        log::warn!(
+            "Fragment {} of extent {} on disk {} failed its checksum, quarantining",
+            fragment_index,
+            extent_uuid,
+            disk.uuid
+        );
+        self.events.record(
+            EventKind::ChecksumFailure,
+            Some(*extent_uuid),
+            Some(disk.uuid),
+            format!("fragment {} quarantined", fragment_index),
+        );
+        
+        if let Err(e) = disk.quarantine_fragment(extent_uuid, fragment_index) {
+            log::error!("Failed to quarantine fragment {} of extent {}: {}", fragment_index, extent_uuid, e);
+        }
+        match disk.record_corruption() {
+            Ok(true) => log::warn!(
+                "Disk {} marked Suspect after {} corrupt fragments",
+                disk.uuid,
+                disk.corruption_count
+            ),
+            Ok(false) => {}
+            Err(e) => log::error!("Failed to record corruption on disk {}: {}", disk.uuid, e),
+        }
+    }
+    
+    /// Capture the namespace as snapshot `name`
+    ///
+    /// Buffered writes are flushed first. Every inode reachable from the root
+    /// and the extent maps of the regular files are captured, the snapshot is
+    /// recorded as a holder of every extent they list and then committed, all
+    /// under the metadata lock, so the capture is one point in time and nothing
+    /// it lists is freed before it commits.
+    pub fn create_snapshot(&self, name: &str) -> StorageResult<SnapshotInfo> {
+        SnapshotInfo::check_name(name)?;
+        self.check_writable()?;
+        self.write_buffer.flush_all(FlushCause::Explicit, |ino, run, cause| self.flush_run(ino, run, cause))?;
+        
+        let metadata = self.metadata.write().unwrap();
+        let pool_dir = metadata.pool_dir().to_path_buf();
+        if SnapshotInfo::list(&pool_dir)?.iter().any(|info| info.name == name) {
+            return Err(anyhow!("A snapshot named {:?} already exists", name).into());
+        }
+        let id = snapshots::next_snapshot_id(&pool_dir)?;
+        let tree = self.capture_tree(&metadata)?;
+        tree.save(&pool_dir, id)?;
+        snapshots::hold(&pool_dir, id, &tree.extents())?;
+        let files = tree.extent_maps.len() as u64;
+        let bytes = tree.extent_maps.keys().filter_map(|ino| tree.inode(*ino)).map(|inode| inode.size).sum();
+        let info = SnapshotInfo { id, name: name.to_string(), created_at: chrono::Utc::now().timestamp(), files, bytes };
+        info.commit(&pool_dir)?;
+        drop(metadata);
+        self.snapshots.invalidate();
+        
+        log::info!("Created snapshot {:?} (id {}) of {} files, {} bytes", name, id, files, bytes);
+        Ok(info)
+    }
+    
+    /// Every inode reachable from the root, with the extent maps of the regular files
+    ///
+    /// Inodes carry the timestamps not yet flushed to metadata.
+    fn capture_tree(&self, metadata: &MetadataManager) -> Result<SnapshotTree> {
+        let mut inodes = BTreeMap::new();
+        let mut extent_maps = BTreeMap::new();
+        let mut pending = vec![metadata.load_inode(1)?];
+        while let Some(inode) = pending.pop() {
+            if inodes.contains_key(&inode.ino) {
+                continue;
+            }
+            match inode.file_type {
+                FileType::Directory => pending.extend(metadata.list_directory(inode.ino)?),
+                FileType::RegularFile => {
+                    extent_maps.insert(inode.ino, metadata.load_extent_map(inode.ino)?);
+                }
+            }
+            inodes.insert(inode.ino, self.timestamps.merged(inode));
+        }
+        Ok(SnapshotTree::new(inodes, extent_maps))
+    }
+    
+    /// Delete snapshot `name` and free the extents only it still held
+    ///
+    /// Once the snapshot is dropped from the index it holds nothing; a crash
+    /// before the extents it alone kept are freed leaves them allocated.
+    pub fn delete_snapshot(&self, name: &str) -> StorageResult<SnapshotInfo> {
+        self.check_writable()?;
+        let mut metadata = self.metadata.write().unwrap();
+        let pool_dir = metadata.pool_dir().to_path_buf();
+        let info = SnapshotInfo::find(&pool_dir, name)?;
+        let tree = SnapshotTree::load(&pool_dir, info.id)?;
+        
+        // Extents a live file still lists stay; one whose map cannot be read keeps them all
+        let mut unused = HashSet::new();
+        for (ino, map) in &tree.extent_maps {
+            if !metadata.inode_exists(*ino) {
+                unused.extend(map.data_extents().copied());
+                continue;
+            }
+            let Ok(live) = metadata.load_extent_map(*ino) else { continue };
+            unused.extend(map.data_extents().filter(|uuid| !live.extents.contains(uuid)).copied());
+        }
+        
+        info.uncommit(&pool_dir)?;
+        self.snapshots.invalidate();
+        snapshots::unhold(&pool_dir, info.id, &tree.extents())?;
+        SnapshotTree::remove(&pool_dir, info.id)?;
+        
+        // Extents another snapshot holds are kept by `release_extent`
+        if !unused.is_empty() {
+            let tx = metadata.journal_transaction(unused.into_iter().map(MetadataOp::ReleaseExtent).collect())?;
+            metadata.apply_transaction(tx)?;
+        }
+        drop(metadata);
+        self.reclaim_after_commit();
+        
+        log::info!("Deleted snapshot {:?} (id {})", name, info.id);
+        Ok(info)
+    }
+    
+    fn loaded_snapshot(&self, id: u16) -> StorageResult<Arc<LoadedSnapshot>> {
+        self.snapshots.get(id)?.ok_or_else(|| StorageError::NotFound(format!("No snapshot with id {}", id)))
+    }
+    
+    /// Inode of `/.snapshots` or of anything under it
+    fn snapshot_view_inode(&self, ino: u64) -> StorageResult<Inode> {
+        if ino == SNAPSHOTS_DIR_INO {
+            return Ok(snapshots::snapshots_dir_inode(&self.get_inode(1)?, self.snapshots.infos()?.len()));
+        }
+        let (id, captured) = snapshots::split_snapshot_ino(ino);
+        self.loaded_snapshot(id)?
+            .view_inode(captured)
+            .ok_or_else(|| StorageError::NotFound(format!("Inode {} not found in snapshot {}", captured, id)))
+    }
+    
+    fn snapshot_view_children(&self, ino: u64) -> StorageResult<Vec<Inode>> {
+        if ino == SNAPSHOTS_DIR_INO {
+            return self
+                .snapshots
+                .infos()?
+                .iter()
+                .map(|info| self.snapshot_view_inode(snapshots::snapshot_ino(info.id, 1)))
+                .collect();
+        }
+        let (id, captured) = snapshots::split_snapshot_ino(ino);
+        let snapshot = self.loaded_snapshot(id)?;
+        Ok(snapshot.tree.children(captured).filter_map(|child| snapshot.view_inode(child.ino)).collect())
+    }
+    
+    fn snapshot_view_child(&self, ino: u64, name: &[u8]) -> StorageResult<Option<Inode>> {
+        let metadata = self.metadata.read().unwrap();
+        if ino == SNAPSHOTS_DIR_INO {
+            let info = self.snapshots.infos()?.into_iter().find(|info| metadata.same_name(info.name.as_bytes(), name));
+            drop(metadata);
+            return info.map(|info| self.snapshot_view_inode(snapshots::snapshot_ino(info.id, 1))).transpose();
+        }
+        let (id, captured) = snapshots::split_snapshot_ino(ino);
+        let snapshot = self.loaded_snapshot(id)?;
+        let child = snapshot.tree.children(captured).find(|child| metadata.same_name(child.name.as_bytes(), name));
+        Ok(child.and_then(|child| snapshot.view_inode(child.ino)))
+    }
+    
+    /// Read a file under `/.snapshots` through the extent map its snapshot captured
+    fn read_snapshot_range(&self, ino: u64, offset: u64, size: u64) -> StorageResult<Vec<u8>> {
+        let (id, captured) = snapshots::split_snapshot_ino(ino);
+        let snapshot = self.loaded_snapshot(id)?;
+        let Some(inode) = snapshot.tree.inode(captured) else {
+            return Err(StorageError::NotFound(format!("Inode {} not found in snapshot {}", captured, id)));
+        };
+        let end = offset.saturating_add(size).min(inode.size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let Some(extent_map) = snapshot.tree.extent_map(captured) else {
+            return Ok(vec![0; (end - offset) as usize]);
+        };
+        let metadata = self.metadata.read().unwrap();
+        let result = self.read_mapped(&metadata, extent_map, offset, end, None, false)?;
+        self.metrics.record_disk_read(result.len() as u64);
+        Ok(result)
+    }
+    
+    /// Refuse changes under `/.snapshots`
+    fn check_live(ino: u64) -> StorageResult<()> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Err(StorageError::ReadOnly("snapshots are read-only".to_string()));
+        }
+        Ok(())
+    }
+    
+    /// Make `dst_ino` a copy of `src_ino` that shares its extents
+    ///
+    /// The destination's extent map becomes the source's, and both files are
+    /// recorded as holders of every extent, so no data is written. Writes to
+    /// either file replace the extents they touch copy-on-write, and an extent
+    /// is only reclaimed once neither file references it. The destination's
+    /// previous contents are released in the same transaction.
+    pub fn clone_file(&self, src_ino: u64, dst_ino: u64) -> StorageResult<()> {
+        self.check_writable()?;
+        if src_ino == dst_ino {
+            return Err(anyhow!("Cannot clone inode {} onto itself", src_ino).into());
+        }
+        // Both stripes, in order, so two clones in opposite directions cannot deadlock
+        let (low, high) = (src_ino.min(dst_ino), src_ino.max(dst_ino));
+        let _low = self.lock_inode_writes(low);
+        let _high = (low % INODE_WRITE_LOCK_STRIPES as u64 != high % INODE_WRITE_LOCK_STRIPES as u64)
+            .then(|| self.lock_inode_writes(high));
+        self.flush_buffered(src_ino, FlushCause::Explicit)?;
+        self.write_buffer.discard(dst_ino);
+
+        let mut metadata = self.metadata.write().unwrap();
+        let source = metadata.load_inode(src_ino)?;
+        let mut inode = metadata.load_inode(dst_ino)?;
+        if source.file_type != FileType::RegularFile || inode.file_type != FileType::RegularFile {
+            return Err(StorageError::Unsupported("Only regular files can be cloned".to_string()));
+        }
+        let size_change = source.size as i64 - inode.size as i64;
+        metadata.charge_quotas(inode.parent_ino, size_change, 0)?;
+        let source_map = metadata.load_extent_map(src_ino)?;
+        let superseded = metadata.load_extent_map(dst_ino)?;
+
+        let mut ops: Vec<MetadataOp> = source_map
+            .data_extents()
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .map(|uuid| MetadataOp::ShareExtent(*uuid, vec![src_ino, dst_ino]))
+            .collect();
+        ops.push(MetadataOp::SaveExtentMap(ExtentMap { ino: dst_ino, ..source_map.clone() }));
+        inode.size = source.size;
+        inode.touch_contents(now_timespec());
+        ops.extend(Self::quota_ops(&metadata, inode.parent_ino, size_change, 0)?);
+        ops.push(MetadataOp::SaveInode(inode));
+        ops.extend(superseded.data_extents().map(|uuid| MetadataOp::ReleaseExtent(*uuid)));
+        metadata.apply_batch(ops)?;
+        drop(metadata);
+        self.reclaim_after_commit();
+
+        log::info!(
+            "Cloned inode {} into inode {}, sharing {} extents",
+            src_ino,
+            dst_ino,
+            source_map.data_extents().count()
+        );
+        Ok(())
+    }
+    
+    /// Delete a file
+    ///
+    /// The inode, its extent map and quota changes are journaled together
+    /// with release markers for its extents, so after a crash the delete is
+    /// either replayed in full or never happened. Fragments are only deleted
+    /// once nothing references them; see `reclaim_released_extents`.
+    pub fn delete_file(&self, ino: u64) -> StorageResult<()> {
+        log::info!("Deleting inode {}", ino);
+        self.check_writable()?;
+        self.write_buffer.discard(ino);
+        
+        let mut metadata = self.metadata.write().unwrap();
+        let inode = metadata.load_inode(ino).ok();
+        let extent_map = metadata.load_extent_map(ino)?;
+        
+        // The map goes first, so extents shared with other files see it gone
+        let mut ops = vec![MetadataOp::DeleteExtentMap(ino)];
+        ops.extend(extent_map.data_extents().map(|uuid| MetadataOp::ReleaseExtent(*uuid)));
+        ops.push(MetadataOp::DeleteInode(ino));
+        
+        // Release its usage from the quotas above it; a directory's own quota goes with it
+        let parent_ino = inode.as_ref().map(|inode| inode.parent_ino).filter(|parent| *parent != ORPHAN_PARENT_INO);
+        if let Some(inode) = inode {
+            let bytes = if inode.file_type == FileType::Directory { 0 } else { inode.size as i64 };
+            ops.extend(Self::quota_ops(&metadata, inode.parent_ino, -bytes, -1)?);
+            ops.push(MetadataOp::DeleteQuota(ino));
+            if inode.file_type == FileType::Directory {
+                ops.extend(Self::subdir_link_op(&metadata, inode.parent_ino, -1));
+            }
+        }
+        
+        let tx = metadata.journal_transaction(ops)?;
+        if let Err(err) = metadata.apply_transaction(tx) {
+            log::error!("Applying journaled delete of inode {} failed, will replay on recovery: {}", ino, err);
+            return Err(err);
+        }
+        drop(metadata);
+        if let Some(parent_ino) = parent_ino {
+            self.timestamps.record_mtime(parent_ino, now_timespec());
+        }
+        self.reclaim_after_commit();
+        
+        Ok(())
+    }
+    
+    /// Unlink a file that is still open, keeping its data until `delete_file`
+    ///
+    /// The inode moves under `ORPHAN_PARENT_INO` and its usage leaves the
+    /// quotas above it, in one transaction. Files a crash left there are
+    /// deleted by `delete_orphans`.
+    pub fn orphan_file(&self, ino: u64) -> StorageResult<()> {
+        self.check_writable()?;
+        // Buffered growth is charged to the quotas on flush; settle it before uncharging
+        self.flush_buffered(ino, FlushCause::Explicit)?;
+        
+        let mut metadata = self.metadata.write().unwrap();
+        let mut inode = metadata.load_inode(ino)?;
+        let mut ops = Self::quota_ops(&metadata, inode.parent_ino, -(inode.size as i64), -1)?;
+        let parent_ino = inode.parent_ino;
+        let now = now_timespec();
+        inode.parent_ino = ORPHAN_PARENT_INO;
+        inode.name = ino.to_string().into();
+        inode.set_ctime(now);
+        ops.push(MetadataOp::SaveInode(inode));
+        metadata.apply_batch(ops)?;
+        self.timestamps.record_mtime(parent_ino, now);
+        Ok(())
+    }
+    
+    /// Delete files unlinked while open whose last close never came
+    ///
+    /// Run at mount, before anything can have opened them again.
+    pub fn delete_orphans(&self) -> StorageResult<usize> {
+        let orphans = self.metadata.read().unwrap().list_directory(ORPHAN_PARENT_INO)?;
+        for orphan in &orphans {
+            self.delete_file(orphan.ino)?;
+        }
+        Ok(orphans.len())
+    }
+    
+    /// Bytes of idle read buffers kept for reuse; 0 allocates for every read
+    pub fn set_read_buffer_pool_limit(&self, bytes: u64) {
+        self.buffers.set_limit(bytes as usize);
+    }
+    
+    /// Give back a buffer `read_range` returned, once its bytes are no longer needed
+    pub fn recycle_read_buffer(&self, buf: Vec<u8>) {
+        self.buffers.give(buf);
+    }
+    
+    /// Resize the inode cache or change how long its entries live; 0 for either disables it
+    pub fn set_inode_cache_limits(&self, limits: InodeCacheLimits) {
+        self.inode_cache.set_limits(limits);
+    }
+    
+    /// Get inode
+    ///
+    /// The size includes buffered writes past the stored end of file, and the
+    /// timestamps include updates not yet flushed.
+    pub fn get_inode(&self, ino: u64) -> StorageResult<Inode> {
+        let mut inode = self.timestamps.merged(self.metadata.read().unwrap().load_inode(ino)?);
+        if let Some(end) = self.write_buffer.buffered_end(ino) {
+            inode.size = inode.size.max(end);
+        }
+        Ok(inode)
+    }
+    
+    /// List directory
+    pub fn list_directory(&self, parent_ino: u64) -> StorageResult<Vec<Inode>> {
+        let metadata = self.metadata.read().unwrap();
+        let children = metadata.list_directory(parent_ino)?;
+        Ok(children.into_iter().map(|inode| self.timestamps.merged(inode)).collect())
+    }
+    
+    /// Find child by name
+    pub fn find_child(&self, parent_ino: u64, name: impl AsRef<[u8]>) -> StorageResult<Option<Inode>> {
+        let metadata = self.metadata.read().unwrap();
+        let child = metadata.find_child(parent_ino, name)?;
+        Ok(child.map(|inode| self.timestamps.merged(inode)))
+    }
+    
+    /// Create a new file
+    pub fn create_file(&self, parent_ino: u64, name: impl Into<FileName>) -> StorageResult<Inode> {
+        Ok(self.create_inode(parent_ino, Inode::new_file, name.into(), None)?)
+    }
+    
+    /// Create a new directory
+    pub fn create_dir(&self, parent_ino: u64, name: impl Into<FileName>) -> StorageResult<Inode> {
+        Ok(self.create_inode(parent_ino, Inode::new_dir, name.into(), None)?)
+    }
+    
+    /// Create a file owned by `ctx` with permission bits `mode`, if it may write to the parent
+    pub fn create_file_as(
+        &self,
+        ctx: &RequestContext,
+        parent_ino: u64,
+        name: impl Into<FileName>,
+        mode: u32,
+    ) -> StorageResult<Inode> {
+        Ok(self.create_inode(parent_ino, Inode::new_file, name.into(), Some((ctx, mode)))?)
+    }
+    
+    /// Create a directory owned by `ctx` with permission bits `mode`, if it may write to the parent
+    pub fn create_dir_as(
+        &self,
+        ctx: &RequestContext,
+        parent_ino: u64,
+        name: impl Into<FileName>,
+        mode: u32,
+    ) -> StorageResult<Inode> {
+        Ok(self.create_inode(parent_ino, Inode::new_dir, name.into(), Some((ctx, mode)))?)
+    }
+    
+    /// Save a new inode built by `new`, owned by `creator` if given
+    ///
+    /// The owner is set before the first save, so the inode is never visible
+    /// with the mount's owner instead of the creator's.
+    fn create_inode(
+        &self,
+        parent_ino: u64,
+        new: fn(u64, u64, FileName) -> Inode,
+        name: FileName,
+        creator: Option<(&RequestContext, u32)>,
+    ) -> Result<Inode> {
+        self.check_writable()?;
+        let mut metadata = self.metadata.write().unwrap();
+        let parent = match creator {
+            Some((ctx, _)) => {
+                let parent = metadata.load_inode(parent_ino)?;
+                permissions::check_access(&parent, ctx, permissions::WRITE | permissions::EXECUTE)?;
+                Some(parent)
+            }
+            None => None,
+        };
+        let mut quota_ops = Self::quota_ops(&metadata, parent_ino, 0, 1)?;
+        let ino = metadata.allocate_ino()?;
+        let mut inode = new(ino, parent_ino, name);
+        if let (Some(parent), Some((ctx, mode))) = (&parent, creator) {
+            permissions::set_creator(&mut inode, parent, ctx, mode);
+        }
+        inode.generation = metadata.generation();
+        // A subdirectory's `..` is a link to the parent
+        if inode.file_type == FileType::Directory {
+            quota_ops.extend(Self::subdir_link_op(&metadata, parent_ino, 1));
+        }
+        Self::save_inode_with_quotas(&mut metadata, &inode, quota_ops)?;
+        drop(metadata);
+        self.timestamps.record_mtime(parent_ino, (inode.ctime, inode.ctime_nsec));
+        Ok(inode)
+    }
+    
+    /// Update inode
+    ///
+    /// Buffered data is flushed first so the saved size cannot run ahead of
+    /// the extents. `inode` replaces any pending timestamp updates, so times
+    /// set explicitly (`touch -d`) are not overridden by a later flush.
+    ///
+    /// A changed name or parent is a rename: the old and new parents' mtime
+    /// and ctime move, and a directory moves its `..` link between them. The
+    /// subdirectory count is the engine's own and is kept from the record.
+    pub fn update_inode(&self, inode: &Inode) -> StorageResult<()> {
+        self.check_writable()?;
+        self.flush_buffered(inode.ino, FlushCause::Explicit)?;
+        let mut metadata = self.metadata.write().unwrap();
+        let mut inode = inode.clone();
+        let stored = metadata.load_inode(inode.ino).ok();
+        let mut ops = Vec::new();
+        if let Some(stored) = &stored {
+            inode.subdirs = stored.subdirs;
+            if inode.file_type == FileType::Directory && stored.parent_ino != inode.parent_ino {
+                ops.extend(Self::subdir_link_op(&metadata, stored.parent_ino, -1));
+                ops.extend(Self::subdir_link_op(&metadata, inode.parent_ino, 1));
+            }
+        }
+        ops.push(MetadataOp::SaveInode(inode.clone()));
+        metadata.apply_batch(ops)?;
+        self.timestamps.take(inode.ino);
+        drop(metadata);
+        
+        if let Some(stored) = stored.filter(|stored| (stored.parent_ino, &stored.name) != (inode.parent_ino, &inode.name)) {
+            let now = now_timespec();
+            self.timestamps.record_mtime(stored.parent_ino, now);
+            self.timestamps.record_mtime(inode.parent_ino, now);
+        }
+        Ok(())
+    }
+    
+    /// Save of directory `ino` with `delta` more subdirectories; none if it is gone
+    fn subdir_link_op(metadata: &MetadataManager, ino: u64, delta: i64) -> Option<MetadataOp> {
+        let mut dir = metadata.load_inode(ino).ok().filter(|dir| dir.file_type == FileType::Directory)?;
+        dir.subdirs = dir.subdirs.saturating_add_signed(delta);
+        Some(MetadataOp::SaveInode(dir))
+    }
+    
+    /// Flush everything backing an inode to stable storage
+    ///
+    /// Writes out any buffered data first, then syncs the fragment files of the
+    /// inode's extents on every non-failed disk holding them, then the inode,
+    /// extent map and extent metadata files along with their parent directories.
+    pub fn sync_inode(&self, ino: u64) -> StorageResult<()> {
+        self.flush_buffered(ino, FlushCause::Fsync)?;
+        let metadata = self.metadata.read().unwrap();
+        let mut inode = metadata.load_inode(ino)?;
+        if let Some(times) = self.timestamps.take(ino) {
+            times.apply(&mut inode);
+            if let Err(e) = metadata.save_inode(&inode) {
+                self.timestamps.restore(ino, times);
+                return Err(e);
+            }
+        }
+
+        // Directories have no extent map; holes have nothing to flush
+        let extent_uuids: Vec<uuid::Uuid> = metadata
+            .load_extent_map(ino)
+            .map(|map| map.data_extents().copied().collect())
+            .unwrap_or_default();
+
+        {
+            let disks = self.disks.read().unwrap();
+            for extent_uuid in &extent_uuids {
+                let extent = metadata.load_extent(extent_uuid)?;
+                for location in &extent.fragment_locations {
+                    let Some(disk_arc) = disks.iter().find(|d| d.lock().unwrap().uuid == location.disk_uuid) else {
+                        continue;
+                    };
+                    let disk = disk_arc.lock().unwrap();
+                    if disk.health == crate::disk::DiskHealth::Failed {
+                        continue;
+                    }
+                    disk.sync_fragment(&extent.uuid, location.fragment_index)?;
+                }
+            }
+        }
+
+        metadata.sync_inode_metadata(ino, &extent_uuids)
+    }
+
+    /// Disk and extent health counts, as reported by `dynamicfs health`
+    pub fn pool_health(&self) -> StorageResult<crate::monitoring::PoolHealth> {
+        let extents = self.metadata.read().unwrap().extent_totals();
+        let mut health = crate::monitoring::PoolHealth::default();
+        for disk in self.disks.read().unwrap().iter() {
+            health.add_disk(&disk.lock().unwrap());
+        }
+        health.add_extents(&extents);
+        Ok(health)
+    }
+
+    /// Flush every buffered write and make it durable, for a clean shutdown
+    pub fn sync_all(&self) -> StorageResult<()> {
+        let mut flushed = Vec::new();
+        self.write_buffer.flush_all(FlushCause::Fsync, |ino, run, cause| {
+            flushed.push(ino);
+            self.flush_run(ino, run, cause)
+        })?;
+        flushed.sort_unstable();
+        flushed.dedup();
+        for ino in flushed {
+            self.sync_inode(ino)?;
+        }
+        self.flush_inode_times()?;
+        self.metadata.read().unwrap().sync_commits()?;
+        // Fold the allocation journals into the bitmaps so the next mount has nothing to replay
+        for disk in self.disks.read().unwrap().iter() {
+            if let Some(oda) = disk.lock().unwrap().on_device_allocator.as_mut() {
+                oda.checkpoint()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deallocate a byte range of a file so it reads back as zeros
+    ///
+    /// Extents entirely inside the range are replaced by holes in the extent map
+    /// and their fragments deleted. Extents straddling a boundary are re-encoded
+    /// with the covered bytes zeroed. The metadata changes commit as one
+    /// transaction. The file size is unchanged.
+    pub fn punch_hole(&self, ino: u64, offset: u64, length: u64) -> StorageResult<()> {
+        log::debug!("Punching hole in inode {}: offset={}, length={}", ino, offset, length);
+        self.check_writable()?;
+        self.flush_buffered(ino, FlushCause::Explicit)?;
+
+        let mut metadata = self.metadata.write().unwrap();
+        let mut inode = metadata.load_inode(ino)?;
+        let end = offset.saturating_add(length).min(inode.size);
+        if offset >= end {
+            return Ok(());
+        }
+
+        let mut extent_map = metadata.load_extent_map(ino)?;
+        let disk_refs: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().iter().cloned().collect();
+        let verify = self.verify_writes_for(&metadata, ino);
+        let mut in_flight = self.in_flight.begin();
+
+        let first = extent_map.slot_index(offset);
+        let last = extent_map.slot_index(end - 1);
+        let mut released: Vec<Extent> = Vec::new();
+        let mut replacements: Vec<Extent> = Vec::new();
+
+        let journaled = (|| -> StorageResult<Option<crate::metadata_tx::MetadataTransaction>> {
+            for index in first..extent_map.extents.len().min(last + 1) {
+                let extent_uuid = extent_map.extents[index];
+                if ExtentMap::is_hole(&extent_uuid) {
+                    continue;
+                }
+
+                let extent = metadata.load_extent(&extent_uuid)?;
+                let slot_start = extent_map.slot_offset(index);
+                let hole_start = (offset.max(slot_start) - slot_start) as usize;
+                let hole_end = ((end - slot_start) as usize).min(extent.size);
+                if hole_start >= hole_end {
+                    // Range only covers zero padding past the extent's data
+                    continue;
+                }
+
+                if hole_start > 0 || hole_end < extent.size {
+                    // Partially covered: re-encode the surviving bytes
+                    let fragments = self.read_fragments_for_decode(&extent, &disk_refs).fragments;
+                    let mut data = extent.unpack(redundancy::decode(&fragments, extent.redundancy, extent.stored_size())?)?;
+                    if !extent.verify_checksum(&data) {
+                        return Err(checksum_failed(extent_uuid));
+                    }
+                    data[hole_start..hole_end].fill(0);
+
+                    if data.iter().any(|&b| b != 0) {
+                        let wanted = extent.intended_policy.unwrap_or(extent.redundancy);
+                        let (policy, intended) = self.policy_to_write(&disk_refs, wanted);
+                        let mut replacement = Extent::new(&data, policy);
+                        replacement.extent_size = extent_map.extent_size;
+                        let fragments = self.encode_extent(&mut replacement, &data)?;
+                        self.place_extent(&mut replacement, &disk_refs, &fragments, verify, &mut in_flight)?;
+                        self.record_degraded_write(&mut replacement, intended);
+                        extent_map.extents[index] = replacement.uuid;
+                        replacements.push(replacement);
+                        released.push(extent);
+                        continue;
+                    }
+                }
+
+                extent_map.extents[index] = ExtentMap::HOLE;
+                released.push(extent);
+            }
+
+            if released.is_empty() {
+                return Ok(None);
+            }
+
+            // One transaction swaps in the new map and releases the replaced extents
+            let mut ops: Vec<MetadataOp> = replacements
+                .iter()
+                .map(|extent| MetadataOp::SaveExtent(Box::new(extent.clone())))
+                .collect();
+            ops.push(MetadataOp::SaveExtentMap(extent_map.clone()));
+            inode.touch_contents(now_timespec());
+            ops.push(MetadataOp::SaveInode(inode.clone()));
+            ops.extend(released.iter().map(|extent| MetadataOp::ReleaseExtent(extent.uuid)));
+            metadata.journal_transaction(ops).map(Some)
+        })();
+
+        let tx = match journaled {
+            Ok(Some(tx)) => tx,
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                for replacement in &replacements {
+                    Self::delete_fragments(&disk_refs, replacement);
+                }
+                return Err(err);
+            }
+        };
+        metadata.apply_transaction(tx)?;
+        drop(metadata);
+        self.reclaim_after_commit();
+
+        log::info!(
+            "Punched hole in inode {} ({} extents released, {} re-encoded)",
+            ino,
+            released.len(),
+            replacements.len()
+        );
+        Ok(())
+    }
+
+    /// Zero a byte range of a file
+    ///
+    /// Implemented as a hole punch: the range is deallocated and reads as zeros.
+    /// Unless `keep_size` is set, a range reaching past the end of the file grows
+    /// it, with the new tail left sparse.
+    pub fn zero_range(&self, ino: u64, offset: u64, length: u64, keep_size: bool) -> StorageResult<()> {
+        let _writer = self.lock_inode_writes(ino);
+        self.punch_hole(ino, offset, length)?;
+
+        let end = offset.saturating_add(length);
+        if keep_size {
+            return Ok(());
+        }
+
+        let mut metadata = self.metadata.write().unwrap();
+        let mut inode = metadata.load_inode(ino)?;
+        if end > inode.size {
+            let quota_ops = Self::quota_ops(&metadata, inode.parent_ino, (end - inode.size) as i64, 0)?;
+            inode.size = end;
+            inode.touch_contents(now_timespec());
+            Self::save_inode_with_quotas(&mut metadata, &inode, quota_ops)?;
+        }
+        Ok(())
+    }
 
-            let mut inode = metadata.load_inode(ino)?;
-            inode.size = data.len() as u64;
-            inode.mtime = chrono::Utc::now().timestamp();
-            #[cfg(test)]
-            eprintln!("[WRITE_FILE DEBUG] save_inode ino={}", ino);
+    /// Every extent of a file and the disks holding its fragments
+    ///
+    /// Backs the `user.scfs.layout` xattr and `dynamicfs file-layout`.
+    pub fn describe_layout(&self, ino: u64) -> StorageResult<FileLayout> {
+        let metadata = self.metadata.read().unwrap();
+        let inode = metadata.load_inode(ino)?;
+        let extent_map = metadata.load_extent_map(ino)?;
+        let health: std::collections::HashMap<uuid::Uuid, crate::disk::DiskHealth> = self
+            .disks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|disk| {
+                let disk = disk.lock().unwrap();
+                (disk.uuid, disk.health)
+            })
+            .collect();
 
-            metadata.save_inode(&inode)?;
-            Ok(())
-        })() {
-            let disks = self.disks.write().unwrap();
-            for extent in &written_extents {
-                for location in &extent.fragment_locations {
-                    if let Some(disk_arc) = disks.iter().find(|d| d.lock().unwrap().uuid == location.disk_uuid) {
-                        disk_arc.lock().unwrap().delete_fragment(&extent.uuid, location.fragment_index).ok();
-                    }
-                }
+        let mut extents = Vec::new();
+        for (slot, uuid) in extent_map.extents.iter().enumerate() {
+            if ExtentMap::is_hole(uuid) {
+                continue;
             }
-            return Err(err);
+            let extent = metadata.load_extent(uuid)?;
+            let mut fragments: Vec<FragmentLayout> = extent
+                .fragment_locations
+                .iter()
+                .map(|location| FragmentLayout {
+                    index: location.fragment_index,
+                    disk: location.disk_uuid,
+                    disk_health: health.get(&location.disk_uuid).copied(),
+                })
+                .collect();
+            fragments.sort_by_key(|fragment| fragment.index);
+            extents.push(ExtentLayout {
+                slot,
+                uuid: extent.uuid,
+                size: extent.size,
+                redundancy: extent.redundancy.to_string(),
+                compression: extent.compression.to_string(),
+                stored_size: extent.stored_size(),
+                fragments,
+            });
         }
-        
-        // Record metrics for write operation
-        self.metrics.record_disk_write(data.len() as u64);
-        
-        log::info!("Wrote {} bytes to inode {} across {} extents", 
-                   data.len(), ino, written_extents.len());
-        
-        Ok(())
+        Ok(FileLayout { ino, size: inode.size, extent_size: extent_map.extent_size, extents })
+    }
+
+    /// Verify every extent of one file: a scrub scoped to its extent map
+    ///
+    /// Writes still in the write buffer are flushed first. Corrupt fragments
+    /// are charged to their disks as in a pool scrub; nothing is repaired.
+    pub fn verify_file(&self, ino: u64) -> StorageResult<FileCheckReport> {
+        if !self.is_read_only() {
+            self.flush_file(ino)?;
+        }
+        Ok(self.check_file(ino, false)?)
     }
     
-    /// Read data from a file
-    pub fn read_file(&self, ino: u64) -> Result<Vec<u8>> {
-        log::debug!("Reading inode {}", ino);
-        
+    /// Verify one file and rebuild the fragments of its degraded extents
+    ///
+    /// Extents too damaged to decode are reported and left alone.
+    pub fn repair_file(&self, ino: u64) -> StorageResult<FileCheckReport> {
+        self.check_writable()?;
+        self.flush_file(ino)?;
+        Ok(self.check_file(ino, true)?)
+    }
+    
+    fn check_file(&self, ino: u64, repair: bool) -> Result<FileCheckReport> {
         let metadata = self.metadata.read().unwrap();
-        
-        // Load extent map
+        let inode = metadata.load_inode(ino)?;
         let extent_map = metadata.load_extent_map(ino)?;
+        let disks = self.get_disks();
+        let scrubber = Scrubber::new(metadata.pool_dir().to_path_buf());
         
-        if extent_map.extents.is_empty() {
-            return Ok(Vec::new());
+        // An extent shared by several slots is checked, and repaired, once
+        let mut checked: HashMap<uuid::Uuid, (ExtentHealth, Vec<ScrubIssue>)> = HashMap::new();
+        let mut extents = Vec::new();
+        for (slot, uuid) in extent_map.extents.iter().enumerate() {
+            if ExtentMap::is_hole(uuid) {
+                continue;
+            }
+            if !checked.contains_key(uuid) {
+                let mut extent = metadata.load_extent(uuid)?;
+                let mut result = scrubber.verify_extent(&extent, &metadata, &disks)?;
+                if repair && result.status == ScrubStatus::Degraded {
+                    let fragments = Scrubber::read_fragments(&extent, &disks);
+                    result = scrubber.repair_extent(&mut extent, &metadata, &disks, &self.placement, &fragments)?;
+                }
+                for (_, disk_uuid) in &result.corrupt_fragments {
+                    self.metrics.record_fragment_checksum_failure();
+                    if let Some(disk) = self.disks.read().unwrap().iter().find(|d| d.lock().unwrap().uuid == *disk_uuid) {
+                        let mut disk = disk.lock().unwrap();
+                        if disk.record_corruption()? {
+                            log::warn!("Disk {} marked Suspect after {} corrupt fragments", disk.uuid, disk.corruption_count);
+                        }
+                    }
+                }
+                if result.status == ScrubStatus::Repaired {
+                    self.events.record(EventKind::RebuildFinished, Some(*uuid), None, format!("repaired by repair_file of inode {}", ino));
+                }
+                checked.insert(*uuid, (ExtentHealth::from_scrub(&result, &extent), result.issues));
+            }
+            let (health, issues) = checked[uuid].clone();
+            extents.push(ExtentCheck {
+                slot,
+                offset: slot as u64 * extent_map.extent_size as u64,
+                uuid: *uuid,
+                health,
+                issues,
+            });
         }
         
-        let mut result = Vec::new();
+        // A healthy check stands in for the recent-write verification
+        let healthy: Vec<uuid::Uuid> =
+            checked.iter().filter(|(_, (health, _))| *health == ExtentHealth::Healthy).map(|(uuid, _)| *uuid).collect();
+        self.recent_writes.complete(&healthy);
         
-        // Read each extent
-        for extent_uuid in &extent_map.extents {
-            let mut extent = metadata.load_extent(extent_uuid)?;
-            
-            // Record read access
-            extent.record_read();
-            
-            // Read fragments with current policy
-            let disks = self.disks.read().unwrap();
-            let fragments = self.read_fragments(&extent, &disks)?;
-            drop(disks);
-            
-            // Decode data with current policy
-            let extent_data = redundancy::decode(&fragments, extent.redundancy)?;
-            
-            // Verify checksum
-            if !extent.verify_checksum(&extent_data[..extent.size]) {
-                return Err(anyhow!("Checksum verification failed for extent {}", extent_uuid));
-            }
-            
-            // Check if lazy migration is needed (after successful read)
-            let should_migrate = extent.should_migrate();
-            if should_migrate {
-                let recommended_policy = extent.recommended_policy();
-                log::info!(
-                    "Lazy migration triggered for extent {}: {:?} → {:?}",
-                    extent_uuid,
-                    extent.redundancy,
-                    recommended_policy
-                );
-                
-                // Perform migration in background (non-blocking)
-                let disks_mut = self.disks.write().unwrap();
-                if let Err(e) = self.placement.rebundle_extent(&mut extent, &*disks_mut, &fragments, recommended_policy) {
-                    log::error!("Failed to perform lazy migration for extent {}: {}", extent_uuid, e);
-                } else {
-                    metadata.save_extent(&extent)?;
+        // Repairs wrote fragments behind the disks' usage counters
+        if extents.iter().any(|check| matches!(check.health, ExtentHealth::Repaired { .. })) {
+            for disk in self.disks.read().unwrap().iter() {
+                let mut disk = disk.lock().unwrap();
+                if let Err(e) = disk.recount_usage() {
+                    log::error!("Failed to recount usage of disk {} after repairs: {}", disk.uuid, e);
                 }
             }
-            
-            // Check if we need to rebuild
-            let available_count = fragments.iter().filter(|f| f.is_some()).count();
-            if available_count < extent.redundancy.fragment_count()
-                && available_count >= extent.redundancy.min_fragments()
-            {
-                log::warn!(
-                    "Extent {} has only {} of {} fragments, rebuilding",
-                    extent_uuid,
-                    available_count,
-                    extent.redundancy.fragment_count()
-                );
-                
-                self.metrics.record_rebuild_start();
-                let disks_mut = self.disks.write().unwrap();
-                match self.placement.rebuild_extent(&mut extent, &*disks_mut, &fragments) {
-                    Ok(_) => {
-                        self.metrics.record_rebuild_success(extent.size as u64);
+        }
+        Ok(FileCheckReport { ino, size: inode.size, extents })
+    }
+    
+    /// Verify queued recent writes placed at least `settle` ago, oldest first
+    ///
+    /// At most `limit` extents are read, as scrub I/O. A healthy extent is
+    /// taken off the queue. A degraded one takes the path of a degraded read:
+    /// its corrupt fragments are quarantined and charged to their disks, and
+    /// a background rebuild is queued, whose new fragments queue it again. An
+    /// extent still being written or already queued for rebuild is left for a
+    /// later pass. Nothing runs while the engine is read-only.
+    pub fn verify_recent_writes(&self, settle: std::time::Duration, limit: usize) -> StorageResult<RecentWritePass> {
+        let mut pass = RecentWritePass::default();
+        if self.is_read_only() {
+            return Ok(pass);
+        }
+        let due = self.recent_writes.due(chrono::Utc::now().timestamp() - settle.as_secs() as i64, limit);
+        if !due.is_empty() {
+            let _class = IoClass::Scrub.enter();
+            let disks = self.get_disks();
+            let scrubber = Scrubber::new(self.metadata.read().unwrap().pool_dir().to_path_buf());
+            let mut done = Vec::new();
+            for (uuid, _) in due {
+                if self.in_flight.contains(&uuid) || self.rebuild_queue.is_tracked(&uuid) {
+                    continue;
+                }
+                let checked = {
+                    let metadata = self.metadata.read().unwrap();
+                    if !metadata.extent_exists(&uuid) {
+                        pass.gone += 1;
+                        done.push(uuid);
+                        continue;
                     }
+                    metadata.load_extent(&uuid).and_then(|extent| Ok((scrubber.verify_extent(&extent, &metadata, &disks)?, extent)))
+                };
+                let (result, extent) = match checked {
+                    Ok(checked) => checked,
                     Err(e) => {
-                        self.metrics.record_rebuild_failure();
-                        return Err(e);
+                        log::warn!("Could not verify recently written extent {}: {:#}", uuid, e);
+                        pass.errors += 1;
+                        continue;
+                    }
+                };
+                match result.status {
+                    ScrubStatus::Healthy => pass.healthy += 1,
+                    ScrubStatus::Degraded | ScrubStatus::Repaired => {
+                        log::warn!("Recently written extent {} is degraded: {:?}", uuid, result.issues);
+                        for (fragment_index, disk_uuid) in &result.corrupt_fragments {
+                            let disk = self.disks.read().unwrap().iter().find(|d| d.lock().unwrap().uuid == *disk_uuid).cloned();
+                            if let Some(disk) = disk {
+                                self.quarantine_corrupt_fragment(&uuid, *fragment_index, &disk);
+                            }
+                        }
+                        let intact = extent.redundancy.fragment_count().saturating_sub(result.missing_fragments.len());
+                        self.queue_rebuild(uuid, intact.saturating_sub(extent.redundancy.min_fragments()));
+                        pass.repairs_queued += 1;
+                    }
+                    ScrubStatus::Unrecoverable => {
+                        log::error!("Recently written extent {} is unrecoverable: {:?}", uuid, result.issues);
+                        let available = extent.redundancy.fragment_count().saturating_sub(result.missing_fragments.len() + result.corrupt_fragments.len());
+                        self.unrecoverable_event(uuid, available, extent.redundancy.min_fragments());
+                        pass.unrecoverable += 1;
                     }
                 }
-                metadata.save_extent(&extent)?;
+                done.push(uuid);
             }
-            
-            // Save updated extent with new access stats
-            metadata.save_extent(&extent)?;
-            
-            // Append to result (only the actual data, not padding)
-            result.extend_from_slice(&extent_data[..extent.size]);
+            self.recent_writes.complete(&done);
         }
-        
-        // Record metrics for read operation
-        self.metrics.record_disk_read(result.len() as u64);
-        
-        log::debug!("Read {} bytes from inode {}", result.len(), ino);
-        Ok(result)
+        self.metrics.record_recent_write_pass(&pass);
+        self.metrics.update_recent_write_backlog(&self.recent_write_status(settle));
+        Ok(pass)
     }
     
-    /// Read fragments of an extent with smart replica selection
-    fn read_fragments(&self, extent: &Extent, disks: &[Arc<Mutex<Disk>>]) -> Result<Vec<Option<Vec<u8>>>> {
-        let fragment_count = extent.redundancy.fragment_count();
-        let mut fragments = vec![None; fragment_count];
-        
-        // Snapshot disk state without holding locks during reads
-        let disk_snapshots: Vec<Disk> = disks
-            .iter()
-            .map(|d| d.lock().unwrap().clone())
-            .collect();
-        let disk_refs: Vec<&Disk> = disk_snapshots.iter().collect();
-        let disk_lookup: Vec<(uuid::Uuid, Arc<Mutex<Disk>>)> = disks
-            .iter()
-            .map(|d| {
-                let uuid = d.lock().unwrap().uuid;
-                (uuid, d.clone())
-            })
-            .collect();
+    /// Run `verify_recent_writes` every `config.interval` until the engine is dropped
+    pub fn start_recent_write_verifier(&mut self, config: RecentWriteConfig) {
+        let verifier = self.background_handle();
+        self.recent_write_verifier = Some(PeriodicTask::spawn(config.interval, move || {
+            match verifier.verify_recent_writes(config.settle, config.batch) {
+                Ok(pass) if pass.repairs_queued > 0 || pass.unrecoverable > 0 => log::warn!(
+                    "Recent-write verification checked {} extents: {} queued for repair, {} unrecoverable",
+                    pass.verified(),
+                    pass.repairs_queued,
+                    pass.unrecoverable
+                ),
+                Ok(_) => {}
+                Err(e) => log::error!("Recent-write verification failed: {}", e),
+            }
+        }));
+    }
+    
+    /// Backlog of the recent-write queue, with extents due once `settle` old
+    pub fn recent_write_status(&self, settle: std::time::Duration) -> RecentWriteStatus {
+        self.recent_writes.status(chrono::Utc::now().timestamp(), settle)
+    }
+    
+    /// Queue at most `capacity` recent writes; further placements are left to the full scrub
+    pub fn set_recent_write_capacity(&self, capacity: usize) {
+        self.recent_writes.set_capacity(capacity);
+    }
+    
+    /// Bytes of a file backed by extents, excluding holes
+    pub fn allocated_size(&self, ino: u64) -> StorageResult<u64> {
+        let metadata = self.metadata.read().unwrap();
+        let file_size = metadata.load_inode(ino)?.size;
+        Ok(metadata.load_extent_map(ino)?.allocated_bytes(file_size))
+    }
 
-        // Group locations by fragment index to support smart replica selection
-        let mut fragments_by_index: Vec<Vec<(uuid::Uuid, usize)>> = 
-            vec![Vec::new(); fragment_count];
-        
+    /// Delete an extent's fragments, best effort
+    ///
+    /// A fragment that cannot be deleted, or sits on a disk that is not
+    /// attached, is left in place; once the extent record is gone the orphan
+    /// collector picks it up.
+    fn delete_fragments(disks: &[Arc<Mutex<Disk>>], extent: &Extent) {
         for location in &extent.fragment_locations {
-            fragments_by_index[location.fragment_index].push((location.disk_uuid, location.fragment_index));
+            if let Some(disk_arc) = disks.iter().find(|d| d.lock().unwrap().uuid == location.disk_uuid) {
+                #[cfg(test)]
+                let deleted = crate::crash_sim::check_crash_point(crate::crash_sim::CrashPoint::BeforeFragmentDelete)
+                    .and_then(|_| Ok(disk_arc.lock().unwrap().delete_fragment(&extent.uuid, location.fragment_index)?));
+                #[cfg(not(test))]
+                let deleted = disk_arc.lock().unwrap().delete_fragment(&extent.uuid, location.fragment_index);
+                if let Err(e) = deleted {
+                    log::warn!(
+                        "Failed to delete fragment {} of extent {} on disk {}, leaving it to the orphan collector: {}",
+                        location.fragment_index, extent.uuid, location.disk_uuid, e
+                    );
+                }
+            }
         }
+    }
+
+    /// Delete the fragments and records of extents released by committed transactions
+    ///
+    /// Fragments go first and the release marker last, so an interrupted
+    /// reclaim is finished by the next one. Runs when the engine is created,
+    /// which picks up extents released just before a crash.
+    ///
+    /// The transactions that released them are synced first: until then
+    /// power loss can bring back the maps that pointed at the fragments.
+    pub fn reclaim_released_extents(&self) -> StorageResult<usize> {
+        let metadata = self.metadata.read().unwrap();
+        let disk_refs: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().iter().cloned().collect();
+        let released = metadata.released_extents()?;
+        if !released.is_empty() {
+            metadata.sync_commits()?;
+        }
+        for uuid in &released {
+            if let Ok(extent) = metadata.load_extent(uuid) {
+                Self::delete_fragments(&disk_refs, &extent);
+                metadata.delete_extent(uuid)?;
+            }
+            self.data_cache.invalidate(uuid);
+            metadata.forget_released_extent(uuid)?;
+        }
+        if !released.is_empty() {
+            log::debug!("Reclaimed {} released extents", released.len());
+        }
+        Ok(released.len())
+    }
+
+    /// Reclaim released extents once a write has committed
+    ///
+    /// The write already succeeded; a failure here only delays reclaiming
+    /// until the next write or restart.
+    fn reclaim_after_commit(&self) {
+        if let Err(e) = self.reclaim_released_extents() {
+            log::warn!("Deferred reclaim of released extents failed: {}", e);
+        }
+    }
+
+    /// Change redundancy policy for a file
+    ///
+    /// Extents are converted one at a time, in slot order. Each is re-encoded
+    /// into a copy under a new UUID that replaces the original in its own
+    /// journaled transaction, as a rewrite would, and the old fragments are
+    /// only reclaimed once that commits. Writes to the file and the disks lock
+    /// are only held for one extent at a time.
+    ///
+    /// Progress is saved in the pool; see `policy_change`. A change to the same
+    /// policy that was cancelled or interrupted resumes at the next extent.
+    /// Extents that fail are left on the old policy and reported once every
+    /// other extent has been converted. A cancelled change fails with
+    /// `StorageError::Interrupted`.
+    pub fn change_file_redundancy(
+        &self,
+        ino: u64,
+        new_policy: crate::extent::RedundancyPolicy,
+    ) -> StorageResult<()> {
+        log::info!("Changing redundancy policy for inode {}", ino);
+        self.check_writable()?;
+        if !self.policy_changes.begin(ino) {
+            return Err(anyhow!("A policy change of inode {} is already running", ino).into());
+        }
+        let result = self.run_policy_change(ino, new_policy);
+        self.policy_changes.end(ino);
+        Ok(result?)
+    }
+    
+    fn run_policy_change(&self, ino: u64, new_policy: RedundancyPolicy) -> Result<()> {
+        let pool_dir = self.metadata.read().unwrap().pool_dir().to_path_buf();
+        let mut progress = match PolicyChangeProgress::load(&pool_dir, ino)? {
+            Some(mut saved) if saved.policy == new_policy && saved.state != PolicyChangeState::Finished => {
+                log::info!("Resuming policy change of inode {} at slot {}", ino, saved.next_slot);
+                saved.resume();
+                saved
+            }
+            _ => {
+                let (extents, bytes) = self.extents_to_convert(ino, new_policy)?;
+                if extents == 0 {
+                    log::info!("No extents to rebundle for inode {}", ino);
+                    return PolicyChangeProgress::clear(&pool_dir, ino);
+                }
+                PolicyChangeProgress::new(ino, new_policy, extents, bytes)
+            }
+        };
+        progress.save(&pool_dir)?;
         
-        // Collect read tasks for parallel execution
-        let mut read_tasks = Vec::new();
-        
-        // For each fragment index, try to read from best available replica
-        let strategy = ReplicaSelectionStrategy::Smart;
-        for fragment_index in 0..fragment_count {
-            let locations = &fragments_by_index[fragment_index];
+        loop {
+            if self.policy_changes.is_cancelled(ino) {
+                progress.state = PolicyChangeState::Cancelled;
+                progress.save(&pool_dir)?;
+                let message = format!(
+                    "Policy change of inode {} cancelled after {} of {} extents",
+                    ino, progress.extents_done, progress.total_extents
+                );
+                return Err(StorageError::Interrupted(message).into());
+            }
             
-            // Try to select best replica for this fragment
-            let selected_disk = if let Some((disk_uuid, frag_idx)) = ReplicaSelector::select_replica(extent, &disk_refs, strategy) {
-                if frag_idx == fragment_index {
-                    disk_lookup
-                        .iter()
-                        .find(|(uuid, _)| *uuid == disk_uuid)
-                        .map(|(_, arc)| arc.clone())
-                } else {
-                    None
-                }
-            } else {
-                None
+            let writer = self.lock_inode_writes(ino);
+            self.flush_buffered(ino, FlushCause::Explicit)?;
+            let slot = progress.next_slot;
+            let Some(extent_uuid) = self.metadata.read().unwrap().load_extent_map(ino)?.extents.get(slot).copied() else {
+                break;
             };
-            
-            // If no smart selection, fallback to any available replica
-            let disk = selected_disk.or_else(|| {
-                locations.iter().find_map(|(disk_uuid, _)| {
-                    disk_lookup
-                        .iter()
-                        .find(|(uuid, _)| uuid == disk_uuid)
-                        .map(|(_, arc)| arc.clone())
-                })
-            });
-            
-            if let Some(disk) = disk {
-                let extent_uuid = extent.uuid;
-                let disk_clone = disk.clone();
-                let task = thread::spawn(move || {
-                    disk_clone.lock().unwrap().read_fragment(&extent_uuid, fragment_index)
-                });
-                read_tasks.push((fragment_index, task));
+            if ExtentMap::is_hole(&extent_uuid) {
+                progress.next_slot += 1;
+                continue;
             }
-        }
-        
-        // Execute reads in parallel and collect results
-        for (fragment_index, task) in read_tasks {
-            match task.join() {
-                Ok(Ok(data)) => {
-                    fragments[fragment_index] = Some(data);
-                }
-                Ok(Err(e)) => {
-                    self.metrics.record_disk_error();
-                    log::warn!(
-                        "Failed to read fragment {} of extent {}: {}",
-                        fragment_index,
-                        extent.uuid,
-                        e
-                    );
+            let extent = self.metadata.read().unwrap().load_extent(&extent_uuid)?;
+            if extent.redundancy == new_policy {
+                log::debug!("Extent {} already has target policy, skipping", extent_uuid);
+                progress.next_slot += 1;
+                continue;
+            }
+            
+            progress.in_progress = Some(extent_uuid);
+            progress.save(&pool_dir)?;
+            log::info!("Rebundling extent {} for inode {}", extent_uuid, ino);
+            let mut in_flight = self.in_flight.begin();
+            match self.rebundled_copy(&extent, new_policy, &mut in_flight) {
+                Ok(copy) => {
+                    // Metadata that failed to commit may be replayed later; stop and resume from here
+                    self.commit_rebundled_copy(ino, &extent, &copy, new_policy)?;
+                    progress.extents_done += 1;
+                    progress.bytes_converted += extent.size as u64;
                 }
                 Err(e) => {
-                    log::error!("Task join error for fragment {}: {:?}", fragment_index, e);
+                    log::warn!("Failed to rebundle extent {} of inode {}: {:#}", extent_uuid, ino, e);
+                    progress.failed.push(FailedExtent { slot, uuid: extent_uuid, error: format!("{:#}", e) });
                 }
             }
+            progress.in_progress = None;
+            progress.next_slot += 1;
+            progress.save(&pool_dir)?;
+            drop(writer);
+            self.reclaim_after_commit();
         }
         
-        Ok(fragments)
+        progress.state = PolicyChangeState::Finished;
+        progress.save(&pool_dir)?;
+        if let Some(first) = progress.failed.first() {
+            return Err(anyhow!(
+                "{} extents of inode {} are still on their old policy; extent {}: {}",
+                progress.failed.len(),
+                ino,
+                first.uuid,
+                first.error
+            ));
+        }
+        log::info!("Successfully changed redundancy policy for inode {}", ino);
+        Ok(())
     }
     
-    /// Delete a file
-    pub fn delete_file(&self, ino: u64) -> Result<()> {
-        log::info!("Deleting inode {}", ino);
-        
+    /// Extents of a file not yet on `policy`, counting shared extents once, and their bytes
+    fn extents_to_convert(&self, ino: u64, policy: RedundancyPolicy) -> Result<(u64, u64)> {
+        let _writer = self.lock_inode_writes(ino);
+        self.flush_buffered(ino, FlushCause::Explicit)?;
         let metadata = self.metadata.read().unwrap();
-        
-        // Load extent map
         let extent_map = metadata.load_extent_map(ino)?;
-        
-        let disks = self.disks.write().unwrap();
-        
-        // Delete all extents and fragments
-        for extent_uuid in &extent_map.extents {
-            if let Ok(extent) = metadata.load_extent(extent_uuid) {
-                // Delete fragments from disks
-                for location in &extent.fragment_locations {
-                    if let Some(disk_arc) = disks.iter().find(|d| d.lock().unwrap().uuid == location.disk_uuid) {
-                        disk_arc.lock().unwrap().delete_fragment(&extent.uuid, location.fragment_index).ok();
-                    }
-                }
-                
-                // Delete extent metadata
-                metadata.delete_extent(extent_uuid).ok();
+        let mut seen = HashSet::new();
+        let (mut extents, mut bytes) = (0, 0);
+        for uuid in extent_map.data_extents() {
+            if !seen.insert(*uuid) {
+                continue;
+            }
+            let extent = metadata.load_extent(uuid)?;
+            if extent.redundancy != policy {
+                extents += 1;
+                bytes += extent.size as u64;
             }
         }
+        Ok((extents, bytes))
+    }
+    
+    /// Re-encode an extent under `new_policy` into a copy with a new UUID
+    ///
+    /// The copy is registered with `in_flight`, which must be held until it is
+    /// committed. On failure the copy's fragments are deleted.
+    fn rebundled_copy(&self, extent: &Extent, new_policy: RedundancyPolicy, in_flight: &mut InFlightWrite) -> Result<Extent> {
+        let disks = self.disks.read().unwrap();
+        let mut copy = extent.clone();
+        copy.uuid = uuid::Uuid::new_v4();
+        copy.fragment_locations.clear();
+        // The requested policy replaces any a degraded write meant to upgrade to
+        copy.intended_policy = None;
+        in_flight.add(copy.uuid);
+        let placed = self
+            .read_fragments(extent, &disks)
+            .and_then(|fragments| self.placement.rebundle_extent(&mut copy, &disks, &fragments, new_policy));
+        if let Err(err) = placed {
+            let disk_refs: Vec<Arc<Mutex<Disk>>> = disks.iter().cloned().collect();
+            drop(disks);
+            Self::delete_copy_fragments(&disk_refs, &copy, new_policy);
+            return Err(err);
+        }
+        Ok(copy)
+    }
+    
+    /// Switch every slot of a file holding `extent` to its re-encoded `copy`
+    ///
+    /// The caller holds the file's write lock. The switch is one journaled
+    /// transaction; if it is not journaled the copy's fragments are deleted.
+    fn commit_rebundled_copy(&self, ino: u64, extent: &Extent, copy: &Extent, new_policy: RedundancyPolicy) -> Result<()> {
+        let mut metadata = self.metadata.write().unwrap();
+        let journaled = (|| -> StorageResult<crate::metadata_tx::MetadataTransaction> {
+            // Writes to the file are locked out, so this is the map the copy was made from
+            let mut map = metadata.load_extent_map(ino)?;
+            for slot in map.extents.iter_mut().filter(|uuid| **uuid == extent.uuid) {
+                *slot = copy.uuid;
+            }
+            // The map never points at a missing extent, even if applying stops part-way
+            metadata.journal_transaction(vec![
+                MetadataOp::SaveExtent(Box::new(copy.clone())),
+                MetadataOp::SaveExtentMap(map),
+                MetadataOp::ReleaseExtent(extent.uuid),
+            ])
+        })();
+        let tx = match journaled {
+            Ok(tx) => tx,
+            Err(err) => {
+                // Nothing was committed; the copy is unreferenced
+                drop(metadata);
+                let disk_refs: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().iter().cloned().collect();
+                Self::delete_copy_fragments(&disk_refs, copy, new_policy);
+                return Err(err.into());
+            }
+        };
         
-        // Delete extent map
-        metadata.delete_extent_map(ino)?;
-        
-        // Delete inode
-        metadata.delete_inode(ino)?;
-        
+        // The change is committed once journaled; a failed apply is replayed on the next mount
+        if let Err(err) = metadata.apply_transaction(tx) {
+            log::error!("Applying journaled policy change of inode {} failed, will replay on recovery: {}", ino, err);
+            return Err(err.into());
+        }
         Ok(())
     }
     
-    /// Get inode
-    pub fn get_inode(&self, ino: u64) -> Result<Inode> {
-        let metadata = self.metadata.read().unwrap();
-        metadata.load_inode(ino)
+    /// Progress of the last policy change of `ino`, if any is recorded
+    ///
+    /// `active` is set when this engine is running it; a `Running` change that
+    /// is not active was interrupted and resumes when started again.
+    pub fn get_policy_change_progress(&self, ino: u64) -> StorageResult<Option<PolicyChangeProgress>> {
+        let pool_dir = self.metadata.read().unwrap().pool_dir().to_path_buf();
+        let mut progress = PolicyChangeProgress::load(&pool_dir, ino)?;
+        if let Some(progress) = progress.as_mut() {
+            progress.active = self.policy_changes.is_running(ino);
+        }
+        Ok(progress)
     }
     
-    /// List directory
-    pub fn list_directory(&self, parent_ino: u64) -> Result<Vec<Inode>> {
-        let metadata = self.metadata.read().unwrap();
-        metadata.list_directory(parent_ino)
+    /// Every recorded policy change, as `get_policy_change_progress` reports them
+    pub fn policy_changes(&self) -> StorageResult<Vec<PolicyChangeProgress>> {
+        let pool_dir = self.metadata.read().unwrap().pool_dir().to_path_buf();
+        let mut changes = PolicyChangeProgress::list(&pool_dir)?;
+        for change in &mut changes {
+            change.active = self.policy_changes.is_running(change.ino);
+        }
+        Ok(changes)
     }
     
-    /// Find child by name
-    pub fn find_child(&self, parent_ino: u64, name: &str) -> Result<Option<Inode>> {
-        let metadata = self.metadata.read().unwrap();
-        metadata.find_child(parent_ino, name)
+    /// Stop the running policy change of `ino` after its current extent
+    ///
+    /// Returns false if this engine is not running one. The file keeps the
+    /// extents converted so far.
+    pub fn cancel_policy_change(&self, ino: u64) -> bool {
+        self.policy_changes.cancel(ino)
     }
     
-    /// Create a new file
-    pub fn create_file(&self, parent_ino: u64, name: String) -> Result<Inode> {
-        let mut metadata = self.metadata.write().unwrap();
-        let ino = metadata.allocate_ino();
-        let inode = Inode::new_file(ino, parent_ino, name);
-        metadata.save_inode(&inode)?;
-        Ok(inode)
+    /// Delete whatever fragments of a never-committed extent copy made it to a disk
+    fn delete_copy_fragments(disks: &[Arc<Mutex<Disk>>], copy: &Extent, policy: RedundancyPolicy) {
+        for disk in disks {
+            let mut disk = disk.lock().unwrap();
+            for fragment_index in 0..policy.fragment_count() {
+                disk.delete_fragment(&copy.uuid, fragment_index).ok();
+            }
+        }
     }
     
-    /// Create a new directory
-    pub fn create_dir(&self, parent_ino: u64, name: String) -> Result<Inode> {
-        let mut metadata = self.metadata.write().unwrap();
-        let ino = metadata.allocate_ino();
-        let inode = Inode::new_dir(ino, parent_ino, name);
-        metadata.save_inode(&inode)?;
-        Ok(inode)
+    /// Policy requested for a file through the redundancy xattr, if any
+    fn requested_redundancy(&self, ino: u64) -> Option<RedundancyPolicy> {
+        let metadata = self.metadata.read().unwrap();
+        Self::requested_redundancy_in(&metadata, ino)
     }
     
-    /// Update inode
-    pub fn update_inode(&self, inode: &Inode) -> Result<()> {
-        let metadata = self.metadata.read().unwrap();
-        metadata.save_inode(inode)
+    /// Same as `requested_redundancy` for callers already holding the metadata lock
+    fn requested_redundancy_in(metadata: &MetadataManager, ino: u64) -> Option<RedundancyPolicy> {
+        let inode = metadata.load_inode(ino).ok()?;
+        let value = inode.get_xattr(crate::fs_interface::REDUNDANCY_XATTR)?;
+        std::str::from_utf8(value).ok()?.parse().ok()
     }
     
-    /// Change redundancy policy for a file
-    /// This re-bundles all extents with the new policy
-    pub fn change_file_redundancy(
-        &self,
-        ino: u64,
-        new_policy: crate::extent::RedundancyPolicy,
-    ) -> Result<()> {
-        println!("DEBUG: Starting change_file_redundancy for inode {}", ino);
-        log::info!("Changing redundancy policy for inode {}", ino);
-        
+    /// Current redundancy policy of a file's extents
+    ///
+    /// Falls back to the requested policy when the file has no extents yet.
+    pub fn get_file_redundancy(&self, ino: u64) -> StorageResult<Option<RedundancyPolicy>> {
         let extent_map = {
             let metadata = self.metadata.read().unwrap();
             metadata.load_extent_map(ino)?
         };
-        println!("DEBUG: Loaded extent_map with {} extents", extent_map.extents.len());
         
-        if extent_map.extents.is_empty() {
-            log::info!("No extents to rebundle for inode {}", ino);
-            return Ok(());
+        if let Some(extent_uuid) = extent_map.data_extents().next() {
+            let metadata = self.metadata.read().unwrap();
+            let extent = metadata.load_extent(extent_uuid)?;
+            return Ok(Some(extent.redundancy));
         }
         
-        let disks = self.disks.write().unwrap();
-        println!("DEBUG: Acquired disks lock");
-        
-        // Re-bundle each extent
-        for extent_uuid in &extent_map.extents {
-            println!("DEBUG: Processing extent {}", extent_uuid);
-            let mut extent = {
-                let metadata = self.metadata.read().unwrap();
-                metadata.load_extent(extent_uuid)?
-            };
-            println!("DEBUG: Loaded extent {}", extent_uuid);
-            
-            if extent.redundancy == new_policy {
-                log::debug!("Extent {} already has target policy, skipping", extent_uuid);
-                continue;
-            }
-            
-            log::info!(
-                "Rebundling extent {} for inode {}",
-                extent_uuid,
-                ino
-            );
-            
-            // Load fragments with old policy
-            println!("DEBUG: Calling read_fragments for extent {}", extent_uuid);
-            let fragments = self.read_fragments(&extent, &disks)?;
-            println!("DEBUG: read_fragments completed for extent {}", extent_uuid);
-            
-            // Rebundle
-            self.placement.rebundle_extent(
-                &mut extent,
-                &*disks,
-                &fragments,
-                new_policy,
-            )?;
-            println!("DEBUG: rebundle_extent completed for extent {}", extent_uuid);
-            
-            // Save updated extent
-            {
-                let metadata = self.metadata.read().unwrap();
-                metadata.save_extent(&extent)?;
-            }
-            println!("DEBUG: Saved extent {}", extent_uuid);
-            
-            log::info!(
-                "Successfully rebundled extent {} to {:?}",
-                extent_uuid,
-                new_policy
+        Ok(self.requested_redundancy(ino))
+    }
+    
+    /// Record a requested redundancy policy on the inode and re-encode its extents
+    ///
+    /// Fails with `StorageError::NoSpace` if the policy needs more
+    /// fragments than there are healthy disks to hold them.
+    pub fn set_file_redundancy(&self, ino: u64, policy: RedundancyPolicy) -> StorageResult<()> {
+        self.check_writable()?;
+        let healthy_disks = {
+            let disks = self.disks.read().unwrap();
+            disks
+                .iter()
+                .filter(|d| d.lock().unwrap().health == crate::disk::DiskHealth::Healthy)
+                .count()
+        };
+        if policy.fragment_count() > healthy_disks {
+            let message = format!(
+                "Policy {} needs {} disks but only {} are healthy",
+                policy,
+                policy.fragment_count(),
+                healthy_disks
             );
+            self.events.record(EventKind::NoSpace, None, None, format!("inode {}: {}", ino, message));
+            return Err(StorageError::NoSpace(message));
         }
         
-        log::info!("Successfully changed redundancy policy for inode {}", ino);
-        println!("DEBUG: change_file_redundancy completed for inode {}", ino);
-        Ok(())
+        self.change_file_redundancy(ino, policy)?;
+        
+        let metadata = self.metadata.read().unwrap();
+        let mut inode = metadata.load_inode(ino)?;
+        inode.set_xattr(
+            crate::fs_interface::REDUNDANCY_XATTR.to_string(),
+            policy.to_string().into_bytes(),
+        );
+        metadata.save_inode(&inode)
     }
     
     /// Get policy change history for an extent
     pub fn get_extent_policy_history(
         &self,
         extent_uuid: &uuid::Uuid,
-    ) -> Result<Vec<(crate::extent::RedundancyPolicy, i64)>> {
+    ) -> StorageResult<Vec<(crate::extent::RedundancyPolicy, i64)>> {
         let metadata = self.metadata.read().unwrap();
         let extent = metadata.load_extent(extent_uuid)?;
         Ok(extent.get_policy_history())
     }
     
     /// List extents that are in the middle of a policy transition
-    pub fn get_transitioning_extents(&self) -> Result<Vec<uuid::Uuid>> {
+    pub fn get_transitioning_extents(&self) -> StorageResult<Vec<uuid::Uuid>> {
         let metadata = self.metadata.read().unwrap();
-        let all_extents = metadata.list_all_extents()?;
-        
-        Ok(all_extents
-            .iter()
+        Ok(metadata
+            .iter_extents()?
+            .filter_map(Result::ok)
             .filter(|e| e.is_transitioning())
             .map(|e| e.uuid)
             .collect())
@@ -698,33 +3623,33 @@ impl StorageEngine {
     pub fn get_extent_classification(
         &self,
         extent_uuid: &uuid::Uuid,
-    ) -> Result<crate::extent::AccessClassification> {
+    ) -> StorageResult<crate::extent::AccessClassification> {
         let metadata = self.metadata.read().unwrap();
-        let extent = metadata.load_extent(extent_uuid)?;
+        let extent = self.classified(metadata.load_extent(extent_uuid)?);
         Ok(extent.classification())
     }
     
-    /// List all hot extents
-    pub fn get_hot_extents(&self) -> Result<Vec<(uuid::Uuid, crate::extent::AccessStats)>> {
-        let metadata = self.metadata.read().unwrap();
-        let all_extents = metadata.list_all_extents()?;
-        
-        Ok(all_extents
-            .iter()
-            .filter(|e| e.classification() == crate::extent::AccessClassification::Hot)
-            .map(|e| (e.uuid, e.access_stats.clone()))
-            .collect())
+    /// List all hot extents, most frequently accessed first
+    pub fn get_hot_extents(&self) -> StorageResult<Vec<Extent>> {
+        let mut extents = self.extents_with_classification(AccessClassification::Hot)?;
+        extents.sort_by(|a, b| b.access_frequency().total_cmp(&a.access_frequency()));
+        Ok(extents)
+    }
+    
+    /// List all cold extents, least frequently accessed first
+    pub fn get_cold_extents(&self) -> StorageResult<Vec<Extent>> {
+        let mut extents = self.extents_with_classification(AccessClassification::Cold)?;
+        extents.sort_by(|a, b| a.access_frequency().total_cmp(&b.access_frequency()));
+        Ok(extents)
     }
     
-    /// List all cold extents
-    pub fn get_cold_extents(&self) -> Result<Vec<(uuid::Uuid, crate::extent::AccessStats)>> {
+    fn extents_with_classification(&self, classification: AccessClassification) -> Result<Vec<Extent>> {
         let metadata = self.metadata.read().unwrap();
-        let all_extents = metadata.list_all_extents()?;
-        
-        Ok(all_extents
-            .iter()
-            .filter(|e| e.classification() == crate::extent::AccessClassification::Cold)
-            .map(|e| (e.uuid, e.access_stats.clone()))
+        Ok(metadata
+            .iter_extents()?
+            .filter_map(Result::ok)
+            .map(|e| self.classified(e))
+            .filter(|e| e.classification() == classification)
             .collect())
     }
     
@@ -732,9 +3657,9 @@ impl StorageEngine {
     pub fn get_extent_access_stats(
         &self,
         extent_uuid: &uuid::Uuid,
-    ) -> Result<crate::extent::AccessStats> {
+    ) -> StorageResult<crate::extent::AccessStats> {
         let metadata = self.metadata.read().unwrap();
-        let extent = metadata.load_extent(extent_uuid)?;
+        let extent = self.access.merged(metadata.load_extent(extent_uuid)?);
         Ok(extent.access_stats.clone())
     }
     
@@ -742,104 +3667,871 @@ impl StorageEngine {
     pub fn get_recommended_policy(
         &self,
         extent_uuid: &uuid::Uuid,
-    ) -> Result<crate::extent::RedundancyPolicy> {
+    ) -> StorageResult<crate::extent::RedundancyPolicy> {
         let metadata = self.metadata.read().unwrap();
-        let extent = metadata.load_extent(extent_uuid)?;
+        let extent = self.classified(metadata.load_extent(extent_uuid)?);
         Ok(extent.recommended_policy())
     }
     
     /// Check if an extent should be migrated based on classification
-    pub fn extent_needs_migration(&self, extent_uuid: &uuid::Uuid) -> Result<bool> {
+    pub fn extent_needs_migration(&self, extent_uuid: &uuid::Uuid) -> StorageResult<bool> {
         let metadata = self.metadata.read().unwrap();
-        let extent = metadata.load_extent(extent_uuid)?;
+        let extent = self.classified(metadata.load_extent(extent_uuid)?);
         Ok(extent.should_migrate())
     }
+    
+    fn disk_tiers(&self) -> HashMap<uuid::Uuid, StorageTier> {
+        self.disks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|d| {
+                let disk = d.lock().unwrap();
+                (disk.uuid, disk.tier)
+            })
+            .collect()
+    }
+    
+    /// Tier currently holding `extent`; see `tiering::extent_tier`
+    pub fn extent_tier(&self, extent: &Extent) -> Option<StorageTier> {
+        tiering::extent_tier(extent, &self.disk_tiers())
+    }
+    
+    /// Capacity, usage and resident extents of every tier, fastest first
+    ///
+    /// Failed disks count toward neither capacity nor usage.
+    pub fn tier_status(&self) -> StorageResult<Vec<TierStatus>> {
+        let mut status: Vec<TierStatus> = StorageTier::ALL
+            .into_iter()
+            .map(|tier| TierStatus { tier, disks: 0, capacity_bytes: 0, used_bytes: 0, extents: 0, extent_bytes: 0 })
+            .collect();
+        for disk in self.disks.read().unwrap().iter() {
+            let disk = disk.lock().unwrap();
+            let entry = &mut status[disk.tier.rank() as usize];
+            entry.disks += 1;
+            if disk.health != DiskHealth::Failed {
+                entry.capacity_bytes += disk.capacity_bytes;
+                entry.used_bytes += disk.used_bytes;
+            }
+        }
+        
+        let disk_tiers = self.disk_tiers();
+        for extent in self.metadata.read().unwrap().iter_extents()?.filter_map(Result::ok) {
+            if let Some(tier) = tiering::extent_tier(&extent, &disk_tiers) {
+                let entry = &mut status[tier.rank() as usize];
+                entry.extents += 1;
+                entry.extent_bytes += extent.size as u64;
+            }
+        }
+        Ok(status)
+    }
+    
+    /// Run `run_tier_pass` every `config.interval` until the engine is dropped
+    pub fn start_tiering(&mut self, config: TierPassConfig) {
+        let mover = self.background_handle();
+        self.tiering = Some(PeriodicTask::spawn(config.interval, move || {
+            match mover.run_tier_pass(&config) {
+                Ok(report) if report.fragments_moved > 0 || report.failed > 0 => log::info!(
+                    "Tiering pass moved {} fragments ({} bytes) of {} extents; {} failed",
+                    report.fragments_moved,
+                    report.bytes_moved,
+                    report.extents_demoted + report.pressure_demotions,
+                    report.failed
+                ),
+                Ok(_) => {}
+                Err(e) => log::error!("Tiering pass failed: {}", e),
+            }
+        }));
+    }
+    
+    /// Move extents down to the tiers their access pattern calls for
+    ///
+    /// Extents are reclassified first, so data that stopped being read drifts
+    /// to slower disks. Then every tier above `config.high_watermark` percent
+    /// used sheds its coldest extents to the next slower tier until it is
+    /// down to `config.low_watermark`. Promotion is left to placement: hot
+    /// extents reach the fast tier when lazy migration rewrites them. Extents
+    /// being written, rebuilt or re-encoded are skipped until the next pass.
+    pub fn run_tier_pass(&self, config: &TierPassConfig) -> StorageResult<TierPassReport> {
+        let mut report = TierPassReport::default();
+        if self.is_read_only() {
+            return Ok(report);
+        }
+        if let Err(e) = self.flush_access_stats() {
+            log::warn!("Tiering pass could not flush access statistics: {}", e);
+        }
+        
+        let disk_tiers = self.disk_tiers();
+        let model = self.access_model();
+        let mut resident: Vec<(Extent, StorageTier)> = Vec::new();
+        for mut extent in self.metadata.read().unwrap().iter_extents()?.filter_map(Result::ok) {
+            let movable = !extent.is_transitioning()
+                && !extent.rebuild_in_progress
+                && !self.in_flight.contains(&extent.uuid)
+                && extent.fragment_locations.iter().all(|l| l.on_device.is_none());
+            let Some(tier) = tiering::extent_tier(&extent, &disk_tiers).filter(|_| movable) else {
+                continue;
+            };
+            extent.reclassify_with_model(model.as_ref());
+            resident.push((extent, tier));
+        }
+        
+        // Classification first
+        for (extent, tier) in resident.iter_mut() {
+            let target = StorageTier::for_classification(extent.classification());
+            if target.rank() <= tier.rank() {
+                continue;
+            }
+            match self.move_extent_to_tier(&extent.uuid, target) {
+                Ok((fragments, bytes)) if fragments > 0 => {
+                    report.extents_demoted += 1;
+                    report.fragments_moved += fragments;
+                    report.bytes_moved += bytes;
+                    *tier = target;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("Failed to move extent {} to the {} tier: {}", extent.uuid, target, e);
+                    report.failed += 1;
+                }
+            }
+        }
+        
+        // Then capacity, coldest first
+        resident.sort_by(|a, b| a.0.access_frequency().total_cmp(&b.0.access_frequency()));
+        for status in self.tier_status()? {
+            let Some(slower) = status.tier.slower() else { continue };
+            if status.utilization_percent() <= config.high_watermark {
+                continue;
+            }
+            let excess = status.used_bytes as f64 - status.capacity_bytes as f64 * config.low_watermark / 100.0;
+            let mut freed = 0u64;
+            for (extent, tier) in resident.iter_mut().filter(|(_, tier)| *tier == status.tier) {
+                if freed as f64 >= excess {
+                    break;
+                }
+                match self.move_extent_to_tier(&extent.uuid, slower) {
+                    Ok((fragments, bytes)) if fragments > 0 => {
+                        report.pressure_demotions += 1;
+                        report.fragments_moved += fragments;
+                        report.bytes_moved += bytes;
+                        freed += bytes;
+                        *tier = slower;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::warn!("Failed to move extent {} to the {} tier: {}", extent.uuid, slower, e);
+                        report.failed += 1;
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+    
+    /// Compact the on-device allocator of a raw block device disk
+    ///
+    /// Each fragment moved has the placement in its extent record updated
+    /// before its old units are freed. A fragment no extent references stops
+    /// the compaction. Returns the number of fragments moved.
+    pub fn defragment_device(&self, disk_uuid: uuid::Uuid) -> StorageResult<u64> {
+        let metadata = self.metadata.write().unwrap();
+        let disks = self.disks.read().unwrap();
+        let disk = disks
+            .iter()
+            .find(|d| d.lock().unwrap().uuid == disk_uuid)
+            .cloned()
+            .ok_or_else(|| StorageError::DiskUnavailable { disk: disk_uuid, detail: format!("disk {} is not in the pool", disk_uuid) })?;
+        let mut disk = disk.lock().unwrap();
+        let Some(oda) = disk.on_device_allocator.as_mut() else {
+            return Err(anyhow!("disk {} has no on-device allocator", disk_uuid).into());
+        };
+        Ok(oda.defragment(|change| {
+            let mut extent = metadata.load_extent(&change.extent_uuid)?;
+            let location = extent
+                .fragment_locations
+                .iter_mut()
+                .find(|l| {
+                    l.disk_uuid == disk_uuid
+                        && l.fragment_index == change.fragment_index as usize
+                        && l.on_device.as_ref().is_some_and(|p| p.start_unit == change.from.start_unit)
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "extent {} has no fragment {} at unit {} of disk {}",
+                        change.extent_uuid, change.fragment_index, change.from.start_unit, disk_uuid
+                    )
+                })?;
+            location.on_device = Some(change.to.clone());
+            Ok(metadata.save_extent(&extent)?)
+        })?)
+    }
+
+    /// Move the fragments of an extent that sit on tiers faster than `tier` onto `tier`
+    ///
+    /// Returns the fragments and bytes moved; see `move_extent_fragments`.
+    fn move_extent_to_tier(&self, extent_uuid: &uuid::Uuid, tier: StorageTier) -> Result<(u64, u64)> {
+        let moved = self.move_extent_fragments(
+            extent_uuid,
+            |_, _, source| source.tier.rank() < tier.rank(),
+            |target| target.tier == tier,
+        )?;
+        if moved.0 > 0 {
+            log::info!("Moved {} fragments of extent {} to the {} tier", moved.0, extent_uuid, tier);
+        }
+        Ok(moved)
+    }
+    
+    /// Spread the fragments of an extent that share a disk onto disks holding none of it
+    ///
+    /// The first fragment on each disk stays. Extents queued for rebuild, being
+    /// written or re-encoded, or on raw devices are left alone. Returns the
+    /// fragments and bytes moved; see `move_extent_fragments`.
+    pub fn defragment_extent(&self, extent_uuid: &uuid::Uuid) -> StorageResult<(u64, u64)> {
+        let moved = self.move_extent_fragments(
+            extent_uuid,
+            |extent, pos, _| {
+                let disk_uuid = extent.fragment_locations[pos].disk_uuid;
+                extent.fragment_locations[..pos].iter().any(|l| l.disk_uuid == disk_uuid)
+            },
+            |_| true,
+        )?;
+        if moved.0 > 0 {
+            log::info!("Spread {} fragments of extent {} onto disks of their own", moved.0, extent_uuid);
+        }
+        Ok(moved)
+    }
+    
+    /// Lay a file cut into slots smaller than the pool's extent size out again
+    /// in the pool's size
+    ///
+    /// Each run of adjacent small extents filling one new slot is read,
+    /// concatenated and written as a single extent with the file's current
+    /// policy. The new extent map replaces the old one and the old extents are
+    /// released in one transaction; their fragments are reclaimed once it
+    /// commits. Files already in the pool's size or larger are left alone.
+    /// Returns the extents removed and the bytes rewritten.
+    pub fn coalesce_extents(&self, ino: u64) -> StorageResult<(usize, u64)> {
+        self.check_writable()?;
+        let _writer = self.lock_inode_writes(ino);
+        self.flush_buffered(ino, FlushCause::Explicit)?;
+
+        let mut metadata = self.metadata.write().unwrap();
+        let inode = metadata.load_inode(ino)?;
+        let old_map = metadata.load_extent_map(ino)?;
+        let extent_size = self.extent_size();
+        if old_map.extent_size >= extent_size || old_map.data_extents().next().is_none() {
+            return Ok((0, 0));
+        }
+        // Both are powers of two, so old slots never straddle new ones
+        let per_slot = extent_size / old_map.extent_size;
+        let groups: Vec<Vec<Option<Extent>>> = old_map
+            .extents
+            .chunks(per_slot)
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|uuid| (!ExtentMap::is_hole(uuid)).then(|| metadata.load_extent(uuid)).transpose())
+                    .collect()
+            })
+            .collect::<StorageResult<_>>()?;
+
+        let disk_refs: Vec<Arc<Mutex<Disk>>> = self.disks.read().unwrap().iter().cloned().collect();
+        let wanted = Self::policy_for_size(&metadata, ino, inode.size);
+        if self.policy_to_write(&disk_refs, wanted).1.is_some() {
+            // Not worth giving up redundancy for; the next pass tries again
+            log::debug!("Not coalescing inode {} while too few disks are writable for {}", ino, wanted);
+            return Ok((0, 0));
+        }
+        let policy = wanted;
+        let slot_len = |group: &[Option<Extent>]| {
+            group
+                .iter()
+                .enumerate()
+                .filter_map(|(index, extent)| Some(index * old_map.extent_size + extent.as_ref()?.size))
+                .max()
+        };
+        let demand: Vec<(RedundancyPolicy, usize)> =
+            groups.iter().filter_map(|group| slot_len(group)).map(|len| (policy, len)).collect();
+        let _reservation = self.reserve_space(&disk_refs, &demand)?;
+        let verify = self.verify_writes_for(&metadata, ino);
+        let mut in_flight = self.in_flight.begin();
+        let mut new_map = ExtentMap::new(ino, extent_size);
+        let mut released: Vec<Extent> = Vec::new();
+        let mut replacements: Vec<Extent> = Vec::new();
+        let mut bytes = 0u64;
+
+        let journaled = (|| -> StorageResult<crate::metadata_tx::MetadataTransaction> {
+            for group in &groups {
+                let Some(len) = slot_len(group) else {
+                    new_map.extents.push(ExtentMap::HOLE);
+                    continue;
+                };
+                let mut slot = vec![0u8; len];
+                for (index, extent) in group.iter().enumerate() {
+                    let Some(extent) = extent else { continue };
+                    let fragments = self.read_fragments_for_decode(extent, &disk_refs).fragments;
+                    let data = extent.unpack(redundancy::decode(&fragments, extent.redundancy, extent.stored_size())?)?;
+                    if !extent.verify_checksum(&data) {
+                        return Err(checksum_failed(extent.uuid));
+                    }
+                    let start = index * old_map.extent_size;
+                    slot[start..start + data.len()].copy_from_slice(&data);
+                    released.push(extent.clone());
+                }
+
+                let mut replacement = Extent::new(&slot, policy);
+                replacement.extent_size = extent_size;
+                let fragments = self.encode_extent(&mut replacement, &slot)?;
+                self.place_extent(&mut replacement, &disk_refs, &fragments, verify, &mut in_flight)?;
+                new_map.extents.push(replacement.uuid);
+                replacements.push(replacement);
+                bytes += len as u64;
+            }
+
+            // The file's contents and times are unchanged; only its layout moves
+            let mut ops: Vec<MetadataOp> = replacements
+                .iter()
+                .map(|extent| MetadataOp::SaveExtent(Box::new(extent.clone())))
+                .collect();
+            ops.push(MetadataOp::SaveExtentMap(new_map.clone()));
+            ops.extend(released.iter().map(|extent| MetadataOp::ReleaseExtent(extent.uuid)));
+            metadata.journal_transaction(ops)
+        })();
+
+        let tx = match journaled {
+            Ok(tx) => tx,
+            Err(err) => {
+                for replacement in &replacements {
+                    Self::delete_fragments(&disk_refs, replacement);
+                }
+                return Err(err);
+            }
+        };
+        metadata.apply_transaction(tx)?;
+        drop(metadata);
+        self.reclaim_after_commit();
+
+        let removed = released.len().saturating_sub(replacements.len());
+        log::info!(
+            "Coalesced {} extents of inode {} into {} of {} KB",
+            released.len(),
+            ino,
+            replacements.len(),
+            extent_size / 1024
+        );
+        Ok((removed, bytes))
+    }
+
+    /// Move every fragment of an extent that is not on `tier` onto it
+    ///
+    /// Unlike the tiering pass this also promotes. Returns the fragments and
+    /// bytes moved; see `move_extent_fragments`.
+    pub fn place_extent_on_tier(&self, extent_uuid: &uuid::Uuid, tier: StorageTier) -> StorageResult<(u64, u64)> {
+        let moved = self.move_extent_fragments(extent_uuid, |_, _, source| source.tier != tier, |target| target.tier == tier)?;
+        if moved.0 > 0 {
+            log::info!("Moved {} fragments of extent {} to the {} tier", moved.0, extent_uuid, tier);
+        }
+        Ok(moved)
+    }
+    
+    /// Move the fragments of an extent off disks fuller than the pool average onto emptier ones
+    ///
+    /// Returns the fragments and bytes moved; see `move_extent_fragments`.
+    pub fn rebalance_extent(&self, extent_uuid: &uuid::Uuid) -> StorageResult<(u64, u64)> {
+        let utilizations: Vec<f64> = self
+            .disks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|d| d.lock().unwrap())
+            .filter(|d| d.health == DiskHealth::Healthy)
+            .map(|d| d.utilization())
+            .collect();
+        if utilizations.is_empty() {
+            return Ok((0, 0));
+        }
+        let mean = utilizations.iter().sum::<f64>() / utilizations.len() as f64;
+        let moved = self.move_extent_fragments(
+            extent_uuid,
+            |_, _, source| source.utilization() > mean,
+            |target| target.utilization() < mean,
+        )?;
+        if moved.0 > 0 {
+            log::info!("Moved {} fragments of extent {} to emptier disks", moved.0, extent_uuid);
+        }
+        Ok(moved)
+    }
+    
+    /// Decode an extent into the data cache ahead of its reads; returns its size
+    pub fn cache_extent(&self, extent_uuid: &uuid::Uuid) -> StorageResult<u64> {
+        let extent = self.metadata.read().unwrap().load_extent(extent_uuid)?;
+        let data = self.read_extent(*extent_uuid)?;
+        if !extent.verify_checksum(&data) {
+            return Err(checksum_failed(*extent_uuid));
+        }
+        let size = data.len() as u64;
+        self.data_cache.put(*extent_uuid, data, true);
+        Ok(size)
+    }
+    
+    /// Drop an extent from the data cache, returning whether it was cached
+    pub fn evict_cached_extent(&self, extent_uuid: &uuid::Uuid) -> bool {
+        let cached = self.data_cache.contains(extent_uuid);
+        self.data_cache.invalidate(extent_uuid);
+        cached
+    }
+    
+    /// Share of the data cache in use, from 0.0 to 1.0
+    pub fn cache_utilization(&self) -> f64 {
+        self.data_cache.utilization()
+    }
+    
+    /// Discard the free space of a raw block device disk, returning the bytes discarded
+    ///
+    /// Directory disks free their blocks in the host filesystem when fragment
+    /// files are deleted, so there is nothing to discard on them.
+    pub fn trim_free_space(&self, disk_uuid: uuid::Uuid) -> StorageResult<u64> {
+        if self.is_read_only() {
+            return Ok(0);
+        }
+        let disks = self.disks.read().unwrap();
+        let disk = disks
+            .iter()
+            .find(|d| d.lock().unwrap().uuid == disk_uuid)
+            .ok_or_else(|| StorageError::DiskUnavailable { disk: disk_uuid, detail: format!("disk {} is not in the pool", disk_uuid) })?;
+        let disk = disk.lock().unwrap();
+        match disk.on_device_allocator.as_ref() {
+            Some(oda) => Ok(oda.trim_free_units()?),
+            None => Ok(0),
+        }
+    }
+    
+    /// Move the fragments `should_move` picks onto disks `target_ok` accepts
+    ///
+    /// `should_move` sees the extent as moved so far, a fragment position and
+    /// the disk holding that fragment. Each
+    /// fragment goes to the emptiest accepted healthy disk not already holding
+    /// part of the extent, and is verified there before the extent record is
+    /// switched over; the old copies are deleted last. Fragments without such a
+    /// disk stay where they are. The metadata write lock is held throughout,
+    /// so rebuilds and writes of the extent wait, and extents a rebuild or
+    /// write already has in hand are skipped, as is everything on a read-only
+    /// engine. Returns the fragments and bytes moved.
+    fn move_extent_fragments(
+        &self,
+        extent_uuid: &uuid::Uuid,
+        should_move: impl Fn(&Extent, usize, &Disk) -> bool,
+        target_ok: impl Fn(&Disk) -> bool,
+    ) -> Result<(u64, u64)> {
+        if self.is_read_only() {
+            return Ok((0, 0));
+        }
+        // Defrag relocations stop at the high watermark; tiering demotions keep freeing the fast tiers
+        if self.write_priority() == WritePriority::Low {
+            self.check_write_watermark()?;
+        }
+        let metadata = self.metadata.write().unwrap();
+        let disks = self.disks.read().unwrap();
+        if self.in_flight.contains(extent_uuid) || self.rebuild_queue.is_tracked(extent_uuid) {
+            return Ok((0, 0));
+        }
+        let mut in_flight = self.in_flight.begin();
+        in_flight.add(*extent_uuid);
+        let Ok(mut extent) = metadata.load_extent(extent_uuid) else {
+            return Ok((0, 0));
+        };
+        if extent.is_transitioning()
+            || extent.rebuild_in_progress
+            || extent.fragment_locations.iter().any(|l| l.on_device.is_some())
+        {
+            return Ok((0, 0));
+        }
+        let find = |uuid: uuid::Uuid| disks.iter().find(|d| d.lock().unwrap().uuid == uuid).cloned();
+        let reserve_percent = self.space_reserve_percent();
+        
+        let mut moved: Vec<(uuid::Uuid, usize, Arc<Mutex<Disk>>)> = Vec::new();
+        let mut bytes = 0u64;
+        let result = (|| -> Result<()> {
+            for pos in 0..extent.fragment_locations.len() {
+                let location = extent.fragment_locations[pos].clone();
+                let Some(source) = find(location.disk_uuid) else { continue };
+                if !should_move(&extent, pos, &source.lock().unwrap()) {
+                    continue;
+                }
+                
+                let data = {
+                    let mut disk = source.lock().unwrap();
+                    let result = disk.read_fragment(extent_uuid, location.fragment_index);
+                    disk.track_io(&result);
+                    result?
+                };
+                if !location.verify_checksum(&data) {
+                    return Err(anyhow!("fragment {} fails its checksum on disk {}", location.fragment_index, location.disk_uuid));
+                }
+                
+                let holders: Vec<uuid::Uuid> = extent.fragment_locations.iter().map(|l| l.disk_uuid).collect();
+                let Some(target) = disks
+                    .iter()
+                    .filter(|d| {
+                        let disk = d.lock().unwrap();
+                        target_ok(&disk)
+                            && disk.health == DiskHealth::Healthy
+                            && !holders.contains(&disk.uuid)
+                            && crate::placement::writable_bytes(&disk, reserve_percent) >= data.len() as u64
+                    })
+                    .min_by(|a, b| a.lock().unwrap().utilization().total_cmp(&b.lock().unwrap().utilization()))
+                    .cloned()
+                else {
+                    continue;
+                };
+                
+                let mut disk = target.lock().unwrap();
+                let placement = {
+                    let result = disk.write_fragment(extent_uuid, location.fragment_index, &data);
+                    disk.track_io(&result);
+                    result?
+                };
+                let checksum = *blake3::hash(&data).as_bytes();
+                let copy_ok = disk
+                    .read_fragment(extent_uuid, location.fragment_index)
+                    .is_ok_and(|copy| *blake3::hash(&copy).as_bytes() == checksum);
+                if !copy_ok {
+                    disk.delete_fragment(extent_uuid, location.fragment_index).ok();
+                    return Err(anyhow!("copy on disk {} failed verification", disk.uuid));
+                }
+                extent.fragment_locations[pos] = FragmentLocation {
+                    disk_uuid: disk.uuid,
+                    fragment_index: location.fragment_index,
+                    on_device: placement,
+                    checksum: Some(checksum),
+                };
+                drop(disk);
+                moved.push((location.disk_uuid, location.fragment_index, Arc::clone(&target)));
+                bytes += data.len() as u64;
+            }
+            if !moved.is_empty() {
+                metadata.save_extent(&extent)?;
+            }
+            Ok(())
+        })();
+        
+        if let Err(e) = result {
+            // The record still points at the old copies
+            for (_, fragment_index, target) in &moved {
+                target.lock().unwrap().delete_fragment(extent_uuid, *fragment_index).ok();
+            }
+            return Err(e);
+        }
+        for (source_uuid, fragment_index, _) in &moved {
+            if let Some(source) = find(*source_uuid) {
+                source.lock().unwrap().delete_fragment(extent_uuid, *fragment_index).ok();
+            }
+        }
+        Ok((moved.len() as u64, bytes))
+    }
+}
+
+impl Drop for StorageEngine {
+    fn drop(&mut self) {
+        if let Some(orphan_gc) = self.orphan_gc.take() {
+            orphan_gc.stop();
+        }
+        if let Some(disk_probe) = self.disk_probe.take() {
+            disk_probe.stop();
+        }
+        if let Some(smart_poll) = self.smart_poll.take() {
+            smart_poll.stop();
+        }
+        if let Some(space_watch) = self.space_watch.take() {
+            space_watch.stop();
+        }
+        if let Some(access_flush) = self.access_flush.take() {
+            access_flush.stop();
+        }
+        if let Some(tiering) = self.tiering.take() {
+            tiering.stop();
+        }
+        if let Some(prefetcher) = self.prefetcher.take() {
+            self.prefetch_queue.shutdown();
+            prefetcher.join().ok();
+        }
+        
+        // Buffered data is written out before the engine goes away
+        if let Some(flusher) = self.buffer_flusher.take() {
+            self.write_buffer.shutdown();
+            flusher.join().ok();
+            if let Err(e) = self.write_buffer.flush_all(FlushCause::Explicit, |ino, run, cause| self.flush_run(ino, run, cause)) {
+                log::error!("Flushing write buffer on shutdown failed: {}", e);
+            }
+        }
+        
+        // Only the owning engine stops the worker; background handles share the queue
+        if let Some(worker) = self.rebuild_worker.take() {
+            self.rebuild_budget.shutdown();
+            if let Err(e) = self.flush_access_stats() {
+                log::error!("Flushing extent access statistics on shutdown failed: {}", e);
+            }
+            if let Err(e) = self.flush_inode_times() {
+                log::error!("Flushing inode timestamps on shutdown failed: {}", e);
+            }
+            self.rebuild_queue.shutdown();
+            worker.join().ok();
+        }
+    }
+}
+
+/// A decoded extent whose data does not match its checksum
+fn checksum_failed(extent: uuid::Uuid) -> StorageError {
+    StorageError::Corruption { extent, detail: format!("Checksum verification failed for extent {}", extent) }
 }
 
 impl crate::fs_interface::FilesystemInterface for StorageEngine {
+    // Inodes under `/.snapshots` are served from their snapshot's captured
+    // tree and refuse every change; see `crate::snapshots`.
+
     fn read_file(&self, ino: u64) -> Result<Vec<u8>> {
-        self.read_file(ino)
+        if snapshots::is_snapshot_ino(ino) {
+            let size = self.snapshot_view_inode(ino)?.size;
+            return Ok(self.read_snapshot_range(ino, 0, size)?);
+        }
+        Ok(self.read_file(ino)?)
     }
 
     fn write_file(&self, ino: u64, data: &[u8], offset: u64) -> Result<()> {
-        self.write_file(ino, data, offset)
+        Self::check_live(ino)?;
+        Ok(self.write_file(ino, data, offset)?)
+    }
+
+    fn create_file(&self, parent_ino: u64, name: FileName) -> Result<Inode> {
+        Self::check_live(parent_ino)?;
+        Ok(self.create_file(parent_ino, name)?)
+    }
+
+    fn create_dir(&self, parent_ino: u64, name: FileName) -> Result<Inode> {
+        Self::check_live(parent_ino)?;
+        Ok(self.create_dir(parent_ino, name)?)
+    }
+
+    fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> Result<()> {
+        if mask & permissions::WRITE != 0 {
+            Self::check_live(ino)?;
+        }
+        let inode = crate::fs_interface::FilesystemInterface::get_inode(self, ino)?;
+        permissions::check_access(&inode, ctx, mask)
     }
 
-    fn create_file(&self, parent_ino: u64, name: String) -> Result<Inode> {
-        self.create_file(parent_ino, name)
+    fn create_file_as(&self, ctx: &RequestContext, parent_ino: u64, name: FileName, mode: u32) -> Result<Inode> {
+        Self::check_live(parent_ino)?;
+        Ok(self.create_file_as(ctx, parent_ino, name, mode)?)
     }
 
-    fn create_dir(&self, parent_ino: u64, name: String) -> Result<Inode> {
-        self.create_dir(parent_ino, name)
+    fn create_dir_as(&self, ctx: &RequestContext, parent_ino: u64, name: FileName, mode: u32) -> Result<Inode> {
+        Self::check_live(parent_ino)?;
+        Ok(self.create_dir_as(ctx, parent_ino, name, mode)?)
     }
 
     fn delete_file(&self, ino: u64) -> Result<()> {
-        self.delete_file(ino)
+        Self::check_live(ino)?;
+        Ok(self.delete_file(ino)?)
+    }
+
+    fn orphan_file(&self, ino: u64) -> Result<()> {
+        Self::check_live(ino)?;
+        Ok(self.orphan_file(ino)?)
     }
 
     fn delete_dir(&self, ino: u64) -> Result<()> {
+        Self::check_live(ino)?;
         // For now, assume delete_file works for directories too
         // In a real implementation, we'd check if directory is empty
-        self.delete_file(ino)
+        Ok(self.delete_file(ino)?)
     }
 
     fn get_inode(&self, ino: u64) -> Result<Inode> {
-        self.get_inode(ino)
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(self.snapshot_view_inode(ino)?);
+        }
+        let mut inode = self.get_inode(ino)?;
+        if ino == 1 && !self.snapshots.infos()?.is_empty() {
+            // `.snapshots` is a subdirectory of the root while it is listed
+            inode.subdirs += 1;
+        }
+        Ok(inode)
     }
 
     fn list_directory(&self, parent_ino: u64) -> Result<Vec<Inode>> {
-        self.list_directory(parent_ino)
+        if snapshots::is_snapshot_ino(parent_ino) {
+            return Ok(self.snapshot_view_children(parent_ino)?);
+        }
+        let mut children = self.list_directory(parent_ino)?;
+        if parent_ino == 1 {
+            // The view shadows an entry of the same name made before it existed
+            children.retain(|child| child.name != SNAPSHOTS_DIR_NAME);
+        }
+        // Lookups always find `.snapshots`, but it is only listed once there is something in it
+        if parent_ino == 1 && !self.snapshots.infos()?.is_empty() {
+            children.push(self.snapshot_view_inode(SNAPSHOTS_DIR_INO)?);
+        }
+        Ok(children)
     }
 
-    fn find_child(&self, parent_ino: u64, name: &str) -> Result<Option<Inode>> {
-        self.find_child(parent_ino, name)
+    fn find_child(&self, parent_ino: u64, name: &[u8]) -> Result<Option<Inode>> {
+        if snapshots::is_snapshot_ino(parent_ino) {
+            return Ok(self.snapshot_view_child(parent_ino, name)?);
+        }
+        if parent_ino == 1 && name == SNAPSHOTS_DIR_NAME.as_bytes() {
+            return Ok(self.snapshot_view_inode(SNAPSHOTS_DIR_INO).map(Some)?);
+        }
+        Ok(self.find_child(parent_ino, name)?)
     }
 
     fn update_inode(&self, inode: &Inode) -> Result<()> {
-        self.update_inode(inode)
+        Self::check_live(inode.ino)?;
+        Self::check_live(inode.parent_ino)?;
+        Ok(self.update_inode(inode)?)
+    }
+
+    fn get_redundancy(&self, ino: u64) -> Result<Option<RedundancyPolicy>> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(None);
+        }
+        Ok(self.get_file_redundancy(ino)?)
+    }
+
+    fn set_redundancy(&self, ino: u64, policy: RedundancyPolicy) -> Result<()> {
+        Self::check_live(ino)?;
+        Ok(self.set_file_redundancy(ino, policy)?)
+    }
+
+    fn sync_inode(&self, ino: u64) -> Result<()> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(());
+        }
+        Ok(self.sync_inode(ino)?)
+    }
+
+    fn read_range(&self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(self.read_snapshot_range(ino, offset, size)?);
+        }
+        Ok(self.read_range(ino, offset, size)?)
+    }
+
+    fn read_range_uncached(&self, ino: u64, offset: u64, size: u64) -> Result<Vec<u8>> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(self.read_snapshot_range(ino, offset, size)?);
+        }
+        Ok(self.read_range_uncached(ino, offset, size)?)
+    }
+
+    fn punch_hole(&self, ino: u64, offset: u64, length: u64) -> Result<()> {
+        Self::check_live(ino)?;
+        Ok(self.punch_hole(ino, offset, length)?)
+    }
+
+    fn zero_range(&self, ino: u64, offset: u64, length: u64, keep_size: bool) -> Result<()> {
+        Self::check_live(ino)?;
+        Ok(self.zero_range(ino, offset, length, keep_size)?)
+    }
+
+    fn clone_file(&self, src_ino: u64, dst_ino: u64) -> Result<()> {
+        Self::check_live(src_ino)?;
+        Self::check_live(dst_ino)?;
+        Ok(self.clone_file(src_ino, dst_ino)?)
+    }
+
+    fn buffered_write(&self, ino: u64, offset: u64, data: &[u8]) -> Result<()> {
+        Self::check_live(ino)?;
+        Ok(self.buffered_write(ino, offset, data)?)
+    }
+
+    fn append_write(&self, ino: u64, data: &[u8]) -> Result<u64> {
+        Self::check_live(ino)?;
+        Ok(self.append_write(ino, data)?)
+    }
+
+    fn flush_file(&self, ino: u64) -> Result<()> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(());
+        }
+        Ok(self.flush_file(ino)?)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(self.sync_all()?)
+    }
+
+    fn metrics(&self) -> Option<Arc<Metrics>> {
+        Some(self.metrics())
+    }
+
+    fn prefetch(&self, ino: u64, offset: u64, extents: usize) {
+        if !snapshots::is_snapshot_ino(ino) {
+            self.prefetch(ino, offset, extents)
+        }
+    }
+
+    fn cancel_prefetch(&self, ino: u64) {
+        self.cancel_prefetch(ino)
+    }
+
+    fn recycle_read_buffer(&self, buf: Vec<u8>) {
+        self.recycle_read_buffer(buf)
+    }
+
+    fn allocated_size(&self, ino: u64) -> Result<u64> {
+        if snapshots::is_snapshot_ino(ino) {
+            let (id, captured) = snapshots::split_snapshot_ino(ino);
+            let Some(snapshot) = self.snapshots.get(id)? else { return Ok(0) };
+            let size = snapshot.tree.inode(captured).map_or(0, |inode| inode.size);
+            return Ok(snapshot.tree.extent_map(captured).map_or(0, |map| map.allocated_bytes(size)));
+        }
+        Ok(self.allocated_size(ino)?)
+    }
+
+    fn describe_layout(&self, ino: u64) -> Result<Option<FileLayout>> {
+        if snapshots::is_snapshot_ino(ino) {
+            return Ok(None);
+        }
+        Ok(self.describe_layout(ino).map(Some)?)
     }
 
     fn stat(&self) -> Result<crate::fs_interface::FilesystemStats> {
-        // For now, return basic stats. In a full implementation, we'd track these metrics.
-        // This is a simplified version that doesn't scan all metadata.
+        // Failed disks contribute nothing; only healthy disks accept new fragments,
+        // so only their free space is counted as free.
         let disks = self.disks.read().unwrap();
-        let total_space: u64 = disks.iter().map(|d| d.lock().unwrap().capacity_bytes).sum();
-        let used_space: u64 = disks.iter().map(|d| d.lock().unwrap().used_bytes).sum();
+        let mut raw_used = 0u64;
+        let mut raw_free = 0u64;
+        for disk_arc in disks.iter() {
+            let disk = disk_arc.lock().unwrap();
+            if disk.health == crate::disk::DiskHealth::Failed {
+                continue;
+            }
+            raw_used = raw_used.saturating_add(disk.used_bytes);
+            if disk.health == crate::disk::DiskHealth::Healthy {
+                raw_free = raw_free.saturating_add(crate::placement::writable_bytes(&disk, self.space_reserve_percent()));
+            }
+        }
+        drop(disks);
+        // Space held by writes in progress and the reserve are not free to userspace
+        let raw_free = raw_free.saturating_sub(self.space_reservations.reserved());
 
-        // Access metadata manager for counting
-        let metadata_w = self.metadata.write().unwrap();
+        // Report usable bytes: scale raw space by the overhead of the small-file
+        // default policy (replication:3), the most expensive policy write_file picks.
+        let overhead = STATFS_REDUNDANCY_POLICY.storage_overhead();
+        let used_space = (raw_used as f64 / overhead) as u64;
+        let free_space = (raw_free as f64 / overhead) as u64;
+
+        // Counted as inodes are saved and deleted, so statfs never walks the inode table
+        let totals = self.metadata.read().unwrap().inode_totals();
 
         Ok(crate::fs_interface::FilesystemStats {
-            // Count files and directories by scanning inode_table persisted entries
-            total_files: {
-                let mut count = 0u64;
-                for ino in metadata_w.inode_table.list_keys() {
-                    if let Some(inode) = metadata_w.inode_table.get(&ino) {
-                        if inode.file_type == crate::metadata::FileType::RegularFile { count += 1; }
-                    }
-                }
-                count
-            },
-            total_dirs: {
-                let mut count = 0u64;
-                for ino in metadata_w.inode_table.list_keys() {
-                    if let Some(inode) = metadata_w.inode_table.get(&ino) {
-                        if inode.file_type == crate::metadata::FileType::Directory { count += 1; }
-                    }
-                }
-                if count == 0 { 1 } else { count }
-            },
-            total_size: {
-                let mut total = 0u64;
-                for ino in metadata_w.inode_table.list_keys() {
-                    if let Some(inode) = metadata_w.inode_table.get(&ino) {
-                        total = total.saturating_add(inode.size);
-                    }
-                }
-                total
-            },
+            total_files: totals.files,
+            total_dirs: totals.dirs,
+            total_size: totals.logical_bytes,
             used_space,
-            free_space: total_space - used_space,
+            free_space,
         })
     }
 }
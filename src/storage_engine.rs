@@ -14,10 +14,13 @@ mod tests {
     use crate::storage::StorageEngine;
     use crate::metadata::MetadataManager;
     use crate::disk::Disk;
+    use crate::error::{StorageError, StorageResult};
+    use crate::test_utils::{test_metadata_backend, test_pool_dir};
     use tempfile::TempDir;
+    use std::time::{Duration, Instant};
 
     fn setup_test_storage() -> (TempDir, Vec<TempDir>, Box<dyn FilesystemInterface + Send + Sync>) {
-        let pool_dir = tempfile::tempdir().unwrap();
+        let pool_dir = test_pool_dir();
 
         // Create test disks
         let disk_dirs: Vec<TempDir> = (0..3)
@@ -40,11 +43,11 @@ mod tests {
         let (_pool_dir, _disk_dirs, storage) = setup_test_storage();
 
         // Create a directory
-        let root_dir = storage.create_dir(1, "test_dir".to_string()).unwrap();
+        let root_dir = storage.create_dir(1, "test_dir".into()).unwrap();
         assert_eq!(root_dir.name, "test_dir");
 
         // Create a file in the directory
-        let file = storage.create_file(root_dir.ino, "test.txt".to_string()).unwrap();
+        let file = storage.create_file(root_dir.ino, "test.txt".into()).unwrap();
         assert_eq!(file.name, "test.txt");
 
         // Write to the file
@@ -56,7 +59,7 @@ mod tests {
         assert_eq!(read_data, data);
 
         // Find the file by name
-        let found = storage.find_child(root_dir.ino, "test.txt").unwrap();
+        let found = storage.find_child(root_dir.ino, b"test.txt").unwrap();
         assert!(found.is_some());
         assert_eq!(found.unwrap().ino, file.ino);
 
@@ -74,7 +77,7 @@ mod tests {
         storage.delete_file(file.ino).unwrap();
 
         // Verify file is gone
-        let found_after_delete = storage.find_child(root_dir.ino, "test.txt").unwrap();
+        let found_after_delete = storage.find_child(root_dir.ino, b"test.txt").unwrap();
         assert!(found_after_delete.is_none());
     }
 
@@ -83,11 +86,11 @@ mod tests {
         let (_pool_dir, _disk_dirs, storage) = setup_test_storage();
 
         // Create some files and directories
-        let dir1 = storage.create_dir(1, "dir1".to_string()).unwrap();
-        let _dir2 = storage.create_dir(1, "dir2".to_string()).unwrap();
+        let dir1 = storage.create_dir(1, "dir1".into()).unwrap();
+        let _dir2 = storage.create_dir(1, "dir2".into()).unwrap();
 
-        let file1 = storage.create_file(dir1.ino, "file1.txt".to_string()).unwrap();
-        let file2 = storage.create_file(dir1.ino, "file2.txt".to_string()).unwrap();
+        let file1 = storage.create_file(dir1.ino, "file1.txt".into()).unwrap();
+        let file2 = storage.create_file(dir1.ino, "file2.txt".into()).unwrap();
 
         let data1 = b"Short";
         let data2 = b"Longer content here";
@@ -105,4 +108,3537 @@ mod tests {
         assert!(stats.total_size >= data1.len() as u64 + data2.len() as u64);
         assert!(stats.free_space > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_filesystem_stats_usable_space_excludes_failed_disks() {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| Disk::new(td.path().to_path_buf()).unwrap())
+            .collect();
+        for disk in disks.iter_mut() {
+            disk.capacity_bytes = 3 * 1024 * 1024;
+            disk.used_bytes = 0;
+        }
+        disks[2].health = crate::disk::DiskHealth::Failed;
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        storage.set_space_reserve_percent(0);
+        let stats = storage.stat().unwrap();
+
+        // Two live disks of 3 MiB raw under replication:3 overhead
+        assert_eq!(stats.used_space, 0);
+        assert_eq!(stats.free_space, 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_redundancy_control_via_interface() {
+        let (_pool_dir, _disk_dirs, storage) = setup_test_storage();
+
+        let file = storage.create_file(1, "policy.txt".into()).unwrap();
+        let data = b"redundancy controlled content";
+        storage.write_file(file.ino, data, 0).unwrap();
+        assert_eq!(
+            storage.get_redundancy(file.ino).unwrap(),
+            Some(crate::extent::RedundancyPolicy::Replication { copies: 3 })
+        );
+
+        let policy: crate::extent::RedundancyPolicy = "replication:2".parse().unwrap();
+        storage.set_redundancy(file.ino, policy).unwrap();
+        assert_eq!(storage.get_redundancy(file.ino).unwrap(), Some(policy));
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        assert_eq!(storage.get_redundancy(file.ino).unwrap(), Some(policy));
+
+        // Later rewrites keep the requested policy
+        storage.write_file(file.ino, b"rewritten", 0).unwrap();
+        assert_eq!(storage.get_redundancy(file.ino).unwrap(), Some(policy));
+
+        // erasure:6+3 needs 9 healthy disks; only 3 exist
+        let wide: crate::extent::RedundancyPolicy = "erasure:6+3".parse().unwrap();
+        let err = storage.set_redundancy(file.ino, wide).unwrap_err();
+        assert!(matches!(StorageError::find(&err), Some(StorageError::NoSpace(_))), "{:#}", err);
+        assert_eq!(storage.get_redundancy(file.ino).unwrap(), Some(policy));
+
+        assert!("replication:0".parse::<crate::extent::RedundancyPolicy>().is_err());
+        assert!("erasure:6".parse::<crate::extent::RedundancyPolicy>().is_err());
+        assert!("mirror".parse::<crate::extent::RedundancyPolicy>().is_err());
+        assert_eq!(wide.to_string(), "erasure:6+3");
+    }
+
+    #[test]
+    fn test_degraded_read_queues_background_rebuild() {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| Disk::new(td.path().to_path_buf()).unwrap())
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+
+        let file = storage.create_file(1, "degraded.txt".to_string()).unwrap();
+        let data = b"degraded extent content".to_vec();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        // Remove one replica behind the engine's back
+        let extent = storage.metadata().read().unwrap().list_all_extents().unwrap().remove(0);
+        let lost = extent.fragment_locations[0].clone();
+        let disk = storage.get_disks().into_iter().find(|d| d.uuid == lost.disk_uuid).unwrap();
+        std::fs::remove_file(disk.fragment_path(&extent.uuid, lost.fragment_index)).unwrap();
+
+        // The read succeeds from the surviving replicas and only queues the repair
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        storage.wait_for_rebuilds();
+
+        let snapshot = storage.metrics().snapshot();
+        assert_eq!(snapshot.rebuilds_successful, 1);
+        assert_eq!(snapshot.rebuild_queue_depth, 0);
+
+        let rebuilt = storage.metadata().read().unwrap().load_extent(&extent.uuid).unwrap();
+        let disks = storage.get_disks();
+        for location in &rebuilt.fragment_locations {
+            let disk = disks.iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+            assert!(disk.fragment_path(&rebuilt.uuid, location.fragment_index).exists());
+        }
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+    }
+
+    fn setup_storage_with_disks(count: usize) -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..count).map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| Disk::new(td.path().to_path_buf()).unwrap())
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        (pool_dir, disk_dirs, StorageEngine::new(metadata, disks))
+    }
+
+    fn setup_buffered_storage(
+        config: crate::write_optimizer::WriteBufferConfig,
+    ) -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..6).map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| Disk::new(td.path().to_path_buf()).unwrap())
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let metrics = std::sync::Arc::new(crate::metrics::Metrics::new());
+        (pool_dir, disk_dirs, StorageEngine::with_write_buffer(metadata, disks, metrics, config))
+    }
+
+    /// Delete the on-disk fragment with the given index and return its disk
+    fn remove_fragment(storage: &StorageEngine, extent: &crate::extent::Extent, fragment_index: usize) -> uuid::Uuid {
+        let location = extent
+            .fragment_locations
+            .iter()
+            .find(|l| l.fragment_index == fragment_index)
+            .unwrap();
+        let disk = storage.get_disks().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+        std::fs::remove_file(disk.fragment_path(&extent.uuid, fragment_index)).unwrap();
+        disk.uuid
+    }
+
+    /// Flip a byte of the on-disk fragment with the given index and return its disk
+    fn corrupt_fragment(storage: &StorageEngine, extent: &crate::extent::Extent, fragment_index: usize) -> uuid::Uuid {
+        let location = extent
+            .fragment_locations
+            .iter()
+            .find(|l| l.fragment_index == fragment_index)
+            .unwrap();
+        let disk = storage.get_disks().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+        let path = disk.fragment_path(&extent.uuid, fragment_index);
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[17] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+        disk.uuid
+    }
+
+    /// Whether every fragment of an extent is on disk and matches its checksum
+    fn fragments_intact(storage: &StorageEngine, extent_uuid: &uuid::Uuid) -> bool {
+        let extent = storage.metadata().read().unwrap().load_extent(extent_uuid).unwrap();
+        let disks = storage.get_disks();
+        extent.fragment_locations.len() == extent.redundancy.fragment_count()
+            && extent.fragment_locations.iter().all(|location| {
+                let disk = disks.iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+                disk.read_fragment(&extent.uuid, location.fragment_index)
+                    .is_ok_and(|data| location.checksum.is_some() && location.verify_checksum(&data))
+            })
+    }
+
+    #[test]
+    fn test_corrupt_fragment_is_quarantined_and_rebuilt_on_read() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+
+        // Pin the policy so the read does not migrate the extent instead of rebuilding it
+        let file = storage.create_file(1, "ec.bin".to_string()).unwrap();
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        storage.set_file_redundancy(file.ino, policy).unwrap();
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 249) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        // Data shards are always read, so the flipped byte is seen
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        let bad_disk = corrupt_fragment(&storage, &extent, 0);
+
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        storage.wait_for_rebuilds();
+
+        let snapshot = storage.metrics().snapshot();
+        assert_eq!(snapshot.fragment_checksum_failures, 1);
+        assert_eq!(snapshot.rebuilds_successful, 1);
+        assert!(fragments_intact(&storage, &extent.uuid));
+
+        let disk = storage.get_disks().into_iter().find(|d| d.uuid == bad_disk).unwrap();
+        assert_eq!(disk.corruption_count, 1);
+        assert_eq!(disk.health, crate::disk::DiskHealth::Healthy);
+        assert!(disk.path.join("quarantine").join(format!("{}-0.frag", extent.uuid)).exists());
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+    }
+
+    #[test]
+    fn test_recent_writes_are_verified_once_settled_and_corruption_is_repaired() {
+        use crate::recent_writes::RecentWriteQueue;
+        use crate::scrubber::{ScrubConfig, ScrubPassProgress, Scrubber};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 241) as u8).collect();
+        let write = |name: &str| {
+            let file = storage.create_file(1, name.to_string()).unwrap();
+            storage.set_file_redundancy(file.ino, policy).unwrap();
+            storage.write_file(file.ino, &data, 0).unwrap();
+            let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+            storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap()
+        };
+
+        // Queued on placement, and left alone until it has settled
+        let healthy = write("healthy.bin");
+        assert_eq!(storage.recent_write_status(Duration::ZERO).queued, 1);
+        assert_eq!(storage.verify_recent_writes(Duration::from_secs(3600), 16).unwrap().verified(), 0);
+        let pass = storage.verify_recent_writes(Duration::ZERO, 16).unwrap();
+        assert_eq!((pass.healthy, pass.repairs_queued), (1, 0));
+        assert_eq!(storage.recent_write_status(Duration::ZERO).queued, 0);
+
+        // A corrupt fragment takes the repair path, and the rebuilt extent is queued again
+        let damaged = write("damaged.bin");
+        let bad_disk = corrupt_fragment(&storage, &damaged, 0);
+        let pass = storage.verify_recent_writes(Duration::ZERO, 16).unwrap();
+        assert_eq!((pass.healthy, pass.repairs_queued), (0, 1));
+        storage.wait_for_rebuilds();
+        assert!(fragments_intact(&storage, &damaged.uuid));
+        let disk = storage.get_disks().into_iter().find(|d| d.uuid == bad_disk).unwrap();
+        assert_eq!(disk.corruption_count, 1);
+        let snapshot = storage.metrics().snapshot();
+        assert_eq!((snapshot.recent_writes_verified, snapshot.recent_write_failures), (2, 1));
+
+        // The queue survives a restart, and a full scrub leaves its extents out
+        let reopened = RecentWriteQueue::open(pool_dir.path(), 16).unwrap();
+        assert_eq!(reopened.extents(), vec![damaged.uuid]);
+        let config = ScrubConfig { workers: 2, max_bytes_per_sec: None, repair: false, skip: reopened.extents().into_iter().collect() };
+        let progress = ScrubPassProgress::default();
+        let results = Scrubber::new(pool_dir.path().to_path_buf())
+            .scrub_pool(&storage.metadata().read().unwrap(), &mut storage.get_disks(), &config, &progress)
+            .unwrap();
+        assert_eq!(results.iter().map(|r| r.extent_uuid).collect::<Vec<_>>(), vec![healthy.uuid]);
+        assert_eq!(storage.verify_recent_writes(Duration::ZERO, 16).unwrap().healthy, 1);
+        assert!(RecentWriteQueue::open(pool_dir.path(), 16).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rebuilds_are_paced_by_the_budget_and_limits_change_over_the_control_socket() {
+        use crate::control::{request, ControlReply, ControlRequest, ControlServer};
+        use crate::rebuild_budget::RebuildLimits;
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let file = storage.create_file(1, "budget.bin".to_string()).unwrap();
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        storage.set_file_redundancy(file.ino, policy).unwrap();
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 239) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        corrupt_fragment(&storage, &extent, 0);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        storage.wait_for_rebuilds();
+        let status = storage.rebuild_status();
+        assert_eq!(status.running, 0);
+        assert_eq!(status.throughput_bytes_per_sec, extent.size as u64 / 10);
+        let metrics = storage.metrics().snapshot();
+        assert_eq!(metrics.rebuild_throughput_bytes_per_sec, status.throughput_bytes_per_sec);
+
+        let socket = pool_dir.path().join(crate::control::CONTROL_SOCKET);
+        let server = ControlServer::start(&socket, storage.background_handle(), None).unwrap();
+        let limits = RebuildLimits { max_concurrent: 2, max_bytes_per_sec: 50 * 1024 * 1024, ..Default::default() };
+        match request(&socket, &ControlRequest::SetRebuildLimits { limits }).unwrap() {
+            ControlReply::RebuildStatus { status } => assert_eq!(status.limits, limits),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(storage.rebuild_status().limits, limits);
+        let refused = RebuildLimits { idle_max_concurrent: 0, ..limits };
+        assert!(matches!(
+            request(&socket, &ControlRequest::SetRebuildLimits { limits: refused }).unwrap(),
+            ControlReply::Error { .. }
+        ));
+        server.stop();
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_live_config_keys_apply_over_the_control_socket() {
+        use crate::config::{config_key, set_config_value, LiveSettings, PoolConfig};
+        use crate::control::{request_mounted, ControlReply, ControlRequest, ControlServer};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let mut pool = crate::disk::DiskPool::new();
+        let mut config = PoolConfig::default();
+        for (key, value) in [
+            ("verify_writes", "on"),
+            ("space_reserve_percent", "25"),
+            ("space.high_watermark", "80"),
+            ("rebuild.max_concurrent", "3"),
+        ] {
+            set_config_value(&mut pool, &mut config, config_key(key).unwrap(), value).unwrap();
+        }
+        let settings = LiveSettings::from_pool(&pool);
+        assert!(request_mounted(pool_dir.path(), &ControlRequest::ApplySettings { settings }).is_none());
+
+        let socket = pool_dir.path().join(crate::control::CONTROL_SOCKET);
+        let server = ControlServer::start(&socket, storage.background_handle(), None).unwrap();
+        match request_mounted(pool_dir.path(), &ControlRequest::ApplySettings { settings }) {
+            Some(ControlReply::SettingsApplied { settings: applied }) => assert_eq!(applied, settings),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(storage.verify_writes());
+        assert_eq!(storage.space_reserve_percent(), 25);
+        assert_eq!(storage.space_watermarks().high_percent, 80);
+        assert_eq!(storage.rebuild_status().limits.max_concurrent, 3);
+        server.stop();
+    }
+
+    #[test]
+    fn test_activity_snapshot_reports_disk_io_and_hot_inodes_over_the_control_socket() {
+        use crate::activity::ActivityReport;
+        use crate::control::{request_activity, ControlServer};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let socket = pool_dir.path().join(crate::control::CONTROL_SOCKET);
+        let server = ControlServer::start(&socket, storage.background_handle(), None).unwrap();
+        let before = request_activity(&socket, 5, 10).unwrap();
+        assert!(before.hot_inodes.is_empty());
+
+        let busy = storage.create_file(1, "busy.bin".to_string()).unwrap();
+        let quiet = storage.create_file(1, "quiet.bin".to_string()).unwrap();
+        storage.write_file(busy.ino, &[7u8; 8192], 0).unwrap();
+        storage.read_file(busy.ino).unwrap();
+        storage.read_range(busy.ino, 0, 4096).unwrap();
+        storage.write_file(quiet.ino, b"once", 0).unwrap();
+
+        let after = request_activity(&socket, 5, 1).unwrap();
+        assert_eq!((after.counters.writes, after.counters.reads), (2, 2));
+        assert_eq!(after.hot_inodes.len(), 1);
+        assert_eq!(after.hot_inodes[0].path, "/busy.bin");
+        assert_eq!((after.hot_inodes[0].ops, after.hot_inodes[0].bytes), (3, 8192 * 2 + 4096));
+        // Every fragment written and read is charged to its disk
+        let written: u64 = after.disks.iter().map(|d| d.io.write_bytes).sum();
+        assert!(written >= 8192 + 4);
+        assert!(after.disks.iter().any(|d| d.io.reads > 0));
+
+        let report = ActivityReport::between(&before, &after);
+        assert!(report.writes_per_sec > 0.0);
+        assert_eq!(report.disks.len(), 3);
+        server.stop();
+    }
+
+    #[test]
+    fn test_defragment_spreads_fragments_sharing_a_disk_and_the_mount_serves_analysis() {
+        use crate::control::{request, ControlReply, ControlRequest, ControlServer};
+        use crate::defrag::{DefragConfig, DefragmentationEngine};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(7);
+        let file = storage.create_file(1, "fragmented.bin".to_string()).unwrap();
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        storage.set_file_redundancy(file.ino, policy).unwrap();
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 233) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+
+        // Move the second fragment onto the disk holding the first
+        let mut extent = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap();
+        let disks = storage.get_disks();
+        let find = |uuid: uuid::Uuid| disks.iter().find(|d| d.uuid == uuid).unwrap();
+        let (shared, moved) = (extent.fragment_locations[0].disk_uuid, extent.fragment_locations[1].clone());
+        let from = find(moved.disk_uuid).fragment_path(&extent_uuid, moved.fragment_index);
+        let to = find(shared).fragment_path(&extent_uuid, moved.fragment_index);
+        std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+        std::fs::rename(&from, &to).unwrap();
+        extent.fragment_locations[1].disk_uuid = shared;
+        storage.metadata().read().unwrap().save_extent(&extent).unwrap();
+
+        let socket = pool_dir.path().join(crate::control::CONTROL_SOCKET);
+        let engine = std::sync::Arc::new(DefragmentationEngine::new(DefragConfig::default()));
+        let server = ControlServer::start(&socket, storage.background_handle(), Some(engine.clone())).unwrap();
+        let analyze = || match request(&socket, &ControlRequest::DefragAnalyze).unwrap() {
+            ControlReply::DefragAnalysis { analysis } => analysis,
+            other => panic!("unexpected reply {:?}", other),
+        };
+        assert_eq!(analyze().fragmented_extents, 1);
+
+        let refused = DefragConfig { fragmentation_threshold: 2.0, ..DefragConfig::default() };
+        assert!(matches!(
+            request(&socket, &ControlRequest::SetDefragConfig { config: refused }).unwrap(),
+            ControlReply::Error { .. }
+        ));
+        let enabled = DefragConfig { enabled: true, schedule: Some("02:00-05:00".parse().unwrap()), ..DefragConfig::default() };
+        match request(&socket, &ControlRequest::SetDefragConfig { config: enabled }).unwrap() {
+            ControlReply::DefragStatus { config, .. } => assert!(config.enabled),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert_eq!(engine.config().schedule.unwrap().to_string(), "02:00-05:00");
+
+        let (fragments, bytes) = storage.defragment_extent(&extent_uuid).unwrap();
+        assert_eq!(fragments, 1);
+        assert!(bytes > 0);
+        let spread = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap();
+        let holders: std::collections::HashSet<_> = spread.fragment_locations.iter().map(|l| l.disk_uuid).collect();
+        assert_eq!(holders.len(), 6);
+        assert!(!to.exists());
+        assert!(fragments_intact(&storage, &extent_uuid));
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        assert_eq!(storage.defragment_extent(&extent_uuid).unwrap(), (0, 0));
+        assert_eq!(analyze().fragmented_extents, 0);
+        server.stop();
+    }
+
+    #[test]
+    fn test_disk_usage_counters_follow_fragments_and_a_recount_fixes_drift() {
+        use crate::logging::EventKind;
+
+        let (pool_dir, disk_dirs, storage) = setup_storage_with_disks(6);
+        let big = storage.create_file(1, "big.bin".to_string()).unwrap();
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        storage.set_file_redundancy(big.ino, policy).unwrap();
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 233) as u8).collect();
+        storage.write_file(big.ino, &data, 0).unwrap();
+        let small = storage.create_file(1, "small.txt".to_string()).unwrap();
+        storage.write_file(small.ino, b"short lived", 0).unwrap();
+        storage.delete_file(small.ino).unwrap();
+
+        // A quarantined fragment and its rebuilt replacement are counted too
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(big.ino).unwrap();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        corrupt_fragment(&storage, &extent, 0);
+        assert_eq!(storage.read_file(big.ino).unwrap(), data);
+        storage.wait_for_rebuilds();
+        for disk in storage.get_disks() {
+            assert_eq!(disk.usage(), disk.recalculate_usage().unwrap());
+        }
+        assert_eq!(storage.get_disks().iter().map(|d| d.fragment_count).sum::<u64>(), 6);
+        assert!(storage.check_disk_usage().is_empty());
+        drop(storage);
+
+        // Counters that lost track of a fragment, e.g. written by an older version
+        let mut drifted = Disk::load(disk_dirs[0].path()).unwrap();
+        let actual = drifted.usage();
+        drifted.fragment_count += 2;
+        drifted.used_bytes += 3 * 1024 * 1024;
+        drifted.save().unwrap();
+
+        let disks: Vec<Disk> = disk_dirs.iter().map(|td| Disk::load(td.path()).unwrap()).collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        let corrections = storage.check_disk_usage();
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].disk_uuid, drifted.uuid);
+        assert_eq!(corrections[0].after, actual);
+        assert_eq!(Disk::load(disk_dirs[0].path()).unwrap().usage(), actual);
+        let events = storage.events().since(0);
+        assert_eq!(events.iter().filter(|e| e.kind == EventKind::UsageCorrected).count(), 1);
+        assert!(storage.check_disk_usage().is_empty());
+
+        // A temporary file left by a crash takes space but is not a fragment
+        let mut disk = Disk::load(disk_dirs[1].path()).unwrap();
+        let before = disk.usage();
+        std::fs::write(disk.path.join("fragments").join("leftover.frag.tmp"), [0u8; 100]).unwrap();
+        let correction = disk.recount_usage().unwrap().unwrap();
+        assert_eq!(correction.after, crate::disk::FragmentUsage { fragments: before.fragments, bytes: before.bytes + 100 });
+        assert_eq!(disk.recount_usage().unwrap(), None);
+    }
+
+    #[test]
+    fn test_orphaned_files_survive_until_mount_and_lost_allocator_bumps_generation() {
+        use crate::metadata::ORPHAN_PARENT_INO;
+
+        let (pool_dir, disk_dirs, storage) = setup_storage_with_disks(6);
+        let kept = storage.create_file(1, "kept.bin".to_string()).unwrap();
+        let open = storage.create_file(1, "open.bin".to_string()).unwrap();
+        storage.write_file(open.ino, b"in use", 0).unwrap();
+        storage.orphan_file(open.ino).unwrap();
+        assert!(storage.find_child(1, "open.bin").unwrap().is_none());
+        assert_eq!(storage.read_file(open.ino).unwrap(), b"in use");
+        assert_eq!(storage.list_directory(ORPHAN_PARENT_INO).unwrap().len(), 1);
+        assert_eq!(storage.metadata().read().unwrap().generation(), 0);
+        drop(storage);
+
+        // Lose the allocator state: numbers continue past every live inode under a new generation
+        std::fs::remove_file(pool_dir.path().join("metadata").join("next_ino")).unwrap();
+        let disks: Vec<Disk> = disk_dirs.iter().map(|td| Disk::load(td.path()).unwrap()).collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        assert_eq!(storage.delete_orphans().unwrap(), 1);
+        assert!(storage.get_inode(open.ino).is_err());
+        assert_eq!(storage.delete_orphans().unwrap(), 0);
+
+        let fresh = storage.create_file(1, "fresh.bin".to_string()).unwrap();
+        assert!(fresh.ino > open.ino && fresh.ino != kept.ino);
+        assert_eq!(fresh.generation, 1);
+        assert_eq!(storage.get_inode(kept.ino).unwrap().generation, 0);
+    }
+
+    #[test]
+    fn test_storage_events_are_recorded_and_served_on_the_event_socket() {
+        use crate::logging::{request_events, EventKind, EventQuery, EventServer};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let file = storage.create_file(1, "events.bin".to_string()).unwrap();
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        storage.set_file_redundancy(file.ino, policy).unwrap();
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 241) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        let bad_disk = corrupt_fragment(&storage, &extent, 0);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        storage.wait_for_rebuilds();
+        let too_wide = crate::extent::RedundancyPolicy::Replication { copies: 7 };
+        assert!(storage.set_file_redundancy(file.ino, too_wide).is_err());
+
+        let events = storage.events().since(0);
+        let kinds: Vec<EventKind> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::ChecksumFailure,
+                EventKind::DegradedRead,
+                EventKind::RebuildStarted,
+                EventKind::RebuildFinished,
+                EventKind::NoSpace,
+            ]
+        );
+        assert_eq!(events[0].disk_uuid, Some(bad_disk));
+        assert!(events[..4].iter().all(|e| e.extent_uuid == Some(extent.uuid)));
+        assert!(events.windows(2).all(|w| w[1].seq == w[0].seq + 1));
+
+        let socket = pool_dir.path().join(crate::logging::EVENTS_SOCKET);
+        let server = EventServer::start(&socket, storage.events()).unwrap();
+        let query = EventQuery { follow: false, kind: Some(EventKind::DegradedRead) };
+        let served: Vec<_> = request_events(&socket, &query).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(served.len(), 1);
+        assert_eq!(served[0].seq, events[1].seq);
+
+        // A follower sees the backlog, then events recorded after it connected
+        let query = EventQuery { follow: true, kind: Some(EventKind::DiskHealth) };
+        let mut follower = request_events(&socket, &query).unwrap();
+        let mut disk = storage.get_disks().into_iter().find(|d| d.uuid == bad_disk).unwrap();
+        disk.mark_suspect().unwrap();
+        let followed = follower.next().unwrap().unwrap();
+        assert_eq!(followed.disk_uuid, Some(bad_disk));
+        assert!(followed.message.contains("Healthy -> Suspect"), "{}", followed.message);
+
+        server.stop();
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_replicated_reads_use_the_fastest_replica_and_fall_back_to_slower_ones() {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut disks: Vec<Disk> = disk_dirs.iter().map(|td| Disk::new(td.path().to_path_buf()).unwrap()).collect();
+        let delay = Duration::from_millis(200);
+        disks[1].read_delay = Some(delay);
+        let slow_uuid = disks[1].uuid;
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        let slow = storage.get_disks().into_iter().find(|d| d.uuid == slow_uuid).unwrap();
+
+        let file = storage.create_file(1, "replicated.bin".to_string()).unwrap();
+        storage.set_file_redundancy(file.ino, crate::extent::RedundancyPolicy::Replication { copies: 2 }).unwrap();
+        let data: Vec<u8> = (0..256 * 1024).map(|i| (i % 251) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        // The first read teaches the engine how fast the disk it used is
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        let slow_reads = slow.io_counters.snapshot().reads;
+        for _ in 0..5 {
+            let started = std::time::Instant::now();
+            assert_eq!(storage.read_file(file.ino).unwrap(), data);
+            assert!(started.elapsed() < delay / 2, "read took {:?}", started.elapsed());
+        }
+        assert_eq!(slow.io_counters.snapshot().reads, slow_reads);
+
+        // Only a lost fast replica sends reads to the slow disk
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        let fast_copy = extent.fragment_locations.iter().find(|l| l.disk_uuid != slow_uuid).unwrap();
+        remove_fragment(&storage, &extent, fast_copy.fragment_index);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        assert!(slow.io_counters.snapshot().reads > slow_reads);
+        assert!(slow.io_counters.read_latency().unwrap() >= delay / 8);
+    }
+
+    #[test]
+    fn test_mount_rebuild_notifies_unrecoverable_extents_and_its_completion() {
+        use crate::logging::EventKind;
+        use crate::notify::{Notification, NotificationConfig, Notifier};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let policy = crate::extent::RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE).map(|i| (i % 239) as u8).collect();
+        let mut extents = Vec::new();
+        for name in ["degraded.bin", "lost.bin"] {
+            let file = storage.create_file(1, name.to_string()).unwrap();
+            storage.set_file_redundancy(file.ino, policy).unwrap();
+            storage.write_file(file.ino, &data, 0).unwrap();
+            let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+            extents.push(storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap());
+        }
+        remove_fragment(&storage, &extents[0], 0);
+        for index in 0..3 {
+            remove_fragment(&storage, &extents[1], index);
+        }
+
+        let log = pool_dir.path().join("notifications.jsonl");
+        let config: NotificationConfig = serde_json::from_value(serde_json::json!({
+            "sinks": [{"type": "logfile", "path": log, "events": ["unrecoverable", "rebuild_pass_finished"]}]
+        }))
+        .unwrap();
+        let notifier = Notifier::start(pool_dir.path(), &config).unwrap();
+        notifier.subscribe(&storage.events());
+
+        storage.perform_mount_rebuild().unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(30);
+        while !storage.events().since(0).iter().any(|e| e.kind == EventKind::RebuildPassFinished) {
+            assert!(std::time::Instant::now() < deadline, "rebuild pass did not finish");
+            storage.events().wait_since(0, Duration::from_millis(100));
+        }
+        assert!(fragments_intact(&storage, &extents[0].uuid));
+        notifier.shutdown(Duration::from_secs(10));
+
+        let sent: Vec<Notification> =
+            std::fs::read_to_string(&log).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(sent.iter().map(|n| n.kind).collect::<Vec<_>>(), [EventKind::Unrecoverable, EventKind::RebuildPassFinished]);
+        assert_eq!(sent[0].extent_uuid, Some(extents[1].uuid));
+        assert_eq!(sent[1].fields, serde_json::json!({"queued": 1, "unrecoverable": 1}));
+        assert_eq!(sent[1].pool, pool_dir.path());
+    }
+
+    #[test]
+    fn test_event_ring_rate_limits_each_kind_and_rotates_its_log() {
+        use crate::logging::{read_event_log, EventKind, EventRing};
+
+        let dir = TempDir::new().unwrap();
+        let ring = EventRing::with_limits(4, 3);
+        ring.attach_file(&dir.path().join(crate::logging::EVENTS_FILE), 600).unwrap();
+
+        let accepted = (0..5).filter(|i| ring.record(EventKind::DegradedRead, None, None, format!("read {}", i))).count();
+        assert_eq!(accepted, 3);
+        // Limited per kind, so other events still get through
+        assert!(ring.record(EventKind::NoSpace, None, None, "full"));
+        assert!(ring.record(EventKind::DiskHealth, None, None, "failed"));
+
+        // Capacity 4: the oldest was dropped
+        let held = ring.since(0);
+        assert_eq!(held.len(), 4);
+        assert_eq!(held[0].message, "read 1");
+        assert_eq!(held.last().unwrap().seq, 5);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(ring.record(EventKind::DegradedRead, None, None, "read 5"));
+        assert_eq!(ring.since(5)[0].message, "read 5 (2 similar events suppressed)");
+
+        // Every event is in the log, split across the rotated and current files
+        let logged = read_event_log(dir.path()).unwrap();
+        assert_eq!(logged.iter().map(|e| e.seq).collect::<Vec<_>>(), (1..=6).collect::<Vec<_>>());
+        assert!(dir.path().join("events.log.1").exists());
+        assert!(std::fs::metadata(dir.path().join("events.log")).unwrap().len() <= 600);
+    }
+
+    #[test]
+    fn test_scrub_pinpoints_corrupt_replica_and_repairs_it() {
+        use crate::scrubber::{ScrubStatus, Scrubber};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(4);
+        let file = storage.create_file(1, "small.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"replicated bytes to scrub", 0).unwrap();
+
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let extent_uuid = extent_map.extents[0];
+        let mut extent = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap();
+        let bad_disk = corrupt_fragment(&storage, &extent, 1);
+
+        let scrubber = Scrubber::new(pool_dir.path().to_path_buf());
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let mut disks = storage.get_disks();
+        let result = scrubber.verify_extent(&extent, &metadata, &disks).unwrap();
+        assert_eq!(result.extent_uuid, extent_uuid);
+        assert_eq!(result.status, ScrubStatus::Degraded);
+        assert_eq!(result.corrupt_fragments, vec![(1, bad_disk)]);
+
+        // Repeated findings against the same disk mark it Suspect
+        assert!(Scrubber::record_corruption(&result, &mut disks).unwrap().is_empty());
+        assert!(Scrubber::record_corruption(&result, &mut disks).unwrap().is_empty());
+        assert_eq!(Scrubber::record_corruption(&result, &mut disks).unwrap(), vec![bad_disk]);
+        let suspect = disks.iter().find(|d| d.uuid == bad_disk).unwrap();
+        assert_eq!(suspect.health, crate::disk::DiskHealth::Suspect);
+        assert_eq!(suspect.corruption_count, crate::disk::CORRUPTION_SUSPECT_THRESHOLD);
+
+        let fragments: Vec<Option<Vec<u8>>> = (0..3)
+            .map(|i| {
+                let location = extent.fragment_locations.iter().find(|l| l.fragment_index == i).unwrap();
+                let disk = disks.iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+                disk.read_fragment(&extent.uuid, i).ok()
+            })
+            .collect();
+        let placement = crate::placement::PlacementEngine;
+        let repaired = scrubber
+            .repair_extent(&mut extent, &metadata, &disks, &placement, &fragments)
+            .unwrap();
+        assert_eq!(repaired.status, ScrubStatus::Repaired);
+        drop(metadata);
+
+        // The replacement replica lives on a healthy disk
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap();
+        assert!(extent.fragment_locations.iter().all(|l| l.disk_uuid != bad_disk));
+        assert!(fragments_intact(&storage, &extent_uuid));
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"replicated bytes to scrub");
+    }
+
+    #[test]
+    fn test_scrub_outvotes_divergent_replicas_and_checks_shards_without_checksums() {
+        use crate::extent::RedundancyPolicy;
+        use crate::scrubber::{IssueKind, ScrubStatus, Scrubber};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let scrubber = Scrubber::new(pool_dir.path().to_path_buf());
+        let placement = crate::placement::PlacementEngine;
+        // Extents written before fragment checksums were recorded
+        let legacy_extent = |ino: u64| {
+            let metadata = storage.metadata();
+            let metadata = metadata.read().unwrap();
+            let mut extent = metadata.load_extent(&metadata.load_extent_map(ino).unwrap().extents[0]).unwrap();
+            for location in &mut extent.fragment_locations {
+                location.checksum = None;
+            }
+            metadata.save_extent(&extent).unwrap();
+            extent
+        };
+
+        let small = storage.create_file(1, "small.txt".to_string()).unwrap();
+        storage.write_file(small.ino, b"replicated bytes to scrub", 0).unwrap();
+        let mut extent = legacy_extent(small.ino);
+        assert!(matches!(extent.redundancy, RedundancyPolicy::Replication { copies: 3 }));
+        let bad_disk = corrupt_fragment(&storage, &extent, 2);
+
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let disks = storage.get_disks();
+        let result = scrubber.verify_extent(&extent, &metadata, &disks).unwrap();
+        assert_eq!(result.status, ScrubStatus::Degraded);
+        assert_eq!(result.corrupt_fragments, vec![(2, bad_disk)]);
+        let issue = &result.issues[0];
+        assert_eq!((issue.kind, issue.fragment_index, issue.disk_uuid), (IssueKind::ReplicaMismatch, Some(2), Some(bad_disk)));
+        assert_eq!(serde_json::to_value(issue).unwrap()["kind"], "replica_mismatch");
+
+        let fragments = Scrubber::read_fragments(&extent, &disks);
+        let repaired = scrubber.repair_extent(&mut extent, &metadata, &disks, &placement, &fragments).unwrap();
+        assert_eq!(repaired.status, ScrubStatus::Repaired);
+        drop(metadata);
+        assert_eq!(storage.read_file(small.ino).unwrap(), b"replicated bytes to scrub");
+
+        // A corrupt parity shard is not needed to decode, but re-encoding exposes it
+        let large = storage.create_file(1, "large.bin".to_string()).unwrap();
+        storage.set_file_redundancy(large.ino, RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 }).unwrap();
+        storage.write_file(large.ino, &patterned(256 * 1024), 0).unwrap();
+        let extent = legacy_extent(large.ino);
+        let data_shards = 4;
+        let bad_disk = corrupt_fragment(&storage, &extent, data_shards);
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let result = scrubber.verify_extent(&extent, &metadata, &storage.get_disks()).unwrap();
+        assert_eq!(result.status, ScrubStatus::Degraded);
+        assert_eq!(result.corrupt_fragments, vec![(data_shards, bad_disk)]);
+        assert_eq!(result.missing_fragments, vec![data_shards]);
+        assert_eq!(result.issues[0].kind, IssueKind::ShardMismatch);
+    }
+
+    #[test]
+    fn test_verify_file_reports_each_extent_and_repair_file_fixes_degraded_ones() {
+        use crate::scrubber::ExtentHealth;
+        let small = 64 * 1024;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        storage.set_extent_size(small).unwrap();
+        let dir = storage.create_dir(1, "reports".to_string()).unwrap();
+        let file = storage.create_file(dir.ino, "q3.bin".to_string()).unwrap();
+        let data = patterned(4 * small);
+        storage.write_file(file.ino, &data, 0).unwrap();
+        assert_eq!(storage.metadata().read().unwrap().resolve_path("/reports/q3.bin").unwrap().ino, file.ino);
+
+        // One corrupt fragment, one deleted fragment, one extent lost outright, one untouched
+        let map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let load = |slot: usize| storage.metadata().read().unwrap().load_extent(&map.extents[slot]).unwrap();
+        corrupt_fragment(&storage, &load(0), 1);
+        remove_fragment(&storage, &load(1), 0);
+        let mut lost = load(2);
+        let needed = lost.redundancy.min_fragments();
+        lost.fragment_locations.clear();
+        storage.metadata().read().unwrap().save_extent(&lost).unwrap();
+
+        let states = |report: &crate::scrubber::FileCheckReport| {
+            report.extents.iter().map(|check| (check.slot, check.offset, check.health.clone())).collect::<Vec<_>>()
+        };
+        let unreadable = ExtentHealth::Unreadable { readable: 0, needed };
+        let report = storage.verify_file(file.ino).unwrap();
+        assert_eq!(
+            states(&report),
+            vec![
+                (0, 0, ExtentHealth::Degraded { missing_fragments: vec![1] }),
+                (1, small as u64, ExtentHealth::Degraded { missing_fragments: vec![0] }),
+                (2, 2 * small as u64, unreadable.clone()),
+                (3, 3 * small as u64, ExtentHealth::Healthy),
+            ]
+        );
+        assert_eq!((report.summary().degraded, report.unrecoverable()), (2, 1));
+
+        let report = storage.repair_file(file.ino).unwrap();
+        assert_eq!(report.extents[0].health, ExtentHealth::Repaired { rebuilt_fragments: vec![1] });
+        assert_eq!(report.extents[1].health, ExtentHealth::Repaired { rebuilt_fragments: vec![0] });
+        assert_eq!(report.extents[2].health, unreadable);
+        let summary = storage.verify_file(file.ino).unwrap().summary();
+        assert_eq!((summary.healthy, summary.degraded, summary.unreadable), (3, 0, 1));
+        assert_eq!(storage.read_range(file.ino, 0, 2 * small as u64).unwrap(), data[..2 * small]);
+    }
+
+    #[test]
+    fn test_io_error_history_drives_disk_health() {
+        use crate::disk::{DiskHealth, DiskHealthPolicy, DiskPool};
+
+        let pool_dir = test_pool_dir();
+        let disk_dir = tempfile::tempdir().unwrap();
+        Disk::new(disk_dir.path().to_path_buf()).unwrap();
+        let mut pool = DiskPool::new();
+        pool.add_disk(disk_dir.path().to_path_buf()).unwrap();
+        pool.health_policy = DiskHealthPolicy {
+            suspect_errors: 3,
+            failed_errors: 6,
+            window_secs: 600,
+            successes_per_decay: 2,
+        };
+        pool.save(pool_dir.path()).unwrap();
+
+        // Thresholds come from the pool config
+        let mut disk = DiskPool::load(pool_dir.path()).unwrap().load_disks().unwrap().remove(0);
+        assert_eq!(disk.health_policy, pool.health_policy);
+
+        assert_eq!(disk.record_io_error().unwrap(), None);
+        assert_eq!(disk.record_io_error().unwrap(), None);
+        assert_eq!(disk.record_io_error().unwrap(), Some(DiskHealth::Suspect));
+        assert_eq!(disk.recent_io_errors(), 3);
+
+        // Successes decay the errors until the disk recovers
+        for _ in 0..5 {
+            assert_eq!(disk.record_io_success().unwrap(), None);
+        }
+        assert_eq!(disk.record_io_success().unwrap(), Some(DiskHealth::Healthy));
+        assert_eq!(disk.recent_io_errors(), 0);
+
+        for _ in 0..5 {
+            disk.record_io_error().unwrap();
+        }
+        assert_eq!(disk.health, DiskHealth::Suspect);
+        assert_eq!(disk.record_io_error().unwrap(), Some(DiskHealth::Failed));
+
+        // Failed never recovers on its own, and the history survives a reload
+        for _ in 0..20 {
+            disk.record_io_success().unwrap();
+        }
+        assert_eq!(disk.health, DiskHealth::Failed);
+        let reloaded = Disk::load(disk_dir.path()).unwrap();
+        assert_eq!(reloaded.health, DiskHealth::Failed);
+    }
+
+    #[test]
+    fn test_read_errors_mark_disk_suspect_and_steer_writes_away() {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| {
+                let mut disk = Disk::new(td.path().to_path_buf()).unwrap();
+                disk.health_policy.suspect_errors = 1;
+                disk
+            })
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+
+        let file = storage.create_file(1, "flaky.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"bytes on a flaky disk", 0).unwrap();
+
+        // A fragment path that cannot be read as a file is an I/O error, unlike a missing one
+        // on the replica read first, so the read falls back to another one
+        let extent = storage.metadata().read().unwrap().list_all_extents().unwrap().remove(0);
+        let all_disks = storage.get_disks();
+        let holders: Vec<Option<&Disk>> = (0..crate::redundancy::total_fragments_for_policy(extent.redundancy))
+            .map(|index| {
+                let location = extent.fragment_locations.iter().find(|l| l.fragment_index == index)?;
+                all_disks.iter().find(|d| d.uuid == location.disk_uuid)
+            })
+            .collect();
+        let first = crate::scheduler::fragment_read_order(&extent, &holders)[0];
+        let location = extent.fragment_locations.iter().find(|l| l.fragment_index == first).unwrap().clone();
+        let disk = holders[first].unwrap();
+        let path = disk.fragment_path(&extent.uuid, location.fragment_index);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"bytes on a flaky disk");
+        let suspect = storage.get_disks().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+        assert_eq!(suspect.health, crate::disk::DiskHealth::Suspect);
+
+        // The failed read queued a rebuild, which reads every fragment and so hits the path once more
+        storage.wait_for_rebuilds();
+        let suspect = storage.get_disks().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+        assert_eq!(suspect.recent_io_errors(), 2);
+
+        let other = storage.create_file(1, "fresh.txt".to_string()).unwrap();
+        storage.write_file(other.ino, b"new data", 0).unwrap();
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(other.ino).unwrap();
+        let fresh = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        assert!(fresh.fragment_locations.iter().all(|l| l.disk_uuid != location.disk_uuid));
+    }
+
+    /// Engine over disks with the given capacity, usage and health
+    fn setup_storage_with_usage(
+        disks: &[(u64, u64, crate::disk::DiskHealth)],
+    ) -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = disks.iter().map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .zip(disks)
+            .map(|(td, &(capacity_bytes, used_bytes, health))| {
+                let mut disk = Disk::new(td.path().to_path_buf()).unwrap();
+                disk.capacity_bytes = capacity_bytes;
+                disk.used_bytes = used_bytes;
+                disk.health = health;
+                disk
+            })
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        (pool_dir, disk_dirs, StorageEngine::new(metadata, disks))
+    }
+
+    #[test]
+    fn test_write_fails_fast_with_storage_full_before_placing_fragments() {
+        use crate::disk::DiskHealth;
+        use crate::logging::EventKind;
+
+        const MIB: u64 = 1024 * 1024;
+        let (_pool, _disks, storage) = setup_storage_with_usage(&[(MIB, 0, DiskHealth::Healthy); 3]);
+        let reserve = MIB / 100 * 2;
+        // Replicated three ways, so usable space is one disk's worth minus its reserve
+        assert_eq!(storage.stat().unwrap().free_space, MIB - reserve);
+
+        // Fits on the disks, but only by eating into the reserve
+        let file = storage.create_file(1, "big.bin".to_string()).unwrap();
+        let err = storage.write_file(file.ino, &vec![1u8; (MIB - reserve / 2) as usize], 0).unwrap_err();
+        assert!(matches!(err, StorageError::NoSpace(_)), "{:?}", err);
+        assert!(storage.get_disks().iter().all(|d| d.used_bytes == 0));
+        assert_eq!(storage.events().since(0).last().unwrap().kind, EventKind::NoSpace);
+
+        let data = vec![2u8; 400 * 1024];
+        storage.write_file(file.ino, &data, 0).unwrap();
+        // Partial writes are checked too; the grown copy of the extent needs the reserve
+        let tail = vec![3u8; 215_000];
+        let err = storage.write_file(file.ino, &tail, data.len() as u64).unwrap_err();
+        assert!(matches!(err, StorageError::NoSpace(_)), "{:?}", err);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+
+        storage.set_space_reserve_percent(0);
+        storage.write_file(file.ino, &tail, data.len() as u64).unwrap();
+        assert_eq!(storage.read_file(file.ino).unwrap(), [data, tail].concat());
+    }
+
+    #[test]
+    fn test_errors_carry_their_class() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        assert!(matches!(storage.get_inode(99), Err(StorageError::NotFound(_))));
+        assert!(matches!(storage.metadata().read().unwrap().load_inode(99), Err(StorageError::NotFound(_))));
+
+        let file = storage.create_file(1, "data.bin".to_string()).unwrap();
+        storage.write_file(file.ino, b"checksummed", 0).unwrap();
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        let mut extent = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap();
+        extent.checksum = [0u8; 32];
+        storage.metadata().read().unwrap().save_extent(&extent).unwrap();
+        match storage.read_file(file.ino).unwrap_err() {
+            StorageError::Corruption { extent, .. } => assert_eq!(extent, extent_uuid),
+            other => panic!("expected Corruption, got {:?}", other),
+        }
+        // The class survives the trip through the filesystem interface
+        let err = crate::fs_interface::FilesystemInterface::read_file(&storage, file.ino).unwrap_err();
+        assert_eq!(StorageError::errno_of(&err), libc::EIO);
+        assert!(matches!(StorageError::from(err), StorageError::Corruption { .. }));
+    }
+
+    #[test]
+    fn test_inode_cache_serves_repeat_lookups_and_follows_changes() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let counts = || {
+            let snapshot = storage.metrics().snapshot();
+            (snapshot.inode_cache_hits, snapshot.inode_cache_misses)
+        };
+        let file = storage.create_file(1, "a".to_string()).unwrap();
+        storage.get_inode(file.ino).unwrap();
+        let before = counts();
+        assert_eq!(storage.find_child(1, "a").unwrap().unwrap().ino, file.ino);
+        assert_eq!(storage.find_child(1, "a").unwrap().unwrap().ino, file.ino);
+        storage.get_inode(file.ino).unwrap();
+        let after = counts();
+        // Only the first lookup of the name misses
+        assert_eq!((after.0 - before.0, after.1 - before.1), (4, 1));
+
+        storage.write_file(file.ino, b"grown", 0).unwrap();
+        storage.flush_file(file.ino).unwrap();
+        assert_eq!(storage.metadata().read().unwrap().load_inode(file.ino).unwrap().size, 5);
+
+        // Renamed by a save under a new name
+        let mut renamed = storage.get_inode(file.ino).unwrap();
+        renamed.name = "b".into();
+        storage.metadata().read().unwrap().save_inode(&renamed).unwrap();
+        assert!(storage.find_child(1, "a").unwrap().is_none());
+        assert_eq!(storage.find_child(1, "b").unwrap().unwrap().ino, file.ino);
+
+        storage.delete_file(file.ino).unwrap();
+        assert!(matches!(storage.get_inode(file.ino), Err(StorageError::NotFound(_))));
+        assert!(storage.find_child(1, "b").unwrap().is_none());
+
+        // Disabled, every lookup reads the record
+        storage.set_inode_cache_limits(crate::inode_cache::InodeCacheLimits { capacity: 0, ..Default::default() });
+        let dir = storage.create_dir(1, "d".to_string()).unwrap();
+        let before = counts();
+        storage.get_inode(dir.ino).unwrap();
+        storage.get_inode(dir.ino).unwrap();
+        assert_eq!(counts().0, before.0);
+    }
+
+    #[test]
+    fn test_uncached_reads_leave_the_data_cache_alone() {
+        use crate::fs_interface::NOCACHE_XATTR;
+
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        storage.set_extent_size(64 * 1024).unwrap();
+        let hot = storage.create_file(1, "hot".to_string()).unwrap();
+        storage.write_file(hot.ino, &[1u8; 4096], 0).unwrap();
+        let hot_extent = storage.metadata().read().unwrap().load_extent_map(hot.ino).unwrap().extents[0];
+        storage.cache_extent(&hot_extent).unwrap();
+        storage.read_range(hot.ino, 0, 4096).unwrap();
+
+        let data: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let big = storage.create_file(1, "backup.tar".to_string()).unwrap();
+        storage.write_file(big.ino, &data, 0).unwrap();
+        let flagged = storage.create_file(1, "scan.log".to_string()).unwrap();
+        storage.write_file(flagged.ino, &data[..256 * 1024], 0).unwrap();
+        let mut inode = storage.get_inode(flagged.ino).unwrap();
+        inode.set_xattr(NOCACHE_XATTR.to_string(), b"on".to_vec());
+        storage.update_inode(&inode).unwrap();
+
+        let cache_counts = || {
+            let snapshot = storage.metrics().snapshot();
+            (snapshot.cache_hits, snapshot.cache_misses)
+        };
+        let utilization = storage.cache_utilization();
+        let counts = cache_counts();
+        let mut read = Vec::new();
+        for offset in (0..data.len() as u64).step_by(32 * 1024) {
+            read.extend(storage.read_range_uncached(big.ino, offset, 32 * 1024).unwrap());
+        }
+        assert_eq!(read, data);
+        // The xattr makes every read of the file uncached
+        assert_eq!(storage.read_range(flagged.ino, 0, 256 * 1024).unwrap(), &data[..256 * 1024]);
+        assert_eq!(storage.cache_utilization(), utilization);
+        assert_eq!(cache_counts(), counts);
+        assert!(storage.evict_cached_extent(&hot_extent), "the hot extent is still cached");
+
+        // Scan reads are counted, but apart from the reads that make data hot
+        storage.flush_access_stats().unwrap();
+        let first = storage.metadata().read().unwrap().load_extent_map(big.ino).unwrap().extents[0];
+        let extent = storage.metadata().read().unwrap().load_extent(&first).unwrap();
+        assert_eq!((extent.access_stats.read_count, extent.access_stats.scan_reads), (2, 2));
+        assert_eq!(extent.access_stats.last_read, 0);
+    }
+
+    #[test]
+    fn test_changed_files_since_a_generation() {
+        use crate::backup_evolution::{changed_files, ChangedRange};
+
+        const SLOT: u64 = 64 * 1024;
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        storage.set_extent_size(SLOT as usize).unwrap();
+        let dir = storage.create_dir(1, "d".to_string()).unwrap();
+        let mut inos = Vec::new();
+        for name in ["edited", "renamed", "truncated", "untouched"] {
+            let file = storage.create_file(dir.ino, name.to_string()).unwrap();
+            storage.write_file(file.ino, &vec![1u8; 4 * SLOT as usize], 0).unwrap();
+            inos.push(file.ino);
+        }
+        let since = storage.metadata().read().unwrap().change_generation();
+        let changes = || changed_files(&storage.metadata().read().unwrap(), since).unwrap();
+        assert!(changes().files.is_empty());
+
+        // Only the slots written to are listed, merged when adjacent
+        storage.write_file(inos[0], &[2u8; 10], SLOT + 100).unwrap();
+        storage.write_file(inos[0], &[2u8; 10], 2 * SLOT).unwrap();
+        let mut renamed = storage.get_inode(inos[1]).unwrap();
+        renamed.name = "moved".into();
+        renamed.parent_ino = 1;
+        storage.metadata().read().unwrap().save_inode(&renamed).unwrap();
+        storage.write_file(inos[2], &[], 0).unwrap();
+        let mut truncated = storage.get_inode(inos[2]).unwrap();
+        truncated.size = 0;
+        storage.update_inode(&truncated).unwrap();
+        // Reads and access-time updates are not changes
+        storage.read_file(inos[3]).unwrap();
+        storage.flush_inode_times().unwrap();
+
+        let listed = changes();
+        assert!(listed.generation > since);
+        let summary: Vec<(&str, u64, &[ChangedRange])> =
+            listed.files.iter().map(|file| (file.path.as_str(), file.size, &file.ranges[..])).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/d/edited", 4 * SLOT, &[ChangedRange { offset: SLOT, length: 2 * SLOT }][..]),
+                ("/d/truncated", 0, &[][..]),
+                ("/moved", 4 * SLOT, &[][..]),
+            ]
+        );
+
+        // Generations keep going up across a reopen
+        let generation = storage.metadata().read().unwrap().change_generation();
+        drop(storage);
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        assert!(metadata.change_generation() >= generation);
+        let mut inode = metadata.load_inode(inos[3]).unwrap();
+        inode.size = 1;
+        metadata.save_inode(&inode).unwrap();
+        let listed = changed_files(&metadata, generation).unwrap();
+        assert_eq!(listed.files.len(), 1);
+        assert!(listed.files[0].change_generation > generation);
+    }
+
+    #[test]
+    fn test_space_watermarks_refuse_writes_but_not_deletes() {
+        use crate::disk::DiskHealth;
+        use crate::logging::EventKind;
+        use crate::placement::{SpaceLevel, SpaceWatermarks};
+
+        const KIB: usize = 1024;
+        let (_pool, _disks, storage) = setup_storage_with_usage(&[(1024 * 1024, 0, DiskHealth::Healthy); 3]);
+        storage.set_space_watermarks(SpaceWatermarks { high_percent: 50, critical_percent: 80 });
+        let is_full = |err: StorageError| matches!(err, StorageError::NoSpace(_));
+        let write = |name: &str, len: usize| {
+            let file = storage.create_file(1, name.to_string())?;
+            storage.write_file(file.ino, &vec![7u8; len], 0).map(|()| file.ino)
+        };
+
+        // Each file is replicated to all three disks, so usage is its size over one disk
+        let a = write("a", 400 * KIB).unwrap();
+        let b = write("b", 200 * KIB).unwrap();
+        assert_eq!(storage.check_space_watermarks(), SpaceLevel::High);
+        let crossed = storage.events().since(0).into_iter().rfind(|e| e.kind == EventKind::SpaceWatermark).unwrap();
+        assert_eq!(crossed.fields.unwrap()["level"], "high");
+
+        // Past the high watermark only low-priority writes are refused
+        storage.set_low_priority_writes(true);
+        assert!(is_full(write("bench", KIB).unwrap_err()));
+        storage.set_low_priority_writes(false);
+        let c = write("c", 250 * KIB).unwrap();
+        assert_eq!(storage.check_space_watermarks(), SpaceLevel::Critical);
+
+        // Past the critical watermark new data is refused, but truncates and deletes go through
+        assert!(is_full(write("d", 10).unwrap_err()));
+        assert_eq!(storage.events().since(0).last().unwrap().kind, EventKind::NoSpace);
+        storage.write_file(c, &[], 0).unwrap();
+        assert_eq!(storage.check_space_watermarks(), SpaceLevel::High);
+        storage.delete_file(b).unwrap();
+        assert_eq!(storage.check_space_watermarks(), SpaceLevel::Normal);
+        let back = storage.events().since(0).into_iter().rfind(|e| e.kind == EventKind::SpaceWatermark).unwrap();
+        assert_eq!(back.fields.unwrap()["level"], "normal");
+        storage.write_file(c, &vec![8u8; 10], 0).unwrap();
+        assert_eq!(storage.read_file(a).unwrap().len(), 400 * KIB);
+    }
+
+    #[test]
+    fn test_concurrent_writers_cannot_overcommit_free_space() {
+        use crate::disk::DiskHealth;
+        use std::sync::Arc;
+
+        const MIB: u64 = 1024 * 1024;
+        let (_pool, _disks, storage) = setup_storage_with_usage(&[(MIB, 0, DiskHealth::Healthy); 3]);
+        let storage = Arc::new(storage);
+        let files: Vec<u64> = (0..4)
+            .map(|i| storage.create_file(1, format!("racer{}.bin", i)).unwrap().ino)
+            .collect();
+
+        // Each write takes 60% of every disk, so only one can succeed
+        let barrier = Arc::new(std::sync::Barrier::new(files.len()));
+        let writers: Vec<_> = files
+            .iter()
+            .map(|&ino| {
+                let storage = Arc::clone(&storage);
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    storage.write_file(ino, &vec![7u8; (MIB * 6 / 10) as usize], 0)
+                })
+            })
+            .collect();
+        let results: Vec<_> = writers.into_iter().map(|w| w.join().unwrap()).collect();
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        for err in results.iter().filter_map(|r| r.as_ref().err()) {
+            assert!(matches!(err, StorageError::NoSpace(_)), "{:?}", err);
+        }
+        assert!(storage.get_disks().iter().all(|d| d.used_bytes <= MIB * 7 / 10));
+    }
+
+    /// Disks holding each fragment of every extent of a file
+    fn fragment_disks(storage: &StorageEngine, ino: u64) -> Vec<Vec<uuid::Uuid>> {
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let extent_map = metadata.load_extent_map(ino).unwrap();
+        extent_map
+            .data_extents()
+            .map(|uuid| {
+                let extent = metadata.load_extent(uuid).unwrap();
+                extent.fragment_locations.iter().map(|l| l.disk_uuid).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_placement_simulate_unbalanced_pool_converges() {
+        use crate::disk::DiskHealth::Healthy;
+        const MIB: u64 = 1024 * 1024;
+
+        // One disk starts half full, the rest nearly empty and unevenly so
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_usage(&[
+            (64 * MIB, 32 * MIB, Healthy),
+            (64 * MIB, 0, Healthy),
+            (64 * MIB, 2 * MIB, Healthy),
+            (64 * MIB, 4 * MIB, Healthy),
+            (64 * MIB, 6 * MIB, Healthy),
+        ]);
+        let spread = |disks: &[Disk]| {
+            let utilization: Vec<f64> = disks.iter().map(|d| d.utilization()).collect();
+            utilization.iter().cloned().fold(f64::MIN, f64::max) - utilization.iter().cloned().fold(f64::MAX, f64::min)
+        };
+        let initial_spread = spread(&storage.get_disks());
+
+        let data = vec![0x5au8; 256 * 1024];
+        for i in 0..200 {
+            let file = storage.create_file(1, format!("sim_{:03}.bin", i)).unwrap();
+            storage.write_file(file.ino, &data, 0).unwrap();
+            for disks in fragment_disks(&storage, file.ino) {
+                let mut distinct = disks.clone();
+                distinct.sort();
+                distinct.dedup();
+                assert_eq!(distinct.len(), disks.len(), "two fragments of one extent share a disk");
+            }
+        }
+
+        // 150 MiB of replicas is enough for the others to catch up with the full disk,
+        // after which all five fill evenly
+        let disks = storage.get_disks();
+        let final_spread = spread(&disks);
+        assert!(final_spread < initial_spread / 4.0, "spread went from {} to {}", initial_spread, final_spread);
+        assert!(final_spread <= 256.0 * 1024.0 / (64 * MIB) as f64 + f64::EPSILON);
+    }
+
+    #[test]
+    fn test_placement_excludes_draining_and_avoids_suspect_disks() {
+        use crate::disk::DiskHealth::{Draining, Healthy, Suspect};
+        const MIB: u64 = 1024 * 1024;
+
+        // The draining disk is the emptiest, so only the health check keeps writes off it
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_usage(&[
+            (64 * MIB, 0, Draining),
+            (64 * MIB, 8 * MIB, Healthy),
+            (64 * MIB, 8 * MIB, Healthy),
+            (64 * MIB, 8 * MIB, Healthy),
+            (64 * MIB, 0, Suspect),
+        ]);
+        let disks = storage.get_disks();
+        let (draining, suspect) = (disks[0].uuid, disks[4].uuid);
+
+        for i in 0..20 {
+            let file = storage.create_file(1, format!("d_{:02}.bin", i)).unwrap();
+            storage.write_file(file.ino, &[i as u8; 4096], 0).unwrap();
+            for disks in fragment_disks(&storage, file.ino) {
+                assert!(!disks.contains(&draining));
+                assert!(!disks.contains(&suspect));
+            }
+        }
+
+        // With one healthy disk gone, the Suspect disk makes up the third replica
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_usage(&[
+            (64 * MIB, 0, Draining),
+            (64 * MIB, 8 * MIB, Healthy),
+            (64 * MIB, 8 * MIB, Healthy),
+            (64 * MIB, 0, Suspect),
+        ]);
+        let disks = storage.get_disks();
+        let file = storage.create_file(1, "fallback.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[7u8; 4096], 0).unwrap();
+        let placed = fragment_disks(&storage, file.ino).remove(0);
+        assert!(placed.contains(&disks[3].uuid));
+        assert!(!placed.contains(&disks[0].uuid));
+    }
+
+    #[test]
+    fn test_erasure_read_falls_back_to_parity_per_fragment() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+
+        let file = storage.create_file(1, "ec.bin".to_string()).unwrap();
+        let data: Vec<u8> = (0..(crate::extent::DEFAULT_EXTENT_SIZE + 4096)).map(|i| (i % 251) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        // Lose two data shards of the first extent; the read must use both parity shards
+        let extent_map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent_map.extents[0]).unwrap();
+        assert!(matches!(extent.redundancy, crate::extent::RedundancyPolicy::ErasureCoding { .. }));
+        remove_fragment(&storage, &extent, 0);
+        remove_fragment(&storage, &extent, 2);
+
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        assert_eq!(storage.metrics().snapshot().disk_errors, 2);
+    }
+
+    #[test]
+    fn test_replicated_read_survives_lost_replicas() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+
+        let file = storage.create_file(1, "replicated.txt".to_string()).unwrap();
+        let data = b"only one replica left".to_vec();
+        storage.write_file(file.ino, &data, 0).unwrap();
+
+        let extent = storage.metadata().read().unwrap().list_all_extents().unwrap().remove(0);
+        remove_fragment(&storage, &extent, 0);
+        remove_fragment(&storage, &extent, 1);
+
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+
+        // Every replica gone: the read fails instead of returning partial data
+        remove_fragment(&storage, &extent, 2);
+        storage.wait_for_rebuilds();
+        let extent = storage.metadata().read().unwrap().load_extent(&extent.uuid).unwrap();
+        for location in &extent.fragment_locations {
+            let disk = storage.get_disks().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+            std::fs::remove_file(disk.fragment_path(&extent.uuid, location.fragment_index)).ok();
+        }
+        assert!(storage.read_file(file.ino).is_err());
+    }
+
+    #[test]
+    fn test_hot_extents_sorted_by_access_frequency() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+
+        let quiet = storage.create_file(1, "quiet.txt".to_string()).unwrap();
+        let busy = storage.create_file(1, "busy.txt".to_string()).unwrap();
+        storage.write_file(quiet.ino, b"quiet", 0).unwrap();
+        storage.write_file(busy.ino, b"busy", 0).unwrap();
+        for _ in 0..5 {
+            storage.read_file(busy.ino).unwrap();
+        }
+
+        let busy_extent = storage.metadata().read().unwrap().load_extent_map(busy.ino).unwrap().extents[0];
+        let hot = storage.get_hot_extents().unwrap();
+        assert_eq!(hot[0].uuid, busy_extent);
+        assert_eq!(hot[0].access_stats().read_count, 5);
+        assert!(hot.windows(2).all(|w| w[0].access_frequency() >= w[1].access_frequency()));
+        assert!(storage.get_cold_extents().unwrap().iter().all(|e| e.uuid != busy_extent));
+    }
+
+    #[test]
+    fn test_reads_are_counted_in_memory_and_flushed_in_batches() {
+        fn snapshot(dir: &std::path::Path, files: &mut Vec<(std::path::PathBuf, Vec<u8>)>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    snapshot(&path, files);
+                } else {
+                    files.push((path.clone(), std::fs::read(&path).unwrap()));
+                }
+            }
+            files.sort();
+        }
+        let persisted_reads = |pool: &std::path::Path, uuid: &uuid::Uuid| {
+            let metadata = MetadataManager::new(pool.to_path_buf()).unwrap();
+            metadata.load_extent(uuid).unwrap().access_stats.read_count
+        };
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let file = storage.create_file(1, "read-mostly.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"read me often", 0).unwrap();
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        // The write's group commit retires its journal record once synced
+        storage.sync_inode(file.ino).unwrap();
+
+        // A pure-read workload leaves the metadata untouched
+        let mut before = Vec::new();
+        snapshot(pool_dir.path(), &mut before);
+        for _ in 0..20 {
+            assert_eq!(storage.read_file(file.ino).unwrap(), b"read me often");
+        }
+        let mut after = Vec::new();
+        snapshot(pool_dir.path(), &mut after);
+        assert!(before == after, "reads wrote to the pool directory");
+
+        // Queries see the reads before they are flushed
+        assert_eq!(storage.get_extent_access_stats(&extent_uuid).unwrap().read_count, 20);
+        assert_eq!(storage.get_hot_extents().unwrap()[0].uuid, extent_uuid);
+        assert_eq!(persisted_reads(pool_dir.path(), &extent_uuid), 0);
+
+        assert_eq!(storage.flush_access_stats().unwrap(), 1);
+        assert_eq!(storage.flush_access_stats().unwrap(), 0);
+        assert_eq!(persisted_reads(pool_dir.path(), &extent_uuid), 20);
+        assert_eq!(storage.get_extent_access_stats(&extent_uuid).unwrap().read_count, 20);
+
+        {
+            let mut flusher = storage.background_handle();
+            flusher.start_access_stats_flush(Duration::from_millis(50));
+            storage.read_file(file.ino).unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while persisted_reads(pool_dir.path(), &extent_uuid) != 21 {
+                assert!(Instant::now() < deadline, "periodic flush did not persist the read");
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        }
+
+        // Reads still pending at unmount are not lost
+        storage.read_file(file.ino).unwrap();
+        storage.read_file(file.ino).unwrap();
+        drop(storage);
+        assert_eq!(persisted_reads(pool_dir.path(), &extent_uuid), 23);
+    }
+
+    #[test]
+    fn test_atime_modes_and_batched_mtime_updates() {
+        use crate::access_tracker::AtimeMode;
+
+        let persisted = |pool: &std::path::Path, ino: u64| MetadataManager::new(pool.to_path_buf()).unwrap().load_inode(ino).unwrap();
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let file = storage.create_file(1, "times.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"0123456789", 0).unwrap();
+        // Creating the file moved the root's times; only the file's are counted below
+        storage.flush_inode_times().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let set_times = |atime: i64, mtime: i64| {
+            let mut inode = storage.get_inode(file.ino).unwrap();
+            inode.atime = atime;
+            inode.mtime = mtime;
+            inode.ctime = mtime;
+            storage.update_inode(&inode).unwrap();
+        };
+
+        storage.set_atime_mode(AtimeMode::Noatime);
+        set_times(now - 1000, now - 2000);
+        storage.read_file(file.ino).unwrap();
+        storage.read_range(file.ino, 2, 4).unwrap();
+        assert_eq!(storage.get_inode(file.ino).unwrap().atime, now - 1000);
+
+        // Relatime leaves a recent atime newer than mtime alone, but refreshes a stale one
+        storage.set_atime_mode(AtimeMode::Relatime);
+        storage.read_file(file.ino).unwrap();
+        assert_eq!(storage.get_inode(file.ino).unwrap().atime, now - 1000);
+        set_times(now - 3000, now - 2000);
+        storage.read_range(file.ino, 0, 1).unwrap();
+        assert!(storage.get_inode(file.ino).unwrap().atime >= now);
+        set_times(now - 2 * 24 * 3600, now - 3 * 24 * 3600);
+        storage.read_file(file.ino).unwrap();
+        assert!(storage.get_inode(file.ino).unwrap().atime >= now);
+
+        storage.set_atime_mode(AtimeMode::Strict);
+        set_times(now - 1000, now - 2000);
+        storage.read_file(file.ino).unwrap();
+        assert!(storage.get_inode(file.ino).unwrap().atime >= now);
+
+        // The new atime is only in memory until the flush
+        assert_eq!(persisted(pool_dir.path(), file.ino).atime, now - 1000);
+        assert_eq!(storage.flush_inode_times().unwrap(), 1);
+        assert_eq!(storage.flush_inode_times().unwrap(), 0);
+        assert!(persisted(pool_dir.path(), file.ino).atime >= now);
+
+        // A burst of overwrites moves mtime in memory and writes the inode once
+        set_times(now - 1000, now - 2000);
+        for i in 0..10u8 {
+            storage.write_range(file.ino, i as u64, &[b'a' + i]).unwrap();
+        }
+        let inode = storage.get_inode(file.ino).unwrap();
+        assert!(inode.mtime >= now && inode.ctime >= now);
+        assert_eq!(persisted(pool_dir.path(), file.ino).mtime, now - 2000);
+        assert_eq!(storage.flush_inode_times().unwrap(), 1);
+        assert!(persisted(pool_dir.path(), file.ino).mtime >= now);
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"abcdefghij");
+
+        // Timestamps still pending at unmount are not lost
+        set_times(now - 1000, now - 2000);
+        storage.write_range(file.ino, 0, b"z").unwrap();
+        drop(storage);
+        assert!(persisted(pool_dir.path(), file.ino).mtime >= now);
+    }
+
+    /// Engine over disks of the given tiers and capacities
+    fn setup_storage_with_tiers(disks: &[(crate::tiering::StorageTier, u64)]) -> (TempDir, Vec<TempDir>, StorageEngine) {
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = disks.iter().map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .zip(disks)
+            .map(|(td, &(tier, capacity_bytes))| {
+                let mut disk = Disk::new(td.path().to_path_buf()).unwrap();
+                disk.tier = tier;
+                disk.capacity_bytes = capacity_bytes;
+                disk
+            })
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        (pool_dir, disk_dirs, StorageEngine::new(metadata, disks))
+    }
+
+    fn extent_disk_tiers(storage: &StorageEngine, extent_uuid: &uuid::Uuid) -> Vec<crate::tiering::StorageTier> {
+        let extent = storage.metadata().read().unwrap().load_extent(extent_uuid).unwrap();
+        let disks = storage.get_disks();
+        extent
+            .fragment_locations
+            .iter()
+            .map(|l| disks.iter().find(|d| d.uuid == l.disk_uuid).unwrap().tier)
+            .collect()
+    }
+
+    /// Write an extent-sized file and read it until lazy migration moves it to the fast tier
+    fn write_hot_extent(storage: &StorageEngine, name: &str, reads: usize) -> (u64, uuid::Uuid) {
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+
+        let data: Vec<u8> = (0..DEFAULT_EXTENT_SIZE).map(|i| (i % 251) as u8).collect();
+        let file = storage.create_file(1, name.to_string()).unwrap();
+        storage.write_file(file.ino, &data, 0).unwrap();
+        for _ in 0..reads {
+            assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        }
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        (file.ino, extent_uuid)
+    }
+
+    #[test]
+    fn test_cold_extents_move_to_slower_tiers_in_the_tier_pass() {
+        use crate::extent::AccessClassification;
+        use crate::tiering::{StorageTier, TierPassConfig};
+
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let mut tiers = vec![(StorageTier::Hot, GIB); 3];
+        tiers.extend([(StorageTier::Cold, GIB); 6]);
+        let (_pool, _disks, storage) = setup_storage_with_tiers(&tiers);
+
+        // New data starts out cold, on the slow tier
+        let file = storage.create_file(1, "report.bin".to_string()).unwrap();
+        storage.write_file(file.ino, b"quarterly numbers", 0).unwrap();
+        let cold_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        assert_eq!(extent_disk_tiers(&storage, &cold_uuid), vec![StorageTier::Cold; 3]);
+
+        // Reading it often enough makes lazy migration rewrite it onto the fast tier
+        let (ino, extent_uuid) = write_hot_extent(&storage, "dashboard.bin", 60);
+        assert_eq!(extent_disk_tiers(&storage, &extent_uuid), vec![StorageTier::Hot; 3]);
+        let hot = storage.get_hot_extents().unwrap();
+        assert_eq!(hot[0].uuid, extent_uuid);
+        assert_eq!(storage.extent_tier(&hot[0]), Some(StorageTier::Hot));
+        assert_eq!(storage.run_tier_pass(&TierPassConfig::default()).unwrap().fragments_moved, 0);
+
+        // Not touched for a week
+        {
+            let metadata = storage.metadata();
+            let metadata = metadata.write().unwrap();
+            let mut extent = metadata.load_extent(&extent_uuid).unwrap();
+            let week_ago = chrono::Utc::now().timestamp() - 7 * 86400;
+            extent.access_stats.created_at = week_ago;
+            extent.access_stats.last_read = week_ago;
+            extent.access_stats.last_write = week_ago;
+            extent.access_stats.read_count = 0;
+            extent.access_stats.classification = AccessClassification::Cold;
+            metadata.save_extent(&extent).unwrap();
+        }
+
+        let report = storage.run_tier_pass(&TierPassConfig::default()).unwrap();
+        assert_eq!((report.extents_demoted, report.fragments_moved, report.failed), (1, 3, 0));
+        assert_eq!(extent_disk_tiers(&storage, &extent_uuid), vec![StorageTier::Cold; 3]);
+        for disk in storage.get_disks().iter().filter(|d| d.tier == StorageTier::Hot) {
+            assert!((0..3).all(|index| !disk.fragment_path(&extent_uuid, index).exists()));
+            assert_eq!(disk.used_bytes, 0);
+        }
+        assert_eq!(storage.read_file(ino).unwrap().len(), crate::extent::DEFAULT_EXTENT_SIZE);
+
+        let status = storage.tier_status().unwrap();
+        let hot = status.iter().find(|s| s.tier == StorageTier::Hot).unwrap();
+        let cold = status.iter().find(|s| s.tier == StorageTier::Cold).unwrap();
+        assert_eq!((hot.disks, hot.extents, hot.used_bytes), (3, 0, 0));
+        assert_eq!((cold.disks, cold.extents), (6, 2));
+        assert_eq!(status.iter().find(|s| s.tier == StorageTier::Warm).unwrap().disks, 0);
+    }
+
+    #[test]
+    fn test_fast_tier_over_its_watermark_demotes_its_coldest_extents_first() {
+        use crate::tiering::{StorageTier, TierPassConfig};
+
+        const GIB: u64 = 1024 * 1024 * 1024;
+        // Room for two replicas per fast disk, just above the 85% watermark
+        let mut tiers = vec![(StorageTier::Hot, 2_400_000); 3];
+        tiers.extend([(StorageTier::Warm, GIB); 3]);
+        tiers.extend([(StorageTier::Cold, GIB); 6]);
+        let (_pool, _disks, storage) = setup_storage_with_tiers(&tiers);
+        storage.set_space_reserve_percent(0);
+
+        let (_, rarely_read) = write_hot_extent(&storage, "rarely-read", 55);
+        let (_, often_read) = write_hot_extent(&storage, "often-read", 80);
+        assert_eq!(extent_disk_tiers(&storage, &rarely_read), vec![StorageTier::Hot; 3]);
+        assert_eq!(extent_disk_tiers(&storage, &often_read), vec![StorageTier::Hot; 3]);
+        let used = storage.tier_status().unwrap()[0].utilization_percent();
+        assert!(used > 85.0, "fast tier only {:.1}% used", used);
+
+        let report = storage.run_tier_pass(&TierPassConfig::default()).unwrap();
+        assert_eq!((report.extents_demoted, report.pressure_demotions, report.failed), (0, 1, 0));
+        assert_eq!(extent_disk_tiers(&storage, &rarely_read), vec![StorageTier::Warm; 3]);
+        assert_eq!(extent_disk_tiers(&storage, &often_read), vec![StorageTier::Hot; 3]);
+        assert!(storage.tier_status().unwrap()[0].utilization_percent() < 75.0);
+    }
+
+    #[test]
+    fn test_punch_hole_deallocates_covered_extents() {
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+
+        let file = storage.create_file(1, "image.bin".to_string()).unwrap();
+        let data: Vec<u8> = (0..3 * DEFAULT_EXTENT_SIZE).map(|i| (i % 251) as u8 + 1).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+        let before = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        let middle = storage.metadata().read().unwrap().load_extent(&before.extents[1]).unwrap();
+
+        // Second half of extent 0, all of extent 1, first half of extent 2
+        let hole_start = DEFAULT_EXTENT_SIZE / 2;
+        let hole_end = hole_start + 2 * DEFAULT_EXTENT_SIZE;
+        storage
+            .punch_hole(file.ino, hole_start as u64, (hole_end - hole_start) as u64)
+            .unwrap();
+
+        let mut expected = data.clone();
+        expected[hole_start..hole_end].fill(0);
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, data.len() as u64);
+
+        // The fully covered extent is gone, the partial ones were re-encoded
+        let after = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert!(crate::metadata::ExtentMap::is_hole(&after.extents[1]));
+        assert_ne!(after.extents[0], before.extents[0]);
+        assert_ne!(after.extents[2], before.extents[2]);
+        assert!(storage.metadata().read().unwrap().load_extent(&middle.uuid).is_err());
+        for location in &middle.fragment_locations {
+            let disk = storage.get_disks().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+            assert!(!disk.has_fragment(&middle.uuid, location.fragment_index));
+        }
+        assert_eq!(storage.allocated_size(file.ino).unwrap(), 2 * DEFAULT_EXTENT_SIZE as u64);
+
+        // Ranged reads over the hole synthesize zeros
+        let window = storage
+            .read_range(file.ino, (DEFAULT_EXTENT_SIZE - 16) as u64, 32)
+            .unwrap();
+        assert_eq!(window, vec![0u8; 32]);
+        let tail = storage.read_range(file.ino, (hole_end - 4) as u64, 8).unwrap();
+        assert_eq!(tail, expected[hole_end - 4..hole_end + 4]);
+    }
+
+    /// Patterned bytes that differ between neighbouring extents
+    fn patterned(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 + 1).collect()
+    }
+
+    #[test]
+    fn test_small_extent_pool_slots_files_in_its_extent_size() {
+        let extent_size = 256 * 1024;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        storage.set_extent_size(extent_size).unwrap();
+
+        let file = storage.create_file(1, "small.bin".to_string()).unwrap();
+        let mut expected = patterned(4 * extent_size + 100);
+        storage.write_file(file.ino, &expected, 0).unwrap();
+        let map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert_eq!((map.extent_size, map.extents.len()), (extent_size, 5));
+        for uuid in &map.extents {
+            let extent = storage.metadata().read().unwrap().load_extent(uuid).unwrap();
+            assert_eq!(extent.extent_size, extent_size);
+        }
+
+        // Patch across the boundary of slots 1 and 2, then punch out slot 3
+        let patch_at = 2 * extent_size - 8;
+        storage.write_range(file.ino, patch_at as u64, &[0xEE; 16]).unwrap();
+        expected[patch_at..patch_at + 16].fill(0xEE);
+        storage.punch_hole(file.ino, 3 * extent_size as u64, extent_size as u64).unwrap();
+        expected[3 * extent_size..4 * extent_size].fill(0);
+
+        let map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert_eq!(map.extents.len(), 5);
+        assert!(crate::metadata::ExtentMap::is_hole(&map.extents[3]));
+        assert_eq!(storage.allocated_size(file.ino).unwrap(), 3 * extent_size as u64 + 100);
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+        let window = storage.read_range(file.ino, (patch_at - 4) as u64, 2 * extent_size as u64).unwrap();
+        assert_eq!(window, expected[patch_at - 4..patch_at - 4 + 2 * extent_size]);
+    }
+
+    #[test]
+    fn test_large_extent_pool_slots_files_in_its_extent_size() {
+        let extent_size = 16 * 1024 * 1024;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        storage.set_extent_size(extent_size).unwrap();
+        for invalid in [32 * 1024, 3 * 64 * 1024, 128 * 1024 * 1024] {
+            assert!(storage.set_extent_size(invalid).is_err());
+        }
+        assert_eq!(storage.extent_size(), extent_size);
+
+        let file = storage.create_file(1, "large.bin".to_string()).unwrap();
+        let mut expected = patterned(extent_size + 4096);
+        storage.write_file(file.ino, &expected, 0).unwrap();
+        let map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert_eq!((map.extent_size, map.extents.len()), (extent_size, 2));
+
+        // Growing the short last extent stays within its 16 MB slot
+        let grow_at = extent_size + 4 * 1024 * 1024;
+        storage.write_range(file.ino, grow_at as u64, b"tail").unwrap();
+        expected.resize(grow_at, 0);
+        expected.extend_from_slice(b"tail");
+        let map = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert_eq!(map.extents.len(), 2);
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, expected.len() as u64);
+
+        let window = storage.read_range(file.ino, (extent_size - 10) as u64, 20).unwrap();
+        assert_eq!(window, expected[extent_size - 10..extent_size + 10]);
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_files_keep_their_extent_size_when_the_pool_size_differs() {
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+        use crate::metadata::ExtentMap;
+        let small = 64 * 1024;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let load_map = |ino: u64| storage.metadata().read().unwrap().load_extent_map(ino).unwrap();
+
+        // Written before the pool had an extent size of its own
+        let old = storage.create_file(1, "old.bin".to_string()).unwrap();
+        let mut old_data = patterned(2 * DEFAULT_EXTENT_SIZE + 500);
+        storage.write_file(old.ino, &old_data, 0).unwrap();
+        let legacy: ExtentMap = serde_json::from_str(r#"{"ino": 9, "extents": []}"#).unwrap();
+        assert_eq!(legacy.extent_size, DEFAULT_EXTENT_SIZE);
+
+        storage.set_extent_size(small).unwrap();
+        let new = storage.create_file(1, "new.bin".to_string()).unwrap();
+        let new_data = patterned(3 * small + 7);
+        storage.write_file(new.ino, &new_data, 0).unwrap();
+        assert_eq!((load_map(new.ino).extent_size, load_map(new.ino).extents.len()), (small, 4));
+
+        // Partial writes keep the old file's 1 MB slots
+        storage.write_range(old.ino, (DEFAULT_EXTENT_SIZE - 10) as u64, &[0xAB; 20]).unwrap();
+        old_data[DEFAULT_EXTENT_SIZE - 10..DEFAULT_EXTENT_SIZE + 10].fill(0xAB);
+        assert_eq!((load_map(old.ino).extent_size, load_map(old.ino).extents.len()), (DEFAULT_EXTENT_SIZE, 3));
+        assert_eq!(storage.read_file(old.ino).unwrap(), old_data);
+        assert_eq!(storage.read_file(new.ino).unwrap(), new_data);
+        let window = storage.read_range(old.ino, (DEFAULT_EXTENT_SIZE - 20) as u64, 40).unwrap();
+        assert_eq!(window, old_data[DEFAULT_EXTENT_SIZE - 20..DEFAULT_EXTENT_SIZE + 20]);
+
+        // A file without data yet, and a full rewrite, take the pool's size
+        let sparse = storage.create_file(1, "sparse.bin".to_string()).unwrap();
+        storage.write_range(sparse.ino, small as u64 + 1, b"x").unwrap();
+        assert_eq!((load_map(sparse.ino).extent_size, load_map(sparse.ino).extents.len()), (small, 2));
+        let rewritten = patterned(small + 1);
+        storage.write_file(old.ino, &rewritten, 0).unwrap();
+        assert_eq!((load_map(old.ino).extent_size, load_map(old.ino).extents.len()), (small, 2));
+        assert_eq!(storage.read_file(old.ino).unwrap(), rewritten);
+    }
+
+    #[test]
+    fn test_coalesce_rewrites_small_extents_into_the_pool_extent_size() {
+        use crate::defrag::{DefragConfig, DefragmentationEngine};
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+        use crate::metadata::ExtentMap;
+        let small = 64 * 1024;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let load_map = |ino: u64| storage.metadata().read().unwrap().load_extent_map(ino).unwrap();
+        storage.set_extent_size(small).unwrap();
+
+        // 20 slots of 64 KB, one of them punched out, and a second file of one slot
+        let file = storage.create_file(1, "pieces.bin".to_string()).unwrap();
+        let mut expected = patterned(20 * small + 300);
+        storage.write_file(file.ino, &expected, 0).unwrap();
+        storage.punch_hole(file.ino, 17 * small as u64, small as u64).unwrap();
+        expected[17 * small..18 * small].fill(0);
+        let single = storage.create_file(1, "single.bin".to_string()).unwrap();
+        storage.write_file(single.ino, b"just one", 0).unwrap();
+        assert_eq!(load_map(file.ino).data_extents().count(), 20);
+
+        storage.set_extent_size(DEFAULT_EXTENT_SIZE).unwrap();
+        let engine = DefragmentationEngine::new(DefragConfig::default());
+        let analysis = engine.analyze_fragmentation(&storage).unwrap();
+        assert_eq!(analysis.coalesce_candidates, 1);
+        assert_eq!((analysis.files[0].ino, analysis.files[0].extents), (file.ino, 20));
+
+        let (removed, bytes) = storage.coalesce_extents(file.ino).unwrap();
+        assert_eq!((removed, bytes), (18, expected.len() as u64));
+        let map = load_map(file.ino);
+        assert_eq!((map.extent_size, map.extents.len()), (DEFAULT_EXTENT_SIZE, 2));
+        assert!(!ExtentMap::is_hole(&map.extents[1]));
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+        let window = storage.read_range(file.ino, (DEFAULT_EXTENT_SIZE - 10) as u64, 20).unwrap();
+        assert_eq!(window, expected[DEFAULT_EXTENT_SIZE - 10..DEFAULT_EXTENT_SIZE + 10]);
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, expected.len() as u64);
+
+        // Coalesced files, and files too small to gain from it, are left alone
+        assert_eq!(storage.coalesce_extents(file.ino).unwrap(), (0, 0));
+        assert_eq!(engine.analyze_fragmentation(&storage).unwrap().coalesce_candidates, 0);
+        assert_eq!(storage.read_file(single.ino).unwrap(), b"just one");
+    }
+
+    #[test]
+    fn test_zero_range_extends_file_sparsely() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+
+        let file = storage.create_file(1, "grow.bin".to_string()).unwrap();
+        storage.write_file(file.ino, b"hello world", 0).unwrap();
+
+        storage.zero_range(file.ino, 6, 100, true).unwrap();
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"hello \0\0\0\0\0");
+
+        storage.zero_range(file.ino, 0, 4096, false).unwrap();
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, 4096);
+        assert_eq!(storage.read_file(file.ino).unwrap(), vec![0u8; 4096]);
+        assert_eq!(storage.allocated_size(file.ino).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_parent_times_and_link_counts_follow_entries() {
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let a = storage.create_dir(1, "a".to_string()).unwrap();
+        let b = storage.create_dir(1, "b".to_string()).unwrap();
+        let parent_time = |ino: u64| {
+            let inode = storage.get_inode(ino).unwrap();
+            ((inode.mtime, inode.mtime_nsec), (inode.ctime, inode.ctime_nsec))
+        };
+        let set_old_times = |ino: u64| {
+            let mut inode = storage.get_inode(ino).unwrap();
+            inode.set_mtime((1000, 0));
+            inode.set_ctime((1000, 0));
+            storage.update_inode(&inode).unwrap();
+        };
+        let moved_on = |ino: u64| {
+            let (mtime, ctime) = parent_time(ino);
+            mtime > (1000, 0) && ctime > (1000, 0)
+        };
+        assert_eq!(storage.get_inode(1).unwrap().nlink(), 4);
+        assert_eq!(storage.get_inode(a.ino).unwrap().nlink(), 2);
+
+        // Creating a file moves the parent's times in memory; the flush persists them
+        set_old_times(a.ino);
+        let file = storage.create_file(a.ino, "f".to_string()).unwrap();
+        assert!(moved_on(a.ino));
+        assert_eq!(storage.get_inode(a.ino).unwrap().nlink(), 2, "files add no link");
+        let persisted = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap().load_inode(a.ino).unwrap();
+        assert_eq!(persisted.mtime, 1000);
+        storage.flush_inode_times().unwrap();
+        let persisted = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap().load_inode(a.ino).unwrap();
+        assert!(persisted.mtime > 1000);
+
+        set_old_times(a.ino);
+        let sub = storage.create_dir(a.ino, "sub".to_string()).unwrap();
+        assert!(moved_on(a.ino));
+        assert_eq!(storage.get_inode(a.ino).unwrap().nlink(), 3);
+
+        set_old_times(a.ino);
+        storage.delete_file(file.ino).unwrap();
+        assert!(moved_on(a.ino));
+
+        // Moving the subdirectory moves its link and both parents' times; a stale copy keeps the count
+        let stale_b = storage.get_inode(b.ino).unwrap();
+        set_old_times(a.ino);
+        set_old_times(b.ino);
+        let mut moved = storage.get_inode(sub.ino).unwrap();
+        moved.parent_ino = b.ino;
+        storage.update_inode(&moved).unwrap();
+        assert!(moved_on(a.ino) && moved_on(b.ino));
+        assert_eq!((storage.get_inode(a.ino).unwrap().nlink(), storage.get_inode(b.ino).unwrap().nlink()), (2, 3));
+        storage.update_inode(&stale_b).unwrap();
+        assert_eq!(storage.get_inode(b.ino).unwrap().nlink(), 3);
+
+        set_old_times(b.ino);
+        storage.delete_file(sub.ino).unwrap();
+        assert!(moved_on(b.ino));
+        assert_eq!(storage.get_inode(b.ino).unwrap().nlink(), 2);
+        assert_eq!(storage.get_inode(1).unwrap().nlink(), 4);
+    }
+
+    #[test]
+    fn test_dir_index_follows_create_rename_and_delete() {
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+
+        let docs = storage.create_dir(1, "docs".to_string()).unwrap();
+        let a = storage.create_file(docs.ino, "a.txt".to_string()).unwrap();
+        let mut b = storage.create_file(docs.ino, "b.txt".to_string()).unwrap();
+
+        // Rename b.txt into the root as c.txt
+        b.parent_ino = 1;
+        b.name = "c.txt".into();
+        storage.update_inode(&b).unwrap();
+
+        assert!(storage.find_child(docs.ino, "b.txt").unwrap().is_none());
+        assert_eq!(storage.find_child(1, "c.txt").unwrap().unwrap().ino, b.ino);
+        let names: Vec<String> = storage.list_directory(1).unwrap().into_iter().map(|i| i.name.to_string()).collect();
+        assert_eq!(names, vec!["c.txt", "docs"]);
+
+        storage.delete_file(a.ino).unwrap();
+        assert!(storage.list_directory(docs.ino).unwrap().is_empty());
+
+        // The index is persisted and agrees with the inode records after reopening
+        drop(storage);
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        assert_eq!(metadata.find_child(1, "c.txt").unwrap().unwrap().ino, b.ino);
+        assert!(metadata.check_dir_index(false).unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_dir_index_rebuilt_for_old_pool_and_repaired() {
+        let pool_dir = test_pool_dir();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        for (ino, name) in [(2, "one"), (3, "two"), (4, "three")] {
+            metadata
+                .save_inode(&crate::metadata::Inode::new_file(ino, 1, name.to_string()))
+                .unwrap();
+        }
+        drop(metadata);
+
+        // A pool from before the index existed has only inode records
+        for file in ["dir_index.btree", "dir_index.btlog"] {
+            let _ = std::fs::remove_file(pool_dir.path().join("metadata").join(file));
+        }
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        assert_eq!(metadata.find_child(1, "two").unwrap().unwrap().ino, 3);
+        assert_eq!(metadata.list_directory(1).unwrap().len(), 3);
+
+        // Diverge the index: lose one entry and add one that has no inode
+        metadata.dir_index.remove(&(1, crate::metadata::FileName::from("one"))).unwrap();
+        metadata.dir_index.insert((1, crate::metadata::FileName::from("ghost")), 99).unwrap();
+
+        let report = metadata.check_dir_index(false).unwrap();
+        assert_eq!(report.expected_entries, 3);
+        assert_eq!(report.missing.len(), 1);
+        assert_eq!(report.missing[0].name, "one");
+        assert_eq!(report.stale.len(), 1);
+        assert_eq!(report.stale[0].ino, 99);
+        assert!(!report.repaired);
+        assert!(metadata.find_child(1, "one").unwrap().is_none());
+        assert!(metadata.find_child(1, "ghost").unwrap().is_none());
+
+        assert!(metadata.check_dir_index(true).unwrap().repaired);
+        assert!(metadata.check_dir_index(false).unwrap().is_consistent());
+        assert_eq!(metadata.find_child(1, "one").unwrap().unwrap().ino, 2);
+    }
+
+    #[test]
+    fn test_clone_shares_extents_and_writes_copy_on_write() {
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+        use crate::usage::UsageScanner;
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let usage = || {
+            let metadata = storage.metadata();
+            let root = UsageScanner::new(&metadata.read().unwrap(), Some(0), 0).unwrap().scan("/").unwrap().remove(0);
+            (root.physical_bytes, root.shared_bytes)
+        };
+        let extent_count = || storage.metadata().read().unwrap().list_all_extents().unwrap().len();
+
+        let original = storage.create_file(1, "take1.mov".to_string()).unwrap();
+        let data = patterned(16 * DEFAULT_EXTENT_SIZE);
+        storage.write_file(original.ino, &data, 0).unwrap();
+        let (physical, _) = usage();
+        let copy = storage.create_file(1, "take1-edit.mov".to_string()).unwrap();
+        storage.write_file(copy.ino, b"replaced by the clone", 0).unwrap();
+        storage.clone_file(original.ino, copy.ino).unwrap();
+
+        // Nothing new was written: every extent is shared and counted once
+        assert_eq!(usage(), (0, physical));
+        assert_eq!(extent_count(), 16);
+        assert_eq!(storage.get_inode(copy.ino).unwrap().size, data.len() as u64);
+        assert_eq!(storage.read_file(copy.ino).unwrap(), data);
+
+        // A write to the copy replaces only the extent it touches
+        let mut edited = data.clone();
+        edited[3 * DEFAULT_EXTENT_SIZE + 10..3 * DEFAULT_EXTENT_SIZE + 20].fill(0xEE);
+        storage.write_range(copy.ino, 3 * DEFAULT_EXTENT_SIZE as u64 + 10, &[0xEE; 10]).unwrap();
+        assert_eq!(extent_count(), 17);
+        // Reads may have migrated extents since, so sizes are taken from their records
+        let on_disk = |uuid: &uuid::Uuid| {
+            let extent = storage.metadata().read().unwrap().load_extent(uuid).unwrap();
+            extent.redundancy.fragment_size(extent.stored_size()) as u64 * extent.fragment_locations.len() as u64
+        };
+        let original_map = storage.metadata().read().unwrap().load_extent_map(original.ino).unwrap();
+        let copy_map = storage.metadata().read().unwrap().load_extent_map(copy.ino).unwrap();
+        let shared: u64 = original_map.extents.iter().enumerate().filter(|(i, _)| *i != 3).map(|(_, uuid)| on_disk(uuid)).sum();
+        assert_eq!(usage(), (on_disk(&original_map.extents[3]) + on_disk(&copy_map.extents[3]), shared));
+
+        // Releasing, or replaying the release of, an extent both files still list frees nothing
+        {
+            let metadata = storage.metadata();
+            let metadata = metadata.read().unwrap();
+            for _ in 0..2 {
+                metadata.release_extent(&original_map.extents[0]).unwrap();
+            }
+            assert_eq!(metadata.extent_holders(&original_map.extents[0]).unwrap(), vec![original.ino, copy.ino]);
+            assert!(metadata.released_extents().unwrap().is_empty());
+        }
+        assert_eq!(storage.read_file(original.ino).unwrap(), data);
+        assert_eq!(storage.read_file(copy.ino).unwrap(), edited);
+
+        // Deleting one copy keeps the extents the other still uses
+        storage.delete_file(original.ino).unwrap();
+        assert_eq!(extent_count(), 16);
+        assert_eq!(storage.read_file(copy.ino).unwrap(), edited);
+        let map = storage.metadata().read().unwrap().load_extent_map(copy.ino).unwrap();
+        assert_eq!(storage.metadata().read().unwrap().extent_holders(&map.extents[0]).unwrap(), vec![copy.ino]);
+        storage.delete_file(copy.ino).unwrap();
+        assert_eq!(extent_count(), 0);
+        assert!(std::fs::read_dir(pool_dir.path().join("extent_refs")).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_du_reports_logical_physical_and_shared_usage_per_directory() {
+        use crate::usage::UsageScanner;
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+
+        let projects = storage.create_dir(1, "projects".to_string()).unwrap();
+        let alpha = storage.create_dir(projects.ino, "alpha".to_string()).unwrap();
+        let deep = storage.create_dir(alpha.ino, "deep".to_string()).unwrap();
+        let small = storage.create_file(alpha.ino, "small.txt".to_string()).unwrap();
+        storage.write_file(small.ino, b"hello world", 0).unwrap();
+        let big = storage.create_file(deep.ino, "big.bin".to_string()).unwrap();
+        storage.write_file(big.ino, &vec![7u8; 3 * 1024 * 1024], 0).unwrap();
+
+        // A second file referencing the small file's extent, as a reflink would
+        let mut clone = storage.create_file(1, "clone.txt".to_string()).unwrap();
+        clone.size = 11;
+        storage.update_inode(&clone).unwrap();
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let small_map = metadata.load_extent_map(small.ino).unwrap();
+        metadata
+            .save_extent_map(&crate::metadata::ExtentMap { ino: clone.ino, ..small_map.clone() })
+            .unwrap();
+
+        let on_disk = |ino: u64| -> (u64, u64) {
+            metadata.load_extent_map(ino).unwrap().data_extents().fold((0, 0), |(bytes, fragments), uuid| {
+                let extent = metadata.load_extent(uuid).unwrap();
+                let count = extent.fragment_locations.len() as u64;
+                (bytes + extent.redundancy.fragment_size(extent.stored_size()) as u64 * count, fragments + count)
+            })
+        };
+        let (big_bytes, big_fragments) = on_disk(big.ino);
+        let (small_bytes, small_fragments) = on_disk(small.ino);
+
+        let rows = UsageScanner::new(&metadata, Some(1), 0).unwrap().scan("/").unwrap();
+        let paths: Vec<&str> = rows.iter().map(|row| row.path.to_str().unwrap()).collect();
+        assert_eq!(paths, vec!["/", "/projects"]);
+
+        let root = &rows[0];
+        assert_eq!(root.files, 3);
+        assert_eq!(root.logical_bytes, 3 * 1024 * 1024 + 2 * 11);
+        assert_eq!(root.physical_bytes, big_bytes);
+        assert!(root.physical_bytes > 3 * 1024 * 1024);
+        // Referenced twice but counted once
+        assert_eq!(root.shared_bytes, small_bytes);
+        assert_eq!(root.fragments, big_fragments + small_fragments);
+
+        let rows = UsageScanner::new(&metadata, None, 0).unwrap().scan("/projects/alpha").unwrap();
+        let paths: Vec<&str> = rows.iter().map(|row| row.path.to_str().unwrap()).collect();
+        assert_eq!(paths, vec!["/projects/alpha", "/projects/alpha/deep"]);
+        assert_eq!(rows[1].depth, 1);
+        assert_eq!((rows[1].files, rows[1].physical_bytes, rows[1].shared_bytes), (1, big_bytes, 0));
+        assert_eq!(rows[0].shared_bytes, small_bytes);
+    }
+
+    #[test]
+    fn test_non_utf8_names_are_kept_byte_for_byte() {
+        use crate::metadata::FileName;
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let latin1 = FileName::from_bytes(b"caf\xe9.txt".to_vec());
+        let dir = storage.create_dir(1, b"r\xe9sum\xe9s".to_vec()).unwrap();
+        let file = storage.create_file(dir.ino, latin1.clone()).unwrap();
+        storage.write_file(file.ino, b"espresso", 0).unwrap();
+        // A UTF-8 name that shows the same once made lossy is still a different entry
+        storage.create_file(dir.ino, "caf\u{fffd}.txt").unwrap();
+
+        let found = storage.find_child(dir.ino, b"caf\xe9.txt").unwrap().unwrap();
+        assert_eq!((found.ino, &found.name), (file.ino, &latin1));
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"espresso");
+        let names: Vec<FileName> = storage.list_directory(dir.ino).unwrap().into_iter().map(|i| i.name).collect();
+        assert_eq!(names, [latin1.clone(), FileName::from("caf\u{fffd}.txt")]);
+        let path = storage.metadata().read().unwrap().path_of(file.ino).unwrap();
+        assert_eq!(path.as_bytes(), b"/r\xe9sum\xe9s/caf\xe9.txt");
+
+        // Rename to another non-UTF-8 name
+        let mut renamed = found.clone();
+        renamed.name = FileName::from_bytes(b"caf\xc3.txt".to_vec());
+        storage.update_inode(&renamed).unwrap();
+        assert!(storage.find_child(dir.ino, b"caf\xe9.txt").unwrap().is_none());
+        assert_eq!(storage.find_child(dir.ino, b"caf\xc3.txt").unwrap().unwrap().ino, file.ino);
+
+        // Records and the directory index read back the same bytes after a reopen
+        drop(storage);
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let reopened = metadata.resolve_path(b"/r\xe9sum\xe9s/caf\xc3.txt").unwrap();
+        assert_eq!(reopened.ino, file.ino);
+        assert_eq!(reopened.name.as_bytes(), b"caf\xc3.txt");
+        assert!(metadata.check_dir_index(false).unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_case_insensitive_pool_resolves_names_ignoring_case() {
+        let pool_dir = test_pool_dir();
+        let mut pool = crate::disk::DiskPool::new();
+        pool.metadata_backend = test_metadata_backend();
+        pool.case_insensitive = true;
+        pool.save(pool_dir.path()).unwrap();
+        let disk_dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| Disk::new(td.path().to_path_buf()).unwrap())
+            .collect();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+
+        let docs = storage.create_dir(1, "Docs".to_string()).unwrap();
+        let report = storage.create_file(docs.ino, "Report.docx".to_string()).unwrap();
+        storage.create_file(docs.ino, "ÉTÉ.txt".to_string()).unwrap();
+
+        // Any casing finds the entry, which keeps the casing it was created with
+        let found = storage.find_child(1, "DOCS").unwrap().unwrap();
+        assert_eq!(found.ino, docs.ino);
+        assert_eq!(found.name, "Docs");
+        assert_eq!(storage.find_child(docs.ino, "report.DOCX").unwrap().unwrap().ino, report.ino);
+        assert!(storage.find_child(docs.ino, "été.txt").unwrap().is_some());
+        let names: Vec<String> = storage.list_directory(docs.ino).unwrap().into_iter().map(|i| i.name.to_string()).collect();
+        assert_eq!(names, vec!["Report.docx", "ÉTÉ.txt"]);
+
+        // A case-only rename keeps the entry and changes the listed casing
+        let mut renamed = report.clone();
+        renamed.name = "REPORT.docx".into();
+        storage.update_inode(&renamed).unwrap();
+        assert_eq!(storage.find_child(docs.ino, "report.docx").unwrap().unwrap().name, "REPORT.docx");
+        assert_eq!(storage.list_directory(docs.ino).unwrap().len(), 2);
+
+        // The folded index survives a reopen and agrees with the inode records
+        drop(storage);
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        assert_eq!(metadata.find_child(docs.ino, "Report.Docx").unwrap().unwrap().ino, report.ino);
+        assert!(metadata.check_dir_index(false).unwrap().is_consistent());
+    }
+
+    #[test]
+    fn test_switching_case_mode_rebuilds_the_dir_index() {
+        let pool_dir = test_pool_dir();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        metadata
+            .save_inode(&crate::metadata::Inode::new_file(2, 1, "Mixed.TXT".to_string()))
+            .unwrap();
+        assert!(metadata.find_child(1, "mixed.txt").unwrap().is_none());
+        drop(metadata);
+
+        let mut pool = crate::disk::DiskPool::new();
+        pool.metadata_backend = test_metadata_backend();
+        pool.case_insensitive = true;
+        pool.save(pool_dir.path()).unwrap();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        assert_eq!(metadata.find_child(1, "mixed.txt").unwrap().unwrap().ino, 2);
+        assert!(metadata.check_dir_index(false).unwrap().is_consistent());
+        drop(metadata);
+
+        pool.case_insensitive = false;
+        pool.save(pool_dir.path()).unwrap();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        assert!(metadata.find_child(1, "mixed.txt").unwrap().is_none());
+        assert_eq!(metadata.find_child(1, "Mixed.TXT").unwrap().unwrap().ino, 2);
+    }
+
+    /// Lookup cost should not grow with directory size
+    #[test]
+    fn test_sequential_small_writes_coalesce_into_whole_extents() {
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+        let config = crate::write_optimizer::WriteBufferConfig {
+            flush_interval: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let (_pool_dir, _disk_dirs, storage) = setup_buffered_storage(config);
+        let file = storage.create_file(1, "seq.bin".to_string()).unwrap();
+
+        let data: Vec<u8> = (0..2 * DEFAULT_EXTENT_SIZE + 8192).map(|i| (i % 251) as u8).collect();
+        for (i, chunk) in data.chunks(4096).enumerate() {
+            storage.buffered_write(file.ino, (i * 4096) as u64, chunk).unwrap();
+        }
+
+        // Each full extent was encoded once; the 8 KiB tail is still buffered.
+        // The first write and the first after each extent flush start new runs.
+        let snapshot = storage.metrics().snapshot();
+        assert_eq!(snapshot.write_buffer_coalesced, (data.len() / 4096 - 3) as u64);
+        assert_eq!(snapshot.write_buffer_flushes_extent_full, 2);
+        let stored = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert_eq!(stored.extents.len(), 2);
+
+        // Reads and stat see the buffered tail before it is flushed
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, data.len() as u64);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+        assert_eq!(
+            storage.read_range(file.ino, 2 * DEFAULT_EXTENT_SIZE as u64 - 100, 200).unwrap(),
+            &data[2 * DEFAULT_EXTENT_SIZE - 100..2 * DEFAULT_EXTENT_SIZE + 100]
+        );
+
+        storage.flush_file(file.ino).unwrap();
+        assert_eq!(storage.metrics().snapshot().write_buffer_flushes_release, 1);
+        let stored = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap();
+        assert_eq!(stored.extents.len(), 3);
+        assert_eq!(storage.metadata().read().unwrap().load_inode(file.ino).unwrap().size, data.len() as u64);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+    }
+
+    #[test]
+    fn test_write_buffer_flushes_on_fsync_seek_and_memory_pressure() {
+        let config = crate::write_optimizer::WriteBufferConfig {
+            memory_budget: 16 * 1024,
+            flush_interval: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let (_pool_dir, _disk_dirs, storage) = setup_buffered_storage(config);
+        let a = storage.create_file(1, "a.bin".to_string()).unwrap();
+        let b = storage.create_file(1, "b.bin".to_string()).unwrap();
+        let c = storage.create_file(1, "c.bin".to_string()).unwrap();
+
+        // A write away from the buffered run flushes the run first
+        storage.buffered_write(a.ino, 0, &[1u8; 8192]).unwrap();
+        storage.buffered_write(a.ino, 100 * 1024, &[2u8; 4096]).unwrap();
+        assert_eq!(storage.metrics().snapshot().write_buffer_flushes_non_sequential, 1);
+
+        storage.sync_inode(a.ino).unwrap();
+        assert_eq!(storage.metrics().snapshot().write_buffer_flushes_fsync, 1);
+        let mut expected = vec![1u8; 8192];
+        expected.resize(100 * 1024, 0);
+        expected.extend_from_slice(&[2u8; 4096]);
+        assert_eq!(storage.read_file(a.ino).unwrap(), expected);
+
+        // Going over budget writes out the least recently written file
+        storage.buffered_write(b.ino, 0, &[3u8; 12 * 1024]).unwrap();
+        storage.buffered_write(c.ino, 0, &[4u8; 8 * 1024]).unwrap();
+        assert_eq!(storage.metrics().snapshot().write_buffer_flushes_memory_pressure, 1);
+        let stored_b = storage.metadata().read().unwrap().load_inode(b.ino).unwrap();
+        assert_eq!(stored_b.size, 12 * 1024);
+        assert_eq!(storage.read_file(c.ino).unwrap(), vec![4u8; 8 * 1024]);
+    }
+
+    #[test]
+    fn test_write_buffer_timer_flush_and_flush_on_drop() {
+        let config = crate::write_optimizer::WriteBufferConfig {
+            flush_interval: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let (_pool_dir, _disk_dirs, storage) = setup_buffered_storage(config);
+        let file = storage.create_file(1, "idle.bin".to_string()).unwrap();
+        storage.buffered_write(file.ino, 0, b"idle data").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while storage.metrics().snapshot().write_buffer_flushes_timer == 0 {
+            assert!(Instant::now() < deadline, "timer never flushed the idle run");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let stored = storage.metadata().read().unwrap().load_inode(file.ino).unwrap();
+        assert_eq!(stored.size, 9);
+
+        // Dropping the engine writes out whatever is still buffered
+        let config = crate::write_optimizer::WriteBufferConfig {
+            flush_interval: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let (pool_dir, disk_dirs, storage) = setup_buffered_storage(config);
+        let file = storage.create_file(1, "pending.bin".to_string()).unwrap();
+        storage.buffered_write(file.ino, 0, b"pending").unwrap();
+        drop(storage);
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let disks = disk_dirs.iter().map(|td| Disk::load(td.path()).unwrap()).collect();
+        let reopened = StorageEngine::new(metadata, disks);
+        assert_eq!(reopened.read_file(file.ino).unwrap(), b"pending");
+    }
+
+    #[test]
+    fn test_write_file_at_offset_patches_range() {
+        let (_pool_dir, _disk_dirs, storage) = setup_storage_with_disks(4);
+        let file = storage.create_file(1, "patch.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[7u8; 10_000], 0).unwrap();
+
+        storage.write_file(file.ino, b"hello", 5_000).unwrap();
+        storage.write_file(file.ino, b"tail", 12_000).unwrap();
+
+        let mut expected = vec![7u8; 10_000];
+        expected[5_000..5_005].copy_from_slice(b"hello");
+        expected.resize(12_000, 0);
+        expected.extend_from_slice(b"tail");
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, 12_004);
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_find_child_latency_flat_in_large_directory() {
+        let pool_dir = test_pool_dir();
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+
+        let time_lookups = |metadata: &MetadataManager, count: u64| -> Duration {
+            let start = Instant::now();
+            for i in 0..1000 {
+                let name = format!("file_{:06}", (i * 7919) % count);
+                assert!(metadata.find_child(1, &name).unwrap().is_some());
+            }
+            start.elapsed()
+        };
+
+        let mut small = Duration::ZERO;
+        for i in 0..50_000u64 {
+            metadata
+                .save_inode(&crate::metadata::Inode::new_file(i + 2, 1, format!("file_{:06}", i)))
+                .unwrap();
+            if i + 1 == 1_000 {
+                small = time_lookups(&metadata, 1_000);
+            }
+        }
+        let large = time_lookups(&metadata, 50_000);
+
+        println!("1000 lookups: {:?} with 1k entries, {:?} with 50k entries", small, large);
+        assert!(
+            large < small * 5,
+            "lookup latency grew from {:?} to {:?}",
+            small,
+            large
+        );
+    }
+
+    /// Empty 16 MiB disks to add to a pool after it was filled
+    fn empty_disks(count: usize) -> (Vec<TempDir>, Vec<Disk>) {
+        let dirs: Vec<TempDir> = (0..count).map(|_| tempfile::tempdir().unwrap()).collect();
+        let disks = dirs
+            .iter()
+            .map(|td| {
+                let mut disk = Disk::new(td.path().to_path_buf()).unwrap();
+                disk.capacity_bytes = 16 * 1024 * 1024;
+                disk
+            })
+            .collect();
+        (dirs, disks)
+    }
+
+    #[test]
+    fn test_rebalance_spreads_fragments_onto_new_disks() {
+        use crate::disk::DiskHealth::Healthy;
+        use crate::rebalance::{RebalanceConfig, RebalanceProgress, Rebalancer};
+        use std::sync::atomic::AtomicBool;
+        const MIB: u64 = 1024 * 1024;
+
+        // Three full-ish disks hold every replica; two empty disks join afterwards
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_usage(&[(16 * MIB, 0, Healthy); 3]);
+        let mut files = Vec::new();
+        for i in 0..20u8 {
+            let file = storage.create_file(1, format!("f{}", i)).unwrap();
+            let data = vec![i; 64 * 1024];
+            storage.write_file(file.ino, &data, 0).unwrap();
+            files.push((file.ino, data));
+        }
+        let (_new_dirs, new_disks) = empty_disks(2);
+        let mut disks = storage.get_disks();
+        disks.extend(new_disks);
+        assert!(Rebalancer::spread(&disks) > 7.0);
+
+        let rebalancer = Rebalancer::new(pool_dir.path().to_path_buf());
+        let stop = AtomicBool::new(false);
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+
+        // A dry run reports the movement per disk but leaves every fragment in place
+        let dry_run = RebalanceConfig { target_spread: 2.0, dry_run: true, ..Default::default() };
+        let plan = rebalancer.run(&metadata, &mut disks, &dry_run, &stop).unwrap();
+        assert!(!plan.moves.is_empty());
+        assert!(plan.spread_after < 2.0);
+        let bytes_out: u64 = plan.per_disk.iter().map(|d| d.bytes_out).sum();
+        let bytes_in: u64 = plan.per_disk.iter().map(|d| d.bytes_in).sum();
+        assert_eq!(bytes_out, bytes_in);
+        assert!(plan.per_disk[3..].iter().all(|d| d.bytes_in > 0 && d.bytes_out == 0));
+        assert!(disks[3..].iter().all(|d| d.used_bytes == 0));
+        assert!(!pool_dir.path().join(crate::rebalance::PROGRESS_FILE).exists());
+
+        let config = RebalanceConfig { target_spread: 2.0, ..Default::default() };
+        let report = rebalancer.run(&metadata, &mut disks, &config, &stop).unwrap();
+        assert_eq!(report.moves, plan.moves);
+        assert_eq!(report.skipped, 0);
+        assert!(report.spread_after < 2.0);
+        assert!(RebalanceProgress::load(pool_dir.path()).unwrap().is_none());
+
+        // Every extent still has three replicas on distinct disks
+        for extent in metadata.list_all_extents().unwrap() {
+            let mut holders: Vec<_> = extent.fragment_locations.iter().map(|l| l.disk_uuid).collect();
+            holders.sort();
+            holders.dedup();
+            assert_eq!(holders.len(), 3);
+        }
+        drop(metadata);
+        drop(storage);
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        for extent in storage.metadata().read().unwrap().list_all_extents().unwrap() {
+            assert!(fragments_intact(&storage, &extent.uuid));
+        }
+        for (ino, data) in files {
+            assert_eq!(storage.read_file(ino).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_rebalance_resumes_interrupted_move() {
+        use crate::disk::DiskHealth::Healthy;
+        use crate::rebalance::{FragmentMove, RebalanceConfig, RebalanceProgress, Rebalancer};
+        use std::sync::atomic::AtomicBool;
+        const MIB: u64 = 1024 * 1024;
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_usage(&[(16 * MIB, 0, Healthy); 3]);
+        let file = storage.create_file(1, "moving.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"fragment caught mid-move", 0).unwrap();
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        let (_new_dirs, new_disks) = empty_disks(1);
+        let mut disks = storage.get_disks();
+        disks.extend(new_disks);
+
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let extent = metadata.load_extent(&extent_uuid).unwrap();
+        let from_disk = extent.fragment_locations[0].disk_uuid;
+        let to_disk = disks[3].uuid;
+        let fragment_index = extent.fragment_locations[0].fragment_index;
+        let bytes = disks.iter().find(|d| d.uuid == from_disk).unwrap().read_fragment(&extent_uuid, fragment_index).unwrap();
+        let mv = FragmentMove { extent_uuid, fragment_index, from_disk, to_disk, bytes: bytes.len() as u64 };
+
+        // One small extent leaves nothing to plan, so each run only reconciles the interrupted move
+        let rebalancer = Rebalancer::new(pool_dir.path().to_path_buf());
+        let stop = AtomicBool::new(false);
+        let config = RebalanceConfig { target_spread: 0.0, ..Default::default() };
+        let has_copy = |disks: &[Disk], uuid| disks.iter().find(|d| d.uuid == uuid).unwrap().has_fragment(&extent_uuid, fragment_index);
+
+        // Copy written but never recorded: the new copy is rolled back
+        disks[3].write_fragment(&extent_uuid, fragment_index, &bytes).unwrap();
+        let progress = RebalanceProgress { in_flight: Some(mv.clone()), fragments_moved: 4, bytes_moved: 400 };
+        progress.save(pool_dir.path()).unwrap();
+        let report = rebalancer.run(&metadata, &mut disks, &config, &stop).unwrap();
+        assert!(report.resumed && report.moves.is_empty());
+        assert_eq!(report.total_fragments_moved, 4);
+        assert!(RebalanceProgress::load(pool_dir.path()).unwrap().is_none());
+        assert!(!has_copy(&disks, to_disk));
+        assert!(has_copy(&disks, from_disk));
+        assert_eq!(metadata.load_extent(&extent_uuid).unwrap().fragment_locations[0].disk_uuid, from_disk);
+
+        // Copy recorded but old copy not yet deleted: the move is completed
+        disks[3].write_fragment(&extent_uuid, fragment_index, &bytes).unwrap();
+        let mut moved = metadata.load_extent(&extent_uuid).unwrap();
+        moved.fragment_locations[0].disk_uuid = to_disk;
+        metadata.save_extent(&moved).unwrap();
+        let progress = RebalanceProgress { in_flight: Some(mv), ..Default::default() };
+        progress.save(pool_dir.path()).unwrap();
+        assert!(rebalancer.run(&metadata, &mut disks, &config, &stop).unwrap().resumed);
+        assert!(has_copy(&disks, to_disk));
+        assert!(!has_copy(&disks, from_disk));
+        assert!(RebalanceProgress::load(pool_dir.path()).unwrap().is_none());
+        drop(metadata);
+        drop(storage);
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        assert!(fragments_intact(&storage, &extent_uuid));
+    }
+
+    #[test]
+    fn test_snapshot_view_reads_captured_files_until_the_snapshot_is_deleted() {
+        use crate::metadata::Inode;
+        use crate::snapshots::{is_snapshot_ino, SnapshotInfo, SNAPSHOTS_DIR_INO};
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let view: &dyn FilesystemInterface = &storage;
+        let extent_count = || storage.metadata().read().unwrap().list_all_extents().unwrap().len();
+        let names = |ino: u64| {
+            let mut names: Vec<String> = view.list_directory(ino).unwrap().iter().map(|c| c.name.to_string()).collect();
+            names.sort();
+            names
+        };
+
+        let docs = storage.create_dir(1, "docs".to_string()).unwrap();
+        let report = storage.create_file(docs.ino, "report.txt".to_string()).unwrap();
+        storage.write_file(report.ino, b"first draft", 0).unwrap();
+        let notes = storage.create_file(1, "notes.txt".to_string()).unwrap();
+        storage.write_file(notes.ino, b"keep me", 0).unwrap();
+        let info = storage.create_snapshot("monday").unwrap();
+        assert_eq!((info.files, info.bytes), (2, 18));
+        assert!(storage.create_snapshot("monday").is_err());
+        assert!(storage.create_snapshot(".hidden").is_err());
+        assert_eq!(SnapshotInfo::list(pool_dir.path()).unwrap(), vec![info]);
+
+        // The live files move on; the snapshot holds on to their old extents
+        storage.write_range(report.ino, 0, b"final version").unwrap();
+        storage.delete_file(notes.ino).unwrap();
+        assert_eq!(extent_count(), 3);
+
+        assert_eq!(names(1), vec![".snapshots", "docs"]);
+        assert_eq!(view.get_inode(1).unwrap().nlink(), 4);
+        let snapshots_dir = view.find_child(1, b".snapshots").unwrap().unwrap();
+        assert_eq!((snapshots_dir.ino, snapshots_dir.mode), (SNAPSHOTS_DIR_INO, 0o555));
+        assert_eq!(names(SNAPSHOTS_DIR_INO), vec!["monday"]);
+        let monday = view.find_child(SNAPSHOTS_DIR_INO, b"monday").unwrap().unwrap();
+        assert_eq!(monday.parent_ino, SNAPSHOTS_DIR_INO);
+        assert_eq!(names(monday.ino), vec!["docs", "notes.txt"]);
+        let old_docs = view.find_child(monday.ino, b"docs").unwrap().unwrap();
+        let old_report = view.find_child(old_docs.ino, b"report.txt").unwrap().unwrap();
+        assert!(is_snapshot_ino(old_report.ino) && old_report.ino != report.ino);
+        assert_eq!(view.get_inode(old_report.ino).unwrap().parent_ino, old_docs.ino);
+        assert_eq!(view.read_range(old_report.ino, 0, 100).unwrap(), b"first draft");
+        assert_eq!(view.read_range(old_report.ino, 6, 3).unwrap(), b"dra");
+        let old_notes = view.find_child(monday.ino, b"notes.txt").unwrap().unwrap();
+        assert_eq!(view.read_file(old_notes.ino).unwrap(), b"keep me");
+        assert_eq!(view.read_file(report.ino).unwrap(), b"final version");
+
+        let erofs = |result: anyhow::Result<()>| StorageError::errno_of(&result.unwrap_err()) == libc::EROFS;
+        assert!(erofs(view.write_file(old_report.ino, b"x", 0)));
+        assert!(erofs(view.create_file(old_docs.ino, "new.txt".into()).map(drop)));
+        assert!(erofs(view.delete_file(old_notes.ino)));
+        assert!(erofs(view.update_inode(&old_report)));
+        assert!(erofs(view.update_inode(&Inode { parent_ino: old_docs.ino, ..view.get_inode(report.ino).unwrap() })));
+        assert!(erofs(view.delete_dir(SNAPSHOTS_DIR_INO)));
+
+        // Deleting it frees what only it held, and keeps what the live files use
+        storage.delete_snapshot("monday").unwrap();
+        assert_eq!(extent_count(), 1);
+        assert!(view.find_child(SNAPSHOTS_DIR_INO, b"monday").unwrap().is_none());
+        assert!(view.get_inode(old_report.ino).is_err());
+        assert_eq!(view.read_file(report.ino).unwrap(), b"final version");
+        assert!(std::fs::read_dir(pool_dir.path().join("snapshots/held")).unwrap().next().is_none());
+        assert!(storage.delete_snapshot("monday").is_err());
+    }
+
+    #[test]
+    fn test_policy_change_cancels_between_extents_and_resumes_where_it_stopped() {
+        use crate::extent::{RedundancyPolicy, DEFAULT_EXTENT_SIZE};
+        use crate::policy_change::{PolicyChangeProgress, PolicyChangeState};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(6);
+        let file = storage.create_file(1, "converting.bin".to_string()).unwrap();
+        let data: Vec<u8> = (0..2 * DEFAULT_EXTENT_SIZE).map(|i| (i % 247) as u8).collect();
+        storage.write_file(file.ino, &data, 0).unwrap();
+        let target = RedundancyPolicy::Replication { copies: 2 };
+        let extent_map = |storage: &StorageEngine| storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents;
+        let policy_of = |storage: &StorageEngine, uuid| storage.metadata().read().unwrap().load_extent(&uuid).unwrap().redundancy;
+        assert!(!storage.cancel_policy_change(file.ino));
+
+        // Holding the metadata lock parks the change before its first extent
+        let metadata = storage.metadata();
+        let guard = metadata.write().unwrap();
+        let cancelled = std::thread::scope(|scope| {
+            let change = scope.spawn(|| storage.change_file_redundancy(file.ino, target));
+            while !storage.cancel_policy_change(file.ino) {
+                std::thread::yield_now();
+            }
+            drop(guard);
+            change.join().unwrap()
+        });
+        let err = cancelled.unwrap_err();
+        assert!(matches!(err, StorageError::Interrupted(_)), "{:?}", err);
+        let progress = storage.get_policy_change_progress(file.ino).unwrap().unwrap();
+        assert_eq!((progress.state_label(), progress.extents_done, progress.total_extents), ("cancelled", 0, 2));
+        assert_eq!(progress.total_bytes, data.len() as u64);
+
+        // A crash after the first extent leaves the file mixed and the change running
+        storage.write_file(file.ino, &data[..DEFAULT_EXTENT_SIZE], 0).unwrap();
+        storage.change_file_redundancy(file.ino, target).unwrap();
+        storage.write_range(file.ino, DEFAULT_EXTENT_SIZE as u64, &data[DEFAULT_EXTENT_SIZE..]).unwrap();
+        let mixed = extent_map(&storage);
+        assert_eq!(policy_of(&storage, mixed[0]), target);
+        assert_ne!(policy_of(&storage, mixed[1]), target);
+        let mut progress = PolicyChangeProgress::new(file.ino, target, 2, data.len() as u64);
+        progress.next_slot = 1;
+        progress.in_progress = Some(mixed[1]);
+        progress.extents_done = 1;
+        progress.bytes_converted = DEFAULT_EXTENT_SIZE as u64;
+        progress.save(pool_dir.path()).unwrap();
+        assert_eq!(storage.get_policy_change_progress(file.ino).unwrap().unwrap().state_label(), "interrupted");
+
+        // Starting the same change again only converts the rest
+        storage.change_file_redundancy(file.ino, target).unwrap();
+        let done = extent_map(&storage);
+        assert_eq!(done[0], mixed[0]);
+        assert_ne!(done[1], mixed[1]);
+        assert!(done.iter().all(|uuid| policy_of(&storage, *uuid) == target));
+        let progress = storage.get_policy_change_progress(file.ino).unwrap().unwrap();
+        assert_eq!(progress.state, PolicyChangeState::Finished);
+        assert_eq!((progress.extents_done, progress.bytes_converted), (2, data.len() as u64));
+        assert!(progress.failed.is_empty() && progress.in_progress.is_none() && !progress.active);
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rebuild_replaces_fragments_of_lost_disk_on_new_disk() {
+        use crate::disk::DiskHealth::Healthy;
+        use crate::rebuild::{RebuildPassProgress, RebuildProgress, Rebuilder};
+        use uuid::Uuid;
+        use std::sync::atomic::AtomicBool;
+        const MIB: u64 = 1024 * 1024;
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_usage(&[(16 * MIB, 0, Healthy); 3]);
+        let mut files = Vec::new();
+        for i in 0..6u8 {
+            let file = storage.create_file(1, format!("f{}", i)).unwrap();
+            let data = vec![i; 32 * 1024];
+            storage.write_file(file.ino, &data, 0).unwrap();
+            files.push((file.ino, data));
+        }
+
+        // The first disk dies and leaves the pool; an empty replacement joins
+        let mut disks = storage.get_disks();
+        let lost = disks.remove(0).uuid;
+        let (_new_dirs, new_disks) = empty_disks(1);
+        let replacement = new_disks[0].uuid;
+        disks.extend(new_disks);
+
+        let rebuilder = Rebuilder::new(pool_dir.path().to_path_buf());
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let mut extents: Vec<Uuid> = metadata.list_all_extents().unwrap().iter().map(|e| e.uuid).collect();
+        extents.sort();
+
+        // A stop request ends the run before any extent
+        let report = rebuilder
+            .run(&metadata, &mut disks, None, &RebuildPassProgress::default(), &AtomicBool::new(true))
+            .unwrap();
+        assert!(report.interrupted && report.scanned == 0);
+
+        // Resuming after the first extent leaves it degraded
+        let progress = RebuildProgress { cursor: Some(extents[0]), extents_rebuilt: 1, bytes_written: 10 };
+        progress.save(pool_dir.path()).unwrap();
+        let live = RebuildPassProgress::default();
+        let report = rebuilder.run(&metadata, &mut disks, None, &live, &AtomicBool::new(false)).unwrap();
+        assert!(report.resumed && !report.interrupted);
+        assert_eq!(report.scanned as usize, extents.len() - 1);
+        assert_eq!(report.rebuilt.len(), extents.len() - 1);
+        assert_eq!(live.rebuilt() as usize, extents.len() - 1);
+        assert_eq!(report.total_extents_rebuilt as usize, extents.len());
+        assert!(report.failed.is_empty() && report.unrecoverable.is_empty());
+        assert!(RebuildProgress::load(pool_dir.path()).unwrap().is_none());
+        let holders = |uuid: &Uuid| -> Vec<Uuid> {
+            metadata.load_extent(uuid).unwrap().fragment_locations.iter().map(|l| l.disk_uuid).collect()
+        };
+        assert!(holders(&extents[0]).contains(&lost));
+
+        let report = rebuilder
+            .run(&metadata, &mut disks, None, &RebuildPassProgress::default(), &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(report.rebuilt, vec![extents[0]]);
+
+        // Locations on the lost disk were replaced, not appended to
+        for uuid in &extents {
+            let holders = holders(uuid);
+            assert_eq!(holders.len(), 3);
+            assert!(holders.contains(&replacement) && !holders.contains(&lost));
+        }
+        drop(metadata);
+        drop(storage);
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        for uuid in &extents {
+            assert!(fragments_intact(&storage, uuid));
+        }
+        for (ino, data) in files {
+            assert_eq!(storage.read_file(ino).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_read_only_rejects_mutations_but_serves_reads() {
+        let (_pool, _disks, storage) = setup_storage_with_disks(6);
+        let file = storage.create_file(1, "ro.txt".to_string()).unwrap();
+        storage.write_file(file.ino, b"before read-only", 0).unwrap();
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        let reads_before = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap().access_stats.read_count;
+
+        storage.set_read_only(true);
+        let is_erofs = |r: StorageResult<()>| matches!(r, Err(StorageError::ReadOnly(_)));
+        assert!(is_erofs(storage.write_file(file.ino, b"overwrite", 0)));
+        assert!(is_erofs(storage.write_range(file.ino, 3, b"patch")));
+        assert!(is_erofs(storage.buffered_write(file.ino, 0, b"buffered")));
+        assert!(is_erofs(storage.create_file(1, "new.txt".to_string()).map(|_| ())));
+        assert!(is_erofs(storage.create_dir(1, "dir".to_string()).map(|_| ())));
+        assert!(is_erofs(storage.update_inode(&file)));
+        assert!(is_erofs(storage.punch_hole(file.ino, 0, 4)));
+        assert!(is_erofs(storage.delete_file(file.ino)));
+
+        // Reads still work and leave the extent record untouched
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"before read-only");
+        let reads_after = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap().access_stats.read_count;
+        assert_eq!(reads_after, reads_before);
+
+        storage.set_read_only(false);
+        storage.write_file(file.ino, b"after", 0).unwrap();
+        assert_eq!(storage.read_file(file.ino).unwrap(), b"after");
+    }
+
+    #[test]
+    fn test_metrics_server_exports_live_counters_and_pool_health() {
+        use crate::monitoring::{MetricsServer, PoolHealthRefresh, PrometheusExporter};
+        use std::io::{Read, Write};
+
+        let (_pool, _disks, storage) = setup_storage_with_disks(3);
+        let file = storage.create_file(1, "metrics.bin".to_string()).unwrap();
+        storage.set_file_redundancy(file.ino, crate::extent::RedundancyPolicy::Replication { copies: 3 }).unwrap();
+        storage.write_file(file.ino, &[7u8; 4096], 0).unwrap();
+
+        let handle = storage.background_handle();
+        let refresh = PoolHealthRefresh {
+            interval: Duration::from_millis(50),
+            compute: Box::new(move || Ok(handle.pool_health()?)),
+        };
+        let server = MetricsServer::start("127.0.0.1:0", PrometheusExporter::new(storage.metrics()), Some(refresh)).unwrap();
+        // Mounted FUSE tests tearing down in the same process can reset a
+        // connection mid-request, so retry transient I/O errors
+        let get = |path: &str| {
+            let request = || -> std::io::Result<String> {
+                let mut stream = std::net::TcpStream::connect(server.local_addr())?;
+                write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response)?;
+                Ok(response)
+            };
+            let mut attempts = 0;
+            loop {
+                match request() {
+                    Ok(response) if !response.is_empty() => break response,
+                    result if attempts < 5 => {
+                        attempts += 1;
+                        log::debug!("Retrying {}: {:?}", path, result.err());
+                        std::thread::sleep(Duration::from_millis(20));
+                    }
+                    result => panic!("GET {} failed: {:?}", path, result),
+                }
+            }
+        };
+
+        let body = get("/metrics");
+        let writes = storage.metrics().snapshot().disk_writes;
+        assert!(writes > 0);
+        assert!(body.contains(&format!("dynamicfs_disk_writes_total {}", writes)), "{}", body);
+        assert!(body.contains("dynamicfs_pool_disks{state=\"healthy\"} 3"), "{}", body);
+        assert!(body.contains("dynamicfs_pool_extents{state=\"healthy\"} 1"), "{}", body);
+        assert_eq!(body.matches("dynamicfs_pool_disk_capacity_bytes{disk=").count(), 3);
+        let health = get("/health");
+        assert!(health.starts_with("HTTP/1.1 200") && health.contains("\"healthy\""), "{}", health);
+
+        // Losing every fragment makes the extent unreadable: /health turns critical
+        let extent_uuid = storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents[0];
+        let mut extent = storage.metadata().read().unwrap().load_extent(&extent_uuid).unwrap();
+        extent.fragment_locations.clear();
+        storage.metadata().read().unwrap().save_extent(&extent).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let health = loop {
+            let health = get("/health");
+            if health.contains("critical") || Instant::now() > deadline {
+                break health;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        };
+        assert!(health.starts_with("HTTP/1.1 503") && health.contains("\"critical\""), "{}", health);
+        assert!(get("/metrics").contains("dynamicfs_pool_health 2"));
+
+        let addr = server.local_addr();
+        server.stop();
+        assert!(std::net::TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_parallel_scrub_matches_serial_scrub() {
+        use crate::scrubber::{ScrubConfig, ScrubPassProgress, Scrubber};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(4);
+        for i in 0..300 {
+            let file = storage.create_file(1, format!("extent-{}.bin", i)).unwrap();
+            storage.write_file(file.ino, &vec![(i % 251) as u8; 512 + i], 0).unwrap();
+        }
+
+        // Damage a spread of extents: corrupt replicas, lost fragments and one unrecoverable
+        let extents = storage.metadata().read().unwrap().list_all_extents().unwrap();
+        assert_eq!(extents.len(), 300);
+        for (i, extent) in extents.iter().enumerate() {
+            if i % 7 == 0 {
+                corrupt_fragment(&storage, extent, i % 3);
+            } else if i % 11 == 0 {
+                let mut extent = extent.clone();
+                extent.fragment_locations.retain(|l| l.fragment_index != 0);
+                storage.metadata().read().unwrap().save_extent(&extent).unwrap();
+            }
+        }
+        let mut lost = extents[1].clone();
+        lost.fragment_locations.clear();
+        storage.metadata().read().unwrap().save_extent(&lost).unwrap();
+
+        let scrubber = Scrubber::new(pool_dir.path().to_path_buf());
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let scrub = |workers: usize, max_bytes_per_sec: Option<u64>| {
+            let config = ScrubConfig { workers, max_bytes_per_sec, repair: false, skip: Default::default() };
+            let progress = ScrubPassProgress::default();
+            let started = Instant::now();
+            let results = scrubber.scrub_pool(&metadata, &mut storage.get_disks(), &config, &progress).unwrap();
+            assert_eq!((progress.extents_done(), progress.extents_total()), (300, 300));
+            (results, progress.io_bytes(), started.elapsed())
+        };
+
+        let (serial, io_bytes, _) = scrub(1, None);
+        let (parallel, _, _) = scrub(8, None);
+        let stats = Scrubber::stats(&serial);
+        assert_eq!(stats, Scrubber::stats(&parallel));
+        assert_eq!(stats.total_extents, 300);
+        assert_eq!(stats.unrecoverable, 1);
+        assert!(stats.degraded > 40, "{}", stats);
+        let summary = |results: &[crate::scrubber::ScrubResult]| {
+            results.iter().map(|r| (r.extent_uuid, r.status, r.corrupt_fragments.clone())).collect::<Vec<_>>()
+        };
+        assert_eq!(summary(&serial), summary(&parallel));
+
+        // Half a second's worth of budget per pass-worth of reads: the pass waits out the rest
+        let (_, _, elapsed) = scrub(8, Some(io_bytes / 2));
+        assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+
+        // Parallel repair fixes every degraded extent
+        let config = ScrubConfig { workers: 8, max_bytes_per_sec: None, repair: true, skip: Default::default() };
+        let results = scrubber.scrub_pool(&metadata, &mut storage.get_disks(), &config, &ScrubPassProgress::default()).unwrap();
+        let repaired = Scrubber::stats(&results);
+        assert_eq!(repaired.repaired, stats.degraded);
+        let (rescan, _, _) = scrub(8, None);
+        let rescan = Scrubber::stats(&rescan);
+        assert_eq!((rescan.healthy, rescan.degraded, rescan.unrecoverable), (299, 0, 1));
+    }
+
+    #[test]
+    fn test_foreground_reads_stay_fast_under_a_saturating_scrub() {
+        use crate::io_scheduler::{self, IoClass};
+        use crate::scrubber::{ScrubConfig, ScrubPassProgress, Scrubber};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(4);
+        for i in 0..200 {
+            let file = storage.create_file(1, format!("file-{}.bin", i)).unwrap();
+            storage.write_file(file.ino, &vec![(i % 251) as u8; 64 * 1024], 0).unwrap();
+        }
+        let extents: Vec<uuid::Uuid> =
+            storage.metadata().read().unwrap().list_all_extents().unwrap().iter().map(|e| e.uuid).collect();
+        let disk_uuids: Vec<uuid::Uuid> = storage.get_disks().iter().map(|d| d.uuid).collect();
+        let scrub_completed = || -> u64 {
+            let queues = io_scheduler::scheduler().queues();
+            disk_uuids.iter().filter_map(|disk| queues.get(disk)).map(|classes| classes[&IoClass::Scrub].completed).sum()
+        };
+        // Extent reads bypass the data cache, so every one reaches the disks
+        let p95 = || {
+            let mut latencies: Vec<Duration> = extents
+                .iter()
+                .map(|uuid| {
+                    let started = Instant::now();
+                    storage.read_extent(*uuid).unwrap();
+                    started.elapsed()
+                })
+                .collect();
+            latencies.sort();
+            latencies[latencies.len() * 95 / 100]
+        };
+        let baseline = p95();
+
+        let scrubber = Scrubber::new(pool_dir.path().to_path_buf());
+        let metadata = storage.metadata();
+        let stop = AtomicBool::new(false);
+        let (loaded, scrubbed) = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let metadata = metadata.read().unwrap();
+                let config = ScrubConfig { workers: 16, max_bytes_per_sec: None, repair: false, skip: Default::default() };
+                while !stop.load(Ordering::Relaxed) {
+                    scrubber.scrub_pool(&metadata, &mut storage.get_disks(), &config, &ScrubPassProgress::default()).unwrap();
+                }
+            });
+            while scrub_completed() == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let before = scrub_completed();
+            let loaded = p95();
+            let scrubbed = scrub_completed() - before;
+            stop.store(true, Ordering::Relaxed);
+            (loaded, scrubbed)
+        });
+
+        // The scrub kept reading throughout, yet foreground reads only waited for requests already in flight
+        assert!(scrubbed > extents.len() as u64, "scrub read {} fragments", scrubbed);
+        let bound = baseline.max(Duration::from_millis(2)) * 8;
+        assert!(loaded <= bound, "p95 {:?} under scrub against {:?} unloaded", loaded, baseline);
+        assert!(io_scheduler::scheduler().queues()[&disk_uuids[0]][&IoClass::Foreground].completed > 0);
+    }
+
+    #[test]
+    fn test_encrypted_pool_stores_ciphertext_and_needs_its_key() {
+        use crate::disk::DiskPool;
+        use crate::encryption::PoolKeySource;
+        use crate::scrubber::{ScrubConfig, ScrubPassProgress, Scrubber};
+
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let key = PoolKeySource::KeyFile(pool_dir.path().join("pool.key"));
+        let mut pool = DiskPool::new();
+        pool.metadata_backend = test_metadata_backend();
+        pool.enable_encryption(&key).unwrap();
+        for td in &disk_dirs {
+            let mut disk = Disk::new(td.path().to_path_buf()).unwrap();
+            pool.adopt_disk(&mut disk).unwrap();
+            pool.add_disk(td.path().to_path_buf()).unwrap();
+        }
+        pool.save(pool_dir.path()).unwrap();
+        assert!(pool.enable_encryption(&key).is_err());
+
+        let data = b"confidential payload ".repeat(100);
+        let storage = StorageEngine::new(MetadataManager::new(pool_dir.path().to_path_buf()).unwrap(), pool.load_disks().unwrap());
+        let file = storage.create_file(1, "secret.txt".to_string()).unwrap();
+        storage.write_file(file.ino, &data, 0).unwrap();
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+
+        // Nothing on the disks is plaintext, yet a scrub (with the key) finds every fragment intact
+        for td in &disk_dirs {
+            for entry in std::fs::read_dir(td.path().join("fragments")).unwrap() {
+                let bytes = std::fs::read(entry.unwrap().path()).unwrap();
+                assert!(bytes.starts_with(b"DFSENC01"));
+                assert!(!bytes.windows(12).any(|w| w == b"confidential"));
+            }
+        }
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let config = ScrubConfig { workers: 2, max_bytes_per_sec: None, repair: false, skip: Default::default() };
+        let results = Scrubber::new(pool_dir.path().to_path_buf())
+            .scrub_pool(&metadata, &mut storage.get_disks(), &config, &ScrubPassProgress::default())
+            .unwrap();
+        let stats = Scrubber::stats(&results);
+        assert_eq!((stats.healthy, stats.total_extents), (1, 1));
+        drop(metadata);
+        drop(storage);
+
+        // Reloaded without a key the pool refuses fragment I/O; with the wrong key it does not load
+        let locked = DiskPool::load(pool_dir.path()).unwrap();
+        let err = locked.require_key().unwrap_err();
+        assert!(err.to_string().contains("Pool is encrypted"), "{}", err);
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let extent = metadata.load_extent(&metadata.load_extent_map(file.ino).unwrap().extents[0]).unwrap();
+        let location = &extent.fragment_locations[0];
+        let disk = locked.load_disks().unwrap().into_iter().find(|d| d.uuid == location.disk_uuid).unwrap();
+        assert!(disk.encrypted);
+        let err = disk.read_fragment(&extent.uuid, location.fragment_index).unwrap_err();
+        assert!(err.to_string().contains("key was not supplied"), "{}", err);
+        let mut wrong = DiskPool::load(pool_dir.path()).unwrap();
+        let other_key = pool_dir.path().join("other.key");
+        crate::encryption::generate_key_file(&other_key).unwrap();
+        assert!(wrong.unlock(&PoolKeySource::KeyFile(other_key)).is_err());
+
+        let mut unlocked = DiskPool::load(pool_dir.path()).unwrap();
+        unlocked.unlock(&key).unwrap();
+        let storage = StorageEngine::new(MetadataManager::new(pool_dir.path().to_path_buf()).unwrap(), unlocked.load_disks().unwrap());
+        assert_eq!(storage.read_file(file.ino).unwrap(), data);
+
+        // A plaintext disk cannot join the encrypted pool
+        let plain_dir = tempfile::tempdir().unwrap();
+        Disk::new(plain_dir.path().to_path_buf()).unwrap();
+        unlocked.add_disk(plain_dir.path().to_path_buf()).unwrap();
+        let err = unlocked.load_disks().unwrap_err();
+        assert!(err.to_string().contains("not encrypted but the pool is encrypted"), "{}", err);
+    }
+
+    #[test]
+    fn test_compressed_extents_round_trip_through_writes_and_scrub() {
+        use crate::compression::Compression;
+        use crate::scrubber::{ScrubConfig, ScrubPassProgress, ScrubStatus, Scrubber};
+
+        let (pool_dir, disk_dirs, storage) = setup_storage_with_disks(6);
+        storage.set_compression(Compression::Zstd);
+        let log: Vec<u8> = (0..60_000).flat_map(|i| format!("{:08} GET /api/items 200 3ms\n", i).into_bytes()).collect();
+        let logs = storage.create_file(1, "app.log".to_string()).unwrap();
+        storage.write_file(logs.ino, &log, 0).unwrap();
+        let noise: Vec<u8> = (0..200_000u32).map(|i| blake3::hash(&i.to_le_bytes()).as_bytes()[3]).collect();
+        let random = storage.create_file(1, "random.bin".to_string()).unwrap();
+        storage.write_file(random.ino, &noise, 0).unwrap();
+
+        let extents_of = |ino| {
+            let metadata = storage.metadata();
+            let metadata = metadata.read().unwrap();
+            let map = metadata.load_extent_map(ino).unwrap();
+            map.extents.iter().map(|uuid| metadata.load_extent(uuid).unwrap()).collect::<Vec<_>>()
+        };
+        let log_extents = extents_of(logs.ino);
+        assert!(log_extents.len() > 1);
+        assert!(log_extents.iter().all(|e| e.compression == Compression::Zstd && e.stored_size() * 4 < e.size));
+        assert_eq!(log_extents.iter().map(|e| e.size).sum::<usize>(), log.len());
+        assert!(extents_of(random.ino).iter().all(|e| e.compression == Compression::None && e.compressed_size.is_none()));
+        let on_disk: u64 = disk_dirs.iter().map(|d| crate::disk::Disk::load(d.path()).unwrap().used_bytes).sum();
+        assert!(on_disk < (log.len() + noise.len() * 3) as u64);
+
+        // Reads, partial writes and truncation all see the uncompressed data
+        assert_eq!(storage.read_file(logs.ino).unwrap(), log);
+        assert_eq!(storage.read_range(logs.ino, 1_500_000, 100).unwrap(), &log[1_500_000..1_500_100]);
+        let mut expected = log.clone();
+        expected[1_048_000..1_049_000].fill(b'#');
+        storage.write_file(logs.ino, &[b'#'; 1_000], 1_048_000).unwrap();
+        assert_eq!(storage.read_file(logs.ino).unwrap(), expected);
+        storage.punch_hole(logs.ino, 10, 20).unwrap();
+        expected[10..30].fill(0);
+        assert_eq!(storage.read_file(logs.ino).unwrap(), expected);
+        assert_eq!(storage.read_file(random.ino).unwrap(), noise);
+
+        // Existing extents stay readable after the setting changes
+        storage.set_compression(Compression::None);
+        storage.write_file(logs.ino, &expected[..5_000], 0).unwrap();
+        assert_eq!(storage.read_file(logs.ino).unwrap(), &expected[..5_000]);
+        assert_eq!(extents_of(logs.ino)[0].compression, Compression::None);
+        storage.set_compression(Compression::Lz4);
+        storage.write_file(logs.ino, &log, 0).unwrap();
+        assert_eq!(extents_of(logs.ino)[0].compression, Compression::Lz4);
+
+        // Scrub checks compressed and plain extents alike, and repairs a compressed one
+        let corrupted = extents_of(logs.ino).remove(0);
+        corrupt_fragment(&storage, &corrupted, 0);
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let scrubber = Scrubber::new(pool_dir.path().to_path_buf());
+        let config = ScrubConfig { workers: 2, max_bytes_per_sec: None, repair: true, skip: Default::default() };
+        let results = scrubber.scrub_pool(&metadata, &mut storage.get_disks(), &config, &ScrubPassProgress::default()).unwrap();
+        let stats = Scrubber::stats(&results);
+        assert_eq!((stats.repaired, stats.unrecoverable), (1, 0), "{}", stats);
+        assert!(results.iter().all(|r| r.status != ScrubStatus::Degraded));
+        drop(metadata);
+        assert_eq!(storage.read_file(logs.ino).unwrap(), log);
+    }
+
+    #[test]
+    fn test_verify_on_write_rolls_back_and_names_the_corrupting_disk() {
+        use crate::fs_interface::VERIFY_WRITES_XATTR;
+
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut disks: Vec<Disk> = disk_dirs
+            .iter()
+            .map(|td| Disk::new(td.path().to_path_buf()).unwrap())
+            .collect();
+        disks[1].corrupt_writes = true;
+        let bad_disk = disks[1].uuid;
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, disks);
+        let fragment_files = || -> usize {
+            disk_dirs.iter().map(|d| std::fs::read_dir(d.path().join("fragments")).unwrap().count()).sum()
+        };
+
+        // Off by default: the corruption goes unnoticed until the fragment is read
+        let unverified = storage.create_file(1, "unverified.bin".to_string()).unwrap();
+        storage.write_file(unverified.ino, &[1u8; 4096], 0).unwrap();
+        let before = fragment_files();
+
+        storage.set_verify_writes(true);
+        let file = storage.create_file(1, "verified.bin".to_string()).unwrap();
+        let err = storage.write_file(file.ino, &[2u8; 4096], 0).unwrap_err();
+        assert!(err.to_string().contains(&bad_disk.to_string()), "{}", err);
+        assert_eq!(fragment_files(), before);
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, 0);
+        assert!(storage.metadata().read().unwrap().load_extent_map(file.ino).unwrap().extents.is_empty());
+
+        let health = |uuid| storage.get_disks().into_iter().find(|d| d.uuid == uuid).unwrap().health;
+        assert_eq!(health(bad_disk), crate::disk::DiskHealth::Suspect);
+        assert_eq!(storage.metrics().snapshot().write_verify_failures.get(&bad_disk), Some(&1));
+
+        // Partial overwrites roll back the same way and leave the old contents
+        assert!(storage.write_file(unverified.ino, b"patch", 100).is_err());
+        assert_eq!(fragment_files(), before);
+        assert_eq!(storage.metrics().snapshot().write_verify_failures.get(&bad_disk), Some(&2));
+
+        // A file can opt out of the pool setting, or into it when the pool has it off
+        let set_verify = |ino: u64, value: &[u8]| {
+            let mut inode = storage.get_inode(ino).unwrap();
+            inode.set_xattr(VERIFY_WRITES_XATTR.to_string(), value.to_vec());
+            storage.update_inode(&inode).unwrap();
+        };
+        set_verify(file.ino, b"off");
+        storage.write_file(file.ino, &[2u8; 4096], 0).unwrap();
+        storage.set_verify_writes(false);
+        set_verify(file.ino, b"on");
+        assert!(storage.write_file(file.ino, &[3u8; 4096], 0).is_err());
+        storage.write_file(unverified.ino, &[4u8; 4096], 0).unwrap();
+    }
+
+    #[test]
+    fn test_collect_orphans_removes_unreferenced_fragments_and_records_status() {
+        let (pool_dir, disk_dirs, mut storage) = setup_storage_with_disks(3);
+        let file = storage.create_file(1, "kept.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[7u8; 4096], 0).unwrap();
+
+        let orphan = disk_dirs[0].path().join("fragments").join(format!("{}-0.frag", uuid::Uuid::new_v4()));
+        std::fs::write(&orphan, [0u8; 100]).unwrap();
+
+        let report = storage.collect_orphans(0).unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.bytes_reclaimed(), 100);
+        assert!(!orphan.exists());
+        assert_eq!(storage.read_file(file.ino).unwrap(), vec![7u8; 4096]);
+
+        let snapshot = storage.metrics().snapshot();
+        assert_eq!(snapshot.orphan_gc_runs, 1);
+        assert_eq!(snapshot.orphan_gc_fragments_removed, 1);
+        assert_eq!(snapshot.orphan_gc_bytes_reclaimed, 100);
+        assert!(snapshot.orphan_gc_last_run > 0);
+
+        // The background pass writes the status shown by `orphan-stats`
+        std::fs::write(&orphan, [0u8; 50]).unwrap();
+        storage.start_orphan_gc(crate::gc::OrphanGcConfig {
+            interval: Duration::from_millis(20),
+            min_age_seconds: 0,
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while orphan.exists() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(storage);
+        assert!(!orphan.exists());
+        let status = crate::gc::GcStatus::load(pool_dir.path()).unwrap();
+        assert!(status.last_run.is_some());
+        assert_eq!(status.total_removed, 1);
+        assert_eq!(status.total_bytes_reclaimed, 50);
+    }
+
+    #[test]
+    fn test_unplugged_disk_is_detached_and_used_again_when_it_returns() {
+        use crate::disk::{DiskHealth, DiskPool};
+
+        let (pool_dir, disk_dirs, storage) = setup_storage_with_disks(4);
+        let mut pool = DiskPool::new();
+        for dir in &disk_dirs {
+            pool.add_disk(dir.path().to_path_buf()).unwrap();
+        }
+        let health = |uuid| storage.get_disks().into_iter().find(|d| d.uuid == uuid).unwrap().health;
+        let dir_of = |uuid| storage.get_disks().into_iter().find(|d| d.uuid == uuid).unwrap().path;
+        let unplug = |uuid| std::fs::rename(dir_of(uuid), pool_dir.path().join(uuid::Uuid::to_string(&uuid))).unwrap();
+        let plug = |uuid| std::fs::rename(pool_dir.path().join(uuid::Uuid::to_string(&uuid)), dir_of(uuid)).unwrap();
+
+        let a = storage.create_file(1, "a.bin".to_string()).unwrap();
+        storage.write_file(a.ino, &[1u8; 4096], 0).unwrap();
+        let holders: Vec<uuid::Uuid> = storage.metadata().read().unwrap().list_all_extents().unwrap()[0]
+            .fragment_locations
+            .iter()
+            .map(|l| l.disk_uuid)
+            .collect();
+
+        // One replica's disk goes away: reads use redundancy, writes avoid it
+        unplug(holders[0]);
+        assert!(storage.reprobe_disks(&pool).is_empty());
+        assert_eq!(health(holders[0]), DiskHealth::Failed);
+        let b = storage.create_file(1, "b.bin".to_string()).unwrap();
+        storage.write_file(b.ino, &[2u8; 4096], 0).unwrap();
+        assert_eq!(storage.read_file(a.ino).unwrap(), vec![1u8; 4096]);
+        storage.wait_for_rebuilds();
+
+        // With every copy gone the read fails naming a disk, but getattr still works
+        let others: Vec<uuid::Uuid> = storage.get_disks().iter().map(|d| d.uuid).filter(|u| *u != holders[0]).collect();
+        for uuid in &others {
+            unplug(*uuid);
+        }
+        let err = storage.read_file(a.ino).unwrap_err();
+        assert!(others.iter().any(|u| err.to_string().contains(&u.to_string())), "{}", err);
+        assert_eq!(storage.get_inode(a.ino).unwrap().size, 4096);
+
+        for uuid in others.iter().chain(std::iter::once(&holders[0])) {
+            plug(*uuid);
+        }
+        assert_eq!(storage.reprobe_disks(&pool), vec![holders[0]]);
+        storage.wait_for_rebuilds();
+        assert_eq!(health(holders[0]), DiskHealth::Healthy);
+        assert_eq!(storage.read_file(a.ino).unwrap(), vec![1u8; 4096]);
+        assert_eq!(storage.read_file(b.ino).unwrap(), vec![2u8; 4096]);
+    }
+
+    #[test]
+    fn test_degraded_writes_fall_back_while_disks_are_away_and_upgrade_when_they_return() {
+        use crate::disk::DiskPool;
+        use crate::extent::RedundancyPolicy;
+        use crate::placement::DegradedWrites;
+
+        let (pool_dir, disk_dirs, storage) = setup_storage_with_disks(6);
+        let mut pool = DiskPool::new();
+        for dir in &disk_dirs {
+            pool.add_disk(dir.path().to_path_buf()).unwrap();
+        }
+        let aside = |i: usize| pool_dir.path().join(format!("unplugged{}", i));
+        for (i, dir) in disk_dirs.iter().enumerate().take(3) {
+            std::fs::rename(dir.path(), aside(i)).unwrap();
+        }
+        assert!(storage.reprobe_disks(&pool).is_empty());
+
+        // Large files want erasure:4+2, which three disks cannot hold
+        let data: Vec<u8> = (0..crate::extent::DEFAULT_EXTENT_SIZE + 4096).map(|i| (i % 251) as u8).collect();
+        let file = storage.create_file(1, "big.bin".to_string()).unwrap();
+        assert!(storage.write_file(file.ino, &data, 0).is_err());
+
+        storage.set_degraded_writes(DegradedWrites::Allow, RedundancyPolicy::Replication { copies: 2 });
+        storage.write_file(file.ino, &data, 0).unwrap();
+        let wanted = RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 };
+        let extents = || storage.metadata().read().unwrap().list_all_extents().unwrap();
+        for extent in extents() {
+            assert_eq!(extent.redundancy, RedundancyPolicy::Replication { copies: 3 });
+            assert_eq!(extent.intended_policy, Some(wanted));
+        }
+        // Overwrites keep aiming for the intended policy
+        storage.write_file(file.ino, &[7u8; 100], 10).unwrap();
+        let mut expected = data.clone();
+        expected[10..110].fill(7);
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+        assert!(extents().iter().all(|e| e.intended_policy == Some(wanted)));
+        assert_eq!(storage.metrics().snapshot().degraded_writes, 3);
+        assert_eq!(storage.metadata().read().unwrap().extent_totals().below_policy.len(), 2);
+
+        // A floor stronger than the disks allow denies the write again
+        storage.set_degraded_writes(DegradedWrites::Allow, RedundancyPolicy::Replication { copies: 4 });
+        let other = storage.create_file(1, "other.bin".to_string()).unwrap();
+        assert!(storage.write_file(other.ino, &data, 0).is_err());
+
+        for (i, dir) in disk_dirs.iter().enumerate().take(3) {
+            std::fs::rename(aside(i), dir.path()).unwrap();
+        }
+        assert_eq!(storage.reprobe_disks(&pool).len(), 3);
+        storage.wait_for_rebuilds();
+        for extent in extents() {
+            assert_eq!(extent.redundancy, wanted);
+            assert_eq!(extent.intended_policy, None);
+        }
+        assert_eq!(storage.metrics().snapshot().degraded_upgrades, 2);
+        assert!(storage.metadata().read().unwrap().extent_totals().below_policy.is_empty());
+        assert_eq!(storage.read_file(file.ino).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_disk_missing_at_open_is_added_when_it_appears() {
+        use crate::disk::DiskPool;
+
+        let pool_dir = test_pool_dir();
+        let disk_dirs: Vec<TempDir> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut pool = DiskPool::new();
+        for dir in &disk_dirs {
+            Disk::new(dir.path().to_path_buf()).unwrap();
+            pool.add_disk(dir.path().to_path_buf()).unwrap();
+        }
+        let aside = pool_dir.path().join("unplugged");
+        std::fs::rename(disk_dirs[3].path(), &aside).unwrap();
+
+        let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+        let storage = StorageEngine::new(metadata, pool.load_disks().unwrap());
+        assert_eq!(storage.get_disks().len(), 3);
+        let file = storage.create_file(1, "early.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[5u8; 4096], 0).unwrap();
+        assert!(storage.reprobe_disks(&pool).is_empty());
+
+        std::fs::rename(&aside, disk_dirs[3].path()).unwrap();
+        assert_eq!(storage.reprobe_disks(&pool).len(), 1);
+        assert_eq!(storage.get_disks().len(), 4);
+        storage.wait_for_rebuilds();
+        assert_eq!(storage.read_file(file.ino).unwrap(), vec![5u8; 4096]);
+    }
+
+    #[test]
+    fn test_describe_layout_lists_extents_and_fragment_disks() {
+        use crate::extent::DEFAULT_EXTENT_SIZE;
+
+        let (_pool, _disks, storage) = setup_storage_with_disks(6);
+        let file = storage.create_file(1, "sparse.bin".to_string()).unwrap();
+        storage.write_file(file.ino, &[3u8; 4096], 0).unwrap();
+        storage.write_file(file.ino, &[5u8; 1000], 2 * DEFAULT_EXTENT_SIZE as u64).unwrap();
+
+        // The hole in slot 1 has no extent to report
+        let layout = storage.describe_layout(file.ino).unwrap();
+        assert_eq!(layout.size, 2 * DEFAULT_EXTENT_SIZE as u64 + 1000);
+        assert_eq!(layout.extents.iter().map(|e| e.slot).collect::<Vec<_>>(), vec![0, 2]);
+
+        let metadata = storage.metadata();
+        let metadata = metadata.read().unwrap();
+        let extent_map = metadata.load_extent_map(file.ino).unwrap();
+        let pool_disks: Vec<uuid::Uuid> = storage.get_disks().iter().map(|d| d.uuid).collect();
+        for extent in &layout.extents {
+            assert_eq!(extent.uuid, extent_map.extents[extent.slot]);
+            let stored = metadata.load_extent(&extent.uuid).unwrap();
+            assert_eq!(extent.redundancy, stored.redundancy.to_string());
+            assert_eq!(extent.fragments.len(), stored.fragment_locations.len());
+            assert!(extent.fragments.windows(2).all(|w| w[0].index < w[1].index));
+            for fragment in &extent.fragments {
+                assert!(pool_disks.contains(&fragment.disk));
+                assert_eq!(fragment.disk_health, Some(crate::disk::DiskHealth::Healthy));
+            }
+        }
+        assert_eq!(layout.extents[1].size, 1000);
+    }
+
+    #[test]
+    fn test_nested_quotas_track_usage_and_enforce_limits() {
+        use crate::quota::Quota;
+
+        let (_pool, _disks, storage) = setup_storage_with_disks(3);
+        let exceeded = |result: StorageResult<()>| matches!(result, Err(StorageError::QuotaExceeded(_)));
+        let quota = |ino: u64| storage.metadata().read().unwrap().load_quota(ino).unwrap().unwrap();
+        let usage = |ino: u64| {
+            let quota = quota(ino);
+            (quota.bytes_used, quota.inodes_used)
+        };
+
+        let projects = storage.create_dir(1, "projects".to_string()).unwrap();
+        let foo = storage.create_dir(projects.ino, "foo".to_string()).unwrap();
+        for (ino, bytes, inodes) in [(projects.ino, 10_000, 10), (foo.ino, 4_000, 2)] {
+            let metadata = storage.metadata();
+            let metadata = metadata.read().unwrap();
+            let mut quota = Quota::new(ino, Some(bytes), Some(inodes));
+            (quota.bytes_used, quota.inodes_used) = metadata.tree_usage(ino).unwrap();
+            metadata.save_quota(&quota).unwrap();
+        }
+        assert_eq!(usage(projects.ino), (0, 1));
+
+        // Creates and writes are charged to every quota up to the root
+        let a = storage.create_file(foo.ino, "a".to_string()).unwrap();
+        storage.write_file(a.ino, &[1u8; 3_000], 0).unwrap();
+        assert_eq!(usage(foo.ino), (3_000, 1));
+        assert_eq!(usage(projects.ino), (3_000, 2));
+
+        // The nested limit applies even though the outer one has room
+        assert!(exceeded(storage.write_range(a.ino, 3_000, &[2u8; 2_000])));
+        assert!(exceeded(storage.buffered_write(a.ino, 3_500, &[2u8; 1_000])));
+        storage.write_range(a.ino, 3_000, &[2u8; 1_000]).unwrap();
+        assert_eq!(storage.get_inode(a.ino).unwrap().size, 4_000);
+        storage.create_file(foo.ino, "b".to_string()).unwrap();
+        assert!(exceeded(storage.create_file(foo.ino, "c".to_string()).map(|_| ())));
+        assert!(exceeded(storage.create_dir(foo.ino, "d".to_string()).map(|_| ())));
+        assert_eq!(usage(foo.ino), (4_000, 2));
+
+        // And the outer limit applies to files outside the nested tree
+        let big = storage.create_file(projects.ino, "big".to_string()).unwrap();
+        assert!(exceeded(storage.write_file(big.ino, &vec![3u8; 7_000], 0)));
+        assert!(storage.read_file(big.ino).unwrap().is_empty());
+        storage.write_file(big.ino, &vec![3u8; 5_000], 0).unwrap();
+        assert!(exceeded(storage.zero_range(big.ino, 5_000, 2_000, false)));
+        storage.zero_range(big.ino, 5_000, 1_000, false).unwrap();
+        assert_eq!(usage(projects.ino), (10_000, 4));
+
+        // Truncating and deleting give the space and inodes back
+        storage.write_file(a.ino, &[], 0).unwrap();
+        let b = storage.find_child(foo.ino, "b").unwrap().unwrap();
+        storage.delete_file(b.ino).unwrap();
+        assert_eq!(usage(foo.ino), (0, 1));
+        assert_eq!(usage(projects.ino), (6_000, 3));
+        storage.create_file(foo.ino, "c".to_string()).unwrap();
+
+        // Counters match a walk of the tree; removing a directory drops its quota
+        let metadata = storage.metadata();
+        assert_eq!(metadata.read().unwrap().tree_usage(projects.ino).unwrap(), usage(projects.ino));
+        assert_eq!(metadata.read().unwrap().tree_usage(foo.ino).unwrap(), usage(foo.ino));
+        for child in storage.list_directory(foo.ino).unwrap() {
+            storage.delete_file(child.ino).unwrap();
+        }
+        storage.delete_file(foo.ino).unwrap();
+        assert_eq!(metadata.read().unwrap().list_quotas().unwrap(), vec![quota(projects.ino)]);
+        assert_eq!(usage(projects.ino), (6_000, 1));
+    }
+
+    #[test]
+    fn test_concurrent_appenders_each_land_exactly_once() {
+        use crate::write_optimizer::WriteBufferConfig;
+        use std::sync::Arc;
+
+        // Small extents so appends keep crossing flush boundaries
+        let config = WriteBufferConfig { extent_size: 4096, ..Default::default() };
+        let (_pool, _disks, storage) = setup_buffered_storage(config);
+        let storage = Arc::new(storage);
+        let file = storage.create_file(1, "app.log".to_string()).unwrap();
+        let header = b"HEADER-v0000\n";
+        storage.write_file(file.ino, header, 0).unwrap();
+
+        const THREADS: usize = 8;
+        const APPENDS: usize = 60;
+        let mut writers: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let storage = Arc::clone(&storage);
+                std::thread::spawn(move || {
+                    let mut written = 0;
+                    for i in 0..APPENDS {
+                        let marker = format!("<{}:{}>{}\n", t, i, "x".repeat((t * 7 + i) % 50));
+                        storage.append_write(file.ino, marker.as_bytes()).unwrap();
+                        written += marker.len();
+                        if i % 16 == 0 {
+                            storage.flush_file(file.ino).unwrap();
+                        }
+                    }
+                    written
+                })
+            })
+            .collect();
+        // A pwrite-ing writer rewriting the header in place alongside the appenders
+        writers.push({
+            let storage = Arc::clone(&storage);
+            std::thread::spawn(move || {
+                for version in 1..=100 {
+                    let header = format!("HEADER-v{:04}\n", version);
+                    storage.buffered_write(file.ino, 0, header.as_bytes()).unwrap();
+                }
+                0
+            })
+        });
+        let appended: usize = writers.into_iter().map(|w| w.join().unwrap()).sum();
+
+        storage.flush_file(file.ino).unwrap();
+        let data = storage.read_file(file.ino).unwrap();
+        assert_eq!(data.len(), header.len() + appended);
+        assert_eq!(storage.get_inode(file.ino).unwrap().size, data.len() as u64);
+        assert_eq!(&data[..header.len()], b"HEADER-v0100\n");
+
+        let text = String::from_utf8(data[header.len()..].to_vec()).unwrap();
+        let mut markers: Vec<&str> = text.lines().map(|line| line.trim_end_matches('x')).collect();
+        assert_eq!(markers.len(), THREADS * APPENDS);
+        markers.sort_unstable();
+        markers.dedup();
+        assert_eq!(markers.len(), THREADS * APPENDS);
+        for t in 0..THREADS {
+            // Each appender's markers are in the order it wrote them
+            let order: Vec<usize> = text
+                .lines()
+                .filter_map(|line| line.strip_prefix(&format!("<{}:", t)))
+                .map(|rest| rest.split('>').next().unwrap().parse().unwrap())
+                .collect();
+            assert_eq!(order, (0..APPENDS).collect::<Vec<_>>());
+        }
+
+        // The same contents once everything is reread from the extents
+        storage.sync_inode(file.ino).unwrap();
+        assert_eq!(storage.read_range(file.ino, 0, u64::MAX / 2).unwrap().len(), header.len() + appended);
+    }
+
+    #[test]
+    fn test_callers_own_what_they_create_and_mode_bits_decide_access() {
+        use crate::permissions::{is_denied, RequestContext, EXECUTE, READ, WRITE};
+
+        let (_pool_dir, _disk_dirs, storage) = setup_test_storage();
+        let owner = RequestContext::new(1000, 1000);
+        let other = RequestContext::new(1001, 1001);
+        let member = RequestContext::new(1002, 1002).with_groups(std::sync::Arc::new(|gid| gid == 1000));
+        let mut root = storage.get_inode(1).unwrap();
+        (root.uid, root.gid, root.mode) = (1000, 1000, 0o755);
+        storage.update_inode(&root).unwrap();
+
+        let home = storage.create_dir_as(&owner, 1, "home".into(), 0o755).unwrap();
+        let file = storage.create_file_as(&owner, home.ino, "secret".into(), 0o600).unwrap();
+        storage.write_file(file.ino, b"for my eyes", 0).unwrap();
+        let saved = storage.get_inode(file.ino).unwrap();
+        assert_eq!((saved.uid, saved.gid, saved.mode), (1000, 1000, 0o600));
+
+        let matrix = [
+            (0o600, &owner, READ | WRITE, true),
+            (0o600, &other, READ, false),
+            (0o600, &other, WRITE, false),
+            (0o600, &member, READ, false),
+            (0o644, &other, READ, true),
+            (0o644, &other, WRITE, false),
+            (0o640, &member, READ, true),
+            (0o640, &member, WRITE, false),
+            (0o640, &other, READ, false),
+            (0o666, &other, READ | WRITE, true),
+            (0o066, &owner, READ, false),
+            (0o000, &RequestContext::new(0, 0), READ | WRITE, true),
+            (0o644, &RequestContext::new(0, 0), EXECUTE, false),
+        ];
+        for (mode, ctx, mask, allowed) in matrix {
+            let mut inode = storage.get_inode(file.ino).unwrap();
+            inode.mode = mode;
+            storage.update_inode(&inode).unwrap();
+            match storage.access(ctx, file.ino, mask) {
+                Ok(()) => assert!(allowed, "{:?} got {:o} on mode {:o}", ctx, mask, mode),
+                Err(e) => assert!(!allowed && is_denied(&e), "{:?} denied {:o} on mode {:o}: {}", ctx, mask, mode, e),
+            }
+        }
+        assert!(!is_denied(&storage.access(&owner, 99, READ).unwrap_err()));
+
+        // Only callers who may write to the directory can add to it
+        assert!(is_denied(&storage.create_file_as(&other, home.ino, "intruder".into(), 0o644).unwrap_err()));
+        assert!(is_denied(&storage.create_dir_as(&other, home.ino, "intruder".into(), 0o755).unwrap_err()));
+        assert!(storage.find_child(home.ino, b"intruder").unwrap().is_none());
+        let theirs = storage.create_file_as(&RequestContext::new(0, 0), home.ino, "rootfile".into(), 0o644).unwrap();
+        assert_eq!((theirs.uid, theirs.gid), (0, 0));
+    }
+
+    #[test]
+    fn test_metadata_transactions_share_a_sync_within_the_commit_window() {
+        use crate::metadata_tx::{journal_dir, CommitStats};
+
+        let (pool_dir, _disk_dirs, storage) = setup_storage_with_disks(3);
+        let stats = || storage.metadata().read().unwrap().commit_stats();
+
+        // Without a window every transaction syncs before it returns
+        storage.set_metadata_commit_window(0);
+        for i in 0..5 {
+            storage.create_file(1, format!("eager_{}", i)).unwrap();
+        }
+        assert_eq!(stats(), CommitStats { transactions: 5, syncs: 5 });
+
+        // Creates from many threads fold into the syncs of a few windows
+        storage.set_metadata_commit_window(200);
+        std::thread::scope(|scope| {
+            for t in 0..8 {
+                let storage = &storage;
+                scope.spawn(move || {
+                    for i in 0..10 {
+                        storage.create_file(1, format!("grouped_{}_{}", t, i)).unwrap();
+                    }
+                });
+            }
+        });
+        storage.sync_all().unwrap();
+        let grouped = stats();
+        assert_eq!(grouped.transactions, 85);
+        assert!(grouped.syncs - 5 < 40, "{} syncs for 80 creates", grouped.syncs - 5);
+        assert_eq!(storage.list_directory(1).unwrap().len(), 85);
+
+        // Synced transactions need no journal records
+        let records = std::fs::read_dir(journal_dir(pool_dir.path())).unwrap().flatten();
+        assert_eq!(records.filter(|entry| entry.file_name().to_string_lossy().starts_with("tx.")).count(), 0);
+    }
+}
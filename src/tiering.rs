@@ -1,14 +1,15 @@
 use uuid::Uuid;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use serde::{Serialize, Deserialize};
 use crate::extent::{Extent, AccessClassification};
 
 /// Storage tier definition
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum StorageTier {
-    Hot,      // Fast, expensive, local NVMe
-    Warm,     // Medium speed, local HDD
-    Cold,     // Slow, cheap, archive storage
+    Hot,      // NVMe
+    Warm,     // SATA/SAS SSD
+    Cold,     // Rotational HDD or archive storage
 }
 
 impl Default for StorageTier {
@@ -21,8 +22,47 @@ impl StorageTier {
     pub fn description(&self) -> &'static str {
         match self {
             StorageTier::Hot => "Fast local NVMe storage for active data",
-            StorageTier::Warm => "Medium-speed local HDD storage for warm data",
-            StorageTier::Cold => "Slow archive storage for cold/historical data",
+            StorageTier::Warm => "SSD storage for warm data",
+            StorageTier::Cold => "Rotational or archive storage for cold/historical data",
+        }
+    }
+
+    /// Fastest first
+    pub const ALL: [StorageTier; 3] = [StorageTier::Hot, StorageTier::Warm, StorageTier::Cold];
+
+    /// 0 for the fastest tier, increasing toward slower ones
+    pub fn rank(&self) -> u8 {
+        match self {
+            StorageTier::Hot => 0,
+            StorageTier::Warm => 1,
+            StorageTier::Cold => 2,
+        }
+    }
+
+    /// The next slower tier, if any
+    pub fn slower(&self) -> Option<StorageTier> {
+        match self {
+            StorageTier::Hot => Some(StorageTier::Warm),
+            StorageTier::Warm => Some(StorageTier::Cold),
+            StorageTier::Cold => None,
+        }
+    }
+
+    /// Tier that extents of `classification` belong on
+    pub fn for_classification(classification: AccessClassification) -> Self {
+        match classification {
+            AccessClassification::Hot => StorageTier::Hot,
+            AccessClassification::Warm => StorageTier::Warm,
+            AccessClassification::Cold => StorageTier::Cold,
+        }
+    }
+
+    /// Kind of device the tier stands for, as accepted by `add-disk --tier`
+    pub fn device_kind(&self) -> &'static str {
+        match self {
+            StorageTier::Hot => "nvme",
+            StorageTier::Warm => "ssd",
+            StorageTier::Cold => "hdd",
         }
     }
 
@@ -302,6 +342,92 @@ fn current_timestamp() -> u64 {
         .as_secs()
 }
 
+impl std::str::FromStr for StorageTier {
+    type Err = anyhow::Error;
+
+    /// Parses `nvme`, `ssd` or `hdd`, or the tier names `hot`, `warm` and `cold`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "nvme" | "hot" => Ok(StorageTier::Hot),
+            "ssd" | "warm" => Ok(StorageTier::Warm),
+            "hdd" | "cold" => Ok(StorageTier::Cold),
+            _ => Err(anyhow::anyhow!("Unknown tier '{}' (expected nvme, ssd or hdd)", s)),
+        }
+    }
+}
+
+/// Tier holding most of an extent's fragments, ties going to the slower tier
+///
+/// Reads wait for the slowest fragment they need, so a split extent counts
+/// as slow. `None` when none of its fragments are on a known disk.
+pub fn extent_tier(extent: &Extent, disk_tiers: &HashMap<Uuid, StorageTier>) -> Option<StorageTier> {
+    let mut counts = [0usize; 3];
+    for location in &extent.fragment_locations {
+        if let Some(tier) = disk_tiers.get(&location.disk_uuid) {
+            counts[tier.rank() as usize] += 1;
+        }
+    }
+    StorageTier::ALL
+        .into_iter()
+        .filter(|tier| counts[tier.rank() as usize] > 0)
+        .max_by_key(|tier| (counts[tier.rank() as usize], tier.rank()))
+}
+
+/// Settings of the background pass that moves extents between tiers
+#[derive(Debug, Clone, Copy)]
+pub struct TierPassConfig {
+    pub interval: Duration,
+    /// Percent used above which a tier's coldest extents move one tier down
+    pub high_watermark: f64,
+    /// Demotion for capacity stops once the tier is down to this percent
+    pub low_watermark: f64,
+}
+
+impl Default for TierPassConfig {
+    fn default() -> Self {
+        TierPassConfig {
+            interval: Duration::from_secs(3600),
+            high_watermark: 85.0,
+            low_watermark: 75.0,
+        }
+    }
+}
+
+/// What one tiering pass moved
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TierPassReport {
+    /// Extents moved to the slower tier their classification asks for
+    pub extents_demoted: u64,
+    /// Extents moved down to relieve a tier above its high watermark
+    pub pressure_demotions: u64,
+    pub fragments_moved: u64,
+    pub bytes_moved: u64,
+    /// Extents that could not be moved; they are tried again next pass
+    pub failed: u64,
+}
+
+/// Capacity and contents of one tier, for `tier-status`
+#[derive(Debug, Clone, Serialize)]
+pub struct TierStatus {
+    pub tier: StorageTier,
+    pub disks: usize,
+    pub capacity_bytes: u64,
+    pub used_bytes: u64,
+    /// Extents whose fragments are mostly on this tier
+    pub extents: u64,
+    pub extent_bytes: u64,
+}
+
+impl TierStatus {
+    pub fn utilization_percent(&self) -> f64 {
+        if self.capacity_bytes == 0 {
+            0.0
+        } else {
+            self.used_bytes as f64 / self.capacity_bytes as f64 * 100.0
+        }
+    }
+}
+
 impl std::fmt::Display for StorageTier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
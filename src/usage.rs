@@ -0,0 +1,139 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::metadata::{FileName, FileType, Inode, MetadataManager};
+
+/// Space used by the tree under one path, as printed by `dynamicfs du`
+///
+/// `logical_bytes` is the sum of file sizes. Physical usage is what the
+/// fragments of the referenced extents take on disk, every copy or shard
+/// included: extents referenced by a single file count in `physical_bytes`,
+/// extents referenced more than once (by snapshots or reflinks) count once
+/// in `shared_bytes`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SubtreeUsage {
+    pub path: FileName,
+    pub ino: u64,
+    /// Levels below the path `du` was asked about
+    pub depth: usize,
+    pub files: u64,
+    pub logical_bytes: u64,
+    pub physical_bytes: u64,
+    pub shared_bytes: u64,
+    pub extents: u64,
+    pub fragments: u64,
+}
+
+/// Shared extents already counted in a subtree with their bytes and fragments,
+/// so its ancestors count each of them once too
+type SharedSeen = HashMap<Uuid, (u64, u64)>;
+
+/// Walks the tree under a path and reports usage per directory
+pub struct UsageScanner<'a> {
+    metadata: &'a MetadataManager,
+    references: HashMap<Uuid, u32>,
+    max_depth: Option<usize>,
+    /// Bytes each fragment carries on top of its data, e.g. the encryption header
+    fragment_overhead: usize,
+}
+
+impl<'a> UsageScanner<'a> {
+    pub fn new(metadata: &'a MetadataManager, max_depth: Option<usize>, fragment_overhead: usize) -> Result<Self> {
+        Ok(UsageScanner {
+            metadata,
+            references: metadata.extent_reference_counts()?,
+            max_depth,
+            fragment_overhead,
+        })
+    }
+
+    /// Usage of `path` and of every directory under it down to the maximum depth
+    ///
+    /// Directories are read one at a time, so memory grows with the depth of the
+    /// tree and the number of shared extents rather than with the inode count.
+    /// Rows are ordered by total physical usage, largest first.
+    pub fn scan(&self, path: &str) -> Result<Vec<SubtreeUsage>> {
+        let root = self.metadata.resolve_path(path)?;
+        let mut rows = Vec::new();
+        let (usage, _) = self.scan_inode(&root, self.metadata.path_of(root.ino)?, 0, &mut rows)?;
+        rows.push(usage);
+        rows.sort_by(|a, b| {
+            (b.physical_bytes + b.shared_bytes)
+                .cmp(&(a.physical_bytes + a.shared_bytes))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        Ok(rows)
+    }
+
+    /// Usage of the tree under `inode`, pushing the rows of directories below it to `rows`
+    fn scan_inode(
+        &self,
+        inode: &Inode,
+        path: FileName,
+        depth: usize,
+        rows: &mut Vec<SubtreeUsage>,
+    ) -> Result<(SubtreeUsage, SharedSeen)> {
+        let mut usage = SubtreeUsage { path, ino: inode.ino, depth, ..Default::default() };
+        let mut shared = SharedSeen::new();
+        if inode.file_type == FileType::Directory {
+            for child in self.metadata.list_directory(inode.ino)? {
+                let child_path = usage.path.join(&child.name);
+                let (child_usage, child_shared) = self.scan_inode(&child, child_path, depth + 1, rows)?;
+                usage.files += child_usage.files;
+                usage.logical_bytes += child_usage.logical_bytes;
+                usage.physical_bytes += child_usage.physical_bytes;
+                usage.extents += child_usage.extents - child_shared.len() as u64;
+                usage.fragments += child_usage.fragments - child_shared.values().map(|(_, f)| f).sum::<u64>();
+                for (uuid, counted) in child_shared {
+                    shared.entry(uuid).or_insert(counted);
+                }
+                if child.file_type == FileType::Directory && self.reported(depth + 1) {
+                    rows.push(child_usage);
+                }
+            }
+        } else {
+            usage.files = 1;
+            usage.logical_bytes = inode.size;
+            if let Ok(map) = self.metadata.load_extent_map(inode.ino) {
+                for uuid in map.data_extents() {
+                    if self.references.get(uuid).copied().unwrap_or(0) > 1 {
+                        shared.entry(*uuid).or_insert_with(|| self.extent_usage(uuid));
+                        continue;
+                    }
+                    let (bytes, fragments) = self.extent_usage(uuid);
+                    usage.physical_bytes += bytes;
+                    usage.extents += 1;
+                    usage.fragments += fragments;
+                }
+            }
+        }
+        // Shared extents are added last, once each, whichever children referenced them
+        for (bytes, fragments) in shared.values() {
+            usage.shared_bytes += bytes;
+            usage.extents += 1;
+            usage.fragments += fragments;
+        }
+        Ok((usage, shared))
+    }
+
+    fn reported(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max| depth <= max)
+    }
+
+    /// Bytes on disk and fragment count of one extent
+    fn extent_usage(&self, uuid: &Uuid) -> (u64, u64) {
+        match self.metadata.load_extent(uuid) {
+            Ok(extent) => {
+                let fragment_bytes = extent.redundancy.fragment_size(extent.stored_size()) + self.fragment_overhead;
+                let fragments = extent.fragment_locations.len() as u64;
+                (fragment_bytes as u64 * fragments, fragments)
+            }
+            Err(e) => {
+                log::warn!("Extent {} is referenced but cannot be loaded: {}", uuid, e);
+                (0, 0)
+            }
+        }
+    }
+}
@@ -34,8 +34,15 @@
 //! ```
 
 use anyhow::Result;
+use std::ffi::c_void;
 use std::path::Path;
+use crate::error::StorageError;
 use crate::fs_interface::FilesystemInterface;
+use crate::metadata::{FileType, Inode};
+use crate::winfsp_sys::{self, DirInfo, FileInfo, VolumeParams, WinFsp, NTSTATUS};
+
+/// Inode of the volume root
+const ROOT_INO: u64 = 1;
 
 /// Windows filesystem implementation using WinFsp
 ///
@@ -46,22 +53,23 @@ use crate::fs_interface::FilesystemInterface;
 /// # WinFsp Integration
 ///
 /// WinFsp provides a FUSE-like API for Windows, allowing user-mode filesystems.
-/// The integration would involve:
+/// Mounting:
 ///
 /// 1. **Initialization**: Load WinFsp DLL and create filesystem instance
-/// 2. **Callbacks**: Implement WinFsp operation callbacks
+/// 2. **Callbacks**: Register the operation callbacks in `callbacks`
 /// 3. **Mounting**: Mount the filesystem at a specified drive letter or path
 /// 4. **Operations**: Forward filesystem operations to the storage backend
 /// 5. **Unmounting**: Clean shutdown and resource cleanup
 ///
 /// # NTFS Compatibility
 ///
-/// The implementation provides NTFS-compatible semantics:
-/// - File attributes (readonly, hidden, system, archive)
-/// - Security descriptors (owner, group, DACL, SACL)
-/// - Alternate data streams (ADS)
-/// - File IDs and volume serial numbers
-/// - Reparse points and symbolic links
+/// The implementation provides NTFS-compatible semantics for:
+/// - File attributes (readonly, directory)
+/// - A fixed security descriptor on every file
+/// - File IDs (inode numbers) and volume serial numbers
+///
+/// Alternate data streams, reparse points and extended attributes are not
+/// supported.
 pub struct WindowsFS {
     /// The underlying storage backend implementing FilesystemInterface
     pub(crate) storage: Box<dyn FilesystemInterface + Send + Sync>,
@@ -135,14 +143,13 @@ impl WindowsFS {
 
     /// Mount the filesystem using WinFsp
     ///
+    /// Blocks until [`WindowsFS::unmount`] or [`request_shutdown`] is called
+    /// or Ctrl+C arrives, then unmounts and flushes buffered writes.
+    ///
     /// # Arguments
     ///
     /// * `mountpoint` - Drive letter (e.g., "Z:") or directory path
     ///
-    /// # Returns
-    ///
-    /// Returns `Ok(())` on successful mount, or an error describing the failure.
-    ///
     /// # Errors
     ///
     /// Returns an error if:
@@ -150,15 +157,6 @@ impl WindowsFS {
     /// - The mountpoint is invalid or already in use
     /// - Insufficient permissions to mount filesystem
     ///
-    /// # WinFsp Implementation Notes
-    ///
-    /// The actual implementation would:
-    /// 1. Load WinFsp DLL (winfsp-x64.dll or winfsp-x86.dll)
-    /// 2. Create FSP_FILE_SYSTEM structure
-    /// 3. Set up operation callbacks (Create, Read, Write, etc.)
-    /// 4. Call FspFileSystemCreate and FspFileSystemSetMountPoint
-    /// 5. Start the filesystem service with FspFileSystemStartDispatcher
-    ///
     /// # Example
     ///
     /// ```rust,ignore
@@ -168,84 +166,82 @@ impl WindowsFS {
     /// windows_fs.mount(mountpoint)?;
     /// ```
     pub fn mount(&self, mountpoint: &Path) -> Result<()> {
-        // Phase 9.2: WinFsp Integration Interface
-        //
-        // This is the entry point for WinFsp integration. To implement:
-        //
-        // 1. Use the `winfsp` crate or create FFI bindings
-        // 2. Load WinFsp DLL dynamically
-        // 3. Create filesystem configuration
-        // 4. Register operation callbacks
-        // 5. Mount at specified location
-        //
-        // Example skeleton:
-        // ```
-        // let mut fs_params = FspFileSystemParams::default();
-        // fs_params.Version = FSP_FSCTL_VOLUME_PARAMS_VERSION;
-        // fs_params.SectorSize = 512;
-        // fs_params.SectorsPerAllocationUnit = 1;
-        // fs_params.VolumeCreationTime = ..;
-        // fs_params.VolumeSerialNumber = ..;
-        // fs_params.FileInfoTimeout = 1000;
-        // fs_params.CaseSensitiveSearch = 1;
-        // fs_params.CasePreservedNames = 1;
-        // fs_params.UnicodeOnDisk = 1;
-        // fs_params.PersistentAcls = 1;
-        //
-        // let ops = FspFileSystemInterface {
-        //     GetVolumeInfo: Some(get_volume_info_callback),
-        //     SetVolumeLabel: Some(set_volume_label_callback),
-        //     GetSecurityByName: Some(get_security_by_name_callback),
-        //     Create: Some(create_callback),
-        //     Open: Some(open_callback),
-        //     Read: Some(read_callback),
-        //     Write: Some(write_callback),
-        //     // ... more callbacks
-        // };
-        //
-        // FspFileSystemCreate(device_path, &fs_params, &ops, &mut fs)?;
-        // FspFileSystemSetMountPoint(fs, mountpoint)?;
-        // FspFileSystemStartDispatcher(fs, 0)?;
-        // ```
-
-        log::info!("Attempting to mount Windows filesystem at {:?}", mountpoint);
-        log::warn!("WinFsp integration requires the WinFsp driver to be installed");
-        log::warn!("Download from: https://winfsp.dev/");
-        
-        Err(anyhow::anyhow!(
-            "WinFsp integration not yet implemented. \n\
-             To complete Phase 9.2, you need to:\n\
-             1. Install WinFsp from https://winfsp.dev/\n\
-             2. Add WinFsp bindings to Cargo.toml\n\
-             3. Implement the FSP_FILE_SYSTEM_INTERFACE callbacks\n\
-             4. Create and start the filesystem dispatcher\n\
-             \n\
-             The interface and structure are ready - only WinFsp-specific \n\
-             code needs to be added."
-        ))
+        self.mount_then(mountpoint, || {})
+    }
+
+    /// Mount as [`WindowsFS::mount`] does, calling `mounted` once the
+    /// dispatcher is serving requests
+    pub fn mount_then(&self, mountpoint: &Path, mounted: impl FnOnce()) -> Result<()> {
+        log::info!("Mounting Windows filesystem at {:?}", mountpoint);
+        let winfsp = WinFsp::load()?;
+        let volume = Volume {
+            fs: self,
+            security: windows_utils::default_security_descriptor()?,
+            case_sensitive: self.fs_flags & windows_utils::FILE_CASE_SENSITIVE_SEARCH != 0,
+            add_dir_info: winfsp.add_dir_info,
+        };
+
+        let (now, now_nsec) = crate::metadata::now_timespec();
+        // SAFETY: all-zero is the documented default for every field
+        let mut params: VolumeParams = unsafe { std::mem::zeroed() };
+        params.version = std::mem::size_of::<VolumeParams>() as u16;
+        params.sector_size = 4096;
+        params.sectors_per_allocation_unit = 1;
+        params.max_component_length = self.max_component_length.min(u16::MAX as u32) as u16;
+        params.volume_creation_time = windows_utils::unix_to_filetime(now, now_nsec);
+        params.volume_serial_number = now as u32;
+        params.file_info_timeout = 1000;
+        params.flags = volume_flags(self.fs_flags);
+        winfsp_sys::copy_wide(&mut params.file_system_name, &self.fs_name);
+
+        // WinFsp keeps pointers to both until FspFileSystemDelete
+        let interface = callbacks::interface();
+        let device = windows_utils::path_to_wide(Path::new(winfsp_sys::DISK_DEVICE_NAME));
+        let mut fsp: *mut winfsp_sys::FileSystem = std::ptr::null_mut();
+        // SAFETY: params, interface and volume outlive the file system, which is deleted below
+        unsafe {
+            check((winfsp.create)(device.as_ptr(), &params, &interface, &mut fsp), "FspFileSystemCreate")?;
+            (*fsp).user_context = &volume as *const Volume as *mut c_void;
+            let started = check(
+                (winfsp.set_mount_point)(fsp, windows_utils::path_to_wide(mountpoint).as_ptr()),
+                "FspFileSystemSetMountPoint",
+            )
+            .and_then(|()| check((winfsp.start_dispatcher)(fsp, 0), "FspFileSystemStartDispatcher"));
+            if let Err(e) = started {
+                (winfsp.delete)(fsp);
+                return Err(e);
+            }
+        }
+
+        shutdown::install();
+        log::info!("Mounted {:?}", mountpoint);
+        mounted();
+        while !shutdown::requested() {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        log::info!("Shutdown requested, unmounting {:?}", mountpoint);
+
+        // SAFETY: fsp was created above and is not used after this
+        unsafe {
+            (winfsp.stop_dispatcher)(fsp);
+            (winfsp.remove_mount_point)(fsp);
+            (winfsp.delete)(fsp);
+        }
+        shutdown::restore();
+        self.storage
+            .sync_all()
+            .map_err(|e| anyhow::anyhow!("Failed to flush filesystem on unmount: {}", e))
     }
 
     /// Unmount the filesystem
     ///
-    /// # Arguments
-    ///
-    /// * `mountpoint` - The mountpoint to unmount
-    ///
-    /// # Returns
-    ///
-    /// Returns `Ok(())` on successful unmount, or an error.
-    ///
-    /// # Implementation
-    ///
-    /// The actual implementation would call:
-    /// ```ignore
-    /// FspFileSystemStopDispatcher(fs);
-    /// FspFileSystemDelete(fs);
-    /// ```
-    pub fn unmount(&self, _mountpoint: &Path) -> Result<()> {
-        log::info!("Unmounting Windows filesystem");
-        
-        Err(anyhow::anyhow!("WinFsp unmounting not yet implemented"))
+    /// Asks the [`WindowsFS::mount`] call serving this process to stop its
+    /// dispatcher and remove the mountpoint; the mount call returns once
+    /// buffered writes are flushed.
+    pub fn unmount(&self, mountpoint: &Path) -> Result<()> {
+        log::info!("Unmounting Windows filesystem at {:?}", mountpoint);
+        request_shutdown();
+        Ok(())
     }
 
     /// Get volume information for Windows
@@ -291,6 +287,689 @@ pub struct WinFspVolumeInfo {
     pub fs_name: String,
 }
 
+/// Ask a running [`WindowsFS::mount`] to unmount and return
+///
+/// A request made before the mount starts makes it return once mounted.
+pub fn request_shutdown() {
+    shutdown::request();
+}
+
+/// Fail with the name of the WinFsp call unless `status` is a success
+fn check(status: NTSTATUS, call: &str) -> Result<()> {
+    if status >= 0 {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("{} failed with NTSTATUS {:#010x}", call, status as u32))
+    }
+}
+
+/// WinFsp volume parameter flags for `FILE_*` filesystem flags
+///
+/// Names are always stored as Unicode. Cleanup is only posted for handles
+/// that modified or deleted their file.
+fn volume_flags(fs_flags: u32) -> u32 {
+    use winfsp_sys::volume_flags::*;
+    use windows_utils::*;
+
+    let mapping = [
+        (FILE_CASE_SENSITIVE_SEARCH, CASE_SENSITIVE_SEARCH),
+        (FILE_CASE_PRESERVED_NAMES, CASE_PRESERVED_NAMES),
+        (FILE_PERSISTENT_ACLS, PERSISTENT_ACLS),
+        (FILE_READ_ONLY_VOLUME, READ_ONLY_VOLUME),
+    ];
+    mapping
+        .iter()
+        .filter(|(fs_flag, _)| fs_flags & fs_flag != 0)
+        .fold(UNICODE_ON_DISK | POST_CLEANUP_WHEN_MODIFIED_ONLY, |flags, (_, bit)| flags | bit)
+}
+
+/// Map a storage error to an NTSTATUS, surfacing `NoSpace` as
+/// STATUS_DISK_FULL, `QuotaExceeded` as STATUS_QUOTA_EXCEEDED, `ReadOnly` as
+/// STATUS_MEDIA_WRITE_PROTECTED and `Corruption` as STATUS_FILE_CORRUPT_ERROR
+fn ntstatus(err: &anyhow::Error) -> NTSTATUS {
+    match StorageError::find(err) {
+        Some(StorageError::NotFound(_)) => winfsp_sys::STATUS_OBJECT_NAME_NOT_FOUND,
+        Some(StorageError::NoSpace(_)) => winfsp_sys::STATUS_DISK_FULL,
+        Some(StorageError::QuotaExceeded(_)) => winfsp_sys::STATUS_QUOTA_EXCEEDED,
+        Some(StorageError::ReadOnly(_)) => winfsp_sys::STATUS_MEDIA_WRITE_PROTECTED,
+        Some(StorageError::PermissionDenied(_)) => winfsp_sys::STATUS_ACCESS_DENIED,
+        Some(StorageError::Corruption { .. }) => winfsp_sys::STATUS_FILE_CORRUPT_ERROR,
+        Some(StorageError::Unsupported(_)) => winfsp_sys::STATUS_NOT_SUPPORTED,
+        Some(StorageError::Interrupted(_)) => winfsp_sys::STATUS_CANCELLED,
+        Some(StorageError::Io(io_err)) => io_ntstatus(io_err.kind()),
+        Some(_) => winfsp_sys::STATUS_IO_DEVICE_ERROR,
+        None => err
+            .downcast_ref::<std::io::Error>()
+            .map_or(winfsp_sys::STATUS_IO_DEVICE_ERROR, |io_err| io_ntstatus(io_err.kind())),
+    }
+}
+
+fn io_ntstatus(kind: std::io::ErrorKind) -> NTSTATUS {
+    use std::io::ErrorKind;
+
+    match kind {
+        ErrorKind::StorageFull => winfsp_sys::STATUS_DISK_FULL,
+        ErrorKind::QuotaExceeded => winfsp_sys::STATUS_QUOTA_EXCEEDED,
+        ErrorKind::ReadOnlyFilesystem => winfsp_sys::STATUS_MEDIA_WRITE_PROTECTED,
+        ErrorKind::Interrupted => winfsp_sys::STATUS_CANCELLED,
+        ErrorKind::PermissionDenied => winfsp_sys::STATUS_ACCESS_DENIED,
+        ErrorKind::NotFound => winfsp_sys::STATUS_OBJECT_NAME_NOT_FOUND,
+        ErrorKind::DirectoryNotEmpty => winfsp_sys::STATUS_DIRECTORY_NOT_EMPTY,
+        _ => winfsp_sys::STATUS_IO_DEVICE_ERROR,
+    }
+}
+
+/// Console Ctrl+C, Ctrl+Break and close handling while mounted
+mod shutdown {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+
+    /// Console events received since `install`
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Shutdown asked for by `request`, which may come before `install`
+    static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    unsafe extern "system" fn handle(_ctrl_type: u32) -> i32 {
+        // A second event means the clean shutdown is stuck
+        if RECEIVED.fetch_add(1, Ordering::SeqCst) > 0 || REQUESTED.load(Ordering::SeqCst) {
+            std::process::exit(1);
+        }
+        1
+    }
+
+    pub(super) fn request() {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn install() {
+        RECEIVED.store(0, Ordering::SeqCst);
+        unsafe { SetConsoleCtrlHandler(Some(handle), 1) };
+    }
+
+    pub(super) fn requested() -> bool {
+        REQUESTED.load(Ordering::SeqCst) || RECEIVED.load(Ordering::SeqCst) > 0
+    }
+
+    pub(super) fn restore() {
+        REQUESTED.store(false, Ordering::SeqCst);
+        unsafe { SetConsoleCtrlHandler(Some(handle), 0) };
+    }
+}
+
+/// What the callbacks reach through `FSP_FILE_SYSTEM::UserContext`
+struct Volume<'a> {
+    fs: &'a WindowsFS,
+    /// Self-relative descriptor reported for every file
+    security: Vec<u8>,
+    case_sensitive: bool,
+    add_dir_info: unsafe extern "system" fn(*mut DirInfo, *mut c_void, u32, *mut u32) -> u8,
+}
+
+impl Volume<'_> {
+    fn storage(&self) -> &(dyn FilesystemInterface + Send + Sync) {
+        &*self.fs.storage
+    }
+
+    /// Child `name` of `parent`, ignoring case unless the volume is case-sensitive
+    fn find_child(&self, parent: u64, name: &str) -> Result<Option<Inode>> {
+        if let Some(child) = self.storage().find_child(parent, name.as_bytes())? {
+            return Ok(Some(child));
+        }
+        if self.case_sensitive {
+            return Ok(None);
+        }
+        let folded = name.to_lowercase();
+        Ok(self.storage().list_directory(parent)?.into_iter().find(|child| child.name.to_string_lossy().to_lowercase() == folded))
+    }
+
+    /// The inode at a WinFsp path such as `\dir\file`
+    fn resolve(&self, path: &str) -> Result<Inode, NTSTATUS> {
+        let mut inode = status(self.storage().get_inode(ROOT_INO))?;
+        let mut components = path.split('\\').filter(|c| !c.is_empty()).peekable();
+        while let Some(name) = components.next() {
+            if !matches!(inode.file_type, FileType::Directory) {
+                return Err(winfsp_sys::STATUS_NOT_A_DIRECTORY);
+            }
+            let missing = if components.peek().is_some() {
+                winfsp_sys::STATUS_OBJECT_PATH_NOT_FOUND
+            } else {
+                winfsp_sys::STATUS_OBJECT_NAME_NOT_FOUND
+            };
+            inode = status(self.find_child(inode.ino, name))?.ok_or(missing)?;
+        }
+        Ok(inode)
+    }
+
+    /// The directory holding `path` and the last component of `path`
+    fn resolve_parent<'p>(&self, path: &'p str) -> Result<(Inode, &'p str), NTSTATUS> {
+        let path = path.trim_end_matches('\\');
+        let (dir, name) = path.rsplit_once('\\').unwrap_or(("", path));
+        if name.is_empty() || name.encode_utf16().count() > self.fs.max_component_length as usize {
+            return Err(winfsp_sys::STATUS_OBJECT_NAME_INVALID);
+        }
+        let parent = self.resolve(dir).map_err(|s| match s {
+            winfsp_sys::STATUS_OBJECT_NAME_NOT_FOUND => winfsp_sys::STATUS_OBJECT_PATH_NOT_FOUND,
+            other => other,
+        })?;
+        if !matches!(parent.file_type, FileType::Directory) {
+            return Err(winfsp_sys::STATUS_OBJECT_PATH_NOT_FOUND);
+        }
+        Ok((parent, name))
+    }
+
+    /// Truncate or zero-extend a regular file
+    fn resize(&self, inode: &Inode, size: u64) -> Result<()> {
+        let storage = self.storage();
+        if size < inode.size {
+            let data = storage.read_range(inode.ino, 0, size)?;
+            storage.write_file(inode.ino, &data, 0)?;
+        } else if size > inode.size && storage.zero_range(inode.ino, inode.size, size - inode.size, false).is_err() {
+            storage.buffered_write(inode.ino, inode.size, &vec![0; (size - inode.size) as usize])?;
+        }
+        let mut inode = storage.get_inode(inode.ino)?;
+        inode.touch_contents(crate::metadata::now_timespec());
+        storage.update_inode(&inode)
+    }
+}
+
+/// Bytes per allocation unit reported to Windows
+const ALLOCATION_UNIT: u64 = 4096;
+
+/// WinFsp file information for an inode
+fn file_info(inode: &Inode) -> FileInfo {
+    use windows_utils::{unix_mode_to_windows_attrs, unix_to_filetime};
+
+    let is_dir = matches!(inode.file_type, FileType::Directory);
+    let size = if is_dir { 0 } else { inode.size };
+    let (crtime, crtime_nsec) = inode.crtime();
+    FileInfo {
+        file_attributes: unix_mode_to_windows_attrs(inode.mode | if is_dir { 0o040000 } else { 0 }),
+        allocation_size: size.div_ceil(ALLOCATION_UNIT) * ALLOCATION_UNIT,
+        file_size: size,
+        creation_time: unix_to_filetime(crtime, crtime_nsec),
+        last_access_time: unix_to_filetime(inode.atime, inode.atime_nsec),
+        last_write_time: unix_to_filetime(inode.mtime, inode.mtime_nsec),
+        change_time: unix_to_filetime(inode.ctime, inode.ctime_nsec),
+        index_number: inode.ino,
+        ..FileInfo::default()
+    }
+}
+
+/// Set or clear FILE_ATTRIBUTE_READONLY by dropping or restoring the owner write bit
+fn set_readonly(inode: &mut Inode, readonly: bool) {
+    if readonly {
+        inode.mode &= !0o222;
+    } else if inode.mode & 0o200 == 0 {
+        inode.mode |= 0o200;
+    }
+}
+
+fn status<T>(result: Result<T>) -> Result<T, NTSTATUS> {
+    result.map_err(|e| ntstatus(&e))
+}
+
+/// The `extern "system"` functions WinFsp calls
+///
+/// Each one recovers the [`Volume`] from the file system's user context and
+/// the open inode from the file context it handed out in Create or Open.
+mod callbacks {
+    use super::*;
+    use crate::winfsp_sys::*;
+    use std::panic::AssertUnwindSafe;
+
+    /// The file context of an open handle
+    struct OpenFile {
+        ino: u64,
+    }
+
+    pub(super) fn interface() -> FileSystemInterface {
+        FileSystemInterface {
+            get_volume_info: Some(get_volume_info),
+            set_volume_label: None,
+            get_security_by_name: Some(get_security_by_name),
+            create: Some(create),
+            open: Some(open),
+            overwrite: Some(overwrite),
+            cleanup: Some(cleanup),
+            close: Some(close),
+            read: Some(read),
+            write: Some(write),
+            flush: Some(flush),
+            get_file_info: Some(get_file_info),
+            set_basic_info: Some(set_basic_info),
+            set_file_size: Some(set_file_size),
+            can_delete: Some(can_delete),
+            rename: Some(rename),
+            get_security: Some(get_security),
+            set_security: None,
+            read_directory: Some(read_directory),
+            unused: [0; 45],
+        }
+    }
+
+    /// Run a callback body, turning a panic into STATUS_IO_DEVICE_ERROR
+    fn run(body: impl FnOnce() -> Result<(), NTSTATUS>) -> NTSTATUS {
+        match std::panic::catch_unwind(AssertUnwindSafe(body)) {
+            Ok(Ok(())) => STATUS_SUCCESS,
+            Ok(Err(status)) => status,
+            Err(_) => {
+                log::error!("WinFsp callback panicked");
+                STATUS_IO_DEVICE_ERROR
+            }
+        }
+    }
+
+    unsafe fn volume<'a>(fs: *mut FileSystem) -> &'a Volume<'a> {
+        &*((*fs).user_context as *const Volume)
+    }
+
+    unsafe fn open_ino(context: *mut c_void) -> u64 {
+        (*(context as *const OpenFile)).ino
+    }
+
+    unsafe fn hand_out(inode: &Inode, p_context: *mut *mut c_void, info: *mut FileInfo) {
+        *p_context = Box::into_raw(Box::new(OpenFile { ino: inode.ino })) as *mut c_void;
+        *info = file_info(inode);
+    }
+
+    /// Copy the volume descriptor out, or report the size needed
+    unsafe fn copy_security(volume: &Volume, descriptor: *mut c_void, p_size: *mut usize) -> Result<(), NTSTATUS> {
+        let needed = volume.security.len();
+        if *p_size < needed {
+            *p_size = needed;
+            return Err(STATUS_BUFFER_OVERFLOW);
+        }
+        *p_size = needed;
+        if !descriptor.is_null() {
+            std::ptr::copy_nonoverlapping(volume.security.as_ptr(), descriptor as *mut u8, needed);
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn get_volume_info(fs: *mut FileSystem, info: *mut VolumeInfo) -> NTSTATUS {
+        run(|| {
+            let stats = volume(fs).fs.get_volume_info();
+            (*info).total_size = stats.total_size;
+            (*info).free_size = stats.free_size;
+            let len = copy_wide(&mut (*info).volume_label, &stats.volume_label);
+            (*info).volume_label_length = (len * 2) as u16;
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn get_security_by_name(
+        fs: *mut FileSystem,
+        name: *mut u16,
+        p_attributes: *mut u32,
+        descriptor: *mut c_void,
+        p_size: *mut usize,
+    ) -> NTSTATUS {
+        run(|| {
+            let volume = volume(fs);
+            let inode = volume.resolve(&from_wide(name).unwrap_or_default())?;
+            if !p_attributes.is_null() {
+                *p_attributes = file_info(&inode).file_attributes;
+            }
+            if !p_size.is_null() {
+                copy_security(volume, descriptor, p_size)?;
+            }
+            Ok(())
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe extern "system" fn create(
+        fs: *mut FileSystem,
+        name: *mut u16,
+        create_options: u32,
+        _granted_access: u32,
+        attributes: u32,
+        _descriptor: *mut c_void,
+        _allocation_size: u64,
+        p_context: *mut *mut c_void,
+        info: *mut FileInfo,
+    ) -> NTSTATUS {
+        run(|| {
+            let volume = volume(fs);
+            let storage = volume.storage();
+            let path = from_wide(name).unwrap_or_default();
+            let (parent, leaf) = volume.resolve_parent(&path)?;
+            if status(volume.find_child(parent.ino, leaf))?.is_some() {
+                return Err(STATUS_OBJECT_NAME_COLLISION);
+            }
+            let mut inode = if create_options & FILE_DIRECTORY_FILE != 0 {
+                status(storage.create_dir(parent.ino, leaf.into()))?
+            } else {
+                status(storage.create_file(parent.ino, leaf.into()))?
+            };
+            if attributes & windows_utils::FILE_ATTRIBUTE_READONLY != 0 {
+                set_readonly(&mut inode, true);
+                status(storage.update_inode(&inode))?;
+            }
+            hand_out(&inode, p_context, info);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn open(
+        fs: *mut FileSystem,
+        name: *mut u16,
+        _create_options: u32,
+        _granted_access: u32,
+        p_context: *mut *mut c_void,
+        info: *mut FileInfo,
+    ) -> NTSTATUS {
+        run(|| {
+            let inode = volume(fs).resolve(&from_wide(name).unwrap_or_default())?;
+            hand_out(&inode, p_context, info);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn overwrite(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        attributes: u32,
+        replace_attributes: u8,
+        _allocation_size: u64,
+        info: *mut FileInfo,
+    ) -> NTSTATUS {
+        run(|| {
+            let storage = volume(fs).storage();
+            let ino = open_ino(context);
+            status(storage.write_file(ino, &[], 0))?;
+            let mut inode = status(storage.get_inode(ino))?;
+            let readonly = attributes & windows_utils::FILE_ATTRIBUTE_READONLY != 0;
+            if replace_attributes != 0 || readonly {
+                set_readonly(&mut inode, readonly);
+            }
+            inode.touch_contents(crate::metadata::now_timespec());
+            status(storage.update_inode(&inode))?;
+            *info = file_info(&inode);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn cleanup(fs: *mut FileSystem, context: *mut c_void, _name: *mut u16, flags: u32) {
+        run(|| {
+            let storage = volume(fs).storage();
+            let ino = open_ino(context);
+            if flags & FSP_CLEANUP_DELETE == 0 {
+                return status(storage.flush_file(ino));
+            }
+            let inode = status(storage.get_inode(ino))?;
+            let deleted = match inode.file_type {
+                FileType::Directory => storage.delete_dir(ino),
+                FileType::RegularFile => storage.delete_file(ino),
+            };
+            if let Err(e) = deleted {
+                log::error!("Deleting inode {} on cleanup failed: {}", ino, e);
+            }
+            Ok(())
+        });
+    }
+
+    unsafe extern "system" fn close(_fs: *mut FileSystem, context: *mut c_void) {
+        drop(Box::from_raw(context as *mut OpenFile));
+    }
+
+    unsafe extern "system" fn read(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        buffer: *mut c_void,
+        offset: u64,
+        length: u32,
+        p_transferred: *mut u32,
+    ) -> NTSTATUS {
+        run(|| {
+            let storage = volume(fs).storage();
+            let ino = open_ino(context);
+            if offset >= status(storage.get_inode(ino))?.size {
+                return Err(STATUS_END_OF_FILE);
+            }
+            let data = status(storage.read_range(ino, offset, length as u64))?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), buffer as *mut u8, data.len());
+            *p_transferred = data.len() as u32;
+            Ok(())
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe extern "system" fn write(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        buffer: *mut c_void,
+        offset: u64,
+        length: u32,
+        write_to_end_of_file: u8,
+        constrained_io: u8,
+        p_transferred: *mut u32,
+        info: *mut FileInfo,
+    ) -> NTSTATUS {
+        run(|| {
+            let storage = volume(fs).storage();
+            let ino = open_ino(context);
+            let inode = status(storage.get_inode(ino))?;
+            let offset = if write_to_end_of_file != 0 { inode.size } else { offset };
+            // Paging I/O may not extend the file
+            let length = if constrained_io != 0 {
+                (length as u64).min(inode.size.saturating_sub(offset))
+            } else {
+                length as u64
+            };
+            if length > 0 {
+                let data = std::slice::from_raw_parts(buffer as *const u8, length as usize);
+                status(storage.buffered_write(ino, offset, data))?;
+            }
+            *p_transferred = length as u32;
+            *info = file_info(&status(storage.get_inode(ino))?);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn flush(fs: *mut FileSystem, context: *mut c_void, info: *mut FileInfo) -> NTSTATUS {
+        run(|| {
+            let storage = volume(fs).storage();
+            // A null context flushes the whole volume
+            if context.is_null() {
+                return status(storage.sync_all());
+            }
+            let ino = open_ino(context);
+            status(storage.sync_inode(ino))?;
+            *info = file_info(&status(storage.get_inode(ino))?);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn get_file_info(fs: *mut FileSystem, context: *mut c_void, info: *mut FileInfo) -> NTSTATUS {
+        run(|| {
+            *info = file_info(&status(volume(fs).storage().get_inode(open_ino(context)))?);
+            Ok(())
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe extern "system" fn set_basic_info(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        attributes: u32,
+        creation_time: u64,
+        last_access_time: u64,
+        last_write_time: u64,
+        change_time: u64,
+        info: *mut FileInfo,
+    ) -> NTSTATUS {
+        use windows_utils::filetime_to_unix;
+
+        run(|| {
+            let storage = volume(fs).storage();
+            let mut inode = status(storage.get_inode(open_ino(context)))?;
+            if attributes != INVALID_FILE_ATTRIBUTES {
+                set_readonly(&mut inode, attributes & windows_utils::FILE_ATTRIBUTE_READONLY != 0);
+            }
+            // Zero leaves a time unchanged
+            if creation_time != 0 {
+                inode.crtime = Some(filetime_to_unix(creation_time));
+            }
+            if last_access_time != 0 {
+                inode.set_atime(filetime_to_unix(last_access_time));
+            }
+            if last_write_time != 0 {
+                inode.set_mtime(filetime_to_unix(last_write_time));
+            }
+            if change_time != 0 {
+                inode.set_ctime(filetime_to_unix(change_time));
+            }
+            status(storage.update_inode(&inode))?;
+            *info = file_info(&inode);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn set_file_size(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        new_size: u64,
+        set_allocation_size: u8,
+        info: *mut FileInfo,
+    ) -> NTSTATUS {
+        run(|| {
+            let volume = volume(fs);
+            let ino = open_ino(context);
+            let inode = status(volume.storage().get_inode(ino))?;
+            // Allocation is not reserved ahead; only shrinking it below the size truncates
+            if set_allocation_size == 0 || new_size < inode.size {
+                status(volume.resize(&inode, new_size))?;
+            }
+            *info = file_info(&status(volume.storage().get_inode(ino))?);
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn can_delete(fs: *mut FileSystem, context: *mut c_void, _name: *mut u16) -> NTSTATUS {
+        run(|| {
+            let storage = volume(fs).storage();
+            let ino = open_ino(context);
+            let inode = status(storage.get_inode(ino))?;
+            if matches!(inode.file_type, FileType::Directory) && !status(storage.list_directory(ino))?.is_empty() {
+                return Err(STATUS_DIRECTORY_NOT_EMPTY);
+            }
+            Ok(())
+        })
+    }
+
+    unsafe extern "system" fn rename(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        _name: *mut u16,
+        new_name: *mut u16,
+        replace_if_exists: u8,
+    ) -> NTSTATUS {
+        run(|| {
+            let volume = volume(fs);
+            let storage = volume.storage();
+            let mut inode = status(storage.get_inode(open_ino(context)))?;
+            let new_path = from_wide(new_name).unwrap_or_default();
+            let (parent, leaf) = volume.resolve_parent(&new_path)?;
+
+            // A directory cannot move below itself
+            let mut ancestor = parent.clone();
+            loop {
+                if ancestor.ino == inode.ino {
+                    return Err(STATUS_INVALID_PARAMETER);
+                }
+                if ancestor.ino == ROOT_INO {
+                    break;
+                }
+                ancestor = status(storage.get_inode(ancestor.parent_ino))?;
+            }
+
+            // The same inode under another case is a rename in place
+            if let Some(existing) = status(volume.find_child(parent.ino, leaf))?.filter(|e| e.ino != inode.ino) {
+                if replace_if_exists == 0 {
+                    return Err(STATUS_OBJECT_NAME_COLLISION);
+                }
+                if matches!(existing.file_type, FileType::Directory) {
+                    return Err(STATUS_ACCESS_DENIED);
+                }
+                status(storage.delete_file(existing.ino))?;
+            }
+
+            inode.parent_ino = parent.ino;
+            inode.name = leaf.into();
+            inode.set_ctime(crate::metadata::now_timespec());
+            status(storage.update_inode(&inode))
+        })
+    }
+
+    unsafe extern "system" fn get_security(
+        fs: *mut FileSystem,
+        _context: *mut c_void,
+        descriptor: *mut c_void,
+        p_size: *mut usize,
+    ) -> NTSTATUS {
+        run(|| copy_security(volume(fs), descriptor, p_size))
+    }
+
+    /// Entries sorted by name, with `.` and `..` first below the root,
+    /// resuming after `marker`
+    unsafe extern "system" fn read_directory(
+        fs: *mut FileSystem,
+        context: *mut c_void,
+        _pattern: *mut u16,
+        marker: *mut u16,
+        buffer: *mut c_void,
+        length: u32,
+        p_transferred: *mut u32,
+    ) -> NTSTATUS {
+        run(|| {
+            let volume = volume(fs);
+            let storage = volume.storage();
+            let dir = status(storage.get_inode(open_ino(context)))?;
+            let mut entries = Vec::new();
+            if dir.ino != ROOT_INO {
+                entries.push((".".to_string(), file_info(&dir)));
+                entries.push(("..".to_string(), file_info(&status(storage.get_inode(dir.parent_ino))?)));
+            }
+            let mut children = status(storage.list_directory(dir.ino))?;
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+            entries.extend(children.iter().map(|child| (child.name.to_string_lossy().into_owned(), file_info(child))));
+
+            // The marker is the last name returned; if it is gone, resume after where it sorted
+            let start = match from_wide(marker) {
+                None => 0,
+                Some(marker) => match entries.iter().position(|(name, _)| *name == marker) {
+                    Some(i) => i + 1,
+                    None => entries
+                        .iter()
+                        .position(|(name, _)| name != "." && name != ".." && *name > marker)
+                        .unwrap_or(entries.len()),
+                },
+            };
+
+            *p_transferred = 0;
+            for (name, info) in &entries[start..] {
+                let name: Vec<u16> = name.encode_utf16().collect();
+                let size = std::mem::size_of::<DirInfo>() + name.len() * 2;
+                // u64 storage keeps the header aligned
+                let mut raw = vec![0u64; size.div_ceil(8)];
+                let dir_info = raw.as_mut_ptr() as *mut DirInfo;
+                (*dir_info).size = size as u16;
+                (*dir_info).file_info = *info;
+                let name_ptr = (dir_info as *mut u8).add(std::mem::size_of::<DirInfo>()) as *mut u16;
+                std::ptr::copy_nonoverlapping(name.as_ptr(), name_ptr, name.len());
+                if (volume.add_dir_info)(dir_info, buffer, length, p_transferred) == 0 {
+                    // Full; WinFsp asks again from the last name it got
+                    return Ok(());
+                }
+            }
+            (volume.add_dir_info)(std::ptr::null_mut(), buffer, length, p_transferred);
+            Ok(())
+        })
+    }
+}
+
 /// Windows-specific path and permission utilities
 ///
 /// Phase 9.2: Windows-specific optimizations for path handling,
@@ -301,9 +980,60 @@ pub mod windows_utils {
     use std::os::windows::ffi::OsStrExt;
     use anyhow::Result;
 
-    // Note: These would use winapi for actual Windows integration
-    // For now, we provide the interface without full winapi implementation
-    // to avoid platform-specific compilation issues
+    /// `FILE_*` filesystem flags, as returned by GetVolumeInformation
+    pub const FILE_CASE_SENSITIVE_SEARCH: u32 = 0x0000_0001;
+    pub const FILE_CASE_PRESERVED_NAMES: u32 = 0x0000_0002;
+    pub const FILE_UNICODE_ON_DISK: u32 = 0x0000_0004;
+    pub const FILE_PERSISTENT_ACLS: u32 = 0x0000_0008;
+    pub const FILE_READ_ONLY_VOLUME: u32 = 0x0008_0000;
+
+    pub const FILE_ATTRIBUTE_READONLY: u32 = 0x0000_0001;
+
+    /// Seconds from the FILETIME epoch (1601-01-01) to the Unix epoch
+    const FILETIME_UNIX_OFFSET: i64 = 11_644_473_600;
+
+    /// Convert Unix seconds and nanoseconds to a FILETIME (100ns ticks since 1601)
+    ///
+    /// Times before 1601 clamp to zero, which WinFsp reads as "unset".
+    pub fn unix_to_filetime(secs: i64, nsec: u32) -> u64 {
+        let ticks = (secs as i128 + FILETIME_UNIX_OFFSET as i128) * 10_000_000 + (nsec / 100) as i128;
+        ticks.clamp(0, u64::MAX as i128) as u64
+    }
+
+    /// Convert a FILETIME to Unix seconds and nanoseconds
+    pub fn filetime_to_unix(filetime: u64) -> (i64, u32) {
+        let secs = (filetime / 10_000_000) as i64 - FILETIME_UNIX_OFFSET;
+        let nsec = (filetime % 10_000_000) as u32 * 100;
+        (secs, nsec)
+    }
+
+    /// Self-relative security descriptor reported for every file
+    ///
+    /// Owned by Administrators with full control for SYSTEM, Administrators
+    /// and Everyone; Unix permission bits still apply through the storage.
+    pub fn default_security_descriptor() -> Result<Vec<u8>> {
+        use winapi::shared::sddl::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+        use winapi::um::winbase::LocalFree;
+
+        let sddl: Vec<u16> = "O:BAG:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)(A;;FA;;;WD)"
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect();
+        let mut descriptor = std::ptr::null_mut();
+        let mut size = 0;
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(sddl.as_ptr(), SDDL_REVISION_1 as u32, &mut descriptor, &mut size)
+        };
+        if converted == 0 {
+            return Err(anyhow::anyhow!(
+                "Failed to build the volume security descriptor: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(descriptor as *const u8, size as usize) }.to_vec();
+        unsafe { LocalFree(descriptor) };
+        Ok(bytes)
+    }
 
     /// Convert Rust path to Windows wide string (UTF-16)
     ///
@@ -399,13 +1129,12 @@ pub mod windows_utils {
     /// - FILE_ATTRIBUTE_SYSTEM (0x00000004)
     /// - FILE_ATTRIBUTE_DIRECTORY (0x00000010)
     /// - FILE_ATTRIBUTE_ARCHIVE (0x00000020)
-    /// - FILE_ATTRIBUTE_NORMAL (0x00000080)
+    /// - FILE_ATTRIBUTE_NORMAL (0x00000080), only when nothing else applies
     pub fn unix_mode_to_windows_attrs(mode: u32) -> u32 {
         const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x00000010;
-        const FILE_ATTRIBUTE_READONLY: u32 = 0x00000001;
         const FILE_ATTRIBUTE_NORMAL: u32 = 0x00000080;
 
-        let mut attrs = FILE_ATTRIBUTE_NORMAL;
+        let mut attrs = 0;
 
         // Check if it's a directory (S_IFDIR = 0o040000)
         if mode & 0o040000 != 0 {
@@ -417,6 +1146,10 @@ pub mod windows_utils {
             attrs |= FILE_ATTRIBUTE_READONLY;
         }
 
+        // NORMAL is only valid on its own
+        if attrs == 0 {
+            attrs = FILE_ATTRIBUTE_NORMAL;
+        }
         attrs
     }
 
@@ -432,7 +1165,6 @@ pub mod windows_utils {
     /// - Readonly file: 0o444 (r--r--r--)
     pub fn windows_attrs_to_unix_mode(attrs: u32) -> u32 {
         const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x00000010;
-        const FILE_ATTRIBUTE_READONLY: u32 = 0x00000001;
 
         let mut mode = 0o644; // Default: rw-r--r--
 
@@ -595,4 +1327,100 @@ mod tests {
         assert_eq!(normalize_windows_path("path/to/file"), "path\\to\\file");
         assert_eq!(normalize_windows_path("C:/Users/test"), "C:\\Users\\test");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_filetime_conversion() {
+        use windows_utils::{filetime_to_unix, unix_to_filetime};
+
+        // 1970-01-01 is 11644473600 seconds after 1601-01-01
+        assert_eq!(unix_to_filetime(0, 0), 116_444_736_000_000_000);
+        assert_eq!(filetime_to_unix(116_444_736_000_000_000), (0, 0));
+        assert_eq!(filetime_to_unix(unix_to_filetime(1_700_000_000, 123_456_700)), (1_700_000_000, 123_456_700));
+        // Sub-100ns precision is lost, and times before 1601 clamp to zero
+        assert_eq!(filetime_to_unix(unix_to_filetime(5, 99)), (5, 0));
+        assert_eq!(unix_to_filetime(-20_000_000_000, 0), 0);
+    }
+
+    #[test]
+    fn test_normal_attribute_stands_alone() {
+        use windows_utils::unix_mode_to_windows_attrs;
+
+        assert_eq!(unix_mode_to_windows_attrs(0o644), 0x80);
+        assert_eq!(unix_mode_to_windows_attrs(0o040755), 0x10);
+        assert_eq!(unix_mode_to_windows_attrs(0o040555), 0x11);
+    }
+
+    #[test]
+    fn test_volume_flags_follow_fs_flags() {
+        use winfsp_sys::volume_flags::*;
+        use windows_utils::*;
+
+        let flags = volume_flags(FILE_CASE_SENSITIVE_SEARCH | FILE_CASE_PRESERVED_NAMES);
+        assert_ne!(flags & CASE_SENSITIVE_SEARCH, 0);
+        assert_ne!(flags & CASE_PRESERVED_NAMES, 0);
+        assert_ne!(flags & UNICODE_ON_DISK, 0);
+        assert_eq!(flags & READ_ONLY_VOLUME, 0);
+
+        let flags = volume_flags(FILE_CASE_PRESERVED_NAMES | FILE_READ_ONLY_VOLUME);
+        assert_eq!(flags & CASE_SENSITIVE_SEARCH, 0);
+        assert_ne!(flags & READ_ONLY_VOLUME, 0);
+    }
+
+    #[test]
+    fn test_file_info_for_inodes() {
+        let mut file = Inode::new_file(7, ROOT_INO, "a.txt".to_string());
+        file.size = 5000;
+        let info = file_info(&file);
+        assert_eq!((info.file_size, info.allocation_size, info.index_number), (5000, 8192, 7));
+        assert_eq!(windows_utils::filetime_to_unix(info.last_write_time), (file.mtime, file.mtime_nsec / 100 * 100));
+
+        set_readonly(&mut file, true);
+        assert_eq!(file_info(&file).file_attributes, windows_utils::FILE_ATTRIBUTE_READONLY);
+        set_readonly(&mut file, false);
+        assert_eq!(file.mode & 0o200, 0o200);
+
+        let dir = Inode::new_dir(8, ROOT_INO, "d".to_string());
+        let info = file_info(&dir);
+        assert_eq!((info.file_attributes, info.file_size), (0x10, 0));
+    }
+
+    #[test]
+    fn test_storage_errors_map_to_ntstatus() {
+        use std::io::{Error, ErrorKind};
+
+        let status = |kind| ntstatus(&anyhow::Error::new(Error::new(kind, "test")));
+        assert_eq!(status(ErrorKind::StorageFull), winfsp_sys::STATUS_DISK_FULL);
+        assert_eq!(status(ErrorKind::QuotaExceeded), winfsp_sys::STATUS_QUOTA_EXCEEDED);
+        assert_eq!(status(ErrorKind::ReadOnlyFilesystem), winfsp_sys::STATUS_MEDIA_WRITE_PROTECTED);
+        assert_eq!(ntstatus(&anyhow::anyhow!("checksum mismatch")), winfsp_sys::STATUS_IO_DEVICE_ERROR);
+        let corrupt = StorageError::Corruption { extent: uuid::Uuid::nil(), detail: "bad checksum".into() };
+        assert_eq!(ntstatus(&corrupt.into()), winfsp_sys::STATUS_FILE_CORRUPT_ERROR);
+        assert_eq!(ntstatus(&StorageError::NoSpace("full".into()).into()), winfsp_sys::STATUS_DISK_FULL);
+    }
+
+    #[test]
+    fn test_resolve_paths_on_a_case_insensitive_volume() {
+        use crate::memory_fs::MemoryFs;
+
+        let fs = WindowsFS::new(Box::new(MemoryFs::new()));
+        let dir = fs.storage.create_dir(ROOT_INO, "Docs".into()).unwrap();
+        let file = fs.storage.create_file(dir.ino, "Notes.txt".into()).unwrap();
+        let volume = |case_sensitive| Volume { fs: &fs, security: Vec::new(), case_sensitive, add_dir_info: no_dir_info };
+
+        let insensitive = volume(false);
+        assert_eq!(insensitive.resolve("\\docs\\NOTES.TXT").unwrap().ino, file.ino);
+        assert_eq!(insensitive.resolve("\\").unwrap().ino, ROOT_INO);
+        assert_eq!(insensitive.resolve("\\docs\\missing").unwrap_err(), winfsp_sys::STATUS_OBJECT_NAME_NOT_FOUND);
+        assert_eq!(insensitive.resolve("\\nope\\x").unwrap_err(), winfsp_sys::STATUS_OBJECT_PATH_NOT_FOUND);
+        let (parent, leaf) = insensitive.resolve_parent("\\DOCS\\new.txt").unwrap();
+        assert_eq!((parent.ino, leaf), (dir.ino, "new.txt"));
+
+        let sensitive = volume(true);
+        assert_eq!(sensitive.resolve("\\Docs\\Notes.txt").unwrap().ino, file.ino);
+        assert_eq!(sensitive.resolve("\\docs").unwrap_err(), winfsp_sys::STATUS_OBJECT_NAME_NOT_FOUND);
+    }
+
+    unsafe extern "system" fn no_dir_info(_: *mut DirInfo, _: *mut c_void, _: u32, _: *mut u32) -> u8 {
+        0
+    }
+}
@@ -0,0 +1,246 @@
+//! Raw bindings to the WinFsp user-mode API
+//!
+//! Only what `windows_fs` uses is declared. The layouts follow `winfsp/fsctl.h`
+//! and `winfsp/winfsp.h` from WinFsp 2.0. The DLL is loaded when a filesystem
+//! is mounted, so the binary starts on machines without WinFsp and fails only
+//! when asked to mount.
+
+// Most fields are only read on the WinFsp side of the boundary
+#![allow(dead_code)]
+
+use anyhow::{anyhow, Result};
+use crate::windows_fs::windows_utils::path_to_wide;
+use std::ffi::c_void;
+use winapi::shared::minwindef::HMODULE;
+use winapi::um::libloaderapi::{GetProcAddress, LoadLibraryW};
+
+pub type NTSTATUS = i32;
+
+pub const STATUS_SUCCESS: NTSTATUS = 0;
+pub const STATUS_BUFFER_OVERFLOW: NTSTATUS = 0x8000_0005_u32 as i32;
+pub const STATUS_INVALID_PARAMETER: NTSTATUS = 0xC000_000D_u32 as i32;
+pub const STATUS_END_OF_FILE: NTSTATUS = 0xC000_0011_u32 as i32;
+pub const STATUS_ACCESS_DENIED: NTSTATUS = 0xC000_0022_u32 as i32;
+pub const STATUS_OBJECT_NAME_INVALID: NTSTATUS = 0xC000_0033_u32 as i32;
+pub const STATUS_OBJECT_NAME_NOT_FOUND: NTSTATUS = 0xC000_0034_u32 as i32;
+pub const STATUS_OBJECT_NAME_COLLISION: NTSTATUS = 0xC000_0035_u32 as i32;
+pub const STATUS_OBJECT_PATH_NOT_FOUND: NTSTATUS = 0xC000_003A_u32 as i32;
+pub const STATUS_QUOTA_EXCEEDED: NTSTATUS = 0xC000_0044_u32 as i32;
+pub const STATUS_DISK_FULL: NTSTATUS = 0xC000_007F_u32 as i32;
+pub const STATUS_MEDIA_WRITE_PROTECTED: NTSTATUS = 0xC000_00A2_u32 as i32;
+pub const STATUS_NOT_SUPPORTED: NTSTATUS = 0xC000_00BB_u32 as i32;
+pub const STATUS_CANCELLED: NTSTATUS = 0xC000_0120_u32 as i32;
+pub const STATUS_DIRECTORY_NOT_EMPTY: NTSTATUS = 0xC000_0101_u32 as i32;
+pub const STATUS_FILE_CORRUPT_ERROR: NTSTATUS = 0xC000_0102_u32 as i32;
+pub const STATUS_NOT_A_DIRECTORY: NTSTATUS = 0xC000_0103_u32 as i32;
+pub const STATUS_IO_DEVICE_ERROR: NTSTATUS = 0xC000_0185_u32 as i32;
+
+/// `FILE_DIRECTORY_FILE` in the CreateOptions of Create
+pub const FILE_DIRECTORY_FILE: u32 = 0x0000_0001;
+/// FileAttributes value meaning "leave the attributes alone" in SetBasicInfo
+pub const INVALID_FILE_ATTRIBUTES: u32 = 0xFFFF_FFFF;
+/// Cleanup flag: the file was marked for deletion and this is its last handle
+pub const FSP_CLEANUP_DELETE: u32 = 0x01;
+
+/// Device for volumes mounted at a drive letter or directory
+pub const DISK_DEVICE_NAME: &str = "WinFsp.Disk";
+
+/// Bits of `VolumeParams::flags`, in the order of the C bitfield
+pub mod volume_flags {
+    pub const CASE_SENSITIVE_SEARCH: u32 = 1 << 0;
+    pub const CASE_PRESERVED_NAMES: u32 = 1 << 1;
+    pub const UNICODE_ON_DISK: u32 = 1 << 2;
+    pub const PERSISTENT_ACLS: u32 = 1 << 3;
+    pub const READ_ONLY_VOLUME: u32 = 1 << 9;
+    pub const POST_CLEANUP_WHEN_MODIFIED_ONLY: u32 = 1 << 10;
+}
+
+/// `FSP_FSCTL_VOLUME_PARAMS`
+#[repr(C)]
+pub struct VolumeParams {
+    pub version: u16,
+    pub sector_size: u16,
+    pub sectors_per_allocation_unit: u16,
+    pub max_component_length: u16,
+    pub volume_creation_time: u64,
+    pub volume_serial_number: u32,
+    pub transact_timeout: u32,
+    pub irp_timeout: u32,
+    pub irp_capacity: u32,
+    pub file_info_timeout: u32,
+    pub flags: u32,
+    pub prefix: [u16; 192],
+    pub file_system_name: [u16; 16],
+    pub additional_flags: u32,
+    pub volume_info_timeout: u32,
+    pub dir_info_timeout: u32,
+    pub security_timeout: u32,
+    pub stream_info_timeout: u32,
+    pub ea_timeout: u32,
+    pub fsext_control_code: u32,
+    pub reserved32: [u32; 1],
+    pub reserved64: [u64; 2],
+}
+
+const _: () = assert!(std::mem::size_of::<VolumeParams>() == 504);
+
+/// `FSP_FSCTL_VOLUME_INFO`
+#[repr(C)]
+pub struct VolumeInfo {
+    pub total_size: u64,
+    pub free_size: u64,
+    /// In bytes
+    pub volume_label_length: u16,
+    pub volume_label: [u16; 32],
+}
+
+/// `FSP_FSCTL_FILE_INFO`; times are FILETIMEs
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct FileInfo {
+    pub file_attributes: u32,
+    pub reparse_tag: u32,
+    pub allocation_size: u64,
+    pub file_size: u64,
+    pub creation_time: u64,
+    pub last_access_time: u64,
+    pub last_write_time: u64,
+    pub change_time: u64,
+    pub index_number: u64,
+    pub hard_links: u32,
+    pub ea_size: u32,
+}
+
+const _: () = assert!(std::mem::size_of::<FileInfo>() == 72);
+
+/// `FSP_FSCTL_DIR_INFO`, followed in memory by the UTF-16 name
+#[repr(C)]
+pub struct DirInfo {
+    /// Header plus name, in bytes
+    pub size: u16,
+    pub file_info: FileInfo,
+    pub next_offset: u64,
+    pub padding: [u8; 16],
+}
+
+const _: () = assert!(std::mem::size_of::<DirInfo>() == 104);
+
+/// The head of `FSP_FILE_SYSTEM`; the rest is private to WinFsp
+#[repr(C)]
+pub struct FileSystem {
+    pub version: u16,
+    pub user_context: *mut c_void,
+}
+
+type Fs = *mut FileSystem;
+type Wstr = *mut u16;
+
+/// `FSP_FILE_SYSTEM_INTERFACE`; entries left `None` fail with STATUS_INVALID_DEVICE_REQUEST
+#[repr(C)]
+#[allow(clippy::type_complexity)]
+pub struct FileSystemInterface {
+    pub get_volume_info: Option<unsafe extern "system" fn(Fs, *mut VolumeInfo) -> NTSTATUS>,
+    pub set_volume_label: Option<unsafe extern "system" fn(Fs, Wstr, *mut VolumeInfo) -> NTSTATUS>,
+    pub get_security_by_name:
+        Option<unsafe extern "system" fn(Fs, Wstr, *mut u32, *mut c_void, *mut usize) -> NTSTATUS>,
+    pub create: Option<
+        unsafe extern "system" fn(Fs, Wstr, u32, u32, u32, *mut c_void, u64, *mut *mut c_void, *mut FileInfo) -> NTSTATUS,
+    >,
+    pub open: Option<unsafe extern "system" fn(Fs, Wstr, u32, u32, *mut *mut c_void, *mut FileInfo) -> NTSTATUS>,
+    pub overwrite: Option<unsafe extern "system" fn(Fs, *mut c_void, u32, u8, u64, *mut FileInfo) -> NTSTATUS>,
+    pub cleanup: Option<unsafe extern "system" fn(Fs, *mut c_void, Wstr, u32)>,
+    pub close: Option<unsafe extern "system" fn(Fs, *mut c_void)>,
+    pub read: Option<unsafe extern "system" fn(Fs, *mut c_void, *mut c_void, u64, u32, *mut u32) -> NTSTATUS>,
+    pub write: Option<
+        unsafe extern "system" fn(Fs, *mut c_void, *mut c_void, u64, u32, u8, u8, *mut u32, *mut FileInfo) -> NTSTATUS,
+    >,
+    pub flush: Option<unsafe extern "system" fn(Fs, *mut c_void, *mut FileInfo) -> NTSTATUS>,
+    pub get_file_info: Option<unsafe extern "system" fn(Fs, *mut c_void, *mut FileInfo) -> NTSTATUS>,
+    pub set_basic_info:
+        Option<unsafe extern "system" fn(Fs, *mut c_void, u32, u64, u64, u64, u64, *mut FileInfo) -> NTSTATUS>,
+    pub set_file_size: Option<unsafe extern "system" fn(Fs, *mut c_void, u64, u8, *mut FileInfo) -> NTSTATUS>,
+    pub can_delete: Option<unsafe extern "system" fn(Fs, *mut c_void, Wstr) -> NTSTATUS>,
+    pub rename: Option<unsafe extern "system" fn(Fs, *mut c_void, Wstr, Wstr, u8) -> NTSTATUS>,
+    pub get_security: Option<unsafe extern "system" fn(Fs, *mut c_void, *mut c_void, *mut usize) -> NTSTATUS>,
+    pub set_security: Option<unsafe extern "system" fn(Fs, *mut c_void, u32, *mut c_void) -> NTSTATUS>,
+    pub read_directory:
+        Option<unsafe extern "system" fn(Fs, *mut c_void, Wstr, Wstr, *mut c_void, u32, *mut u32) -> NTSTATUS>,
+    /// ResolveReparsePoints through DispatcherStopped, then the reserved slots
+    pub unused: [usize; 45],
+}
+
+const _: () = assert!(std::mem::size_of::<FileSystemInterface>() == 64 * std::mem::size_of::<usize>());
+
+/// Entry points resolved from the WinFsp DLL
+pub struct WinFsp {
+    pub create: unsafe extern "system" fn(*const u16, *const VolumeParams, *const FileSystemInterface, *mut Fs) -> NTSTATUS,
+    pub set_mount_point: unsafe extern "system" fn(Fs, *const u16) -> NTSTATUS,
+    pub start_dispatcher: unsafe extern "system" fn(Fs, u32) -> NTSTATUS,
+    pub stop_dispatcher: unsafe extern "system" fn(Fs),
+    pub remove_mount_point: unsafe extern "system" fn(Fs),
+    pub delete: unsafe extern "system" fn(Fs),
+    pub add_dir_info: unsafe extern "system" fn(*mut DirInfo, *mut c_void, u32, *mut u32) -> u8,
+}
+
+#[cfg(target_pointer_width = "64")]
+const DLL_NAME: &str = "winfsp-x64.dll";
+#[cfg(not(target_pointer_width = "64"))]
+const DLL_NAME: &str = "winfsp-x86.dll";
+
+impl WinFsp {
+    /// Load the DLL from the search path, then from the default install directory
+    pub fn load() -> Result<Self> {
+        let mut candidates = vec![std::path::PathBuf::from(DLL_NAME)];
+        for var in ["ProgramFiles(x86)", "ProgramFiles"] {
+            if let Some(dir) = std::env::var_os(var) {
+                candidates.push(std::path::Path::new(&dir).join("WinFsp").join("bin").join(DLL_NAME));
+            }
+        }
+        let module = candidates
+            .iter()
+            .map(|path| unsafe { LoadLibraryW(path_to_wide(path).as_ptr()) })
+            .find(|module| !module.is_null())
+            .ok_or_else(|| anyhow!("{} not found; install WinFsp from https://winfsp.dev/", DLL_NAME))?;
+
+        unsafe {
+            Ok(WinFsp {
+                create: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemCreate")?),
+                set_mount_point: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemSetMountPoint")?),
+                start_dispatcher: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemStartDispatcher")?),
+                stop_dispatcher: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemStopDispatcher")?),
+                remove_mount_point: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemRemoveMountPoint")?),
+                delete: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemDelete")?),
+                add_dir_info: std::mem::transmute::<*mut c_void, _>(symbol(module, "FspFileSystemAddDirInfo")?),
+            })
+        }
+    }
+}
+
+unsafe fn symbol(module: HMODULE, name: &str) -> Result<*mut c_void> {
+    let cname = std::ffi::CString::new(name)?;
+    let address = GetProcAddress(module, cname.as_ptr());
+    if address.is_null() {
+        return Err(anyhow!("{} does not export {}; WinFsp may be too old", DLL_NAME, name));
+    }
+    Ok(address as *mut c_void)
+}
+
+/// Copy `s` into a fixed UTF-16 field, truncating and leaving a terminator
+pub fn copy_wide(dest: &mut [u16], s: &str) -> usize {
+    let units: Vec<u16> = s.encode_utf16().take(dest.len().saturating_sub(1)).collect();
+    dest[..units.len()].copy_from_slice(&units);
+    dest[units.len()] = 0;
+    units.len()
+}
+
+/// Read a null-terminated UTF-16 string from WinFsp; None for a null pointer
+///
+/// # Safety
+/// `ptr` must be null or point at a null-terminated UTF-16 string.
+pub unsafe fn from_wide(ptr: *const u16) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let len = (0..).take_while(|&i| *ptr.add(i) != 0).count();
+    Some(String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len)))
+}
@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, Condvar};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
@@ -324,9 +324,8 @@ impl GroupCommitCoordinator {
                 }
                 MetadataOperation::SaveExtentMap(ino, extent_uuids) => {
                     let extent_map = crate::metadata::ExtentMap {
-                        ino,
                         extents: extent_uuids,
-                        checksum: None,
+                        ..crate::metadata::ExtentMap::new(ino, crate::extent::DEFAULT_EXTENT_SIZE)
                     };
                     metadata.save_extent_map(&extent_map)?;
                 }
@@ -359,3 +358,261 @@ impl GroupCommitCoordinator {
     }
 }
 
+
+/// Why buffered file data was written out to extents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushCause {
+    /// The buffered run reached an extent boundary
+    ExtentFull,
+    /// A write landed outside the buffered run
+    NonSequential,
+    /// fsync(2) on the file
+    Fsync,
+    /// The file was closed
+    Release,
+    /// The run sat idle for longer than the flush interval
+    Timer,
+    /// Buffered bytes exceeded the memory budget
+    MemoryPressure,
+    /// Another operation needed the file's data on disk (hole punch, policy change, unmount)
+    Explicit,
+}
+
+/// Tuning for the per-inode write buffer
+#[derive(Debug, Clone)]
+pub struct WriteBufferConfig {
+    /// Runs are written out in whole extents of this size as soon as they fill one
+    pub extent_size: usize,
+    /// Maximum dirty bytes held across all files before the oldest runs are flushed
+    pub memory_budget: usize,
+    /// Idle time after which a run is flushed by the background timer
+    pub flush_interval: Duration,
+}
+
+impl Default for WriteBufferConfig {
+    fn default() -> Self {
+        WriteBufferConfig {
+            extent_size: crate::extent::DEFAULT_EXTENT_SIZE,
+            memory_budget: 64 * 1024 * 1024,
+            flush_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Contiguous dirty bytes of one file
+#[derive(Debug, Clone)]
+pub struct DirtyRun {
+    pub offset: u64,
+    pub data: Vec<u8>,
+    last_write: Instant,
+}
+
+impl DirtyRun {
+    pub fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+/// Write-back buffer coalescing small sequential writes per inode
+///
+/// Each inode has at most one dirty run. Writes touching or overlapping it are
+/// merged in; a write elsewhere flushes the run first. Whole extents are handed
+/// to the flush callback as soon as the run crosses an extent boundary, so a
+/// stream of 4 KiB writes encodes each extent once instead of once per write.
+///
+/// Flush callbacks run with the buffer locked, which keeps flushes of the same
+/// file ordered. They must not call back into the buffer.
+pub struct WriteBuffer {
+    config: WriteBufferConfig,
+    runs: Mutex<HashMap<u64, DirtyRun>>,
+    shutdown: Mutex<bool>,
+    shutdown_signal: Condvar,
+}
+
+impl WriteBuffer {
+    pub fn new(config: WriteBufferConfig) -> Self {
+        WriteBuffer {
+            config,
+            runs: Mutex::new(HashMap::new()),
+            shutdown: Mutex::new(false),
+            shutdown_signal: Condvar::new(),
+        }
+    }
+
+    /// Buffer `data` at `offset`, flushing whatever the write pushes out
+    ///
+    /// Returns whether the write was coalesced into an existing run.
+    pub fn write<F>(&self, ino: u64, offset: u64, data: &[u8], mut flush: F) -> anyhow::Result<bool>
+    where
+        F: FnMut(u64, &DirtyRun, FlushCause) -> anyhow::Result<()>,
+    {
+        let mut runs = self.runs.lock().unwrap();
+        let now = Instant::now();
+
+        let coalesced = match runs.get_mut(&ino) {
+            Some(run) if offset >= run.offset && offset <= run.end() => {
+                let start = (offset - run.offset) as usize;
+                let end = start + data.len();
+                if run.data.len() < end {
+                    run.data.resize(end, 0);
+                }
+                run.data[start..end].copy_from_slice(data);
+                run.last_write = now;
+                true
+            }
+            Some(_) => {
+                let previous = runs.remove(&ino).unwrap();
+                Self::flush_run(&mut runs, ino, previous, FlushCause::NonSequential, &mut flush)?;
+                false
+            }
+            None => false,
+        };
+        if !coalesced {
+            runs.insert(ino, DirtyRun { offset, data: data.to_vec(), last_write: now });
+        }
+
+        // Hand off everything up to the last extent boundary the run has crossed
+        let run = runs.get_mut(&ino).unwrap();
+        let extent_size = self.config.extent_size as u64;
+        let boundary = run.end() / extent_size * extent_size;
+        if boundary > run.offset {
+            let tail = run.data.split_off((boundary - run.offset) as usize);
+            let full = DirtyRun {
+                offset: run.offset,
+                data: std::mem::replace(&mut run.data, tail),
+                last_write: now,
+            };
+            run.offset = boundary;
+            if run.data.is_empty() {
+                runs.remove(&ino);
+            }
+            Self::flush_run(&mut runs, ino, full, FlushCause::ExtentFull, &mut flush)?;
+        }
+
+        // Over budget: write out the least recently written runs
+        while runs.values().map(|run| run.data.len()).sum::<usize>() > self.config.memory_budget {
+            let oldest = *runs.iter().min_by_key(|(_, run)| run.last_write).unwrap().0;
+            let run = runs.remove(&oldest).unwrap();
+            Self::flush_run(&mut runs, oldest, run, FlushCause::MemoryPressure, &mut flush)?;
+        }
+
+        Ok(coalesced)
+    }
+
+    /// Flush the run of one inode, if any
+    pub fn flush_inode<F>(&self, ino: u64, cause: FlushCause, mut flush: F) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, &DirtyRun, FlushCause) -> anyhow::Result<()>,
+    {
+        let mut runs = self.runs.lock().unwrap();
+        match runs.remove(&ino) {
+            Some(run) => Self::flush_run(&mut runs, ino, run, cause, &mut flush),
+            None => Ok(()),
+        }
+    }
+
+    /// Flush every run, continuing past failures; returns the first error
+    pub fn flush_all<F>(&self, cause: FlushCause, mut flush: F) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, &DirtyRun, FlushCause) -> anyhow::Result<()>,
+    {
+        let mut runs = self.runs.lock().unwrap();
+        let inos: Vec<u64> = runs.keys().copied().collect();
+        Self::flush_each(&mut runs, inos, cause, &mut flush)
+    }
+
+    /// Flush runs idle for at least the flush interval; returns the first error
+    pub fn flush_expired<F>(&self, mut flush: F) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, &DirtyRun, FlushCause) -> anyhow::Result<()>,
+    {
+        let mut runs = self.runs.lock().unwrap();
+        let inos: Vec<u64> = runs
+            .iter()
+            .filter(|(_, run)| run.last_write.elapsed() >= self.config.flush_interval)
+            .map(|(ino, _)| *ino)
+            .collect();
+        Self::flush_each(&mut runs, inos, FlushCause::Timer, &mut flush)
+    }
+
+    /// Drop an inode's buffered data without writing it (overwrite, truncate, delete)
+    pub fn discard(&self, ino: u64) {
+        self.runs.lock().unwrap().remove(&ino);
+    }
+
+    /// Copy of an inode's dirty run, for reads to overlay on the stored data
+    pub fn snapshot(&self, ino: u64) -> Option<DirtyRun> {
+        self.runs.lock().unwrap().get(&ino).cloned()
+    }
+
+    /// End offset of an inode's dirty run
+    pub fn buffered_end(&self, ino: u64) -> Option<u64> {
+        self.runs.lock().unwrap().get(&ino).map(|run| run.end())
+    }
+
+    /// Sleep for half the flush interval; returns false once shut down
+    pub fn wait_tick(&self) -> bool {
+        let shutdown = self.shutdown.lock().unwrap();
+        let (shutdown, _) = self
+            .shutdown_signal
+            .wait_timeout_while(shutdown, (self.config.flush_interval / 2).max(Duration::from_millis(10)), |stop| !*stop)
+            .unwrap();
+        !*shutdown
+    }
+
+    /// Wake and stop the timer thread
+    pub fn shutdown(&self) {
+        *self.shutdown.lock().unwrap() = true;
+        self.shutdown_signal.notify_all();
+    }
+
+    fn flush_each<F>(
+        runs: &mut HashMap<u64, DirtyRun>,
+        inos: Vec<u64>,
+        cause: FlushCause,
+        flush: &mut F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, &DirtyRun, FlushCause) -> anyhow::Result<()>,
+    {
+        let mut first_error = None;
+        for ino in inos {
+            if let Some(run) = runs.remove(&ino) {
+                if let Err(e) = Self::flush_run(runs, ino, run, cause, flush) {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Hand a run to the flush callback, keeping it buffered if the flush fails
+    fn flush_run<F>(
+        runs: &mut HashMap<u64, DirtyRun>,
+        ino: u64,
+        run: DirtyRun,
+        cause: FlushCause,
+        flush: &mut F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(u64, &DirtyRun, FlushCause) -> anyhow::Result<()>,
+    {
+        if let Err(e) = flush(ino, &run, cause) {
+            match runs.get_mut(&ino) {
+                // The unflushed head of a run that has moved on past an extent boundary
+                Some(rest) if rest.offset == run.end() => {
+                    let mut data = run.data;
+                    data.extend_from_slice(&rest.data);
+                    rest.data = data;
+                    rest.offset = run.offset;
+                }
+                Some(_) => log::error!("Dropping {} unflushable bytes of inode {}", run.data.len(), ino),
+                None => {
+                    runs.insert(ino, run);
+                }
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
@@ -43,8 +43,9 @@ $BIN init --pool /tmp/dynamicfs_test/pool
 
 # Add disks
 echo "4. Adding disks to pool..."
+# The disks are directories on one filesystem, so they share a device
 for i in {1..6}; do
-    $BIN add-disk --pool /tmp/dynamicfs_test/pool --disk /tmp/dynamicfs_test/disk$i
+    $BIN add-disk --pool /tmp/dynamicfs_test/pool --disk /tmp/dynamicfs_test/disk$i --force
 done
 
 # List disks
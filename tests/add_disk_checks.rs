@@ -0,0 +1,59 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn dynamicfs(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap()
+}
+
+fn add_disk(pool: &Path, disk: &Path, flags: &[&str]) -> Output {
+    let mut args = vec!["add-disk", "--pool", pool.to_str().unwrap(), "--disk", disk.to_str().unwrap()];
+    args.extend_from_slice(flags);
+    dynamicfs(&args)
+}
+
+fn refused(output: Output, reason: &str) {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "add-disk was accepted; wanted it refused with {:?}", reason);
+    assert!(stderr.contains(reason), "{}", stderr);
+}
+
+fn disk_paths(pool: &Path) -> usize {
+    let pool_json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(pool.join("pool.json")).unwrap()).unwrap();
+    pool_json["disk_paths"].as_array().unwrap().len()
+}
+
+#[test]
+fn test_add_disk_rejects_the_same_disk_nested_paths_and_another_pools_disk() {
+    let root = tempfile::tempdir().unwrap();
+    let pool = root.path().join("pool");
+    let disk = root.path().join("disk1");
+    assert!(dynamicfs(&["init", "--pool", pool.to_str().unwrap()]).status.success());
+    assert!(add_disk(&pool, &disk, &[]).status.success());
+
+    // Every other temporary directory shares the first disk's device
+    refused(add_disk(&pool, &root.path().join("disk2"), &[]), "same device as disk");
+    let shared = add_disk(&pool, &root.path().join("disk2"), &["--force"]);
+    assert!(shared.status.success(), "{}", String::from_utf8_lossy(&shared.stderr));
+    assert!(String::from_utf8_lossy(&shared.stdout).contains("WARNING"));
+
+    // Paths are compared after resolving symlinks, and nesting is refused even with --force
+    let link = root.path().join("disk1-link");
+    std::os::unix::fs::symlink(&disk, &link).unwrap();
+    refused(add_disk(&pool, &link, &["--force"]), "already a disk of this pool");
+    refused(add_disk(&pool, &disk.join("inner"), &["--force"]), "inside disk");
+    refused(add_disk(&pool, root.path(), &["--force"]), "contains disk");
+    refused(add_disk(&pool, &pool.join("disk"), &["--force"]), "inside the pool directory");
+    assert!(!disk.join("inner").exists() && !pool.join("disk").exists());
+
+    // A disk of another pool needs --force-takeover
+    let other = root.path().join("other");
+    let foreign = root.path().join("foreign");
+    assert!(dynamicfs(&["init", "--pool", other.to_str().unwrap()]).status.success());
+    assert!(add_disk(&other, &foreign, &["--force"]).status.success());
+    refused(add_disk(&pool, &foreign, &["--force"]), "another pool");
+    assert_eq!(disk_paths(&pool), 2);
+    assert!(add_disk(&pool, &foreign, &["--force", "--force-takeover"]).status.success());
+    assert_eq!(disk_paths(&pool), 3);
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+use dynamicfs::MetadataManager;
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn dynamicfs(args: &[&str]) -> Output {
+    let output = Command::new(BIN).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn root_entries(pool: &Path) -> Vec<String> {
+    let metadata = MetadataManager::new(pool.to_path_buf()).unwrap();
+    metadata.list_directory(1).unwrap().into_iter().map(|inode| inode.name.to_string()).collect()
+}
+
+#[test]
+fn test_benchmark_removes_its_files_unless_kept() {
+    let root = tempfile::tempdir().unwrap();
+    let pool = root.path().join("pool");
+    let pool_arg = pool.to_str().unwrap();
+    dynamicfs(&["init", "--pool", pool_arg]);
+    for i in 0..6 {
+        let disk = root.path().join(format!("disk{}", i));
+        dynamicfs(&["add-disk", "--pool", pool_arg, "--disk", disk.to_str().unwrap(), "--force"]);
+    }
+
+    let bench = ["--json", "benchmark", "--pool", pool_arg, "--file-size", "8192", "--operations", "40", "--files", "4"];
+    let output = dynamicfs(&[&bench[..], &["--mode", "mixed", "--read-ratio", "0.75", "--threads", "3", "--pattern", "random", "--seed", "7"]].concat());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let (writes, reads) = (&report["write"], &report["read"]);
+    assert_eq!(writes["count"].as_u64().unwrap() + reads["count"].as_u64().unwrap(), 40);
+    assert_eq!(writes["errors"].as_u64().unwrap() + reads["errors"].as_u64().unwrap(), 0);
+    let latency = &reads["latency_ms"];
+    assert!(latency["p50"].as_f64().unwrap() <= latency["p95"].as_f64().unwrap());
+    assert!(latency["p95"].as_f64().unwrap() <= latency["p99"].as_f64().unwrap());
+    assert!(root_entries(&pool).is_empty(), "{:?}", root_entries(&pool));
+
+    let output = dynamicfs(&[&bench[..], &["--mode", "write", "--keep"]].concat());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["read"]["count"], 0);
+    let directory = report["directory"].as_str().unwrap();
+    assert_eq!(root_entries(&pool), vec![directory.trim_start_matches('/').to_string()]);
+}
@@ -0,0 +1,148 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dynamicfs::disk::Disk;
+use dynamicfs::distributed::{DistributedCluster, LogEntry, MetadataOperation, NodeState, RaftRole, StateMachine};
+use dynamicfs::storage::StorageEngine;
+use dynamicfs::MetadataManager;
+use tempfile::TempDir;
+
+/// A node on a free localhost port with its own pool of three disks
+fn node(node_id: u64, dirs: &mut Vec<TempDir>) -> DistributedCluster {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let disks: Vec<Disk> = (0..3)
+        .map(|_| {
+            let dir = tempfile::tempdir().unwrap();
+            let disk = Disk::new(dir.path().to_path_buf()).unwrap();
+            dirs.push(dir);
+            disk
+        })
+        .collect();
+    dirs.push(pool_dir);
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut cluster = DistributedCluster::with_storage(node_id, addr, StorageEngine::new(metadata, disks))
+        .with_heartbeat_timing(Duration::from_secs(1), Duration::from_secs(5));
+    cluster.bootstrap().unwrap();
+    cluster
+}
+
+fn wait_for(what: &str, timeout: Duration, done: impl Fn() -> bool) {
+    let deadline = Instant::now() + timeout;
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn test_two_nodes_exchange_extents_and_stay_healthy_on_heartbeats() {
+    let mut dirs = Vec::new();
+    let mut first = node(1, &mut dirs);
+    let mut second = node(2, &mut dirs);
+    second.add_peer(first.listen_addr()).unwrap();
+    assert_eq!(second.node_health(&1), Some(NodeState::Healthy));
+    assert_eq!(first.node_health(&2), Some(NodeState::Healthy));
+
+    // An extent stored through one node is read back from the other
+    let extent = uuid::Uuid::new_v4();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    second.put_remote_extent(1, extent, data.clone()).unwrap();
+    assert_eq!(second.get_remote_extent(1, extent).unwrap(), data);
+    assert!(second.get_remote_extent(1, uuid::Uuid::new_v4()).is_err());
+
+    // Well past the point an unanswered peer is suspected, both stay healthy
+    thread::sleep(Duration::from_secs(4));
+    assert_eq!(first.node_health(&2), Some(NodeState::Healthy));
+    assert_eq!(second.node_health(&1), Some(NodeState::Healthy));
+    assert_eq!(first.cluster_status().healthy_nodes, 1);
+
+    // Once one node stops, the other fails it and queues what it held for re-replication
+    first.replicate_extent(extent, &data, 2).unwrap();
+    let events = first.subscribe_membership();
+    drop(second);
+    wait_for("node 2 to fail", Duration::from_secs(10), || first.node_health(&2) == Some(NodeState::Failed));
+    let states: Vec<NodeState> = events.try_iter().map(|event| event.to).collect();
+    assert_eq!(states, [NodeState::Suspected, NodeState::Failed]);
+    wait_for("the extent to be queued", Duration::from_secs(3), || first.pending_repairs() == [extent]);
+}
+
+#[test]
+fn test_joining_needs_a_running_node_with_another_id() {
+    let mut dirs = Vec::new();
+    let first = node(1, &mut dirs);
+    let mut unbound = DistributedCluster::new(3, "127.0.0.1:0".parse().unwrap());
+    assert!(unbound.add_peer(first.listen_addr()).is_err());
+
+    let mut twin = node(1, &mut dirs);
+    let err = twin.add_peer(first.listen_addr()).unwrap_err();
+    assert!(err.to_string().contains("has this node's ID"), "{}", err);
+    assert_eq!(first.cluster_status().total_nodes, 0);
+}
+
+/// Records the extents of applied CreateExtent operations
+struct CreatedExtents(Arc<Mutex<Vec<uuid::Uuid>>>);
+
+impl StateMachine for CreatedExtents {
+    fn apply(&mut self, entry: &LogEntry) -> anyhow::Result<()> {
+        if let MetadataOperation::CreateExtent { extent_uuid, .. } = entry.operation {
+            self.0.lock().unwrap().push(extent_uuid);
+        }
+        Ok(())
+    }
+}
+
+/// The one leader among `nodes` once every other node follows it in its term
+fn settled_leader(nodes: &[&DistributedCluster]) -> Option<usize> {
+    let leaders: Vec<usize> = (0..nodes.len()).filter(|&i| nodes[i].raft_progress().0 == RaftRole::Leader).collect();
+    let [leader] = leaders[..] else { return None };
+    let term = nodes[leader].raft_progress().1;
+    nodes.iter().all(|node| node.raft_progress().1 == term).then_some(leader)
+}
+
+#[test]
+fn test_three_nodes_elect_one_leader_replicate_and_survive_losing_it() {
+    let dirs: Vec<TempDir> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    let applied: Vec<Arc<Mutex<Vec<uuid::Uuid>>>> = (0..3).map(|_| Arc::default()).collect();
+    let mut nodes: Vec<Option<DistributedCluster>> = (0..3)
+        .map(|i| {
+            let mut node = DistributedCluster::new(i as u64 + 1, "127.0.0.1:0".parse().unwrap())
+                .with_raft_timing(Duration::from_millis(50), Duration::from_millis(300))
+                .with_raft_dir(dirs[i].path())
+                .with_state_machine(Box::new(CreatedExtents(applied[i].clone())));
+            node.bootstrap().unwrap();
+            Some(node)
+        })
+        .collect();
+    let first_addr = nodes[0].as_ref().unwrap().listen_addr();
+    for node in nodes[1..].iter_mut() {
+        node.as_mut().unwrap().add_peer(first_addr).unwrap();
+    }
+    let live = |nodes: &[Option<DistributedCluster>]| -> Vec<usize> { (0..3).filter(|&i| nodes[i].is_some()).collect() };
+    let leader_of = |nodes: &[Option<DistributedCluster>]| {
+        let alive = live(nodes);
+        let refs: Vec<&DistributedCluster> = alive.iter().map(|&i| nodes[i].as_ref().unwrap()).collect();
+        settled_leader(&refs).map(|i| alive[i])
+    };
+    wait_for("a leader", Duration::from_secs(10), || leader_of(&nodes).is_some());
+    let leader = leader_of(&nodes).unwrap();
+    let first_term = nodes[leader].as_ref().unwrap().raft_progress().1;
+
+    let extent = uuid::Uuid::new_v4();
+    let index = nodes[leader].as_mut().unwrap().raft_propose(MetadataOperation::CreateExtent { extent_uuid: extent, size: 4096 }).unwrap();
+    wait_for("all nodes to apply the extent", Duration::from_secs(5), || applied.iter().all(|a| a.lock().unwrap().contains(&extent)));
+    assert!(nodes.iter().all(|node| node.as_ref().unwrap().is_committed(index)));
+
+    // The two left elect a new leader in a later term and still commit
+    nodes[leader] = None;
+    wait_for("a new leader", Duration::from_secs(10), || leader_of(&nodes).is_some());
+    let new_leader = leader_of(&nodes).unwrap();
+    assert!(nodes[new_leader].as_ref().unwrap().raft_progress().1 > first_term);
+    let second = uuid::Uuid::new_v4();
+    nodes[new_leader].as_mut().unwrap().raft_propose(MetadataOperation::CreateExtent { extent_uuid: second, size: 4096 }).unwrap();
+    wait_for("the survivors to apply the second extent", Duration::from_secs(5), || {
+        live(&nodes).iter().all(|&i| *applied[i].lock().unwrap() == [extent, second])
+    });
+}
@@ -1,4 +1,10 @@
 mod unit;
+// test_utils resolves its helpers through `crate::`, so mirror the library paths here
+use dynamicfs::disk;
+mod metadata {
+    pub use dynamicfs::MetadataManager;
+}
+use dynamicfs::metadata_store;
 use dynamicfs::storage::StorageEngine; 
 use dynamicfs::disk::Disk;
 use unit::test_utils::setup_test_env;
@@ -21,6 +27,7 @@ pub fn drain_rebuild_flow() {
     // Perform mount-time rebuild which should migrate fragments off draining disk
     let res = storage.perform_mount_rebuild();
     assert!(res.is_ok(), "perform_mount_rebuild should succeed");
+    storage.wait_for_rebuilds();
 
     // After rebuild, ensure no fragment references point to the drained disk
     let metadata_mgr = storage.metadata();
@@ -0,0 +1,45 @@
+use std::process::{Command, Output, Stdio};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn dynamicfs(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).stdin(Stdio::null()).output().unwrap()
+}
+
+fn run(args: &[&str]) {
+    let output = dynamicfs(args);
+    assert!(output.status.success(), "dynamicfs {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_encrypted_pool_refuses_to_open_without_its_key() {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    let mountpoint = tempfile::tempdir().unwrap();
+    let pool = pool_dir.path().to_str().unwrap();
+    let mnt = mountpoint.path().to_str().unwrap();
+    let key = pool_dir.path().join("pool.key");
+    let key = key.to_str().unwrap();
+
+    // --encrypt generates the key file when it does not exist
+    run(&["init", "--pool", pool, "--encrypt", "--key-file", key]);
+    assert_eq!(std::fs::read(key).unwrap().len(), 32);
+    // The temporary disks share one device
+    for disk in &disk_dirs {
+        run(&["add-disk", "--pool", pool, "--disk", disk.path().to_str().unwrap(), "--force"]);
+    }
+
+    let output = dynamicfs(&["mount", "--pool", pool, "--mountpoint", mnt]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Pool is encrypted: supply its key"), "{}", stderr);
+
+    let wrong = pool_dir.path().join("wrong.key");
+    std::fs::write(&wrong, [7u8; 32]).unwrap();
+    let output = dynamicfs(&["mount", "--pool", pool, "--mountpoint", mnt, "--key-file", wrong.to_str().unwrap()]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Wrong key for encrypted pool"), "{}", stderr);
+
+    run(&["scrub", "--pool", pool, "--key-file", key]);
+}
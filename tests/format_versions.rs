@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use dynamicfs::disk::{Disk, DiskPool, DISK_FORMAT_VERSION, POOL_FORMAT_VERSION};
+use dynamicfs::format_version::FormatVersion;
+
+/// Every fixture `<kind>-<version>[-<variant>].json`, one per format files were ever written in
+fn fixtures(kind: &str) -> Vec<(PathBuf, FormatVersion)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/formats");
+    let mut fixtures: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.strip_prefix(kind)?.strip_prefix('-')?.to_string();
+            let version = name.split('-').next()?.parse().ok()?;
+            Some((path, version))
+        })
+        .collect();
+    fixtures.sort();
+    assert!(fixtures.iter().any(|(_, version)| *version == FormatVersion::UNVERSIONED), "no unversioned {} fixture", kind);
+    fixtures
+}
+
+fn json(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn test_every_pool_format_loads_and_is_upgraded_on_the_next_save() {
+    let current = fixtures("pool");
+    assert!(current.iter().any(|(_, version)| *version == POOL_FORMAT_VERSION), "add a fixture of the current pool format");
+    for (fixture, version) in current {
+        let pool_dir = tempfile::tempdir().unwrap();
+        let pool_path = pool_dir.path().join("pool.json");
+        fs::copy(&fixture, &pool_path).unwrap();
+
+        let pool = DiskPool::load(pool_dir.path()).unwrap();
+        assert_eq!(pool.format_version, POOL_FORMAT_VERSION, "{:?}", fixture);
+        assert!(!pool.disk_paths.is_empty(), "{:?}", fixture);
+        // Loading alone leaves the file as it was
+        assert_eq!(fs::read(&pool_path).unwrap(), fs::read(&fixture).unwrap());
+
+        pool.save(pool_dir.path()).unwrap();
+        let backup = pool_dir.path().join("pool.json.bak");
+        if version < POOL_FORMAT_VERSION {
+            assert_eq!(fs::read(&backup).unwrap(), fs::read(&fixture).unwrap(), "{:?}", fixture);
+        } else {
+            assert!(!backup.exists(), "{:?}", fixture);
+        }
+        assert_eq!(json(&pool_path)["format_version"], POOL_FORMAT_VERSION.to_string());
+        let reloaded = DiskPool::load(pool_dir.path()).unwrap();
+        assert_eq!(reloaded.disk_paths, pool.disk_paths);
+        assert_eq!(reloaded.uuid, pool.uuid);
+    }
+}
+
+#[test]
+fn test_every_disk_format_loads_and_is_upgraded_on_the_next_save() {
+    let current = fixtures("disk");
+    assert!(current.iter().any(|(_, version)| *version == DISK_FORMAT_VERSION), "add a fixture of the current disk format");
+    for (fixture, version) in current {
+        let disk_dir = tempfile::tempdir().unwrap();
+        let disk_path = disk_dir.path().join("disk.json");
+        fs::copy(&fixture, &disk_path).unwrap();
+
+        let mut disk = Disk::load(disk_dir.path()).unwrap();
+        assert_eq!(disk.format_version, DISK_FORMAT_VERSION, "{:?}", fixture);
+        assert_eq!(disk.uuid.to_string(), json(&fixture)["uuid"].as_str().unwrap());
+
+        disk.path = disk_dir.path().to_path_buf();
+        disk.save().unwrap();
+        let backup = disk_dir.path().join("disk.json.bak");
+        if version < DISK_FORMAT_VERSION {
+            assert_eq!(fs::read(&backup).unwrap(), fs::read(&fixture).unwrap(), "{:?}", fixture);
+        } else {
+            assert!(!backup.exists(), "{:?}", fixture);
+        }
+        assert_eq!(Disk::load(disk_dir.path()).unwrap().capacity_bytes, disk.capacity_bytes);
+    }
+}
+
+/// The current fixture of `kind`, rewritten with another version and an extra field
+fn with_version(kind: &str, current: FormatVersion, version: FormatVersion) -> String {
+    let fixture = fixtures(kind).into_iter().find(|(_, v)| *v == current).unwrap().0;
+    let mut value = json(&fixture);
+    value["format_version"] = version.to_string().into();
+    value["placement_groups"] = serde_json::json!({"count": 4});
+    serde_json::to_string_pretty(&value).unwrap()
+}
+
+#[test]
+fn test_newer_minor_formats_keep_their_fields_and_newer_majors_are_refused() {
+    let newer_minor = FormatVersion::new(POOL_FORMAT_VERSION.major, POOL_FORMAT_VERSION.minor + 1);
+    let pool_dir = tempfile::tempdir().unwrap();
+    let pool_path = pool_dir.path().join("pool.json");
+    fs::write(&pool_path, with_version("pool", POOL_FORMAT_VERSION, newer_minor)).unwrap();
+    let mut pool = DiskPool::load(pool_dir.path()).unwrap();
+    assert_eq!(pool.format_version, newer_minor);
+    pool.verify_writes = true;
+    pool.save(pool_dir.path()).unwrap();
+    let saved = json(&pool_path);
+    assert_eq!(saved["format_version"], newer_minor.to_string());
+    assert_eq!(saved["placement_groups"]["count"], 4);
+    assert_eq!(saved["verify_writes"], true);
+    assert!(!pool_dir.path().join("pool.json.bak").exists());
+
+    let newer_major = FormatVersion::new(POOL_FORMAT_VERSION.major + 1, 0);
+    fs::write(&pool_path, with_version("pool", POOL_FORMAT_VERSION, newer_major)).unwrap();
+    let err = format!("{:#}", DiskPool::load(pool_dir.path()).unwrap_err());
+    assert!(err.contains("newer than this build") && err.contains("upgrade dynamicfs"), "{}", err);
+
+    let disk_dir = tempfile::tempdir().unwrap();
+    let newer_major = FormatVersion::new(DISK_FORMAT_VERSION.major + 1, 0);
+    fs::write(disk_dir.path().join("disk.json"), with_version("disk", DISK_FORMAT_VERSION, newer_major)).unwrap();
+    let err = format!("{:#}", Disk::load(disk_dir.path()).unwrap_err());
+    assert!(err.contains("upgrade dynamicfs"), "{}", err);
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn run(args: &[&str]) {
+    let status = Command::new(BIN).args(args).stdout(Stdio::null()).stderr(Stdio::null()).status().unwrap();
+    assert!(status.success(), "dynamicfs {:?} failed", args);
+}
+
+fn is_mounted(mountpoint: &Path) -> bool {
+    let mounts = std::fs::read_to_string("/proc/mounts").unwrap();
+    let mountpoint = mountpoint.to_str().unwrap();
+    mounts.lines().any(|line| line.split_whitespace().nth(1) == Some(mountpoint))
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait().unwrap() {
+            return Some(status);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+/// Start `dynamicfs mount` in a child process; `None` if FUSE is unavailable
+fn spawn_mount(pool: &Path, mountpoint: &Path) -> Option<Child> {
+    let mut child = Command::new(BIN)
+        .args(["mount", "--no-allow-other", "--write-flush-secs", "600", "--pool"])
+        .arg(pool)
+        .arg("--mountpoint")
+        .arg(mountpoint)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while !is_mounted(mountpoint) {
+        if child.try_wait().unwrap().is_some() || Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Some(child)
+}
+
+/// SIGTERM the mount and expect a clean exit with the mountpoint released
+fn terminate(mut child: Child, mountpoint: &Path) {
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let status = wait_with_timeout(&mut child, Duration::from_secs(30));
+    if status.is_none() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    assert_eq!(status.and_then(|s| s.code()), Some(0), "mount did not exit cleanly on SIGTERM");
+    assert!(!is_mounted(mountpoint), "mountpoint still mounted after shutdown");
+    // A wedged mountpoint fails with ENOTCONN
+    std::fs::read_dir(mountpoint).unwrap();
+}
+
+#[test]
+fn test_sigterm_unmounts_cleanly_and_keeps_data() {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    let mountpoint = tempfile::tempdir().unwrap();
+    let pool = pool_dir.path().to_str().unwrap();
+    run(&["init", "--pool", pool]);
+    // The temporary disks share one device
+    for disk in &disk_dirs {
+        run(&["add-disk", "--pool", pool, "--disk", disk.path().to_str().unwrap(), "--force"]);
+    }
+
+    let Some(child) = spawn_mount(pool_dir.path(), mountpoint.path()) else {
+        eprintln!("skipping mount test: FUSE unavailable");
+        return;
+    };
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let path = mountpoint.path().join("data.bin");
+    std::fs::write(&path, &data).unwrap();
+    terminate(child, mountpoint.path());
+
+    let child = spawn_mount(pool_dir.path(), mountpoint.path()).expect("remount failed");
+    let read_back = std::fs::read(&path);
+    terminate(child, mountpoint.path());
+    assert_eq!(read_back.unwrap(), data);
+}
@@ -0,0 +1,90 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn dynamicfs(args: &[&str]) -> Output {
+    let output = Command::new(BIN).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn json(args: &[&str]) -> serde_json::Value {
+    serde_json::from_slice(&dynamicfs(&[&["--json"], args].concat()).stdout).unwrap()
+}
+
+fn tier_extents(pool: &str, tier: &str) -> u64 {
+    let status = json(&["tier-status", "--pool", pool]);
+    let tiers = status["tiers"].as_array().unwrap();
+    tiers.iter().find(|t| t["tier"] == tier).unwrap()["extents"].as_u64().unwrap()
+}
+
+fn write_policy(dir: &Path, name: &str, text: &str) -> String {
+    let path = dir.join(name);
+    std::fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_policies_are_stored_run_against_the_pool_and_audited() {
+    let root = tempfile::tempdir().unwrap();
+    let pool = root.path().join("pool");
+    let pool = pool.to_str().unwrap();
+    dynamicfs(&["init", "--pool", pool]);
+    for (i, tier) in ["hdd", "hdd", "hdd", "nvme", "nvme", "nvme"].iter().enumerate() {
+        let disk = root.path().join(format!("disk{}", i));
+        dynamicfs(&["add-disk", "--pool", pool, "--disk", disk.to_str().unwrap(), "--tier", tier, "--force"]);
+    }
+    dynamicfs(&["benchmark", "--pool", pool, "--mode", "write", "--files", "2", "--operations", "2", "--file-size", "4096", "--keep"]);
+    assert_eq!(tier_extents(pool, "nvme"), 0);
+
+    let promote = r#"
+        name = "promote"
+        schedule = { type = "hourly" }
+
+        [[rules]]
+        type = "access_frequency"
+        min_accesses = 1
+
+        [[actions]]
+        type = "migrate_tier"
+        target = "nvme"
+
+        [[actions]]
+        type = "promote_to_cache"
+    "#;
+    dynamicfs(&["policy", "add", "--pool", pool, "--file", &write_policy(root.path(), "promote.toml", promote)]);
+    let broken = write_policy(root.path(), "broken.toml", "name = \"broken\"\nactions = []\n");
+    assert!(!Command::new(BIN).args(["policy", "add", "--pool", pool, "--file", &broken]).output().unwrap().status.success());
+    let listed = json(&["policy", "list", "--pool", pool]);
+    assert_eq!(listed["policies"].as_array().unwrap().len(), 1);
+    assert_eq!(listed["policies"][0]["actions"][0]["target"], "nvme");
+
+    // A dry run proposes without moving anything
+    let dry = json(&["policy", "run", "--pool", pool, "--dry-run"]);
+    let outcomes = dry["run"]["outcomes"].as_array().unwrap();
+    assert_eq!(outcomes.len(), 4, "{}", dry);
+    assert!(outcomes.iter().all(|o| o["impact"].is_null() && o["refused"].is_null()));
+    assert_eq!(tier_extents(pool, "nvme"), 0);
+    assert!(json(&["policy", "audit", "--pool", pool])["entries"].as_array().unwrap().is_empty());
+
+    // Migrations reach the tier; the data cache needs a mount
+    let run = json(&["policy", "run", "--pool", pool]);
+    assert_eq!(run["mounted"], false);
+    let outcomes = run["run"]["outcomes"].as_array().unwrap();
+    let migrated: Vec<_> = outcomes.iter().filter(|o| o["proposal"]["action"]["type"] == "migrate_tier").collect();
+    assert!(migrated.iter().all(|o| o["impact"]["bytes"].as_u64().unwrap() > 0), "{}", run);
+    let cached: Vec<_> = outcomes.iter().filter(|o| o["proposal"]["action"]["type"] == "promote_to_cache").collect();
+    assert!(cached.iter().all(|o| o["error"].as_str().unwrap().contains("mounted")), "{}", run);
+    assert_eq!(tier_extents(pool, "nvme"), 2);
+
+    let audit = json(&["policy", "audit", "--pool", pool]);
+    let entries = audit["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries.iter().filter(|e| e["success"] == true).count(), 2);
+    assert_eq!(json(&["policy", "audit", "--pool", pool, "--limit", "1"])["entries"].as_array().unwrap().len(), 1);
+
+    dynamicfs(&["policy", "remove", "--pool", pool, "promote"]);
+    assert!(json(&["policy", "list", "--pool", pool])["policies"].as_array().unwrap().is_empty());
+    assert!(!Command::new(BIN).args(["policy", "run", "--pool", pool]).output().unwrap().status.success());
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn dynamicfs(args: &[&str]) -> Output {
+    Command::new(BIN).args(args).output().unwrap()
+}
+
+fn stdout(args: &[&str]) -> String {
+    let output = dynamicfs(args);
+    assert!(output.status.success(), "dynamicfs {:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn config_json(pool: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(pool.join("config.json")).unwrap()).unwrap()
+}
+
+#[test]
+fn test_config_set_get_list_round_trip() {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let pool = pool_dir.path().to_str().unwrap();
+    stdout(&["init", "--pool", pool]);
+
+    // Defaults before anything is written
+    assert_eq!(stdout(&["config", "get", "--pool", pool, "write_buffer"]).trim(), "64M");
+    assert!(!pool_dir.path().join("config.json").exists());
+
+    let set = stdout(&["config", "set", "--pool", pool, "write_buffer", "512M"]);
+    assert!(set.contains("next mount"), "{}", set);
+    stdout(&["config", "set", "--pool", pool, "atime", "noatime"]);
+    stdout(&["config", "set", "--pool", pool, "rebuild.max_rate", "128M"]);
+    assert_eq!(stdout(&["config", "get", "--pool", pool, "write_buffer"]).trim(), "512M");
+    assert_eq!(config_json(pool_dir.path())["write_buffer_bytes"], 512 << 20);
+    assert_eq!(config_json(pool_dir.path())["atime"], "noatime");
+
+    // pool.json keys stay where the set-* commands keep them
+    let pool_json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(pool_dir.path().join("pool.json")).unwrap()).unwrap();
+    assert_eq!(pool_json["rebuild_limits"]["max_bytes_per_sec"], 128 << 20);
+
+    let values: serde_json::Value = serde_json::from_str(&stdout(&["--json", "config", "get", "--pool", pool])).unwrap();
+    assert_eq!(values["rebuild.max_rate"], 128 << 20);
+    assert_eq!(values["verify_writes"], false);
+    let list: serde_json::Value = serde_json::from_str(&stdout(&["--json", "config", "list", "--pool", pool])).unwrap();
+    let keys = list["keys"].as_array().unwrap();
+    let live = |name: &str| keys.iter().find(|entry| entry["key"]["name"] == name).unwrap()["key"]["live"].clone();
+    assert_eq!((live("rebuild.max_rate"), live("write_buffer")), (true.into(), false.into()));
+
+    // Rejected values leave the config alone
+    for (key, value) in [("verify_writes", "perhaps"), ("space_reserve_percent", "90"), ("no_such_key", "1")] {
+        assert!(!dynamicfs(&["config", "set", "--pool", pool, key, value]).status.success());
+    }
+    assert_eq!(stdout(&["config", "get", "--pool", pool, "space_reserve_percent"]).trim(), "2%");
+}
+
+#[test]
+fn test_config_from_a_newer_build() {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let pool = pool_dir.path().to_str().unwrap();
+    stdout(&["init", "--pool", pool]);
+
+    // Same version with keys this build does not know: read, and kept on write
+    let newer = r#"{"version": 1, "write_flush_secs": 9, "cache_dirs": ["/ssd"]}"#;
+    std::fs::write(pool_dir.path().join("config.json"), newer).unwrap();
+    assert_eq!(stdout(&["config", "get", "--pool", pool, "write_flush_secs"]).trim(), "9");
+    stdout(&["config", "set", "--pool", pool, "write_flush_secs", "2"]);
+    assert_eq!(config_json(pool_dir.path())["cache_dirs"], serde_json::json!(["/ssd"]));
+    assert!(stdout(&["config", "list", "--pool", pool]).contains("cache_dirs"));
+
+    // A later version is refused outright
+    std::fs::write(pool_dir.path().join("config.json"), r#"{"version": 99}"#).unwrap();
+    let output = dynamicfs(&["config", "get", "--pool", pool, "write_flush_secs"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("newer than this build"));
+}
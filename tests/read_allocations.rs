@@ -0,0 +1,86 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dynamicfs::disk::Disk;
+use dynamicfs::storage::StorageEngine;
+use dynamicfs::MetadataManager;
+
+/// System allocator that counts allocations and the bytes they asked for
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const READ_SIZE: u64 = 128 * 1024;
+const READS: u64 = 64;
+
+/// Allocations and bytes allocated per uncached read, averaged over `READS`
+/// reads that each hand their buffer back the way the FUSE layer does
+fn per_read(storage: &StorageEngine, ino: u64, file_size: u64) -> (f64, f64) {
+    let read = |i: u64| {
+        let offset = (i * READ_SIZE) % file_size;
+        let data = storage.read_range_uncached(ino, offset, READ_SIZE).unwrap();
+        assert_eq!(data.len() as u64, READ_SIZE);
+        assert_eq!(data[0], (offset % 251) as u8);
+        storage.recycle_read_buffer(data);
+    };
+    // Warm the pool and any lazily built state first
+    for i in 0..8 {
+        read(i);
+    }
+    let (count, bytes) = (ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed));
+    for i in 0..READS {
+        read(i);
+    }
+    (
+        (ALLOCATIONS.load(Ordering::Relaxed) - count) as f64 / READS as f64,
+        (ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes) as f64 / READS as f64,
+    )
+}
+
+#[test]
+fn test_pooled_reads_allocate_less() {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..6).map(|_| tempfile::tempdir().unwrap()).collect();
+    let disks = disk_dirs.iter().map(|dir| Disk::new(dir.path().to_path_buf()).unwrap()).collect();
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let storage = StorageEngine::new(metadata, disks);
+    let file_size = 4u64 << 20;
+    let ino = storage.create_file(1, "data.bin".to_string()).unwrap().ino;
+    let content: Vec<u8> = (0..file_size).map(|i| (i % 251) as u8).collect();
+    storage.write_file(ino, &content, 0).unwrap();
+
+    storage.set_read_buffer_pool_limit(0);
+    let (unpooled_count, unpooled_bytes) = per_read(&storage, ino, file_size);
+    storage.set_read_buffer_pool_limit(dynamicfs::buffer_pool::DEFAULT_READ_BUFFER_POOL_BYTES);
+    let (pooled_count, pooled_bytes) = per_read(&storage, ino, file_size);
+    println!(
+        "per read: {:.1} allocations / {:.0} bytes unpooled, {:.1} allocations / {:.0} bytes pooled",
+        unpooled_count, unpooled_bytes, pooled_count, pooled_bytes
+    );
+
+    assert!(unpooled_bytes >= READ_SIZE as f64, "each unpooled read allocates its buffers");
+    assert!(pooled_bytes * 4.0 < unpooled_bytes, "pooled reads reuse the fragment and extent buffers");
+    assert!(pooled_count < unpooled_count);
+}
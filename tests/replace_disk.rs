@@ -0,0 +1,62 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn dynamicfs(args: &[&str]) -> Output {
+    let output = Command::new(BIN).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn read_json(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// Extents with a fragment location on the disk `uuid`
+fn extents_on(pool: &Path, uuid: &str) -> usize {
+    std::fs::read_dir(pool.join("extents"))
+        .unwrap()
+        .map(|entry| read_json(&entry.unwrap().path()))
+        .filter(|extent| extent["fragment_locations"].as_array().unwrap().iter().any(|l| l["disk_uuid"] == uuid))
+        .count()
+}
+
+#[test]
+fn test_replace_disk_rebuilds_onto_the_new_disk_and_removes_the_old_one() {
+    let root = tempfile::tempdir().unwrap();
+    let pool = root.path().join("pool");
+    let pool_arg = pool.to_str().unwrap();
+    dynamicfs(&["init", "--pool", pool_arg]);
+    for i in 0..6 {
+        let disk = root.path().join(format!("disk{}", i));
+        dynamicfs(&["add-disk", "--pool", pool_arg, "--disk", disk.to_str().unwrap(), "--force"]);
+    }
+    dynamicfs(&["benchmark", "--pool", pool_arg, "--mode", "write", "--files", "8", "--operations", "8", "--file-size", "4096", "--keep"]);
+
+    let old = root.path().join("disk0");
+    let old_uuid = read_json(&old.join("disk.json"))["uuid"].as_str().unwrap().to_string();
+    let on_old = extents_on(&pool, &old_uuid);
+    assert!(on_old > 0);
+    dynamicfs(&["fail-disk", "--pool", pool_arg, "--disk", old.to_str().unwrap()]);
+
+    let new = root.path().join("disk6");
+    let output = dynamicfs(&[
+        "--json", "replace-disk", "--pool", pool_arg, "--old", &old_uuid, "--new", new.to_str().unwrap(), "--force",
+    ]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["repaired"].as_array().unwrap().len(), on_old, "{}", report);
+    assert!(report["unrecoverable"].as_array().unwrap().is_empty());
+    assert_eq!(report["old_disk_removed"], true);
+
+    // Every location moved to the new disk, which took the old one's place in the pool
+    let new_uuid = report["new_disk"].as_str().unwrap();
+    assert_eq!(extents_on(&pool, &old_uuid), 0);
+    assert_eq!(extents_on(&pool, new_uuid), on_old);
+    let paths = read_json(&pool.join("pool.json"))["disk_paths"].clone();
+    let paths: Vec<&str> = paths.as_array().unwrap().iter().map(|p| p.as_str().unwrap()).collect();
+    assert_eq!(paths.len(), 6);
+    assert!(paths.contains(&new.to_str().unwrap()) && !paths.contains(&old.to_str().unwrap()));
+    assert!(!pool.join("replace_disk.json").exists());
+    dynamicfs(&["check", "--pool", pool_arg]);
+}
@@ -0,0 +1,121 @@
+use std::ffi::CString;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const BIN: &str = env!("CARGO_BIN_EXE_dynamicfs");
+
+fn run(args: &[&str]) {
+    let status = Command::new(BIN).args(args).stdout(Stdio::null()).stderr(Stdio::null()).status().unwrap();
+    assert!(status.success(), "dynamicfs {:?} failed", args);
+}
+
+fn is_mounted(mountpoint: &Path) -> bool {
+    let mounts = std::fs::read_to_string("/proc/mounts").unwrap();
+    let mountpoint = mountpoint.to_str().unwrap();
+    mounts.lines().any(|line| line.split_whitespace().nth(1) == Some(mountpoint))
+}
+
+/// Start `dynamicfs mount` in a child process; `None` if FUSE is unavailable
+fn spawn_mount(pool: &Path, mountpoint: &Path) -> Option<Child> {
+    let mut child = Command::new(BIN)
+        .args(["mount", "--no-allow-other", "--pool"])
+        .arg(pool)
+        .arg("--mountpoint")
+        .arg(mountpoint)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while !is_mounted(mountpoint) {
+        if child.try_wait().unwrap().is_some() || Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Some(child)
+}
+
+/// SIGTERM the mount and wait for the mountpoint to be released
+fn terminate(mut child: Child, mountpoint: &Path) {
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let _ = child.wait();
+    assert!(!is_mounted(mountpoint), "mountpoint still mounted after shutdown");
+}
+
+/// Set atime and mtime of `path` with utimensat(2)
+fn utimensat(path: &Path, atime: (i64, i64), mtime: (i64, i64)) {
+    let path = CString::new(path.to_str().unwrap()).unwrap();
+    let times = [
+        libc::timespec { tv_sec: atime.0, tv_nsec: atime.1 },
+        libc::timespec { tv_sec: mtime.0, tv_nsec: mtime.1 },
+    ];
+    let result = unsafe { libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), 0) };
+    assert_eq!(result, 0, "utimensat failed: {}", std::io::Error::last_os_error());
+}
+
+/// What rsync compares to decide a file is unchanged
+fn quick_check(path: &Path) -> (u64, i64, i64) {
+    let meta = std::fs::metadata(path).unwrap();
+    (meta.size(), meta.mtime(), meta.mtime_nsec())
+}
+
+#[test]
+fn test_utimensat_round_trips_exact_times_across_remounts() {
+    let pool_dir = tempfile::tempdir().unwrap();
+    let disk_dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+    let mountpoint = tempfile::tempdir().unwrap();
+    let pool = pool_dir.path().to_str().unwrap();
+    run(&["init", "--pool", pool]);
+    // The temporary disks share one device
+    for disk in &disk_dirs {
+        run(&["add-disk", "--pool", pool, "--disk", disk.path().to_str().unwrap(), "--force"]);
+    }
+
+    let Some(child) = spawn_mount(pool_dir.path(), mountpoint.path()) else {
+        eprintln!("skipping mount test: FUSE unavailable");
+        return;
+    };
+    let copied = mountpoint.path().join("copied.bin");
+    let historical = mountpoint.path().join("historical.txt");
+    std::fs::write(&copied, vec![7u8; 10_000]).unwrap();
+    std::fs::write(&historical, b"older than the epoch").unwrap();
+
+    // What `rsync -a` does after copying: the source's times, to the nanosecond
+    utimensat(&copied, (1_500_000_000, 1), (1_234_567_890, 123_456_789));
+    // 1969-12-31 23:59:58.75
+    utimensat(&historical, (0, 0), (-2, 750_000_000));
+    let meta = std::fs::metadata(&copied).unwrap();
+    assert_eq!((meta.atime(), meta.atime_nsec()), (1_500_000_000, 1));
+    assert_eq!(quick_check(&copied), (10_000, 1_234_567_890, 123_456_789));
+    assert_eq!(quick_check(&historical), (20, -2, 750_000_000));
+
+    // Reading and chmod leave mtime alone; chmod moves ctime
+    let ctime = (meta.ctime(), meta.ctime_nsec());
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(std::fs::read(&copied).unwrap().len(), 10_000);
+    std::fs::set_permissions(&copied, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+    let meta = std::fs::metadata(&copied).unwrap();
+    assert!((meta.ctime(), meta.ctime_nsec()) > ctime);
+    assert_eq!(quick_check(&copied), (10_000, 1_234_567_890, 123_456_789));
+    terminate(child, mountpoint.path());
+
+    // A later rsync run sees the files as unchanged
+    let child = spawn_mount(pool_dir.path(), mountpoint.path()).expect("remount failed");
+    let after_remount = (quick_check(&copied), quick_check(&historical));
+    terminate(child, mountpoint.path());
+    assert_eq!(after_remount, ((10_000, 1_234_567_890, 123_456_789), (20, -2, 750_000_000)));
+}
@@ -1,5 +1,6 @@
 use super::*;
-use crate::test_utils::setup_test_env;
+use crate::metadata_store::{MetadataBackend, RecordKind};
+use crate::test_utils::{init_pool_dir, record_store, setup_test_env, setup_test_env_with, test_metadata_backend};
 use crate::crash_sim::{get_crash_simulator, CrashPoint};
 use tempfile::TempDir;
 use std::fs;
@@ -181,7 +182,8 @@ fn test_multiple_operations_with_crash() {
 
 #[test]
 fn test_recovery_cleans_temp_files() {
-    let (pool_dir, _disk_dirs, metadata, disks) = setup_test_env();
+    // Temp files are the file store's; the kv store has none
+    let (pool_dir, _disk_dirs, metadata, disks) = setup_test_env_with(MetadataBackend::Files);
     let storage = StorageEngine::new(metadata, disks);
     
     // Manually create a temp file to simulate interrupted operation
@@ -291,3 +293,814 @@ fn test_concurrent_crash_scenarios() {
     let children2 = storage.list_directory(1).unwrap();
     assert!(children2.iter().any(|c| c.name == "after_crash.txt"));
 }
+
+#[test]
+fn test_fsynced_data_survives_crash() {
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let storage = StorageEngine::new(metadata, disks);
+
+    let inode = storage.create_file(1, "durable.bin".to_string()).unwrap();
+    let synced_data = vec![0x5Au8; 4096];
+    storage.write_file(inode.ino, &synced_data, 0).unwrap();
+    storage.sync_inode(inode.ino).unwrap();
+
+    // Crash part-way through a later overwrite, before it reaches the journal
+    let sim = get_crash_simulator();
+    sim.enable_at(CrashPoint::BeforeFragmentWrite);
+    let result = storage.write_file(inode.ino, &vec![0xA5u8; 4096], 0);
+    assert!(result.is_err(), "Should fail writing fragments");
+    sim.disable();
+    drop(storage);
+
+    // Reopen the pool from disk as a restart would
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let disks: Vec<Disk> = disk_dirs
+        .iter()
+        .map(|td| Disk::load(td.path()).unwrap())
+        .collect();
+    let storage = StorageEngine::new(metadata, disks);
+
+    let read_back = storage.read_file(inode.ino).unwrap();
+    assert_eq!(read_back, synced_data, "fsync-acknowledged data must survive the crash");
+}
+
+/// Reopen a pool from disk as a restart would, running metadata recovery
+fn reopen_storage(pool_dir: &TempDir, disk_dirs: &[TempDir]) -> StorageEngine {
+    let metadata = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    let disks: Vec<Disk> = disk_dirs
+        .iter()
+        .map(|td| Disk::load(td.path()).unwrap())
+        .collect();
+    StorageEngine::new(metadata, disks)
+}
+
+/// Every extent record on disk is referenced by some extent map
+fn assert_no_orphaned_extents(storage: &StorageEngine, inos: &[u64]) {
+    let metadata = storage.metadata();
+    let metadata = metadata.read().unwrap();
+    let referenced: Vec<uuid::Uuid> = inos
+        .iter()
+        .flat_map(|ino| metadata.load_extent_map(*ino).unwrap().extents)
+        .collect();
+    for extent in metadata.list_all_extents().unwrap() {
+        assert!(referenced.contains(&extent.uuid), "Orphaned extent metadata {}", extent.uuid);
+    }
+}
+
+#[test]
+fn test_crash_after_journal_write_replays_on_recovery() {
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let storage = StorageEngine::new(metadata, disks);
+
+    let inode = storage.create_file(1, "journaled.bin".to_string()).unwrap();
+    let data: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 253) as u8).collect();
+
+    let sim = get_crash_simulator();
+    sim.enable_at(CrashPoint::AfterJournalWrite);
+    let result = storage.write_file(inode.ino, &data, 0);
+    assert!(result.is_err(), "Should fail after the journal write");
+    sim.disable();
+
+    // Nothing was applied before the crash
+    assert!(storage.read_file(inode.ino).unwrap().is_empty());
+    drop(storage);
+
+    // Recovery replays the journaled transaction in full
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    assert_eq!(storage.get_inode(inode.ino).unwrap().size, data.len() as u64);
+    assert_eq!(storage.read_file(inode.ino).unwrap(), data);
+    assert_no_orphaned_extents(&storage, &[inode.ino]);
+    assert!(crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap().is_empty());
+}
+
+#[test]
+fn test_crash_mid_apply_replays_on_recovery() {
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let storage = StorageEngine::new(metadata, disks);
+
+    let inode = storage.create_file(1, "half_applied.bin".to_string()).unwrap();
+    storage.write_file(inode.ino, b"version 1", 0).unwrap();
+
+    // Crash after the extent record is saved but before the map and inode
+    let sim = get_crash_simulator();
+    sim.enable_at(CrashPoint::MidApply);
+    let new_data = b"version 2, a little longer";
+    let result = storage.write_file(inode.ino, new_data, 0);
+    assert!(result.is_err(), "Should fail while applying the transaction");
+    sim.disable();
+    drop(storage);
+
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    assert_eq!(storage.get_inode(inode.ino).unwrap().size, new_data.len() as u64);
+    assert_eq!(storage.read_file(inode.ino).unwrap(), new_data);
+}
+
+#[test]
+fn test_snapshot_survives_reopen_and_an_interrupted_delete_finishes_on_replay() {
+    use crate::fs_interface::FilesystemInterface;
+    use crate::fsck::{check_pool, CheckOptions, FindingKind};
+    use crate::snapshots::{SnapshotInfo, SNAPSHOTS_DIR_INO};
+
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let storage = StorageEngine::new(metadata, disks);
+    let extent_count = |storage: &StorageEngine| storage.metadata().read().unwrap().list_all_extents().unwrap().len();
+    let old = storage.create_file(1, "old.txt".to_string()).unwrap();
+    storage.write_file(old.ino, b"captured", 0).unwrap();
+    let older = storage.create_file(1, "older.txt".to_string()).unwrap();
+    storage.write_file(older.ino, b"also captured", 0).unwrap();
+    let kept = storage.create_file(1, "kept.txt".to_string()).unwrap();
+    storage.write_file(kept.ino, b"unchanged", 0).unwrap();
+    storage.create_snapshot("before").unwrap();
+    storage.delete_file(old.ino).unwrap();
+    storage.delete_file(older.ino).unwrap();
+    drop(storage);
+
+    // The deleted files' extents are still held by the snapshot
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    assert_eq!(extent_count(&storage), 3);
+    let view: &dyn FilesystemInterface = &storage;
+    let before = view.find_child(SNAPSHOTS_DIR_INO, b"before").unwrap().unwrap();
+    let captured = view.find_child(before.ino, b"old.txt").unwrap().unwrap();
+    assert_eq!(view.read_file(captured.ino).unwrap(), b"captured");
+    drop(storage);
+    let mut disks: Vec<Disk> = disk_dirs.iter().map(|td| Disk::load(td.path()).unwrap()).collect();
+    let report = check_pool(pool_dir.path().to_path_buf(), &mut disks, 0, CheckOptions::default()).unwrap();
+    assert!(report.findings.iter().all(|f| f.kind != FindingKind::UnreferencedExtent), "{:?}", report.findings);
+
+    // A delete cut short between the extents it frees is finished when its journal is replayed
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    let sim = get_crash_simulator();
+    sim.enable_at(CrashPoint::MidApply);
+    assert!(storage.delete_snapshot("before").is_err());
+    sim.disable();
+    drop(storage);
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    assert!(SnapshotInfo::list(pool_dir.path()).unwrap().is_empty());
+    assert_eq!(extent_count(&storage), 1);
+    assert_eq!(storage.read_file(kept.ino).unwrap(), b"unchanged");
+}
+
+#[test]
+fn test_torn_journal_is_discarded_on_recovery() {
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let storage = StorageEngine::new(metadata, disks);
+
+    let inode = storage.create_file(1, "torn.bin".to_string()).unwrap();
+
+    let sim = get_crash_simulator();
+    sim.enable_at(CrashPoint::AfterJournalWrite);
+    assert!(storage.write_file(inode.ino, &[0x42u8; 2048], 0).is_err());
+    sim.disable();
+    drop(storage);
+
+    // Power loss while the record was written tore it: none of the write may become visible
+    let records = crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap();
+    assert_eq!(records.len(), 1);
+    let journal_path = &records[0].0;
+    let contents = fs::read(journal_path).unwrap();
+    fs::write(journal_path, &contents[..contents.len() / 2]).unwrap();
+
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    assert_eq!(storage.get_inode(inode.ino).unwrap().size, 0);
+    assert!(storage.read_file(inode.ino).unwrap().is_empty());
+    assert_no_orphaned_extents(&storage, &[inode.ino]);
+    assert!(crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap().is_empty());
+}
+
+#[test]
+fn test_unsynced_batch_is_replayed_in_full_or_discarded_in_full() {
+    use crate::metadata_tx::MetadataOp;
+
+    // Crashing mid-apply leaves an intact record; tearing it stands for a power loss while it was written
+    for (point, torn) in [(CrashPoint::MidApply, false), (CrashPoint::AfterJournalWrite, true)] {
+        let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+        let storage = StorageEngine::new(metadata, disks);
+        // Neither batch reaches its group's sync before the crash
+        storage.set_metadata_commit_window(1000);
+
+        let batch = |prefix: &str| -> Vec<Inode> {
+            let metadata = storage.metadata();
+            let mut metadata = metadata.write().unwrap();
+            (0..4).map(|i| Inode::new_file(metadata.allocate_ino().unwrap(), 1, format!("{}_{}", prefix, i))).collect()
+        };
+        let apply = |inodes: &[Inode]| {
+            let ops = inodes.iter().cloned().map(MetadataOp::SaveInode).collect();
+            storage.metadata().write().unwrap().apply_batch(ops)
+        };
+        let committed = batch("committed");
+        apply(&committed).unwrap();
+        let crashed = batch("crashed");
+        let sim = get_crash_simulator();
+        sim.enable_at(point);
+        assert!(apply(&crashed).is_err());
+        sim.disable();
+        assert_eq!(storage.metadata().read().unwrap().commit_stats().syncs, 0);
+        drop(storage);
+
+        // The committed batch only waited for its sync; the crashed one is left to recover
+        let records = crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap();
+        assert_eq!(records.len(), 1);
+        if torn {
+            let contents = fs::read(&records[0].0).unwrap();
+            fs::write(&records[0].0, &contents[..contents.len() / 2]).unwrap();
+        }
+
+        let storage = reopen_storage(&pool_dir, &disk_dirs);
+        let visible = |inodes: &[Inode]| -> Vec<bool> {
+            inodes.iter().map(|inode| storage.find_child(1, &inode.name).unwrap().is_some()).collect()
+        };
+        assert_eq!(visible(&committed), vec![true; 4]);
+        assert_eq!(visible(&crashed), vec![!torn; 4], "after a crash at {:?}", point);
+        assert!(crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap().is_empty());
+    }
+}
+
+#[test]
+fn test_power_loss_inside_a_partly_synced_batch_recovers_every_committed_transaction() {
+    use crate::metadata_tx::MetadataOp;
+
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let storage = StorageEngine::new(metadata, disks);
+    storage.set_metadata_commit_window(1000);
+    let metadata = storage.metadata();
+    let batch = |prefix: &str| -> Vec<Inode> {
+        let mut metadata = metadata.write().unwrap();
+        (0..4).map(|i| Inode::new_file(metadata.allocate_ino().unwrap(), 1, format!("{}_{}", prefix, i))).collect()
+    };
+    let apply = |inodes: &[Inode]| {
+        let ops = inodes.iter().cloned().map(MetadataOp::SaveInode).collect();
+        metadata.write().unwrap().apply_batch(ops).unwrap();
+    };
+
+    // Create a batch, then grow its files, and sync both
+    let mut synced = batch("synced");
+    apply(&synced);
+    let stale_record = crate::metadata_tx::applied_journal_records(pool_dir.path()).unwrap()[0].1.clone();
+    let stale_contents = fs::read(&stale_record).unwrap();
+    for inode in &mut synced {
+        inode.size = 42;
+    }
+    apply(&synced);
+    metadata.read().unwrap().sync_commits().unwrap();
+
+    // The next batch is journaled and applied, and its root written, but it is never synced
+    let unsynced = batch("unsynced");
+    apply(&unsynced);
+    assert_eq!(metadata.read().unwrap().commit_stats().syncs, 1);
+    drop(storage);
+
+    // Power is cut part way through writing the batch out: the root made it,
+    // half of its inode records did not, and neither did the removal of the
+    // first synced batch's journal record. The machine then boots afresh.
+    let store = record_store(pool_dir.path());
+    for inode in &unsynced[..2] {
+        assert!(store.delete(RecordKind::Inode, &inode.ino.to_string()).unwrap());
+    }
+    drop(store);
+    fs::write(&stale_record, stale_contents).unwrap();
+    fs::write(crate::metadata_tx::journal_dir(pool_dir.path()).join("boot"), "an earlier boot").unwrap();
+
+    let storage = reopen_storage(&pool_dir, &disk_dirs);
+    // The journal brings back all of the unsynced batch
+    for inode in &unsynced {
+        assert!(storage.find_child(1, &inode.name).unwrap().is_some(), "{} lost", inode.name);
+    }
+    // Replaying the synced record would have undone the growth that followed it
+    for inode in &synced {
+        assert_eq!(storage.find_child(1, &inode.name).unwrap().unwrap().size, 42);
+    }
+    assert!(crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap().is_empty());
+}
+
+/// Files, directories and bytes counted straight from the inode records
+fn brute_force_inode_totals(pool_dir: &std::path::Path) -> crate::inode_totals::InodeTotals {
+    let mut totals = crate::inode_totals::InodeTotals::default();
+    let store = record_store(pool_dir);
+    for key in store.keys(RecordKind::Inode).unwrap() {
+        let contents = store.load(RecordKind::Inode, &key.unwrap()).unwrap().unwrap();
+        totals.add(&serde_json::from_slice(&contents).unwrap());
+    }
+    totals
+}
+
+#[test]
+fn test_inode_totals_match_a_recount_through_writes_deletes_and_crashes() {
+    use crate::fs_interface::FilesystemInterface;
+    use crate::fsck::{check_pool, CheckOptions, FindingKind, FindingStatus};
+
+    let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+    let mut storage = StorageEngine::new(metadata, disks);
+    let counted = |storage: &StorageEngine| storage.metadata().read().unwrap().inode_totals();
+
+    let dir = storage.create_dir(1, "dir".to_string()).unwrap();
+    let kept = storage.create_file(dir.ino, "kept.bin".to_string()).unwrap();
+    let deleted = storage.create_file(1, "deleted.bin".to_string()).unwrap();
+    storage.write_file(kept.ino, &[1u8; 5000], 0).unwrap();
+    storage.write_file(deleted.ino, &[2u8; 100], 0).unwrap();
+    let totals = counted(&storage);
+    assert_eq!((totals.files, totals.dirs, totals.logical_bytes), (2, 2, 5100));
+    assert_eq!(totals, brute_force_inode_totals(pool_dir.path()));
+
+    // Truncating and deleting take their bytes back out
+    storage.write_file(kept.ino, &[], 0).unwrap();
+    storage.delete_file(deleted.ino).unwrap();
+    let totals = counted(&storage);
+    assert_eq!((totals.files, totals.dirs, totals.logical_bytes), (1, 2, 0));
+    assert_eq!(totals, brute_force_inode_totals(pool_dir.path()));
+    let stats = storage.stat().unwrap();
+    assert_eq!((stats.total_files, stats.total_dirs, stats.total_size), (1, 2, 0));
+
+    // Replaying a transaction a crash interrupted counts its inodes once
+    let sim = get_crash_simulator();
+    for point in [CrashPoint::AfterJournalWrite, CrashPoint::MidApply] {
+        let inode = storage.create_file(1, format!("{:?}.bin", point)).unwrap();
+        sim.enable_at(point);
+        assert!(storage.write_file(inode.ino, &[3u8; 3000], 0).is_err());
+        sim.disable();
+        drop(storage);
+        storage = reopen_storage(&pool_dir, &disk_dirs);
+        assert_eq!(storage.get_inode(inode.ino).unwrap().size, 3000);
+        assert_eq!(counted(&storage), brute_force_inode_totals(pool_dir.path()));
+
+        sim.enable_at(CrashPoint::MidApply);
+        assert!(storage.delete_file(inode.ino).is_err());
+        sim.disable();
+        drop(storage);
+        storage = reopen_storage(&pool_dir, &disk_dirs);
+        assert_eq!(counted(&storage), brute_force_inode_totals(pool_dir.path()));
+    }
+    drop(storage);
+
+    // An inode written behind the counters' back is caught and recounted by check
+    let stray = Inode::new_file(999, 1, "stray.bin".to_string());
+    record_store(pool_dir.path()).save(RecordKind::Inode, "999", &serde_json::to_vec(&stray).unwrap()).unwrap();
+    let mut disks: Vec<Disk> = disk_dirs.iter().map(|td| Disk::load(td.path()).unwrap()).collect();
+    let report = check_pool(pool_dir.path().to_path_buf(), &mut disks, 0, CheckOptions { repair: true, force: false }).unwrap();
+    let stale = report.findings.iter().find(|f| f.kind == FindingKind::StaleInodeTotals).unwrap();
+    assert_eq!(stale.status, FindingStatus::Repaired);
+    let reopened = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    assert_eq!(reopened.inode_totals(), brute_force_inode_totals(pool_dir.path()));
+    assert_eq!(reopened.inode_totals().files, 2);
+
+    // Pools from before the counters are counted when opened
+    drop(reopened);
+    fs::remove_file(pool_dir.path().join("metadata/inode_totals.json")).unwrap();
+    let reopened = MetadataManager::new(pool_dir.path().to_path_buf()).unwrap();
+    assert_eq!(reopened.inode_totals(), brute_force_inode_totals(pool_dir.path()));
+}
+
+/// Crash an overwrite at each occurrence of each crash point on its path
+///
+/// After recovery the file must hold exactly the old or the new contents, and
+/// no extent record or release marker may be left behind.
+fn assert_overwrite_is_atomic(old: &[u8], offset: u64, patch: &[u8]) {
+    let points = [
+        CrashPoint::BeforeFragmentWrite,
+        CrashPoint::AfterFragmentWrite,
+        CrashPoint::AfterJournalWrite,
+        CrashPoint::MidApply,
+        CrashPoint::DuringExtentMetadata,
+        CrashPoint::DuringExtentMap,
+        CrashPoint::BeforeTempWrite,
+        CrashPoint::AfterTempWrite,
+        CrashPoint::BeforeRename,
+        CrashPoint::AfterRename,
+    ];
+    let mut new = old.to_vec();
+    let end = offset as usize + patch.len();
+    if new.len() < end {
+        new.resize(end, 0);
+    }
+    new[offset as usize..end].copy_from_slice(patch);
+    if offset == 0 {
+        new.truncate(patch.len());
+    }
+
+    let sim = get_crash_simulator();
+    for point in points {
+        for occurrence in 1..=20 {
+            let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+            let storage = StorageEngine::new(metadata, disks);
+            let inode = storage.create_file(1, "cow.bin".to_string()).unwrap();
+            storage.write_file(inode.ino, old, 0).unwrap();
+
+            sim.enable_after_n_ops(point, occurrence);
+            let result = storage.write_file(inode.ino, patch, offset);
+            sim.disable();
+            drop(storage);
+
+            let storage = reopen_storage(&pool_dir, &disk_dirs);
+            let contents = storage.read_file(inode.ino).unwrap();
+            assert!(
+                contents == old || contents == new,
+                "{:?} #{}: file is neither the old nor the new version",
+                point,
+                occurrence
+            );
+            if result.is_ok() {
+                assert_eq!(contents, new);
+            }
+            assert_no_orphaned_extents(&storage, &[inode.ino]);
+            assert!(storage.metadata().read().unwrap().released_extents().unwrap().is_empty());
+
+            // The point was hit fewer times than `occurrence`: every crash on it is covered
+            if result.is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_crash_during_whole_file_overwrite_keeps_old_or_new_version() {
+    let old: Vec<u8> = (0..6000u32).map(|i| (i % 251) as u8).collect();
+    assert_overwrite_is_atomic(&old, 0, &[0xEEu8; 4000]);
+}
+
+#[test]
+fn test_crash_during_in_place_overwrite_keeps_old_or_new_version() {
+    let old: Vec<u8> = (0..6000u32).map(|i| (i % 251) as u8).collect();
+    assert_overwrite_is_atomic(&old, 1000, &[0xEEu8; 7000]);
+}
+
+/// Extent UUIDs of every fragment file left on the disks
+fn fragments_on_disks(disk_dirs: &[TempDir]) -> Vec<uuid::Uuid> {
+    let mut extents = Vec::new();
+    for dir in disk_dirs {
+        for entry in fs::read_dir(dir.path().join("fragments")).unwrap() {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            if let Some(uuid) = name.get(..36).and_then(|prefix| uuid::Uuid::parse_str(prefix).ok()) {
+                extents.push(uuid);
+            }
+        }
+    }
+    extents
+}
+
+#[test]
+fn test_crash_during_delete_never_leaves_references_to_missing_metadata() {
+    let points = [
+        CrashPoint::AfterJournalWrite,
+        CrashPoint::MidApply,
+        CrashPoint::BeforeFragmentDelete,
+    ];
+    let data: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 249) as u8).collect();
+
+    let sim = get_crash_simulator();
+    for point in points {
+        for occurrence in 1..=20 {
+            let (pool_dir, disk_dirs, metadata, disks) = setup_test_env();
+            let storage = StorageEngine::new(metadata, disks);
+            let doomed = storage.create_file(1, "doomed.bin".to_string()).unwrap();
+            storage.write_file(doomed.ino, &data, 0).unwrap();
+            let kept = storage.create_file(1, "kept.bin".to_string()).unwrap();
+            storage.write_file(kept.ino, b"still here", 0).unwrap();
+            let doomed_extents = storage.metadata().read().unwrap().load_extent_map(doomed.ino).unwrap().extents;
+
+            sim.enable_after_n_ops_on_current_thread(point, occurrence);
+            let result = storage.delete_file(doomed.ino);
+            // The point was reached `occurrence` times, so this run failed there
+            let crashed = sim.operation_count() >= occurrence;
+            sim.disable();
+            drop(storage);
+
+            // Journaled before the first step that can fail, so recovery always completes the delete
+            let storage = reopen_storage(&pool_dir, &disk_dirs);
+            assert!(storage.get_inode(doomed.ino).is_err(), "{:?} #{}: inode survived", point, occurrence);
+            assert!(storage.find_child(1, "doomed.bin").unwrap().is_none());
+            assert!(storage.metadata().read().unwrap().load_extent_map(doomed.ino).unwrap().extents.is_empty());
+            assert_eq!(storage.read_file(kept.ino).unwrap(), b"still here");
+            assert_no_orphaned_extents(&storage, &[doomed.ino, kept.ino]);
+            assert!(storage.metadata().read().unwrap().released_extents().unwrap().is_empty());
+            assert!(crate::metadata_tx::pending_journal_records(pool_dir.path()).unwrap().is_empty());
+
+            // Fragments whose deletion failed are left to the orphan collector
+            if crashed && point == CrashPoint::BeforeFragmentDelete {
+                result.as_ref().unwrap();
+                assert!(fragments_on_disks(&disk_dirs).iter().any(|uuid| doomed_extents.contains(uuid)));
+            }
+            storage.collect_orphans(0).unwrap();
+            let remaining = fragments_on_disks(&disk_dirs);
+            assert!(
+                remaining.iter().all(|uuid| !doomed_extents.contains(uuid)),
+                "{:?} #{}: fragments of the deleted file were never reclaimed",
+                point,
+                occurrence
+            );
+
+            if !crashed {
+                result.unwrap();
+                break;
+            }
+        }
+    }
+}
+
+fn superblock_copy(path: &std::path::Path, offset: u64) -> Result<crate::on_device_allocator::Superblock> {
+    use std::io::{Read, Seek, SeekFrom};
+    let mut f = fs::File::open(path)?;
+    let mut buf = vec![0u8; crate::on_device_allocator::SUPERBLOCK_SIZE];
+    f.seek(SeekFrom::Start(offset))?;
+    f.read_exact(&mut buf)?;
+    crate::on_device_allocator::Superblock::from_bytes(&buf)
+}
+
+fn tear_superblock_copy(path: &std::path::Path, offset: u64, garbage: &[u8]) {
+    use std::io::{Seek, SeekFrom, Write};
+    let mut f = fs::OpenOptions::new().write(true).open(path).unwrap();
+    f.seek(SeekFrom::Start(offset)).unwrap();
+    f.write_all(garbage).unwrap();
+}
+
+#[test]
+fn test_torn_primary_superblock_falls_back_to_the_backup() {
+    use crate::on_device_allocator::{
+        FragmentHeader, OnDeviceAllocator, BACKUP_SUPERBLOCK_OFFSET, PRIMARY_SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE,
+    };
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("device.img");
+    OnDeviceAllocator::format_device(&path, uuid::Uuid::new_v4(), 4 << 20, 4096, 64).unwrap();
+    let fragment = |data: &[u8]| FragmentHeader {
+        extent_uuid: uuid::Uuid::new_v4(),
+        fragment_index: 0,
+        total_length: data.len() as u64,
+        data_checksum: *blake3::hash(data).as_bytes(),
+    };
+
+    let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+    let first = oda.allocate_contiguous(2).unwrap();
+    oda.write_fragment_at(first, b"first", &fragment(b"first")).unwrap();
+
+    let second = oda.allocate_contiguous(3).unwrap();
+    oda.write_fragment_at(second, b"second", &fragment(b"second")).unwrap();
+
+    // Power loss during a checkpoint, after the backup superblock is durable:
+    // the bitmap and the backup are new, the primary still has the previous seq
+    let sim = get_crash_simulator();
+    sim.enable_after_n_ops_on_current_thread(CrashPoint::BetweenSuperblockWrites, 1);
+    let result = oda.checkpoint();
+    sim.disable();
+    assert!(result.unwrap_err().to_string().contains("SIMULATED POWER LOSS"));
+    let bitmap = oda.bitmap().to_vec();
+    drop(oda);
+
+    let backup_seq = superblock_copy(&path, BACKUP_SUPERBLOCK_OFFSET).unwrap().seq;
+    assert_eq!(superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET).unwrap().seq + 1, backup_seq);
+
+    // The primary write was torn as well: its second half is garbage
+    let garbage: Vec<u8> = blake3::hash(b"torn").as_bytes().iter().cycle().take(SUPERBLOCK_SIZE / 2).copied().collect();
+    tear_superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET + SUPERBLOCK_SIZE as u64 / 2, &garbage);
+    assert!(superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET).is_err());
+
+    let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+    assert_eq!(oda.bitmap(), &bitmap[..]);
+    assert_eq!(oda.read_fragment_at(first).unwrap().1, b"first");
+    assert_eq!(oda.read_fragment_at(second).unwrap().1, b"second");
+
+    // Loading rewrote the primary from the backup
+    assert_eq!(superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET).unwrap().seq, backup_seq);
+    drop(oda);
+
+    // A primary truncated to zeros is just as recoverable; with both copies gone the load fails
+    tear_superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET, &vec![0u8; SUPERBLOCK_SIZE]);
+    assert_eq!(OnDeviceAllocator::load_from_device(&path).unwrap().bitmap(), &bitmap[..]);
+    tear_superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET, &vec![0u8; SUPERBLOCK_SIZE]);
+    tear_superblock_copy(&path, BACKUP_SUPERBLOCK_OFFSET, &garbage);
+    let err = OnDeviceAllocator::load_from_device(&path).unwrap_err().to_string();
+    assert!(err.contains("No valid superblock"), "{}", err);
+}
+
+#[test]
+fn test_damaged_backup_superblock_is_rewritten_from_the_primary() {
+    use crate::on_device_allocator::{OnDeviceAllocator, BACKUP_SUPERBLOCK_OFFSET, PRIMARY_SUPERBLOCK_OFFSET};
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("device.img");
+    OnDeviceAllocator::format_device(&path, uuid::Uuid::new_v4(), 4 << 20, 4096, 64).unwrap();
+    let mut oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+    oda.allocate_contiguous(5).unwrap();
+    oda.persist().unwrap();
+    let bitmap = oda.bitmap().to_vec();
+    drop(oda);
+
+    tear_superblock_copy(&path, BACKUP_SUPERBLOCK_OFFSET, b"not a superblock");
+    let oda = OnDeviceAllocator::load_from_device(&path).unwrap();
+    assert_eq!(oda.bitmap(), &bitmap[..]);
+    assert_eq!(
+        superblock_copy(&path, BACKUP_SUPERBLOCK_OFFSET).unwrap().seq,
+        superblock_copy(&path, PRIMARY_SUPERBLOCK_OFFSET).unwrap().seq
+    );
+}
+
+/// Open the pool laid out by `chaos_pool` as a fresh mount would
+fn open_chaos_pool(root: &std::path::Path) -> Result<StorageEngine> {
+    let metadata = MetadataManager::new(root.join("pool"))?;
+    let disks = (0..6)
+        .map(|i| Disk::load(&root.join(format!("disk{}", i))))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(StorageEngine::new(metadata, disks))
+}
+
+/// Open the pool again after a simulated power loss, which recovery may hit too
+fn remount_chaos_pool(root: &std::path::Path) -> StorageEngine {
+    let mut last_error = None;
+    for _ in 0..50 {
+        match open_chaos_pool(root) {
+            Ok(storage) => return storage,
+            Err(e) => last_error = Some(e),
+        }
+    }
+    panic!("pool never came back after power loss: {:?}", last_error);
+}
+
+/// Prints the seed of a chaos run that panicked, so it can be replayed
+struct ReportSeedOnFailure(u64);
+
+impl Drop for ReportSeedOnFailure {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!("chaos run failed with seed {0}; replay with SCFS_CHAOS_SEED={0}", self.0);
+        }
+    }
+}
+
+/// A file of the chaos workload; `None` once a failed write left its contents undefined
+struct ChaosFile {
+    name: String,
+    contents: Option<Vec<u8>>,
+}
+
+/// Randomized workload under injected power loss and I/O errors
+///
+/// Each power loss drops the engine and mounts the pool again, like a restart.
+/// With injection off, the pool must pass the consistency check, scrub
+/// without unrecoverable extents and read back every file whose last write
+/// succeeded. `SCFS_CHAOS_SEED` replays a run; `SCFS_CHAOS_OPS` lengthens it.
+#[test]
+fn test_chaos_workload_under_injected_faults_leaves_a_consistent_pool() {
+    use crate::crash_sim::{inject_faults, FaultPlan, SeededRng};
+    use crate::fsck::{check_pool, CheckOptions};
+    use crate::scrubber::{ScrubConfig, ScrubPassProgress, Scrubber};
+    use std::collections::BTreeMap;
+
+    let seed = std::env::var("SCFS_CHAOS_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64);
+    let operations: usize = std::env::var("SCFS_CHAOS_OPS").ok().and_then(|ops| ops.parse().ok()).unwrap_or(2000);
+    let _report = ReportSeedOnFailure(seed);
+    eprintln!("chaos run with seed {}", seed);
+
+    let root = TempDir::new().unwrap();
+    fs::create_dir(root.path().join("pool")).unwrap();
+    init_pool_dir(&root.path().join("pool"), test_metadata_backend());
+    for i in 0..6 {
+        let path = root.path().join(format!("disk{}", i));
+        fs::create_dir(&path).unwrap();
+        Disk::new(path).unwrap();
+    }
+    let mut storage = open_chaos_pool(root.path()).unwrap();
+    let policies = [
+        RedundancyPolicy::Replication { copies: 2 },
+        RedundancyPolicy::Replication { copies: 3 },
+        RedundancyPolicy::ErasureCoding { data_shards: 4, parity_shards: 2 },
+    ];
+    let mut rng = SeededRng::new(seed);
+    let mut files: BTreeMap<u64, ChaosFile> = BTreeMap::new();
+    let mut next_name = 0;
+
+    let scope = inject_faults(FaultPlan {
+        seed,
+        probability: 0.002,
+        power_loss_at: vec![
+            CrashPoint::AfterJournalWrite,
+            CrashPoint::MidApply,
+            CrashPoint::AfterTempWrite,
+            CrashPoint::AfterRename,
+        ],
+        io_errors_at: vec![
+            CrashPoint::BeforeFragmentRead,
+            CrashPoint::BeforeFragmentWrite,
+            CrashPoint::AfterFragmentWrite,
+            CrashPoint::BeforeTempWrite,
+            CrashPoint::BeforeRename,
+            CrashPoint::DuringExtentMetadata,
+            CrashPoint::DuringExtentMap,
+        ],
+        root: root.path().to_path_buf(),
+    });
+
+    for op in 0..operations {
+        let power_losses = scope.counts().power_losses;
+        let inos: Vec<u64> = files.keys().copied().collect();
+        let target = (!inos.is_empty()).then(|| inos[rng.below(inos.len() as u64) as usize]);
+        match (rng.below(100), target) {
+            (0..=14, _) | (_, None) if files.len() < 40 => {
+                let name = format!("chaos-{}", next_name);
+                next_name += 1;
+                match storage.create_file(1, name.clone()) {
+                    Ok(inode) => {
+                        files.insert(inode.ino, ChaosFile { name, contents: Some(Vec::new()) });
+                    }
+                    Err(_) => {
+                        if let Ok(Some(inode)) = storage.find_child(1, &name) {
+                            files.insert(inode.ino, ChaosFile { name, contents: None });
+                        }
+                    }
+                }
+            }
+            (0..=54, Some(ino)) => {
+                let len = match rng.below(10) {
+                    0 => 1_048_576 + rng.below(1_048_576),
+                    1..=3 => 16_384 + rng.below(262_144),
+                    _ => 1 + rng.below(8192),
+                } as usize;
+                let fill = rng.below(256) as u8;
+                let data: Vec<u8> = (0..len).map(|i| fill.wrapping_add((i % 251) as u8)).collect();
+                let file = files.get_mut(&ino).unwrap();
+                let offset = match &file.contents {
+                    Some(contents) if !contents.is_empty() && rng.chance(0.5) => rng.below(contents.len() as u64 + 1),
+                    _ => 0,
+                };
+                let result = storage.write_file(ino, &data, offset);
+                file.contents = match (result, file.contents.take()) {
+                    (Ok(()), _) if offset == 0 => Some(data),
+                    (Ok(()), Some(mut contents)) => {
+                        let end = offset as usize + data.len();
+                        contents.resize(contents.len().max(end), 0);
+                        contents[offset as usize..end].copy_from_slice(&data);
+                        Some(contents)
+                    }
+                    _ => None,
+                };
+            }
+            (55..=69, Some(ino)) => {
+                if let (Ok(contents), Some(expected)) = (storage.read_file(ino), &files[&ino].contents) {
+                    assert!(contents == *expected, "op {}: ino {} read back other contents", op, ino);
+                }
+            }
+            (70..=79, Some(ino)) => {
+                let name = files[&ino].name.clone();
+                if storage.delete_file(ino).is_ok() || storage.find_child(1, &name).is_ok_and(|child| child.is_none()) {
+                    files.remove(&ino);
+                } else {
+                    files.get_mut(&ino).unwrap().contents = None;
+                }
+            }
+            (80..=94, Some(ino)) => {
+                let policy = policies[rng.below(policies.len() as u64) as usize].clone();
+                if storage.set_file_redundancy(ino, policy).is_err() {
+                    files.get_mut(&ino).unwrap().contents = None;
+                }
+            }
+            _ => {
+                let metadata = storage.metadata();
+                let metadata = metadata.read().unwrap();
+                let config = ScrubConfig { workers: 2, max_bytes_per_sec: None, repair: true, skip: Default::default() };
+                Scrubber::new(root.path().join("pool"))
+                    .scrub_pool(&metadata, &mut storage.get_disks(), &config, &ScrubPassProgress::default())
+                    .ok();
+            }
+        }
+
+        if scope.counts().power_losses > power_losses {
+            drop(storage);
+            storage = remount_chaos_pool(root.path());
+        }
+    }
+
+    let counts = scope.counts();
+    eprintln!("chaos run with seed {}: {} power losses, {} I/O errors injected", seed, counts.power_losses, counts.io_errors);
+    assert!(counts.power_losses + counts.io_errors > 0, "the workload never reached a fault point");
+    drop(scope);
+    drop(storage);
+
+    // Recovery first: a fresh mount replays what the last power loss left in the journal
+    let storage = open_chaos_pool(root.path()).unwrap();
+    storage.wait_for_rebuilds();
+    drop(storage);
+
+    let mut disks: Vec<Disk> = (0..6).map(|i| Disk::load(&root.path().join(format!("disk{}", i))).unwrap()).collect();
+    let report = check_pool(root.path().join("pool"), &mut disks, 0, CheckOptions::default()).unwrap();
+    let errors: Vec<_> = report.findings.iter().filter(|f| f.kind.is_error()).collect();
+    assert!(errors.is_empty(), "consistency check found {:#?}", errors);
+
+    let storage = open_chaos_pool(root.path()).unwrap();
+    let metadata = storage.metadata();
+    let metadata = metadata.read().unwrap();
+    let config = ScrubConfig { workers: 4, max_bytes_per_sec: None, repair: true, skip: Default::default() };
+    let results = Scrubber::new(root.path().join("pool"))
+        .scrub_pool(&metadata, &mut storage.get_disks(), &config, &ScrubPassProgress::default())
+        .unwrap();
+    drop(metadata);
+    let stats = Scrubber::stats(&results);
+    assert_eq!(stats.unrecoverable, 0, "{}", stats);
+
+    for (ino, file) in &files {
+        let contents = storage.read_file(*ino).unwrap_or_else(|e| panic!("{} is unreadable: {:#}", file.name, e));
+        if let Some(expected) = &file.contents {
+            assert!(contents == *expected, "{} lost its last successful write", file.name);
+        }
+    }
+}
@@ -7,7 +7,7 @@ fn metadata_btree_roundtrip() -> Result<()> {
     let td = tempdir()?;
     let pool = td.path().to_path_buf();
     let mut mgr = MetadataManager::new(pool.clone())?;
-    let ino = mgr.allocate_ino();
+    let ino = mgr.allocate_ino().unwrap();
     let inode = crate::metadata::Inode::new_file(ino, 1, "f1".to_string());
     mgr.save_inode(&inode)?;
     // reload manager
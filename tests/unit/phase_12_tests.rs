@@ -268,6 +268,7 @@ fn test_defrag_needs_defragmentation() {
         prioritize_hot_extents: true,
         pause_on_high_load: true,
         max_concurrent_operations: 2,
+        schedule: None,
     };
 
     // Test with default min_extent_fragments
@@ -36,7 +36,8 @@ mod phase_15_tests {
                 last_write: now,
                 created_at: now,
                 classification: AccessClassification::Cold,
-                hmm_classifier: None,
+                hourly_accesses: Vec::new(),
+                scan_reads: 0,
             },
             rebuild_in_progress: false,
             rebuild_progress: None,
@@ -3,13 +3,16 @@ use std::fs;
 use uuid::Uuid;
 
 use crate::disk::Disk;
-use crate::extent::{Extent, RedundancyPolicy, FragmentLocation};
+use crate::error::StorageError;
+use crate::extent::{Extent, RedundancyPolicy, FragmentLocation, DEFAULT_EXTENT_SIZE};
 use crate::gc::{GarbageCollector};
 use crate::metadata::{MetadataManager, Inode, ExtentMap};
+use crate::metadata_store::RecordKind;
+use crate::test_utils::{record_store, test_pool_dir};
 
 #[test]
 fn test_inode_checksum_verification() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     let mut metadata = MetadataManager::new(pool_dir.clone())?;
@@ -25,10 +28,10 @@ fn test_inode_checksum_verification() -> Result<()> {
     assert!(loaded.checksum.is_some(), "Checksum should be present");
     
     // Manually corrupt the saved inode
-    let inode_path = pool_dir.join("inodes").join("42");
-    let mut contents: serde_json::Value = serde_json::from_str(&fs::read_to_string(&inode_path)?)?;
+    let store = record_store(&pool_dir);
+    let mut contents: serde_json::Value = serde_json::from_slice(&store.load(RecordKind::Inode, "42")?.unwrap())?;
     contents["size"] = serde_json::json!(999); // Change data but not checksum
-    fs::write(&inode_path, serde_json::to_string_pretty(&contents)?)?;
+    store.save(RecordKind::Inode, "42", serde_json::to_string_pretty(&contents)?.as_bytes())?;
     
     // Loading should now fail due to checksum mismatch
     let result = metadata.load_inode(42);
@@ -43,7 +46,7 @@ fn test_inode_checksum_verification() -> Result<()> {
 
 #[test]
 fn test_extent_map_checksum_verification() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     let mut metadata = MetadataManager::new(pool_dir.clone())?;
@@ -53,6 +56,9 @@ fn test_extent_map_checksum_verification() -> Result<()> {
         ino: 42,
         extents: vec![Uuid::new_v4(), Uuid::new_v4()],
         checksum: None,
+        extent_size: DEFAULT_EXTENT_SIZE,
+        change_generation: 0,
+        slot_generations: Vec::new(),
     };
     metadata.save_extent_map(&extent_map)?;
     
@@ -63,10 +69,10 @@ fn test_extent_map_checksum_verification() -> Result<()> {
     assert!(loaded.checksum.is_some(), "Checksum should be present");
     
     // Manually corrupt the saved extent map
-    let map_path = pool_dir.join("extent_maps").join("42");
-    let mut contents: serde_json::Value = serde_json::from_str(&fs::read_to_string(&map_path)?)?;
+    let store = record_store(&pool_dir);
+    let mut contents: serde_json::Value = serde_json::from_slice(&store.load(RecordKind::ExtentMap, "42")?.unwrap())?;
     contents["extents"] = serde_json::json!([]); // Remove extents but not checksum
-    fs::write(&map_path, serde_json::to_string_pretty(&contents)?)?;
+    store.save(RecordKind::ExtentMap, "42", serde_json::to_string_pretty(&contents)?.as_bytes())?;
     
     // Loading should now fail due to checksum mismatch
     let result = metadata.load_extent_map(42);
@@ -80,7 +86,7 @@ fn test_extent_map_checksum_verification() -> Result<()> {
 
 #[test]
 fn test_orphan_detection() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     // Set up metadata
@@ -116,11 +122,13 @@ fn test_orphan_detection() -> Result<()> {
                 disk_uuid: disk.uuid,
                 fragment_index: 0,
                 on_device: None,
+                checksum: None,
             },
             FragmentLocation {
                 disk_uuid: disk.uuid,
                 fragment_index: 1,
                 on_device: None,
+                checksum: None,
             },
         ],
         access_stats: crate::extent::AccessStats {
@@ -130,7 +138,8 @@ fn test_orphan_detection() -> Result<()> {
             last_write: now,
             created_at: now,
             classification: crate::extent::AccessClassification::Cold,
-            hmm_classifier: None,
+            hourly_accesses: Vec::new(),
+            scan_reads: 0,
         },
         previous_policy: None,
         policy_transitions: Vec::new(),
@@ -138,6 +147,10 @@ fn test_orphan_detection() -> Result<()> {
         rebuild_in_progress: false,
         rebuild_progress: None,
         generation: 0,
+        compression: crate::compression::Compression::None,
+        compressed_size: None,
+        extent_size: DEFAULT_EXTENT_SIZE,
+        intended_policy: None,
     };
     metadata.save_extent(&extent1)?;
     
@@ -157,7 +170,7 @@ fn test_orphan_detection() -> Result<()> {
 
 #[test]
 fn test_orphan_cleanup() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     // Set up metadata
@@ -194,7 +207,7 @@ fn test_orphan_cleanup() -> Result<()> {
 
 #[test]
 fn test_orphan_cleanup_age_filter() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     // Set up metadata
@@ -228,7 +241,7 @@ fn test_orphan_cleanup_age_filter() -> Result<()> {
 
 #[test]
 fn test_orphan_cleanup_dry_run() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     // Set up metadata
@@ -262,7 +275,7 @@ fn test_orphan_cleanup_dry_run() -> Result<()> {
 
 #[test]
 fn test_orphan_stats() -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
+    let temp_dir = test_pool_dir();
     let pool_dir = temp_dir.path().to_path_buf();
     
     // Set up metadata
@@ -295,3 +308,238 @@ fn test_orphan_stats() -> Result<()> {
     
     Ok(())
 }
+
+#[test]
+fn test_orphan_collect_keeps_protected_and_unreadable_disks() -> Result<()> {
+    use crate::gc::InFlightExtents;
+    use std::sync::Arc;
+
+    let temp_dir = test_pool_dir();
+    let pool_dir = temp_dir.path().to_path_buf();
+    MetadataManager::new(pool_dir.clone())?;
+
+    fs::create_dir_all(temp_dir.path().join("disk_a"))?;
+    fs::create_dir_all(temp_dir.path().join("disk_b"))?;
+    let disk_a = Disk::new(temp_dir.path().join("disk_a"))?;
+    let disk_b = Disk::new(temp_dir.path().join("disk_b"))?;
+    let orphan = Uuid::new_v4();
+    let writing = Uuid::new_v4();
+    fs::write(disk_a.fragment_path(&orphan, 0), b"orphan")?;
+    fs::write(disk_a.fragment_path(&writing, 1), b"in flight")?;
+
+    // disk_b holds a fragment of an extent whose metadata cannot be parsed
+    let damaged = Uuid::new_v4();
+    record_store(&pool_dir).save(RecordKind::Extent, &damaged.to_string(), b"not json")?;
+    fs::write(disk_b.fragment_path(&damaged, 0), b"maybe live")?;
+    fs::write(disk_b.fragment_path(&Uuid::new_v4(), 0), b"orphan too")?;
+
+    let in_flight = Arc::new(InFlightExtents::default());
+    let mut write = in_flight.begin();
+    write.add(writing);
+
+    let gc = GarbageCollector::new(pool_dir, vec![disk_a.clone(), disk_b.clone()]);
+    let report = gc.collect(0, false, &|uuid| in_flight.contains(uuid))?;
+    assert_eq!(report.removed.len(), 1);
+    assert_eq!(report.removed[0].extent_uuid, orphan);
+    assert_eq!(report.skipped_disks, vec![disk_b.uuid]);
+    assert_eq!(report.protected, 1);
+    assert!(disk_a.fragment_path(&writing, 1).exists());
+    assert_eq!(fs::read_dir(disk_b.path.join("fragments"))?.count(), 2);
+
+    // Once the write is dropped without committing, its fragment is an orphan
+    drop(write);
+    let report = gc.collect(0, false, &|uuid| in_flight.contains(uuid))?;
+    assert_eq!(report.removed.len(), 1);
+    assert!(!disk_a.fragment_path(&writing, 1).exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_extent_map_checksum_upgrade_and_scrub_check() -> Result<()> {
+    use crate::metadata::ExtentMapChecksumMismatch;
+
+    let temp_dir = test_pool_dir();
+    let pool_dir = temp_dir.path().to_path_buf();
+    let metadata = MetadataManager::new(pool_dir.clone())?;
+    let extent = Extent::new(b"mapped", RedundancyPolicy::Replication { copies: 1 });
+    metadata.save_extent(&extent)?;
+    let store = record_store(&pool_dir);
+    let write_map = |ino: u64, contents: &[u8]| store.save(RecordKind::ExtentMap, &ino.to_string(), contents);
+
+    // Maps written before checksums load, and gain one on their next save
+    let legacy = ExtentMap { extents: vec![extent.uuid], ..ExtentMap::new(7, DEFAULT_EXTENT_SIZE) };
+    write_map(7, serde_json::to_string(&legacy)?.as_bytes())?;
+    let loaded = metadata.load_extent_map(7)?;
+    assert!(loaded.checksum.is_none());
+    metadata.save_extent_map(&loaded)?;
+    assert_eq!(metadata.load_extent_map(7)?.checksum, Some(loaded.compute_checksum()));
+
+    // A mismatch is a distinct error
+    let mut stale: serde_json::Value = serde_json::from_slice(&store.load(RecordKind::ExtentMap, "7")?.unwrap())?;
+    stale["checksum"] = serde_json::json!("0".repeat(64));
+    write_map(7, stale.to_string().as_bytes())?;
+    let err = metadata.load_extent_map(7).unwrap_err();
+    assert!(
+        matches!(&err, StorageError::Other(e) if e.downcast_ref::<ExtentMapChecksumMismatch>().is_some()),
+        "{:#}",
+        err
+    );
+
+    // Listing an extent that is gone cannot be fixed by a new checksum
+    let gone = ExtentMap { extents: vec![Uuid::new_v4()], checksum: Some("0".repeat(64)), ..ExtentMap::new(8, DEFAULT_EXTENT_SIZE) };
+    write_map(8, serde_json::to_string(&gone)?.as_bytes())?;
+    write_map(9, b"{\"ino\": 9, \"exte")?;
+
+    let report = metadata.check_extent_maps(false)?;
+    assert_eq!(report.checked, 3);
+    assert_eq!(report.stale, vec![7]);
+    assert_eq!(report.corrupt.iter().map(|c| c.ino).collect::<Vec<_>>(), vec![8, 9]);
+    assert!(report.repaired.is_empty());
+
+    let report = metadata.check_extent_maps(true)?;
+    assert_eq!(report.repaired, vec![7]);
+    assert_eq!(metadata.load_extent_map(7)?.extents, vec![extent.uuid]);
+    assert!(metadata.load_extent_map(9).is_err());
+    assert!(metadata.check_extent_maps(false)?.stale.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_check_reports_and_repairs_metadata_graph() -> Result<()> {
+    use crate::fsck::{check_pool, CheckOptions, FindingKind, FindingStatus};
+
+    let temp_dir = test_pool_dir();
+    let pool_dir = temp_dir.path().to_path_buf();
+    let mut metadata = MetadataManager::new(pool_dir.clone())?;
+    fs::create_dir_all(temp_dir.path().join("disk"))?;
+    let disk = Disk::new(temp_dir.path().join("disk"))?;
+
+    // A file whose parent directory is gone, listing a live and a deleted extent
+    let mut live = Extent::new(b"live", RedundancyPolicy::Replication { copies: 1 });
+    live.fragment_locations.push(FragmentLocation {
+        disk_uuid: disk.uuid,
+        fragment_index: 0,
+        on_device: None,
+        checksum: None,
+    });
+    fs::write(disk.fragment_path(&live.uuid, 0), b"live")?;
+    metadata.save_extent(&live)?;
+    let lost = Inode::new_file(metadata.allocate_ino().unwrap(), 999, "lost".to_string());
+    metadata.save_inode(&lost)?;
+    let deleted = Uuid::new_v4();
+    let mut map = ExtentMap { extents: vec![live.uuid, deleted], ..ExtentMap::new(lost.ino, DEFAULT_EXTENT_SIZE) };
+    map.checksum = Some(map.compute_checksum());
+    metadata.save_extent_map(&map)?;
+
+    // An extent nobody lists, and a map whose inode is gone
+    let stray = Extent::new(b"stray", RedundancyPolicy::Replication { copies: 1 });
+    metadata.save_extent(&stray)?;
+    metadata.save_extent_map(&ExtentMap::new(500, DEFAULT_EXTENT_SIZE))?;
+
+    let kinds = |report: &crate::fsck::CheckReport| {
+        let mut kinds: Vec<FindingKind> = report.findings.iter().map(|f| f.kind).collect();
+        kinds.sort();
+        kinds
+    };
+    let report = check_pool(pool_dir.clone(), &mut [disk.clone()], 0, CheckOptions::default())?;
+    assert_eq!(
+        kinds(&report),
+        vec![
+            FindingKind::DisconnectedInode,
+            FindingKind::OrphanedExtentMap,
+            FindingKind::DanglingExtentRef,
+            FindingKind::UnreferencedExtent,
+            FindingKind::UnrecoverableExtent,
+        ]
+    );
+    assert_eq!(report.unresolved_errors(), 4);
+    assert!(report.findings.iter().all(|f| f.status == FindingStatus::Open));
+
+    // Safe repairs apply; dropping the orphaned map needs --force
+    let report = check_pool(pool_dir.clone(), &mut [disk.clone()], 0, CheckOptions { repair: true, force: false })?;
+    for finding in &report.findings {
+        match finding.kind {
+            FindingKind::OrphanedExtentMap => {
+                assert_eq!(finding.status, FindingStatus::Refused("needs --force".to_string()))
+            }
+            FindingKind::UnrecoverableExtent => assert_eq!(finding.status, FindingStatus::Open),
+            _ => assert_eq!(finding.status, FindingStatus::Repaired, "{:?}", finding),
+        }
+    }
+    let metadata = MetadataManager::new(pool_dir.clone())?;
+    let reattached = metadata.load_inode(lost.ino)?;
+    let lost_found = metadata.find_child(1, "lost+found")?.expect("lost+found created");
+    assert_eq!(reattached.parent_ino, lost_found.ino);
+    assert_eq!(reattached.name, format!("#{}", lost.ino));
+    assert_eq!(metadata.load_extent_map(lost.ino)?.extents, vec![live.uuid, ExtentMap::HOLE]);
+    assert!(metadata.released_extents()?.contains(&stray.uuid));
+
+    let report = check_pool(pool_dir.clone(), &mut [disk.clone()], 0, CheckOptions { repair: true, force: true })?;
+    assert_eq!(kinds(&report), vec![FindingKind::OrphanedExtentMap, FindingKind::UnrecoverableExtent]);
+    assert!(!record_store(&pool_dir).exists(RecordKind::ExtentMap, "500")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_extent_totals_follow_saves_and_deletes() -> Result<()> {
+    use crate::extent_totals::ExtentTotals;
+    use crate::fsck::{check_pool, CheckOptions, FindingKind, FindingStatus};
+
+    let temp_dir = test_pool_dir();
+    let pool_dir = temp_dir.path().to_path_buf();
+    let metadata = MetadataManager::new(pool_dir.clone())?;
+    let disk_uuid = Uuid::new_v4();
+    let extent_with = |data: &[u8], fragments: usize| {
+        let mut extent = Extent::new(data, RedundancyPolicy::ErasureCoding { data_shards: 2, parity_shards: 1 });
+        for fragment_index in 0..fragments {
+            extent.fragment_locations.push(FragmentLocation { disk_uuid, fragment_index, on_device: None, checksum: None });
+        }
+        extent
+    };
+
+    let complete = extent_with(b"complete", 3);
+    let mut degraded = extent_with(b"degraded!", 2);
+    let unreadable = extent_with(b"gone", 1);
+    for extent in [&complete, &degraded, &unreadable] {
+        metadata.save_extent(extent)?;
+    }
+    let totals = metadata.extent_totals();
+    assert_eq!((totals.extents, totals.logical_bytes, totals.complete()), (3, 21, 1));
+    assert_eq!(totals.degraded.iter().collect::<Vec<_>>(), vec![&degraded.uuid]);
+    assert_eq!(totals.unreadable.iter().collect::<Vec<_>>(), vec![&unreadable.uuid]);
+
+    // A rebuild saves the extent again; only its health changes
+    degraded.fragment_locations.push(FragmentLocation { disk_uuid, fragment_index: 2, on_device: None, checksum: None });
+    metadata.save_extent(&degraded)?;
+    metadata.delete_extent(&unreadable.uuid)?;
+    let totals = metadata.extent_totals();
+    assert_eq!((totals.extents, totals.logical_bytes, totals.complete()), (2, 17, 2));
+    assert!(totals.degraded.is_empty() && totals.unreadable.is_empty());
+
+    // Saved with the pool, and counted from the records for pools without them
+    assert_eq!(MetadataManager::new(pool_dir.clone())?.extent_totals(), totals);
+    fs::remove_file(pool_dir.join("metadata/extent_totals.json"))?;
+    assert_eq!(MetadataManager::new(pool_dir.clone())?.extent_totals(), totals);
+
+    // UUID order resumes after the last extent handled
+    let mut uuids = [complete.uuid, degraded.uuid];
+    uuids.sort();
+    let records = metadata.extent_records_sorted(None)?;
+    assert_eq!(records.remaining(), Some(2));
+    assert_eq!(records.map(|(uuid, extent)| (uuid, extent.unwrap().uuid)).collect::<Vec<_>>(),
+               uuids.iter().map(|uuid| (*uuid, *uuid)).collect::<Vec<_>>());
+    let rest: Vec<Uuid> = metadata.extent_records_sorted(Some(uuids[0]))?.map(|(uuid, _)| uuid).collect();
+    assert_eq!(rest, vec![uuids[1]]);
+
+    // An extent written behind the counters' back is caught and recounted by check
+    let stray = extent_with(b"stray", 3);
+    record_store(&pool_dir).save(RecordKind::Extent, &stray.uuid.to_string(), &serde_json::to_vec(&stray)?)?;
+    let report = check_pool(pool_dir.clone(), &mut [], 0, CheckOptions { repair: true, force: false })?;
+    let stale = report.findings.iter().find(|f| f.kind == FindingKind::StaleExtentTotals).unwrap();
+    assert_eq!(stale.status, FindingStatus::Repaired);
+    assert_eq!(ExtentTotals::load(&pool_dir)?.unwrap().extents, 3);
+    Ok(())
+}
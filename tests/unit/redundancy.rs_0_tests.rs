@@ -14,12 +14,12 @@ use super::*;
         
         // Decode with all fragments
         let options: Vec<Option<Vec<u8>>> = fragments.into_iter().map(Some).collect();
-        let decoded = decode(&options, policy).unwrap();
+        let decoded = decode(&options, policy, data.len()).unwrap();
         assert_eq!(decoded, data);
         
         // Decode with only first fragment
         let options = vec![Some(data.to_vec()), None, None];
-        let decoded = decode(&options, policy).unwrap();
+        let decoded = decode(&options, policy, data.len()).unwrap();
         assert_eq!(decoded, data);
     }
     
@@ -36,13 +36,13 @@ use super::*;
         
         // Decode with all fragments
         let options: Vec<Option<Vec<u8>>> = fragments.iter().map(|f| Some(f.clone())).collect();
-        let decoded = decode(&options, policy).unwrap();
+        let decoded = decode(&options, policy, data.len()).unwrap();
         assert_eq!(&decoded[..data.len()], data);
         
         // Decode with missing fragments (simulate 2 disk failures)
         let mut options = options;
         options[1] = None; // Missing fragment
         options[4] = None; // Missing fragment
-        let decoded = decode(&options, policy).unwrap();
+        let decoded = decode(&options, policy, data.len()).unwrap();
         assert_eq!(&decoded[..data.len()], data);
     }
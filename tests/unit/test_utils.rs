@@ -3,10 +3,11 @@ use std::thread;
 use std::time::Duration;
 // tempfile is a dev-dependency used only by tests
 use tempfile::TempDir;
-use std::path::PathBuf;
+use std::path::Path;
 
-use crate::disk::Disk;
+use crate::disk::{Disk, DiskPool};
 use crate::metadata::MetadataManager;
+use crate::metadata_store::{MetadataBackend, MetadataStore};
 
 /// Run a closure and return Err if it doesn't complete within `secs` seconds.
 /// Intended for use in tests to avoid hanging forever when something deadlocks.
@@ -27,9 +28,52 @@ where
     }
 }
 
+/// Metadata backend the suites run on: the kv store when built with
+/// `--features kv-metadata`, the file store otherwise.
+pub fn test_metadata_backend() -> MetadataBackend {
+    if cfg!(feature = "kv-metadata") {
+        MetadataBackend::Kv
+    } else {
+        MetadataBackend::Files
+    }
+}
+
+/// Make the new pool at `pool_dir` keep its metadata on `backend`.
+pub fn init_pool_dir(pool_dir: &Path, backend: MetadataBackend) {
+    // Pools without a pool.json use the file store
+    if backend != MetadataBackend::Files {
+        let mut pool = DiskPool::new();
+        pool.metadata_backend = backend;
+        pool.save(pool_dir).unwrap();
+    }
+}
+
+/// Create an empty pool directory that keeps its metadata on `backend`.
+pub fn pool_dir_with(backend: MetadataBackend) -> TempDir {
+    let pool_dir = tempfile::tempdir().unwrap();
+    init_pool_dir(pool_dir.path(), backend);
+    pool_dir
+}
+
+/// Create an empty pool directory on the backend the suites run on.
+pub fn test_pool_dir() -> TempDir {
+    pool_dir_with(test_metadata_backend())
+}
+
+/// The record store of the pool at `pool_dir`, on whichever backend it uses,
+/// for tests that damage or plant records behind the metadata manager
+pub fn record_store(pool_dir: &Path) -> std::sync::Arc<dyn MetadataStore> {
+    crate::metadata_store::open(pool_dir, DiskPool::metadata_backend(pool_dir).unwrap()).unwrap()
+}
+
 /// Create a test environment: pool tempdir, disk tempdirs, metadata manager and Disk objects.
 pub fn setup_test_env() -> (TempDir, Vec<TempDir>, MetadataManager, Vec<Disk>) {
-    let pool_dir = tempfile::tempdir().unwrap();
+    setup_test_env_with(test_metadata_backend())
+}
+
+/// Like `setup_test_env`, with the pool's metadata on `backend`.
+pub fn setup_test_env_with(backend: MetadataBackend) -> (TempDir, Vec<TempDir>, MetadataManager, Vec<Disk>) {
+    let pool_dir = pool_dir_with(backend);
 
     // Create 6 test disks
     let disk_dirs: Vec<TempDir> = (0..6)
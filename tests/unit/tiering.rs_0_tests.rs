@@ -25,7 +25,8 @@ use super::*;
                 last_write: current_timestamp() as i64,
                 created_at: (current_timestamp() - 86400 * 30) as i64, // 30 days old
                 classification,
-                hmm_classifier: None,
+                hourly_accesses: Vec::new(),
+                scan_reads: 0,
             },
             rebuild_in_progress: false,
             rebuild_progress: None,
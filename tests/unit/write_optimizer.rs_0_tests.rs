@@ -64,7 +64,8 @@ use super::*;
                 last_write: 0,
                 created_at: Utc::now().timestamp(),
                 classification: AccessClassification::Cold,
-                hmm_classifier: None,
+                hourly_accesses: Vec::new(),
+                scan_reads: 0,
             },
             rebuild_in_progress: false,
             rebuild_progress: None,
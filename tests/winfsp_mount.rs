@@ -0,0 +1,60 @@
+//! Mounts a WinFsp volume over `MemoryFs` and drives it through std::fs
+//!
+//! Needs WinFsp installed and a free drive letter, `X:` unless
+//! DYNAMICFS_TEST_DRIVE names another:
+//!
+//! ```text
+//! cargo test --features winfsp-tests --test winfsp_mount
+//! ```
+#![cfg(all(target_os = "windows", feature = "winfsp-tests"))]
+
+use dynamicfs::memory_fs::MemoryFs;
+use dynamicfs::windows_fs::{request_shutdown, WindowsFS};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_mount_at_drive_letter() {
+    let drive = std::env::var("DYNAMICFS_TEST_DRIVE").unwrap_or_else(|_| "X:".to_string());
+    let root = PathBuf::from(format!("{}\\", drive));
+
+    let (mounted_tx, mounted_rx) = std::sync::mpsc::channel();
+    let mount_drive = drive.clone();
+    let mount = std::thread::spawn(move || {
+        let fs = WindowsFS::new(Box::new(MemoryFs::new()));
+        fs.mount_then(mount_drive.as_ref(), || mounted_tx.send(()).unwrap())
+    });
+    mounted_rx.recv_timeout(Duration::from_secs(20)).expect("volume did not mount");
+
+    let dir = root.join("Docs");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("notes.txt"), b"hello from windows").unwrap();
+    assert_eq!(fs::read(dir.join("notes.txt")).unwrap(), b"hello from windows");
+    // Case-sensitive search is on by default
+    assert!(fs::read(dir.join("NOTES.TXT")).is_err());
+
+    fs::rename(dir.join("notes.txt"), root.join("moved.txt")).unwrap();
+    let mut names: Vec<_> = fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name()).collect();
+    names.sort();
+    assert_eq!(names, ["Docs", "moved.txt"]);
+
+    let file = fs::OpenOptions::new().write(true).open(root.join("moved.txt")).unwrap();
+    file.set_len(5).unwrap();
+    drop(file);
+    assert_eq!(fs::read(root.join("moved.txt")).unwrap(), b"hello");
+
+    assert!(fs::remove_dir(root.join("missing")).is_err());
+    fs::remove_file(root.join("moved.txt")).unwrap();
+    fs::remove_dir(&dir).unwrap();
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+
+    request_shutdown();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !mount.is_finished() {
+        assert!(Instant::now() < deadline, "mount did not return after shutdown");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    mount.join().unwrap().unwrap();
+    assert!(!root.exists());
+}